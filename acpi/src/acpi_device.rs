@@ -23,6 +23,15 @@ use util::{
 // Frequency of PM Timer in HZ.
 const PM_TIMER_FREQUENCY: u128 = 3_579_545;
 const ACPI_BITMASK_SLEEP_ENABLE: u16 = 0x2000;
+const ACPI_BITMASK_SLEEP_TYPE: u16 = 0x1C00;
+const ACPI_SLEEP_TYPE_SHIFT: u16 = 10;
+const ACPI_BITMASK_POWER_BUTTON_STATUS: u16 = 0x0100;
+const ACPI_BITMASK_WAKE_STATUS: u16 = 0x8000;
+
+/// SLP_TYP value of sleep state S3 (suspend to RAM), exposed by `_S3` object.
+pub const ACPI_SLEEP_TYPE_S3: u16 = 1;
+/// SLP_TYP value of sleep state S5 (soft off), exposed by `_S5` object.
+pub const ACPI_SLEEP_TYPE_S5: u16 = 5;
 
 /// ACPI Power Management Timer
 #[allow(clippy::upper_case_acronyms)]
@@ -75,6 +84,28 @@ impl AcpiPmEvent {
         }
     }
 
+    /// Set the status of power button, which is cleared by guest.
+    pub fn press_power_button(&mut self) {
+        self.status |= ACPI_BITMASK_POWER_BUTTON_STATUS;
+    }
+
+    /// Set the wake status, which tells guest that system is woken up from sleep state.
+    pub fn set_wake_status(&mut self) {
+        self.status |= ACPI_BITMASK_WAKE_STATUS;
+    }
+
+    /// Reset the registers. The wake status is kept as the system is reset when it's
+    /// woken up, and guest checks it after resume.
+    pub fn reset(&mut self) {
+        self.status &= ACPI_BITMASK_WAKE_STATUS;
+        self.enable = 0;
+    }
+
+    /// SCI is asserted while any enabled event is pending.
+    pub fn sci_level(&self) -> bool {
+        self.status & self.enable != 0
+    }
+
     pub fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
         match offset {
             0 => write_data_u16(data, self.status),
@@ -125,7 +156,16 @@ impl AcpiPmCtrl {
         write_data_u16(data, self.control)
     }
 
-    // Return true when guest want poweroff.
+    pub fn reset(&mut self) {
+        self.control = 0;
+    }
+
+    /// Sleep type of the last sleep request.
+    pub fn sleep_type(&self) -> u16 {
+        (self.control & ACPI_BITMASK_SLEEP_TYPE) >> ACPI_SLEEP_TYPE_SHIFT
+    }
+
+    // Return true when guest want to enter sleep state, see `sleep_type` for the state.
     pub fn write(&mut self, data: &[u8], _base: GuestAddress, _offset: u64) -> bool {
         let mut value = 0;
         if !read_data_u16(data, &mut value) {
@@ -27,6 +27,9 @@ pub const ACPI_GTDT_CAP_ALWAYS_ON: u32 = 4;
 /// IORT node types, reference: ARM Document number: ARM DEN 0049B, October 2015.
 pub const ACPI_IORT_NODE_ITS_GROUP: u8 = 0x00;
 pub const ACPI_IORT_NODE_PCI_ROOT_COMPLEX: u8 = 0x02;
+/// VIOT node types, reference: ACPI Specification 6.5, section 5.2.32.
+pub const ACPI_VIOT_NODE_PCI_RANGE: u8 = 0x01;
+pub const ACPI_VIOT_NODE_VIRTIO_PCI_IOMMU: u8 = 0x03;
 /// Root Complex Node in IORT
 pub const ROOT_COMPLEX_ENTRY_SIZE: u16 = 36;
 pub const ID_MAPPING_ENTRY_SIZE: u16 = 20;
@@ -56,6 +59,16 @@ impl AcpiGenericAddress {
             address: addr.into(),
         }
     }
+
+    pub fn new_mem_address<T: Into<u64>>(addr: T) -> AcpiGenericAddress {
+        AcpiGenericAddress {
+            space_id: 0,
+            bit_width: 8 * std::mem::size_of::<T>() as u8,
+            bit_offset: 0,
+            access_size: std::mem::size_of::<T>() as u8,
+            address: addr.into(),
+        }
+    }
 }
 
 impl ByteCode for AcpiGenericAddress {}
@@ -511,6 +524,32 @@ pub mod madt_subtable {
             Vec::from(self.as_bytes())
         }
     }
+
+    /// Interrupt Source Override structure.
+    #[repr(C, packed)]
+    #[derive(Default, Copy, Clone)]
+    pub struct AcpiInterruptSourceOverride {
+        /// Type ID.
+        pub type_id: u8,
+        /// The length of this structure.
+        pub length: u8,
+        /// Bus, 0 means ISA.
+        pub bus: u8,
+        /// Bus-relative interrupt source.
+        pub source: u8,
+        /// The GSI that this bus-relative interrupt source will signal.
+        pub gsi: u32,
+        /// MPS INTI flags, polarity and trigger mode.
+        pub flags: u16,
+    }
+
+    impl ByteCode for AcpiInterruptSourceOverride {}
+
+    impl AmlBuilder for AcpiInterruptSourceOverride {
+        fn aml_bytes(&self) -> Vec<u8> {
+            Vec::from(self.as_bytes())
+        }
+    }
 }
 
 /// This module describes ACPI MADT's sub-tables on aarch64 platform.
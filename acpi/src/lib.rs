@@ -17,7 +17,9 @@ pub mod error;
 mod acpi_device;
 mod table_loader;
 
-pub use acpi_device::{AcpiPMTimer, AcpiPmCtrl, AcpiPmEvent};
+pub use acpi_device::{
+    AcpiPMTimer, AcpiPmCtrl, AcpiPmEvent, ACPI_SLEEP_TYPE_S3, ACPI_SLEEP_TYPE_S5,
+};
 pub use acpi_table::madt_subtable::*;
 pub use acpi_table::*;
 pub use aml_compiler::*;
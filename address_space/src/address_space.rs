@@ -19,13 +19,15 @@ use anyhow::{anyhow, Context, Result};
 use arc_swap::ArcSwap;
 
 use crate::{
-    AddressRange, AddressSpaceError, FlatRange, GuestAddress, Listener, ListenerReqType, Region,
-    RegionIoEventFd, RegionType,
+    AddressRange, AddressSpaceError, FlatRange, GuestAddress, IommuTranslate, Listener,
+    ListenerReqType, Region, RegionIoEventFd, RegionType,
 };
 use migration::{migration::Migratable, MigrationManager};
 use util::aio::Iovec;
 use util::byte_code::ByteCode;
+use util::num_ops::{round_down, round_up};
 use util::test_helper::is_test_enabled;
+use util::unix::host_page_size;
 
 /// Contains an array of `FlatRange`.
 #[derive(Default, Clone, Debug)]
@@ -127,11 +129,18 @@ pub struct AddressSpace {
     root: Region,
     /// `flat_view` is the output of rendering all regions in parent `address-space`,
     /// every time the topology changed (add/delete region), `flat_view` would be updated.
+    /// It is an immutable snapshot published atomically, so readers such as DMA of
+    /// devices look up regions without taking any lock.
     flat_view: Arc<ArcSwap<FlatView>>,
+    /// Serialize the topology updates, which render and publish a new `flat_view` and
+    /// notify the listeners of the difference from the old one.
+    topology_lock: Arc<Mutex<()>>,
     /// The triggered call-backs when flat_view changed.
     listeners: Arc<Mutex<Vec<ListenerObj>>>,
     /// The current layout of ioeventfds, which is compared with new ones in topology-update stage.
     ioeventfds: Arc<Mutex<Vec<RegionIoEventFd>>>,
+    /// DMA address translation, if it's the view of a device behind the IOMMU.
+    iommu: Option<Arc<dyn IommuTranslate>>,
 }
 
 impl fmt::Debug for AddressSpace {
@@ -156,8 +165,10 @@ impl AddressSpace {
             name: String::from(name),
             root: root.clone(),
             flat_view: Arc::new(ArcSwap::new(Arc::new(FlatView::default()))),
+            topology_lock: Arc::new(Mutex::new(())),
             listeners: Arc::new(Mutex::new(Vec::new())),
             ioeventfds: Arc::new(Mutex::new(Vec::new())),
+            iommu: None,
         });
 
         root.set_belonged_address_space(&space);
@@ -175,6 +186,93 @@ impl AddressSpace {
         &self.root
     }
 
+    /// Create a view of AddressSpace for the DMA of a device behind the IOMMU, in which the
+    /// addresses are IO virtual addresses and translated by `iommu`. The view shares the
+    /// regions and listeners with this AddressSpace.
+    ///
+    /// # Arguments
+    ///
+    /// * `iommu` - DMA address translation of the device.
+    /// * `name` - the name of the view.
+    pub fn new_iommu_view(&self, iommu: Arc<dyn IommuTranslate>, name: &str) -> Arc<AddressSpace> {
+        let mut view = self.clone();
+        view.name = String::from(name);
+        view.iommu = Some(iommu);
+        Arc::new(view)
+    }
+
+    /// Get the DMA address translation if it's the view of a device behind the IOMMU.
+    pub fn iommu(&self) -> Option<&Arc<dyn IommuTranslate>> {
+        self.iommu.as_ref()
+    }
+
+    /// Translate the address to guest physical address, returns the guest physical address
+    /// and the size of contiguous range starting from `addr`.
+    fn translate(&self, addr: GuestAddress) -> Option<(GuestAddress, u64)> {
+        match self.iommu.as_ref() {
+            Some(iommu) => iommu.translate(addr).filter(|(_, size)| *size != 0),
+            None => Some((addr, u64::MAX - addr.raw_value())),
+        }
+    }
+
+    /// Split the address range into pieces of contiguous guest physical address.
+    fn translate_range(&self, addr: GuestAddress, count: u64) -> Result<Vec<(GuestAddress, u64)>> {
+        let mut ranges = Vec::new();
+        let mut start = addr;
+        let mut len = count;
+        while len > 0 {
+            let (gpa, size) = self
+                .translate(start)
+                .ok_or_else(|| anyhow!(AddressSpaceError::IommuFault(start.raw_value())))?;
+            let l = std::cmp::min(len, size);
+            ranges.push((gpa, l));
+            start = start.unchecked_add(l);
+            len -= l;
+        }
+        Ok(ranges)
+    }
+
+    /// Return the RAM ranges which can be accessed by DMA. If it's the view of a device behind
+    /// the IOMMU, the ranges are the mapped ones and their addresses are IO virtual addresses.
+    pub fn dma_ram_ranges(&self) -> Vec<FlatRange> {
+        let view = self.flat_view.load();
+        let mappings = match self.iommu.as_ref().and_then(|iommu| iommu.mappings()) {
+            Some(mappings) => mappings,
+            None => {
+                return view
+                    .0
+                    .iter()
+                    .filter(|fr| fr.owner.region_type() == RegionType::Ram)
+                    .cloned()
+                    .collect();
+            }
+        };
+
+        let mut ranges = Vec::new();
+        for mapping in mappings {
+            let mut offset = 0;
+            while offset < mapping.size {
+                let gpa = GuestAddress(mapping.gpa + offset);
+                let fr = match view.find_flatrange(gpa) {
+                    Some(fr) => fr,
+                    None => break,
+                };
+                let fr_offset = gpa.offset_from(fr.addr_range.base);
+                let len = std::cmp::min(mapping.size - offset, fr.addr_range.size - fr_offset);
+                if fr.owner.region_type() == RegionType::Ram {
+                    ranges.push(FlatRange {
+                        addr_range: AddressRange::new(GuestAddress(mapping.iova + offset), len),
+                        owner: fr.owner.clone(),
+                        offset_in_region: fr.offset_in_region + fr_offset,
+                        rom_dev_romd: None,
+                    });
+                }
+                offset += len;
+            }
+        }
+        ranges
+    }
+
     pub fn memspace_show(&self) {
         let view = self.flat_view.load();
 
@@ -438,6 +536,7 @@ impl AddressSpace {
     ///
     /// * `addr` - Guest address.
     pub fn get_host_address(&self, addr: GuestAddress) -> Option<u64> {
+        let (addr, _) = self.translate(addr)?;
         let view = self.flat_view.load();
 
         view.find_flatrange(addr).and_then(|range| {
@@ -457,6 +556,7 @@ impl AddressSpace {
     /// Return Error if the `addr` is not mapped.
     /// or return the HVA address and available mem length
     pub fn addr_cache_init(&self, addr: GuestAddress) -> Option<(u64, u64)> {
+        let (addr, map_size) = self.translate(addr)?;
         let view = self.flat_view.load();
 
         if let Some(flat_range) = view.find_flatrange(addr) {
@@ -469,7 +569,7 @@ impl AddressSpace {
             return flat_range.owner.get_host_address().map(|host| {
                 (
                     host + region_offset,
-                    std::cmp::min(fr_remain, region_remain),
+                    std::cmp::min(std::cmp::min(fr_remain, region_remain), map_size),
                 )
             });
         }
@@ -539,18 +639,38 @@ impl AddressSpace {
     ///
     /// * `addr` - Guest address.
     pub fn address_in_memory(&self, addr: GuestAddress, size: u64) -> bool {
+        let (addr, map_size) = match self.translate(addr) {
+            Some(translated) => translated,
+            None => return false,
+        };
         let view = &self.flat_view.load();
 
         view.find_flatrange(addr).map_or(false, |range| {
             range.owner.region_type() == RegionType::Ram
                 && size <= range.addr_range.end_addr().offset_from(addr)
+                && size <= map_size
         })
     }
 
     pub fn get_region_cache(&self, addr: GuestAddress) -> Option<RegionCache> {
+        let (gpa, map_size) = self.translate(addr)?;
         let view = &self.flat_view.load();
-        if let Some(range) = view.find_flatrange(addr) {
+        if let Some(range) = view.find_flatrange(gpa) {
             let reg_type = range.owner.region_type();
+            if self.iommu.is_some() {
+                // The cache is in IO virtual address space, and only covers the contiguous
+                // range starting from `addr`.
+                let size = std::cmp::min(range.addr_range.end_addr().offset_from(gpa), map_size);
+                let host_base = range.owner.get_host_address().map_or(0, |host| {
+                    host + range.offset_in_region + gpa.offset_from(range.addr_range.base)
+                });
+                return Some(RegionCache {
+                    reg_type,
+                    host_base,
+                    start: addr.0,
+                    end: addr.0 + size,
+                });
+            }
             let start = range.addr_range.base.0;
             let end = range.addr_range.end_addr().0;
             let host_base = self.get_host_address(GuestAddress(start)).unwrap_or(0);
@@ -576,6 +696,52 @@ impl AddressSpace {
             .map_or(GuestAddress(0), |fr| fr.addr_range.end_addr())
     }
 
+    /// Advise the host how to handle the memory of all Ram regions, such as `MADV_COLD`
+    /// or `MADV_PAGEOUT` which reclaim the memory of a paused VM without losing its content.
+    /// Return the size of the advised memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `advice` - The advice passed to madvise.
+    pub fn advise_ram(&self, advice: libc::c_int) -> Result<u64> {
+        let page_size = host_page_size();
+        let mut advised = 0;
+        for fr in self.flat_view.load().0.iter() {
+            if fr.owner.region_type() != RegionType::Ram {
+                continue;
+            }
+            let host_addr = match fr.owner.get_host_address() {
+                Some(host_addr) => host_addr + fr.offset_in_region,
+                None => continue,
+            };
+            let start = round_up(host_addr, page_size).unwrap_or(u64::MAX);
+            let end = round_down(host_addr + fr.addr_range.size, page_size).unwrap_or(0);
+            if start >= end {
+                continue;
+            }
+
+            let ret = unsafe {
+                libc::madvise(
+                    start as *mut libc::c_void,
+                    (end - start) as libc::size_t,
+                    advice,
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error()).with_context(|| {
+                    format!(
+                        "Failed to advise memory 0x{:X} size 0x{:X} with {}",
+                        fr.addr_range.base.raw_value(),
+                        fr.addr_range.size,
+                        advice
+                    )
+                });
+            }
+            advised += end - start;
+        }
+        Ok(advised)
+    }
+
     /// Read memory segment to `dst`.
     ///
     /// # Arguments
@@ -590,6 +756,12 @@ impl AddressSpace {
     pub fn read(&self, dst: &mut dyn std::io::Write, addr: GuestAddress, count: u64) -> Result<()> {
         let view = self.flat_view.load();
 
+        if self.iommu.is_some() {
+            for (gpa, len) in self.translate_range(addr, count)? {
+                view.read(dst, gpa, len)?;
+            }
+            return Ok(());
+        }
         view.read(dst, addr, count)?;
         Ok(())
     }
@@ -632,6 +804,12 @@ impl AddressSpace {
             }
         }
 
+        if self.iommu.is_some() {
+            for (gpa, len) in self.translate_range(addr, count)? {
+                view.write(src, gpa, len)?;
+            }
+            return Ok(());
+        }
         view.write(src, addr, count)?;
         Ok(())
     }
@@ -711,7 +889,8 @@ impl AddressSpace {
 
     /// Update the topology of memory.
     pub fn update_topology(&self) -> Result<()> {
-        let old_fv = self.flat_view.load();
+        let _topology_guard = self.topology_lock.lock().unwrap();
+        let old_fv = self.flat_view.load_full();
 
         let addr_range = AddressRange::new(GuestAddress(0), self.root.size());
         let new_fv = self
@@ -736,7 +915,7 @@ mod test {
     use vmm_sys_util::eventfd::EventFd;
 
     use super::*;
-    use crate::{HostMemMapping, RegionOps};
+    use crate::{HostMemMapping, IommuMapping, IommuNotifier, RegionOps};
 
     #[derive(Default, Clone)]
     struct TestListener {
@@ -1019,6 +1198,55 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_concurrent_update_topology() {
+        let root = Region::init_container_region(0x10000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let listener = Arc::new(Mutex::new(TestListener::default()));
+        space.register_listener(listener.clone()).unwrap();
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let reader_space = space.clone();
+        let reader_done = done.clone();
+        // The reader always sees a consistent snapshot while the topology is being updated.
+        let reader = std::thread::spawn(move || {
+            while !reader_done.load(std::sync::atomic::Ordering::SeqCst) {
+                let view = reader_space.flat_view.load();
+                for pair in view.0.windows(2) {
+                    assert!(pair[0].addr_range.end_addr() <= pair[1].addr_range.base);
+                }
+            }
+        });
+
+        let writers: Vec<_> = (0..8_u64)
+            .map(|i| {
+                let root = root.clone();
+                std::thread::spawn(move || {
+                    let ops = RegionOps {
+                        read: Arc::new(|_: &mut [u8], _: GuestAddress, _: u64| -> bool { true }),
+                        write: Arc::new(|_: &[u8], _: GuestAddress, _: u64| -> bool { true }),
+                    };
+                    let region = Region::init_io_region(0x1000, ops, "region");
+                    root.add_subregion(region, i * 0x1000).unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        reader.join().unwrap();
+
+        assert_eq!(space.flat_view.load().0.len(), 8);
+        // Each update is notified exactly once, based on the view published by the previous one.
+        let locked_listener = listener.lock().unwrap();
+        let reqs = locked_listener.reqs.lock().unwrap();
+        assert_eq!(reqs.len(), 8);
+        assert!(reqs
+            .iter()
+            .all(|(req_type, _)| matches!(req_type, ListenerReqType::AddRegion)));
+    }
+
     #[test]
     fn test_update_ioeventfd() {
         let ioeventfds = vec![RegionIoEventFd {
@@ -1211,4 +1439,95 @@ mod test {
         assert_eq!(data1, 10000);
         assert!(space.write_object(&data, GuestAddress(993)).is_err());
     }
+
+    #[test]
+    fn test_advise_ram() {
+        let root = Region::init_container_region(0x10000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x8000, None, false, false, false).unwrap(),
+        );
+        root.add_subregion(Region::init_ram_region(ram, "ram"), 0)
+            .unwrap();
+
+        let data: u64 = 10000;
+        space.write_object(&data, GuestAddress(0x4000)).unwrap();
+        assert_eq!(space.advise_ram(libc::MADV_COLD).unwrap(), 0x8000);
+        assert_eq!(space.advise_ram(libc::MADV_PAGEOUT).unwrap(), 0x8000);
+        // The content of memory is kept after reclaimed.
+        let data1: u64 = space.read_object(GuestAddress(0x4000)).unwrap();
+        assert_eq!(data1, 10000);
+    }
+
+    struct TestIommu {
+        mapping: IommuMapping,
+    }
+
+    impl IommuTranslate for TestIommu {
+        fn translate(&self, iova: GuestAddress) -> Option<(GuestAddress, u64)> {
+            let end = self.mapping.iova + self.mapping.size;
+            if iova.0 < self.mapping.iova || iova.0 >= end {
+                return None;
+            }
+            Some((
+                GuestAddress(self.mapping.gpa + iova.0 - self.mapping.iova),
+                end - iova.0,
+            ))
+        }
+
+        fn mappings(&self) -> Option<Vec<IommuMapping>> {
+            Some(vec![self.mapping])
+        }
+
+        fn add_notifier(&self, _notifier: IommuNotifier) {}
+
+        fn clear_notifiers(&self) {}
+    }
+
+    #[test]
+    fn test_iommu_view() {
+        let root = Region::init_container_region(8000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+        );
+        let region_a = Region::init_ram_region(ram1.clone(), "region_a");
+        root.add_subregion(region_a, ram1.start_address().raw_value())
+            .unwrap();
+
+        let iommu = Arc::new(TestIommu {
+            mapping: IommuMapping {
+                iova: 0x10000,
+                gpa: 500,
+                size: 500,
+            },
+        });
+        let view = space.new_iommu_view(iommu, "view");
+        assert!(view.iommu().is_some());
+
+        let data: u64 = 10000;
+        assert!(view.write_object(&data, GuestAddress(0x10008)).is_ok());
+        let data1: u64 = space.read_object(GuestAddress(508)).unwrap();
+        assert_eq!(data1, 10000);
+        assert_eq!(
+            view.get_host_address(GuestAddress(0x10000)),
+            Some(ram1.host_address() + 500)
+        );
+        assert!(view.address_in_memory(GuestAddress(0x10000), 500));
+
+        // Not mapped, or exceeding the end of mapping.
+        assert!(view.read_object::<u64>(GuestAddress(508)).is_err());
+        assert!(view
+            .write_object(&data, GuestAddress(0x10000 + 496))
+            .is_err());
+        assert!(!view.address_in_memory(GuestAddress(0x10000), 501));
+        assert!(view.get_host_address(GuestAddress(0x10000 + 500)).is_none());
+
+        let ranges = view.dma_ram_ranges();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].addr_range.base, GuestAddress(0x10000));
+        assert_eq!(ranges[0].addr_range.size, 500);
+        assert_eq!(ranges[0].offset_in_region, 500);
+        assert_eq!(space.dma_ram_ranges()[0].addr_range.size, 1000);
+    }
 }
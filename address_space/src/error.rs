@@ -48,6 +48,8 @@ pub enum AddressSpaceError {
     NoMatchedKvmSlot(u64, u64),
     #[error("Added KVM mem range (0x{:X}, 0x{:X}) overlaps with exist one (0x{:X}, 0x{:X})", add.0, add.1, exist.0, exist.1)]
     KvmSlotOverlap { add: (u64, u64), exist: (u64, u64) },
+    #[error("DMA to IO virtual address 0x{0:X} is blocked by IOMMU")]
+    IommuFault(u64),
     #[error("Invalid offset: offset 0x{0:X}, data length 0x{1:X}, region size 0x{2:X}")]
     InvalidOffset(u64, u64, u64),
 }
@@ -10,11 +10,14 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::fs::File;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
 use log::{error, info};
@@ -27,6 +30,14 @@ use util::{
 };
 
 const MAX_PREALLOC_THREAD: u8 = 16;
+/// Number of pages touched before updating the preallocation progress.
+const PREALLOC_PROGRESS_BATCH: u64 = 1024;
+/// Interval to log the preallocation progress.
+const PREALLOC_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Sysfs directory of the hugepage pools.
+const HUGEPAGES_SYSFS_DIR: &str = "/sys/kernel/mm/hugepages";
+/// Magic number of hugetlbfs.
+const HUGETLBFS_MAGIC: i64 = 0x9584_58f6;
 /// Verify existing pages in the mapping.
 const MPOL_MF_STRICT: u32 = 1;
 /// Move pages owned by this process to conform to mapping.
@@ -177,6 +188,16 @@ fn max_nr_threads(nr_vcpus: u8) -> u8 {
     1
 }
 
+/// Get the number of threads to touch pages.
+///
+/// # Arguments
+///
+/// * `threads` - Number of threads configured by user.
+/// * `nr_vcpus` - Number of vcpus.
+fn prealloc_threads(threads: Option<u8>, nr_vcpus: u8) -> u8 {
+    threads.unwrap_or_else(|| max_nr_threads(nr_vcpus))
+}
+
 /// Touch pages to pre-alloc memory for VM.
 ///
 /// # Arguments
@@ -184,19 +205,26 @@ fn max_nr_threads(nr_vcpus: u8) -> u8 {
 /// * `start` - The start host address of memory segment.
 /// * `page_size` - Size of host page.
 /// * `nr_pages` - Number of pages.
-fn touch_pages(start: u64, page_size: u64, nr_pages: u64) {
+/// * `progress` - Number of pages touched by all the threads.
+fn touch_pages(start: u64, page_size: u64, nr_pages: u64, progress: &AtomicU64) {
     let mut addr = start;
-    for _i in 0..nr_pages {
-        // Safe, because the data read from raw pointer is written to the same address.
-        unsafe {
-            let read_addr = addr as *mut u8;
-            let data: u8 = *read_addr;
-            // This function is used to prevent compiler optimization.
-            // If `*read = data` is used, the compiler optimizes it as no-op,
-            // which means that the pages will not be touched.
-            std::ptr::write_volatile(read_addr, data);
+    let mut touched = 0;
+    while touched < nr_pages {
+        let batch = min(PREALLOC_PROGRESS_BATCH, nr_pages - touched);
+        for _i in 0..batch {
+            // Safe, because the data read from raw pointer is written to the same address.
+            unsafe {
+                let read_addr = addr as *mut u8;
+                let data: u8 = *read_addr;
+                // This function is used to prevent compiler optimization.
+                // If `*read = data` is used, the compiler optimizes it as no-op,
+                // which means that the pages will not be touched.
+                std::ptr::write_volatile(read_addr, data);
+            }
+            addr += page_size;
         }
-        addr += page_size;
+        touched += batch;
+        progress.fetch_add(batch, Ordering::Relaxed);
     }
 }
 
@@ -206,13 +234,14 @@ fn touch_pages(start: u64, page_size: u64, nr_pages: u64) {
 ///
 /// * `host_addr` - The start host address to pre allocate.
 /// * `size` - Size of memory.
-/// * `nr_vcpus` - Number of vcpus.
-fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
-    let page_size = host_page_size();
-    let threads = max_nr_threads(nr_vcpus);
-    let nr_pages = (size + page_size - 1) / page_size;
+/// * `page_size` - Size of the pages backing the memory.
+/// * `threads` - Number of threads to touch pages.
+fn mem_prealloc(host_addr: u64, size: u64, page_size: u64, threads: u8) {
+    let nr_pages = size.div_ceil(page_size);
     let pages_per_thread = nr_pages / (threads as u64);
     let left = nr_pages % (threads as u64);
+    let progress = Arc::new(AtomicU64::new(0));
+    let (done_sender, done_receiver) = channel();
     let mut addr = host_addr;
     let mut threads_join = Vec::new();
     for i in 0..threads {
@@ -221,18 +250,108 @@ fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
         } else {
             pages_per_thread
         };
+        let progress = progress.clone();
+        let done_sender = done_sender.clone();
         let thread = thread::spawn(move || {
-            touch_pages(addr, page_size, touch_nr_pages);
+            touch_pages(addr, page_size, touch_nr_pages, &progress);
+            let _ = done_sender.send(());
         });
         threads_join.push(thread);
         addr += touch_nr_pages * page_size;
     }
+    drop(done_sender);
+
+    info!(
+        "Preallocating memory: {} pages of size {} with {} threads",
+        nr_pages, page_size, threads
+    );
+    let start = Instant::now();
+    let mut finished = 0;
+    while finished < threads {
+        match done_receiver.recv_timeout(PREALLOC_PROGRESS_INTERVAL) {
+            Ok(()) => finished += 1,
+            Err(RecvTimeoutError::Timeout) => {
+                let touched = progress.load(Ordering::Relaxed);
+                info!(
+                    "Preallocating memory: {}/{} pages ({}%)",
+                    touched,
+                    nr_pages,
+                    touched * 100 / max(nr_pages, 1)
+                );
+            }
+            // All the threads exit, maybe some of them panic.
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
     // join all threads to wait for pre-allocating.
     while let Some(thread) = threads_join.pop() {
         if let Err(ref e) = thread.join() {
             error!("Failed to join thread: {:?}", e);
         }
     }
+    info!(
+        "Preallocating memory: finished in {} ms",
+        start.elapsed().as_millis()
+    );
+}
+
+fn read_hugepages_count(page_size: u64, name: &str) -> Result<u64> {
+    let path = format!(
+        "{}/hugepages-{}kB/{}",
+        HUGEPAGES_SYSFS_DIR,
+        page_size >> 10,
+        name
+    );
+    let count = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path))?
+        .trim()
+        .parse::<u64>()
+        .with_context(|| format!("Failed to parse {}", path))?;
+    Ok(count)
+}
+
+/// Get the hugepage size if memory is backed by hugetlbfs.
+///
+/// # Arguments
+///
+/// * `f_back` - File that backs memory.
+fn hugepage_size(f_back: &Option<FileBackend>) -> Option<u64> {
+    let fb = f_back.as_ref()?;
+    // Safe because struct `statfs` only contains plain-data-type field,
+    // and set to all-zero will not cause any undefined behavior.
+    let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the file is valid and fstat is a valid statfs struct.
+    if unsafe { libc::fstatfs(fb.file.as_raw_fd(), &mut fstat) } != 0 {
+        return None;
+    }
+    if fstat.f_type as i64 == HUGETLBFS_MAGIC {
+        Some(fstat.f_bsize as u64)
+    } else {
+        None
+    }
+}
+
+/// Check the free hugepages are sufficient before preallocating memory, so that the VM
+/// fails fast instead of being killed by SIGBUS when touching pages.
+///
+/// # Arguments
+///
+/// * `page_size` - Size of the hugepage.
+/// * `size` - Size of memory.
+fn check_hugepages(page_size: u64, size: u64) -> Result<()> {
+    let free = read_hugepages_count(page_size, "free_hugepages")?;
+    let reserved = read_hugepages_count(page_size, "resv_hugepages")?;
+    let available = free.saturating_sub(reserved);
+    let needed = size.div_ceil(page_size);
+    if available < needed {
+        bail!(
+            "Insufficient hugepages of size {}kB for preallocation, needed {}, available {}",
+            page_size >> 10,
+            needed,
+            available
+        );
+    }
+    Ok(())
 }
 
 /// If the memory is not configured numa, use this
@@ -269,6 +388,13 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
             page_size: host_page_size(),
         });
     }
+    let hugepage_size = hugepage_size(&f_back);
+    if mem_config.mem_prealloc && mem_config.prealloc_check {
+        if let Some(page_size) = hugepage_size {
+            check_hugepages(page_size, mem_config.mem_size)?;
+        }
+    }
+    let page_size = hugepage_size.unwrap_or_else(host_page_size);
     let block = Arc::new(HostMemMapping::new(
         GuestAddress(0),
         None,
@@ -280,7 +406,12 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
     )?);
 
     if mem_config.mem_prealloc {
-        mem_prealloc(block.host_address(), mem_config.mem_size, thread_num);
+        mem_prealloc(
+            block.host_address(),
+            mem_config.mem_size,
+            page_size,
+            prealloc_threads(mem_config.prealloc_threads, thread_num),
+        );
     }
     let region = Region::init_ram_region(block, "DefaultRam");
 
@@ -321,6 +452,13 @@ pub fn create_backend_mem(mem_config: &MemZoneConfig, thread_num: u8) -> Result<
                 .with_context(|| "Failed to create file that backs memory")?,
         );
     }
+    let hugepage_size = hugepage_size(&f_back);
+    if mem_config.prealloc && mem_config.prealloc_check {
+        if let Some(page_size) = hugepage_size {
+            check_hugepages(page_size, mem_config.size)?;
+        }
+    }
+    let page_size = hugepage_size.unwrap_or_else(host_page_size);
     let block = Arc::new(HostMemMapping::new(
         GuestAddress(0),
         None,
@@ -331,7 +469,12 @@ pub fn create_backend_mem(mem_config: &MemZoneConfig, thread_num: u8) -> Result<
         false,
     )?);
     if mem_config.prealloc {
-        mem_prealloc(block.host_address(), mem_config.size, thread_num);
+        mem_prealloc(
+            block.host_address(),
+            mem_config.size,
+            page_size,
+            prealloc_threads(mem_config.prealloc_threads, thread_num),
+        );
     }
     set_host_memory_policy(&block, mem_config)?;
 
@@ -597,7 +740,8 @@ mod test {
         assert_eq!(max_nr_threads(1), 1);
         // The max threads limit is 16, or the number of host CPUs, it will never be 20.
         assert_ne!(max_nr_threads(20), 20);
-        mem_prealloc(host_addr, 0x20_0000, 20);
+        assert_eq!(prealloc_threads(Some(20), 1), 20);
+        mem_prealloc(host_addr, 0x20_0000, host_page_size(), max_nr_threads(20));
 
         // Mmap and prealloc with file backend.
         let file_path = String::from("back_mem_test");
@@ -612,7 +756,8 @@ mod test {
             false,
         )
         .unwrap();
-        mem_prealloc(host_addr, 0x10_0000, 2);
+        mem_prealloc(host_addr, 0x10_0000, host_page_size(), 2);
+        assert_eq!(hugepage_size(&Some(f_back)), None);
         std::fs::remove_file(file_path).unwrap();
     }
 }
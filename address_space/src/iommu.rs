@@ -0,0 +1,46 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::Arc;
+
+use crate::GuestAddress;
+
+/// Mapping from a range of IO virtual address to guest physical address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IommuMapping {
+    /// Start IO virtual address.
+    pub iova: u64,
+    /// Start guest physical address.
+    pub gpa: u64,
+    /// Size of the mapping.
+    pub size: u64,
+}
+
+/// Callback which is called after the mappings of a device behind the IOMMU are changed.
+pub type IommuNotifier = Arc<dyn Fn() + Send + Sync>;
+
+/// DMA address translation of a device behind the IOMMU.
+pub trait IommuTranslate: Send + Sync {
+    /// Translate the IO virtual address to guest physical address. Return the guest physical
+    /// address and the size of the contiguous mapping starting from `iova`, or None if the
+    /// DMA to `iova` is blocked.
+    fn translate(&self, iova: GuestAddress) -> Option<(GuestAddress, u64)>;
+
+    /// Return all mappings of the device, or None if the DMA of the device bypasses the IOMMU.
+    fn mappings(&self) -> Option<Vec<IommuMapping>>;
+
+    /// Add a notifier of mapping changes.
+    fn add_notifier(&self, notifier: IommuNotifier);
+
+    /// Remove all notifiers of mapping changes.
+    fn clear_notifiers(&self);
+}
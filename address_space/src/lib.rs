@@ -84,6 +84,7 @@ pub mod error;
 mod address;
 mod address_space;
 mod host_mmap;
+mod iommu;
 mod listener;
 mod region;
 mod state;
@@ -94,6 +95,7 @@ pub use crate::address_space::{AddressSpace, RegionCache};
 pub use address::{AddressRange, GuestAddress};
 pub use error::AddressSpaceError;
 pub use host_mmap::{create_backend_mem, create_default_mem, FileBackend, HostMemMapping};
+pub use iommu::{IommuMapping, IommuNotifier, IommuTranslate};
 #[cfg(target_arch = "x86_64")]
 pub use listener::KvmIoListener;
 pub use listener::KvmMemoryListener;
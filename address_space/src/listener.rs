@@ -14,12 +14,10 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, bail, Context, Result};
-use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_READONLY};
-use kvm_ioctls::{IoEventAddress, NoDatamatch};
 use log::{debug, warn};
 
 use crate::{AddressRange, AddressSpaceError, FlatRange, RegionIoEventFd, RegionType};
-use hypervisor::kvm::KVM_FDS;
+use hypervisor::{hypervisor, DataMatch, IoEventAddress, MemoryRegion};
 use util::{num_ops::round_down, unix::host_page_size};
 
 /// Request type of listener.
@@ -65,6 +63,20 @@ pub trait Listener: Send + Sync {
     }
 }
 
+/// Get the data that the guest writes must match to signal the ioeventfd.
+fn ioevent_datamatch(ioevtfd: &RegionIoEventFd) -> Result<Option<DataMatch>> {
+    if !ioevtfd.data_match {
+        return Ok(None);
+    }
+    let datamatch = match ioevtfd.addr_range.size {
+        2 => DataMatch::U16(ioevtfd.data as u16),
+        4 => DataMatch::U32(ioevtfd.data as u32),
+        8 => DataMatch::U64(ioevtfd.data),
+        length => bail!("Unexpected ioeventfd data length {}", length),
+    };
+    Ok(Some(datamatch))
+}
+
 /// Records information that manage the slot resource and current usage.
 #[derive(Default, Copy, Clone)]
 struct MemSlot {
@@ -245,41 +257,24 @@ impl KvmMemoryListener {
             .get_free_slot(aligned_addr.raw_value(), aligned_size, aligned_hva)
             .with_context(|| "Failed to get available KVM mem slot")?;
 
-        let mut flags = 0_u32;
-        if flat_range.owner.get_rom_device_romd().unwrap_or(false) {
-            flags |= KVM_MEM_READONLY;
-        }
-        let kvm_region = kvm_userspace_memory_region {
+        let region = MemoryRegion {
             slot: slot_idx | (self.as_id.load(Ordering::SeqCst) << 16),
             guest_phys_addr: aligned_addr.raw_value(),
             memory_size: aligned_size,
             userspace_addr: aligned_hva,
-            flags,
+            read_only: flat_range.owner.get_rom_device_romd().unwrap_or(false),
         };
-        unsafe {
-            KVM_FDS
-                .load()
-                .add_mem_slot(kvm_region)
-                .with_context(|| "Failed to add memory slot to kvm")?;
-
-            KVM_FDS
-                .load()
-                .vm_fd
-                .as_ref()
-                .unwrap()
-                .set_user_memory_region(kvm_region)
-                .or_else(|e| {
-                    self.delete_slot(aligned_addr.raw_value(), aligned_size)
-                        .with_context(|| "Failed to delete Kvm mem slot")?;
-                    Err(e).with_context(|| {
-                        format!(
-                            "KVM register memory region failed: addr 0x{:X}, size 0x{:X}",
-                            aligned_addr.raw_value(),
-                            aligned_size
-                        )
-                    })
-                })?;
-        }
+        hypervisor().set_user_memory_region(region).or_else(|e| {
+            self.delete_slot(aligned_addr.raw_value(), aligned_size)
+                .with_context(|| "Failed to delete Kvm mem slot")?;
+            Err(e).with_context(|| {
+                format!(
+                    "KVM register memory region failed: addr 0x{:X}, size 0x{:X}",
+                    aligned_addr.raw_value(),
+                    aligned_size
+                )
+            })
+        })?;
         Ok(())
     }
 
@@ -309,32 +304,21 @@ impl KvmMemoryListener {
             }
         };
 
-        let kvm_region = kvm_userspace_memory_region {
+        let region = MemoryRegion {
             slot: mem_slot.index | (self.as_id.load(Ordering::SeqCst) << 16),
             guest_phys_addr: mem_slot.guest_addr,
             memory_size: 0_u64,
             userspace_addr: mem_slot.host_addr,
-            flags: 0,
+            read_only: false,
         };
-        unsafe {
-            KVM_FDS
-                .load()
-                .remove_mem_slot(kvm_region)
-                .with_context(|| "Failed to remove memory slot to kvm")?;
-
-            KVM_FDS
-                .load()
-                .vm_fd
-                .as_ref()
-                .unwrap()
-                .set_user_memory_region(kvm_region)
-                .with_context(|| {
-                    format!(
-                        "KVM unregister memory region failed: addr 0x{:X}",
-                        aligned_addr.raw_value(),
-                    )
-                })?;
-        }
+        hypervisor()
+            .set_user_memory_region(region)
+            .with_context(|| {
+                format!(
+                    "KVM unregister memory region failed: addr 0x{:X}",
+                    aligned_addr.raw_value(),
+                )
+            })?;
 
         Ok(())
     }
@@ -349,33 +333,21 @@ impl KvmMemoryListener {
     ///
     /// Return Error if the length of ioeventfd data is unexpected or syscall failed.
     fn add_ioeventfd(&self, ioevtfd: &RegionIoEventFd) -> Result<()> {
-        let kvm_fds = KVM_FDS.load();
-        let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
         let io_addr = IoEventAddress::Mmio(ioevtfd.addr_range.base.raw_value());
-        let ioctl_ret = if ioevtfd.data_match {
-            let length = ioevtfd.addr_range.size;
-            match length {
-                2 => vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u16),
-                4 => vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u32),
-                8 => vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data),
-                _ => bail!("Unexpected ioeventfd data length {}", length),
-            }
-        } else {
-            vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, NoDatamatch)
-        };
-
-        ioctl_ret.with_context(|| {
-            format!(
-                "KVM register ioeventfd failed, mmio addr 0x{:X}, size 0x{:X}, data_match {}",
-                ioevtfd.addr_range.base.raw_value(),
-                ioevtfd.addr_range.size,
-                if ioevtfd.data_match {
-                    ioevtfd.data
-                } else {
-                    u64::MAX
-                }
-            )
-        })?;
+        hypervisor()
+            .register_ioevent(&ioevtfd.fd, io_addr, ioevent_datamatch(ioevtfd)?)
+            .with_context(|| {
+                format!(
+                    "KVM register ioeventfd failed, mmio addr 0x{:X}, size 0x{:X}, data_match {}",
+                    ioevtfd.addr_range.base.raw_value(),
+                    ioevtfd.addr_range.size,
+                    if ioevtfd.data_match {
+                        ioevtfd.data
+                    } else {
+                        u64::MAX
+                    }
+                )
+            })?;
 
         Ok(())
     }
@@ -386,33 +358,21 @@ impl KvmMemoryListener {
     ///
     /// * `ioevtfd` - IoEvent would be deleted.
     fn delete_ioeventfd(&self, ioevtfd: &RegionIoEventFd) -> Result<()> {
-        let kvm_fds = KVM_FDS.load();
-        let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
         let io_addr = IoEventAddress::Mmio(ioevtfd.addr_range.base.raw_value());
-        let ioctl_ret = if ioevtfd.data_match {
-            let length = ioevtfd.addr_range.size;
-            match length {
-                2 => vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u16),
-                4 => vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u32),
-                8 => vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data),
-                _ => bail!("Unexpected ioeventfd data length {}", length),
-            }
-        } else {
-            vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, NoDatamatch)
-        };
-
-        ioctl_ret.with_context(|| {
-            format!(
-                "KVM unregister ioeventfd failed: mmio addr 0x{:X}, size 0x{:X}, data_match {}",
-                ioevtfd.addr_range.base.raw_value(),
-                ioevtfd.addr_range.size,
-                if ioevtfd.data_match {
-                    ioevtfd.data
-                } else {
-                    u64::MAX
-                }
-            )
-        })?;
+        hypervisor()
+            .unregister_ioevent(&ioevtfd.fd, io_addr, ioevent_datamatch(ioevtfd)?)
+            .with_context(|| {
+                format!(
+                    "KVM unregister ioeventfd failed: mmio addr 0x{:X}, size 0x{:X}, data_match {}",
+                    ioevtfd.addr_range.base.raw_value(),
+                    ioevtfd.addr_range.size,
+                    if ioevtfd.data_match {
+                        ioevtfd.data
+                    } else {
+                        u64::MAX
+                    }
+                )
+            })?;
 
         Ok(())
     }
@@ -494,33 +454,21 @@ impl KvmIoListener {
     ///
     /// Return Error if the length of ioeventfd data is unexpected or syscall failed.
     fn add_ioeventfd(&self, ioevtfd: &RegionIoEventFd) -> Result<()> {
-        let kvm_fds = KVM_FDS.load();
-        let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
         let io_addr = IoEventAddress::Pio(ioevtfd.addr_range.base.raw_value());
-        let ioctl_ret = if ioevtfd.data_match {
-            let length = ioevtfd.addr_range.size;
-            match length {
-                2 => vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u16),
-                4 => vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u32),
-                8 => vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data),
-                _ => bail!("unexpected ioeventfd data length {}", length),
-            }
-        } else {
-            vm_fd.register_ioevent(&ioevtfd.fd, &io_addr, NoDatamatch)
-        };
-
-        ioctl_ret.with_context(|| {
-            format!(
-                "KVM register ioeventfd failed: io addr 0x{:X}, size 0x{:X}, data_match {}",
-                ioevtfd.addr_range.base.raw_value(),
-                ioevtfd.addr_range.size,
-                if ioevtfd.data_match {
-                    ioevtfd.data
-                } else {
-                    u64::MAX
-                }
-            )
-        })?;
+        hypervisor()
+            .register_ioevent(&ioevtfd.fd, io_addr, ioevent_datamatch(ioevtfd)?)
+            .with_context(|| {
+                format!(
+                    "KVM register ioeventfd failed: io addr 0x{:X}, size 0x{:X}, data_match {}",
+                    ioevtfd.addr_range.base.raw_value(),
+                    ioevtfd.addr_range.size,
+                    if ioevtfd.data_match {
+                        ioevtfd.data
+                    } else {
+                        u64::MAX
+                    }
+                )
+            })?;
 
         Ok(())
     }
@@ -531,33 +479,21 @@ impl KvmIoListener {
     ///
     /// * `ioevtfd` - IoEvent of Region.
     fn delete_ioeventfd(&self, ioevtfd: &RegionIoEventFd) -> Result<()> {
-        let kvm_fds = KVM_FDS.load();
-        let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
         let io_addr = IoEventAddress::Pio(ioevtfd.addr_range.base.raw_value());
-        let ioctl_ret = if ioevtfd.data_match {
-            let length = ioevtfd.addr_range.size;
-            match length {
-                2 => vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u16),
-                4 => vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data as u32),
-                8 => vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, ioevtfd.data),
-                _ => bail!("Unexpected ioeventfd data length {}", length),
-            }
-        } else {
-            vm_fd.unregister_ioevent(&ioevtfd.fd, &io_addr, NoDatamatch)
-        };
-
-        ioctl_ret.with_context(|| {
-            format!(
-                "KVM unregister ioeventfd failed: io addr 0x{:X}, size 0x{:X}, data_match {}",
-                ioevtfd.addr_range.base.raw_value(),
-                ioevtfd.addr_range.size,
-                if ioevtfd.data_match {
-                    ioevtfd.data
-                } else {
-                    u64::MAX
-                }
-            )
-        })?;
+        hypervisor()
+            .unregister_ioevent(&ioevtfd.fd, io_addr, ioevent_datamatch(ioevtfd)?)
+            .with_context(|| {
+                format!(
+                    "KVM unregister ioeventfd failed: io addr 0x{:X}, size 0x{:X}, data_match {}",
+                    ioevtfd.addr_range.base.raw_value(),
+                    ioevtfd.addr_range.size,
+                    if ioevtfd.data_match {
+                        ioevtfd.data
+                    } else {
+                        u64::MAX
+                    }
+                )
+            })?;
 
         Ok(())
     }
@@ -620,7 +556,8 @@ mod test {
 
     use super::*;
     use crate::{GuestAddress, HostMemMapping, Region, RegionIoEventFd};
-    use hypervisor::kvm::KVMFds;
+    use hypervisor::kvm::{KVMFds, KVM_FDS};
+    use kvm_ioctls::NoDatamatch;
 
     fn generate_region_ioeventfd<T: Into<u64>>(addr: u64, datamatch: T) -> RegionIoEventFd {
         let data = datamatch.into();
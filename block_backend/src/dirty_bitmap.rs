@@ -0,0 +1,537 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use log::{error, warn};
+use once_cell::sync::Lazy;
+
+use crate::SECTOR_SIZE;
+use machine_manager::temp_cleaner::{ExitNotifier, TempCleaner};
+use util::bitmap::Bitmap;
+
+pub const DIRTY_BITMAP_GRANULARITY_DEFAULT: u64 = 1 << 16;
+const DIRTY_BITMAP_GRANULARITY_MAX: u64 = 1 << 31;
+const DIRTY_BITMAP_MAGIC: &[u8; 8] = b"SVBITMAP";
+const DIRTY_BITMAP_VERSION: u32 = 1;
+/// The bitmaps are being updated by a running VM, so they are stale if
+/// the flag is still set when loading them.
+const DIRTY_BITMAP_FLAG_IN_USE: u32 = 1;
+/// Magic(8) + version(4) + flags(4) + count(4) + reserved(4).
+const DIRTY_BITMAP_HEADER_SIZE: usize = 24;
+/// Name length(4) + reserved(4) + granularity(8) + disk size(8) + data length(8).
+const DIRTY_BITMAP_ENTRY_SIZE: usize = 32;
+
+type DirtyBitmapListType = Lazy<Arc<Mutex<HashMap<String, Arc<Mutex<DirtyBitmaps>>>>>>;
+/// Dirty bitmaps of all block devices, indexed by drive id.
+pub static DIRTY_BITMAP_LIST: DirtyBitmapListType =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Record which areas of the disk have been written, each bit represents
+/// `granularity` bytes of the disk.
+pub struct DirtyBitmap {
+    name: String,
+    granularity: u64,
+    disk_size: u64,
+    persistent: bool,
+    bitmap: Bitmap<u64>,
+}
+
+impl DirtyBitmap {
+    pub fn new(name: &str, granularity: u64, disk_size: u64, persistent: bool) -> Result<Self> {
+        if !granularity.is_power_of_two()
+            || !(SECTOR_SIZE..=DIRTY_BITMAP_GRANULARITY_MAX).contains(&granularity)
+        {
+            bail!(
+                "Granularity {} of dirty bitmap {} is invalid, it should be power of 2 and within the range of [{}:{}]",
+                granularity,
+                name,
+                SECTOR_SIZE,
+                DIRTY_BITMAP_GRANULARITY_MAX
+            );
+        }
+        let bits = disk_size.div_ceil(granularity);
+        Ok(DirtyBitmap {
+            name: name.to_string(),
+            granularity,
+            disk_size,
+            persistent,
+            bitmap: Bitmap::new(bits.div_ceil(u64::BITS as u64) as usize),
+        })
+    }
+
+    fn bits(&self) -> u64 {
+        self.disk_size.div_ceil(self.granularity)
+    }
+
+    fn mark(&mut self, offset: u64, nbytes: u64) -> Result<()> {
+        if nbytes == 0 || offset >= self.disk_size {
+            return Ok(());
+        }
+        let end = std::cmp::min(offset.saturating_add(nbytes), self.disk_size);
+        let start_bit = offset / self.granularity;
+        let end_bit = end.div_ceil(self.granularity);
+        self.bitmap
+            .set_range(start_bit as usize, (end_bit - start_bit) as usize)
+    }
+
+    fn mark_all(&mut self) -> Result<()> {
+        self.mark(0, self.disk_size)
+    }
+
+    fn clear(&mut self) {
+        self.bitmap.clear_all();
+    }
+
+    /// Get the dirty areas as (offset, length) in bytes, adjacent dirty bits are merged.
+    fn dirty_ranges(&self) -> Result<Vec<(u64, u64)>> {
+        let bits = self.bits() as usize;
+        let mut ranges = Vec::new();
+        let mut start = self.bitmap.find_next_bit(0)?;
+        while start < bits {
+            let end = std::cmp::min(self.bitmap.find_next_zero(start)?, bits);
+            let offset = start as u64 * self.granularity;
+            let end_offset = std::cmp::min(end as u64 * self.granularity, self.disk_size);
+            ranges.push((offset, end_offset - offset));
+            if end >= bits {
+                break;
+            }
+            start = self.bitmap.find_next_bit(end)?;
+        }
+        Ok(ranges)
+    }
+
+    /// Serialize the bitmap, bit N of the data is the Nth granularity of the disk.
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let bits = self.bits();
+        let data_len = bits.div_ceil(8) as usize;
+        let mut buf = vec![0_u8; DIRTY_BITMAP_ENTRY_SIZE];
+        LittleEndian::write_u32(&mut buf[0..4], self.name.len() as u32);
+        LittleEndian::write_u64(&mut buf[8..16], self.granularity);
+        LittleEndian::write_u64(&mut buf[16..24], self.disk_size);
+        LittleEndian::write_u64(&mut buf[24..32], data_len as u64);
+        buf.extend_from_slice(self.name.as_bytes());
+
+        let mut data = vec![0_u8; data_len];
+        let mut bit = self.bitmap.find_next_bit(0)?;
+        while (bit as u64) < bits {
+            data[bit / 8] |= 1 << (bit % 8);
+            bit = self.bitmap.find_next_bit(bit + 1)?;
+        }
+        buf.append(&mut data);
+        Ok(buf)
+    }
+
+    /// Deserialize one bitmap from the buffer, return it and the consumed length.
+    fn from_bytes(buf: &[u8], persistent: bool) -> Result<(Self, usize)> {
+        if buf.len() < DIRTY_BITMAP_ENTRY_SIZE {
+            bail!("Dirty bitmap entry is truncated");
+        }
+        let name_len = LittleEndian::read_u32(&buf[0..4]) as usize;
+        let granularity = LittleEndian::read_u64(&buf[8..16]);
+        let disk_size = LittleEndian::read_u64(&buf[16..24]);
+        let data_len = LittleEndian::read_u64(&buf[24..32]) as usize;
+        let data_start = DIRTY_BITMAP_ENTRY_SIZE + name_len;
+        let end = data_start
+            .checked_add(data_len)
+            .filter(|&end| end <= buf.len())
+            .with_context(|| "Dirty bitmap data is truncated")?;
+        let name = String::from_utf8(buf[DIRTY_BITMAP_ENTRY_SIZE..data_start].to_vec())
+            .with_context(|| "Invalid name of dirty bitmap")?;
+
+        let mut dirty_bitmap = DirtyBitmap::new(&name, granularity, disk_size, persistent)?;
+        if data_len as u64 != dirty_bitmap.bits().div_ceil(8) {
+            bail!("Invalid data length {} of dirty bitmap {}", data_len, name);
+        }
+        for (index, byte) in buf[data_start..end].iter().enumerate() {
+            for pos in 0..8 {
+                if byte & (1 << pos) != 0 {
+                    dirty_bitmap.bitmap.set(index * 8 + pos)?;
+                }
+            }
+        }
+        Ok((dirty_bitmap, end))
+    }
+}
+
+fn serialize_bitmaps(bitmaps: &[&DirtyBitmap], flags: u32) -> Result<Vec<u8>> {
+    let mut buf = vec![0_u8; DIRTY_BITMAP_HEADER_SIZE];
+    buf[0..8].copy_from_slice(DIRTY_BITMAP_MAGIC);
+    LittleEndian::write_u32(&mut buf[8..12], DIRTY_BITMAP_VERSION);
+    LittleEndian::write_u32(&mut buf[12..16], flags);
+    LittleEndian::write_u32(&mut buf[16..20], bitmaps.len() as u32);
+    for bitmap in bitmaps {
+        buf.append(&mut bitmap.to_bytes()?);
+    }
+    Ok(buf)
+}
+
+/// Write the buffer to a temporary file first, so the old file is kept if failed.
+fn write_file_atomic(path: &str, buf: &[u8]) -> Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, buf).with_context(|| format!("Failed to write {}", tmp_path))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to rename {}", tmp_path))
+}
+
+/// All the dirty bitmaps of a block device.
+pub struct DirtyBitmaps {
+    disk_size: u64,
+    /// The file to store persistent bitmaps, `None` if persistence is unsupported.
+    path: Option<String>,
+    bitmaps: BTreeMap<String, DirtyBitmap>,
+    /// Bitmaps used by block jobs, which are invisible to the user and never persistent.
+    job_bitmaps: BTreeMap<String, DirtyBitmap>,
+}
+
+impl DirtyBitmaps {
+    pub fn new(disk_size: u64, path: Option<String>) -> Self {
+        DirtyBitmaps {
+            disk_size,
+            path,
+            bitmaps: BTreeMap::new(),
+            job_bitmaps: BTreeMap::new(),
+        }
+    }
+
+    pub fn disk_size(&self) -> u64 {
+        self.disk_size
+    }
+
+    /// Load the persistent bitmaps, and mark the bitmaps file in use.
+    pub fn load(&mut self) -> Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) if Path::new(path).exists() => path.clone(),
+            _ => return Ok(()),
+        };
+        let buf = fs::read(&path).with_context(|| format!("Failed to read {}", path))?;
+        if buf.len() < DIRTY_BITMAP_HEADER_SIZE || &buf[0..8] != DIRTY_BITMAP_MAGIC {
+            bail!("Invalid dirty bitmaps file {}", path);
+        }
+        let version = LittleEndian::read_u32(&buf[8..12]);
+        if version != DIRTY_BITMAP_VERSION {
+            bail!("Unsupported version {} of dirty bitmaps file", version);
+        }
+        let in_use = LittleEndian::read_u32(&buf[12..16]) & DIRTY_BITMAP_FLAG_IN_USE != 0;
+        let count = LittleEndian::read_u32(&buf[16..20]);
+
+        let mut offset = DIRTY_BITMAP_HEADER_SIZE;
+        for _ in 0..count {
+            let (mut bitmap, len) = DirtyBitmap::from_bytes(&buf[offset..], true)
+                .with_context(|| format!("Failed to load dirty bitmaps from {}", path))?;
+            offset += len;
+            if bitmap.disk_size != self.disk_size {
+                warn!(
+                    "Disk size of dirty bitmap {} is changed, drop it",
+                    bitmap.name
+                );
+                continue;
+            }
+            if in_use {
+                // The VM exited abnormally last time, writes may be missing in the bitmap.
+                warn!(
+                    "Dirty bitmap {} is inconsistent, mark all dirty",
+                    bitmap.name
+                );
+                bitmap.mark_all()?;
+            }
+            self.bitmaps.insert(bitmap.name.clone(), bitmap);
+        }
+
+        self.store(true)
+    }
+
+    /// Store the persistent bitmaps, the file is removed if there is no persistent bitmap.
+    pub fn store(&self, in_use: bool) -> Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let persistent: Vec<&DirtyBitmap> = self
+            .bitmaps
+            .values()
+            .filter(|bitmap| bitmap.persistent)
+            .collect();
+        if persistent.is_empty() {
+            if Path::new(path).exists() {
+                fs::remove_file(path).with_context(|| format!("Failed to remove {}", path))?;
+            }
+            return Ok(());
+        }
+
+        let flags = if in_use { DIRTY_BITMAP_FLAG_IN_USE } else { 0 };
+        write_file_atomic(path, &serialize_bitmaps(&persistent, flags)?)
+    }
+
+    pub fn add(&mut self, name: &str, granularity: Option<u64>, persistent: bool) -> Result<()> {
+        if self.bitmaps.contains_key(name) {
+            bail!("Dirty bitmap {} already exists", name);
+        }
+        if persistent && self.path.is_none() {
+            bail!("Persistent dirty bitmap is only supported for regular image file");
+        }
+        let granularity = granularity.unwrap_or(DIRTY_BITMAP_GRANULARITY_DEFAULT);
+        let bitmap = DirtyBitmap::new(name, granularity, self.disk_size, persistent)?;
+        self.bitmaps.insert(name.to_string(), bitmap);
+        if persistent {
+            self.store(true)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let bitmap = self
+            .bitmaps
+            .remove(name)
+            .with_context(|| format!("Dirty bitmap {} not found", name))?;
+        if bitmap.persistent {
+            self.store(true)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self, name: &str) -> Result<()> {
+        self.bitmaps
+            .get_mut(name)
+            .with_context(|| format!("Dirty bitmap {} not found", name))?
+            .clear();
+        Ok(())
+    }
+
+    /// Export the bitmap to the file, and clear it if `clear` is true.
+    pub fn export(&mut self, name: &str, file: &str, clear: bool) -> Result<()> {
+        let bitmap = self
+            .bitmaps
+            .get_mut(name)
+            .with_context(|| format!("Dirty bitmap {} not found", name))?;
+        write_file_atomic(file, &serialize_bitmaps(&[bitmap], 0)?)?;
+        if clear {
+            bitmap.clear();
+        }
+        Ok(())
+    }
+
+    /// Add the bitmap of block job, the whole disk is marked dirty if `all_dirty` is true.
+    pub fn add_job_bitmap(&mut self, name: &str, granularity: u64, all_dirty: bool) -> Result<()> {
+        if self.job_bitmaps.contains_key(name) {
+            bail!("Dirty bitmap of job {} already exists", name);
+        }
+        let mut bitmap = DirtyBitmap::new(name, granularity, self.disk_size, false)?;
+        if all_dirty {
+            bitmap.mark_all()?;
+        }
+        self.job_bitmaps.insert(name.to_string(), bitmap);
+        Ok(())
+    }
+
+    pub fn remove_job_bitmap(&mut self, name: &str) {
+        self.job_bitmaps.remove(name);
+    }
+
+    /// Get the dirty areas of the job bitmap and clear it.
+    pub fn take_job_dirty(&mut self, name: &str) -> Result<Vec<(u64, u64)>> {
+        let bitmap = self
+            .job_bitmaps
+            .get_mut(name)
+            .with_context(|| format!("Dirty bitmap of job {} not found", name))?;
+        let ranges = bitmap.dirty_ranges()?;
+        bitmap.clear();
+        Ok(ranges)
+    }
+
+    /// Mark the area dirty in the job bitmap, which is used to return the areas not copied.
+    pub fn mark_job_dirty(&mut self, name: &str, offset: u64, nbytes: u64) -> Result<()> {
+        self.job_bitmaps
+            .get_mut(name)
+            .with_context(|| format!("Dirty bitmap of job {} not found", name))?
+            .mark(offset, nbytes)
+    }
+
+    /// Get the dirty bytes of the job bitmap.
+    pub fn job_dirty_bytes(&self, name: &str) -> Result<u64> {
+        let bitmap = self
+            .job_bitmaps
+            .get(name)
+            .with_context(|| format!("Dirty bitmap of job {} not found", name))?;
+        Ok(bitmap.dirty_ranges()?.iter().map(|(_, len)| len).sum())
+    }
+
+    /// Mark the written area in all the bitmaps.
+    pub fn mark_dirty(&mut self, offset: u64, nbytes: u64) {
+        for bitmap in self
+            .bitmaps
+            .values_mut()
+            .chain(self.job_bitmaps.values_mut())
+        {
+            if let Err(e) = bitmap.mark(offset, nbytes) {
+                error!("Failed to mark dirty bitmap {}: {:?}", bitmap.name, e);
+            }
+        }
+    }
+}
+
+fn exit_notifier_id(drive_id: &str) -> String {
+    format!("{}-dirty-bitmaps", drive_id)
+}
+
+/// Create the dirty bitmaps of the drive, and load the persistent ones from `path`.
+pub fn register_dirty_bitmaps(
+    drive_id: &str,
+    disk_size: u64,
+    path: Option<String>,
+) -> Result<Arc<Mutex<DirtyBitmaps>>> {
+    let mut bitmaps = DirtyBitmaps::new(disk_size, path);
+    bitmaps.load()?;
+    let bitmaps = Arc::new(Mutex::new(bitmaps));
+    DIRTY_BITMAP_LIST
+        .lock()
+        .unwrap()
+        .insert(drive_id.to_string(), bitmaps.clone());
+
+    let cloned_bitmaps = Arc::downgrade(&bitmaps);
+    let exit_notifier = Arc::new(move || {
+        if let Some(bitmaps) = cloned_bitmaps.upgrade() {
+            // Don't wait for the lock, the holder may never release it when exiting.
+            match bitmaps.try_lock() {
+                Ok(locked_bitmaps) => {
+                    if let Err(e) = locked_bitmaps.store(false) {
+                        error!("Failed to store dirty bitmaps {:?}", e);
+                    }
+                }
+                Err(_) => error!("Failed to store dirty bitmaps as they are in use"),
+            }
+        }
+    }) as Arc<ExitNotifier>;
+    TempCleaner::add_exit_notifier(exit_notifier_id(drive_id), exit_notifier);
+
+    Ok(bitmaps)
+}
+
+/// Store the persistent bitmaps of the drive and remove them.
+pub fn unregister_dirty_bitmaps(drive_id: &str) {
+    TempCleaner::remove_exit_notifier(&exit_notifier_id(drive_id));
+    if let Some(bitmaps) = DIRTY_BITMAP_LIST.lock().unwrap().remove(drive_id) {
+        if let Err(e) = bitmaps.lock().unwrap().store(false) {
+            error!("Failed to store dirty bitmaps of {}: {:?}", drive_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tmp_path(name: &str) -> String {
+        format!("/tmp/dirty_bitmap_{}_{}", name, std::process::id())
+    }
+
+    #[test]
+    fn test_dirty_bitmap_mark_and_export() {
+        let mut bitmaps = DirtyBitmaps::new(1 << 20, None);
+        assert!(bitmaps.add("bitmap0", Some(1000), false).is_err());
+        assert!(bitmaps.add("bitmap0", Some(1 << 16), true).is_err());
+        bitmaps.add("bitmap0", Some(1 << 16), false).unwrap();
+        assert!(bitmaps.add("bitmap0", None, false).is_err());
+
+        // Write [65536 - 512, 65536 + 512) and the last sector.
+        bitmaps.mark_dirty((1 << 16) - 512, 1024);
+        bitmaps.mark_dirty((1 << 20) - 512, 4096);
+
+        let file = tmp_path("export");
+        bitmaps.export("bitmap0", &file, true).unwrap();
+        let buf = fs::read(&file).unwrap();
+        assert_eq!(&buf[0..8], DIRTY_BITMAP_MAGIC);
+        assert_eq!(LittleEndian::read_u32(&buf[16..20]), 1);
+        let (bitmap, len) =
+            DirtyBitmap::from_bytes(&buf[DIRTY_BITMAP_HEADER_SIZE..], false).unwrap();
+        assert_eq!(len + DIRTY_BITMAP_HEADER_SIZE, buf.len());
+        assert_eq!(bitmap.name, "bitmap0");
+        let data = &buf[buf.len() - 2..];
+        assert_eq!(data, &[0b0000_0011, 0b1000_0000]);
+        assert!(!bitmap.bitmap.contain(2).unwrap());
+        assert!(bitmap.bitmap.contain(15).unwrap());
+        fs::remove_file(&file).unwrap();
+
+        // The bitmap has been cleared by exporting.
+        bitmaps.export("bitmap0", &file, false).unwrap();
+        let buf = fs::read(&file).unwrap();
+        assert_eq!(&buf[buf.len() - 2..], &[0, 0]);
+        fs::remove_file(&file).unwrap();
+
+        bitmaps.mark_dirty(0, 512);
+        bitmaps.clear("bitmap0").unwrap();
+        assert!(bitmaps.bitmaps["bitmap0"].bitmap.find_next_bit(0).unwrap() >= 16);
+        bitmaps.remove("bitmap0").unwrap();
+        assert!(bitmaps.remove("bitmap0").is_err());
+    }
+
+    #[test]
+    fn test_dirty_bitmap_persistent() {
+        let path = tmp_path("persistent");
+        let mut bitmaps = DirtyBitmaps::new(1 << 20, Some(path.clone()));
+        bitmaps.add("persistent", None, true).unwrap();
+        bitmaps.add("temporary", None, false).unwrap();
+        bitmaps.mark_dirty(0, 512);
+        bitmaps.store(false).unwrap();
+
+        let mut loaded = DirtyBitmaps::new(1 << 20, Some(path.clone()));
+        loaded.load().unwrap();
+        assert_eq!(loaded.bitmaps.len(), 1);
+        let bitmap = &loaded.bitmaps["persistent"];
+        assert!(bitmap.bitmap.contain(0).unwrap());
+        assert!(!bitmap.bitmap.contain(1).unwrap());
+
+        // The file is still in use as it is not stored when exiting, all will be dirty.
+        let mut reloaded = DirtyBitmaps::new(1 << 20, Some(path.clone()));
+        reloaded.load().unwrap();
+        let bitmap = &reloaded.bitmaps["persistent"];
+        assert_eq!(bitmap.bitmap.find_next_zero(0).unwrap(), 16);
+
+        // Bitmaps of the disk with another size are dropped.
+        let mut resized = DirtyBitmaps::new(1 << 21, Some(path.clone()));
+        resized.load().unwrap();
+        assert!(resized.bitmaps.is_empty());
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_dirty_bitmap_job() {
+        let mut bitmaps = DirtyBitmaps::new((1 << 20) + 512, None);
+        bitmaps.add_job_bitmap("job0", 1 << 16, true).unwrap();
+        assert!(bitmaps.add_job_bitmap("job0", 1 << 16, false).is_err());
+        assert_eq!(bitmaps.job_dirty_bytes("job0").unwrap(), (1 << 20) + 512);
+        assert_eq!(
+            bitmaps.take_job_dirty("job0").unwrap(),
+            vec![(0, (1 << 20) + 512)]
+        );
+        assert!(bitmaps.take_job_dirty("job0").unwrap().is_empty());
+
+        // Job bitmaps record the writes, and adjacent areas are merged.
+        bitmaps.mark_dirty(512, 512);
+        bitmaps.mark_dirty(1 << 16, 1 << 16);
+        bitmaps.mark_job_dirty("job0", 1 << 20, 512).unwrap();
+        assert_eq!(
+            bitmaps.take_job_dirty("job0").unwrap(),
+            vec![(0, 1 << 17), (1 << 20, 512)]
+        );
+        // They are invisible to the user.
+        assert!(bitmaps.remove("job0").is_err());
+        bitmaps.remove_job_bitmap("job0");
+        assert!(bitmaps.take_job_dirty("job0").is_err());
+    }
+}
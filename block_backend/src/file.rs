@@ -15,11 +15,12 @@ use std::{
     fs::File,
     io::{Seek, SeekFrom},
     os::unix::prelude::{AsRawFd, RawFd},
-    rc::Rc,
+    rc::{Rc, Weak},
     sync::{
         atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering},
         Arc, Mutex,
     },
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
@@ -27,7 +28,12 @@ use log::error;
 use vmm_sys_util::epoll::EventSet;
 
 use crate::{BlockIoErrorCallback, BlockProperty};
-use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use machine_manager::{
+    config::{IoTimeout, IoTimeoutAction},
+    event,
+    event_loop::{register_event_helper, unregister_event_helper, EventLoop},
+    qmp::{qmp_channel::QmpChannel, qmp_schema},
+};
 use util::{
     aio::{Aio, AioCb, AioEngine, Iovec, OpCode},
     loop_context::{
@@ -57,6 +63,8 @@ pub struct FileDriver<T: Clone + 'static> {
     incomplete: Arc<AtomicU64>,
     delete_evts: Vec<RawFd>,
     block_prop: BlockProperty,
+    /// Stop flag of the io timeout watchdog.
+    watchdog_stopped: Option<Arc<AtomicBool>>,
 }
 
 impl<T: Clone + 'static> FileDriver<T> {
@@ -67,6 +75,7 @@ impl<T: Clone + 'static> FileDriver<T> {
             aio: Rc::new(RefCell::new(aio)),
             delete_evts: Vec::new(),
             block_prop,
+            watchdog_stopped: None,
         }
     }
 
@@ -127,7 +136,7 @@ impl<T: Clone + 'static> FileDriver<T> {
         self.process_request(OpCode::Preadv, req_list, completecb)
     }
 
-    fn complete_request(
+    pub(crate) fn complete_request(
         &mut self,
         opcode: OpCode,
         iovec: &[Iovec],
@@ -187,10 +196,28 @@ impl<T: Clone + 'static> FileDriver<T> {
             notifiers,
             self.block_prop.iothread.as_ref(),
             &mut self.delete_evts,
-        )
+        )?;
+
+        if let Some(io_timeout) = self.block_prop.io_timeout {
+            let stopped = Arc::new(AtomicBool::new(false));
+            if let Some(old) = self.watchdog_stopped.replace(stopped.clone()) {
+                old.store(true, Ordering::SeqCst);
+            }
+            arm_io_watchdog(
+                Rc::downgrade(&self.aio),
+                self.block_prop.id.clone(),
+                io_timeout,
+                self.block_prop.iothread.clone(),
+                stopped,
+            );
+        }
+        Ok(())
     }
 
     pub fn unregister_io_event(&mut self) -> Result<()> {
+        if let Some(stopped) = self.watchdog_stopped.take() {
+            stopped.store(true, Ordering::SeqCst);
+        }
         unregister_event_helper(self.block_prop.iothread.as_ref(), &mut self.delete_evts)
     }
 
@@ -211,6 +238,78 @@ impl<T: Clone + 'static> FileDriver<T> {
     }
 }
 
+/// Interval of checking the expired io requests.
+const IO_WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Check the io requests which are not completed within the timeout periodically, in the
+/// same thread as the aio completion handler. The watchdog stops when the driver is dropped
+/// or the io event is unregistered.
+fn io_watchdog<T: Clone + 'static>(
+    aio: Weak<RefCell<Aio<T>>>,
+    id: String,
+    io_timeout: IoTimeout,
+    iothread: Option<String>,
+    stopped: Arc<AtomicBool>,
+) {
+    if stopped.load(Ordering::SeqCst) {
+        return;
+    }
+    let aio_rc = match aio.upgrade() {
+        Some(aio) => aio,
+        None => return,
+    };
+
+    let fail = io_timeout.action == IoTimeoutAction::Fail;
+    let result = aio_rc
+        .borrow_mut()
+        .check_timeout(Duration::from_secs(io_timeout.timeout), fail);
+    drop(aio_rc);
+    match result {
+        Ok(0) => (),
+        Ok(count) => {
+            error!(
+                "Drive {}: {} io requests are not completed within {}s",
+                id, count, io_timeout.timeout
+            );
+            let action = if fail { "fail" } else { "report" };
+            let timeout_msg = qmp_schema::BlockIoTimeout {
+                device: id.clone(),
+                count: count as u64,
+                timeout: io_timeout.timeout,
+                action: action.to_string(),
+            };
+            event!(BlockIoTimeout; timeout_msg);
+        }
+        Err(e) => error!("Drive {}: failed to check io timeout: {:?}", id, e),
+    }
+
+    arm_io_watchdog(aio, id, io_timeout, iothread, stopped);
+}
+
+fn arm_io_watchdog<T: Clone + 'static>(
+    aio: Weak<RefCell<Aio<T>>>,
+    id: String,
+    io_timeout: IoTimeout,
+    iothread: Option<String>,
+    stopped: Arc<AtomicBool>,
+) {
+    let ctx = match EventLoop::get_ctx(iothread.as_ref()) {
+        Some(ctx) => ctx,
+        None => return,
+    };
+    let thread = iothread.clone();
+    let func = Box::new(move || {
+        io_watchdog(
+            aio.clone(),
+            id.clone(),
+            io_timeout,
+            thread.clone(),
+            stopped.clone(),
+        );
+    });
+    ctx.timer_add(func, IO_WATCHDOG_INTERVAL);
+}
+
 struct FileIoHandler<T: Clone + 'static> {
     aio: Rc<RefCell<Aio<T>>>,
     broken: Arc<AtomicBool>,
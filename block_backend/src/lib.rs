@@ -10,9 +10,14 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod dirty_bitmap;
 pub mod file;
+pub mod luks;
+pub mod mirror;
+pub mod nbd;
 pub mod qcow2;
 pub mod raw;
+pub mod zoned;
 
 use std::{
     fs::File,
@@ -22,13 +27,14 @@ use std::{
 use anyhow::{bail, Context, Result};
 use log::{error, info};
 
+use luks::LuksDriver;
 use machine_manager::{
-    config::DiskFormat,
+    config::{DiskFormat, IoTimeout},
     temp_cleaner::{ExitNotifier, TempCleaner},
 };
-use qcow2::{qcow2_flush_metadata, Qcow2Driver, QCOW2_LIST};
+use qcow2::{backing::Qcow2Backing, qcow2_flush_metadata, Qcow2Driver, SyncAioInfo, QCOW2_LIST};
 use raw::RawDriver;
-use util::aio::{Aio, Iovec, WriteZeroesState};
+use util::aio::{Aio, AioEngine, Iovec, WriteZeroesState};
 
 /// Callback function which is called when aio handle failed.
 pub type BlockIoErrorCallback = Arc<dyn Fn() + Send + Sync>;
@@ -67,6 +73,8 @@ pub struct Qcow2CreateOptions {
     pub version: u32,
     pub cluster_size: u64,
     pub refcount_bits: u64,
+    pub backing_file: Option<String>,
+    pub backing_format: Option<DiskFormat>,
 }
 
 #[derive(Default)]
@@ -75,6 +83,9 @@ pub struct CreateOptions {
     pub img_size: u64,
     pub cluster_size: Option<u64>,
     pub refcount_bits: Option<u64>,
+    /// Backing file of the new image, and its format.
+    pub backing_file: Option<String>,
+    pub backing_format: Option<DiskFormat>,
     pub conf: BlockProperty,
 }
 
@@ -101,6 +112,10 @@ impl CreateOptions {
             bail!("Format raw does not support parameter 'refcount_bits'");
         }
 
+        if self.backing_file.is_some() {
+            bail!("Format raw does not support parameter 'backing_file'");
+        }
+
         let options_raw = RawCreateOptions {
             path: self.path.clone(),
             img_size: self.img_size,
@@ -141,6 +156,8 @@ impl CreateOptions {
             version: DEFAULT_QCOW2_VERSION,
             cluster_size,
             refcount_bits,
+            backing_file: self.backing_file.clone(),
+            backing_format: self.backing_format,
         };
 
         Ok(options_qcow2)
@@ -265,6 +282,12 @@ pub struct BlockProperty {
     pub write_zeroes: WriteZeroesState,
     pub l2_cache_size: Option<u64>,
     pub refcount_cache_size: Option<u64>,
+    /// Secret used to unlock the encrypted image.
+    pub key_secret: Option<String>,
+    /// Populate the clusters read from backing image into the top image.
+    pub copy_on_read: bool,
+    /// Timeout of the IO requests, and the action when they expire.
+    pub io_timeout: Option<IoTimeout>,
 }
 
 impl Default for BlockProperty {
@@ -280,6 +303,9 @@ impl Default for BlockProperty {
             write_zeroes: WriteZeroesState::Off,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         }
     }
 }
@@ -336,6 +362,18 @@ pub fn create_block_backend<T: Clone + 'static + Send + Sync>(
             }
             Ok(Arc::new(Mutex::new(raw_file)))
         }
+        DiskFormat::Luks => {
+            let mut luks = LuksDriver::new(file, aio, prop.clone())
+                .with_context(|| "Failed to create luks driver")?;
+            let disk_size = luks.disk_size()?;
+            if disk_size & (prop.req_align as u64 - 1) != 0 {
+                bail!(
+                    "The size of luks payload is not aligned to {}.",
+                    prop.req_align
+                );
+            }
+            Ok(Arc::new(Mutex::new(luks)))
+        }
         DiskFormat::Qcow2 => {
             let mut qcow2 = Qcow2Driver::new(file, aio, prop.clone())
                 .with_context(|| "Failed to create qcow2 driver")?;
@@ -394,6 +432,44 @@ pub fn create_block_backend<T: Clone + 'static + Send + Sync>(
     }
 }
 
+/// Create a qcow2 overlay image whose backing file is `backing_file`, the virtual size is
+/// the same as the backing file.
+pub fn create_qcow2_overlay(
+    path: &str,
+    backing_file: &str,
+    backing_format: DiskFormat,
+) -> Result<()> {
+    let img_size = Qcow2Backing::open(backing_file, Some(backing_format), 1)?.size;
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create overlay image {}", path))?;
+    let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off)?;
+    let conf = BlockProperty {
+        format: DiskFormat::Qcow2,
+        ..Default::default()
+    };
+    let options = CreateOptions {
+        path: path.to_string(),
+        img_size,
+        backing_file: Some(backing_file.to_string()),
+        backing_format: Some(backing_format),
+        conf: conf.clone(),
+        ..Default::default()
+    };
+    let mut qcow2 = Qcow2Driver::new(file, aio, conf)?;
+    if let Err(e) = qcow2.create_image(&options) {
+        drop(qcow2);
+        std::fs::remove_file(path)
+            .unwrap_or_else(|e| error!("Failed to remove overlay image {}: {:?}", path, e));
+        return Err(e);
+    }
+    info!("Create overlay image {} on {}", path, backing_file);
+    Ok(())
+}
+
 pub fn remove_block_backend(id: &str) {
     QCOW2_LIST.lock().unwrap().remove(id);
     TempCleaner::remove_exit_notifier(id);
@@ -0,0 +1,345 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::num::NonZeroU32;
+
+use aes::{
+    cipher::{
+        generic_array::GenericArray, BlockCipher, BlockDecryptMut, BlockEncryptMut, InnerIvInit,
+        KeyInit,
+    },
+    Aes128, Aes192, Aes256,
+};
+use anyhow::{bail, Result};
+use ring::{digest, pbkdf2};
+use xts_mode::{get_tweak_default, Xts128};
+
+pub const AES_BLOCK_SIZE: usize = 16;
+/// The plain64 IV counts 512 bytes sectors whatever the sector size of the segment is.
+pub const IV_SECTOR_SIZE: usize = 512;
+
+/// AES block cipher with 128, 192 or 256 bits key.
+#[derive(Clone)]
+enum AesCipher {
+    Aes128(Aes128),
+    Aes192(Aes192),
+    Aes256(Aes256),
+}
+
+impl AesCipher {
+    fn new(key: &[u8]) -> Result<Self> {
+        let cipher = match key.len() {
+            16 => AesCipher::Aes128(Aes128::new(GenericArray::from_slice(key))),
+            24 => AesCipher::Aes192(Aes192::new(GenericArray::from_slice(key))),
+            32 => AesCipher::Aes256(Aes256::new(GenericArray::from_slice(key))),
+            _ => bail!("Invalid AES key length {}", key.len()),
+        };
+        Ok(cipher)
+    }
+}
+
+/// The ciphers are boxed as they hold the expanded keys.
+enum XtsMode {
+    Aes128(Box<Xts128<Aes128>>),
+    Aes256(Box<Xts128<Aes256>>),
+}
+
+/// AES in XTS mode with plain64 IV, the default cipher of LUKS2.
+pub struct XtsCipher {
+    mode: XtsMode,
+}
+
+impl XtsCipher {
+    /// Create the cipher, `key` contains the data key followed by the tweak key.
+    pub fn new(key: &[u8]) -> Result<Self> {
+        let (data_key, tweak_key) = key.split_at(key.len() / 2);
+        let mode = match key.len() {
+            32 => XtsMode::Aes128(Box::new(Xts128::new(
+                Aes128::new(GenericArray::from_slice(data_key)),
+                Aes128::new(GenericArray::from_slice(tweak_key)),
+            ))),
+            64 => XtsMode::Aes256(Box::new(Xts128::new(
+                Aes256::new(GenericArray::from_slice(data_key)),
+                Aes256::new(GenericArray::from_slice(tweak_key)),
+            ))),
+            _ => bail!("Invalid AES-XTS key length {}", key.len()),
+        };
+        Ok(Self { mode })
+    }
+
+    /// Encrypt or decrypt one data unit whose 128 bits IV is `iv`, the length of `buf`
+    /// must not be less than the AES block size.
+    pub fn process_with_iv(&self, buf: &mut [u8], iv: &[u8; AES_BLOCK_SIZE], encrypt: bool) {
+        match (&self.mode, encrypt) {
+            (XtsMode::Aes128(xts), true) => xts.encrypt_sector(buf, *iv),
+            (XtsMode::Aes128(xts), false) => xts.decrypt_sector(buf, *iv),
+            (XtsMode::Aes256(xts), true) => xts.encrypt_sector(buf, *iv),
+            (XtsMode::Aes256(xts), false) => xts.decrypt_sector(buf, *iv),
+        }
+    }
+
+    /// Encrypt the sectors in `buf`, the first of which has the IV `iv`. The IV
+    /// of the following sectors steps by `sector_size / IV_SECTOR_SIZE`.
+    pub fn encrypt(&self, buf: &mut [u8], sector_size: usize, iv: u64) {
+        let step = (sector_size / IV_SECTOR_SIZE) as u128;
+        let tweak = |i: u128| get_tweak_default(iv as u128 + i * step);
+        match &self.mode {
+            XtsMode::Aes128(xts) => xts.encrypt_area(buf, sector_size, 0, tweak),
+            XtsMode::Aes256(xts) => xts.encrypt_area(buf, sector_size, 0, tweak),
+        }
+    }
+
+    /// Decrypt the sectors in `buf`, the first of which has the IV `iv`. The IV
+    /// of the following sectors steps by `sector_size / IV_SECTOR_SIZE`.
+    pub fn decrypt(&self, buf: &mut [u8], sector_size: usize, iv: u64) {
+        let step = (sector_size / IV_SECTOR_SIZE) as u128;
+        let tweak = |i: u128| get_tweak_default(iv as u128 + i * step);
+        match &self.mode {
+            XtsMode::Aes128(xts) => xts.decrypt_area(buf, sector_size, 0, tweak),
+            XtsMode::Aes256(xts) => xts.decrypt_area(buf, sector_size, 0, tweak),
+        }
+    }
+}
+
+fn cbc_encrypt<C: BlockEncryptMut + BlockCipher>(
+    cipher: C,
+    buf: &mut [u8],
+    iv: &[u8; AES_BLOCK_SIZE],
+) {
+    let mut encryptor = cbc::Encryptor::<C>::inner_iv_init(cipher, GenericArray::from_slice(iv));
+    for block in buf.chunks_exact_mut(AES_BLOCK_SIZE) {
+        encryptor.encrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+}
+
+fn cbc_decrypt<C: BlockDecryptMut + BlockCipher>(
+    cipher: C,
+    buf: &mut [u8],
+    iv: &[u8; AES_BLOCK_SIZE],
+) {
+    let mut decryptor = cbc::Decryptor::<C>::inner_iv_init(cipher, GenericArray::from_slice(iv));
+    for block in buf.chunks_exact_mut(AES_BLOCK_SIZE) {
+        decryptor.decrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+}
+
+/// AES in CBC mode.
+pub struct CbcCipher {
+    cipher: AesCipher,
+}
+
+impl CbcCipher {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        Ok(Self {
+            cipher: AesCipher::new(key)?,
+        })
+    }
+
+    /// Encrypt `buf` whose length must be multiple of the AES block size.
+    pub fn encrypt(&self, buf: &mut [u8], iv: &[u8; AES_BLOCK_SIZE]) {
+        match &self.cipher {
+            AesCipher::Aes128(c) => cbc_encrypt(c.clone(), buf, iv),
+            AesCipher::Aes192(c) => cbc_encrypt(c.clone(), buf, iv),
+            AesCipher::Aes256(c) => cbc_encrypt(c.clone(), buf, iv),
+        }
+    }
+
+    /// Decrypt `buf` whose length must be multiple of the AES block size.
+    pub fn decrypt(&self, buf: &mut [u8], iv: &[u8; AES_BLOCK_SIZE]) {
+        match &self.cipher {
+            AesCipher::Aes128(c) => cbc_decrypt(c.clone(), buf, iv),
+            AesCipher::Aes192(c) => cbc_decrypt(c.clone(), buf, iv),
+            AesCipher::Aes256(c) => cbc_decrypt(c.clone(), buf, iv),
+        }
+    }
+}
+
+/// Hash algorithms used by LUKS2 kdf, digest and anti-forensic splitter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlg {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlg {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "sha1" => Ok(HashAlg::Sha1),
+            "sha256" => Ok(HashAlg::Sha256),
+            "sha512" => Ok(HashAlg::Sha512),
+            _ => bail!("Unsupported hash algorithm {}", name),
+        }
+    }
+
+    fn digest_alg(self) -> &'static digest::Algorithm {
+        match self {
+            HashAlg::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            HashAlg::Sha256 => &digest::SHA256,
+            HashAlg::Sha512 => &digest::SHA512,
+        }
+    }
+
+    fn pbkdf2_alg(self) -> pbkdf2::Algorithm {
+        match self {
+            HashAlg::Sha1 => pbkdf2::PBKDF2_HMAC_SHA1,
+            HashAlg::Sha256 => pbkdf2::PBKDF2_HMAC_SHA256,
+            HashAlg::Sha512 => pbkdf2::PBKDF2_HMAC_SHA512,
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        digest::digest(self.digest_alg(), data).as_ref().to_vec()
+    }
+}
+
+pub fn pbkdf2_derive(
+    hash: HashAlg,
+    iterations: u32,
+    salt: &[u8],
+    secret: &[u8],
+    out: &mut [u8],
+) -> Result<()> {
+    let Some(iterations) = NonZeroU32::new(iterations) else {
+        bail!("PBKDF2 iterations can't be zero");
+    };
+    pbkdf2::derive(hash.pbkdf2_alg(), iterations, salt, secret, out);
+    Ok(())
+}
+
+fn af_diffuse(hash: HashAlg, buf: &mut [u8]) {
+    let digest_size = hash.digest_alg().output_len;
+    for (i, chunk) in buf.chunks_mut(digest_size).enumerate() {
+        let mut data = (i as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(chunk);
+        let hashed = hash.digest(&data);
+        let len = chunk.len();
+        chunk.copy_from_slice(&hashed[..len]);
+    }
+}
+
+/// Recover the key from the anti-forensic split material.
+pub fn af_merge(
+    hash: HashAlg,
+    material: &[u8],
+    key_size: usize,
+    stripes: usize,
+) -> Result<Vec<u8>> {
+    if stripes == 0 || material.len() < key_size * stripes {
+        bail!(
+            "Anti-forensic material length {} is less than {} stripes of key size {}",
+            material.len(),
+            stripes,
+            key_size
+        );
+    }
+    let mut key = vec![0_u8; key_size];
+    for (i, stripe) in material.chunks_exact(key_size).take(stripes).enumerate() {
+        key.iter_mut().zip(stripe.iter()).for_each(|(k, s)| *k ^= s);
+        if i != stripes - 1 {
+            af_diffuse(hash, &mut key);
+        }
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+pub(crate) fn af_split(hash: HashAlg, key: &[u8], stripes: usize) -> Vec<u8> {
+    let mut material = Vec::with_capacity(key.len() * stripes);
+    let mut block = vec![0_u8; key.len()];
+    for i in 0..stripes - 1 {
+        let stripe: Vec<u8> = (0..key.len()).map(|j| (i * 7 + j * 13) as u8).collect();
+        block
+            .iter_mut()
+            .zip(stripe.iter())
+            .for_each(|(b, s)| *b ^= s);
+        af_diffuse(hash, &mut block);
+        material.extend_from_slice(&stripe);
+    }
+    let last: Vec<u8> = block.iter().zip(key.iter()).map(|(b, k)| b ^ k).collect();
+    material.extend_from_slice(&last);
+    material
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_aes_xts() {
+        // Vector 1 of IEEE 1619.
+        let xts = XtsCipher::new(&[0_u8; 32]).unwrap();
+        let mut buf = vec![0_u8; 32];
+        xts.encrypt(&mut buf, 32, 0);
+        assert_eq!(
+            buf,
+            from_hex("917cf69ebd68b2ec9b9fe9a3eadda692cd43d2f59598ed858c02c2652fbf922e")
+        );
+        xts.decrypt(&mut buf, 32, 0);
+        assert_eq!(buf, vec![0_u8; 32]);
+
+        let key: Vec<u8> = (0..64).collect();
+        let xts = XtsCipher::new(&key).unwrap();
+        let plain: Vec<u8> = (0..2048).map(|i| i as u8).collect();
+        let mut buf = plain.clone();
+        xts.encrypt(&mut buf, 512, 100);
+        assert_ne!(buf[..512], buf[512..1024]);
+        let mut sector = buf[512..1024].to_vec();
+        xts.decrypt(&mut sector, 512, 101);
+        assert_eq!(sector, plain[512..1024]);
+        xts.decrypt(&mut buf, 512, 100);
+        assert_eq!(buf, plain);
+
+        // 4096 bytes sectors step the IV by 8.
+        let mut buf = plain.repeat(4);
+        xts.encrypt(&mut buf, 4096, 8);
+        let mut sector = buf[4096..].to_vec();
+        xts.decrypt(&mut sector, 4096, 16);
+        assert_eq!(sector, plain.repeat(2));
+        assert!(XtsCipher::new(&[0_u8; 48]).is_err());
+    }
+
+    #[test]
+    fn test_aes_cbc() {
+        // Vector F.2.1 of NIST SP 800-38A.
+        let cbc = CbcCipher::new(&from_hex("2b7e151628aed2a6abf7158809cf4f3c")).unwrap();
+        let mut iv = [0_u8; AES_BLOCK_SIZE];
+        iv.copy_from_slice(&from_hex("000102030405060708090a0b0c0d0e0f"));
+        let plain = from_hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let mut buf = plain.clone();
+        cbc.encrypt(&mut buf, &iv);
+        assert_eq!(
+            buf,
+            from_hex("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2")
+        );
+        cbc.decrypt(&mut buf, &iv);
+        assert_eq!(buf, plain);
+        assert!(CbcCipher::new(&[0_u8; 20]).is_err());
+    }
+
+    #[test]
+    fn test_af_merge() {
+        let key: Vec<u8> = (0..64).map(|i| (i * 3) as u8).collect();
+        for hash in [HashAlg::Sha1, HashAlg::Sha256, HashAlg::Sha512] {
+            let material = af_split(hash, &key, 4000);
+            assert_eq!(af_merge(hash, &material, key.len(), 4000).unwrap(), key);
+            assert!(af_merge(hash, &material, key.len(), 4001).is_err());
+        }
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use byteorder::{BigEndian, ByteOrder};
+use serde::{Deserialize, Serialize};
+
+use super::crypto::HashAlg;
+
+pub const LUKS_MAGIC: &[u8; 6] = b"LUKS\xba\xbe";
+pub const LUKS2_VERSION: u16 = 2;
+/// Size of the binary header, the JSON area follows it.
+pub const LUKS2_BIN_HEADER_SIZE: usize = 4096;
+/// Minimum and maximum size of binary header and JSON area.
+pub const LUKS2_HEADER_SIZE_MIN: u64 = 0x4000;
+pub const LUKS2_HEADER_SIZE_MAX: u64 = 0x40_0000;
+/// The only cipher supported for keyslots and data segment.
+pub const LUKS2_CIPHER: &str = "aes-xts-plain64";
+/// Sector size used to encrypt keyslot areas.
+pub const LUKS2_KEYSLOT_SECTOR_SIZE: usize = 512;
+
+const CHECKSUM_ALG_OFFSET: usize = 72;
+const CHECKSUM_ALG_LEN: usize = 32;
+const CHECKSUM_OFFSET: usize = 448;
+const CHECKSUM_LEN: usize = 64;
+
+/// Binary header of LUKS2, only the fields used by StratoVirt are kept.
+#[derive(Debug, Default, Clone)]
+pub struct LuksBinaryHeader {
+    pub version: u16,
+    /// Size of binary header and JSON area.
+    pub hdr_size: u64,
+    pub checksum_alg: String,
+    pub checksum: Vec<u8>,
+}
+
+fn c_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).to_string()
+}
+
+impl LuksBinaryHeader {
+    pub fn from_vec(buf: &[u8]) -> Result<Self> {
+        if buf.len() < LUKS2_BIN_HEADER_SIZE {
+            bail!("Invalid LUKS header length {}", buf.len());
+        }
+        if &buf[0..6] != LUKS_MAGIC {
+            bail!("Invalid LUKS magic");
+        }
+        let header = Self {
+            version: BigEndian::read_u16(&buf[6..8]),
+            hdr_size: BigEndian::read_u64(&buf[8..16]),
+            checksum_alg: c_string(
+                &buf[CHECKSUM_ALG_OFFSET..CHECKSUM_ALG_OFFSET + CHECKSUM_ALG_LEN],
+            ),
+            checksum: buf[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_LEN].to_vec(),
+        };
+        header.check()?;
+        Ok(header)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.version != LUKS2_VERSION {
+            bail!("Unsupported LUKS version {}", self.version);
+        }
+        if !self.hdr_size.is_power_of_two()
+            || !(LUKS2_HEADER_SIZE_MIN..=LUKS2_HEADER_SIZE_MAX).contains(&self.hdr_size)
+        {
+            bail!("Invalid LUKS2 header size {}", self.hdr_size);
+        }
+        Ok(())
+    }
+
+    /// Verify the checksum of the whole header area `buf`.
+    pub fn verify_checksum(&self, buf: &[u8]) -> Result<()> {
+        let hash = HashAlg::from_name(&self.checksum_alg)?;
+        let mut data = buf[..self.hdr_size as usize].to_vec();
+        data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_LEN].fill(0);
+        let digest = hash.digest(&data);
+        if self.checksum[..digest.len()] != digest[..] {
+            bail!("LUKS2 header checksum mismatch");
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn to_vec(&self) -> Vec<u8> {
+        let mut buf = vec![0_u8; LUKS2_BIN_HEADER_SIZE];
+        buf[0..6].copy_from_slice(LUKS_MAGIC);
+        BigEndian::write_u16(&mut buf[6..8], self.version);
+        BigEndian::write_u64(&mut buf[8..16], self.hdr_size);
+        let alg = self.checksum_alg.as_bytes();
+        buf[CHECKSUM_ALG_OFFSET..CHECKSUM_ALG_OFFSET + alg.len()].copy_from_slice(alg);
+        buf[CHECKSUM_OFFSET..CHECKSUM_OFFSET + self.checksum.len()].copy_from_slice(&self.checksum);
+        buf
+    }
+}
+
+/// Anti-forensic splitter of keyslot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksAf {
+    #[serde(rename = "type")]
+    pub af_type: String,
+    pub stripes: u32,
+    pub hash: String,
+}
+
+/// Area of keyslot which stores the encrypted key material.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksArea {
+    #[serde(rename = "type")]
+    pub area_type: String,
+    pub offset: String,
+    pub size: String,
+    pub encryption: String,
+    pub key_size: u32,
+}
+
+/// Key derivation function of keyslot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksKdf {
+    #[serde(rename = "type")]
+    pub kdf_type: String,
+    #[serde(default)]
+    pub hash: String,
+    #[serde(default)]
+    pub iterations: u32,
+    pub salt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksKeyslot {
+    #[serde(rename = "type")]
+    pub keyslot_type: String,
+    pub key_size: u32,
+    pub af: LuksAf,
+    pub area: LuksArea,
+    pub kdf: LuksKdf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksSegment {
+    #[serde(rename = "type")]
+    pub segment_type: String,
+    pub offset: String,
+    /// Size of segment in bytes, or "dynamic" which means up to the end of image.
+    pub size: String,
+    pub iv_tweak: String,
+    pub encryption: String,
+    pub sector_size: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksDigest {
+    #[serde(rename = "type")]
+    pub digest_type: String,
+    pub keyslots: Vec<String>,
+    pub segments: Vec<String>,
+    pub hash: String,
+    pub iterations: u32,
+    pub salt: String,
+    pub digest: String,
+}
+
+/// JSON metadata of LUKS2.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuksMetadata {
+    pub keyslots: BTreeMap<String, LuksKeyslot>,
+    pub segments: BTreeMap<String, LuksSegment>,
+    pub digests: BTreeMap<String, LuksDigest>,
+}
+
+pub fn parse_u64(value: &str, name: &str) -> Result<u64> {
+    value
+        .parse::<u64>()
+        .with_context(|| format!("Invalid {} {} in LUKS2 metadata", name, value))
+}
+
+pub fn decode_base64(value: &str, name: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(value)
+        .with_context(|| format!("Invalid base64 {} in LUKS2 metadata", name))
+}
+
+impl LuksMetadata {
+    /// Parse the JSON area, which is terminated by NUL.
+    pub fn from_json_area(buf: &[u8]) -> Result<Self> {
+        let end = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+        let metadata: LuksMetadata = serde_json::from_slice(&buf[..end])
+            .with_context(|| "Failed to parse LUKS2 JSON metadata")?;
+        Ok(metadata)
+    }
+
+    /// Get the crypt segment of data. Only one segment is supported.
+    pub fn data_segment(&self) -> Result<(&String, &LuksSegment)> {
+        if self.segments.len() != 1 {
+            bail!(
+                "LUKS2 image with {} segments is not supported",
+                self.segments.len()
+            );
+        }
+        let (id, segment) = self.segments.iter().next().unwrap();
+        if segment.segment_type != "crypt" {
+            bail!("Unsupported LUKS2 segment type {}", segment.segment_type);
+        }
+        if segment.encryption != LUKS2_CIPHER {
+            bail!("Unsupported LUKS2 segment cipher {}", segment.encryption);
+        }
+        if segment.sector_size != 512 && segment.sector_size != 4096 {
+            bail!("Unsupported LUKS2 sector size {}", segment.sector_size);
+        }
+        Ok((id, segment))
+    }
+
+    /// Get the digest which verifies the key of `keyslot` for `segment`.
+    pub fn digest_for(&self, keyslot: &str, segment: &str) -> Option<&LuksDigest> {
+        self.digests.values().find(|d| {
+            d.keyslots.iter().any(|k| k == keyslot) && d.segments.iter().any(|s| s == segment)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_luks_binary_header() {
+        let header = LuksBinaryHeader {
+            version: LUKS2_VERSION,
+            hdr_size: LUKS2_HEADER_SIZE_MIN,
+            checksum_alg: "sha256".to_string(),
+            checksum: Vec::new(),
+        };
+        let mut buf = header.to_vec();
+        buf.resize(LUKS2_HEADER_SIZE_MIN as usize, 0);
+        let digest = HashAlg::Sha256.digest(&buf);
+        buf[CHECKSUM_OFFSET..CHECKSUM_OFFSET + digest.len()].copy_from_slice(&digest);
+
+        let parsed = LuksBinaryHeader::from_vec(&buf).unwrap();
+        assert_eq!(parsed.hdr_size, LUKS2_HEADER_SIZE_MIN);
+        assert_eq!(parsed.checksum_alg, "sha256");
+        assert!(parsed.verify_checksum(&buf).is_ok());
+        buf[LUKS2_BIN_HEADER_SIZE] = 1;
+        assert!(parsed.verify_checksum(&buf).is_err());
+
+        // Wrong magic, version and header size.
+        let mut invalid = buf.clone();
+        invalid[0] = b'X';
+        assert!(LuksBinaryHeader::from_vec(&invalid).is_err());
+        let mut invalid = buf.clone();
+        BigEndian::write_u16(&mut invalid[6..8], 1);
+        assert!(LuksBinaryHeader::from_vec(&invalid).is_err());
+        let mut invalid = buf;
+        BigEndian::write_u64(&mut invalid[8..16], 0x5000);
+        assert!(LuksBinaryHeader::from_vec(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_luks_metadata() {
+        let json = br#"{"keyslots":{"0":{"type":"luks2","key_size":64,
+            "af":{"type":"luks1","stripes":4000,"hash":"sha256"},
+            "area":{"type":"raw","offset":"32768","size":"258048","encryption":"aes-xts-plain64","key_size":64},
+            "kdf":{"type":"argon2id","time":4,"memory":1048576,"cpus":4,"salt":"AAAA"}}},
+            "tokens":{},
+            "segments":{"0":{"type":"crypt","offset":"16777216","size":"dynamic","iv_tweak":"0",
+            "encryption":"aes-xts-plain64","sector_size":512}},
+            "digests":{"0":{"type":"pbkdf2","keyslots":["0"],"segments":["0"],"hash":"sha256",
+            "iterations":1000,"salt":"AAAA","digest":"AAAA"}},
+            "config":{"json_size":"12288","keyslots_size":"16744448"}}"#;
+        let mut buf = json.to_vec();
+        buf.resize(0x3000, 0);
+        let metadata = LuksMetadata::from_json_area(&buf).unwrap();
+        assert_eq!(metadata.keyslots["0"].kdf.kdf_type, "argon2id");
+        assert_eq!(metadata.keyslots["0"].kdf.iterations, 0);
+        let (id, segment) = metadata.data_segment().unwrap();
+        assert_eq!(id, "0");
+        assert_eq!(parse_u64(&segment.offset, "offset").unwrap(), 16777216);
+        assert!(parse_u64(&segment.size, "size").is_err());
+        assert!(metadata.digest_for("0", "0").is_some());
+        assert!(metadata.digest_for("1", "0").is_none());
+        assert_eq!(decode_base64("AAAA", "salt").unwrap(), vec![0_u8; 3]);
+
+        assert!(LuksMetadata::from_json_area(b"{\"keyslots\":{}}").is_err());
+    }
+}
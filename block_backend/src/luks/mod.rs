@@ -0,0 +1,949 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+pub mod crypto;
+pub mod header;
+
+use std::{
+    cmp,
+    fs::File,
+    os::unix::io::AsRawFd,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+
+use self::{
+    crypto::{af_merge, pbkdf2_derive, HashAlg, XtsCipher, IV_SECTOR_SIZE},
+    header::{
+        decode_base64, parse_u64, LuksBinaryHeader, LuksKeyslot, LuksMetadata,
+        LUKS2_BIN_HEADER_SIZE, LUKS2_CIPHER, LUKS2_KEYSLOT_SECTOR_SIZE,
+    },
+};
+use crate::{
+    file::{CombineRequest, FileDriver},
+    qcow2::SyncAioInfo,
+    BlockDriverOps, BlockIoErrorCallback, BlockProperty, BlockStatus, CheckResult, CreateOptions,
+};
+use util::{
+    aio::{
+        get_iov_size, iov_discard_front_direct, iov_from_buf_direct, iov_to_buf_direct, Aio, AioCb,
+        AioCompleteFunc, AioReqResult, Iovec, OpCode,
+    },
+    num_ops::{round_down, round_up},
+    unix::host_page_size,
+};
+
+/// Max length of one bounce buffer, the larger request is split into several buffers.
+const MAX_BOUNCE_BUF_SIZE: u64 = 1 << 20;
+
+/// Buffer aligned to the host page size which holds the encrypted data, so it can be
+/// submitted with direct IO.
+struct BounceBuffer {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl BounceBuffer {
+    fn new(len: usize) -> Result<Self> {
+        // SAFETY: we allocate aligned memory and free it when the buffer is dropped.
+        let ptr = unsafe { libc::memalign(host_page_size() as usize, len) } as *mut u8;
+        if ptr.is_null() {
+            bail!("Failed to alloc bounce buffer with length {}", len);
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn iovec(&self) -> Iovec {
+        Iovec::new(self.ptr as u64, self.len as u64)
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the memory is allocated by us with length `len`.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for BounceBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the memory is allocated by us and will not be used anymore.
+        unsafe { libc::free(self.ptr as *mut libc::c_void) };
+    }
+}
+
+/// Request of the user which is submitted to the image through the bounce buffers. It's
+/// shared by the aio of the bounce buffers, and completed after all of them are done.
+struct LuksRequest<T: Clone> {
+    complete_func: Arc<AioCompleteFunc<T>>,
+    completecb: T,
+    opcode: OpCode,
+    /// Iovec of the user, the decrypted data is copied to it on read.
+    iovec: Vec<Iovec>,
+    offset: usize,
+    nbytes: u64,
+    /// Bounce buffers which cover the request with whole sectors.
+    bounce: Vec<Mutex<BounceBuffer>>,
+    cipher: Arc<XtsCipher>,
+    sector_size: usize,
+    /// IV of the first sector in the bounce buffers.
+    iv: u64,
+    /// Offset of the request in the first bounce buffer.
+    head: usize,
+}
+
+impl<T: Clone> LuksRequest<T> {
+    /// Decrypt the bounce buffers and copy the data of the request to the iovec.
+    fn decrypt_to_iovec(&self) -> Result<()> {
+        let mut iovec = self.iovec.clone();
+        let mut iovs = &mut iovec[..];
+        let mut iv = self.iv;
+        let mut skip = self.head;
+        let mut remain = self.nbytes as usize;
+        for buf in self.bounce.iter() {
+            let mut buf = buf.lock().unwrap();
+            let data = buf.as_mut_slice();
+            self.cipher.decrypt(data, self.sector_size, iv);
+            iv += (data.len() / IV_SECTOR_SIZE) as u64;
+
+            let len = cmp::min(data.len() - skip, remain);
+            iov_from_buf_direct(iovs, &data[skip..skip + len])?;
+            remain -= len;
+            if remain == 0 {
+                break;
+            }
+            skip = 0;
+            iovs = iov_discard_front_direct(iovs, len as u64)
+                .with_context(|| "Failed to adjust iovec for LUKS2 read")?;
+        }
+        Ok(())
+    }
+}
+
+/// Complete the aio of the bounce buffers, the request of the user is completed with
+/// the decrypted data after all of its bounce buffers are done.
+fn luks_complete_func<T: Clone>(aiocb: &AioCb<Arc<LuksRequest<T>>>, mut ret: i64) -> Result<()> {
+    match aiocb.req_is_completed(ret) {
+        AioReqResult::Inflight => return Ok(()),
+        AioReqResult::Error(v) => ret = v,
+        AioReqResult::Done => (),
+    }
+
+    let req = &aiocb.iocompletecb;
+    if ret >= 0 {
+        ret = req.nbytes as i64;
+        if req.opcode == OpCode::Preadv {
+            if let Err(e) = req.decrypt_to_iovec() {
+                error!("Failed to decrypt LUKS2 data: {:?}", e);
+                ret = -1;
+            }
+        }
+    }
+
+    let user_aiocb = AioCb {
+        direct: aiocb.direct,
+        req_align: aiocb.req_align,
+        buf_align: aiocb.buf_align,
+        discard: aiocb.discard,
+        write_zeroes: aiocb.write_zeroes,
+        file_fd: aiocb.file_fd,
+        opcode: req.opcode,
+        iovec: req.iovec.clone(),
+        offset: req.offset,
+        nbytes: req.nbytes,
+        user_data: 0,
+        iocompletecb: req.completecb.clone(),
+        combine_req: None,
+    };
+    (req.complete_func)(&user_aiocb, ret)
+}
+
+/// Driver of LUKS2 encrypted image. Data is decrypted after being read from and
+/// encrypted before being written to the image, so the image is always encrypted
+/// at rest. The encrypted data is submitted through the aio of the drive with
+/// bounce buffers, and decrypted when the aio is completed.
+pub struct LuksDriver<T: Clone + 'static> {
+    driver: FileDriver<Arc<LuksRequest<T>>>,
+    /// Complete function of the user's requests.
+    complete_func: Arc<AioCompleteFunc<T>>,
+    /// Aio for sync read of the keyslots and the partial sectors.
+    sync_aio: SyncAioInfo,
+    cipher: Arc<XtsCipher>,
+    /// Offset of encrypted payload in image.
+    payload_offset: u64,
+    /// Size of payload, None means up to the end of image.
+    payload_size: Option<u64>,
+    sector_size: u64,
+    iv_tweak: u64,
+    status: Arc<Mutex<BlockStatus>>,
+}
+
+// SAFETY: Send and Sync is not auto-implemented for raw pointer type in Aio.
+// We use Arc<Mutex<LuksDriver<T>>> to allow used in multi-threading.
+unsafe impl<T: Clone + 'static> Send for LuksDriver<T> {}
+unsafe impl<T: Clone + 'static> Sync for LuksDriver<T> {}
+
+impl<T: Clone + 'static> LuksDriver<T> {
+    pub fn new(file: File, aio: Aio<T>, prop: BlockProperty) -> Result<Self> {
+        let passphrase = prop
+            .key_secret
+            .clone()
+            .with_context(|| "Secret of LUKS image is not specified")?;
+        let mut sync_aio = SyncAioInfo::new(file.as_raw_fd(), prop.clone())?;
+
+        let mut buf = vec![0_u8; LUKS2_BIN_HEADER_SIZE];
+        sync_aio.read_buffer(0, &mut buf)?;
+        let header = LuksBinaryHeader::from_vec(&buf)?;
+        buf.resize(header.hdr_size as usize, 0);
+        sync_aio.read_buffer(0, &mut buf)?;
+        header.verify_checksum(&buf)?;
+        let metadata = LuksMetadata::from_json_area(&buf[LUKS2_BIN_HEADER_SIZE..])?;
+
+        let (segment_id, segment) = metadata.data_segment()?;
+        let payload_offset = parse_u64(&segment.offset, "segment offset")?;
+        let payload_size = match segment.size.as_str() {
+            "dynamic" => None,
+            size => Some(parse_u64(size, "segment size")?),
+        };
+        let sector_size = segment.sector_size as u64;
+        if payload_offset % sector_size != 0 {
+            bail!("LUKS2 segment offset {} is not aligned", payload_offset);
+        }
+        let iv_tweak = parse_u64(&segment.iv_tweak, "segment iv_tweak")?;
+
+        let mut master_key = None;
+        for (id, keyslot) in metadata.keyslots.iter() {
+            match Self::open_keyslot(
+                &mut sync_aio,
+                &metadata,
+                id,
+                keyslot,
+                segment_id,
+                &passphrase,
+            ) {
+                Ok(Some(key)) => {
+                    info!("LUKS2 image {} is unlocked by keyslot {}", prop.id, id);
+                    master_key = Some(key);
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Skip keyslot {} of LUKS2 image {}: {:?}", id, prop.id, e),
+            }
+        }
+        let master_key = master_key.with_context(|| {
+            format!(
+                "Failed to unlock LUKS2 image {}: invalid passphrase or no usable keyslot",
+                prop.id
+            )
+        })?;
+
+        let luks_aio = Aio::new(Arc::new(luks_complete_func::<T>), aio.get_engine())?;
+        Ok(Self {
+            driver: FileDriver::new(file, luks_aio, prop),
+            complete_func: aio.complete_func.clone(),
+            sync_aio,
+            cipher: Arc::new(XtsCipher::new(&master_key)?),
+            payload_offset,
+            payload_size,
+            sector_size,
+            iv_tweak,
+            status: Arc::new(Mutex::new(BlockStatus::Init)),
+        })
+    }
+
+    /// Try to recover the master key from keyslot. Returns None if the passphrase
+    /// does not match.
+    fn open_keyslot(
+        sync_aio: &mut SyncAioInfo,
+        metadata: &LuksMetadata,
+        id: &str,
+        keyslot: &LuksKeyslot,
+        segment_id: &str,
+        passphrase: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        if keyslot.keyslot_type != "luks2" {
+            bail!("unsupported keyslot type {}", keyslot.keyslot_type);
+        }
+        // Argon2 is the default kdf of cryptsetup, but only PBKDF2 is supported now.
+        if keyslot.kdf.kdf_type != "pbkdf2" {
+            bail!(
+                "unsupported kdf {}, please use pbkdf2",
+                keyslot.kdf.kdf_type
+            );
+        }
+        if keyslot.af.af_type != "luks1" {
+            bail!("unsupported anti-forensic type {}", keyslot.af.af_type);
+        }
+        if keyslot.area.area_type != "raw" || keyslot.area.encryption != LUKS2_CIPHER {
+            bail!(
+                "unsupported keyslot area encryption {}",
+                keyslot.area.encryption
+            );
+        }
+        let digest = metadata
+            .digest_for(id, segment_id)
+            .with_context(|| "no digest for keyslot")?;
+        if digest.digest_type != "pbkdf2" {
+            bail!("unsupported digest type {}", digest.digest_type);
+        }
+
+        // Decrypt the key material with the key derived from passphrase.
+        let mut area_key = vec![0_u8; keyslot.area.key_size as usize];
+        let salt = decode_base64(&keyslot.kdf.salt, "kdf salt")?;
+        pbkdf2_derive(
+            HashAlg::from_name(&keyslot.kdf.hash)?,
+            keyslot.kdf.iterations,
+            &salt,
+            passphrase.as_bytes(),
+            &mut area_key,
+        )?;
+        let key_size = keyslot.key_size as usize;
+        let stripes = keyslot.af.stripes as usize;
+        let material_len = round_up(
+            (key_size * stripes) as u64,
+            LUKS2_KEYSLOT_SECTOR_SIZE as u64,
+        )
+        .with_context(|| "keyslot material is too large")?;
+        if material_len > parse_u64(&keyslot.area.size, "area size")? {
+            bail!("keyslot area is too small");
+        }
+        let mut material = vec![0_u8; material_len as usize];
+        sync_aio.read_buffer(
+            parse_u64(&keyslot.area.offset, "area offset")?,
+            &mut material,
+        )?;
+        XtsCipher::new(&area_key)?.decrypt(&mut material, LUKS2_KEYSLOT_SECTOR_SIZE, 0);
+        let key = af_merge(
+            HashAlg::from_name(&keyslot.af.hash)?,
+            &material,
+            key_size,
+            stripes,
+        )?;
+
+        // Verify the master key with digest.
+        let expected = decode_base64(&digest.digest, "digest")?;
+        let mut actual = vec![0_u8; expected.len()];
+        pbkdf2_derive(
+            HashAlg::from_name(&digest.hash)?,
+            digest.iterations,
+            &decode_base64(&digest.salt, "digest salt")?,
+            &key,
+            &mut actual,
+        )?;
+        if actual != expected {
+            return Ok(None);
+        }
+
+        Ok(Some(key))
+    }
+
+    fn payload_size(&mut self) -> Result<u64> {
+        if let Some(size) = self.payload_size {
+            return Ok(size);
+        }
+        let file_size = self.driver.disk_size()?;
+        if file_size < self.payload_offset {
+            bail!("LUKS2 image is smaller than the payload offset");
+        }
+        Ok(round_down(file_size - self.payload_offset, self.sector_size).unwrap_or(0))
+    }
+
+    fn check_request(&mut self, offset: u64, nbytes: u64) -> Result<()> {
+        let size = self.payload_size()?;
+        if offset.checked_add(nbytes).is_none_or(|end| end > size) {
+            bail!(
+                "Request offset {} nbytes {} exceeds the disk size {}",
+                offset,
+                nbytes,
+                size
+            );
+        }
+        Ok(())
+    }
+
+    /// Get the range aligned to sector size which covers `[offset, offset + nbytes)`.
+    fn aligned_range(&self, offset: u64, nbytes: u64) -> (u64, u64) {
+        // Sector size is power of two, so round up and down never fails.
+        let start = round_down(offset, self.sector_size).unwrap();
+        let end = round_up(offset + nbytes, self.sector_size).unwrap();
+        (start, end)
+    }
+
+    /// IV of the sector at guest offset `offset`. Like dm-crypt, the plain64 IV counts
+    /// 512 bytes sectors even if the segment has larger sectors.
+    fn sector_iv(&self, offset: u64) -> u64 {
+        offset / IV_SECTOR_SIZE as u64 + self.iv_tweak
+    }
+
+    /// Read and decrypt the whole sectors at guest offset `offset` synchronously.
+    fn read_plain(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.sync_aio
+            .read_buffer(self.payload_offset + offset, buf)?;
+        self.cipher
+            .decrypt(buf, self.sector_size as usize, self.sector_iv(offset));
+        Ok(())
+    }
+
+    /// Alloc the bounce buffers which cover the sector aligned range `[start, end)`.
+    fn alloc_bounce(&self, start: u64, end: u64) -> Result<Vec<Mutex<BounceBuffer>>> {
+        let mut bounce = Vec::new();
+        let mut pos = start;
+        while pos < end {
+            let len = cmp::min(end - pos, MAX_BOUNCE_BUF_SIZE);
+            bounce.push(Mutex::new(BounceBuffer::new(len as usize)?));
+            pos += len;
+        }
+        Ok(bounce)
+    }
+
+    /// Build the encrypted bounce buffers of the write request. The data is copied from
+    /// `iovec`, or zeroes if it's None. Like the misaligned direct IO of aio, the partial
+    /// sectors at the head and tail are loaded synchronously before being merged.
+    fn encrypt_bounce(
+        &mut self,
+        offset: u64,
+        nbytes: u64,
+        iovec: Option<&[Iovec]>,
+    ) -> Result<Vec<Mutex<BounceBuffer>>> {
+        let (start, end) = self.aligned_range(offset, nbytes);
+        let mut bounce = self.alloc_bounce(start, end)?;
+        if bounce.is_empty() {
+            return Ok(bounce);
+        }
+
+        let sector_size = self.sector_size as usize;
+        let head_partial = offset != start;
+        if head_partial {
+            let data = bounce[0].get_mut().unwrap().as_mut_slice();
+            self.read_plain(start, &mut data[..sector_size])?;
+        }
+        // The tail sector is already loaded if it's the head sector too.
+        if offset + nbytes != end && !(head_partial && end - start == self.sector_size) {
+            let last = bounce.len() - 1;
+            let data = bounce[last].get_mut().unwrap().as_mut_slice();
+            let len = data.len();
+            self.read_plain(end - self.sector_size, &mut data[len - sector_size..])?;
+        }
+
+        let mut pos = start;
+        for buf in bounce.iter_mut() {
+            let data = buf.get_mut().unwrap().as_mut_slice();
+            let buf_end = pos + data.len() as u64;
+            let low = cmp::max(pos, offset);
+            let high = cmp::min(buf_end, offset + nbytes);
+            let dst = &mut data[(low - pos) as usize..(high - pos) as usize];
+            match iovec {
+                Some(iovec) => {
+                    iov_to_buf_direct(iovec, low - offset, dst)?;
+                }
+                None => dst.fill(0),
+            }
+            self.cipher.encrypt(data, sector_size, self.sector_iv(pos));
+            pos = buf_end;
+        }
+        Ok(bounce)
+    }
+
+    fn new_request(
+        &self,
+        opcode: OpCode,
+        iovec: Vec<Iovec>,
+        offset: u64,
+        nbytes: u64,
+        completecb: T,
+        bounce: Vec<Mutex<BounceBuffer>>,
+    ) -> Arc<LuksRequest<T>> {
+        let (start, _) = self.aligned_range(offset, nbytes);
+        Arc::new(LuksRequest {
+            complete_func: self.complete_func.clone(),
+            completecb,
+            opcode,
+            iovec,
+            offset: offset as usize,
+            nbytes,
+            bounce,
+            cipher: self.cipher.clone(),
+            sector_size: self.sector_size as usize,
+            iv: self.sector_iv(start),
+            head: (offset - start) as usize,
+        })
+    }
+
+    /// Submit the bounce buffers of the request to the image through aio.
+    fn submit_bounce(&mut self, req: Arc<LuksRequest<T>>) -> Result<()> {
+        let (start, _) = self.aligned_range(req.offset as u64, req.nbytes);
+        let mut offset = self.payload_offset + start;
+        let mut req_list = Vec::new();
+        for buf in req.bounce.iter() {
+            let iov = buf.lock().unwrap().iovec();
+            let len = iov.iov_len;
+            req_list.push(CombineRequest::new(vec![iov], offset, len));
+            offset += len;
+        }
+
+        if req.opcode == OpCode::Preadv {
+            self.driver.read_vectored(req_list, req)
+        } else {
+            self.driver.write_vectored(req_list, req)
+        }
+    }
+
+    /// Submit the bounce buffers of the write request. If the head or tail sector is
+    /// partial, the request is read-modify-write, and it's written synchronously like the
+    /// misaligned direct IO of aio. Otherwise another write which merges into the same
+    /// sector may load it before this one lands, and overwrite it with stale data.
+    fn submit_write(&mut self, req: Arc<LuksRequest<T>>) -> Result<()> {
+        let offset = req.offset as u64;
+        let (start, end) = self.aligned_range(offset, req.nbytes);
+        if offset == start && offset + req.nbytes == end {
+            return self.submit_bounce(req);
+        }
+
+        let mut pos = self.payload_offset + start;
+        for buf in req.bounce.iter() {
+            let mut buf = buf.lock().unwrap();
+            let data = buf.as_mut_slice();
+            self.sync_aio.write_buffer(pos, data)?;
+            pos += data.len() as u64;
+        }
+        self.driver.complete_request(req.opcode, &[], 0, 0, req)
+    }
+}
+
+impl<T: Clone + Send + Sync> BlockDriverOps<T> for LuksDriver<T> {
+    fn create_image(&mut self, _options: &CreateOptions) -> Result<String> {
+        bail!("Format luks does not support creating image");
+    }
+
+    fn check_image(&mut self, _res: &mut CheckResult, _quite: bool, _fix: u64) -> Result<()> {
+        bail!("This image format does not support checks");
+    }
+
+    fn read_vectored(&mut self, iovec: Vec<Iovec>, offset: usize, completecb: T) -> Result<()> {
+        let offset = offset as u64;
+        let nbytes = get_iov_size(&iovec);
+        self.check_request(offset, nbytes)
+            .with_context(|| " Invalid read request")?;
+
+        let (start, end) = self.aligned_range(offset, nbytes);
+        let bounce = self.alloc_bounce(start, end)?;
+        let req = self.new_request(OpCode::Preadv, iovec, offset, nbytes, completecb, bounce);
+        self.submit_bounce(req)
+    }
+
+    fn write_vectored(&mut self, iovec: Vec<Iovec>, offset: usize, completecb: T) -> Result<()> {
+        let offset = offset as u64;
+        let nbytes = get_iov_size(&iovec);
+        self.check_request(offset, nbytes)
+            .with_context(|| " Invalid write request")?;
+
+        let bounce = self.encrypt_bounce(offset, nbytes, Some(iovec.as_slice()))?;
+        let req = self.new_request(OpCode::Pwritev, iovec, offset, nbytes, completecb, bounce);
+        self.submit_write(req)
+    }
+
+    fn write_zeroes(
+        &mut self,
+        offset: usize,
+        nbytes: u64,
+        completecb: T,
+        unmap: bool,
+    ) -> Result<()> {
+        let offset = offset as u64;
+        self.check_request(offset, nbytes)
+            .with_context(|| " Invalid write zeroes request")?;
+
+        // Zeroes are encrypted too, so they can't be passed to the file directly, and
+        // the range can't be unmapped.
+        let opcode = if unmap {
+            OpCode::WriteZeroesUnmap
+        } else {
+            OpCode::WriteZeroes
+        };
+        let bounce = self.encrypt_bounce(offset, nbytes, None)?;
+        let req = self.new_request(opcode, Vec::new(), offset, nbytes, completecb, bounce);
+        self.submit_write(req)
+    }
+
+    fn discard(&mut self, offset: usize, nbytes: u64, completecb: T) -> Result<()> {
+        let offset = offset as u64;
+        self.check_request(offset, nbytes)
+            .with_context(|| " Invalid discard request")?;
+        let req = self.new_request(
+            OpCode::Discard,
+            Vec::new(),
+            offset,
+            nbytes,
+            completecb,
+            Vec::new(),
+        );
+        self.driver.discard(
+            vec![CombineRequest::new(
+                Vec::new(),
+                self.payload_offset + offset,
+                nbytes,
+            )],
+            req,
+        )
+    }
+
+    fn datasync(&mut self, completecb: T) -> Result<()> {
+        let req = self.new_request(OpCode::Fdsync, Vec::new(), 0, 0, completecb, Vec::new());
+        self.driver.datasync(req)
+    }
+
+    fn flush_request(&mut self) -> Result<()> {
+        self.driver.flush_request()
+    }
+
+    fn drain_request(&self) {
+        self.driver.drain_request();
+    }
+
+    fn register_io_event(
+        &mut self,
+        broken: Arc<AtomicBool>,
+        error_cb: BlockIoErrorCallback,
+    ) -> Result<()> {
+        self.driver.register_io_event(broken, error_cb)
+    }
+
+    fn unregister_io_event(&mut self) -> Result<()> {
+        self.driver.unregister_io_event()
+    }
+
+    fn disk_size(&mut self) -> Result<u64> {
+        self.payload_size()
+    }
+
+    fn get_status(&mut self) -> Arc<Mutex<BlockStatus>> {
+        self.status.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        fs::{remove_file, OpenOptions},
+        os::unix::fs::FileExt,
+    };
+
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use ring::digest;
+
+    use super::*;
+    use crate::luks::{
+        crypto::af_split,
+        header::{LuksAf, LuksArea, LuksDigest, LuksKdf, LuksSegment, LUKS2_VERSION},
+    };
+    use machine_manager::config::DiskFormat;
+    use util::aio::AioEngine;
+
+    const KEYSLOT_OFFSET: u64 = 0x8000;
+    const PAYLOAD_OFFSET: u64 = 0x10_0000;
+    const PAYLOAD_SIZE: u64 = 0x20_0000;
+    const STRIPES: usize = 4000;
+    const ITERATIONS: u32 = 1000;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Create LUKS2 image with one pbkdf2 keyslot in the same way as cryptsetup.
+    fn create_luks_image(path: &str, passphrase: &str, master_key: &[u8], sector_size: u32) {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        file.set_len(PAYLOAD_OFFSET + PAYLOAD_SIZE).unwrap();
+
+        let kdf_salt = [0x11_u8; 32];
+        let mut area_key = vec![0_u8; master_key.len()];
+        pbkdf2_derive(
+            HashAlg::Sha256,
+            ITERATIONS,
+            &kdf_salt,
+            passphrase.as_bytes(),
+            &mut area_key,
+        )
+        .unwrap();
+        let mut material = af_split(HashAlg::Sha256, master_key, STRIPES);
+        material.resize(
+            round_up(material.len() as u64, LUKS2_KEYSLOT_SECTOR_SIZE as u64).unwrap() as usize,
+            0,
+        );
+        XtsCipher::new(&area_key)
+            .unwrap()
+            .encrypt(&mut material, LUKS2_KEYSLOT_SECTOR_SIZE, 0);
+        file.write_all_at(&material, KEYSLOT_OFFSET).unwrap();
+
+        let digest_salt = [0x22_u8; 32];
+        let mut digest = vec![0_u8; 32];
+        pbkdf2_derive(
+            HashAlg::Sha256,
+            ITERATIONS,
+            &digest_salt,
+            master_key,
+            &mut digest,
+        )
+        .unwrap();
+
+        let keyslot = LuksKeyslot {
+            keyslot_type: "luks2".to_string(),
+            key_size: master_key.len() as u32,
+            af: LuksAf {
+                af_type: "luks1".to_string(),
+                stripes: STRIPES as u32,
+                hash: "sha256".to_string(),
+            },
+            area: LuksArea {
+                area_type: "raw".to_string(),
+                offset: KEYSLOT_OFFSET.to_string(),
+                size: material.len().to_string(),
+                encryption: LUKS2_CIPHER.to_string(),
+                key_size: master_key.len() as u32,
+            },
+            kdf: LuksKdf {
+                kdf_type: "pbkdf2".to_string(),
+                hash: "sha256".to_string(),
+                iterations: ITERATIONS,
+                salt: BASE64.encode(kdf_salt),
+            },
+        };
+        let segment = LuksSegment {
+            segment_type: "crypt".to_string(),
+            offset: PAYLOAD_OFFSET.to_string(),
+            size: "dynamic".to_string(),
+            iv_tweak: "0".to_string(),
+            encryption: LUKS2_CIPHER.to_string(),
+            sector_size,
+        };
+        let digest = LuksDigest {
+            digest_type: "pbkdf2".to_string(),
+            keyslots: vec!["0".to_string()],
+            segments: vec!["0".to_string()],
+            hash: "sha256".to_string(),
+            iterations: ITERATIONS,
+            salt: BASE64.encode(digest_salt),
+            digest: BASE64.encode(digest),
+        };
+        let metadata = LuksMetadata {
+            keyslots: BTreeMap::from([("0".to_string(), keyslot)]),
+            segments: BTreeMap::from([("0".to_string(), segment)]),
+            digests: BTreeMap::from([("0".to_string(), digest)]),
+        };
+
+        let mut header = LuksBinaryHeader {
+            version: LUKS2_VERSION,
+            hdr_size: 0x4000,
+            checksum_alg: "sha256".to_string(),
+            checksum: Vec::new(),
+        };
+        let mut buf = header.to_vec();
+        buf.extend(serde_json::to_vec(&metadata).unwrap());
+        buf.resize(header.hdr_size as usize, 0);
+        header.checksum = HashAlg::Sha256.digest(&buf);
+        let mut buf_with_csum = header.to_vec();
+        buf_with_csum.extend_from_slice(&buf[LUKS2_BIN_HEADER_SIZE..]);
+        file.write_all_at(&buf_with_csum, 0).unwrap();
+    }
+
+    fn open_luks_image(path: &str, passphrase: &str) -> Result<LuksDriver<()>> {
+        open_luks_image_with_engine(path, passphrase, AioEngine::Off)
+    }
+
+    fn open_luks_image_with_engine(
+        path: &str,
+        passphrase: &str,
+        engine: AioEngine,
+    ) -> Result<LuksDriver<()>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), engine).unwrap();
+        let prop = BlockProperty {
+            format: DiskFormat::Luks,
+            key_secret: Some(passphrase.to_string()),
+            ..Default::default()
+        };
+        LuksDriver::new(file, aio, prop)
+    }
+
+    fn read_data(driver: &mut LuksDriver<()>, offset: u64, len: usize) -> Vec<u8> {
+        let buf = vec![0_u8; len];
+        let iovec = vec![Iovec::new(buf.as_ptr() as u64, len as u64)];
+        driver.read_vectored(iovec, offset as usize, ()).unwrap();
+        buf
+    }
+
+    fn write_data(driver: &mut LuksDriver<()>, offset: u64, buf: &[u8]) {
+        let iovec = vec![Iovec::new(buf.as_ptr() as u64, buf.len() as u64)];
+        driver.write_vectored(iovec, offset as usize, ()).unwrap();
+    }
+
+    #[test]
+    fn test_luks_open() {
+        let path = "/tmp/test_luks_open.img";
+        let master_key: Vec<u8> = (0..64).collect();
+        create_luks_image(path, "passw0rd", &master_key, 512);
+
+        assert!(open_luks_image(path, "wrong").is_err());
+        let mut driver = open_luks_image(path, "passw0rd").unwrap();
+        assert_eq!(driver.disk_size().unwrap(), PAYLOAD_SIZE);
+        assert!(driver.create_image(&CreateOptions::default()).is_err());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_luks_read_write() {
+        for sector_size in [512, 4096] {
+            let path = "/tmp/test_luks_read_write.img";
+            let master_key: Vec<u8> = (0..64).map(|i| i * 3).collect();
+            create_luks_image(path, "passw0rd", &master_key, sector_size);
+            let mut driver = open_luks_image(path, "passw0rd").unwrap();
+
+            let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+            write_data(&mut driver, 512, &data);
+            assert_eq!(read_data(&mut driver, 512, data.len()), data);
+            assert_eq!(read_data(&mut driver, 1024, 512), data[512..1024]);
+
+            // Data in image is encrypted with the master key.
+            let file = File::open(path).unwrap();
+            let mut raw = vec![0_u8; data.len()];
+            file.read_exact_at(&mut raw, PAYLOAD_OFFSET + 512).unwrap();
+            assert_ne!(raw, data);
+            let mut sector = vec![0_u8; sector_size as usize];
+            file.read_exact_at(&mut sector, PAYLOAD_OFFSET + sector_size as u64)
+                .unwrap();
+            XtsCipher::new(&master_key).unwrap().decrypt(
+                &mut sector,
+                sector_size as usize,
+                u64::from(sector_size / 512),
+            );
+            let offset = sector_size as usize - 512;
+            assert_eq!(sector, data[offset..offset + sector_size as usize]);
+
+            // Write zeroes and out of range request.
+            driver.write_zeroes(1024, 512, (), false).unwrap();
+            assert_eq!(read_data(&mut driver, 1024, 512), vec![0_u8; 512]);
+            assert_eq!(read_data(&mut driver, 512, 512), data[..512]);
+            let buf = vec![0_u8; 512];
+            let iovec = vec![Iovec::new(buf.as_ptr() as u64, 512)];
+            assert!(driver
+                .read_vectored(iovec, PAYLOAD_SIZE as usize, ())
+                .is_err());
+
+            // Reopen the image.
+            drop(driver);
+            let mut driver = open_luks_image(path, "passw0rd").unwrap();
+            assert_eq!(read_data(&mut driver, 2048, 512), data[1536..2048]);
+
+            remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_luks_large_sector_iv() {
+        let path = "/tmp/test_luks_large_sector_iv.img";
+        let master_key: Vec<u8> = (0..64).collect();
+        create_luks_image(path, "passw0rd", &master_key, 4096);
+        let mut driver = open_luks_image(path, "passw0rd").unwrap();
+        let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+        write_data(&mut driver, 4096, &data);
+
+        // Ciphertext of aes-xts-plain64 with 4096 bytes sectors, whose IVs are 8 and 16
+        // as dm-crypt counts them in 512 bytes sectors.
+        let file = File::open(path).unwrap();
+        let mut raw = vec![0_u8; data.len()];
+        file.read_exact_at(&mut raw, PAYLOAD_OFFSET + 4096).unwrap();
+        assert_eq!(
+            raw[..32],
+            from_hex("d669f5e2d9e798975a7ec16179cd66c78ef2c4b05463fe13a7fcf9894375e2aa")
+        );
+        assert_eq!(
+            raw[4096..4128],
+            from_hex("a985efa5c67338ba488362c8a4e91eded3787e1e78640b000cbe882fe1f9a3eb")
+        );
+        assert_eq!(
+            digest::digest(&digest::SHA256, &raw).as_ref(),
+            from_hex("0853d077663d9fcc16612d9dd65e86800c4b8939fadc7ee60d120452ad42e83e")
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_luks_partial_sector_write() {
+        let path = "/tmp/test_luks_partial_sector_write.img";
+        let master_key: Vec<u8> = (0..64).collect();
+        create_luks_image(path, "passw0rd", &master_key, 4096);
+        let mut driver = open_luks_image_with_engine(path, "passw0rd", AioEngine::Threads).unwrap();
+
+        // Both halves of the sector are read-modify-write. They are written synchronously,
+        // so the second one loads the sector after the first one lands.
+        let head = vec![0x5a_u8; 2048];
+        let tail = vec![0xa5_u8; 2048];
+        write_data(&mut driver, 4096, &head);
+        write_data(&mut driver, 6144, &tail);
+
+        let file = File::open(path).unwrap();
+        let mut sector = vec![0_u8; 4096];
+        file.read_exact_at(&mut sector, PAYLOAD_OFFSET + 4096)
+            .unwrap();
+        XtsCipher::new(&master_key)
+            .unwrap()
+            .decrypt(&mut sector, 4096, 8);
+        assert_eq!(sector[..2048], head);
+        assert_eq!(sector[2048..], tail);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_luks_split_bounce() {
+        let path = "/tmp/test_luks_split_bounce.img";
+        let master_key: Vec<u8> = (0..32).collect();
+        create_luks_image(path, "passw0rd", &master_key, 4096);
+        let mut driver = open_luks_image(path, "passw0rd").unwrap();
+
+        // The request is larger than one bounce buffer, and not aligned to the sector.
+        let len = MAX_BOUNCE_BUF_SIZE as usize + 8192;
+        let data: Vec<u8> = (0..len).map(|i| (i % 253) as u8).collect();
+        write_data(&mut driver, 1536, &data);
+        assert_eq!(read_data(&mut driver, 1536, len), data);
+
+        driver
+            .write_zeroes(512, MAX_BOUNCE_BUF_SIZE + 1024, (), false)
+            .unwrap();
+        assert_eq!(
+            read_data(&mut driver, 512, MAX_BOUNCE_BUF_SIZE as usize + 1024),
+            vec![0_u8; MAX_BOUNCE_BUF_SIZE as usize + 1024]
+        );
+        let tail = 512 + MAX_BOUNCE_BUF_SIZE as usize + 1024;
+        assert_eq!(
+            read_data(&mut driver, tail as u64, 1024),
+            data[tail - 1536..tail - 512]
+        );
+
+        remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Mirror job which copies a drive to the target image while the guest keeps running.
+//! The writes of the guest are tracked by the dirty bitmap of the job and copied again,
+//! the job is ready once the target is in sync, and then the device can be switched
+//! to the target.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    os::unix::fs::FileExt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::{
+    dirty_bitmap::{DirtyBitmaps, DIRTY_BITMAP_GRANULARITY_DEFAULT},
+    qcow2::{InternalSnapshotOps, Qcow2Driver, SyncAioInfo},
+    raw::RawDriver,
+    BlockDriverOps, BlockProperty, CreateOptions,
+};
+use machine_manager::{
+    config::DiskFormat,
+    job::{job_get, job_register, Job, JobOps, JobStatus},
+    qmp::qmp_schema::BlockJobInfo,
+};
+use util::aio::{Aio, AioEngine, Iovec};
+
+/// Size of the buffer to copy data.
+const MIRROR_BUF_SIZE: u64 = 1 << 20;
+/// Interval to check the new writes when the target is in sync.
+const MIRROR_IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+type MirrorJobListType = Lazy<Mutex<HashMap<String, Arc<MirrorJob>>>>;
+/// Mirror jobs, indexed by job id.
+static MIRROR_JOB_LIST: MirrorJobListType = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The image which is mirrored.
+pub enum MirrorSource {
+    /// Raw image, which is read by the file opened by the job.
+    Raw(File),
+    /// Qcow2 image, which is read by the backend of the device, as the metadata is cached by it.
+    Qcow2(Arc<Mutex<dyn InternalSnapshotOps>>),
+}
+
+impl MirrorSource {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        match self {
+            MirrorSource::Raw(file) => file
+                .read_exact_at(buf, offset)
+                .with_context(|| format!("Failed to read source at offset {}", offset)),
+            MirrorSource::Qcow2(qcow2) => qcow2.lock().unwrap().read_data(offset, buf),
+        }
+    }
+}
+
+struct MirrorState {
+    source: MirrorSource,
+    target: Box<dyn BlockDriverOps<()>>,
+    /// The target is zero before the first pass finishes, so that zero data is skipped.
+    first_pass: bool,
+}
+
+pub struct MirrorJob {
+    /// Id of the job.
+    pub id: String,
+    /// Id of the drive which is mirrored.
+    pub device: String,
+    /// Path of the target image.
+    pub target: String,
+    /// Format of the target image.
+    pub format: DiskFormat,
+    /// The generic job, which records the status and progress.
+    pub job: Arc<Job>,
+    bitmaps: Arc<Mutex<DirtyBitmaps>>,
+    state: Mutex<MirrorState>,
+    /// Request the copying thread to stop.
+    stop: AtomicBool,
+    busy: AtomicBool,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    this: Weak<MirrorJob>,
+}
+
+impl MirrorJob {
+    fn copy_range(&self, state: &mut MirrorState, offset: u64, nbytes: u64) -> Result<()> {
+        let mut buf = vec![0_u8; std::cmp::min(nbytes, MIRROR_BUF_SIZE) as usize];
+        let mut copied = 0;
+        while copied < nbytes {
+            let len = std::cmp::min(nbytes - copied, MIRROR_BUF_SIZE) as usize;
+            let pos = offset + copied;
+            let data = &mut buf[..len];
+            state.source.read(pos, data)?;
+            if !state.first_pass || data.iter().any(|byte| *byte != 0) {
+                let iov = Iovec::new(data.as_ptr() as u64, len as u64);
+                state
+                    .target
+                    .write_vectored(vec![iov], pos as usize, ())
+                    .with_context(|| format!("Failed to write target at offset {}", pos))?;
+            }
+            copied += len as u64;
+            self.job.add_progress(len as u64);
+        }
+        Ok(())
+    }
+
+    /// Copy the dirty areas, return false if the target is already in sync. Copying
+    /// stops when `interruptible` is true and stopping is requested, the areas not
+    /// copied are marked dirty again.
+    fn copy_dirty(&self, interruptible: bool) -> Result<bool> {
+        let ranges = self.bitmaps.lock().unwrap().take_job_dirty(&self.id)?;
+        if ranges.is_empty() {
+            return Ok(false);
+        }
+        let (current, _) = self.job.progress();
+        self.job
+            .set_total(current + ranges.iter().map(|(_, len)| len).sum::<u64>());
+
+        let mut state = self.state.lock().unwrap();
+        for (index, &(offset, nbytes)) in ranges.iter().enumerate() {
+            let result = if interruptible && self.stop.load(Ordering::SeqCst) {
+                Err(None)
+            } else {
+                self.copy_range(&mut state, offset, nbytes).map_err(Some)
+            };
+            if let Err(e) = result {
+                let mut locked_bitmaps = self.bitmaps.lock().unwrap();
+                for &(offset, nbytes) in &ranges[index..] {
+                    locked_bitmaps.mark_job_dirty(&self.id, offset, nbytes)?;
+                }
+                // The areas marked dirty again are counted by the dirty bitmap.
+                self.job.set_total(self.job.progress().0);
+                return match e {
+                    Some(e) => Err(e),
+                    None => Ok(true),
+                };
+            }
+        }
+        state.first_pass = false;
+        Ok(true)
+    }
+
+    fn run(&self) {
+        while !self.stop.load(Ordering::SeqCst) {
+            self.busy.store(true, Ordering::SeqCst);
+            match self.copy_dirty(true) {
+                Ok(true) => continue,
+                Ok(false) => {
+                    if self.job.status() == JobStatus::Running {
+                        info!("Mirror job {} is ready", self.id);
+                        self.job
+                            .transit(JobStatus::Ready)
+                            .unwrap_or_else(|e| error!("{:?}", e));
+                    }
+                    self.busy.store(false, Ordering::SeqCst);
+                    thread::sleep(MIRROR_IDLE_INTERVAL);
+                }
+                Err(e) => {
+                    error!("Mirror job {} failed: {:?}", self.id, e);
+                    self.busy.store(false, Ordering::SeqCst);
+                    // The failed job is removed, and the target image is kept.
+                    MIRROR_JOB_LIST.lock().unwrap().remove(&self.id);
+                    self.bitmaps.lock().unwrap().remove_job_bitmap(&self.id);
+                    self.job.finish(Some(format!("{:?}", e)), false);
+                    return;
+                }
+            }
+        }
+        self.busy.store(false, Ordering::SeqCst);
+    }
+
+    /// Start the copying thread, it's also used to restart the job after it's stopped.
+    pub fn start(&self) -> Result<()> {
+        let job = self
+            .this
+            .upgrade()
+            .with_context(|| format!("Mirror job {} has been released", self.id))?;
+        self.stop.store(false, Ordering::SeqCst);
+        let handle = thread::Builder::new()
+            .name(format!("mirror-{}", self.id))
+            .spawn(move || job.run())
+            .with_context(|| "Failed to create mirror thread")?;
+        *self.thread.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    /// Stop the copying thread and wait for it to exit.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.thread.lock().unwrap().take() {
+            if handle.join().is_err() {
+                error!("Mirror thread of job {} panicked", self.id);
+            }
+        }
+    }
+
+    /// Copy the remaining dirty areas and flush the target. The job must be stopped, and
+    /// the writes of the device must be quiesced.
+    pub fn sync_target(&self) -> Result<()> {
+        while self.copy_dirty(false)? {}
+        self.state
+            .lock()
+            .unwrap()
+            .target
+            .datasync(())
+            .with_context(|| format!("Failed to flush target {}", self.target))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.job.status() == JobStatus::Ready
+    }
+
+    fn info(&self) -> BlockJobInfo {
+        let status = self.job.status();
+        let (offset, total) = self.job.progress();
+        let dirty = self
+            .bitmaps
+            .lock()
+            .unwrap()
+            .job_dirty_bytes(&self.id)
+            .unwrap_or(0);
+        BlockJobInfo {
+            job_type: self.job.job_type.clone(),
+            device: self.id.clone(),
+            len: total + dirty,
+            offset,
+            busy: self.busy.load(Ordering::SeqCst),
+            paused: status == JobStatus::Paused,
+            speed: 0,
+            io_status: "ok".to_string(),
+            ready: status == JobStatus::Ready,
+            status: status.as_str().to_string(),
+        }
+    }
+}
+
+impl JobOps for MirrorJob {
+    fn pause(&self) -> Result<()> {
+        self.stop();
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        self.start()
+    }
+
+    fn cancel(&self) -> Result<()> {
+        mirror_finish(&self.id, true)
+    }
+}
+
+/// Create the target image, and open it to be written synchronously.
+fn create_target(path: &str, format: DiskFormat, size: u64) -> Result<Box<dyn BlockDriverOps<()>>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create target image {}", path))?;
+    let conf = BlockProperty {
+        id: path.to_string(),
+        format,
+        ..Default::default()
+    };
+    let options = CreateOptions {
+        path: path.to_string(),
+        img_size: size,
+        conf: conf.clone(),
+        ..Default::default()
+    };
+    let new_aio = || Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off);
+    let result = match format {
+        DiskFormat::Raw => {
+            let mut raw = RawDriver::new(file, new_aio()?, conf);
+            raw.create_image(&options)
+                .map(|_| Box::new(raw) as Box<dyn BlockDriverOps<()>>)
+        }
+        DiskFormat::Qcow2 => Qcow2Driver::new(file.try_clone()?, new_aio()?, conf.clone())
+            .and_then(|mut qcow2| qcow2.create_image(&options))
+            .and_then(|_| {
+                let mut qcow2 = Qcow2Driver::new(file, new_aio()?, conf.clone())?;
+                qcow2.load_metadata(conf)?;
+                Ok(Box::new(qcow2) as Box<dyn BlockDriverOps<()>>)
+            }),
+        DiskFormat::Luks => Err(anyhow::anyhow!("Target of format luks is not supported")),
+    };
+    if result.is_err() {
+        std::fs::remove_file(path)
+            .unwrap_or_else(|e| error!("Failed to remove target image {}: {:?}", path, e));
+    }
+    result
+}
+
+/// Start the job which mirrors the drive `device` to the new image `target`. All the
+/// data is copied, and the writes are tracked by `bitmaps` of the drive.
+pub fn mirror_start(
+    id: &str,
+    device: &str,
+    target: &str,
+    format: DiskFormat,
+    source: MirrorSource,
+    bitmaps: Arc<Mutex<DirtyBitmaps>>,
+) -> Result<()> {
+    let mut jobs = MIRROR_JOB_LIST.lock().unwrap();
+    if jobs.contains_key(id) || job_get(id).is_ok() {
+        bail!("Block job {} already exists", id);
+    }
+    if jobs.values().any(|job| job.device == device) {
+        bail!("Drive {} is in use by another block job", device);
+    }
+
+    let size = bitmaps.lock().unwrap().disk_size();
+    let target_driver = create_target(target, format, size)?;
+    bitmaps
+        .lock()
+        .unwrap()
+        .add_job_bitmap(id, DIRTY_BITMAP_GRANULARITY_DEFAULT, true)?;
+    let mirror = Arc::new_cyclic(|this: &Weak<MirrorJob>| MirrorJob {
+        id: id.to_string(),
+        device: device.to_string(),
+        target: target.to_string(),
+        format,
+        job: Arc::new(Job::new(id, "mirror", this.clone())),
+        bitmaps: bitmaps.clone(),
+        state: Mutex::new(MirrorState {
+            source,
+            target: target_driver,
+            first_pass: true,
+        }),
+        stop: AtomicBool::new(false),
+        busy: AtomicBool::new(false),
+        thread: Mutex::new(None),
+        this: this.clone(),
+    });
+    let result = job_register(mirror.job.clone()).and_then(|()| {
+        mirror.job.transit(JobStatus::Running)?;
+        mirror.start().map_err(|e| {
+            mirror.job.finish(Some(format!("{:?}", e)), false);
+            e
+        })
+    });
+    if let Err(e) = result {
+        bitmaps.lock().unwrap().remove_job_bitmap(id);
+        return Err(e);
+    }
+    info!("Mirror job {} starts to copy {} to {}", id, device, target);
+    jobs.insert(id.to_string(), mirror);
+    Ok(())
+}
+
+pub fn mirror_job(id: &str) -> Result<Arc<MirrorJob>> {
+    MIRROR_JOB_LIST
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .with_context(|| format!("Block job {} not found", id))
+}
+
+/// Whether the drive is mirrored by a job.
+pub fn mirror_job_active(device: &str) -> bool {
+    MIRROR_JOB_LIST
+        .lock()
+        .unwrap()
+        .values()
+        .any(|job| job.device == device)
+}
+
+/// Stop the job and remove it, the target image is kept.
+pub fn mirror_finish(id: &str, cancelled: bool) -> Result<()> {
+    let mirror = MIRROR_JOB_LIST
+        .lock()
+        .unwrap()
+        .remove(id)
+        .with_context(|| format!("Block job {} not found", id))?;
+    mirror.stop();
+    mirror.bitmaps.lock().unwrap().remove_job_bitmap(id);
+    mirror.job.finish(None, cancelled);
+    Ok(())
+}
+
+pub fn query_mirror_jobs() -> Vec<BlockJobInfo> {
+    let mut jobs: Vec<BlockJobInfo> = MIRROR_JOB_LIST
+        .lock()
+        .unwrap()
+        .values()
+        .map(|job| job.info())
+        .collect();
+    jobs.sort_by(|a, b| a.device.cmp(&b.device));
+    jobs
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use machine_manager::qmp::qmp_channel::QmpChannel;
+
+    const TEST_DISK_SIZE: u64 = 4 << 20;
+
+    fn tmp_path(name: &str) -> String {
+        format!("/tmp/mirror_{}_{}", name, std::process::id())
+    }
+
+    fn wait_ready(job: &MirrorJob) {
+        let start = Instant::now();
+        while !job.is_ready() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn read_target(job: &MirrorJob, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0_u8; len];
+        let iov = Iovec::new(buf.as_mut_ptr() as u64, len as u64);
+        job.state
+            .lock()
+            .unwrap()
+            .target
+            .read_vectored(vec![iov], offset as usize, ())
+            .unwrap();
+        buf
+    }
+
+    fn test_mirror(format: DiskFormat) {
+        let source_path = tmp_path(&format!("source_{}", format.to_string()));
+        let target_path = tmp_path(&format!("target_{}", format.to_string()));
+        let source = File::create(&source_path).unwrap();
+        source.set_len(TEST_DISK_SIZE).unwrap();
+        source.write_all_at(&[1_u8; 4096], 0).unwrap();
+        source
+            .write_all_at(&[2_u8; 512], TEST_DISK_SIZE - 512)
+            .unwrap();
+
+        QmpChannel::object_init();
+        let bitmaps = Arc::new(Mutex::new(DirtyBitmaps::new(TEST_DISK_SIZE, None)));
+        let job_id = format!("job-{}", format.to_string());
+        mirror_start(
+            &job_id,
+            "drive0",
+            &target_path,
+            format,
+            MirrorSource::Raw(File::open(&source_path).unwrap()),
+            bitmaps.clone(),
+        )
+        .unwrap();
+        assert!(mirror_job_active("drive0"));
+        let job = mirror_job(&job_id).unwrap();
+        wait_ready(&job);
+        assert_eq!(read_target(&job, 0, 4096), vec![1_u8; 4096]);
+        let info = job.info();
+        assert_eq!(info.len, TEST_DISK_SIZE);
+        assert_eq!(info.offset, TEST_DISK_SIZE);
+        assert_eq!(info.status, "ready");
+
+        // New writes are copied after being marked dirty.
+        source.write_all_at(&[3_u8; 512], 1 << 20).unwrap();
+        bitmaps.lock().unwrap().mark_dirty(1 << 20, 512);
+        job.stop();
+        assert_eq!(
+            job.info().len,
+            TEST_DISK_SIZE + DIRTY_BITMAP_GRANULARITY_DEFAULT
+        );
+        job.sync_target().unwrap();
+        assert_eq!(read_target(&job, 1 << 20, 512), vec![3_u8; 512]);
+        assert_eq!(
+            read_target(&job, TEST_DISK_SIZE - 512, 512),
+            vec![2_u8; 512]
+        );
+        assert_eq!(read_target(&job, 4096, 512), vec![0_u8; 512]);
+        let progress = TEST_DISK_SIZE + DIRTY_BITMAP_GRANULARITY_DEFAULT;
+        assert_eq!(job.job.progress(), (progress, progress));
+
+        mirror_finish(&job_id, false).unwrap();
+        assert!(mirror_job(&job_id).is_err());
+        assert!(job_get(&job_id).is_err());
+        assert_eq!(job.job.status(), JobStatus::Done);
+        assert!(bitmaps.lock().unwrap().take_job_dirty(&job_id).is_err());
+        drop(job);
+        std::fs::remove_file(&source_path).unwrap();
+        std::fs::remove_file(&target_path).unwrap();
+    }
+
+    #[test]
+    fn test_mirror_raw_and_qcow2() {
+        test_mirror(DiskFormat::Raw);
+        test_mirror(DiskFormat::Qcow2);
+    }
+}
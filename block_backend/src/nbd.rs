@@ -0,0 +1,727 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Embedded NBD server which exports the drives of the running VM, the protocol
+//! is described in https://github.com/NetworkBlockDevice/nbd/blob/master/doc/proto.md.
+//! Only the fixed newstyle negotiation and simple replies are supported.
+
+use std::{
+    cmp,
+    collections::HashMap,
+    fs::File,
+    io::{Read, Write},
+    net::TcpListener,
+    os::unix::net::UnixListener,
+    path::Path,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::{create_block_backend, BlockDriverOps, BlockProperty};
+use machine_manager::config::{parse_incoming_uri, DiskFormat, MigrateMode};
+use util::aio::{Aio, AioCb, AioEngine, AioReqResult, Iovec};
+
+const NBD_MAGIC: u64 = 0x4e42_444d_4147_4943;
+const NBD_OPTS_MAGIC: u64 = 0x4948_4156_454f_5054;
+const NBD_REP_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const NBD_REQUEST_MAGIC: u32 = 0x2560_9513;
+const NBD_SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+/// Handshake flags.
+const NBD_FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const NBD_FLAG_NO_ZEROES: u16 = 1 << 1;
+/// Client flags.
+const NBD_FLAG_C_FIXED_NEWSTYLE: u32 = 1 << 0;
+const NBD_FLAG_C_NO_ZEROES: u32 = 1 << 1;
+
+/// Transmission flags.
+const NBD_FLAG_HAS_FLAGS: u16 = 1 << 0;
+const NBD_FLAG_READ_ONLY: u16 = 1 << 1;
+const NBD_FLAG_SEND_FLUSH: u16 = 1 << 2;
+const NBD_FLAG_SEND_FUA: u16 = 1 << 3;
+const NBD_FLAG_SEND_TRIM: u16 = 1 << 5;
+const NBD_FLAG_SEND_WRITE_ZEROES: u16 = 1 << 6;
+
+/// Options.
+const NBD_OPT_EXPORT_NAME: u32 = 1;
+const NBD_OPT_ABORT: u32 = 2;
+const NBD_OPT_LIST: u32 = 3;
+const NBD_OPT_INFO: u32 = 6;
+const NBD_OPT_GO: u32 = 7;
+
+/// Option replies.
+const NBD_REP_ACK: u32 = 1;
+const NBD_REP_SERVER: u32 = 2;
+const NBD_REP_INFO: u32 = 3;
+const NBD_REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const NBD_REP_ERR_INVALID: u32 = (1 << 31) + 3;
+const NBD_REP_ERR_UNKNOWN: u32 = (1 << 31) + 6;
+const NBD_INFO_EXPORT: u16 = 0;
+
+/// Commands and command flags.
+const NBD_CMD_READ: u16 = 0;
+const NBD_CMD_WRITE: u16 = 1;
+const NBD_CMD_DISC: u16 = 2;
+const NBD_CMD_FLUSH: u16 = 3;
+const NBD_CMD_TRIM: u16 = 4;
+const NBD_CMD_WRITE_ZEROES: u16 = 6;
+const NBD_CMD_FLAG_FUA: u16 = 1 << 0;
+const NBD_CMD_FLAG_NO_HOLE: u16 = 1 << 1;
+
+/// Errors of transmission replies.
+const NBD_EPERM: u32 = 1;
+const NBD_EIO: u32 = 5;
+const NBD_EINVAL: u32 = 22;
+
+/// Max length of the option data sent by client.
+const NBD_MAX_OPTION_SIZE: usize = 4096;
+/// Max length of the read/write request.
+const NBD_MAX_BUFFER_SIZE: u32 = 32 << 20;
+/// Length of the buffer used to fall back write zeroes.
+const NBD_ZERO_BUFFER_SIZE: u64 = 1 << 20;
+const NBD_REQUEST_SIZE: usize = 28;
+
+#[derive(Clone)]
+pub struct NbdCompleteCb {
+    sender: Sender<i64>,
+}
+
+fn nbd_complete_func(aiocb: &AioCb<NbdCompleteCb>, mut ret: i64) -> Result<()> {
+    match aiocb.req_is_completed(ret) {
+        AioReqResult::Inflight => return Ok(()),
+        AioReqResult::Error(v) => ret = v,
+        AioReqResult::Done => (),
+    }
+    aiocb
+        .iocompletecb
+        .sender
+        .send(ret)
+        .with_context(|| "Failed to send the result of nbd request")
+}
+
+struct NbdExport {
+    size: u64,
+    read_only: bool,
+    discard: bool,
+    backend: Arc<Mutex<dyn BlockDriverOps<NbdCompleteCb>>>,
+}
+
+impl NbdExport {
+    fn flags(&self) -> u16 {
+        let mut flags = NBD_FLAG_HAS_FLAGS | NBD_FLAG_SEND_FLUSH;
+        if self.read_only {
+            flags |= NBD_FLAG_READ_ONLY;
+        } else {
+            flags |= NBD_FLAG_SEND_FUA | NBD_FLAG_SEND_WRITE_ZEROES;
+            if self.discard {
+                flags |= NBD_FLAG_SEND_TRIM;
+            }
+        }
+        flags
+    }
+
+    /// Submit the request to the backend and wait for its completion.
+    fn request<F>(&self, op: F) -> Result<i64>
+    where
+        F: FnOnce(&mut dyn BlockDriverOps<NbdCompleteCb>, NbdCompleteCb) -> Result<()>,
+    {
+        let (sender, receiver) = channel();
+        let mut locked_backend = self.backend.lock().unwrap();
+        op(&mut *locked_backend, NbdCompleteCb { sender })?;
+        locked_backend.flush_request()?;
+        drop(locked_backend);
+        receiver
+            .recv()
+            .with_context(|| "Failed to receive the result of nbd request")
+    }
+
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<i64> {
+        let iovec = vec![Iovec::new(buf.as_mut_ptr() as u64, buf.len() as u64)];
+        self.request(|backend, cb| backend.read_vectored(iovec, offset as usize, cb))
+    }
+
+    fn write(&self, buf: &[u8], offset: u64) -> Result<i64> {
+        let iovec = vec![Iovec::new(buf.as_ptr() as u64, buf.len() as u64)];
+        self.request(|backend, cb| backend.write_vectored(iovec, offset as usize, cb))
+    }
+
+    fn write_zeroes(&self, offset: u64, nbytes: u64, unmap: bool) -> Result<i64> {
+        let ret =
+            self.request(|backend, cb| backend.write_zeroes(offset as usize, nbytes, cb, unmap))?;
+        if ret >= 0 {
+            return Ok(ret);
+        }
+
+        // Write zeroes is not supported by the host file, write the zero buffer instead.
+        let zeroes = vec![0_u8; cmp::min(nbytes, NBD_ZERO_BUFFER_SIZE) as usize];
+        let mut done = 0;
+        while done < nbytes {
+            let len = cmp::min(nbytes - done, zeroes.len() as u64) as usize;
+            let ret = self.write(&zeroes[..len], offset + done)?;
+            if ret < 0 {
+                return Ok(ret);
+            }
+            done += len as u64;
+        }
+        Ok(0)
+    }
+
+    fn discard(&self, offset: u64, nbytes: u64) -> Result<i64> {
+        self.request(|backend, cb| backend.discard(offset as usize, nbytes, cb))
+    }
+
+    fn flush(&self) -> Result<i64> {
+        self.request(|backend, cb| backend.datasync(cb))
+    }
+}
+
+type NbdExports = Arc<Mutex<HashMap<String, Arc<NbdExport>>>>;
+
+struct NbdServer {
+    exports: NbdExports,
+}
+
+static NBD_SERVER: Lazy<Mutex<Option<NbdServer>>> = Lazy::new(|| Mutex::new(None));
+
+/// Start the nbd server listening on `uri`, which is `unix:<path>` or `tcp:<ip>:<port>`.
+pub fn nbd_server_start(uri: &str) -> Result<()> {
+    let mut server = NBD_SERVER.lock().unwrap();
+    if server.is_some() {
+        bail!("NBD server is already running");
+    }
+
+    let exports: NbdExports = Arc::new(Mutex::new(HashMap::new()));
+    let cloned_exports = exports.clone();
+    let (mode, path) = parse_incoming_uri(uri)?;
+    match mode {
+        MigrateMode::Unix => {
+            if Path::new(&path).exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove socket file {}", path))?;
+            }
+            let listener = UnixListener::bind(&path)
+                .with_context(|| format!("Failed to bind nbd server to {}", path))?;
+            thread::Builder::new()
+                .name("nbd-server".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream {
+                            Ok(stream) => spawn_client(stream, cloned_exports.clone()),
+                            Err(e) => error!("Failed to accept nbd client: {:?}", e),
+                        }
+                    }
+                })?;
+        }
+        MigrateMode::Tcp => {
+            let listener = TcpListener::bind(&path)
+                .with_context(|| format!("Failed to bind nbd server to {}", path))?;
+            thread::Builder::new()
+                .name("nbd-server".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream {
+                            Ok(stream) => spawn_client(stream, cloned_exports.clone()),
+                            Err(e) => error!("Failed to accept nbd client: {:?}", e),
+                        }
+                    }
+                })?;
+        }
+        _ => bail!("Unsupported nbd server address {}", uri),
+    }
+    info!("NBD server is listening on {}", uri);
+
+    *server = Some(NbdServer { exports });
+    Ok(())
+}
+
+/// Export the drive by the name. The export opens its own backend on the
+/// drive file, and requests are executed by aio synchronously.
+pub fn nbd_server_add(name: &str, file: File, prop: BlockProperty, read_only: bool) -> Result<()> {
+    let server = NBD_SERVER.lock().unwrap();
+    let server = server
+        .as_ref()
+        .with_context(|| "NBD server is not running")?;
+    let mut exports = server.exports.lock().unwrap();
+    if exports.contains_key(name) {
+        bail!("NBD export {} already exists", name);
+    }
+    // The metadata of qcow2 is cached by the backend of the device.
+    if !read_only && prop.format == DiskFormat::Qcow2 {
+        bail!("Writable nbd export is not supported for qcow2 drive");
+    }
+
+    let discard = prop.discard;
+    let aio = Aio::new(Arc::new(nbd_complete_func), AioEngine::Off)?;
+    let backend = create_block_backend(file, aio, prop)?;
+    let size = backend.lock().unwrap().disk_size()?;
+    exports.insert(
+        name.to_string(),
+        Arc::new(NbdExport {
+            size,
+            read_only,
+            discard,
+            backend,
+        }),
+    );
+    Ok(())
+}
+
+fn spawn_client<S: Read + Write + Send + 'static>(stream: S, exports: NbdExports) {
+    let result = thread::Builder::new()
+        .name("nbd-client".to_string())
+        .spawn(move || {
+            let mut client = NbdClient {
+                stream,
+                exports,
+                no_zeroes: false,
+            };
+            if let Err(e) = client.run() {
+                error!("NBD client exits: {:?}", e);
+            }
+        });
+    if let Err(e) = result {
+        error!("Failed to create nbd client thread: {:?}", e);
+    }
+}
+
+struct NbdClient<S: Read + Write> {
+    stream: S,
+    exports: NbdExports,
+    /// Omit the zeroes padding of NBD_OPT_EXPORT_NAME reply.
+    no_zeroes: bool,
+}
+
+impl<S: Read + Write> NbdClient<S> {
+    fn run(&mut self) -> Result<()> {
+        if let Some(export) = self.negotiate()? {
+            self.transmit(export)?;
+        }
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0_u8; 4];
+        self.stream.read_exact(&mut buf)?;
+        Ok(BigEndian::read_u32(&buf))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0_u8; 8];
+        self.stream.read_exact(&mut buf)?;
+        Ok(BigEndian::read_u64(&buf))
+    }
+
+    fn find_export(&self, name: &[u8]) -> Option<Arc<NbdExport>> {
+        let name = String::from_utf8_lossy(name);
+        self.exports.lock().unwrap().get(name.as_ref()).cloned()
+    }
+
+    fn send_option_reply(&mut self, option: u32, reply: u32, data: &[u8]) -> Result<()> {
+        let mut buf = vec![0_u8; 20];
+        BigEndian::write_u64(&mut buf[0..8], NBD_REP_MAGIC);
+        BigEndian::write_u32(&mut buf[8..12], option);
+        BigEndian::write_u32(&mut buf[12..16], reply);
+        BigEndian::write_u32(&mut buf[16..20], data.len() as u32);
+        buf.extend_from_slice(data);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    fn send_export_info(&mut self, option: u32, export: &NbdExport) -> Result<()> {
+        let mut info = [0_u8; 12];
+        BigEndian::write_u16(&mut info[0..2], NBD_INFO_EXPORT);
+        BigEndian::write_u64(&mut info[2..10], export.size);
+        BigEndian::write_u16(&mut info[10..12], export.flags());
+        self.send_option_reply(option, NBD_REP_INFO, &info)?;
+        self.send_option_reply(option, NBD_REP_ACK, &[])
+    }
+
+    /// Handle the handshake and options, return the export to serve or `None`
+    /// if client aborts.
+    fn negotiate(&mut self) -> Result<Option<Arc<NbdExport>>> {
+        let mut buf = [0_u8; 18];
+        BigEndian::write_u64(&mut buf[0..8], NBD_MAGIC);
+        BigEndian::write_u64(&mut buf[8..16], NBD_OPTS_MAGIC);
+        BigEndian::write_u16(
+            &mut buf[16..18],
+            NBD_FLAG_FIXED_NEWSTYLE | NBD_FLAG_NO_ZEROES,
+        );
+        self.stream.write_all(&buf)?;
+
+        let client_flags = self.read_u32()?;
+        if client_flags & !(NBD_FLAG_C_FIXED_NEWSTYLE | NBD_FLAG_C_NO_ZEROES) != 0 {
+            bail!("Unsupported nbd client flags 0x{:x}", client_flags);
+        }
+        self.no_zeroes = client_flags & NBD_FLAG_C_NO_ZEROES != 0;
+
+        loop {
+            if self.read_u64()? != NBD_OPTS_MAGIC {
+                bail!("Invalid magic of nbd option");
+            }
+            let option = self.read_u32()?;
+            let len = self.read_u32()? as usize;
+            if len > NBD_MAX_OPTION_SIZE {
+                bail!("Too long nbd option data {}", len);
+            }
+            let mut data = vec![0_u8; len];
+            self.stream.read_exact(&mut data)?;
+
+            match option {
+                NBD_OPT_EXPORT_NAME => {
+                    let export = self
+                        .find_export(&data)
+                        .with_context(|| "Unknown nbd export")?;
+                    let mut reply = vec![0_u8; 10];
+                    BigEndian::write_u64(&mut reply[0..8], export.size);
+                    BigEndian::write_u16(&mut reply[8..10], export.flags());
+                    if !self.no_zeroes {
+                        reply.resize(reply.len() + 124, 0);
+                    }
+                    self.stream.write_all(&reply)?;
+                    return Ok(Some(export));
+                }
+                NBD_OPT_ABORT => {
+                    self.send_option_reply(option, NBD_REP_ACK, &[])?;
+                    return Ok(None);
+                }
+                NBD_OPT_LIST => {
+                    if len != 0 {
+                        self.send_option_reply(option, NBD_REP_ERR_INVALID, &[])?;
+                        continue;
+                    }
+                    let names: Vec<String> = self.exports.lock().unwrap().keys().cloned().collect();
+                    for name in names {
+                        let mut reply = vec![0_u8; 4];
+                        BigEndian::write_u32(&mut reply, name.len() as u32);
+                        reply.extend_from_slice(name.as_bytes());
+                        self.send_option_reply(option, NBD_REP_SERVER, &reply)?;
+                    }
+                    self.send_option_reply(option, NBD_REP_ACK, &[])?;
+                }
+                NBD_OPT_INFO | NBD_OPT_GO => {
+                    let name = match parse_info_request(&data) {
+                        Some(name) => name,
+                        None => {
+                            self.send_option_reply(option, NBD_REP_ERR_INVALID, &[])?;
+                            continue;
+                        }
+                    };
+                    let export = match self.find_export(name) {
+                        Some(export) => export,
+                        None => {
+                            self.send_option_reply(option, NBD_REP_ERR_UNKNOWN, &[])?;
+                            continue;
+                        }
+                    };
+                    self.send_export_info(option, &export)?;
+                    if option == NBD_OPT_GO {
+                        return Ok(Some(export));
+                    }
+                }
+                _ => self.send_option_reply(option, NBD_REP_ERR_UNSUP, &[])?,
+            }
+        }
+    }
+
+    fn send_reply(&mut self, handle: u64, error: u32, data: &[u8]) -> Result<()> {
+        let mut buf = vec![0_u8; 16];
+        BigEndian::write_u32(&mut buf[0..4], NBD_SIMPLE_REPLY_MAGIC);
+        BigEndian::write_u32(&mut buf[4..8], error);
+        BigEndian::write_u64(&mut buf[8..16], handle);
+        if error == 0 {
+            buf.extend_from_slice(data);
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    fn transmit(&mut self, export: Arc<NbdExport>) -> Result<()> {
+        loop {
+            let mut req = [0_u8; NBD_REQUEST_SIZE];
+            self.stream.read_exact(&mut req)?;
+            if BigEndian::read_u32(&req[0..4]) != NBD_REQUEST_MAGIC {
+                bail!("Invalid magic of nbd request");
+            }
+            let flags = BigEndian::read_u16(&req[4..6]);
+            let cmd = BigEndian::read_u16(&req[6..8]);
+            let handle = BigEndian::read_u64(&req[8..16]);
+            let offset = BigEndian::read_u64(&req[16..24]);
+            let len = BigEndian::read_u32(&req[24..28]);
+            let in_range = offset
+                .checked_add(len as u64)
+                .filter(|&end| end <= export.size)
+                .is_some();
+
+            let mut data = Vec::new();
+            let error = match cmd {
+                NBD_CMD_DISC => return Ok(()),
+                NBD_CMD_READ => {
+                    if !in_range || len > NBD_MAX_BUFFER_SIZE {
+                        NBD_EINVAL
+                    } else {
+                        data.resize(len as usize, 0);
+                        result_to_error(export.read(&mut data, offset)?)
+                    }
+                }
+                NBD_CMD_WRITE => {
+                    if len > NBD_MAX_BUFFER_SIZE {
+                        bail!("Too long nbd write request {}", len);
+                    }
+                    // The payload must be consumed even if the request fails.
+                    let mut payload = vec![0_u8; len as usize];
+                    self.stream.read_exact(&mut payload)?;
+                    if export.read_only {
+                        NBD_EPERM
+                    } else if !in_range {
+                        NBD_EINVAL
+                    } else {
+                        let mut error = result_to_error(export.write(&payload, offset)?);
+                        if error == 0 && flags & NBD_CMD_FLAG_FUA != 0 {
+                            error = result_to_error(export.flush()?);
+                        }
+                        error
+                    }
+                }
+                NBD_CMD_FLUSH => result_to_error(export.flush()?),
+                NBD_CMD_TRIM => {
+                    if export.read_only {
+                        NBD_EPERM
+                    } else if !in_range {
+                        NBD_EINVAL
+                    } else if !export.discard {
+                        // Trim is advisory, nothing to do.
+                        0
+                    } else {
+                        result_to_error(export.discard(offset, len as u64)?)
+                    }
+                }
+                NBD_CMD_WRITE_ZEROES => {
+                    if export.read_only {
+                        NBD_EPERM
+                    } else if !in_range {
+                        NBD_EINVAL
+                    } else {
+                        let unmap = export.discard && flags & NBD_CMD_FLAG_NO_HOLE == 0;
+                        let mut error =
+                            result_to_error(export.write_zeroes(offset, len as u64, unmap)?);
+                        if error == 0 && flags & NBD_CMD_FLAG_FUA != 0 {
+                            error = result_to_error(export.flush()?);
+                        }
+                        error
+                    }
+                }
+                _ => NBD_EINVAL,
+            };
+            self.send_reply(handle, error, &data)?;
+        }
+    }
+}
+
+/// Get the export name from the data of NBD_OPT_INFO and NBD_OPT_GO, which is
+/// name length(4) + name + number of info requests(2) + info requests(2 * N).
+fn parse_info_request(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 6 {
+        return None;
+    }
+    let name_end = 4 + BigEndian::read_u32(&data[0..4]) as usize;
+    if name_end + 2 > data.len() {
+        return None;
+    }
+    let requests = BigEndian::read_u16(&data[name_end..name_end + 2]) as usize;
+    if data.len() != name_end + 2 + requests * 2 {
+        return None;
+    }
+    Some(&data[4..name_end])
+}
+
+fn result_to_error(ret: i64) -> u32 {
+    if ret < 0 {
+        NBD_EIO
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::raw::RawDriver;
+
+    fn create_export(path: &str, size: u64, read_only: bool) -> Arc<NbdExport> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        file.set_len(size).unwrap();
+        let aio = Aio::new(Arc::new(nbd_complete_func), AioEngine::Off).unwrap();
+        let backend = RawDriver::new(file, aio, BlockProperty::default());
+        Arc::new(NbdExport {
+            size,
+            read_only,
+            discard: false,
+            backend: Arc::new(Mutex::new(backend)),
+        })
+    }
+
+    fn write_request(stream: &mut UnixStream, cmd: u16, handle: u64, offset: u64, len: u32) {
+        let mut req = [0_u8; NBD_REQUEST_SIZE];
+        BigEndian::write_u32(&mut req[0..4], NBD_REQUEST_MAGIC);
+        BigEndian::write_u16(&mut req[6..8], cmd);
+        BigEndian::write_u64(&mut req[8..16], handle);
+        BigEndian::write_u64(&mut req[16..24], offset);
+        BigEndian::write_u32(&mut req[24..28], len);
+        stream.write_all(&req).unwrap();
+    }
+
+    fn read_reply(stream: &mut UnixStream, handle: u64) -> u32 {
+        let mut reply = [0_u8; 16];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(BigEndian::read_u32(&reply[0..4]), NBD_SIMPLE_REPLY_MAGIC);
+        assert_eq!(BigEndian::read_u64(&reply[8..16]), handle);
+        BigEndian::read_u32(&reply[4..8])
+    }
+
+    fn read_option_reply(stream: &mut UnixStream, option: u32) -> (u32, Vec<u8>) {
+        let mut reply = [0_u8; 20];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(BigEndian::read_u64(&reply[0..8]), NBD_REP_MAGIC);
+        assert_eq!(BigEndian::read_u32(&reply[8..12]), option);
+        let mut data = vec![0_u8; BigEndian::read_u32(&reply[16..20]) as usize];
+        stream.read_exact(&mut data).unwrap();
+        (BigEndian::read_u32(&reply[12..16]), data)
+    }
+
+    fn write_option(stream: &mut UnixStream, option: u32, data: &[u8]) {
+        let mut buf = vec![0_u8; 16];
+        BigEndian::write_u64(&mut buf[0..8], NBD_OPTS_MAGIC);
+        BigEndian::write_u32(&mut buf[8..12], option);
+        BigEndian::write_u32(&mut buf[12..16], data.len() as u32);
+        buf.extend_from_slice(data);
+        stream.write_all(&buf).unwrap();
+    }
+
+    fn go_option_data(name: &str) -> Vec<u8> {
+        let mut data = vec![0_u8; 4];
+        BigEndian::write_u32(&mut data, name.len() as u32);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_nbd_negotiate_and_transmit() {
+        let path = format!("/tmp/nbd_test_{}.img", std::process::id());
+        let exports: NbdExports = Arc::new(Mutex::new(HashMap::new()));
+        exports
+            .lock()
+            .unwrap()
+            .insert("rw".to_string(), create_export(&path, 1 << 20, false));
+        let ro_path = format!("{}.ro", path);
+        exports
+            .lock()
+            .unwrap()
+            .insert("ro".to_string(), create_export(&ro_path, 1 << 20, true));
+
+        // Read-write export negotiated by NBD_OPT_GO.
+        let (mut stream, server) = UnixStream::pair().unwrap();
+        spawn_client(server, exports.clone());
+        let mut handshake = [0_u8; 18];
+        stream.read_exact(&mut handshake).unwrap();
+        assert_eq!(BigEndian::read_u64(&handshake[0..8]), NBD_MAGIC);
+        assert_eq!(BigEndian::read_u64(&handshake[8..16]), NBD_OPTS_MAGIC);
+        stream
+            .write_all(&(NBD_FLAG_C_FIXED_NEWSTYLE | NBD_FLAG_C_NO_ZEROES).to_be_bytes())
+            .unwrap();
+
+        write_option(&mut stream, NBD_OPT_LIST, &[]);
+        let mut names = Vec::new();
+        loop {
+            let (reply, data) = read_option_reply(&mut stream, NBD_OPT_LIST);
+            if reply == NBD_REP_ACK {
+                break;
+            }
+            assert_eq!(reply, NBD_REP_SERVER);
+            names.push(String::from_utf8(data[4..].to_vec()).unwrap());
+        }
+        names.sort();
+        assert_eq!(names, vec!["ro".to_string(), "rw".to_string()]);
+
+        write_option(&mut stream, NBD_OPT_GO, &go_option_data("none"));
+        assert_eq!(
+            read_option_reply(&mut stream, NBD_OPT_GO).0,
+            NBD_REP_ERR_UNKNOWN
+        );
+        write_option(&mut stream, 100, &[]);
+        assert_eq!(read_option_reply(&mut stream, 100).0, NBD_REP_ERR_UNSUP);
+
+        write_option(&mut stream, NBD_OPT_GO, &go_option_data("rw"));
+        let (reply, info) = read_option_reply(&mut stream, NBD_OPT_GO);
+        assert_eq!(reply, NBD_REP_INFO);
+        assert_eq!(BigEndian::read_u64(&info[2..10]), 1 << 20);
+        assert_eq!(BigEndian::read_u16(&info[10..12]) & NBD_FLAG_READ_ONLY, 0);
+        assert_eq!(read_option_reply(&mut stream, NBD_OPT_GO).0, NBD_REP_ACK);
+
+        write_request(&mut stream, NBD_CMD_WRITE, 1, 4096, 512);
+        stream.write_all(&[0x5a_u8; 512]).unwrap();
+        assert_eq!(read_reply(&mut stream, 1), 0);
+        write_request(&mut stream, NBD_CMD_READ, 2, 4096 - 512, 1024);
+        assert_eq!(read_reply(&mut stream, 2), 0);
+        let mut data = [0_u8; 1024];
+        stream.read_exact(&mut data).unwrap();
+        assert!(data[..512].iter().all(|&b| b == 0));
+        assert!(data[512..].iter().all(|&b| b == 0x5a));
+        write_request(&mut stream, NBD_CMD_WRITE_ZEROES, 3, 4096, 512);
+        assert_eq!(read_reply(&mut stream, 3), 0);
+        write_request(&mut stream, NBD_CMD_READ, 4, 4096, 512);
+        assert_eq!(read_reply(&mut stream, 4), 0);
+        stream.read_exact(&mut data[..512]).unwrap();
+        assert!(data[..512].iter().all(|&b| b == 0));
+        write_request(&mut stream, NBD_CMD_READ, 5, 1 << 20, 512);
+        assert_eq!(read_reply(&mut stream, 5), NBD_EINVAL);
+        write_request(&mut stream, NBD_CMD_FLUSH, 6, 0, 0);
+        assert_eq!(read_reply(&mut stream, 6), 0);
+        write_request(&mut stream, NBD_CMD_DISC, 7, 0, 0);
+
+        // Read-only export negotiated by NBD_OPT_EXPORT_NAME.
+        let (mut stream, server) = UnixStream::pair().unwrap();
+        spawn_client(server, exports);
+        stream.read_exact(&mut handshake).unwrap();
+        stream
+            .write_all(&NBD_FLAG_C_FIXED_NEWSTYLE.to_be_bytes())
+            .unwrap();
+        write_option(&mut stream, NBD_OPT_EXPORT_NAME, b"ro");
+        let mut reply = [0_u8; 134];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(BigEndian::read_u64(&reply[0..8]), 1 << 20);
+        assert_ne!(BigEndian::read_u16(&reply[8..10]) & NBD_FLAG_READ_ONLY, 0);
+        write_request(&mut stream, NBD_CMD_WRITE, 1, 0, 512);
+        stream.write_all(&[0x5a_u8; 512]).unwrap();
+        assert_eq!(read_reply(&mut stream, 1), NBD_EPERM);
+        write_request(&mut stream, NBD_CMD_DISC, 2, 0, 0);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&ro_path).unwrap();
+    }
+}
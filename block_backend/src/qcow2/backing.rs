@@ -0,0 +1,180 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::{
+    fs::{File, OpenOptions},
+    os::unix::fs::FileExt,
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{header::QCOW_MAGIC, Qcow2Driver, SyncAioInfo};
+use crate::{raw::RawDriver, BlockDriverOps, BlockProperty};
+use machine_manager::config::DiskFormat;
+use util::{
+    aio::{get_iov_size, iovec_write_zero, iovecs_split, Aio, AioEngine, Iovec},
+    num_ops::round_up,
+};
+
+/// End of the header extension area.
+pub const QCOW2_EXT_MAGIC_END: u32 = 0;
+/// Header extension which records the format of backing file.
+pub const QCOW2_EXT_MAGIC_BACKING_FORMAT: u32 = 0xe279_2aca;
+const QCOW2_EXT_HEADER_LEN: usize = 8;
+/// Max depth of the backing chain, which also stops the looped chain.
+pub const MAX_BACKING_CHAIN_DEPTH: u32 = 16;
+const QCOW2_EXT_ALIGN: u64 = 8;
+
+/// Backing image of qcow2, the unallocated clusters of qcow2 are read from it.
+pub struct Qcow2Backing {
+    /// Path of the backing image.
+    pub file: String,
+    /// Format of the backing image.
+    pub format: DiskFormat,
+    /// Virtual size of the backing image.
+    pub size: u64,
+    driver: Box<dyn BlockDriverOps<()>>,
+}
+
+impl Qcow2Backing {
+    /// Open the backing image read-only. The format is probed if it is not recorded in
+    /// the header extension of qcow2. The backing file of qcow2 is opened recursively,
+    /// `depth` is the depth of this image in the backing chain.
+    pub fn open(file: &str, format: Option<DiskFormat>, depth: u32) -> Result<Self> {
+        let image = OpenOptions::new()
+            .read(true)
+            .open(file)
+            .with_context(|| format!("Failed to open backing file {}", file))?;
+        let format = match format {
+            Some(fmt) => fmt,
+            None => probe_format(&image)?,
+        };
+        let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off)?;
+        let prop = BlockProperty {
+            id: file.to_string(),
+            format,
+            ..Default::default()
+        };
+        let mut driver: Box<dyn BlockDriverOps<()>> = match format {
+            DiskFormat::Raw => Box::new(RawDriver::new(image, aio, prop)),
+            DiskFormat::Qcow2 => {
+                let mut qcow2 = Qcow2Driver::new(image, aio, prop.clone())?;
+                qcow2.chain_depth = depth;
+                qcow2
+                    .load_metadata(prop)
+                    .with_context(|| format!("Failed to load metadata of {}", file))?;
+                Box::new(qcow2)
+            }
+            DiskFormat::Luks => bail!("Backing file of format luks is not supported"),
+        };
+        let size = driver.disk_size()?;
+
+        Ok(Self {
+            file: file.to_string(),
+            format,
+            size,
+            driver,
+        })
+    }
+
+    /// Read data at the guest offset synchronously. The range beyond the end of backing
+    /// image reads as zero.
+    pub fn read_vectored(&mut self, iovec: Vec<Iovec>, offset: u64) -> Result<()> {
+        let nbytes = get_iov_size(&iovec);
+        let valid = std::cmp::min(self.size.saturating_sub(offset), nbytes);
+        let (begin, end) = iovecs_split(iovec, valid);
+        if valid != 0 {
+            self.driver.read_vectored(begin, offset as usize, ())?;
+        }
+        iovec_write_zero(&end);
+        Ok(())
+    }
+
+    pub fn read_buffer(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let iov = Iovec::new(buf.as_mut_ptr() as u64, buf.len() as u64);
+        self.read_vectored(vec![iov], offset)
+    }
+}
+
+/// Probe the image format by the magic, images which are not qcow2 are treated as raw.
+fn probe_format(file: &File) -> Result<DiskFormat> {
+    let mut buf = [0_u8; 4];
+    if file.metadata()?.len() < buf.len() as u64 {
+        return Ok(DiskFormat::Raw);
+    }
+    file.read_exact_at(&mut buf, 0)?;
+    if BigEndian::read_u32(&buf) == QCOW_MAGIC {
+        Ok(DiskFormat::Qcow2)
+    } else {
+        Ok(DiskFormat::Raw)
+    }
+}
+
+/// Get the backing format from the header extension area.
+pub fn parse_backing_format(buf: &[u8]) -> Result<Option<DiskFormat>> {
+    let mut offset = 0;
+    while offset + QCOW2_EXT_HEADER_LEN <= buf.len() {
+        let magic = BigEndian::read_u32(&buf[offset..offset + 4]);
+        let len = BigEndian::read_u32(&buf[offset + 4..offset + 8]) as usize;
+        offset += QCOW2_EXT_HEADER_LEN;
+        if magic == QCOW2_EXT_MAGIC_END {
+            break;
+        }
+        if offset + len > buf.len() {
+            bail!("Header extension 0x{:x} is out of range", magic);
+        }
+        if magic == QCOW2_EXT_MAGIC_BACKING_FORMAT {
+            let fmt = std::str::from_utf8(&buf[offset..offset + len])
+                .with_context(|| "Invalid backing format")?;
+            return Ok(Some(DiskFormat::from_str(fmt)?));
+        }
+        offset += round_up(len as u64, QCOW2_EXT_ALIGN).unwrap() as usize;
+    }
+    Ok(None)
+}
+
+/// Build the header extension area which records the backing format.
+pub fn backing_format_extension(format: DiskFormat) -> Vec<u8> {
+    let fmt = format.to_string();
+    let len = round_up(fmt.len() as u64, QCOW2_EXT_ALIGN).unwrap() as usize;
+    let mut buf = vec![0_u8; QCOW2_EXT_HEADER_LEN * 2 + len];
+    BigEndian::write_u32(&mut buf[0..4], QCOW2_EXT_MAGIC_BACKING_FORMAT);
+    BigEndian::write_u32(&mut buf[4..8], fmt.len() as u32);
+    buf[QCOW2_EXT_HEADER_LEN..QCOW2_EXT_HEADER_LEN + fmt.len()].copy_from_slice(fmt.as_bytes());
+    // The end of extension area is all zero.
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backing_format_extension() {
+        let buf = backing_format_extension(DiskFormat::Qcow2);
+        assert_eq!(buf.len(), 24);
+        assert_eq!(parse_backing_format(&buf).unwrap(), Some(DiskFormat::Qcow2));
+
+        let buf = backing_format_extension(DiskFormat::Raw);
+        assert_eq!(parse_backing_format(&buf).unwrap(), Some(DiskFormat::Raw));
+
+        // No extension.
+        assert_eq!(parse_backing_format(&[0_u8; 16]).unwrap(), None);
+        // Extension out of range.
+        let mut buf = backing_format_extension(DiskFormat::Raw);
+        BigEndian::write_u32(&mut buf[4..8], 64);
+        assert!(parse_backing_format(&buf).is_err());
+    }
+}
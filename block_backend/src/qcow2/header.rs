@@ -23,6 +23,7 @@ const MIN_CLUSTER_BIT: u32 = 9;
 const MAX_CLUSTER_BIT: u32 = 21;
 const MAX_REFTABLE_SIZE: u64 = 8 * (1 << 20);
 const MAX_L1TABLE_SIZE: u64 = 32 * (1 << 20);
+pub const MAX_BACKING_FILE_NAME_LEN: u32 = 1023;
 
 #[repr(C)]
 #[derive(Clone, Debug, Default)]
@@ -145,11 +146,24 @@ impl QcowHeader {
                 self.cluster_size()
             );
         }
-        // NOTE: not support backing file now.
-        if self.backing_file_offset != 0 {
+        if self.backing_file_size > MAX_BACKING_FILE_NAME_LEN {
             bail!(
-                "Don't support backing file offset, {}",
-                self.backing_file_offset
+                "Backing file name length {} over limit {}",
+                self.backing_file_size,
+                MAX_BACKING_FILE_NAME_LEN
+            );
+        }
+        // NOTE: the backing file name must be stored in the first cluster.
+        if self.backing_file_offset != 0
+            && self
+                .backing_file_offset
+                .checked_add(self.backing_file_size as u64)
+                .is_none_or(|end| end > self.cluster_size())
+        {
+            bail!(
+                "Invalid backing file offset {}, size {}",
+                self.backing_file_offset,
+                self.backing_file_size
             );
         }
         // NOTE: only support refcount_order == 4.
@@ -353,7 +367,14 @@ mod test {
         // Invalid backing file offset.
         let mut buf = valid_header_v3();
         BigEndian::write_u32(&mut buf[8..16], 0x2000);
-        list.push((buf, format!("Don't support backing file offset")));
+        list.push((buf, format!("Invalid backing file offset")));
+        // Large backing file name length.
+        let mut buf = valid_header_v3();
+        BigEndian::write_u32(&mut buf[16..20], 0x1000);
+        list.push((
+            buf,
+            format!("Backing file name length {} over limit", 0x1000),
+        ));
         // Invalid refcount order.
         let mut buf = valid_header_v3();
         BigEndian::write_u32(&mut buf[96..100], 5);
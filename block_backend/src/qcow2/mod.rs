@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod backing;
 pub mod cache;
 pub mod check;
 pub mod header;
@@ -24,6 +25,7 @@ use std::{
     io::{Seek, SeekFrom, Write},
     mem::size_of,
     os::unix::io::{AsRawFd, RawFd},
+    path::Path,
     rc::Rc,
     sync::{atomic::AtomicBool, Arc, Mutex, Weak},
     time::Duration,
@@ -35,7 +37,13 @@ use log::{debug, error, info};
 use once_cell::sync::Lazy;
 
 use self::{
-    cache::ENTRY_SIZE_U64, check::Qcow2Check, header::QCOW_MAGIC, refcount::Qcow2DiscardType,
+    backing::{
+        backing_format_extension, parse_backing_format, Qcow2Backing, MAX_BACKING_CHAIN_DEPTH,
+    },
+    cache::ENTRY_SIZE_U64,
+    check::Qcow2Check,
+    header::{MAX_BACKING_FILE_NAME_LEN, QCOW_MAGIC},
+    refcount::Qcow2DiscardType,
 };
 use crate::{
     file::{CombineRequest, FileDriver},
@@ -52,8 +60,8 @@ use machine_manager::event_loop::EventLoop;
 use machine_manager::qmp::qmp_schema::SnapshotInfo;
 use util::{
     aio::{
-        get_iov_size, iovec_write_zero, iovecs_split, raw_write_zeroes, Aio, AioCb, AioEngine,
-        Iovec, OpCode,
+        get_iov_size, iov_from_buf_direct, iovec_write_zero, iovecs_split, raw_write_zeroes, Aio,
+        AioCb, AioEngine, Iovec, OpCode,
     },
     num_ops::{div_round_up, ranges_overlap, round_down, round_up},
     time::{get_format_time, gettime},
@@ -107,6 +115,8 @@ pub enum HostRange {
     DataNotInit(u64),
     /// Start address and size.
     DataAddress(u64, u64),
+    /// Data size which is read from backing file.
+    DataBacking(u64),
 }
 
 pub struct SyncAioInfo {
@@ -161,7 +171,7 @@ impl SyncAioInfo {
         }
     }
 
-    fn read_buffer(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+    pub(crate) fn read_buffer(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
         let ptr = buf.as_mut_ptr() as u64;
         let cnt = buf.len() as u64;
         let aiocb = self.package_sync_aiocb(
@@ -224,6 +234,10 @@ pub struct Qcow2Driver<T: Clone + 'static> {
     pub refcount: RefCount,
     pub snapshot: InternalSnapshot,
     pub status: Arc<Mutex<BlockStatus>>,
+    /// Backing image which the unallocated clusters are read from.
+    pub backing: Option<Qcow2Backing>,
+    /// Depth of this image in the backing chain, the top image is 0.
+    chain_depth: u32,
 }
 
 impl<T: Clone + 'static> Drop for Qcow2Driver<T> {
@@ -268,6 +282,8 @@ impl<T: Clone + 'static> Qcow2Driver<T> {
             refcount: RefCount::new(sync_aio.clone()),
             snapshot: InternalSnapshot::new(sync_aio),
             status: Arc::new(Mutex::new(BlockStatus::Init)),
+            backing: None,
+            chain_depth: 0,
         })
     }
 
@@ -275,6 +291,8 @@ impl<T: Clone + 'static> Qcow2Driver<T> {
         self.load_header()
             .with_context(|| "Failed to load header")?;
         self.header.check().with_context(|| "Invalid header")?;
+        self.load_backing()
+            .with_context(|| "Failed to load backing file")?;
         self.table
             .init_table_info(&self.header, &conf)
             .with_context(|| "Failed to create qcow2 table")?;
@@ -315,9 +333,43 @@ impl<T: Clone + 'static> Qcow2Driver<T> {
         let mut buf = vec![0; QcowHeader::len()];
         self.sync_aio.borrow_mut().read_buffer(0, &mut buf)?;
         self.header = QcowHeader::from_vec(&buf)?;
-        if self.header.backing_file_size != 0 {
-            bail!("Backing file is not supported now");
+        Ok(())
+    }
+
+    /// Open the backing file recorded in the header, the relative path is based on the
+    /// directory of this image.
+    fn load_backing(&mut self) -> Result<()> {
+        if self.header.backing_file_offset == 0 || self.header.backing_file_size == 0 {
+            return Ok(());
         }
+        let name_start = self.header.backing_file_offset as usize;
+        let mut buf = vec![0; name_start + self.header.backing_file_size as usize];
+        self.sync_aio.borrow_mut().read_buffer(0, &mut buf)?;
+        let name = String::from_utf8(buf[name_start..].to_vec())
+            .with_context(|| "Invalid backing file name")?;
+        let ext_start = self.header.header_length as usize;
+        let format = if ext_start < name_start {
+            parse_backing_format(&buf[ext_start..name_start])?
+        } else {
+            None
+        };
+
+        let mut path = name.clone();
+        if !Path::new(&name).is_absolute() {
+            let image =
+                std::fs::read_link(format!("/proc/self/fd/{}", self.driver.file.as_raw_fd()))
+                    .with_context(|| "Failed to get the path of image")?;
+            if let Some(dir) = image.parent() {
+                path = dir.join(&name).to_string_lossy().to_string();
+            }
+        }
+        if self.chain_depth >= MAX_BACKING_CHAIN_DEPTH {
+            bail!(
+                "Backing chain is deeper than {}, maybe it is looped",
+                MAX_BACKING_CHAIN_DEPTH
+            );
+        }
+        self.backing = Some(Qcow2Backing::open(&path, format, self.chain_depth + 1)?);
         Ok(())
     }
 
@@ -403,10 +455,15 @@ impl<T: Clone + 'static> Qcow2Driver<T> {
         let size = std::cmp::min(req_len, l2_max_len);
         let l2_address = self.table.get_l1_table_entry(guest_offset) & L1_TABLE_OFFSET_MASK;
         if l2_address == 0 {
+            if self.backing.is_some() {
+                return Ok(HostRange::DataBacking(size));
+            }
             return Ok(HostRange::DataNotInit(size));
         }
         let (cluster_type, host_start, bytes) = self.get_continuous_address(guest_offset, size)?;
-        if cluster_type.is_read_zero() {
+        if cluster_type == Qcow2ClusterType::Unallocated && self.backing.is_some() {
+            Ok(HostRange::DataBacking(bytes))
+        } else if cluster_type.is_read_zero() {
             Ok(HostRange::DataNotInit(bytes))
         } else {
             Ok(HostRange::DataAddress(host_start, bytes))
@@ -423,6 +480,16 @@ impl<T: Clone + 'static> Qcow2Driver<T> {
         let mut cluster_addr = l2_entry & L2_TABLE_OFFSET_MASK;
         if cluster_addr == 0 {
             let new_addr = self.alloc_cluster(1, true)?;
+            // Copy on write for the cluster in backing file, unless it reads as zero.
+            if nbytes < self.header.cluster_size() && old_l2_entry & QCOW2_OFLAG_ZERO == 0 {
+                if let Some(backing) = self.backing.as_mut() {
+                    let cluster_start = round_down(guest_offset, self.header.cluster_size())
+                        .with_context(|| format!("invalid offset {}", guest_offset))?;
+                    let mut data = vec![0_u8; self.header.cluster_size() as usize];
+                    backing.read_buffer(cluster_start, &mut data)?;
+                    self.sync_aio.borrow_mut().write_buffer(new_addr, &data)?;
+                }
+            }
             l2_entry = new_addr | QCOW2_OFFSET_COPIED;
             cluster_addr = new_addr & L2_TABLE_OFFSET_MASK;
         } else if l2_entry & QCOW2_OFFSET_COPIED == 0 {
@@ -457,6 +524,35 @@ impl<T: Clone + 'static> Qcow2Driver<T> {
         Ok(cluster_addr + self.offset_into_cluster(guest_offset))
     }
 
+    /// Read the clusters covering the range from backing image, and populate them into this
+    /// image, so that the later reads don't go through the backing chain.
+    fn copy_on_read(&mut self, iovec: Vec<Iovec>, offset: u64) -> Result<()> {
+        let nbytes = get_iov_size(&iovec);
+        let cluster_size = self.header.cluster_size();
+        let start = round_down(offset, cluster_size)
+            .with_context(|| format!("invalid offset {}", offset))?;
+        let end = round_up(offset + nbytes, cluster_size)
+            .with_context(|| format!("invalid offset {}", offset + nbytes))?;
+        let mut data = vec![0_u8; (end - start) as usize];
+        // It's safe to unwrap, as only the image with backing file calls it.
+        self.backing
+            .as_mut()
+            .unwrap()
+            .read_buffer(start, &mut data)?;
+
+        for (idx, cluster) in data.chunks(cluster_size as usize).enumerate() {
+            let cluster_start = start + idx as u64 * cluster_size;
+            let host_offset = self.host_offset_for_write(cluster_start, cluster_size)?;
+            self.sync_aio
+                .borrow_mut()
+                .write_buffer(host_offset, cluster)?;
+        }
+
+        let begin = (offset - start) as usize;
+        iov_from_buf_direct(&iovec, &data[begin..begin + nbytes as usize])?;
+        Ok(())
+    }
+
     /// Extend the l1 table.
     pub fn grow_l1_table(&mut self, new_l1_size: u64) -> Result<()> {
         let old_l1_size = self.header.l1_size as u64;
@@ -1352,6 +1448,9 @@ pub trait InternalSnapshotOps: Send + Sync {
     fn apply_snapshot(&mut self, name: String) -> Result<()>;
     fn list_snapshots(&self) -> String;
     fn get_status(&self) -> Arc<Mutex<BlockStatus>>;
+    fn flush_metadata(&mut self) -> Result<()>;
+    /// Read the guest data synchronously, which is consistent with the cached metadata.
+    fn read_data(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
 }
 
 impl<T: Clone + 'static> InternalSnapshotOps for Qcow2Driver<T> {
@@ -1391,6 +1490,39 @@ impl<T: Clone + 'static> InternalSnapshotOps for Qcow2Driver<T> {
     fn get_status(&self) -> Arc<Mutex<BlockStatus>> {
         self.status.clone()
     }
+
+    fn flush_metadata(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    fn read_data(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let nbytes = buf.len() as u64;
+        self.check_request(offset as usize, nbytes)
+            .with_context(|| " Invalid read request")?;
+
+        let mut copied = 0;
+        while copied < nbytes {
+            let pos = offset + copied;
+            match self.host_offset_for_read(pos, nbytes - copied)? {
+                HostRange::DataAddress(host_offset, cnt) => {
+                    let data = &mut buf[copied as usize..(copied + cnt) as usize];
+                    self.sync_aio.borrow_mut().read_buffer(host_offset, data)?;
+                    copied += cnt;
+                }
+                HostRange::DataNotInit(cnt) => {
+                    buf[copied as usize..(copied + cnt) as usize].fill(0);
+                    copied += cnt;
+                }
+                HostRange::DataBacking(cnt) => {
+                    let data = &mut buf[copied as usize..(copied + cnt) as usize];
+                    // It's safe to unwrap, as only the image with backing file returns it.
+                    self.backing.as_mut().unwrap().read_buffer(pos, data)?;
+                    copied += cnt;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 // SAFETY: Send and Sync is not auto-implemented for raw pointer type in Aio.
@@ -1522,11 +1654,32 @@ impl<T: Clone + Send + Sync> BlockDriverOps<T> for Qcow2Driver<T> {
             rc_block.append(&mut count.clone());
         }
 
+        // The backing format extension and backing file name follow the header.
+        let header_length = std::mem::size_of::<QcowHeader>() as u32;
+        let mut backing_buf = Vec::new();
+        let mut backing_file_offset = 0;
+        if let Some(backing_file) = qcow2_options.backing_file.as_ref() {
+            if backing_file.len() > MAX_BACKING_FILE_NAME_LEN as usize {
+                bail!("Backing file name {} is too long", backing_file);
+            }
+            if let Some(format) = qcow2_options.backing_format {
+                backing_buf.append(&mut backing_format_extension(format));
+            }
+            backing_file_offset = header_length as u64 + backing_buf.len() as u64;
+            backing_buf.extend_from_slice(backing_file.as_bytes());
+            if backing_file_offset + backing_file.len() as u64 > cluster_size {
+                bail!("Backing file name {} over cluster size", backing_file);
+            }
+        }
+
         let header = QcowHeader {
             magic: QCOW_MAGIC,
             version: qcow2_options.version,
-            backing_file_offset: 0,
-            backing_file_size: 0,
+            backing_file_offset,
+            backing_file_size: qcow2_options
+                .backing_file
+                .as_ref()
+                .map_or(0, |file| file.len() as u32),
             cluster_bits: qcow2_options.cluster_size.trailing_zeros(),
             size: qcow2_options.img_size,
             crypt_method: 0,
@@ -1540,7 +1693,7 @@ impl<T: Clone + Send + Sync> BlockDriverOps<T> for Qcow2Driver<T> {
             compatible_features: 0,
             autoclear_features: 0,
             refcount_order: qcow2_options.refcount_bits.trailing_zeros(),
-            header_length: std::mem::size_of::<QcowHeader>() as u32,
+            header_length,
         };
 
         let conf = options.conf.clone();
@@ -1562,6 +1715,7 @@ impl<T: Clone + Send + Sync> BlockDriverOps<T> for Qcow2Driver<T> {
         }
         self.driver.file.rewind()?;
         self.driver.file.write_all(&self.header.to_vec())?;
+        self.driver.file.write_all(&backing_buf)?;
 
         // Refcount table.
         self.driver.file.seek(SeekFrom::Start(cluster_size))?;
@@ -1579,12 +1733,15 @@ impl<T: Clone + Send + Sync> BlockDriverOps<T> for Qcow2Driver<T> {
         self.grow_l1_table(l1_size)?;
         self.flush()?;
 
-        let image_info = format!(
+        let mut image_info = format!(
             "fmt=qcow2 cluster_size={} extended_l2=off compression_type=zlib size={} lazy_refcounts=off refcount_bits={}",
             qcow2_options.cluster_size,
             qcow2_options.img_size,
             qcow2_options.refcount_bits
         );
+        if let Some(backing_file) = qcow2_options.backing_file.as_ref() {
+            image_info = format!("{} backing_file={}", image_info, backing_file);
+        }
         Ok(image_info)
     }
 
@@ -1636,6 +1793,17 @@ impl<T: Clone + Send + Sync> BlockDriverOps<T> for Qcow2Driver<T> {
                     iovec_write_zero(&begin);
                     copied += cnt;
                 }
+                HostRange::DataBacking(cnt) => {
+                    let (begin, end) = iovecs_split(left, cnt);
+                    left = end;
+                    if self.sync_aio.borrow().prop.copy_on_read {
+                        self.copy_on_read(begin, pos)?;
+                    } else {
+                        // It's safe to unwrap, as only the image with backing file returns it.
+                        self.backing.as_mut().unwrap().read_vectored(begin, pos)?;
+                    }
+                    copied += cnt;
+                }
             }
         }
 
@@ -1984,6 +2152,9 @@ mod test {
             write_zeroes: WriteZeroesState::Off,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         };
         image.file = file.try_clone().unwrap();
         let mut qcow2_driver = Qcow2Driver::new(file, aio, conf.clone()).unwrap();
@@ -2304,6 +2475,9 @@ mod test {
                     write_zeroes: WriteZeroesState::On,
                     l2_cache_size: None,
                     refcount_cache_size: None,
+                    key_secret: None,
+                    copy_on_read: false,
+                    io_timeout: None,
                 };
                 let mut qcow2_driver = image.create_qcow2_driver(conf.clone());
 
@@ -2349,6 +2523,9 @@ mod test {
             write_zeroes: WriteZeroesState::Off,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         };
 
         // (offset_begin, offset_end)
@@ -2423,6 +2600,9 @@ mod test {
             write_zeroes: WriteZeroesState::Off,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         };
 
         let mut qcow2_driver = image.create_qcow2_driver(conf);
@@ -2510,6 +2690,9 @@ mod test {
                     write_zeroes: WriteZeroesState::On,
                     l2_cache_size: None,
                     refcount_cache_size: None,
+                    key_secret: None,
+                    copy_on_read: false,
+                    io_timeout: None,
                 };
 
                 let mut qcow2_driver = image.create_qcow2_driver(conf);
@@ -2760,4 +2943,185 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_backing_file_read_write() {
+        let backing_path = "/tmp/block_backend_test_backing.raw";
+        let overlay_path = "/tmp/block_backend_test_overlay.qcow2";
+        let cluster_size = CLUSTER_SIZE as usize;
+        let img_size = 4 * CLUSTER_SIZE;
+
+        // Backing image is filled with 1 in cluster 0 and 2 in cluster 1.
+        let mut backing = vec![1_u8; cluster_size];
+        backing.append(&mut vec![2_u8; cluster_size]);
+        std::fs::write(backing_path, &backing).unwrap();
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CREAT | libc::O_TRUNC)
+            .open(overlay_path)
+            .unwrap();
+        let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off).unwrap();
+        let conf = BlockProperty {
+            format: DiskFormat::Qcow2,
+            ..Default::default()
+        };
+        let mut qcow2_driver = Qcow2Driver::new(file, aio, conf.clone()).unwrap();
+        let options = CreateOptions {
+            path: overlay_path.to_string(),
+            img_size,
+            backing_file: Some(backing_path.to_string()),
+            backing_format: Some(DiskFormat::Raw),
+            conf: conf.clone(),
+            ..Default::default()
+        };
+        qcow2_driver.create_image(&options).unwrap();
+        drop(qcow2_driver);
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(overlay_path)
+            .unwrap();
+        let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off).unwrap();
+        let mut qcow2_driver = Qcow2Driver::new(file, aio, conf.clone()).unwrap();
+        qcow2_driver.load_metadata(conf).unwrap();
+        let backing_file = qcow2_driver.backing.as_ref().unwrap();
+        assert_eq!(backing_file.file, backing_path);
+        assert_eq!(backing_file.format, DiskFormat::Raw);
+        assert_eq!(backing_file.size, 2 * CLUSTER_SIZE);
+
+        // Unallocated clusters are read from backing file, and zero beyond it.
+        let mut buf = vec![0_u8; img_size as usize];
+        qcow2_read(&mut qcow2_driver, &mut buf, 0).unwrap();
+        assert_eq!(buf[..2 * cluster_size], backing);
+        assert!(vec_is_zero(&buf[2 * cluster_size..]));
+
+        // Partial write copies the rest of cluster from backing file.
+        let wbuf = vec![3_u8; 512];
+        qcow2_write(&mut qcow2_driver, &wbuf, cluster_size + 512).unwrap();
+        let mut buf = vec![0_u8; cluster_size];
+        qcow2_read(&mut qcow2_driver, &mut buf, cluster_size).unwrap();
+        assert!(buf[..512].iter().all(|v| *v == 2));
+        assert!(buf[512..1024].iter().all(|v| *v == 3));
+        assert!(buf[1024..].iter().all(|v| *v == 2));
+
+        // Zero clusters are not read from backing file.
+        qcow2_driver
+            .write_zeroes(0, CLUSTER_SIZE, (), false)
+            .unwrap();
+        qcow2_read(&mut qcow2_driver, &mut buf, 0).unwrap();
+        assert!(vec_is_zero(&buf));
+
+        // Backing file is not changed.
+        drop(qcow2_driver);
+        assert_eq!(std::fs::read(backing_path).unwrap(), backing);
+        remove_file(backing_path).unwrap();
+        remove_file(overlay_path).unwrap();
+    }
+
+    fn create_overlay_image(path: &str, img_size: u64, backing_file: &str) {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CREAT | libc::O_TRUNC)
+            .open(path)
+            .unwrap();
+        let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off).unwrap();
+        let conf = BlockProperty {
+            format: DiskFormat::Qcow2,
+            ..Default::default()
+        };
+        let mut qcow2_driver = Qcow2Driver::new(file, aio, conf.clone()).unwrap();
+        let options = CreateOptions {
+            path: path.to_string(),
+            img_size,
+            backing_file: Some(backing_file.to_string()),
+            conf,
+            ..Default::default()
+        };
+        qcow2_driver.create_image(&options).unwrap();
+    }
+
+    fn open_qcow2_image(path: &str, copy_on_read: bool) -> Result<Qcow2Driver<()>> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let aio = Aio::new(Arc::new(SyncAioInfo::complete_func), AioEngine::Off).unwrap();
+        let conf = BlockProperty {
+            format: DiskFormat::Qcow2,
+            copy_on_read,
+            ..Default::default()
+        };
+        let mut qcow2_driver = Qcow2Driver::new(file, aio, conf.clone()).unwrap();
+        qcow2_driver.load_metadata(conf)?;
+        Ok(qcow2_driver)
+    }
+
+    #[test]
+    fn test_backing_chain_copy_on_read() {
+        let base_path = "/tmp/block_backend_test_chain_base.raw";
+        let mid_path = "/tmp/block_backend_test_chain_mid.qcow2";
+        let top_path = "/tmp/block_backend_test_chain_top.qcow2";
+        let cluster_size = CLUSTER_SIZE as usize;
+        let img_size = 4 * CLUSTER_SIZE;
+
+        // Chain: base(raw) <- mid(qcow2) <- top(qcow2), the formats are probed.
+        let mut base = vec![1_u8; cluster_size];
+        base.append(&mut vec![2_u8; cluster_size]);
+        std::fs::write(base_path, &base).unwrap();
+        create_overlay_image(mid_path, img_size, base_path);
+        create_overlay_image(top_path, img_size, mid_path);
+
+        // Cluster 1 is overwritten in mid.
+        let mut mid = open_qcow2_image(mid_path, false).unwrap();
+        qcow2_write(&mut mid, &vec![4_u8; cluster_size], cluster_size).unwrap();
+        drop(mid);
+
+        let mut top = open_qcow2_image(top_path, true).unwrap();
+        let mid_backing = top.backing.as_ref().unwrap();
+        assert_eq!(mid_backing.format, DiskFormat::Qcow2);
+        for offset in (0..img_size).step_by(cluster_size) {
+            assert!(matches!(
+                top.host_offset_for_read(offset, CLUSTER_SIZE).unwrap(),
+                HostRange::DataBacking(CLUSTER_SIZE)
+            ));
+        }
+
+        // Unallocated clusters are resolved by the nearest ancestor.
+        let mut buf = vec![0_u8; 512];
+        qcow2_read(&mut top, &mut buf, 512).unwrap();
+        assert!(buf.iter().all(|v| *v == 1));
+        let mut buf = vec![0_u8; 3 * cluster_size];
+        qcow2_read(&mut top, &mut buf, cluster_size).unwrap();
+        assert!(buf[..cluster_size].iter().all(|v| *v == 4));
+        assert!(vec_is_zero(&buf[cluster_size..]));
+
+        // The whole clusters are populated into top by copy-on-read.
+        for offset in (0..img_size).step_by(cluster_size) {
+            assert!(matches!(
+                top.host_offset_for_read(offset, CLUSTER_SIZE).unwrap(),
+                HostRange::DataAddress(_, CLUSTER_SIZE)
+            ));
+        }
+        drop(top);
+        let mut top = open_qcow2_image(top_path, false).unwrap();
+        top.backing = None;
+        let mut buf = vec![0_u8; 2 * cluster_size];
+        qcow2_read(&mut top, &mut buf, 0).unwrap();
+        assert!(buf[..cluster_size].iter().all(|v| *v == 1));
+        assert!(buf[cluster_size..].iter().all(|v| *v == 4));
+        drop(top);
+
+        // Looped chain is refused.
+        create_overlay_image(top_path, img_size, top_path);
+        assert!(open_qcow2_image(top_path, false).is_err());
+
+        remove_file(base_path).unwrap();
+        remove_file(mid_path).unwrap();
+        remove_file(top_path).unwrap();
+    }
 }
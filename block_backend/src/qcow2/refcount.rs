@@ -795,6 +795,9 @@ mod test {
             write_zeroes: WriteZeroesState::Off,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         };
         let cloned_file = file.try_clone().unwrap();
         let mut qcow2_driver = Qcow2Driver::new(file, aio, conf.clone()).unwrap();
@@ -0,0 +1,406 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Zoned block devices of host, such as ZNS NVMe namespaces and SMR disks.
+//!
+//! The zones are reported and managed by the zone ioctls of linux. The host doesn't provide
+//! zone append to userspace, so the write pointers of the zones are tracked here, and the
+//! zone append is emulated by writing at the write pointer.
+
+use std::fs::{self, File};
+use std::mem::size_of;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use vmm_sys_util::ioctl::{ioctl_with_mut_ptr, ioctl_with_mut_ref, ioctl_with_ref};
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+use crate::SECTOR_BITS;
+use util::byte_code::ByteCode;
+
+const BLK_IOCTL_TYPE: u32 = 0x12;
+
+ioctl_iowr_nr!(BLKREPORTZONE, BLK_IOCTL_TYPE, 130, BlkZoneReport);
+ioctl_iow_nr!(BLKRESETZONE, BLK_IOCTL_TYPE, 131, BlkZoneRange);
+ioctl_ior_nr!(BLKGETZONESZ, BLK_IOCTL_TYPE, 132, u32);
+ioctl_ior_nr!(BLKGETNRZONES, BLK_IOCTL_TYPE, 133, u32);
+ioctl_iow_nr!(BLKOPENZONE, BLK_IOCTL_TYPE, 134, BlkZoneRange);
+ioctl_iow_nr!(BLKCLOSEZONE, BLK_IOCTL_TYPE, 135, BlkZoneRange);
+ioctl_iow_nr!(BLKFINISHZONE, BLK_IOCTL_TYPE, 136, BlkZoneRange);
+
+/// The zone capacity is valid in the zone report.
+const BLK_ZONE_REP_CAPACITY: u32 = 1;
+
+/// Max number of zones reported by one ioctl.
+const MAX_REPORT_ZONES: usize = 1024;
+
+/// Header of the zone report, followed by the zone descriptors.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct BlkZoneReport {
+    sector: u64,
+    nr_zones: u32,
+    flags: u32,
+}
+
+impl ByteCode for BlkZoneReport {}
+
+/// Zone descriptor reported by host.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct BlkZone {
+    start: u64,
+    len: u64,
+    wp: u64,
+    zone_type: u8,
+    cond: u8,
+    non_seq: u8,
+    reset: u8,
+    resv: [u8; 4],
+    capacity: u64,
+    reserved: [u8; 24],
+}
+
+impl ByteCode for BlkZone {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct BlkZoneRange {
+    sector: u64,
+    nr_sectors: u64,
+}
+
+/// Type of zone.
+pub const BLK_ZONE_TYPE_CONVENTIONAL: u8 = 1;
+pub const BLK_ZONE_TYPE_SEQWRITE_REQ: u8 = 2;
+pub const BLK_ZONE_TYPE_SEQWRITE_PREF: u8 = 3;
+
+/// Condition of zone.
+pub const BLK_ZONE_COND_NOT_WP: u8 = 0;
+pub const BLK_ZONE_COND_EMPTY: u8 = 1;
+pub const BLK_ZONE_COND_IMP_OPEN: u8 = 2;
+pub const BLK_ZONE_COND_EXP_OPEN: u8 = 3;
+pub const BLK_ZONE_COND_CLOSED: u8 = 4;
+pub const BLK_ZONE_COND_READONLY: u8 = 13;
+pub const BLK_ZONE_COND_FULL: u8 = 14;
+pub const BLK_ZONE_COND_OFFLINE: u8 = 15;
+
+/// The zone model of the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZoneModel {
+    /// Writes must be sequential in the sequential zones.
+    HostManaged,
+    /// Random writes are allowed, but sequential writes are preferred.
+    HostAware,
+}
+
+/// Zone management operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZoneOp {
+    Open,
+    Close,
+    Finish,
+    Reset,
+}
+
+/// Zone information, all the fields are in sectors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ZoneInfo {
+    pub start: u64,
+    pub len: u64,
+    pub capacity: u64,
+    pub wp: u64,
+    pub zone_type: u8,
+    pub cond: u8,
+}
+
+impl From<&BlkZone> for ZoneInfo {
+    fn from(zone: &BlkZone) -> Self {
+        ZoneInfo {
+            start: zone.start,
+            len: zone.len,
+            capacity: zone.capacity,
+            wp: zone.wp,
+            zone_type: zone.zone_type,
+            cond: zone.cond,
+        }
+    }
+}
+
+/// A zoned block device of host.
+pub struct ZonedDevice {
+    /// The opened block device.
+    file: File,
+    pub model: ZoneModel,
+    /// Number of sectors of each zone.
+    pub zone_sectors: u64,
+    pub nr_zones: u32,
+    /// Max number of open zones, 0 means no limit.
+    pub max_open_zones: u32,
+    /// Max number of active zones, 0 means no limit.
+    pub max_active_zones: u32,
+    /// Max number of sectors of one zone append.
+    pub max_append_sectors: u32,
+    /// Write pointers of all the zones, which are used to emulate zone append.
+    wps: Mutex<Vec<u64>>,
+}
+
+impl ZonedDevice {
+    /// Probe the zoned block device, return None if it's not a zoned block device.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The opened image file.
+    pub fn probe(file: &File) -> Result<Option<Self>> {
+        let meta = file.metadata()?;
+        if !meta.file_type().is_block_device() {
+            return Ok(None);
+        }
+        let (major, minor) = (libc::major(meta.rdev()), libc::minor(meta.rdev()));
+        let sysfs = format!("/sys/dev/block/{}:{}/queue", major, minor);
+        let model = match read_sysfs(&sysfs, "zoned").as_deref() {
+            Some("host-managed") => ZoneModel::HostManaged,
+            Some("host-aware") => ZoneModel::HostAware,
+            _ => return Ok(None),
+        };
+
+        let mut zone_sectors = 0_u32;
+        // SAFETY: file is valid and the zone_sectors is big enough.
+        let ret = unsafe { ioctl_with_mut_ref(file, BLKGETZONESZ(), &mut zone_sectors) };
+        if ret < 0 || zone_sectors == 0 {
+            bail!(
+                "Failed to get zone size of zoned device: {:?}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let mut nr_zones = 0_u32;
+        // SAFETY: file is valid and the nr_zones is big enough.
+        let ret = unsafe { ioctl_with_mut_ref(file, BLKGETNRZONES(), &mut nr_zones) };
+        if ret < 0 || nr_zones == 0 {
+            bail!(
+                "Failed to get number of zones of zoned device: {:?}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let parse = |name: &str| -> u64 {
+            read_sysfs(&sysfs, name)
+                .and_then(|val| val.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        let mut max_append_sectors = parse("zone_append_max_bytes") >> SECTOR_BITS;
+        if max_append_sectors == 0 {
+            max_append_sectors = (parse("max_sectors_kb") << 10) >> SECTOR_BITS;
+        }
+
+        let device = ZonedDevice {
+            file: file
+                .try_clone()
+                .with_context(|| "Failed to clone zoned device file")?,
+            model,
+            zone_sectors: zone_sectors as u64,
+            nr_zones,
+            max_open_zones: parse("max_open_zones") as u32,
+            max_active_zones: parse("max_active_zones") as u32,
+            max_append_sectors: max_append_sectors.min(zone_sectors as u64) as u32,
+            wps: Mutex::new(Vec::new()),
+        };
+        device.refresh_wps()?;
+        Ok(Some(device))
+    }
+
+    /// Report at most `nr_zones` zones from the zone containing `sector`.
+    pub fn report_zones(&self, sector: u64, nr_zones: usize) -> Result<Vec<ZoneInfo>> {
+        let mut zones = Vec::new();
+        let mut sector = sector;
+        while zones.len() < nr_zones {
+            let count = (nr_zones - zones.len()).min(MAX_REPORT_ZONES);
+            let hdr_size = size_of::<BlkZoneReport>();
+            let mut buf = vec![0_u8; hdr_size + count * size_of::<BlkZone>()];
+            let hdr = BlkZoneReport {
+                sector,
+                nr_zones: count as u32,
+                flags: 0,
+            };
+            buf[..hdr_size].copy_from_slice(hdr.as_bytes());
+            // SAFETY: file is valid and the buffer is big enough for the zones.
+            let ret = unsafe {
+                ioctl_with_mut_ptr(
+                    &self.file,
+                    BLKREPORTZONE(),
+                    buf.as_mut_ptr() as *mut BlkZoneReport,
+                )
+            };
+            if ret < 0 {
+                bail!(
+                    "Failed to report zones from sector {}: {:?}",
+                    sector,
+                    std::io::Error::last_os_error()
+                );
+            }
+            let hdr = *BlkZoneReport::from_bytes(&buf[..hdr_size]).unwrap();
+            if hdr.nr_zones == 0 {
+                break;
+            }
+            for index in 0..(hdr.nr_zones as usize).min(count) {
+                let offset = hdr_size + index * size_of::<BlkZone>();
+                let zone =
+                    BlkZone::from_bytes(&buf[offset..offset + size_of::<BlkZone>()]).unwrap();
+                let mut info = ZoneInfo::from(zone);
+                if hdr.flags & BLK_ZONE_REP_CAPACITY == 0 {
+                    info.capacity = info.len;
+                }
+                sector = info.start + info.len;
+                zones.push(info);
+            }
+        }
+        Ok(zones)
+    }
+
+    /// Open, close, finish or reset the zones in sectors [sector, sector + nr_sectors).
+    pub fn zone_mgmt(&self, op: ZoneOp, sector: u64, nr_sectors: u64) -> Result<()> {
+        let range = BlkZoneRange { sector, nr_sectors };
+        let req = match op {
+            ZoneOp::Open => BLKOPENZONE(),
+            ZoneOp::Close => BLKCLOSEZONE(),
+            ZoneOp::Finish => BLKFINISHZONE(),
+            ZoneOp::Reset => BLKRESETZONE(),
+        };
+        // SAFETY: file is valid and the range is valid.
+        let ret = unsafe { ioctl_with_ref(&self.file, req, &range) };
+        let result = if ret < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        // The write pointers are changed by reset and finish, even if it fails partly.
+        if matches!(op, ZoneOp::Reset | ZoneOp::Finish) {
+            self.refresh_wps()?;
+        }
+        result.with_context(|| {
+            format!(
+                "Failed to {:?} zones in sectors [{}, {})",
+                op,
+                sector,
+                sector + nr_sectors
+            )
+        })
+    }
+
+    /// Reload the write pointers of all the zones from the device.
+    pub fn refresh_wps(&self) -> Result<()> {
+        let zones = self.report_zones(0, self.nr_zones as usize)?;
+        *self.wps.lock().unwrap() = zones.iter().map(|zone| self.zone_wp(zone)).collect();
+        Ok(())
+    }
+
+    fn zone_wp(&self, zone: &ZoneInfo) -> u64 {
+        match zone.cond {
+            BLK_ZONE_COND_FULL => zone.start + zone.len,
+            _ => zone.wp,
+        }
+    }
+
+    /// Reserve `nr_sectors` sectors at the write pointer of the zone which starts at
+    /// `zone_start`, and return the start sector of them.
+    pub fn append_sector(&self, zone_start: u64, nr_sectors: u64) -> Result<u64> {
+        if !zone_start.is_multiple_of(self.zone_sectors) {
+            bail!("Zone append to unaligned sector {}", zone_start);
+        }
+        let index = (zone_start / self.zone_sectors) as usize;
+        let mut locked_wps = self.wps.lock().unwrap();
+        let wp = locked_wps
+            .get_mut(index)
+            .with_context(|| format!("Zone append to invalid sector {}", zone_start))?;
+        if *wp + nr_sectors > zone_start + self.zone_sectors {
+            bail!(
+                "Zone append of {} sectors exceeds zone {}",
+                nr_sectors,
+                zone_start
+            );
+        }
+        let sector = *wp;
+        *wp += nr_sectors;
+        Ok(sector)
+    }
+
+    /// Move the write pointers forward after writing sectors [sector, sector + nr_sectors).
+    pub fn advance_wp(&self, sector: u64, nr_sectors: u64) {
+        let end = sector + nr_sectors;
+        let mut locked_wps = self.wps.lock().unwrap();
+        let mut zone_start = sector - sector % self.zone_sectors;
+        while zone_start < end {
+            let index = (zone_start / self.zone_sectors) as usize;
+            let zone_end = zone_start + self.zone_sectors;
+            match locked_wps.get_mut(index) {
+                Some(wp) => *wp = (*wp).max(end.min(zone_end)),
+                None => break,
+            }
+            zone_start = zone_end;
+        }
+    }
+}
+
+fn read_sysfs(dir: &str, name: &str) -> Option<String> {
+    fs::read_to_string(format!("{}/{}", dir, name))
+        .ok()
+        .map(|val| val.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_zoned_probe() {
+        assert_eq!(size_of::<BlkZoneReport>(), 16);
+        assert_eq!(size_of::<BlkZone>(), 64);
+        assert_eq!(size_of::<BlkZoneRange>(), 16);
+
+        // Regular file is not zoned.
+        let file = TempFile::new().unwrap();
+        assert!(ZonedDevice::probe(file.as_file()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_zoned_append_sector() {
+        let file = TempFile::new().unwrap();
+        let device = ZonedDevice {
+            file: file.into_file(),
+            model: ZoneModel::HostManaged,
+            zone_sectors: 0x100,
+            nr_zones: 2,
+            max_open_zones: 0,
+            max_active_zones: 0,
+            max_append_sectors: 0x80,
+            wps: Mutex::new(vec![0x10, 0x100]),
+        };
+        assert_eq!(device.append_sector(0, 8).unwrap(), 0x10);
+        assert_eq!(device.append_sector(0, 8).unwrap(), 0x18);
+        assert_eq!(device.append_sector(0x100, 0x100).unwrap(), 0x100);
+        // The zone is full.
+        assert!(device.append_sector(0x100, 1).is_err());
+        // Unaligned zone start and out of range.
+        assert!(device.append_sector(0x10, 1).is_err());
+        assert!(device.append_sector(0x200, 1).is_err());
+
+        // Regular writes move the write pointers of all the zones written.
+        device.wps.lock().unwrap().copy_from_slice(&[0, 0x100]);
+        device.advance_wp(0, 0x120);
+        assert_eq!(device.append_sector(0, 0).unwrap(), 0x100);
+        assert_eq!(device.append_sector(0x100, 0x10).unwrap(), 0x120);
+        device.advance_wp(0x10, 0x10);
+        assert_eq!(device.append_sector(0, 0).unwrap(), 0x100);
+    }
+}
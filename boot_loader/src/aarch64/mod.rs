@@ -15,16 +15,27 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
 use log::info;
 
 use crate::error::BootLoaderError;
 use address_space::{AddressSpace, GuestAddress};
 use devices::legacy::{error::LegacyError as FwcfgErrorKind, FwCfgEntryType, FwCfgOps};
 use util::byte_code::ByteCode;
+use util::device_tree::merge_fdt;
 
 const AARCH64_KERNEL_OFFSET: u64 = 0x8_0000;
 
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LSB: u8 = 1;
+const ELF_MACHINE_AARCH64: u16 = 183;
+const ELF_HEADER_SIZE: usize = 64;
+const ELF_PROGRAM_HEADER_SIZE: usize = 56;
+const ELF_PT_LOAD: u32 = 1;
+
 /// Boot loader config used for aarch64.
 #[derive(Default, Debug)]
 pub struct AArch64BootLoaderConfig {
@@ -48,20 +59,143 @@ pub struct AArch64BootLoader {
     pub dtb_start: u64,
 }
 
+fn read_le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_le_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0_u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_le_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0_u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Flatten the loadable segments of an ELF vmlinux to a kernel image, which is
+/// the same as the Image converted by `objcopy -O binary`.
+///
+/// Returns the image and the offset of entry point in it.
+fn flatten_elf_kernel(elf: &[u8], max_size: u64) -> Result<(Vec<u8>, u64)> {
+    let invalid = |msg: &str| anyhow!(BootLoaderError::InvalidElfKernel(msg.to_string()));
+    if elf.len() < ELF_HEADER_SIZE {
+        return Err(invalid("too short"));
+    }
+    if elf[4] != ELF_CLASS_64 || elf[5] != ELF_DATA_LSB {
+        return Err(invalid("not a 64-bit little-endian ELF"));
+    }
+    if read_le_u16(elf, 18) != ELF_MACHINE_AARCH64 {
+        return Err(invalid("machine is not aarch64"));
+    }
+    let entry = read_le_u64(elf, 24);
+    let phoff = read_le_u64(elf, 32) as usize;
+    let phentsize = read_le_u16(elf, 54) as usize;
+    let phnum = read_le_u16(elf, 56) as usize;
+    if phentsize < ELF_PROGRAM_HEADER_SIZE
+        || phoff
+            .checked_add(phentsize * phnum)
+            .filter(|end| *end <= elf.len())
+            .is_none()
+    {
+        return Err(invalid("program headers out of range"));
+    }
+
+    // (file offset, virtual address, file size, memory size) of loadable segments.
+    let mut segments = Vec::new();
+    for index in 0..phnum {
+        let ph = &elf[phoff + index * phentsize..];
+        if read_le_u32(ph, 0) != ELF_PT_LOAD {
+            continue;
+        }
+        let offset = read_le_u64(ph, 8);
+        let vaddr = read_le_u64(ph, 16);
+        let filesz = read_le_u64(ph, 32);
+        let memsz = read_le_u64(ph, 40);
+        if filesz > memsz
+            || offset
+                .checked_add(filesz)
+                .filter(|end| *end <= elf.len() as u64)
+                .is_none()
+            || vaddr.checked_add(memsz).is_none()
+        {
+            return Err(invalid("loadable segment out of range"));
+        }
+        segments.push((offset, vaddr, filesz, memsz));
+    }
+    let base = segments
+        .iter()
+        .map(|seg| seg.1)
+        .min()
+        .ok_or_else(|| invalid("no loadable segment"))?;
+    let end = segments.iter().map(|seg| seg.1 + seg.3).max().unwrap();
+    if end - base > max_size {
+        bail!(BootLoaderError::KernelOverflow(base, end - base));
+    }
+    if entry < base || entry >= end {
+        return Err(invalid("entry point out of loadable segments"));
+    }
+
+    let mut image = vec![0_u8; (end - base) as usize];
+    for (offset, vaddr, filesz, _) in segments {
+        let start = (vaddr - base) as usize;
+        image[start..start + filesz as usize]
+            .copy_from_slice(&elf[offset as usize..(offset + filesz) as usize]);
+    }
+
+    Ok((image, entry - base))
+}
+
+/// Read kernel from file, which can be a raw Image, a gzip-compressed Image or an
+/// ELF vmlinux.
+///
+/// Returns the image to be loaded and the offset of entry point in it.
+fn read_kernel(kernel_path: &Path, max_size: u64) -> Result<(Vec<u8>, u64)> {
+    let mut kernel_image =
+        File::open(kernel_path).with_context(|| BootLoaderError::BootLoaderOpenKernel)?;
+    let mut kernel_data = Vec::new();
+    kernel_image.read_to_end(&mut kernel_data)?;
+
+    if kernel_data.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(kernel_data.as_slice())
+            .take(max_size + 1)
+            .read_to_end(&mut decompressed)
+            .with_context(|| "Failed to decompress gzip kernel")?;
+        kernel_data = decompressed;
+        info!("Kernel image is gzip-compressed.");
+    }
+    if kernel_data.starts_with(&ELF_MAGIC) {
+        info!("Kernel image is in ELF format.");
+        return flatten_elf_kernel(&kernel_data, max_size);
+    }
+    if kernel_data.len() as u64 > max_size {
+        bail!(BootLoaderError::KernelOverflow(0, kernel_data.len() as u64));
+    }
+
+    Ok((kernel_data, 0))
+}
+
+/// Load kernel to guest memory, or to fwcfg if boot from firmware.
+///
+/// Returns the end address of kernel and the offset of entry point.
 fn load_kernel(
     fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
     kernel_start: u64,
     kernel_path: &Path,
     sys_mem: &Arc<AddressSpace>,
-) -> Result<u64> {
-    let mut kernel_image =
-        File::open(kernel_path).with_context(|| BootLoaderError::BootLoaderOpenKernel)?;
-    let kernel_size = kernel_image.metadata().unwrap().len();
+) -> Result<(u64, u64)> {
+    let max_size = sys_mem
+        .memory_end_address()
+        .raw_value()
+        .saturating_sub(kernel_start);
+    let (kernel_data, entry_offset) = read_kernel(kernel_path, max_size)?;
+    let kernel_size = kernel_data.len() as u64;
     let kernel_end = kernel_start + kernel_size;
 
     if let Some(fw_cfg) = fwcfg {
-        let mut kernel_data = Vec::new();
-        kernel_image.read_to_end(&mut kernel_data)?;
         let mut lock_dev = fw_cfg.lock().unwrap();
         lock_dev
             .add_data_entry(
@@ -85,10 +219,14 @@ fn load_kernel(
             )));
         }
         sys_mem
-            .write(&mut kernel_image, GuestAddress(kernel_start), kernel_size)
+            .write(
+                &mut kernel_data.as_slice(),
+                GuestAddress(kernel_start),
+                kernel_size,
+            )
             .with_context(|| "Fail to write kernel to guest memory")?;
     }
-    Ok(kernel_end)
+    Ok((kernel_end, entry_offset))
 }
 
 fn load_initrd(
@@ -136,7 +274,8 @@ fn load_initrd(
     Ok((initrd_start, initrd_size))
 }
 
-/// Load PE(vmlinux.bin) linux kernel and other boot source to Guest Memory.
+/// Load linux kernel (Image, gzip-compressed Image or ELF vmlinux) and other boot
+/// source to Guest Memory.
 ///
 /// # Steps
 ///
@@ -176,7 +315,7 @@ pub fn load_linux(
     }
 
     let kernel_start = config.mem_start + AARCH64_KERNEL_OFFSET;
-    let boot_pc = if fwcfg.is_some() { 0 } else { kernel_start };
+    let mut boot_pc = if fwcfg.is_some() { 0 } else { kernel_start };
 
     if config.kernel.is_none() {
         return Ok(AArch64BootLoader {
@@ -187,13 +326,16 @@ pub fn load_linux(
         });
     }
 
-    let kernel_end = load_kernel(
+    let (kernel_end, entry_offset) = load_kernel(
         fwcfg,
         kernel_start,
         config.kernel.as_ref().unwrap(),
         sys_mem,
     )
     .with_context(|| "Fail to load kernel")?;
+    if fwcfg.is_none() {
+        boot_pc += entry_offset;
+    }
 
     let mut initrd_start = 0_u64;
     let mut initrd_size = 0_u64;
@@ -213,3 +355,71 @@ pub fn load_linux(
         dtb_start: dtb_addr,
     })
 }
+
+/// Merge the user-supplied dtb file into the machine-generated flattened device tree.
+///
+/// # Arguments
+///
+/// * `fdt` - The machine-generated flattened device tree.
+/// * `dtb_path` - Path of the user-supplied dtb file.
+pub fn load_dtb(fdt: &[u8], dtb_path: &Path) -> Result<Vec<u8>> {
+    let mut dtb_file = File::open(dtb_path).with_context(|| BootLoaderError::BootLoaderOpenDtb)?;
+    let mut dtb_data = Vec::new();
+    dtb_file.read_to_end(&mut dtb_data)?;
+
+    merge_fdt(fdt, &dtb_data).with_context(|| "Fail to merge user-supplied dtb")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_elf(entry: u64, segments: &[(u64, &[u8], u64)]) -> Vec<u8> {
+        let phoff = ELF_HEADER_SIZE;
+        let data_start = phoff + segments.len() * ELF_PROGRAM_HEADER_SIZE;
+        let mut elf = vec![0_u8; data_start];
+        elf[0..4].copy_from_slice(&ELF_MAGIC);
+        elf[4] = ELF_CLASS_64;
+        elf[5] = ELF_DATA_LSB;
+        elf[18..20].copy_from_slice(&ELF_MACHINE_AARCH64.to_le_bytes());
+        elf[24..32].copy_from_slice(&entry.to_le_bytes());
+        elf[32..40].copy_from_slice(&(phoff as u64).to_le_bytes());
+        elf[54..56].copy_from_slice(&(ELF_PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        elf[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for (index, (vaddr, data, memsz)) in segments.iter().enumerate() {
+            let offset = elf.len() as u64;
+            let ph = phoff + index * ELF_PROGRAM_HEADER_SIZE;
+            elf[ph..ph + 4].copy_from_slice(&ELF_PT_LOAD.to_le_bytes());
+            elf[ph + 8..ph + 16].copy_from_slice(&offset.to_le_bytes());
+            elf[ph + 16..ph + 24].copy_from_slice(&vaddr.to_le_bytes());
+            elf[ph + 24..ph + 32].copy_from_slice(&vaddr.to_le_bytes());
+            elf[ph + 32..ph + 40].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            elf[ph + 40..ph + 48].copy_from_slice(&memsz.to_le_bytes());
+            elf.extend_from_slice(data);
+        }
+        elf
+    }
+
+    #[test]
+    fn test_flatten_elf_kernel() {
+        let base = 0xffff_8000_0800_0000;
+        let elf = build_elf(
+            base + 4,
+            &[(base, &[1, 2, 3, 4, 5, 6], 8), (base + 16, &[7, 8], 4)],
+        );
+        let (image, entry_offset) = flatten_elf_kernel(&elf, 0x1000).unwrap();
+        assert_eq!(entry_offset, 4);
+        assert_eq!(
+            image,
+            vec![1, 2, 3, 4, 5, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 8, 0, 0]
+        );
+
+        // Image is larger than the available memory.
+        assert!(flatten_elf_kernel(&elf, 0x10).is_err());
+        // Entry point is out of the loadable segments.
+        let elf = build_elf(base + 0x100, &[(base, &[1, 2, 3, 4], 4)]);
+        assert!(flatten_elf_kernel(&elf, 0x1000).is_err());
+        // Truncated program headers.
+        assert!(flatten_elf_kernel(&elf[..ELF_HEADER_SIZE + 8], 0x1000).is_err());
+    }
+}
@@ -44,6 +44,12 @@ pub enum BootLoaderError {
     BootLoaderOpenKernel,
     #[error("Failed to open initrd image")]
     BootLoaderOpenInitrd,
+    #[error("Failed to open dtb file")]
+    #[cfg(target_arch = "aarch64")]
+    BootLoaderOpenDtb,
+    #[error("Invalid ELF-format kernel: {0}")]
+    #[cfg(target_arch = "aarch64")]
+    InvalidElfKernel(String),
     #[error("Configure cpu number({0}) above supported max cpu numbers(254)")]
     MaxCpus(u8),
     #[error("Invalid bzImage kernel file")]
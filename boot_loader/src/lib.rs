@@ -88,12 +88,12 @@ pub mod error;
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
 
-#[cfg(target_arch = "aarch64")]
-pub use aarch64::load_linux;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::AArch64BootLoader as BootLoader;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::AArch64BootLoaderConfig as BootLoaderConfig;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::{load_dtb, load_linux};
 pub use error::BootLoaderError;
 
 #[cfg(target_arch = "x86_64")]
@@ -17,6 +17,7 @@ fn main() {
         feature = "ramfb",
         feature = "virtio_gpu",
         feature = "vnc",
+        feature = "display_channel",
     )) {
         println!("cargo:rustc-link-arg=-lpixman-1");
     }
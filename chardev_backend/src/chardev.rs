@@ -10,22 +10,26 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::{HashMap, VecDeque};
 use std::fs::{read_link, File, OpenOptions};
-use std::io::{Stdin, Stdout};
-use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::io::{ErrorKind, Stdin, Stdout};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
 use anyhow::{bail, Context, Result};
 use libc::{cfmakeraw, tcgetattr, tcsetattr, termios};
-use log::{error, info};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
 use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
 
 use machine_manager::machine::{PathInfo, PTY_PATH};
 use machine_manager::{
     config::{ChardevConfig, ChardevType},
+    event_loop::EventLoop,
     temp_cleaner::TempCleaner,
 };
 use util::file::clear_file;
@@ -35,6 +39,41 @@ use util::loop_context::{
 use util::set_termi_raw_mode;
 use util::unix::limit_permission;
 
+use crate::mux::{
+    execute_monitor_command, monitor_quit, Mux, MuxAction, MuxFocus, MUX_MONITOR_BUF_SIZE,
+};
+use crate::vdagent::VdAgent;
+use ui::clipboard::{register_clipboard, unregister_clipboard};
+
+/// High watermark of the output buffer of socket chardev. The device should stop
+/// sending data to the chardev once it is reached, and wait for the listener to be
+/// notified.
+const OUTBUF_HIGH_WATERMARK: usize = 64 * 1024;
+
+/// Chardevs used by devices, the backend of which can be changed at runtime.
+static CHARDEV_LIST: Lazy<Mutex<HashMap<String, Weak<Mutex<Chardev>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Register the chardev so that it can be found by `chardev-change`.
+pub fn register_chardev(chardev: &Arc<Mutex<Chardev>>) {
+    let id = chardev.lock().unwrap().id.clone();
+    CHARDEV_LIST
+        .lock()
+        .unwrap()
+        .insert(id, Arc::downgrade(chardev));
+}
+
+/// Change the backend of the chardev with `id` at runtime.
+pub fn change_chardev(id: &str, backend: ChardevType) -> Result<()> {
+    let chardev = CHARDEV_LIST
+        .lock()
+        .unwrap()
+        .get(id)
+        .and_then(|chardev| chardev.upgrade())
+        .with_context(|| format!("Chardev {} not found", id))?;
+    Chardev::change_backend(&chardev, backend)
+}
+
 /// Provide the trait that helps handle the input data.
 pub trait InputReceiver: Send {
     /// Handle the input data and trigger interrupt if necessary.
@@ -71,6 +110,16 @@ pub struct Chardev {
     receiver: Option<Arc<Mutex<dyn InputReceiver>>>,
     /// Used to notify device the socket is opened or closed.
     dev: Option<Arc<Mutex<dyn ChardevNotifyDevice>>>,
+    /// Data which has not been written to the socket stream yet.
+    outbuf: VecDeque<u8>,
+    /// Duplicated socket stream used to watch its writability.
+    output_watch: Option<UnixStream>,
+    /// Whether the writability of socket stream is being watched.
+    output_watched: bool,
+    /// Notified when the output buffer is drained.
+    output_listener: Option<Arc<EventFd>>,
+    /// Demultiplexer of input if the backend is shared with monitor.
+    mux: Option<Mux>,
 }
 
 impl Chardev {
@@ -84,6 +133,15 @@ impl Chardev {
             stream_fd: None,
             receiver: None,
             dev: None,
+            outbuf: VecDeque::new(),
+            output_watch: None,
+            output_watched: false,
+            output_listener: None,
+            mux: if chardev_cfg.mux {
+                Some(Mux::new())
+            } else {
+                None
+            },
         }
     }
 
@@ -142,6 +200,12 @@ impl Chardev {
                 ));
                 self.output = Some(file);
             }
+            ChardevType::Vdagent => {
+                let agent = Arc::new(Mutex::new(VdAgent::new(&self.id)?));
+                register_clipboard(&self.id, agent.clone());
+                self.input = Some(agent.clone());
+                self.output = Some(agent);
+            }
         };
         Ok(())
     }
@@ -153,6 +217,257 @@ impl Chardev {
     pub fn set_device(&mut self, dev: Arc<Mutex<dyn ChardevNotifyDevice>>) {
         self.dev = Some(dev.clone());
     }
+
+    /// Whether the output buffer reaches the high watermark. The device should stop
+    /// sending data until the listener passed to `fill_outbuf` is notified.
+    pub fn outbuf_is_full(&self) -> bool {
+        self.outbuf.len() >= OUTBUF_HIGH_WATERMARK
+    }
+
+    /// Send data to chardev backend. For socket chardev, the data which can not be
+    /// written at once is buffered and flushed when the socket becomes writable.
+    ///
+    /// # Arguments
+    ///
+    /// * `chardev` - The chardev to send data to.
+    /// * `buf` - The data to send.
+    /// * `listener` - Notified when the buffered data is drained.
+    pub fn fill_outbuf(
+        chardev: &Arc<Mutex<Chardev>>,
+        buf: &[u8],
+        listener: Option<Arc<EventFd>>,
+    ) -> Result<()> {
+        let mut locked_chardev = chardev.lock().unwrap();
+        if !matches!(locked_chardev.backend, ChardevType::Socket { .. }) {
+            let output = locked_chardev
+                .output
+                .clone()
+                .with_context(|| "Failed to get output fd")?;
+            drop(locked_chardev);
+            let mut locked_output = output.lock().unwrap();
+            locked_output
+                .write_all(buf)
+                .with_context(|| "Failed to write msg to chardev")?;
+            locked_output
+                .flush()
+                .with_context(|| "Failed to flush msg to chardev")?;
+            return Ok(());
+        }
+
+        if locked_chardev.stream_fd.is_none() {
+            bail!("Chardev {} is not connected", locked_chardev.id);
+        }
+        locked_chardev.outbuf.extend(buf);
+        locked_chardev.consume_outbuf()?;
+        if locked_chardev.outbuf.is_empty() {
+            return Ok(());
+        }
+
+        locked_chardev.output_listener = listener;
+        if locked_chardev.output_watched {
+            return Ok(());
+        }
+        let watch_fd = locked_chardev
+            .output_watch
+            .as_ref()
+            .with_context(|| "Failed to get watch fd of chardev socket")?
+            .as_raw_fd();
+        locked_chardev.output_watched = true;
+        drop(locked_chardev);
+
+        EventLoop::update_event(
+            vec![EventNotifier::new(
+                NotifierOperation::AddShared,
+                watch_fd,
+                None,
+                EventSet::OUT,
+                vec![get_output_handler(chardev.clone())],
+            )],
+            None,
+        )
+    }
+
+    /// Write buffered data to the socket stream without blocking.
+    fn consume_outbuf(&mut self) -> Result<()> {
+        let stream_fd = self
+            .stream_fd
+            .with_context(|| format!("Chardev {} is not connected", self.id))?;
+        while !self.outbuf.is_empty() {
+            let (data, _) = self.outbuf.as_slices();
+            // SAFETY: data is a valid slice and the length is checked by send.
+            let ret = unsafe {
+                libc::send(
+                    stream_fd,
+                    data.as_ptr() as *const libc::c_void,
+                    data.len(),
+                    libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                match err.kind() {
+                    ErrorKind::WouldBlock => break,
+                    ErrorKind::Interrupted => continue,
+                    _ => {
+                        self.outbuf.clear();
+                        bail!("Failed to write msg to chardev {}: {:?}", self.id, err);
+                    }
+                }
+            }
+            self.outbuf.drain(..ret as usize);
+        }
+        Ok(())
+    }
+
+    /// Fds registered to the event loop by the chardev. Stream must be deleted before
+    /// listener, as listener is parked by stream.
+    fn notifier_fds(&self) -> Vec<RawFd> {
+        let mut fds = Vec::new();
+        match &self.backend {
+            ChardevType::Stdio | ChardevType::Pty | ChardevType::Vdagent => {
+                if let Some(input) = self.input.as_ref() {
+                    fds.push(input.lock().unwrap().as_raw_fd());
+                }
+            }
+            ChardevType::Socket { .. } => {
+                if let Some(stream_fd) = self.stream_fd {
+                    fds.push(stream_fd);
+                }
+                if self.output_watched {
+                    if let Some(watch) = self.output_watch.as_ref() {
+                        fds.push(watch.as_raw_fd());
+                    }
+                }
+                if let Some(listener) = self.listener.as_ref() {
+                    fds.push(listener.as_raw_fd());
+                }
+            }
+            ChardevType::File(_) => (),
+        }
+        fds
+    }
+
+    /// Remove the event notifiers of the chardev, used when the chardev is released
+    /// at runtime.
+    pub fn unrealize(&mut self) -> Result<()> {
+        let fds = self.notifier_fds();
+        self.receiver = None;
+        self.dev = None;
+        if self.backend == ChardevType::Vdagent {
+            unregister_clipboard(&self.id);
+        }
+        if !fds.is_empty() {
+            EventLoop::update_event(gen_delete_notifiers(&fds), None)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the backend of the chardev without touching the device. The new backend
+    /// is attached first, so the old one is kept if it fails. The output buffered for the
+    /// old backend is flushed, and the rest is redirected to the new backend if possible.
+    pub fn change_backend(chardev: &Arc<Mutex<Chardev>>, backend: ChardevType) -> Result<()> {
+        let mut locked_chardev = chardev.lock().unwrap();
+        // The multiplexer is kept, only the backend is replaced.
+        let mut new_chardev = Chardev::new(ChardevConfig {
+            id: locked_chardev.id.clone(),
+            backend,
+            mux: false,
+        });
+        new_chardev.realize().with_context(|| {
+            format!(
+                "Failed to realize new backend of chardev {}",
+                new_chardev.id
+            )
+        })?;
+
+        let fds = locked_chardev.notifier_fds();
+        if !locked_chardev.outbuf.is_empty() {
+            if let Err(e) = locked_chardev.consume_outbuf() {
+                warn!(
+                    "Failed to flush output of chardev {}: {:?}",
+                    locked_chardev.id, e
+                );
+            }
+        }
+        if !locked_chardev.outbuf.is_empty() {
+            let pending: Vec<u8> = locked_chardev.outbuf.drain(..).collect();
+            if let Some(output) = new_chardev.output.as_ref() {
+                let mut locked_output = output.lock().unwrap();
+                if let Err(e) = locked_output
+                    .write_all(&pending)
+                    .and_then(|_| locked_output.flush())
+                {
+                    error!(
+                        "Failed to write pending output to new chardev backend: {:?}",
+                        e
+                    );
+                }
+            } else {
+                warn!(
+                    "Drop {} bytes pending output of chardev {}",
+                    pending.len(),
+                    locked_chardev.id
+                );
+            }
+        }
+        locked_chardev.reset_outbuf();
+        if !fds.is_empty() {
+            EventLoop::update_event(gen_delete_notifiers(&fds), None)?;
+        }
+
+        if locked_chardev.stream_fd.is_some() {
+            if let Some(dev) = &locked_chardev.dev {
+                dev.lock().unwrap().chardev_notify(ChardevStatus::Close);
+            }
+        }
+        if locked_chardev.backend == ChardevType::Pty {
+            let mut pty_paths = PTY_PATH.lock().unwrap();
+            if let Some(pos) = pty_paths
+                .iter()
+                .position(|info| info.label == locked_chardev.id)
+            {
+                pty_paths.remove(pos);
+            }
+        }
+        if locked_chardev.backend == ChardevType::Vdagent
+            && new_chardev.backend != ChardevType::Vdagent
+        {
+            unregister_clipboard(&locked_chardev.id);
+        }
+
+        locked_chardev.backend = new_chardev.backend;
+        locked_chardev.listener = new_chardev.listener;
+        locked_chardev.input = new_chardev.input;
+        locked_chardev.output = new_chardev.output;
+        locked_chardev.stream_fd = None;
+        locked_chardev.output_watch = None;
+        // Pty and vdagent are opened once realized, the same as the device is created with it.
+        if matches!(
+            locked_chardev.backend,
+            ChardevType::Pty | ChardevType::Vdagent
+        ) {
+            if let Some(dev) = &locked_chardev.dev {
+                dev.lock().unwrap().chardev_notify(ChardevStatus::Open);
+            }
+        }
+        drop(locked_chardev);
+
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(chardev.clone()),
+            None,
+        )
+    }
+
+    /// Drop the buffered data and wake up the device waiting for the output buffer.
+    fn reset_outbuf(&mut self) {
+        self.outbuf.clear();
+        self.output_watched = false;
+        if let Some(listener) = self.output_listener.take() {
+            if let Err(e) = listener.write(1) {
+                error!("Failed to notify chardev output listener: {:?}", e);
+            }
+        }
+    }
 }
 
 fn set_pty_raw_mode() -> Result<(i32, PathBuf)> {
@@ -214,36 +529,89 @@ fn set_pty_raw_mode() -> Result<(i32, PathBuf)> {
     Ok((master, path))
 }
 
+fn get_output_handler(chardev: Arc<Mutex<Chardev>>) -> Rc<NotifierCallback> {
+    Rc::new(move |_, fd| {
+        let mut locked_chardev = chardev.lock().unwrap();
+        if let Err(e) = locked_chardev.consume_outbuf() {
+            error!("{:?}", e);
+        }
+        if !locked_chardev.outbuf.is_empty() {
+            return None;
+        }
+        locked_chardev.reset_outbuf();
+        Some(gen_delete_notifiers(&[fd]))
+    })
+}
+
+/// Read the input from backend and send it to the receiver. If the backend is
+/// multiplexed, the input is sent to the monitor instead when it gets the focus.
+fn handle_input(chardev: &Arc<Mutex<Chardev>>) {
+    let locked_chardev = chardev.lock().unwrap();
+    if locked_chardev.receiver.is_none() {
+        error!("Failed to get chardev receiver");
+        return;
+    }
+    if locked_chardev.input.is_none() {
+        error!("Failed to get chardev input fd");
+        return;
+    }
+    let receiver = locked_chardev.receiver.clone().unwrap();
+    let input = locked_chardev.input.clone().unwrap();
+    let mux_focus = locked_chardev.mux.as_ref().map(|mux| mux.focus());
+    drop(locked_chardev);
+
+    let mut locked_receiver = receiver.lock().unwrap();
+    let buff_size = match mux_focus {
+        Some(MuxFocus::Monitor) => MUX_MONITOR_BUF_SIZE,
+        _ => locked_receiver.remain_size(),
+    };
+    if buff_size == 0 {
+        return;
+    }
+    let mut buffer = vec![0_u8; buff_size];
+    let index = match input.lock().unwrap().chr_read_raw(&mut buffer) {
+        Ok(index) => index,
+        Err(_) => {
+            error!("Failed to read input data");
+            return;
+        }
+    };
+    if mux_focus.is_none() {
+        locked_receiver.receive(&buffer[..index]);
+        return;
+    }
+    drop(locked_receiver);
+
+    let actions = match chardev.lock().unwrap().mux.as_mut() {
+        Some(mux) => mux.demux(&buffer[..index]),
+        None => return,
+    };
+    for action in actions {
+        match action {
+            MuxAction::Frontend(data) => receiver.lock().unwrap().receive(&data),
+            MuxAction::Output(data) => mux_output(chardev, &data),
+            MuxAction::Command(cmdline) => {
+                let output = execute_monitor_command(&cmdline);
+                mux_output(chardev, output.as_bytes());
+            }
+            MuxAction::Quit => monitor_quit(),
+        }
+    }
+}
+
+fn mux_output(chardev: &Arc<Mutex<Chardev>>, data: &[u8]) {
+    if let Err(e) = Chardev::fill_outbuf(chardev, data, None) {
+        error!("Failed to write monitor output: {:?}", e);
+    }
+}
+
 fn get_notifier_handler(
     chardev: Arc<Mutex<Chardev>>,
     backend: ChardevType,
 ) -> Rc<NotifierCallback> {
     match backend {
-        ChardevType::Stdio | ChardevType::Pty => Rc::new(move |_, _| {
-            let locked_chardev = chardev.lock().unwrap();
-            if locked_chardev.receiver.is_none() {
-                error!("Failed to get chardev receiver");
-                return None;
-            }
-            if locked_chardev.input.is_none() {
-                error!("Failed to get chardev input fd");
-                return None;
-            }
-            let receiver = locked_chardev.receiver.clone().unwrap();
-            let input = locked_chardev.input.clone().unwrap();
-            drop(locked_chardev);
-
-            let mut locked_receiver = receiver.lock().unwrap();
-            let buff_size = locked_receiver.remain_size();
-            if buff_size == 0 {
-                return None;
-            }
-            let mut buffer = vec![0_u8; buff_size];
-            if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
-                locked_receiver.receive(&buffer[..index]);
-            } else {
-                error!("Failed to read input data");
-            }
+        ChardevType::Stdio | ChardevType::Pty | ChardevType::Vdagent => Rc::new(move |_, _| {
+            handle_input(&chardev);
             None
         }),
         ChardevType::Socket { .. } => Rc::new(move |_, _| {
@@ -251,7 +619,16 @@ fn get_notifier_handler(
             let (stream, _) = locked_chardev.listener.as_ref().unwrap().accept().unwrap();
             let listener_fd = locked_chardev.listener.as_ref().unwrap().as_raw_fd();
             let stream_fd = stream.as_raw_fd();
+            let output_watch = match stream.try_clone() {
+                Ok(watch) => watch,
+                Err(e) => {
+                    error!("Failed to duplicate chardev socket stream: {:?}", e);
+                    return None;
+                }
+            };
+            let watch_fd = output_watch.as_raw_fd();
             locked_chardev.stream_fd = Some(stream_fd);
+            locked_chardev.output_watch = Some(output_watch);
             let stream_arc = Arc::new(Mutex::new(stream));
             locked_chardev.input = Some(stream_arc.clone());
             locked_chardev.output = Some(stream_arc);
@@ -262,41 +639,25 @@ fn get_notifier_handler(
 
             let cloned_chardev = chardev.clone();
             let inner_handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
-                let mut locked_chardev = cloned_chardev.lock().unwrap();
                 if event == EventSet::IN {
-                    if locked_chardev.receiver.is_none() {
-                        error!("Failed to get chardev receiver");
-                        return None;
-                    }
-                    if locked_chardev.input.is_none() {
-                        error!("Failed to get chardev input fd");
-                        return None;
-                    }
-                    let receiver = locked_chardev.receiver.clone().unwrap();
-                    let input = locked_chardev.input.clone().unwrap();
-                    drop(locked_chardev);
-
-                    let mut locked_receiver = receiver.lock().unwrap();
-                    let buff_size = locked_receiver.remain_size();
-                    if buff_size == 0 {
-                        return None;
-                    }
-                    let mut buffer = vec![0_u8; buff_size];
-                    if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
-                        locked_receiver.receive(&buffer[..index]);
-                    } else {
-                        error!("Failed to read input data");
-                    }
+                    handle_input(&cloned_chardev);
                     None
                 } else if event & EventSet::HANG_UP == EventSet::HANG_UP {
+                    let mut locked_chardev = cloned_chardev.lock().unwrap();
                     // Always allow disconnect even if has deactivated.
                     if let Some(dev) = &locked_chardev.dev {
                         dev.lock().unwrap().chardev_notify(ChardevStatus::Close);
                     }
+                    let mut fds = vec![stream_fd];
+                    if locked_chardev.output_watched {
+                        fds.push(watch_fd);
+                    }
+                    locked_chardev.reset_outbuf();
                     locked_chardev.input = None;
                     locked_chardev.output = None;
                     locked_chardev.stream_fd = None;
-                    Some(gen_delete_notifiers(&[stream_fd]))
+                    locked_chardev.output_watch = None;
+                    Some(gen_delete_notifiers(&fds))
                 } else {
                     None
                 }
@@ -319,7 +680,7 @@ impl EventNotifierHelper for Chardev {
         let backend = chardev.lock().unwrap().backend.clone();
         let cloned_chardev = chardev.clone();
         match backend {
-            ChardevType::Stdio | ChardevType::Pty => {
+            ChardevType::Stdio | ChardevType::Pty | ChardevType::Vdagent => {
                 if let Some(input) = chardev.lock().unwrap().input.clone() {
                     notifiers.push(EventNotifier::new(
                         NotifierOperation::AddShared,
@@ -378,3 +739,53 @@ impl CommunicatInInterface for Stdin {}
 impl CommunicatOutInterface for UnixStream {}
 impl CommunicatOutInterface for File {}
 impl CommunicatOutInterface for Stdout {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_socket_fill_outbuf() {
+        let chardev_cfg = ChardevConfig {
+            id: "chardev".to_string(),
+            backend: ChardevType::Socket {
+                path: "/tmp/test_socket_fill_outbuf.sock".to_string(),
+                server: true,
+                nowait: true,
+            },
+            mux: false,
+        };
+        let chardev = Arc::new(Mutex::new(Chardev::new(chardev_cfg)));
+        // Socket chardev is not connected.
+        assert!(Chardev::fill_outbuf(&chardev, &[0x1; 16], None).is_err());
+
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        chardev.lock().unwrap().stream_fd = Some(stream.as_raw_fd());
+        assert!(Chardev::fill_outbuf(&chardev, &[0x1; 16], None).is_ok());
+        assert!(chardev.lock().unwrap().outbuf.is_empty());
+        assert!(!chardev.lock().unwrap().outbuf_is_full());
+
+        let mut buf = [0_u8; 16];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x1; 16]);
+
+        // Data is kept in output buffer when socket is not writable.
+        chardev.lock().unwrap().outbuf.extend(vec![0x2; 4096]);
+        peer.set_nonblocking(true).unwrap();
+        loop {
+            chardev.lock().unwrap().consume_outbuf().unwrap();
+            if chardev.lock().unwrap().outbuf.is_empty() {
+                chardev.lock().unwrap().outbuf.extend(vec![0x2; 4096]);
+            } else {
+                break;
+            }
+        }
+        assert!(!chardev.lock().unwrap().outbuf.is_empty());
+        let mut buf = vec![0_u8; 4096];
+        while peer.read(&mut buf).is_ok() {}
+        chardev.lock().unwrap().consume_outbuf().unwrap();
+        assert!(chardev.lock().unwrap().outbuf.is_empty());
+    }
+}
@@ -11,3 +11,5 @@
 // See the Mulan PSL v2 for more details.
 
 pub mod chardev;
+pub mod mux;
+pub mod vdagent;
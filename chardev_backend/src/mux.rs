@@ -0,0 +1,361 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use machine_manager::event;
+use machine_manager::machine::{MachineExternalInterface, ShutdownCause};
+use machine_manager::qmp::qmp_channel::QmpChannel;
+use machine_manager::qmp::qmp_response::Response;
+use machine_manager::qmp::qmp_schema;
+use machine_manager::temp_cleaner::TempCleaner;
+use util::set_termi_canon_mode;
+
+/// Escape character of mux chardev, Ctrl-A.
+const MUX_ESCAPE_CHAR: u8 = 0x01;
+/// Size of the buffer to read input for monitor.
+pub const MUX_MONITOR_BUF_SIZE: usize = 256;
+const MONITOR_PROMPT: &str = "(stratovirt) ";
+const MONITOR_BANNER: &str = "\r\nStratoVirt monitor - type 'help' for more information\r\n";
+const MUX_HELP: &str = "\r\n\
+C-a h    print this help\r\n\
+C-a x    exit emulator\r\n\
+C-a c    switch between console and monitor\r\n\
+C-a C-a  sends C-a\r\n";
+const MONITOR_HELP: &str = "\
+help|?            -- show this help\r\n\
+info status       -- show the VM status\r\n\
+stop              -- stop the VM\r\n\
+cont              -- resume the VM\r\n\
+system_reset      -- reset the VM\r\n\
+system_powerdown  -- send system power down event\r\n\
+quit|q            -- quit the emulator\r\n\
+drive_add, drive_del, info snapshots, savevm, loadvm, delvm are the same as human-monitor-command\r\n";
+
+type MonitorController = Arc<Mutex<dyn MachineExternalInterface + Send + Sync>>;
+
+/// The machine which executes the commands from mux monitor.
+static MUX_MONITOR: Lazy<Mutex<Option<MonitorController>>> = Lazy::new(|| Mutex::new(None));
+
+/// Register the machine to mux monitor.
+pub fn register_mux_monitor(controller: MonitorController) {
+    *MUX_MONITOR.lock().unwrap() = Some(controller);
+}
+
+/// Which subchannel the input of mux chardev goes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MuxFocus {
+    /// The device which uses the chardev.
+    Frontend,
+    /// The human monitor.
+    Monitor,
+}
+
+/// What to do with the demultiplexed input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MuxAction {
+    /// Input for the device.
+    Frontend(Vec<u8>),
+    /// Data written back to the backend, such as echo and prompt.
+    Output(Vec<u8>),
+    /// Command line to be executed by monitor.
+    Command(String),
+    /// Exit the emulator.
+    Quit,
+}
+
+/// Demultiplexer of chardev input, the input is switched between the device and
+/// the monitor by `Ctrl-A c`.
+pub struct Mux {
+    focus: MuxFocus,
+    /// Whether the last character is the escape character.
+    escape: bool,
+    /// Whether the last character is carriage return, used to handle "\r\n".
+    last_cr: bool,
+    /// Command line of monitor being edited.
+    line: Vec<u8>,
+}
+
+impl Default for Mux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mux {
+    pub fn new() -> Self {
+        Mux {
+            focus: MuxFocus::Frontend,
+            escape: false,
+            last_cr: false,
+            line: Vec::new(),
+        }
+    }
+
+    pub fn focus(&self) -> MuxFocus {
+        self.focus
+    }
+
+    /// Split the input into actions in order.
+    pub fn demux(&mut self, data: &[u8]) -> Vec<MuxAction> {
+        let mut actions = Vec::new();
+        for &byte in data {
+            if self.escape {
+                self.escape = false;
+                match byte {
+                    MUX_ESCAPE_CHAR => self.input(&mut actions, byte),
+                    b'c' => self.switch_focus(&mut actions),
+                    b'h' | b'?' => push_output(&mut actions, MUX_HELP.as_bytes()),
+                    b'x' => actions.push(MuxAction::Quit),
+                    _ => (),
+                }
+            } else if byte == MUX_ESCAPE_CHAR {
+                self.escape = true;
+            } else {
+                self.input(&mut actions, byte);
+            }
+        }
+        actions
+    }
+
+    fn switch_focus(&mut self, actions: &mut Vec<MuxAction>) {
+        match self.focus {
+            MuxFocus::Frontend => {
+                self.focus = MuxFocus::Monitor;
+                let mut output = MONITOR_BANNER.as_bytes().to_vec();
+                output.extend(MONITOR_PROMPT.as_bytes());
+                output.extend(&self.line);
+                push_output(actions, &output);
+            }
+            MuxFocus::Monitor => {
+                self.focus = MuxFocus::Frontend;
+                push_output(actions, b"\r\n");
+            }
+        }
+    }
+
+    fn input(&mut self, actions: &mut Vec<MuxAction>, byte: u8) {
+        if self.focus == MuxFocus::Frontend {
+            if let Some(MuxAction::Frontend(data)) = actions.last_mut() {
+                data.push(byte);
+            } else {
+                actions.push(MuxAction::Frontend(vec![byte]));
+            }
+            return;
+        }
+
+        let last_cr = self.last_cr;
+        self.last_cr = byte == b'\r';
+        match byte {
+            b'\n' if last_cr => (),
+            b'\r' | b'\n' => {
+                push_output(actions, b"\r\n");
+                if self.line.is_empty() {
+                    push_output(actions, MONITOR_PROMPT.as_bytes());
+                } else {
+                    let line = String::from_utf8_lossy(&self.line).to_string();
+                    self.line.clear();
+                    actions.push(MuxAction::Command(line));
+                }
+            }
+            // Backspace and delete.
+            0x08 | 0x7f if !self.line.is_empty() => {
+                self.line.pop();
+                push_output(actions, b"\x08 \x08");
+            }
+            0x20..=0x7e => {
+                self.line.push(byte);
+                push_output(actions, &[byte]);
+            }
+            _ => (),
+        }
+    }
+}
+
+fn push_output(actions: &mut Vec<MuxAction>, output: &[u8]) {
+    if let Some(MuxAction::Output(data)) = actions.last_mut() {
+        data.extend(output);
+    } else {
+        actions.push(MuxAction::Output(output.to_vec()));
+    }
+}
+
+/// Execute the command line of monitor, return the output followed by prompt.
+pub fn execute_monitor_command(cmdline: &str) -> String {
+    let mut output = match MUX_MONITOR.lock().unwrap().clone() {
+        Some(controller) => monitor_command(&controller, cmdline),
+        None => "Monitor is not available\r\n".to_string(),
+    };
+    output.push_str(MONITOR_PROMPT);
+    output
+}
+
+fn monitor_command(controller: &MonitorController, cmdline: &str) -> String {
+    let args: Vec<&str> = cmdline.split_whitespace().collect();
+    let ret = match args.as_slice() {
+        ["help"] | ["?"] => return MONITOR_HELP.to_string(),
+        ["info", "status"] => {
+            let resp = controller.lock().unwrap().query_status();
+            return status_to_string(resp);
+        }
+        ["stop"] => controller.lock().unwrap().pause(),
+        ["cont"] => controller.lock().unwrap().resume(),
+        ["system_reset"] => controller.lock().unwrap().reset(),
+        ["system_powerdown"] => controller.lock().unwrap().powerdown(),
+        ["quit"] | ["q"] => {
+            monitor_quit();
+            return String::new();
+        }
+        _ => {
+            let resp = controller.lock().unwrap().human_monitor_command(
+                qmp_schema::HumanMonitorCmdArgument {
+                    command_line: cmdline.to_string(),
+                },
+            );
+            return response_to_string(resp);
+        }
+    };
+    if ret {
+        String::new()
+    } else {
+        format!("Failed to execute command: {}\r\n", cmdline)
+    }
+}
+
+/// Exit the emulator as `quit` qmp command does.
+pub fn monitor_quit() {
+    if let Some(controller) = MUX_MONITOR.lock().unwrap().clone() {
+        controller.lock().unwrap().destroy();
+    }
+    let shutdown_msg = qmp_schema::Shutdown {
+        guest: false,
+        reason: ShutdownCause::HostQmpQuit.reason().to_string(),
+    };
+    event!(Shutdown; shutdown_msg);
+    TempCleaner::clean();
+    if let Err(e) = set_termi_canon_mode() {
+        error!("Failed to set terminal to canonical mode: {:?}", e);
+    }
+
+    std::process::exit(0);
+}
+
+fn status_to_string(resp: Response) -> String {
+    match serde_json::to_value(&resp) {
+        Ok(value) => match value["return"]["status"].as_str() {
+            Some(status) => format!("VM status: {}\r\n", status),
+            None => response_to_string(resp),
+        },
+        Err(e) => format!("Error: {:?}\r\n", e),
+    }
+}
+
+fn response_to_string(resp: Response) -> String {
+    let value = match serde_json::to_value(&resp) {
+        Ok(value) => value,
+        Err(e) => return format!("Error: {:?}\r\n", e),
+    };
+    if let Some(desc) = value["error"]["desc"].as_str() {
+        return format!("Error: {}\r\n", desc);
+    }
+    match &value["return"] {
+        Value::String(s) if s.is_empty() => String::new(),
+        Value::String(s) => {
+            let mut output = s.replace("\r\n", "\n").replace('\n', "\r\n");
+            if !output.ends_with("\r\n") {
+                output.push_str("\r\n");
+            }
+            output
+        }
+        Value::Object(obj) if obj.is_empty() => String::new(),
+        Value::Null => String::new(),
+        other => format!("{}\r\n", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mux_demux() {
+        let mut mux = Mux::new();
+        assert_eq!(mux.focus(), MuxFocus::Frontend);
+        assert_eq!(
+            mux.demux(b"ls\r"),
+            vec![MuxAction::Frontend(b"ls\r".to_vec())]
+        );
+
+        // Ctrl-A Ctrl-A sends Ctrl-A to the device, unknown escape is ignored.
+        assert_eq!(
+            mux.demux(&[b'a', 0x01, 0x01, 0x01, b'z', b'b']),
+            vec![MuxAction::Frontend(vec![b'a', 0x01, b'b'])]
+        );
+
+        // Switch to monitor, the escape sequence can be split.
+        assert_eq!(
+            mux.demux(&[b'1', 0x01]),
+            vec![MuxAction::Frontend(vec![b'1'])]
+        );
+        let actions = mux.demux(b"cinfo statuz\x7fs\r\n");
+        assert_eq!(mux.focus(), MuxFocus::Monitor);
+        assert_eq!(actions.len(), 2);
+        assert!(matches!(&actions[0], MuxAction::Output(_)));
+        assert_eq!(actions[1], MuxAction::Command("info status".to_string()));
+
+        // Empty line only prints prompt.
+        let mut output = b"\r\n".to_vec();
+        output.extend(MONITOR_PROMPT.as_bytes());
+        assert_eq!(mux.demux(b"\n"), vec![MuxAction::Output(output)]);
+
+        // Switch back to device.
+        assert_eq!(
+            mux.demux(&[b's', b't', 0x01, b'c', b'l']),
+            vec![
+                MuxAction::Output(b"st\r\n".to_vec()),
+                MuxAction::Frontend(vec![b'l'])
+            ]
+        );
+        assert_eq!(mux.focus(), MuxFocus::Frontend);
+        // The unfinished command line is kept.
+        let actions = mux.demux(&[0x01, b'c', b'o', b'p', b'\r']);
+        assert_eq!(actions[1], MuxAction::Command("stop".to_string()));
+
+        assert_eq!(mux.demux(&[0x01, b'x']), vec![MuxAction::Quit]);
+    }
+
+    #[test]
+    fn test_mux_monitor_response() {
+        assert_eq!(
+            response_to_string(Response::create_empty_response()),
+            String::new()
+        );
+        let resp = Response::create_response(
+            serde_json::to_value("snapshot1\nsnapshot2".to_string()).unwrap(),
+            None,
+        );
+        assert_eq!(response_to_string(resp), "snapshot1\r\nsnapshot2\r\n");
+        let resp = Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError("Invalid command".to_string()),
+            None,
+        );
+        assert_eq!(response_to_string(resp), "Error: Invalid command\r\n");
+        assert_eq!(
+            execute_monitor_command("info status"),
+            format!("Monitor is not available\r\n{}", MONITOR_PROMPT)
+        );
+    }
+}
@@ -0,0 +1,332 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Backend talking with the spice vdagent in the guest, which is attached to the
+//! virtio serial port named `com.redhat.spice.0`. The clipboard text is shared
+//! between the guest and the display clients.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use log::{debug, error, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::chardev::{CommunicatInInterface, CommunicatOutInterface};
+use ui::clipboard::{clipboard_set, clipboard_text, ClipboardOpts, CLIPBOARD_MAX_SIZE};
+
+const VDP_CLIENT_PORT: u32 = 1;
+const VD_AGENT_PROTOCOL: u32 = 1;
+/// Max data size of each chunk.
+const VD_AGENT_MAX_DATA_SIZE: usize = 2048;
+const CHUNK_HEADER_SIZE: usize = 8;
+const MESSAGE_HEADER_SIZE: usize = 20;
+/// Max size of the message accepted from the guest.
+const MESSAGE_MAX_SIZE: usize = CLIPBOARD_MAX_SIZE + 4;
+
+// Message types.
+const VD_AGENT_CLIPBOARD: u32 = 4;
+const VD_AGENT_ANNOUNCE_CAPABILITIES: u32 = 6;
+const VD_AGENT_CLIPBOARD_GRAB: u32 = 7;
+const VD_AGENT_CLIPBOARD_REQUEST: u32 = 8;
+const VD_AGENT_CLIPBOARD_RELEASE: u32 = 9;
+
+// Capabilities.
+const VD_AGENT_CAP_CLIPBOARD_BY_DEMAND: u32 = 5;
+
+// Clipboard types.
+const VD_AGENT_CLIPBOARD_NONE: u32 = 0;
+const VD_AGENT_CLIPBOARD_UTF8_TEXT: u32 = 1;
+
+pub struct VdAgent {
+    /// Id of chardev, also the name in the clipboard.
+    id: String,
+    /// Notified when there is data for the guest.
+    input_evt: EventFd,
+    /// Data to the guest.
+    inbuf: VecDeque<u8>,
+    /// Data from the guest which is not a complete chunk yet.
+    outbuf: Vec<u8>,
+    /// Message assembled from the chunks.
+    msg: Vec<u8>,
+    /// Size of the oversized message to be discarded.
+    discard: usize,
+    /// The guest agent supports clipboard.
+    clipboard: bool,
+    /// The guest holds the clipboard.
+    guest_grab: bool,
+}
+
+impl VdAgent {
+    pub fn new(id: &str) -> Result<Self> {
+        Ok(VdAgent {
+            id: id.to_string(),
+            input_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            inbuf: VecDeque::new(),
+            outbuf: Vec::new(),
+            msg: Vec::new(),
+            discard: 0,
+            clipboard: false,
+            guest_grab: false,
+        })
+    }
+
+    /// Send the message to the guest, which is split into chunks.
+    fn send_message(&mut self, msg_type: u32, data: &[u8]) {
+        let mut msg = vec![0_u8; MESSAGE_HEADER_SIZE];
+        LittleEndian::write_u32(&mut msg[0..4], VD_AGENT_PROTOCOL);
+        LittleEndian::write_u32(&mut msg[4..8], msg_type);
+        LittleEndian::write_u32(&mut msg[16..20], data.len() as u32);
+        msg.extend_from_slice(data);
+
+        for chunk in msg.chunks(VD_AGENT_MAX_DATA_SIZE) {
+            let mut header = [0_u8; CHUNK_HEADER_SIZE];
+            LittleEndian::write_u32(&mut header[0..4], VDP_CLIENT_PORT);
+            LittleEndian::write_u32(&mut header[4..8], chunk.len() as u32);
+            self.inbuf.extend(header);
+            self.inbuf.extend(chunk);
+        }
+        if let Err(e) = self.input_evt.write(1) {
+            error!("Failed to notify input of vdagent {}: {:?}", self.id, e);
+        }
+    }
+
+    fn announce_capabilities(&mut self, request: bool) {
+        let mut data = [0_u8; 8];
+        LittleEndian::write_u32(&mut data[0..4], u32::from(request));
+        LittleEndian::write_u32(&mut data[4..8], 1 << VD_AGENT_CAP_CLIPBOARD_BY_DEMAND);
+        self.send_message(VD_AGENT_ANNOUNCE_CAPABILITIES, &data);
+    }
+
+    fn handle_message(&mut self, msg_type: u32, data: &[u8]) {
+        debug!(
+            "Vdagent {} receive message type {}, size {}",
+            self.id,
+            msg_type,
+            data.len()
+        );
+        match msg_type {
+            VD_AGENT_ANNOUNCE_CAPABILITIES if data.len() >= 4 => {
+                let caps = data.get(4..8).map_or(0, LittleEndian::read_u32);
+                self.clipboard = caps & (1 << VD_AGENT_CAP_CLIPBOARD_BY_DEMAND) != 0;
+                self.guest_grab = false;
+                if LittleEndian::read_u32(&data[0..4]) != 0 {
+                    self.announce_capabilities(false);
+                }
+            }
+            VD_AGENT_CLIPBOARD_GRAB => {
+                let has_text = data
+                    .chunks_exact(4)
+                    .any(|t| LittleEndian::read_u32(t) == VD_AGENT_CLIPBOARD_UTF8_TEXT);
+                self.guest_grab = true;
+                if has_text {
+                    let mut request = [0_u8; 4];
+                    LittleEndian::write_u32(&mut request, VD_AGENT_CLIPBOARD_UTF8_TEXT);
+                    self.send_message(VD_AGENT_CLIPBOARD_REQUEST, &request);
+                }
+            }
+            VD_AGENT_CLIPBOARD
+                if data.len() >= 4
+                    && LittleEndian::read_u32(&data[0..4]) == VD_AGENT_CLIPBOARD_UTF8_TEXT =>
+            {
+                let text = &data[4..];
+                let len = text.iter().position(|&c| c == 0).unwrap_or(text.len());
+                clipboard_set(&self.id, &text[..len]);
+            }
+            VD_AGENT_CLIPBOARD_REQUEST if data.len() >= 4 => {
+                let mut reply = vec![0_u8; 4];
+                if LittleEndian::read_u32(&data[0..4]) == VD_AGENT_CLIPBOARD_UTF8_TEXT
+                    && !self.guest_grab
+                {
+                    LittleEndian::write_u32(&mut reply, VD_AGENT_CLIPBOARD_UTF8_TEXT);
+                    reply.extend(clipboard_text());
+                } else {
+                    LittleEndian::write_u32(&mut reply, VD_AGENT_CLIPBOARD_NONE);
+                }
+                self.send_message(VD_AGENT_CLIPBOARD, &reply);
+            }
+            VD_AGENT_CLIPBOARD_RELEASE => {
+                self.guest_grab = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Assemble the messages from the chunk data.
+    fn receive_chunk(&mut self, data: &[u8]) {
+        self.msg.extend_from_slice(data);
+        while !self.msg.is_empty() {
+            if self.discard > 0 {
+                let len = cmp::min(self.discard, self.msg.len());
+                self.msg.drain(..len);
+                self.discard -= len;
+                continue;
+            }
+            if self.msg.len() < MESSAGE_HEADER_SIZE {
+                break;
+            }
+            let msg_type = LittleEndian::read_u32(&self.msg[4..8]);
+            let size = LittleEndian::read_u32(&self.msg[16..20]) as usize;
+            if size > MESSAGE_MAX_SIZE {
+                warn!(
+                    "Vdagent {} discard message type {} with size {}",
+                    self.id, msg_type, size
+                );
+                self.discard = MESSAGE_HEADER_SIZE + size;
+                continue;
+            }
+            if self.msg.len() < MESSAGE_HEADER_SIZE + size {
+                break;
+            }
+            let msg: Vec<u8> = self.msg.drain(..MESSAGE_HEADER_SIZE + size).collect();
+            self.handle_message(msg_type, &msg[MESSAGE_HEADER_SIZE..]);
+        }
+    }
+}
+
+impl ClipboardOpts for VdAgent {
+    fn clipboard_update(&mut self, _text: &[u8]) {
+        if !self.clipboard {
+            return;
+        }
+        // The text is sent when the guest requests it.
+        self.guest_grab = false;
+        let mut grab = [0_u8; 4];
+        LittleEndian::write_u32(&mut grab, VD_AGENT_CLIPBOARD_UTF8_TEXT);
+        self.send_message(VD_AGENT_CLIPBOARD_GRAB, &grab);
+    }
+}
+
+impl AsRawFd for VdAgent {
+    fn as_raw_fd(&self) -> RawFd {
+        self.input_evt.as_raw_fd()
+    }
+}
+
+impl CommunicatInInterface for VdAgent {
+    fn chr_read_raw(&mut self, buf: &mut [u8]) -> Result<usize> {
+        // Input event is nonblocking, and may be written again below.
+        let _ = self.input_evt.read();
+        let len = cmp::min(buf.len(), self.inbuf.len());
+        for (dst, src) in buf.iter_mut().zip(self.inbuf.drain(..len)) {
+            *dst = src;
+        }
+        if !self.inbuf.is_empty() {
+            self.input_evt.write(1)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for VdAgent {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.outbuf.extend_from_slice(buf);
+        while self.outbuf.len() >= CHUNK_HEADER_SIZE {
+            let size = LittleEndian::read_u32(&self.outbuf[4..8]) as usize;
+            if size > VD_AGENT_MAX_DATA_SIZE {
+                error!("Vdagent {} receive invalid chunk size {}", self.id, size);
+                self.outbuf.clear();
+                self.msg.clear();
+                self.discard = 0;
+                break;
+            }
+            if self.outbuf.len() < CHUNK_HEADER_SIZE + size {
+                break;
+            }
+            let chunk: Vec<u8> = self.outbuf.drain(..CHUNK_HEADER_SIZE + size).collect();
+            self.receive_chunk(&chunk[CHUNK_HEADER_SIZE..]);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CommunicatOutInterface for VdAgent {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_type: u32, data: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0_u8; CHUNK_HEADER_SIZE + MESSAGE_HEADER_SIZE];
+        LittleEndian::write_u32(&mut msg[0..4], VDP_CLIENT_PORT);
+        LittleEndian::write_u32(&mut msg[4..8], (MESSAGE_HEADER_SIZE + data.len()) as u32);
+        LittleEndian::write_u32(&mut msg[8..12], VD_AGENT_PROTOCOL);
+        LittleEndian::write_u32(&mut msg[12..16], msg_type);
+        LittleEndian::write_u32(&mut msg[24..28], data.len() as u32);
+        msg.extend_from_slice(data);
+        msg
+    }
+
+    fn read_message(agent: &mut VdAgent) -> (u32, Vec<u8>) {
+        let mut buf = vec![0_u8; 4096];
+        let len = agent.chr_read_raw(&mut buf).unwrap();
+        assert!(len >= CHUNK_HEADER_SIZE + MESSAGE_HEADER_SIZE);
+        let msg_type = LittleEndian::read_u32(&buf[12..16]);
+        (
+            msg_type,
+            buf[CHUNK_HEADER_SIZE + MESSAGE_HEADER_SIZE..len].to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_vdagent_clipboard() {
+        let mut agent = VdAgent::new("vdagent-test").unwrap();
+
+        // Guest announces the capabilities, and requests the ones of host.
+        let mut caps = [0_u8; 8];
+        LittleEndian::write_u32(&mut caps[0..4], 1);
+        LittleEndian::write_u32(&mut caps[4..8], 1 << VD_AGENT_CAP_CLIPBOARD_BY_DEMAND);
+        // Message is split at any position.
+        let msg = message(VD_AGENT_ANNOUNCE_CAPABILITIES, &caps);
+        agent.write_all(&msg[..5]).unwrap();
+        agent.write_all(&msg[5..]).unwrap();
+        assert!(agent.clipboard);
+        let (msg_type, data) = read_message(&mut agent);
+        assert_eq!(msg_type, VD_AGENT_ANNOUNCE_CAPABILITIES);
+        assert_eq!(LittleEndian::read_u32(&data[0..4]), 0);
+
+        // Guest grabs the clipboard, and host requests the text.
+        let mut types = [0_u8; 4];
+        LittleEndian::write_u32(&mut types, VD_AGENT_CLIPBOARD_UTF8_TEXT);
+        agent
+            .write_all(&message(VD_AGENT_CLIPBOARD_GRAB, &types))
+            .unwrap();
+        let (msg_type, data) = read_message(&mut agent);
+        assert_eq!(msg_type, VD_AGENT_CLIPBOARD_REQUEST);
+        assert_eq!(data, types);
+
+        let mut text = types.to_vec();
+        text.extend_from_slice(b"guest text");
+        agent
+            .write_all(&message(VD_AGENT_CLIPBOARD, &text))
+            .unwrap();
+        assert_eq!(clipboard_text(), b"guest text");
+
+        // Host grabs the clipboard, and guest requests the text.
+        agent.clipboard_update(b"guest text");
+        let (msg_type, _) = read_message(&mut agent);
+        assert_eq!(msg_type, VD_AGENT_CLIPBOARD_GRAB);
+        agent
+            .write_all(&message(VD_AGENT_CLIPBOARD_REQUEST, &types))
+            .unwrap();
+        let (msg_type, data) = read_message(&mut agent);
+        assert_eq!(msg_type, VD_AGENT_CLIPBOARD);
+        assert_eq!(&data[4..], b"guest text");
+    }
+}
@@ -17,7 +17,7 @@ use kvm_bindings::{
 use kvm_ioctls::{Cap, Kvm, VcpuFd};
 
 use super::core_regs::Result;
-use machine_manager::config::{CpuConfig, PmuConfig};
+use machine_manager::config::{CpuConfig, PmuConfig, SveConfig};
 
 // Capabilities for ARM cpu.
 #[derive(Debug, Clone)]
@@ -49,6 +49,9 @@ impl ArmCPUCaps {
 #[derive(Copy, Clone, Debug, Default)]
 pub struct ArmCPUFeatures {
     pub pmu: bool,
+    pub sve: bool,
+    /// Max SVE vector length in quadwords, `0` means all the host supported lengths.
+    pub sve_max_vq: u32,
 }
 
 impl From<&CpuConfig> for ArmCPUFeatures {
@@ -58,6 +61,11 @@ impl From<&CpuConfig> for ArmCPUFeatures {
                 PmuConfig::On => true,
                 PmuConfig::Off => false,
             },
+            sve: conf.sve == SveConfig::On,
+            sve_max_vq: match &conf.sve {
+                SveConfig::On => conf.sve_max_vq.unwrap_or(0),
+                SveConfig::Off => 0,
+            },
         }
     }
 }
@@ -21,18 +21,20 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use kvm_bindings::{
-    kvm_device_attr, kvm_mp_state, kvm_regs, kvm_vcpu_events, kvm_vcpu_init, RegList,
-    KVM_ARM_VCPU_PMU_V3_CTRL, KVM_ARM_VCPU_PMU_V3_INIT, KVM_ARM_VCPU_PMU_V3_IRQ,
-    KVM_MP_STATE_RUNNABLE, KVM_MP_STATE_STOPPED,
+    kvm_device_attr, kvm_mp_state, kvm_one_reg, kvm_regs, kvm_vcpu_events, kvm_vcpu_init, RegList,
+    KVM_ARM_VCPU_PMU_V3_CTRL, KVM_ARM_VCPU_PMU_V3_INIT, KVM_ARM_VCPU_PMU_V3_IRQ, KVM_ARM_VCPU_SVE,
+    KVM_MP_STATE_RUNNABLE, KVM_MP_STATE_STOPPED, KVM_REG_ARM64, KVM_REG_ARM64_SVE,
+    KVM_REG_SIZE_U512,
 };
 use kvm_ioctls::{DeviceFd, VcpuFd};
+use vmm_sys_util::ioctl::ioctl_with_ref;
 
 use self::caps::CpregListEntry;
-use self::core_regs::{get_core_regs, set_core_regs};
+use self::core_regs::{get_core_regs, set_core_regs, Arm64CoreRegs};
 use crate::CPU;
-use hypervisor::kvm::KVM_FDS;
+use hypervisor::kvm::{KVM_ARM_VCPU_FINALIZE, KVM_FDS, KVM_GET_ONE_REG, KVM_SET_ONE_REG};
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -58,8 +60,35 @@ const SYS_MPIDR_EL1: u64 = 0x6030_0000_0013_c005;
 // this register is SYS_CNTV_CVAL_EL0.
 const SYS_CNTV_CNT_EL0: u64 = 0x6030_0000_0013_df1a;
 
+// System registers used to translate guest virtual address.
+// SCTLR_EL1 - System Control Register.
+const SYS_SCTLR_EL1: u64 = 0x6030_0000_0013_c080;
+// TTBR0_EL1 - Translation Table Base Register 0.
+const SYS_TTBR0_EL1: u64 = 0x6030_0000_0013_c100;
+// TTBR1_EL1 - Translation Table Base Register 1.
+const SYS_TTBR1_EL1: u64 = 0x6030_0000_0013_c101;
+// TCR_EL1 - Translation Control Register.
+const SYS_TCR_EL1: u64 = 0x6030_0000_0013_c102;
+// MMU enable bit of SCTLR_EL1.
+const SCTLR_M_BIT: u64 = 0x1;
+// Base address of translation table in TTBR and descriptors.
+const TTBR_BADDR_MASK: u64 = 0x0000_ffff_ffff_fffe;
+const DESC_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+const PSR_MODE_MASK: u64 = 0x0000_000f;
+
+/// Number of general purpose registers in gdb `g` packet, sp and pc follow them.
+const GDB_GPRS_NUM: usize = 31;
+/// Size of registers in gdb `g` packet: x0-x30, sp, pc and cpsr.
+const GDB_REGS_SIZE: usize = (GDB_GPRS_NUM + 2) * 8 + 4;
+
 const KVM_MAX_CPREG_ENTRIES: usize = 500;
 
+// See: https://elixir.bootlin.com/linux/v5.6/source/Documentation/virt/kvm/api.rst#L2311
+// Pseudo-register of the SVE vector lengths, bit `vq - 1` stands for the length of `vq` quadwords.
+const KVM_REG_ARM64_SVE_VLS: u64 =
+    KVM_REG_ARM64 | KVM_REG_ARM64_SVE as u64 | KVM_REG_SIZE_U512 | 0xffff;
+const SVE_VLS_WORDS: usize = 8;
+
 /// Interrupt ID for pmu.
 /// See: https://developer.arm.com/documentation/den0094/b/
 /// And: https://developer.arm.com/documentation/dai0492/b/
@@ -189,18 +218,72 @@ impl ArmCPUState {
         if vcpu_config.pmu {
             self.kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PMU_V3;
         }
+        // Enable SVE from config.
+        if vcpu_config.sve {
+            self.kvi.features[0] |= 1 << KVM_ARM_VCPU_SVE;
+        }
+        self.features = *vcpu_config;
 
         self.set_core_reg(boot_config);
 
         vcpu_fd
             .vcpu_init(&self.kvi)
             .with_context(|| "Failed to init kvm vcpu")?;
+        if self.features.sve {
+            self.finalize_sve(vcpu_fd)?;
+        }
         self.mpidr = vcpu_fd
             .get_one_reg(SYS_MPIDR_EL1)
             .with_context(|| "Failed to get mpidr")? as u64;
 
-        self.features = *vcpu_config;
+        Ok(())
+    }
+
+    /// Limit the SVE vector lengths to the configured max one, then finalize
+    /// SVE. It must be called after `vcpu_init` and before running the vcpu.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn finalize_sve(&self, vcpu_fd: &VcpuFd) -> Result<()> {
+        let max_vq = self.features.sve_max_vq as usize;
+        if max_vq != 0 {
+            let mut vls = [0_u64; SVE_VLS_WORDS];
+            let vls_reg = kvm_one_reg {
+                id: KVM_REG_ARM64_SVE_VLS,
+                addr: vls.as_mut_ptr() as u64,
+            };
+            // Safe because vcpu_fd is valid and vls is large enough to hold the 512 bits register.
+            let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_GET_ONE_REG(), &vls_reg) };
+            if ret < 0 {
+                return Err(anyhow!(std::io::Error::last_os_error()))
+                    .with_context(|| "Failed to get SVE vector lengths");
+            }
+            if vls[(max_vq - 1) / 64] & (1 << ((max_vq - 1) % 64)) == 0 {
+                bail!(
+                    "SVE vector length of {} bits is not supported by host",
+                    max_vq * 128
+                );
+            }
+            // Clear all the vector lengths larger than the max one.
+            for vq in max_vq..SVE_VLS_WORDS * 64 {
+                vls[vq / 64] &= !(1 << (vq % 64));
+            }
+            // Safe because vcpu_fd is valid and vls is large enough to hold the 512 bits register.
+            let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_SET_ONE_REG(), &vls_reg) };
+            if ret < 0 {
+                return Err(anyhow!(std::io::Error::last_os_error()))
+                    .with_context(|| "Failed to set SVE vector lengths");
+            }
+        }
 
+        let feature = KVM_ARM_VCPU_SVE as std::os::raw::c_int;
+        // Safe because vcpu_fd is valid and the feature is passed by reference.
+        let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_ARM_VCPU_FINALIZE(), &feature) };
+        if ret < 0 {
+            return Err(anyhow!(std::io::Error::last_os_error()))
+                .with_context(|| "Failed to finalize SVE for vCPU");
+        }
         Ok(())
     }
 
@@ -323,6 +406,121 @@ impl CPU {
 
         Ok(())
     }
+
+    /// Get registers in the layout of gdb `g` packet, see `gdb/features/aarch64-core.xml`:
+    /// x0-x30, sp, pc, cpsr.
+    pub fn get_gdb_regs(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(GDB_REGS_SIZE);
+        for idx in 0..GDB_GPRS_NUM {
+            let reg =
+                self.fd
+                    .get_one_reg(Arm64CoreRegs::UserPTRegRegs(idx).into())? as u64;
+            data.extend_from_slice(&reg.to_le_bytes());
+        }
+        let pstate = self.fd.get_one_reg(Arm64CoreRegs::UserPTRegPState.into())? as u64;
+        let sp = self.fd.get_one_reg(self.gdb_sp_reg(pstate))? as u64;
+        let pc = self.fd.get_one_reg(Arm64CoreRegs::UserPTRegPc.into())? as u64;
+        data.extend_from_slice(&sp.to_le_bytes());
+        data.extend_from_slice(&pc.to_le_bytes());
+        data.extend_from_slice(&(pstate as u32).to_le_bytes());
+        Ok(data)
+    }
+
+    /// Set registers in the layout of gdb `G` packet.
+    pub fn set_gdb_regs(&self, data: &[u8]) -> Result<()> {
+        if data.len() < GDB_REGS_SIZE {
+            bail!("Invalid length {} of gdb registers", data.len());
+        }
+
+        let read_u64 =
+            |idx: usize| u64::from_le_bytes(data[idx * 8..idx * 8 + 8].try_into().unwrap());
+        for idx in 0..GDB_GPRS_NUM {
+            self.fd.set_one_reg(
+                Arm64CoreRegs::UserPTRegRegs(idx).into(),
+                read_u64(idx) as u128,
+            )?;
+        }
+        let pstate = self.fd.get_one_reg(Arm64CoreRegs::UserPTRegPState.into())? as u64;
+        self.fd
+            .set_one_reg(self.gdb_sp_reg(pstate), read_u64(GDB_GPRS_NUM) as u128)?;
+        self.fd.set_one_reg(
+            Arm64CoreRegs::UserPTRegPc.into(),
+            read_u64(GDB_GPRS_NUM + 1) as u128,
+        )?;
+        Ok(())
+    }
+
+    /// The stack pointer seen by gdb is the one selected by current exception level.
+    fn gdb_sp_reg(&self, pstate: u64) -> u64 {
+        if pstate & PSR_MODE_MASK == PSR_MODE_EL1h {
+            Arm64CoreRegs::KvmSpEl1.into()
+        } else {
+            Arm64CoreRegs::UserPTRegSp.into()
+        }
+    }
+
+    /// Translate guest virtual address to guest physical address with the page table of `CPU`.
+    /// Only 4KB translation granule is supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `gva` - Guest virtual address.
+    /// * `read_u64` - Read a descriptor of page table at the guest physical address.
+    pub fn translate_gva(&self, gva: u64, read_u64: &dyn Fn(u64) -> Result<u64>) -> Result<u64> {
+        let sctlr = self.fd.get_one_reg(SYS_SCTLR_EL1)? as u64;
+        if sctlr & SCTLR_M_BIT == 0 {
+            return Ok(gva);
+        }
+
+        let tcr = self.fd.get_one_reg(SYS_TCR_EL1)? as u64;
+        // Bit 55 of address selects TTBR1_EL1 or TTBR0_EL1.
+        let (ttbr_reg, txsz, granule_4k) = if gva & (1 << 55) != 0 {
+            (SYS_TTBR1_EL1, (tcr >> 16) & 0x3f, (tcr >> 30) & 0x3 == 0x2)
+        } else {
+            (SYS_TTBR0_EL1, tcr & 0x3f, (tcr >> 14) & 0x3 == 0x0)
+        };
+        if !granule_4k {
+            bail!("Only 4KB translation granule is supported");
+        }
+        let ttbr = self.fd.get_one_reg(ttbr_reg)? as u64;
+
+        walk_page_table(gva, ttbr & TTBR_BADDR_MASK, 64 - txsz, read_u64)
+    }
+}
+
+/// Walk the page table with 4KB granule, each level resolves 9 bits of address.
+fn walk_page_table(
+    gva: u64,
+    table_base: u64,
+    va_bits: u64,
+    read_u64: &dyn Fn(u64) -> Result<u64>,
+) -> Result<u64> {
+    if !(25..=48).contains(&va_bits) {
+        bail!("Unsupported virtual address size {}", va_bits);
+    }
+    let levels = (va_bits - 12 + 8) / 9;
+    let mut table = table_base;
+    for level in (4 - levels)..4 {
+        let shift = 12 + 9 * (3 - level);
+        let index_bits = std::cmp::min(9, va_bits - shift);
+        let index = (gva >> shift) & ((1 << index_bits) - 1);
+        let desc = read_u64(table + index * 8)?;
+        if desc & 0x1 == 0 {
+            bail!("Guest virtual address {:#x} is not mapped", gva);
+        }
+
+        // Bit 1 distinguishes table from block descriptor, and it must be set for pages.
+        let is_table = desc & 0x2 != 0;
+        if level == 3 || !is_table {
+            if level == 3 && !is_table {
+                bail!("Invalid page descriptor {:#x} for {:#x}", desc, gva);
+            }
+            let offset_mask = (1 << shift) - 1;
+            return Ok((desc & DESC_ADDR_MASK & !offset_mask) | (gva & offset_mask));
+        }
+        table = desc & DESC_ADDR_MASK;
+    }
+    bail!("Failed to translate guest virtual address {:#x}", gva)
 }
 
 impl StateTransfer for CPU {
@@ -366,6 +564,11 @@ impl StateTransfer for CPU {
 
         self.fd.vcpu_init(&cpu_state.kvi)?;
 
+        if cpu_state.features.sve {
+            cpu_state
+                .finalize_sve(&self.fd)
+                .with_context(|| MigrationError::FromBytesError("Failed to finalize sve."))?;
+        }
         if cpu_state.features.pmu {
             self.init_pmu()
                 .with_context(|| MigrationError::FromBytesError("Failed to init pmu."))?;
@@ -51,6 +51,8 @@ pub use aarch64::PMU_INTR;
 pub use aarch64::PPI_BASE;
 pub use error::CpuError;
 #[cfg(target_arch = "x86_64")]
+pub use x86_64::caps::X86CPUFeatures as CPUFeatures;
+#[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUBootConfig as CPUBootConfig;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUState as ArchCPU;
@@ -58,25 +60,43 @@ pub use x86_64::X86CPUState as ArchCPU;
 pub use x86_64::X86CPUTopology as CPUTopology;
 
 use std::cell::RefCell;
-use std::sync::atomic::{fence, AtomicBool, Ordering};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
 use std::thread;
 use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::kvm_interrupt;
+use kvm_bindings::{
+    kvm_guest_debug, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_SW_BP,
+};
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use libc::{c_int, c_void, siginfo_t};
 use log::{error, info, warn};
+use vmm_sys_util::eventfd::EventFd;
+#[cfg(target_arch = "x86_64")]
+use vmm_sys_util::ioctl::ioctl_with_ref;
 use vmm_sys_util::signal::{register_signal_handler, Killable};
 
+use hypervisor::kvm::KVM_FDS;
+#[cfg(target_arch = "x86_64")]
+use hypervisor::kvm::KVM_INTERRUPT;
+use machine_manager::config::RebootAction;
 use machine_manager::config::ShutdownAction::{ShutdownActionPause, ShutdownActionPoweroff};
+use machine_manager::cpu_throttle::CpuThrottleOps;
 use machine_manager::event;
-use machine_manager::machine::MachineInterface;
+use machine_manager::machine::{request_shutdown_cause, MachineInterface, ShutdownCause};
 use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_schema};
+use util::cgroup::{join_thread_cgroup, CgroupThread};
+use util::metrics::{register_metric, Metric, MetricType};
 #[cfg(not(test))]
 use util::test_helper::is_test_enabled;
 #[cfg(target_arch = "x86_64")]
 use x86_64::caps::X86CPUCaps as CPUCaps;
+#[cfg(target_arch = "x86_64")]
+use x86_64::KvmRun;
 
 // SIGRTMIN = 34 (GNU, in MUSL is 35) and SIGRTMAX = 64  in linux, VCPU signal
 // number should be assigned to SIGRTMIN + n, (n = 0...30).
@@ -88,6 +108,10 @@ const VCPU_TASK_SIGNAL: i32 = 35;
 const VCPU_RESET_SIGNAL: i32 = 35;
 #[cfg(target_env = "musl")]
 const VCPU_RESET_SIGNAL: i32 = 36;
+#[cfg(not(target_env = "musl"))]
+const VCPU_THROTTLE_SIGNAL: i32 = 36;
+#[cfg(target_env = "musl")]
+const VCPU_THROTTLE_SIGNAL: i32 = 37;
 
 /// Watch `0x3ff` IO port to record the magic value trapped from guest kernel.
 #[cfg(all(target_arch = "x86_64", feature = "boot_time"))]
@@ -125,7 +149,7 @@ pub trait CPUInterface {
         &self,
         boot: &CPUBootConfig,
         topology: &CPUTopology,
-        #[cfg(target_arch = "aarch64")] features: &CPUFeatures,
+        features: &CPUFeatures,
     ) -> Result<()>;
 
     /// Start `CPU` thread and run virtual CPU in kvm.
@@ -160,10 +184,93 @@ pub trait CPUInterface {
     /// Make `CPU` destroy because of guest inner reset.
     fn guest_reset(&self) -> Result<()>;
 
+    /// Reset or shutdown the VM by the reboot action because of guest inner reboot.
+    fn guest_reboot(&self) -> Result<()>;
+
     /// Handle vcpu event from `kvm`.
     fn kvm_vcpu_exec(&self) -> Result<bool>;
 }
 
+/// Counters of the kvm exits of one VCPU.
+struct VcpuExitMetrics {
+    io: Arc<Metric>,
+    mmio: Arc<Metric>,
+    halt: Arc<Metric>,
+    system_event: Arc<Metric>,
+    debug: Arc<Metric>,
+    signal: Arc<Metric>,
+    other: Arc<Metric>,
+}
+
+impl VcpuExitMetrics {
+    fn new(id: u8) -> Self {
+        let cpu = id.to_string();
+        let register = |reason: &str| {
+            register_metric(
+                "stratovirt_vcpu_exits_total",
+                "Number of kvm exits of the vcpu.",
+                MetricType::Counter,
+                &[("cpu", &cpu), ("reason", reason)],
+            )
+        };
+        VcpuExitMetrics {
+            io: register("io"),
+            mmio: register("mmio"),
+            halt: register("halt"),
+            system_event: register("system_event"),
+            debug: register("debug"),
+            signal: register("signal"),
+            other: register("other"),
+        }
+    }
+
+    /// The exit counters named as the kvm statistics.
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        let counters = vec![
+            ("io_exits", self.io.get()),
+            ("mmio_exits", self.mmio.get()),
+            ("halt_exits", self.halt.get()),
+            ("system_event_exits", self.system_event.get()),
+            ("debug_exits", self.debug.get()),
+            ("signal_exits", self.signal.get()),
+            ("other_exits", self.other.get()),
+        ];
+        let total = counters.iter().map(|(_, value)| value).sum();
+        let mut stats = vec![("exits", total)];
+        stats.extend(counters);
+        stats
+    }
+}
+
+/// The statistics kept by kvm for each VCPU, exposed in debugfs.
+const KVM_DEBUGFS_VCPU_STATS: [&str; 2] = ["halt_successful_poll", "halt_attempted_poll"];
+
+/// Get the time in nanoseconds the thread has spent waiting on the host runqueue,
+/// which is the time stolen from the VCPU running in this thread.
+fn thread_run_delay(tid: u64) -> Option<u64> {
+    let schedstat = std::fs::read_to_string(format!("/proc/self/task/{}/schedstat", tid)).ok()?;
+    schedstat.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Read the statistic of the VCPU from kvm debugfs, `None` if debugfs is not mounted
+/// or not accessible.
+fn kvm_debugfs_vcpu_stat(vcpu_id: u8, name: &str) -> Option<u64> {
+    let vm_fd = KVM_FDS.load().vm_fd.as_ref()?.as_raw_fd();
+    let path = format!(
+        "/sys/kernel/debug/kvm/{}-{}/vcpu{}/{}",
+        std::process::id(),
+        vm_fd,
+        vcpu_id,
+        name
+    );
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Acknowledge the interrupt requested by the PIC emulated in userspace, and return
+/// its vector. `None` if the request has been withdrawn.
+#[cfg(target_arch = "x86_64")]
+pub type ExtIntHandler = Box<dyn Fn() -> Option<u8> + Send + Sync>;
+
 /// `CPU` is a wrapper around creating and using a kvm-based VCPU.
 #[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
@@ -187,6 +294,23 @@ pub struct CPU {
     boot_state: Arc<Mutex<ArchCPU>>,
     /// Sync the pause state of vCPU in kvm and userspace.
     pause_signal: Arc<AtomicBool>,
+    /// Notify the debugger that vCPU stops for guest debug, `None` if guest debug is disabled.
+    debug_evt: Arc<Mutex<Option<Arc<EventFd>>>>,
+    /// Whether vCPU stops at a breakpoint or after a single step.
+    debug_stopped: Arc<AtomicBool>,
+    /// Counters of the kvm exits.
+    exit_metrics: Arc<VcpuExitMetrics>,
+    /// Time in nanoseconds which vCPU is forced to sleep for throttling.
+    throttle_ns: Arc<AtomicU64>,
+    /// Level of the interrupt output of the userspace PIC connected to this vCPU.
+    #[cfg(target_arch = "x86_64")]
+    extint_pending: Arc<AtomicBool>,
+    /// Acknowledge the interrupt of the userspace PIC.
+    #[cfg(target_arch = "x86_64")]
+    extint_handler: Arc<Mutex<Option<ExtIntHandler>>>,
+    /// The `kvm_run` to request the interrupt window for the userspace PIC.
+    #[cfg(target_arch = "x86_64")]
+    kvm_run: Arc<Mutex<Option<KvmRun>>>,
 }
 
 impl CPU {
@@ -215,6 +339,16 @@ impl CPU {
             caps: CPUCaps::init_capabilities(),
             boot_state: Arc::new(Mutex::new(ArchCPU::default())),
             pause_signal: Arc::new(AtomicBool::new(false)),
+            debug_evt: Arc::new(Mutex::new(None)),
+            debug_stopped: Arc::new(AtomicBool::new(false)),
+            exit_metrics: Arc::new(VcpuExitMetrics::new(id)),
+            throttle_ns: Arc::new(AtomicU64::new(0)),
+            #[cfg(target_arch = "x86_64")]
+            extint_pending: Arc::new(AtomicBool::new(false)),
+            #[cfg(target_arch = "x86_64")]
+            extint_handler: Arc::new(Mutex::new(None)),
+            #[cfg(target_arch = "x86_64")]
+            kvm_run: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -254,10 +388,181 @@ impl CPU {
         (*self.tid.lock().unwrap()).unwrap_or(0)
     }
 
+    /// Get the runtime statistics of this `CPU`: the exits to userspace by reason,
+    /// the steal time in nanoseconds, and the halt polling statistics if kvm debugfs
+    /// is available.
+    pub fn stats(&self) -> Vec<qmp_schema::Stats> {
+        let mut stats = self.exit_metrics.stats();
+        if let Some(steal_time) = (*self.tid.lock().unwrap()).and_then(thread_run_delay) {
+            stats.push(("steal_time_ns", steal_time));
+        }
+        for name in KVM_DEBUGFS_VCPU_STATS {
+            if let Some(value) = kvm_debugfs_vcpu_stat(self.id, name) {
+                stats.push((name, value));
+            }
+        }
+        stats
+            .into_iter()
+            .map(|(name, value)| qmp_schema::Stats {
+                name: name.to_string(),
+                value,
+            })
+            .collect()
+    }
+
     /// Set thread id for `CPU`.
     fn set_tid(&self) {
         *self.tid.lock().unwrap() = Some(util::unix::gettid());
     }
+
+    /// Enable or disable guest debug for `CPU`.
+    ///
+    /// # Arguments
+    ///
+    /// * `debug_evt` - Notified when `CPU` stops for guest debug, `None` to disable guest debug.
+    /// * `single_step` - Stop `CPU` after executing one instruction.
+    pub fn set_guest_debug(
+        &self,
+        debug_evt: Option<Arc<EventFd>>,
+        single_step: bool,
+    ) -> Result<()> {
+        let mut control = 0;
+        if debug_evt.is_some() {
+            control = KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP;
+            if single_step {
+                control |= KVM_GUESTDBG_SINGLESTEP;
+            }
+        }
+        let debug = kvm_guest_debug {
+            control,
+            pad: 0,
+            arch: Default::default(),
+        };
+        self.fd
+            .set_guest_debug(&debug)
+            .with_context(|| format!("Failed to set guest debug for vcpu{}", self.id))?;
+        *self.debug_evt.lock().unwrap() = debug_evt;
+        Ok(())
+    }
+
+    /// Check whether `CPU` stopped for guest debug, and clear the flag.
+    pub fn take_debug_stop(&self) -> bool {
+        self.debug_stopped.swap(false, Ordering::SeqCst)
+    }
+
+    /// Force `CPU` to exit kvm emulation and sleep for `sleep`, it's woken up early if
+    /// `CPU` is paused or destroyed.
+    pub fn throttle(&self, sleep: Duration) {
+        self.throttle_ns
+            .store(sleep.as_nanos() as u64, Ordering::SeqCst);
+        if let Some(thread) = self.task.lock().unwrap().as_ref() {
+            if let Err(e) = thread.kill(VCPU_THROTTLE_SIGNAL) {
+                error!("Failed to throttle vcpu{}: {:?}", self.id, e);
+            }
+        }
+    }
+
+    /// Connect the interrupt output of the PIC emulated in userspace to this `CPU`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_extint_handler(&self, handler: ExtIntHandler) -> Result<()> {
+        *self.kvm_run.lock().unwrap() = Some(KvmRun::new(&self.fd)?);
+        *self.extint_handler.lock().unwrap() = Some(handler);
+        Ok(())
+    }
+
+    /// Set the level of the external interrupt from the userspace PIC. `CPU` is forced
+    /// to exit kvm emulation to request the interrupt window.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_extint_level(&self, level: bool) {
+        self.extint_pending.store(level, Ordering::SeqCst);
+        if !level {
+            return;
+        }
+        if let Some(thread) = self.task.lock().unwrap().as_ref() {
+            // vCPU only exits kvm for the throttling signal, it doesn't sleep without throttling.
+            if let Err(e) = thread.kill(VCPU_THROTTLE_SIGNAL) {
+                error!(
+                    "Failed to kick vcpu{} for external interrupt: {:?}",
+                    self.id, e
+                );
+            }
+        }
+    }
+
+    /// Inject the external interrupt of the userspace PIC before entering kvm. Like QEMU,
+    /// the PIC is only acknowledged when kvm is ready for the injection, otherwise the
+    /// interrupt window is requested, and kvm exits once the guest is able to take it.
+    #[cfg(target_arch = "x86_64")]
+    fn inject_extint(&self) {
+        let kvm_run = self.kvm_run.lock().unwrap();
+        let run = match kvm_run.as_ref() {
+            Some(run) => run.get(),
+            None => return,
+        };
+
+        if self.extint_pending.load(Ordering::SeqCst) && run.ready_for_interrupt_injection != 0 {
+            let vector = match self.extint_handler.lock().unwrap().as_ref() {
+                Some(handler) => handler(),
+                None => None,
+            };
+            if let Some(irq) = vector {
+                let interrupt = kvm_interrupt {
+                    irq: u32::from(irq),
+                };
+                // SAFETY: the kvm_interrupt is well defined, and the return value is checked.
+                let ret = unsafe { ioctl_with_ref(self.fd.as_ref(), KVM_INTERRUPT(), &interrupt) };
+                if ret < 0 {
+                    error!(
+                        "Failed to inject external interrupt 0x{:x} to vcpu{}: {}",
+                        irq,
+                        self.id,
+                        std::io::Error::last_os_error()
+                    );
+                }
+            }
+        }
+        run.request_interrupt_window = u8::from(self.extint_pending.load(Ordering::SeqCst));
+    }
+
+    /// Sleep for the pending throttling while `CPU` is running.
+    fn throttle_sleep(&self) {
+        let sleep = self.throttle_ns.swap(0, Ordering::SeqCst);
+        if sleep == 0 {
+            return;
+        }
+        let (cpu_state, cvar) = &*self.state;
+        let cpu_state = cpu_state.lock().unwrap();
+        let _ = cvar
+            .wait_timeout_while(cpu_state, Duration::from_nanos(sleep), |state| {
+                *state == CpuLifecycleState::Running
+            })
+            .unwrap();
+    }
+
+    /// `CPU` stops at a breakpoint or after a single step, keep it paused and notify the debugger.
+    fn guest_debug_stop(&self) {
+        let debug_evt = self.debug_evt.lock().unwrap().clone();
+        let debug_evt = match debug_evt {
+            Some(evt) => evt,
+            None => return,
+        };
+
+        let (cpu_state, _) = &*self.state;
+        let mut cpu_state = cpu_state.lock().unwrap();
+        if *cpu_state == CpuLifecycleState::Running {
+            *cpu_state = CpuLifecycleState::Paused;
+            self.pause_signal.store(true, Ordering::SeqCst);
+        }
+        drop(cpu_state);
+
+        self.debug_stopped.store(true, Ordering::SeqCst);
+        if let Err(e) = debug_evt.write(1) {
+            error!(
+                "Failed to notify guest debug stop of vcpu{}: {:?}",
+                self.id, e
+            );
+        }
+    }
 }
 
 impl CPUInterface for CPU {
@@ -265,7 +570,7 @@ impl CPUInterface for CPU {
         &self,
         boot: &CPUBootConfig,
         topology: &CPUTopology,
-        #[cfg(target_arch = "aarch64")] config: &CPUFeatures,
+        config: &CPUFeatures,
     ) -> Result<()> {
         trace_cpu_boot_config(boot);
         let (cpu_state, _) = &*self.state;
@@ -279,12 +584,7 @@ impl CPUInterface for CPU {
         self.arch_cpu
             .lock()
             .unwrap()
-            .set_boot_config(
-                &self.fd,
-                boot,
-                #[cfg(target_arch = "aarch64")]
-                config,
-            )
+            .set_boot_config(&self.fd, boot, config)
             .with_context(|| "Failed to realize arch cpu")?;
 
         self.arch_cpu
@@ -416,6 +716,8 @@ impl CPUInterface for CPU {
         let mut cpu_state = cpu_state.lock().unwrap();
         if *cpu_state == CpuLifecycleState::Running {
             *cpu_state = CpuLifecycleState::Stopping;
+            // Wake up vCPU if it sleeps for throttling.
+            cvar.notify_one();
         } else if *cpu_state == CpuLifecycleState::Stopped
             || *cpu_state == CpuLifecycleState::Paused
         {
@@ -458,7 +760,7 @@ impl CPUInterface for CPU {
         if QmpChannel::is_connected() {
             let shutdown_msg = qmp_schema::Shutdown {
                 guest: true,
-                reason: "guest-shutdown".to_string(),
+                reason: ShutdownCause::GuestShutdown.reason().to_string(),
             };
             event!(Shutdown; shutdown_msg);
         }
@@ -466,8 +768,21 @@ impl CPUInterface for CPU {
         Ok(())
     }
 
+    fn guest_reboot(&self) -> Result<()> {
+        let vm = self
+            .vm
+            .upgrade()
+            .with_context(|| CpuError::NoMachineInterface)?;
+        let reboot_act = vm.lock().unwrap().get_reboot_action();
+        match reboot_act {
+            RebootAction::Reset => self.guest_reset(),
+            RebootAction::Shutdown => self.guest_shutdown(),
+        }
+    }
+
     fn guest_reset(&self) -> Result<()> {
         if let Some(vm) = self.vm.upgrade() {
+            request_shutdown_cause(ShutdownCause::GuestReset);
             vm.lock().unwrap().reset();
         } else {
             return Err(anyhow!(CpuError::NoMachineInterface));
@@ -482,42 +797,62 @@ impl CPUInterface for CPU {
             .upgrade()
             .with_context(|| CpuError::NoMachineInterface)?;
 
+        #[cfg(target_arch = "x86_64")]
+        self.inject_extint();
         match self.fd.run() {
             Ok(run) => match run {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoIn(addr, data) => {
+                    self.exit_metrics.io.inc();
                     vm.lock().unwrap().pio_in(u64::from(addr), data);
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoOut(addr, data) => {
+                    self.exit_metrics.io.inc();
                     #[cfg(feature = "boot_time")]
                     capture_boot_signal(addr as u64, data);
 
                     vm.lock().unwrap().pio_out(u64::from(addr), data);
                 }
                 VcpuExit::MmioRead(addr, data) => {
+                    self.exit_metrics.mmio.inc();
                     vm.lock().unwrap().mmio_read(addr, data);
                 }
                 VcpuExit::MmioWrite(addr, data) => {
+                    self.exit_metrics.mmio.inc();
                     #[cfg(all(target_arch = "aarch64", feature = "boot_time"))]
                     capture_boot_signal(addr, data);
 
                     vm.lock().unwrap().mmio_write(addr, data);
                 }
                 #[cfg(target_arch = "x86_64")]
+                VcpuExit::IoapicEoi(vector) => {
+                    self.exit_metrics.other.inc();
+                    vm.lock().unwrap().ioapic_eoi(vector);
+                }
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::IrqWindowOpen => {
+                    // The pending external interrupt is injected before next entry.
+                    self.exit_metrics.other.inc();
+                }
+                #[cfg(target_arch = "x86_64")]
                 VcpuExit::Hlt => {
+                    self.exit_metrics.halt.inc();
                     info!("Vcpu{} received KVM_EXIT_HLT signal", self.id());
                     return Err(anyhow!(CpuError::VcpuHltEvent(self.id())));
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::Shutdown => {
+                    self.exit_metrics.other.inc();
                     info!("Vcpu{} received an KVM_EXIT_SHUTDOWN signal", self.id());
-                    self.guest_shutdown()?;
+                    // KVM_EXIT_SHUTDOWN is caused by triple fault, which is a reboot request.
+                    self.guest_reboot()?;
 
                     return Ok(false);
                 }
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event, flags) => {
+                    self.exit_metrics.system_event.inc();
                     if event == kvm_bindings::KVM_SYSTEM_EVENT_SHUTDOWN {
                         info!(
                             "Vcpu{} received an KVM_SYSTEM_EVENT_SHUTDOWN signal",
@@ -531,7 +866,7 @@ impl CPUInterface for CPU {
                             "Vcpu{} received an KVM_SYSTEM_EVENT_RESET signal",
                             self.id()
                         );
-                        self.guest_reset()
+                        self.guest_reboot()
                             .with_context(|| "Some error occurred in guest reset")?;
                         return Ok(true);
                     } else {
@@ -544,7 +879,12 @@ impl CPUInterface for CPU {
                     }
                     return Ok(false);
                 }
+                VcpuExit::Debug(_) => {
+                    self.exit_metrics.debug.inc();
+                    self.guest_debug_stop();
+                }
                 VcpuExit::FailEntry(reason, cpuid) => {
+                    self.exit_metrics.other.inc();
                     info!(
                         "Vcpu{} received KVM_EXIT_FAIL_ENTRY signal. the vcpu could not be run due to unknown reasons({})",
                         cpuid, reason
@@ -552,10 +892,12 @@ impl CPUInterface for CPU {
                     return Ok(false);
                 }
                 VcpuExit::InternalError => {
+                    self.exit_metrics.other.inc();
                     info!("Vcpu{} received KVM_EXIT_INTERNAL_ERROR signal", self.id());
                     return Ok(false);
                 }
                 r => {
+                    self.exit_metrics.other.inc();
                     return Err(anyhow!(CpuError::VcpuExitReason(
                         self.id(),
                         format!("{:?}", r)
@@ -566,6 +908,7 @@ impl CPUInterface for CPU {
                 match e.errno() {
                     libc::EAGAIN => {}
                     libc::EINTR => {
+                        self.exit_metrics.signal.inc();
                         self.fd.set_kvm_immediate_exit(0);
                     }
                     _ => {
@@ -627,6 +970,11 @@ impl CPUThreadWorker {
                         fence(Ordering::Release)
                     });
                 }
+                VCPU_THROTTLE_SIGNAL => {
+                    let _ = CPUThreadWorker::run_on_local_thread_vcpu(|vcpu| {
+                        vcpu.fd().set_kvm_immediate_exit(1);
+                    });
+                }
                 VCPU_RESET_SIGNAL => {
                     let _ = CPUThreadWorker::run_on_local_thread_vcpu(|vcpu| {
                         if let Err(e) = vcpu.arch_cpu.lock().unwrap().reset_vcpu(
@@ -646,6 +994,8 @@ impl CPUThreadWorker {
             .with_context(|| "Failed to register VCPU_TASK_SIGNAL signal.")?;
         register_signal_handler(VCPU_RESET_SIGNAL, handle_signal)
             .with_context(|| "Failed to register VCPU_TASK_SIGNAL signal.")?;
+        register_signal_handler(VCPU_THROTTLE_SIGNAL, handle_signal)
+            .with_context(|| "Failed to register VCPU_THROTTLE_SIGNAL signal.")?;
 
         Ok(())
     }
@@ -688,6 +1038,12 @@ impl CPUThreadWorker {
         }
 
         self.thread_cpu.set_tid();
+        if let Err(e) = join_thread_cgroup(CgroupThread::Vcpu) {
+            error!(
+                "Failed to join cgroup for cpu{}: {:?}",
+                self.thread_cpu.id, e
+            );
+        }
 
         // The vcpu thread is going to run,
         // reset its running environment.
@@ -727,6 +1083,7 @@ impl CPUThreadWorker {
             {
                 thread::sleep(Duration::from_millis(5));
             }
+            self.thread_cpu.throttle_sleep();
         }
 
         // The vcpu thread is about to exit, marking the state
@@ -739,6 +1096,25 @@ impl CPUThreadWorker {
     }
 }
 
+/// Throttle all vCPUs of the VM.
+pub struct CpuThrottler {
+    cpus: Vec<Arc<CPU>>,
+}
+
+impl CpuThrottler {
+    pub fn new(cpus: Vec<Arc<CPU>>) -> Self {
+        CpuThrottler { cpus }
+    }
+}
+
+impl CpuThrottleOps for CpuThrottler {
+    fn throttle(&self, sleep: Duration) {
+        for cpu in self.cpus.iter() {
+            cpu.throttle(sleep);
+        }
+    }
+}
+
 /// The wrapper for topology for VCPU.
 #[derive(Clone)]
 pub struct CpuTopology {
@@ -1091,4 +1467,22 @@ mod tests {
         assert_eq!(test_cpu_topo.get_topo_item(29), (3, 0, 0, 2, 1));
         assert_eq!(test_cpu_topo.get_topo_item(31), (3, 0, 0, 3, 1));
     }
+
+    #[test]
+    fn test_vcpu_exit_stats() {
+        let metrics = VcpuExitMetrics::new(200);
+        metrics.io.add(3);
+        metrics.mmio.add(2);
+        metrics.signal.inc();
+
+        let stats = metrics.stats();
+        assert_eq!(stats[0], ("exits", 6));
+        assert!(stats.contains(&("io_exits", 3)));
+        assert!(stats.contains(&("mmio_exits", 2)));
+        assert!(stats.contains(&("signal_exits", 1)));
+        assert!(stats.contains(&("halt_exits", 0)));
+
+        let tid = util::unix::gettid();
+        assert!(thread_run_delay(tid).is_some());
+    }
 }
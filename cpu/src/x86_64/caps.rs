@@ -10,10 +10,15 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use anyhow::{anyhow, Result};
 use kvm_bindings::{kvm_msr_entry, Msrs};
 use kvm_ioctls::{Cap, Kvm};
 use vmm_sys_util::fam::Error;
 
+use super::cpu_model::{find_feature, find_model, FEATURE_WORDS_NUM};
+use crate::CpuError;
+use machine_manager::config::CpuConfig;
+
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/arch/x86/include/asm/msr-index.h#L558
 const MSR_IA32_MISC_ENABLE: ::std::os::raw::c_uint = 0x1a0;
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/arch/x86/include/asm/msr-index.h#L597
@@ -69,3 +74,44 @@ impl X86CPUCaps {
         Msrs::from_entries(&entry_vec)
     }
 }
+
+/// CPU model and features of x86 vcpu.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct X86CPUFeatures {
+    /// Index of the CPU model in `X86_CPU_MODELS`, 0 means passing through the host CPU.
+    pub model: u32,
+    /// Features enabled explicitly, indexed by feature word.
+    pub enabled: [u32; FEATURE_WORDS_NUM],
+    /// Features disabled explicitly, indexed by feature word.
+    pub disabled: [u32; FEATURE_WORDS_NUM],
+}
+
+impl TryFrom<&CpuConfig> for X86CPUFeatures {
+    type Error = anyhow::Error;
+
+    fn try_from(conf: &CpuConfig) -> Result<Self> {
+        let mut features = X86CPUFeatures::default();
+        if let Some(name) = &conf.model {
+            features.model = find_model(name).ok_or_else(|| {
+                anyhow!(CpuError::RealizeVcpu(format!("Unknown CPU model {}", name)))
+            })? as u32;
+        }
+        for (name, enabled) in &conf.features {
+            let (word, bit) = find_feature(name).ok_or_else(|| {
+                anyhow!(CpuError::RealizeVcpu(format!(
+                    "Unknown CPU feature {}",
+                    name
+                )))
+            })?;
+            if *enabled {
+                features.enabled[word] |= 1 << bit;
+                features.disabled[word] &= !(1 << bit);
+            } else {
+                features.disabled[word] |= 1 << bit;
+                features.enabled[word] &= !(1 << bit);
+            }
+        }
+        Ok(features)
+    }
+}
@@ -0,0 +1,513 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use kvm_bindings::kvm_cpuid_entry2;
+
+/// Register of a CPUID leaf.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CpuidReg {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// The CPUID registers which contain feature flags.
+/// See: Intel SDM Vol.2A, CPUID—CPU Identification.
+pub const FEATURE_WORDS: &[(u32, u32, CpuidReg)] = &[
+    (1, 0, CpuidReg::Edx),
+    (1, 0, CpuidReg::Ecx),
+    (7, 0, CpuidReg::Ebx),
+    (7, 0, CpuidReg::Ecx),
+    (7, 0, CpuidReg::Edx),
+    (0x8000_0001, 0, CpuidReg::Edx),
+    (0x8000_0001, 0, CpuidReg::Ecx),
+];
+pub const FEATURE_WORDS_NUM: usize = 7;
+
+const FEAT_1_EDX: usize = 0;
+const FEAT_1_ECX: usize = 1;
+const FEAT_7_0_EBX: usize = 2;
+const FEAT_7_0_ECX: usize = 3;
+const FEAT_7_0_EDX: usize = 4;
+const FEAT_8000_0001_EDX: usize = 5;
+const FEAT_8000_0001_ECX: usize = 6;
+
+/// Name, feature word and bit of the CPU features, which use the same names as Linux.
+pub const CPU_FEATURES: &[(&str, usize, u32)] = &[
+    ("fpu", FEAT_1_EDX, 0),
+    ("vme", FEAT_1_EDX, 1),
+    ("de", FEAT_1_EDX, 2),
+    ("pse", FEAT_1_EDX, 3),
+    ("tsc", FEAT_1_EDX, 4),
+    ("msr", FEAT_1_EDX, 5),
+    ("pae", FEAT_1_EDX, 6),
+    ("mce", FEAT_1_EDX, 7),
+    ("cx8", FEAT_1_EDX, 8),
+    ("apic", FEAT_1_EDX, 9),
+    ("sep", FEAT_1_EDX, 11),
+    ("mtrr", FEAT_1_EDX, 12),
+    ("pge", FEAT_1_EDX, 13),
+    ("mca", FEAT_1_EDX, 14),
+    ("cmov", FEAT_1_EDX, 15),
+    ("pat", FEAT_1_EDX, 16),
+    ("pse36", FEAT_1_EDX, 17),
+    ("clflush", FEAT_1_EDX, 19),
+    ("mmx", FEAT_1_EDX, 23),
+    ("fxsr", FEAT_1_EDX, 24),
+    ("sse", FEAT_1_EDX, 25),
+    ("sse2", FEAT_1_EDX, 26),
+    ("ss", FEAT_1_EDX, 27),
+    ("ht", FEAT_1_EDX, 28),
+    ("sse3", FEAT_1_ECX, 0),
+    ("pclmulqdq", FEAT_1_ECX, 1),
+    ("monitor", FEAT_1_ECX, 3),
+    ("vmx", FEAT_1_ECX, 5),
+    ("ssse3", FEAT_1_ECX, 9),
+    ("fma", FEAT_1_ECX, 12),
+    ("cx16", FEAT_1_ECX, 13),
+    ("pdcm", FEAT_1_ECX, 15),
+    ("pcid", FEAT_1_ECX, 17),
+    ("sse4.1", FEAT_1_ECX, 19),
+    ("sse4.2", FEAT_1_ECX, 20),
+    ("x2apic", FEAT_1_ECX, 21),
+    ("movbe", FEAT_1_ECX, 22),
+    ("popcnt", FEAT_1_ECX, 23),
+    ("tsc-deadline", FEAT_1_ECX, 24),
+    ("aes", FEAT_1_ECX, 25),
+    ("xsave", FEAT_1_ECX, 26),
+    ("avx", FEAT_1_ECX, 28),
+    ("f16c", FEAT_1_ECX, 29),
+    ("rdrand", FEAT_1_ECX, 30),
+    ("hypervisor", FEAT_1_ECX, 31),
+    ("fsgsbase", FEAT_7_0_EBX, 0),
+    ("tsc-adjust", FEAT_7_0_EBX, 1),
+    ("bmi1", FEAT_7_0_EBX, 3),
+    ("hle", FEAT_7_0_EBX, 4),
+    ("avx2", FEAT_7_0_EBX, 5),
+    ("smep", FEAT_7_0_EBX, 7),
+    ("bmi2", FEAT_7_0_EBX, 8),
+    ("erms", FEAT_7_0_EBX, 9),
+    ("invpcid", FEAT_7_0_EBX, 10),
+    ("rtm", FEAT_7_0_EBX, 11),
+    ("mpx", FEAT_7_0_EBX, 14),
+    ("avx512f", FEAT_7_0_EBX, 16),
+    ("avx512dq", FEAT_7_0_EBX, 17),
+    ("rdseed", FEAT_7_0_EBX, 18),
+    ("adx", FEAT_7_0_EBX, 19),
+    ("smap", FEAT_7_0_EBX, 20),
+    ("avx512ifma", FEAT_7_0_EBX, 21),
+    ("clflushopt", FEAT_7_0_EBX, 23),
+    ("clwb", FEAT_7_0_EBX, 24),
+    ("avx512pf", FEAT_7_0_EBX, 26),
+    ("avx512er", FEAT_7_0_EBX, 27),
+    ("avx512cd", FEAT_7_0_EBX, 28),
+    ("sha-ni", FEAT_7_0_EBX, 29),
+    ("avx512bw", FEAT_7_0_EBX, 30),
+    ("avx512vl", FEAT_7_0_EBX, 31),
+    ("avx512vbmi", FEAT_7_0_ECX, 1),
+    ("umip", FEAT_7_0_ECX, 2),
+    ("pku", FEAT_7_0_ECX, 3),
+    ("avx512vbmi2", FEAT_7_0_ECX, 6),
+    ("gfni", FEAT_7_0_ECX, 8),
+    ("vaes", FEAT_7_0_ECX, 9),
+    ("vpclmulqdq", FEAT_7_0_ECX, 10),
+    ("avx512vnni", FEAT_7_0_ECX, 11),
+    ("avx512bitalg", FEAT_7_0_ECX, 12),
+    ("avx512-vpopcntdq", FEAT_7_0_ECX, 14),
+    ("la57", FEAT_7_0_ECX, 16),
+    ("rdpid", FEAT_7_0_ECX, 22),
+    ("avx512-4vnniw", FEAT_7_0_EDX, 2),
+    ("avx512-4fmaps", FEAT_7_0_EDX, 3),
+    ("md-clear", FEAT_7_0_EDX, 10),
+    ("serialize", FEAT_7_0_EDX, 14),
+    ("avx512-fp16", FEAT_7_0_EDX, 23),
+    ("spec-ctrl", FEAT_7_0_EDX, 26),
+    ("stibp", FEAT_7_0_EDX, 27),
+    ("arch-capabilities", FEAT_7_0_EDX, 29),
+    ("ssbd", FEAT_7_0_EDX, 31),
+    ("syscall", FEAT_8000_0001_EDX, 11),
+    ("nx", FEAT_8000_0001_EDX, 20),
+    ("mmxext", FEAT_8000_0001_EDX, 22),
+    ("fxsr-opt", FEAT_8000_0001_EDX, 25),
+    ("pdpe1gb", FEAT_8000_0001_EDX, 26),
+    ("rdtscp", FEAT_8000_0001_EDX, 27),
+    ("lm", FEAT_8000_0001_EDX, 29),
+    ("lahf-lm", FEAT_8000_0001_ECX, 0),
+    ("svm", FEAT_8000_0001_ECX, 2),
+    ("abm", FEAT_8000_0001_ECX, 5),
+    ("sse4a", FEAT_8000_0001_ECX, 6),
+    ("misalignsse", FEAT_8000_0001_ECX, 7),
+    ("3dnowprefetch", FEAT_8000_0001_ECX, 8),
+    ("xop", FEAT_8000_0001_ECX, 11),
+    ("fma4", FEAT_8000_0001_ECX, 16),
+    ("tbm", FEAT_8000_0001_ECX, 21),
+    ("topoext", FEAT_8000_0001_ECX, 22),
+];
+
+/// Features which are always managed by StratoVirt, no matter which CPU model is used.
+const MANAGED_FEATURES: &[&str] = &["hypervisor", "tsc-deadline"];
+
+const BASE_FEATURES: &[&str] = &[
+    "fpu", "de", "pse", "tsc", "msr", "pae", "mce", "cx8", "apic", "sep", "mtrr", "pge", "mca",
+    "cmov", "pat", "pse36", "clflush", "mmx", "fxsr", "sse", "sse2", "sse3", "cx16", "syscall",
+    "nx", "lm", "lahf-lm",
+];
+const NEHALEM_FEATURES: &[&str] = &["ssse3", "sse4.1", "sse4.2", "popcnt"];
+const WESTMERE_FEATURES: &[&str] = &["pclmulqdq", "aes"];
+const SANDYBRIDGE_FEATURES: &[&str] = &["x2apic", "xsave", "avx", "rdtscp"];
+const IVYBRIDGE_FEATURES: &[&str] = &["f16c", "rdrand", "fsgsbase", "smep", "erms"];
+const HASWELL_FEATURES: &[&str] = &[
+    "fma", "movbe", "pcid", "invpcid", "bmi1", "bmi2", "avx2", "abm",
+];
+const BROADWELL_FEATURES: &[&str] = &["rdseed", "adx", "smap", "3dnowprefetch"];
+const SKYLAKE_FEATURES: &[&str] = &["clflushopt"];
+const SKYLAKE_SERVER_FEATURES: &[&str] = &[
+    "clwb", "avx512f", "avx512dq", "avx512cd", "avx512bw", "avx512vl", "pdpe1gb",
+];
+const CASCADELAKE_FEATURES: &[&str] = &["avx512vnni"];
+const ICELAKE_FEATURES: &[&str] = &[
+    "avx512vbmi",
+    "umip",
+    "pku",
+    "avx512vbmi2",
+    "gfni",
+    "vaes",
+    "vpclmulqdq",
+    "avx512bitalg",
+    "avx512-vpopcntdq",
+    "rdpid",
+];
+const EPYC_FEATURES: &[&str] = &[
+    "ssse3",
+    "sse4.1",
+    "sse4.2",
+    "popcnt",
+    "pclmulqdq",
+    "aes",
+    "xsave",
+    "avx",
+    "f16c",
+    "rdrand",
+    "fma",
+    "movbe",
+    "bmi1",
+    "bmi2",
+    "avx2",
+    "fsgsbase",
+    "smep",
+    "rdseed",
+    "adx",
+    "smap",
+    "clflushopt",
+    "sha-ni",
+    "rdtscp",
+    "pdpe1gb",
+    "mmxext",
+    "fxsr-opt",
+    "abm",
+    "sse4a",
+    "misalignsse",
+    "3dnowprefetch",
+];
+
+/// Named CPU model.
+pub struct X86CPUModel {
+    pub name: &'static str,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    /// Processor brand string.
+    pub model_id: &'static str,
+    /// Groups of features supported by this model.
+    pub features: &'static [&'static [&'static str]],
+}
+
+/// Supported CPU models, the first one is the host passthrough model.
+pub const X86_CPU_MODELS: &[X86CPUModel] = &[
+    X86CPUModel {
+        name: "host",
+        family: 0,
+        model: 0,
+        stepping: 0,
+        model_id: "",
+        features: &[],
+    },
+    X86CPUModel {
+        name: "Nehalem",
+        family: 6,
+        model: 26,
+        stepping: 3,
+        model_id: "Intel Core i7 9xx (Nehalem Class Core i7)",
+        features: &[BASE_FEATURES, NEHALEM_FEATURES],
+    },
+    X86CPUModel {
+        name: "Westmere",
+        family: 6,
+        model: 44,
+        stepping: 1,
+        model_id: "Westmere E56xx/L56xx/X56xx (Nehalem-C)",
+        features: &[BASE_FEATURES, NEHALEM_FEATURES, WESTMERE_FEATURES],
+    },
+    X86CPUModel {
+        name: "SandyBridge",
+        family: 6,
+        model: 42,
+        stepping: 1,
+        model_id: "Intel Xeon E312xx (Sandy Bridge)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "IvyBridge",
+        family: 6,
+        model: 58,
+        stepping: 9,
+        model_id: "Intel Xeon E3-12xx v2 (Ivy Bridge)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "Haswell",
+        family: 6,
+        model: 60,
+        stepping: 4,
+        model_id: "Intel Core Processor (Haswell, no TSX)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+            HASWELL_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "Broadwell",
+        family: 6,
+        model: 61,
+        stepping: 2,
+        model_id: "Intel Core Processor (Broadwell, no TSX)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "Skylake-Client",
+        family: 6,
+        model: 94,
+        stepping: 3,
+        model_id: "Intel Core Processor (Skylake, no TSX)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "Skylake-Server",
+        family: 6,
+        model: 85,
+        stepping: 4,
+        model_id: "Intel Xeon Processor (Skylake, no TSX)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_FEATURES,
+            SKYLAKE_SERVER_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "Cascadelake-Server",
+        family: 6,
+        model: 85,
+        stepping: 6,
+        model_id: "Intel Xeon Processor (Cascadelake, no TSX)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_FEATURES,
+            SKYLAKE_SERVER_FEATURES,
+            CASCADELAKE_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "Icelake-Server",
+        family: 6,
+        model: 106,
+        stepping: 0,
+        model_id: "Intel Xeon Processor (Icelake, no TSX)",
+        features: &[
+            BASE_FEATURES,
+            NEHALEM_FEATURES,
+            WESTMERE_FEATURES,
+            SANDYBRIDGE_FEATURES,
+            IVYBRIDGE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_FEATURES,
+            SKYLAKE_SERVER_FEATURES,
+            CASCADELAKE_FEATURES,
+            ICELAKE_FEATURES,
+        ],
+    },
+    X86CPUModel {
+        name: "EPYC",
+        family: 23,
+        model: 1,
+        stepping: 2,
+        model_id: "AMD EPYC Processor",
+        features: &[BASE_FEATURES, EPYC_FEATURES],
+    },
+];
+
+/// Find the feature word index and bit of the feature.
+pub fn find_feature(name: &str) -> Option<(usize, u32)> {
+    CPU_FEATURES
+        .iter()
+        .find(|(feat, _, _)| *feat == name)
+        .map(|(_, word, bit)| (*word, *bit))
+}
+
+/// Find the index of CPU model in `X86_CPU_MODELS`.
+pub fn find_model(name: &str) -> Option<usize> {
+    X86_CPU_MODELS.iter().position(|model| model.name == name)
+}
+
+impl X86CPUModel {
+    /// Get the feature words which are supported by this model.
+    pub fn feature_words(&self) -> [u32; FEATURE_WORDS_NUM] {
+        let mut words = [0_u32; FEATURE_WORDS_NUM];
+        for name in self
+            .features
+            .iter()
+            .flat_map(|group| group.iter())
+            .chain(MANAGED_FEATURES.iter())
+        {
+            // It's safe to unwrap as the names in the model table are all valid.
+            let (word, bit) = find_feature(name).unwrap();
+            words[word] |= 1 << bit;
+        }
+        words
+    }
+
+    /// Get the value of CPUID[1].EAX, which contains family, model and stepping.
+    pub fn signature(&self) -> u32 {
+        let mut eax = self.stepping & 0xf;
+        eax |= (self.model & 0xf) << 4 | (self.model >> 4 & 0xf) << 16;
+        if self.family > 0xf {
+            eax |= 0xf << 8 | (self.family - 0xf) << 20;
+        } else {
+            eax |= self.family << 8;
+        }
+        eax
+    }
+
+    /// Get the processor brand string, which is reported by CPUID[0x80000002..0x80000004].
+    pub fn brand_string(&self) -> [u32; 12] {
+        let mut bytes = [0_u8; 48];
+        // The brand string must be null-terminated.
+        let len = std::cmp::min(self.model_id.len(), bytes.len() - 1);
+        bytes[..len].copy_from_slice(&self.model_id.as_bytes()[..len]);
+        let mut brand = [0_u32; 12];
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            brand[i] = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        brand
+    }
+}
+
+/// Get the mutable register of the CPUID entry.
+pub fn cpuid_reg(entry: &mut kvm_cpuid_entry2, reg: CpuidReg) -> &mut u32 {
+    match reg {
+        CpuidReg::Eax => &mut entry.eax,
+        CpuidReg::Ebx => &mut entry.ebx,
+        CpuidReg::Ecx => &mut entry.ecx,
+        CpuidReg::Edx => &mut entry.edx,
+    }
+}
+
+/// Get the index of feature word located in the CPUID entry.
+pub fn feature_word_of(function: u32, index: u32, reg: CpuidReg) -> Option<usize> {
+    FEATURE_WORDS
+        .iter()
+        .position(|(leaf, subleaf, r)| *leaf == function && *subleaf == index && *r == reg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cpu_model_table() {
+        assert_eq!(FEATURE_WORDS.len(), FEATURE_WORDS_NUM);
+        for model in X86_CPU_MODELS {
+            // All the features of models must be valid.
+            model.feature_words();
+        }
+        assert_eq!(find_model("host"), Some(0));
+        assert!(find_model("Skylake-Server").is_some());
+        assert!(find_model("skylake-server").is_none());
+
+        let model = &X86_CPU_MODELS[find_model("Skylake-Server").unwrap()];
+        let words = model.feature_words();
+        assert_ne!(words[FEAT_7_0_EBX] & 1 << 16, 0);
+        assert_ne!(words[FEAT_1_ECX] & 1 << 31, 0);
+        assert_eq!(words[FEAT_7_0_ECX] & 1 << 11, 0);
+        assert_eq!(model.signature(), 0x50654);
+
+        let model = &X86_CPU_MODELS[find_model("EPYC").unwrap()];
+        assert_eq!(model.signature(), 0x800f12);
+        let brand = model.brand_string();
+        assert_eq!(brand[0], u32::from_le_bytes(*b"AMD "));
+        assert_eq!(brand[11], 0);
+    }
+
+    #[test]
+    fn test_find_feature() {
+        assert_eq!(find_feature("avx512f"), Some((FEAT_7_0_EBX, 16)));
+        assert_eq!(find_feature("lm"), Some((FEAT_8000_0001_EDX, 29)));
+        assert_eq!(find_feature("avx1024"), None);
+        assert_eq!(feature_word_of(7, 0, CpuidReg::Ecx), Some(FEAT_7_0_ECX));
+        assert_eq!(feature_word_of(7, 1, CpuidReg::Ecx), None);
+    }
+}
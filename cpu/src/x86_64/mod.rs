@@ -12,21 +12,29 @@
 
 pub mod caps;
 
+mod cpu_model;
 mod cpuid;
 
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use kvm_bindings::{
     kvm_cpuid_entry2, kvm_debugregs, kvm_fpu, kvm_lapic_state, kvm_mp_state, kvm_msr_entry,
-    kvm_regs, kvm_segment, kvm_sregs, kvm_vcpu_events, kvm_xcrs, kvm_xsave, CpuId, Msrs,
+    kvm_regs, kvm_run, kvm_segment, kvm_sregs, kvm_vcpu_events, kvm_xcrs, kvm_xsave, CpuId, Msrs,
     KVM_CPUID_FLAG_SIGNIFCANT_INDEX, KVM_MAX_CPUID_ENTRIES, KVM_MP_STATE_RUNNABLE,
     KVM_MP_STATE_UNINITIALIZED,
 };
 use kvm_ioctls::{Kvm, VcpuFd};
 
+use self::caps::X86CPUFeatures;
+use self::cpu_model::{
+    cpuid_reg, feature_word_of, CpuidReg, CPU_FEATURES, FEATURE_WORDS, FEATURE_WORDS_NUM,
+    X86_CPU_MODELS,
+};
 use self::cpuid::host_cpuid;
-use crate::CPU;
+use crate::{CpuError, CPU};
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -54,6 +62,11 @@ const MSR_LIST: &[u32] = &[
 const MSR_IA32_MISC_ENABLE: u32 = 0x01a0;
 const MSR_IA32_MISC_ENABLE_FAST_STRING: u64 = 0x1;
 
+/// Number of general purpose registers in gdb `g` packet, rip follows them.
+const GDB_GPRS_NUM: usize = 16;
+/// Size of registers in gdb `g` packet: 16 gprs, rip, eflags and 6 segment selectors.
+const GDB_REGS_SIZE: usize = (GDB_GPRS_NUM + 1) * 8 + 4 + 6 * 4;
+
 const ECX_INVALID: u32 = 0u32 << 8;
 const ECX_THREAD: u32 = 1u32 << 8;
 const ECX_CORE: u32 = 2u32 << 8;
@@ -126,6 +139,7 @@ pub struct X86CPUState {
     xsave: kvm_xsave,
     xcrs: kvm_xcrs,
     debugregs: kvm_debugregs,
+    features: X86CPUFeatures,
 }
 
 impl X86CPUState {
@@ -170,6 +184,7 @@ impl X86CPUState {
         self.xsave = locked_cpu_state.xsave;
         self.xcrs = locked_cpu_state.xcrs;
         self.debugregs = locked_cpu_state.debugregs;
+        self.features = locked_cpu_state.features;
     }
 
     /// Set register value in `X86CPUState` according to `boot_config`.
@@ -178,11 +193,15 @@ impl X86CPUState {
     ///
     /// * `vcpu_fd` - Vcpu file descriptor in kvm.
     /// * `boot_config` - Boot message from boot_loader.
+    /// * `features` - CPU model and features of vcpu.
     pub fn set_boot_config(
         &mut self,
         vcpu_fd: &Arc<VcpuFd>,
         boot_config: &X86CPUBootConfig,
+        features: &X86CPUFeatures,
     ) -> Result<()> {
+        self.check_features(features)?;
+        self.features = *features;
         self.setup_lapic(vcpu_fd)?;
         self.setup_regs(boot_config);
         self.setup_sregs(vcpu_fd, boot_config)?;
@@ -204,6 +223,77 @@ impl X86CPUState {
         Ok(())
     }
 
+    /// Get the name of CPU model.
+    pub fn model_name(&self) -> &'static str {
+        X86_CPU_MODELS
+            .get(self.features.model as usize)
+            .map_or("unknown", |model| model.name)
+    }
+
+    /// Get the names of features which are exposed to the guest.
+    pub fn feature_names(&self) -> Result<Vec<String>> {
+        let words = get_feature_words(&self.build_cpuid()?);
+        Ok(CPU_FEATURES
+            .iter()
+            .filter(|(_, word, bit)| words[*word] & (1 << bit) != 0)
+            .map(|(name, _, _)| name.to_string())
+            .collect())
+    }
+
+    /// Check that the features enabled explicitly are supported by kvm.
+    fn check_features(&self, features: &X86CPUFeatures) -> Result<()> {
+        let supported = get_feature_words(&get_supported_cpuid()?);
+        for (name, word, bit) in CPU_FEATURES {
+            if features.enabled[*word] & (1 << bit) != 0 && supported[*word] & (1 << bit) == 0 {
+                return Err(anyhow!(CpuError::RealizeVcpu(format!(
+                    "CPU feature {} is not supported by host",
+                    name
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the CPU model and features to the cpuid entries.
+    fn apply_features(&self, entries: &mut [kvm_cpuid_entry2]) {
+        let model = X86_CPU_MODELS.get(self.features.model as usize);
+        // The first model means passing through the host CPU, nothing needs to be masked.
+        let model = model.filter(|_| self.features.model != 0);
+        let model_words = model.map(|m| m.feature_words());
+        for entry in entries.iter_mut() {
+            for reg in [CpuidReg::Eax, CpuidReg::Ebx, CpuidReg::Ecx, CpuidReg::Edx] {
+                let word = match feature_word_of(entry.function, entry.index, reg) {
+                    Some(word) => word,
+                    None => continue,
+                };
+                let value = cpuid_reg(entry, reg);
+                let supported = *value;
+                if let Some(words) = model_words {
+                    *value &= words[word];
+                }
+                *value |= self.features.enabled[word] & supported;
+                *value &= !self.features.disabled[word];
+            }
+
+            let model = match model {
+                Some(model) => model,
+                None => continue,
+            };
+            match entry.function {
+                1 => entry.eax = model.signature(),
+                0x8000_0002..=0x8000_0004 => {
+                    let brand = model.brand_string();
+                    let base = ((entry.function - 0x8000_0002) * 4) as usize;
+                    entry.eax = brand[base];
+                    entry.ebx = brand[base + 1];
+                    entry.ecx = brand[base + 2];
+                    entry.edx = brand[base + 3];
+                }
+                _ => (),
+            }
+        }
+    }
+
     /// Reset register value with `X86CPUState`.
     ///
     /// # Arguments
@@ -422,18 +512,19 @@ impl X86CPUState {
     }
 
     fn setup_cpuid(&self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
+        let cpuid = self.build_cpuid()?;
+        vcpu_fd
+            .set_cpuid2(&cpuid)
+            .with_context(|| format!("Failed to set cpuid for CPU {}/KVM", self.apic_id))?;
+        Ok(())
+    }
+
+    fn build_cpuid(&self) -> Result<CpuId> {
         let core_offset = 32u32 - (self.nr_threads - 1).leading_zeros();
         let die_offset = (32u32 - (self.nr_cores - 1).leading_zeros()) + core_offset;
         let pkg_offset = (32u32 - (self.nr_dies - 1).leading_zeros()) + die_offset;
-        let sys_fd = match Kvm::new() {
-            Ok(fd) => fd,
-            _ => bail!("setup_cpuid: Open /dev/kvm failed"),
-        };
-        let mut cpuid = sys_fd
-            .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
-            .with_context(|| {
-                format!("Failed to get supported cpuid for CPU {}/KVM", self.apic_id)
-            })?;
+        let mut cpuid = get_supported_cpuid()
+            .with_context(|| format!("Failed to setup cpuid for CPU {}/KVM", self.apic_id))?;
         self.adjust_cpuid(&mut cpuid)?;
         let entries = cpuid.as_mut_slice();
 
@@ -556,12 +647,162 @@ impl X86CPUState {
                 _ => (),
             }
         }
+        self.apply_features(entries);
 
-        vcpu_fd
-            .set_cpuid2(&cpuid)
-            .with_context(|| format!("Failed to set cpuid for CPU {}/KVM", self.apic_id))?;
+        Ok(cpuid)
+    }
+}
+
+fn get_supported_cpuid() -> Result<CpuId> {
+    let sys_fd = match Kvm::new() {
+        Ok(fd) => fd,
+        _ => bail!("Open /dev/kvm failed"),
+    };
+    sys_fd
+        .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
+        .with_context(|| "Failed to get supported cpuid from KVM")
+}
+
+/// Collect the feature words from the cpuid entries.
+fn get_feature_words(cpuid: &CpuId) -> [u32; FEATURE_WORDS_NUM] {
+    let mut words = [0_u32; FEATURE_WORDS_NUM];
+    for entry in cpuid.as_slice() {
+        let mut entry = *entry;
+        for (word, (function, index, reg)) in FEATURE_WORDS.iter().enumerate() {
+            if entry.function == *function && entry.index == *index {
+                words[word] = *cpuid_reg(&mut entry, *reg);
+            }
+        }
+    }
+    words
+}
+
+/// The `kvm_run` of the vCPU mapped by userspace once more, as `VcpuFd` only lends it
+/// mutably, while the fields of the interrupt window are accessed through `Arc<VcpuFd>`.
+pub(crate) struct KvmRun {
+    ptr: *mut kvm_run,
+}
+
+// SAFETY: kvm_run is only accessed in the vCPU thread.
+unsafe impl Send for KvmRun {}
+// SAFETY: kvm_run is only accessed in the vCPU thread.
+unsafe impl Sync for KvmRun {}
+
+impl KvmRun {
+    pub(crate) fn new(vcpu_fd: &VcpuFd) -> Result<Self> {
+        // SAFETY: kvm_run is at offset 0 of the vCPU fd, and the return value is checked.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size_of::<kvm_run>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            bail!(
+                "Failed to map kvm_run of vcpu: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(Self {
+            ptr: ptr as *mut kvm_run,
+        })
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) fn get(&self) -> &mut kvm_run {
+        // SAFETY: the mapping is large enough to hold kvm_run, and it lives until drop.
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl Drop for KvmRun {
+    fn drop(&mut self) {
+        // SAFETY: the memory is mapped by us, and not used anymore.
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, size_of::<kvm_run>()) };
+    }
+}
+
+impl CPU {
+    /// Get registers in the layout of gdb `g` packet, see `gdb/features/i386/64bit-core.xml`:
+    /// rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8-r15, rip, eflags, cs, ss, ds, es, fs, gs.
+    pub fn get_gdb_regs(&self) -> Result<Vec<u8>> {
+        let regs = self.fd.get_regs()?;
+        let sregs = self.fd.get_sregs()?;
+
+        let mut data = Vec::with_capacity(GDB_REGS_SIZE);
+        for reg in gdb_gprs(&regs) {
+            data.extend_from_slice(&reg.to_le_bytes());
+        }
+        data.extend_from_slice(&regs.rip.to_le_bytes());
+        data.extend_from_slice(&(regs.rflags as u32).to_le_bytes());
+        for seg in [sregs.cs, sregs.ss, sregs.ds, sregs.es, sregs.fs, sregs.gs] {
+            data.extend_from_slice(&u32::from(seg.selector).to_le_bytes());
+        }
+        Ok(data)
+    }
+
+    /// Set registers in the layout of gdb `G` packet. Segment selectors are ignored, as they
+    /// can't be changed without segment descriptors.
+    pub fn set_gdb_regs(&self, data: &[u8]) -> Result<()> {
+        if data.len() < (GDB_GPRS_NUM + 1) * 8 + 4 {
+            bail!("Invalid length {} of gdb registers", data.len());
+        }
+
+        let read_u64 =
+            |idx: usize| u64::from_le_bytes(data[idx * 8..idx * 8 + 8].try_into().unwrap());
+        let mut regs = self.fd.get_regs()?;
+        for (idx, reg) in gdb_gprs_mut(&mut regs).into_iter().enumerate() {
+            *reg = read_u64(idx);
+        }
+        regs.rip = read_u64(GDB_GPRS_NUM);
+        let eflags_offset = (GDB_GPRS_NUM + 1) * 8;
+        regs.rflags = u64::from(u32::from_le_bytes(
+            data[eflags_offset..eflags_offset + 4].try_into().unwrap(),
+        ));
+        self.fd.set_regs(&regs)?;
         Ok(())
     }
+
+    /// Translate guest virtual address to guest physical address with the page table of `CPU`.
+    pub fn translate_gva(&self, gva: u64, _read_u64: &dyn Fn(u64) -> Result<u64>) -> Result<u64> {
+        let translation = self.fd.translate_gva(gva)?;
+        if translation.valid == 0 {
+            bail!("Guest virtual address {:#x} is not mapped", gva);
+        }
+        Ok(translation.physical_address)
+    }
+}
+
+fn gdb_gprs(regs: &kvm_regs) -> [u64; GDB_GPRS_NUM] {
+    [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15,
+    ]
+}
+
+fn gdb_gprs_mut(regs: &mut kvm_regs) -> [&mut u64; GDB_GPRS_NUM] {
+    [
+        &mut regs.rax,
+        &mut regs.rbx,
+        &mut regs.rcx,
+        &mut regs.rdx,
+        &mut regs.rsi,
+        &mut regs.rdi,
+        &mut regs.rbp,
+        &mut regs.rsp,
+        &mut regs.r8,
+        &mut regs.r9,
+        &mut regs.r10,
+        &mut regs.r11,
+        &mut regs.r12,
+        &mut regs.r13,
+        &mut regs.r14,
+        &mut regs.r15,
+    ]
 }
 
 impl StateTransfer for CPU {
@@ -613,6 +854,7 @@ mod test {
     use super::*;
     use hypervisor::kvm::{KVMFds, KVM_FDS};
     use kvm_bindings::kvm_segment;
+    use machine_manager::config::CpuConfig;
     use std::sync::Arc;
 
     #[test]
@@ -676,7 +918,9 @@ mod test {
         let vcpu = Arc::new(vm_fd.create_vcpu(0).unwrap());
         let mut x86_cpu = X86CPUState::new(0, 1);
         // test `set_boot_config` function
-        assert!(x86_cpu.set_boot_config(&vcpu, &cpu_config).is_ok());
+        assert!(x86_cpu
+            .set_boot_config(&vcpu, &cpu_config, &X86CPUFeatures::default())
+            .is_ok());
 
         // test setup special registers
         let cpu_caps = caps::X86CPUCaps::init_capabilities();
@@ -712,4 +956,63 @@ mod test {
             assert_eq!(x86_fpu.fcw, 0x37f);
         }
     }
+
+    #[test]
+    fn test_x86_64_cpu_features() {
+        let mut cpu_config = CpuConfig {
+            model: Some("Skylake-Server".to_string()),
+            features: vec![("avx512f".to_string(), false), ("vmx".to_string(), true)],
+            ..Default::default()
+        };
+        let features = X86CPUFeatures::try_from(&cpu_config).unwrap();
+        let mut x86_cpu = X86CPUState::new(0, 1);
+        x86_cpu.features = features;
+        assert_eq!(x86_cpu.model_name(), "Skylake-Server");
+
+        let mut entries = vec![
+            kvm_cpuid_entry2 {
+                function: 1,
+                ecx: u32::MAX,
+                edx: u32::MAX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 7,
+                ebx: u32::MAX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x8000_0002,
+                ..Default::default()
+            },
+        ];
+        x86_cpu.apply_features(&mut entries);
+        // Skylake-Server has avx2 but avx512f is disabled explicitly.
+        assert_ne!(entries[1].ebx & 1 << 5, 0);
+        assert_eq!(entries[1].ebx & 1 << 16, 0);
+        // Features which are not in the model are masked, except the enabled ones.
+        assert_eq!(entries[1].ebx & 1 << 11, 0);
+        assert_ne!(entries[0].ecx & 1 << 5, 0);
+        assert_ne!(entries[0].ecx & 1 << 31, 0);
+        assert_eq!(entries[0].eax, 0x50654);
+        assert_eq!(entries[2].eax, u32::from_le_bytes(*b"Inte"));
+
+        // Host model keeps all the supported features.
+        cpu_config.model = None;
+        x86_cpu.features = X86CPUFeatures::try_from(&cpu_config).unwrap();
+        assert_eq!(x86_cpu.model_name(), "host");
+        let mut entries = vec![kvm_cpuid_entry2 {
+            function: 7,
+            ebx: u32::MAX,
+            ..Default::default()
+        }];
+        x86_cpu.apply_features(&mut entries);
+        assert_eq!(entries[0].ebx, !(1 << 16));
+
+        cpu_config.model = Some("Skylake".to_string());
+        assert!(X86CPUFeatures::try_from(&cpu_config).is_err());
+        cpu_config.model = None;
+        cpu_config.features = vec![("avx1024".to_string(), true)];
+        assert!(X86CPUFeatures::try_from(&cpu_config).is_err());
+    }
 }
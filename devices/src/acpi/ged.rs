@@ -20,6 +20,8 @@ use log::error;
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
+use crate::acpi::mem_hotplug::AML_MHPC_SCAN;
+use crate::acpi::vmgenid::AML_VMGENID_DEV;
 use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysRes};
 use crate::{Device, DeviceBase};
 use acpi::{
@@ -43,6 +45,8 @@ pub enum AcpiEvent {
     AcadSt = 2,
     BatteryInf = 4,
     BatterySt = 8,
+    MemHotplug = 16,
+    VmGenId = 32,
 }
 
 const AML_GED_EVT_REG: &str = "EREG";
@@ -53,6 +57,8 @@ pub struct Ged {
     base: SysBusDevBase,
     notification_type: Arc<AtomicU32>,
     battery_present: bool,
+    mem_hotplug: bool,
+    vmgenid: bool,
 }
 
 impl Default for Ged {
@@ -61,6 +67,8 @@ impl Default for Ged {
             base: SysBusDevBase::default(),
             notification_type: Arc::new(AtomicU32::new(AcpiEvent::Nothing as u32)),
             battery_present: false,
+            mem_hotplug: false,
+            vmgenid: false,
         }
     }
 }
@@ -71,6 +79,7 @@ impl Ged {
         sysbus: &mut SysBus,
         power_button: Arc<EventFd>,
         battery_present: bool,
+        mem_hotplug: bool,
         region_base: u64,
         region_size: u64,
     ) -> Result<Arc<Mutex<Ged>>> {
@@ -78,6 +87,7 @@ impl Ged {
         self.set_sys_resource(sysbus, region_base, region_size)
             .with_context(|| AcpiError::Alignment(region_size.try_into().unwrap()))?;
         self.battery_present = battery_present;
+        self.mem_hotplug = mem_hotplug;
 
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size, "Ged")?;
@@ -116,6 +126,11 @@ impl Ged {
         Ok(())
     }
 
+    /// Notify the vmgenid device in `_EVT` method when its event is injected.
+    pub fn enable_vmgenid_event(&mut self) {
+        self.vmgenid = true;
+    }
+
     pub fn inject_acpi_event(&self, evt: AcpiEvent) {
         self.notification_type
             .fetch_or(evt as u32, Ordering::SeqCst);
@@ -123,10 +138,10 @@ impl Ged {
     }
 
     fn inject_interrupt(&self) {
-        if let Some(evt_fd) = self.interrupt_evt() {
-            evt_fd
-                .write(1)
-                .unwrap_or_else(|e| error!("ged: failed to write interrupt eventfd ({:?}).", e));
+        if self.interrupt_evt().is_some() {
+            self.base
+                .inject_interrupt()
+                .unwrap_or_else(|e| error!("ged: failed to inject interrupt ({:?}).", e));
         }
     }
 }
@@ -237,6 +252,29 @@ impl AmlBuilder for Ged {
             method.append_child(if_scope);
         }
 
+        if self.mem_hotplug {
+            let evt = AcpiEvent::MemHotplug as u64;
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(AmlLocal(0), AmlInteger(evt), AmlLocal(1)),
+                AmlInteger(evt),
+            ));
+            if_scope.append_child(AmlName(AML_MHPC_SCAN.to_string()));
+            method.append_child(if_scope);
+        }
+
+        if self.vmgenid {
+            let evt = AcpiEvent::VmGenId as u64;
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(AmlLocal(0), AmlInteger(evt), AmlLocal(1)),
+                AmlInteger(evt),
+            ));
+            if_scope.append_child(AmlNotify::new(
+                AmlName(AML_VMGENID_DEV.to_string()),
+                AmlInteger(0x80),
+            ));
+            method.append_child(if_scope);
+        }
+
         acpi_dev.append_child(method);
 
         acpi_dev.aml_bytes()
@@ -0,0 +1,489 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+
+use crate::acpi::ged::{AcpiEvent, Ged};
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AcpiError, AmlAcquire, AmlAdd, AmlAddressSpaceDecode, AmlAddressSpaceType, AmlAnd, AmlArg,
+    AmlBuilder, AmlCacheable, AmlCallWithArgs1, AmlCallWithArgs2, AmlCreateQWordField, AmlDevice,
+    AmlEisaId, AmlEqual, AmlField, AmlFieldAccessType, AmlFieldLockRule, AmlFieldUnit,
+    AmlFieldUpdateRule, AmlIf, AmlIncrement, AmlInteger, AmlLLess, AmlLocal, AmlMethod, AmlMutex,
+    AmlName, AmlNameDecl, AmlNotify, AmlOpRegion, AmlOr, AmlQWordDesc, AmlReadAndWrite, AmlRelease,
+    AmlResTemplate, AmlReturn, AmlScopeBuilder, AmlShiftLeft, AmlStore, AmlString, AmlSubtract,
+    AmlWhile,
+};
+use address_space::GuestAddress;
+use util::num_ops::{read_data_u32, round_up, write_data_u32};
+
+/// Size of the registers of memory hotplug controller.
+pub const MEM_HOTPLUG_REGS_SIZE: u64 = 0x1C;
+
+const REG_SELECTOR: u64 = 0x0;
+const REG_BASE_LO: u64 = 0x4;
+const REG_BASE_HI: u64 = 0x8;
+const REG_LEN_LO: u64 = 0xC;
+const REG_LEN_HI: u64 = 0x10;
+const REG_PROXIMITY: u64 = 0x14;
+const REG_STATUS: u64 = 0x18;
+
+/// Alignment of the guest physical address of pc-dimm.
+const DIMM_ADDR_ALIGN: u64 = 1 << 30;
+
+/// The slot is plugged with a dimm.
+const SLOT_STATUS_ENABLED: u32 = 1 << 0;
+/// The dimm is hot-plugged and the guest has not scanned it yet.
+const SLOT_STATUS_INSERTING: u32 = 1 << 1;
+
+const AML_MHPC_REG: &str = "MHPR";
+const AML_MHPC_SEL: &str = "MSEL";
+const AML_MHPC_BASE_LO: &str = "MBAL";
+const AML_MHPC_BASE_HI: &str = "MBAH";
+const AML_MHPC_LEN_LO: &str = "MLNL";
+const AML_MHPC_LEN_HI: &str = "MLNH";
+const AML_MHPC_PROXIMITY: &str = "MPRX";
+const AML_MHPC_STATUS: &str = "MSTS";
+const AML_MHPC_LOCK: &str = "MLCK";
+/// Method called by GED to notify the guest of the inserted dimms.
+pub const AML_MHPC_SCAN: &str = "\\_SB.MHPC.MSCN";
+
+/// Memory range of a pc-dimm in the slot.
+#[derive(Clone, Default)]
+struct MemSlot {
+    id: String,
+    base: u64,
+    size: u64,
+    node: u32,
+    status: u32,
+}
+
+/// Memory hotplug controller, which reports the pc-dimms to the guest through the ACPI
+/// memory devices. The slot is selected by writing the selector register, then the range,
+/// proximity and status of the dimm in it can be read.
+pub struct MemHotplugController {
+    base: SysBusDevBase,
+    slots: Vec<MemSlot>,
+    /// Guest physical address window in which the pc-dimms are mapped.
+    window: (u64, u64),
+    selector: u32,
+    ged: Arc<Mutex<Ged>>,
+}
+
+impl MemHotplugController {
+    pub fn new(slots: u32, window: (u64, u64), ged: Arc<Mutex<Ged>>) -> Self {
+        Self {
+            base: SysBusDevBase::default(),
+            slots: vec![MemSlot::default(); slots as usize],
+            window,
+            selector: 0,
+            ged,
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<MemHotplugController>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| AcpiError::Alignment(region_size.try_into().unwrap()))?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size, "MemHotplugController")?;
+        Ok(dev)
+    }
+
+    /// Find the guest physical address for a pc-dimm, after the ones already plugged.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of the pc-dimm.
+    pub fn alloc_addr(&self, size: u64) -> Result<u64> {
+        if self.slots.iter().all(|slot| slot.status != 0) {
+            bail!("No free memory slot, {} slots are used", self.slots.len());
+        }
+        let (window_start, window_size) = self.window;
+        let end = self
+            .slots
+            .iter()
+            .filter(|slot| slot.status != 0)
+            .map(|slot| slot.base + slot.size)
+            .max()
+            .unwrap_or(window_start);
+        let base = round_up(end, DIMM_ADDR_ALIGN)
+            .with_context(|| "Failed to align the address of pc-dimm")?;
+        if base
+            .checked_add(size)
+            .is_none_or(|end| end > window_start + window_size)
+        {
+            bail!(
+                "No enough guest physical address space for pc-dimm of size 0x{:X}",
+                size
+            );
+        }
+        Ok(base)
+    }
+
+    /// Put a pc-dimm into a free slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the pc-dimm.
+    /// * `base` - Guest physical address of the pc-dimm.
+    /// * `size` - Size of the pc-dimm.
+    /// * `node` - Guest numa node of the pc-dimm.
+    /// * `hotplug` - Notify the guest by GED if the pc-dimm is hot-plugged.
+    pub fn plug(&mut self, id: &str, base: u64, size: u64, node: u32, hotplug: bool) -> Result<()> {
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.status == 0)
+            .with_context(|| format!("No free memory slot for pc-dimm {}", id))?;
+        *slot = MemSlot {
+            id: id.to_string(),
+            base,
+            size,
+            node,
+            status: SLOT_STATUS_ENABLED,
+        };
+        if hotplug {
+            slot.status |= SLOT_STATUS_INSERTING;
+            self.ged
+                .lock()
+                .unwrap()
+                .inject_acpi_event(AcpiEvent::MemHotplug);
+        }
+        Ok(())
+    }
+
+    /// Check whether the pc-dimm is plugged.
+    pub fn contains(&self, id: &str) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| slot.status != 0 && slot.id == id)
+    }
+
+    fn selected_slot(&self) -> Option<&MemSlot> {
+        self.slots.get(self.selector as usize)
+    }
+
+    fn build_slot_method(name: &str, field: &str) -> AmlMethod {
+        let mut method = AmlMethod::new(name, 1, true);
+        method.append_child(AmlAcquire::new(AmlName(AML_MHPC_LOCK.to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlArg(0), AmlName(AML_MHPC_SEL.to_string())));
+        method.append_child(AmlStore::new(AmlName(field.to_string()), AmlLocal(0)));
+        method.append_child(AmlRelease::new(AmlName(AML_MHPC_LOCK.to_string())));
+        method.append_child(AmlReturn::with_value(AmlLocal(0)));
+        method
+    }
+}
+
+impl Device for MemHotplugController {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for MemHotplugController {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let slot = self.selected_slot().cloned().unwrap_or_default();
+        let value = match offset {
+            REG_SELECTOR => self.selector,
+            REG_BASE_LO => slot.base as u32,
+            REG_BASE_HI => (slot.base >> 32) as u32,
+            REG_LEN_LO => slot.size as u32,
+            REG_LEN_HI => (slot.size >> 32) as u32,
+            REG_PROXIMITY => slot.node,
+            REG_STATUS => slot.status,
+            _ => return false,
+        };
+        write_data_u32(data, value)
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let mut value = 0;
+        if !read_data_u32(data, &mut value) {
+            return false;
+        }
+        match offset {
+            REG_SELECTOR => self.selector = value,
+            REG_STATUS => {
+                // Writing 1 to the inserting bit acknowledges the hot-plugged dimm.
+                if let Some(slot) = self.slots.get_mut(self.selector as usize) {
+                    slot.status &= !(value & SLOT_STATUS_INSERTING);
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+}
+
+impl AmlBuilder for MemHotplugController {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let slots_count = self.slots.len() as u64;
+        let mut mhpc = AmlDevice::new("MHPC");
+        mhpc.append_child(AmlNameDecl::new("_HID", AmlString("PNP0A06".to_string())));
+        mhpc.append_child(AmlNameDecl::new(
+            "_UID",
+            AmlString("Memory hotplug resources".to_string()),
+        ));
+        mhpc.append_child(AmlOpRegion::new(
+            AML_MHPC_REG,
+            AmlAddressSpaceType::SystemMemory,
+            self.base.res.region_base,
+            self.base.res.region_size,
+        ));
+        let mut field = AmlField::new(
+            AML_MHPC_REG,
+            AmlFieldAccessType::DWord,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::Preserve,
+        );
+        for name in [
+            AML_MHPC_SEL,
+            AML_MHPC_BASE_LO,
+            AML_MHPC_BASE_HI,
+            AML_MHPC_LEN_LO,
+            AML_MHPC_LEN_HI,
+            AML_MHPC_PROXIMITY,
+            AML_MHPC_STATUS,
+        ] {
+            field.append_child(AmlFieldUnit::new(Some(name), 32));
+        }
+        mhpc.append_child(field);
+        mhpc.append_child(AmlMutex::new(AML_MHPC_LOCK, 0));
+
+        // MSTA(slot): _STA of the memory device in the slot.
+        let mut method = AmlMethod::new("MSTA", 1, true);
+        method.append_child(AmlAcquire::new(AmlName(AML_MHPC_LOCK.to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlArg(0), AmlName(AML_MHPC_SEL.to_string())));
+        method.append_child(AmlStore::new(AmlInteger(0), AmlLocal(0)));
+        let mut if_scope = AmlIf::new(AmlEqual::new(
+            AmlAnd::new(
+                AmlName(AML_MHPC_STATUS.to_string()),
+                AmlInteger(SLOT_STATUS_ENABLED as u64),
+                AmlLocal(1),
+            ),
+            AmlInteger(SLOT_STATUS_ENABLED as u64),
+        ));
+        if_scope.append_child(AmlStore::new(AmlInteger(0xF), AmlLocal(0)));
+        method.append_child(if_scope);
+        method.append_child(AmlRelease::new(AmlName(AML_MHPC_LOCK.to_string())));
+        method.append_child(AmlReturn::with_value(AmlLocal(0)));
+        mhpc.append_child(method);
+
+        // MCRS(slot): _CRS of the memory device in the slot.
+        method = AmlMethod::new("MCRS", 1, true);
+        method.append_child(AmlAcquire::new(AmlName(AML_MHPC_LOCK.to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlArg(0), AmlName(AML_MHPC_SEL.to_string())));
+        let mut crs = AmlResTemplate::new();
+        crs.append_child(AmlQWordDesc::new_memory(
+            AmlAddressSpaceDecode::Positive,
+            AmlCacheable::Cacheable,
+            AmlReadAndWrite::ReadWrite,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ));
+        method.append_child(AmlNameDecl::new("MR64", crs));
+        // Byte offsets of the minimum, maximum and length in the QWord descriptor.
+        method.append_child(AmlCreateQWordField::new(
+            AmlName("MR64".to_string()),
+            AmlInteger(14),
+            "MINL",
+        ));
+        method.append_child(AmlCreateQWordField::new(
+            AmlName("MR64".to_string()),
+            AmlInteger(22),
+            "MAXL",
+        ));
+        method.append_child(AmlCreateQWordField::new(
+            AmlName("MR64".to_string()),
+            AmlInteger(38),
+            "LENL",
+        ));
+        method.append_child(AmlShiftLeft::new(
+            AmlName(AML_MHPC_BASE_HI.to_string()),
+            AmlInteger(32),
+            AmlLocal(0),
+        ));
+        method.append_child(AmlOr::new(
+            AmlLocal(0),
+            AmlName(AML_MHPC_BASE_LO.to_string()),
+            AmlName("MINL".to_string()),
+        ));
+        method.append_child(AmlShiftLeft::new(
+            AmlName(AML_MHPC_LEN_HI.to_string()),
+            AmlInteger(32),
+            AmlLocal(0),
+        ));
+        method.append_child(AmlOr::new(
+            AmlLocal(0),
+            AmlName(AML_MHPC_LEN_LO.to_string()),
+            AmlName("LENL".to_string()),
+        ));
+        method.append_child(AmlAdd::new(
+            AmlName("MINL".to_string()),
+            AmlName("LENL".to_string()),
+            AmlLocal(0),
+        ));
+        method.append_child(AmlSubtract::new(
+            AmlLocal(0),
+            AmlInteger(1),
+            AmlName("MAXL".to_string()),
+        ));
+        method.append_child(AmlRelease::new(AmlName(AML_MHPC_LOCK.to_string())));
+        method.append_child(AmlReturn::with_value(AmlName("MR64".to_string())));
+        mhpc.append_child(method);
+
+        // MPXM(slot): _PXM of the memory device in the slot.
+        mhpc.append_child(Self::build_slot_method("MPXM", AML_MHPC_PROXIMITY));
+
+        // MTFY(slot, event): notify the memory device in the slot.
+        method = AmlMethod::new("MTFY", 2, true);
+        for i in 0..slots_count {
+            let mut if_scope = AmlIf::new(AmlEqual::new(AmlArg(0), AmlInteger(i)));
+            if_scope.append_child(AmlNotify::new(AmlName(format!("MP{:02X}", i)), AmlArg(1)));
+            method.append_child(if_scope);
+        }
+        mhpc.append_child(method);
+
+        // MSCN(): notify the guest of all the inserted dimms, called by GED.
+        method = AmlMethod::new("MSCN", 0, true);
+        method.append_child(AmlAcquire::new(AmlName(AML_MHPC_LOCK.to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlInteger(0), AmlLocal(0)));
+        let mut while_scope = AmlWhile::new(AmlLLess::new(AmlLocal(0), AmlInteger(slots_count)));
+        while_scope.append_child(AmlStore::new(
+            AmlLocal(0),
+            AmlName(AML_MHPC_SEL.to_string()),
+        ));
+        let mut if_scope = AmlIf::new(AmlEqual::new(
+            AmlAnd::new(
+                AmlName(AML_MHPC_STATUS.to_string()),
+                AmlInteger(SLOT_STATUS_INSERTING as u64),
+                AmlLocal(1),
+            ),
+            AmlInteger(SLOT_STATUS_INSERTING as u64),
+        ));
+        // Device check notification.
+        if_scope.append_child(AmlCallWithArgs2::new("MTFY", AmlLocal(0), AmlInteger(1)));
+        if_scope.append_child(AmlStore::new(
+            AmlInteger(SLOT_STATUS_INSERTING as u64),
+            AmlName(AML_MHPC_STATUS.to_string()),
+        ));
+        while_scope.append_child(if_scope);
+        while_scope.append_child(AmlIncrement::new(AmlLocal(0)));
+        method.append_child(while_scope);
+        method.append_child(AmlRelease::new(AmlName(AML_MHPC_LOCK.to_string())));
+        mhpc.append_child(method);
+
+        let mut bytes = mhpc.aml_bytes();
+        for i in 0..slots_count {
+            let mut dev = AmlDevice::new(format!("MP{:02X}", i).as_str());
+            dev.append_child(AmlNameDecl::new("_HID", AmlEisaId::new("PNP0C80")));
+            dev.append_child(AmlNameDecl::new("_UID", AmlInteger(i)));
+            for (name, callee) in [("_STA", "MSTA"), ("_CRS", "MCRS"), ("_PXM", "MPXM")] {
+                let mut method = AmlMethod::new(name, 0, false);
+                method.append_child(AmlReturn::with_value(AmlCallWithArgs1::new(
+                    format!("\\_SB.MHPC.{}", callee).as_str(),
+                    AmlInteger(i),
+                )));
+                dev.append_child(method);
+            }
+            bytes.extend(dev.aml_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mem_hotplug_controller_regs() {
+        let ged = Arc::new(Mutex::new(Ged::default()));
+        let mut mhpc = MemHotplugController::new(2, (0x1_0000_0000, 0x3_0000_0000), ged);
+        let base = GuestAddress(0);
+        let mut data = [0_u8; 4];
+
+        assert!(mhpc.alloc_addr(0x4_0000_0000).is_err());
+        let addr = mhpc.alloc_addr(0x2000_0000).unwrap();
+        assert_eq!(addr, 0x1_0000_0000);
+        mhpc.plug("dimm0", addr, 0x2000_0000, 1, false).unwrap();
+        // The address of the next dimm is aligned.
+        let addr = mhpc.alloc_addr(0x1_0000_0000).unwrap();
+        assert_eq!(addr, 0x1_4000_0000);
+        mhpc.plug("dimm1", addr, 0x1_0000_0000, 0, true).unwrap();
+        assert!(mhpc.alloc_addr(0x4000_0000).is_err());
+        assert!(mhpc
+            .plug("dimm2", 0x2_8000_0000, 0x4000_0000, 0, true)
+            .is_err());
+        assert!(mhpc.contains("dimm1"));
+        assert!(!mhpc.contains("dimm2"));
+
+        let read_reg = |mhpc: &mut MemHotplugController, data: &mut [u8; 4], offset| {
+            assert!(mhpc.read(data, base, offset));
+            u32::from_le_bytes(*data)
+        };
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_BASE_LO), 0);
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_BASE_HI), 0x1);
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_LEN_LO), 0x2000_0000);
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_PROXIMITY), 1);
+        assert_eq!(
+            read_reg(&mut mhpc, &mut data, REG_STATUS),
+            SLOT_STATUS_ENABLED
+        );
+
+        // Select the hot-plugged dimm and acknowledge it.
+        assert!(mhpc.write(&1_u32.to_le_bytes(), base, REG_SELECTOR));
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_BASE_LO), 0x4000_0000);
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_LEN_HI), 0x1);
+        assert_eq!(
+            read_reg(&mut mhpc, &mut data, REG_STATUS),
+            SLOT_STATUS_ENABLED | SLOT_STATUS_INSERTING
+        );
+        assert!(mhpc.write(&SLOT_STATUS_INSERTING.to_le_bytes(), base, REG_STATUS));
+        assert_eq!(
+            read_reg(&mut mhpc, &mut data, REG_STATUS),
+            SLOT_STATUS_ENABLED
+        );
+
+        // The slot out of range reads as empty.
+        assert!(mhpc.write(&2_u32.to_le_bytes(), base, REG_SELECTOR));
+        assert_eq!(read_reg(&mut mhpc, &mut data, REG_STATUS), 0);
+    }
+}
@@ -11,4 +11,6 @@
 // See the Mulan PSL v2 for more details.
 
 pub mod ged;
+pub mod mem_hotplug;
 pub mod power;
+pub mod vmgenid;
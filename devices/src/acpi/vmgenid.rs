@@ -0,0 +1,308 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use log::error;
+
+use crate::acpi::ged::{AcpiEvent, Ged};
+use crate::sysbus::{
+    Result as SysBusResult, SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes,
+};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl, AmlPackage, AmlScopeBuilder, AmlString,
+};
+use address_space::{GuestAddress, HostMemMapping, Region};
+use machine_manager::config::Uuid;
+use migration::{
+    snapshot::VMGENID_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
+    MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
+use util::byte_code::ByteCode;
+
+/// Size of the guest memory which holds the generation ID. It's mapped as RAM, so that
+/// the guest reads it by plain memory access, and 64KiB keeps it aligned to host page.
+pub const VMGENID_REGION_SIZE: u64 = 0x1_0000;
+/// Size of the generation ID.
+const VMGENID_GUID_SIZE: usize = 16;
+/// Path of the ACPI device, which is notified by GED when the generation ID changes.
+pub const AML_VMGENID_DEV: &str = "\\_SB.VGEN";
+
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct VmGenIdState {
+    guid: [u8; 16],
+}
+
+/// VM generation ID device. The 128-bit generation ID is exposed to guest through the
+/// ACPI device `VGEN`, whose `ADDR` object is the guest physical address of the ID. The
+/// guest is notified by GED once the ID changes, such as after the VM is restored from
+/// a snapshot.
+pub struct VmGenId {
+    base: SysBusDevBase,
+    /// Generation ID in the byte order of the guest.
+    guid: [u8; VMGENID_GUID_SIZE],
+    /// The ID is regenerated randomly when the VM is restored.
+    auto: bool,
+    host_mmap: Arc<HostMemMapping>,
+    ged: Arc<Mutex<Ged>>,
+}
+
+impl VmGenId {
+    /// Create vmgenid device.
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - The generation ID, `None` for a random one.
+    /// * `region_base` - Guest physical address of the generation ID.
+    /// * `ged` - GED which notifies the guest when generation ID changes.
+    pub fn new(guid: Option<Uuid>, region_base: u64, ged: Arc<Mutex<Ged>>) -> Result<Self> {
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(region_base),
+                None,
+                VMGENID_REGION_SIZE,
+                None,
+                false,
+                false,
+                false,
+            )
+            .with_context(|| "Failed to create memory of vmgenid")?,
+        );
+        let mut vmgenid = VmGenId {
+            base: SysBusDevBase::new(SysBusDevType::VmGenId),
+            guid: [0; VMGENID_GUID_SIZE],
+            auto: guid.is_none(),
+            host_mmap,
+            ged,
+        };
+        vmgenid.base.res.region_base = region_base;
+        vmgenid.base.res.region_size = VMGENID_REGION_SIZE;
+        vmgenid.set_guid(guid);
+        Ok(vmgenid)
+    }
+
+    pub fn realize(self, sysbus: &mut SysBus) -> Result<Arc<Mutex<VmGenId>>> {
+        let region = Region::init_ram_region(self.host_mmap.clone(), "VmGenId");
+        sysbus
+            .sys_mem
+            .root()
+            .add_subregion(region, self.base.res.region_base)
+            .with_context(|| "Failed to register memory of vmgenid")?;
+        self.ged.lock().unwrap().enable_vmgenid_event();
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_dynamic_device(&dev)?;
+        MigrationManager::register_device_instance(
+            VmGenIdState::descriptor(),
+            dev.clone(),
+            VMGENID_SNAPSHOT_ID,
+        );
+        Ok(dev)
+    }
+
+    /// Get the generation ID as UUID string.
+    pub fn guid(&self) -> String {
+        Uuid {
+            name: self.guid.to_vec(),
+        }
+        .to_string()
+    }
+
+    /// Update the generation ID and notify the guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - The new generation ID, `None` for a random one.
+    pub fn update_guid(&mut self, guid: Option<Uuid>) {
+        self.auto = guid.is_none();
+        self.set_guid(guid);
+        self.notify_guest();
+    }
+
+    fn set_guid(&mut self, guid: Option<Uuid>) {
+        match guid {
+            Some(uuid) => self.guid.copy_from_slice(&uuid.name),
+            None => {
+                self.guid = rand::random();
+                // Random UUID of version 4 and variant 1. The version is in the high
+                // byte of the third field, which is stored in little endian.
+                self.guid[7] = (self.guid[7] & 0x0F) | 0x40;
+                self.guid[8] = (self.guid[8] & 0x3F) | 0x80;
+            }
+        }
+        self.write_guid();
+    }
+
+    fn write_guid(&self) {
+        // SAFETY: The host memory is mapped with size `VMGENID_REGION_SIZE`, which is
+        // larger than the generation ID.
+        let buf = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.host_mmap.host_address() as *mut u8,
+                VMGENID_GUID_SIZE,
+            )
+        };
+        buf.copy_from_slice(&self.guid);
+    }
+
+    fn notify_guest(&self) {
+        self.ged
+            .lock()
+            .unwrap()
+            .inject_acpi_event(AcpiEvent::VmGenId);
+    }
+}
+
+impl Device for VmGenId {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for VmGenId {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, _data: &mut [u8], _base: GuestAddress, _offset: u64) -> bool {
+        error!("VmGenId is mapped as memory, can not be read!");
+        false
+    }
+
+    fn write(&mut self, _data: &[u8], _base: GuestAddress, _offset: u64) -> bool {
+        error!("VmGenId is mapped as memory, can not be written!");
+        false
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> SysBusResult<()> {
+        // The memory is writable by guest, restore the generation ID.
+        self.write_guid();
+        Ok(())
+    }
+}
+
+impl AmlBuilder for VmGenId {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new("VGEN");
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlString("VMGENCTR".to_string())));
+        acpi_dev.append_child(AmlNameDecl::new(
+            "_CID",
+            AmlString("VM_Gen_Counter".to_string()),
+        ));
+        acpi_dev.append_child(AmlNameDecl::new(
+            "_DDN",
+            AmlString("VM_Gen_Counter".to_string()),
+        ));
+
+        let addr = self.base.res.region_base;
+        let mut pkg = AmlPackage::new(2);
+        pkg.append_child(AmlInteger(addr & 0xFFFF_FFFF));
+        pkg.append_child(AmlInteger(addr >> 32));
+        acpi_dev.append_child(AmlNameDecl::new("ADDR", pkg));
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+impl StateTransfer for VmGenId {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let state = VmGenIdState { guid: self.guid };
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state = VmGenIdState::from_bytes(state)
+            .with_context(|| MigrationError::FromBytesError("VMGENID"))?;
+        // The restored VM is a new instance from the view of guest, so the random
+        // generation ID is regenerated, and the specified one is kept.
+        if self.auto {
+            self.set_guid(None);
+        } else {
+            self.guid = state.guid;
+            self.write_guid();
+        }
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&VmGenIdState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for VmGenId {
+    fn resume(&mut self) -> migration::Result<()> {
+        self.notify_guest();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn read_guid(vmgenid: &VmGenId) -> [u8; VMGENID_GUID_SIZE] {
+        let mut guid = [0_u8; VMGENID_GUID_SIZE];
+        // SAFETY: The host memory is larger than the generation ID.
+        let buf = unsafe {
+            std::slice::from_raw_parts(
+                vmgenid.host_mmap.host_address() as *const u8,
+                VMGENID_GUID_SIZE,
+            )
+        };
+        guid.copy_from_slice(buf);
+        guid
+    }
+
+    #[test]
+    fn test_vmgenid_guid() {
+        let ged = Arc::new(Mutex::new(Ged::default()));
+        let uuid = Uuid::from_str("324e6eaf-d1d1-4bf6-bf41-b9bb6c91fb87").unwrap();
+        let mut vmgenid = VmGenId::new(Some(uuid.clone()), 0x1000_0000, ged).unwrap();
+        assert_eq!(read_guid(&vmgenid).to_vec(), uuid.name);
+        assert_eq!(vmgenid.guid(), "324e6eaf-d1d1-4bf6-bf41-b9bb6c91fb87");
+
+        // The specified generation ID is kept after restored.
+        let state = vmgenid.get_state_vec().unwrap();
+        vmgenid.set_state_mut(&state).unwrap();
+        assert_eq!(read_guid(&vmgenid).to_vec(), uuid.name);
+
+        // The random generation ID is UUID of version 4.
+        vmgenid.update_guid(None);
+        let guid = read_guid(&vmgenid);
+        assert_ne!(guid.to_vec(), uuid.name);
+        assert_eq!(vmgenid.guid().as_bytes()[14], b'4');
+
+        // The random generation ID is regenerated after restored.
+        let state = vmgenid.get_state_vec().unwrap();
+        vmgenid.set_state_mut(&state).unwrap();
+        assert_ne!(read_guid(&vmgenid), guid);
+    }
+}
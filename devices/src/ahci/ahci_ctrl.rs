@@ -0,0 +1,1556 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use log::{error, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::ata::{AtaDevice, ATAPI_SENSE_SIZE, ATA_MAX_MULTIPLE_SECTORS, ATA_SECTOR_SHIFT};
+use crate::ScsiBus::{
+    scsi_cdb_xfer, ScsiRequest, ScsiRequestOps, ScsiSense, ScsiXferMode, EMULATE_SCSI_OPS, GOOD,
+    REPORT_LUNS, REQUEST_SENSE, SCSI_CMD_BUF_SIZE, SCSI_SENSE_INVALID_OPCODE, SCSI_SENSE_IO_ERROR,
+    SCSI_SENSE_WRITE_PROTECTED,
+};
+use address_space::{AddressSpace, GuestAddress};
+use machine_manager::config::AHCI_MAX_PORTS;
+use util::aio::{
+    get_iov_size, iov_from_buf_direct, iovecs_split, AioCb, AioReqResult, Iovec, OpCode,
+};
+use util::byte_code::ByteCode;
+
+/// Generic host control registers, AHCI 1.3 section 3.1.
+const AHCI_REG_CAP: u64 = 0x00;
+const AHCI_REG_GHC: u64 = 0x04;
+const AHCI_REG_IS: u64 = 0x08;
+const AHCI_REG_PI: u64 = 0x0c;
+const AHCI_REG_VS: u64 = 0x10;
+
+const AHCI_CAP_NCS_SHIFT: u32 = 8;
+const AHCI_CAP_ISS_GEN1: u32 = 1 << 20;
+const AHCI_CAP_SAM: u32 = 1 << 18;
+const AHCI_CAP_SNCQ: u32 = 1 << 30;
+const AHCI_CAP_S64A: u32 = 1 << 31;
+
+const AHCI_GHC_HR: u32 = 1;
+const AHCI_GHC_IE: u32 = 1 << 1;
+const AHCI_GHC_AE: u32 = 1 << 31;
+
+/// Version 1.0.
+const AHCI_VERSION: u32 = 0x0001_0000;
+const AHCI_MAX_SLOTS: usize = 32;
+
+/// Port registers start at offset 0x100 with the stride of 0x80, AHCI 1.3 section 3.3.
+const AHCI_PORT_BASE: u64 = 0x100;
+const AHCI_PORT_SIZE: u64 = 0x80;
+pub const AHCI_MMIO_SIZE: u64 = 0x1000;
+
+const PORT_REG_CLB: u64 = 0x00;
+const PORT_REG_CLBU: u64 = 0x04;
+const PORT_REG_FB: u64 = 0x08;
+const PORT_REG_FBU: u64 = 0x0c;
+const PORT_REG_IS: u64 = 0x10;
+const PORT_REG_IE: u64 = 0x14;
+const PORT_REG_CMD: u64 = 0x18;
+const PORT_REG_TFD: u64 = 0x20;
+const PORT_REG_SIG: u64 = 0x24;
+const PORT_REG_SSTS: u64 = 0x28;
+const PORT_REG_SCTL: u64 = 0x2c;
+const PORT_REG_SERR: u64 = 0x30;
+const PORT_REG_SACT: u64 = 0x34;
+const PORT_REG_CI: u64 = 0x38;
+
+const PORT_CMD_ST: u32 = 1;
+const PORT_CMD_SUD: u32 = 1 << 1;
+const PORT_CMD_POD: u32 = 1 << 2;
+const PORT_CMD_CLO: u32 = 1 << 3;
+const PORT_CMD_FRE: u32 = 1 << 4;
+const PORT_CMD_FR: u32 = 1 << 14;
+const PORT_CMD_CR: u32 = 1 << 15;
+const PORT_CMD_ATAPI: u32 = 1 << 24;
+const PORT_CMD_WRITABLE: u32 = PORT_CMD_ST | PORT_CMD_FRE | PORT_CMD_ATAPI;
+
+const PORT_IRQ_DHRS: u32 = 1;
+const PORT_IRQ_PSS: u32 = 1 << 1;
+const PORT_IRQ_SDBS: u32 = 1 << 3;
+const PORT_IRQ_HBFS: u32 = 1 << 29;
+const PORT_IRQ_TFES: u32 = 1 << 30;
+const PORT_IRQ_MASK: u32 = 0xfdc0_00ff;
+
+/// Device is present and the communication is established in 1.5Gbps, and the link is active.
+const PORT_SSTS_ACTIVE: u32 = 0x113;
+const PORT_SCTL_DET_MASK: u32 = 0xf;
+const PORT_SCTL_DET_COMRESET: u32 = 1;
+/// Task file data of the port without the device.
+const PORT_TFD_NO_DEVICE: u32 = 0x7f;
+
+/// Command header, AHCI 1.3 section 4.2.2.
+const CMD_HDR_SIZE: u64 = 32;
+const CMD_HDR_PRDTL_SHIFT: u32 = 16;
+/// Command table, AHCI 1.3 section 4.2.3.
+const CMD_TBL_ACMD: u64 = 0x40;
+const CMD_TBL_PRDT: u64 = 0x80;
+const PRD_SIZE: u64 = 16;
+const PRD_DBC_MASK: u32 = 0x3f_ffff;
+
+/// Offsets of the received FISes, AHCI 1.3 section 4.2.1.
+const RX_FIS_PIO_SETUP: u64 = 0x20;
+const RX_FIS_D2H: u64 = 0x40;
+const RX_FIS_SDB: u64 = 0x58;
+
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+const FIS_TYPE_REG_D2H: u8 = 0x34;
+const FIS_TYPE_PIO_SETUP: u8 = 0x5f;
+const FIS_TYPE_SDB: u8 = 0xa1;
+const FIS_H2D_SIZE: usize = 20;
+/// The command register is updated, otherwise it's the device control register.
+const FIS_H2D_C: u8 = 0x80;
+const FIS_INTERRUPT: u8 = 0x40;
+/// Data is transferred from the device to host.
+const FIS_PIO_D2H: u8 = 0x20;
+const ATA_CTL_SRST: u8 = 0x04;
+
+/// ATA status and error registers.
+const ATA_STAT_DRDY: u8 = 0x40;
+const ATA_STAT_DSC: u8 = 0x10;
+const ATA_STAT_ERR: u8 = 0x01;
+const ATA_ERR_ABRT: u8 = 0x04;
+const ATA_ERR_IDNF: u8 = 0x10;
+const ATA_ERR_UNC: u8 = 0x40;
+/// Interrupt reason of the completed packet command: the status is transferred to host.
+const ATAPI_INTR_IO_CD: u16 = 0x3;
+
+/// ATA commands.
+const ATA_CMD_DSM: u8 = 0x06;
+const ATA_CMD_DEVICE_RESET: u8 = 0x08;
+const ATA_CMD_READ_SECTORS: u8 = 0x20;
+const ATA_CMD_READ_SECTORS_EXT: u8 = 0x24;
+const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
+const ATA_CMD_READ_MULTIPLE_EXT: u8 = 0x29;
+const ATA_CMD_WRITE_SECTORS: u8 = 0x30;
+const ATA_CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_CMD_WRITE_MULTIPLE_EXT: u8 = 0x39;
+const ATA_CMD_READ_VERIFY: u8 = 0x40;
+const ATA_CMD_READ_VERIFY_EXT: u8 = 0x42;
+const ATA_CMD_READ_FPDMA_QUEUED: u8 = 0x60;
+const ATA_CMD_WRITE_FPDMA_QUEUED: u8 = 0x61;
+const ATA_CMD_SEEK: u8 = 0x70;
+const ATA_CMD_EXEC_DEVICE_DIAGNOSTIC: u8 = 0x90;
+const ATA_CMD_INIT_DEVICE_PARAMS: u8 = 0x91;
+const ATA_CMD_PACKET: u8 = 0xa0;
+const ATA_CMD_IDENTIFY_PACKET: u8 = 0xa1;
+const ATA_CMD_READ_MULTIPLE: u8 = 0xc4;
+const ATA_CMD_WRITE_MULTIPLE: u8 = 0xc5;
+const ATA_CMD_SET_MULTIPLE: u8 = 0xc6;
+const ATA_CMD_READ_DMA: u8 = 0xc8;
+const ATA_CMD_WRITE_DMA: u8 = 0xca;
+const ATA_CMD_STANDBY_IMMEDIATE: u8 = 0xe0;
+const ATA_CMD_IDLE_IMMEDIATE: u8 = 0xe1;
+const ATA_CMD_STANDBY: u8 = 0xe2;
+const ATA_CMD_IDLE: u8 = 0xe3;
+const ATA_CMD_CHECK_POWER_MODE: u8 = 0xe5;
+const ATA_CMD_FLUSH_CACHE: u8 = 0xe7;
+const ATA_CMD_FLUSH_CACHE_EXT: u8 = 0xea;
+const ATA_CMD_IDENTIFY: u8 = 0xec;
+const ATA_CMD_SET_FEATURES: u8 = 0xef;
+
+/// Subcommands of the set features command.
+const ATA_SETFEATURES_WC_ON: u8 = 0x02;
+const ATA_SETFEATURES_XFER: u8 = 0x03;
+const ATA_SETFEATURES_SATA_ENABLE: u8 = 0x10;
+const ATA_SETFEATURES_RLA_OFF: u8 = 0x55;
+const ATA_SETFEATURES_WC_OFF: u8 = 0x82;
+const ATA_SETFEATURES_SATA_DISABLE: u8 = 0x90;
+const ATA_SETFEATURES_RLA_ON: u8 = 0xaa;
+
+pub type AhciIrqCallback = Arc<dyn Fn(bool) + Send + Sync>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct AhciCmdHeader {
+    /// Command FIS length, ATAPI, write and the PRD table length in bits 31:16.
+    flags: u32,
+    /// Number of bytes transferred.
+    prdbc: u32,
+    ctba: u64,
+    rsvd: [u32; 4],
+}
+
+impl ByteCode for AhciCmdHeader {}
+
+/// Physical region descriptor.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct AhciPrd {
+    dba: u64,
+    rsvd: u32,
+    /// Byte count which is 0's based in bits 21:0.
+    dbc: u32,
+}
+
+impl ByteCode for AhciPrd {}
+
+/// Registers reported in the FIS when the command completes.
+#[derive(Clone, Copy, Default)]
+struct AtaTaskFile {
+    error: u8,
+    count: u16,
+    lba: u64,
+}
+
+impl AtaTaskFile {
+    fn error(error: u8) -> Self {
+        AtaTaskFile {
+            error,
+            ..Default::default()
+        }
+    }
+
+    /// The signature is in the count and LBA registers.
+    fn signature(sig: u32) -> Self {
+        AtaTaskFile {
+            error: 0,
+            count: sig as u16 & 0xff,
+            lba: (sig >> 8) as u64,
+        }
+    }
+}
+
+/// The command being processed in the slot.
+#[derive(Clone, Copy, Default)]
+struct AhciSlot {
+    /// Address of the command header.
+    header: u64,
+    ncq: bool,
+    /// The status of the PIO data-in command is reported in the PIO setup FIS.
+    pio: bool,
+    atapi: bool,
+    read: bool,
+    /// Number of bytes transferred.
+    nbytes: u32,
+}
+
+#[derive(Default)]
+struct AhciPort {
+    clb: u64,
+    fb: u64,
+    is: u32,
+    ie: u32,
+    cmd: u32,
+    tfd: u32,
+    sig: u32,
+    sctl: u32,
+    serr: u32,
+    sact: u32,
+    ci: u32,
+    /// Slots of the commands which are being processed.
+    busy: u32,
+    slots: [AhciSlot; AHCI_MAX_SLOTS],
+    /// It's increased when the commands in flight are dropped, and their completions are ignored.
+    gen: u64,
+    /// The device is in the software reset state.
+    srst: bool,
+    init_d2h_sent: bool,
+    dev: Option<AtaDevice>,
+}
+
+impl AhciPort {
+    /// Stop processing the commands, and the commands in flight are dropped.
+    fn stop(&mut self) {
+        self.ci = 0;
+        self.sact = 0;
+        self.busy = 0;
+        self.gen += 1;
+    }
+
+    fn reset_device(&mut self) {
+        self.stop();
+        self.srst = false;
+        match self.dev.as_mut() {
+            Some(dev) => {
+                dev.reset();
+                self.sig = dev.signature();
+                // ATAPI device doesn't set DRDY after reset, and the error register is the
+                // diagnostic code which means no error.
+                let status = if dev.is_atapi() {
+                    0
+                } else {
+                    ATA_STAT_DRDY | ATA_STAT_DSC
+                };
+                self.tfd = 1 << 8 | status as u32;
+            }
+            None => {
+                self.sig = u32::MAX;
+                self.tfd = PORT_TFD_NO_DEVICE;
+            }
+        }
+    }
+}
+
+/// Completion of the command reported by the aio callbacks.
+struct AhciCompletion {
+    port: usize,
+    slot: usize,
+    gen: u64,
+    ok: bool,
+    sense: Option<ScsiSense>,
+}
+
+/// The commands are completed by the controller in the io handler, because the aio callbacks
+/// may be called when the controller is locked.
+pub struct AhciCompletions {
+    queue: Mutex<Vec<AhciCompletion>>,
+    kick_evt: Arc<EventFd>,
+}
+
+#[derive(Clone)]
+pub struct AhciCompleteCb {
+    completions: Arc<AhciCompletions>,
+    port: usize,
+    slot: usize,
+    gen: u64,
+}
+
+impl AhciCompleteCb {
+    fn complete(&self, ok: bool, sense: Option<ScsiSense>) {
+        self.completions.queue.lock().unwrap().push(AhciCompletion {
+            port: self.port,
+            slot: self.slot,
+            gen: self.gen,
+            ok,
+            sense,
+        });
+        if let Err(e) = self.completions.kick_evt.write(1) {
+            error!("Failed to kick ahci, {:?}", e);
+        }
+    }
+}
+
+pub fn ahci_aio_complete_cb(aiocb: &AioCb<AhciCompleteCb>, mut ret: i64) -> Result<()> {
+    match aiocb.req_is_completed(ret) {
+        AioReqResult::Inflight => return Ok(()),
+        AioReqResult::Error(v) => ret = v,
+        AioReqResult::Done => (),
+    }
+    if ret < 0 {
+        error!(
+            "Failed to execute ahci {} command, ret {}",
+            match aiocb.opcode {
+                OpCode::Preadv => "read",
+                OpCode::Pwritev => "write",
+                _ => "flush",
+            },
+            ret
+        );
+    }
+    aiocb.iocompletecb.complete(ret >= 0, None);
+    Ok(())
+}
+
+impl ScsiRequestOps for AhciCompleteCb {
+    fn scsi_request_complete_cb(&mut self, status: u8, scsisense: Option<ScsiSense>) -> Result<()> {
+        self.complete(status == GOOD, scsisense);
+        Ok(())
+    }
+}
+
+/// AHCI host bus adapter with 6 ports, each of which is attached with one ATA device at most.
+pub struct AhciCtrl {
+    id: String,
+    mem_space: Arc<AddressSpace>,
+    ghc: u32,
+    ports: Vec<AhciPort>,
+    irq: AhciIrqCallback,
+    completions: Arc<AhciCompletions>,
+    iothread: Option<String>,
+    /// The backend meets unrecoverable error.
+    pub broken: Arc<AtomicBool>,
+}
+
+impl AhciCtrl {
+    pub fn new(
+        id: &str,
+        mem_space: &Arc<AddressSpace>,
+        kick_evt: Arc<EventFd>,
+        iothread: Option<String>,
+    ) -> Self {
+        let mut ctrl = AhciCtrl {
+            id: id.to_string(),
+            mem_space: mem_space.clone(),
+            ghc: AHCI_GHC_AE,
+            ports: (0..AHCI_MAX_PORTS).map(|_| AhciPort::default()).collect(),
+            irq: Arc::new(|_| {}),
+            completions: Arc::new(AhciCompletions {
+                queue: Mutex::new(Vec::new()),
+                kick_evt,
+            }),
+            iothread,
+            broken: Arc::new(AtomicBool::new(false)),
+        };
+        ctrl.reset();
+        ctrl
+    }
+
+    /// Attach the device to the port, and open its drive.
+    pub fn attach_device(&mut self, mut dev: AtaDevice) -> Result<()> {
+        let idx = dev.config.port as usize;
+        let port = &mut self.ports[idx];
+        if let Some(old) = port.dev.as_ref() {
+            bail!(
+                "Port {} of ahci {} has been used by {}",
+                idx,
+                self.id,
+                old.config.id
+            );
+        }
+        dev.realize(self.iothread.clone(), self.broken.clone())?;
+        port.dev = Some(dev);
+        port.reset_device();
+        Ok(())
+    }
+
+    pub fn unrealize(&mut self) -> Result<()> {
+        for dev in self.ports.iter_mut().filter_map(|port| port.dev.as_mut()) {
+            dev.unrealize()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_irq_callback(&mut self, irq: AhciIrqCallback) {
+        self.irq = irq;
+    }
+
+    /// HBA reset, which is caused by the PCI reset or the GHC.HR bit.
+    pub fn reset(&mut self) {
+        self.ghc = AHCI_GHC_AE;
+        for port in self.ports.iter_mut() {
+            port.clb = 0;
+            port.fb = 0;
+            port.is = 0;
+            port.ie = 0;
+            port.cmd = PORT_CMD_SUD | PORT_CMD_POD;
+            port.sctl = 0;
+            port.serr = 0;
+            port.init_d2h_sent = false;
+            port.reset_device();
+        }
+        self.broken.store(false, Ordering::SeqCst);
+        self.update_irq();
+    }
+
+    fn irq_status(&self) -> u32 {
+        self.ports
+            .iter()
+            .enumerate()
+            .filter(|(_, port)| port.is & port.ie != 0)
+            .fold(0, |is, (idx, _)| is | 1 << idx)
+    }
+
+    fn update_irq(&self) {
+        (self.irq)(self.ghc & AHCI_GHC_IE != 0 && self.irq_status() != 0);
+    }
+
+    fn kick(&self) {
+        if let Err(e) = self.completions.kick_evt.write(1) {
+            error!("Failed to kick ahci {}, {:?}", self.id, e);
+        }
+    }
+
+    fn reg_dword(&self, offset: u64) -> u32 {
+        if offset < AHCI_PORT_BASE {
+            return match offset {
+                AHCI_REG_CAP => {
+                    (AHCI_MAX_PORTS as u32 - 1)
+                        | (AHCI_MAX_SLOTS as u32 - 1) << AHCI_CAP_NCS_SHIFT
+                        | AHCI_CAP_ISS_GEN1
+                        | AHCI_CAP_SAM
+                        | AHCI_CAP_SNCQ
+                        | AHCI_CAP_S64A
+                }
+                AHCI_REG_GHC => self.ghc,
+                AHCI_REG_IS => self.irq_status(),
+                AHCI_REG_PI => (1 << AHCI_MAX_PORTS) - 1,
+                AHCI_REG_VS => AHCI_VERSION,
+                _ => 0,
+            };
+        }
+        let idx = ((offset - AHCI_PORT_BASE) / AHCI_PORT_SIZE) as usize;
+        let Some(port) = self.ports.get(idx) else {
+            return 0;
+        };
+        match (offset - AHCI_PORT_BASE) % AHCI_PORT_SIZE {
+            PORT_REG_CLB => port.clb as u32,
+            PORT_REG_CLBU => (port.clb >> 32) as u32,
+            PORT_REG_FB => port.fb as u32,
+            PORT_REG_FBU => (port.fb >> 32) as u32,
+            PORT_REG_IS => port.is,
+            PORT_REG_IE => port.ie,
+            PORT_REG_CMD => port.cmd,
+            PORT_REG_TFD => port.tfd,
+            PORT_REG_SIG => port.sig,
+            PORT_REG_SSTS
+                if port.dev.is_some()
+                    && port.sctl & PORT_SCTL_DET_MASK != PORT_SCTL_DET_COMRESET =>
+            {
+                PORT_SSTS_ACTIVE
+            }
+            PORT_REG_SCTL => port.sctl,
+            PORT_REG_SERR => port.serr,
+            PORT_REG_SACT => port.sact,
+            PORT_REG_CI => port.ci,
+            _ => 0,
+        }
+    }
+
+    pub fn read_reg(&self, offset: u64, data: &mut [u8]) -> bool {
+        if data.len() > 4 || offset & 3 != 0 && data.len() + (offset & 3) as usize > 4 {
+            warn!(
+                "Invalid ahci register read, offset 0x{:x} len {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        let val = self.reg_dword(offset & !3) >> ((offset & 3) * 8);
+        let len = data.len();
+        data.copy_from_slice(&val.to_le_bytes()[..len]);
+        true
+    }
+
+    pub fn write_reg(&mut self, offset: u64, data: &[u8]) -> bool {
+        if data.len() != 4 || offset & 3 != 0 {
+            warn!(
+                "Invalid ahci register write, offset 0x{:x} len {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        let val = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        if offset < AHCI_PORT_BASE {
+            match offset {
+                AHCI_REG_GHC => {
+                    if val & AHCI_GHC_HR != 0 {
+                        // The reset is done immediately, so the HR bit is always 0.
+                        self.reset();
+                    } else {
+                        self.ghc = AHCI_GHC_AE | val & AHCI_GHC_IE;
+                        self.update_irq();
+                    }
+                }
+                // The interrupt status is derived from the ports.
+                AHCI_REG_IS => (),
+                _ => warn!("Write to read-only ahci register 0x{:x}", offset),
+            }
+            return true;
+        }
+        let idx = ((offset - AHCI_PORT_BASE) / AHCI_PORT_SIZE) as usize;
+        if idx < self.ports.len() {
+            self.write_port_reg(idx, (offset - AHCI_PORT_BASE) % AHCI_PORT_SIZE, val);
+        }
+        true
+    }
+
+    fn write_port_reg(&mut self, idx: usize, offset: u64, val: u32) {
+        let port = &mut self.ports[idx];
+        match offset {
+            PORT_REG_CLB => port.clb = port.clb & !0xffff_ffff | (val & !0x3ff) as u64,
+            PORT_REG_CLBU => port.clb = port.clb & 0xffff_ffff | (val as u64) << 32,
+            PORT_REG_FB => port.fb = port.fb & !0xffff_ffff | (val & !0xff) as u64,
+            PORT_REG_FBU => port.fb = port.fb & 0xffff_ffff | (val as u64) << 32,
+            PORT_REG_IS => {
+                port.is &= !val;
+                self.update_irq();
+            }
+            PORT_REG_IE => {
+                port.ie = val & PORT_IRQ_MASK;
+                self.update_irq();
+            }
+            PORT_REG_CMD => self.write_port_cmd(idx, val),
+            PORT_REG_SCTL => {
+                let old = port.sctl;
+                port.sctl = val;
+                // COMRESET is done when the DET is changed from 1 to 0.
+                if old & PORT_SCTL_DET_MASK == PORT_SCTL_DET_COMRESET
+                    && val & PORT_SCTL_DET_MASK == 0
+                {
+                    port.reset_device();
+                    self.post_signature(idx);
+                }
+            }
+            PORT_REG_SERR => port.serr &= !val,
+            PORT_REG_SACT if port.cmd & PORT_CMD_ST != 0 => port.sact |= val,
+            PORT_REG_CI if port.cmd & PORT_CMD_ST != 0 => {
+                port.ci |= val;
+                self.kick();
+            }
+            _ => (),
+        }
+    }
+
+    fn write_port_cmd(&mut self, idx: usize, val: u32) {
+        let port = &mut self.ports[idx];
+        let old = port.cmd;
+        let mut cmd =
+            old & !(PORT_CMD_WRITABLE | PORT_CMD_CR | PORT_CMD_FR) | val & PORT_CMD_WRITABLE;
+        if cmd & PORT_CMD_ST != 0 {
+            cmd |= PORT_CMD_CR;
+        }
+        if cmd & PORT_CMD_FRE != 0 {
+            cmd |= PORT_CMD_FR;
+        }
+        port.cmd = cmd;
+        if val & PORT_CMD_CLO != 0 {
+            // Command list override clears BSY and DRQ.
+            port.tfd &= !0x88;
+        }
+        if old & PORT_CMD_ST != 0 && cmd & PORT_CMD_ST == 0 {
+            port.stop();
+        }
+        if old & PORT_CMD_FRE == 0 && cmd & PORT_CMD_FRE != 0 && !port.init_d2h_sent {
+            self.post_signature(idx);
+        }
+        if old & PORT_CMD_ST == 0 && cmd & PORT_CMD_ST != 0 && self.ports[idx].ci != 0 {
+            self.kick();
+        }
+    }
+
+    /// Post the D2H register FIS with the signature after the device is reset.
+    fn post_signature(&mut self, idx: usize) {
+        let port = &mut self.ports[idx];
+        if port.dev.is_none() || port.cmd & PORT_CMD_FRE == 0 {
+            return;
+        }
+        port.init_d2h_sent = true;
+        let fis = d2h_fis(
+            port.tfd as u8,
+            (port.tfd >> 8) as u8,
+            &AtaTaskFile::signature(port.sig),
+            false,
+        );
+        self.write_fis(idx, RX_FIS_D2H, &fis);
+    }
+
+    fn write_fis(&self, idx: usize, offset: u64, fis: &[u8]) {
+        let port = &self.ports[idx];
+        if port.cmd & PORT_CMD_FRE == 0 {
+            return;
+        }
+        if let Err(e) = self.mem_space.write(
+            &mut &fis[..],
+            GuestAddress(port.fb + offset),
+            fis.len() as u64,
+        ) {
+            error!("Failed to write ahci fis, {:?}", e);
+        }
+    }
+
+    /// Execute the issued commands, and complete the commands done by the aio callbacks.
+    pub fn process(&mut self) {
+        for idx in 0..self.ports.len() {
+            self.process_port(idx);
+        }
+        let completions = std::mem::take(&mut *self.completions.queue.lock().unwrap());
+        for c in completions {
+            self.aio_done(c);
+        }
+    }
+
+    fn process_port(&mut self, idx: usize) {
+        let port = &self.ports[idx];
+        if port.cmd & PORT_CMD_ST == 0 || port.dev.is_none() {
+            return;
+        }
+        let mut pending = port.ci & !port.busy;
+        let mut submitted = false;
+        while pending != 0 {
+            let slot = pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+            self.ports[idx].busy |= 1 << slot;
+            match self.exec_cmd(idx, slot) {
+                Some(tf) => self.complete_cmd(idx, slot, tf),
+                None => submitted = true,
+            }
+        }
+        if submitted {
+            if let Some(disk) = self.ports[idx].dev.as_ref().and_then(|dev| dev.disk()) {
+                if let Err(e) = disk.lock().unwrap().flush_request() {
+                    error!("Failed to flush ahci requests, {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn aio_done(&mut self, c: AhciCompletion) {
+        let port = &mut self.ports[c.port];
+        if c.gen != port.gen {
+            return;
+        }
+        let slot = port.slots[c.slot];
+        let tf = if slot.atapi {
+            match c.sense {
+                Some(sense) if !c.ok => self.atapi_error(c.port, sense),
+                _ if !c.ok => self.atapi_error(c.port, SCSI_SENSE_IO_ERROR),
+                _ => AtaTaskFile {
+                    count: ATAPI_INTR_IO_CD,
+                    ..Default::default()
+                },
+            }
+        } else if c.ok {
+            AtaTaskFile::default()
+        } else if slot.read {
+            AtaTaskFile::error(ATA_ERR_UNC)
+        } else {
+            AtaTaskFile::error(ATA_ERR_ABRT)
+        };
+        self.complete_cmd(c.port, c.slot, tf);
+    }
+
+    fn complete_cmd(&mut self, idx: usize, slot_idx: usize, tf: AtaTaskFile) {
+        let bit = 1 << slot_idx;
+        let port = &mut self.ports[idx];
+        if port.busy & bit == 0 {
+            return;
+        }
+        port.busy &= !bit;
+        port.ci &= !bit;
+        let slot = port.slots[slot_idx];
+        let mut status = ATA_STAT_DRDY | ATA_STAT_DSC;
+        if tf.error != 0 {
+            status |= ATA_STAT_ERR;
+            port.is |= PORT_IRQ_TFES;
+        }
+        port.tfd = (tf.error as u32) << 8 | status as u32;
+
+        if slot.ncq {
+            port.sact &= !bit;
+            port.is |= PORT_IRQ_SDBS;
+            let mut fis = [0_u8; 8];
+            fis[0] = FIS_TYPE_SDB;
+            fis[1] = FIS_INTERRUPT;
+            fis[2] = status & 0x77;
+            fis[3] = tf.error;
+            fis[4..8].copy_from_slice(&bit.to_le_bytes());
+            self.write_fis(idx, RX_FIS_SDB, &fis);
+        } else {
+            if let Err(e) = self
+                .mem_space
+                .write_object(&slot.nbytes, GuestAddress(slot.header + 4))
+            {
+                error!("Failed to write ahci command header, {:?}", e);
+            }
+            if slot.pio {
+                port.is |= PORT_IRQ_PSS;
+                let mut fis = d2h_fis(status, tf.error, &tf, true);
+                fis[0] = FIS_TYPE_PIO_SETUP;
+                fis[1] |= FIS_PIO_D2H;
+                // The ending status and the transfer count.
+                fis[15] = status;
+                fis[16..18].copy_from_slice(&(slot.nbytes.min(0xffff) as u16).to_le_bytes());
+                self.write_fis(idx, RX_FIS_PIO_SETUP, &fis);
+            }
+            self.ports[idx].is |= PORT_IRQ_DHRS;
+            let fis = d2h_fis(status, tf.error, &tf, true);
+            self.write_fis(idx, RX_FIS_D2H, &fis);
+        }
+        self.update_irq();
+    }
+
+    /// Returns None if the command is not completed right now.
+    fn exec_cmd(&mut self, idx: usize, slot: usize) -> Option<AtaTaskFile> {
+        let header_addr = self.ports[idx].clb + slot as u64 * CMD_HDR_SIZE;
+        self.ports[idx].slots[slot] = AhciSlot {
+            header: header_addr,
+            ..Default::default()
+        };
+        let header = match self
+            .mem_space
+            .read_object::<AhciCmdHeader>(GuestAddress(header_addr))
+        {
+            Ok(header) => header,
+            Err(e) => {
+                error!("Failed to fetch ahci command header, {:?}", e);
+                self.ports[idx].is |= PORT_IRQ_HBFS;
+                return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+            }
+        };
+        let mut cfis = [0_u8; FIS_H2D_SIZE];
+        if let Err(e) = self.mem_space.read(
+            &mut cfis.as_mut(),
+            GuestAddress(header.ctba),
+            FIS_H2D_SIZE as u64,
+        ) {
+            error!("Failed to fetch ahci command fis, {:?}", e);
+            self.ports[idx].is |= PORT_IRQ_HBFS;
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        }
+        if cfis[0] != FIS_TYPE_REG_H2D {
+            warn!("Unsupported ahci fis type 0x{:x}", cfis[0]);
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        }
+        if cfis[1] & FIS_H2D_C == 0 {
+            self.write_device_control(idx, slot, cfis[15]);
+            return None;
+        }
+
+        let port = &mut self.ports[idx];
+        // The device is attached because the commands are processed.
+        let dev = port.dev.as_mut().unwrap();
+        let atapi = dev.is_atapi();
+        let tf = match cfis[2] {
+            ATA_CMD_IDENTIFY if atapi => {
+                // ATAPI device aborts the command, and reports the signature.
+                let mut tf = AtaTaskFile::signature(dev.signature());
+                tf.error = ATA_ERR_ABRT;
+                tf
+            }
+            ATA_CMD_IDENTIFY | ATA_CMD_IDENTIFY_PACKET
+                if atapi == (cfis[2] == ATA_CMD_IDENTIFY_PACKET) =>
+            {
+                let id = dev.identify();
+                return Some(self.pio_data_in(idx, slot, &header, &id));
+            }
+            ATA_CMD_READ_DMA
+            | ATA_CMD_READ_DMA_EXT
+            | ATA_CMD_WRITE_DMA
+            | ATA_CMD_WRITE_DMA_EXT
+            | ATA_CMD_READ_SECTORS
+            | ATA_CMD_READ_SECTORS_EXT
+            | ATA_CMD_WRITE_SECTORS
+            | ATA_CMD_WRITE_SECTORS_EXT
+            | ATA_CMD_READ_MULTIPLE
+            | ATA_CMD_READ_MULTIPLE_EXT
+            | ATA_CMD_WRITE_MULTIPLE
+            | ATA_CMD_WRITE_MULTIPLE_EXT
+            | ATA_CMD_READ_FPDMA_QUEUED
+            | ATA_CMD_WRITE_FPDMA_QUEUED
+                if !atapi =>
+            {
+                return self.ata_rw(idx, slot, &header, &cfis);
+            }
+            ATA_CMD_FLUSH_CACHE | ATA_CMD_FLUSH_CACHE_EXT if !atapi => {
+                return self.ata_flush(idx, slot);
+            }
+            ATA_CMD_PACKET if atapi => return self.atapi_cmd(idx, slot, &header, &cfis),
+            ATA_CMD_DEVICE_RESET if atapi => {
+                dev.reset();
+                AtaTaskFile::signature(dev.signature())
+            }
+            ATA_CMD_EXEC_DEVICE_DIAGNOSTIC => {
+                dev.reset();
+                AtaTaskFile::signature(dev.signature())
+            }
+            ATA_CMD_SET_FEATURES => match cfis[3] {
+                ATA_SETFEATURES_WC_ON | ATA_SETFEATURES_WC_OFF => {
+                    dev.set_write_cache(cfis[3] == ATA_SETFEATURES_WC_ON);
+                    AtaTaskFile::default()
+                }
+                ATA_SETFEATURES_XFER
+                | ATA_SETFEATURES_SATA_ENABLE
+                | ATA_SETFEATURES_SATA_DISABLE
+                | ATA_SETFEATURES_RLA_ON
+                | ATA_SETFEATURES_RLA_OFF => AtaTaskFile::default(),
+                _ => AtaTaskFile::error(ATA_ERR_ABRT),
+            },
+            ATA_CMD_SET_MULTIPLE if !atapi => {
+                let count = cfis[12];
+                if count > ATA_MAX_MULTIPLE_SECTORS || count & count.wrapping_sub(1) != 0 {
+                    AtaTaskFile::error(ATA_ERR_ABRT)
+                } else {
+                    dev.multiple = count;
+                    AtaTaskFile::default()
+                }
+            }
+            ATA_CMD_READ_VERIFY | ATA_CMD_READ_VERIFY_EXT if !atapi => {
+                let lba48 = cfis[2] == ATA_CMD_READ_VERIFY_EXT;
+                let (lba, count) = ata_lba_count(&cfis, lba48);
+                if lba.checked_add(count).is_none_or(|end| end > dev.sectors) {
+                    AtaTaskFile::error(ATA_ERR_IDNF)
+                } else {
+                    AtaTaskFile::default()
+                }
+            }
+            ATA_CMD_CHECK_POWER_MODE => AtaTaskFile {
+                // The device is in the active or idle mode.
+                count: 0xff,
+                ..Default::default()
+            },
+            ATA_CMD_STANDBY_IMMEDIATE
+            | ATA_CMD_IDLE_IMMEDIATE
+            | ATA_CMD_STANDBY
+            | ATA_CMD_IDLE
+            | ATA_CMD_SEEK
+            | ATA_CMD_INIT_DEVICE_PARAMS => AtaTaskFile::default(),
+            cmd => {
+                if cmd != ATA_CMD_DSM {
+                    warn!("Unsupported ata command 0x{:x}", cmd);
+                }
+                AtaTaskFile::error(ATA_ERR_ABRT)
+            }
+        };
+        Some(tf)
+    }
+
+    /// The device control register is written in the FIS without the C bit, which is used
+    /// by the software reset.
+    fn write_device_control(&mut self, idx: usize, slot: usize, control: u8) {
+        let bit = 1 << slot;
+        let port = &mut self.ports[idx];
+        port.busy &= !bit;
+        port.ci &= !bit;
+        if control & ATA_CTL_SRST != 0 {
+            port.srst = true;
+        } else if port.srst {
+            let ci = port.ci;
+            port.reset_device();
+            // The other slots are not affected by the reset.
+            port.ci = ci;
+            self.post_signature(idx);
+        }
+    }
+
+    /// Map the PRD table to the host iovecs, which are limited to `len` bytes if it's set.
+    fn map_prdt(&self, header: &AhciCmdHeader, len: Option<u64>) -> Option<Vec<Iovec>> {
+        let mut iovecs: Vec<Iovec> = Vec::new();
+        let mut mapped = 0;
+        let prdtl = (header.flags >> CMD_HDR_PRDTL_SHIFT) as u64;
+        for i in 0..prdtl {
+            if len.is_some_and(|len| mapped >= len) {
+                break;
+            }
+            let addr = header.ctba + CMD_TBL_PRDT + i * PRD_SIZE;
+            let prd = self
+                .mem_space
+                .read_object::<AhciPrd>(GuestAddress(addr))
+                .ok()?;
+            let mut size = (prd.dbc & PRD_DBC_MASK) as u64 + 1;
+            if let Some(len) = len {
+                size = size.min(len - mapped);
+            }
+            for iov in self
+                .mem_space
+                .get_address_map(GuestAddress(prd.dba), size)
+                .ok()?
+            {
+                match iovecs.last_mut() {
+                    Some(last) if last.iov_base + last.iov_len == iov.iov_base => {
+                        last.iov_len += iov.iov_len
+                    }
+                    _ => iovecs.push(iov),
+                }
+            }
+            mapped += size;
+        }
+        if len.is_some_and(|len| mapped < len) {
+            return None;
+        }
+        Some(iovecs)
+    }
+
+    fn pio_data_in(
+        &mut self,
+        idx: usize,
+        slot: usize,
+        header: &AhciCmdHeader,
+        data: &[u8],
+    ) -> AtaTaskFile {
+        let Some(iovecs) = self.map_prdt(header, Some(data.len() as u64)) else {
+            return AtaTaskFile::error(ATA_ERR_ABRT);
+        };
+        if let Err(e) = iov_from_buf_direct(&iovecs, data) {
+            error!("Failed to write ahci data, {:?}", e);
+            return AtaTaskFile::error(ATA_ERR_ABRT);
+        }
+        let slot = &mut self.ports[idx].slots[slot];
+        slot.pio = true;
+        slot.nbytes = data.len() as u32;
+        AtaTaskFile::default()
+    }
+
+    fn complete_cb(&self, idx: usize, slot: usize) -> AhciCompleteCb {
+        AhciCompleteCb {
+            completions: self.completions.clone(),
+            port: idx,
+            slot,
+            gen: self.ports[idx].gen,
+        }
+    }
+
+    fn ata_rw(
+        &mut self,
+        idx: usize,
+        slot: usize,
+        header: &AhciCmdHeader,
+        cfis: &[u8; FIS_H2D_SIZE],
+    ) -> Option<AtaTaskFile> {
+        let cmd = cfis[2];
+        let ncq = matches!(cmd, ATA_CMD_READ_FPDMA_QUEUED | ATA_CMD_WRITE_FPDMA_QUEUED);
+        let write = matches!(
+            cmd,
+            ATA_CMD_WRITE_DMA
+                | ATA_CMD_WRITE_DMA_EXT
+                | ATA_CMD_WRITE_SECTORS
+                | ATA_CMD_WRITE_SECTORS_EXT
+                | ATA_CMD_WRITE_MULTIPLE
+                | ATA_CMD_WRITE_MULTIPLE_EXT
+                | ATA_CMD_WRITE_FPDMA_QUEUED
+        );
+        let pio = matches!(
+            cmd,
+            ATA_CMD_READ_SECTORS
+                | ATA_CMD_READ_SECTORS_EXT
+                | ATA_CMD_READ_MULTIPLE
+                | ATA_CMD_READ_MULTIPLE_EXT
+        );
+        let lba48 = ncq
+            || matches!(
+                cmd,
+                ATA_CMD_READ_DMA_EXT
+                    | ATA_CMD_WRITE_DMA_EXT
+                    | ATA_CMD_READ_SECTORS_EXT
+                    | ATA_CMD_WRITE_SECTORS_EXT
+                    | ATA_CMD_READ_MULTIPLE_EXT
+                    | ATA_CMD_WRITE_MULTIPLE_EXT
+            );
+        let (lba, count) = if ncq {
+            // The sector count is in the features registers, and the tag is in the count.
+            let (lba, _) = ata_lba_count(cfis, true);
+            let count = cfis[3] as u64 | (cfis[11] as u64) << 8;
+            (lba, if count == 0 { 0x10000 } else { count })
+        } else {
+            ata_lba_count(cfis, lba48)
+        };
+
+        let port = &self.ports[idx];
+        let dev = port.dev.as_ref().unwrap();
+        if ncq && ((cfis[12] >> 3) as usize != slot || port.sact & 1 << slot == 0) {
+            warn!("Invalid ncq tag {} in slot {}", cfis[12] >> 3, slot);
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        }
+        let multiple = matches!(
+            cmd,
+            ATA_CMD_READ_MULTIPLE
+                | ATA_CMD_READ_MULTIPLE_EXT
+                | ATA_CMD_WRITE_MULTIPLE
+                | ATA_CMD_WRITE_MULTIPLE_EXT
+        );
+        if write && dev.read_only() || multiple && dev.multiple == 0 {
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        }
+        if lba.checked_add(count).is_none_or(|end| end > dev.sectors) {
+            return Some(AtaTaskFile::error(ATA_ERR_IDNF));
+        }
+        let nbytes = count << ATA_SECTOR_SHIFT;
+        let Some(iovecs) = self.map_prdt(header, Some(nbytes)) else {
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        };
+        // The disk is opened when the ata device is attached.
+        let backend = dev.disk().unwrap();
+        let cb = self.complete_cb(idx, slot);
+        let port = &mut self.ports[idx];
+        port.slots[slot].ncq = ncq;
+        port.slots[slot].pio = pio;
+        port.slots[slot].read = !write;
+        port.slots[slot].nbytes = nbytes as u32;
+        if ncq {
+            // The queued command is accepted, and the status is reported in the SDB FIS.
+            port.ci &= !(1 << slot);
+        }
+
+        let offset = (lba << ATA_SECTOR_SHIFT) as usize;
+        let mut locked_backend = backend.lock().unwrap();
+        let ret = if write {
+            locked_backend.write_vectored(iovecs, offset, cb)
+        } else {
+            locked_backend.read_vectored(iovecs, offset, cb)
+        };
+        match ret {
+            Ok(()) => None,
+            Err(e) => {
+                error!("Failed to submit ata command 0x{:x}, {:?}", cmd, e);
+                Some(AtaTaskFile::error(ATA_ERR_ABRT))
+            }
+        }
+    }
+
+    fn ata_flush(&mut self, idx: usize, slot: usize) -> Option<AtaTaskFile> {
+        let backend = self.ports[idx].dev.as_ref().unwrap().disk().unwrap();
+        let cb = self.complete_cb(idx, slot);
+        let ret = backend.lock().unwrap().datasync(cb);
+        match ret {
+            Ok(()) => None,
+            Err(e) => {
+                error!("Failed to flush ata device, {:?}", e);
+                Some(AtaTaskFile::error(ATA_ERR_ABRT))
+            }
+        }
+    }
+
+    /// The packet command fails with the sense data, and the sense key is in the error register.
+    fn atapi_error(&mut self, idx: usize, sense: ScsiSense) -> AtaTaskFile {
+        let dev = self.ports[idx].dev.as_mut().unwrap();
+        dev.set_sense(sense);
+        let key = dev.sense_key();
+        AtaTaskFile {
+            error: if key == 0 { ATA_ERR_ABRT } else { key << 4 },
+            count: ATAPI_INTR_IO_CD,
+            lba: 0,
+        }
+    }
+
+    fn atapi_cmd(
+        &mut self,
+        idx: usize,
+        slot: usize,
+        header: &AhciCmdHeader,
+        cfis: &[u8; FIS_H2D_SIZE],
+    ) -> Option<AtaTaskFile> {
+        let mut cdb = [0_u8; SCSI_CMD_BUF_SIZE];
+        if let Err(e) = self.mem_space.read(
+            &mut cdb.as_mut(),
+            GuestAddress(header.ctba + CMD_TBL_ACMD),
+            SCSI_CMD_BUF_SIZE as u64,
+        ) {
+            error!("Failed to fetch atapi command, {:?}", e);
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        }
+        {
+            let slot = &mut self.ports[idx].slots[slot];
+            slot.atapi = true;
+            // Bit 0 of the features register means DMA.
+            slot.pio = cfis[3] & 1 == 0;
+        }
+
+        let dev = self.ports[idx].dev.as_mut().unwrap();
+        match cdb[0] {
+            // The sense data is kept by the ATAPI device rather than the scsi device.
+            REQUEST_SENSE => {
+                let sense = dev.take_sense();
+                let len = ATAPI_SENSE_SIZE.min(cdb[4] as usize);
+                let pio = self.ports[idx].slots[slot].pio;
+                let mut tf = self.pio_data_in(idx, slot, header, &sense[..len]);
+                self.ports[idx].slots[slot].pio = pio;
+                tf.count = ATAPI_INTR_IO_CD;
+                return Some(tf);
+            }
+            // There is only one lun.
+            REPORT_LUNS => return Some(self.atapi_error(idx, SCSI_SENSE_INVALID_OPCODE)),
+            _ => (),
+        }
+
+        // The device is a cdrom because the packet command is accepted.
+        let cdrom = dev.cdrom().unwrap();
+        let xfer = scsi_cdb_xfer(&cdb, cdrom.clone());
+        if xfer < 0 {
+            return Some(self.atapi_error(idx, SCSI_SENSE_INVALID_OPCODE));
+        }
+        let Some(iovecs) = self.map_prdt(header, None) else {
+            return Some(AtaTaskFile::error(ATA_ERR_ABRT));
+        };
+        let (iovecs, _) = iovecs_split(iovecs, xfer as u64);
+        let datalen = get_iov_size(&iovecs) as u32;
+        self.ports[idx].slots[slot].nbytes = datalen;
+
+        let cb = self.complete_cb(idx, slot);
+        let sreq = match ScsiRequest::new(cdb, 0, iovecs, datalen, cdrom, Box::new(cb)) {
+            Ok(sreq) => sreq,
+            Err(e) => {
+                warn!("Invalid atapi command 0x{:x}, {:?}", cdb[0], e);
+                return Some(self.atapi_error(idx, SCSI_SENSE_INVALID_OPCODE));
+            }
+        };
+        if matches!(sreq.cmd.mode, ScsiXferMode::ScsiXferToDev) {
+            return Some(self.atapi_error(idx, SCSI_SENSE_WRITE_PROTECTED));
+        }
+        let ret = if sreq.opstype == EMULATE_SCSI_OPS {
+            sreq.emulate_execute()
+        } else {
+            sreq.execute()
+        };
+        if let Err(e) = ret {
+            error!("Failed to execute atapi command 0x{:x}, {:?}", cdb[0], e);
+            return Some(self.atapi_error(idx, SCSI_SENSE_IO_ERROR));
+        }
+        None
+    }
+}
+
+/// Returns the LBA and the number of sectors in the register FIS.
+fn ata_lba_count(cfis: &[u8; FIS_H2D_SIZE], lba48: bool) -> (u64, u64) {
+    if lba48 {
+        let lba = cfis[4..7]
+            .iter()
+            .chain(cfis[8..11].iter())
+            .enumerate()
+            .fold(0, |lba, (i, byte)| lba | (*byte as u64) << (i * 8));
+        let count = cfis[12] as u64 | (cfis[13] as u64) << 8;
+        (lba, if count == 0 { 0x10000 } else { count })
+    } else {
+        let lba = cfis[4] as u64
+            | (cfis[5] as u64) << 8
+            | (cfis[6] as u64) << 16
+            | ((cfis[7] & 0xf) as u64) << 24;
+        let count = cfis[12] as u64;
+        (lba, if count == 0 { 0x100 } else { count })
+    }
+}
+
+fn d2h_fis(status: u8, error: u8, tf: &AtaTaskFile, irq: bool) -> [u8; 20] {
+    let mut fis = [0_u8; 20];
+    fis[0] = FIS_TYPE_REG_D2H;
+    if irq {
+        fis[1] = FIS_INTERRUPT;
+    }
+    fis[2] = status;
+    fis[3] = error;
+    for i in 0..3 {
+        fis[4 + i] = (tf.lba >> (i * 8)) as u8;
+        fis[8 + i] = (tf.lba >> (24 + i * 8)) as u8;
+    }
+    fis[12..14].copy_from_slice(&tf.count.to_le_bytes());
+    fis
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use address_space::{HostMemMapping, Region};
+    use machine_manager::config::{DriveConfig, IdeDevConfig, VmConfig};
+    use machine_manager::event_loop::EventLoop;
+    use util::aio::AioEngine;
+
+    const CLB_ADDR: u64 = 0x10000;
+    const FB_ADDR: u64 = 0x11000;
+    const CMD_TBL_ADDR: u64 = 0x20000;
+    const DATA_ADDR: u64 = 0x40000;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36, "root");
+        let sys_space = AddressSpace::new(root, "sys_space").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x100_0000, None, false, false, false)
+                .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone(), "region_1"),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn new_ctrl(mem_space: &Arc<AddressSpace>, path: &str, atapi: bool) -> AhciCtrl {
+        let mut drive_files = HashMap::new();
+        VmConfig::add_drive_file(&mut drive_files, "drive0", path, atapi, false).unwrap();
+        let config = IdeDevConfig {
+            id: "disk0".to_string(),
+            cntlr: "ahci0".to_string(),
+            port: 0,
+            serial: Some("deadbeef".to_string()),
+            drive: DriveConfig {
+                id: "drive0".to_string(),
+                path_on_host: path.to_string(),
+                read_only: atapi,
+                direct: false,
+                aio: AioEngine::Off,
+                media: if atapi { "cdrom" } else { "disk" }.to_string(),
+                ..Default::default()
+            },
+            key_secret: None,
+        };
+        let mut ctrl = AhciCtrl::new(
+            "ahci0",
+            mem_space,
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            None,
+        );
+        let dev = AtaDevice::new(config, atapi, Arc::new(Mutex::new(drive_files)));
+        ctrl.attach_device(dev).unwrap();
+        ctrl
+    }
+
+    fn read_port_reg(ctrl: &AhciCtrl, port: u64, offset: u64) -> u32 {
+        let mut data = [0_u8; 4];
+        assert!(ctrl.read_reg(AHCI_PORT_BASE + port * AHCI_PORT_SIZE + offset, &mut data));
+        u32::from_le_bytes(data)
+    }
+
+    fn write_port_reg(ctrl: &mut AhciCtrl, offset: u64, val: u32) {
+        assert!(ctrl.write_reg(AHCI_PORT_BASE + offset, &val.to_le_bytes()));
+    }
+
+    fn start_port(ctrl: &mut AhciCtrl) {
+        write_port_reg(ctrl, PORT_REG_CLB, CLB_ADDR as u32);
+        write_port_reg(ctrl, PORT_REG_FB, FB_ADDR as u32);
+        write_port_reg(ctrl, PORT_REG_IE, PORT_IRQ_MASK);
+        write_port_reg(ctrl, PORT_REG_CMD, PORT_CMD_FRE | PORT_CMD_ST);
+    }
+
+    /// Build the command in the slot, issue it and returns the task file data.
+    fn issue(
+        ctrl: &mut AhciCtrl,
+        mem_space: &AddressSpace,
+        slot: u64,
+        cfis: [u8; FIS_H2D_SIZE],
+        acmd: Option<[u8; SCSI_CMD_BUF_SIZE]>,
+        len: u32,
+    ) -> u32 {
+        let ctba = CMD_TBL_ADDR + slot * 0x1000;
+        let header = AhciCmdHeader {
+            flags: 1 << CMD_HDR_PRDTL_SHIFT | 5,
+            prdbc: 0,
+            ctba,
+            rsvd: [0; 4],
+        };
+        mem_space
+            .write_object(&header, GuestAddress(CLB_ADDR + slot * CMD_HDR_SIZE))
+            .unwrap();
+        mem_space
+            .write(&mut cfis.as_ref(), GuestAddress(ctba), FIS_H2D_SIZE as u64)
+            .unwrap();
+        if let Some(acmd) = acmd {
+            mem_space
+                .write(
+                    &mut acmd.as_ref(),
+                    GuestAddress(ctba + CMD_TBL_ACMD),
+                    SCSI_CMD_BUF_SIZE as u64,
+                )
+                .unwrap();
+        }
+        let prd = AhciPrd {
+            dba: DATA_ADDR,
+            rsvd: 0,
+            dbc: len - 1,
+        };
+        mem_space
+            .write_object(&prd, GuestAddress(ctba + CMD_TBL_PRDT))
+            .unwrap();
+        write_port_reg(ctrl, PORT_REG_CI, 1 << slot);
+        ctrl.process();
+        assert_eq!(read_port_reg(ctrl, 0, PORT_REG_CI), 0);
+        read_port_reg(ctrl, 0, PORT_REG_TFD)
+    }
+
+    fn ata_cmd(cmd: u8, lba: u64, count: u16) -> [u8; FIS_H2D_SIZE] {
+        let mut cfis = [0_u8; FIS_H2D_SIZE];
+        cfis[0] = FIS_TYPE_REG_H2D;
+        cfis[1] = FIS_H2D_C;
+        cfis[2] = cmd;
+        for i in 0..3 {
+            cfis[4 + i] = (lba >> (i * 8)) as u8;
+            cfis[8 + i] = (lba >> (24 + i * 8)) as u8;
+        }
+        cfis[7] = 0x40;
+        cfis[12..14].copy_from_slice(&count.to_le_bytes());
+        cfis
+    }
+
+    fn read_guest(mem_space: &AddressSpace, addr: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0_u8; len];
+        mem_space
+            .read(&mut buf.as_mut_slice(), GuestAddress(addr), len as u64)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_ahci_disk() {
+        EventLoop::object_init(&None).unwrap();
+        let path = "/tmp/stratovirt_test_ahci.img";
+        let file = std::fs::File::create(path).unwrap();
+        file.set_len(0x10_0000).unwrap();
+        let mem_space = address_space_init();
+        let mut ctrl = new_ctrl(&mem_space, path, false);
+        let irq = Arc::new(AtomicBool::new(false));
+        let cloned_irq = irq.clone();
+        ctrl.set_irq_callback(Arc::new(move |level| {
+            cloned_irq.store(level, Ordering::SeqCst)
+        }));
+
+        // Only the port 0 is attached with the device.
+        let mut cap = [0_u8; 4];
+        assert!(ctrl.read_reg(AHCI_REG_CAP, &mut cap));
+        assert_eq!(u32::from_le_bytes(cap) & 0x1f, AHCI_MAX_PORTS as u32 - 1);
+        assert_eq!(read_port_reg(&ctrl, 0, PORT_REG_SIG), 0x101);
+        assert_eq!(read_port_reg(&ctrl, 0, PORT_REG_SSTS), PORT_SSTS_ACTIVE);
+        assert_eq!(read_port_reg(&ctrl, 1, PORT_REG_SSTS), 0);
+
+        // The signature FIS is posted when the FIS receive is enabled.
+        start_port(&mut ctrl);
+        let fis = read_guest(&mem_space, FB_ADDR + RX_FIS_D2H, 20);
+        assert_eq!(fis[0], FIS_TYPE_REG_D2H);
+        assert_eq!(fis[12], 1);
+        // COMRESET.
+        write_port_reg(&mut ctrl, PORT_REG_SCTL, PORT_SCTL_DET_COMRESET);
+        assert_eq!(read_port_reg(&ctrl, 0, PORT_REG_SSTS), 0);
+        write_port_reg(&mut ctrl, PORT_REG_SCTL, 0);
+        assert_eq!(read_port_reg(&ctrl, 0, PORT_REG_TFD), 0x150);
+
+        // Identify device, which is reported by the PIO setup FIS.
+        assert!(ctrl.write_reg(AHCI_REG_GHC, &(AHCI_GHC_AE | AHCI_GHC_IE).to_le_bytes()));
+        let tfd = issue(
+            &mut ctrl,
+            &mem_space,
+            0,
+            ata_cmd(ATA_CMD_IDENTIFY, 0, 0),
+            None,
+            512,
+        );
+        assert_eq!(tfd, 0x50);
+        let id = read_guest(&mem_space, DATA_ADDR, 512);
+        // Serial number is byte-swapped.
+        assert_eq!(&id[20..28], b"eddaebfe");
+        // Number of the LBA48 sectors.
+        assert_eq!(
+            u32::from_le_bytes([id[200], id[201], id[202], id[203]]),
+            0x800
+        );
+        let header: AhciCmdHeader = mem_space.read_object(GuestAddress(CLB_ADDR)).unwrap();
+        assert_eq!(header.prdbc, 512);
+        let is = read_port_reg(&ctrl, 0, PORT_REG_IS);
+        assert_eq!(is, PORT_IRQ_DHRS | PORT_IRQ_PSS);
+        assert!(irq.load(Ordering::SeqCst));
+        write_port_reg(&mut ctrl, PORT_REG_IS, is);
+        assert!(!irq.load(Ordering::SeqCst));
+
+        // Write 8 sectors by DMA, and read them back by NCQ.
+        let buf: Vec<u8> = (0..0x1000).map(|i| i as u8).collect();
+        mem_space
+            .write(&mut buf.as_slice(), GuestAddress(DATA_ADDR), 0x1000)
+            .unwrap();
+        let tfd = issue(
+            &mut ctrl,
+            &mem_space,
+            1,
+            ata_cmd(ATA_CMD_WRITE_DMA_EXT, 2, 8),
+            None,
+            0x1000,
+        );
+        assert_eq!(tfd, 0x50);
+        mem_space
+            .write(
+                &mut vec![0_u8; 0x1000].as_slice(),
+                GuestAddress(DATA_ADDR),
+                0x1000,
+            )
+            .unwrap();
+        let mut cfis = ata_cmd(ATA_CMD_READ_FPDMA_QUEUED, 2, 3 << 3);
+        cfis[3] = 8;
+        write_port_reg(&mut ctrl, PORT_REG_SACT, 1 << 3);
+        let tfd = issue(&mut ctrl, &mem_space, 3, cfis, None, 0x1000);
+        assert_eq!(tfd, 0x50);
+        assert_eq!(read_port_reg(&ctrl, 0, PORT_REG_SACT), 0);
+        assert_eq!(read_guest(&mem_space, DATA_ADDR, 0x1000), buf);
+        let fis = read_guest(&mem_space, FB_ADDR + RX_FIS_SDB, 8);
+        assert_eq!(fis[0], FIS_TYPE_SDB);
+        assert_eq!(u32::from_le_bytes([fis[4], fis[5], fis[6], fis[7]]), 1 << 3);
+        assert_ne!(read_port_reg(&ctrl, 0, PORT_REG_IS) & PORT_IRQ_SDBS, 0);
+
+        // The sectors are out of range.
+        write_port_reg(&mut ctrl, PORT_REG_IS, u32::MAX);
+        let tfd = issue(
+            &mut ctrl,
+            &mem_space,
+            0,
+            ata_cmd(ATA_CMD_READ_DMA_EXT, 0x7ff, 2),
+            None,
+            0x400,
+        );
+        assert_eq!(tfd, (ATA_ERR_IDNF as u32) << 8 | 0x51);
+        assert_ne!(read_port_reg(&ctrl, 0, PORT_REG_IS) & PORT_IRQ_TFES, 0);
+
+        ctrl.unrealize().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ahci_cdrom() {
+        EventLoop::object_init(&None).unwrap();
+        let path = "/tmp/stratovirt_test_ahci.iso";
+        let data: Vec<u8> = (0..0x2000).map(|i| (i / 0x800) as u8 + 1).collect();
+        std::fs::write(path, &data).unwrap();
+        let mem_space = address_space_init();
+        let mut ctrl = new_ctrl(&mem_space, path, true);
+        assert_eq!(read_port_reg(&ctrl, 0, PORT_REG_SIG), 0xeb14_0101);
+        start_port(&mut ctrl);
+
+        // IDENTIFY DEVICE is aborted by the ATAPI device.
+        let tfd = issue(
+            &mut ctrl,
+            &mem_space,
+            0,
+            ata_cmd(ATA_CMD_IDENTIFY, 0, 0),
+            None,
+            512,
+        );
+        assert_eq!(tfd >> 8, ATA_ERR_ABRT as u32);
+        let tfd = issue(
+            &mut ctrl,
+            &mem_space,
+            0,
+            ata_cmd(ATA_CMD_IDENTIFY_PACKET, 0, 0),
+            None,
+            512,
+        );
+        assert_eq!(tfd, 0x50);
+        assert_eq!(read_guest(&mem_space, DATA_ADDR, 2), [0xc0, 0x85]);
+
+        // Inquiry and read the second sector by DMA.
+        let mut cfis = ata_cmd(ATA_CMD_PACKET, 0, 0);
+        cfis[3] = 1;
+        let mut cdb = [0_u8; SCSI_CMD_BUF_SIZE];
+        cdb[0] = 0x12;
+        cdb[4] = 36;
+        let tfd = issue(&mut ctrl, &mem_space, 1, cfis, Some(cdb), 36);
+        assert_eq!(tfd, 0x50);
+        assert_eq!(read_guest(&mem_space, DATA_ADDR, 1), [0x05]);
+        cdb = [0; SCSI_CMD_BUF_SIZE];
+        cdb[0] = 0x28;
+        cdb[5] = 1;
+        cdb[8] = 1;
+        let tfd = issue(&mut ctrl, &mem_space, 2, cfis, Some(cdb), 0x800);
+        assert_eq!(tfd, 0x50);
+        assert_eq!(
+            read_guest(&mem_space, DATA_ADDR, 0x800),
+            data[0x800..0x1000]
+        );
+
+        // The invalid opcode fails with the sense key in the error register.
+        cdb = [0; SCSI_CMD_BUF_SIZE];
+        cdb[0] = 0xff;
+        let tfd = issue(&mut ctrl, &mem_space, 3, cfis, Some(cdb), 0x800);
+        assert_eq!(tfd, 0x5 << 12 | 0x51);
+        cdb[0] = REQUEST_SENSE;
+        cdb[4] = ATAPI_SENSE_SIZE as u8;
+        let tfd = issue(
+            &mut ctrl,
+            &mem_space,
+            4,
+            cfis,
+            Some(cdb),
+            ATAPI_SENSE_SIZE as u32,
+        );
+        assert_eq!(tfd, 0x50);
+        let sense = read_guest(&mem_space, DATA_ADDR, ATAPI_SENSE_SIZE);
+        assert_eq!((sense[0], sense[2], sense[12]), (0x70, 0x5, 0x20));
+
+        ctrl.unrealize().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
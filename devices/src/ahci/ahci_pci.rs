@@ -0,0 +1,306 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{bail, Result};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::ahci_ctrl::{AhciCtrl, AHCI_MMIO_SIZE};
+use super::ata::AtaDevice;
+use crate::pci::config::{
+    PciConfig, RegionType, DEVICE_ID, PCI_CLASS_STORAGE_SATA, PCI_CONFIG_SPACE_SIZE, REVISION_ID,
+    SUB_CLASS_CODE, VENDOR_ID,
+};
+use crate::pci::{init_intx, le_write_u16, PciBus, PciDevBase, PciDevOps};
+use crate::{Device, DeviceBase};
+use address_space::{AddressSpace, GuestAddress, Region, RegionOps};
+use machine_manager::config::AhciConfig;
+use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+const PCI_VENDOR_ID_INTEL: u16 = 0x8086;
+/// ICH9 6-port SATA controller in AHCI mode.
+const PCI_DEVICE_ID_INTEL_ICH9_AHCI: u16 = 0x2922;
+
+/// Programming interface of AHCI 1.0.
+const PCI_CLASS_PI: usize = 0x09;
+const PCI_CLASS_PI_AHCI: u8 = 0x01;
+
+/// The index/data pair in BAR4, which is used to access the AHCI registers by the port IO.
+const AHCI_IDP_BAR: usize = 4;
+const AHCI_IDP_SIZE: u64 = 0x20;
+const AHCI_IDP_INDEX: u64 = 0x10;
+const AHCI_IDP_DATA: u64 = 0x14;
+/// The AHCI base address is in BAR5.
+const AHCI_ABAR: usize = 5;
+
+/// AHCI controller which can be attached to PCI bus.
+pub struct AhciPciDevice {
+    base: PciDevBase,
+    ctrl: Arc<Mutex<AhciCtrl>>,
+    kick_evt: Arc<EventFd>,
+    iothread: Option<String>,
+    delete_evts: Vec<RawFd>,
+}
+
+impl AhciPciDevice {
+    pub fn new(
+        config: &AhciConfig,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+        mem_space: &Arc<AddressSpace>,
+    ) -> Self {
+        let kick_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        Self {
+            base: PciDevBase {
+                base: DeviceBase::new(config.id.clone(), false),
+                config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 6),
+                devfn,
+                parent_bus,
+            },
+            ctrl: Arc::new(Mutex::new(AhciCtrl::new(
+                &config.id,
+                mem_space,
+                kick_evt.clone(),
+                config.iothread.clone(),
+            ))),
+            kick_evt,
+            iothread: config.iothread.clone(),
+            delete_evts: Vec::new(),
+        }
+    }
+
+    /// Attach the ide-hd or ide-cd device to its port.
+    pub fn attach_device(&self, dev: AtaDevice) -> Result<()> {
+        self.ctrl.lock().unwrap().attach_device(dev)
+    }
+
+    fn abar_region(&self) -> Region {
+        let read_ctrl = self.ctrl.clone();
+        let write_ctrl = self.ctrl.clone();
+        let ops = RegionOps {
+            read: Arc::new(
+                move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+                    read_ctrl.lock().unwrap().read_reg(offset, data)
+                },
+            ),
+            write: Arc::new(move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+                write_ctrl.lock().unwrap().write_reg(offset, data)
+            }),
+        };
+        Region::init_io_region(AHCI_MMIO_SIZE, ops, "AhciAbarRegion")
+    }
+
+    fn idp_region(&self) -> Region {
+        let index = Arc::new(AtomicU32::new(0));
+        let read_index = index.clone();
+        let read_ctrl = self.ctrl.clone();
+        let write_ctrl = self.ctrl.clone();
+        let ops = RegionOps {
+            read: Arc::new(
+                move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+                    match offset {
+                        AHCI_IDP_INDEX if data.len() == 4 => {
+                            let val = read_index.load(Ordering::Acquire);
+                            data.copy_from_slice(&val.to_le_bytes());
+                            true
+                        }
+                        AHCI_IDP_DATA => read_ctrl
+                            .lock()
+                            .unwrap()
+                            .read_reg(read_index.load(Ordering::Acquire) as u64, data),
+                        _ => {
+                            data.fill(0);
+                            true
+                        }
+                    }
+                },
+            ),
+            write: Arc::new(move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+                match offset {
+                    AHCI_IDP_INDEX if data.len() == 4 => {
+                        let val = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                        index.store(val & (AHCI_MMIO_SIZE as u32 - 4), Ordering::Release);
+                        true
+                    }
+                    AHCI_IDP_DATA => write_ctrl
+                        .lock()
+                        .unwrap()
+                        .write_reg(index.load(Ordering::Acquire) as u64, data),
+                    _ => true,
+                }
+            }),
+        };
+        Region::init_io_region(AHCI_IDP_SIZE, ops, "AhciIdpRegion")
+    }
+}
+
+impl Device for AhciPciDevice {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl PciDevOps for AhciPciDevice {
+    fn pci_base(&self) -> &PciDevBase {
+        &self.base
+    }
+
+    fn pci_base_mut(&mut self) -> &mut PciDevBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<()> {
+        self.init_write_mask(false)?;
+        self.init_write_clear_mask(false)?;
+        le_write_u16(
+            &mut self.base.config.config,
+            VENDOR_ID as usize,
+            PCI_VENDOR_ID_INTEL,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            DEVICE_ID as usize,
+            PCI_DEVICE_ID_INTEL_ICH9_AHCI,
+        )?;
+        self.base.config.config[REVISION_ID] = 0x2;
+        le_write_u16(
+            &mut self.base.config.config,
+            SUB_CLASS_CODE as usize,
+            PCI_CLASS_STORAGE_SATA,
+        )?;
+        self.base.config.config[PCI_CLASS_PI] = PCI_CLASS_PI_AHCI;
+
+        #[cfg(target_arch = "aarch64")]
+        self.base.config.set_interrupt_pin();
+
+        let handler = Arc::new(Mutex::new(AhciIoHandler {
+            ctrl: self.ctrl.clone(),
+            kick_evt: self.kick_evt.clone(),
+        }));
+        register_event_helper(
+            EventNotifierHelper::internal_notifiers(handler),
+            self.iothread.as_ref(),
+            &mut self.delete_evts,
+        )?;
+
+        init_intx(
+            self.name(),
+            &mut self.base.config,
+            self.base.parent_bus.clone(),
+            self.base.devfn,
+        )?;
+
+        let idp_region = self.idp_region();
+        self.base.config.register_bar(
+            AHCI_IDP_BAR,
+            idp_region,
+            RegionType::Io,
+            false,
+            AHCI_IDP_SIZE,
+        )?;
+        let abar_region = self.abar_region();
+        self.base.config.register_bar(
+            AHCI_ABAR,
+            abar_region,
+            RegionType::Mem32Bit,
+            false,
+            AHCI_MMIO_SIZE,
+        )?;
+
+        // It is safe to unwrap, because it is initialized in init_intx.
+        let cloned_intx = self.base.config.intx.as_ref().unwrap().clone();
+        self.ctrl
+            .lock()
+            .unwrap()
+            .set_irq_callback(Arc::new(move |level: bool| {
+                cloned_intx.lock().unwrap().notify(level as u8);
+            }));
+
+        // Attach to the PCI bus.
+        let pci_bus = self.base.parent_bus.upgrade().unwrap();
+        let mut locked_pci_bus = pci_bus.lock().unwrap();
+        let pci_device = locked_pci_bus.devices.get(&self.base.devfn);
+        match pci_device {
+            Some(device) => bail!(
+                "Devfn {:?} has been used by {:?}",
+                &self.base.devfn,
+                device.lock().unwrap().name()
+            ),
+            None => locked_pci_bus
+                .devices
+                .insert(self.base.devfn, Arc::new(Mutex::new(self))),
+        };
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        unregister_event_helper(self.iothread.as_ref(), &mut self.delete_evts)?;
+        self.ctrl.lock().unwrap().unrealize()
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        let parent_bus = self.base.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+
+        self.base.config.write(
+            offset,
+            data,
+            0,
+            #[cfg(target_arch = "x86_64")]
+            Some(&locked_parent_bus.io_region),
+            Some(&locked_parent_bus.mem_region),
+        );
+    }
+
+    fn reset(&mut self, _reset_child_device: bool) -> Result<()> {
+        self.ctrl.lock().unwrap().reset();
+        self.base.config.reset()?;
+        Ok(())
+    }
+}
+
+/// Execute the issued commands and complete the finished ones in the iothread.
+struct AhciIoHandler {
+    ctrl: Arc<Mutex<AhciCtrl>>,
+    kick_evt: Arc<EventFd>,
+}
+
+impl EventNotifierHelper for AhciIoHandler {
+    fn internal_notifiers(io_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_io_handler = io_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_event, fd: RawFd| {
+            read_fd(fd);
+            let locked_handler = cloned_io_handler.lock().unwrap();
+            locked_handler.ctrl.lock().unwrap().process();
+            None
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            io_handler.lock().unwrap().kick_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use log::error;
+
+use super::ahci_ctrl::{ahci_aio_complete_cb, AhciCompleteCb};
+use crate::ScsiBus::ScsiSense;
+use crate::ScsiDisk::{ScsiDevice, SCSI_TYPE_ROM};
+use block_backend::{create_block_backend, BlockDriverOps, BlockProperty};
+use machine_manager::config::{DriveFile, IdeDevConfig, ScsiDevConfig, VmConfig};
+use util::aio::Aio;
+
+/// Signatures reported after the device reset.
+pub const ATA_SIGNATURE_DISK: u32 = 0x0000_0101;
+pub const ATA_SIGNATURE_ATAPI: u32 = 0xeb14_0101;
+
+/// Size of the sector of the ATA disk.
+pub const ATA_SECTOR_SHIFT: u32 = 9;
+/// Max number of the sectors transferred by the multiple commands.
+pub const ATA_MAX_MULTIPLE_SECTORS: u8 = 16;
+/// Max number of the outstanding NCQ commands, which is 0's based.
+const ATA_NCQ_DEPTH: u16 = 31;
+/// Size of the identify data.
+pub const ATA_IDENTIFY_SIZE: usize = 512;
+/// Size of the fixed format sense data returned by REQUEST SENSE.
+pub const ATAPI_SENSE_SIZE: usize = 18;
+
+/// ATA disk or ATAPI CD-ROM attached to one port of the AHCI controller.
+pub struct AtaDevice {
+    pub config: IdeDevConfig,
+    /// The CD-ROM accepts the SCSI commands in the packets, which are emulated by the scsi device.
+    cdrom: Option<Arc<Mutex<ScsiDevice>>>,
+    disk: Option<Arc<Mutex<dyn BlockDriverOps<AhciCompleteCb>>>>,
+    drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// Number of the sectors of the disk.
+    pub sectors: u64,
+    /// Sense data of the last failed packet command.
+    sense: ScsiSense,
+    /// Write cache is enabled by the set features command.
+    write_cache: bool,
+    /// Number of the sectors per block of the multiple commands.
+    pub multiple: u8,
+}
+
+impl AtaDevice {
+    pub fn new(
+        config: IdeDevConfig,
+        atapi: bool,
+        drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    ) -> Self {
+        let cdrom = atapi.then(|| {
+            let scsi_cfg = ScsiDevConfig {
+                id: config.id.clone(),
+                path_on_host: config.drive.path_on_host.clone(),
+                serial: config.serial.clone(),
+                cntlr: config.cntlr.clone(),
+                read_only: true,
+                direct: config.drive.direct,
+                aio_type: config.drive.aio,
+                format: config.drive.format,
+                l2_cache_size: config.drive.l2_cache_size,
+                refcount_cache_size: config.drive.refcount_cache_size,
+                key_secret: config.key_secret.clone(),
+                copy_on_read: config.drive.copy_on_read,
+                io_timeout: config.drive.io_timeout,
+                ..Default::default()
+            };
+            Arc::new(Mutex::new(ScsiDevice::new(
+                scsi_cfg,
+                SCSI_TYPE_ROM,
+                drive_files.clone(),
+            )))
+        });
+        AtaDevice {
+            config,
+            cdrom,
+            disk: None,
+            drive_files,
+            sectors: 0,
+            sense: ScsiSense::default(),
+            write_cache: true,
+            multiple: ATA_MAX_MULTIPLE_SECTORS,
+        }
+    }
+
+    /// Open the drive, and the completions are reported in the iothread of the controller.
+    pub fn realize(&mut self, iothread: Option<String>, broken: Arc<AtomicBool>) -> Result<()> {
+        let id = self.config.id.clone();
+        let err_cb =
+            Arc::new(move || error!("Ide device {} is broken because of the io error", id));
+        if let Some(cdrom) = self.cdrom.as_ref() {
+            let mut locked_cdrom = cdrom.lock().unwrap();
+            locked_cdrom.realize(iothread)?;
+            self.sectors = locked_cdrom.disk_sectors;
+            // SAFETY: the block backend is created in realize.
+            let backend = locked_cdrom.block_backend.as_ref().unwrap();
+            return backend.lock().unwrap().register_io_event(broken, err_cb);
+        }
+
+        let drive = &self.config.drive;
+        if drive.media != "disk" {
+            bail!("Ide-hd {} only supports the disk media", self.config.id);
+        }
+        let drive_files = self.drive_files.lock().unwrap();
+        let file = VmConfig::fetch_drive_file(&drive_files, &drive.path_on_host)?;
+        let (req_align, buf_align) =
+            VmConfig::fetch_drive_align(&drive_files, &drive.path_on_host)?;
+        let drive_id = VmConfig::get_drive_id(&drive_files, &drive.path_on_host)?;
+        drop(drive_files);
+        if req_align > 1 << ATA_SECTOR_SHIFT {
+            bail!(
+                "Ide-hd {} doesn't support the drive which requires {} bytes aligned io",
+                self.config.id,
+                req_align
+            );
+        }
+
+        let aio = Aio::new(Arc::new(ahci_aio_complete_cb), drive.aio)?;
+        let conf = BlockProperty {
+            id: drive_id,
+            format: drive.format,
+            iothread,
+            direct: drive.direct,
+            req_align,
+            buf_align,
+            discard: false,
+            write_zeroes: drive.write_zeroes,
+            l2_cache_size: drive.l2_cache_size,
+            refcount_cache_size: drive.refcount_cache_size,
+            key_secret: self.config.key_secret.clone(),
+            copy_on_read: drive.copy_on_read,
+            io_timeout: drive.io_timeout,
+        };
+        let backend = create_block_backend(file, aio, conf)?;
+        self.sectors = backend.lock().unwrap().disk_size()? >> ATA_SECTOR_SHIFT;
+        backend.lock().unwrap().register_io_event(broken, err_cb)?;
+        self.disk = Some(backend);
+        Ok(())
+    }
+
+    pub fn unrealize(&mut self) -> Result<()> {
+        if let Some(cdrom) = self.cdrom.as_ref() {
+            if let Some(backend) = cdrom.lock().unwrap().block_backend.as_ref() {
+                backend.lock().unwrap().unregister_io_event()?;
+            }
+        }
+        if let Some(backend) = self.disk.take() {
+            backend.lock().unwrap().unregister_io_event()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_atapi(&self) -> bool {
+        self.cdrom.is_some()
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.config.drive.read_only
+    }
+
+    pub fn cdrom(&self) -> Option<Arc<Mutex<ScsiDevice>>> {
+        self.cdrom.clone()
+    }
+
+    pub fn disk(&self) -> Option<Arc<Mutex<dyn BlockDriverOps<AhciCompleteCb>>>> {
+        self.disk.clone()
+    }
+
+    pub fn signature(&self) -> u32 {
+        if self.is_atapi() {
+            ATA_SIGNATURE_ATAPI
+        } else {
+            ATA_SIGNATURE_DISK
+        }
+    }
+
+    /// Device reset, which drops the sense data and restores the default features.
+    pub fn reset(&mut self) {
+        self.sense = ScsiSense::default();
+        self.write_cache = true;
+        self.multiple = ATA_MAX_MULTIPLE_SECTORS;
+    }
+
+    pub fn set_write_cache(&mut self, enabled: bool) {
+        self.write_cache = enabled;
+    }
+
+    pub fn set_sense(&mut self, sense: ScsiSense) {
+        self.sense = sense;
+    }
+
+    /// Returns the fixed format sense data, and the sense is cleared after being reported.
+    pub fn take_sense(&mut self) -> [u8; ATAPI_SENSE_SIZE] {
+        let sense = std::mem::take(&mut self.sense);
+        let mut buf = [0_u8; ATAPI_SENSE_SIZE];
+        // Current errors in the fixed format.
+        buf[0] = 0x70;
+        buf[2] = sense.key;
+        // Additional sense length.
+        buf[7] = 10;
+        buf[12] = sense.asc;
+        buf[13] = sense.ascq;
+        buf
+    }
+
+    /// Key of the sense data, which is reported in the error register.
+    pub fn sense_key(&self) -> u8 {
+        self.sense.key
+    }
+
+    /// Data returned by IDENTIFY DEVICE or IDENTIFY PACKET DEVICE.
+    pub fn identify(&self) -> [u8; ATA_IDENTIFY_SIZE] {
+        let mut id = [0_u16; ATA_IDENTIFY_SIZE / 2];
+        let serial = match self.config.serial.as_ref() {
+            Some(serial) => serial.clone(),
+            None => format!("STRA{:016}", self.config.port),
+        };
+        put_ata_string(&mut id[10..20], &serial);
+        put_ata_string(&mut id[23..27], env!("CARGO_PKG_VERSION"));
+        if self.is_atapi() {
+            put_ata_string(&mut id[27..47], "STRA CDROM");
+            // ATAPI, CD-ROM, removable, 50us DRQ and 12 bytes packet.
+            id[0] = 0x85c0;
+            // LBA and DMA.
+            id[49] = 0x0300;
+            id[53] = 0x0006;
+            // Multiword DMA 0-2 and PIO 3-4.
+            id[63] = 0x0007;
+            id[64] = 0x0003;
+            id[65..69].fill(120);
+            // SATA 1.5Gbps.
+            id[76] = 0x0002;
+            // ATA/ATAPI-4 to ATA/ATAPI-7.
+            id[80] = 0x00f0;
+            // Packet feature set.
+            id[82] = 1 << 14 | 1 << 4;
+            id[83] = 1 << 14;
+            id[84] = 1 << 14;
+            id[85] = 1 << 14 | 1 << 4;
+            id[87] = 1 << 14;
+            // UDMA 0-5, and UDMA 5 is selected.
+            id[88] = 0x203f;
+        } else {
+            put_ata_string(&mut id[27..47], "STRA HARDDISK");
+            let lba28 = self.sectors.min(0x0fff_ffff) as u32;
+            let cylinders = (self.sectors / (16 * 63)).clamp(1, 16383) as u16;
+            // Fixed device.
+            id[0] = 0x0040;
+            // Default CHS geometry.
+            id[1] = cylinders;
+            id[3] = 16;
+            id[6] = 63;
+            id[47] = 0x8000 | ATA_MAX_MULTIPLE_SECTORS as u16;
+            id[49] = 0x0300;
+            id[50] = 0x4000;
+            id[53] = 0x0007;
+            // Current CHS geometry and capacity.
+            id[54] = cylinders;
+            id[55] = 16;
+            id[56] = 63;
+            let chs_sectors = cylinders as u32 * 16 * 63;
+            id[57] = chs_sectors as u16;
+            id[58] = (chs_sectors >> 16) as u16;
+            id[59] = 0x0100 | self.multiple as u16;
+            id[60] = lba28 as u16;
+            id[61] = (lba28 >> 16) as u16;
+            id[63] = 0x0007;
+            id[64] = 0x0003;
+            id[65..69].fill(120);
+            id[75] = ATA_NCQ_DEPTH;
+            // NCQ and SATA 1.5Gbps.
+            id[76] = 1 << 8 | 1 << 1;
+            // ATA/ATAPI-4 to ATA8-ACS.
+            id[80] = 0x01f0;
+            // Write cache and NOP.
+            id[82] = 1 << 14 | 1 << 5;
+            // 48-bit address, flush cache and flush cache ext.
+            id[83] = 1 << 14 | 1 << 13 | 1 << 12 | 1 << 10;
+            id[84] = 1 << 14;
+            id[85] = 1 << 14 | (self.write_cache as u16) << 5;
+            id[86] = 1 << 13 | 1 << 12 | 1 << 10;
+            id[87] = 1 << 14;
+            id[88] = 0x203f;
+            for (i, word) in id[100..104].iter_mut().enumerate() {
+                *word = (self.sectors >> (16 * i)) as u16;
+            }
+            // The logical sector is 512 bytes.
+            id[106] = 0x4000;
+        }
+
+        let mut buf = [0_u8; ATA_IDENTIFY_SIZE];
+        for (bytes, word) in buf.chunks_exact_mut(2).zip(id.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        buf
+    }
+}
+
+/// The ATA string is padded with spaces, and the first character is in the high byte of each word.
+fn put_ata_string(words: &mut [u16], s: &str) {
+    let mut bytes = vec![b' '; words.len() * 2];
+    let len = bytes.len().min(s.len());
+    bytes[..len].copy_from_slice(&s.as_bytes()[..len]);
+    for (word, pair) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+        *word = (pair[0] as u16) << 8 | pair[1] as u16;
+    }
+}
@@ -0,0 +1,585 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Frame format conversion of the camera backend.
+//!
+//! Besides the formats of the backend, the YUY2 and NV12 frames decoded from MJPEG or
+//! scaled down from bigger frames are offered to the guest. The backend is set to the
+//! source format of the frame chosen by the guest, and each source frame is converted
+//! before it's copied to the guest. Source frames are dropped if the backend runs faster
+//! than the frame interval chosen by the guest.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+
+use super::mjpeg::decode_mjpeg;
+use super::{
+    CamBasicFmt, CameraBackend, CameraBrokenCallback, CameraFormatList, CameraFrame,
+    CameraNotifyCallback, FmtType, INTERVALS_PER_SEC,
+};
+use util::aio::{mem_from_buf, Iovec};
+
+/// Common sizes offered by scaling down the bigger frames with the same aspect ratio.
+const SCALED_SIZES: [(u32, u32); 8] = [
+    (1920, 1080),
+    (1280, 720),
+    (960, 540),
+    (640, 480),
+    (640, 360),
+    (320, 240),
+    (320, 180),
+    (160, 120),
+];
+/// Max number of the converted frames added to each format.
+const MAX_CONVERTED_FRAMES: usize = 32;
+
+/// Plane of the YUV image. The sample of pixel (x, y) is at
+/// (x * h / hmax, y * v / vmax) of the plane.
+pub struct YuvPlane {
+    pub data: Vec<u8>,
+    pub stride: usize,
+    pub h: usize,
+    pub hmax: usize,
+    pub v: usize,
+    pub vmax: usize,
+}
+
+/// YUV image with the planes of Y, U and V. Only Y plane exists for grayscale image.
+pub struct YuvImage {
+    pub width: usize,
+    pub height: usize,
+    pub planes: Vec<YuvPlane>,
+}
+
+impl YuvImage {
+    fn from_yuy2(data: &[u8], width: usize, height: usize) -> Result<Self> {
+        if width % 2 != 0 {
+            bail!("Invalid YUY2 frame width {}", width);
+        }
+        if data.len() < width * height * 2 {
+            bail!(
+                "YUY2 frame size {} is less than {}x{}",
+                data.len(),
+                width,
+                height
+            );
+        }
+        let chroma_width = width / 2;
+        let mut y = Vec::with_capacity(width * height);
+        let mut u = Vec::with_capacity(chroma_width * height);
+        let mut v = Vec::with_capacity(chroma_width * height);
+        for row in data.chunks_exact(width * 2).take(height) {
+            for pixels in row.chunks_exact(4) {
+                y.extend_from_slice(&[pixels[0], pixels[2]]);
+                u.push(pixels[1]);
+                v.push(pixels[3]);
+            }
+        }
+        let plane = |data: Vec<u8>, stride: usize, h: usize| YuvPlane {
+            data,
+            stride,
+            h,
+            hmax: 2,
+            v: 1,
+            vmax: 1,
+        };
+        Ok(YuvImage {
+            width,
+            height,
+            planes: vec![
+                plane(y, chroma_width * 2, 2),
+                plane(u, chroma_width, 1),
+                plane(v, chroma_width, 1),
+            ],
+        })
+    }
+
+    /// Get the sample of the plane at pixel (x, y) of the image.
+    fn sample(&self, plane: usize, x: usize, y: usize) -> u8 {
+        match self.planes.get(plane) {
+            Some(p) => p.data[(y * p.v / p.vmax) * p.stride + x * p.h / p.hmax],
+            None => 128,
+        }
+    }
+
+    /// Scale the image to `width` x `height` by the nearest samples, and pack it in YUY2.
+    fn to_yuy2(&self, width: usize, height: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(width * height * 2);
+        for y in 0..height {
+            let sy = y * self.height / height;
+            for x in (0..width).step_by(2) {
+                let sx0 = x * self.width / width;
+                let sx1 = std::cmp::min((x + 1) * self.width / width, self.width - 1);
+                out.extend_from_slice(&[
+                    self.sample(0, sx0, sy),
+                    self.sample(1, sx0, sy),
+                    self.sample(0, sx1, sy),
+                    self.sample(2, sx0, sy),
+                ]);
+            }
+        }
+        out
+    }
+
+    /// Scale the image to `width` x `height` by the nearest samples, and pack it in NV12.
+    fn to_nv12(&self, width: usize, height: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(nv12_frame_size(width, height));
+        for y in 0..height {
+            let sy = y * self.height / height;
+            for x in 0..width {
+                out.push(self.sample(0, x * self.width / width, sy));
+            }
+        }
+        for y in (0..height).step_by(2) {
+            let sy = y * self.height / height;
+            for x in (0..width).step_by(2) {
+                let sx = x * self.width / width;
+                out.extend_from_slice(&[self.sample(1, sx, sy), self.sample(2, sx, sy)]);
+            }
+        }
+        out
+    }
+}
+
+fn nv12_frame_size(width: usize, height: usize) -> usize {
+    width * height + (width + 1) / 2 * ((height + 1) / 2) * 2
+}
+
+/// Convert the YUY2 frame to NV12.
+pub fn yuy2_to_nv12(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    let image = YuvImage::from_yuy2(data, width as usize, height as usize)?;
+    Ok(image.to_nv12(width as usize, height as usize))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FrameConversion {
+    src: FmtType,
+    src_width: usize,
+    src_height: usize,
+    dst: FmtType,
+    width: usize,
+    height: usize,
+}
+
+impl FrameConversion {
+    fn convert(&self, frame: &[u8]) -> Result<Vec<u8>> {
+        let image = match self.src {
+            FmtType::Mjpg => decode_mjpeg(frame)?,
+            FmtType::Yuy2 => YuvImage::from_yuy2(frame, self.src_width, self.src_height)?,
+            _ => bail!("Unsupported source format {:?}", self.src),
+        };
+        if image.width == 0 || image.height == 0 {
+            bail!("Empty source frame");
+        }
+        match self.dst {
+            FmtType::Yuy2 => Ok(image.to_yuy2(self.width, self.height)),
+            FmtType::Nv12 => Ok(image.to_nv12(self.width, self.height)),
+            _ => bail!("Unsupported target format {:?}", self.dst),
+        }
+    }
+}
+
+/// The source frame of the backend for a frame offered to the guest.
+#[derive(Clone, Copy, Debug)]
+struct FrameRoute {
+    src: CamBasicFmt,
+    conversion: Option<FrameConversion>,
+}
+
+#[derive(Default)]
+struct ConvertState {
+    conversion: Option<FrameConversion>,
+    /// Frame interval chosen by the guest.
+    frame_interval: Duration,
+    last_frame: Option<Instant>,
+    /// The current frame of the backend is being transferred to the guest.
+    accepted: bool,
+    /// The converted current frame.
+    output: Vec<u8>,
+    /// Conversion errors have been reported.
+    error_reported: bool,
+}
+
+/// Camera backend which converts the frames of the inner backend.
+pub struct ConvertCameraBackend {
+    id: String,
+    backend: Arc<Mutex<dyn CameraBackend>>,
+    fmt_list: Vec<CameraFormatList>,
+    /// Routes of the frames offered to the guest, keyed by format, width, height and fps.
+    routes: HashMap<(FmtType, u32, u32, u32), FrameRoute>,
+    state: Mutex<ConvertState>,
+}
+
+fn frame_fps(interval: u32) -> Result<u32> {
+    INTERVALS_PER_SEC
+        .checked_div(interval)
+        .with_context(|| format!("Invalid frame interval {}", interval))
+}
+
+impl ConvertCameraBackend {
+    pub fn new(id: String, backend: Arc<Mutex<dyn CameraBackend>>) -> Self {
+        ConvertCameraBackend {
+            id,
+            backend,
+            fmt_list: Vec::new(),
+            routes: HashMap::new(),
+            state: Mutex::new(ConvertState::default()),
+        }
+    }
+
+    fn build_format_list(&mut self, src_list: Vec<CameraFormatList>) -> Result<()> {
+        self.routes.clear();
+        let mut list: Vec<CameraFormatList> = Vec::new();
+        // Source frames which can be converted.
+        let mut sources: Vec<(FmtType, CameraFrame)> = Vec::new();
+        for fmt in src_list {
+            for frm in &fmt.frame {
+                let src = CamBasicFmt {
+                    width: frm.width,
+                    height: frm.height,
+                    fps: frame_fps(frm.interval)?,
+                    fmttype: fmt.format,
+                };
+                self.routes.insert(
+                    (fmt.format, frm.width, frm.height, src.fps),
+                    FrameRoute {
+                        src,
+                        conversion: None,
+                    },
+                );
+                if matches!(fmt.format, FmtType::Yuy2 | FmtType::Mjpg) {
+                    sources.push((fmt.format, frm.clone()));
+                }
+            }
+            list.push(CameraFormatList {
+                fmt_index: list.len() as u8 + 1,
+                ..fmt
+            });
+        }
+
+        let mut sizes: Vec<(u32, u32)> = sources
+            .iter()
+            .map(|(_, frm)| (frm.width, frm.height))
+            .chain(SCALED_SIZES)
+            .collect();
+        sizes.sort_by_key(|&(w, h)| std::cmp::Reverse((w as u64 * h as u64, w)));
+        sizes.dedup();
+
+        for target in [FmtType::Yuy2, FmtType::Nv12] {
+            let mut frames = Vec::new();
+            for &(width, height) in &sizes {
+                let src_frames = match best_source_frames(&sources, target, width, height) {
+                    Some(src_frames) => src_frames,
+                    None => continue,
+                };
+                for (src_fmt, src_frm) in src_frames {
+                    if frames.len() >= MAX_CONVERTED_FRAMES {
+                        break;
+                    }
+                    let fps = frame_fps(src_frm.interval)?;
+                    if self.routes.contains_key(&(target, width, height, fps)) {
+                        continue;
+                    }
+                    let route = FrameRoute {
+                        src: CamBasicFmt {
+                            width: src_frm.width,
+                            height: src_frm.height,
+                            fps,
+                            fmttype: src_fmt,
+                        },
+                        conversion: Some(FrameConversion {
+                            src: src_fmt,
+                            src_width: src_frm.width as usize,
+                            src_height: src_frm.height as usize,
+                            dst: target,
+                            width: width as usize,
+                            height: height as usize,
+                        }),
+                    };
+                    self.routes.insert((target, width, height, fps), route);
+                    frames.push((width, height, src_frm.interval));
+                }
+            }
+            if frames.is_empty() {
+                continue;
+            }
+
+            let fmt = match list.iter().position(|f| f.format == target) {
+                Some(pos) => &mut list[pos],
+                None => {
+                    list.push(CameraFormatList {
+                        format: target,
+                        fmt_index: list.len() as u8 + 1,
+                        frame: Vec::new(),
+                    });
+                    list.last_mut().unwrap()
+                }
+            };
+            let mut index = fmt.frame.iter().map(|f| f.index).max().unwrap_or(0);
+            for (width, height, interval) in frames {
+                index += 1;
+                fmt.frame.push(CameraFrame {
+                    width,
+                    height,
+                    index,
+                    interval,
+                });
+            }
+        }
+
+        self.fmt_list = list;
+        Ok(())
+    }
+
+    /// Take the current frame of the backend for the guest. Return false if there is no
+    /// frame or the frame is dropped.
+    fn accept_frame(&self, state: &mut ConvertState) -> bool {
+        let mut locked_backend = self.backend.lock().unwrap();
+        let size = locked_backend.get_frame_size();
+        if size == 0 {
+            return false;
+        }
+
+        let mut drop_frame = matches!(state.last_frame,
+            Some(last) if last.elapsed() < state.frame_interval * 9 / 10);
+        if !drop_frame {
+            if let Some(conversion) = state.conversion {
+                let mut frame = vec![0_u8; size];
+                let iov = Iovec::new(frame.as_mut_ptr() as u64, size as u64);
+                match locked_backend
+                    .get_frame(&[iov], 0, size)
+                    .and_then(|_| conversion.convert(&frame))
+                {
+                    Ok(output) => state.output = output,
+                    Err(e) => {
+                        if !state.error_reported {
+                            warn!("Camera {} failed to convert frame: {:?}", self.id, e);
+                            state.error_reported = true;
+                        }
+                        debug!("Camera {} drop frame: {:?}", self.id, e);
+                        drop_frame = true;
+                    }
+                }
+            }
+        }
+        if drop_frame {
+            if let Err(e) = locked_backend.next_frame() {
+                warn!("Camera {} failed to drop frame: {:?}", self.id, e);
+            }
+            return false;
+        }
+
+        state.accepted = true;
+        state.last_frame = Some(Instant::now());
+        true
+    }
+}
+
+/// Find the source frames for the target frame, which are the frames of the smallest size
+/// not less than the target with the same aspect ratio. YUY2 is preferred to MJPEG as it's
+/// cheaper to convert.
+fn best_source_frames(
+    sources: &[(FmtType, CameraFrame)],
+    target: FmtType,
+    width: u32,
+    height: u32,
+) -> Option<Vec<(FmtType, CameraFrame)>> {
+    let (fmt, frm) = sources
+        .iter()
+        .filter(|(fmt, frm)| {
+            frm.width >= width
+                && frm.height >= height
+                && frm.width as u64 * height as u64 == frm.height as u64 * width as u64
+                // The YUY2 frames of the same size are offered by the backend.
+                && !(*fmt == target && frm.width == width && frm.height == height)
+        })
+        .min_by_key(|(fmt, frm)| (frm.width as u64 * frm.height as u64, *fmt != FmtType::Yuy2))?;
+    Some(
+        sources
+            .iter()
+            .filter(|(f, s)| f == fmt && s.width == frm.width && s.height == frm.height)
+            .cloned()
+            .collect(),
+    )
+}
+
+impl CameraBackend for ConvertCameraBackend {
+    fn set_fmt(&mut self, fmt: &CamBasicFmt) -> Result<()> {
+        let route = *self
+            .routes
+            .get(&(fmt.fmttype, fmt.width, fmt.height, fmt.fps))
+            .with_context(|| format!("Camera {} format {:?} is not found", self.id, fmt))?;
+        info!(
+            "Camera {} set format {:?}, source {:?}",
+            self.id, fmt, route.src
+        );
+        self.backend.lock().unwrap().set_fmt(&route.src)?;
+
+        let mut locked_state = self.state.lock().unwrap();
+        *locked_state = ConvertState {
+            conversion: route.conversion,
+            frame_interval: Duration::from_nanos(fmt.get_frame_intervals()? as u64 * 100),
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    fn set_ctl(&self) -> Result<()> {
+        self.backend.lock().unwrap().set_ctl()
+    }
+
+    fn video_stream_on(&mut self) -> Result<()> {
+        self.backend.lock().unwrap().video_stream_on()
+    }
+
+    fn video_stream_off(&mut self) -> Result<()> {
+        self.backend.lock().unwrap().video_stream_off()
+    }
+
+    fn list_format(&mut self) -> Result<Vec<CameraFormatList>> {
+        let src_list = self.backend.lock().unwrap().list_format()?;
+        self.build_format_list(src_list)?;
+        Ok(self.fmt_list.clone())
+    }
+
+    fn reset(&mut self) {
+        self.backend.lock().unwrap().reset();
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.accepted = false;
+        locked_state.last_frame = None;
+        locked_state.output.clear();
+    }
+
+    fn get_frame_size(&self) -> usize {
+        let mut locked_state = self.state.lock().unwrap();
+        if !locked_state.accepted && !self.accept_frame(&mut locked_state) {
+            return 0;
+        }
+        match locked_state.conversion {
+            Some(_) => locked_state.output.len(),
+            None => self.backend.lock().unwrap().get_frame_size(),
+        }
+    }
+
+    fn get_frame(&self, iovecs: &[Iovec], frame_offset: usize, len: usize) -> Result<usize> {
+        let locked_state = self.state.lock().unwrap();
+        if locked_state.conversion.is_none() {
+            drop(locked_state);
+            return self
+                .backend
+                .lock()
+                .unwrap()
+                .get_frame(iovecs, frame_offset, len);
+        }
+        if frame_offset + len > locked_state.output.len() {
+            bail!("Invalid frame offset {} or len {}", frame_offset, len);
+        }
+        let mut copied = 0;
+        for iov in iovecs {
+            if len == copied {
+                break;
+            }
+            let cnt = std::cmp::min(iov.iov_len as usize, len - copied);
+            let start = frame_offset + copied;
+            mem_from_buf(&locked_state.output[start..start + cnt], iov.iov_base)
+                .with_context(|| format!("Failed to write data to {:x}", iov.iov_base))?;
+            copied += cnt;
+        }
+        Ok(copied)
+    }
+
+    fn get_format_by_index(&self, format_index: u8, frame_index: u8) -> Result<CamBasicFmt> {
+        let fmt = self
+            .fmt_list
+            .iter()
+            .find(|fmt| fmt.fmt_index == format_index)
+            .with_context(|| format!("format with idx {} is not found", format_index))?;
+        let frm = fmt
+            .frame
+            .iter()
+            .find(|frm| frm.index == frame_index)
+            .with_context(|| {
+                format!(
+                    "format/frame with idx {}/{} is not found",
+                    format_index, frame_index
+                )
+            })?;
+        Ok(CamBasicFmt {
+            width: frm.width,
+            height: frm.height,
+            fps: frame_fps(frm.interval)?,
+            fmttype: fmt.format,
+        })
+    }
+
+    fn next_frame(&mut self) -> Result<()> {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.accepted = false;
+        locked_state.output.clear();
+        drop(locked_state);
+        self.backend.lock().unwrap().next_frame()
+    }
+
+    fn register_notify_cb(&mut self, cb: CameraNotifyCallback) {
+        self.backend.lock().unwrap().register_notify_cb(cb);
+    }
+
+    fn register_broken_cb(&mut self, cb: CameraBrokenCallback) {
+        self.backend.lock().unwrap().register_broken_cb(cb);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_yuy2_conversion() {
+        // 2x2 YUY2 frame.
+        let frame = [10, 100, 20, 200, 30, 110, 40, 210];
+        let image = YuvImage::from_yuy2(&frame, 2, 2).unwrap();
+        assert_eq!(image.to_yuy2(2, 2), frame);
+        assert_eq!(image.to_nv12(2, 2), [10, 20, 30, 40, 100, 200]);
+        // Scale down to 2x1.
+        assert_eq!(image.to_yuy2(2, 1), [10, 100, 20, 200]);
+        assert!(YuvImage::from_yuy2(&frame, 4, 2).is_err());
+    }
+
+    #[test]
+    fn test_best_source_frames() {
+        let frame = |width, height, index| CameraFrame {
+            width,
+            height,
+            index,
+            interval: INTERVALS_PER_SEC / 30,
+        };
+        let sources = vec![
+            (FmtType::Mjpg, frame(1280, 720, 1)),
+            (FmtType::Mjpg, frame(640, 480, 2)),
+            (FmtType::Yuy2, frame(640, 480, 1)),
+        ];
+        let src = best_source_frames(&sources, FmtType::Yuy2, 1280, 720).unwrap();
+        assert_eq!(src[0].0, FmtType::Mjpg);
+        let src = best_source_frames(&sources, FmtType::Yuy2, 640, 360).unwrap();
+        assert_eq!((src[0].1.width, src[0].1.height), (1280, 720));
+        let src = best_source_frames(&sources, FmtType::Nv12, 320, 240).unwrap();
+        assert_eq!(src[0].0, FmtType::Yuy2);
+        // The YUY2 frame of the backend is used directly.
+        let src = best_source_frames(&sources, FmtType::Yuy2, 640, 480).unwrap();
+        assert_eq!(src[0].0, FmtType::Mjpg);
+        assert!(best_source_frames(&sources, FmtType::Yuy2, 1920, 1080).is_none());
+    }
+}
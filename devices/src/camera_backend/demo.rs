@@ -23,6 +23,7 @@ use log::{debug, error, info};
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
+use super::convert::yuy2_to_nv12;
 use super::INTERVALS_PER_SEC;
 use crate::camera_backend::{
     CamBasicFmt, CameraBackend, CameraBrokenCallback, CameraFormatList, CameraFrame,
@@ -250,6 +251,9 @@ impl ImageFrame {
             FmtType::Mjpg => build_fake_mjpg(width, height),
             FmtType::Yuy2 => convert_to_yuy2(data.deref(), width, height),
             FmtType::Rgb565 => data.deref().to_vec(),
+            FmtType::Nv12 => {
+                yuy2_to_nv12(&convert_to_yuy2(data.deref(), width, height), width, height)?
+            }
         };
         self.frame_idx += 1;
         if self.frame_idx > FRAME_IDX_LIMIT {
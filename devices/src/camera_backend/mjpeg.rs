@@ -0,0 +1,700 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Decoder of the MJPEG frames, which are baseline JPEG images. The huffman tables are
+//! usually omitted in the MJPEG frames of cameras, the default tables of the JPEG spec
+//! (Annex K.3) are used in that case.
+
+use anyhow::{bail, Context, Result};
+
+use super::convert::{YuvImage, YuvPlane};
+
+const MARKER_SOF0: u8 = 0xc0;
+const MARKER_SOF1: u8 = 0xc1;
+const MARKER_SOF2: u8 = 0xc2;
+const MARKER_SOF15: u8 = 0xcf;
+const MARKER_DHT: u8 = 0xc4;
+const MARKER_JPG: u8 = 0xc8;
+const MARKER_DAC: u8 = 0xcc;
+const MARKER_RST0: u8 = 0xd0;
+const MARKER_RST7: u8 = 0xd7;
+const MARKER_SOI: u8 = 0xd8;
+const MARKER_EOI: u8 = 0xd9;
+const MARKER_SOS: u8 = 0xda;
+const MARKER_DQT: u8 = 0xdb;
+const MARKER_DRI: u8 = 0xdd;
+
+const MAX_COMPONENTS: usize = 3;
+const MAX_SAMPLING_FACTOR: usize = 2;
+
+/// Natural order index of the coefficients in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const DEFAULT_DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DEFAULT_DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DEFAULT_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const DEFAULT_AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const DEFAULT_AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const DEFAULT_AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const DEFAULT_AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Canonical huffman table, see JPEG spec F.2.2.3.
+#[derive(Clone)]
+struct HuffmanTable {
+    maxcode: [i32; 17],
+    mincode: [i32; 17],
+    valptr: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(bits: &[u8], values: &[u8]) -> Result<Self> {
+        let total: usize = bits.iter().map(|&n| n as usize).sum();
+        if total > values.len() || total > 256 {
+            bail!("Invalid huffman table with {} codes", total);
+        }
+        let mut table = HuffmanTable {
+            maxcode: [-1; 17],
+            mincode: [0; 17],
+            valptr: [0; 17],
+            values: values[..total].to_vec(),
+        };
+        let mut code = 0_i32;
+        let mut k = 0_usize;
+        for (i, &count) in bits.iter().enumerate().take(16) {
+            let len = i + 1;
+            let count = count as i32;
+            table.valptr[len] = k;
+            table.mincode[len] = code;
+            code += count;
+            k += count as usize;
+            if count > 0 {
+                table.maxcode[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        Ok(table)
+    }
+}
+
+/// Reader of the entropy-coded data, which removes the stuffed zero bytes and stops at markers.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    nbits: u32,
+    marker_hit: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        BitReader {
+            data,
+            pos,
+            bits: 0,
+            nbits: 0,
+            marker_hit: false,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.nbits <= 24 {
+            let mut byte = 0;
+            if !self.marker_hit && self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xff {
+                    match self.data.get(self.pos + 1) {
+                        Some(0) => self.pos += 2,
+                        _ => {
+                            // Pad the left bits with zeros when a marker is hit.
+                            self.marker_hit = true;
+                            byte = 0;
+                        }
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.bits |= (byte as u32) << (24 - self.nbits);
+            self.nbits += 8;
+        }
+    }
+
+    fn get_bits(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        if self.nbits < n {
+            self.fill();
+        }
+        let val = self.bits >> (32 - n);
+        self.bits <<= n;
+        self.nbits -= n;
+        val
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8> {
+        let mut code = self.get_bits(1) as i32;
+        for (len, &maxcode) in table.maxcode.iter().enumerate().skip(1) {
+            if code <= maxcode {
+                let idx = table.valptr[len] + (code - table.mincode[len]) as usize;
+                return table
+                    .values
+                    .get(idx)
+                    .copied()
+                    .with_context(|| "Invalid huffman code");
+            }
+            code = (code << 1) | self.get_bits(1) as i32;
+        }
+        bail!("Invalid huffman code");
+    }
+
+    fn receive_extend(&mut self, size: u32) -> i32 {
+        if size == 0 {
+            return 0;
+        }
+        let val = self.get_bits(size) as i32;
+        if val < 1 << (size - 1) {
+            val - (1 << size) + 1
+        } else {
+            val
+        }
+    }
+
+    /// Skip the RSTn marker at the end of the restart interval.
+    fn restart(&mut self) {
+        self.bits = 0;
+        self.nbits = 0;
+        self.marker_hit = false;
+        if self.pos + 1 < self.data.len()
+            && self.data[self.pos] == 0xff
+            && (MARKER_RST0..=MARKER_RST7).contains(&self.data[self.pos + 1])
+        {
+            self.pos += 2;
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    tq: usize,
+    td: usize,
+    ta: usize,
+    pred: i32,
+    /// Number of blocks per line and per column, padded to the MCUs.
+    blocks_w: usize,
+    blocks_h: usize,
+    data: Vec<u8>,
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    qt: [[u16; 64]; 4],
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    restart_interval: usize,
+    hmax: usize,
+    vmax: usize,
+    idct_table: [[f32; 8]; 8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        let mut idct_table = [[0_f32; 8]; 8];
+        for (x, row) in idct_table.iter_mut().enumerate() {
+            for (u, c) in row.iter_mut().enumerate() {
+                let cu = if u == 0 {
+                    std::f32::consts::FRAC_1_SQRT_2
+                } else {
+                    1.0
+                };
+                *c = cu * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos() / 2.0;
+            }
+        }
+        Decoder {
+            data,
+            pos: 0,
+            width: 0,
+            height: 0,
+            components: Vec::new(),
+            qt: [[1; 64]; 4],
+            dc_tables: [None, None, None, None],
+            ac_tables: [None, None, None, None],
+            restart_interval: 0,
+            hmax: 1,
+            vmax: 1,
+            idct_table,
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let val = *self
+            .data
+            .get(self.pos)
+            .with_context(|| "Unexpected end of MJPEG frame")?;
+        self.pos += 1;
+        Ok(val)
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(((self.read_u8()? as u16) << 8) | self.read_u8()? as u16)
+    }
+
+    /// Read the segment following the marker, excluding its length field.
+    fn read_segment(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u16()? as usize;
+        if len < 2 || self.pos + len - 2 > self.data.len() {
+            bail!("Invalid segment length {}", len);
+        }
+        let data: &'a [u8] = self.data;
+        let seg = &data[self.pos..self.pos + len - 2];
+        self.pos += len - 2;
+        Ok(seg)
+    }
+
+    fn next_marker(&mut self) -> Result<u8> {
+        loop {
+            if self.read_u8()? != 0xff {
+                continue;
+            }
+            let mut marker = self.read_u8()?;
+            // Fill bytes.
+            while marker == 0xff {
+                marker = self.read_u8()?;
+            }
+            if marker != 0 {
+                return Ok(marker);
+            }
+        }
+    }
+
+    fn decode(mut self) -> Result<YuvImage> {
+        if self.read_u8()? != 0xff || self.read_u8()? != MARKER_SOI {
+            bail!("MJPEG frame doesn't start with SOI");
+        }
+        let mut frame_parsed = false;
+        loop {
+            match self.next_marker()? {
+                MARKER_SOF0 | MARKER_SOF1 => {
+                    self.parse_sof()?;
+                    frame_parsed = true;
+                }
+                MARKER_DHT => self.parse_dht()?,
+                m @ MARKER_SOF2..=MARKER_SOF15 if m != MARKER_JPG && m != MARKER_DAC => {
+                    bail!("Unsupported JPEG process, SOF marker {:x}", m);
+                }
+                MARKER_DQT => self.parse_dqt()?,
+                MARKER_DRI => {
+                    let seg = self.read_segment()?;
+                    if seg.len() < 2 {
+                        bail!("Invalid DRI segment");
+                    }
+                    self.restart_interval = ((seg[0] as usize) << 8) | seg[1] as usize;
+                }
+                MARKER_SOS => {
+                    if !frame_parsed {
+                        bail!("SOS before SOF");
+                    }
+                    self.parse_sos()?;
+                }
+                MARKER_EOI => break,
+                MARKER_RST0..=MARKER_RST7 => {}
+                _ => {
+                    self.read_segment()?;
+                }
+            }
+        }
+        if !frame_parsed {
+            bail!("No frame in MJPEG data");
+        }
+        Ok(self.into_image())
+    }
+
+    fn parse_sof(&mut self) -> Result<()> {
+        let seg = self.read_segment()?;
+        if seg.len() < 6 || seg[0] != 8 {
+            bail!("Only 8 bits precision is supported");
+        }
+        self.height = ((seg[1] as usize) << 8) | seg[2] as usize;
+        self.width = ((seg[3] as usize) << 8) | seg[4] as usize;
+        let num = seg[5] as usize;
+        if self.width == 0 || self.height == 0 {
+            bail!("Invalid image size {}x{}", self.width, self.height);
+        }
+        if (num != 1 && num != MAX_COMPONENTS) || seg.len() < 6 + num * 3 {
+            bail!("Unsupported component number {}", num);
+        }
+        self.components.clear();
+        for i in 0..num {
+            let c = &seg[6 + i * 3..9 + i * 3];
+            let comp = Component {
+                id: c[0],
+                h: (c[1] >> 4) as usize,
+                v: (c[1] & 0xf) as usize,
+                tq: (c[2] & 0x3) as usize,
+                ..Default::default()
+            };
+            if !(1..=MAX_SAMPLING_FACTOR).contains(&comp.h)
+                || !(1..=MAX_SAMPLING_FACTOR).contains(&comp.v)
+            {
+                bail!("Unsupported sampling factor {}x{}", comp.h, comp.v);
+            }
+            self.components.push(comp);
+        }
+        if num == 1 {
+            // The single component is not interleaved, its sampling factor is meaningless.
+            self.components[0].h = 1;
+            self.components[0].v = 1;
+        }
+        self.hmax = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        self.vmax = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        let mcux = (self.width + 8 * self.hmax - 1) / (8 * self.hmax);
+        let mcuy = (self.height + 8 * self.vmax - 1) / (8 * self.vmax);
+        for comp in self.components.iter_mut() {
+            comp.blocks_w = mcux * comp.h;
+            comp.blocks_h = mcuy * comp.v;
+            comp.data = vec![0; comp.blocks_w * comp.blocks_h * 64];
+        }
+        Ok(())
+    }
+
+    fn parse_dht(&mut self) -> Result<()> {
+        let seg = self.read_segment()?;
+        let mut off = 0;
+        while off < seg.len() {
+            if off + 17 > seg.len() {
+                bail!("Invalid DHT segment");
+            }
+            let class = seg[off] >> 4;
+            let id = (seg[off] & 0x3) as usize;
+            let bits = &seg[off + 1..off + 17];
+            let total: usize = bits.iter().map(|&n| n as usize).sum();
+            if off + 17 + total > seg.len() {
+                bail!("Invalid DHT segment");
+            }
+            let table = HuffmanTable::new(bits, &seg[off + 17..off + 17 + total])?;
+            match class {
+                0 => self.dc_tables[id] = Some(table),
+                _ => self.ac_tables[id] = Some(table),
+            }
+            off += 17 + total;
+        }
+        Ok(())
+    }
+
+    fn parse_dqt(&mut self) -> Result<()> {
+        let seg = self.read_segment()?;
+        let mut off = 0;
+        while off < seg.len() {
+            let precision = seg[off] >> 4;
+            let id = (seg[off] & 0x3) as usize;
+            off += 1;
+            let size = if precision == 0 { 64 } else { 128 };
+            if off + size > seg.len() {
+                bail!("Invalid DQT segment");
+            }
+            for (k, q) in self.qt[id].iter_mut().enumerate() {
+                *q = match precision {
+                    0 => seg[off + k] as u16,
+                    _ => ((seg[off + 2 * k] as u16) << 8) | seg[off + 2 * k + 1] as u16,
+                };
+            }
+            off += size;
+        }
+        Ok(())
+    }
+
+    fn install_default_tables(&mut self) -> Result<()> {
+        if self.dc_tables[0].is_none() {
+            self.dc_tables[0] = Some(HuffmanTable::new(
+                &DEFAULT_DC_LUMA_BITS,
+                &DEFAULT_DC_VALUES,
+            )?);
+        }
+        if self.dc_tables[1].is_none() {
+            self.dc_tables[1] = Some(HuffmanTable::new(
+                &DEFAULT_DC_CHROMA_BITS,
+                &DEFAULT_DC_VALUES,
+            )?);
+        }
+        if self.ac_tables[0].is_none() {
+            self.ac_tables[0] = Some(HuffmanTable::new(
+                &DEFAULT_AC_LUMA_BITS,
+                &DEFAULT_AC_LUMA_VALUES,
+            )?);
+        }
+        if self.ac_tables[1].is_none() {
+            self.ac_tables[1] = Some(HuffmanTable::new(
+                &DEFAULT_AC_CHROMA_BITS,
+                &DEFAULT_AC_CHROMA_VALUES,
+            )?);
+        }
+        Ok(())
+    }
+
+    fn parse_sos(&mut self) -> Result<()> {
+        let seg = self.read_segment()?;
+        if seg.is_empty() {
+            bail!("Invalid SOS segment");
+        }
+        let num = seg[0] as usize;
+        if num == 0 || num > self.components.len() || seg.len() < 1 + num * 2 + 3 {
+            bail!("Invalid SOS segment");
+        }
+        let mut scan = Vec::with_capacity(num);
+        for i in 0..num {
+            let id = seg[1 + i * 2];
+            let tables = seg[2 + i * 2];
+            let idx = self
+                .components
+                .iter()
+                .position(|c| c.id == id)
+                .with_context(|| format!("Unknown component {} in scan", id))?;
+            self.components[idx].td = (tables >> 4) as usize & 0x3;
+            self.components[idx].ta = (tables & 0xf) as usize & 0x3;
+            scan.push(idx);
+        }
+        self.install_default_tables()?;
+        for comp in self.components.iter_mut() {
+            comp.pred = 0;
+        }
+
+        let mut reader = BitReader::new(self.data, self.pos);
+        let mut coef = [0_i32; 64];
+        let (units_x, units_y) = if num == 1 {
+            let comp = &self.components[scan[0]];
+            (
+                (self.width * comp.h / self.hmax + 7) / 8,
+                (self.height * comp.v / self.vmax + 7) / 8,
+            )
+        } else {
+            (
+                self.components[scan[0]].blocks_w / self.components[scan[0]].h,
+                self.components[scan[0]].blocks_h / self.components[scan[0]].v,
+            )
+        };
+        let mut units = 0;
+        for uy in 0..units_y {
+            for ux in 0..units_x {
+                if self.restart_interval != 0 && units != 0 && units % self.restart_interval == 0 {
+                    reader.restart();
+                    for comp in self.components.iter_mut() {
+                        comp.pred = 0;
+                    }
+                }
+                units += 1;
+                if num == 1 {
+                    self.decode_block(&mut reader, scan[0], &mut coef, ux, uy)?;
+                    continue;
+                }
+                for &idx in scan.iter() {
+                    let (h, v) = (self.components[idx].h, self.components[idx].v);
+                    for by in 0..v {
+                        for bx in 0..h {
+                            self.decode_block(
+                                &mut reader,
+                                idx,
+                                &mut coef,
+                                ux * h + bx,
+                                uy * v + by,
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+        // Continue parsing from the marker after the entropy-coded data.
+        self.pos = reader.pos;
+        Ok(())
+    }
+
+    fn decode_block(
+        &mut self,
+        reader: &mut BitReader,
+        idx: usize,
+        coef: &mut [i32; 64],
+        bx: usize,
+        by: usize,
+    ) -> Result<()> {
+        let comp = &self.components[idx];
+        let dc_table = self.dc_tables[comp.td]
+            .as_ref()
+            .with_context(|| "Missing DC huffman table")?;
+        let ac_table = self.ac_tables[comp.ta]
+            .as_ref()
+            .with_context(|| "Missing AC huffman table")?;
+        let qt = &self.qt[comp.tq];
+
+        coef.iter_mut().for_each(|c| *c = 0);
+        let size = reader.decode(dc_table)? as u32;
+        if size > 11 {
+            bail!("Invalid DC coefficient size {}", size);
+        }
+        let pred = comp.pred + reader.receive_extend(size);
+        coef[0] = pred * qt[0] as i32;
+        let mut k = 1;
+        while k < 64 {
+            let rs = reader.decode(ac_table)?;
+            let (run, size) = ((rs >> 4) as usize, (rs & 0xf) as u32);
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run;
+            if k > 63 {
+                bail!("Invalid AC coefficient index {}", k);
+            }
+            coef[ZIGZAG[k]] = reader.receive_extend(size) * qt[k] as i32;
+            k += 1;
+        }
+        self.components[idx].pred = pred;
+
+        if bx >= self.components[idx].blocks_w || by >= self.components[idx].blocks_h {
+            // Blocks out of the padded plane, which should not happen.
+            return Ok(());
+        }
+        self.idct_block(idx, coef, bx, by);
+        Ok(())
+    }
+
+    fn idct_block(&mut self, idx: usize, coef: &[i32; 64], bx: usize, by: usize) {
+        let t = &self.idct_table;
+        let mut tmp = [0_f32; 64];
+        // Rows: tmp[v][x] = sum_u C(u) * coef[v][u] * cos((2x+1)u/16).
+        for v in 0..8 {
+            let row = &coef[v * 8..v * 8 + 8];
+            for (x, tx) in t.iter().enumerate() {
+                tmp[v * 8 + x] = tx.iter().zip(row).map(|(c, &f)| c * f as f32).sum();
+            }
+        }
+        let comp = &mut self.components[idx];
+        let stride = comp.blocks_w * 8;
+        for (y, ty) in t.iter().enumerate() {
+            for x in 0..8 {
+                let sum: f32 = ty.iter().enumerate().map(|(v, c)| c * tmp[v * 8 + x]).sum();
+                let val = (sum + 128.0).round().clamp(0.0, 255.0) as u8;
+                comp.data[(by * 8 + y) * stride + bx * 8 + x] = val;
+            }
+        }
+    }
+
+    fn into_image(self) -> YuvImage {
+        let (hmax, vmax) = (self.hmax, self.vmax);
+        let planes = self
+            .components
+            .into_iter()
+            .map(|comp| YuvPlane {
+                stride: comp.blocks_w * 8,
+                h: comp.h,
+                hmax,
+                v: comp.v,
+                vmax,
+                data: comp.data,
+            })
+            .collect();
+        YuvImage {
+            width: self.width,
+            height: self.height,
+            planes,
+        }
+    }
+}
+
+/// Decode the MJPEG frame into YUV planes.
+pub fn decode_mjpeg(data: &[u8]) -> Result<YuvImage> {
+    Decoder::new(data).decode()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 8x8 grayscale baseline JPEG without huffman tables, all quantization values are 1.
+    fn gray_jpeg(scan: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xff, MARKER_SOI, 0xff, MARKER_DQT, 0x00, 0x43, 0x00];
+        data.extend_from_slice(&[1; 64]);
+        data.extend_from_slice(&[
+            0xff,
+            MARKER_SOF0,
+            0x00,
+            0x0b,
+            0x08,
+            0x00,
+            0x08,
+            0x00,
+            0x08,
+            0x01,
+            0x01,
+            0x11,
+            0x00,
+        ]);
+        data.extend_from_slice(&[
+            0xff, MARKER_SOS, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00,
+        ]);
+        data.extend_from_slice(scan);
+        data.extend_from_slice(&[0xff, MARKER_EOI]);
+        data
+    }
+
+    #[test]
+    fn test_decode_mjpeg() {
+        // DC diff 0 and EOB.
+        let image = decode_mjpeg(&gray_jpeg(&[0x2b])).unwrap();
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.planes.len(), 1);
+        assert!(image.planes[0].data.iter().all(|&y| y == 128));
+
+        // DC diff 128 and EOB, each sample is 128 + 128 / 8.
+        let image = decode_mjpeg(&gray_jpeg(&[0xfa, 0x02, 0xbf])).unwrap();
+        assert!(image.planes[0].data.iter().all(|&y| y == 144));
+
+        assert!(decode_mjpeg(&[0xff, MARKER_SOI, 0xff, 0xe0]).is_err());
+    }
+}
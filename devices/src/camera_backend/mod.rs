@@ -14,7 +14,9 @@
 //! Backend devices, such as v4l2, usb, or demo device, etc., shall implement trait
 //! CameraBackend.
 
+pub mod convert;
 pub mod demo;
+pub mod mjpeg;
 #[cfg(feature = "usb_camera_v4l2")]
 pub mod v4l2;
 
@@ -22,6 +24,7 @@ use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Context, Result};
 
+use self::convert::ConvertCameraBackend;
 use self::demo::DemoCameraBackend;
 #[cfg(feature = "usb_camera_v4l2")]
 use self::v4l2::V4l2CameraBackend;
@@ -54,6 +57,7 @@ pub enum FmtType {
     Yuy2 = 0,
     Rgb565,
     Mjpg,
+    Nv12,
 }
 
 #[derive(Clone, Debug)]
@@ -98,6 +102,7 @@ macro_rules! video_fourcc {
 pub const PIXFMT_RGB565: u32 = video_fourcc!('R', 'G', 'B', 'P');
 pub const PIXFMT_YUYV: u32 = video_fourcc!('Y', 'U', 'Y', 'V');
 pub const PIXFMT_MJPG: u32 = video_fourcc!('M', 'J', 'P', 'G');
+pub const PIXFMT_NV12: u32 = video_fourcc!('N', 'V', '1', '2');
 
 /// Callback function which is called when frame data is coming.
 pub type CameraNotifyCallback = Arc<dyn Fn() + Send + Sync>;
@@ -144,6 +149,8 @@ pub trait CameraBackend: Send + Sync {
 }
 
 pub fn create_cam_backend(config: UsbCameraConfig) -> Result<Arc<Mutex<dyn CameraBackend>>> {
+    let id = config.id.clone().unwrap();
+    let convert = config.convert;
     let cam: Arc<Mutex<dyn CameraBackend>> = match config.backend {
         #[cfg(feature = "usb_camera_v4l2")]
         CamBackendType::V4l2 => Arc::new(Mutex::new(V4l2CameraBackend::new(
@@ -161,5 +168,8 @@ pub fn create_cam_backend(config: UsbCameraConfig) -> Result<Arc<Mutex<dyn Camer
         )?)),
     };
 
+    if convert {
+        return Ok(Arc::new(Mutex::new(ConvertCameraBackend::new(id, cam))));
+    }
     Ok(cam)
 }
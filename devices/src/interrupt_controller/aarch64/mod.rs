@@ -20,7 +20,7 @@ pub use gicv3::{GICv3, GICv3Config};
 
 use std::sync::Arc;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use kvm_ioctls::DeviceFd;
 
 use crate::interrupt_controller::error::InterruptError;
@@ -180,10 +180,17 @@ impl InterruptController {
         self.gic.reset().with_context(|| "Failed to reset GIC")
     }
 
-    /// Change `InterruptController` lifecycle state to `Stopped`.
-    pub fn stop(&self) {
-        self.gic
-            .notify_lifecycle(KvmVmState::Running, KvmVmState::Paused);
+    /// Change `InterruptController` lifecycle state to `Stopped`. The pending tables and
+    /// the ITS tables are flushed into guest RAM, which must succeed before the guest
+    /// memory is saved for migration or snapshot.
+    pub fn stop(&self) -> Result<()> {
+        if !self
+            .gic
+            .notify_lifecycle(KvmVmState::Running, KvmVmState::Paused)
+        {
+            bail!("Failed to flush GIC tables into guest memory");
+        }
+        Ok(())
     }
 
     pub fn get_redist_count(&self) -> u8 {
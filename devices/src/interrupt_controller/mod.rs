@@ -19,21 +19,40 @@
 //! This module offers support for:
 //! 1. Create kvm-based interrupt controller.
 //! 2. Manager lifecycle for `GIC`.
+//! 3. Emulate `IOAPIC` and `PIC` in userspace when kvm only emulates the local APICs.
 //!
 //! ## Platform Support
 //!
 //! - `aarch64`
+//! - `x86_64`
 
+#[cfg(target_arch = "aarch64")]
 #[allow(clippy::upper_case_acronyms)]
 mod aarch64;
+#[cfg(target_arch = "aarch64")]
 mod error;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
 
+#[cfg(target_arch = "aarch64")]
 pub use anyhow::Result;
 
+#[cfg(target_arch = "aarch64")]
 pub use aarch64::GICConfig as ICGICConfig;
+#[cfg(target_arch = "aarch64")]
 pub use aarch64::GICv2Config as ICGICv2Config;
+#[cfg(target_arch = "aarch64")]
 pub use aarch64::GICv3Config as ICGICv3Config;
+#[cfg(target_arch = "aarch64")]
 pub use aarch64::InterruptController;
+#[cfg(target_arch = "aarch64")]
 pub use aarch64::GIC_IRQ_INTERNAL;
+#[cfg(target_arch = "aarch64")]
 pub use aarch64::GIC_IRQ_MAX;
+#[cfg(target_arch = "aarch64")]
 pub use error::InterruptError;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::{
+    gsi_irq_handler, IoApic, IoApicRouteHandler, MsiHandler, Pic, PicOutputHandler,
+    IOAPIC_NUM_PINS, IOAPIC_REGION_SIZE,
+};
@@ -0,0 +1,590 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use log::{error, warn};
+
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::AmlBuilder;
+use address_space::GuestAddress;
+use hypervisor::MsiMessage;
+use migration::{
+    snapshot::IOAPIC_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
+    MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
+use util::byte_code::ByteCode;
+use util::num_ops::{read_data_u32, write_data_u32};
+
+/// Number of the input pins of IOAPIC.
+pub const IOAPIC_NUM_PINS: usize = 24;
+/// Size of the IOAPIC register block.
+pub const IOAPIC_REGION_SIZE: u64 = 0x1000;
+/// Version 0x20 supports the EOI register.
+const IOAPIC_VERSION: u32 = 0x20;
+
+/// Register select register.
+const IOAPIC_IOREGSEL: u64 = 0x00;
+/// Register data window.
+const IOAPIC_IOWIN: u64 = 0x10;
+/// EOI register.
+const IOAPIC_EOI: u64 = 0x40;
+
+/// Indirect registers accessed through the data window.
+const IOAPIC_REG_ID: u8 = 0x00;
+const IOAPIC_REG_VER: u8 = 0x01;
+const IOAPIC_REG_ARB: u8 = 0x02;
+/// Redirection table, each entry takes two registers.
+const IOAPIC_REG_REDTBL_BASE: u8 = 0x10;
+
+const IOAPIC_ID_SHIFT: u32 = 24;
+const IOAPIC_ID_MASK: u8 = 0x0F;
+const IOAPIC_VER_ENTRIES_SHIFT: u32 = 16;
+
+// Fields of the redirection table entry.
+const IOAPIC_LVT_VECTOR_MASK: u64 = 0xFF;
+const IOAPIC_LVT_DELIV_MODE_SHIFT: u64 = 8;
+const IOAPIC_LVT_DELIV_MODE_MASK: u64 = 0x7 << IOAPIC_LVT_DELIV_MODE_SHIFT;
+const IOAPIC_LVT_DEST_MODE_SHIFT: u64 = 11;
+const IOAPIC_LVT_DELIV_STATUS: u64 = 1 << 12;
+const IOAPIC_LVT_REMOTE_IRR: u64 = 1 << 14;
+const IOAPIC_LVT_TRIGGER_MODE_SHIFT: u64 = 15;
+const IOAPIC_LVT_TRIGGER_MODE: u64 = 1 << IOAPIC_LVT_TRIGGER_MODE_SHIFT;
+const IOAPIC_LVT_MASKED: u64 = 1 << 16;
+const IOAPIC_LVT_DEST_SHIFT: u64 = 56;
+// Delivery status and remote IRR are read-only.
+const IOAPIC_LVT_RO_BITS: u64 = IOAPIC_LVT_DELIV_STATUS | IOAPIC_LVT_REMOTE_IRR;
+// The interrupt of PIC is delivered to the local APICs by the PIC itself.
+const IOAPIC_DM_EXTINT: u64 = 0x7;
+
+/// Base address of the interrupt message of local APIC.
+const MSI_ADDR_BASE: u64 = 0xFEE0_0000;
+const MSI_ADDR_DEST_SHIFT: u64 = 12;
+const MSI_ADDR_DEST_MODE_SHIFT: u64 = 2;
+const MSI_DATA_TRIGGER_SHIFT: u64 = 15;
+
+/// Deliver the interrupt message to the local APICs.
+pub type MsiHandler = Box<dyn Fn(MsiMessage) -> Result<()> + Send + Sync>;
+/// Update the routes of the pins in kvm, the interrupts signaled by irqfd are delivered
+/// to the local APICs with them. The masked pins are `None`.
+pub type IoApicRouteHandler = Box<dyn Fn(&[Option<MsiMessage>]) -> Result<()> + Send + Sync>;
+
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct IoApicState {
+    /// Index of the register accessed through the data window.
+    ioregsel: u32,
+    /// ID of the IOAPIC.
+    id: u32,
+    /// Interrupt requests of the pins.
+    irr: u32,
+    /// Level of the input lines, which is used to detect the rising edges.
+    line_level: u32,
+    /// Redirection table.
+    ioredtbl: [u64; 24],
+}
+
+impl IoApicState {
+    fn new() -> Self {
+        IoApicState {
+            ioredtbl: [IOAPIC_LVT_MASKED; IOAPIC_NUM_PINS],
+            ..Default::default()
+        }
+    }
+}
+
+/// IOAPIC emulated in userspace while the local APICs are emulated in kvm (split
+/// irqchip). The interrupts are delivered to the local APICs as MSI messages, and the
+/// level-triggered interrupts are acknowledged by the EOI exits of vCPUs.
+pub struct IoApic {
+    base: SysBusDevBase,
+    state: IoApicState,
+    msi_handler: MsiHandler,
+    route_handler: IoApicRouteHandler,
+}
+
+impl IoApic {
+    /// Create IOAPIC device.
+    ///
+    /// # Arguments
+    ///
+    /// * `msi_handler` - Deliver the interrupt message to the local APICs.
+    /// * `route_handler` - Update the routes of the pins in kvm.
+    pub fn new(msi_handler: MsiHandler, route_handler: IoApicRouteHandler) -> Self {
+        IoApic {
+            base: SysBusDevBase::new(SysBusDevType::IoApic),
+            state: IoApicState::new(),
+            msi_handler,
+            route_handler,
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<IoApic>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| "Failed to set system resource for IOAPIC")?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus
+            .attach_device(&dev, region_base, region_size, "IOAPIC")
+            .with_context(|| "Failed to attach IOAPIC to system bus")?;
+        MigrationManager::register_device_instance(
+            IoApicState::descriptor(),
+            dev.clone(),
+            IOAPIC_SNAPSHOT_ID,
+        );
+        dev.lock().unwrap().update_routes();
+        Ok(dev)
+    }
+
+    /// Set the level of the input pin.
+    pub fn set_irq(&mut self, pin: u32, level: bool) {
+        // ISA IRQ 0 is connected to pin 2, which is reported by the interrupt source override.
+        let pin = if pin == 0 { 2 } else { pin };
+        if pin as usize >= IOAPIC_NUM_PINS {
+            return;
+        }
+
+        let mask = 1_u32 << pin;
+        let entry = self.state.ioredtbl[pin as usize];
+        if entry & IOAPIC_LVT_TRIGGER_MODE != 0 {
+            if level {
+                self.state.irr |= mask;
+            } else {
+                self.state.irr &= !mask;
+            }
+        } else if level && self.state.line_level & mask == 0 {
+            self.state.irr |= mask;
+        }
+        if level {
+            self.state.line_level |= mask;
+        } else {
+            self.state.line_level &= !mask;
+        }
+        self.service();
+    }
+
+    /// The level-triggered interrupt `vector` is acknowledged by guest.
+    pub fn eoi(&mut self, vector: u8) {
+        let mut need_service = false;
+        for entry in self.state.ioredtbl.iter_mut() {
+            if entry_vector(*entry) != vector
+                || *entry & IOAPIC_LVT_TRIGGER_MODE == 0
+                || *entry & IOAPIC_LVT_REMOTE_IRR == 0
+            {
+                continue;
+            }
+            *entry &= !IOAPIC_LVT_REMOTE_IRR;
+            need_service = true;
+        }
+        // The interrupt is delivered again if the line is still asserted.
+        if need_service {
+            self.service();
+        }
+    }
+
+    /// Deliver the pending interrupts of the unmasked pins.
+    fn service(&mut self) {
+        for pin in 0..IOAPIC_NUM_PINS {
+            let mask = 1_u32 << pin;
+            if self.state.irr & mask == 0 {
+                continue;
+            }
+            let entry = self.state.ioredtbl[pin];
+            if entry & IOAPIC_LVT_MASKED != 0 {
+                continue;
+            }
+            if entry & IOAPIC_LVT_TRIGGER_MODE != 0 {
+                if entry & IOAPIC_LVT_REMOTE_IRR != 0 {
+                    continue;
+                }
+                self.state.ioredtbl[pin] |= IOAPIC_LVT_REMOTE_IRR;
+            } else {
+                self.state.irr &= !mask;
+            }
+
+            if let Some(msi) = entry_to_msi(entry) {
+                if let Err(e) = (self.msi_handler)(msi) {
+                    error!(
+                        "IOAPIC: failed to deliver interrupt of pin {}: {:?}",
+                        pin, e
+                    );
+                }
+            } else {
+                warn!(
+                    "IOAPIC: unsupported delivery mode of pin {}, entry 0x{:x}",
+                    pin, entry
+                );
+            }
+        }
+    }
+
+    fn update_routes(&self) {
+        let routes: Vec<Option<MsiMessage>> = self
+            .state
+            .ioredtbl
+            .iter()
+            .map(|entry| {
+                if entry & IOAPIC_LVT_MASKED != 0 {
+                    None
+                } else {
+                    entry_to_msi(*entry)
+                }
+            })
+            .collect();
+        if let Err(e) = (self.route_handler)(&routes) {
+            error!("IOAPIC: failed to update routes: {:?}", e);
+        }
+    }
+
+    fn read_reg(&self) -> u32 {
+        let index = self.state.ioregsel as u8;
+        match index {
+            IOAPIC_REG_ID | IOAPIC_REG_ARB => self.state.id << IOAPIC_ID_SHIFT,
+            IOAPIC_REG_VER => {
+                ((IOAPIC_NUM_PINS as u32 - 1) << IOAPIC_VER_ENTRIES_SHIFT) | IOAPIC_VERSION
+            }
+            _ => match redtbl_index(index) {
+                Some((pin, high)) => {
+                    let entry = self.state.ioredtbl[pin];
+                    if high {
+                        (entry >> 32) as u32
+                    } else {
+                        entry as u32
+                    }
+                }
+                None => 0,
+            },
+        }
+    }
+
+    fn write_reg(&mut self, value: u32) {
+        let index = self.state.ioregsel as u8;
+        match index {
+            IOAPIC_REG_ID => {
+                self.state.id = ((value >> IOAPIC_ID_SHIFT) as u8 & IOAPIC_ID_MASK) as u32;
+            }
+            IOAPIC_REG_VER | IOAPIC_REG_ARB => {}
+            _ => {
+                let (pin, high) = match redtbl_index(index) {
+                    Some(redtbl) => redtbl,
+                    None => return,
+                };
+                let entry = &mut self.state.ioredtbl[pin];
+                let ro_bits = *entry & IOAPIC_LVT_RO_BITS;
+                if high {
+                    *entry = (*entry & 0xFFFF_FFFF) | ((value as u64) << 32);
+                } else {
+                    *entry = (*entry & !0xFFFF_FFFF) | value as u64;
+                }
+                *entry = (*entry & !IOAPIC_LVT_RO_BITS) | ro_bits;
+                // The remote IRR of edge-triggered interrupt is meaningless.
+                if *entry & IOAPIC_LVT_TRIGGER_MODE == 0 {
+                    *entry &= !IOAPIC_LVT_REMOTE_IRR;
+                }
+                self.update_routes();
+                self.service();
+            }
+        }
+    }
+}
+
+/// Get the pin and whether it's the high half of the redirection table entry.
+fn redtbl_index(index: u8) -> Option<(usize, bool)> {
+    let offset = index.checked_sub(IOAPIC_REG_REDTBL_BASE)? as usize;
+    if offset >= IOAPIC_NUM_PINS * 2 {
+        return None;
+    }
+    Some((offset / 2, offset % 2 != 0))
+}
+
+fn entry_vector(entry: u64) -> u8 {
+    (entry & IOAPIC_LVT_VECTOR_MASK) as u8
+}
+
+/// Compose the interrupt message of the redirection table entry.
+fn entry_to_msi(entry: u64) -> Option<MsiMessage> {
+    let delivery_mode = (entry & IOAPIC_LVT_DELIV_MODE_MASK) >> IOAPIC_LVT_DELIV_MODE_SHIFT;
+    if delivery_mode == IOAPIC_DM_EXTINT {
+        return None;
+    }
+    let dest = entry >> IOAPIC_LVT_DEST_SHIFT;
+    let dest_mode = (entry >> IOAPIC_LVT_DEST_MODE_SHIFT) & 0x1;
+    let trigger_mode = (entry >> IOAPIC_LVT_TRIGGER_MODE_SHIFT) & 0x1;
+    Some(MsiMessage {
+        address: MSI_ADDR_BASE
+            | (dest << MSI_ADDR_DEST_SHIFT)
+            | (dest_mode << MSI_ADDR_DEST_MODE_SHIFT),
+        data: (entry_vector(entry) as u64
+            | (delivery_mode << IOAPIC_LVT_DELIV_MODE_SHIFT)
+            | (trigger_mode << MSI_DATA_TRIGGER_SHIFT)) as u32,
+        dev_id: 0,
+    })
+}
+
+impl Device for IoApic {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for IoApic {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let value = match offset {
+            IOAPIC_IOREGSEL => self.state.ioregsel,
+            IOAPIC_IOWIN => self.read_reg(),
+            _ => {
+                error!(
+                    "IOAPIC: invalid read, offset 0x{:x}, size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+        };
+        write_data_u32(data, value)
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let mut value = 0_u32;
+        if !read_data_u32(data, &mut value) {
+            return false;
+        }
+        match offset {
+            IOAPIC_IOREGSEL => self.state.ioregsel = value & 0xFF,
+            IOAPIC_IOWIN => self.write_reg(value),
+            IOAPIC_EOI => self.eoi(value as u8),
+            _ => {
+                error!(
+                    "IOAPIC: invalid write, offset 0x{:x}, size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // The level of the input lines is kept by the devices.
+        self.state = IoApicState {
+            line_level: self.state.line_level,
+            ..IoApicState::new()
+        };
+        self.update_routes();
+        Ok(())
+    }
+}
+
+impl AmlBuilder for IoApic {
+    fn aml_bytes(&self) -> Vec<u8> {
+        // IOAPIC is described by MADT.
+        Vec::new()
+    }
+}
+
+impl StateTransfer for IoApic {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        Ok(self.state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.state = *IoApicState::from_bytes(state)
+            .with_context(|| MigrationError::FromBytesError("IOAPIC"))?;
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&IoApicState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for IoApic {
+    fn resume(&mut self) -> migration::Result<()> {
+        self.update_routes();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_reg(ioapic: &mut IoApic, index: u8) -> u32 {
+        let mut data = [0_u8; 4];
+        assert!(ioapic.write(
+            &(index as u32).to_le_bytes(),
+            GuestAddress(0),
+            IOAPIC_IOREGSEL
+        ));
+        assert!(ioapic.read(&mut data, GuestAddress(0), IOAPIC_IOWIN));
+        u32::from_le_bytes(data)
+    }
+
+    fn write_reg(ioapic: &mut IoApic, index: u8, value: u32) {
+        assert!(ioapic.write(
+            &(index as u32).to_le_bytes(),
+            GuestAddress(0),
+            IOAPIC_IOREGSEL
+        ));
+        assert!(ioapic.write(&value.to_le_bytes(), GuestAddress(0), IOAPIC_IOWIN));
+    }
+
+    fn write_entry(ioapic: &mut IoApic, pin: u8, entry: u64) {
+        write_reg(
+            ioapic,
+            IOAPIC_REG_REDTBL_BASE + pin * 2 + 1,
+            (entry >> 32) as u32,
+        );
+        write_reg(ioapic, IOAPIC_REG_REDTBL_BASE + pin * 2, entry as u32);
+    }
+
+    fn create_ioapic(
+        msis: &Arc<Mutex<Vec<MsiMessage>>>,
+        routes: &Arc<Mutex<Vec<Option<MsiMessage>>>>,
+    ) -> IoApic {
+        let msis = msis.clone();
+        let routes = routes.clone();
+        IoApic::new(
+            Box::new(move |msi| {
+                msis.lock().unwrap().push(msi);
+                Ok(())
+            }),
+            Box::new(move |new_routes| {
+                *routes.lock().unwrap() = new_routes.to_vec();
+                Ok(())
+            }),
+        )
+    }
+
+    #[test]
+    fn test_ioapic_registers() {
+        let msis = Arc::new(Mutex::new(Vec::new()));
+        let routes = Arc::new(Mutex::new(Vec::new()));
+        let mut ioapic = create_ioapic(&msis, &routes);
+
+        assert_eq!(read_reg(&mut ioapic, IOAPIC_REG_VER), 0x0017_0020);
+        write_reg(&mut ioapic, IOAPIC_REG_ID, 0x0300_0000);
+        assert_eq!(read_reg(&mut ioapic, IOAPIC_REG_ID), 0x0300_0000);
+        assert_eq!(read_reg(&mut ioapic, IOAPIC_REG_ARB), 0x0300_0000);
+
+        // All the pins are masked after reset.
+        let index = IOAPIC_REG_REDTBL_BASE + 4 * 2;
+        assert_eq!(read_reg(&mut ioapic, index), IOAPIC_LVT_MASKED as u32);
+
+        // Route pin 4 to vector 0x24 of APIC 1, the read-only bits are kept.
+        write_entry(
+            &mut ioapic,
+            4,
+            (1 << IOAPIC_LVT_DEST_SHIFT) | 0x24 | IOAPIC_LVT_REMOTE_IRR,
+        );
+        assert_eq!(read_reg(&mut ioapic, index), 0x24);
+        assert_eq!(read_reg(&mut ioapic, index + 1), 0x0100_0000);
+        let routes = routes.lock().unwrap().clone();
+        assert_eq!(routes.len(), IOAPIC_NUM_PINS);
+        assert!(routes[3].is_none());
+        assert_eq!(
+            routes[4],
+            Some(MsiMessage {
+                address: 0xFEE0_1000,
+                data: 0x24,
+                dev_id: 0,
+            })
+        );
+
+        ioapic.reset().unwrap();
+        assert_eq!(read_reg(&mut ioapic, IOAPIC_REG_ID), 0);
+        assert_eq!(read_reg(&mut ioapic, index), IOAPIC_LVT_MASKED as u32);
+    }
+
+    #[test]
+    fn test_ioapic_edge_irq() {
+        let msis = Arc::new(Mutex::new(Vec::new()));
+        let routes = Arc::new(Mutex::new(Vec::new()));
+        let mut ioapic = create_ioapic(&msis, &routes);
+
+        // The edge on the masked pin is pending until it's unmasked.
+        ioapic.set_irq(4, true);
+        ioapic.set_irq(4, false);
+        assert!(msis.lock().unwrap().is_empty());
+        write_entry(&mut ioapic, 4, 0x24);
+        assert_eq!(msis.lock().unwrap().len(), 1);
+        assert_eq!(msis.lock().unwrap()[0].data, 0x24);
+
+        // Only the rising edge triggers the interrupt.
+        ioapic.set_irq(4, true);
+        ioapic.set_irq(4, true);
+        assert_eq!(msis.lock().unwrap().len(), 2);
+
+        // ISA IRQ 0 is connected to pin 2.
+        write_entry(&mut ioapic, 2, 0x30);
+        ioapic.set_irq(0, true);
+        assert_eq!(msis.lock().unwrap()[2].data, 0x30);
+    }
+
+    #[test]
+    fn test_ioapic_level_irq() {
+        let msis = Arc::new(Mutex::new(Vec::new()));
+        let routes = Arc::new(Mutex::new(Vec::new()));
+        let mut ioapic = create_ioapic(&msis, &routes);
+
+        write_entry(&mut ioapic, 9, IOAPIC_LVT_TRIGGER_MODE | 0x29);
+        assert_eq!(routes.lock().unwrap()[9].unwrap().data, 0x8029);
+        ioapic.set_irq(9, true);
+        assert_eq!(msis.lock().unwrap().len(), 1);
+        let index = IOAPIC_REG_REDTBL_BASE + 9 * 2;
+        assert_ne!(
+            read_reg(&mut ioapic, index) as u64 & IOAPIC_LVT_REMOTE_IRR,
+            0
+        );
+
+        // The interrupt is not delivered again until it's acknowledged.
+        ioapic.set_irq(9, true);
+        assert_eq!(msis.lock().unwrap().len(), 1);
+        // It's delivered again after EOI if the line is still asserted.
+        ioapic.eoi(0x29);
+        assert_eq!(msis.lock().unwrap().len(), 2);
+
+        ioapic.set_irq(9, false);
+        assert!(ioapic.write(&0x29_u32.to_le_bytes(), GuestAddress(0), IOAPIC_EOI));
+        assert_eq!(msis.lock().unwrap().len(), 2);
+        assert_eq!(
+            read_reg(&mut ioapic, index) as u64 & IOAPIC_LVT_REMOTE_IRR,
+            0
+        );
+    }
+}
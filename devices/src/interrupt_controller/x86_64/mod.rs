@@ -0,0 +1,38 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod ioapic;
+mod pic;
+
+pub use ioapic::{IoApic, IoApicRouteHandler, MsiHandler, IOAPIC_NUM_PINS, IOAPIC_REGION_SIZE};
+pub use pic::{Pic, PicOutputHandler};
+
+use std::sync::{Arc, Mutex};
+
+use crate::pci::InterruptHandler;
+
+/// Number of the ISA irqs connected to PIC.
+const PIC_NUM_IRQS: u32 = 16;
+
+/// Create the handler which sets the level of GSI when the interrupt controllers are
+/// emulated in userspace. The ISA irqs are connected to both PIC and IOAPIC.
+pub fn gsi_irq_handler(pic: &Arc<Mutex<Pic>>, ioapic: &Arc<Mutex<IoApic>>) -> InterruptHandler {
+    let pic = pic.clone();
+    let ioapic = ioapic.clone();
+    Box::new(move |gsi: u32, level: bool| {
+        if gsi < PIC_NUM_IRQS {
+            pic.lock().unwrap().set_irq(gsi, level);
+        }
+        ioapic.lock().unwrap().set_irq(gsi, level);
+        Ok(())
+    })
+}
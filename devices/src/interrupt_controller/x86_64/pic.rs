@@ -0,0 +1,565 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+
+use address_space::{AddressSpace, GuestAddress, Region, RegionOps};
+use migration::{
+    snapshot::PIC_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
+    MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
+use util::byte_code::ByteCode;
+
+/// IO ports of the master and slave PIC.
+const PIC_MASTER_PORT: u64 = 0x20;
+const PIC_SLAVE_PORT: u64 = 0xA0;
+const PIC_PORT_SIZE: u64 = 2;
+/// IO ports of the edge/level control registers.
+const PIC_ELCR_PORT: u64 = 0x4D0;
+const PIC_ELCR_SIZE: u64 = 2;
+/// IRQ 0, 1, 2, 8 and 13 can't be level-triggered.
+const PIC_ELCR_MASK: [u8; 2] = [0xF8, 0xDE];
+
+const PIC_MASTER: usize = 0;
+const PIC_SLAVE: usize = 1;
+/// The slave PIC is connected to IRQ 2 of the master PIC.
+const PIC_CASCADE_IRQ: u8 = 2;
+/// The interrupt reported when the request is withdrawn before acknowledge.
+const PIC_SPURIOUS_IRQ: u8 = 7;
+/// No interrupt is requested or in service.
+const PIC_NO_PRIORITY: u8 = 8;
+
+// Bits of the command register.
+const PIC_ICW1_INIT: u8 = 0x10;
+const PIC_ICW1_IC4: u8 = 0x01;
+const PIC_ICW1_SNGL: u8 = 0x02;
+const PIC_OCW3_SEL: u8 = 0x08;
+const PIC_OCW3_POLL: u8 = 0x04;
+const PIC_OCW3_RR: u8 = 0x02;
+const PIC_OCW3_ESMM: u8 = 0x40;
+
+/// Called when the interrupt output of the master PIC changes, which is connected
+/// to the LINT0 of the boot processor.
+pub type PicOutputHandler = Box<dyn Fn(bool) + Send + Sync>;
+
+/// State of one 8259A chip.
+#[repr(C)]
+#[derive(Clone, Copy, ByteCode)]
+struct PicChip {
+    /// Level of the input lines, which is used to detect the rising edges.
+    last_irr: u8,
+    /// Interrupt request register.
+    irr: u8,
+    /// Interrupt mask register.
+    imr: u8,
+    /// In-service register.
+    isr: u8,
+    /// The irq with the highest priority.
+    priority_add: u8,
+    /// Vector of irq 0.
+    irq_base: u8,
+    /// Read ISR instead of IRR from the command register.
+    read_reg_select: u8,
+    /// The next read is a poll command.
+    poll: u8,
+    special_mask: u8,
+    /// Index of the next initialization command word.
+    init_state: u8,
+    auto_eoi: u8,
+    rotate_on_auto_eoi: u8,
+    special_fully_nested_mode: u8,
+    /// ICW4 is needed.
+    init4: u8,
+    /// There is no slave PIC, and ICW3 is skipped.
+    single_mode: u8,
+    /// Edge/level control register.
+    elcr: u8,
+}
+
+impl PicChip {
+    fn init_reset(&mut self) {
+        *self = PicChip {
+            elcr: self.elcr,
+            ..Default::default()
+        };
+    }
+
+    /// Get the priority of the highest irq in `mask`, 0 is the highest.
+    fn get_priority(&self, mask: u8) -> u8 {
+        if mask == 0 {
+            return PIC_NO_PRIORITY;
+        }
+        let mut priority = 0;
+        while mask & (1 << ((priority + self.priority_add) & 7)) == 0 {
+            priority += 1;
+        }
+        priority
+    }
+
+    /// Get the irq to be delivered.
+    fn get_irq(&self, master: bool) -> Option<u8> {
+        let priority = self.get_priority(self.irr & !self.imr);
+        if priority == PIC_NO_PRIORITY {
+            return None;
+        }
+        let mut mask = self.isr;
+        if self.special_mask != 0 {
+            mask &= !self.imr;
+        }
+        // The irq from the slave PIC doesn't block the others in special fully nested mode.
+        if self.special_fully_nested_mode != 0 && master {
+            mask &= !(1 << PIC_CASCADE_IRQ);
+        }
+        if priority < self.get_priority(mask) {
+            Some((priority + self.priority_add) & 7)
+        } else {
+            None
+        }
+    }
+
+    fn set_irq(&mut self, irq: u8, level: bool) {
+        let mask = 1 << irq;
+        if self.elcr & mask != 0 {
+            if level {
+                self.irr |= mask;
+                self.last_irr |= mask;
+            } else {
+                self.irr &= !mask;
+                self.last_irr &= !mask;
+            }
+        } else if level {
+            if self.last_irr & mask == 0 {
+                self.irr |= mask;
+            }
+            self.last_irr |= mask;
+        } else {
+            self.last_irr &= !mask;
+        }
+    }
+
+    fn intack(&mut self, irq: u8) {
+        if self.auto_eoi != 0 {
+            if self.rotate_on_auto_eoi != 0 {
+                self.priority_add = (irq + 1) & 7;
+            }
+        } else {
+            self.isr |= 1 << irq;
+        }
+        // The level-triggered irq is kept until the device withdraws it.
+        if self.elcr & (1 << irq) == 0 {
+            self.irr &= !(1 << irq);
+        }
+    }
+
+    fn write_command(&mut self, value: u8) {
+        if value & PIC_ICW1_INIT != 0 {
+            self.init_reset();
+            self.init_state = 1;
+            self.init4 = value & PIC_ICW1_IC4;
+            self.single_mode = value & PIC_ICW1_SNGL;
+        } else if value & PIC_OCW3_SEL != 0 {
+            if value & PIC_OCW3_POLL != 0 {
+                self.poll = 1;
+            }
+            if value & PIC_OCW3_RR != 0 {
+                self.read_reg_select = value & 1;
+            }
+            if value & PIC_OCW3_ESMM != 0 {
+                self.special_mask = (value >> 5) & 1;
+            }
+        } else {
+            let cmd = value >> 5;
+            match cmd {
+                // Rotate in automatic EOI mode.
+                0 | 4 => self.rotate_on_auto_eoi = cmd >> 2,
+                // Non-specific EOI, with rotation for 5.
+                1 | 5 => {
+                    let priority = self.get_priority(self.isr);
+                    if priority != PIC_NO_PRIORITY {
+                        let irq = (priority + self.priority_add) & 7;
+                        self.isr &= !(1 << irq);
+                        if cmd == 5 {
+                            self.priority_add = (irq + 1) & 7;
+                        }
+                    }
+                }
+                // Specific EOI.
+                3 => self.isr &= !(1 << (value & 7)),
+                // Set priority.
+                6 => self.priority_add = (value + 1) & 7,
+                // Specific EOI with rotation.
+                7 => {
+                    let irq = value & 7;
+                    self.isr &= !(1 << irq);
+                    self.priority_add = (irq + 1) & 7;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        match self.init_state {
+            // OCW1.
+            0 => self.imr = value,
+            // ICW2.
+            1 => {
+                self.irq_base = value & 0xF8;
+                self.init_state = match (self.single_mode != 0, self.init4 != 0) {
+                    (false, _) => 2,
+                    (true, true) => 3,
+                    (true, false) => 0,
+                };
+            }
+            // ICW3, the cascade is fixed.
+            2 => self.init_state = if self.init4 != 0 { 3 } else { 0 },
+            // ICW4.
+            3 => {
+                self.special_fully_nested_mode = (value >> 4) & 1;
+                self.auto_eoi = (value >> 1) & 1;
+                self.init_state = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct PicState {
+    /// The master and slave chip.
+    chips: [PicChip; 2],
+}
+
+/// A pair of cascaded 8259A programmable interrupt controllers, which is emulated in
+/// userspace along with IOAPIC when kvm only emulates the local APICs.
+pub struct Pic {
+    state: PicState,
+    /// Level of the interrupt output of the master PIC.
+    output: bool,
+    output_handler: Option<PicOutputHandler>,
+}
+
+impl Default for Pic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pic {
+    pub fn new() -> Self {
+        Pic {
+            state: PicState::default(),
+            output: false,
+            output_handler: None,
+        }
+    }
+
+    pub fn realize(self, sys_io: &Arc<AddressSpace>) -> Result<Arc<Mutex<Pic>>> {
+        let dev = Arc::new(Mutex::new(self));
+        for (index, port) in [(PIC_MASTER, PIC_MASTER_PORT), (PIC_SLAVE, PIC_SLAVE_PORT)] {
+            let read_dev = dev.clone();
+            let read_ops = move |data: &mut [u8], _addr: GuestAddress, offset: u64| -> bool {
+                data.fill(0);
+                data[0] = read_dev.lock().unwrap().read_chip(index, offset);
+                true
+            };
+            let write_dev = dev.clone();
+            let write_ops = move |data: &[u8], _addr: GuestAddress, offset: u64| -> bool {
+                write_dev.lock().unwrap().write_chip(index, offset, data[0]);
+                true
+            };
+            let ops = RegionOps {
+                read: Arc::new(read_ops),
+                write: Arc::new(write_ops),
+            };
+            let region = Region::init_io_region(PIC_PORT_SIZE, ops, "PIC");
+            sys_io
+                .root()
+                .add_subregion(region, port)
+                .with_context(|| format!("Failed to register PIC at port 0x{:x}", port))?;
+        }
+
+        let read_dev = dev.clone();
+        let read_ops = move |data: &mut [u8], _addr: GuestAddress, offset: u64| -> bool {
+            let chips = &read_dev.lock().unwrap().state.chips;
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = chips.get(offset as usize + i).map_or(0, |chip| chip.elcr);
+            }
+            true
+        };
+        let write_dev = dev.clone();
+        let write_ops = move |data: &[u8], _addr: GuestAddress, offset: u64| -> bool {
+            let mut pic = write_dev.lock().unwrap();
+            for (i, byte) in data.iter().enumerate() {
+                let index = offset as usize + i;
+                if let Some(chip) = pic.state.chips.get_mut(index) {
+                    chip.elcr = byte & PIC_ELCR_MASK[index];
+                }
+            }
+            true
+        };
+        let ops = RegionOps {
+            read: Arc::new(read_ops),
+            write: Arc::new(write_ops),
+        };
+        let region = Region::init_io_region(PIC_ELCR_SIZE, ops, "PicElcr");
+        sys_io
+            .root()
+            .add_subregion(region, PIC_ELCR_PORT)
+            .with_context(|| "Failed to register ELCR of PIC")?;
+
+        MigrationManager::register_device_instance(
+            PicState::descriptor(),
+            dev.clone(),
+            PIC_SNAPSHOT_ID,
+        );
+        Ok(dev)
+    }
+
+    /// Set the handler of the interrupt output, it's called with the current level.
+    pub fn set_output_handler(&mut self, handler: PicOutputHandler) {
+        handler(self.output);
+        self.output_handler = Some(handler);
+    }
+
+    /// Set the level of ISA irq 0~15.
+    pub fn set_irq(&mut self, irq: u32, level: bool) {
+        match irq {
+            0..=7 => self.state.chips[PIC_MASTER].set_irq(irq as u8, level),
+            8..=15 => self.state.chips[PIC_SLAVE].set_irq(irq as u8 - 8, level),
+            _ => return,
+        }
+        self.update();
+    }
+
+    /// Acknowledge the interrupt requested by the output, and return its vector. Returns
+    /// `None` if the request has been withdrawn.
+    pub fn interrupt_ack(&mut self) -> Option<u8> {
+        let irq = self.state.chips[PIC_MASTER].get_irq(true)?;
+        let vector = if irq == PIC_CASCADE_IRQ {
+            let slave = &mut self.state.chips[PIC_SLAVE];
+            let slave_irq = match slave.get_irq(false) {
+                Some(slave_irq) => {
+                    slave.intack(slave_irq);
+                    slave_irq
+                }
+                None => PIC_SPURIOUS_IRQ,
+            };
+            let vector = slave.irq_base + slave_irq;
+            self.update_cascade();
+            vector
+        } else {
+            self.state.chips[PIC_MASTER].irq_base + irq
+        };
+        self.state.chips[PIC_MASTER].intack(irq);
+        self.update();
+        Some(vector)
+    }
+
+    pub fn reset(&mut self) {
+        for chip in self.state.chips.iter_mut() {
+            chip.elcr = 0;
+            chip.init_reset();
+        }
+        self.update();
+    }
+
+    fn read_chip(&mut self, index: usize, offset: u64) -> u8 {
+        let chip = &mut self.state.chips[index];
+        if chip.poll != 0 {
+            chip.poll = 0;
+            match chip.get_irq(index == PIC_MASTER) {
+                Some(irq) => {
+                    chip.intack(irq);
+                    self.update();
+                    irq | 0x80
+                }
+                None => 0,
+            }
+        } else if offset == 0 {
+            if chip.read_reg_select != 0 {
+                chip.isr
+            } else {
+                chip.irr
+            }
+        } else {
+            chip.imr
+        }
+    }
+
+    fn write_chip(&mut self, index: usize, offset: u64, value: u8) {
+        let chip = &mut self.state.chips[index];
+        if offset == 0 {
+            chip.write_command(value);
+        } else {
+            chip.write_data(value);
+        }
+        self.update();
+    }
+
+    /// Pass the output of the slave PIC to the cascade irq of the master PIC.
+    fn update_cascade(&mut self) {
+        let level = self.state.chips[PIC_SLAVE].get_irq(false).is_some();
+        self.state.chips[PIC_MASTER].set_irq(PIC_CASCADE_IRQ, level);
+    }
+
+    fn update(&mut self) {
+        self.update_cascade();
+        let output = self.state.chips[PIC_MASTER].get_irq(true).is_some();
+        if output == self.output {
+            return;
+        }
+        self.output = output;
+        if let Some(handler) = &self.output_handler {
+            handler(output);
+        }
+    }
+}
+
+impl StateTransfer for Pic {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        Ok(self.state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.state =
+            *PicState::from_bytes(state).with_context(|| MigrationError::FromBytesError("PIC"))?;
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&PicState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Pic {
+    fn resume(&mut self) -> migration::Result<()> {
+        // Notify the level of the output restored.
+        self.output = false;
+        self.update();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_pic(pic: &mut Pic) {
+        // ICW1~ICW4 of the master PIC, vector base 0x08.
+        pic.write_chip(PIC_MASTER, 0, 0x11);
+        pic.write_chip(PIC_MASTER, 1, 0x08);
+        pic.write_chip(PIC_MASTER, 1, 0x04);
+        pic.write_chip(PIC_MASTER, 1, 0x01);
+        // ICW1~ICW4 of the slave PIC, vector base 0x70.
+        pic.write_chip(PIC_SLAVE, 0, 0x11);
+        pic.write_chip(PIC_SLAVE, 1, 0x70);
+        pic.write_chip(PIC_SLAVE, 1, 0x02);
+        pic.write_chip(PIC_SLAVE, 1, 0x01);
+        // Unmask all irqs.
+        pic.write_chip(PIC_MASTER, 1, 0x00);
+        pic.write_chip(PIC_SLAVE, 1, 0x00);
+    }
+
+    fn create_pic() -> (Pic, Arc<Mutex<bool>>) {
+        let output = Arc::new(Mutex::new(false));
+        let cloned_output = output.clone();
+        let mut pic = Pic::new();
+        pic.set_output_handler(Box::new(move |level| {
+            *cloned_output.lock().unwrap() = level;
+        }));
+        init_pic(&mut pic);
+        (pic, output)
+    }
+
+    #[test]
+    fn test_pic_master_irq() {
+        let (mut pic, output) = create_pic();
+        assert!(!*output.lock().unwrap());
+        assert_eq!(pic.interrupt_ack(), None);
+
+        pic.set_irq(1, true);
+        assert!(*output.lock().unwrap());
+        // The higher priority irq is delivered first.
+        pic.set_irq(4, true);
+        assert_eq!(pic.interrupt_ack(), Some(0x09));
+        assert!(!*output.lock().unwrap());
+        assert_eq!(pic.state.chips[PIC_MASTER].isr, 0x02);
+        // Read ISR by OCW3.
+        pic.write_chip(PIC_MASTER, 0, 0x0B);
+        assert_eq!(pic.read_chip(PIC_MASTER, 0), 0x02);
+
+        // Non-specific EOI.
+        pic.write_chip(PIC_MASTER, 0, 0x20);
+        assert!(*output.lock().unwrap());
+        assert_eq!(pic.interrupt_ack(), Some(0x0C));
+        pic.write_chip(PIC_MASTER, 0, 0x20);
+        assert!(!*output.lock().unwrap());
+
+        // The masked irq is not delivered.
+        pic.write_chip(PIC_MASTER, 1, 0x08);
+        assert_eq!(pic.read_chip(PIC_MASTER, 1), 0x08);
+        pic.set_irq(1, false);
+        pic.set_irq(3, true);
+        assert!(!*output.lock().unwrap());
+        pic.write_chip(PIC_MASTER, 1, 0x00);
+        assert!(*output.lock().unwrap());
+        assert_eq!(pic.interrupt_ack(), Some(0x0B));
+    }
+
+    #[test]
+    fn test_pic_slave_irq() {
+        let (mut pic, output) = create_pic();
+
+        pic.set_irq(12, true);
+        assert!(*output.lock().unwrap());
+        assert_eq!(pic.interrupt_ack(), Some(0x74));
+        assert_eq!(pic.state.chips[PIC_SLAVE].isr, 0x10);
+        assert_eq!(pic.state.chips[PIC_MASTER].isr, 0x04);
+        pic.write_chip(PIC_SLAVE, 0, 0x20);
+        pic.write_chip(PIC_MASTER, 0, 0x20);
+        assert!(!*output.lock().unwrap());
+
+        // Poll command.
+        pic.set_irq(12, false);
+        pic.set_irq(9, true);
+        pic.write_chip(PIC_SLAVE, 0, 0x0C);
+        assert_eq!(pic.read_chip(PIC_SLAVE, 0), 0x81);
+        assert_eq!(pic.state.chips[PIC_SLAVE].isr, 0x02);
+    }
+
+    #[test]
+    fn test_pic_level_irq() {
+        let (mut pic, output) = create_pic();
+        pic.state.chips[PIC_MASTER].elcr = 0x08;
+
+        // The level-triggered irq is delivered again after EOI if it's still asserted.
+        pic.set_irq(3, true);
+        assert_eq!(pic.interrupt_ack(), Some(0x0B));
+        pic.write_chip(PIC_MASTER, 0, 0x20);
+        assert!(*output.lock().unwrap());
+        assert_eq!(pic.interrupt_ack(), Some(0x0B));
+        pic.set_irq(3, false);
+        pic.write_chip(PIC_MASTER, 0, 0x20);
+        assert!(!*output.lock().unwrap());
+
+        pic.reset();
+        assert_eq!(pic.state.chips[PIC_MASTER].elcr, 0);
+        assert_eq!(pic.state.chips[PIC_MASTER].imr, 0);
+        assert_eq!(pic.state.chips[PIC_MASTER].irq_base, 0);
+    }
+}
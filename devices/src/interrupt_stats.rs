@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Interrupt accounting, which counts the interrupts injected by StratoVirt for each
+//! device and vector. The interrupts delivered by kvm irqfd directly, such as the ones
+//! of VFIO and vhost devices, are not counted.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use once_cell::sync::Lazy;
+
+static INTERRUPT_COUNTERS: Lazy<Mutex<Vec<Weak<InterruptCounter>>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptType {
+    /// Interrupt of the system bus device, the vector is the GSI.
+    Gsi,
+    /// PCI INTx interrupt, the vector is the interrupt pin.
+    Intx,
+    /// PCI MSI-X interrupt, the vector is the MSI-X table entry.
+    Msix,
+}
+
+impl InterruptType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            InterruptType::Gsi => "gsi",
+            InterruptType::Intx => "intx",
+            InterruptType::Msix => "msix",
+        }
+    }
+}
+
+/// Counter of the interrupts injected through one vector of the device.
+pub struct InterruptCounter {
+    device: String,
+    int_type: InterruptType,
+    vector: u32,
+    count: AtomicU64,
+}
+
+impl InterruptCounter {
+    pub fn inc(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Register an interrupt counter, the counter is unregistered when the caller drops it.
+///
+/// # Arguments
+///
+/// * `device` - Id of the device.
+/// * `int_type` - Type of the interrupt.
+/// * `vector` - GSI, INTx pin or MSI-X vector.
+pub fn register_interrupt_counter(
+    device: &str,
+    int_type: InterruptType,
+    vector: u32,
+) -> Arc<InterruptCounter> {
+    let counter = Arc::new(InterruptCounter {
+        device: device.to_string(),
+        int_type,
+        vector,
+        count: AtomicU64::new(0),
+    });
+    let mut counters = INTERRUPT_COUNTERS.lock().unwrap();
+    counters.retain(|counter| counter.strong_count() > 0);
+    counters.push(Arc::downgrade(&counter));
+    counter
+}
+
+/// Statistics of the interrupts injected through one vector of the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterruptStat {
+    pub device: String,
+    pub int_type: String,
+    pub vector: u32,
+    pub count: u64,
+}
+
+/// Query the statistics of all the registered interrupt counters, sorted by device and vector.
+pub fn query_interrupt_stats() -> Vec<InterruptStat> {
+    let mut stats: Vec<InterruptStat> = INTERRUPT_COUNTERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|counter| counter.upgrade())
+        .map(|counter| InterruptStat {
+            device: counter.device.clone(),
+            int_type: counter.int_type.as_str().to_string(),
+            vector: counter.vector,
+            count: counter.get(),
+        })
+        .collect();
+    stats.sort_by(|a, b| (&a.device, a.vector).cmp(&(&b.device, b.vector)));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_stats() {
+        let msix1 = register_interrupt_counter("test-virtio-blk", InterruptType::Msix, 1);
+        let msix0 = register_interrupt_counter("test-virtio-blk", InterruptType::Msix, 0);
+        let gsi = register_interrupt_counter("test-serial", InterruptType::Gsi, 4);
+        msix1.inc();
+        msix1.inc();
+        gsi.inc();
+
+        let stats: Vec<InterruptStat> = query_interrupt_stats()
+            .into_iter()
+            .filter(|stat| stat.device.starts_with("test-"))
+            .collect();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].device, "test-serial");
+        assert_eq!(stats[0].int_type, "gsi");
+        assert_eq!(stats[0].count, 1);
+        assert_eq!((stats[1].vector, stats[1].count), (0, 0));
+        assert_eq!((stats[2].vector, stats[2].count), (1, 2));
+
+        drop(msix0);
+        let stats = query_interrupt_stats();
+        assert!(!stats
+            .iter()
+            .any(|stat| stat.device == "test-virtio-blk" && stat.vector == 0));
+    }
+}
@@ -0,0 +1,889 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{error, warn};
+
+use crate::pci::InterruptHandler;
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AmlBuilder, AmlDevice, AmlEisaId, AmlInteger, AmlMemory32Fixed, AmlNameDecl, AmlReadAndWrite,
+    AmlResTemplate, AmlScopeBuilder,
+};
+use address_space::GuestAddress;
+use machine_manager::event_loop::EventLoop;
+use migration::{
+    snapshot::HPET_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
+    MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
+use util::byte_code::ByteCode;
+use util::num_ops::{read_data_u32, write_data_u32};
+
+/// Size of the HPET register block.
+pub const HPET_REGION_SIZE: u64 = 0x400;
+/// Number of the timers.
+const HPET_NUM_TIMERS: usize = 3;
+/// Period of the main counter in femtoseconds, the counter runs at 100MHz.
+const HPET_CLK_PERIOD_FS: u64 = 10_000_000;
+/// Period of the main counter in nanoseconds.
+const HPET_CLK_PERIOD_NS: u64 = 10;
+const HPET_VENDOR_ID: u64 = 0x8086;
+const HPET_REV_ID: u64 = 0x01;
+
+/// General capabilities and ID register.
+const HPET_GCAP_ID: u64 = 0x000;
+/// General configuration register.
+const HPET_GEN_CONF: u64 = 0x010;
+/// General interrupt status register.
+const HPET_GINTR_STA: u64 = 0x020;
+/// Main counter value register.
+const HPET_MAIN_COUNTER: u64 = 0x0F0;
+/// Registers of timer N start at 0x100 + 0x20 * N.
+const HPET_TIMER_BASE: u64 = 0x100;
+const HPET_TIMER_SIZE: u64 = 0x20;
+/// Timer N configuration and capability register.
+const HPET_TN_CONF: u64 = 0x00;
+/// Timer N comparator value register.
+const HPET_TN_CMP: u64 = 0x08;
+/// Timer N FSB interrupt route register.
+const HPET_TN_FSB: u64 = 0x10;
+
+// The timers can be routed as legacy replacement.
+const HPET_CAP_LEG_RT: u64 = 1 << 15;
+// The main counter is 64-bit.
+const HPET_CAP_COUNT_SIZE: u64 = 1 << 13;
+const HPET_CAP_NUM_TIM_SHIFT: u64 = 8;
+const HPET_CAP_VENDOR_ID_SHIFT: u64 = 16;
+const HPET_CAP_CLK_PERIOD_SHIFT: u64 = 32;
+
+// The main counter runs and the timers generate interrupts.
+const HPET_CFG_ENABLE: u64 = 1 << 0;
+// Timer 0 and 1 are routed to the IRQ of PIT and RTC.
+const HPET_CFG_LEGACY: u64 = 1 << 1;
+const HPET_CFG_WRITE_MASK: u64 = HPET_CFG_ENABLE | HPET_CFG_LEGACY;
+
+// Level-triggered interrupt, otherwise edge-triggered.
+const HPET_TN_TYPE_LEVEL: u64 = 1 << 1;
+// Interrupt enable.
+const HPET_TN_ENABLE: u64 = 1 << 2;
+// Periodic mode, otherwise one-shot mode.
+const HPET_TN_PERIODIC: u64 = 1 << 3;
+const HPET_TN_PERIODIC_CAP: u64 = 1 << 4;
+// The timer is 64-bit.
+const HPET_TN_SIZE_CAP: u64 = 1 << 5;
+// The next write to comparator sets the accumulator of periodic timer.
+const HPET_TN_SETVAL: u64 = 1 << 6;
+// Force the 64-bit timer to work in 32-bit mode.
+const HPET_TN_32BIT: u64 = 1 << 8;
+const HPET_TN_INT_ROUTE_SHIFT: u64 = 9;
+const HPET_TN_INT_ROUTE_MASK: u64 = 0x1F << HPET_TN_INT_ROUTE_SHIFT;
+const HPET_TN_INT_ROUTE_CAP_SHIFT: u64 = 32;
+// FSB interrupt delivery is not supported.
+const HPET_TN_CFG_WRITE_MASK: u64 = HPET_TN_TYPE_LEVEL
+    | HPET_TN_ENABLE
+    | HPET_TN_PERIODIC
+    | HPET_TN_SETVAL
+    | HPET_TN_32BIT
+    | HPET_TN_INT_ROUTE_MASK;
+
+/// IRQ of timer 0 and 1 in legacy replacement mode, which are the IRQ of PIT and RTC.
+const HPET_LEGACY_IRQ: [u32; 2] = [0, 8];
+
+/// Stop or restart the interrupt of PIT, it's stopped while HPET works in legacy
+/// replacement mode.
+pub type PitLegacyHandler = Box<dyn Fn(bool) -> Result<()> + Send + Sync>;
+
+fn ticks_to_ns(ticks: u64) -> u64 {
+    ticks.wrapping_mul(HPET_CLK_PERIOD_NS)
+}
+
+fn ns_to_ticks(ns: u64) -> u64 {
+    ns / HPET_CLK_PERIOD_NS
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct HpetState {
+    config: u64,
+    isr: u64,
+    counter: u64,
+    timer_config: [u64; 3],
+    timer_cmp: [u64; 3],
+    timer_period: [u64; 3],
+    timer_wrap: [u64; 3],
+}
+
+/// Comparator of HPET.
+#[derive(Default)]
+struct HpetTimer {
+    config: u64,
+    cmp: u64,
+    /// Period of periodic timer in ticks.
+    period: u64,
+    /// One-shot timer in 32-bit mode also expires when the counter wraps around.
+    wrap_flag: bool,
+    timer_id: Option<u64>,
+    /// Increased each time the timer starts, the callback of the stopped timer which
+    /// has already expired finds it stale.
+    generation: u64,
+}
+
+impl HpetTimer {
+    fn is_periodic(&self) -> bool {
+        self.config & HPET_TN_PERIODIC != 0
+    }
+
+    fn is_32bit(&self) -> bool {
+        self.config & HPET_TN_32BIT != 0
+    }
+
+    /// Ticks from `cur_tick` to the comparator, 0 if the comparator has passed.
+    fn ticks_to_cmp(&self, cur_tick: u64) -> u64 {
+        if self.is_32bit() {
+            let diff = (self.cmp as u32).wrapping_sub(cur_tick as u32);
+            if (diff as i32) > 0 {
+                diff as u64
+            } else {
+                0
+            }
+        } else {
+            let diff = self.cmp.wrapping_sub(cur_tick);
+            if (diff as i64) > 0 {
+                diff
+            } else {
+                0
+            }
+        }
+    }
+
+    /// Advance the comparator of periodic timer to the next period after `cur_tick`.
+    fn advance_cmp(&mut self, cur_tick: u64) {
+        if self.is_32bit() {
+            let cur_tick = cur_tick as u32;
+            let cmp = self.cmp as u32;
+            let period = self.period as u32;
+            let passed = cur_tick.wrapping_sub(cmp);
+            if (passed as i32) >= 0 && period != 0 {
+                let periods = passed / period + 1;
+                self.cmp = cmp.wrapping_add(periods.wrapping_mul(period)) as u64;
+            }
+        } else {
+            let passed = cur_tick.wrapping_sub(self.cmp);
+            if (passed as i64) >= 0 && self.period != 0 {
+                let periods = passed / self.period + 1;
+                self.cmp = self.cmp.wrapping_add(periods.wrapping_mul(self.period));
+            }
+        }
+    }
+}
+
+/// High Precision Event Timer, which works with IO APIC. The timer 0 and 1 can replace
+/// the interrupt of PIT and RTC in legacy replacement mode.
+pub struct Hpet {
+    base: SysBusDevBase,
+    /// General configuration register.
+    config: u64,
+    /// General interrupt status register, only level-triggered interrupts are recorded.
+    isr: u64,
+    /// Value of the main counter while it's stopped.
+    counter: u64,
+    /// Offset from the clock to the main counter in nanoseconds while it's running.
+    counter_offset: u64,
+    /// Base of the clock.
+    clock_base: Instant,
+    timers: [HpetTimer; HPET_NUM_TIMERS],
+    /// Bitmap of the GSIs which the timers can be routed to.
+    int_route_cap: u32,
+    /// Set the level of the GSI.
+    irq_handler: InterruptHandler,
+    pit_handler: PitLegacyHandler,
+    /// The device itself, which is used by timers.
+    dev: Weak<Mutex<Hpet>>,
+}
+
+impl Hpet {
+    /// Create HPET device.
+    ///
+    /// # Arguments
+    ///
+    /// * `int_route_cap` - Bitmap of the GSIs which the timers can be routed to.
+    /// * `irq_handler` - Set the level of the GSI.
+    /// * `pit_handler` - Stop or restart the interrupt of PIT.
+    pub fn new(
+        int_route_cap: u32,
+        irq_handler: InterruptHandler,
+        pit_handler: PitLegacyHandler,
+    ) -> Self {
+        let mut hpet = Hpet {
+            base: SysBusDevBase::new(SysBusDevType::Hpet),
+            config: 0,
+            isr: 0,
+            counter: 0,
+            counter_offset: 0,
+            clock_base: Instant::now(),
+            timers: Default::default(),
+            int_route_cap,
+            irq_handler,
+            pit_handler,
+            dev: Weak::new(),
+        };
+        hpet.reset_timers();
+        hpet
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<Hpet>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| "Failed to set system resource for HPET")?;
+
+        let dev = Arc::new(Mutex::new(self));
+        dev.lock().unwrap().dev = Arc::downgrade(&dev);
+        sysbus
+            .attach_device(&dev, region_base, region_size, "HPET")
+            .with_context(|| "Failed to attach HPET to system bus")?;
+        MigrationManager::register_device_instance(
+            HpetState::descriptor(),
+            dev.clone(),
+            HPET_SNAPSHOT_ID,
+        );
+        Ok(dev)
+    }
+
+    /// Value of the general capabilities and ID register.
+    pub fn capability(&self) -> u64 {
+        HPET_REV_ID
+            | ((HPET_NUM_TIMERS as u64 - 1) << HPET_CAP_NUM_TIM_SHIFT)
+            | HPET_CAP_COUNT_SIZE
+            | HPET_CAP_LEG_RT
+            | (HPET_VENDOR_ID << HPET_CAP_VENDOR_ID_SHIFT)
+            | (HPET_CLK_PERIOD_FS << HPET_CAP_CLK_PERIOD_SHIFT)
+    }
+
+    /// Guest physical address of the register block.
+    pub fn region_base(&self) -> u64 {
+        self.base.res.region_base
+    }
+
+    fn reset_timers(&mut self) {
+        for timer in self.timers.iter_mut() {
+            timer.config = HPET_TN_PERIODIC_CAP
+                | HPET_TN_SIZE_CAP
+                | ((self.int_route_cap as u64) << HPET_TN_INT_ROUTE_CAP_SHIFT);
+            timer.cmp = u64::MAX;
+            timer.period = 0;
+            timer.wrap_flag = false;
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config & HPET_CFG_ENABLE != 0
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.config & HPET_CFG_LEGACY != 0
+    }
+
+    fn clock_ns(&self) -> u64 {
+        self.clock_base.elapsed().as_nanos() as u64
+    }
+
+    /// Value of the running main counter.
+    fn get_ticks(&self) -> u64 {
+        ns_to_ticks(self.clock_ns().wrapping_add(self.counter_offset))
+    }
+
+    fn main_counter(&self) -> u64 {
+        if self.is_enabled() {
+            self.get_ticks()
+        } else {
+            self.counter
+        }
+    }
+
+    /// GSI which the timer is routed to.
+    fn timer_route(&self, index: usize) -> Option<u32> {
+        if index < HPET_LEGACY_IRQ.len() && self.is_legacy() {
+            return Some(HPET_LEGACY_IRQ[index]);
+        }
+        let route = ((self.timers[index].config & HPET_TN_INT_ROUTE_MASK)
+            >> HPET_TN_INT_ROUTE_SHIFT) as u32;
+        if self.int_route_cap & (1 << route) == 0 {
+            return None;
+        }
+        Some(route)
+    }
+
+    fn set_irq_line(&self, gsi: u32, level: bool) {
+        if let Err(e) = (self.irq_handler)(gsi, level) {
+            error!("HPET: failed to set irq {} level {}: {:?}", gsi, level, e);
+        }
+    }
+
+    fn update_irq(&mut self, index: usize, set: bool) {
+        let mask = 1_u64 << index;
+        let route = match self.timer_route(index) {
+            Some(route) => route,
+            None => {
+                if set {
+                    warn!(
+                        "HPET: timer {} is routed to unsupported irq, config 0x{:x}",
+                        index, self.timers[index].config
+                    );
+                }
+                self.isr &= !mask;
+                return;
+            }
+        };
+
+        let config = self.timers[index].config;
+        if !set || config & HPET_TN_ENABLE == 0 || !self.is_enabled() {
+            self.isr &= !mask;
+            self.set_irq_line(route, false);
+        } else if config & HPET_TN_TYPE_LEVEL != 0 {
+            self.isr |= mask;
+            self.set_irq_line(route, true);
+        } else {
+            self.isr &= !mask;
+            self.set_irq_line(route, true);
+            self.set_irq_line(route, false);
+        }
+    }
+
+    fn start_timer(&mut self, index: usize, ticks: u64) {
+        self.stop_timer(index);
+        let timer = &mut self.timers[index];
+        timer.generation += 1;
+        let generation = timer.generation;
+        let dev = self.dev.clone();
+        let func = Box::new(move || {
+            if let Some(dev) = dev.upgrade() {
+                dev.lock().unwrap().timer_expired(index, generation);
+            }
+        });
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            let delay = Duration::from_nanos(ticks.saturating_mul(HPET_CLK_PERIOD_NS));
+            timer.timer_id = Some(ctx.timer_add(func, delay));
+        }
+    }
+
+    fn stop_timer(&mut self, index: usize) {
+        if let Some(timer_id) = self.timers[index].timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
+
+    /// Arm the timer to expire at the comparator.
+    fn set_timer(&mut self, index: usize) {
+        let cur_tick = self.get_ticks();
+        let timer = &mut self.timers[index];
+        timer.wrap_flag = false;
+        let mut diff = timer.ticks_to_cmp(cur_tick);
+        if timer.is_32bit() && !timer.is_periodic() {
+            // One-shot timer in 32-bit mode also generates interrupt when the counter wraps.
+            let wrap_diff = (u32::MAX - cur_tick as u32) as u64;
+            if wrap_diff < diff {
+                diff = wrap_diff;
+                timer.wrap_flag = true;
+            }
+        }
+        self.start_timer(index, diff);
+    }
+
+    fn del_timer(&mut self, index: usize) {
+        self.stop_timer(index);
+        self.update_irq(index, false);
+    }
+
+    fn timer_expired(&mut self, index: usize, generation: u64) {
+        let timer = &mut self.timers[index];
+        if generation != timer.generation {
+            return;
+        }
+        timer.timer_id = None;
+
+        let cur_tick = self.get_ticks();
+        let timer = &mut self.timers[index];
+        if timer.is_periodic() && timer.period != 0 {
+            timer.advance_cmp(cur_tick);
+            let diff = timer.ticks_to_cmp(cur_tick);
+            self.start_timer(index, diff);
+        } else if timer.wrap_flag {
+            timer.wrap_flag = false;
+            let diff = timer.ticks_to_cmp(cur_tick);
+            self.start_timer(index, diff);
+        }
+        self.update_irq(index, true);
+    }
+
+    fn set_pit_legacy(&self, legacy: bool) {
+        if let Err(e) = (self.pit_handler)(legacy) {
+            error!("HPET: failed to set legacy replacement of PIT: {:?}", e);
+        }
+    }
+
+    fn write_config(&mut self, value: u64) {
+        let old_val = self.config;
+        let new_val = (old_val & !HPET_CFG_WRITE_MASK) | (value & HPET_CFG_WRITE_MASK);
+        let activated = !old_val & new_val;
+        let deactivated = old_val & !new_val;
+
+        if deactivated & HPET_CFG_ENABLE != 0 {
+            // Freeze the main counter.
+            self.counter = self.get_ticks();
+            for index in 0..HPET_NUM_TIMERS {
+                self.del_timer(index);
+            }
+        }
+        if (activated | deactivated) & HPET_CFG_LEGACY != 0 {
+            // Release the irq before the route changes.
+            for index in 0..HPET_LEGACY_IRQ.len() {
+                self.update_irq(index, false);
+            }
+        }
+
+        self.config = new_val;
+        if activated & HPET_CFG_ENABLE != 0 {
+            self.counter_offset = ticks_to_ns(self.counter).wrapping_sub(self.clock_ns());
+            for index in 0..HPET_NUM_TIMERS {
+                let timer = &self.timers[index];
+                if timer.cmp != u64::MAX || timer.period != 0 {
+                    self.set_timer(index);
+                }
+            }
+        }
+        if activated & HPET_CFG_LEGACY != 0 {
+            self.set_pit_legacy(true);
+        } else if deactivated & HPET_CFG_LEGACY != 0 {
+            self.set_pit_legacy(false);
+        }
+    }
+
+    fn write_timer_config(&mut self, index: usize, value: u64) {
+        let old_val = self.timers[index].config;
+        let new_val = (old_val & !HPET_TN_CFG_WRITE_MASK) | (value & HPET_TN_CFG_WRITE_MASK);
+        if old_val & !new_val & (HPET_TN_TYPE_LEVEL | HPET_TN_INT_ROUTE_MASK) != 0 {
+            // Release the irq before the trigger mode or the route changes.
+            self.update_irq(index, false);
+        }
+
+        let timer = &mut self.timers[index];
+        timer.config = new_val;
+        if new_val & HPET_TN_32BIT != 0 {
+            timer.cmp = timer.cmp as u32 as u64;
+            timer.period = timer.period as u32 as u64;
+        }
+        if !old_val & new_val & HPET_TN_ENABLE != 0 && self.isr & (1 << index) != 0 {
+            self.update_irq(index, true);
+        }
+        if self.is_enabled() {
+            self.set_timer(index);
+        }
+    }
+
+    fn write_timer_cmp(&mut self, index: usize, mut value: u64, mut mask: u64) {
+        let timer = &mut self.timers[index];
+        if timer.is_32bit() {
+            mask &= u32::MAX as u64;
+            value &= mask;
+        }
+        if !timer.is_periodic() || timer.config & HPET_TN_SETVAL != 0 {
+            timer.cmp = (timer.cmp & !mask) | value;
+        }
+        if timer.is_periodic() {
+            timer.period = (timer.period & !mask) | value;
+        }
+        timer.config &= !HPET_TN_SETVAL;
+        if self.is_enabled() {
+            self.set_timer(index);
+        }
+    }
+
+    fn read_reg(&self, offset: u64) -> u64 {
+        if offset >= HPET_TIMER_BASE {
+            let index = ((offset - HPET_TIMER_BASE) / HPET_TIMER_SIZE) as usize;
+            if index >= HPET_NUM_TIMERS {
+                return 0;
+            }
+            let timer = &self.timers[index];
+            return match (offset - HPET_TIMER_BASE) % HPET_TIMER_SIZE {
+                HPET_TN_CONF => timer.config,
+                HPET_TN_CMP => timer.cmp,
+                _ => 0,
+            };
+        }
+
+        match offset {
+            HPET_GCAP_ID => self.capability(),
+            HPET_GEN_CONF => self.config,
+            HPET_GINTR_STA => self.isr,
+            HPET_MAIN_COUNTER => self.main_counter(),
+            _ => 0,
+        }
+    }
+
+    /// Write the bits in `mask` of the 64-bit register.
+    fn write_reg(&mut self, offset: u64, value: u64, mask: u64) {
+        if offset >= HPET_TIMER_BASE {
+            let index = ((offset - HPET_TIMER_BASE) / HPET_TIMER_SIZE) as usize;
+            if index >= HPET_NUM_TIMERS {
+                return;
+            }
+            match (offset - HPET_TIMER_BASE) % HPET_TIMER_SIZE {
+                HPET_TN_CONF => {
+                    let config = (self.timers[index].config & !mask) | value;
+                    self.write_timer_config(index, config);
+                }
+                HPET_TN_CMP => self.write_timer_cmp(index, value, mask),
+                HPET_TN_FSB => {}
+                _ => {}
+            }
+            return;
+        }
+
+        match offset {
+            HPET_GEN_CONF => self.write_config((self.config & !mask) | value),
+            HPET_GINTR_STA => {
+                // Write 1 to clear the status of level-triggered interrupt.
+                for index in 0..HPET_NUM_TIMERS {
+                    if value & self.isr & (1 << index) != 0 {
+                        self.update_irq(index, false);
+                    }
+                }
+            }
+            HPET_MAIN_COUNTER => {
+                if self.is_enabled() {
+                    warn!("HPET: writing main counter while it's running is ignored");
+                } else {
+                    self.counter = (self.counter & !mask) | value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Restart the timers after the state is restored.
+    fn restore_timers(&mut self) {
+        if self.is_legacy() {
+            self.set_pit_legacy(true);
+        }
+        if !self.is_enabled() {
+            return;
+        }
+        for index in 0..HPET_NUM_TIMERS {
+            let timer = &self.timers[index];
+            if timer.cmp != u64::MAX || timer.period != 0 {
+                let wrap_flag = timer.wrap_flag;
+                self.set_timer(index);
+                self.timers[index].wrap_flag |= wrap_flag;
+            }
+            if self.isr & (1 << index) != 0 {
+                self.update_irq(index, true);
+            }
+        }
+    }
+}
+
+impl Device for Hpet {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for Hpet {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let size = data.len() as u64;
+        if (size != 4 && size != 8) || offset & (size - 1) != 0 {
+            error!(
+                "HPET: invalid read, offset 0x{:x}, size {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+
+        let value = self.read_reg(offset & !0x7);
+        if size == 8 {
+            data.copy_from_slice(&value.to_le_bytes());
+            true
+        } else {
+            write_data_u32(data, (value >> ((offset & 0x4) * 8)) as u32)
+        }
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let size = data.len() as u64;
+        if (size != 4 && size != 8) || offset & (size - 1) != 0 {
+            error!(
+                "HPET: invalid write, offset 0x{:x}, size {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+
+        let (value, mask) = if size == 8 {
+            let mut bytes = [0_u8; 8];
+            bytes.copy_from_slice(data);
+            (u64::from_le_bytes(bytes), u64::MAX)
+        } else {
+            let mut value = 0_u32;
+            if !read_data_u32(data, &mut value) {
+                return false;
+            }
+            let shift = (offset & 0x4) * 8;
+            ((value as u64) << shift, (u32::MAX as u64) << shift)
+        };
+        self.write_reg(offset & !0x7, value, mask);
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        for index in 0..HPET_NUM_TIMERS {
+            self.del_timer(index);
+        }
+        if self.is_legacy() {
+            self.set_pit_legacy(false);
+        }
+        self.config = 0;
+        self.isr = 0;
+        self.counter = 0;
+        self.counter_offset = 0;
+        self.reset_timers();
+        Ok(())
+    }
+}
+
+impl AmlBuilder for Hpet {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new("HPET");
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlEisaId::new("PNP0103")));
+        acpi_dev.append_child(AmlNameDecl::new("_UID", AmlInteger(0)));
+
+        let mut res = AmlResTemplate::new();
+        res.append_child(AmlMemory32Fixed::new(
+            AmlReadAndWrite::ReadOnly,
+            self.base.res.region_base as u32,
+            self.base.res.region_size as u32,
+        ));
+        acpi_dev.append_child(AmlNameDecl::new("_CRS", res));
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+impl StateTransfer for Hpet {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = HpetState {
+            config: self.config,
+            isr: self.isr,
+            counter: self.main_counter(),
+            timer_config: [0; HPET_NUM_TIMERS],
+            timer_cmp: [0; HPET_NUM_TIMERS],
+            timer_period: [0; HPET_NUM_TIMERS],
+            timer_wrap: [0; HPET_NUM_TIMERS],
+        };
+        for (index, timer) in self.timers.iter().enumerate() {
+            state.timer_config[index] = timer.config;
+            state.timer_cmp[index] = timer.cmp;
+            state.timer_period[index] = timer.period;
+            state.timer_wrap[index] = timer.wrap_flag as u64;
+        }
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state =
+            HpetState::from_bytes(state).with_context(|| MigrationError::FromBytesError("HPET"))?;
+        self.config = state.config;
+        self.isr = state.isr;
+        self.counter = state.counter;
+        self.counter_offset = ticks_to_ns(state.counter).wrapping_sub(self.clock_ns());
+        for (index, timer) in self.timers.iter_mut().enumerate() {
+            timer.config = state.timer_config[index];
+            timer.cmp = state.timer_cmp[index];
+            timer.period = state.timer_period[index];
+            timer.wrap_flag = state.timer_wrap[index] != 0;
+        }
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&HpetState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Hpet {
+    fn resume(&mut self) -> migration::Result<()> {
+        self.restore_timers();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // FSB interrupt delivery, which is not supported.
+    const HPET_TN_FSB_EN: u64 = 1 << 14;
+
+    fn read_reg(hpet: &mut Hpet, offset: u64) -> u64 {
+        let mut data = [0_u8; 8];
+        assert!(hpet.read(&mut data, GuestAddress(0), offset));
+        u64::from_le_bytes(data)
+    }
+
+    fn write_reg(hpet: &mut Hpet, offset: u64, value: u64) {
+        assert!(hpet.write(&value.to_le_bytes(), GuestAddress(0), offset));
+    }
+
+    fn create_hpet(irqs: &Arc<Mutex<Vec<(u32, bool)>>>, legacy: &Arc<Mutex<bool>>) -> Hpet {
+        let irqs = irqs.clone();
+        let legacy = legacy.clone();
+        Hpet::new(
+            0x00F0_0000,
+            Box::new(move |gsi, level| {
+                irqs.lock().unwrap().push((gsi, level));
+                Ok(())
+            }),
+            Box::new(move |enable| {
+                *legacy.lock().unwrap() = enable;
+                Ok(())
+            }),
+        )
+    }
+
+    #[test]
+    fn test_hpet_registers() {
+        EventLoop::object_init(&None).unwrap();
+        let irqs = Arc::new(Mutex::new(Vec::new()));
+        let legacy = Arc::new(Mutex::new(false));
+        let mut hpet = create_hpet(&irqs, &legacy);
+
+        let cap = read_reg(&mut hpet, HPET_GCAP_ID);
+        assert_eq!(cap >> HPET_CAP_CLK_PERIOD_SHIFT, HPET_CLK_PERIOD_FS);
+        assert_eq!((cap >> HPET_CAP_NUM_TIM_SHIFT) & 0x1F, 2);
+        assert_ne!(cap & HPET_CAP_LEG_RT, 0);
+        let mut data = [0_u8; 4];
+        assert!(hpet.read(&mut data, GuestAddress(0), HPET_GCAP_ID + 4));
+        assert_eq!(u32::from_le_bytes(data) as u64, HPET_CLK_PERIOD_FS);
+        // Unaligned access is rejected.
+        assert!(!hpet.read(&mut data, GuestAddress(0), HPET_GCAP_ID + 2));
+
+        // Main counter is writable while it's stopped.
+        write_reg(&mut hpet, HPET_MAIN_COUNTER, 0x1000);
+        assert_eq!(read_reg(&mut hpet, HPET_MAIN_COUNTER), 0x1000);
+
+        let conf = HPET_TIMER_BASE + HPET_TIMER_SIZE + HPET_TN_CONF;
+        let route_cap = read_reg(&mut hpet, conf) >> HPET_TN_INT_ROUTE_CAP_SHIFT;
+        assert_eq!(route_cap, 0x00F0_0000);
+        // Read-only capability bits are kept.
+        write_reg(&mut hpet, conf, HPET_TN_32BIT | HPET_TN_FSB_EN);
+        let config = read_reg(&mut hpet, conf);
+        assert_ne!(config & HPET_TN_32BIT, 0);
+        assert_ne!(config & HPET_TN_SIZE_CAP, 0);
+        assert_eq!(config & HPET_TN_FSB_EN, 0);
+
+        // Legacy replacement mode stops the interrupt of PIT.
+        write_reg(&mut hpet, HPET_GEN_CONF, HPET_CFG_ENABLE | HPET_CFG_LEGACY);
+        assert!(*legacy.lock().unwrap());
+        assert!(read_reg(&mut hpet, HPET_MAIN_COUNTER) >= 0x1000);
+        hpet.reset().unwrap();
+        assert!(!*legacy.lock().unwrap());
+        assert_eq!(read_reg(&mut hpet, HPET_GEN_CONF), 0);
+    }
+
+    #[test]
+    fn test_hpet_timer_irq() {
+        EventLoop::object_init(&None).unwrap();
+        let irqs = Arc::new(Mutex::new(Vec::new()));
+        let legacy = Arc::new(Mutex::new(false));
+        let mut hpet = create_hpet(&irqs, &legacy);
+
+        // Timer 0 is periodic and routed to IRQ 0 in legacy replacement mode.
+        let conf0 = HPET_TIMER_BASE + HPET_TN_CONF;
+        let cmp0 = HPET_TIMER_BASE + HPET_TN_CMP;
+        write_reg(
+            &mut hpet,
+            conf0,
+            HPET_TN_ENABLE | HPET_TN_PERIODIC | HPET_TN_SETVAL,
+        );
+        write_reg(&mut hpet, cmp0, 1000);
+        assert_eq!(hpet.timers[0].period, 1000);
+        write_reg(&mut hpet, HPET_GEN_CONF, HPET_CFG_ENABLE | HPET_CFG_LEGACY);
+        assert!(hpet.timers[0].timer_id.is_some());
+
+        let generation = hpet.timers[0].generation;
+        hpet.timer_expired(0, generation);
+        assert_eq!(*irqs.lock().unwrap(), vec![(0, true), (0, false)]);
+        // The comparator is advanced to the next period after the counter.
+        let cmp = read_reg(&mut hpet, cmp0);
+        assert!(cmp > hpet.get_ticks() && (cmp / 1000) * 1000 == cmp);
+        // The callback of the stopped timer is ignored.
+        irqs.lock().unwrap().clear();
+        hpet.timer_expired(0, generation);
+        assert!(irqs.lock().unwrap().is_empty());
+
+        // Timer 2 is level-triggered and routed to GSI 20.
+        let conf2 = HPET_TIMER_BASE + 2 * HPET_TIMER_SIZE + HPET_TN_CONF;
+        write_reg(
+            &mut hpet,
+            conf2,
+            HPET_TN_ENABLE | HPET_TN_TYPE_LEVEL | (20 << HPET_TN_INT_ROUTE_SHIFT),
+        );
+        let generation = hpet.timers[2].generation;
+        hpet.timer_expired(2, generation);
+        assert_eq!(*irqs.lock().unwrap(), vec![(20, true)]);
+        assert_eq!(read_reg(&mut hpet, HPET_GINTR_STA), 1 << 2);
+        write_reg(&mut hpet, HPET_GINTR_STA, 1 << 2);
+        assert_eq!(read_reg(&mut hpet, HPET_GINTR_STA), 0);
+        assert_eq!(irqs.lock().unwrap().last(), Some(&(20, false)));
+
+        // The status is kept by migration.
+        let generation = hpet.timers[2].generation;
+        hpet.timer_expired(2, generation);
+        let state = hpet.get_state_vec().unwrap();
+        let mut new_hpet = create_hpet(&irqs, &legacy);
+        new_hpet.set_state_mut(&state).unwrap();
+        assert_eq!(read_reg(&mut new_hpet, HPET_GINTR_STA), 1 << 2);
+        assert_eq!(read_reg(&mut new_hpet, conf2), read_reg(&mut hpet, conf2));
+        assert!(read_reg(&mut new_hpet, HPET_MAIN_COUNTER) >= read_reg(&mut hpet, cmp0) - 1000);
+        hpet.reset().unwrap();
+    }
+}
@@ -28,6 +28,8 @@
 pub mod error;
 
 mod fwcfg;
+#[cfg(target_arch = "x86_64")]
+mod hpet;
 mod pflash;
 #[cfg(target_arch = "aarch64")]
 mod pl011;
@@ -37,6 +39,7 @@ mod pl031;
 mod ramfb;
 #[cfg(target_arch = "x86_64")]
 mod rtc;
+mod rtc_clock;
 mod serial;
 
 pub use anyhow::Result;
@@ -49,6 +52,8 @@ pub use fwcfg::FwCfgIO;
 #[cfg(target_arch = "aarch64")]
 pub use fwcfg::FwCfgMem;
 pub use fwcfg::{FwCfgEntryType, FwCfgOps};
+#[cfg(target_arch = "x86_64")]
+pub use hpet::{Hpet, PitLegacyHandler, HPET_REGION_SIZE};
 pub use pflash::PFlash;
 #[cfg(target_arch = "aarch64")]
 pub use pl011::PL011;
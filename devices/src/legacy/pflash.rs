@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -42,6 +42,9 @@ pub struct PFlash {
     write_cycle: i32,
     /// PFlash is read only or not.
     read_only: bool,
+    /// Writes from guest are rejected when sealed, the backend is kept writable so that it
+    /// can be unsealed later.
+    sealed: bool,
     /// Command to control PFlash.
     cmd: u8,
     /// PFlash status.
@@ -185,6 +188,7 @@ impl PFlash {
             max_device_width: device_width,
             write_cycle: 0,
             read_only,
+            sealed: false,
             cmd: 0,
             status: 0x80,
             cfi_table,
@@ -195,12 +199,65 @@ impl PFlash {
     }
 
     pub fn realize(
-        mut self,
+        self,
         sysbus: &mut SysBus,
         region_base: u64,
         region_size: u64,
         backend: Option<File>,
-    ) -> Result<()> {
+    ) -> Result<Arc<Mutex<PFlash>>> {
+        let read_only = self.read_only;
+        self.realize_region(
+            sysbus,
+            region_base,
+            region_size,
+            backend.map(FileBackend::new_common),
+            read_only,
+        )
+    }
+
+    /// Realize the read-only PFlash whose content is copied from the image file to the
+    /// memory backend, e.g. hugepages, rather than mapped from the image file.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_backend` - Memory which backs the PFlash.
+    /// * `image` - Image file of the PFlash, such as firmware code.
+    pub fn realize_with_image(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+        mem_backend: FileBackend,
+        mut image: File,
+    ) -> Result<Arc<Mutex<PFlash>>> {
+        if !self.read_only {
+            bail!("PFlash copied from image must be read only");
+        }
+        // The content is never synchronized to the image.
+        self.has_backend = false;
+        let dev =
+            self.realize_region(sysbus, region_base, region_size, Some(mem_backend), false)?;
+
+        let mut buf = vec![0_u8; dev.lock().unwrap().block_len as usize];
+        let mut offset = 0;
+        while offset < region_size {
+            image
+                .read_exact(&mut buf)
+                .with_context(|| "Failed to read PFlash image")?;
+            dev.lock().unwrap().write_data(&buf, offset)?;
+            offset += buf.len() as u64;
+        }
+        Ok(dev)
+    }
+
+    fn realize_region(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+        backend: Option<FileBackend>,
+        read_only_map: bool,
+    ) -> Result<Arc<Mutex<PFlash>>> {
         self.set_sys_resource(sysbus, region_base, region_size)
             .with_context(|| "Failed to allocate system resource for PFlash.")?;
 
@@ -208,10 +265,10 @@ impl PFlash {
             GuestAddress(region_base),
             None,
             region_size,
-            backend.map(FileBackend::new_common),
+            backend,
             false,
             true,
-            self.read_only,
+            read_only_map,
         )?);
 
         let dev = Arc::new(Mutex::new(self));
@@ -224,11 +281,23 @@ impl PFlash {
             .root()
             .add_subregion(rom_region, region_base)
             .with_context(|| "Failed to attach PFlash to system bus")?;
-        sysbus.devices.push(dev);
+        sysbus.devices.push(dev.clone());
+
+        Ok(dev)
+    }
 
+    pub fn set_sealed(&mut self, sealed: bool) -> Result<()> {
+        if self.read_only && !sealed {
+            bail!("PFlash is read only and can't be unsealed");
+        }
+        self.sealed = sealed;
         Ok(())
     }
 
+    fn write_protected(&self) -> bool {
+        self.read_only || self.sealed
+    }
+
     fn set_read_array_mode(&mut self, is_illegal_cmd: bool) -> Result<()> {
         self.rom
             .as_ref()
@@ -406,7 +475,7 @@ impl PFlash {
             }
             0x20 => {
                 let offset_mask = offset & !(self.block_len as u64 - 1);
-                if !self.read_only {
+                if !self.write_protected() {
                     let all_one = vec![0xff_u8; self.block_len as usize];
                     if let Err(e) = self.write_data(all_one.as_slice(), offset_mask) {
                         error!("Failed to write PFlash device: {:?}", e);
@@ -473,7 +542,7 @@ impl PFlash {
     ) -> bool {
         match self.cmd {
             0x10 | 0x40 => {
-                if !self.read_only {
+                if !self.write_protected() {
                     if let Err(e) = self.write_data(data, offset) {
                         error!("Failed to write to PFlash device: {:?}.", e);
                     }
@@ -580,7 +649,7 @@ impl PFlash {
     fn handle_write_third_pass(&mut self, offset: u64, data: &[u8]) -> bool {
         match self.cmd {
             0xe8 => {
-                if !self.read_only {
+                if !self.write_protected() {
                     if let Err(e) = self.write_data(data, offset) {
                         error!("Failed to write to PFlash device: {:?}", e);
                     }
@@ -591,7 +660,7 @@ impl PFlash {
                 if self.counter == 0 {
                     let mask: u64 = !(self.write_blk_size as u64 - 1);
                     self.write_cycle += 1;
-                    if !self.read_only {
+                    if !self.write_protected() {
                         if let Err(e) = self.update_content(offset & mask, self.write_blk_size) {
                             error!("Failed to update content for PFlash device: {:?}", e);
                         }
@@ -1058,6 +1127,87 @@ mod test {
         fs::remove_file(file_name).unwrap();
     }
 
+    #[test]
+    fn test_realize_with_image() {
+        let file_name = "flash_code_for_image.fd";
+        let sector_len: u32 = 0x40_000;
+        let flash_size: u64 = 0x80_0000;
+        let flash_base: u64 = 0x0A00_0000;
+
+        let mut image = vec![0_u8; flash_size as usize];
+        image[0..4].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        image[flash_size as usize - 1] = 0x9a;
+        fs::write(file_name, &image).unwrap();
+        let image_fd = File::open(file_name).unwrap();
+        let fd = Some(image_fd.try_clone().unwrap());
+        let mem_backend = FileBackend::new_mem("/tmp", flash_size).unwrap();
+
+        let mut sysbus = sysbus_init();
+        // The PFlash copied from image must be read only.
+        let pflash = PFlash::new(flash_size, &fd, sector_len, 4, 2, false).unwrap();
+        assert!(PFlash::realize_with_image(
+            pflash,
+            &mut sysbus,
+            flash_base,
+            flash_size,
+            mem_backend.clone(),
+            File::open(file_name).unwrap(),
+        )
+        .is_err());
+
+        let pflash = PFlash::new(flash_size, &fd, sector_len, 4, 2, true).unwrap();
+        let dev = PFlash::realize_with_image(
+            pflash,
+            &mut sysbus,
+            flash_base,
+            flash_size,
+            mem_backend,
+            image_fd,
+        )
+        .unwrap();
+        assert!(!dev.lock().unwrap().has_backend);
+
+        let base = GuestAddress(flash_base);
+        let mut read_data = vec![0, 0, 0, 0];
+        assert!(dev.lock().unwrap().read(&mut read_data, base, 0));
+        assert_eq!(read_data, vec![0x12, 0x34, 0x56, 0x78]);
+        let mut read_data = vec![0];
+        assert!(dev
+            .lock()
+            .unwrap()
+            .read(&mut read_data, base, flash_size - 1));
+        assert_eq!(read_data, vec![0x9a]);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn test_write_sealed() {
+        let file_name = "flash_vars_for_write_4.fd";
+        let dev = pflash_dev_init(file_name);
+        let base = GuestAddress(0x0000);
+        let offset = 0_u64;
+        dev.lock().unwrap().set_sealed(true).unwrap();
+
+        let data = vec![0x10, 0, 0, 0];
+        dev.lock().unwrap().write_cycle = 0;
+        assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
+        let data = vec![0x70, 0, 0x70, 0];
+        assert!(dev.lock().unwrap().write(data.as_ref(), base, offset));
+        // Program error is reported in status.
+        assert_eq!(dev.lock().unwrap().status & 0x10, 0x10);
+
+        let mut read_data = vec![0, 0, 0, 0];
+        dev.lock().unwrap().cmd = 0x00;
+        assert!(dev.lock().unwrap().read(&mut read_data, base, offset));
+        assert_eq!(read_data, vec![0, 0, 0, 0]);
+
+        dev.lock().unwrap().read_only = true;
+        assert!(dev.lock().unwrap().set_sealed(false).is_err());
+
+        fs::remove_file(file_name).unwrap();
+    }
+
     #[test]
     fn test_write_to_buffer() {
         let file_name = "flash_vars_for_write_3.fd";
@@ -25,7 +25,7 @@ use acpi::{
     AmlScopeBuilder, AmlString, INTERRUPT_PPIS_COUNT, INTERRUPT_SGIS_COUNT,
 };
 use address_space::GuestAddress;
-use chardev_backend::chardev::{Chardev, InputReceiver};
+use chardev_backend::chardev::{register_chardev, Chardev, InputReceiver};
 use machine_manager::{
     config::{BootSource, Param, SerialConfig},
     event_loop::EventLoop,
@@ -136,6 +136,7 @@ impl PL011 {
                 dev_type: SysBusDevType::PL011,
                 res: SysRes::default(),
                 interrupt_evt: Some(Arc::new(EventFd::new(libc::EFD_NONBLOCK)?)),
+                interrupt_counter: None,
             },
             state: PL011State::new(),
             chardev: Arc::new(Mutex::new(Chardev::new(cfg.chardev))),
@@ -147,7 +148,7 @@ impl PL011 {
 
         let flag = self.state.int_level & self.state.int_enabled;
         if flag & irq_mask != 0 {
-            if let Err(e) = self.interrupt_evt().unwrap().write(1) {
+            if let Err(e) = self.base.inject_interrupt() {
                 error!(
                     "Failed to trigger interrupt for PL011, flag is 0x{:x}, error is {:?}",
                     flag, e,
@@ -192,6 +193,7 @@ impl PL011 {
             None,
         )
         .with_context(|| LegacyError::RegNotifierErr)?;
+        register_chardev(&locked_dev.chardev);
         Ok(())
     }
 }
@@ -466,6 +468,7 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            mux: false,
         };
         let mut pl011_dev = PL011::new(SerialConfig {
             chardev: chardev_cfg,
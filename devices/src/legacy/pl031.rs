@@ -11,7 +11,6 @@
 // See the Mulan PSL v2 for more details.
 
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
@@ -19,10 +18,13 @@ use log::error;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::error::LegacyError;
+use super::rtc_clock::RtcTime;
 use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
 use crate::{Device, DeviceBase};
 use acpi::AmlBuilder;
 use address_space::GuestAddress;
+use machine_manager::config::RtcConfig;
+use machine_manager::qmp::qmp_schema;
 use migration::{
     snapshot::PL031_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
     MigrationManager, StateTransfer,
@@ -73,34 +75,36 @@ pub struct PL031 {
     base: SysBusDevBase,
     /// State of device PL031.
     state: PL031State,
-    /// The duplicate of Load register value.
-    tick_offset: u32,
-    /// Record the real time.
-    base_time: Instant,
+    /// Wall clock time of RTC, which is loaded by Load register.
+    time: RtcTime,
 }
 
 impl Default for PL031 {
     fn default() -> Self {
+        Self::new(&RtcConfig::default())
+    }
+}
+
+impl PL031 {
+    /// Construct function of PL031 device.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Start time and clock of RTC.
+    pub fn new(config: &RtcConfig) -> Self {
         Self {
             base: SysBusDevBase::new(SysBusDevType::Rtc),
             state: PL031State::default(),
-            // since 1970-01-01 00:00:00,it never cause overflow.
-            tick_offset: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("time wrong")
-                .as_secs() as u32,
-            base_time: Instant::now(),
+            time: RtcTime::new(config),
         }
     }
-}
 
-impl PL031 {
     pub fn realize(
         mut self,
         sysbus: &mut SysBus,
         region_base: u64,
         region_size: u64,
-    ) -> Result<()> {
+    ) -> Result<Arc<Mutex<PL031>>> {
         self.base.interrupt_evt = Some(Arc::new(EventFd::new(libc::EFD_NONBLOCK)?));
         self.set_sys_resource(sysbus, region_base, region_size)
             .with_context(|| LegacyError::SetSysResErr)?;
@@ -110,26 +114,27 @@ impl PL031 {
 
         MigrationManager::register_device_instance(
             PL031State::descriptor(),
-            dev,
+            dev.clone(),
             PL031_SNAPSHOT_ID,
         );
 
-        Ok(())
+        Ok(dev)
     }
 
     /// Get current clock value.
     fn get_current_value(&self) -> u32 {
-        (self.base_time.elapsed().as_secs() as u128 + self.tick_offset as u128) as u32
+        self.time.now() as u32
+    }
+
+    /// Query the time of RTC.
+    pub fn query(&self) -> qmp_schema::RtcInfo {
+        self.time.query()
     }
 
     fn inject_interrupt(&self) {
-        if let Some(evt_fd) = self.interrupt_evt() {
-            if let Err(e) = evt_fd.write(1) {
-                error!("pl031: failed to write interrupt eventfd ({:?}).", e);
-            }
-            return;
+        if let Err(e) = self.base.inject_interrupt() {
+            error!("pl031: failed to inject interrupt ({:?}).", e);
         }
-        error!("pl031: failed to get interrupt event fd.");
     }
 }
 
@@ -189,8 +194,7 @@ impl SysBusDevOps for PL031 {
             }
             RTC_LR => {
                 self.state.lr = value;
-                self.tick_offset = value;
-                self.base_time = Instant::now();
+                self.time.set(value as i64);
             }
             RTC_IMSC => {
                 self.state.imsr = value & 1;
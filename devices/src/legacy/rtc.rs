@@ -10,13 +10,14 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use log::{debug, error, warn};
 use vmm_sys_util::eventfd::EventFd;
 
+use super::rtc_clock::RtcTime;
 use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
 use crate::{Device, DeviceBase};
 use acpi::{
@@ -24,15 +25,25 @@ use acpi::{
     AmlResTemplate, AmlScopeBuilder,
 };
 use address_space::GuestAddress;
+use hypervisor::hypervisor;
+use machine_manager::config::{RtcConfig, RtcDriftFix};
+use machine_manager::event;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_schema};
 use util::time::{mktime64, NANOSECONDS_PER_SECOND};
 
 /// IO port of RTC device to select Register to read/write.
 pub const RTC_PORT_INDEX: u64 = 0x70;
+/// ISA IRQ of RTC device.
+const RTC_IRQ: i32 = 8;
 
 /// Index of register of time in RTC static RAM.
 const RTC_SECONDS: u8 = 0x00;
+const RTC_SECONDS_ALARM: u8 = 0x01;
 const RTC_MINUTES: u8 = 0x02;
+const RTC_MINUTES_ALARM: u8 = 0x03;
 const RTC_HOURS: u8 = 0x04;
+const RTC_HOURS_ALARM: u8 = 0x05;
 const RTC_DAY_OF_WEEK: u8 = 0x06;
 const RTC_DAY_OF_MONTH: u8 = 0x07;
 const RTC_MONTH: u8 = 0x08;
@@ -42,11 +53,46 @@ const RTC_REG_B: u8 = 0x0B;
 const RTC_REG_C: u8 = 0x0C;
 const RTC_REG_D: u8 = 0x0D;
 const RTC_CENTURY_BCD: u8 = 0x32;
+// Shutdown status byte, firmware checks it to tell resume from S3 and normal boot.
+const CMOS_SHUTDOWN_STATUS: u8 = 0x0F;
+// Shutdown status which means the system is resumed from S3.
+const SHUTDOWN_STATUS_S3_RESUME: u8 = 0xFE;
 
 // Update in progress (UIP) bit.
 const REG_A_UIP: u8 = 0x80;
+// Rate selection bits of periodic interrupt.
+const REG_A_RATE_MASK: u8 = 0x0F;
 // UIP bit held for last 244 us of every second.
 const UIP_HOLD_LENGTH: u64 = 8 * NANOSECONDS_PER_SECOND / 32768;
+// Frequency of the time base.
+const RTC_CLOCK_HZ: u64 = 32768;
+
+// Update of the time is inhibited.
+const REG_B_SET: u8 = 0x80;
+// Periodic interrupt enable.
+const REG_B_PIE: u8 = 0x40;
+// Alarm interrupt enable.
+const REG_B_AIE: u8 = 0x20;
+// Update-ended interrupt enable.
+const REG_B_UIE: u8 = 0x10;
+// Time is in binary format rather than BCD.
+const REG_B_DM: u8 = 0x04;
+// Hours are in 24-hour format rather than 12-hour.
+const REG_B_24H: u8 = 0x02;
+
+// Interrupt request flag, set when any enabled interrupt is pending.
+const REG_C_IRQF: u8 = 0x80;
+// Periodic interrupt flag.
+const REG_C_PF: u8 = 0x40;
+// Alarm interrupt flag.
+const REG_C_AF: u8 = 0x20;
+// Update-ended interrupt flag.
+const REG_C_UF: u8 = 0x10;
+
+// PM bit of hours in 12-hour format.
+const HOURS_PM: u8 = 0x80;
+// The alarm register matches any value when its two high bits are set.
+const ALARM_DONT_CARE: u8 = 0xC0;
 
 // Index of memory data in RTC static RAM.
 // 0x15/0x16 stores low/high byte below 1MB, range is [0, 640KB].
@@ -109,15 +155,74 @@ pub struct RTC {
     mem_size: u64,
     /// The start address of gap.
     gap_start: u64,
-    /// The tick offset.
-    tick_offset: u64,
-    /// Record the real time.
-    base_time: Instant,
+    /// Wall clock time of RTC.
+    time: RtcTime,
+    /// Policy of the missed periodic interrupts.
+    driftfix: RtcDriftFix,
+    /// The device itself, which is used by timers.
+    dev: Weak<Mutex<RTC>>,
+    /// Timer of periodic interrupt.
+    periodic_timer: RtcTimer,
+    /// Expire time of the next periodic interrupt.
+    periodic_deadline: Instant,
+    /// Number of the periodic interrupts missed by guest, which are reinjected
+    /// when driftfix is `slew`.
+    irq_coalesced: u32,
+    /// Timer of update-ended and alarm interrupt, which expires every second.
+    second_timer: RtcTimer,
+}
+
+/// One-shot timer of RTC, it runs in the main loop.
+#[derive(Default)]
+struct RtcTimer {
+    timer_id: Option<u64>,
+    /// Increased each time the timer starts, the callback of the stopped timer which
+    /// has already expired finds it stale.
+    generation: u64,
+}
+
+impl RtcTimer {
+    fn start(&mut self, dev: &Weak<Mutex<RTC>>, delay: Duration, expire: fn(&mut RTC, u64)) {
+        self.stop();
+        self.generation += 1;
+        let generation = self.generation;
+        let dev = dev.clone();
+        let func = Box::new(move || {
+            if let Some(dev) = dev.upgrade() {
+                expire(&mut dev.lock().unwrap(), generation);
+            }
+        });
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            self.timer_id = Some(ctx.timer_add(func, delay));
+        }
+    }
+
+    /// Returns whether the expired timer is the current one, the id is invalid after
+    /// the timer fires.
+    fn expire(&mut self, generation: u64) -> bool {
+        if generation != self.generation {
+            return false;
+        }
+        self.timer_id = None;
+        true
+    }
+
+    fn stop(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
 }
 
 impl RTC {
     /// Construct function of RTC device.
-    pub fn new() -> Result<RTC> {
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Start time and drift policy of RTC.
+    pub fn new(config: &RtcConfig) -> Result<RTC> {
         let mut rtc = RTC {
             base: SysBusDevBase {
                 base: DeviceBase::default(),
@@ -128,24 +233,26 @@ impl RTC {
                     irq: -1,
                 },
                 interrupt_evt: Some(Arc::new(EventFd::new(libc::EFD_NONBLOCK)?)),
+                interrupt_counter: None,
             },
             cmos_data: [0_u8; 128],
             cur_index: 0_u8,
             mem_size: 0,
             gap_start: 0,
-            // Since 1970-01-01 00:00:00, it never cause overflow.
-            tick_offset: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("time wrong")
-                .as_secs(),
-            base_time: Instant::now(),
+            time: RtcTime::new(config),
+            driftfix: config.driftfix,
+            dev: Weak::new(),
+            periodic_timer: RtcTimer::default(),
+            periodic_deadline: Instant::now(),
+            irq_coalesced: 0,
+            second_timer: RtcTimer::default(),
         };
 
+        rtc.init_rtc_reg();
+
         let tm = rtc_time_to_tm(rtc.get_current_value());
         rtc.set_rtc_cmos(tm);
 
-        rtc.init_rtc_reg();
-
         Ok(rtc)
     }
 
@@ -188,6 +295,12 @@ impl RTC {
         }
     }
 
+    /// Record that the system is suspended to RAM, so that firmware resumes the guest
+    /// through the waking vector instead of booting it.
+    pub fn notify_suspend(&mut self) {
+        self.cmos_data[CMOS_SHUTDOWN_STATUS as usize] = SHUTDOWN_STATUS_S3_RESUME;
+    }
+
     fn init_rtc_reg(&mut self) {
         // Set Time frequency divider and Rate selection frequency in Register-A.
         // Bits 6-4 = Time frequency divider (010 = 32.768KHz).
@@ -195,32 +308,40 @@ impl RTC {
         self.cmos_data[RTC_REG_A as usize] = 0x26;
 
         // Set 24 hour mode in Register-B.
-        self.cmos_data[RTC_REG_B as usize] = 0x02;
+        self.cmos_data[RTC_REG_B as usize] = REG_B_24H;
 
         // Set VRT bit in Register-D, indicates that RAM and time are valid.
         self.cmos_data[RTC_REG_D as usize] = 0x80;
     }
 
+    fn reg_b(&self) -> u8 {
+        self.cmos_data[RTC_REG_B as usize]
+    }
+
     fn read_data(&mut self, data: &mut [u8]) -> bool {
         if data.len() != 1 {
             error!("RTC only supports reading data byte by byte.");
             return false;
         }
 
-        let tm = rtc_time_to_tm(self.get_current_value());
-        self.set_rtc_cmos(tm);
+        // The time is not updated while guest is setting it.
+        if self.reg_b() & REG_B_SET == 0 {
+            let tm = rtc_time_to_tm(self.get_current_value());
+            self.set_rtc_cmos(tm);
+        }
         match self.cur_index {
             RTC_REG_A => {
                 data[0] = self.cmos_data[RTC_REG_A as usize];
                 // UIP(update in progress) bit will be set at last 244us of every second.
-                if self.update_in_progress() {
+                if self.reg_b() & REG_B_SET == 0 && self.update_in_progress() {
                     data[0] |= REG_A_UIP;
-                    self.inject_interrupt();
                 }
             }
             RTC_REG_C => {
-                // The interrupt request flag (IRQF), alarm interrupt flag (AF).
-                data[0] = 1 << 7 | 1 << 5;
+                // Reading Register-C acknowledges all the pending interrupts.
+                data[0] = self.cmos_data[RTC_REG_C as usize];
+                self.cmos_data[RTC_REG_C as usize] = 0;
+                self.reinject_periodic_irq();
             }
             _ => {
                 data[0] = self.cmos_data[self.cur_index as usize];
@@ -241,7 +362,9 @@ impl RTC {
             | RTC_MONTH | RTC_YEAR | RTC_CENTURY_BCD => {
                 if self.rtc_valid_check(data[0]) {
                     self.cmos_data[self.cur_index as usize] = data[0];
-                    self.update_rtc_time();
+                    if self.reg_b() & REG_B_SET == 0 {
+                        self.update_rtc_time();
+                    }
                 } else {
                     warn!(
                         "Set invalid RTC time, index {}, data {}",
@@ -249,6 +372,11 @@ impl RTC {
                     );
                 }
             }
+            RTC_REG_A => {
+                self.cmos_data[RTC_REG_A as usize] = data[0] & !REG_A_UIP;
+                self.update_periodic_timer();
+            }
+            RTC_REG_B => self.write_reg_b(data[0]),
             RTC_REG_C | RTC_REG_D => {
                 warn!(
                     "Failed to write: read-only register, index {}, data {}",
@@ -263,43 +391,117 @@ impl RTC {
         true
     }
 
-    pub fn realize(mut self, sysbus: &mut SysBus) -> Result<()> {
+    fn write_reg_b(&mut self, mut val: u8) {
+        let old_val = self.reg_b();
+        if val & REG_B_SET != 0 {
+            // Setting the time aborts the update cycle, so does the update-ended interrupt.
+            val &= !REG_B_UIE;
+            if old_val & REG_B_SET == 0 {
+                // Keep the registers up-to-date before the update is inhibited.
+                let tm = rtc_time_to_tm(self.get_current_value());
+                self.set_rtc_cmos(tm);
+            }
+        }
+        self.cmos_data[RTC_REG_B as usize] = val;
+
+        if (old_val ^ val) & (REG_B_DM | REG_B_24H) != 0 && val & REG_B_SET == 0 {
+            // The format is changed, refresh the time registers.
+            let tm = rtc_time_to_tm(self.get_current_value());
+            self.set_rtc_cmos(tm);
+        }
+        if old_val & REG_B_SET != 0 && val & REG_B_SET == 0 {
+            self.update_rtc_time();
+        }
+        self.update_periodic_timer();
+        self.update_second_timer();
+    }
+
+    pub fn realize(mut self, sysbus: &mut SysBus) -> Result<Arc<Mutex<RTC>>> {
         let region_base = self.base.res.region_base;
         let region_size = self.base.res.region_size;
         self.set_sys_resource(sysbus, region_base, region_size)?;
 
         let dev = Arc::new(Mutex::new(self));
+        dev.lock().unwrap().dev = Arc::downgrade(&dev);
         sysbus.attach_device(&dev, region_base, region_size, "RTC")?;
-        Ok(())
+        Ok(dev)
     }
 
-    fn inject_interrupt(&self) {
-        if let Some(evt_fd) = self.interrupt_evt() {
-            if let Err(e) = evt_fd.write(1) {
-                error!("cmos rtc: failed to write interrupt eventfd ({:?}).", e);
-            }
-            return;
+    /// Query the time of RTC.
+    pub fn query(&self) -> qmp_schema::RtcInfo {
+        self.time.query()
+    }
+
+    /// Drop the missed periodic interrupts which are waiting to be reinjected.
+    pub fn reset_reinjection(&mut self) {
+        self.irq_coalesced = 0;
+    }
+
+    /// Raise the interrupt of the given flag in Register-C.
+    fn raise_irq(&mut self, flag: u8) {
+        self.cmos_data[RTC_REG_C as usize] |= REG_C_IRQF | flag;
+        if let Err(e) = self.base.inject_interrupt() {
+            error!("cmos rtc: failed to inject interrupt ({:?}).", e);
         }
-        error!("cmos rtc: failed to get interrupt event fd.");
     }
 
     /// Get current clock value.
     fn get_current_value(&self) -> i64 {
-        (self.base_time.elapsed().as_secs() as i128 + self.tick_offset as i128) as i64
+        self.time.now()
+    }
+
+    fn bin_to_reg(&self, val: u8) -> u8 {
+        if self.reg_b() & REG_B_DM != 0 {
+            val
+        } else {
+            bin_to_bcd(val)
+        }
+    }
+
+    fn reg_to_bin(&self, val: u8) -> u64 {
+        if self.reg_b() & REG_B_DM != 0 {
+            val as u64
+        } else {
+            bcd_to_bin(val)
+        }
+    }
+
+    fn hour_to_reg(&self, hour: u8) -> u8 {
+        if self.reg_b() & REG_B_24H != 0 {
+            return self.bin_to_reg(hour);
+        }
+        let pm = if hour >= 12 { HOURS_PM } else { 0 };
+        match hour % 12 {
+            0 => self.bin_to_reg(12) | pm,
+            hour => self.bin_to_reg(hour) | pm,
+        }
+    }
+
+    fn reg_to_hour(&self, val: u8) -> u64 {
+        if self.reg_b() & REG_B_24H != 0 {
+            return self.reg_to_bin(val);
+        }
+        let hour = self.reg_to_bin(val & !HOURS_PM) % 12;
+        if val & HOURS_PM != 0 {
+            hour + 12
+        } else {
+            hour
+        }
     }
 
     fn set_rtc_cmos(&mut self, tm: libc::tm) {
-        self.cmos_data[RTC_SECONDS as usize] = bin_to_bcd(tm.tm_sec as u8);
-        self.cmos_data[RTC_MINUTES as usize] = bin_to_bcd(tm.tm_min as u8);
-        self.cmos_data[RTC_HOURS as usize] = bin_to_bcd(tm.tm_hour as u8);
-        self.cmos_data[RTC_DAY_OF_WEEK as usize] = bin_to_bcd((tm.tm_wday + 1) as u8);
-        self.cmos_data[RTC_DAY_OF_MONTH as usize] = bin_to_bcd(tm.tm_mday as u8);
-        self.cmos_data[RTC_MONTH as usize] = bin_to_bcd((tm.tm_mon + 1) as u8);
-        self.cmos_data[RTC_YEAR as usize] = bin_to_bcd(((tm.tm_year + 1900) % 100) as u8);
+        self.cmos_data[RTC_SECONDS as usize] = self.bin_to_reg(tm.tm_sec as u8);
+        self.cmos_data[RTC_MINUTES as usize] = self.bin_to_reg(tm.tm_min as u8);
+        self.cmos_data[RTC_HOURS as usize] = self.hour_to_reg(tm.tm_hour as u8);
+        self.cmos_data[RTC_DAY_OF_WEEK as usize] = self.bin_to_reg((tm.tm_wday + 1) as u8);
+        self.cmos_data[RTC_DAY_OF_MONTH as usize] = self.bin_to_reg(tm.tm_mday as u8);
+        self.cmos_data[RTC_MONTH as usize] = self.bin_to_reg((tm.tm_mon + 1) as u8);
+        self.cmos_data[RTC_YEAR as usize] = self.bin_to_reg(((tm.tm_year + 1900) % 100) as u8);
+        // The century is always in BCD format.
         self.cmos_data[RTC_CENTURY_BCD as usize] = bin_to_bcd(((tm.tm_year + 1900) / 100) as u8);
     }
 
-    fn rtc_valid_check(&self, val: u8) -> bool {
+    fn rtc_valid_check(&self, mut val: u8) -> bool {
         let range = [
             [0, 59], // Seconds
             [0, 59], // Seconds Alarm
@@ -313,32 +515,40 @@ impl RTC {
             [0, 99], // Year
         ];
 
-        if (val >> 4) > 9 || (val & 0x0f) > 9 {
-            return false;
+        let mut valid_range = range.get(self.cur_index as usize).copied();
+        if self.cur_index == RTC_HOURS && self.reg_b() & REG_B_24H == 0 {
+            val &= !HOURS_PM;
+            valid_range = Some([1, 12]);
         }
 
-        let value = bcd_to_bin(val);
+        let value = if self.reg_b() & REG_B_DM == 0 || self.cur_index == RTC_CENTURY_BCD {
+            if (val >> 4) > 9 || (val & 0x0f) > 9 {
+                return false;
+            }
+            bcd_to_bin(val)
+        } else {
+            val as u64
+        };
 
-        if self.cur_index <= 9
-            && (value < range[self.cur_index as usize][0]
-                || value > range[self.cur_index as usize][1])
-        {
-            return false;
+        if let Some([min, max]) = valid_range {
+            if value < min || value > max {
+                return false;
+            }
         }
 
         true
     }
 
     fn update_rtc_time(&mut self) {
-        let sec = bcd_to_bin(self.cmos_data[RTC_SECONDS as usize]);
-        let min = bcd_to_bin(self.cmos_data[RTC_MINUTES as usize]);
-        let hour = bcd_to_bin(self.cmos_data[RTC_HOURS as usize]);
-        let day = bcd_to_bin(self.cmos_data[RTC_DAY_OF_MONTH as usize]);
-        let mon = bcd_to_bin(self.cmos_data[RTC_MONTH as usize]);
-        let year = bcd_to_bin(self.cmos_data[RTC_YEAR as usize])
+        let sec = self.reg_to_bin(self.cmos_data[RTC_SECONDS as usize]);
+        let min = self.reg_to_bin(self.cmos_data[RTC_MINUTES as usize]);
+        let hour = self.reg_to_hour(self.cmos_data[RTC_HOURS as usize]);
+        let day = self.reg_to_bin(self.cmos_data[RTC_DAY_OF_MONTH as usize]);
+        let mon = self.reg_to_bin(self.cmos_data[RTC_MONTH as usize]);
+        let year = self.reg_to_bin(self.cmos_data[RTC_YEAR as usize])
             + bcd_to_bin(self.cmos_data[RTC_CENTURY_BCD as usize]) * 100;
 
-        // Check rtc time is valid to prevent tick_offset overflow.
+        // Check rtc time is valid to prevent time overflow.
         if year < 1970 || !(1..=12).contains(&mon) || !(1..=31).contains(&day) {
             warn!(
                 "RTC: the updated rtc time {}-{}-{} may be invalid.",
@@ -347,13 +557,143 @@ impl RTC {
             return;
         }
 
-        self.tick_offset = mktime64(year, mon, day, hour, min, sec);
+        self.time
+            .set(mktime64(year, mon, day, hour, min, sec) as i64);
+        // The second boundary is moved.
+        self.update_second_timer();
 
-        self.base_time = Instant::now();
+        let rtc_change = qmp_schema::RtcChange {
+            offset: self.time.offset(),
+        };
+        event!(RtcChange; rtc_change);
     }
 
     fn update_in_progress(&self) -> bool {
-        self.base_time.elapsed().subsec_nanos() >= (NANOSECONDS_PER_SECOND - UIP_HOLD_LENGTH) as u32
+        self.time.elapsed().subsec_nanos() >= (NANOSECONDS_PER_SECOND - UIP_HOLD_LENGTH) as u32
+    }
+
+    /// Period of the periodic interrupt, `None` if it's disabled.
+    fn periodic_period(&self) -> Option<Duration> {
+        if self.reg_b() & REG_B_PIE == 0 {
+            return None;
+        }
+        let mut rate = self.cmos_data[RTC_REG_A as usize] & REG_A_RATE_MASK;
+        match rate {
+            0 => return None,
+            // Rate 1 and 2 are the same as 8 and 9 with 32.768KHz time base.
+            1 | 2 => rate += 7,
+            _ => {}
+        }
+        let ticks = 1_u64 << (rate - 1);
+        Some(Duration::from_nanos(
+            ticks * NANOSECONDS_PER_SECOND / RTC_CLOCK_HZ,
+        ))
+    }
+
+    fn update_periodic_timer(&mut self) {
+        self.periodic_timer.stop();
+        match self.periodic_period() {
+            Some(period) => {
+                self.periodic_deadline = Instant::now() + period;
+                let dev = self.dev.clone();
+                self.periodic_timer
+                    .start(&dev, period, RTC::periodic_timer_expired);
+            }
+            None => self.irq_coalesced = 0,
+        }
+    }
+
+    fn periodic_timer_expired(&mut self, generation: u64) {
+        if !self.periodic_timer.expire(generation) {
+            return;
+        }
+        let period = match self.periodic_period() {
+            Some(period) => period,
+            None => return,
+        };
+
+        // Count the periods elapsed, more than one if the timer is delayed.
+        let now = Instant::now();
+        let mut elapsed = 1_u32;
+        if now > self.periodic_deadline {
+            let late = (now - self.periodic_deadline).as_nanos() / period.as_nanos();
+            elapsed = elapsed.saturating_add(late.min(u32::MAX as u128) as u32);
+        }
+        self.periodic_deadline += period * elapsed;
+
+        // The interrupt is missed if the previous one is not acknowledged yet.
+        let missed = if self.cmos_data[RTC_REG_C as usize] & REG_C_PF != 0 {
+            elapsed
+        } else {
+            self.raise_irq(REG_C_PF);
+            elapsed - 1
+        };
+        if self.driftfix == RtcDriftFix::Slew {
+            self.irq_coalesced = self.irq_coalesced.saturating_add(missed);
+        }
+
+        let delay = self.periodic_deadline.saturating_duration_since(now);
+        let dev = self.dev.clone();
+        self.periodic_timer
+            .start(&dev, delay, RTC::periodic_timer_expired);
+    }
+
+    /// Reinject one missed periodic interrupt once the previous one is acknowledged.
+    fn reinject_periodic_irq(&mut self) {
+        if self.irq_coalesced > 0 && self.periodic_period().is_some() {
+            self.irq_coalesced -= 1;
+            self.raise_irq(REG_C_PF);
+        }
+    }
+
+    fn update_second_timer(&mut self) {
+        self.second_timer.stop();
+        let reg_b = self.reg_b();
+        if reg_b & REG_B_SET != 0 || reg_b & (REG_B_UIE | REG_B_AIE) == 0 {
+            return;
+        }
+        // The update cycle ends at the boundary of every second.
+        let delay = NANOSECONDS_PER_SECOND - self.time.elapsed().subsec_nanos() as u64;
+        let dev = self.dev.clone();
+        self.second_timer
+            .start(&dev, Duration::from_nanos(delay), RTC::second_timer_expired);
+    }
+
+    fn second_timer_expired(&mut self, generation: u64) {
+        if !self.second_timer.expire(generation) {
+            return;
+        }
+        let reg_b = self.reg_b();
+        if reg_b & REG_B_SET != 0 {
+            return;
+        }
+
+        let tm = rtc_time_to_tm(self.get_current_value());
+        self.set_rtc_cmos(tm);
+        let mut flags = REG_C_UF;
+        if self.alarm_match() {
+            flags |= REG_C_AF;
+        }
+        self.cmos_data[RTC_REG_C as usize] |= flags;
+        if (reg_b & REG_B_UIE != 0 && flags & REG_C_UF != 0)
+            || (reg_b & REG_B_AIE != 0 && flags & REG_C_AF != 0)
+        {
+            self.raise_irq(0);
+        }
+        self.update_second_timer();
+    }
+
+    fn alarm_match(&self) -> bool {
+        [
+            (RTC_SECONDS_ALARM, RTC_SECONDS),
+            (RTC_MINUTES_ALARM, RTC_MINUTES),
+            (RTC_HOURS_ALARM, RTC_HOURS),
+        ]
+        .iter()
+        .all(|(alarm, time)| {
+            let alarm = self.cmos_data[*alarm as usize];
+            alarm & ALARM_DONT_CARE == ALARM_DONT_CARE || alarm == self.cmos_data[*time as usize]
+        })
     }
 }
 
@@ -398,12 +738,27 @@ impl SysBusDevOps for RTC {
         }
     }
 
+    fn set_irq(&mut self, _sysbus: &mut SysBus) -> Result<i32> {
+        let mut irq: i32 = -1;
+        if let Some(e) = self.interrupt_evt() {
+            irq = RTC_IRQ;
+            hypervisor().register_irqfd(&e, irq as u32)?;
+        }
+        Ok(irq)
+    }
+
     fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
         Some(&mut self.base.res)
     }
 
     fn reset(&mut self) -> Result<()> {
+        self.periodic_timer.stop();
+        self.second_timer.stop();
+        self.irq_coalesced = 0;
+        // The shutdown status survives reset, it's cleared by firmware after it's checked.
+        let shutdown_status = self.cmos_data[CMOS_SHUTDOWN_STATUS as usize];
         self.cmos_data.fill(0);
+        self.cmos_data[CMOS_SHUTDOWN_STATUS as usize] = shutdown_status;
         self.init_rtc_reg();
         self.set_memory(self.mem_size, self.gap_start);
         Ok(())
@@ -447,6 +802,8 @@ mod test {
     }
 
     fn cmos_write(rtc: &mut RTC, index: u8, val: u8) {
+        // RTC_CHANGE event is sent once the time is written.
+        QmpChannel::object_init();
         let mut data: [u8; 1] = [index; 1];
         RTC::write(rtc, &mut data, GuestAddress(0), 0);
         data[0] = val;
@@ -455,7 +812,8 @@ mod test {
 
     #[test]
     fn test_set_year_20xx() -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
         // Set rtc time: 2013-11-13 02:04:56
         cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x20);
         cmos_write(&mut rtc, RTC_YEAR, 0x13);
@@ -489,7 +847,8 @@ mod test {
 
     #[test]
     fn test_set_year_1970() -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
         // Set rtc time (min): 1970-01-01 00:00:00
         cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x19);
         cmos_write(&mut rtc, RTC_YEAR, 0x70);
@@ -512,7 +871,8 @@ mod test {
 
     #[test]
     fn test_invalid_rtc_time() -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
         // Set rtc year: 1969
         cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x19);
         cmos_write(&mut rtc, RTC_YEAR, 0x69);
@@ -540,4 +900,135 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_rtc_mode() -> Result<()> {
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
+        // Set rtc time in binary and 12-hour format: 2023-05-06 15:04:56
+        cmos_write(&mut rtc, RTC_REG_B, REG_B_SET | REG_B_DM);
+        cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x20);
+        cmos_write(&mut rtc, RTC_YEAR, 23);
+        cmos_write(&mut rtc, RTC_MONTH, 5);
+        cmos_write(&mut rtc, RTC_DAY_OF_MONTH, 6);
+        cmos_write(&mut rtc, RTC_HOURS, HOURS_PM | 3);
+        cmos_write(&mut rtc, RTC_MINUTES, 4);
+        cmos_write(&mut rtc, RTC_SECONDS, 56);
+        // Hour 13 is invalid in 12-hour format.
+        cmos_write(&mut rtc, RTC_HOURS, 13);
+        assert_eq!(cmos_read(&mut rtc, RTC_HOURS), HOURS_PM | 3);
+        cmos_write(&mut rtc, RTC_REG_B, REG_B_DM);
+
+        assert!((cmos_read(&mut rtc, RTC_SECONDS) - 56) <= WIGGLE);
+        assert_eq!(cmos_read(&mut rtc, RTC_MINUTES), 4);
+        assert_eq!(cmos_read(&mut rtc, RTC_HOURS), HOURS_PM | 3);
+        assert_eq!(cmos_read(&mut rtc, RTC_YEAR), 23);
+
+        // Switch to BCD and 24-hour format.
+        cmos_write(&mut rtc, RTC_REG_B, REG_B_24H);
+        assert_eq!(cmos_read(&mut rtc, RTC_HOURS), 0x15);
+        assert_eq!(cmos_read(&mut rtc, RTC_MONTH), 0x05);
+        assert_eq!(cmos_read(&mut rtc, RTC_YEAR), 0x23);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_suspend() -> Result<()> {
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
+        rtc.set_memory(1 << 30, 0xC000_0000);
+        rtc.notify_suspend();
+        rtc.reset()?;
+        assert_eq!(
+            cmos_read(&mut rtc, CMOS_SHUTDOWN_STATUS),
+            SHUTDOWN_STATUS_S3_RESUME
+        );
+        // Firmware clears it after resume.
+        cmos_write(&mut rtc, CMOS_SHUTDOWN_STATUS, 0);
+        rtc.reset()?;
+        assert_eq!(cmos_read(&mut rtc, CMOS_SHUTDOWN_STATUS), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_alarm_irq() -> Result<()> {
+        EventLoop::object_init(&None).unwrap();
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
+        assert_eq!(cmos_read(&mut rtc, RTC_REG_C), 0);
+
+        // The alarm matches any time.
+        cmos_write(&mut rtc, RTC_SECONDS_ALARM, ALARM_DONT_CARE);
+        cmos_write(&mut rtc, RTC_MINUTES_ALARM, ALARM_DONT_CARE);
+        cmos_write(&mut rtc, RTC_HOURS_ALARM, ALARM_DONT_CARE);
+        cmos_write(&mut rtc, RTC_REG_B, REG_B_24H | REG_B_AIE);
+        rtc.second_timer_expired(rtc.second_timer.generation);
+        assert_eq!(
+            cmos_read(&mut rtc, RTC_REG_C),
+            REG_C_IRQF | REG_C_AF | REG_C_UF
+        );
+        // The flags are cleared after read.
+        assert_eq!(cmos_read(&mut rtc, RTC_REG_C), 0);
+
+        // The alarm doesn't match, only the update-ended flag is set without interrupt.
+        let hours = cmos_read(&mut rtc, RTC_HOURS);
+        cmos_write(&mut rtc, RTC_HOURS_ALARM, if hours == 0 { 1 } else { 0 });
+        rtc.second_timer_expired(rtc.second_timer.generation);
+        assert_eq!(cmos_read(&mut rtc, RTC_REG_C), REG_C_UF);
+
+        // The expired timer which has been stopped is ignored.
+        let generation = rtc.second_timer.generation;
+        cmos_write(&mut rtc, RTC_REG_B, REG_B_24H | REG_B_UIE);
+        rtc.second_timer_expired(generation);
+        assert_eq!(cmos_read(&mut rtc, RTC_REG_C), 0);
+        rtc.second_timer_expired(rtc.second_timer.generation);
+        assert_eq!(cmos_read(&mut rtc, RTC_REG_C), REG_C_IRQF | REG_C_UF);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_periodic_irq() -> Result<()> {
+        EventLoop::object_init(&None).unwrap();
+        for driftfix in [RtcDriftFix::None, RtcDriftFix::Slew] {
+            let config = RtcConfig {
+                driftfix,
+                ..Default::default()
+            };
+            let mut rtc = RTC::new(&config).with_context(|| "Failed to create RTC device")?;
+            // 1024Hz periodic interrupt.
+            cmos_write(&mut rtc, RTC_REG_A, 0x26);
+            cmos_write(&mut rtc, RTC_REG_B, REG_B_24H | REG_B_PIE);
+            let period = rtc.periodic_period().unwrap();
+            assert_eq!(period, Duration::from_nanos(976_562));
+
+            // The timer is delayed for 3 periods.
+            rtc.periodic_deadline = Instant::now() - period * 3;
+            rtc.periodic_timer_expired(rtc.periodic_timer.generation);
+            let missed = if driftfix == RtcDriftFix::Slew { 3 } else { 0 };
+            assert_eq!(rtc.irq_coalesced, missed);
+
+            // The missed interrupts are reinjected after acknowledged.
+            for i in 0..=missed {
+                assert_eq!(cmos_read(&mut rtc, RTC_REG_C), REG_C_IRQF | REG_C_PF);
+                assert_eq!(rtc.irq_coalesced, missed.saturating_sub(i + 1));
+            }
+            assert_eq!(cmos_read(&mut rtc, RTC_REG_C), 0);
+
+            // The missed interrupts are dropped by rtc-reset-reinjection.
+            rtc.periodic_deadline = Instant::now() - period * 3;
+            rtc.periodic_timer_expired(rtc.periodic_timer.generation);
+            rtc.reset_reinjection();
+            assert_eq!(cmos_read(&mut rtc, RTC_REG_C), REG_C_IRQF | REG_C_PF);
+            assert_eq!(cmos_read(&mut rtc, RTC_REG_C), 0);
+
+            // Periodic interrupt is disabled with rate 0.
+            cmos_write(&mut rtc, RTC_REG_A, 0x20);
+            assert!(rtc.periodic_period().is_none());
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,107 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::time::{Duration, Instant};
+
+use machine_manager::config::{RtcClock, RtcConfig};
+use machine_manager::event_loop::EventLoop;
+use machine_manager::qmp::qmp_schema;
+
+/// Wall clock time kept by the RTC devices. It's not reset with the VM, so the
+/// time set by `-rtc base` or by guest is preserved across reset.
+pub struct RtcTime {
+    /// Start time and clock of RTC.
+    config: RtcConfig,
+    /// The time in seconds since 1970-01-01 00:00:00 when the time is set.
+    tick_offset: i64,
+    /// The value of the clock when the time is set.
+    base_time: Duration,
+    /// The origin of host clock.
+    origin: Instant,
+}
+
+impl RtcTime {
+    pub fn new(config: &RtcConfig) -> Self {
+        let mut rtc_time = RtcTime {
+            config: *config,
+            tick_offset: config.base.start_time(),
+            base_time: Duration::ZERO,
+            origin: Instant::now(),
+        };
+        rtc_time.base_time = rtc_time.clock_now();
+        rtc_time
+    }
+
+    /// The value of the clock which RTC runs with, the virtual clock stops
+    /// when the VM is paused.
+    fn clock_now(&self) -> Duration {
+        if self.config.clock == RtcClock::Vm {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                return ctx.get_virtual_clock();
+            }
+        }
+        self.origin.elapsed()
+    }
+
+    /// Time elapsed since the time is set.
+    pub fn elapsed(&self) -> Duration {
+        self.clock_now().saturating_sub(self.base_time)
+    }
+
+    /// Get the current time in seconds since 1970-01-01 00:00:00.
+    pub fn now(&self) -> i64 {
+        self.tick_offset + self.elapsed().as_secs() as i64
+    }
+
+    /// Set the current time in seconds since 1970-01-01 00:00:00.
+    pub fn set(&mut self, time: i64) {
+        self.tick_offset = time;
+        self.base_time = self.clock_now();
+    }
+
+    /// Offset in seconds between the current time and the host time in `-rtc base`.
+    pub fn offset(&self) -> i64 {
+        self.now() - self.config.base.host_time()
+    }
+
+    pub fn query(&self) -> qmp_schema::RtcInfo {
+        qmp_schema::RtcInfo {
+            base: self.config.base.name(),
+            clock: self.config.clock.name().to_string(),
+            time: self.now(),
+            offset: self.offset(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use machine_manager::config::RtcBase;
+
+    #[test]
+    fn test_rtc_time() {
+        let config = RtcConfig {
+            base: RtcBase::Datetime(1150560081),
+            ..Default::default()
+        };
+        let mut rtc_time = RtcTime::new(&config);
+        assert!(rtc_time.now() - 1150560081 <= 1);
+        assert!(rtc_time.offset() < 0);
+
+        rtc_time.set(2000000000);
+        assert!(rtc_time.now() - 2000000000 <= 1);
+        let info = rtc_time.query();
+        assert_eq!(info.base, "2006-06-17T16:01:21");
+        assert_eq!(info.clock, "host");
+    }
+}
@@ -26,8 +26,8 @@ use acpi::{
     AmlResourceUsage, AmlScopeBuilder,
 };
 use address_space::GuestAddress;
-use chardev_backend::chardev::{Chardev, InputReceiver};
-use hypervisor::kvm::KVM_FDS;
+use chardev_backend::chardev::{register_chardev, Chardev, InputReceiver};
+use hypervisor::hypervisor;
 #[cfg(target_arch = "aarch64")]
 use machine_manager::config::{BootSource, Param};
 use machine_manager::{config::SerialConfig, event_loop::EventLoop};
@@ -169,6 +169,7 @@ impl Serial {
             None,
         )
         .with_context(|| LegacyError::RegNotifierErr)?;
+        register_chardev(&locked_dev.chardev);
         Ok(())
     }
 
@@ -187,13 +188,9 @@ impl Serial {
 
         self.state.iir = iir;
         if iir != UART_IIR_NO_INT {
-            if let Some(evt) = self.interrupt_evt() {
-                if let Err(e) = evt.write(1) {
-                    error!("serial: failed to write interrupt eventfd ({:?}).", e);
-                }
-                return;
+            if let Err(e) = self.base.inject_interrupt() {
+                error!("serial: failed to update iir ({:?}).", e);
             }
-            error!("serial: failed to update iir.");
         }
     }
 
@@ -401,7 +398,7 @@ impl SysBusDevOps for Serial {
         let mut irq: i32 = -1;
         if let Some(e) = self.interrupt_evt() {
             irq = UART_IRQ;
-            KVM_FDS.load().register_irqfd(&e, irq as u32)?;
+            hypervisor().register_irqfd(&e, irq as u32)?;
         }
         Ok(irq)
     }
@@ -480,6 +477,7 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            mux: false,
         };
         let mut usart = Serial::new(SerialConfig {
             chardev: chardev_cfg.clone(),
@@ -535,6 +533,7 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            mux: false,
         };
         let mut usart = Serial::new(SerialConfig {
             chardev: chardev_cfg,
@@ -13,22 +13,30 @@
 //! Interfaces for simulating various devices.
 //!
 //! This crate simulates:
-//! - interrupt controller (aarch64)
+//! - interrupt controller
 //! - legacy devices, such as serial devices
 
 pub mod acpi;
+pub mod ahci;
 #[cfg(feature = "usb_camera")]
 pub mod camera_backend;
+pub mod interrupt_stats;
 pub mod legacy;
 pub mod misc;
+pub mod net;
+pub mod nvme;
 pub mod pci;
 pub mod scsi;
 pub mod sysbus;
 pub mod usb;
 
-#[cfg(target_arch = "aarch64")]
 mod interrupt_controller;
 
+#[cfg(target_arch = "x86_64")]
+pub use interrupt_controller::{
+    gsi_irq_handler, IoApic, IoApicRouteHandler, MsiHandler, Pic, PicOutputHandler,
+    IOAPIC_NUM_PINS, IOAPIC_REGION_SIZE,
+};
 #[cfg(target_arch = "aarch64")]
 pub use interrupt_controller::{
     ICGICConfig, ICGICv2Config, ICGICv3Config, InterruptController, InterruptError as IntCtrlErrs,
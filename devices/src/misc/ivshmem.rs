@@ -15,7 +15,8 @@ use std::sync::{
     Arc, Mutex, Weak,
 };
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use log::warn;
 
 use crate::pci::{
     config::{
@@ -25,7 +26,8 @@ use crate::pci::{
     le_write_u16, PciBus, PciDevBase, PciDevOps,
 };
 use crate::{Device, DeviceBase};
-use address_space::{GuestAddress, Region, RegionOps};
+use address_space::{FileBackend, GuestAddress, HostMemMapping, Region, RegionOps};
+use machine_manager::config::IvshmemConfig;
 
 const PCI_VENDOR_ID_IVSHMEM: u16 = PCI_VENDOR_ID_REDHAT_QUMRANET;
 const PCI_DEVICE_ID_IVSHMEM: u16 = 0x1110;
@@ -61,6 +63,54 @@ impl Ivshmem {
         }
     }
 
+    /// Create an ivshmem-plain device, whose shared memory is backed by the host file
+    /// of the memory backend.
+    pub fn new_plain(
+        cfg: &IvshmemConfig,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+    ) -> Result<Self> {
+        let mem_path = cfg
+            .mem_cfg
+            .mem_path
+            .as_ref()
+            .with_context(|| format!("Ivshmem {} is not backed by file", cfg.id))?;
+        let file_back = FileBackend::new_mem(mem_path, cfg.mem_cfg.size)
+            .with_context(|| format!("Failed to open shared memory file {}", mem_path))?;
+        let host_mmap = Arc::new(HostMemMapping::new(
+            GuestAddress(0),
+            None,
+            cfg.mem_cfg.size,
+            Some(file_back),
+            false,
+            true,
+            cfg.readonly,
+        )?);
+
+        let ram_mem_region = if cfg.readonly {
+            // The guest writes trap to the ops because the memory slot is read only in KVM,
+            // drop them so that the shared memory is never changed by this VM.
+            let id = cfg.id.clone();
+            let read = move |_: &mut [u8], _: GuestAddress, _: u64| -> bool { true };
+            let write = move |_: &[u8], _: GuestAddress, offset: u64| -> bool {
+                warn!(
+                    "Write to the read only shared memory of ivshmem {} at offset 0x{:x}",
+                    id, offset
+                );
+                true
+            };
+            let ops = RegionOps {
+                read: Arc::new(read),
+                write: Arc::new(write),
+            };
+            Region::init_rom_device_region(host_mmap, ops, "IvshmemRom")
+        } else {
+            Region::init_ram_region(host_mmap, "IvshmemRam")
+        };
+
+        Ok(Self::new(cfg.id.clone(), devfn, parent_bus, ram_mem_region))
+    }
+
     fn register_bars(&mut self) -> Result<()> {
         // Currently, ivshmem uses only the shared memory and does not use interrupt.
         // Therefore, bar0 read and write callback is not implemented.
@@ -150,6 +200,11 @@ impl PciDevOps for Ivshmem {
         Ok(())
     }
 
+    fn unrealize(&mut self) -> Result<()> {
+        let bus = self.base.parent_bus.upgrade().unwrap();
+        self.base.config.unregister_bars(&bus)
+    }
+
     fn write_config(&mut self, offset: usize, data: &[u8]) {
         let parent_bus = self.base.parent_bus.upgrade().unwrap();
         let locked_parent_bus = parent_bus.lock().unwrap();
@@ -13,5 +13,6 @@
 #[cfg(feature = "scream")]
 pub mod scream;
 
-#[cfg(feature = "scream")]
-mod ivshmem;
+pub mod ivshmem;
+
+pub mod watchdog;
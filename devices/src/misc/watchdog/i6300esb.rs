@@ -0,0 +1,471 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{
+    atomic::{AtomicU16, Ordering},
+    Arc, Mutex, Weak,
+};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::error;
+
+use super::{WatchdogActionTrigger, WatchdogTimer};
+use crate::pci::{
+    config::{
+        PciConfig, RegionType, DEVICE_ID, PCI_CLASS_SYSTEM_OTHER, PCI_CONFIG_SPACE_SIZE,
+        SUB_CLASS_CODE, VENDOR_ID,
+    },
+    le_write_u16, PciBus, PciDevBase, PciDevOps,
+};
+use crate::{Device, DeviceBase};
+use address_space::{GuestAddress, Region, RegionOps};
+use util::num_ops::{read_data_u32, write_data_u32};
+
+const PCI_VENDOR_ID_INTEL: u16 = 0x8086;
+const PCI_DEVICE_ID_ESB_9: u16 = 0x25ab;
+
+const ESB_BAR_SIZE: u64 = 0x10;
+
+/// Registers in PCI configuration space.
+const ESB_CONFIG_REG: usize = 0x60;
+const ESB_LOCK_REG: usize = 0x68;
+
+/// Memory mapped registers in bar0.
+const ESB_TIMER1_REG: u64 = 0x00;
+const ESB_TIMER2_REG: u64 = 0x04;
+const ESB_RELOAD_REG: u64 = 0x0c;
+
+/// Bits of lock register.
+const ESB_WDT_FUNC: u8 = 0x1 << 2;
+const ESB_WDT_ENABLE: u8 = 0x1 << 1;
+const ESB_WDT_LOCK: u8 = 0x1 << 0;
+
+/// Bits of config register.
+const ESB_WDT_REBOOT: u16 = 0x1 << 5;
+const ESB_WDT_FREQ: u16 = 0x1 << 2;
+const ESB_WDT_INTTYPE: u16 = 0x3;
+
+/// Bits of reload register.
+const ESB_WDT_RELOAD: u32 = 0x1 << 8;
+const ESB_WDT_TIMEOUT: u32 = 0x1 << 9;
+
+/// Magic values to unlock the memory mapped registers.
+const ESB_UNLOCK1: u32 = 0x80;
+const ESB_UNLOCK2: u32 = 0x86;
+
+const ESB_PRELOAD_MASK: u32 = 0xf_ffff;
+/// The timer is driven by the 33MHz PCI clock.
+const ESB_CLOCK_HZ: u128 = 33_000_000;
+
+struct EsbState {
+    /// Whether to take action when stage 2 expires.
+    reboot_enabled: bool,
+    /// The preload value is in units of 2^5 clocks for 1MHz scale, otherwise 2^15 clocks.
+    clock_scale_1mhz: bool,
+    int_type: u16,
+    /// Restart stage 1 after stage 2 expires.
+    free_run: bool,
+    /// The lock register can't be changed once locked until reset.
+    locked: bool,
+    enabled: bool,
+    timer1_preload: u32,
+    timer2_preload: u32,
+    /// Current stage of the timer, 1 or 2.
+    stage: u8,
+    /// Progress of the unlock sequence, the registers are writable when it's 2.
+    unlock_state: u8,
+    /// The watchdog has caused a reboot.
+    previous_reboot_flag: bool,
+    timer: WatchdogTimer,
+    action_trigger: WatchdogActionTrigger,
+}
+
+impl EsbState {
+    fn new(action_trigger: WatchdogActionTrigger) -> Self {
+        let mut state = Self {
+            reboot_enabled: true,
+            clock_scale_1mhz: false,
+            int_type: 0,
+            free_run: false,
+            locked: false,
+            enabled: false,
+            timer1_preload: ESB_PRELOAD_MASK,
+            timer2_preload: ESB_PRELOAD_MASK,
+            stage: 1,
+            unlock_state: 0,
+            previous_reboot_flag: false,
+            timer: WatchdogTimer::default(),
+            action_trigger,
+        };
+        state.reset();
+        state
+    }
+
+    fn reset(&mut self) {
+        self.timer.stop();
+        self.reboot_enabled = true;
+        self.clock_scale_1mhz = false;
+        self.int_type = 0;
+        self.free_run = false;
+        self.locked = false;
+        self.enabled = false;
+        self.timer1_preload = ESB_PRELOAD_MASK;
+        self.timer2_preload = ESB_PRELOAD_MASK;
+        self.stage = 1;
+        self.unlock_state = 0;
+    }
+
+    fn config_reg(&self) -> u16 {
+        let mut value = self.int_type;
+        if !self.reboot_enabled {
+            value |= ESB_WDT_REBOOT;
+        }
+        if self.clock_scale_1mhz {
+            value |= ESB_WDT_FREQ;
+        }
+        value
+    }
+
+    fn lock_reg(&self) -> u8 {
+        let mut value = 0;
+        if self.locked {
+            value |= ESB_WDT_LOCK;
+        }
+        if self.enabled {
+            value |= ESB_WDT_ENABLE;
+        }
+        if self.free_run {
+            value |= ESB_WDT_FUNC;
+        }
+        value
+    }
+
+    fn write_config_reg(&mut self, value: u16) {
+        self.reboot_enabled = value & ESB_WDT_REBOOT == 0;
+        self.clock_scale_1mhz = value & ESB_WDT_FREQ != 0;
+        self.int_type = value & ESB_WDT_INTTYPE;
+    }
+
+    /// Returns whether the timer needs to be started.
+    fn write_lock_reg(&mut self, value: u8) -> bool {
+        if self.locked {
+            return false;
+        }
+        self.locked = value & ESB_WDT_LOCK != 0;
+        self.free_run = value & ESB_WDT_FUNC != 0;
+        let old_enabled = self.enabled;
+        self.enabled = value & ESB_WDT_ENABLE != 0;
+        if !self.enabled {
+            self.timer.stop();
+        }
+        !old_enabled && self.enabled
+    }
+
+    /// Returns whether the timer needs to be restarted from stage 1.
+    fn write_mmio(&mut self, offset: u64, value: u32) -> bool {
+        let mut reload = false;
+        if offset == ESB_RELOAD_REG && value == ESB_UNLOCK1 {
+            self.unlock_state = 1;
+        } else if offset == ESB_RELOAD_REG && value == ESB_UNLOCK2 && self.unlock_state == 1 {
+            self.unlock_state = 2;
+        } else if self.unlock_state == 2 {
+            match offset {
+                ESB_RELOAD_REG => {
+                    reload = value & ESB_WDT_RELOAD != 0;
+                    if value & ESB_WDT_TIMEOUT != 0 {
+                        self.previous_reboot_flag = false;
+                    }
+                }
+                ESB_TIMER1_REG => self.timer1_preload = value & ESB_PRELOAD_MASK,
+                ESB_TIMER2_REG => self.timer2_preload = value & ESB_PRELOAD_MASK,
+                _ => {}
+            }
+            self.unlock_state = 0;
+        }
+        reload
+    }
+
+    fn read_mmio(&self, offset: u64) -> u32 {
+        if offset == ESB_RELOAD_REG && self.previous_reboot_flag {
+            return ESB_WDT_TIMEOUT;
+        }
+        0
+    }
+
+    /// Timeout of current stage.
+    fn timeout(&self) -> Duration {
+        let preload = if self.stage == 1 {
+            self.timer1_preload
+        } else {
+            self.timer2_preload
+        };
+        let shift = if self.clock_scale_1mhz { 5 } else { 15 };
+        let clocks = (preload as u128) << shift;
+        Duration::from_nanos((clocks * 1_000_000_000 / ESB_CLOCK_HZ) as u64)
+    }
+}
+
+/// Restart the timer from the given stage.
+fn restart_timer(state: &Arc<Mutex<EsbState>>, stage: u8) {
+    let mut locked_state = state.lock().unwrap();
+    if !locked_state.enabled {
+        return;
+    }
+    locked_state.stage = stage;
+    let timeout = locked_state.timeout();
+    let weak_state = Arc::downgrade(state);
+    let func = Box::new(move || {
+        if let Some(state) = weak_state.upgrade() {
+            timer_expired(&state);
+        }
+    });
+    locked_state.timer.start(func, timeout);
+}
+
+fn timer_expired(state: &Arc<Mutex<EsbState>>) {
+    let mut locked_state = state.lock().unwrap();
+    locked_state.timer.expire();
+    if locked_state.stage == 1 {
+        // The interrupt of stage 1 is not supported, which is not used by guest driver.
+        drop(locked_state);
+        restart_timer(state, 2);
+        return;
+    }
+
+    if locked_state.reboot_enabled {
+        locked_state.previous_reboot_flag = true;
+        locked_state.action_trigger.trigger();
+        locked_state.reset();
+    }
+    let free_run = locked_state.free_run;
+    drop(locked_state);
+    if free_run {
+        restart_timer(state, 1);
+    }
+}
+
+/// Intel 6300ESB PCI watchdog device.
+pub struct I6300Esb {
+    base: PciDevBase,
+    dev_id: Arc<AtomicU16>,
+    state: Arc<Mutex<EsbState>>,
+}
+
+impl I6300Esb {
+    pub fn new(
+        name: String,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+        action_trigger: WatchdogActionTrigger,
+    ) -> Self {
+        Self {
+            base: PciDevBase {
+                base: DeviceBase::new(name, false),
+                config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 1),
+                devfn,
+                parent_bus,
+            },
+            dev_id: Arc::new(AtomicU16::new(0)),
+            state: Arc::new(Mutex::new(EsbState::new(action_trigger))),
+        }
+    }
+
+    fn register_bar(&mut self) -> Result<()> {
+        let cloned_state = self.state.clone();
+        let read = move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+            write_data_u32(data, cloned_state.lock().unwrap().read_mmio(offset))
+        };
+        let cloned_state = self.state.clone();
+        let write = move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+            let mut value = 0;
+            if !read_data_u32(data, &mut value) {
+                return false;
+            }
+            let reload = cloned_state.lock().unwrap().write_mmio(offset, value);
+            if reload {
+                restart_timer(&cloned_state, 1);
+            }
+            true
+        };
+        let region_ops = RegionOps {
+            read: Arc::new(read),
+            write: Arc::new(write),
+        };
+
+        self.base.config.register_bar(
+            0,
+            Region::init_io_region(ESB_BAR_SIZE, region_ops, "I6300EsbMmio"),
+            RegionType::Mem32Bit,
+            false,
+            ESB_BAR_SIZE,
+        )
+    }
+
+    /// Sync the device specific registers to configuration space.
+    fn update_config(&mut self) {
+        let locked_state = self.state.lock().unwrap();
+        let config_reg = locked_state.config_reg();
+        let lock_reg = locked_state.lock_reg();
+        drop(locked_state);
+
+        if let Err(e) = le_write_u16(&mut self.base.config.config, ESB_CONFIG_REG, config_reg) {
+            error!("Failed to update config register of i6300esb: {:?}", e);
+        }
+        self.base.config.config[ESB_LOCK_REG] = lock_reg;
+    }
+}
+
+impl Device for I6300Esb {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl PciDevOps for I6300Esb {
+    fn pci_base(&self) -> &PciDevBase {
+        &self.base
+    }
+
+    fn pci_base_mut(&mut self) -> &mut PciDevBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<()> {
+        self.init_write_mask(false)?;
+        self.init_write_clear_mask(false)?;
+        le_write_u16(
+            &mut self.base.config.config,
+            VENDOR_ID as usize,
+            PCI_VENDOR_ID_INTEL,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            DEVICE_ID as usize,
+            PCI_DEVICE_ID_ESB_9,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            SUB_CLASS_CODE as usize,
+            PCI_CLASS_SYSTEM_OTHER,
+        )?;
+        self.update_config();
+        self.register_bar()?;
+
+        // Attach to the PCI bus.
+        let pci_bus = self.base.parent_bus.upgrade().unwrap();
+        let mut locked_pci_bus = pci_bus.lock().unwrap();
+        let pci_device = locked_pci_bus.devices.get(&self.base.devfn);
+        match pci_device {
+            Some(device) => bail!(
+                "Devfn {:?} has been used by {:?}",
+                &self.base.devfn,
+                device.lock().unwrap().name()
+            ),
+            None => locked_pci_bus
+                .devices
+                .insert(self.base.devfn, Arc::new(Mutex::new(self))),
+        };
+        Ok(())
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        if offset == ESB_CONFIG_REG && data.len() == 2 {
+            let value = u16::from_le_bytes([data[0], data[1]]);
+            self.state.lock().unwrap().write_config_reg(value);
+            self.update_config();
+            return;
+        }
+        if offset == ESB_LOCK_REG && data.len() == 1 {
+            let start = self.state.lock().unwrap().write_lock_reg(data[0]);
+            if start {
+                restart_timer(&self.state, 1);
+            }
+            self.update_config();
+            return;
+        }
+
+        let parent_bus = self.base.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+
+        self.base.config.write(
+            offset,
+            data,
+            self.dev_id.load(Ordering::Acquire),
+            #[cfg(target_arch = "x86_64")]
+            Some(&locked_parent_bus.io_region),
+            Some(&locked_parent_bus.mem_region),
+        );
+    }
+
+    fn reset(&mut self, _reset_child_device: bool) -> Result<()> {
+        self.state.lock().unwrap().reset();
+        self.update_config();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_manager::config::WatchdogAction;
+
+    #[test]
+    fn test_i6300esb_registers() {
+        let trigger = WatchdogActionTrigger::new(WatchdogAction::Debug, None);
+        let mut state = EsbState::new(trigger);
+        assert_eq!(state.config_reg(), 0);
+        assert_eq!(state.lock_reg(), 0);
+
+        // Registers are not writable before unlocking.
+        assert!(!state.write_mmio(ESB_TIMER1_REG, 0x100));
+        assert_eq!(state.timer1_preload, ESB_PRELOAD_MASK);
+        state.write_mmio(ESB_RELOAD_REG, ESB_UNLOCK1);
+        state.write_mmio(ESB_RELOAD_REG, ESB_UNLOCK2);
+        state.write_mmio(ESB_TIMER1_REG, 0x100);
+        assert_eq!(state.timer1_preload, 0x100);
+        // Unlock is needed for every write.
+        state.write_mmio(ESB_TIMER2_REG, 0x200);
+        assert_eq!(state.timer2_preload, ESB_PRELOAD_MASK);
+        state.write_mmio(ESB_RELOAD_REG, ESB_UNLOCK1);
+        state.write_mmio(ESB_RELOAD_REG, ESB_UNLOCK2);
+        state.write_mmio(ESB_TIMER2_REG, 0x200);
+        assert_eq!(state.timer2_preload, 0x200);
+        state.write_mmio(ESB_RELOAD_REG, ESB_UNLOCK1);
+        state.write_mmio(ESB_RELOAD_REG, ESB_UNLOCK2);
+        assert!(state.write_mmio(ESB_RELOAD_REG, ESB_WDT_RELOAD));
+
+        // 0x100 << 15 clocks of 33MHz.
+        assert_eq!(state.timeout(), Duration::from_nanos(254_200_242));
+        state.write_config_reg(ESB_WDT_REBOOT | ESB_WDT_FREQ);
+        assert!(!state.reboot_enabled);
+        assert_eq!(state.config_reg(), ESB_WDT_REBOOT | ESB_WDT_FREQ);
+        assert_eq!(state.timeout(), Duration::from_nanos(248_242));
+
+        assert!(state.write_lock_reg(ESB_WDT_ENABLE | ESB_WDT_LOCK));
+        assert_eq!(state.lock_reg(), ESB_WDT_ENABLE | ESB_WDT_LOCK);
+        // The lock register can't be changed once locked.
+        assert!(!state.write_lock_reg(0));
+        assert!(state.enabled);
+
+        state.previous_reboot_flag = true;
+        assert_eq!(state.read_mmio(ESB_RELOAD_REG), ESB_WDT_TIMEOUT);
+        state.reset();
+        assert_eq!(state.lock_reg(), 0);
+        assert_eq!(state.timer1_preload, ESB_PRELOAD_MASK);
+        assert!(state.previous_reboot_flag);
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod i6300esb;
+#[cfg(target_arch = "aarch64")]
+mod sbsa_gwdt;
+
+pub use i6300esb::I6300Esb;
+#[cfg(target_arch = "aarch64")]
+pub use sbsa_gwdt::SbsaGwdt;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use machine_manager::config::WatchdogAction;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::{request_shutdown_cause, ShutdownCause};
+
+/// Performs the configured action when the watchdog timer expires.
+#[derive(Clone)]
+pub struct WatchdogActionTrigger {
+    action: WatchdogAction,
+    /// Eventfd of the VM control request for the action, `None` for `debug`.
+    action_evt: Option<Arc<EventFd>>,
+}
+
+impl WatchdogActionTrigger {
+    pub fn new(action: WatchdogAction, action_evt: Option<Arc<EventFd>>) -> Self {
+        Self { action, action_evt }
+    }
+
+    fn trigger(&self) {
+        warn!("Watchdog timer expired, action: {:?}", self.action);
+        if matches!(
+            self.action,
+            WatchdogAction::Reset | WatchdogAction::Shutdown
+        ) {
+            request_shutdown_cause(ShutdownCause::Watchdog);
+        }
+        if let Some(evt) = self.action_evt.as_ref() {
+            if let Err(e) = evt.write(1) {
+                error!("Failed to perform watchdog action: {:?}", e);
+            }
+        }
+    }
+}
+
+/// One-shot timer of watchdog, it runs in the main loop.
+#[derive(Default)]
+struct WatchdogTimer {
+    timer_id: Option<u64>,
+}
+
+impl WatchdogTimer {
+    fn start(&mut self, func: Box<dyn Fn()>, delay: Duration) {
+        self.stop();
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            self.timer_id = Some(ctx.timer_add(func, delay));
+        }
+    }
+
+    /// Must be called at the beginning of the timer callback, the id is invalid after the
+    /// timer fires.
+    fn expire(&mut self) {
+        self.timer_id = None;
+    }
+
+    fn stop(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::error;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::{WatchdogActionTrigger, WatchdogTimer};
+use crate::interrupt_stats::InterruptCounter;
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::AmlBuilder;
+use address_space::GuestAddress;
+use util::num_ops::{read_data_u32, read_u32, write_data_u32, write_u64_high, write_u64_low};
+
+/// Size of the control frame and the refresh frame.
+const SBSA_GWDT_FRAME_SIZE: u64 = 0x1000;
+/// The refresh frame locates after the control frame.
+const SBSA_GWDT_REFRESH_FRAME: u64 = SBSA_GWDT_FRAME_SIZE;
+
+/// Registers in refresh frame.
+const SBSA_GWDT_WRR: u64 = 0x000;
+/// Registers in control frame.
+const SBSA_GWDT_WCS: u64 = 0x000;
+const SBSA_GWDT_WOR: u64 = 0x008;
+const SBSA_GWDT_WORU: u64 = 0x00c;
+const SBSA_GWDT_WCV: u64 = 0x010;
+const SBSA_GWDT_WCVU: u64 = 0x014;
+/// Interface identification register in both frames.
+const SBSA_GWDT_W_IIDR: u64 = 0xfcc;
+
+/// Bits of WCS register.
+const SBSA_GWDT_WCS_EN: u32 = 0x1 << 0;
+const SBSA_GWDT_WCS_WS0: u32 = 0x1 << 1;
+const SBSA_GWDT_WCS_WS1: u32 = 0x1 << 2;
+
+/// The upper 16 bits of WOR are valid.
+const SBSA_GWDT_WORU_MASK: u32 = 0xffff;
+/// Implementer is ARM, architecture version 0.
+const SBSA_GWDT_IIDR: u32 = 0x043b;
+
+/// Get the frequency of the system counter, which is the clock source of the watchdog.
+fn get_cntfrq() -> u64 {
+    let freq: u64;
+    // Safe because reading CNTFRQ_EL0 has no side effect.
+    unsafe { std::arch::asm!("mrs {}, cntfrq_el0", out(reg) freq) };
+    freq
+}
+
+struct GwdtState {
+    /// Control and status register.
+    wcs: u32,
+    /// Offset register, the period of each stage.
+    wor: u64,
+    /// Compare value register.
+    wcv: u64,
+    /// Frequency of the system counter.
+    freq: u64,
+    /// The time when system counter is zero.
+    base_time: Instant,
+    timer: WatchdogTimer,
+    action_trigger: WatchdogActionTrigger,
+    interrupt_evt: Option<Arc<EventFd>>,
+    interrupt_counter: Option<Arc<InterruptCounter>>,
+}
+
+impl GwdtState {
+    fn counter(&self) -> u64 {
+        (self.base_time.elapsed().as_nanos() * self.freq as u128 / 1_000_000_000) as u64
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_nanos((ticks as u128 * 1_000_000_000 / self.freq as u128) as u64)
+    }
+
+    fn reset(&mut self) {
+        self.timer.stop();
+        self.wcs = 0;
+        self.wor = 0;
+        self.wcv = 0;
+    }
+
+    fn inject_interrupt(&self) {
+        if let Some(evt) = self.interrupt_evt.as_ref() {
+            if let Err(e) = evt.write(1) {
+                error!("sbsa-gwdt: failed to write interrupt eventfd ({:?}).", e);
+                return;
+            }
+            if let Some(counter) = self.interrupt_counter.as_ref() {
+                counter.inc();
+            }
+        }
+    }
+}
+
+/// Reload the timer with WOR, the watch signals are cleared for explicit refresh.
+fn refresh_timer(state: &Arc<Mutex<GwdtState>>, explicit: bool) {
+    let mut locked_state = state.lock().unwrap();
+    if explicit {
+        locked_state.wcs &= !(SBSA_GWDT_WCS_WS0 | SBSA_GWDT_WCS_WS1);
+    }
+    if locked_state.wcs & SBSA_GWDT_WCS_EN == 0 {
+        locked_state.timer.stop();
+        return;
+    }
+    locked_state.wcv = locked_state.counter().saturating_add(locked_state.wor);
+    let wor = locked_state.wor;
+    start_timer(state, &mut locked_state, wor);
+}
+
+/// Program the timer to fire when the system counter reaches WCV.
+fn update_wcv(state: &Arc<Mutex<GwdtState>>) {
+    let mut locked_state = state.lock().unwrap();
+    if locked_state.wcs & SBSA_GWDT_WCS_EN == 0 {
+        return;
+    }
+    let ticks = locked_state.wcv.saturating_sub(locked_state.counter());
+    start_timer(state, &mut locked_state, ticks);
+}
+
+fn start_timer(state: &Arc<Mutex<GwdtState>>, locked_state: &mut GwdtState, ticks: u64) {
+    let weak_state = Arc::downgrade(state);
+    let func = Box::new(move || {
+        if let Some(state) = weak_state.upgrade() {
+            timer_expired(&state);
+        }
+    });
+    let delay = locked_state.ticks_to_duration(ticks);
+    locked_state.timer.start(func, delay);
+}
+
+fn timer_expired(state: &Arc<Mutex<GwdtState>>) {
+    let mut locked_state = state.lock().unwrap();
+    locked_state.timer.expire();
+    if locked_state.wcs & SBSA_GWDT_WCS_WS0 == 0 {
+        // The first stage raises the interrupt, then waits for another period.
+        locked_state.wcs |= SBSA_GWDT_WCS_WS0;
+        locked_state.inject_interrupt();
+        drop(locked_state);
+        refresh_timer(state, false);
+        return;
+    }
+
+    locked_state.wcs |= SBSA_GWDT_WCS_WS1;
+    locked_state.action_trigger.trigger();
+}
+
+/// SBSA generic watchdog device, consists of a control frame and a refresh frame.
+pub struct SbsaGwdt {
+    base: SysBusDevBase,
+    state: Arc<Mutex<GwdtState>>,
+}
+
+impl SbsaGwdt {
+    pub fn new(action_trigger: WatchdogActionTrigger) -> Self {
+        Self {
+            base: SysBusDevBase::new(SysBusDevType::SbsaGwdt),
+            state: Arc::new(Mutex::new(GwdtState {
+                wcs: 0,
+                wor: 0,
+                wcv: 0,
+                freq: get_cntfrq(),
+                base_time: Instant::now(),
+                timer: WatchdogTimer::default(),
+                action_trigger,
+                interrupt_evt: None,
+                interrupt_counter: None,
+            })),
+        }
+    }
+
+    pub fn realize(mut self, sysbus: &mut SysBus, region_base: u64) -> Result<()> {
+        let interrupt_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
+        self.base.interrupt_evt = Some(interrupt_evt.clone());
+        self.state.lock().unwrap().interrupt_evt = Some(interrupt_evt);
+        let region_size = 2 * SBSA_GWDT_FRAME_SIZE;
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| "Failed to allocate system resource for sbsa-gwdt.")?;
+        self.state.lock().unwrap().interrupt_counter = self.base.interrupt_counter.clone();
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size, "SbsaGwdt")?;
+        Ok(())
+    }
+
+    fn read_control_frame(&self, offset: u64) -> u32 {
+        let locked_state = self.state.lock().unwrap();
+        match offset {
+            SBSA_GWDT_WCS => locked_state.wcs,
+            SBSA_GWDT_WOR => read_u32(locked_state.wor, 0),
+            SBSA_GWDT_WORU => read_u32(locked_state.wor, 1),
+            SBSA_GWDT_WCV => read_u32(locked_state.wcv, 0),
+            SBSA_GWDT_WCVU => read_u32(locked_state.wcv, 1),
+            SBSA_GWDT_W_IIDR => SBSA_GWDT_IIDR,
+            _ => 0,
+        }
+    }
+
+    fn write_control_frame(&self, offset: u64, value: u32) {
+        let mut locked_state = self.state.lock().unwrap();
+        match offset {
+            SBSA_GWDT_WCS => {
+                locked_state.wcs = value & SBSA_GWDT_WCS_EN;
+                drop(locked_state);
+                refresh_timer(&self.state, true);
+            }
+            SBSA_GWDT_WOR => {
+                locked_state.wor = write_u64_low(locked_state.wor, value);
+                drop(locked_state);
+                refresh_timer(&self.state, true);
+            }
+            SBSA_GWDT_WORU => {
+                locked_state.wor = write_u64_high(locked_state.wor, value & SBSA_GWDT_WORU_MASK);
+                drop(locked_state);
+                refresh_timer(&self.state, true);
+            }
+            SBSA_GWDT_WCV => {
+                locked_state.wcv = write_u64_low(locked_state.wcv, value);
+                drop(locked_state);
+                update_wcv(&self.state);
+            }
+            SBSA_GWDT_WCVU => {
+                locked_state.wcv = write_u64_high(locked_state.wcv, value);
+                drop(locked_state);
+                update_wcv(&self.state);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Device for SbsaGwdt {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for SbsaGwdt {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let value = if offset >= SBSA_GWDT_REFRESH_FRAME {
+            match offset - SBSA_GWDT_REFRESH_FRAME {
+                SBSA_GWDT_W_IIDR => SBSA_GWDT_IIDR,
+                _ => 0,
+            }
+        } else {
+            self.read_control_frame(offset)
+        };
+        write_data_u32(data, value)
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let mut value = 0;
+        if !read_data_u32(data, &mut value) {
+            return false;
+        }
+        if offset >= SBSA_GWDT_REFRESH_FRAME {
+            // Any write to WRR is an explicit refresh.
+            if offset - SBSA_GWDT_REFRESH_FRAME == SBSA_GWDT_WRR {
+                refresh_timer(&self.state, true);
+            }
+        } else {
+            self.write_control_frame(offset, value);
+        }
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.state.lock().unwrap().reset();
+        Ok(())
+    }
+}
+
+impl AmlBuilder for SbsaGwdt {
+    fn aml_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
@@ -0,0 +1,1874 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use log::{error, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::pci::intx::Intx;
+use crate::pci::msix::Msix;
+use address_space::{AddressSpace, GuestAddress};
+use machine_manager::event_loop::EventLoop;
+use util::byte_code::ByteCode;
+
+/// Size of the register space in BAR0.
+pub const E1000E_MMIO_SIZE: u64 = 0x20000;
+/// Number of the msi-x vectors: RxQ0, RxQ1, TxQ0, TxQ1 and Other.
+pub const E1000E_MSIX_VECTORS: u16 = 5;
+
+/// Registers offset in BAR0.
+const E1000_CTRL: usize = 0x0000;
+const E1000_STATUS: usize = 0x0008;
+const E1000_EECD: usize = 0x0010;
+const E1000_EERD: usize = 0x0014;
+const E1000_CTRL_EXT: usize = 0x0018;
+const E1000_MDIC: usize = 0x0020;
+const E1000_VET: usize = 0x0038;
+const E1000_ICR: usize = 0x00C0;
+const E1000_ITR: usize = 0x00C4;
+const E1000_ICS: usize = 0x00C8;
+const E1000_IMS: usize = 0x00D0;
+const E1000_IMC: usize = 0x00D8;
+const E1000_EIAC: usize = 0x00DC;
+const E1000_IAM: usize = 0x00E0;
+const E1000_IVAR: usize = 0x00E4;
+const E1000_EITR: usize = 0x00E8;
+const E1000_RCTL: usize = 0x0100;
+const E1000_TCTL: usize = 0x0400;
+const E1000_EEMNGCTL: usize = 0x1010;
+const E1000_EEWR: usize = 0x102C;
+const E1000_RDBAL: usize = 0x2800;
+const E1000_RDBAH: usize = 0x2804;
+const E1000_RDLEN: usize = 0x2808;
+const E1000_RDH: usize = 0x2810;
+const E1000_RDT: usize = 0x2818;
+const E1000_TDBAL: usize = 0x3800;
+const E1000_TDBAH: usize = 0x3804;
+const E1000_TDLEN: usize = 0x3808;
+const E1000_TDH: usize = 0x3810;
+const E1000_TDT: usize = 0x3818;
+const E1000_STATS_START: usize = 0x4000;
+const E1000_STATS_END: usize = 0x4128;
+const E1000_MPRC: usize = 0x407C;
+const E1000_GPRC: usize = 0x4074;
+const E1000_BPRC: usize = 0x4078;
+const E1000_GPTC: usize = 0x4080;
+const E1000_GORCL: usize = 0x4088;
+const E1000_GOTCL: usize = 0x4090;
+const E1000_TORL: usize = 0x40C0;
+const E1000_TOTL: usize = 0x40C8;
+const E1000_TPR: usize = 0x40D0;
+const E1000_TPT: usize = 0x40D4;
+const E1000_MPTC: usize = 0x40F0;
+const E1000_BPTC: usize = 0x40F4;
+const E1000_RFCTL: usize = 0x5008;
+const E1000_MTA: usize = 0x5200;
+const E1000_RAL: usize = 0x5400;
+const E1000_RAH: usize = 0x5404;
+const E1000_VFTA: usize = 0x5600;
+
+/// Number of the receive address registers.
+const E1000_RAR_ENTRIES: usize = 16;
+
+/// Bits of device control register.
+const E1000_CTRL_FD: u32 = 1 << 0;
+const E1000_CTRL_GIO_MASTER_DISABLE: u32 = 1 << 2;
+const E1000_CTRL_SLU: u32 = 1 << 6;
+const E1000_CTRL_SPD_1000: u32 = 2 << 8;
+const E1000_CTRL_RST: u32 = 1 << 26;
+const E1000_CTRL_VME: u32 = 1 << 30;
+const E1000_CTRL_PHY_RST: u32 = 1 << 31;
+
+/// Bits of device status register.
+const E1000_STATUS_FD: u32 = 1 << 0;
+const E1000_STATUS_LU: u32 = 1 << 1;
+const E1000_STATUS_SPEED_1000: u32 = 2 << 6;
+const E1000_STATUS_ASDV_1000: u32 = 2 << 8;
+const E1000_STATUS_GIO_MASTER_ENABLE: u32 = 1 << 19;
+
+/// Bits of eeprom control register.
+const E1000_EECD_REQ: u32 = 1 << 6;
+const E1000_EECD_GNT: u32 = 1 << 7;
+const E1000_EECD_PRES: u32 = 1 << 8;
+const E1000_EECD_AUTO_RD: u32 = 1 << 9;
+const E1000_EECD_SIZE_EX_MASK: u32 = 0xF << 11;
+
+/// Bits of eeprom read/write register.
+const E1000_EERW_START: u32 = 1 << 0;
+const E1000_EERW_DONE: u32 = 1 << 1;
+const E1000_EERW_ADDR_SHIFT: u32 = 2;
+const E1000_EERW_ADDR_MASK: u32 = 0x3FFF;
+const E1000_EERW_DATA_SHIFT: u32 = 16;
+
+/// Configuration of the port 0 and 1 is done.
+const E1000_EEMNGCTL_CFG_DONE: u32 = 0x3 << 18;
+
+/// Bits of mdi control register.
+const E1000_MDIC_DATA_MASK: u32 = 0xFFFF;
+const E1000_MDIC_REG_SHIFT: u32 = 16;
+const E1000_MDIC_PHY_SHIFT: u32 = 21;
+const E1000_MDIC_OP_WRITE: u32 = 1 << 26;
+const E1000_MDIC_OP_READ: u32 = 2 << 26;
+const E1000_MDIC_READY: u32 = 1 << 28;
+const E1000_MDIC_INT_EN: u32 = 1 << 29;
+const E1000_MDIC_ERROR: u32 = 1 << 30;
+
+/// Bits of extended device control register.
+const E1000_CTRL_EXT_IAME: u32 = 1 << 27;
+
+/// Interrupt causes.
+const E1000_ICR_TXDW: u32 = 1 << 0;
+const E1000_ICR_TXQE: u32 = 1 << 1;
+const E1000_ICR_LSC: u32 = 1 << 2;
+const E1000_ICR_RXDMT0: u32 = 1 << 4;
+const E1000_ICR_RXO: u32 = 1 << 6;
+const E1000_ICR_RXT0: u32 = 1 << 7;
+const E1000_ICR_MDAC: u32 = 1 << 9;
+const E1000_ICR_SRPD: u32 = 1 << 16;
+const E1000_ICR_ACK: u32 = 1 << 17;
+const E1000_ICR_MNG: u32 = 1 << 18;
+const E1000_ICR_RXQ0: u32 = 1 << 20;
+const E1000_ICR_RXQ1: u32 = 1 << 21;
+const E1000_ICR_TXQ0: u32 = 1 << 22;
+const E1000_ICR_TXQ1: u32 = 1 << 23;
+const E1000_ICR_OTHER: u32 = 1 << 24;
+const E1000_ICR_ASSERTED: u32 = 1 << 31;
+/// Causes reported through the Other cause in msi-x mode.
+const E1000_ICR_OTHER_CAUSES: u32 =
+    E1000_ICR_LSC | E1000_ICR_RXO | E1000_ICR_MDAC | E1000_ICR_SRPD | E1000_ICR_ACK | E1000_ICR_MNG;
+
+/// Each entry of IVAR is 4 bits, bit 3 is valid bit and bit 0~2 is the vector.
+const E1000_IVAR_ENTRY_VALID: u32 = 0x8;
+const E1000_IVAR_ENTRY_VEC_MASK: u32 = 0x7;
+
+/// Bits of receive control register.
+const E1000_RCTL_EN: u32 = 1 << 1;
+const E1000_RCTL_UPE: u32 = 1 << 3;
+const E1000_RCTL_MPE: u32 = 1 << 4;
+const E1000_RCTL_RDMTS_SHIFT: u32 = 8;
+const E1000_RCTL_DTYP_MASK: u32 = 0x3 << 10;
+const E1000_RCTL_MO_SHIFT: u32 = 12;
+const E1000_RCTL_BAM: u32 = 1 << 15;
+const E1000_RCTL_BSIZE_SHIFT: u32 = 16;
+const E1000_RCTL_VFE: u32 = 1 << 18;
+const E1000_RCTL_BSEX: u32 = 1 << 25;
+const E1000_RCTL_SECRC: u32 = 1 << 26;
+
+/// Extended receive descriptors are used.
+const E1000_RFCTL_EXTEN: u32 = 1 << 15;
+
+/// Bits of transmit control register.
+const E1000_TCTL_EN: u32 = 1 << 1;
+
+/// Address valid bit of RAH.
+const E1000_RAH_AV: u32 = 1 << 31;
+
+/// Status bits of the receive descriptor.
+const E1000_RXD_STAT_DD: u32 = 1 << 0;
+const E1000_RXD_STAT_EOP: u32 = 1 << 1;
+const E1000_RXD_STAT_IXSM: u32 = 1 << 2;
+const E1000_RXD_STAT_VP: u32 = 1 << 3;
+
+/// Command bits of the transmit descriptor.
+const E1000_TXD_DTYP_D: u32 = 1 << 20;
+const E1000_TXD_CMD_EOP: u32 = 1 << 24;
+const E1000_TXD_CMD_IC: u32 = 1 << 26;
+const E1000_TXD_CMD_TCP: u32 = 1 << 24;
+const E1000_TXD_CMD_IP: u32 = 1 << 25;
+const E1000_TXD_CMD_TSE: u32 = 1 << 26;
+const E1000_TXD_CMD_RS: u32 = 1 << 27;
+const E1000_TXD_CMD_RPS: u32 = 1 << 28;
+const E1000_TXD_CMD_DEXT: u32 = 1 << 29;
+const E1000_TXD_CMD_VLE: u32 = 1 << 30;
+/// Status bits of the transmit descriptor.
+const E1000_TXD_STAT_DD: u32 = 1 << 0;
+/// Packet options of the data descriptor.
+const E1000_TXD_POPTS_IXSM: u8 = 1 << 0;
+const E1000_TXD_POPTS_TXSM: u8 = 1 << 1;
+
+/// PHY registers.
+const PHY_CTRL: usize = 0x00;
+const PHY_STATUS: usize = 0x01;
+const PHY_ID1: usize = 0x02;
+const PHY_ID2: usize = 0x03;
+const PHY_AUTONEG_ADV: usize = 0x04;
+const PHY_LP_ABILITY: usize = 0x05;
+const PHY_AUTONEG_EXP: usize = 0x06;
+const PHY_1000T_CTRL: usize = 0x09;
+const PHY_1000T_STATUS: usize = 0x0A;
+const PHY_EXT_STATUS: usize = 0x0F;
+const PHY_SPEC_CTRL: usize = 0x10;
+const PHY_SPEC_STATUS: usize = 0x11;
+const PHY_PAGE_SELECT: usize = 0x1F;
+/// Registers below it are the same on all pages.
+const PHY_MAX_MULTI_PAGE_REG: usize = 0x0F;
+const PHY_PAGE_SHIFT: u32 = 5;
+const PHY_REGS: usize = 32;
+const PHY_PAGES: usize = 8;
+/// The only phy address which is present on MDIO bus.
+const PHY_ADDR: u32 = 1;
+
+/// Bits of PHY control register.
+const PHY_CTRL_RESTART_AUTONEG: u16 = 1 << 9;
+const PHY_CTRL_RESET: u16 = 1 << 15;
+
+/// Registers which are read only in PHY page 0.
+const PHY_READ_ONLY_REGS: [usize; 8] = [
+    PHY_STATUS,
+    PHY_ID1,
+    PHY_ID2,
+    PHY_LP_ABILITY,
+    PHY_AUTONEG_EXP,
+    PHY_1000T_STATUS,
+    PHY_EXT_STATUS,
+    PHY_SPEC_STATUS,
+];
+
+/// Words of eeprom.
+const EEPROM_WORDS: usize = 64;
+/// Word index of the eeprom checksum.
+const EEPROM_CHECKSUM_REG: usize = 0x3F;
+/// The sum of the words 0x00~0x3F should be this value.
+const EEPROM_SUM: u16 = 0xBABA;
+
+/// Eeprom content of 82574L, the first three words are replaced by the mac address and
+/// the last word is the checksum.
+const EEPROM_TEMPLATE: [u16; EEPROM_WORDS] = [
+    0x0000, 0x0000, 0x0000, 0x0420, 0xf746, 0x2010, 0xffff, 0xffff, // 0x00
+    0x0000, 0x0000, 0x026b, 0x0000, 0x8086, 0x0000, 0x0000, 0x8058, // 0x08
+    0x0000, 0x2001, 0x7e7c, 0xffff, 0x1000, 0x00c8, 0x0000, 0x2704, // 0x10
+    0x6cc9, 0x3150, 0x070e, 0x460b, 0x2d84, 0x0100, 0xf000, 0x0706, // 0x18
+    0x6000, 0x0080, 0x0f04, 0x7fff, 0x4f01, 0xc600, 0x0000, 0x20ff, // 0x20
+    0x0028, 0x0003, 0x0000, 0x0000, 0x0000, 0x0003, 0x0000, 0xffff, // 0x28
+    0x0100, 0xc000, 0x121c, 0xc007, 0xffff, 0xffff, 0xffff, 0xffff, // 0x30
+    0xffff, 0x0000, 0x0000, 0x0000, 0xffff, 0xffff, 0x0000, 0x0000, // 0x38
+];
+
+/// Ether type of 802.1Q.
+const ETH_P_VLAN: u32 = 0x8100;
+const ETH_ALEN: usize = 6;
+const ETH_HLEN: usize = 14;
+const VLAN_HLEN: usize = 4;
+/// Frames shorter than it are padded.
+const ETH_ZLEN: usize = 60;
+const ETH_FCS_LEN: usize = 4;
+const IPV6_HLEN: usize = 40;
+/// Max size of the packet assembled from the transmit descriptors.
+const E1000_TX_MAX_SIZE: usize = 0x10000;
+
+/// The throttling interval of ITR and EITR is in units of 256ns.
+const E1000_ITR_UNIT_NS: u64 = 256;
+const E1000_ITR_INTERVAL_MASK: u32 = 0xFFFF;
+/// Throttling timers, one for each msi-x vector and the last one for INTx.
+const E1000_THROTTLES: usize = E1000E_MSIX_VECTORS as usize + 1;
+const E1000_LEGACY_THROTTLE: usize = E1000E_MSIX_VECTORS as usize;
+
+/// The layout of all kinds of descriptors is 16 bytes: a 64 bits buffer address
+/// (or the context fields) and two 32 bits fields.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct E1000eDesc {
+    buffer_addr: u64,
+    lower: u32,
+    upper: u32,
+}
+
+impl ByteCode for E1000eDesc {}
+
+/// Offload properties loaded from the transmit context descriptor.
+#[derive(Copy, Clone, Debug, Default)]
+struct E1000eTxContext {
+    /// IP checksum start, offset and end.
+    ipcss: u8,
+    ipcso: u8,
+    ipcse: u16,
+    /// TCP/UDP checksum start, offset and end.
+    tucss: u8,
+    tucso: u8,
+    tucse: u16,
+    /// Payload length of the whole tcp segmentation.
+    paylen: u32,
+    hdr_len: u8,
+    mss: u16,
+    /// IPv4 or IPv6.
+    ip: bool,
+    /// TCP or UDP.
+    tcp: bool,
+    tse: bool,
+}
+
+impl E1000eTxContext {
+    fn from_desc(desc: &E1000eDesc) -> Self {
+        let fields = desc.buffer_addr;
+        Self {
+            ipcss: fields as u8,
+            ipcso: (fields >> 8) as u8,
+            ipcse: (fields >> 16) as u16,
+            tucss: (fields >> 32) as u8,
+            tucso: (fields >> 40) as u8,
+            tucse: (fields >> 48) as u16,
+            paylen: desc.lower & 0xF_FFFF,
+            hdr_len: (desc.upper >> 8) as u8,
+            mss: (desc.upper >> 16) as u16,
+            ip: desc.lower & E1000_TXD_CMD_IP != 0,
+            tcp: desc.lower & E1000_TXD_CMD_TCP != 0,
+            tse: desc.lower & E1000_TXD_CMD_TSE != 0,
+        }
+    }
+}
+
+/// State of the packet being assembled from the transmit descriptors.
+#[derive(Default)]
+struct E1000eTx {
+    /// Properties for the normal packets.
+    props: E1000eTxContext,
+    /// Properties for the tcp segmentation.
+    tso_props: E1000eTxContext,
+    data: Vec<u8>,
+    /// Headers copied to the beginning of every segment.
+    header: Vec<u8>,
+    /// Packet options of the first data descriptor.
+    sum_needed: u8,
+    vlan_needed: bool,
+    vlan_tci: u16,
+    /// The tcp segmentation is in progress.
+    cptse: bool,
+    /// Number of the segments sent.
+    tso_frames: u16,
+    /// Checksum start and offset of the legacy descriptor.
+    legacy_sum: Option<(usize, usize)>,
+}
+
+impl E1000eTx {
+    fn clear_packet(&mut self) {
+        self.data.clear();
+        self.sum_needed = 0;
+        self.vlan_needed = false;
+        self.cptse = false;
+        self.tso_frames = 0;
+        self.legacy_sum = None;
+    }
+}
+
+#[derive(Default)]
+struct E1000eThrottle {
+    timer_id: Option<u64>,
+    /// An interrupt is postponed until the timer expires.
+    pending: bool,
+}
+
+/// Emulation of the registers, the eeprom and the phy of Intel 82574L, and the
+/// descriptor rings processing.
+pub struct E1000eCore {
+    /// Registers in BAR0, indexed by the dword offset.
+    mac: Vec<u32>,
+    phy: [[u16; PHY_REGS]; PHY_PAGES],
+    phy_page: usize,
+    eeprom: [u16; EEPROM_WORDS],
+    mac_addr: [u8; ETH_ALEN],
+    mem_space: Arc<AddressSpace>,
+    tx: E1000eTx,
+    /// Causes which have been sent through msi-x and not acknowledged.
+    msix_causes_pending: u32,
+    throttles: [E1000eThrottle; E1000_THROTTLES],
+    msix: Option<Arc<Mutex<Msix>>>,
+    intx: Option<Arc<Mutex<Intx>>>,
+    dev_id: Arc<AtomicU16>,
+    /// Notify the io handler to receive packets after receive buffers are added.
+    rx_kick: Arc<EventFd>,
+    /// Notify the io handler to transmit packets.
+    tx_kick: Arc<EventFd>,
+    iothread: Option<String>,
+    self_ref: Weak<Mutex<E1000eCore>>,
+}
+
+impl E1000eCore {
+    pub fn new(
+        mac_addr: [u8; ETH_ALEN],
+        mem_space: &Arc<AddressSpace>,
+        rx_kick: Arc<EventFd>,
+        tx_kick: Arc<EventFd>,
+        iothread: Option<String>,
+    ) -> Arc<Mutex<Self>> {
+        let mut eeprom = EEPROM_TEMPLATE;
+        for i in 0..3 {
+            eeprom[i] = u16::from_le_bytes([mac_addr[2 * i], mac_addr[2 * i + 1]]);
+        }
+        let sum = eeprom[..EEPROM_CHECKSUM_REG]
+            .iter()
+            .fold(0_u16, |sum, word| sum.wrapping_add(*word));
+        eeprom[EEPROM_CHECKSUM_REG] = EEPROM_SUM.wrapping_sub(sum);
+
+        let core = Arc::new(Mutex::new(Self {
+            mac: vec![0; E1000E_MMIO_SIZE as usize / 4],
+            phy: [[0; PHY_REGS]; PHY_PAGES],
+            phy_page: 0,
+            eeprom,
+            mac_addr,
+            mem_space: mem_space.clone(),
+            tx: E1000eTx::default(),
+            msix_causes_pending: 0,
+            throttles: Default::default(),
+            msix: None,
+            intx: None,
+            dev_id: Arc::new(AtomicU16::new(0)),
+            rx_kick,
+            tx_kick,
+            iothread,
+            self_ref: Weak::new(),
+        }));
+        let mut locked_core = core.lock().unwrap();
+        locked_core.self_ref = Arc::downgrade(&core);
+        locked_core.reset();
+        drop(locked_core);
+        core
+    }
+
+    pub fn set_interrupts(
+        &mut self,
+        msix: Option<Arc<Mutex<Msix>>>,
+        intx: Option<Arc<Mutex<Intx>>>,
+        dev_id: Arc<AtomicU16>,
+    ) {
+        self.msix = msix;
+        self.intx = intx;
+        self.dev_id = dev_id;
+    }
+
+    fn reg(&self, offset: usize) -> u32 {
+        self.mac[offset >> 2]
+    }
+
+    fn reg_mut(&mut self, offset: usize) -> &mut u32 {
+        &mut self.mac[offset >> 2]
+    }
+
+    pub fn reset(&mut self) {
+        self.stop_throttle_timers();
+        self.mac.fill(0);
+        *self.reg_mut(E1000_CTRL) = E1000_CTRL_FD | E1000_CTRL_SLU | E1000_CTRL_SPD_1000;
+        *self.reg_mut(E1000_EECD) = E1000_EECD_PRES | E1000_EECD_AUTO_RD;
+        *self.reg_mut(E1000_EERD) = E1000_EERW_DONE;
+        *self.reg_mut(E1000_EEMNGCTL) = E1000_EEMNGCTL_CFG_DONE;
+        *self.reg_mut(E1000_VET) = ETH_P_VLAN;
+        // The receive address 0 is loaded from eeprom.
+        let mac = self.mac_addr;
+        *self.reg_mut(E1000_RAL) = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+        *self.reg_mut(E1000_RAH) = u32::from_le_bytes([mac[4], mac[5], 0, 0]) | E1000_RAH_AV;
+        self.phy_reset();
+        self.tx = E1000eTx::default();
+        self.msix_causes_pending = 0;
+        self.set_intx(false);
+    }
+
+    pub fn unrealize(&mut self) {
+        self.stop_throttle_timers();
+    }
+
+    fn phy_reset(&mut self) {
+        self.phy = [[0; PHY_REGS]; PHY_PAGES];
+        self.phy_page = 0;
+        let page0 = &mut self.phy[0];
+        page0[PHY_CTRL] = 0x1140;
+        // Link is up and auto negotiation is complete.
+        page0[PHY_STATUS] = 0x796D;
+        page0[PHY_ID1] = 0x0141;
+        page0[PHY_ID2] = 0x0CB1;
+        page0[PHY_AUTONEG_ADV] = 0x0DE1;
+        page0[PHY_LP_ABILITY] = 0x45E1;
+        page0[PHY_AUTONEG_EXP] = 0x0005;
+        page0[PHY_1000T_CTRL] = 0x0300;
+        page0[PHY_1000T_STATUS] = 0x3C00;
+        page0[PHY_EXT_STATUS] = 0x3000;
+        page0[PHY_SPEC_CTRL] = 0x0360;
+        // 1000Mb/s, full duplex, speed and duplex resolved, link up.
+        page0[PHY_SPEC_STATUS] = 0xAC00;
+    }
+
+    fn phy_locate(&self, reg: usize) -> (usize, usize) {
+        if reg <= PHY_MAX_MULTI_PAGE_REG || reg == PHY_PAGE_SELECT {
+            (0, reg)
+        } else {
+            (self.phy_page, reg)
+        }
+    }
+
+    fn phy_read(&self, reg: usize) -> u16 {
+        if reg == PHY_PAGE_SELECT {
+            return (self.phy_page as u16) << PHY_PAGE_SHIFT;
+        }
+        let (page, reg) = self.phy_locate(reg);
+        self.phy.get(page).map_or(0, |regs| regs[reg])
+    }
+
+    fn phy_write(&mut self, reg: usize, val: u16) {
+        if reg == PHY_PAGE_SELECT {
+            self.phy_page = (val >> PHY_PAGE_SHIFT) as usize;
+            return;
+        }
+        let (page, reg) = self.phy_locate(reg);
+        if page == 0 {
+            if PHY_READ_ONLY_REGS.contains(&reg) {
+                return;
+            }
+            if reg == PHY_CTRL {
+                if val & PHY_CTRL_RESET != 0 {
+                    self.phy_reset();
+                    return;
+                }
+                // Auto negotiation completes immediately.
+                self.phy[0][PHY_CTRL] = val & !PHY_CTRL_RESTART_AUTONEG;
+                return;
+            }
+        }
+        if let Some(regs) = self.phy.get_mut(page) {
+            regs[reg] = val;
+        }
+    }
+
+    fn set_mdic(&mut self, val: u32) {
+        let mut val = val;
+        let reg = ((val >> E1000_MDIC_REG_SHIFT) & 0x1F) as usize;
+        let phy_addr = (val >> E1000_MDIC_PHY_SHIFT) & 0x1F;
+        if phy_addr != PHY_ADDR {
+            val |= E1000_MDIC_ERROR;
+        } else if val & E1000_MDIC_OP_READ != 0 {
+            val = (val & !E1000_MDIC_DATA_MASK) | self.phy_read(reg) as u32;
+        } else if val & E1000_MDIC_OP_WRITE != 0 {
+            self.phy_write(reg, (val & E1000_MDIC_DATA_MASK) as u16);
+        }
+        *self.reg_mut(E1000_MDIC) = val | E1000_MDIC_READY;
+        if val & E1000_MDIC_INT_EN != 0 {
+            self.set_interrupt_cause(E1000_ICR_MDAC);
+        }
+    }
+
+    fn set_eerd(&mut self, val: u32) {
+        let addr = (val >> E1000_EERW_ADDR_SHIFT) & E1000_EERW_ADDR_MASK;
+        let mut flags = 0;
+        let mut data = 0;
+        if val & E1000_EERW_START != 0 && (addr as usize) < EEPROM_WORDS {
+            data = self.eeprom[addr as usize] as u32;
+            flags = E1000_EERW_DONE;
+        }
+        *self.reg_mut(E1000_EERD) =
+            flags | (addr << E1000_EERW_ADDR_SHIFT) | (data << E1000_EERW_DATA_SHIFT);
+    }
+
+    fn status(&self) -> u32 {
+        let mut status =
+            E1000_STATUS_FD | E1000_STATUS_LU | E1000_STATUS_SPEED_1000 | E1000_STATUS_ASDV_1000;
+        if self.reg(E1000_CTRL) & E1000_CTRL_GIO_MASTER_DISABLE == 0 {
+            status |= E1000_STATUS_GIO_MASTER_ENABLE;
+        }
+        status
+    }
+
+    /// Read the registers in BAR0, the access is in 1, 2 or 4 bytes.
+    pub fn read_reg(&mut self, offset: u64, data: &mut [u8]) -> bool {
+        if !matches!(data.len(), 1 | 2 | 4) || offset + data.len() as u64 > E1000E_MMIO_SIZE {
+            error!(
+                "Invalid e1000e register read, offset {:#x}, len {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        let aligned = (offset & !3) as usize;
+        let shift = (offset & 3) as usize;
+        if shift + data.len() > 4 {
+            error!("Unaligned e1000e register read, offset {:#x}", offset);
+            return false;
+        }
+        let val = self.read_mac(aligned).to_le_bytes();
+        data.copy_from_slice(&val[shift..shift + data.len()]);
+        true
+    }
+
+    /// Write the registers in BAR0, the access is in 1, 2 or 4 bytes.
+    pub fn write_reg(&mut self, offset: u64, data: &[u8]) -> bool {
+        if !matches!(data.len(), 1 | 2 | 4) || offset + data.len() as u64 > E1000E_MMIO_SIZE {
+            error!(
+                "Invalid e1000e register write, offset {:#x}, len {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        let aligned = (offset & !3) as usize;
+        let shift = (offset & 3) as usize;
+        if shift + data.len() > 4 {
+            error!("Unaligned e1000e register write, offset {:#x}", offset);
+            return false;
+        }
+        let mut val = self.reg(aligned).to_le_bytes();
+        val[shift..shift + data.len()].copy_from_slice(data);
+        self.write_mac(aligned, u32::from_le_bytes(val));
+        true
+    }
+
+    fn read_mac(&mut self, offset: usize) -> u32 {
+        match offset {
+            E1000_STATUS => self.status(),
+            E1000_EECD => {
+                let eecd = self.reg(E1000_EECD);
+                if eecd & E1000_EECD_REQ != 0 {
+                    eecd | E1000_EECD_GNT
+                } else {
+                    eecd
+                }
+            }
+            E1000_ICR => self.read_icr(),
+            E1000_ICS => self.reg(E1000_ICR),
+            E1000_IMC => 0,
+            E1000_STATS_START..=E1000_STATS_END => {
+                // Statistics registers are cleared on read.
+                let val = self.reg(offset);
+                *self.reg_mut(offset) = 0;
+                val
+            }
+            _ => self.reg(offset),
+        }
+    }
+
+    fn write_mac(&mut self, offset: usize, val: u32) {
+        match offset {
+            E1000_CTRL => {
+                if val & E1000_CTRL_RST != 0 {
+                    self.reset();
+                    return;
+                }
+                if val & E1000_CTRL_PHY_RST != 0 {
+                    self.phy_reset();
+                }
+                *self.reg_mut(E1000_CTRL) = val;
+            }
+            E1000_STATUS | E1000_STATS_START..=E1000_STATS_END => {}
+            E1000_EECD => {
+                let ro_bits = E1000_EECD_PRES | E1000_EECD_AUTO_RD | E1000_EECD_SIZE_EX_MASK;
+                let eecd = self.reg(E1000_EECD);
+                *self.reg_mut(E1000_EECD) = (eecd & ro_bits) | (val & !ro_bits);
+            }
+            E1000_EERD => self.set_eerd(val),
+            // The eeprom is read only.
+            E1000_EEWR => *self.reg_mut(E1000_EEWR) = val | E1000_EERW_DONE,
+            E1000_MDIC => self.set_mdic(val),
+            E1000_ICR => self.write_icr(val),
+            E1000_ICS => self.set_interrupt_cause(val),
+            E1000_IMS => {
+                *self.reg_mut(E1000_IMS) |= val;
+                self.update_interrupt_state();
+            }
+            E1000_IMC => {
+                *self.reg_mut(E1000_IMS) &= !val;
+                self.update_interrupt_state();
+            }
+            E1000_RCTL => {
+                if val & E1000_RCTL_DTYP_MASK != 0 {
+                    warn!("Packet split receive descriptors of e1000e are not supported");
+                }
+                *self.reg_mut(E1000_RCTL) = val;
+                self.kick(true);
+            }
+            E1000_RDBAL | E1000_TDBAL => *self.reg_mut(offset) = val & !0xF,
+            E1000_RDLEN | E1000_TDLEN => *self.reg_mut(offset) = val & 0xF_FF80,
+            E1000_RDH | E1000_TDH => *self.reg_mut(offset) = val & 0xFFFF,
+            E1000_RDT => {
+                *self.reg_mut(E1000_RDT) = val & 0xFFFF;
+                self.kick(true);
+            }
+            E1000_TDT => {
+                *self.reg_mut(E1000_TDT) = val & 0xFFFF;
+                self.kick(false);
+            }
+            E1000_TCTL => {
+                *self.reg_mut(E1000_TCTL) = val;
+                self.kick(false);
+            }
+            _ => *self.reg_mut(offset) = val,
+        }
+    }
+
+    fn kick(&self, rx: bool) {
+        let evt = if rx { &self.rx_kick } else { &self.tx_kick };
+        if let Err(e) = evt.write(1) {
+            error!("Failed to kick e1000e io handler: {:?}", e);
+        }
+    }
+
+    fn msix_enabled(&self) -> bool {
+        self.msix
+            .as_ref()
+            .is_some_and(|msix| msix.lock().unwrap().enabled)
+    }
+
+    fn set_intx(&self, level: bool) {
+        if let Some(intx) = self.intx.as_ref() {
+            intx.lock().unwrap().notify(level as u8);
+        }
+    }
+
+    fn msix_notify(&self, vector: u16) {
+        if let Some(msix) = self.msix.as_ref() {
+            msix.lock()
+                .unwrap()
+                .notify(vector, self.dev_id.load(Ordering::Acquire));
+        }
+    }
+
+    fn read_icr(&mut self) -> u32 {
+        let icr = self.reg(E1000_ICR);
+        if self.reg(E1000_IMS) == 0 || !self.msix_enabled() {
+            *self.reg_mut(E1000_ICR) = 0;
+        }
+        if self.reg(E1000_ICR) & E1000_ICR_ASSERTED != 0
+            && self.reg(E1000_CTRL_EXT) & E1000_CTRL_EXT_IAME != 0
+        {
+            *self.reg_mut(E1000_ICR) = 0;
+            let iam = self.reg(E1000_IAM);
+            *self.reg_mut(E1000_IMS) &= !iam;
+        }
+        self.update_interrupt_state();
+        icr
+    }
+
+    fn write_icr(&mut self, val: u32) {
+        if self.reg(E1000_ICR) & E1000_ICR_ASSERTED != 0
+            && self.reg(E1000_CTRL_EXT) & E1000_CTRL_EXT_IAME != 0
+        {
+            let iam = self.reg(E1000_IAM);
+            *self.reg_mut(E1000_IMS) &= !iam;
+        }
+        let mut icr = self.reg(E1000_ICR) & !val;
+        // Clearing the Other cause clears all the causes it represents.
+        if val & E1000_ICR_OTHER != 0 {
+            icr &= !E1000_ICR_OTHER_CAUSES;
+        }
+        *self.reg_mut(E1000_ICR) = icr;
+        self.update_interrupt_state();
+    }
+
+    fn set_interrupt_cause(&mut self, cause: u32) {
+        *self.reg_mut(E1000_ICR) |= cause;
+        self.update_interrupt_state();
+    }
+
+    fn update_interrupt_state(&mut self) {
+        let msix = self.msix_enabled();
+        if msix && self.reg(E1000_ICR) & E1000_ICR_OTHER_CAUSES != 0 {
+            *self.reg_mut(E1000_ICR) |= E1000_ICR_OTHER;
+        }
+        let icr = self.reg(E1000_ICR) & !E1000_ICR_ASSERTED;
+        *self.reg_mut(E1000_ICR) = if icr != 0 {
+            icr | E1000_ICR_ASSERTED
+        } else {
+            0
+        };
+        let icr = self.reg(E1000_ICR);
+        *self.reg_mut(E1000_ICS) = icr;
+
+        let pending = self.reg(E1000_IMS) & icr != 0;
+        if msix {
+            if pending {
+                self.msix_send();
+            }
+        } else if pending {
+            if !self.should_postpone(E1000_LEGACY_THROTTLE) {
+                self.set_intx(true);
+            }
+        } else {
+            self.set_intx(false);
+        }
+    }
+
+    fn msix_send(&mut self) {
+        let mut causes = self.reg(E1000_ICR) & self.reg(E1000_IMS) & !E1000_ICR_ASSERTED;
+        // Each cause is sent once until it is acknowledged.
+        self.msix_causes_pending &= causes;
+        causes ^= self.msix_causes_pending;
+        if causes == 0 {
+            return;
+        }
+        self.msix_causes_pending |= causes;
+
+        let ivar = self.reg(E1000_IVAR);
+        for (cause, shift) in [
+            (E1000_ICR_RXQ0, 0),
+            (E1000_ICR_RXQ1, 4),
+            (E1000_ICR_TXQ0, 8),
+            (E1000_ICR_TXQ1, 12),
+            (E1000_ICR_OTHER, 16),
+        ] {
+            if causes & cause != 0 {
+                self.msix_notify_one(cause, (ivar >> shift) & 0xF);
+            }
+        }
+    }
+
+    fn msix_notify_one(&mut self, cause: u32, int_cfg: u32) {
+        if int_cfg & E1000_IVAR_ENTRY_VALID != 0 {
+            let vector = (int_cfg & E1000_IVAR_ENTRY_VEC_MASK) as u16;
+            if vector < E1000E_MSIX_VECTORS && !self.should_postpone(vector as usize) {
+                self.msix_notify(vector);
+            }
+        }
+        // Auto clear the causes which are set in EIAC.
+        let eiac = self.reg(E1000_EIAC) & cause;
+        *self.reg_mut(E1000_ICR) &= !eiac;
+        self.msix_causes_pending &= !eiac;
+        if self.reg(E1000_CTRL_EXT) & E1000_CTRL_EXT_IAME == 0 {
+            *self.reg_mut(E1000_IMS) &= !eiac;
+        }
+    }
+
+    fn throttle_interval(&self, index: usize) -> u64 {
+        let reg = if index == E1000_LEGACY_THROTTLE {
+            self.reg(E1000_ITR)
+        } else {
+            self.reg(E1000_EITR + index * 4)
+        };
+        (reg & E1000_ITR_INTERVAL_MASK) as u64 * E1000_ITR_UNIT_NS
+    }
+
+    /// Check whether the interrupt should be postponed by the interrupt throttling,
+    /// the throttling timer is started if the interrupt is sent now.
+    fn should_postpone(&mut self, index: usize) -> bool {
+        if self.throttles[index].timer_id.is_some() {
+            self.throttles[index].pending = true;
+            return true;
+        }
+        self.start_throttle_timer(index);
+        false
+    }
+
+    fn start_throttle_timer(&mut self, index: usize) {
+        let interval = self.throttle_interval(index);
+        if interval == 0 {
+            return;
+        }
+        let core = self.self_ref.clone();
+        let func = Box::new(move || {
+            if let Some(core) = core.upgrade() {
+                core.lock().unwrap().throttle_expired(index);
+            }
+        });
+        if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
+            self.throttles[index].timer_id =
+                Some(ctx.timer_add(func, Duration::from_nanos(interval)));
+        }
+    }
+
+    fn stop_throttle_timers(&mut self) {
+        for throttle in self.throttles.iter_mut() {
+            throttle.pending = false;
+            if let Some(timer_id) = throttle.timer_id.take() {
+                if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
+                    ctx.timer_del(timer_id);
+                }
+            }
+        }
+    }
+
+    fn throttle_expired(&mut self, index: usize) {
+        self.throttles[index].timer_id = None;
+        if !self.throttles[index].pending {
+            return;
+        }
+        self.throttles[index].pending = false;
+        if index == E1000_LEGACY_THROTTLE {
+            if self.msix_enabled() || self.reg(E1000_IMS) & self.reg(E1000_ICR) == 0 {
+                return;
+            }
+            self.set_intx(true);
+        } else {
+            self.msix_notify(index as u16);
+        }
+        self.start_throttle_timer(index);
+    }
+
+    fn inc_reg(&mut self, offset: usize) {
+        let reg = self.reg_mut(offset);
+        *reg = reg.saturating_add(1);
+    }
+
+    /// Add the bytes to the 64 bits statistics register.
+    fn grow_reg64(&mut self, offset: usize, len: usize) {
+        let low = self.reg(offset) as u64 | ((self.reg(offset + 4) as u64) << 32);
+        let val = low.saturating_add(len as u64);
+        *self.reg_mut(offset) = val as u32;
+        *self.reg_mut(offset + 4) = (val >> 32) as u32;
+    }
+
+    fn update_stats(&mut self, frame: &[u8], rx: bool) {
+        let (good, total, octets, total_octets, bcast, mcast) = if rx {
+            (
+                E1000_GPRC,
+                E1000_TPR,
+                E1000_GORCL,
+                E1000_TORL,
+                E1000_BPRC,
+                E1000_MPRC,
+            )
+        } else {
+            (
+                E1000_GPTC,
+                E1000_TPT,
+                E1000_GOTCL,
+                E1000_TOTL,
+                E1000_BPTC,
+                E1000_MPTC,
+            )
+        };
+        self.inc_reg(good);
+        self.inc_reg(total);
+        self.grow_reg64(octets, frame.len());
+        self.grow_reg64(total_octets, frame.len());
+        if frame.len() >= ETH_ALEN && frame[0] & 1 != 0 {
+            if frame[..ETH_ALEN] == [0xFF; ETH_ALEN] {
+                self.inc_reg(bcast);
+            } else {
+                self.inc_reg(mcast);
+            }
+        }
+    }
+
+    fn vlan_enabled(&self) -> bool {
+        self.reg(E1000_CTRL) & E1000_CTRL_VME != 0
+    }
+
+    fn is_vlan_packet(&self, frame: &[u8]) -> bool {
+        frame.len() >= ETH_HLEN + VLAN_HLEN
+            && u16::from_be_bytes([frame[12], frame[13]]) as u32 == self.reg(E1000_VET) & 0xFFFF
+    }
+
+    fn ring_base(&self, bal: usize, bah: usize) -> u64 {
+        ((self.reg(bah) as u64) << 32) | self.reg(bal) as u64
+    }
+
+    /// Process the transmit descriptors between TDH and TDT, the packets are sent
+    /// through `send`.
+    pub fn process_tx(&mut self, send: &mut dyn FnMut(&[u8])) {
+        if self.reg(E1000_TCTL) & E1000_TCTL_EN == 0 {
+            return;
+        }
+        let base = self.ring_base(E1000_TDBAL, E1000_TDBAH);
+        let size = self.reg(E1000_TDLEN) / size_of::<E1000eDesc>() as u32;
+        if size == 0 || self.reg(E1000_TDT) >= size {
+            return;
+        }
+
+        let mut cause = E1000_ICR_TXQE;
+        let mut processed = 0;
+        while self.reg(E1000_TDH) != self.reg(E1000_TDT) {
+            let head = self.reg(E1000_TDH);
+            if head >= size || processed >= size {
+                error!("Invalid e1000e transmit ring head {}, size {}", head, size);
+                break;
+            }
+            let addr = base + head as u64 * size_of::<E1000eDesc>() as u64;
+            let desc = match self.mem_space.read_object::<E1000eDesc>(GuestAddress(addr)) {
+                Ok(desc) => desc,
+                Err(e) => {
+                    error!("Failed to read e1000e transmit descriptor: {:?}", e);
+                    break;
+                }
+            };
+            self.process_tx_desc(&desc, send);
+            if desc.lower & (E1000_TXD_CMD_RS | E1000_TXD_CMD_RPS) != 0 {
+                let upper = desc.upper | E1000_TXD_STAT_DD;
+                if let Err(e) = self.mem_space.write_object(&upper, GuestAddress(addr + 12)) {
+                    error!("Failed to write back e1000e transmit descriptor: {:?}", e);
+                }
+                cause |= if self.msix_enabled() {
+                    E1000_ICR_TXQ0
+                } else {
+                    E1000_ICR_TXDW
+                };
+            }
+            *self.reg_mut(E1000_TDH) = (head + 1) % size;
+            processed += 1;
+        }
+        self.set_interrupt_cause(cause);
+    }
+
+    fn process_tx_desc(&mut self, desc: &E1000eDesc, send: &mut dyn FnMut(&[u8])) {
+        let lower = desc.lower;
+        let dtype = lower & (E1000_TXD_CMD_DEXT | E1000_TXD_DTYP_D);
+        let mut split_size;
+        if dtype == E1000_TXD_CMD_DEXT {
+            // Context descriptor.
+            let ctx = E1000eTxContext::from_desc(desc);
+            if ctx.tse {
+                self.tx.tso_props = ctx;
+                self.tx.tso_frames = 0;
+            } else {
+                self.tx.props = ctx;
+            }
+            return;
+        } else if dtype == E1000_TXD_CMD_DEXT | E1000_TXD_DTYP_D {
+            // Data descriptor.
+            if self.tx.data.is_empty() {
+                self.tx.sum_needed = (desc.upper >> 8) as u8;
+            }
+            self.tx.cptse = lower & E1000_TXD_CMD_TSE != 0;
+            split_size = (lower & 0xF_FFFF) as usize;
+        } else {
+            // Legacy descriptor.
+            if self.tx.data.is_empty() && lower & E1000_TXD_CMD_IC != 0 {
+                let css = ((desc.upper >> 8) & 0xFF) as usize;
+                let cso = ((lower >> 16) & 0xFF) as usize;
+                self.tx.legacy_sum = Some((css, cso));
+            }
+            self.tx.cptse = false;
+            split_size = (lower & 0xFFFF) as usize;
+        }
+
+        if self.vlan_enabled()
+            && lower & E1000_TXD_CMD_VLE != 0
+            && (self.tx.cptse || lower & E1000_TXD_CMD_EOP != 0)
+        {
+            self.tx.vlan_needed = true;
+            self.tx.vlan_tci = (desc.upper >> 16) as u16;
+        }
+
+        let mut addr = desc.buffer_addr;
+        if self.tx.cptse {
+            let hdr_len = self.tx.tso_props.hdr_len as usize;
+            let msh = hdr_len + self.tx.tso_props.mss as usize;
+            loop {
+                let size = self.tx.data.len();
+                if size >= msh {
+                    break;
+                }
+                let mut bytes = min(split_size, msh - size);
+                bytes = min(bytes, E1000_TX_MAX_SIZE - size);
+                if !self.read_tx_data(addr, bytes) {
+                    self.tx.clear_packet();
+                    return;
+                }
+                let sz = size + bytes;
+                if sz >= hdr_len && size < hdr_len {
+                    self.tx.header = self.tx.data[..hdr_len].to_vec();
+                }
+                addr += bytes as u64;
+                if sz == msh {
+                    self.xmit_seg(send);
+                    self.tx.data.clear();
+                    self.tx.data.extend_from_slice(&self.tx.header);
+                }
+                split_size -= bytes;
+                if bytes == 0 || split_size == 0 {
+                    break;
+                }
+            }
+        } else {
+            let bytes = min(split_size, E1000_TX_MAX_SIZE - self.tx.data.len());
+            if !self.read_tx_data(addr, bytes) {
+                self.tx.clear_packet();
+                return;
+            }
+        }
+
+        if lower & E1000_TXD_CMD_EOP == 0 {
+            return;
+        }
+        let hdr_len = self.tx.tso_props.hdr_len as usize;
+        if (self.tx.cptse && self.tx.data.len() > hdr_len)
+            || (!self.tx.cptse && !self.tx.data.is_empty())
+        {
+            self.xmit_seg(send);
+        }
+        self.tx.clear_packet();
+    }
+
+    fn read_tx_data(&mut self, addr: u64, bytes: usize) -> bool {
+        if bytes == 0 {
+            return true;
+        }
+        if let Err(e) = self
+            .mem_space
+            .read(&mut self.tx.data, GuestAddress(addr), bytes as u64)
+        {
+            error!("Failed to read e1000e transmit buffer: {:?}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Fix the headers of the segment, insert the checksums and send it.
+    fn xmit_seg(&mut self, send: &mut dyn FnMut(&[u8])) {
+        let props = if self.tx.cptse {
+            self.tx.tso_props
+        } else {
+            self.tx.props
+        };
+        let vet = self.reg(E1000_VET) as u16;
+        let data = &mut self.tx.data;
+        let size = data.len();
+        if self.tx.cptse {
+            let css = props.ipcss as usize;
+            if props.ip {
+                put_be16(data, css + 2, size.saturating_sub(css) as u16);
+                let id = get_be16(data, css + 4).wrapping_add(self.tx.tso_frames);
+                put_be16(data, css + 4, id);
+            } else {
+                put_be16(data, css + 4, size.saturating_sub(css + IPV6_HLEN) as u16);
+            }
+            let css = props.tucss as usize;
+            let len = size.saturating_sub(css);
+            if props.tcp {
+                let sofar = self.tx.tso_frames as u32 * props.mss as u32;
+                if css + 14 <= size {
+                    let seq = u32::from_be_bytes([
+                        data[css + 4],
+                        data[css + 5],
+                        data[css + 6],
+                        data[css + 7],
+                    ]);
+                    data[css + 4..css + 8].copy_from_slice(&seq.wrapping_add(sofar).to_be_bytes());
+                    // Clear PSH and FIN except the last segment.
+                    if props.paylen.saturating_sub(sofar) > props.mss as u32 {
+                        data[css + 13] &= !0x9;
+                    }
+                }
+            } else {
+                put_be16(data, css + 4, len as u16);
+            }
+            if self.tx.sum_needed & E1000_TXD_POPTS_TXSM != 0 {
+                // Add the length to the pseudo header checksum.
+                let sloc = props.tucso as usize;
+                let mut sum = get_be16(data, sloc) as u32 + len as u32;
+                sum = (sum >> 16) + (sum & 0xFFFF);
+                put_be16(data, sloc, sum as u16);
+            }
+            self.tx.tso_frames = self.tx.tso_frames.wrapping_add(1);
+        }
+
+        if self.tx.sum_needed & E1000_TXD_POPTS_TXSM != 0 {
+            put_checksum(
+                data,
+                props.tucso as usize,
+                props.tucss as usize,
+                props.tucse as usize,
+            );
+        }
+        if self.tx.sum_needed & E1000_TXD_POPTS_IXSM != 0 {
+            put_checksum(
+                data,
+                props.ipcso as usize,
+                props.ipcss as usize,
+                props.ipcse as usize,
+            );
+        }
+        if let Some((css, cso)) = self.tx.legacy_sum {
+            put_checksum(data, cso, css, 0);
+        }
+
+        if self.tx.vlan_needed && size >= ETH_ALEN * 2 {
+            let mut frame = Vec::with_capacity(size + VLAN_HLEN);
+            frame.extend_from_slice(&data[..ETH_ALEN * 2]);
+            frame.extend_from_slice(&vet.to_be_bytes());
+            frame.extend_from_slice(&self.tx.vlan_tci.to_be_bytes());
+            frame.extend_from_slice(&data[ETH_ALEN * 2..]);
+            send(&frame);
+            self.update_stats(&frame, false);
+        } else {
+            send(data);
+            let frame = std::mem::take(&mut self.tx.data);
+            self.update_stats(&frame, false);
+            self.tx.data = frame;
+        }
+    }
+
+    /// Check whether the frame passes the vlan, the broadcast, the multicast and the
+    /// unicast filters.
+    fn rx_filter(&self, frame: &[u8]) -> bool {
+        if frame.len() < ETH_HLEN {
+            return false;
+        }
+        let rctl = self.reg(E1000_RCTL);
+        if rctl & E1000_RCTL_VFE != 0 && self.is_vlan_packet(frame) {
+            let vid = (u16::from_be_bytes([frame[14], frame[15]]) & 0xFFF) as usize;
+            let vfta = self.reg(E1000_VFTA + (vid >> 5) * 4);
+            if vfta & (1 << (vid & 0x1F)) == 0 {
+                return false;
+            }
+        }
+
+        let dst = &frame[..ETH_ALEN];
+        let multicast = dst[0] & 1 != 0;
+        if dst == [0xFF; ETH_ALEN] && rctl & E1000_RCTL_BAM != 0 {
+            return true;
+        }
+        if multicast && rctl & E1000_RCTL_MPE != 0 {
+            return true;
+        }
+        if !multicast && rctl & E1000_RCTL_UPE != 0 {
+            return true;
+        }
+        for i in 0..E1000_RAR_ENTRIES {
+            let rah = self.reg(E1000_RAH + i * 8);
+            if rah & E1000_RAH_AV == 0 {
+                continue;
+            }
+            let ral = self.reg(E1000_RAL + i * 8).to_le_bytes();
+            let rah = rah.to_le_bytes();
+            if dst[..4] == ral && dst[4..] == rah[..2] {
+                return true;
+            }
+        }
+        if multicast {
+            const MTA_SHIFT: [u32; 4] = [4, 3, 2, 0];
+            let shift = MTA_SHIFT[((rctl >> E1000_RCTL_MO_SHIFT) & 0x3) as usize];
+            let hash = ((u16::from_le_bytes([dst[4], dst[5]]) as u32 >> shift) & 0xFFF) as usize;
+            return self.reg(E1000_MTA + (hash >> 5) * 4) & (1 << (hash & 0x1F)) != 0;
+        }
+        false
+    }
+
+    fn rx_buf_size(&self) -> usize {
+        let rctl = self.reg(E1000_RCTL);
+        let bsize = (rctl >> E1000_RCTL_BSIZE_SHIFT) & 0x3;
+        if rctl & E1000_RCTL_BSEX != 0 {
+            // The value 0 is reserved with BSEX.
+            16384 >> bsize.max(1)
+        } else {
+            2048 >> bsize
+        }
+    }
+
+    fn rx_ring_size(&self) -> u32 {
+        self.reg(E1000_RDLEN) / size_of::<E1000eDesc>() as u32
+    }
+
+    fn rx_desc_available(&self) -> u32 {
+        let size = self.rx_ring_size();
+        let head = self.reg(E1000_RDH);
+        let tail = self.reg(E1000_RDT);
+        if head >= size || tail >= size {
+            return 0;
+        }
+        if tail >= head {
+            tail - head
+        } else {
+            size - head + tail
+        }
+    }
+
+    /// Whether the receiver is enabled and there are receive buffers.
+    pub fn can_receive(&self) -> bool {
+        self.reg(E1000_RCTL) & E1000_RCTL_EN != 0 && self.rx_desc_available() > 0
+    }
+
+    /// Receive one frame into the receive ring. Return false if there are not enough
+    /// receive buffers, the frame should be retried later.
+    pub fn receive(&mut self, frame: &[u8]) -> bool {
+        if !self.can_receive() {
+            return false;
+        }
+        if self.reg(E1000_RCTL) & E1000_RCTL_DTYP_MASK != 0 || !self.rx_filter(frame) {
+            return true;
+        }
+
+        let mut status = E1000_RXD_STAT_DD | E1000_RXD_STAT_IXSM;
+        let mut vlan_tci = 0;
+        let mut pkt = if self.vlan_enabled() && self.is_vlan_packet(frame) {
+            status |= E1000_RXD_STAT_VP;
+            vlan_tci = u16::from_be_bytes([frame[14], frame[15]]);
+            let mut pkt = frame[..ETH_ALEN * 2].to_vec();
+            pkt.extend_from_slice(&frame[ETH_HLEN + 2..]);
+            pkt
+        } else {
+            frame.to_vec()
+        };
+        if pkt.len() < ETH_ZLEN {
+            pkt.resize(ETH_ZLEN, 0);
+        }
+        if self.reg(E1000_RCTL) & E1000_RCTL_SECRC == 0 {
+            pkt.extend_from_slice(&[0; ETH_FCS_LEN]);
+        }
+
+        let buf_size = self.rx_buf_size();
+        let needed = pkt.len().div_ceil(buf_size) as u32;
+        if needed >= self.rx_ring_size() {
+            // It can never be received.
+            return true;
+        }
+        if self.rx_desc_available() < needed {
+            return false;
+        }
+
+        let base = self.ring_base(E1000_RDBAL, E1000_RDBAH);
+        let size = self.rx_ring_size();
+        let extended = self.reg(E1000_RFCTL) & E1000_RFCTL_EXTEN != 0;
+        let chunks = pkt.chunks(buf_size).count();
+        for (i, chunk) in pkt.chunks(buf_size).enumerate() {
+            let head = self.reg(E1000_RDH);
+            let addr = base + head as u64 * size_of::<E1000eDesc>() as u64;
+            let buffer_addr = match self.mem_space.read_object::<E1000eDesc>(GuestAddress(addr)) {
+                Ok(desc) => desc.buffer_addr,
+                Err(e) => {
+                    error!("Failed to read e1000e receive descriptor: {:?}", e);
+                    return true;
+                }
+            };
+            if buffer_addr != 0 {
+                let mut src = chunk;
+                if let Err(e) =
+                    self.mem_space
+                        .write(&mut src, GuestAddress(buffer_addr), chunk.len() as u64)
+                {
+                    error!("Failed to write e1000e receive buffer: {:?}", e);
+                }
+            }
+            let mut status = status;
+            if i == chunks - 1 {
+                status |= E1000_RXD_STAT_EOP;
+            }
+            let desc = if extended {
+                E1000eDesc {
+                    buffer_addr: 0,
+                    lower: status,
+                    upper: chunk.len() as u32 | ((vlan_tci as u32) << 16),
+                }
+            } else {
+                E1000eDesc {
+                    buffer_addr,
+                    lower: chunk.len() as u32,
+                    upper: status | ((vlan_tci as u32) << 16),
+                }
+            };
+            if let Err(e) = self.mem_space.write_object(&desc, GuestAddress(addr)) {
+                error!("Failed to write back e1000e receive descriptor: {:?}", e);
+            }
+            *self.reg_mut(E1000_RDH) = (head + 1) % size;
+        }
+        self.update_stats(frame, true);
+
+        let cause = if self.msix_enabled() {
+            E1000_ICR_RXQ0
+        } else {
+            let rdmts = (self.reg(E1000_RCTL) >> E1000_RCTL_RDMTS_SHIFT) & 0x3;
+            if self.rx_desc_available() <= size >> (rdmts + 1) {
+                E1000_ICR_RXT0 | E1000_ICR_RXDMT0
+            } else {
+                E1000_ICR_RXT0
+            }
+        };
+        self.set_interrupt_cause(cause);
+        true
+    }
+}
+
+fn get_be16(data: &[u8], offset: usize) -> u16 {
+    if offset + 2 > data.len() {
+        return 0;
+    }
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn put_be16(data: &mut [u8], offset: usize, val: u16) {
+    if offset + 2 > data.len() {
+        return;
+    }
+    data[offset..offset + 2].copy_from_slice(&val.to_be_bytes());
+}
+
+/// Calculate the internet checksum of data[css..=cse] and put it at `sloc`, the
+/// checksum is calculated to the end of the data if `cse` is 0.
+fn put_checksum(data: &mut [u8], sloc: usize, css: usize, cse: usize) {
+    let mut end = data.len();
+    if cse != 0 && cse < end {
+        end = cse + 1;
+    }
+    if css >= end || sloc + 2 > end {
+        return;
+    }
+    let mut sum = data[css..end].chunks(2).fold(0_u32, |sum, word| {
+        let val = if word.len() == 2 {
+            u16::from_be_bytes([word[0], word[1]])
+        } else {
+            (word[0] as u16) << 8
+        };
+        sum + val as u32
+    });
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    let sum = !(sum as u16);
+    put_be16(data, sloc, if sum == 0 { 0xFFFF } else { sum });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address_space::{AddressSpace, HostMemMapping, Region};
+
+    const MAC: [u8; ETH_ALEN] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    const TX_RING: u64 = 0x1000;
+    const RX_RING: u64 = 0x2000;
+    const TX_BUF: u64 = 0x4000;
+    const RX_BUF: u64 = 0x10000;
+
+    fn create_core() -> Arc<Mutex<E1000eCore>> {
+        let root = Region::init_container_region(1 << 36, "root");
+        let sys_mem = AddressSpace::new(root, "sys_mem").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x40000, None, false, false, false).unwrap(),
+        );
+        sys_mem
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone(), "sysmem"),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        E1000eCore::new(
+            MAC,
+            &sys_mem,
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            None,
+        )
+    }
+
+    fn read32(core: &mut E1000eCore, offset: usize) -> u32 {
+        let mut data = [0_u8; 4];
+        assert!(core.read_reg(offset as u64, &mut data));
+        u32::from_le_bytes(data)
+    }
+
+    fn write32(core: &mut E1000eCore, offset: usize, val: u32) {
+        assert!(core.write_reg(offset as u64, &val.to_le_bytes()));
+    }
+
+    fn eeprom_read(core: &mut E1000eCore, addr: u32) -> u16 {
+        write32(
+            core,
+            E1000_EERD,
+            (addr << E1000_EERW_ADDR_SHIFT) | E1000_EERW_START,
+        );
+        let eerd = read32(core, E1000_EERD);
+        assert_ne!(eerd & E1000_EERW_DONE, 0);
+        (eerd >> E1000_EERW_DATA_SHIFT) as u16
+    }
+
+    fn phy_read(core: &mut E1000eCore, reg: u32) -> u16 {
+        write32(
+            core,
+            E1000_MDIC,
+            E1000_MDIC_OP_READ | (PHY_ADDR << E1000_MDIC_PHY_SHIFT) | (reg << 16),
+        );
+        let mdic = read32(core, E1000_MDIC);
+        assert_ne!(mdic & E1000_MDIC_READY, 0);
+        assert_eq!(mdic & E1000_MDIC_ERROR, 0);
+        mdic as u16
+    }
+
+    #[test]
+    fn test_e1000e_eeprom_and_phy() {
+        let core = create_core();
+        let mut locked_core = core.lock().unwrap();
+        let core = &mut *locked_core;
+
+        let mut sum = 0_u16;
+        for addr in 0..EEPROM_WORDS as u32 {
+            sum = sum.wrapping_add(eeprom_read(core, addr));
+        }
+        assert_eq!(sum, EEPROM_SUM);
+        assert_eq!(eeprom_read(core, 0), 0x5452);
+        assert_eq!(eeprom_read(core, 2), 0x5634);
+        // Out of range read is not done.
+        write32(
+            core,
+            E1000_EERD,
+            (0x40 << E1000_EERW_ADDR_SHIFT) | E1000_EERW_START,
+        );
+        assert_eq!(read32(core, E1000_EERD) & E1000_EERW_DONE, 0);
+
+        // The mac address is loaded into the receive address 0.
+        assert_eq!(read32(core, E1000_RAL), 0x1200_5452);
+        assert_eq!(read32(core, E1000_RAH), E1000_RAH_AV | 0x5634);
+        let status = read32(core, E1000_STATUS);
+        assert_ne!(status & E1000_STATUS_LU, 0);
+        assert_ne!(status & E1000_STATUS_GIO_MASTER_ENABLE, 0);
+        write32(core, E1000_CTRL, E1000_CTRL_GIO_MASTER_DISABLE);
+        assert_eq!(
+            read32(core, E1000_STATUS) & E1000_STATUS_GIO_MASTER_ENABLE,
+            0
+        );
+
+        assert_eq!(phy_read(core, PHY_ID1 as u32), 0x0141);
+        assert_eq!(phy_read(core, PHY_ID2 as u32), 0x0CB1);
+        assert_ne!(phy_read(core, PHY_STATUS as u32) & 0x4, 0);
+        // Wrong phy address.
+        write32(core, E1000_MDIC, E1000_MDIC_OP_READ | (2 << 21));
+        assert_ne!(read32(core, E1000_MDIC) & E1000_MDIC_ERROR, 0);
+        // Auto negotiation restarting completes immediately.
+        write32(
+            core,
+            E1000_MDIC,
+            E1000_MDIC_OP_WRITE | (PHY_ADDR << 21) | 0x1340,
+        );
+        assert_eq!(phy_read(core, PHY_CTRL as u32), 0x1140);
+        // Paged registers.
+        write32(
+            core,
+            E1000_MDIC,
+            E1000_MDIC_OP_WRITE | (PHY_ADDR << 21) | (0x1F << 16) | (2 << PHY_PAGE_SHIFT),
+        );
+        write32(
+            core,
+            E1000_MDIC,
+            E1000_MDIC_OP_WRITE | (PHY_ADDR << 21) | (0x15 << 16) | 0xABCD,
+        );
+        assert_eq!(phy_read(core, 0x15), 0xABCD);
+        assert_eq!(phy_read(core, PHY_ID1 as u32), 0x0141);
+        assert_eq!(core.phy[0][0x15], 0);
+
+        // Software reset restores the registers.
+        write32(core, E1000_IMS, 0xFFFF);
+        write32(core, E1000_CTRL, E1000_CTRL_RST);
+        assert_eq!(read32(core, E1000_IMS), 0);
+        assert_eq!(read32(core, E1000_CTRL) & E1000_CTRL_RST, 0);
+    }
+
+    #[test]
+    fn test_e1000e_interrupt_causes() {
+        let core = create_core();
+        let mut locked_core = core.lock().unwrap();
+        let core = &mut *locked_core;
+
+        // Causes are latched in ICR and cleared on read in INTx mode.
+        write32(core, E1000_ICS, E1000_ICR_LSC);
+        assert_eq!(read32(core, E1000_ICR), E1000_ICR_LSC | E1000_ICR_ASSERTED);
+        assert_eq!(read32(core, E1000_ICR), 0);
+        write32(core, E1000_IMS, E1000_ICR_RXT0 | E1000_ICR_TXDW);
+        write32(core, E1000_IMC, E1000_ICR_TXDW);
+        assert_eq!(read32(core, E1000_IMS), E1000_ICR_RXT0);
+        // Write 1 to clear.
+        write32(core, E1000_ICS, E1000_ICR_RXT0 | E1000_ICR_TXDW);
+        write32(core, E1000_ICR, E1000_ICR_TXDW);
+        assert_eq!(read32(core, E1000_ICS), E1000_ICR_RXT0 | E1000_ICR_ASSERTED);
+        // Auto mask on ICR write.
+        write32(core, E1000_IAM, E1000_ICR_RXT0);
+        write32(core, E1000_CTRL_EXT, E1000_CTRL_EXT_IAME);
+        write32(core, E1000_ICR, E1000_ICR_RXT0);
+        assert_eq!(read32(core, E1000_IMS), 0);
+        assert_eq!(read32(core, E1000_ICR), 0);
+    }
+
+    #[test]
+    fn test_e1000e_checksum() {
+        // IPv4 header example with the checksum 0xb1e6.
+        let mut data = vec![
+            0x45, 0x00, 0x00, 0x3c, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0xac, 0x10,
+            0x0a, 0x63, 0xac, 0x10, 0x0a, 0x0c, 0xff, 0xff,
+        ];
+        put_checksum(&mut data, 10, 0, 19);
+        assert_eq!(get_be16(&data, 10), 0xb1e6);
+        // Out of range checksum location is ignored.
+        put_checksum(&mut data, 30, 0, 0);
+        assert_eq!(data.len(), 22);
+    }
+
+    fn setup_tx_ring(core: &mut E1000eCore) {
+        write32(core, E1000_TDBAL, TX_RING as u32);
+        write32(core, E1000_TDLEN, 8 * 16);
+        write32(core, E1000_TCTL, E1000_TCTL_EN);
+    }
+
+    fn write_desc(core: &E1000eCore, addr: u64, desc: &E1000eDesc) {
+        core.mem_space
+            .write_object(desc, GuestAddress(addr))
+            .unwrap();
+    }
+
+    fn write_buf(core: &E1000eCore, addr: u64, data: &[u8]) {
+        let mut src = data;
+        core.mem_space
+            .write(&mut src, GuestAddress(addr), data.len() as u64)
+            .unwrap();
+    }
+
+    /// Build an ethernet + IPv4 + TCP frame with `payload` bytes.
+    fn tcp_frame(payload: usize) -> Vec<u8> {
+        let mut frame = vec![0_u8; 54 + payload];
+        frame[..6].copy_from_slice(&[0x52, 0x54, 0x00, 0xAA, 0xBB, 0xCC]);
+        frame[6..12].copy_from_slice(&MAC);
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[14] = 0x45;
+        frame[18..20].copy_from_slice(&[0x12, 0x34]);
+        frame[22] = 64;
+        frame[23] = 6;
+        frame[26..30].copy_from_slice(&[10, 0, 0, 1]);
+        frame[30..34].copy_from_slice(&[10, 0, 0, 2]);
+        frame[38..42].copy_from_slice(&1000_u32.to_be_bytes());
+        frame[46] = 0x50;
+        // PSH | ACK | FIN.
+        frame[47] = 0x19;
+        for (i, byte) in frame[54..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        frame
+    }
+
+    #[test]
+    fn test_e1000e_tx_tso() {
+        let core = create_core();
+        let mut locked_core = core.lock().unwrap();
+        let core = &mut *locked_core;
+        setup_tx_ring(core);
+
+        let mss = 100_u16;
+        let payload = 250;
+        let frame = tcp_frame(payload);
+        write_buf(core, TX_BUF, &frame);
+        // Context descriptor: ipcss 14, ipcso 24, ipcse 33, tucss 34, tucso 50,
+        // TSE | IP | TCP.
+        let ctx = E1000eDesc {
+            buffer_addr: 14 | (24 << 8) | (33 << 16) | (34 << 32) | (50 << 40),
+            lower: payload as u32
+                | E1000_TXD_CMD_DEXT
+                | E1000_TXD_CMD_TSE
+                | E1000_TXD_CMD_IP
+                | E1000_TXD_CMD_TCP,
+            upper: (54 << 8) | ((mss as u32) << 16),
+        };
+        write_desc(core, TX_RING, &ctx);
+        // The frame is split into two data descriptors.
+        let popts = ((E1000_TXD_POPTS_IXSM | E1000_TXD_POPTS_TXSM) as u32) << 8;
+        let data_cmd = E1000_TXD_CMD_DEXT | E1000_TXD_DTYP_D | E1000_TXD_CMD_TSE;
+        write_desc(
+            core,
+            TX_RING + 16,
+            &E1000eDesc {
+                buffer_addr: TX_BUF,
+                lower: 80 | data_cmd,
+                upper: popts,
+            },
+        );
+        write_desc(
+            core,
+            TX_RING + 32,
+            &E1000eDesc {
+                buffer_addr: TX_BUF + 80,
+                lower: (frame.len() - 80) as u32 | data_cmd | E1000_TXD_CMD_EOP | E1000_TXD_CMD_RS,
+                upper: popts,
+            },
+        );
+        write32(core, E1000_TDT, 3);
+
+        let mut sent = Vec::new();
+        core.process_tx(&mut |pkt: &[u8]| sent.push(pkt.to_vec()));
+        assert_eq!(sent.len(), 3);
+        for (i, seg) in sent.iter().enumerate() {
+            let seg_payload = min(mss as usize, payload - i * mss as usize);
+            assert_eq!(seg.len(), 54 + seg_payload);
+            // IP total length and id.
+            assert_eq!(get_be16(seg, 16) as usize, 40 + seg_payload);
+            assert_eq!(get_be16(seg, 18), 0x1234 + i as u16);
+            // TCP sequence.
+            let seq = u32::from_be_bytes([seg[38], seg[39], seg[40], seg[41]]);
+            assert_eq!(seq, 1000 + i as u32 * mss as u32);
+            // PSH and FIN are only set on the last segment.
+            let last = i == sent.len() - 1;
+            assert_eq!(seg[47] & 0x9 != 0, last);
+            // The IP checksum is valid.
+            let mut ip = seg[14..34].to_vec();
+            put_checksum(&mut ip, 10, 0, 0);
+            assert_eq!(get_be16(&ip, 10), 0xFFFF);
+            assert_eq!(
+                &seg[54..],
+                &frame[54 + i * mss as usize..54 + i * mss as usize + seg_payload]
+            );
+        }
+        assert_eq!(read32(core, E1000_TDH), 3);
+        let upper = core
+            .mem_space
+            .read_object::<u32>(GuestAddress(TX_RING + 32 + 12))
+            .unwrap();
+        assert_ne!(upper & E1000_TXD_STAT_DD, 0);
+        assert_ne!(
+            read32(core, E1000_ICR) & (E1000_ICR_TXDW | E1000_ICR_TXQE),
+            0
+        );
+        assert_eq!(read32(core, E1000_GPTC), 3);
+    }
+
+    #[test]
+    fn test_e1000e_tx_vlan_insertion() {
+        let core = create_core();
+        let mut locked_core = core.lock().unwrap();
+        let core = &mut *locked_core;
+        setup_tx_ring(core);
+        write32(core, E1000_CTRL, E1000_CTRL_VME);
+
+        let frame = tcp_frame(10);
+        write_buf(core, TX_BUF, &frame);
+        write_desc(
+            core,
+            TX_RING,
+            &E1000eDesc {
+                buffer_addr: TX_BUF,
+                lower: frame.len() as u32 | E1000_TXD_CMD_EOP | E1000_TXD_CMD_VLE,
+                upper: 0x0064 << 16,
+            },
+        );
+        write32(core, E1000_TDT, 1);
+        let mut sent = Vec::new();
+        core.process_tx(&mut |pkt: &[u8]| sent.push(pkt.to_vec()));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].len(), frame.len() + VLAN_HLEN);
+        assert_eq!(&sent[0][12..16], &[0x81, 0x00, 0x00, 0x64]);
+        assert_eq!(&sent[0][16..], &frame[12..]);
+
+        // Transmit is disabled.
+        write32(core, E1000_TCTL, 0);
+        write32(core, E1000_TDT, 2);
+        sent.clear();
+        core.process_tx(&mut |pkt: &[u8]| sent.push(pkt.to_vec()));
+        assert!(sent.is_empty());
+        assert_eq!(read32(core, E1000_TDH), 1);
+    }
+
+    /// Setup the receive ring with 8 descriptors, and `avail` of them are available.
+    fn setup_rx_ring(core: &mut E1000eCore, avail: u32, rctl: u32) {
+        let descs = 8;
+        for i in 0..descs as u64 {
+            write_desc(
+                core,
+                RX_RING + i * 16,
+                &E1000eDesc {
+                    buffer_addr: RX_BUF + i * 0x1000,
+                    ..Default::default()
+                },
+            );
+        }
+        write32(core, E1000_RDBAL, RX_RING as u32);
+        write32(core, E1000_RDLEN, descs * 16);
+        write32(core, E1000_RCTL, rctl | E1000_RCTL_EN | E1000_RCTL_SECRC);
+        write32(core, E1000_RDT, avail);
+    }
+
+    fn read_desc(core: &E1000eCore, index: u64) -> E1000eDesc {
+        core.mem_space
+            .read_object::<E1000eDesc>(GuestAddress(RX_RING + index * 16))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_e1000e_rx_filter() {
+        let core = create_core();
+        let mut locked_core = core.lock().unwrap();
+        let core = &mut *locked_core;
+        setup_rx_ring(core, 7, 0);
+
+        let mut frame = tcp_frame(10);
+        frame[..6].copy_from_slice(&MAC);
+        assert!(core.rx_filter(&frame));
+        // Unicast to other address.
+        frame[5] = 0x57;
+        assert!(!core.rx_filter(&frame));
+        write32(core, E1000_RCTL, E1000_RCTL_EN | E1000_RCTL_UPE);
+        assert!(core.rx_filter(&frame));
+        // Broadcast.
+        frame[..6].copy_from_slice(&[0xFF; 6]);
+        assert!(!core.rx_filter(&frame));
+        write32(core, E1000_RCTL, E1000_RCTL_EN | E1000_RCTL_BAM);
+        assert!(core.rx_filter(&frame));
+        // Multicast hash of 01:00:5e:00:00:fb is 0xfb0 with MO 0.
+        frame[..6].copy_from_slice(&[0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB]);
+        assert!(!core.rx_filter(&frame));
+        write32(core, E1000_MTA + (0xFB0 >> 5) * 4, 1 << (0xFB0 & 0x1F));
+        assert!(core.rx_filter(&frame));
+        write32(core, E1000_MTA + (0xFB0 >> 5) * 4, 0);
+        write32(core, E1000_RCTL, E1000_RCTL_EN | E1000_RCTL_MPE);
+        assert!(core.rx_filter(&frame));
+
+        // Vlan filter.
+        let mut vlan_frame = frame[..12].to_vec();
+        vlan_frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x05]);
+        vlan_frame.extend_from_slice(&frame[12..]);
+        write32(
+            core,
+            E1000_RCTL,
+            E1000_RCTL_EN | E1000_RCTL_MPE | E1000_RCTL_VFE,
+        );
+        assert!(!core.rx_filter(&vlan_frame));
+        write32(core, E1000_VFTA, 1 << 5);
+        assert!(core.rx_filter(&vlan_frame));
+        // Too short.
+        assert!(!core.rx_filter(&frame[..10]));
+    }
+
+    #[test]
+    fn test_e1000e_rx() {
+        let core = create_core();
+        let mut locked_core = core.lock().unwrap();
+        let core = &mut *locked_core;
+        // 3 descriptors available, 1024 bytes buffers, legacy descriptors.
+        setup_rx_ring(core, 3, 1 << E1000_RCTL_BSIZE_SHIFT);
+        write32(core, E1000_IMS, E1000_ICR_RXT0);
+
+        let mut frame = tcp_frame(1500);
+        frame[..6].copy_from_slice(&MAC);
+        assert!(core.can_receive());
+        assert!(core.receive(&frame));
+        assert_eq!(read32(core, E1000_RDH), 2);
+        let desc0 = read_desc(core, 0);
+        let desc1 = read_desc(core, 1);
+        assert_eq!(desc0.lower, 1024);
+        assert_eq!(desc0.upper & E1000_RXD_STAT_EOP, 0);
+        assert_ne!(desc0.upper & E1000_RXD_STAT_DD, 0);
+        assert_eq!(desc1.lower as usize, frame.len() - 1024);
+        assert_ne!(desc1.upper & E1000_RXD_STAT_EOP, 0);
+        let mut buf = Vec::new();
+        core.mem_space
+            .read(&mut buf, GuestAddress(RX_BUF), 1024)
+            .unwrap();
+        core.mem_space
+            .read(
+                &mut buf,
+                GuestAddress(RX_BUF + 0x1000),
+                (frame.len() - 1024) as u64,
+            )
+            .unwrap();
+        assert_eq!(buf, frame);
+        assert_ne!(read32(core, E1000_ICR) & E1000_ICR_RXT0, 0);
+        assert_eq!(read32(core, E1000_GPRC), 1);
+
+        // Only one descriptor left, the frame should be retried.
+        assert!(!core.receive(&frame));
+        assert_eq!(read32(core, E1000_RDH), 2);
+
+        // Short frame with vlan stripping into extended descriptor.
+        write32(core, E1000_CTRL, E1000_CTRL_VME);
+        write32(core, E1000_RFCTL, E1000_RFCTL_EXTEN);
+        let mut vlan_frame = frame[..12].to_vec();
+        vlan_frame.extend_from_slice(&[0x81, 0x00, 0x20, 0x07]);
+        vlan_frame.extend_from_slice(&frame[12..20]);
+        assert!(core.receive(&vlan_frame));
+        let desc2 = read_desc(core, 2);
+        assert_eq!(
+            desc2.lower,
+            E1000_RXD_STAT_DD | E1000_RXD_STAT_EOP | E1000_RXD_STAT_IXSM | E1000_RXD_STAT_VP
+        );
+        assert_eq!(desc2.upper, ETH_ZLEN as u32 | (0x2007 << 16));
+        assert!(!core.can_receive());
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{ErrorKind, IoSlice, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::e1000e_core::{E1000eCore, E1000E_MMIO_SIZE, E1000E_MSIX_VECTORS};
+use crate::pci::config::{
+    PciConfig, RegionType, DEVICE_ID, PCI_CLASS_NETWORK_ETHERNET, PCI_CONFIG_SPACE_SIZE,
+    SUBSYSTEM_VENDOR_ID, SUB_CLASS_CODE, VENDOR_ID,
+};
+use crate::pci::msix::update_dev_id;
+use crate::pci::{init_intx, init_msix, le_write_u16, PciBus, PciDevBase, PciDevOps};
+use crate::{Device, DeviceBase};
+use address_space::{AddressSpace, GuestAddress, Region, RegionOps};
+use machine_manager::config::NetworkInterfaceConfig;
+use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use util::tap::Tap;
+
+const PCI_VENDOR_ID_INTEL: u16 = 0x8086;
+/// Intel 82574L gigabit ethernet controller.
+const PCI_DEVICE_ID_82574L: u16 = 0x10d3;
+
+/// BAR0: registers, BAR2: IO window to the registers, BAR3: msi-x table and pba.
+const E1000E_MMIO_BAR: usize = 0;
+const E1000E_IO_BAR: usize = 2;
+const E1000E_MSIX_BAR: usize = 3;
+const E1000E_IO_SIZE: u64 = 0x20;
+const E1000E_MSIX_SIZE: u64 = 0x4000;
+const E1000E_MSIX_TABLE_OFFSET: u32 = 0;
+const E1000E_MSIX_PBA_OFFSET: u32 = 0x2000;
+/// Registers in the IO window.
+const E1000E_IOADDR: u64 = 0x0;
+const E1000E_IODATA: u64 = 0x4;
+
+/// Size of the virtio net header which is prepended to the packets by tap.
+const NET_HDR_LENGTH: usize = 12;
+/// Max size of the frame read from tap.
+const FRAME_BUF_SIZE: usize = 65562;
+
+/// Emulated Intel 82574L network interface card which can be attached to PCI bus.
+pub struct E1000ePciDevice {
+    base: PciDevBase,
+    net_cfg: NetworkInterfaceConfig,
+    core: Arc<Mutex<E1000eCore>>,
+    dev_id: Arc<AtomicU16>,
+    rx_kick: Arc<EventFd>,
+    tx_kick: Arc<EventFd>,
+    delete_evts: Vec<RawFd>,
+}
+
+impl E1000ePciDevice {
+    pub fn new(
+        net_cfg: &NetworkInterfaceConfig,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+        mem_space: &Arc<AddressSpace>,
+    ) -> Result<Self> {
+        let mac = match net_cfg.mac.as_ref() {
+            Some(mac) => parse_mac(mac)?,
+            None => [0x52, 0x54, 0x00, 0x12, 0x34, devfn],
+        };
+        let rx_kick = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
+        let tx_kick = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
+        Ok(Self {
+            base: PciDevBase {
+                base: DeviceBase::new(net_cfg.id.clone(), false),
+                config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 6),
+                devfn,
+                parent_bus,
+            },
+            net_cfg: net_cfg.clone(),
+            core: E1000eCore::new(
+                mac,
+                mem_space,
+                rx_kick.clone(),
+                tx_kick.clone(),
+                net_cfg.iothread.clone(),
+            ),
+            dev_id: Arc::new(AtomicU16::new(0)),
+            rx_kick,
+            tx_kick,
+            delete_evts: Vec::new(),
+        })
+    }
+
+    fn create_tap(&self) -> Result<Tap> {
+        let tap = match self.net_cfg.tap_fds.as_ref() {
+            Some(fds) => Tap::new(None, Some(fds[0]), 1),
+            None => Tap::new(Some(&self.net_cfg.host_dev_name), None, 1),
+        }
+        .with_context(|| format!("Failed to create tap for e1000e {}", self.net_cfg.id))?;
+        tap.set_hdr_size(NET_HDR_LENGTH as u32)
+            .with_context(|| "Failed to set tap hdr size")?;
+        if let Some(size) = self.net_cfg.sndbuf {
+            // The size has been checked not bigger than i32::MAX.
+            tap.set_sndbuf(size as i32)
+                .with_context(|| "Failed to set tap sndbuf")?;
+        }
+        Ok(tap)
+    }
+
+    fn mmio_region(&self) -> Region {
+        let read_core = self.core.clone();
+        let write_core = self.core.clone();
+        let ops = RegionOps {
+            read: Arc::new(
+                move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+                    read_core.lock().unwrap().read_reg(offset, data)
+                },
+            ),
+            write: Arc::new(move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+                write_core.lock().unwrap().write_reg(offset, data)
+            }),
+        };
+        Region::init_io_region(E1000E_MMIO_SIZE, ops, "E1000eMmioRegion")
+    }
+
+    /// The IO window accesses the registers indirectly through IOADDR and IODATA.
+    fn io_region(&self) -> Region {
+        let ioaddr = Arc::new(AtomicU32::new(0));
+        let read_core = self.core.clone();
+        let read_ioaddr = ioaddr.clone();
+        let write_core = self.core.clone();
+        let ops = RegionOps {
+            read: Arc::new(
+                move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+                    match offset {
+                        E1000E_IOADDR => {
+                            let val = read_ioaddr.load(Ordering::Acquire).to_le_bytes();
+                            let len = data.len().min(4);
+                            data[..len].copy_from_slice(&val[..len]);
+                            true
+                        }
+                        E1000E_IODATA => {
+                            let addr = read_ioaddr.load(Ordering::Acquire) as u64;
+                            read_core.lock().unwrap().read_reg(addr, data)
+                        }
+                        _ => {
+                            data.fill(0);
+                            true
+                        }
+                    }
+                },
+            ),
+            write: Arc::new(move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+                match offset {
+                    E1000E_IOADDR if data.len() == 4 => {
+                        let val = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                        ioaddr.store(val, Ordering::Release);
+                        true
+                    }
+                    E1000E_IODATA => {
+                        let addr = ioaddr.load(Ordering::Acquire) as u64;
+                        write_core.lock().unwrap().write_reg(addr, data)
+                    }
+                    _ => true,
+                }
+            }),
+        };
+        Region::init_io_region(E1000E_IO_SIZE, ops, "E1000eIoRegion")
+    }
+}
+
+/// Parse the mac address which has been checked in the format "xx:xx:xx:xx:xx:xx".
+fn parse_mac(mac: &str) -> Result<[u8; 6]> {
+    let mut bytes = [0_u8; 6];
+    let parts: Vec<&str> = mac.split(':').collect();
+    if parts.len() != bytes.len() {
+        bail!("Invalid mac address {}", mac);
+    }
+    for (byte, part) in bytes.iter_mut().zip(parts) {
+        *byte =
+            u8::from_str_radix(part, 16).with_context(|| format!("Invalid mac address {}", mac))?;
+    }
+    Ok(bytes)
+}
+
+impl Device for E1000ePciDevice {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl PciDevOps for E1000ePciDevice {
+    fn pci_base(&self) -> &PciDevBase {
+        &self.base
+    }
+
+    fn pci_base_mut(&mut self) -> &mut PciDevBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<()> {
+        self.init_write_mask(false)?;
+        self.init_write_clear_mask(false)?;
+        let config = &mut self.base.config.config;
+        le_write_u16(config, VENDOR_ID as usize, PCI_VENDOR_ID_INTEL)?;
+        le_write_u16(config, DEVICE_ID as usize, PCI_DEVICE_ID_82574L)?;
+        le_write_u16(config, SUB_CLASS_CODE as usize, PCI_CLASS_NETWORK_ETHERNET)?;
+        le_write_u16(config, SUBSYSTEM_VENDOR_ID, PCI_VENDOR_ID_INTEL)?;
+
+        #[cfg(target_arch = "aarch64")]
+        self.base.config.set_interrupt_pin();
+
+        self.dev_id.store(self.base.devfn as u16, Ordering::SeqCst);
+
+        let mmio_region = self.mmio_region();
+        self.base.config.register_bar(
+            E1000E_MMIO_BAR,
+            mmio_region,
+            RegionType::Mem32Bit,
+            false,
+            E1000E_MMIO_SIZE,
+        )?;
+        let io_region = self.io_region();
+        self.base.config.register_bar(
+            E1000E_IO_BAR,
+            io_region,
+            RegionType::Io,
+            false,
+            E1000E_IO_SIZE,
+        )?;
+
+        let msix_region = Region::init_container_region(E1000E_MSIX_SIZE, "E1000eMsixRegion");
+        init_msix(
+            E1000E_MSIX_BAR,
+            E1000E_MSIX_VECTORS as u32,
+            &mut self.base.config,
+            self.dev_id.clone(),
+            &self.base.base.id,
+            Some(&msix_region),
+            Some((E1000E_MSIX_TABLE_OFFSET, E1000E_MSIX_PBA_OFFSET)),
+        )?;
+        self.base.config.register_bar(
+            E1000E_MSIX_BAR,
+            msix_region,
+            RegionType::Mem32Bit,
+            false,
+            E1000E_MSIX_SIZE,
+        )?;
+
+        init_intx(
+            self.name(),
+            &mut self.base.config,
+            self.base.parent_bus.clone(),
+            self.base.devfn,
+        )?;
+        self.core.lock().unwrap().set_interrupts(
+            self.base.config.msix.clone(),
+            self.base.config.intx.clone(),
+            self.dev_id.clone(),
+        );
+
+        let tap = self.create_tap()?;
+        let handler = Arc::new(Mutex::new(E1000eIoHandler {
+            core: self.core.clone(),
+            tap,
+            rx_kick: self.rx_kick.clone(),
+            tx_kick: self.tx_kick.clone(),
+            pending_rx: None,
+            is_listening: true,
+            frame_buf: vec![0; FRAME_BUF_SIZE],
+        }));
+        register_event_helper(
+            EventNotifierHelper::internal_notifiers(handler),
+            self.net_cfg.iothread.as_ref(),
+            &mut self.delete_evts,
+        )?;
+
+        // Attach to the PCI bus.
+        let pci_bus = self.base.parent_bus.upgrade().unwrap();
+        let mut locked_pci_bus = pci_bus.lock().unwrap();
+        let pci_device = locked_pci_bus.devices.get(&self.base.devfn);
+        match pci_device {
+            Some(device) => bail!(
+                "Devfn {:?} has been used by {:?}",
+                &self.base.devfn,
+                device.lock().unwrap().name()
+            ),
+            None => locked_pci_bus
+                .devices
+                .insert(self.base.devfn, Arc::new(Mutex::new(self))),
+        };
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        unregister_event_helper(self.net_cfg.iothread.as_ref(), &mut self.delete_evts)?;
+        self.core.lock().unwrap().unrealize();
+        Ok(())
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        update_dev_id(&self.base.parent_bus, self.base.devfn, &self.dev_id);
+        let parent_bus = self.base.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+
+        self.base.config.write(
+            offset,
+            data,
+            self.dev_id.load(Ordering::Acquire),
+            #[cfg(target_arch = "x86_64")]
+            Some(&locked_parent_bus.io_region),
+            Some(&locked_parent_bus.mem_region),
+        );
+    }
+
+    fn reset(&mut self, _reset_child_device: bool) -> Result<()> {
+        self.core.lock().unwrap().reset();
+        self.base.config.reset()?;
+        Ok(())
+    }
+}
+
+/// Move the packets between the tap and the descriptor rings in the iothread.
+struct E1000eIoHandler {
+    core: Arc<Mutex<E1000eCore>>,
+    tap: Tap,
+    rx_kick: Arc<EventFd>,
+    tx_kick: Arc<EventFd>,
+    /// The frame read from tap which is waiting for the receive buffers.
+    pending_rx: Option<Vec<u8>>,
+    /// Whether the tap is listened for the incoming frames.
+    is_listening: bool,
+    frame_buf: Vec<u8>,
+}
+
+impl E1000eIoHandler {
+    /// Receive the frames from tap until it is empty, return false if the guest
+    /// can't receive any more.
+    fn handle_rx(&mut self) -> bool {
+        let mut locked_core = self.core.lock().unwrap();
+        if let Some(frame) = self.pending_rx.take() {
+            if !locked_core.receive(&frame) {
+                self.pending_rx = Some(frame);
+                return false;
+            }
+        }
+        loop {
+            if !locked_core.can_receive() {
+                return false;
+            }
+            let size = match self.tap.read(&mut self.frame_buf) {
+                Ok(size) => size,
+                Err(e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        error!("Failed to read tap for e1000e: {:?}", e);
+                    }
+                    return true;
+                }
+            };
+            if size <= NET_HDR_LENGTH {
+                continue;
+            }
+            let frame = &self.frame_buf[NET_HDR_LENGTH..size];
+            if !locked_core.receive(frame) {
+                self.pending_rx = Some(frame.to_vec());
+                return false;
+            }
+        }
+    }
+
+    fn handle_tx(&mut self) {
+        let tap = &self.tap;
+        let hdr = [0_u8; NET_HDR_LENGTH];
+        self.core.lock().unwrap().process_tx(&mut |frame: &[u8]| {
+            let iovs = [IoSlice::new(&hdr), IoSlice::new(frame)];
+            if let Err(e) = tap.file.as_ref().write_vectored(&iovs) {
+                // The packet is dropped if the tap is busy.
+                if e.kind() != ErrorKind::WouldBlock {
+                    error!("Failed to write tap for e1000e: {:?}", e);
+                }
+            }
+        });
+    }
+
+    /// Park the tap if the guest has no receive buffers, and resume it when the buffers
+    /// are added.
+    fn update_tap_listening(&mut self, can_receive: bool) -> Option<Vec<EventNotifier>> {
+        let operation = if can_receive && !self.is_listening {
+            NotifierOperation::Resume
+        } else if !can_receive && self.is_listening {
+            NotifierOperation::Park
+        } else {
+            return None;
+        };
+        self.is_listening = can_receive;
+        Some(vec![EventNotifier::new(
+            operation,
+            self.tap.as_raw_fd(),
+            None,
+            EventSet::IN | EventSet::EDGE_TRIGGERED,
+            Vec::new(),
+        )])
+    }
+}
+
+impl EventNotifierHelper for E1000eIoHandler {
+    fn internal_notifiers(io_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let locked_handler = io_handler.lock().unwrap();
+        let mut notifiers = Vec::new();
+
+        // The guest adds receive buffers or enables the receiver.
+        let cloned_handler = io_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_handler = cloned_handler.lock().unwrap();
+            let can_receive = locked_handler.handle_rx();
+            locked_handler.update_tap_listening(can_receive)
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_handler.rx_kick.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        // The guest updates the transmit ring tail.
+        let cloned_handler = io_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            cloned_handler.lock().unwrap().handle_tx();
+            None
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_handler.tx_kick.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        // Frames arrive at the tap.
+        let cloned_handler = io_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            let mut locked_handler = cloned_handler.lock().unwrap();
+            let can_receive = locked_handler.handle_rx();
+            locked_handler.update_tap_listening(can_receive)
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            locked_handler.tap.as_raw_fd(),
+            None,
+            EventSet::IN | EventSet::EDGE_TRIGGERED,
+            vec![handler],
+        ));
+
+        notifiers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_e1000e_parse_mac() {
+        assert_eq!(
+            parse_mac("52:54:00:AB:cd:01").unwrap(),
+            [0x52, 0x54, 0x00, 0xab, 0xcd, 0x01]
+        );
+        assert!(parse_mac("52:54:00:ab:cd").is_err());
+        assert!(parse_mac("52:54:00:ab:cd:zz").is_err());
+    }
+}
@@ -0,0 +1,1741 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use log::{error, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::pci::config::PCI_VENDOR_ID_REDHAT;
+use address_space::{AddressSpace, GuestAddress};
+use block_backend::{create_block_backend, BlockDriverOps, BlockProperty};
+use machine_manager::config::{DriveConfig, DriveFile, NvmeConfig, VmConfig};
+use util::aio::{iov_from_buf_direct, iov_to_buf_direct, Aio, AioCb, AioReqResult, Iovec, OpCode};
+use util::byte_code::ByteCode;
+
+/// Controller registers, NVMe 1.3 section 3.1.
+const NVME_REG_CAP: u64 = 0x00;
+const NVME_REG_VS: u64 = 0x08;
+const NVME_REG_INTMS: u64 = 0x0c;
+const NVME_REG_INTMC: u64 = 0x10;
+const NVME_REG_CC: u64 = 0x14;
+const NVME_REG_CSTS: u64 = 0x1c;
+const NVME_REG_AQA: u64 = 0x24;
+const NVME_REG_ASQ: u64 = 0x28;
+const NVME_REG_ACQ: u64 = 0x30;
+
+/// Version 1.3.0.
+const NVME_VERSION: u32 = 0x0001_0300;
+
+const NVME_CC_EN: u32 = 1;
+const NVME_CC_CSS_SHIFT: u32 = 4;
+const NVME_CC_MPS_SHIFT: u32 = 7;
+const NVME_CC_SHN_SHIFT: u32 = 14;
+const NVME_CC_IOSQES_SHIFT: u32 = 16;
+const NVME_CC_IOCQES_SHIFT: u32 = 20;
+
+const NVME_CSTS_RDY: u32 = 1;
+const NVME_CSTS_CFS: u32 = 1 << 1;
+const NVME_CSTS_SHST_MASK: u32 = 3 << 2;
+const NVME_CSTS_SHST_COMPLETE: u32 = 2 << 2;
+
+/// Max number of entries of each queue.
+const NVME_MAX_QUEUE_ENTRIES: u32 = 2048;
+/// Size of the submission queue entry is 2^6, and the completion queue entry is 2^4.
+const NVME_SQES_SHIFT: u32 = 6;
+const NVME_CQES_SHIFT: u32 = 4;
+/// Supported memory page size is from 2^12 to 2^16.
+const NVME_MIN_PAGE_SHIFT: u32 = 12;
+const NVME_MAX_PAGE_SHIFT: u32 = 16;
+/// Max data transfer size is 2^7 min memory pages.
+const NVME_MDTS: u8 = 7;
+/// Max number of the outstanding async event requests, which is 0's based.
+const NVME_AERL: u8 = 3;
+/// Max number of the SGL descriptors of one command.
+const NVME_MAX_SGL_DESCS: usize = 1024;
+
+/// Doorbells start at offset 0x1000 with the stride of 4 bytes.
+pub const NVME_DOORBELL_OFFSET: u64 = 0x1000;
+
+const NVME_NSID: u32 = 1;
+const NVME_NSID_BROADCAST: u32 = 0xffff_ffff;
+const NVME_IDENTIFY_DATA_SIZE: usize = 4096;
+
+/// Admin command set.
+const NVME_ADM_DELETE_SQ: u8 = 0x00;
+const NVME_ADM_CREATE_SQ: u8 = 0x01;
+const NVME_ADM_GET_LOG_PAGE: u8 = 0x02;
+const NVME_ADM_DELETE_CQ: u8 = 0x04;
+const NVME_ADM_CREATE_CQ: u8 = 0x05;
+const NVME_ADM_IDENTIFY: u8 = 0x06;
+const NVME_ADM_ABORT: u8 = 0x08;
+const NVME_ADM_SET_FEATURES: u8 = 0x09;
+const NVME_ADM_GET_FEATURES: u8 = 0x0a;
+const NVME_ADM_ASYNC_EVENT_REQ: u8 = 0x0c;
+
+/// NVM command set.
+const NVME_CMD_FLUSH: u8 = 0x00;
+const NVME_CMD_WRITE: u8 = 0x01;
+const NVME_CMD_READ: u8 = 0x02;
+const NVME_CMD_WRITE_ZEROES: u8 = 0x08;
+const NVME_CMD_DSM: u8 = 0x09;
+
+/// Identify CNS values.
+const NVME_ID_CNS_NS: u32 = 0x00;
+const NVME_ID_CNS_CTRL: u32 = 0x01;
+const NVME_ID_CNS_NS_ACTIVE_LIST: u32 = 0x02;
+const NVME_ID_CNS_NS_DESC_LIST: u32 = 0x03;
+
+/// Log page identifiers.
+const NVME_LOG_ERROR: u32 = 0x01;
+const NVME_LOG_SMART: u32 = 0x02;
+const NVME_LOG_FW_SLOT: u32 = 0x03;
+
+/// Feature identifiers.
+const NVME_FEAT_ARBITRATION: u32 = 0x01;
+const NVME_FEAT_POWER_MGMT: u32 = 0x02;
+const NVME_FEAT_TEMP_THRESH: u32 = 0x04;
+const NVME_FEAT_ERR_RECOVERY: u32 = 0x05;
+const NVME_FEAT_VOLATILE_WC: u32 = 0x06;
+const NVME_FEAT_NUM_QUEUES: u32 = 0x07;
+const NVME_FEAT_IRQ_COALESCE: u32 = 0x08;
+const NVME_FEAT_IRQ_CONFIG: u32 = 0x09;
+const NVME_FEAT_WRITE_ATOMICITY: u32 = 0x0a;
+const NVME_FEAT_ASYNC_EVENT: u32 = 0x0b;
+/// Save bit of the set features command.
+const NVME_FEAT_SAVE: u32 = 1 << 31;
+
+/// Deallocate bit in cdw12 of the write zeroes command.
+const NVME_WZ_DEAC: u32 = 1 << 25;
+/// Attribute deallocate bit in cdw11 of the dataset management command.
+const NVME_DSMGMT_AD: u32 = 1 << 2;
+/// Size of the range in the dataset management command.
+const NVME_DSM_RANGE_SIZE: usize = 16;
+
+/// PRP or SGL for data transfer, which is in bits 15:14 of cdw0.
+const NVME_PSDT_SHIFT: u8 = 6;
+const NVME_PSDT_PRP: u8 = 0;
+const NVME_PSDT_SGL_MPTR_CONTIGUOUS: u8 = 1;
+const NVME_PSDT_SGL_MPTR_SGL: u8 = 2;
+/// SGL descriptor types.
+const NVME_SGL_TYPE_DATA_BLOCK: u8 = 0x0;
+const NVME_SGL_TYPE_SEGMENT: u8 = 0x2;
+const NVME_SGL_TYPE_LAST_SEGMENT: u8 = 0x3;
+const NVME_SGL_DESC_SIZE: usize = 16;
+
+/// Status codes, which are the status code type in bits 10:8 and the status code in bits 7:0.
+pub const NVME_SUCCESS: u16 = 0x0000;
+const NVME_INVALID_OPCODE: u16 = 0x0001;
+const NVME_INVALID_FIELD: u16 = 0x0002;
+const NVME_DATA_TRANSFER_ERROR: u16 = 0x0004;
+const NVME_INTERNAL_ERROR: u16 = 0x0006;
+const NVME_INVALID_NSID: u16 = 0x000b;
+const NVME_INVALID_SGL_SEG_DESCR: u16 = 0x000d;
+const NVME_DATA_SGL_LEN_INVALID: u16 = 0x000f;
+const NVME_SGL_DESCR_TYPE_INVALID: u16 = 0x0011;
+const NVME_INVALID_PRP_OFFSET: u16 = 0x0013;
+const NVME_NS_WRITE_PROTECTED: u16 = 0x0020;
+const NVME_LBA_RANGE: u16 = 0x0080;
+const NVME_INVALID_CQID: u16 = 0x0100;
+const NVME_INVALID_QID: u16 = 0x0101;
+const NVME_MAX_QSIZE_EXCEEDED: u16 = 0x0102;
+const NVME_AER_LIMIT_EXCEEDED: u16 = 0x0105;
+const NVME_INVALID_IRQ_VECTOR: u16 = 0x0108;
+const NVME_INVALID_LOG_ID: u16 = 0x0109;
+const NVME_INVALID_QUEUE_DEL: u16 = 0x010c;
+const NVME_FEAT_NOT_SAVEABLE: u16 = 0x010d;
+const NVME_WRITE_FAULT: u16 = 0x0280;
+const NVME_UNRECOVERED_READ: u16 = 0x0281;
+/// Do not retry the command.
+const NVME_DNR: u16 = 0x4000;
+
+/// Result of the command, which is the dword 0 of the completion or the status code.
+type CmdResult = std::result::Result<u32, u16>;
+
+/// Interrupt callback of the controller, the arguments are the vector and the level.
+pub type NvmeIrqCallback = Arc<dyn Fn(u16, bool) + Send + Sync>;
+
+/// Submission queue entry.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct NvmeCmd {
+    pub opcode: u8,
+    pub flags: u8,
+    pub cid: u16,
+    pub nsid: u32,
+    pub cdw2: u32,
+    pub cdw3: u32,
+    pub mptr: u64,
+    pub dptr: [u64; 2],
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+    pub cdw13: u32,
+    pub cdw14: u32,
+    pub cdw15: u32,
+}
+
+impl ByteCode for NvmeCmd {}
+
+/// Completion queue entry.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct NvmeCqe {
+    pub result: u32,
+    pub rsvd: u32,
+    pub sq_head: u16,
+    pub sq_id: u16,
+    pub cid: u16,
+    /// The status in bits 15:1 and the phase tag in bit 0.
+    pub status: u16,
+}
+
+impl ByteCode for NvmeCqe {}
+
+/// SGL descriptor.
+#[derive(Clone, Copy)]
+struct NvmeSglDesc {
+    addr: u64,
+    len: u32,
+    sgl_type: u8,
+}
+
+impl NvmeSglDesc {
+    fn from_bytes(buf: &[u8]) -> Self {
+        NvmeSglDesc {
+            addr: LittleEndian::read_u64(&buf[0..8]),
+            len: LittleEndian::read_u32(&buf[8..12]),
+            sgl_type: buf[15],
+        }
+    }
+}
+
+/// Completion queue, which is shared by the controller and the aio completion callbacks.
+pub struct NvmeCq {
+    mem_space: Arc<AddressSpace>,
+    dma_addr: u64,
+    size: u32,
+    head: u32,
+    tail: u32,
+    phase: bool,
+    vector: u16,
+    irq_enabled: bool,
+    irq: NvmeIrqCallback,
+    /// Number of the commands fetched but not completed, each of them owns a free slot.
+    outstanding: u32,
+    /// Number of the submission queues associated.
+    sq_refs: u32,
+    /// Fetching commands is stopped because there is no free slot.
+    stalled: bool,
+    /// The queue is deleted or the controller is reset, drop the late completions.
+    deleted: bool,
+}
+
+impl NvmeCq {
+    fn used(&self) -> u32 {
+        (self.tail + self.size - self.head) % self.size
+    }
+
+    /// Reserve a slot for the command to be fetched, so that the completion never overflows.
+    fn reserve(&mut self) -> bool {
+        if self.used() + self.outstanding >= self.size - 1 {
+            self.stalled = true;
+            return false;
+        }
+        self.outstanding += 1;
+        true
+    }
+
+    fn unreserve(&mut self) {
+        self.outstanding = self.outstanding.saturating_sub(1);
+    }
+
+    fn post(&mut self, cqe: &mut NvmeCqe) {
+        self.unreserve();
+        if self.deleted {
+            return;
+        }
+        cqe.status |= self.phase as u16;
+        let addr = self.dma_addr + ((self.tail as u64) << NVME_CQES_SHIFT);
+        if let Err(e) = self.mem_space.write_object(cqe, GuestAddress(addr)) {
+            error!("Failed to post nvme completion, {:?}", e);
+            return;
+        }
+        self.tail += 1;
+        if self.tail == self.size {
+            self.tail = 0;
+            self.phase = !self.phase;
+        }
+        if self.irq_enabled {
+            (self.irq)(self.vector, true);
+        }
+    }
+}
+
+struct NvmeSq {
+    dma_addr: u64,
+    size: u32,
+    head: u32,
+    tail: u32,
+    cq: Arc<Mutex<NvmeCq>>,
+}
+
+/// The command being processed, which may be split to several aio requests.
+pub struct NvmeRequest {
+    cq: Arc<Mutex<NvmeCq>>,
+    sqid: u16,
+    sq_head: u16,
+    cid: u16,
+    opcode: u8,
+    /// Number of the aio requests in flight.
+    remaining: AtomicU32,
+    /// The first failed status of the aio requests.
+    status: AtomicU16,
+}
+
+impl NvmeRequest {
+    fn new(cq: Arc<Mutex<NvmeCq>>, sqid: u16, sq_head: u16, cmd: &NvmeCmd) -> Self {
+        NvmeRequest {
+            cq,
+            sqid,
+            sq_head,
+            cid: cmd.cid,
+            opcode: cmd.opcode,
+            remaining: AtomicU32::new(1),
+            status: AtomicU16::new(NVME_SUCCESS),
+        }
+    }
+
+    fn complete(&self, status: u16, result: u32) {
+        let mut cqe = NvmeCqe {
+            result,
+            sq_head: self.sq_head,
+            sq_id: self.sqid,
+            cid: self.cid,
+            status: status << 1,
+            ..Default::default()
+        };
+        self.cq.lock().unwrap().post(&mut cqe);
+    }
+
+    /// One of the aio requests is done, complete the command if it's the last one.
+    fn aio_done(&self, status: u16) {
+        if status != NVME_SUCCESS {
+            let _ = self.status.compare_exchange(
+                NVME_SUCCESS,
+                status,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+        }
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.complete(self.status.load(Ordering::SeqCst), 0);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NvmeCompleteCb {
+    req: Arc<NvmeRequest>,
+}
+
+pub fn nvme_aio_complete_cb(aiocb: &AioCb<NvmeCompleteCb>, mut ret: i64) -> Result<()> {
+    match aiocb.req_is_completed(ret) {
+        AioReqResult::Inflight => return Ok(()),
+        AioReqResult::Error(v) => ret = v,
+        AioReqResult::Done => (),
+    }
+
+    let req = &aiocb.iocompletecb.req;
+    let status = if ret < 0 {
+        error!(
+            "Failed to execute nvme command 0x{:x}, ret {}",
+            req.opcode, ret
+        );
+        match aiocb.opcode {
+            OpCode::Preadv => NVME_UNRECOVERED_READ,
+            OpCode::Pwritev => NVME_WRITE_FAULT,
+            _ => NVME_INTERNAL_ERROR,
+        }
+    } else {
+        NVME_SUCCESS
+    };
+    req.aio_done(status);
+    Ok(())
+}
+
+/// Features set by the driver, which are returned in the get features command.
+#[derive(Default)]
+struct NvmeFeatures {
+    arbitration: u32,
+    temp_thresh: u32,
+    volatile_wc: u32,
+    irq_coalesce: u32,
+    async_config: u32,
+}
+
+/// NVMe controller with one namespace.
+pub struct NvmeCtrl {
+    id: String,
+    serial: String,
+    mem_space: Arc<AddressSpace>,
+    /// Max number of the IO queue pairs.
+    max_ioqs: u16,
+    cap: u64,
+    cc: u32,
+    csts: u32,
+    intms: u32,
+    aqa: u32,
+    asq: u64,
+    acq: u64,
+    page_size: u64,
+    sqs: Vec<Option<NvmeSq>>,
+    cqs: Vec<Option<Arc<Mutex<NvmeCq>>>>,
+    features: NvmeFeatures,
+    /// Number of the outstanding async event requests.
+    aer_count: u8,
+    irq: NvmeIrqCallback,
+    /// Kick the io handler to fetch the commands.
+    kick_evt: Arc<EventFd>,
+    drive: Option<DriveConfig>,
+    key_secret: Option<String>,
+    drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    block_backend: Option<Arc<Mutex<dyn BlockDriverOps<NvmeCompleteCb>>>>,
+    /// Size of the logical block is 2^lba_shift.
+    lba_shift: u32,
+    /// Number of the logical blocks of the namespace.
+    nlbas: u64,
+    read_only: bool,
+    discard: bool,
+    /// The backend meets unrecoverable error.
+    pub broken: Arc<AtomicBool>,
+}
+
+impl NvmeCtrl {
+    pub fn new(
+        cfg: &NvmeConfig,
+        mem_space: &Arc<AddressSpace>,
+        drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+        kick_evt: Arc<EventFd>,
+    ) -> Self {
+        let nr_queues = cfg.queues as usize + 1;
+        NvmeCtrl {
+            id: cfg.id.clone(),
+            serial: cfg.serial.clone(),
+            mem_space: mem_space.clone(),
+            max_ioqs: cfg.queues,
+            cap: Self::default_cap(),
+            cc: 0,
+            csts: 0,
+            intms: 0,
+            aqa: 0,
+            asq: 0,
+            acq: 0,
+            page_size: 1 << NVME_MIN_PAGE_SHIFT,
+            sqs: (0..nr_queues).map(|_| None).collect(),
+            cqs: vec![None; nr_queues],
+            features: NvmeFeatures::default(),
+            aer_count: 0,
+            irq: Arc::new(|_, _| {}),
+            kick_evt,
+            drive: Some(cfg.drive.clone()),
+            key_secret: cfg.key_secret.clone(),
+            drive_files,
+            block_backend: None,
+            lba_shift: 9,
+            nlbas: 0,
+            read_only: cfg.drive.read_only,
+            discard: cfg.drive.discard,
+            broken: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn default_cap() -> u64 {
+        // MQES(0's based) | CQR | TO(7.5s) | CSS(NVM) | MPSMIN | MPSMAX
+        (NVME_MAX_QUEUE_ENTRIES as u64 - 1)
+            | 1 << 16
+            | 0x0f << 24
+            | 1 << 37
+            | ((NVME_MIN_PAGE_SHIFT - 12) as u64) << 48
+            | ((NVME_MAX_PAGE_SHIFT - 12) as u64) << 52
+    }
+
+    /// Open the drive and create the block backend of the namespace.
+    pub fn realize(&mut self, iothread: Option<String>) -> Result<()> {
+        let drive = self.drive.take().with_context(|| "Nvme is realized")?;
+        let drive_files = self.drive_files.lock().unwrap();
+        let file = VmConfig::fetch_drive_file(&drive_files, &drive.path_on_host)?;
+        let (req_align, buf_align) =
+            VmConfig::fetch_drive_align(&drive_files, &drive.path_on_host)?;
+        let drive_id = VmConfig::get_drive_id(&drive_files, &drive.path_on_host)?;
+        drop(drive_files);
+
+        let aio = Aio::new(Arc::new(nvme_aio_complete_cb), drive.aio)?;
+        let conf = BlockProperty {
+            id: drive_id,
+            format: drive.format,
+            iothread,
+            direct: drive.direct,
+            req_align,
+            buf_align,
+            discard: drive.discard,
+            write_zeroes: drive.write_zeroes,
+            l2_cache_size: drive.l2_cache_size,
+            refcount_cache_size: drive.refcount_cache_size,
+            key_secret: self.key_secret.clone(),
+            copy_on_read: drive.copy_on_read,
+            io_timeout: drive.io_timeout,
+        };
+        let backend = create_block_backend(file, aio, conf)?;
+        // The logical block is at least 512 bytes, and is never smaller than the
+        // alignment of direct io.
+        self.lba_shift = req_align.max(512).next_power_of_two().trailing_zeros();
+        self.nlbas = backend.lock().unwrap().disk_size()? >> self.lba_shift;
+        let id = self.id.clone();
+        backend.lock().unwrap().register_io_event(
+            self.broken.clone(),
+            Arc::new(move || error!("Nvme {} is broken because of the io error", id)),
+        )?;
+        self.block_backend = Some(backend);
+        Ok(())
+    }
+
+    pub fn unrealize(&mut self) -> Result<()> {
+        if let Some(backend) = self.block_backend.take() {
+            backend.lock().unwrap().unregister_io_event()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_irq_callback(&mut self, irq: NvmeIrqCallback) {
+        self.irq = irq;
+    }
+
+    fn enabled(&self) -> bool {
+        self.csts & NVME_CSTS_RDY != 0
+    }
+
+    /// Drop all the queues, the completions of the commands in flight are dropped too.
+    fn reset_queues(&mut self) {
+        for cq in self.cqs.iter_mut().flatten() {
+            cq.lock().unwrap().deleted = true;
+        }
+        self.sqs.iter_mut().for_each(|sq| *sq = None);
+        self.cqs.iter_mut().for_each(|cq| *cq = None);
+        self.aer_count = 0;
+        self.broken.store(false, Ordering::SeqCst);
+    }
+
+    /// Controller level reset, which is caused by the PCI reset.
+    pub fn reset(&mut self) {
+        self.reset_queues();
+        self.cc = 0;
+        self.csts = 0;
+        self.intms = 0;
+        self.aqa = 0;
+        self.asq = 0;
+        self.acq = 0;
+        self.page_size = 1 << NVME_MIN_PAGE_SHIFT;
+        self.features = NvmeFeatures::default();
+    }
+
+    fn reg_dword(&self, offset: u64) -> u32 {
+        match offset {
+            NVME_REG_CAP => self.cap as u32,
+            o if o == NVME_REG_CAP + 4 => (self.cap >> 32) as u32,
+            NVME_REG_VS => NVME_VERSION,
+            NVME_REG_INTMS | NVME_REG_INTMC => self.intms,
+            NVME_REG_CC => self.cc,
+            NVME_REG_CSTS => {
+                if self.broken.load(Ordering::SeqCst) {
+                    self.csts | NVME_CSTS_CFS
+                } else {
+                    self.csts
+                }
+            }
+            NVME_REG_AQA => self.aqa,
+            NVME_REG_ASQ => self.asq as u32,
+            o if o == NVME_REG_ASQ + 4 => (self.asq >> 32) as u32,
+            NVME_REG_ACQ => self.acq as u32,
+            o if o == NVME_REG_ACQ + 4 => (self.acq >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    /// Read the controller registers, which are accessed in 4 or 8 bytes.
+    pub fn read_reg(&self, offset: u64, data: &mut [u8]) -> bool {
+        let value = match data.len() {
+            4 => self.reg_dword(offset) as u64,
+            8 => self.reg_dword(offset) as u64 | (self.reg_dword(offset + 4) as u64) << 32,
+            _ => {
+                warn!(
+                    "Invalid nvme register read, offset 0x{:x} size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+        };
+        data.copy_from_slice(&value.to_le_bytes()[..data.len()]);
+        true
+    }
+
+    /// Write the controller registers, which are accessed in 4 or 8 bytes.
+    pub fn write_reg(&mut self, offset: u64, data: &[u8]) -> bool {
+        match data.len() {
+            4 => self.write_reg_dword(offset, LittleEndian::read_u32(data)),
+            8 => {
+                self.write_reg_dword(offset, LittleEndian::read_u32(&data[0..4]));
+                self.write_reg_dword(offset + 4, LittleEndian::read_u32(&data[4..8]));
+            }
+            _ => {
+                warn!(
+                    "Invalid nvme register write, offset 0x{:x} size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    fn write_reg_dword(&mut self, offset: u64, value: u32) {
+        // The admin queue attributes can only be changed when the controller is disabled.
+        let disabled = self.cc & NVME_CC_EN == 0;
+        match offset {
+            NVME_REG_INTMS => self.intms |= value,
+            NVME_REG_INTMC => self.intms &= !value,
+            NVME_REG_CC => self.write_cc(value),
+            NVME_REG_AQA if disabled => self.aqa = value & 0x0fff_0fff,
+            NVME_REG_ASQ if disabled => self.asq = (self.asq & !0xffff_ffff) | value as u64,
+            o if o == NVME_REG_ASQ + 4 && disabled => {
+                self.asq = (self.asq & 0xffff_ffff) | (value as u64) << 32
+            }
+            NVME_REG_ACQ if disabled => self.acq = (self.acq & !0xffff_ffff) | value as u64,
+            o if o == NVME_REG_ACQ + 4 && disabled => {
+                self.acq = (self.acq & 0xffff_ffff) | (value as u64) << 32
+            }
+            _ => warn!(
+                "Ignored nvme register write, offset 0x{:x} value 0x{:x}",
+                offset, value
+            ),
+        }
+    }
+
+    fn write_cc(&mut self, value: u32) {
+        let old = self.cc;
+        self.cc = value;
+        if old & NVME_CC_EN == 0 && value & NVME_CC_EN != 0 {
+            match self.start() {
+                Ok(()) => self.csts |= NVME_CSTS_RDY,
+                Err(e) => {
+                    error!("Failed to enable nvme {}, {:?}", self.id, e);
+                    self.csts |= NVME_CSTS_CFS;
+                }
+            }
+        } else if old & NVME_CC_EN != 0 && value & NVME_CC_EN == 0 {
+            self.reset_queues();
+            self.csts = 0;
+        }
+
+        let shn = (value >> NVME_CC_SHN_SHIFT) & 3;
+        let old_shn = (old >> NVME_CC_SHN_SHIFT) & 3;
+        if shn != 0 && old_shn == 0 {
+            // All the writes completed have been submitted to the backend, and the
+            // driver flushes the volatile write cache before shutting down.
+            self.csts = (self.csts & !NVME_CSTS_SHST_MASK) | NVME_CSTS_SHST_COMPLETE;
+        } else if shn == 0 {
+            self.csts &= !NVME_CSTS_SHST_MASK;
+        }
+    }
+
+    /// Enable the controller, and create the admin queues.
+    fn start(&mut self) -> Result<()> {
+        if self.block_backend.is_none() {
+            bail!("Nvme is not realized");
+        }
+        if (self.cc >> NVME_CC_CSS_SHIFT) & 0x7 != 0 {
+            bail!("Unsupported command set 0x{:x}", self.cc);
+        }
+        let page_shift = ((self.cc >> NVME_CC_MPS_SHIFT) & 0xf) + 12;
+        if !(NVME_MIN_PAGE_SHIFT..=NVME_MAX_PAGE_SHIFT).contains(&page_shift) {
+            bail!("Unsupported memory page size 2^{}", page_shift);
+        }
+        if (self.cc >> NVME_CC_IOSQES_SHIFT) & 0xf != NVME_SQES_SHIFT
+            || (self.cc >> NVME_CC_IOCQES_SHIFT) & 0xf != NVME_CQES_SHIFT
+        {
+            bail!("Unsupported queue entry size, cc 0x{:x}", self.cc);
+        }
+        self.page_size = 1 << page_shift;
+        let asqs = (self.aqa & 0xfff) + 1;
+        let acqs = ((self.aqa >> 16) & 0xfff) + 1;
+        if asqs < 2 || acqs < 2 {
+            bail!("Invalid admin queue size, aqa 0x{:x}", self.aqa);
+        }
+        if self.asq == 0
+            || self.acq == 0
+            || self.asq & (self.page_size - 1) != 0
+            || self.acq & (self.page_size - 1) != 0
+        {
+            bail!(
+                "Invalid admin queue address, asq 0x{:x} acq 0x{:x}",
+                self.asq,
+                self.acq
+            );
+        }
+        self.new_cq(0, self.acq, acqs, 0, true);
+        self.new_sq(0, self.asq, asqs, 0);
+        Ok(())
+    }
+
+    fn new_cq(&mut self, qid: u16, dma_addr: u64, size: u32, vector: u16, irq_enabled: bool) {
+        self.cqs[qid as usize] = Some(Arc::new(Mutex::new(NvmeCq {
+            mem_space: self.mem_space.clone(),
+            dma_addr,
+            size,
+            head: 0,
+            tail: 0,
+            phase: true,
+            vector,
+            irq_enabled,
+            irq: self.irq.clone(),
+            outstanding: 0,
+            sq_refs: 0,
+            stalled: false,
+            deleted: false,
+        })));
+    }
+
+    fn new_sq(&mut self, qid: u16, dma_addr: u64, size: u32, cqid: u16) {
+        // It's checked by the caller that the completion queue exists.
+        let cq = self.cqs[cqid as usize].clone().unwrap();
+        cq.lock().unwrap().sq_refs += 1;
+        self.sqs[qid as usize] = Some(NvmeSq {
+            dma_addr,
+            size,
+            head: 0,
+            tail: 0,
+            cq,
+        });
+    }
+
+    /// Write the submission queue tail or the completion queue head doorbell.
+    pub fn write_doorbell(&mut self, offset: u64, data: &[u8]) -> bool {
+        if data.len() != 4 {
+            warn!("Invalid nvme doorbell write size {}", data.len());
+            return false;
+        }
+        if !self.enabled() {
+            return true;
+        }
+        let value = LittleEndian::read_u32(data);
+        let qid = (offset / 8) as usize;
+        if offset.is_multiple_of(8) {
+            let sq = match self.sqs.get_mut(qid).and_then(|sq| sq.as_mut()) {
+                Some(sq) => sq,
+                None => {
+                    warn!("Doorbell write to the invalid nvme sq {}", qid);
+                    return true;
+                }
+            };
+            if value >= sq.size {
+                warn!("Invalid tail {} of nvme sq {}", value, qid);
+                return true;
+            }
+            sq.tail = value;
+            self.kick();
+        } else {
+            let cq = match self.cqs.get(qid).and_then(|cq| cq.as_ref()) {
+                Some(cq) => cq,
+                None => {
+                    warn!("Doorbell write to the invalid nvme cq {}", qid);
+                    return true;
+                }
+            };
+            let mut locked_cq = cq.lock().unwrap();
+            if value >= locked_cq.size {
+                warn!("Invalid head {} of nvme cq {}", value, qid);
+                return true;
+            }
+            locked_cq.head = value;
+            let stalled = locked_cq.stalled;
+            locked_cq.stalled = false;
+            let vector = locked_cq.vector;
+            drop(locked_cq);
+            if !self.cq_pending() {
+                // Deassert the INTx interrupt when all the completions are consumed.
+                (self.irq)(vector, false);
+            }
+            if stalled {
+                self.kick();
+            }
+        }
+        true
+    }
+
+    fn cq_pending(&self) -> bool {
+        self.cqs.iter().flatten().any(|cq| {
+            let locked_cq = cq.lock().unwrap();
+            locked_cq.irq_enabled && locked_cq.used() != 0
+        })
+    }
+
+    fn kick(&self) {
+        if let Err(e) = self.kick_evt.write(1) {
+            error!("Failed to kick nvme {}, {:?}", self.id, e);
+        }
+    }
+
+    /// Fetch and execute the commands of all the submission queues.
+    pub fn process_queues(&mut self) {
+        if !self.enabled() {
+            return;
+        }
+        let mut submitted = false;
+        for qid in 0..self.sqs.len() {
+            submitted |= self.process_sq(qid);
+        }
+        if submitted {
+            if let Some(backend) = self.block_backend.as_ref() {
+                if let Err(e) = backend.lock().unwrap().flush_request() {
+                    error!("Failed to flush nvme requests, {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Returns true if any aio request is submitted.
+    fn process_sq(&mut self, qid: usize) -> bool {
+        let mut submitted = false;
+        while let Some(sq) = self.sqs[qid].as_mut() {
+            if sq.head == sq.tail || !sq.cq.lock().unwrap().reserve() {
+                break;
+            }
+            let addr = sq.dma_addr + ((sq.head as u64) << NVME_SQES_SHIFT);
+            sq.head = (sq.head + 1) % sq.size;
+            let sq_head = sq.head as u16;
+            let cq = sq.cq.clone();
+
+            let cmd = match self.mem_space.read_object::<NvmeCmd>(GuestAddress(addr)) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    error!("Failed to fetch nvme command, {:?}", e);
+                    cq.lock().unwrap().unreserve();
+                    self.csts |= NVME_CSTS_CFS;
+                    break;
+                }
+            };
+            let req = Arc::new(NvmeRequest::new(cq, qid as u16, sq_head, &cmd));
+            if qid == 0 {
+                match self.admin_cmd(&cmd) {
+                    Some(Ok(result)) => req.complete(NVME_SUCCESS, result),
+                    Some(Err(status)) => req.complete(status, 0),
+                    // The async event request is never completed because no event is reported,
+                    // so it doesn't own the slot.
+                    None => req.cq.lock().unwrap().unreserve(),
+                }
+            } else {
+                match self.io_cmd(&cmd, &req) {
+                    Some(status) => req.complete(status, 0),
+                    None => submitted = true,
+                }
+            }
+        }
+        submitted
+    }
+
+    /// Returns None if the command is not completed right now.
+    fn admin_cmd(&mut self, cmd: &NvmeCmd) -> Option<CmdResult> {
+        let ret = match cmd.opcode {
+            NVME_ADM_DELETE_SQ => self.delete_sq(cmd),
+            NVME_ADM_CREATE_SQ => self.create_sq(cmd),
+            NVME_ADM_GET_LOG_PAGE => self.get_log_page(cmd),
+            NVME_ADM_DELETE_CQ => self.delete_cq(cmd),
+            NVME_ADM_CREATE_CQ => self.create_cq(cmd),
+            NVME_ADM_IDENTIFY => self.identify(cmd),
+            // The command is not aborted.
+            NVME_ADM_ABORT => Ok(1),
+            NVME_ADM_SET_FEATURES => self.set_features(cmd),
+            NVME_ADM_GET_FEATURES => self.get_features(cmd),
+            NVME_ADM_ASYNC_EVENT_REQ => {
+                if self.aer_count > NVME_AERL {
+                    Err(NVME_AER_LIMIT_EXCEEDED)
+                } else {
+                    self.aer_count += 1;
+                    return None;
+                }
+            }
+            _ => {
+                warn!("Unsupported nvme admin command 0x{:x}", cmd.opcode);
+                Err(NVME_INVALID_OPCODE | NVME_DNR)
+            }
+        };
+        Some(ret)
+    }
+
+    fn check_ioq_id(&self, qid: u32) -> std::result::Result<usize, u16> {
+        if qid == 0 || qid > self.max_ioqs as u32 {
+            return Err(NVME_INVALID_QID | NVME_DNR);
+        }
+        Ok(qid as usize)
+    }
+
+    fn check_queue_attr(&self, size: u32, dma_addr: u64) -> std::result::Result<(), u16> {
+        if !(2..=NVME_MAX_QUEUE_ENTRIES).contains(&size) {
+            return Err(NVME_MAX_QSIZE_EXCEEDED | NVME_DNR);
+        }
+        if dma_addr == 0 || dma_addr & (self.page_size - 1) != 0 {
+            return Err(NVME_INVALID_FIELD | NVME_DNR);
+        }
+        Ok(())
+    }
+
+    fn create_cq(&mut self, cmd: &NvmeCmd) -> CmdResult {
+        let qid = self.check_ioq_id(cmd.cdw10 & 0xffff)?;
+        if self.cqs[qid].is_some() {
+            return Err(NVME_INVALID_QID | NVME_DNR);
+        }
+        let size = (cmd.cdw10 >> 16) + 1;
+        self.check_queue_attr(size, cmd.dptr[0])?;
+        // Only physically contiguous queue is supported.
+        if cmd.cdw11 & 1 == 0 {
+            return Err(NVME_INVALID_FIELD | NVME_DNR);
+        }
+        let vector = (cmd.cdw11 >> 16) as u16;
+        if vector > self.max_ioqs {
+            return Err(NVME_INVALID_IRQ_VECTOR | NVME_DNR);
+        }
+        let irq_enabled = cmd.cdw11 & 2 != 0;
+        self.new_cq(qid as u16, cmd.dptr[0], size, vector, irq_enabled);
+        Ok(0)
+    }
+
+    fn create_sq(&mut self, cmd: &NvmeCmd) -> CmdResult {
+        let qid = self.check_ioq_id(cmd.cdw10 & 0xffff)?;
+        if self.sqs[qid].is_some() {
+            return Err(NVME_INVALID_QID | NVME_DNR);
+        }
+        let cqid = cmd.cdw11 >> 16;
+        if cqid == 0 || cqid > self.max_ioqs as u32 || self.cqs[cqid as usize].is_none() {
+            return Err(NVME_INVALID_CQID | NVME_DNR);
+        }
+        let size = (cmd.cdw10 >> 16) + 1;
+        self.check_queue_attr(size, cmd.dptr[0])?;
+        if cmd.cdw11 & 1 == 0 {
+            return Err(NVME_INVALID_FIELD | NVME_DNR);
+        }
+        self.new_sq(qid as u16, cmd.dptr[0], size, cqid as u16);
+        Ok(0)
+    }
+
+    fn delete_sq(&mut self, cmd: &NvmeCmd) -> CmdResult {
+        let qid = self.check_ioq_id(cmd.cdw10 & 0xffff)?;
+        let sq = self.sqs[qid].take().ok_or(NVME_INVALID_QID | NVME_DNR)?;
+        // The commands in flight are still completed to the completion queue.
+        sq.cq.lock().unwrap().sq_refs -= 1;
+        Ok(0)
+    }
+
+    fn delete_cq(&mut self, cmd: &NvmeCmd) -> CmdResult {
+        let qid = self.check_ioq_id(cmd.cdw10 & 0xffff)?;
+        let cq = self.cqs[qid].as_ref().ok_or(NVME_INVALID_QID | NVME_DNR)?;
+        let mut locked_cq = cq.lock().unwrap();
+        if locked_cq.sq_refs != 0 {
+            return Err(NVME_INVALID_QUEUE_DEL | NVME_DNR);
+        }
+        locked_cq.deleted = true;
+        drop(locked_cq);
+        self.cqs[qid] = None;
+        Ok(0)
+    }
+
+    fn identify(&self, cmd: &NvmeCmd) -> CmdResult {
+        let data = match cmd.cdw10 & 0xff {
+            NVME_ID_CNS_NS => {
+                if cmd.nsid != NVME_NSID && cmd.nsid != NVME_NSID_BROADCAST {
+                    return Err(NVME_INVALID_NSID | NVME_DNR);
+                }
+                self.identify_ns()
+            }
+            NVME_ID_CNS_CTRL => self.identify_ctrl(),
+            NVME_ID_CNS_NS_ACTIVE_LIST => {
+                if cmd.nsid >= NVME_NSID_BROADCAST - 1 {
+                    return Err(NVME_INVALID_NSID | NVME_DNR);
+                }
+                let mut data = vec![0_u8; NVME_IDENTIFY_DATA_SIZE];
+                if cmd.nsid < NVME_NSID {
+                    LittleEndian::write_u32(&mut data[0..4], NVME_NSID);
+                }
+                data
+            }
+            NVME_ID_CNS_NS_DESC_LIST => {
+                if cmd.nsid != NVME_NSID {
+                    return Err(NVME_INVALID_NSID | NVME_DNR);
+                }
+                // No namespace identification descriptor.
+                vec![0_u8; NVME_IDENTIFY_DATA_SIZE]
+            }
+            cns => {
+                warn!("Unsupported nvme identify cns 0x{:x}", cns);
+                return Err(NVME_INVALID_FIELD | NVME_DNR);
+            }
+        };
+        self.write_data(cmd, &data)
+    }
+
+    fn identify_ctrl(&self) -> Vec<u8> {
+        let mut id = vec![0_u8; NVME_IDENTIFY_DATA_SIZE];
+        LittleEndian::write_u16(&mut id[0..2], PCI_VENDOR_ID_REDHAT);
+        LittleEndian::write_u16(&mut id[2..4], 0x1af4);
+        write_padded_str(&mut id[4..24], &self.serial);
+        write_padded_str(&mut id[24..64], "StratoVirt NVMe Ctrl");
+        write_padded_str(&mut id[64..72], env!("CARGO_PKG_VERSION"));
+        // Recommended arbitration burst.
+        id[72] = 6;
+        // IEEE OUI identifier.
+        id[73..76].copy_from_slice(&[0x00, 0x54, 0x52]);
+        id[77] = NVME_MDTS;
+        LittleEndian::write_u32(&mut id[80..84], NVME_VERSION);
+        // Controller type is IO controller.
+        id[111] = 1;
+        // Abort command limit and async event request limit.
+        id[258] = 3;
+        id[259] = NVME_AERL;
+        // One read-only firmware slot.
+        id[260] = 0x3;
+        // Warning and critical composite temperature threshold.
+        LittleEndian::write_u16(&mut id[266..268], 0x157);
+        LittleEndian::write_u16(&mut id[268..270], 0x175);
+        id[512] = (NVME_SQES_SHIFT << 4 | NVME_SQES_SHIFT) as u8;
+        id[513] = (NVME_CQES_SHIFT << 4 | NVME_CQES_SHIFT) as u8;
+        // Number of namespaces.
+        LittleEndian::write_u32(&mut id[516..520], 1);
+        // Optional NVM commands: dataset management and write zeroes.
+        LittleEndian::write_u16(&mut id[520..522], 1 << 2 | 1 << 3);
+        // Volatile write cache is present.
+        id[525] = 1;
+        // SGL is supported without the dword alignment.
+        LittleEndian::write_u32(&mut id[536..540], 1);
+        write_padded_str(
+            &mut id[768..1024],
+            &format!("nqn.2023-01.org.openeuler.stratovirt:{}", self.serial),
+        );
+        // Power state 0: max power 25W, entry latency 16us and exit latency 4us.
+        LittleEndian::write_u16(&mut id[2048..2050], 0x9c4);
+        LittleEndian::write_u32(&mut id[2052..2056], 0x10);
+        LittleEndian::write_u32(&mut id[2056..2060], 0x4);
+        id
+    }
+
+    fn identify_ns(&self) -> Vec<u8> {
+        let mut id = vec![0_u8; NVME_IDENTIFY_DATA_SIZE];
+        // Namespace size, capacity and utilization.
+        LittleEndian::write_u64(&mut id[0..8], self.nlbas);
+        LittleEndian::write_u64(&mut id[8..16], self.nlbas);
+        LittleEndian::write_u64(&mut id[16..24], self.nlbas);
+        if self.discard {
+            // Thin provisioning, and the deallocate bit of write zeroes is supported.
+            id[24] = 1;
+            id[33] = 1 << 3;
+        }
+        // Write protected.
+        id[99] = self.read_only as u8;
+        // LBA format 0: no metadata, and the data size is 2^lba_shift.
+        LittleEndian::write_u32(&mut id[128..132], self.lba_shift << 16);
+        id
+    }
+
+    fn get_log_page(&self, cmd: &NvmeCmd) -> CmdResult {
+        let lid = cmd.cdw10 & 0xff;
+        let numd = ((cmd.cdw11 & 0xffff) << 16 | cmd.cdw10 >> 16) as u64 + 1;
+        let offset = cmd.cdw12 as u64 | (cmd.cdw13 as u64) << 32;
+        if !offset.is_multiple_of(4) {
+            return Err(NVME_INVALID_FIELD | NVME_DNR);
+        }
+        let log = match lid {
+            NVME_LOG_ERROR => vec![0_u8; 64],
+            NVME_LOG_SMART => {
+                let mut log = vec![0_u8; 512];
+                // Composite temperature, available spare and spare threshold.
+                LittleEndian::write_u16(&mut log[1..3], 0x143);
+                log[3] = 100;
+                log[4] = 10;
+                log
+            }
+            NVME_LOG_FW_SLOT => {
+                let mut log = vec![0_u8; 512];
+                // Active firmware is in slot 1.
+                log[0] = 1;
+                write_padded_str(&mut log[8..16], env!("CARGO_PKG_VERSION"));
+                log
+            }
+            _ => {
+                warn!("Unsupported nvme log page 0x{:x}", lid);
+                return Err(NVME_INVALID_LOG_ID | NVME_DNR);
+            }
+        };
+        if offset > log.len() as u64 {
+            return Err(NVME_INVALID_FIELD | NVME_DNR);
+        }
+        let len = min(numd * 4, log.len() as u64 - offset) as usize;
+        let start = offset as usize;
+        self.write_data(cmd, &log[start..start + len])
+    }
+
+    fn num_queues_result(&self) -> u32 {
+        let nr = self.max_ioqs as u32 - 1;
+        nr << 16 | nr
+    }
+
+    fn get_features(&self, cmd: &NvmeCmd) -> CmdResult {
+        match cmd.cdw10 & 0xff {
+            NVME_FEAT_ARBITRATION => Ok(self.features.arbitration),
+            NVME_FEAT_POWER_MGMT | NVME_FEAT_ERR_RECOVERY | NVME_FEAT_WRITE_ATOMICITY => Ok(0),
+            NVME_FEAT_TEMP_THRESH => Ok(self.features.temp_thresh),
+            NVME_FEAT_VOLATILE_WC => Ok(self.features.volatile_wc),
+            NVME_FEAT_NUM_QUEUES => Ok(self.num_queues_result()),
+            NVME_FEAT_IRQ_COALESCE => Ok(self.features.irq_coalesce),
+            NVME_FEAT_IRQ_CONFIG => {
+                let vector = cmd.cdw11 & 0xffff;
+                if vector > self.max_ioqs as u32 {
+                    return Err(NVME_INVALID_FIELD | NVME_DNR);
+                }
+                Ok(vector)
+            }
+            NVME_FEAT_ASYNC_EVENT => Ok(self.features.async_config),
+            fid => {
+                warn!("Unsupported nvme feature 0x{:x}", fid);
+                Err(NVME_INVALID_FIELD | NVME_DNR)
+            }
+        }
+    }
+
+    fn set_features(&mut self, cmd: &NvmeCmd) -> CmdResult {
+        if cmd.cdw10 & NVME_FEAT_SAVE != 0 {
+            return Err(NVME_FEAT_NOT_SAVEABLE | NVME_DNR);
+        }
+        match cmd.cdw10 & 0xff {
+            NVME_FEAT_ARBITRATION => self.features.arbitration = cmd.cdw11,
+            NVME_FEAT_POWER_MGMT => {
+                // Only power state 0 is supported.
+                if cmd.cdw11 & 0x1f != 0 {
+                    return Err(NVME_INVALID_FIELD | NVME_DNR);
+                }
+            }
+            NVME_FEAT_TEMP_THRESH => self.features.temp_thresh = cmd.cdw11 & 0xffff,
+            NVME_FEAT_ERR_RECOVERY | NVME_FEAT_IRQ_CONFIG | NVME_FEAT_WRITE_ATOMICITY => (),
+            NVME_FEAT_VOLATILE_WC => self.features.volatile_wc = cmd.cdw11 & 1,
+            NVME_FEAT_NUM_QUEUES => {
+                if cmd.cdw11 & 0xffff == 0xffff || cmd.cdw11 >> 16 == 0xffff {
+                    return Err(NVME_INVALID_FIELD | NVME_DNR);
+                }
+                return Ok(self.num_queues_result());
+            }
+            NVME_FEAT_IRQ_COALESCE => self.features.irq_coalesce = cmd.cdw11 & 0xffff,
+            NVME_FEAT_ASYNC_EVENT => self.features.async_config = cmd.cdw11,
+            fid => {
+                warn!("Unsupported nvme feature 0x{:x}", fid);
+                return Err(NVME_INVALID_FIELD | NVME_DNR);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Returns the status if the command is completed right now.
+    fn io_cmd(&mut self, cmd: &NvmeCmd, req: &Arc<NvmeRequest>) -> Option<u16> {
+        if cmd.nsid != NVME_NSID
+            && !(cmd.opcode == NVME_CMD_FLUSH && cmd.nsid == NVME_NSID_BROADCAST)
+        {
+            return Some(NVME_INVALID_NSID | NVME_DNR);
+        }
+        // It's realized before the controller is enabled.
+        let backend = self.block_backend.clone().unwrap();
+        let mut locked_backend = backend.lock().unwrap();
+        let cb = NvmeCompleteCb { req: req.clone() };
+        let ret = match cmd.opcode {
+            NVME_CMD_FLUSH => locked_backend.datasync(cb),
+            NVME_CMD_READ | NVME_CMD_WRITE => {
+                if cmd.opcode == NVME_CMD_WRITE && self.read_only {
+                    return Some(NVME_NS_WRITE_PROTECTED | NVME_DNR);
+                }
+                let (offset, nbytes) = match self.lba_range(cmd) {
+                    Ok(range) => range,
+                    Err(status) => return Some(status),
+                };
+                if nbytes > (1 << (NVME_MDTS as u32 + NVME_MIN_PAGE_SHIFT)) {
+                    return Some(NVME_INVALID_FIELD | NVME_DNR);
+                }
+                let iovecs = match self.map_data(cmd, nbytes) {
+                    Ok(iovecs) => iovecs,
+                    Err(status) => return Some(status),
+                };
+                if cmd.opcode == NVME_CMD_READ {
+                    locked_backend.read_vectored(iovecs, offset, cb)
+                } else {
+                    locked_backend.write_vectored(iovecs, offset, cb)
+                }
+            }
+            NVME_CMD_WRITE_ZEROES => {
+                if self.read_only {
+                    return Some(NVME_NS_WRITE_PROTECTED | NVME_DNR);
+                }
+                let (offset, nbytes) = match self.lba_range(cmd) {
+                    Ok(range) => range,
+                    Err(status) => return Some(status),
+                };
+                let unmap = self.discard && cmd.cdw12 & NVME_WZ_DEAC != 0;
+                locked_backend.write_zeroes(offset, nbytes, cb, unmap)
+            }
+            NVME_CMD_DSM => return self.dsm(cmd, req, &mut *locked_backend),
+            _ => {
+                warn!("Unsupported nvme io command 0x{:x}", cmd.opcode);
+                return Some(NVME_INVALID_OPCODE | NVME_DNR);
+            }
+        };
+        match ret {
+            Ok(()) => None,
+            Err(e) => {
+                error!("Failed to submit nvme command 0x{:x}, {:?}", cmd.opcode, e);
+                Some(NVME_INTERNAL_ERROR)
+            }
+        }
+    }
+
+    fn dsm(
+        &self,
+        cmd: &NvmeCmd,
+        req: &Arc<NvmeRequest>,
+        backend: &mut dyn BlockDriverOps<NvmeCompleteCb>,
+    ) -> Option<u16> {
+        // Only the deallocate attribute takes effect.
+        if cmd.cdw11 & NVME_DSMGMT_AD == 0 {
+            return Some(NVME_SUCCESS);
+        }
+        if self.read_only {
+            return Some(NVME_NS_WRITE_PROTECTED | NVME_DNR);
+        }
+        let nr = (cmd.cdw10 & 0xff) as usize + 1;
+        let mut buf = vec![0_u8; nr * NVME_DSM_RANGE_SIZE];
+        if let Err(status) = self.read_data(cmd, &mut buf) {
+            return Some(status);
+        }
+        let mut ranges = Vec::new();
+        for range in buf.chunks(NVME_DSM_RANGE_SIZE) {
+            let nlb = LittleEndian::read_u32(&range[4..8]) as u64;
+            let slba = LittleEndian::read_u64(&range[8..16]);
+            if slba.checked_add(nlb).is_none_or(|end| end > self.nlbas) {
+                return Some(NVME_LBA_RANGE | NVME_DNR);
+            }
+            if nlb != 0 {
+                ranges.push(((slba << self.lba_shift) as usize, nlb << self.lba_shift));
+            }
+        }
+        // Deallocation is advisory, so it's done without discarding the data.
+        if !self.discard || ranges.is_empty() {
+            return Some(NVME_SUCCESS);
+        }
+
+        req.remaining.store(ranges.len() as u32, Ordering::SeqCst);
+        for (offset, nbytes) in ranges {
+            let cb = NvmeCompleteCb { req: req.clone() };
+            if let Err(e) = backend.discard(offset, nbytes, cb) {
+                error!("Failed to discard nvme range, {:?}", e);
+                req.aio_done(NVME_INTERNAL_ERROR);
+            }
+        }
+        None
+    }
+
+    /// Returns the offset and length in bytes of the LBA range in the command.
+    fn lba_range(&self, cmd: &NvmeCmd) -> std::result::Result<(usize, u64), u16> {
+        let slba = cmd.cdw10 as u64 | (cmd.cdw11 as u64) << 32;
+        let nlb = (cmd.cdw12 & 0xffff) as u64 + 1;
+        if slba.checked_add(nlb).is_none_or(|end| end > self.nlbas) {
+            return Err(NVME_LBA_RANGE | NVME_DNR);
+        }
+        Ok(((slba << self.lba_shift) as usize, nlb << self.lba_shift))
+    }
+
+    fn write_data(&self, cmd: &NvmeCmd, data: &[u8]) -> CmdResult {
+        let iovecs = self.map_data(cmd, data.len() as u64)?;
+        iov_from_buf_direct(&iovecs, data).map_err(|_| NVME_DATA_TRANSFER_ERROR)?;
+        Ok(0)
+    }
+
+    fn read_data(&self, cmd: &NvmeCmd, data: &mut [u8]) -> std::result::Result<(), u16> {
+        let iovecs = self.map_data(cmd, data.len() as u64)?;
+        iov_to_buf_direct(&iovecs, 0, data).map_err(|_| NVME_DATA_TRANSFER_ERROR)?;
+        Ok(())
+    }
+
+    /// Map the data pointer of the command to the host iovecs.
+    fn map_data(&self, cmd: &NvmeCmd, len: u64) -> std::result::Result<Vec<Iovec>, u16> {
+        match (cmd.flags >> NVME_PSDT_SHIFT) & 0x3 {
+            NVME_PSDT_PRP => self.map_prp(cmd.dptr[0], cmd.dptr[1], len),
+            NVME_PSDT_SGL_MPTR_CONTIGUOUS | NVME_PSDT_SGL_MPTR_SGL => {
+                let mut buf = [0_u8; NVME_SGL_DESC_SIZE];
+                buf[0..8].copy_from_slice(&cmd.dptr[0].to_le_bytes());
+                buf[8..16].copy_from_slice(&cmd.dptr[1].to_le_bytes());
+                self.map_sgl(NvmeSglDesc::from_bytes(&buf), len)
+            }
+            _ => Err(NVME_INVALID_FIELD | NVME_DNR),
+        }
+    }
+
+    fn map_guest(
+        &self,
+        addr: u64,
+        len: u64,
+        iovecs: &mut Vec<Iovec>,
+    ) -> std::result::Result<(), u16> {
+        let mapped = self
+            .mem_space
+            .get_address_map(GuestAddress(addr), len)
+            .map_err(|_| NVME_DATA_TRANSFER_ERROR)?;
+        for iov in mapped {
+            match iovecs.last_mut() {
+                Some(last) if last.iov_base + last.iov_len == iov.iov_base => {
+                    last.iov_len += iov.iov_len
+                }
+                _ => iovecs.push(iov),
+            }
+        }
+        Ok(())
+    }
+
+    fn map_prp(&self, prp1: u64, prp2: u64, len: u64) -> std::result::Result<Vec<Iovec>, u16> {
+        let page_size = self.page_size;
+        let mut iovecs = Vec::new();
+        if len == 0 {
+            return Ok(iovecs);
+        }
+        let first = min(len, page_size - (prp1 & (page_size - 1)));
+        self.map_guest(prp1, first, &mut iovecs)?;
+        let mut remaining = len - first;
+        if remaining == 0 {
+            return Ok(iovecs);
+        }
+        if remaining <= page_size {
+            if prp2 & (page_size - 1) != 0 {
+                return Err(NVME_INVALID_PRP_OFFSET | NVME_DNR);
+            }
+            self.map_guest(prp2, remaining, &mut iovecs)?;
+            return Ok(iovecs);
+        }
+
+        // PRP2 points to the PRP list, whose last entry points to the next list if the
+        // data doesn't fit in.
+        let mut list = prp2;
+        while remaining > 0 {
+            if list & 0x7 != 0 {
+                return Err(NVME_INVALID_PRP_OFFSET | NVME_DNR);
+            }
+            let nents = ((page_size - (list & (page_size - 1))) / 8) as usize;
+            let mut entries = vec![0_u8; nents * 8];
+            self.mem_space
+                .read(
+                    &mut entries.as_mut_slice(),
+                    GuestAddress(list),
+                    (nents * 8) as u64,
+                )
+                .map_err(|_| NVME_DATA_TRANSFER_ERROR)?;
+            for (i, entry) in entries.chunks(8).enumerate() {
+                let entry = LittleEndian::read_u64(entry);
+                if i == nents - 1 && remaining > page_size {
+                    if i == 0 {
+                        // The list makes no progress.
+                        return Err(NVME_INVALID_FIELD | NVME_DNR);
+                    }
+                    list = entry;
+                    break;
+                }
+                if entry & (page_size - 1) != 0 {
+                    return Err(NVME_INVALID_PRP_OFFSET | NVME_DNR);
+                }
+                let chunk = min(page_size, remaining);
+                self.map_guest(entry, chunk, &mut iovecs)?;
+                remaining -= chunk;
+                if remaining == 0 {
+                    break;
+                }
+            }
+        }
+        Ok(iovecs)
+    }
+
+    fn map_sgl(&self, first: NvmeSglDesc, len: u64) -> std::result::Result<Vec<Iovec>, u16> {
+        let mut iovecs = Vec::new();
+        let mut remaining = len;
+        let mut descs = vec![first];
+        let mut count = 0;
+        loop {
+            let mut segment = None;
+            for (i, desc) in descs.iter().enumerate() {
+                // The subtype must be the address.
+                if desc.sgl_type & 0xf != 0 {
+                    return Err(NVME_SGL_DESCR_TYPE_INVALID | NVME_DNR);
+                }
+                match desc.sgl_type >> 4 {
+                    NVME_SGL_TYPE_DATA_BLOCK => {
+                        let chunk = min(desc.len as u64, remaining);
+                        if chunk != 0 {
+                            self.map_guest(desc.addr, chunk, &mut iovecs)?;
+                            remaining -= chunk;
+                        }
+                    }
+                    NVME_SGL_TYPE_SEGMENT | NVME_SGL_TYPE_LAST_SEGMENT if i == descs.len() - 1 => {
+                        segment = Some(*desc);
+                    }
+                    NVME_SGL_TYPE_SEGMENT | NVME_SGL_TYPE_LAST_SEGMENT => {
+                        return Err(NVME_INVALID_SGL_SEG_DESCR | NVME_DNR);
+                    }
+                    _ => return Err(NVME_SGL_DESCR_TYPE_INVALID | NVME_DNR),
+                }
+            }
+            let segment = match segment {
+                Some(segment) => segment,
+                None => break,
+            };
+            let nr = segment.len as usize / NVME_SGL_DESC_SIZE;
+            if nr == 0 || !(segment.len as usize).is_multiple_of(NVME_SGL_DESC_SIZE) {
+                return Err(NVME_INVALID_SGL_SEG_DESCR | NVME_DNR);
+            }
+            count += nr;
+            if count > NVME_MAX_SGL_DESCS {
+                return Err(NVME_INVALID_FIELD | NVME_DNR);
+            }
+            let mut buf = vec![0_u8; segment.len as usize];
+            self.mem_space
+                .read(
+                    &mut buf.as_mut_slice(),
+                    GuestAddress(segment.addr),
+                    segment.len as u64,
+                )
+                .map_err(|_| NVME_DATA_TRANSFER_ERROR)?;
+            descs = buf
+                .chunks(NVME_SGL_DESC_SIZE)
+                .map(NvmeSglDesc::from_bytes)
+                .collect();
+        }
+        if remaining != 0 {
+            return Err(NVME_DATA_SGL_LEN_INVALID | NVME_DNR);
+        }
+        Ok(iovecs)
+    }
+}
+
+/// Write the ASCII string padded with spaces.
+fn write_padded_str(buf: &mut [u8], s: &str) {
+    buf.fill(b' ');
+    let len = min(buf.len(), s.len());
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address_space::{HostMemMapping, Region};
+    use machine_manager::event_loop::EventLoop;
+    use util::aio::AioEngine;
+
+    const ASQ_ADDR: u64 = 0x10000;
+    const ACQ_ADDR: u64 = 0x20000;
+    const IOSQ_ADDR: u64 = 0x30000;
+    const IOCQ_ADDR: u64 = 0x40000;
+    const DATA_ADDR: u64 = 0x50000;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36, "root");
+        let sys_space = AddressSpace::new(root, "sys_space").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x100_0000, None, false, false, false)
+                .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone(), "region_1"),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn nvme_config(path: &str) -> NvmeConfig {
+        NvmeConfig {
+            id: "nvme0".to_string(),
+            serial: "deadbeef".to_string(),
+            iothread: None,
+            queues: 2,
+            drive: DriveConfig {
+                id: "drive0".to_string(),
+                path_on_host: path.to_string(),
+                direct: false,
+                aio: AioEngine::Off,
+                ..Default::default()
+            },
+            key_secret: None,
+        }
+    }
+
+    fn new_ctrl(mem_space: &Arc<AddressSpace>, path: &str) -> NvmeCtrl {
+        let mut drive_files = HashMap::new();
+        if !path.is_empty() {
+            VmConfig::add_drive_file(&mut drive_files, "drive0", path, false, false).unwrap();
+        }
+        NvmeCtrl::new(
+            &nvme_config(path),
+            mem_space,
+            Arc::new(Mutex::new(drive_files)),
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+        )
+    }
+
+    fn submit(
+        ctrl: &mut NvmeCtrl,
+        mem_space: &AddressSpace,
+        qid: u64,
+        idx: u64,
+        cmd: &NvmeCmd,
+    ) -> NvmeCqe {
+        let (sq_addr, cq_addr, size) = if qid == 0 {
+            (ASQ_ADDR, ACQ_ADDR, 8)
+        } else {
+            (IOSQ_ADDR, IOCQ_ADDR, 4)
+        };
+        let next = ((idx + 1) % size) as u32;
+        mem_space
+            .write_object(cmd, GuestAddress(sq_addr + idx * 64))
+            .unwrap();
+        assert!(ctrl.write_doorbell(qid * 8, &next.to_le_bytes()));
+        ctrl.process_queues();
+        let cqe = mem_space
+            .read_object::<NvmeCqe>(GuestAddress(cq_addr + idx * 16))
+            .unwrap();
+        assert!(ctrl.write_doorbell(qid * 8 + 4, &next.to_le_bytes()));
+        cqe
+    }
+
+    #[test]
+    fn test_nvme_map_prp() {
+        let mem_space = address_space_init();
+        let ctrl = new_ctrl(&mem_space, "");
+
+        // The data is in one page.
+        let iovecs = ctrl.map_prp(0x1100, 0, 0x200).unwrap();
+        assert_eq!(iovecs.len(), 1);
+        assert_eq!(iovecs[0].iov_len, 0x200);
+        // The data crosses the page boundary, PRP2 is the second page.
+        let iovecs = ctrl.map_prp(0x1800, 0x8000, 0x1000).unwrap();
+        assert_eq!(iovecs.len(), 2);
+        assert_eq!(iovecs[0].iov_len, 0x800);
+        assert_eq!(iovecs[1].iov_len, 0x800);
+        assert!(ctrl.map_prp(0x1800, 0x8010, 0x1000).is_err());
+
+        // PRP2 points to the list, and the adjacent pages are merged.
+        let list: [u64; 3] = [0x9000, 0xa000, 0xc000];
+        for (i, entry) in list.iter().enumerate() {
+            mem_space
+                .write_object(entry, GuestAddress(0x4000 + i as u64 * 8))
+                .unwrap();
+        }
+        let iovecs = ctrl.map_prp(0x8000, 0x4000, 0x3800).unwrap();
+        assert_eq!(iovecs.len(), 2);
+        assert_eq!(iovecs[0].iov_len, 0x3000);
+        assert_eq!(iovecs[1].iov_len, 0x800);
+        // The entry in the list must be page aligned.
+        mem_space
+            .write_object(&0xa010_u64, GuestAddress(0x4008))
+            .unwrap();
+        assert_eq!(
+            ctrl.map_prp(0x8000, 0x4000, 0x3800),
+            Err(NVME_INVALID_PRP_OFFSET | NVME_DNR)
+        );
+    }
+
+    #[test]
+    fn test_nvme_map_sgl() {
+        let mem_space = address_space_init();
+        let ctrl = new_ctrl(&mem_space, "");
+
+        // The last segment holds two data blocks.
+        let mut seg = [0_u8; 32];
+        LittleEndian::write_u64(&mut seg[0..8], 0x8000);
+        LittleEndian::write_u32(&mut seg[8..12], 0x200);
+        LittleEndian::write_u64(&mut seg[16..24], 0x9000);
+        LittleEndian::write_u32(&mut seg[24..28], 0x200);
+        mem_space
+            .write(&mut seg.as_ref(), GuestAddress(0x4000), 32)
+            .unwrap();
+        let desc = NvmeSglDesc {
+            addr: 0x4000,
+            len: 32,
+            sgl_type: NVME_SGL_TYPE_LAST_SEGMENT << 4,
+        };
+        let iovecs = ctrl.map_sgl(desc, 0x400).unwrap();
+        assert_eq!(iovecs.len(), 2);
+        assert_eq!(iovecs[1].iov_len, 0x200);
+        // The data blocks are shorter than the data.
+        assert_eq!(
+            ctrl.map_sgl(desc, 0x600),
+            Err(NVME_DATA_SGL_LEN_INVALID | NVME_DNR)
+        );
+        // The bit bucket descriptor is not supported.
+        let desc = NvmeSglDesc {
+            addr: 0,
+            len: 0x200,
+            sgl_type: 0x1 << 4,
+        };
+        assert_eq!(
+            ctrl.map_sgl(desc, 0x200),
+            Err(NVME_SGL_DESCR_TYPE_INVALID | NVME_DNR)
+        );
+    }
+
+    #[test]
+    fn test_nvme_admin_and_io() {
+        EventLoop::object_init(&None).unwrap();
+        let path = "/tmp/stratovirt_test_nvme.img";
+        let file = std::fs::File::create(path).unwrap();
+        file.set_len(0x10_0000).unwrap();
+        let mem_space = address_space_init();
+        let mut ctrl = new_ctrl(&mem_space, path);
+        ctrl.realize(None).unwrap();
+        assert_eq!(ctrl.nlbas, 0x800);
+
+        // Enable the controller with the admin queues of 8 entries.
+        let mut data = [0_u8; 8];
+        assert!(ctrl.read_reg(NVME_REG_CAP, &mut data));
+        assert_eq!(u64::from_le_bytes(data) & 0xffff, 2047);
+        assert!(ctrl.write_reg(NVME_REG_AQA, &0x0007_0007_u32.to_le_bytes()));
+        assert!(ctrl.write_reg(NVME_REG_ASQ, &ASQ_ADDR.to_le_bytes()));
+        assert!(ctrl.write_reg(NVME_REG_ACQ, &ACQ_ADDR.to_le_bytes()));
+        assert!(ctrl.write_reg(NVME_REG_CC, &0x0046_0001_u32.to_le_bytes()));
+        let mut csts = [0_u8; 4];
+        assert!(ctrl.read_reg(NVME_REG_CSTS, &mut csts));
+        assert_eq!(u32::from_le_bytes(csts), NVME_CSTS_RDY);
+
+        // Identify controller.
+        let mut cmd = NvmeCmd {
+            opcode: NVME_ADM_IDENTIFY,
+            cid: 1,
+            dptr: [DATA_ADDR, 0],
+            cdw10: NVME_ID_CNS_CTRL,
+            ..Default::default()
+        };
+        let cqe = submit(&mut ctrl, &mem_space, 0, 0, &cmd);
+        assert_eq!(cqe.cid, 1);
+        assert_eq!(cqe.sq_head, 1);
+        assert_eq!(cqe.status, 1);
+        let mut id = [0_u8; 24];
+        mem_space
+            .read(&mut id.as_mut(), GuestAddress(DATA_ADDR), 24)
+            .unwrap();
+        assert_eq!(LittleEndian::read_u16(&id[0..2]), PCI_VENDOR_ID_REDHAT);
+        assert_eq!(&id[4..24], b"deadbeef            ");
+
+        // Unsupported admin command.
+        cmd.opcode = 0x7f;
+        let cqe = submit(&mut ctrl, &mem_space, 0, 1, &cmd);
+        assert_eq!(cqe.status, (NVME_INVALID_OPCODE | NVME_DNR) << 1 | 1);
+
+        // Create the IO queue pair 1 of 4 entries with the interrupt vector 1.
+        cmd = NvmeCmd {
+            opcode: NVME_ADM_CREATE_SQ,
+            cid: 2,
+            dptr: [IOSQ_ADDR, 0],
+            cdw10: 3 << 16 | 1,
+            cdw11: 1 << 16 | 1,
+            ..Default::default()
+        };
+        // The completion queue is not created.
+        let cqe = submit(&mut ctrl, &mem_space, 0, 2, &cmd);
+        assert_eq!(cqe.status, (NVME_INVALID_CQID | NVME_DNR) << 1 | 1);
+        let create_cq = NvmeCmd {
+            opcode: NVME_ADM_CREATE_CQ,
+            cid: 3,
+            dptr: [IOCQ_ADDR, 0],
+            cdw10: 3 << 16 | 1,
+            cdw11: 1 << 16 | 0x3,
+            ..Default::default()
+        };
+        assert_eq!(submit(&mut ctrl, &mem_space, 0, 3, &create_cq).status, 1);
+        assert_eq!(submit(&mut ctrl, &mem_space, 0, 4, &cmd).status, 1);
+
+        // Write 4 blocks, and read them back.
+        let buf: Vec<u8> = (0..0x800).map(|i| i as u8).collect();
+        mem_space
+            .write(&mut buf.as_slice(), GuestAddress(DATA_ADDR), 0x800)
+            .unwrap();
+        cmd = NvmeCmd {
+            opcode: NVME_CMD_WRITE,
+            cid: 4,
+            nsid: NVME_NSID,
+            dptr: [DATA_ADDR, 0],
+            cdw10: 8,
+            cdw12: 3,
+            ..Default::default()
+        };
+        assert_eq!(submit(&mut ctrl, &mem_space, 1, 0, &cmd).status, 1);
+        cmd.opcode = NVME_CMD_READ;
+        cmd.dptr[0] = DATA_ADDR + 0x1000;
+        let cqe = submit(&mut ctrl, &mem_space, 1, 1, &cmd);
+        assert_eq!(cqe.status, 1);
+        assert_eq!(cqe.sq_id, 1);
+        let mut read_buf = vec![0_u8; 0x800];
+        mem_space
+            .read(
+                &mut read_buf.as_mut_slice(),
+                GuestAddress(DATA_ADDR + 0x1000),
+                0x800,
+            )
+            .unwrap();
+        assert_eq!(buf, read_buf);
+
+        // Out of the namespace.
+        cmd.cdw10 = 0x7fe;
+        let cqe = submit(&mut ctrl, &mem_space, 1, 2, &cmd);
+        assert_eq!(cqe.status, (NVME_LBA_RANGE | NVME_DNR) << 1 | 1);
+        // Invalid namespace.
+        cmd.nsid = 2;
+        let cqe = submit(&mut ctrl, &mem_space, 1, 3, &cmd);
+        assert_eq!(cqe.status, (NVME_INVALID_NSID | NVME_DNR) << 1 | 1);
+        // The phase tag is inverted after the queue wraps.
+        cmd.nsid = NVME_NSID;
+        cmd.cdw10 = 0;
+        assert_eq!(submit(&mut ctrl, &mem_space, 1, 0, &cmd).status, 0);
+
+        // The completion queue can't be deleted before the submission queue.
+        cmd = NvmeCmd {
+            opcode: NVME_ADM_DELETE_CQ,
+            cdw10: 1,
+            ..Default::default()
+        };
+        let cqe = submit(&mut ctrl, &mem_space, 0, 5, &cmd);
+        assert_eq!(cqe.status, (NVME_INVALID_QUEUE_DEL | NVME_DNR) << 1 | 1);
+
+        // Disable the controller.
+        assert!(ctrl.write_reg(NVME_REG_CC, &0_u32.to_le_bytes()));
+        assert!(ctrl.read_reg(NVME_REG_CSTS, &mut csts));
+        assert_eq!(u32::from_le_bytes(csts), 0);
+        assert!(ctrl.sqs.iter().all(|sq| sq.is_none()));
+
+        ctrl.unrealize().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::max;
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{bail, Context, Result};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::nvme_ctrl::{NvmeCtrl, NVME_DOORBELL_OFFSET};
+use crate::pci::config::{
+    PciConfig, RegionType, DEVICE_ID, MINIMUM_BAR_SIZE_FOR_MMIO, PCI_CLASS_STORAGE_EXPRESS,
+    PCI_CONFIG_SPACE_SIZE, PCI_DEVICE_ID_REDHAT_NVME, PCI_VENDOR_ID_REDHAT, REVISION_ID,
+    SUB_CLASS_CODE, VENDOR_ID,
+};
+use crate::pci::msix::update_dev_id;
+use crate::pci::{init_intx, init_msix, le_write_u16, PciBus, PciDevBase, PciDevOps};
+use crate::{Device, DeviceBase};
+use address_space::{AddressSpace, GuestAddress, Region, RegionOps};
+use machine_manager::config::{DriveFile, NvmeConfig};
+use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Programming interface of NVM Express.
+const PCI_CLASS_PI: usize = 0x09;
+const PCI_CLASS_PI_NVME: u8 = 0x02;
+
+/// Registers offset.
+/// 0x0    0x1000      0x2000        0x3000       0x4000
+/// | regs | doorbells | MSIX table  | MSIX PBA   |
+const NVME_PCI_BAR_SIZE: u64 = 0x4000;
+const NVME_PCI_REG_LENGTH: u64 = 0x1000;
+const NVME_PCI_DOORBELL_LENGTH: u64 = 0x1000;
+const NVME_MSIX_TABLE_OFFSET: u32 = 0x2000;
+const NVME_MSIX_PBA_OFFSET: u32 = 0x3000;
+
+/// NVMe controller which can be attached to PCI bus.
+pub struct NvmePciDevice {
+    base: PciDevBase,
+    ctrl: Arc<Mutex<NvmeCtrl>>,
+    dev_id: Arc<AtomicU16>,
+    mem_region: Region,
+    kick_evt: Arc<EventFd>,
+    iothread: Option<String>,
+    /// Number of the IO queue pairs.
+    queues: u16,
+    delete_evts: Vec<RawFd>,
+}
+
+impl NvmePciDevice {
+    pub fn new(
+        config: &NvmeConfig,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+        mem_space: &Arc<AddressSpace>,
+        drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    ) -> Self {
+        let kick_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        Self {
+            base: PciDevBase {
+                base: DeviceBase::new(config.id.clone(), false),
+                config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 1),
+                devfn,
+                parent_bus,
+            },
+            ctrl: Arc::new(Mutex::new(NvmeCtrl::new(
+                config,
+                mem_space,
+                drive_files,
+                kick_evt.clone(),
+            ))),
+            dev_id: Arc::new(AtomicU16::new(0)),
+            mem_region: Region::init_container_region(NVME_PCI_BAR_SIZE, "NvmePciContainer"),
+            kick_evt,
+            iothread: config.iothread.clone(),
+            queues: config.queues,
+            delete_evts: Vec::new(),
+        }
+    }
+
+    fn mem_region_init(&mut self) -> Result<()> {
+        let read_ctrl = self.ctrl.clone();
+        let write_ctrl = self.ctrl.clone();
+        let reg_ops = RegionOps {
+            read: Arc::new(
+                move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+                    read_ctrl.lock().unwrap().read_reg(offset, data)
+                },
+            ),
+            write: Arc::new(move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+                write_ctrl.lock().unwrap().write_reg(offset, data)
+            }),
+        };
+        let reg_region = Region::init_io_region(NVME_PCI_REG_LENGTH, reg_ops, "NvmePciRegRegion");
+        self.mem_region
+            .add_subregion(reg_region, 0)
+            .with_context(|| "Failed to register nvme register region.")?;
+
+        let doorbell_ctrl = self.ctrl.clone();
+        let doorbell_ops = RegionOps {
+            read: Arc::new(move |data: &mut [u8], _: GuestAddress, _: u64| -> bool {
+                // The doorbells are write only.
+                data.fill(0);
+                true
+            }),
+            write: Arc::new(move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+                doorbell_ctrl.lock().unwrap().write_doorbell(offset, data)
+            }),
+        };
+        let doorbell_region = Region::init_io_region(
+            NVME_PCI_DOORBELL_LENGTH,
+            doorbell_ops,
+            "NvmePciDoorbellRegion",
+        );
+        self.mem_region
+            .add_subregion(doorbell_region, NVME_DOORBELL_OFFSET)
+            .with_context(|| "Failed to register nvme doorbell region.")?;
+        Ok(())
+    }
+}
+
+impl Device for NvmePciDevice {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl PciDevOps for NvmePciDevice {
+    fn pci_base(&self) -> &PciDevBase {
+        &self.base
+    }
+
+    fn pci_base_mut(&mut self) -> &mut PciDevBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<()> {
+        self.init_write_mask(false)?;
+        self.init_write_clear_mask(false)?;
+        le_write_u16(
+            &mut self.base.config.config,
+            VENDOR_ID as usize,
+            PCI_VENDOR_ID_REDHAT,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            DEVICE_ID as usize,
+            PCI_DEVICE_ID_REDHAT_NVME,
+        )?;
+        self.base.config.config[REVISION_ID] = 0x2;
+        le_write_u16(
+            &mut self.base.config.config,
+            SUB_CLASS_CODE as usize,
+            PCI_CLASS_STORAGE_EXPRESS,
+        )?;
+        self.base.config.config[PCI_CLASS_PI] = PCI_CLASS_PI_NVME;
+
+        #[cfg(target_arch = "aarch64")]
+        self.base.config.set_interrupt_pin();
+
+        self.dev_id.store(self.base.devfn as u16, Ordering::SeqCst);
+        self.ctrl.lock().unwrap().realize(self.iothread.clone())?;
+        self.mem_region_init()?;
+
+        let handler = Arc::new(Mutex::new(NvmeIoHandler {
+            ctrl: self.ctrl.clone(),
+            kick_evt: self.kick_evt.clone(),
+        }));
+        register_event_helper(
+            EventNotifierHelper::internal_notifiers(handler),
+            self.iothread.as_ref(),
+            &mut self.delete_evts,
+        )?;
+
+        // One vector for the admin queue and one for each IO queue.
+        init_msix(
+            0_usize,
+            self.queues as u32 + 1,
+            &mut self.base.config,
+            self.dev_id.clone(),
+            &self.base.base.id,
+            Some(&self.mem_region),
+            Some((NVME_MSIX_TABLE_OFFSET, NVME_MSIX_PBA_OFFSET)),
+        )?;
+
+        init_intx(
+            self.name(),
+            &mut self.base.config,
+            self.base.parent_bus.clone(),
+            self.base.devfn,
+        )?;
+
+        let mem_region_size = max(
+            NVME_PCI_BAR_SIZE.next_power_of_two(),
+            MINIMUM_BAR_SIZE_FOR_MMIO as u64,
+        );
+        self.base.config.register_bar(
+            0_usize,
+            self.mem_region.clone(),
+            RegionType::Mem64Bit,
+            false,
+            mem_region_size,
+        )?;
+
+        // It is safe to unwrap, because they are initialized in init_msix and init_intx.
+        let cloned_msix = self.base.config.msix.as_ref().unwrap().clone();
+        let cloned_intx = self.base.config.intx.as_ref().unwrap().clone();
+        let cloned_dev_id = self.dev_id.clone();
+        self.ctrl
+            .lock()
+            .unwrap()
+            .set_irq_callback(Arc::new(move |vector: u16, level: bool| {
+                let mut locked_msix = cloned_msix.lock().unwrap();
+                if locked_msix.enabled {
+                    if level {
+                        locked_msix.notify(vector, cloned_dev_id.load(Ordering::Acquire));
+                    }
+                    return;
+                }
+                cloned_intx.lock().unwrap().notify(level as u8);
+            }));
+
+        // Attach to the PCI bus.
+        let pci_bus = self.base.parent_bus.upgrade().unwrap();
+        let mut locked_pci_bus = pci_bus.lock().unwrap();
+        let pci_device = locked_pci_bus.devices.get(&self.base.devfn);
+        match pci_device {
+            Some(device) => bail!(
+                "Devfn {:?} has been used by {:?}",
+                &self.base.devfn,
+                device.lock().unwrap().name()
+            ),
+            None => locked_pci_bus
+                .devices
+                .insert(self.base.devfn, Arc::new(Mutex::new(self))),
+        };
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        unregister_event_helper(self.iothread.as_ref(), &mut self.delete_evts)?;
+        self.ctrl.lock().unwrap().unrealize()
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        update_dev_id(&self.base.parent_bus, self.base.devfn, &self.dev_id);
+        let parent_bus = self.base.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+
+        self.base.config.write(
+            offset,
+            data,
+            self.dev_id.load(Ordering::Acquire),
+            #[cfg(target_arch = "x86_64")]
+            Some(&locked_parent_bus.io_region),
+            Some(&locked_parent_bus.mem_region),
+        );
+    }
+
+    fn reset(&mut self, _reset_child_device: bool) -> Result<()> {
+        self.ctrl.lock().unwrap().reset();
+        self.base.config.reset()?;
+        Ok(())
+    }
+}
+
+/// Fetch and execute the commands in the iothread when the doorbells are written.
+struct NvmeIoHandler {
+    ctrl: Arc<Mutex<NvmeCtrl>>,
+    kick_evt: Arc<EventFd>,
+}
+
+impl EventNotifierHelper for NvmeIoHandler {
+    fn internal_notifiers(io_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_io_handler = io_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_event, fd: RawFd| {
+            read_fd(fd);
+            let locked_handler = cloned_io_handler.lock().unwrap();
+            locked_handler.ctrl.lock().unwrap().process_queues();
+            None
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            io_handler.lock().unwrap().kick_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
@@ -13,7 +13,7 @@
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::{error, warn};
 
 use crate::pci::intx::Intx;
@@ -65,6 +65,12 @@ pub const PREF_MEMORY_BASE: u8 = 0x24;
 pub const PREF_MEMORY_LIMIT: u8 = 0x26;
 const ROM_ADDRESS_ENDPOINT: usize = 0x30;
 const ROM_ADDRESS_BRIDGE: usize = 0x38;
+/// Expansion ROM address decode enable.
+const ROM_ADDRESS_ENABLE: u32 = 0x01;
+/// Mask of expansion ROM base address.
+const ROM_ADDRESS_MASK: u32 = 0xffff_f800;
+/// Expansion ROM's minimum size shall be 2KB.
+pub const MINIMUM_ROM_SIZE: u64 = 0x800;
 
 /// 64-bit prefetchable memory addresses.
 pub const PREF_MEM_RANGE_64BIT: u8 = 0x01;
@@ -299,10 +305,16 @@ pub const PCI_EXP_HP_EV_CCI: u16 = PCI_EXP_SLTCTL_CCIE;
 
 // XHCI device id
 pub const PCI_DEVICE_ID_REDHAT_XHCI: u16 = 0x000d;
+// NVMe device id
+pub const PCI_DEVICE_ID_REDHAT_NVME: u16 = 0x0010;
 
 // Device classes and subclasses
+pub const PCI_CLASS_STORAGE_SATA: u16 = 0x0106;
+pub const PCI_CLASS_STORAGE_EXPRESS: u16 = 0x0108;
+pub const PCI_CLASS_NETWORK_ETHERNET: u16 = 0x0200;
 pub const PCI_CLASS_MEMORY_RAM: u16 = 0x0500;
 pub const PCI_CLASS_SERIAL_USB: u16 = 0x0c03;
+pub const PCI_CLASS_SYSTEM_OTHER: u16 = 0x0880;
 
 /// Type of bar region.
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
@@ -397,6 +409,8 @@ pub struct PciConfig {
     pub pci_express_cap_offset: u16,
     /// INTx information.
     pub intx: Option<Arc<Mutex<Intx>>>,
+    /// Index of the expansion ROM in `bars`.
+    pub rom_bar_id: Option<usize>,
 }
 
 impl PciConfig {
@@ -430,6 +444,7 @@ impl PciConfig {
             msix: None,
             pci_express_cap_offset: PCI_CONFIG_HEAD_END as u16,
             intx: None,
+            rom_bar_id: None,
         }
     }
 
@@ -722,6 +737,13 @@ impl PciConfig {
     /// * `id` - Index of the BAR.
     pub fn get_bar_address(&self, id: usize) -> u64 {
         let command = le_read_u16(&self.config, COMMAND as usize).unwrap();
+        if self.rom_bar_id == Some(id) {
+            let rom_val = le_read_u32(&self.config, ROM_ADDRESS_ENDPOINT).unwrap();
+            if command & COMMAND_MEMORY_SPACE == 0 || rom_val & ROM_ADDRESS_ENABLE == 0 {
+                return BAR_SPACE_UNMAPPED;
+            }
+            return (rom_val & ROM_ADDRESS_MASK) as u64;
+        }
         let offset: usize = BAR_0 as usize + id * REG_SIZE;
         if self.config[offset] & BAR_IO_SPACE > 0 {
             if command & COMMAND_IO_SPACE == 0 {
@@ -794,6 +816,41 @@ impl PciConfig {
         Ok(())
     }
 
+    /// Register the expansion ROM of an endpoint. The ROM is kept as an extra
+    /// entry behind the standard BARs in PciConfig::bars.
+    ///
+    /// # Arguments
+    ///
+    /// * `region` - Region mapped for the ROM.
+    /// * `size` - Size of the ROM.
+    pub fn register_rom(&mut self, region: Region, size: u64) -> Result<()> {
+        if self.config[HEADER_TYPE as usize] & HEADER_TYPE_BRIDGE != 0 {
+            bail!("Expansion ROM of PCI bridge is not supported");
+        }
+        if self.rom_bar_id.is_some() {
+            bail!("Expansion ROM has already been registered");
+        }
+        if !size.is_power_of_two() || size < MINIMUM_ROM_SIZE || size > u32::MAX as u64 {
+            return Err(anyhow!(PciError::InvalidConf(
+                "Expansion ROM size".to_string(),
+                size.to_string(),
+            )));
+        }
+
+        let write_mask = (!(size - 1) as u32 & ROM_ADDRESS_MASK) | ROM_ADDRESS_ENABLE;
+        le_write_u32(&mut self.write_mask, ROM_ADDRESS_ENDPOINT, write_mask)?;
+        self.bars.push(Bar {
+            region_type: RegionType::Mem32Bit,
+            address: BAR_SPACE_UNMAPPED,
+            size,
+            region: Some(region),
+            parent_io_region: None,
+            parent_mem_region: None,
+        });
+        self.rom_bar_id = Some(self.bars.len() - 1);
+        Ok(())
+    }
+
     /// Unregister region in PciConfig::bars.
     ///
     /// # Arguments
@@ -1298,6 +1355,44 @@ mod tests {
         assert_eq!(pci_config.get_bar_address(2), MEM_BASE_ADDR_MASK);
     }
 
+    #[test]
+    fn test_register_rom() {
+        let read_ops = move |_data: &mut [u8], _addr: GuestAddress, _offset: u64| -> bool { true };
+        let write_ops = move |_data: &[u8], _addr: GuestAddress, _offset: u64| -> bool { true };
+        let region_ops = RegionOps {
+            read: Arc::new(read_ops),
+            write: Arc::new(write_ops),
+        };
+        let region = Region::init_io_region(0x1000, region_ops.clone(), "rom");
+        let mut pci_config = PciConfig::new(PCI_CONFIG_SPACE_SIZE, 3);
+
+        // ROM size is too small or not power of 2.
+        assert!(pci_config.register_rom(region.clone(), 0x400).is_err());
+        assert!(pci_config.register_rom(region.clone(), 0x1800).is_err());
+        assert!(pci_config.register_rom(region.clone(), 0x1000).is_ok());
+        assert_eq!(pci_config.rom_bar_id, Some(3));
+        // ROM can only be registered once.
+        assert!(pci_config.register_rom(region, 0x1000).is_err());
+
+        // Guest sizes the ROM by writing all 1s, the enable bit is kept.
+        let write_mask = le_read_u32(&pci_config.write_mask, ROM_ADDRESS_ENDPOINT).unwrap();
+        assert_eq!(write_mask, 0xffff_f001);
+        le_write_u32(&mut pci_config.config, ROM_ADDRESS_ENDPOINT, write_mask).unwrap();
+
+        // Memory space is disabled.
+        assert_eq!(pci_config.get_bar_address(3), BAR_SPACE_UNMAPPED);
+        le_write_u16(
+            &mut pci_config.config,
+            COMMAND as usize,
+            COMMAND_MEMORY_SPACE,
+        )
+        .unwrap();
+        assert_eq!(pci_config.get_bar_address(3), 0xffff_f000);
+        // ROM address decode is disabled.
+        le_write_u32(&mut pci_config.config, ROM_ADDRESS_ENDPOINT, 0xffff_f000).unwrap();
+        assert_eq!(pci_config.get_bar_address(3), BAR_SPACE_UNMAPPED);
+    }
+
     #[test]
     fn test_update_bar_mapping() {
         let read_ops = move |_data: &mut [u8], _addr: GuestAddress, _offset: u64| -> bool { true };
@@ -15,6 +15,7 @@ use std::sync::{Arc, Mutex, Weak};
 use anyhow::Result;
 use log::error;
 
+use crate::interrupt_stats::{register_interrupt_counter, InterruptCounter, InterruptType};
 use crate::pci::{swizzle_map_irq, PciBus, PciConfig, INTERRUPT_PIN, PCI_INTR_BASE, PCI_PIN_NUM};
 use util::test_helper::{is_test_enabled, trigger_intx};
 
@@ -52,16 +53,22 @@ pub struct Intx {
     pub enabled: bool,
     /// Interrupt info related to INTx.
     pub intx_state: Option<Arc<Mutex<PciIntxState>>>,
+    /// Counter of the asserted INTx interrupts.
+    interrupt_counter: Option<Arc<InterruptCounter>>,
 }
 
 impl Intx {
     pub fn new(name: String, irq_pin: u32, intx_state: Option<Arc<Mutex<PciIntxState>>>) -> Self {
+        let interrupt_counter = intx_state
+            .as_ref()
+            .map(|_| register_interrupt_counter(&name, InterruptType::Intx, irq_pin));
         Self {
             device_name: name,
             irq_pin,
             level: 0,
             enabled: true,
             intx_state,
+            interrupt_counter,
         }
     }
 
@@ -82,6 +89,11 @@ impl Intx {
         }
 
         self.change_irq_level(change);
+        if level == 1 {
+            if let Some(counter) = self.interrupt_counter.as_ref() {
+                counter.inc();
+            }
+        }
     }
 
     pub fn change_irq_level(&self, change: i8) {
@@ -114,6 +126,13 @@ impl Intx {
         };
     }
 
+    /// Get the gsi which the INTx is routed to, `None` if the INTx is not wired.
+    pub fn gsi(&self) -> Option<u32> {
+        self.intx_state
+            .as_ref()
+            .map(|state| state.lock().unwrap().gsi_base + self.irq_pin)
+    }
+
     pub fn reset(&mut self) {
         self.notify(0);
         self.enabled = true;
@@ -19,6 +19,7 @@ use anyhow::{bail, Context, Result};
 use log::{error, warn};
 use vmm_sys_util::eventfd::EventFd;
 
+use crate::interrupt_stats::{register_interrupt_counter, InterruptCounter, InterruptType};
 use crate::pci::config::{
     CapId, PciConfig, RegionType, MINIMUM_BAR_SIZE_FOR_MMIO, SECONDARY_BUS_NUM,
 };
@@ -27,6 +28,7 @@ use crate::pci::{
 };
 use address_space::{GuestAddress, Region, RegionOps};
 use hypervisor::kvm::{MsiVector, KVM_FDS};
+use hypervisor::{hypervisor, MsiMessage};
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -98,6 +100,10 @@ pub struct Msix {
     pub dev_id: Arc<AtomicU16>,
     /// Maintains a list of GSI with irqfds that are registered to kvm.
     gsi_msi_routes: HashMap<u16, GsiMsiRoute>,
+    /// Device id used in interrupt statistics.
+    name: String,
+    /// Counters of the interrupts injected through each vector.
+    interrupt_counters: HashMap<u16, Arc<InterruptCounter>>,
 }
 
 impl Msix {
@@ -123,6 +129,8 @@ impl Msix {
             msix_cap_offset,
             dev_id,
             gsi_msi_routes: HashMap::new(),
+            name: String::new(),
+            interrupt_counters: HashMap::new(),
         };
         msix.mask_all_vectors();
         msix
@@ -457,6 +465,20 @@ impl Msix {
         }
 
         send_msix(self.get_message(vector), dev_id);
+        self.count_interrupt(vector);
+    }
+
+    /// Count the interrupt injected through the vector, the counter is registered on the
+    /// first injection.
+    fn count_interrupt(&mut self, vector: u16) {
+        if self.name.is_empty() {
+            return;
+        }
+        let name = &self.name;
+        self.interrupt_counters
+            .entry(vector)
+            .or_insert_with(|| register_interrupt_counter(name, InterruptType::Msix, vector as u32))
+            .inc();
     }
 
     pub fn write_config(&mut self, config: &[u8], dev_id: u16, offset: usize, data: &[u8]) {
@@ -482,6 +504,7 @@ impl Msix {
                 if !self.is_vector_masked(v) && self.is_vector_pending(v) {
                     self.clear_pending_vector(v);
                     send_msix(self.get_message(v), dev_id);
+                    self.count_interrupt(v);
                 }
             }
         }
@@ -564,6 +587,7 @@ impl MigrationHook for Msix {
                 if self.is_vector_pending(vector) {
                     self.clear_pending_vector(vector);
                     send_msix(msg, self.dev_id.load(Ordering::Acquire));
+                    self.count_interrupt(vector);
                 }
             }
         }
@@ -591,29 +615,18 @@ fn is_msix_func_masked(msix_cap_offset: usize, config: &[u8]) -> bool {
 }
 
 fn send_msix(msg: Message, dev_id: u16) {
-    #[cfg(target_arch = "aarch64")]
-    let flags: u32 = kvm_bindings::KVM_MSI_VALID_DEVID;
-    #[cfg(target_arch = "x86_64")]
-    let flags: u32 = 0;
-
-    let kvm_msi = kvm_bindings::kvm_msi {
-        address_lo: msg.address_lo,
-        address_hi: msg.address_hi,
+    let msi = MsiMessage {
+        address: (u64::from(msg.address_hi) << 32) | u64::from(msg.address_lo),
         data: msg.data,
-        flags,
-        devid: dev_id as u32,
-        pad: [0; 12],
+        dev_id: u32::from(dev_id),
     };
 
     if is_test_enabled() {
-        let data = msg.data;
-        let mut addr: u64 = msg.address_hi as u64;
-        addr = (addr << 32) + msg.address_lo as u64;
-        add_msix_msg(addr, data);
+        add_msix_msg(msi.address, msi.data);
         return;
     }
 
-    if let Err(e) = KVM_FDS.load().vm_fd.as_ref().unwrap().signal_msi(kvm_msi) {
+    if let Err(e) = hypervisor().signal_msi(msi) {
         error!("Send msix error: {:?}", e);
     };
 }
@@ -626,7 +639,7 @@ fn send_msix(msg: Message, dev_id: u16) {
 /// * `vector_nr` - The number of vector.
 /// * `config` - The PCI config.
 /// * `dev_id` - Dev id.
-/// * `_id` - MSI-X id used in MigrationManager.
+/// * `id` - MSI-X id used in MigrationManager and interrupt statistics.
 /// * `parent_region` - Parent region which the MSI-X region registered. If none, registered in BAR.
 /// * `offset_opt` - Offset of table(table_offset) and Offset of pba(pba_offset). Set the
 ///   table_offset and pba_offset together.
@@ -635,7 +648,7 @@ pub fn init_msix(
     vector_nr: u32,
     config: &mut PciConfig,
     dev_id: Arc<AtomicU16>,
-    _id: &str,
+    id: &str,
     parent_region: Option<&Region>,
     offset_opt: Option<(u32, u32)>,
 ) -> Result<()> {
@@ -672,12 +685,9 @@ pub fn init_msix(
     offset = msix_cap_offset + MSIX_CAP_PBA as usize;
     le_write_u32(&mut config.config, offset, pba_offset | bar_id as u32)?;
 
-    let msix = Arc::new(Mutex::new(Msix::new(
-        table_size,
-        pba_size,
-        msix_cap_offset as u16,
-        dev_id.clone(),
-    )));
+    let mut msix = Msix::new(table_size, pba_size, msix_cap_offset as u16, dev_id.clone());
+    msix.name = id.to_string();
+    let msix = Arc::new(Mutex::new(msix));
     if let Some(region) = parent_region {
         Msix::register_memory_region(
             msix.clone(),
@@ -703,7 +713,7 @@ pub fn init_msix(
     config.msix = Some(msix.clone());
 
     #[cfg(not(test))]
-    MigrationManager::register_device_instance(MsixState::descriptor(), msix, _id);
+    MigrationManager::register_device_instance(MsixState::descriptor(), msix, id);
 
     Ok(())
 }
@@ -777,7 +777,7 @@ fn scsi_cdb_length(cdb: &[u8; SCSI_CMD_BUF_SIZE]) -> i32 {
     }
 }
 
-fn scsi_cdb_xfer(cdb: &[u8; SCSI_CMD_BUF_SIZE], dev: Arc<Mutex<ScsiDevice>>) -> i32 {
+pub fn scsi_cdb_xfer(cdb: &[u8; SCSI_CMD_BUF_SIZE], dev: Arc<Mutex<ScsiDevice>>) -> i32 {
     let dev_lock = dev.lock().unwrap();
     let block_size = dev_lock.block_size as i32;
     drop(dev_lock);
@@ -936,10 +936,8 @@ fn scsi_command_emulate_vpd_page(
         }
         0x83 => {
             // Device Identification.
-            let mut len: u8 = dev_lock.state.device_id.len() as u8;
-            if len > (255 - 8) {
-                len = 255 - 8;
-            }
+            // Leave room for the page header, the designator header and the NAA designator.
+            let len = cmp::min(dev_lock.state.device_id.len(), 255 - 8 - 12) as u8;
 
             if len > 0 {
                 // 0x2: Code Set: ASCII, Protocol Identifier: reserved.
@@ -952,6 +950,15 @@ fn scsi_command_emulate_vpd_page(
                 device_id_vec.truncate(len as usize);
                 outbuf.append(&mut device_id_vec);
             }
+
+            if let Some(wwn) = dev_lock.state.wwn {
+                // 0x1: Code Set: binary, Protocol Identifier: reserved.
+                // 0x3: Identifier Type: NAA, Association: addressed logical unit, Piv: 0.
+                // 0: Reserved.
+                // 8: identifier length.
+                outbuf.append(&mut [0x1_u8, 0x3_u8, 0_u8, 8_u8].to_vec());
+                outbuf.append(&mut wwn.to_be_bytes().to_vec());
+            }
             buflen = outbuf.len();
         }
         0xb0 => {
@@ -985,11 +992,12 @@ fn scsi_command_emulate_vpd_page(
         }
         0xb1 => {
             // Block Device Characteristics.
-            // 0: Medium Rotation Rate: 2Bytes.
+            // Byte[4-5]: Medium Rotation Rate.
             // 0: Product Type.
             // 0: Nominal Form Factor, Wacereq, Wabereq.
             // 0: Vbuls, Fuab, Bocs, Reserved, Zoned, Reserved.
-            outbuf.append(&mut [0_u8, 0_u8, 0_u8, 0_u8, 0_u8].to_vec());
+            outbuf.append(&mut dev_lock.state.rotation_rate.to_be_bytes().to_vec());
+            outbuf.append(&mut [0_u8, 0_u8, 0_u8].to_vec());
             buflen = 0x40;
         }
         0xb2 => {
@@ -70,6 +70,10 @@ pub struct ScsiDevState {
     pub version: String,
     /// Scsi device serial number.
     pub serial: String,
+    /// Scsi device world wide name.
+    pub wwn: Option<u64>,
+    /// Medium rotation rate reported in Block Device Characteristics VPD page.
+    pub rotation_rate: u16,
 }
 
 impl ScsiDevState {
@@ -81,6 +85,8 @@ impl ScsiDevState {
             device_id: "".to_string(),
             version: "".to_string(),
             serial: "".to_string(),
+            wwn: None,
+            rotation_rate: 0,
         }
     }
 }
@@ -165,6 +171,17 @@ impl ScsiDevice {
         if let Some(serial) = &self.config.serial {
             self.state.serial = serial.clone();
         }
+        // Use the serial number as the vendor specific identifier of the device, or the
+        // device id if serial number is not set, so that guest sees a stable identifier.
+        self.state.device_id = self
+            .config
+            .serial
+            .clone()
+            .unwrap_or_else(|| self.config.id.clone());
+        self.state.wwn = self.config.wwn;
+        if self.scsi_type == SCSI_TYPE_DISK {
+            self.state.rotation_rate = self.config.rotation_rate;
+        }
 
         let drive_files = self.drive_files.lock().unwrap();
         // File path can not be empty string. And it has also been checked in CmdParser::parse.
@@ -187,6 +204,9 @@ impl ScsiDevice {
             write_zeroes: WriteZeroesState::Off,
             l2_cache_size: self.config.l2_cache_size,
             refcount_cache_size: self.config.refcount_cache_size,
+            key_secret: self.config.key_secret.clone(),
+            copy_on_read: self.config.copy_on_read,
+            io_timeout: self.config.io_timeout,
         };
         let backend = create_block_backend(file, aio, conf)?;
         let disk_size = backend.lock().unwrap().disk_size()?;
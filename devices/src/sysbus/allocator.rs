@@ -0,0 +1,157 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+
+use super::error::SysBusError;
+
+/// Allocator of the MMIO space of system bus.
+#[derive(Debug)]
+pub struct MmioAllocator {
+    /// The start address of the MMIO space.
+    start: u64,
+    /// The end address (exclusive) of the MMIO space.
+    end: u64,
+    /// The allocated regions, the key is the base and the value is the size.
+    allocated: BTreeMap<u64, u64>,
+}
+
+impl MmioAllocator {
+    /// Create an allocator of the MMIO space [`start`, `end`).
+    pub fn new(start: u64, end: u64) -> Self {
+        Self {
+            start,
+            end,
+            allocated: BTreeMap::new(),
+        }
+    }
+
+    /// Allocate a region with the lowest free base which is aligned to `align`.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of the region.
+    /// * `align` - Alignment of the base, must be a power of 2.
+    pub fn allocate(&mut self, size: u64, align: u64) -> Result<u64> {
+        if size == 0 || !align.is_power_of_two() {
+            bail!(
+                "Invalid mmio region size 0x{:x} or alignment 0x{:x}",
+                size,
+                align
+            );
+        }
+
+        let mut candidate = self.start;
+        for (base, len) in self.allocated.iter() {
+            let aligned = match align_up(candidate, align) {
+                Some(addr) => addr,
+                None => break,
+            };
+            if aligned.checked_add(size).is_some_and(|end| end <= *base) {
+                self.allocated.insert(aligned, size);
+                return Ok(aligned);
+            }
+            candidate = candidate.max(base + len);
+        }
+
+        if let Some(aligned) = align_up(candidate, align) {
+            if aligned.checked_add(size).is_some_and(|end| end <= self.end) {
+                self.allocated.insert(aligned, size);
+                return Ok(aligned);
+            }
+        }
+        bail!(SysBusError::MmioExhausted(size, align));
+    }
+
+    /// Allocate the region at the fixed base.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - Base of the region.
+    /// * `size` - Size of the region.
+    pub fn allocate_at(&mut self, base: u64, size: u64) -> Result<()> {
+        let end = match base.checked_add(size) {
+            Some(end) if size != 0 && base >= self.start && end <= self.end => end,
+            _ => bail!(SysBusError::MmioOccupied(base, size)),
+        };
+        // The region before `end` with the highest base is the only one may overlap.
+        if let Some((prev_base, prev_size)) = self.allocated.range(..end).next_back() {
+            if prev_base + prev_size > base {
+                bail!(SysBusError::MmioOccupied(base, size));
+            }
+        }
+        self.allocated.insert(base, size);
+        Ok(())
+    }
+
+    /// Free the region with the base, return the size of it if it is allocated.
+    pub fn free(&mut self, base: u64) -> Option<u64> {
+        self.allocated.remove(&base)
+    }
+
+    /// Check whether the address is in the MMIO space.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr < self.end
+    }
+}
+
+fn align_up(addr: u64, align: u64) -> Option<u64> {
+    addr.checked_add(align - 1).map(|addr| addr & !(align - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmio_allocate() {
+        let mut allocator = MmioAllocator::new(0x1000, 0x3000);
+        assert_eq!(allocator.allocate(0x200, 0x200).unwrap(), 0x1000);
+        assert_eq!(allocator.allocate(0x200, 0x200).unwrap(), 0x1200);
+        // The base is aligned.
+        assert_eq!(allocator.allocate(0x100, 0x1000).unwrap(), 0x2000);
+        // The hole before the aligned region is used.
+        assert_eq!(allocator.allocate(0x400, 0x200).unwrap(), 0x1400);
+        assert_eq!(allocator.allocate(0x800, 0x200).unwrap(), 0x1800);
+        assert_eq!(allocator.allocate(0x800, 0x200).unwrap(), 0x2200);
+        assert!(allocator.allocate(0x800, 0x200).is_err());
+        assert!(allocator.allocate(0, 0x200).is_err());
+        assert!(allocator.allocate(0x200, 0x300).is_err());
+
+        // The freed region is reused.
+        assert_eq!(allocator.free(0x1200), Some(0x200));
+        assert_eq!(allocator.free(0x1200), None);
+        assert_eq!(allocator.allocate(0x200, 0x200).unwrap(), 0x1200);
+    }
+
+    #[test]
+    fn test_mmio_allocate_at() {
+        let mut allocator = MmioAllocator::new(0x1000, 0x3000);
+        allocator.allocate_at(0x2000, 0x200).unwrap();
+        // Out of range.
+        assert!(allocator.allocate_at(0x800, 0x200).is_err());
+        assert!(allocator.allocate_at(0x2f00, 0x200).is_err());
+        // Overlapped with the allocated region.
+        assert!(allocator.allocate_at(0x1f00, 0x200).is_err());
+        assert!(allocator.allocate_at(0x2100, 0x10).is_err());
+        allocator.allocate_at(0x1e00, 0x200).unwrap();
+        allocator.allocate_at(0x2200, 0x200).unwrap();
+
+        // Allocation skips the fixed regions.
+        assert_eq!(allocator.allocate(0xe00, 0x200).unwrap(), 0x1000);
+        assert_eq!(allocator.allocate(0x200, 0x200).unwrap(), 0x2400);
+        assert!(allocator.contains(0x1000));
+        assert!(!allocator.contains(0x3000));
+    }
+}
@@ -29,4 +29,8 @@ pub enum SysBusError {
         #[from]
         source: kvm_ioctls::Error,
     },
+    #[error("Mmio region space exhausted: size 0x{0:x}, align 0x{1:x}")]
+    MmioExhausted(u64, u64),
+    #[error("Mmio region 0x{0:x} with size 0x{1:x} is invalid or occupied")]
+    MmioOccupied(u64, u64),
 }
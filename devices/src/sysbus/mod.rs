@@ -10,9 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod allocator;
 pub mod error;
 
-pub use anyhow::{bail, Context, Result};
+pub use allocator::MmioAllocator;
+pub use anyhow::{anyhow, bail, Context, Result};
 pub use error::SysBusError;
 
 use std::fmt;
@@ -20,10 +22,11 @@ use std::sync::{Arc, Mutex};
 
 use vmm_sys_util::eventfd::EventFd;
 
+use crate::interrupt_stats::{register_interrupt_counter, InterruptCounter, InterruptType};
 use crate::{Device, DeviceBase};
 use acpi::{AmlBuilder, AmlScope};
 use address_space::{AddressSpace, GuestAddress, Region, RegionIoEventFd, RegionOps};
-use hypervisor::kvm::KVM_FDS;
+use hypervisor::hypervisor;
 use util::AsAny;
 
 // Now that the serial device use a hardcoded IRQ number (4), and the starting
@@ -46,8 +49,13 @@ pub struct SysBus {
     pub devices: Vec<Arc<Mutex<dyn SysBusDevOps>>>,
     pub free_irqs: (i32, i32),
     pub min_free_irq: i32,
+    /// IRQs released by the detached devices, which are reused before `min_free_irq`.
+    pub released_irqs: Vec<i32>,
     pub mmio_region: (u64, u64),
-    pub min_free_base: u64,
+    /// Allocator of the regions in `mmio_region`.
+    pub mmio_allocator: MmioAllocator,
+    /// Regions registered in memory space by the attached devices.
+    regions: Vec<Region>,
 }
 
 impl fmt::Debug for SysBus {
@@ -59,8 +67,9 @@ impl fmt::Debug for SysBus {
             .field("sys_mem", &self.sys_mem)
             .field("free_irqs", &self.free_irqs)
             .field("min_free_irq", &self.min_free_irq)
+            .field("released_irqs", &self.released_irqs)
             .field("mmio_region", &self.mmio_region)
-            .field("min_free_base", &self.min_free_base)
+            .field("mmio_allocator", &self.mmio_allocator)
             .finish();
         #[cfg(target_arch = "aarch64")]
         let debug = f
@@ -68,8 +77,9 @@ impl fmt::Debug for SysBus {
             .field("sys_mem", &self.sys_mem)
             .field("free_irqs", &self.free_irqs)
             .field("min_free_irq", &self.min_free_irq)
+            .field("released_irqs", &self.released_irqs)
             .field("mmio_region", &self.mmio_region)
-            .field("min_free_base", &self.min_free_base)
+            .field("mmio_allocator", &self.mmio_allocator)
             .finish();
         debug
     }
@@ -89,8 +99,10 @@ impl SysBus {
             devices: Vec::new(),
             free_irqs,
             min_free_irq: free_irqs.0,
+            released_irqs: Vec::new(),
             mmio_region,
-            min_free_base: mmio_region.0,
+            mmio_allocator: MmioAllocator::new(mmio_region.0, mmio_region.1),
+            regions: Vec::new(),
         }
     }
 
@@ -111,6 +123,34 @@ impl SysBus {
         }
     }
 
+    /// Allocate a region from the MMIO space with the lowest free base aligned to `align`.
+    pub fn alloc_mmio_region(&mut self, size: u64, align: u64) -> Result<u64> {
+        self.mmio_allocator.allocate(size, align)
+    }
+
+    /// Allocate the region at the fixed base from the MMIO space.
+    pub fn request_mmio_region(&mut self, base: u64, size: u64) -> Result<()> {
+        self.mmio_allocator.allocate_at(base, size)
+    }
+
+    /// Allocate an IRQ which is not bound to the interrupt eventfd of device, for the devices
+    /// owning more than one IRQ.
+    pub fn alloc_irq(&mut self) -> Result<i32> {
+        if let Some(irq) = self.released_irqs.pop() {
+            return Ok(irq);
+        }
+        if self.min_free_irq > self.free_irqs.1 {
+            bail!("IRQ number exhausted.");
+        }
+        self.min_free_irq += 1;
+        Ok(self.min_free_irq - 1)
+    }
+
+    /// Free the region allocated from the MMIO space.
+    pub fn free_mmio_region(&mut self, base: u64) {
+        self.mmio_allocator.free(base);
+    }
+
     pub fn attach_device<T: 'static + SysBusDevOps>(
         &mut self,
         dev: &Arc<Mutex<T>>,
@@ -160,22 +200,78 @@ impl SysBus {
                         )
                     })?;
             }
-            _ => self
-                .sys_mem
-                .root()
-                .add_subregion(region, region_base)
-                .with_context(|| {
-                    format!(
-                        "Failed to register region in memory space: offset={},size={}",
-                        region_base, region_size
-                    )
-                })?,
+            _ => {
+                self.sys_mem
+                    .root()
+                    .add_subregion(region.clone(), region_base)
+                    .with_context(|| {
+                        format!(
+                            "Failed to register region in memory space: offset={},size={}",
+                            region_base, region_size
+                        )
+                    })?;
+                self.regions.push(region);
+            }
         }
 
         self.devices.push(dev.clone());
         Ok(())
     }
 
+    /// Detach the device attached to memory space from system bus. The region and ioeventfds
+    /// of the device are unregistered, the IRQ and the MMIO region of it are released for reuse.
+    ///
+    /// # Arguments
+    ///
+    /// * `dev` - The device to be detached.
+    pub fn detach_device<T: 'static + SysBusDevOps>(&mut self, dev: &Arc<Mutex<T>>) -> Result<()> {
+        let dev_ptr = Arc::as_ptr(dev) as *const u8;
+        let index = self
+            .devices
+            .iter()
+            .position(|d| Arc::as_ptr(d) as *const u8 == dev_ptr)
+            .with_context(|| "The device is not attached to system bus")?;
+
+        let mut locked_dev = dev.lock().unwrap();
+        let res = locked_dev.sysbusdev_base().res;
+        let region_index = self
+            .regions
+            .iter()
+            .position(|r| r.offset().raw_value() == res.region_base && r.size() == res.region_size)
+            .with_context(|| {
+                format!(
+                    "No region in memory space for device: offset=0x{:x},size={}",
+                    res.region_base, res.region_size
+                )
+            })?;
+        // Removing the region updates the topology, which unregisters the ioeventfds as well.
+        self.sys_mem
+            .root()
+            .delete_subregion(&self.regions[region_index])
+            .with_context(|| {
+                format!(
+                    "Failed to unregister region in memory space: offset=0x{:x},size={}",
+                    res.region_base, res.region_size
+                )
+            })?;
+        self.regions.remove(region_index);
+        self.free_mmio_region(res.region_base);
+
+        if res.irq >= 0 {
+            if let Some(evt) = locked_dev.interrupt_evt() {
+                hypervisor().unregister_irqfd(&evt, res.irq as u32)?;
+            }
+            self.released_irqs.push(res.irq);
+        }
+        let base = locked_dev.sysbusdev_base_mut();
+        base.res = SysRes::default();
+        base.interrupt_counter = None;
+        drop(locked_dev);
+
+        self.devices.remove(index);
+        Ok(())
+    }
+
     pub fn attach_dynamic_device<T: 'static + SysBusDevOps>(
         &mut self,
         dev: &Arc<Mutex<T>>,
@@ -214,9 +310,44 @@ pub enum SysBusDevType {
     Flash,
     #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
     Ramfb,
+    #[cfg(target_arch = "aarch64")]
+    SbsaGwdt,
+    #[cfg(target_arch = "aarch64")]
+    VfioPlatform,
+    #[cfg(target_arch = "x86_64")]
+    Hpet,
+    #[cfg(target_arch = "x86_64")]
+    IoApic,
+    VmGenId,
     Others,
 }
 
+impl SysBusDevType {
+    fn name(&self) -> &'static str {
+        match self {
+            SysBusDevType::Serial => "serial",
+            SysBusDevType::Rtc => "rtc",
+            SysBusDevType::VirtioMmio => "virtio-mmio",
+            #[cfg(target_arch = "aarch64")]
+            SysBusDevType::PL011 => "pl011",
+            SysBusDevType::FwCfg => "fw-cfg",
+            SysBusDevType::Flash => "pflash",
+            #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
+            SysBusDevType::Ramfb => "ramfb",
+            #[cfg(target_arch = "aarch64")]
+            SysBusDevType::SbsaGwdt => "sbsa-gwdt",
+            #[cfg(target_arch = "aarch64")]
+            SysBusDevType::VfioPlatform => "vfio-platform",
+            #[cfg(target_arch = "x86_64")]
+            SysBusDevType::Hpet => "hpet",
+            #[cfg(target_arch = "x86_64")]
+            SysBusDevType::IoApic => "ioapic",
+            SysBusDevType::VmGenId => "vmgenid",
+            SysBusDevType::Others => "sysbus",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SysBusDevBase {
     pub base: DeviceBase,
@@ -226,6 +357,8 @@ pub struct SysBusDevBase {
     pub res: SysRes,
     /// Interrupt event file descriptor.
     pub interrupt_evt: Option<Arc<EventFd>>,
+    /// Counter of the interrupts injected through `interrupt_evt`.
+    pub interrupt_counter: Option<Arc<InterruptCounter>>,
 }
 
 impl Default for SysBusDevBase {
@@ -235,6 +368,7 @@ impl Default for SysBusDevBase {
             dev_type: SysBusDevType::Others,
             res: SysRes::default(),
             interrupt_evt: None,
+            interrupt_counter: None,
         }
     }
 }
@@ -246,11 +380,15 @@ impl SysBusDevBase {
             dev_type,
             res: SysRes::default(),
             interrupt_evt: None,
+            interrupt_counter: None,
         }
     }
 
     fn set_irq(&mut self, sysbus: &mut SysBus) -> Result<i32> {
-        let irq = sysbus.min_free_irq;
+        let irq = match sysbus.released_irqs.last() {
+            Some(irq) => *irq,
+            None => sysbus.min_free_irq,
+        };
         if irq > sysbus.free_irqs.1 {
             bail!("IRQ number exhausted.");
         }
@@ -258,8 +396,10 @@ impl SysBusDevBase {
         match &self.interrupt_evt {
             None => Ok(-1_i32),
             Some(evt) => {
-                KVM_FDS.load().register_irqfd(evt, irq as u32)?;
-                sysbus.min_free_irq = irq + 1;
+                hypervisor().register_irqfd(evt, irq as u32)?;
+                if sysbus.released_irqs.pop().is_none() {
+                    sysbus.min_free_irq = irq + 1;
+                }
                 Ok(irq)
             }
         }
@@ -269,6 +409,32 @@ impl SysBusDevBase {
         self.res.irq = irq;
         self.res.region_base = region_base;
         self.res.region_size = region_size;
+        if irq >= 0 && self.interrupt_evt.is_some() {
+            let name = if self.base.id.is_empty() {
+                self.dev_type.name()
+            } else {
+                &self.base.id
+            };
+            self.interrupt_counter = Some(register_interrupt_counter(
+                name,
+                InterruptType::Gsi,
+                irq as u32,
+            ));
+        }
+    }
+
+    /// Inject the interrupt of the device by writing the interrupt eventfd.
+    pub fn inject_interrupt(&self) -> Result<()> {
+        let evt = self
+            .interrupt_evt
+            .as_ref()
+            .with_context(|| "Interrupt eventfd is not initialized")?;
+        evt.write(1)
+            .with_context(|| "Failed to write interrupt eventfd")?;
+        if let Some(counter) = self.interrupt_counter.as_ref() {
+            counter.inc();
+        }
+        Ok(())
     }
 }
 
@@ -1090,7 +1090,7 @@ fn gen_intface_header_desc(fmt_num: u8) -> VsDescInputHeader {
 
 fn gen_fmt_header(fmt: &CameraFormatList) -> Result<Vec<u8>> {
     let header = match fmt.format {
-        FmtType::Yuy2 | FmtType::Rgb565 => VsDescUncompressedFmt {
+        FmtType::Yuy2 | FmtType::Rgb565 | FmtType::Nv12 => VsDescUncompressedFmt {
             bLength: 0x1B,
             bDescriptorType: CS_INTERFACE,
             bDescriptorSubtype: VS_FORMAT_UNCOMPRESSED,
@@ -1099,7 +1099,10 @@ fn gen_fmt_header(fmt: &CameraFormatList) -> Result<Vec<u8>> {
             guidFormat: *MEDIA_TYPE_GUID_HASHMAP
                 .get(&fmt.format)
                 .with_context(|| "unsupported video format.")?,
-            bBitsPerPixel: 0x10,
+            bBitsPerPixel: match fmt.format {
+                FmtType::Nv12 => 0x0C,
+                _ => 0x10,
+            },
             bDefaultFrameIndex: 1,
             bAspectRatioX: 0,
             bAspectRatioY: 0,
@@ -1134,7 +1137,7 @@ fn gen_frm_desc(pixfmt: FmtType, frm: &CameraFrame) -> Result<Vec<u8>> {
         bLength: 0x1e, // TODO: vary with interval number.
         bDescriptorType: CS_INTERFACE,
         bDescriptorSubtype: match pixfmt {
-            FmtType::Rgb565 | FmtType::Yuy2 => VS_FRAME_UNCOMPRESSED,
+            FmtType::Rgb565 | FmtType::Yuy2 | FmtType::Nv12 => VS_FRAME_UNCOMPRESSED,
             FmtType::Mjpg => VS_FRAME_MJPEG,
         },
         bFrameIndex: frm.index,
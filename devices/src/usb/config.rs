@@ -207,6 +207,7 @@ pub const USB_DT_INTERFACE_ASSOCIATION: u8 = 11;
 pub const USB_DT_BOS: u8 = 15;
 pub const USB_DT_DEVICE_CAPABILITY: u8 = 16;
 pub const USB_DT_ENDPOINT_COMPANION: u8 = 48;
+pub const USB_DT_HUB: u8 = 0x29;
 
 /// USB SuperSpeed Device Capability.
 pub const USB_SS_DEVICE_CAP: u8 = 0x3;
@@ -239,5 +240,6 @@ pub const USB_CONFIGURATION_ATTR_REMOTE_WAKEUP: u8 = 1 << 5;
 // USB Class
 pub const USB_CLASS_HID: u8 = 3;
 pub const USB_CLASS_MASS_STORAGE: u8 = 8;
+pub const USB_CLASS_HUB: u8 = 9;
 pub const USB_CLASS_VIDEO: u8 = 0xe;
 pub const USB_CLASS_MISCELLANEOUS: u8 = 0xef;
@@ -151,6 +151,25 @@ const KEYBOARD_REPORT_DESCRIPTOR: [u8; 63] = [
     0x81, 0x00, // Input (Data, Array)
     0xc0, // End Collection
 ];
+/// U2F report descriptor, see FIDO U2F HID Protocol Specification.
+const U2F_REPORT_DESCRIPTOR: [u8; 34] = [
+    0x06, 0xd0, 0xf1, // Usage Page (FIDO Alliance)
+    0x09, 0x01, // Usage (U2F Authenticator Device)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x20, // Usage (Input Report Data)
+    0x15, 0x00, // Logical Minimum (0)
+    0x26, 0xff, 0x00, // Logical Maximum (255)
+    0x75, 0x08, // Report Size (8)
+    0x95, 0x40, // Report Count (64)
+    0x81, 0x02, // Input (Data, Variable, Absolute)
+    0x09, 0x21, // Usage (Output Report Data)
+    0x15, 0x00, // Logical Minimum (0)
+    0x26, 0xff, 0x00, // Logical Maximum (255)
+    0x75, 0x08, // Report Size (8)
+    0x95, 0x40, // Report Count (64)
+    0x91, 0x02, // Output (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
 
 /// HID type
 #[derive(Debug)]
@@ -158,6 +177,7 @@ pub enum HidType {
     Mouse,
     Tablet,
     Keyboard,
+    U2f,
     UnKnown,
 }
 
@@ -405,6 +425,11 @@ impl Hid {
                             .clone_from_slice(&KEYBOARD_REPORT_DESCRIPTOR[..]);
                         packet.actual_length = KEYBOARD_REPORT_DESCRIPTOR.len() as u32;
                     }
+                    HidType::U2f => {
+                        data[..U2F_REPORT_DESCRIPTOR.len()]
+                            .clone_from_slice(&U2F_REPORT_DESCRIPTOR[..]);
+                        packet.actual_length = U2F_REPORT_DESCRIPTOR.len() as u32;
+                    }
                     _ => {
                         error!("Unknown HID type");
                         packet.status = UsbPacketStatus::Stall;
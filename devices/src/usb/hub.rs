@@ -0,0 +1,521 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{bail, Result};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+
+use super::descriptor::{
+    UsbConfigDescriptor, UsbDescConfig, UsbDescDevice, UsbDescEndpoint, UsbDescIface,
+    UsbDescriptorOps, UsbDeviceDescriptor, UsbEndpointDescriptor, UsbInterfaceDescriptor,
+};
+use super::xhci::xhci_controller::{UsbPort, XhciDevice};
+use super::{config::*, USB_DEVICE_BUFFER_DEFAULT_LEN};
+use super::{UsbDevice, UsbDeviceBase, UsbDeviceRequest, UsbEndpoint, UsbPacket, UsbPacketStatus};
+
+/// Hub class request type, see the spec section 11.24.2 Class-specific Requests.
+const USB_HUB_IN_REQUEST: u8 = USB_DIRECTION_DEVICE_TO_HOST | USB_TYPE_CLASS | USB_RECIPIENT_DEVICE;
+const USB_HUB_OUT_REQUEST: u8 =
+    USB_DIRECTION_HOST_TO_DEVICE | USB_TYPE_CLASS | USB_RECIPIENT_DEVICE;
+const USB_PORT_IN_REQUEST: u8 = USB_DIRECTION_DEVICE_TO_HOST | USB_TYPE_CLASS | USB_RECIPIENT_OTHER;
+const USB_PORT_OUT_REQUEST: u8 =
+    USB_DIRECTION_HOST_TO_DEVICE | USB_TYPE_CLASS | USB_RECIPIENT_OTHER;
+
+/// Hub class request code.
+const USB_HUB_REQUEST_CLEAR_TT_BUFFER: u8 = 8;
+const USB_HUB_REQUEST_RESET_TT: u8 = 9;
+const USB_HUB_REQUEST_GET_TT_STATE: u8 = 10;
+const USB_HUB_REQUEST_STOP_TT: u8 = 11;
+
+/// Port feature selector.
+const PORT_ENABLE: u16 = 1;
+const PORT_SUSPEND: u16 = 2;
+const PORT_RESET: u16 = 4;
+const PORT_POWER: u16 = 8;
+const C_PORT_CONNECTION: u16 = 16;
+const C_PORT_ENABLE: u16 = 17;
+const C_PORT_SUSPEND: u16 = 18;
+const C_PORT_OVER_CURRENT: u16 = 19;
+const C_PORT_RESET: u16 = 20;
+const PORT_TEST: u16 = 21;
+const PORT_INDICATOR: u16 = 22;
+
+/// Port status bits, see the spec section 11.24.2.7.1 Port Status Bits.
+const PORT_STAT_CONNECTION: u16 = 1 << 0;
+const PORT_STAT_ENABLE: u16 = 1 << 1;
+const PORT_STAT_SUSPEND: u16 = 1 << 2;
+const PORT_STAT_POWER: u16 = 1 << 8;
+const PORT_STAT_LOW_SPEED: u16 = 1 << 9;
+const PORT_STAT_HIGH_SPEED: u16 = 1 << 10;
+
+/// Port status change bits, see the spec section 11.24.2.7.2 Port Status Change Bits.
+const PORT_STAT_C_CONNECTION: u16 = 1 << 0;
+const PORT_STAT_C_ENABLE: u16 = 1 << 1;
+const PORT_STAT_C_SUSPEND: u16 = 1 << 2;
+const PORT_STAT_C_OVER_CURRENT: u16 = 1 << 3;
+const PORT_STAT_C_RESET: u16 = 1 << 4;
+
+/// Individual port power switching and no over-current protection.
+const HUB_CHARACTERISTICS: u16 = 0x000a;
+/// Time from power-on to power-good in 2ms units.
+const HUB_POWER_ON_TO_GOOD: u8 = 1;
+
+/// Hub device descriptor. The single TT protocol is reported so that the guest drivers accept
+/// the full and low speed devices, but the transaction translator is not emulated as there is
+/// no real split transaction.
+static DESC_DEVICE_HUB: Lazy<Arc<UsbDescDevice>> = Lazy::new(|| {
+    Arc::new(UsbDescDevice {
+        device_desc: UsbDeviceDescriptor {
+            bLength: USB_DT_DEVICE_SIZE,
+            bDescriptorType: USB_DT_DEVICE,
+            idVendor: 0x0409,
+            idProduct: 0x55aa,
+            bcdDevice: 0x0101,
+            iManufacturer: STR_MANUFACTURER_INDEX,
+            iProduct: STR_PRODUCT_HUB_INDEX,
+            iSerialNumber: STR_SERIAL_HUB_INDEX,
+            bcdUSB: 0x0200,
+            bDeviceClass: USB_CLASS_HUB,
+            bDeviceSubClass: 0,
+            bDeviceProtocol: 1,
+            bMaxPacketSize0: 64,
+            bNumConfigurations: 1,
+        },
+        configs: vec![Arc::new(UsbDescConfig {
+            config_desc: UsbConfigDescriptor {
+                bLength: USB_DT_CONFIG_SIZE,
+                bDescriptorType: USB_DT_CONFIGURATION,
+                wTotalLength: 0,
+                bNumInterfaces: 1,
+                bConfigurationValue: 1,
+                iConfiguration: STR_CONFIG_HUB_INDEX,
+                bmAttributes: USB_CONFIGURATION_ATTR_ONE
+                    | USB_CONFIGURATION_ATTR_SELF_POWER
+                    | USB_CONFIGURATION_ATTR_REMOTE_WAKEUP,
+                bMaxPower: 0,
+            },
+            iad_desc: vec![],
+            interfaces: vec![DESC_IFACE_HUB.clone()],
+        })],
+    })
+});
+
+/// Hub interface descriptor.
+static DESC_IFACE_HUB: Lazy<Arc<UsbDescIface>> = Lazy::new(|| {
+    Arc::new(UsbDescIface {
+        interface_desc: UsbInterfaceDescriptor {
+            bLength: USB_DT_INTERFACE_SIZE,
+            bDescriptorType: USB_DT_INTERFACE,
+            bInterfaceNumber: 0,
+            bAlternateSetting: 0,
+            bNumEndpoints: 1,
+            bInterfaceClass: USB_CLASS_HUB,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0,
+            iInterface: 0,
+        },
+        other_desc: vec![],
+        endpoints: vec![Arc::new(UsbDescEndpoint {
+            endpoint_desc: UsbEndpointDescriptor {
+                bLength: USB_DT_ENDPOINT_SIZE,
+                bDescriptorType: USB_DT_ENDPOINT,
+                bEndpointAddress: USB_DIRECTION_DEVICE_TO_HOST | 0x1,
+                bmAttributes: USB_ENDPOINT_ATTR_INT,
+                // The status change bitmap of the hub and at most 15 ports.
+                wMaxPacketSize: 2,
+                bInterval: 0xc,
+            },
+            extra: Vec::new(),
+        })],
+    })
+});
+
+/// String descriptor index
+const STR_MANUFACTURER_INDEX: u8 = 1;
+const STR_PRODUCT_HUB_INDEX: u8 = 2;
+const STR_CONFIG_HUB_INDEX: u8 = 3;
+const STR_SERIAL_HUB_INDEX: u8 = 4;
+
+/// String descriptor
+const DESC_STRINGS: [&str; 5] = ["", "StratoVirt", "StratoVirt USB Hub", "Hub", "1"];
+
+/// Downstream port of the hub.
+struct UsbHubPort {
+    port: Arc<Mutex<UsbPort>>,
+    /// wPortStatus.
+    status: u16,
+    /// wPortChange.
+    change: u16,
+}
+
+impl UsbHubPort {
+    fn new(port_id: u8) -> Self {
+        Self {
+            port: Arc::new(Mutex::new(UsbPort::new(&Weak::new(), port_id))),
+            status: PORT_STAT_POWER,
+            change: 0,
+        }
+    }
+
+    fn device(&self) -> Option<Arc<Mutex<dyn UsbDevice>>> {
+        self.port.lock().unwrap().dev.clone()
+    }
+}
+
+/// USB high speed hub, the devices attached to its downstream ports share one port of the
+/// controller.
+pub struct UsbHub {
+    base: UsbDeviceBase,
+    ports: Vec<UsbHubPort>,
+    /// USB controller used to notify controller to transfer data.
+    cntlr: Option<Weak<Mutex<XhciDevice>>>,
+}
+
+impl UsbHub {
+    pub fn new(id: String, num_ports: u8) -> Self {
+        Self {
+            base: UsbDeviceBase::new(id, USB_DEVICE_BUFFER_DEFAULT_LEN),
+            ports: (1..=num_ports).map(UsbHubPort::new).collect(),
+            cntlr: None,
+        }
+    }
+
+    /// Length of the status change bitmap, bit 0 is for the hub and bit N is for port N.
+    fn bitmap_len(&self) -> usize {
+        (self.ports.len() + 1 + 7) / 8
+    }
+
+    fn hub_descriptor(&self) -> Vec<u8> {
+        let bitmap_len = self.bitmap_len();
+        let mut desc = vec![
+            (7 + bitmap_len * 2) as u8,
+            USB_DT_HUB,
+            self.ports.len() as u8,
+            HUB_CHARACTERISTICS as u8,
+            (HUB_CHARACTERISTICS >> 8) as u8,
+            HUB_POWER_ON_TO_GOOD,
+            0,
+        ];
+        // All devices are removable.
+        desc.extend(vec![0_u8; bitmap_len]);
+        // PortPwrCtrlMask is kept for compatibility and must be all ones.
+        desc.extend(vec![0xff_u8; bitmap_len]);
+        desc
+    }
+
+    fn get_port(&mut self, index: u16) -> Option<&mut UsbHubPort> {
+        if index == 0 {
+            return None;
+        }
+        self.ports.get_mut(index as usize - 1)
+    }
+
+    fn reset_port(port: &mut UsbHubPort) {
+        if let Some(dev) = port.device() {
+            dev.lock().unwrap().reset();
+            port.status |= PORT_STAT_ENABLE;
+            port.change |= PORT_STAT_C_RESET;
+        }
+    }
+
+    fn set_port_feature(&mut self, index: u16, feature: u16) -> Result<()> {
+        let port = match self.get_port(index) {
+            Some(port) => port,
+            None => bail!("Invalid hub port {}", index),
+        };
+        match feature {
+            PORT_SUSPEND => port.status |= PORT_STAT_SUSPEND,
+            PORT_RESET => Self::reset_port(port),
+            PORT_POWER | PORT_TEST | PORT_INDICATOR => {}
+            _ => bail!("Unsupported hub port feature {} to set", feature),
+        }
+        Ok(())
+    }
+
+    fn clear_port_feature(&mut self, index: u16, feature: u16) -> Result<()> {
+        let port = match self.get_port(index) {
+            Some(port) => port,
+            None => bail!("Invalid hub port {}", index),
+        };
+        match feature {
+            PORT_ENABLE => port.status &= !PORT_STAT_ENABLE,
+            PORT_SUSPEND => {
+                if port.status & PORT_STAT_SUSPEND == PORT_STAT_SUSPEND {
+                    port.status &= !PORT_STAT_SUSPEND;
+                    port.change |= PORT_STAT_C_SUSPEND;
+                }
+            }
+            C_PORT_CONNECTION => port.change &= !PORT_STAT_C_CONNECTION,
+            C_PORT_ENABLE => port.change &= !PORT_STAT_C_ENABLE,
+            C_PORT_SUSPEND => port.change &= !PORT_STAT_C_SUSPEND,
+            C_PORT_OVER_CURRENT => port.change &= !PORT_STAT_C_OVER_CURRENT,
+            C_PORT_RESET => port.change &= !PORT_STAT_C_RESET,
+            PORT_POWER | PORT_INDICATOR => {}
+            _ => bail!("Unsupported hub port feature {} to clear", feature),
+        }
+        Ok(())
+    }
+
+    fn handle_hub_request(
+        &mut self,
+        packet: &mut UsbPacket,
+        device_req: &UsbDeviceRequest,
+    ) -> Result<()> {
+        match (device_req.request_type, device_req.request) {
+            (USB_HUB_IN_REQUEST, USB_REQUEST_GET_DESCRIPTOR) => {
+                if (device_req.value >> 8) as u8 != USB_DT_HUB {
+                    bail!("Unsupported hub descriptor type {:x}", device_req.value);
+                }
+                let desc = self.hub_descriptor();
+                let len = std::cmp::min(desc.len(), device_req.length as usize);
+                self.base.data_buf[..len].copy_from_slice(&desc[..len]);
+                packet.actual_length = len as u32;
+            }
+            (USB_HUB_IN_REQUEST, USB_REQUEST_GET_STATUS) => {
+                // Local power is good and no over-current.
+                self.base.data_buf[..4].fill(0);
+                packet.actual_length = 4;
+            }
+            (USB_HUB_OUT_REQUEST, USB_REQUEST_SET_FEATURE)
+            | (USB_HUB_OUT_REQUEST, USB_REQUEST_CLEAR_FEATURE) => {}
+            (USB_PORT_IN_REQUEST, USB_REQUEST_GET_STATUS) => {
+                let index = device_req.index;
+                let (status, change) = match self.get_port(index) {
+                    Some(port) => (port.status, port.change),
+                    None => bail!("Invalid hub port {}", index),
+                };
+                self.base.data_buf[..2].copy_from_slice(&status.to_le_bytes());
+                self.base.data_buf[2..4].copy_from_slice(&change.to_le_bytes());
+                packet.actual_length = 4;
+            }
+            (USB_PORT_OUT_REQUEST, USB_REQUEST_SET_FEATURE) => {
+                self.set_port_feature(device_req.index, device_req.value)?;
+            }
+            (USB_PORT_OUT_REQUEST, USB_REQUEST_CLEAR_FEATURE) => {
+                self.clear_port_feature(device_req.index, device_req.value)?;
+            }
+            // There is no transaction translator to operate.
+            (USB_PORT_OUT_REQUEST, USB_HUB_REQUEST_CLEAR_TT_BUFFER)
+            | (USB_PORT_OUT_REQUEST, USB_HUB_REQUEST_RESET_TT)
+            | (USB_PORT_OUT_REQUEST, USB_HUB_REQUEST_STOP_TT) => {}
+            (USB_PORT_IN_REQUEST, USB_HUB_REQUEST_GET_TT_STATE) => {
+                packet.actual_length = 0;
+            }
+            _ => bail!("Unhandled hub request {:?}", device_req),
+        }
+        Ok(())
+    }
+}
+
+impl UsbDevice for UsbHub {
+    fn usb_device_base(&self) -> &UsbDeviceBase {
+        &self.base
+    }
+
+    fn usb_device_base_mut(&mut self) -> &mut UsbDeviceBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<Arc<Mutex<dyn UsbDevice>>> {
+        self.base.reset_usb_endpoint();
+        self.base.speed = USB_SPEED_HIGH;
+        let mut s: Vec<String> = DESC_STRINGS.iter().map(|&s| s.to_string()).collect();
+        let prefix = &s[STR_SERIAL_HUB_INDEX as usize];
+        s[STR_SERIAL_HUB_INDEX as usize] = self.base.generate_serial_number(prefix);
+        self.base.init_descriptor(DESC_DEVICE_HUB.clone(), s)?;
+
+        let hub: Arc<Mutex<UsbHub>> = Arc::new(Mutex::new(self));
+        Ok(hub)
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        for port in &self.ports {
+            if let Some(dev) = port.device() {
+                let mut locked_dev = dev.lock().unwrap();
+                locked_dev.usb_device_base_mut().unplugged = true;
+                locked_dev.unrealize()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        info!("Hub device reset");
+        self.base.remote_wakeup = 0;
+        self.base.addr = 0;
+        for port in self.ports.iter_mut() {
+            port.status &= !(PORT_STAT_ENABLE | PORT_STAT_SUSPEND);
+            port.change = 0;
+            if port.status & PORT_STAT_CONNECTION == PORT_STAT_CONNECTION {
+                port.change |= PORT_STAT_C_CONNECTION;
+            }
+        }
+    }
+
+    fn handle_control(&mut self, packet: &Arc<Mutex<UsbPacket>>, device_req: &UsbDeviceRequest) {
+        debug!("Hub device handle_control request {:?}", device_req);
+        let mut locked_packet = packet.lock().unwrap();
+        match self
+            .base
+            .handle_control_for_descriptor(&mut locked_packet, device_req)
+        {
+            Ok(handled) => {
+                if handled {
+                    debug!("Hub control handled by descriptor, return directly.");
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("Hub descriptor error {:?}", e);
+                locked_packet.status = UsbPacketStatus::Stall;
+                return;
+            }
+        }
+        if let Err(e) = self.handle_hub_request(&mut locked_packet, device_req) {
+            error!("Hub {}: {:?}", self.device_id(), e);
+            locked_packet.status = UsbPacketStatus::Stall;
+        }
+    }
+
+    fn handle_data(&mut self, packet: &Arc<Mutex<UsbPacket>>) {
+        let mut locked_packet = packet.lock().unwrap();
+        if locked_packet.pid as u8 != USB_TOKEN_IN || locked_packet.ep_number != 1 {
+            error!("Unhandled hub packet {}", locked_packet);
+            locked_packet.status = UsbPacketStatus::Stall;
+            return;
+        }
+
+        let mut bitmap = vec![0_u8; self.bitmap_len()];
+        for (i, port) in self.ports.iter().enumerate() {
+            if port.change != 0 {
+                let bit = i + 1;
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        if bitmap.iter().all(|b| *b == 0) {
+            locked_packet.status = UsbPacketStatus::Nak;
+            return;
+        }
+        let len = bitmap.len();
+        locked_packet.transfer_packet(&mut bitmap, len);
+    }
+
+    fn set_controller(&mut self, cntlr: Weak<Mutex<XhciDevice>>) {
+        for port in &self.ports {
+            port.port.lock().unwrap().xhci = cntlr.clone();
+            if let Some(dev) = port.device() {
+                dev.lock().unwrap().set_controller(cntlr.clone());
+            }
+        }
+        self.cntlr = Some(cntlr);
+    }
+
+    fn get_controller(&self) -> Option<Weak<Mutex<XhciDevice>>> {
+        self.cntlr.clone()
+    }
+
+    fn get_wakeup_endpoint(&self) -> &UsbEndpoint {
+        self.base.get_endpoint(true, 1)
+    }
+
+    fn get_downstream_ports(&self) -> Vec<Arc<Mutex<UsbPort>>> {
+        self.ports.iter().map(|port| port.port.clone()).collect()
+    }
+
+    fn attach_downstream_device(&mut self, dev: &Arc<Mutex<dyn UsbDevice>>) -> Result<()> {
+        let speed = dev.lock().unwrap().speed();
+        if speed == USB_SPEED_SUPER {
+            bail!("Super speed device can't be attached to USB hub");
+        }
+        let port = match self.ports.iter_mut().find(|port| port.device().is_none()) {
+            Some(port) => port,
+            None => bail!("No available port in USB hub {}", self.base.base.id),
+        };
+
+        let mut locked_port = port.port.lock().unwrap();
+        locked_port.used = true;
+        locked_port.dev = Some(dev.clone());
+        let port_id = locked_port.port_id;
+        drop(locked_port);
+
+        port.status |= PORT_STAT_CONNECTION;
+        match speed {
+            USB_SPEED_LOW => port.status |= PORT_STAT_LOW_SPEED,
+            USB_SPEED_HIGH => port.status |= PORT_STAT_HIGH_SPEED,
+            _ => {}
+        }
+        port.change |= PORT_STAT_C_CONNECTION;
+
+        let mut locked_dev = dev.lock().unwrap();
+        locked_dev.set_usb_port(Some(Arc::downgrade(&port.port)));
+        locked_dev.handle_attach()?;
+        if let Some(cntlr) = &self.cntlr {
+            locked_dev.set_controller(cntlr.clone());
+        }
+        debug!(
+            "Attach usb device: hub {} port id {} device id {}",
+            self.base.base.id,
+            port_id,
+            locked_dev.device_id()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::keyboard::UsbKeyboard;
+
+    #[test]
+    fn test_usb_hub_port_status() {
+        let mut hub = UsbHub::new("hub0".to_string(), 4);
+        assert_eq!(
+            hub.hub_descriptor(),
+            vec![9, USB_DT_HUB, 4, 0x0a, 0, 1, 0, 0, 0xff]
+        );
+
+        let kbd = UsbKeyboard::new("kbd0".to_string()).realize().unwrap();
+        assert!(hub.attach_downstream_device(&kbd).is_ok());
+        assert!(hub.ports[0].device().is_some());
+        assert_eq!(hub.ports[0].status, PORT_STAT_POWER | PORT_STAT_CONNECTION);
+        assert_eq!(hub.ports[0].change, PORT_STAT_C_CONNECTION);
+
+        // Status change bitmap reports port 1.
+        let buf = [0_u8; 2];
+        let mut packet = UsbPacket::default();
+        packet.pid = USB_TOKEN_IN as u32;
+        packet.ep_number = 1;
+        packet
+            .iovecs
+            .push(util::aio::Iovec::new(buf.as_ptr() as u64, 2));
+        let packet = Arc::new(Mutex::new(packet));
+        hub.handle_data(&packet);
+        assert_eq!(buf[0], 1 << 1);
+
+        assert!(hub.clear_port_feature(1, C_PORT_CONNECTION).is_ok());
+        assert!(hub.set_port_feature(1, PORT_RESET).is_ok());
+        assert_eq!(hub.ports[0].status & PORT_STAT_ENABLE, PORT_STAT_ENABLE);
+        assert_eq!(hub.ports[0].change, PORT_STAT_C_RESET);
+        assert!(hub.clear_port_feature(1, C_PORT_RESET).is_ok());
+
+        // No change, the interrupt endpoint naks.
+        packet.lock().unwrap().status = UsbPacketStatus::Success;
+        hub.handle_data(&packet);
+        assert_eq!(packet.lock().unwrap().status, UsbPacketStatus::Nak);
+
+        assert!(hub.set_port_feature(0, PORT_RESET).is_err());
+        assert!(hub.set_port_feature(5, PORT_RESET).is_err());
+    }
+}
@@ -17,11 +17,14 @@ pub mod camera_media_type_guid;
 pub mod config;
 pub mod error;
 pub mod hid;
+pub mod hub;
 pub mod keyboard;
 pub mod storage;
 pub mod tablet;
+pub mod u2f;
 #[cfg(feature = "usb_host")]
 pub mod usbhost;
+pub mod usbredir;
 pub mod xhci;
 
 mod descriptor;
@@ -371,6 +374,16 @@ pub trait UsbDevice: Send + Sync {
         usb_dev.port = port;
     }
 
+    /// Get the downstream ports if the device is a hub.
+    fn get_downstream_ports(&self) -> Vec<Arc<Mutex<UsbPort>>> {
+        Vec::new()
+    }
+
+    /// Attach the USB device to a free downstream port if the device is a hub.
+    fn attach_downstream_device(&mut self, _dev: &Arc<Mutex<dyn UsbDevice>>) -> Result<()> {
+        bail!("{} is not a USB hub", self.device_id())
+    }
+
     /// Handle usb packet, used for controller to deliver packet to device.
     fn handle_packet(&mut self, packet: &Arc<Mutex<UsbPacket>>) {
         let mut locked_packet = packet.lock().unwrap();
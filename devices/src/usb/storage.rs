@@ -153,12 +153,60 @@ struct UsbStorageState {
     csw: UsbMsdCsw,
     cdb: Option<[u8; SCSI_CMD_BUF_SIZE]>,
     iovec_len: u32,
+    completion: Arc<Mutex<UsbMsdCompletion>>,
 }
 
-impl ScsiRequestOps for UsbMsdCsw {
+/// Completion state of the SCSI request, shared by usb-storage and the request callback.
+#[derive(Default)]
+struct UsbMsdCompletion {
+    /// The USB packet is waiting for the request asynchronously.
+    pending: bool,
+    /// The request has been completed.
+    done: bool,
+    /// SCSI status of the completed request.
+    status: u8,
+}
+
+/// Upper request of the SCSI request issued by usb-storage. The request may be completed
+/// synchronously or later by the aio engine, in which case the USB packet is resumed here.
+struct UsbMsdRequest {
+    completion: Arc<Mutex<UsbMsdCompletion>>,
+    /// The CBW or data packet which carries the SCSI request.
+    packet: Arc<Mutex<UsbPacket>>,
+    actual_length: u32,
+    cntlr: Option<Weak<Mutex<XhciDevice>>>,
+    slot_id: u32,
+}
+
+impl ScsiRequestOps for UsbMsdRequest {
     fn scsi_request_complete_cb(&mut self, status: u8, _: Option<ScsiSense>) -> Result<()> {
-        if status != GOOD {
-            self.status = UsbMsdCswStatus::Failed as u8;
+        let mut locked_completion = self.completion.lock().unwrap();
+        locked_completion.done = true;
+        locked_completion.status = status;
+        let pending = locked_completion.pending;
+        drop(locked_completion);
+        if !pending {
+            // Completed before the submitter returns, the packet is handled by the submitter.
+            return Ok(());
+        }
+
+        let mut locked_packet = self.packet.lock().unwrap();
+        if !locked_packet.is_async {
+            // The packet has been cancelled.
+            return Ok(());
+        }
+        locked_packet.status = UsbPacketStatus::Success;
+        locked_packet.actual_length = self.actual_length;
+        let ops = locked_packet.xfer_ops.clone();
+        drop(locked_packet);
+        if let Some(ops) = ops.and_then(|ops| ops.upgrade()) {
+            ops.lock().unwrap().submit_transfer();
+        }
+
+        // The CSW packet is NAKed while the request is in flight, kick it again.
+        if let Some(xhci) = self.cntlr.as_ref().and_then(|cntlr| cntlr.upgrade()) {
+            let ep = UsbEndpoint::new(1, true, USB_ENDPOINT_ATTR_BULK);
+            xhci.lock().unwrap().wakeup_endpoint(self.slot_id, &ep)?;
         }
         Ok(())
     }
@@ -172,6 +220,7 @@ impl UsbStorageState {
             csw: UsbMsdCsw::new(),
             cdb: None,
             iovec_len: 0,
+            completion: Arc::new(Mutex::new(UsbMsdCompletion::default())),
         }
     }
 
@@ -356,7 +405,11 @@ impl UsbStorage {
         packet.status = UsbPacketStatus::Stall;
     }
 
-    fn handle_token_out(&mut self, packet: &mut UsbPacket) -> Result<()> {
+    fn handle_token_out(
+        &mut self,
+        packet: &mut UsbPacket,
+        packet_h: &Arc<Mutex<UsbPacket>>,
+    ) -> Result<()> {
         if packet.ep_number != 2 {
             bail!("Error ep_number {}!", packet.ep_number);
         }
@@ -386,7 +439,8 @@ impl UsbStorage {
                 self.state.cdb = Some(self.state.cbw.cmd);
 
                 if self.state.cbw.data_len == 0 {
-                    self.handle_scsi_request(packet)?;
+                    let actual_length = packet.actual_length;
+                    self.handle_scsi_request(packet, packet_h, actual_length)?;
                     self.state.mode = UsbMsdMode::Csw;
                 } else if self.state.cbw.flags & CBW_FLAG_IN == CBW_FLAG_IN {
                     self.state.mode = UsbMsdMode::DataIn;
@@ -395,7 +449,7 @@ impl UsbStorage {
                 }
             }
             UsbMsdMode::DataOut => {
-                self.handle_data_inout_packet(packet, UsbMsdMode::DataOut)?;
+                self.handle_data_inout_packet(packet, packet_h, UsbMsdMode::DataOut)?;
             }
             _ => {
                 bail!(
@@ -407,7 +461,11 @@ impl UsbStorage {
         Ok(())
     }
 
-    fn handle_token_in(&mut self, packet: &mut UsbPacket) -> Result<()> {
+    fn handle_token_in(
+        &mut self,
+        packet: &mut UsbPacket,
+        packet_h: &Arc<Mutex<UsbPacket>>,
+    ) -> Result<()> {
         if packet.ep_number != 1 {
             bail!("Error ep_number {}!", packet.ep_number);
         }
@@ -423,6 +481,17 @@ impl UsbStorage {
                 self.state.check_cdb_exist(true)?;
                 self.state.check_iovec_empty(self.state.cbw.data_len == 0)?;
 
+                let locked_completion = self.state.completion.lock().unwrap();
+                if !locked_completion.done {
+                    // Retry after the request is completed by the aio engine.
+                    packet.status = UsbPacketStatus::Nak;
+                    return Ok(());
+                }
+                if locked_completion.status != GOOD {
+                    self.state.csw.status = UsbMsdCswStatus::Failed as u8;
+                }
+                drop(locked_completion);
+
                 let mut csw_buf = [0_u8; CSW_SIZE as usize];
                 self.state.csw.tag = self.state.cbw.tag;
                 self.state.csw.convert(&mut csw_buf);
@@ -433,7 +502,7 @@ impl UsbStorage {
                 self.state = UsbStorageState::new();
             }
             UsbMsdMode::DataIn => {
-                self.handle_data_inout_packet(packet, UsbMsdMode::DataIn)?;
+                self.handle_data_inout_packet(packet, packet_h, UsbMsdMode::DataIn)?;
             }
             _ => {
                 bail!(
@@ -445,7 +514,12 @@ impl UsbStorage {
         Ok(())
     }
 
-    fn handle_data_inout_packet(&mut self, packet: &mut UsbPacket, mode: UsbMsdMode) -> Result<()> {
+    fn handle_data_inout_packet(
+        &mut self,
+        packet: &mut UsbPacket,
+        packet_h: &Arc<Mutex<UsbPacket>>,
+        mode: UsbMsdMode,
+    ) -> Result<()> {
         self.state.check_cdb_exist(true)?;
         self.state.check_iovec_empty(true)?;
 
@@ -461,27 +535,40 @@ impl UsbStorage {
 
         self.state.iovec_len = iovec_len;
         debug!("Storage: iovec_len {}.", iovec_len);
-        self.handle_scsi_request(packet)?;
         packet.actual_length = iovec_len;
+        self.handle_scsi_request(packet, packet_h, iovec_len)?;
         self.state.mode = UsbMsdMode::Csw;
 
         Ok(())
     }
 
-    // Handle scsi request and save result in self.csw for next CSW packet.
-    fn handle_scsi_request(&mut self, packet: &mut UsbPacket) -> Result<()> {
+    // Handle scsi request and save result in self.state.completion for next CSW packet. If
+    // the request is not completed synchronously, the packet is completed asynchronously by
+    // the request callback with `actual_length`.
+    fn handle_scsi_request(
+        &mut self,
+        packet: &mut UsbPacket,
+        packet_h: &Arc<Mutex<UsbPacket>>,
+        actual_length: u32,
+    ) -> Result<()> {
         self.state
             .cdb
             .with_context(|| "No scsi CDB can be executed")?;
 
-        let csw = Box::new(UsbMsdCsw::new());
+        let upper_req = Box::new(UsbMsdRequest {
+            completion: self.state.completion.clone(),
+            packet: packet_h.clone(),
+            actual_length,
+            cntlr: self.cntlr.clone(),
+            slot_id: self.base.addr as u32,
+        });
         let sreq = ScsiRequest::new(
             self.state.cdb.unwrap(),
             0,
             packet.iovecs.clone(),
             self.state.iovec_len,
             self.scsi_dev.clone(),
-            csw,
+            upper_req,
         )
         .with_context(|| "Error in creating scsirequest.")?;
 
@@ -495,15 +582,17 @@ impl UsbStorage {
             );
         }
 
-        let sreq_h = match sreq.opstype {
+        match sreq.opstype {
             EMULATE_SCSI_OPS => sreq.emulate_execute(),
             _ => sreq.execute(),
         }
         .with_context(|| "Error in executing scsi request.")?;
 
-        let csw_h = &sreq_h.lock().unwrap().upper_req;
-        let csw = csw_h.as_ref().as_any().downcast_ref::<UsbMsdCsw>().unwrap();
-        self.state.csw = *csw;
+        let mut locked_completion = self.state.completion.lock().unwrap();
+        if !locked_completion.done {
+            locked_completion.pending = true;
+            packet.is_async = true;
+        }
 
         Ok(())
     }
@@ -526,8 +615,6 @@ impl UsbDevice for UsbStorage {
         s[STR_SERIAL_STORAGE_INDEX as usize] = self.base.generate_serial_number(prefix);
         self.base.init_descriptor(DESC_DEVICE_STORAGE.clone(), s)?;
 
-        // NOTE: "aio=off,direct=false" must be configured and other aio/direct values are not
-        // supported.
         let mut locked_scsi_dev = self.scsi_dev.lock().unwrap();
         locked_scsi_dev.realize(None)?;
         drop(locked_scsi_dev);
@@ -577,8 +664,8 @@ impl UsbDevice for UsbStorage {
         );
 
         let result = match locked_packet.pid as u8 {
-            USB_TOKEN_OUT => self.handle_token_out(&mut locked_packet),
-            USB_TOKEN_IN => self.handle_token_in(&mut locked_packet),
+            USB_TOKEN_OUT => self.handle_token_out(&mut locked_packet, packet),
+            USB_TOKEN_IN => self.handle_token_in(&mut locked_packet, packet),
             _ => Err(anyhow!("Bad token!")),
         };
 
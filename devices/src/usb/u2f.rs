@@ -0,0 +1,339 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use once_cell::sync::Lazy;
+use vmm_sys_util::epoll::EventSet;
+
+use super::descriptor::{
+    UsbConfigDescriptor, UsbDescConfig, UsbDescDevice, UsbDescEndpoint, UsbDescIface, UsbDescOther,
+    UsbDescriptorOps, UsbDeviceDescriptor, UsbEndpointDescriptor, UsbInterfaceDescriptor,
+};
+use super::hid::{Hid, HidType};
+use super::xhci::xhci_controller::XhciDevice;
+use super::{config::*, USB_DEVICE_BUFFER_DEFAULT_LEN};
+use super::{
+    notify_controller, UsbDevice, UsbDeviceBase, UsbDeviceRequest, UsbEndpoint, UsbPacket,
+    UsbPacketStatus,
+};
+use machine_manager::config::UsbU2fConfig;
+use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use util::loop_context::{
+    gen_delete_notifiers, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Size of the U2FHID packet.
+const U2F_PACKET_SIZE: usize = 64;
+/// Max number of the input packets cached for the guest.
+const U2F_PENDING_PACKETS_MAX: usize = 32;
+
+/// U2F key device descriptor
+static DESC_DEVICE_U2F: Lazy<Arc<UsbDescDevice>> = Lazy::new(|| {
+    Arc::new(UsbDescDevice {
+        device_desc: UsbDeviceDescriptor {
+            bLength: USB_DT_DEVICE_SIZE,
+            bDescriptorType: USB_DT_DEVICE,
+            idVendor: 0x0627,
+            idProduct: 0x0005,
+            bcdDevice: 0,
+            iManufacturer: STR_MANUFACTURER_INDEX,
+            iProduct: STR_PRODUCT_U2F_INDEX,
+            iSerialNumber: STR_SERIAL_U2F_INDEX,
+            bcdUSB: 0x0200,
+            bDeviceClass: 0,
+            bDeviceSubClass: 0,
+            bDeviceProtocol: 0,
+            bMaxPacketSize0: 64,
+            bNumConfigurations: 1,
+        },
+        configs: vec![Arc::new(UsbDescConfig {
+            config_desc: UsbConfigDescriptor {
+                bLength: USB_DT_CONFIG_SIZE,
+                bDescriptorType: USB_DT_CONFIGURATION,
+                wTotalLength: 0,
+                bNumInterfaces: 1,
+                bConfigurationValue: 1,
+                iConfiguration: STR_CONFIG_U2F_INDEX,
+                bmAttributes: USB_CONFIGURATION_ATTR_ONE,
+                bMaxPower: 15,
+            },
+            iad_desc: vec![],
+            interfaces: vec![DESC_IFACE_U2F.clone()],
+        })],
+    })
+});
+/// U2F key interface descriptor
+static DESC_IFACE_U2F: Lazy<Arc<UsbDescIface>> = Lazy::new(|| {
+    Arc::new(UsbDescIface {
+        interface_desc: UsbInterfaceDescriptor {
+            bLength: USB_DT_INTERFACE_SIZE,
+            bDescriptorType: USB_DT_INTERFACE,
+            bInterfaceNumber: 0,
+            bAlternateSetting: 0,
+            bNumEndpoints: 2,
+            bInterfaceClass: USB_CLASS_HID,
+            bInterfaceSubClass: 0,
+            bInterfaceProtocol: 0,
+            iInterface: 0,
+        },
+        other_desc: vec![Arc::new(UsbDescOther {
+            /// HID descriptor
+            data: vec![0x09, 0x21, 0x10, 0x01, 0x00, 0x01, 0x22, 0x22, 0],
+        })],
+        endpoints: vec![
+            Arc::new(UsbDescEndpoint {
+                endpoint_desc: UsbEndpointDescriptor {
+                    bLength: USB_DT_ENDPOINT_SIZE,
+                    bDescriptorType: USB_DT_ENDPOINT,
+                    bEndpointAddress: USB_DIRECTION_DEVICE_TO_HOST | 0x1,
+                    bmAttributes: USB_ENDPOINT_ATTR_INT,
+                    wMaxPacketSize: U2F_PACKET_SIZE as u16,
+                    bInterval: 0x5,
+                },
+                extra: Vec::new(),
+            }),
+            Arc::new(UsbDescEndpoint {
+                endpoint_desc: UsbEndpointDescriptor {
+                    bLength: USB_DT_ENDPOINT_SIZE,
+                    bDescriptorType: USB_DT_ENDPOINT,
+                    bEndpointAddress: USB_DIRECTION_HOST_TO_DEVICE | 0x1,
+                    bmAttributes: USB_ENDPOINT_ATTR_INT,
+                    wMaxPacketSize: U2F_PACKET_SIZE as u16,
+                    bInterval: 0x5,
+                },
+                extra: Vec::new(),
+            }),
+        ],
+    })
+});
+
+/// String descriptor index
+const STR_MANUFACTURER_INDEX: u8 = 1;
+const STR_PRODUCT_U2F_INDEX: u8 = 2;
+const STR_CONFIG_U2F_INDEX: u8 = 3;
+const STR_SERIAL_U2F_INDEX: u8 = 4;
+
+/// String descriptor
+const DESC_STRINGS: [&str; 5] = ["", "StratoVirt", "StratoVirt U2F Key", "U2F Key", "1"];
+
+/// USB U2F key device, which passes through the FIDO token of the host by its hidraw node.
+/// The U2FHID packets are exchanged with the token as they are, so the keys never leave it.
+pub struct UsbU2f {
+    base: UsbDeviceBase,
+    hid: Hid,
+    /// Hidraw node of the host FIDO token.
+    hidraw: File,
+    /// Input packets read from the token, waiting for the guest.
+    pending: VecDeque<[u8; U2F_PACKET_SIZE]>,
+    /// The token has been removed from the host.
+    broken: bool,
+    /// USB controller used to notify controller to transfer data.
+    cntlr: Option<Weak<Mutex<XhciDevice>>>,
+    delete_evts: Vec<RawFd>,
+}
+
+impl UsbU2f {
+    pub fn new(config: UsbU2fConfig) -> Result<Self> {
+        let hidraw = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&config.hidraw)
+            .with_context(|| format!("Failed to open hidraw {}", config.hidraw))?;
+
+        Ok(Self {
+            // SAFETY: id is already checked not none in parse_usb_u2f().
+            base: UsbDeviceBase::new(config.id.unwrap(), USB_DEVICE_BUFFER_DEFAULT_LEN),
+            hid: Hid::new(HidType::U2f),
+            hidraw,
+            pending: VecDeque::new(),
+            broken: false,
+            cntlr: None,
+            delete_evts: Vec::new(),
+        })
+    }
+
+    /// Read the input packets from the token. Return false if the token is broken.
+    fn read_hidraw(&mut self) -> bool {
+        loop {
+            let mut packet = [0_u8; U2F_PACKET_SIZE];
+            match self.hidraw.read(&mut packet) {
+                Ok(0) => return true,
+                Ok(_) => {
+                    if self.pending.len() >= U2F_PENDING_PACKETS_MAX {
+                        warn!("U2F {}: too many pending packets, drop", self.device_id());
+                        self.pending.pop_front();
+                    }
+                    self.pending.push_back(packet);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("U2F {}: failed to read hidraw: {:?}", self.device_id(), e);
+                    self.broken = true;
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn handle_token_in(&mut self, packet: &mut UsbPacket) {
+        if self.broken {
+            packet.status = UsbPacketStatus::Stall;
+            return;
+        }
+        match self.pending.pop_front() {
+            Some(mut buf) => packet.transfer_packet(&mut buf, U2F_PACKET_SIZE),
+            None => packet.status = UsbPacketStatus::Nak,
+        }
+    }
+
+    fn handle_token_out(&mut self, packet: &mut UsbPacket) {
+        if self.broken {
+            packet.status = UsbPacketStatus::Stall;
+            return;
+        }
+        // The first byte is the report number, which is 0 as U2FHID uses no report ids.
+        let mut buf = [0_u8; U2F_PACKET_SIZE + 1];
+        packet.transfer_packet(&mut buf[1..], U2F_PACKET_SIZE);
+        if let Err(e) = self.hidraw.write_all(&buf) {
+            error!("U2F {}: failed to write hidraw: {:?}", self.device_id(), e);
+            packet.status = UsbPacketStatus::Stall;
+        }
+    }
+}
+
+impl UsbDevice for UsbU2f {
+    fn usb_device_base(&self) -> &UsbDeviceBase {
+        &self.base
+    }
+
+    fn usb_device_base_mut(&mut self) -> &mut UsbDeviceBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<Arc<Mutex<dyn UsbDevice>>> {
+        self.base.reset_usb_endpoint();
+        self.base.speed = USB_SPEED_FULL;
+        let mut s: Vec<String> = DESC_STRINGS.iter().map(|&s| s.to_string()).collect();
+        let prefix = &s[STR_SERIAL_U2F_INDEX as usize];
+        s[STR_SERIAL_U2F_INDEX as usize] = self.base.generate_serial_number(prefix);
+        self.base.init_descriptor(DESC_DEVICE_U2F.clone(), s)?;
+
+        let u2f = Arc::new(Mutex::new(self));
+        let notifiers = EventNotifierHelper::internal_notifiers(u2f.clone());
+        register_event_helper(notifiers, None, &mut u2f.lock().unwrap().delete_evts)?;
+
+        Ok(u2f)
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        unregister_event_helper(None, &mut self.delete_evts)
+    }
+
+    fn reset(&mut self) {
+        info!("U2F device reset");
+        self.base.remote_wakeup = 0;
+        self.base.addr = 0;
+        self.hid.reset();
+        self.pending.clear();
+    }
+
+    fn handle_control(&mut self, packet: &Arc<Mutex<UsbPacket>>, device_req: &UsbDeviceRequest) {
+        debug!("handle_control request {:?}", device_req);
+        let mut locked_packet = packet.lock().unwrap();
+        match self
+            .base
+            .handle_control_for_descriptor(&mut locked_packet, device_req)
+        {
+            Ok(handled) => {
+                if handled {
+                    debug!("U2F control handled by descriptor, return directly.");
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("U2F descriptor error {:?}", e);
+                locked_packet.status = UsbPacketStatus::Stall;
+                return;
+            }
+        }
+        self.hid
+            .handle_control_packet(&mut locked_packet, device_req, &mut self.base.data_buf);
+    }
+
+    fn handle_data(&mut self, p: &Arc<Mutex<UsbPacket>>) {
+        let mut locked_p = p.lock().unwrap();
+        if locked_p.ep_number != 1 {
+            error!("Unhandled endpoint {}", locked_p.ep_number);
+            locked_p.status = UsbPacketStatus::Stall;
+            return;
+        }
+        match locked_p.pid as u8 {
+            USB_TOKEN_IN => self.handle_token_in(&mut locked_p),
+            USB_TOKEN_OUT => self.handle_token_out(&mut locked_p),
+            _ => {
+                error!("Unhandled packet {}", locked_p.pid);
+                locked_p.status = UsbPacketStatus::Stall;
+            }
+        }
+    }
+
+    fn set_controller(&mut self, cntlr: Weak<Mutex<XhciDevice>>) {
+        self.cntlr = Some(cntlr);
+    }
+
+    fn get_controller(&self) -> Option<Weak<Mutex<XhciDevice>>> {
+        self.cntlr.clone()
+    }
+
+    fn get_wakeup_endpoint(&self) -> &UsbEndpoint {
+        self.base.get_endpoint(true, 1)
+    }
+}
+
+impl EventNotifierHelper for UsbU2f {
+    fn internal_notifiers(u2f: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_u2f = u2f.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_event, fd: RawFd| {
+            let mut locked_u2f = cloned_u2f.lock().unwrap();
+            if !locked_u2f.read_hidraw() {
+                locked_u2f.delete_evts.clear();
+                return Some(gen_delete_notifiers(&[fd]));
+            }
+            if locked_u2f.pending.is_empty() {
+                return None;
+            }
+            drop(locked_u2f);
+            if let Err(e) = notify_controller(&(cloned_u2f.clone() as Arc<Mutex<dyn UsbDevice>>)) {
+                warn!("U2F failed to notify controller: {:?}", e);
+            }
+            None
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            u2f.lock().unwrap().hidraw.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
@@ -0,0 +1,1003 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, error, info, warn};
+
+use super::config::*;
+use super::xhci::xhci_controller::{UsbPort, XhciDevice};
+use super::{
+    notify_controller, UsbDevice, UsbDeviceBase, UsbDeviceRequest, UsbEndpoint, UsbPacket,
+    UsbPacketStatus,
+};
+use chardev_backend::chardev::{Chardev, ChardevNotifyDevice, ChardevStatus, InputReceiver};
+use machine_manager::{config::UsbRedirConfig, event_loop::EventLoop};
+use util::byte_code::ByteCode;
+use util::loop_context::EventNotifierHelper;
+
+/// Version string sent to the peer in the hello message.
+const USB_REDIR_VERSION: &str = "stratovirt usb-redir";
+const USB_REDIR_VERSION_LEN: usize = 64;
+/// Buffer for control transfers, which is large enough for the max length of the request.
+const USB_REDIR_BUFFER_LEN: usize = 64 * 1024;
+/// Max length of the message from the peer.
+const USB_REDIR_MAX_MSG_LEN: usize = 1024 * 1024;
+/// Max number of the buffered interrupt IN packets of each endpoint.
+const USB_REDIR_MAX_INT_PACKETS: usize = 16;
+const USB_REDIR_MAX_ENDPOINTS: usize = 32;
+
+/// Message types of usbredir protocol.
+const USB_REDIR_HELLO: u32 = 0;
+const USB_REDIR_DEVICE_CONNECT: u32 = 1;
+const USB_REDIR_DEVICE_DISCONNECT: u32 = 2;
+const USB_REDIR_RESET: u32 = 3;
+const USB_REDIR_INTERFACE_INFO: u32 = 4;
+const USB_REDIR_EP_INFO: u32 = 5;
+const USB_REDIR_SET_CONFIGURATION: u32 = 6;
+const USB_REDIR_CONFIGURATION_STATUS: u32 = 8;
+const USB_REDIR_SET_ALT_SETTING: u32 = 9;
+const USB_REDIR_ALT_SETTING_STATUS: u32 = 11;
+const USB_REDIR_START_INTERRUPT_RECEIVING: u32 = 15;
+const USB_REDIR_INTERRUPT_RECEIVING_STATUS: u32 = 17;
+const USB_REDIR_CONTROL_PACKET: u32 = 100;
+const USB_REDIR_BULK_PACKET: u32 = 101;
+const USB_REDIR_INTERRUPT_PACKET: u32 = 103;
+
+/// Capabilities of usbredir protocol.
+const USB_REDIR_CAP_CONNECT_DEVICE_VERSION: u32 = 1;
+const USB_REDIR_CAP_EP_INFO_MAX_PACKET_SIZE: u32 = 4;
+const USB_REDIR_CAP_32BITS_BULK_LENGTH: u32 = 6;
+const USB_REDIR_CAPS: u32 = 1 << USB_REDIR_CAP_CONNECT_DEVICE_VERSION
+    | 1 << USB_REDIR_CAP_EP_INFO_MAX_PACKET_SIZE
+    | 1 << USB_REDIR_CAP_32BITS_BULK_LENGTH;
+
+/// Status of the transfer in usbredir protocol.
+const USB_REDIR_SUCCESS: u8 = 0;
+const USB_REDIR_CANCELLED: u8 = 1;
+const USB_REDIR_INVAL: u8 = 2;
+const USB_REDIR_STALL: u8 = 4;
+const USB_REDIR_BABBLE: u8 = 6;
+
+/// The header of every message. The id is 32 bits as the 64 bits ids capability is not used.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirHeader {
+    msg_type: u32,
+    length: u32,
+    id: u32,
+}
+
+impl ByteCode for UsbRedirHeader {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirDeviceConnect {
+    speed: u8,
+    device_class: u8,
+    device_subclass: u8,
+    device_protocol: u8,
+    vendor_id: u16,
+    product_id: u16,
+}
+
+impl ByteCode for UsbRedirDeviceConnect {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirSetConfiguration {
+    configuration: u8,
+}
+
+impl ByteCode for UsbRedirSetConfiguration {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirConfigurationStatus {
+    status: u8,
+    configuration: u8,
+}
+
+impl ByteCode for UsbRedirConfigurationStatus {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirSetAltSetting {
+    interface: u8,
+    alt: u8,
+}
+
+impl ByteCode for UsbRedirSetAltSetting {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirAltSettingStatus {
+    status: u8,
+    interface: u8,
+    alt: u8,
+}
+
+impl ByteCode for UsbRedirAltSettingStatus {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirStartInterruptReceiving {
+    endpoint: u8,
+}
+
+impl ByteCode for UsbRedirStartInterruptReceiving {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirControlPacket {
+    endpoint: u8,
+    request: u8,
+    request_type: u8,
+    status: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+}
+
+impl ByteCode for UsbRedirControlPacket {}
+
+/// Header of bulk packet, `length_high` is valid only if both sides have the 32 bits
+/// bulk length capability.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirBulkPacket {
+    endpoint: u8,
+    status: u8,
+    length: u16,
+    stream_id: u32,
+    length_high: u16,
+}
+
+impl ByteCode for UsbRedirBulkPacket {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UsbRedirInterruptPacket {
+    endpoint: u8,
+    status: u8,
+    length: u16,
+}
+
+impl ByteCode for UsbRedirInterruptPacket {}
+
+/// Read the message header of type `T` from the start of the payload.
+fn read_header<T: ByteCode>(payload: &[u8]) -> Result<T> {
+    let size = size_of::<T>();
+    if payload.len() < size {
+        bail!("Invalid usbredir message length {}", payload.len());
+    }
+    // It's safe to unwrap as the length is checked.
+    Ok(*T::from_bytes(&payload[..size]).unwrap())
+}
+
+/// Get the index of the endpoint in usbredir protocol, the IN endpoints are 16 to 31.
+fn ep_index(endpoint: u8) -> usize {
+    (((endpoint & USB_DIRECTION_DEVICE_TO_HOST) >> 3) | (endpoint & 0x0f)) as usize
+}
+
+fn redir_status_to_packet_status(status: u8) -> UsbPacketStatus {
+    match status {
+        USB_REDIR_SUCCESS => UsbPacketStatus::Success,
+        USB_REDIR_CANCELLED => UsbPacketStatus::NoDev,
+        USB_REDIR_INVAL | USB_REDIR_STALL => UsbPacketStatus::Stall,
+        USB_REDIR_BABBLE => UsbPacketStatus::Babble,
+        _ => UsbPacketStatus::IoError,
+    }
+}
+
+/// Actions which need the controller lock, and must be done without the device lock held.
+#[derive(Debug, PartialEq, Eq)]
+enum UsbRedirEvent {
+    /// The remote device is connected, attach it to the controller.
+    Attach,
+    /// The remote device is disconnected, detach it from the controller.
+    Detach,
+    /// Data arrives at the interrupt IN endpoint.
+    Wakeup,
+}
+
+/// USB device which is redirected from the remote client by usbredir protocol.
+pub struct UsbRedir {
+    base: UsbDeviceBase,
+    chardev: Arc<Mutex<Chardev>>,
+    cntlr: Option<Weak<Mutex<XhciDevice>>>,
+    /// Capabilities of the peer, available after the hello message is received.
+    peer_caps: Option<u32>,
+    /// Whether the remote device is connected.
+    connected: bool,
+    /// Data from the peer which is not a complete message yet.
+    inbuf: Vec<u8>,
+    next_id: u32,
+    /// Packets waiting for the reply from the peer.
+    inflight: HashMap<u32, Arc<Mutex<UsbPacket>>>,
+    /// Buffered data of the interrupt IN endpoints.
+    interrupt_in: HashMap<u8, VecDeque<Vec<u8>>>,
+    /// Interrupt IN endpoints which the peer has been asked to receive from.
+    interrupt_receiving: [bool; USB_REDIR_MAX_ENDPOINTS],
+    /// Endpoint which is kicked when the interrupt IN data arrives.
+    wakeup_ep: UsbEndpoint,
+}
+
+impl UsbRedir {
+    pub fn new(config: UsbRedirConfig) -> Self {
+        // SAFETY: id is already checked not none in parse_usb_redir().
+        let id = config.id.unwrap();
+        Self {
+            base: UsbDeviceBase::new(id, USB_REDIR_BUFFER_LEN),
+            chardev: Arc::new(Mutex::new(Chardev::new(config.chardev))),
+            cntlr: None,
+            peer_caps: None,
+            connected: false,
+            inbuf: Vec::new(),
+            next_id: 0,
+            inflight: HashMap::new(),
+            interrupt_in: HashMap::new(),
+            interrupt_receiving: [false; USB_REDIR_MAX_ENDPOINTS],
+            wakeup_ep: UsbEndpoint::new(1, true, USB_ENDPOINT_ATTR_INT),
+        }
+    }
+
+    /// Realize the usb-redir device. The device is attached to the controller when the
+    /// remote device is connected, so the controller is set here.
+    pub fn realize_with_controller(
+        self,
+        cntlr: &Arc<Mutex<XhciDevice>>,
+    ) -> Result<Arc<Mutex<dyn UsbDevice>>> {
+        let chardev = self.chardev.clone();
+        let usbredir = Arc::new(Mutex::new(self));
+        usbredir
+            .lock()
+            .unwrap()
+            .set_controller(Arc::downgrade(cntlr));
+
+        chardev
+            .lock()
+            .unwrap()
+            .realize()
+            .with_context(|| "Failed to realize chardev of usb-redir")?;
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(chardev.clone()),
+            None,
+        )?;
+        let receiver = Arc::new(Mutex::new(UsbRedirReceiver {
+            dev: usbredir.clone(),
+        }));
+        let mut locked_chardev = chardev.lock().unwrap();
+        locked_chardev.set_receiver(&receiver);
+        locked_chardev.set_device(receiver);
+
+        Ok(usbredir)
+    }
+
+    fn peer_has_cap(&self, cap: u32) -> bool {
+        self.peer_caps.unwrap_or(0) & (1 << cap) != 0
+    }
+
+    fn bulk_length_32bits(&self) -> bool {
+        self.peer_has_cap(USB_REDIR_CAP_32BITS_BULK_LENGTH)
+    }
+
+    fn send_msg(&mut self, msg_type: u32, id: u32, header: &[u8], data: &[u8]) {
+        let hdr = UsbRedirHeader {
+            msg_type,
+            length: (header.len() + data.len()) as u32,
+            id,
+        };
+        let mut buf = Vec::with_capacity(size_of::<UsbRedirHeader>() + hdr.length as usize);
+        buf.extend_from_slice(hdr.as_bytes());
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(data);
+        if let Err(e) = Chardev::fill_outbuf(&self.chardev, &buf, None) {
+            error!(
+                "Failed to send usbredir message {} of device {}: {:?}",
+                msg_type,
+                self.device_id(),
+                e
+            );
+        }
+    }
+
+    fn send_hello(&mut self) {
+        let mut hello = vec![0_u8; USB_REDIR_VERSION_LEN];
+        hello[..USB_REDIR_VERSION.len()].copy_from_slice(USB_REDIR_VERSION.as_bytes());
+        hello.extend_from_slice(&USB_REDIR_CAPS.to_le_bytes());
+        self.send_msg(USB_REDIR_HELLO, 0, &hello, &[]);
+    }
+
+    /// Send the request and wait for the reply asynchronously.
+    fn send_request(
+        &mut self,
+        packet: &Arc<Mutex<UsbPacket>>,
+        msg_type: u32,
+        header: &[u8],
+        data: &[u8],
+    ) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        packet.lock().unwrap().is_async = true;
+        self.inflight.insert(id, packet.clone());
+        self.send_msg(msg_type, id, header, data);
+    }
+
+    /// Complete the asynchronous packet with the reply from the peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the request.
+    /// * `status` - Status in usbredir protocol.
+    /// * `data` - Data for IN transfer.
+    /// * `out_len` - Transferred length for OUT transfer.
+    fn complete_packet(&mut self, id: u32, status: u8, data: &[u8], out_len: u32) {
+        let packet = match self.inflight.remove(&id) {
+            Some(packet) => packet,
+            None => {
+                warn!("usb-redir {}: no packet for reply {}", self.device_id(), id);
+                return;
+            }
+        };
+        let mut locked_packet = packet.lock().unwrap();
+        if !locked_packet.is_async {
+            // The packet has been cancelled.
+            return;
+        }
+        locked_packet.status = redir_status_to_packet_status(status);
+        if locked_packet.pid as u8 == USB_TOKEN_IN {
+            let mut data = data.to_vec();
+            let len = data.len();
+            locked_packet.transfer_packet(&mut data, len);
+        } else {
+            locked_packet.actual_length = out_len;
+        }
+        let ops = locked_packet.xfer_ops.clone();
+        drop(locked_packet);
+        if let Some(ops) = ops.and_then(|ops| ops.upgrade()) {
+            ops.lock().unwrap().submit_transfer();
+        }
+    }
+
+    /// Complete all the asynchronous packets as the device is gone.
+    fn cancel_packets(&mut self) {
+        let ids: Vec<u32> = self.inflight.keys().copied().collect();
+        for id in ids {
+            self.complete_packet(id, USB_REDIR_CANCELLED, &[], 0);
+        }
+    }
+
+    fn clear_interrupt_state(&mut self) {
+        self.interrupt_in.clear();
+        self.interrupt_receiving = [false; USB_REDIR_MAX_ENDPOINTS];
+    }
+
+    /// Handle the data from the peer, and return the actions to be done without the device lock.
+    fn handle_input(&mut self, data: &[u8]) -> Vec<UsbRedirEvent> {
+        self.inbuf.extend_from_slice(data);
+        let mut events = Vec::new();
+        let hdr_len = size_of::<UsbRedirHeader>();
+        while self.inbuf.len() >= hdr_len {
+            // It's safe to unwrap as the length is checked.
+            let hdr = *UsbRedirHeader::from_bytes(&self.inbuf[..hdr_len]).unwrap();
+            let len = hdr.length as usize;
+            if len > USB_REDIR_MAX_MSG_LEN {
+                error!(
+                    "usb-redir {}: invalid message length {}",
+                    self.device_id(),
+                    len
+                );
+                self.inbuf.clear();
+                break;
+            }
+            if self.inbuf.len() < hdr_len + len {
+                break;
+            }
+            let payload: Vec<u8> = self.inbuf.drain(..hdr_len + len).skip(hdr_len).collect();
+            match self.handle_msg(&hdr, &payload) {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {}
+                Err(e) => error!(
+                    "usb-redir {}: failed to handle message {}: {:?}",
+                    self.device_id(),
+                    { hdr.msg_type },
+                    e
+                ),
+            }
+        }
+        events
+    }
+
+    fn handle_msg(
+        &mut self,
+        hdr: &UsbRedirHeader,
+        payload: &[u8],
+    ) -> Result<Option<UsbRedirEvent>> {
+        if hdr.msg_type != USB_REDIR_HELLO && self.peer_caps.is_none() {
+            bail!("Message before hello");
+        }
+        match hdr.msg_type {
+            USB_REDIR_HELLO => {
+                if payload.len() < USB_REDIR_VERSION_LEN {
+                    bail!("Invalid hello message");
+                }
+                let mut caps = [0_u8; 4];
+                let caps_len = min(payload.len() - USB_REDIR_VERSION_LEN, caps.len());
+                caps[..caps_len].copy_from_slice(
+                    &payload[USB_REDIR_VERSION_LEN..USB_REDIR_VERSION_LEN + caps_len],
+                );
+                let version = String::from_utf8_lossy(&payload[..USB_REDIR_VERSION_LEN]);
+                info!(
+                    "usb-redir {}: peer version {}",
+                    self.device_id(),
+                    version.trim_end_matches('\0')
+                );
+                self.peer_caps = Some(u32::from_le_bytes(caps));
+                self.send_hello();
+            }
+            USB_REDIR_DEVICE_CONNECT => {
+                let connect = read_header::<UsbRedirDeviceConnect>(payload)?;
+                if self.connected {
+                    bail!("Device is already connected");
+                }
+                self.base.speed = match connect.speed as u32 {
+                    speed @ USB_SPEED_LOW..=USB_SPEED_SUPER => speed,
+                    _ => USB_SPEED_FULL,
+                };
+                info!(
+                    "usb-redir {}: device {:04x}:{:04x} connected",
+                    self.device_id(),
+                    { connect.vendor_id },
+                    { connect.product_id }
+                );
+                self.connected = true;
+                return Ok(Some(UsbRedirEvent::Attach));
+            }
+            USB_REDIR_DEVICE_DISCONNECT => {
+                if !self.connected {
+                    return Ok(None);
+                }
+                info!("usb-redir {}: device disconnected", self.device_id());
+                self.connected = false;
+                self.cancel_packets();
+                self.clear_interrupt_state();
+                self.base.reset_usb_endpoint();
+                return Ok(Some(UsbRedirEvent::Detach));
+            }
+            USB_REDIR_INTERFACE_INFO => {}
+            USB_REDIR_EP_INFO => self.handle_ep_info(payload)?,
+            USB_REDIR_CONFIGURATION_STATUS => {
+                let status = read_header::<UsbRedirConfigurationStatus>(payload)?;
+                self.complete_packet(hdr.id, status.status, &[], 0);
+            }
+            USB_REDIR_ALT_SETTING_STATUS => {
+                let status = read_header::<UsbRedirAltSettingStatus>(payload)?;
+                if status.status == USB_REDIR_SUCCESS
+                    && (status.interface as usize) < self.base.altsetting.len()
+                {
+                    self.base.altsetting[status.interface as usize] = status.alt as u32;
+                }
+                self.complete_packet(hdr.id, status.status, &[], 0);
+            }
+            USB_REDIR_CONTROL_PACKET => {
+                let ctrl = read_header::<UsbRedirControlPacket>(payload)?;
+                let data = &payload[size_of::<UsbRedirControlPacket>()..];
+                self.complete_packet(hdr.id, ctrl.status, data, ctrl.length as u32);
+            }
+            USB_REDIR_BULK_PACKET => {
+                let mut bulk = UsbRedirBulkPacket::default();
+                let hdr_len = self.bulk_header_len();
+                if payload.len() < hdr_len {
+                    bail!("Invalid bulk packet length {}", payload.len());
+                }
+                bulk.as_mut_bytes()[..hdr_len].copy_from_slice(&payload[..hdr_len]);
+                let len = (bulk.length_high as u32) << 16 | bulk.length as u32;
+                self.complete_packet(hdr.id, bulk.status, &payload[hdr_len..], len);
+            }
+            USB_REDIR_INTERRUPT_PACKET => {
+                let int = read_header::<UsbRedirInterruptPacket>(payload)?;
+                if int.endpoint & USB_DIRECTION_DEVICE_TO_HOST == 0 {
+                    self.complete_packet(hdr.id, int.status, &[], int.length as u32);
+                    return Ok(None);
+                }
+                if int.status != USB_REDIR_SUCCESS {
+                    return Ok(None);
+                }
+                let data = payload[size_of::<UsbRedirInterruptPacket>()..].to_vec();
+                let queue = self.interrupt_in.entry(int.endpoint).or_default();
+                let was_empty = queue.is_empty();
+                if queue.len() >= USB_REDIR_MAX_INT_PACKETS {
+                    queue.pop_front();
+                }
+                queue.push_back(data);
+                if was_empty {
+                    self.wakeup_ep = self.base.get_endpoint(true, int.endpoint & 0x0f).clone();
+                    return Ok(Some(UsbRedirEvent::Wakeup));
+                }
+            }
+            USB_REDIR_INTERRUPT_RECEIVING_STATUS => {}
+            _ => debug!("usb-redir {}: ignore message {}", self.device_id(), {
+                hdr.msg_type
+            }),
+        }
+        Ok(None)
+    }
+
+    fn bulk_header_len(&self) -> usize {
+        if self.bulk_length_32bits() {
+            size_of::<UsbRedirBulkPacket>()
+        } else {
+            size_of::<UsbRedirBulkPacket>() - size_of::<u16>()
+        }
+    }
+
+    /// Update the endpoints by the ep_info message, which is sent by the peer when the device
+    /// is connected and its configuration or alternate setting is changed.
+    fn handle_ep_info(&mut self, payload: &[u8]) -> Result<()> {
+        let has_mps = self.peer_has_cap(USB_REDIR_CAP_EP_INFO_MAX_PACKET_SIZE);
+        let n = USB_REDIR_MAX_ENDPOINTS;
+        let len = if has_mps { n * 5 } else { n * 3 };
+        if payload.len() < len {
+            bail!("Invalid ep info length {}", payload.len());
+        }
+        let (types, intervals_ifaces) = payload.split_at(n);
+        let ifaces = &intervals_ifaces[n..2 * n];
+        for i in 0..n {
+            let ep_number = (i & 0x0f) as u8;
+            if ep_number == 0 {
+                continue;
+            }
+            let in_direction = i & 0x10 != 0;
+            if types[i] != USB_ENDPOINT_ATTR_INT && in_direction {
+                self.interrupt_receiving[i] = false;
+                self.interrupt_in
+                    .remove(&(ep_number | USB_DIRECTION_DEVICE_TO_HOST));
+            }
+            let ep = self.base.get_mut_endpoint(in_direction, ep_number);
+            ep.ep_type = types[i];
+            ep.ifnum = ifaces[i];
+            ep.halted = false;
+            if has_mps {
+                let off = 3 * n + 2 * i;
+                ep.set_max_packet_size(u16::from_le_bytes([payload[off], payload[off + 1]]));
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_interrupt_in(&mut self, packet: &mut UsbPacket) {
+        let endpoint = packet.ep_number | USB_DIRECTION_DEVICE_TO_HOST;
+        let index = ep_index(endpoint);
+        if !self.interrupt_receiving[index] {
+            self.interrupt_receiving[index] = true;
+            let start = UsbRedirStartInterruptReceiving { endpoint };
+            self.send_msg(
+                USB_REDIR_START_INTERRUPT_RECEIVING,
+                0,
+                start.as_bytes(),
+                &[],
+            );
+        }
+        match self
+            .interrupt_in
+            .get_mut(&endpoint)
+            .and_then(|queue| queue.pop_front())
+        {
+            Some(mut data) => {
+                let len = data.len();
+                if len as u64 > packet.get_iovecs_size() {
+                    packet.status = UsbPacketStatus::Babble;
+                    return;
+                }
+                packet.transfer_packet(&mut data, len);
+            }
+            None => packet.status = UsbPacketStatus::Nak,
+        }
+    }
+
+    /// Handle the actions which need the controller lock.
+    fn handle_events(dev: &Arc<Mutex<UsbRedir>>, events: Vec<UsbRedirEvent>) {
+        for event in events {
+            let result = match event {
+                UsbRedirEvent::Attach => Self::attach(dev),
+                UsbRedirEvent::Detach => Self::detach(dev),
+                UsbRedirEvent::Wakeup => {
+                    let usb_dev: Arc<Mutex<dyn UsbDevice>> = dev.clone();
+                    notify_controller(&usb_dev)
+                }
+            };
+            if let Err(e) = result {
+                error!("usb-redir failed to handle {:?}: {:?}", event, e);
+            }
+        }
+    }
+
+    fn controller(dev: &Arc<Mutex<UsbRedir>>) -> Result<Arc<Mutex<XhciDevice>>> {
+        dev.lock()
+            .unwrap()
+            .cntlr
+            .as_ref()
+            .and_then(|cntlr| cntlr.upgrade())
+            .with_context(|| "USB controller not found")
+    }
+
+    fn port(dev: &Arc<Mutex<UsbRedir>>) -> Option<Arc<Mutex<UsbPort>>> {
+        dev.lock()
+            .unwrap()
+            .base
+            .port
+            .as_ref()
+            .and_then(|port| port.upgrade())
+    }
+
+    fn attach(dev: &Arc<Mutex<UsbRedir>>) -> Result<()> {
+        let xhci = Self::controller(dev)?;
+        if Self::port(dev).is_some() {
+            return Ok(());
+        }
+        let usb_dev: Arc<Mutex<dyn UsbDevice>> = dev.clone();
+        let mut locked_xhci = xhci.lock().unwrap();
+        let port = locked_xhci
+            .assign_usb_port(&usb_dev)
+            .with_context(|| "No available USB port")?;
+        locked_xhci.port_update(&port, false)
+    }
+
+    fn detach(dev: &Arc<Mutex<UsbRedir>>) -> Result<()> {
+        let xhci = Self::controller(dev)?;
+        let port = match Self::port(dev) {
+            Some(port) => port,
+            None => return Ok(()),
+        };
+        let mut locked_xhci = xhci.lock().unwrap();
+        let slot_id = port.lock().unwrap().slot_id;
+        locked_xhci.detach_slot(slot_id)?;
+        locked_xhci.port_update(&port, true)?;
+        locked_xhci.discharge_usb_port(&mut port.lock().unwrap());
+        drop(locked_xhci);
+        dev.lock().unwrap().set_usb_port(None);
+        Ok(())
+    }
+
+    /// The peer is gone, so reset the protocol state and detach the device.
+    fn peer_closed(&mut self) -> Option<UsbRedirEvent> {
+        self.peer_caps = None;
+        self.inbuf.clear();
+        if !self.connected {
+            return None;
+        }
+        self.connected = false;
+        self.cancel_packets();
+        self.clear_interrupt_state();
+        self.base.reset_usb_endpoint();
+        Some(UsbRedirEvent::Detach)
+    }
+}
+
+impl UsbDevice for UsbRedir {
+    fn usb_device_base(&self) -> &UsbDeviceBase {
+        &self.base
+    }
+
+    fn usb_device_base_mut(&mut self) -> &mut UsbDeviceBase {
+        &mut self.base
+    }
+
+    fn realize(self) -> Result<Arc<Mutex<dyn UsbDevice>>> {
+        bail!("usb-redir must be realized with the USB controller");
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        self.chardev.lock().unwrap().unrealize()?;
+        info!("usb-redir device {} is unrealized", self.device_id());
+        Ok(())
+    }
+
+    fn handle_attach(&mut self) -> Result<()> {
+        // The descriptors are provided by the remote device.
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        info!("usb-redir device {} reset", self.device_id());
+        self.base.addr = 0;
+        self.clear_interrupt_state();
+        if self.connected {
+            self.send_msg(USB_REDIR_RESET, 0, &[], &[]);
+        }
+    }
+
+    fn set_controller(&mut self, cntlr: Weak<Mutex<XhciDevice>>) {
+        self.cntlr = Some(cntlr);
+    }
+
+    fn get_controller(&self) -> Option<Weak<Mutex<XhciDevice>>> {
+        self.cntlr.clone()
+    }
+
+    fn get_wakeup_endpoint(&self) -> &UsbEndpoint {
+        &self.wakeup_ep
+    }
+
+    fn handle_control(&mut self, packet: &Arc<Mutex<UsbPacket>>, device_req: &UsbDeviceRequest) {
+        if !self.connected {
+            packet.lock().unwrap().status = UsbPacketStatus::NoDev;
+            return;
+        }
+        match (device_req.request_type, device_req.request) {
+            (USB_DEVICE_OUT_REQUEST, USB_REQUEST_SET_ADDRESS) => {
+                self.base.addr = device_req.value as u8;
+            }
+            (USB_DEVICE_OUT_REQUEST, USB_REQUEST_SET_CONFIGURATION) => {
+                self.clear_interrupt_state();
+                let set = UsbRedirSetConfiguration {
+                    configuration: device_req.value as u8,
+                };
+                self.send_request(packet, USB_REDIR_SET_CONFIGURATION, set.as_bytes(), &[]);
+            }
+            (USB_INTERFACE_OUT_REQUEST, USB_REQUEST_SET_INTERFACE) => {
+                let set = UsbRedirSetAltSetting {
+                    interface: device_req.index as u8,
+                    alt: device_req.value as u8,
+                };
+                self.send_request(packet, USB_REDIR_SET_ALT_SETTING, set.as_bytes(), &[]);
+            }
+            _ => {
+                let ctrl = UsbRedirControlPacket {
+                    endpoint: device_req.request_type & USB_DIRECTION_DEVICE_TO_HOST,
+                    request: device_req.request,
+                    request_type: device_req.request_type,
+                    status: 0,
+                    value: device_req.value,
+                    index: device_req.index,
+                    length: device_req.length,
+                };
+                let data = if ctrl.endpoint == USB_DIRECTION_DEVICE_TO_HOST {
+                    Vec::new()
+                } else {
+                    self.base.data_buf[..device_req.length as usize].to_vec()
+                };
+                self.send_request(packet, USB_REDIR_CONTROL_PACKET, ctrl.as_bytes(), &data);
+            }
+        }
+    }
+
+    fn handle_data(&mut self, packet: &Arc<Mutex<UsbPacket>>) {
+        let mut locked_packet = packet.lock().unwrap();
+        if !self.connected {
+            locked_packet.status = UsbPacketStatus::NoDev;
+            return;
+        }
+        let in_direction = locked_packet.pid as u8 == USB_TOKEN_IN;
+        let ep_number = locked_packet.ep_number;
+        let endpoint = if in_direction {
+            ep_number | USB_DIRECTION_DEVICE_TO_HOST
+        } else {
+            ep_number
+        };
+        let size = locked_packet.get_iovecs_size() as usize;
+        let mut data = Vec::new();
+        if !in_direction {
+            data = vec![0_u8; size];
+            locked_packet.transfer_packet(&mut data, size);
+        }
+
+        match self.base.get_endpoint(in_direction, ep_number).ep_type {
+            USB_ENDPOINT_ATTR_BULK => {
+                if size > u16::MAX as usize && !self.bulk_length_32bits() {
+                    locked_packet.status = UsbPacketStatus::Stall;
+                    return;
+                }
+                drop(locked_packet);
+                let bulk = UsbRedirBulkPacket {
+                    endpoint,
+                    status: 0,
+                    length: size as u16,
+                    stream_id: 0,
+                    length_high: (size >> 16) as u16,
+                };
+                let hdr_len = self.bulk_header_len();
+                self.send_request(
+                    packet,
+                    USB_REDIR_BULK_PACKET,
+                    &bulk.as_bytes()[..hdr_len],
+                    &data,
+                );
+            }
+            USB_ENDPOINT_ATTR_INT => {
+                if in_direction {
+                    self.handle_interrupt_in(&mut locked_packet);
+                    return;
+                }
+                drop(locked_packet);
+                let int = UsbRedirInterruptPacket {
+                    endpoint,
+                    status: 0,
+                    length: size as u16,
+                };
+                self.send_request(packet, USB_REDIR_INTERRUPT_PACKET, int.as_bytes(), &data);
+            }
+            _ => {
+                // Isochronous transfer is not supported.
+                locked_packet.status = UsbPacketStatus::Stall;
+            }
+        }
+    }
+}
+
+/// Receive the usbredir messages from the chardev, and watch the connection of the peer.
+/// It keeps the device alive while the device is not attached to the controller.
+struct UsbRedirReceiver {
+    dev: Arc<Mutex<UsbRedir>>,
+}
+
+impl InputReceiver for UsbRedirReceiver {
+    fn receive(&mut self, buffer: &[u8]) {
+        let events = self.dev.lock().unwrap().handle_input(buffer);
+        UsbRedir::handle_events(&self.dev, events);
+    }
+
+    fn remain_size(&mut self) -> usize {
+        USB_REDIR_BUFFER_LEN
+    }
+}
+
+impl ChardevNotifyDevice for UsbRedirReceiver {
+    fn chardev_notify(&mut self, status: ChardevStatus) {
+        if let ChardevStatus::Open = status {
+            // The hello message is sent after the hello message of the peer is received.
+            return;
+        }
+        let dev = self.dev.clone();
+        // The chardev is locked here, so detach the device in the next loop iteration.
+        let func = Box::new(move || {
+            let event = dev.lock().unwrap().peer_closed();
+            UsbRedir::handle_events(&dev, event.into_iter().collect());
+        });
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            ctx.timer_add(func, Duration::ZERO);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_manager::config::{ChardevConfig, ChardevType};
+
+    fn redir_dev() -> UsbRedir {
+        UsbRedir::new(UsbRedirConfig {
+            id: Some("redir0".to_string()),
+            chardev: ChardevConfig {
+                id: "chr0".to_string(),
+                backend: ChardevType::Socket {
+                    path: "/tmp/usbredir.sock".to_string(),
+                    server: true,
+                    nowait: true,
+                },
+                mux: false,
+            },
+        })
+    }
+
+    fn msg(msg_type: u32, id: u32, payload: &[u8]) -> Vec<u8> {
+        let hdr = UsbRedirHeader {
+            msg_type,
+            length: payload.len() as u32,
+            id,
+        };
+        let mut buf = hdr.as_bytes().to_vec();
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    fn hello(caps: u32) -> Vec<u8> {
+        let mut payload = vec![0_u8; USB_REDIR_VERSION_LEN];
+        payload[..4].copy_from_slice(b"test");
+        payload.extend_from_slice(&caps.to_le_bytes());
+        msg(USB_REDIR_HELLO, 0, &payload)
+    }
+
+    #[test]
+    fn test_usbredir_ep_index() {
+        assert_eq!(ep_index(0x81), 17);
+        assert_eq!(ep_index(0x02), 2);
+        assert_eq!(ep_index(0x8f), 31);
+        assert_eq!(size_of::<UsbRedirHeader>(), 12);
+        assert_eq!(size_of::<UsbRedirControlPacket>(), 10);
+        assert_eq!(size_of::<UsbRedirBulkPacket>(), 10);
+    }
+
+    #[test]
+    fn test_usbredir_handle_input() {
+        let mut dev = redir_dev();
+        // Messages before hello are dropped.
+        let connect = UsbRedirDeviceConnect {
+            speed: USB_SPEED_HIGH as u8,
+            vendor_id: 0x1234,
+            product_id: 0x5678,
+            ..Default::default()
+        };
+        let connect_msg = msg(USB_REDIR_DEVICE_CONNECT, 0, connect.as_bytes());
+        assert!(dev.handle_input(&connect_msg).is_empty());
+        assert!(!dev.connected);
+
+        // The message may be split.
+        let hello_msg = hello(1 << USB_REDIR_CAP_EP_INFO_MAX_PACKET_SIZE);
+        assert!(dev.handle_input(&hello_msg[..10]).is_empty());
+        assert!(dev.handle_input(&hello_msg[10..]).is_empty());
+        assert!(dev.peer_has_cap(USB_REDIR_CAP_EP_INFO_MAX_PACKET_SIZE));
+        assert!(!dev.bulk_length_32bits());
+        assert_eq!(dev.bulk_header_len(), 8);
+
+        // Endpoint 0x81 is interrupt IN, and endpoint 0x02 is bulk OUT.
+        let mut ep_info = vec![USB_ENDPOINT_ATTR_INVALID; USB_REDIR_MAX_ENDPOINTS];
+        ep_info[0x11] = USB_ENDPOINT_ATTR_INT;
+        ep_info[0x02] = USB_ENDPOINT_ATTR_BULK;
+        ep_info.extend(vec![0_u8; USB_REDIR_MAX_ENDPOINTS * 2]);
+        let mut mps = vec![0_u8; USB_REDIR_MAX_ENDPOINTS * 2];
+        mps[0x02 * 2..0x02 * 2 + 2].copy_from_slice(&512_u16.to_le_bytes());
+        ep_info.extend(mps);
+        let mut input = msg(USB_REDIR_EP_INFO, 0, &ep_info);
+        input.extend(connect_msg);
+        assert_eq!(dev.handle_input(&input), vec![UsbRedirEvent::Attach]);
+        assert!(dev.connected);
+        assert_eq!(dev.speed(), USB_SPEED_HIGH);
+        assert_eq!(
+            dev.base.get_endpoint(true, 1).ep_type,
+            USB_ENDPOINT_ATTR_INT
+        );
+        assert_eq!(
+            dev.base.get_endpoint(false, 2).ep_type,
+            USB_ENDPOINT_ATTR_BULK
+        );
+        assert_eq!(dev.base.get_endpoint(false, 2).max_packet_size, 512);
+
+        // Interrupt IN data wakes up the endpoint only if the queue was empty.
+        let int = UsbRedirInterruptPacket {
+            endpoint: 0x81,
+            status: USB_REDIR_SUCCESS,
+            length: 2,
+        };
+        let mut payload = int.as_bytes().to_vec();
+        payload.extend_from_slice(&[1, 2]);
+        let int_msg = msg(USB_REDIR_INTERRUPT_PACKET, 0, &payload);
+        assert_eq!(dev.handle_input(&int_msg), vec![UsbRedirEvent::Wakeup]);
+        assert!(dev.handle_input(&int_msg).is_empty());
+        assert_eq!(dev.interrupt_in.get(&0x81).unwrap().len(), 2);
+
+        let mut packet = UsbPacket {
+            pid: USB_TOKEN_IN as u32,
+            ep_number: 1,
+            ..Default::default()
+        };
+        let buf = [0_u8; 8];
+        packet
+            .iovecs
+            .push(util::aio::Iovec::new(buf.as_ptr() as u64, buf.len() as u64));
+        dev.handle_interrupt_in(&mut packet);
+        assert_eq!(packet.actual_length, 2);
+        assert_eq!(buf[..2], [1, 2]);
+
+        let disconnect = msg(USB_REDIR_DEVICE_DISCONNECT, 0, &[]);
+        assert_eq!(dev.handle_input(&disconnect), vec![UsbRedirEvent::Detach]);
+        assert!(dev.interrupt_in.is_empty());
+        assert_eq!(dev.peer_closed(), None);
+        assert!(dev.peer_caps.is_none());
+    }
+}
@@ -71,6 +71,10 @@ const EVENT_TRB_SLOT_ID_SHIFT: u32 = 24;
 const EVENT_TRB_EP_ID_SHIFT: u32 = 16;
 const PORT_EVENT_ID_SHIFT: u32 = 24;
 const SLOT_CTX_PORT_NUMBER_SHIFT: u32 = 16;
+const SLOT_CTX_ROUTE_STRING_MASK: u32 = 0xfffff;
+/// Each tier of hub takes 4 bits of the route string.
+const ROUTE_STRING_TIER_BITS: u32 = 4;
+const ROUTE_STRING_MAX_TIERS: u32 = 5;
 const ENDPOINT_ID_START: u32 = 1;
 const MAX_ENDPOINTS: u32 = 31;
 const TRANSFER_LEN_MASK: u32 = 0xffffff;
@@ -934,10 +938,28 @@ impl XhciDevice {
             error!("Invalid port: {}", port);
             return None;
         }
-        let usb_port = &self.usb_ports[(port - 1) as usize];
+        let mut usb_port = self.usb_ports[(port - 1) as usize].clone();
+        // The route string locates the device behind hubs, see the spec section 8.9 Route String.
+        let route = slot_ctx.dev_info & SLOT_CTX_ROUTE_STRING_MASK;
+        for tier in 0..ROUTE_STRING_MAX_TIERS {
+            let hub_port = (route >> (tier * ROUTE_STRING_TIER_BITS)) & 0xf;
+            if hub_port == 0 {
+                break;
+            }
+            let hub = usb_port.lock().unwrap().dev.clone()?;
+            let ports = hub.lock().unwrap().get_downstream_ports();
+            usb_port = match ports.get((hub_port - 1) as usize) {
+                Some(port) => port.clone(),
+                None => {
+                    error!("Invalid route string: {:x}", route);
+                    return None;
+                }
+            };
+        }
         let locked_port = usb_port.lock().unwrap();
         if locked_port.used {
-            Some(usb_port.clone())
+            drop(locked_port);
+            Some(usb_port)
         } else {
             None
         }
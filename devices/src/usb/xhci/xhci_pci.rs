@@ -33,7 +33,7 @@ use crate::pci::config::{
 };
 use crate::pci::msix::update_dev_id;
 use crate::pci::{init_intx, init_msix, le_write_u16, PciBus, PciDevBase, PciDevOps};
-use crate::usb::UsbDevice;
+use crate::usb::{notify_controller, UsbDevice};
 use crate::{Device, DeviceBase};
 use address_space::{AddressRange, AddressSpace, Region, RegionIoEventFd};
 use machine_manager::config::XhciConfig;
@@ -189,6 +189,62 @@ impl XhciPciDevice {
         Ok(())
     }
 
+    /// Attach the USB device to the hub whose id is `bus`, or to the controller if there
+    /// is no such hub.
+    pub fn attach_device_to_bus(
+        &self,
+        dev: &Arc<Mutex<dyn UsbDevice>>,
+        bus: Option<&str>,
+    ) -> Result<()> {
+        let mut locked_xhci = self.xhci.lock().unwrap();
+        let hub_port = bus.and_then(|id| locked_xhci.find_usb_port_by_id(id));
+        drop(locked_xhci);
+        let hub = match hub_port.and_then(|port| port.lock().unwrap().dev.clone()) {
+            Some(hub) => hub,
+            None => return self.attach_device(dev),
+        };
+
+        hub.lock().unwrap().attach_downstream_device(dev)?;
+        // Let the hub report the status change of its port.
+        if let Err(e) = notify_controller(&hub) {
+            debug!("Failed to notify controller for hub: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Get the firmware device path of the USB device attached to the controller, or to
+    /// the hub attached to the controller. The node of the device is named by `node`.
+    pub fn get_usb_dev_path(&self, id: &str, node: &str) -> Option<String> {
+        let prefix = self.get_dev_path()?;
+        let locked_xhci = self.xhci.lock().unwrap();
+        for port in &locked_xhci.usb_ports {
+            let locked_port = port.lock().unwrap();
+            let dev = match &locked_port.dev {
+                Some(dev) => dev.clone(),
+                None => continue,
+            };
+            let port_id = locked_port.port_id;
+            drop(locked_port);
+
+            let locked_dev = dev.lock().unwrap();
+            if locked_dev.device_id() == id {
+                return Some(format!("{}/{}@{:x}", prefix, node, port_id));
+            }
+            for hub_port in locked_dev.get_downstream_ports() {
+                let locked_hub_port = hub_port.lock().unwrap();
+                if let Some(hub_dev) = &locked_hub_port.dev {
+                    if hub_dev.lock().unwrap().device_id() == id {
+                        return Some(format!(
+                            "{}/hub@{:x}/{}@{:x}",
+                            prefix, port_id, node, locked_hub_port.port_id
+                        ));
+                    }
+                }
+            }
+        }
+        None
+    }
+
     pub fn detach_device(&self, id: String) -> Result<()> {
         let mut locked_xhci = self.xhci.lock().unwrap();
         let usb_port = locked_xhci.find_usb_port_by_id(&id);
@@ -367,6 +423,13 @@ impl PciDevOps for XhciPciDevice {
 
         Ok(())
     }
+
+    fn get_dev_path(&self) -> Option<String> {
+        let parent_bus = self.base.parent_bus.upgrade().unwrap();
+        let parent_dev_path = self.get_parent_dev_path(parent_bus);
+        let dev_path = self.populate_dev_path(parent_dev_path, self.base.devfn, "/usb@");
+        Some(dev_path)
+    }
 }
 
 struct DoorbellHandler {
@@ -117,6 +117,16 @@ impl IrqRouteTable {
         }
     }
 
+    /// Init irq route table in arch x86_64 when IOAPIC and PIC are emulated in userspace.
+    /// The GSIs of IOAPIC are reserved, and routed by `update_ioapic_routes` as MSI.
+    #[cfg(target_arch = "x86_64")]
+    pub fn init_split_irq_route_table(&mut self) {
+        for i in 0..IOAPIC_NUM_PINS {
+            // This unwrap() will never fail, it is safe.
+            self.gsi_bitmap.set(i as usize).unwrap();
+        }
+    }
+
     /// Init irq route table in arch aarch64.
     #[cfg(target_arch = "aarch64")]
     pub fn init_irq_route_table(&mut self) {
@@ -169,6 +179,20 @@ impl IrqRouteTable {
         Ok(())
     }
 
+    /// Update the routes of the pins of userspace IOAPIC, the masked pins are `None`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn update_ioapic_routes(&mut self, routes: &[Option<MsiVector>]) -> Result<()> {
+        for (gsi, route) in routes.iter().enumerate().take(IOAPIC_NUM_PINS as usize) {
+            match route {
+                Some(msi_vector) => self
+                    .update_msi_route(gsi as u32, *msi_vector)
+                    .with_context(|| format!("Failed to update route of IOAPIC pin {}", gsi))?,
+                None => self.remove_irq_route(gsi as u32),
+            }
+        }
+        Ok(())
+    }
+
     /// Allocate free gsi number.
     pub fn allocate_gsi(&mut self) -> Result<u32> {
         let free_gsi = self
@@ -222,6 +246,8 @@ pub struct MsiVector {
 mod tests {
     use super::super::KVMFds;
     use super::get_maximum_gsi_cnt;
+    #[cfg(target_arch = "x86_64")]
+    use super::MsiVector;
 
     #[test]
     fn test_get_maximum_gsi_cnt() {
@@ -262,4 +288,32 @@ mod tests {
             assert_eq!(irq_route_table.allocate_gsi().unwrap(), 195);
         }
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_split_irq_route_table() {
+        let kvm_fds = KVMFds::new();
+        if kvm_fds.vm_fd.is_none() {
+            return;
+        }
+        let mut irq_route_table = kvm_fds.irq_route_table.lock().unwrap();
+        irq_route_table.init_split_irq_route_table();
+        assert!(irq_route_table.irq_routes.is_empty());
+        assert_eq!(irq_route_table.allocate_gsi().unwrap(), 24);
+
+        let mut routes = vec![None; 24];
+        let msi_vector = MsiVector {
+            msg_addr_lo: 0xFEE0_0000,
+            msg_data: 0x24,
+            ..Default::default()
+        };
+        routes[4] = Some(msi_vector);
+        irq_route_table.update_ioapic_routes(&routes).unwrap();
+        assert_eq!(irq_route_table.get_irq_route_entry(4).len(), 1);
+        assert!(irq_route_table.get_irq_route_entry(3).is_empty());
+
+        routes[4] = None;
+        irq_route_table.update_ioapic_routes(&routes).unwrap();
+        assert!(irq_route_table.irq_routes.is_empty());
+    }
 }
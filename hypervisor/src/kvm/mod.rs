@@ -16,19 +16,24 @@ pub use interrupt::MsiVector;
 
 use std::collections::HashMap;
 use std::mem::{align_of, size_of};
+use std::os::unix::io::AsRawFd;
+#[cfg(target_arch = "x86_64")]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Context, Result};
 use arc_swap::ArcSwap;
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
 use kvm_bindings::*;
-use kvm_ioctls::{Kvm, VmFd};
+use kvm_ioctls::{Kvm, NoDatamatch, VmFd};
 use log::error;
 use once_cell::sync::Lazy;
 use vmm_sys_util::{
-    eventfd::EventFd, ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr,
+    eventfd::EventFd, ioctl::ioctl_with_ref, ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr,
+    ioctl_iowr_nr,
 };
 
+use crate::{DataMatch, HypervisorOps, IoEventAddress, MemoryRegion, MsiMessage};
 use interrupt::{IrqRoute, IrqRouteEntry, IrqRouteTable};
 
 // See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/asm-generic/kvm.h
@@ -66,6 +71,10 @@ ioctl_iow_nr!(KVM_SET_MSRS, KVMIO, 0x89, kvm_msrs);
 #[cfg(target_arch = "x86_64")]
 ioctl_iow_nr!(KVM_SET_VCPU_EVENTS, KVMIO, 0xa0, kvm_vcpu_events);
 #[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_PIT2, KVMIO, 0xa0, kvm_pit_state2);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_CLOCK, KVMIO, 0x7b, kvm_clock_data);
+#[cfg(target_arch = "x86_64")]
 ioctl_ior_nr!(KVM_GET_PIT2, KVMIO, 0x9f, kvm_pit_state2);
 ioctl_ior_nr!(KVM_GET_CLOCK, KVMIO, 0x7c, kvm_clock_data);
 ioctl_iowr_nr!(KVM_GET_IRQCHIP, KVMIO, 0x62, kvm_irqchip);
@@ -93,8 +102,16 @@ ioctl_iow_nr!(KVM_GET_DEVICE_ATTR, KVMIO, 0xe2, kvm_device_attr);
 ioctl_iowr_nr!(KVM_GET_REG_LIST, KVMIO, 0xb0, kvm_reg_list);
 #[cfg(target_arch = "aarch64")]
 ioctl_iow_nr!(KVM_ARM_VCPU_INIT, KVMIO, 0xae, kvm_vcpu_init);
+#[cfg(target_arch = "aarch64")]
+ioctl_iow_nr!(KVM_ARM_VCPU_FINALIZE, KVMIO, 0xc2, std::os::raw::c_int);
 ioctl_iow_nr!(KVM_GET_DIRTY_LOG, KVMIO, 0x42, kvm_dirty_log);
 ioctl_iow_nr!(KVM_IRQ_LINE, KVMIO, 0x61, kvm_irq_level);
+ioctl_iow_nr!(KVM_SET_GUEST_DEBUG, KVMIO, 0x9b, kvm_guest_debug);
+ioctl_iow_nr!(KVM_ENABLE_CAP, KVMIO, 0xa3, kvm_enable_cap);
+#[cfg(target_arch = "x86_64")]
+ioctl_iowr_nr!(KVM_TRANSLATE, KVMIO, 0x85, kvm_translation);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_INTERRUPT, KVMIO, 0x86, kvm_interrupt);
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Default)]
@@ -103,6 +120,12 @@ pub struct KVMFds {
     pub vm_fd: Option<VmFd>,
     pub irq_route_table: Mutex<IrqRouteTable>,
     pub mem_slots: Arc<Mutex<HashMap<u32, MemorySlot>>>,
+    /// The kvmclock saved when VM is paused.
+    #[cfg(target_arch = "x86_64")]
+    paused_clock: Mutex<Option<kvm_clock_data>>,
+    /// Only the local APICs are emulated in kvm, IOAPIC and PIC are emulated in userspace.
+    #[cfg(target_arch = "x86_64")]
+    split_irqchip: AtomicBool,
 }
 
 impl KVMFds {
@@ -122,6 +145,10 @@ impl KVMFds {
                     vm_fd: Some(vm_fd),
                     irq_route_table,
                     mem_slots: Arc::new(Mutex::new(HashMap::new())),
+                    #[cfg(target_arch = "x86_64")]
+                    paused_clock: Mutex::new(None),
+                    #[cfg(target_arch = "x86_64")]
+                    split_irqchip: AtomicBool::new(false),
                 }
             }
             Err(e) => {
@@ -171,6 +198,21 @@ impl KVMFds {
             .with_context(|| format!("Failed to register irqfd: gsi {}.", gsi))
     }
 
+    /// Register the irqfd of the level-triggered interrupt. The `resample_fd` is signaled when
+    /// the interrupt is acknowledged by guest, so that the interrupt source can be re-sampled.
+    pub fn register_irqfd_with_resample(
+        &self,
+        fd: &EventFd,
+        resample_fd: &EventFd,
+        gsi: u32,
+    ) -> Result<()> {
+        self.vm_fd
+            .as_ref()
+            .unwrap()
+            .register_irqfd_with_resample(fd, resample_fd, gsi)
+            .with_context(|| format!("Failed to register irqfd with resample: gsi {}.", gsi))
+    }
+
     pub fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()> {
         self.vm_fd
             .as_ref()
@@ -187,6 +229,120 @@ impl KVMFds {
             .with_context(|| format!("Failed to set irq {} level {:?}.", irq, level))
     }
 
+    /// Save the kvmclock when VM is paused, so that the guest doesn't see the time
+    /// elapsed while it's paused.
+    #[cfg(target_arch = "x86_64")]
+    pub fn pause_clock(&self) -> Result<()> {
+        let mut clock = self
+            .vm_fd
+            .as_ref()
+            .unwrap()
+            .get_clock()
+            .with_context(|| "Failed to get kvm clock")?;
+        // Only the clock value can be set back.
+        clock.flags = 0;
+        *self.paused_clock.lock().unwrap() = Some(clock);
+        Ok(())
+    }
+
+    /// Set the kvmclock saved by `pause_clock` back when VM is resumed.
+    #[cfg(target_arch = "x86_64")]
+    pub fn resume_clock(&self) -> Result<()> {
+        if let Some(clock) = self.paused_clock.lock().unwrap().take() {
+            self.vm_fd
+                .as_ref()
+                .unwrap()
+                .set_clock(&clock)
+                .with_context(|| "Failed to set kvm clock")?;
+        }
+        Ok(())
+    }
+
+    /// Set the maximum time in nanoseconds the VCPUs of this VM poll before halting,
+    /// it overrides the `halt_poll_ns` parameter of kvm module.
+    pub fn set_halt_poll_ns(&self, halt_poll_ns: u32) -> Result<()> {
+        let vm_fd = self.vm_fd.as_ref().with_context(|| "VM is not created")?;
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_HALT_POLL,
+            ..Default::default()
+        };
+        cap.args[0] = u64::from(halt_poll_ns);
+        // Safe because the kvm_enable_cap is well defined, and the return value is checked.
+        let ret = unsafe { ioctl_with_ref(vm_fd, KVM_ENABLE_CAP(), &cap) };
+        if ret < 0 {
+            bail!(
+                "Failed to set halt_poll_ns of VM, error is {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    /// Create the local APICs in kvm, while IOAPIC and PIC are emulated in userspace. The
+    /// interrupts of the `nr_pins` pins of IOAPIC are routed to the local APICs as MSI.
+    /// It must be called before VCPUs are created.
+    #[cfg(target_arch = "x86_64")]
+    pub fn enable_split_irqchip(&self, nr_pins: u32) -> Result<()> {
+        let vm_fd = self.vm_fd.as_ref().with_context(|| "VM is not created")?;
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_SPLIT_IRQCHIP,
+            ..Default::default()
+        };
+        cap.args[0] = u64::from(nr_pins);
+        // Safe because the kvm_enable_cap is well defined, and the return value is checked.
+        let ret = unsafe { ioctl_with_ref(vm_fd, KVM_ENABLE_CAP(), &cap) };
+        if ret < 0 {
+            bail!(
+                "Failed to enable split irqchip, error is {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        self.split_irqchip.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Whether IOAPIC and PIC are emulated in userspace.
+    #[cfg(target_arch = "x86_64")]
+    pub fn is_split_irqchip(&self) -> bool {
+        self.split_irqchip.load(Ordering::SeqCst)
+    }
+
+    /// Get the halt polling statistics summed over all VCPUs from kvm debugfs: the
+    /// attempted polls and the successful ones. `None` if debugfs is not accessible.
+    pub fn halt_poll_stats(&self) -> Option<(u64, u64)> {
+        let vm_fd = self.vm_fd.as_ref()?.as_raw_fd();
+        let dir = format!("/sys/kernel/debug/kvm/{}-{}", std::process::id(), vm_fd);
+        let read_stat = |name: &str| -> Option<u64> {
+            std::fs::read_to_string(format!("{}/{}", dir, name))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        Some((
+            read_stat("halt_attempted_poll")?,
+            read_stat("halt_successful_poll")?,
+        ))
+    }
+
+    /// Stop or restart the interrupt of in-kernel PIT, which is replaced by HPET in
+    /// legacy replacement mode.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_pit_hpet_legacy(&self, legacy: bool) -> Result<()> {
+        let vm_fd = self.vm_fd.as_ref().unwrap();
+        let mut pit_state = vm_fd
+            .get_pit2()
+            .with_context(|| "Failed to get pit state")?;
+        if legacy {
+            pit_state.flags |= KVM_PIT_FLAGS_HPET_LEGACY;
+        } else {
+            pit_state.flags &= !KVM_PIT_FLAGS_HPET_LEGACY;
+        }
+        vm_fd
+            .set_pit2(&pit_state)
+            .with_context(|| "Failed to set pit state")
+    }
+
     /// Start dirty page tracking in kvm.
     pub fn start_dirty_log(&self) -> Result<()> {
         for (_, region) in self.mem_slots.lock().unwrap().iter_mut() {
@@ -274,4 +430,117 @@ impl KVMFds {
     }
 }
 
+impl HypervisorOps for KVMFds {
+    fn set_user_memory_region(&self, region: MemoryRegion) -> Result<()> {
+        let kvm_region = kvm_userspace_memory_region {
+            slot: region.slot,
+            guest_phys_addr: region.guest_phys_addr,
+            memory_size: region.memory_size,
+            userspace_addr: region.userspace_addr,
+            flags: if region.read_only {
+                KVM_MEM_READONLY
+            } else {
+                0
+            },
+        };
+        if region.memory_size == 0 {
+            self.remove_mem_slot(kvm_region)?;
+        } else {
+            self.add_mem_slot(kvm_region)?;
+        }
+        // Safe because the host memory of the region is mapped by caller, and it's kept
+        // mapped until the region is deleted.
+        unsafe {
+            self.vm_fd
+                .as_ref()
+                .unwrap()
+                .set_user_memory_region(kvm_region)
+                .with_context(|| format!("Failed to set memory slot {}", region.slot))
+        }
+    }
+
+    fn register_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()> {
+        KVMFds::register_irqfd(self, fd, gsi)
+    }
+
+    fn register_irqfd_with_resample(
+        &self,
+        fd: &EventFd,
+        resample_fd: &EventFd,
+        gsi: u32,
+    ) -> Result<()> {
+        KVMFds::register_irqfd_with_resample(self, fd, resample_fd, gsi)
+    }
+
+    fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()> {
+        KVMFds::unregister_irqfd(self, fd, gsi)
+    }
+
+    fn set_irq_line(&self, irq: u32, level: bool) -> Result<()> {
+        KVMFds::set_irq_line(self, irq, level)
+    }
+
+    fn signal_msi(&self, msi: MsiMessage) -> Result<()> {
+        #[cfg(target_arch = "aarch64")]
+        let flags: u32 = KVM_MSI_VALID_DEVID;
+        #[cfg(target_arch = "x86_64")]
+        let flags: u32 = 0;
+
+        let kvm_msi = kvm_msi {
+            address_lo: msi.address as u32,
+            address_hi: (msi.address >> 32) as u32,
+            data: msi.data,
+            flags,
+            devid: msi.dev_id,
+            pad: [0; 12],
+        };
+        self.vm_fd
+            .as_ref()
+            .unwrap()
+            .signal_msi(kvm_msi)
+            .with_context(|| "Failed to signal msi")?;
+        Ok(())
+    }
+
+    fn register_ioevent(
+        &self,
+        fd: &EventFd,
+        addr: IoEventAddress,
+        datamatch: Option<DataMatch>,
+    ) -> Result<()> {
+        let vm_fd = self.vm_fd.as_ref().unwrap();
+        let addr = match addr {
+            IoEventAddress::Pio(addr) => kvm_ioctls::IoEventAddress::Pio(addr),
+            IoEventAddress::Mmio(addr) => kvm_ioctls::IoEventAddress::Mmio(addr),
+        };
+        match datamatch {
+            Some(DataMatch::U16(data)) => vm_fd.register_ioevent(fd, &addr, data),
+            Some(DataMatch::U32(data)) => vm_fd.register_ioevent(fd, &addr, data),
+            Some(DataMatch::U64(data)) => vm_fd.register_ioevent(fd, &addr, data),
+            None => vm_fd.register_ioevent(fd, &addr, NoDatamatch),
+        }
+        .with_context(|| "Failed to register ioeventfd")
+    }
+
+    fn unregister_ioevent(
+        &self,
+        fd: &EventFd,
+        addr: IoEventAddress,
+        datamatch: Option<DataMatch>,
+    ) -> Result<()> {
+        let vm_fd = self.vm_fd.as_ref().unwrap();
+        let addr = match addr {
+            IoEventAddress::Pio(addr) => kvm_ioctls::IoEventAddress::Pio(addr),
+            IoEventAddress::Mmio(addr) => kvm_ioctls::IoEventAddress::Mmio(addr),
+        };
+        match datamatch {
+            Some(DataMatch::U16(data)) => vm_fd.unregister_ioevent(fd, &addr, data),
+            Some(DataMatch::U32(data)) => vm_fd.unregister_ioevent(fd, &addr, data),
+            Some(DataMatch::U64(data)) => vm_fd.unregister_ioevent(fd, &addr, data),
+            None => vm_fd.unregister_ioevent(fd, &addr, NoDatamatch),
+        }
+        .with_context(|| "Failed to unregister ioeventfd")
+    }
+}
+
 pub static KVM_FDS: Lazy<ArcSwap<KVMFds>> = Lazy::new(|| ArcSwap::from(Arc::new(KVMFds::new())));
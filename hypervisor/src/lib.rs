@@ -11,8 +11,120 @@
 // See the Mulan PSL v2 for more details.
 
 //! This crate offers interfaces for different kinds of hypervisors, such as KVM.
+//!
+//! The VM-level operations needed by the devices (memory slots, irqfds, ioeventfds, irq
+//! lines and MSIs) are abstracted by `HypervisorOps`, so that backends other than KVM can
+//! be plugged in with `set_hypervisor`. KVM is used if no other backend is set.
+//!
+//! Creating the VM and vCPUs, running vCPUs, and setting up the in-kernel irqchip and the
+//! MSI routing are not abstracted yet, they still use `KVM_FDS` and `kvm_ioctls` directly.
+//! So the other backends only serve the devices, e.g. the `TestHypervisor` which lets the
+//! devices run on hosts without `/dev/kvm`.
 
 pub mod error;
 pub mod kvm;
+#[cfg(any(test, feature = "test_hypervisor"))]
+pub mod test;
 
 pub use error::HypervisorError;
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use vmm_sys_util::eventfd::EventFd;
+
+use kvm::KVM_FDS;
+
+/// Guest memory region mapped from the memory of host.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// Slot id, the address space id is in the high 16 bits.
+    pub slot: u32,
+    pub guest_phys_addr: u64,
+    /// Size of the region, 0 means deleting the slot.
+    pub memory_size: u64,
+    pub userspace_addr: u64,
+    pub read_only: bool,
+}
+
+/// Address of the guest access that signals an ioeventfd.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IoEventAddress {
+    Pio(u64),
+    Mmio(u64),
+}
+
+/// Value that the data written by guest must match to signal an ioeventfd.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataMatch {
+    U16(u16),
+    U32(u32),
+    U64(u64),
+}
+
+/// Message signaled by a device to inject an MSI.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+    /// Requester id of the device, used by the ITS on aarch64.
+    pub dev_id: u32,
+}
+
+/// VM-level operations provided by a hypervisor to the devices.
+pub trait HypervisorOps: Send + Sync {
+    /// Create, modify or delete (if `memory_size` is 0) a guest memory slot.
+    fn set_user_memory_region(&self, region: MemoryRegion) -> Result<()>;
+
+    /// Inject the interrupt `gsi` to guest when `fd` is signaled.
+    fn register_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()>;
+
+    /// Register the irqfd of the level-triggered interrupt. The `resample_fd` is signaled
+    /// when the interrupt is acknowledged by guest.
+    fn register_irqfd_with_resample(
+        &self,
+        fd: &EventFd,
+        resample_fd: &EventFd,
+        gsi: u32,
+    ) -> Result<()>;
+
+    fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()>;
+
+    fn set_irq_line(&self, irq: u32, level: bool) -> Result<()>;
+
+    fn signal_msi(&self, msi: MsiMessage) -> Result<()>;
+
+    /// Signal `fd` when guest writes to `addr`, and the data matches `datamatch` if any.
+    fn register_ioevent(
+        &self,
+        fd: &EventFd,
+        addr: IoEventAddress,
+        datamatch: Option<DataMatch>,
+    ) -> Result<()>;
+
+    fn unregister_ioevent(
+        &self,
+        fd: &EventFd,
+        addr: IoEventAddress,
+        datamatch: Option<DataMatch>,
+    ) -> Result<()>;
+}
+
+// The backend is process-global rather than per-thread, as the devices also call it in the
+// main loop and iothreads.
+static HYPERVISOR: Lazy<RwLock<Option<Arc<dyn HypervisorOps>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Get the hypervisor backend of the VM.
+pub fn hypervisor() -> Arc<dyn HypervisorOps> {
+    match HYPERVISOR.read().unwrap().as_ref() {
+        Some(hypervisor) => hypervisor.clone(),
+        None => KVM_FDS.load_full(),
+    }
+}
+
+/// Replace the hypervisor backend of the VM, `None` means KVM. It affects all threads, so
+/// it must be set before the devices are realized.
+pub fn set_hypervisor(hypervisor: Option<Arc<dyn HypervisorOps>>) {
+    *HYPERVISOR.write().unwrap() = hypervisor;
+}
@@ -0,0 +1,282 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Hypervisor which only records the requests, used by unit tests on hosts without `/dev/kvm`.
+
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::{DataMatch, HypervisorOps, IoEventAddress, MemoryRegion, MsiMessage};
+
+struct IoEvent {
+    /// The fd number of the registered eventfd, to match the unregistering requests.
+    raw_fd: RawFd,
+    fd: EventFd,
+    addr: IoEventAddress,
+    datamatch: Option<DataMatch>,
+}
+
+#[derive(Default)]
+pub struct TestHypervisor {
+    mem_slots: Mutex<HashMap<u32, MemoryRegion>>,
+    /// Registered irqfds and the gsi they are bound to.
+    irqfds: Mutex<HashMap<RawFd, (EventFd, u32)>>,
+    ioevents: Mutex<Vec<IoEvent>>,
+    irq_lines: Mutex<HashMap<u32, bool>>,
+    msis: Mutex<Vec<MsiMessage>>,
+}
+
+impl TestHypervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mem_slots(&self) -> HashMap<u32, MemoryRegion> {
+        self.mem_slots.lock().unwrap().clone()
+    }
+
+    pub fn irqfd_gsi(&self, fd: &EventFd) -> Option<u32> {
+        self.irqfds
+            .lock()
+            .unwrap()
+            .get(&fd.as_raw_fd())
+            .map(|(_, gsi)| *gsi)
+    }
+
+    /// Consume the interrupts injected to `gsi` through irqfds, return whether there is any.
+    pub fn take_irq(&self, gsi: u32) -> bool {
+        let irqfds = self.irqfds.lock().unwrap();
+        let mut injected = false;
+        for (fd, _) in irqfds.values().filter(|(_, g)| *g == gsi) {
+            // The irqfds are non-blocking, reading fails if no interrupt is injected.
+            injected |= fd.read().is_ok();
+        }
+        injected
+    }
+
+    pub fn ioevents(&self) -> Vec<(IoEventAddress, Option<DataMatch>)> {
+        self.ioevents
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| (e.addr, e.datamatch))
+            .collect()
+    }
+
+    pub fn irq_line(&self, irq: u32) -> Option<bool> {
+        self.irq_lines.lock().unwrap().get(&irq).copied()
+    }
+
+    /// Take the MSIs signaled since last call.
+    pub fn take_msis(&self) -> Vec<MsiMessage> {
+        std::mem::take(&mut self.msis.lock().unwrap())
+    }
+
+    /// Emulate the guest write to `addr`, the matched ioeventfd is signaled as what
+    /// KVM does. Return false if no ioeventfd matches, then the write should be
+    /// dispatched to the device.
+    pub fn signal_ioevent(&self, addr: IoEventAddress, data: &[u8]) -> bool {
+        let datamatch = match data.len() {
+            2 => DataMatch::U16(u16::from_le_bytes(data.try_into().unwrap())),
+            4 => DataMatch::U32(u32::from_le_bytes(data.try_into().unwrap())),
+            8 => DataMatch::U64(u64::from_le_bytes(data.try_into().unwrap())),
+            _ => return false,
+        };
+        let ioevents = self.ioevents.lock().unwrap();
+        let matched = ioevents
+            .iter()
+            .find(|e| e.addr == addr && e.datamatch.is_none_or(|d| d == datamatch));
+        match matched {
+            Some(e) => e.fd.write(1).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl HypervisorOps for TestHypervisor {
+    fn set_user_memory_region(&self, region: MemoryRegion) -> Result<()> {
+        let mut slots = self.mem_slots.lock().unwrap();
+        if region.memory_size == 0 {
+            if slots.remove(&region.slot).is_none() {
+                bail!("Memory slot {} is not in use", region.slot);
+            }
+            return Ok(());
+        }
+        let overlapped = slots.values().any(|s| {
+            s.slot != region.slot
+                && s.guest_phys_addr < region.guest_phys_addr + region.memory_size
+                && region.guest_phys_addr < s.guest_phys_addr + s.memory_size
+        });
+        if overlapped {
+            bail!("Memory slot {} overlaps with others", region.slot);
+        }
+        slots.insert(region.slot, region);
+        Ok(())
+    }
+
+    fn register_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()> {
+        let mut irqfds = self.irqfds.lock().unwrap();
+        if irqfds.contains_key(&fd.as_raw_fd()) {
+            bail!("Irqfd {} is already registered", fd.as_raw_fd());
+        }
+        irqfds.insert(fd.as_raw_fd(), (fd.try_clone()?, gsi));
+        Ok(())
+    }
+
+    fn register_irqfd_with_resample(
+        &self,
+        fd: &EventFd,
+        _resample_fd: &EventFd,
+        gsi: u32,
+    ) -> Result<()> {
+        self.register_irqfd(fd, gsi)
+    }
+
+    fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()> {
+        let mut irqfds = self.irqfds.lock().unwrap();
+        if irqfds.get(&fd.as_raw_fd()).map(|(_, g)| *g) != Some(gsi) {
+            bail!("Irqfd {} is not registered to gsi {}", fd.as_raw_fd(), gsi);
+        }
+        irqfds.remove(&fd.as_raw_fd());
+        Ok(())
+    }
+
+    fn set_irq_line(&self, irq: u32, level: bool) -> Result<()> {
+        self.irq_lines.lock().unwrap().insert(irq, level);
+        Ok(())
+    }
+
+    fn signal_msi(&self, msi: MsiMessage) -> Result<()> {
+        self.msis.lock().unwrap().push(msi);
+        Ok(())
+    }
+
+    fn register_ioevent(
+        &self,
+        fd: &EventFd,
+        addr: IoEventAddress,
+        datamatch: Option<DataMatch>,
+    ) -> Result<()> {
+        let mut ioevents = self.ioevents.lock().unwrap();
+        if ioevents
+            .iter()
+            .any(|e| e.addr == addr && e.datamatch == datamatch)
+        {
+            bail!("Ioeventfd {:?} {:?} is already registered", addr, datamatch);
+        }
+        ioevents.push(IoEvent {
+            raw_fd: fd.as_raw_fd(),
+            fd: fd.try_clone()?,
+            addr,
+            datamatch,
+        });
+        Ok(())
+    }
+
+    fn unregister_ioevent(
+        &self,
+        fd: &EventFd,
+        addr: IoEventAddress,
+        datamatch: Option<DataMatch>,
+    ) -> Result<()> {
+        let mut ioevents = self.ioevents.lock().unwrap();
+        match ioevents
+            .iter()
+            .position(|e| e.raw_fd == fd.as_raw_fd() && e.addr == addr && e.datamatch == datamatch)
+        {
+            Some(index) => {
+                ioevents.remove(index);
+                Ok(())
+            }
+            None => bail!("Ioeventfd {:?} {:?} is not registered", addr, datamatch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mem_slots() {
+        let hypervisor = TestHypervisor::new();
+        let mut region = MemoryRegion {
+            slot: 0,
+            guest_phys_addr: 0x1000,
+            memory_size: 0x1000,
+            userspace_addr: 0x10000,
+            read_only: false,
+        };
+        hypervisor.set_user_memory_region(region).unwrap();
+        region.slot = 1;
+        region.guest_phys_addr = 0x1800;
+        assert!(hypervisor.set_user_memory_region(region).is_err());
+        region.guest_phys_addr = 0x2000;
+        hypervisor.set_user_memory_region(region).unwrap();
+        assert_eq!(hypervisor.mem_slots().len(), 2);
+
+        region.memory_size = 0;
+        hypervisor.set_user_memory_region(region).unwrap();
+        assert!(hypervisor.set_user_memory_region(region).is_err());
+        assert_eq!(hypervisor.mem_slots().len(), 1);
+    }
+
+    #[test]
+    fn test_irqfd_and_ioevent() {
+        let hypervisor = TestHypervisor::new();
+        let evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        hypervisor.register_irqfd(&evt, 5).unwrap();
+        assert!(hypervisor.register_irqfd(&evt, 6).is_err());
+        assert_eq!(hypervisor.irqfd_gsi(&evt), Some(5));
+        assert!(!hypervisor.take_irq(5));
+        evt.write(1).unwrap();
+        assert!(hypervisor.take_irq(5));
+        assert!(!hypervisor.take_irq(5));
+        assert!(hypervisor.unregister_irqfd(&evt, 6).is_err());
+        hypervisor.unregister_irqfd(&evt, 5).unwrap();
+        assert_eq!(hypervisor.irqfd_gsi(&evt), None);
+
+        let addr = IoEventAddress::Mmio(0x1000);
+        hypervisor
+            .register_ioevent(&evt, addr, Some(DataMatch::U32(1)))
+            .unwrap();
+        hypervisor
+            .register_ioevent(&evt, addr, Some(DataMatch::U32(2)))
+            .unwrap();
+        assert!(hypervisor
+            .register_ioevent(&evt, addr, Some(DataMatch::U32(1)))
+            .is_err());
+        assert!(hypervisor.signal_ioevent(addr, &2_u32.to_le_bytes()));
+        assert_eq!(evt.read().unwrap(), 1);
+        assert!(!hypervisor.signal_ioevent(addr, &3_u32.to_le_bytes()));
+        hypervisor
+            .unregister_ioevent(&evt, addr, Some(DataMatch::U32(1)))
+            .unwrap();
+        assert_eq!(hypervisor.ioevents(), vec![(addr, Some(DataMatch::U32(2)))]);
+
+        hypervisor.set_irq_line(4, true).unwrap();
+        assert_eq!(hypervisor.irq_line(4), Some(true));
+
+        let msi = MsiMessage {
+            address: 0xfee0_0000,
+            data: 0x21,
+            dev_id: 8,
+        };
+        hypervisor.signal_msi(msi).unwrap();
+        assert_eq!(hypervisor.take_msis(), vec![msi]);
+        assert!(hypervisor.take_msis().is_empty());
+    }
+}
@@ -21,6 +21,7 @@ use anyhow::{bail, Context, Result};
 
 use crate::cmdline::ArgsParse;
 use block_backend::{
+    luks::header::LUKS_MAGIC,
     qcow2::{header::QcowHeader, InternalSnapshotOps, Qcow2Driver, SyncAioInfo},
     raw::RawDriver,
     BlockDriverOps, BlockProperty, CheckResult, CreateOptions, FIX_ERRORS, FIX_LEAKS, NO_FIX,
@@ -75,6 +76,8 @@ impl ImageFile {
             if header.version == 3 {
                 disk_format = DiskFormat::Qcow2;
             }
+        } else if buf.starts_with(LUKS_MAGIC) {
+            disk_format = DiskFormat::Luks;
         }
 
         Ok(disk_format)
@@ -126,6 +129,9 @@ pub(crate) fn image_create(args: Vec<String>) -> Result<()> {
     if let Some(fmt) = arg_parser.opt_str("f") {
         disk_fmt = DiskFormat::from_str(&fmt)?;
     };
+    if disk_fmt == DiskFormat::Luks {
+        bail!("stratovirt-img: Format luks does not support creating image");
+    }
 
     let extra_options = arg_parser.opt_strs("o");
     for option in extra_options {
@@ -183,6 +189,7 @@ pub(crate) fn image_create(args: Vec<String>) -> Result<()> {
             let mut qcow2_driver = Qcow2Driver::new(file, aio, create_options.conf.clone())?;
             qcow2_driver.create_image(&create_options)?
         }
+        DiskFormat::Luks => unreachable!(),
     };
     println!("Stratovirt-img: {}", image_info);
 
@@ -243,7 +250,7 @@ pub(crate) fn image_check(args: Vec<String>) -> Result<()> {
     let mut check_res = CheckResult::default();
     let file = image_file.file.try_clone()?;
     match real_fmt {
-        DiskFormat::Raw => {
+        DiskFormat::Raw | DiskFormat::Luks => {
             bail!("stratovirt-img: This image format does not support checks");
         }
         DiskFormat::Qcow2 => {
@@ -789,7 +796,7 @@ mod test {
             // Get hostoffset of 0
             let mut offset = 0;
             match qcow2_driver.host_offset_for_read(0, cluster_size).unwrap() {
-                HostRange::DataNotInit(_) => assert!(false),
+                HostRange::DataNotInit(_) | HostRange::DataBacking(_) => assert!(false),
                 HostRange::DataAddress(addr, bytes) => {
                     assert!(bytes >= cluster_size);
                     offset = addr;
@@ -0,0 +1,653 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Gdbstub
+//!
+//! Server of GDB remote serial protocol, which is used to debug guest kernel.
+//!
+//! ## Design
+//!
+//! Gdbstub runs in its own thread and serves one client at a time:
+//! 1. VM is paused when client attaches, and guest debug is enabled for all vcpus.
+//! 2. Registers are accessed by vcpu ioctls, memory is accessed by guest virtual address,
+//!    which is translated with the page table of current vcpu.
+//! 3. Software breakpoints are inserted into guest memory, vcpu exits to userspace and
+//!    notifies gdbstub when it stops at a breakpoint or after a single step.
+//! 4. Breakpoints are removed and VM is resumed when client detaches.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+use vmm_sys_util::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use vmm_sys_util::eventfd::EventFd;
+
+use address_space::{AddressSpace, GuestAddress};
+use cpu::CPU;
+use machine_manager::machine::{KvmVmState, MachineLifecycle};
+
+/// Software breakpoint instruction, `int3` on x86_64.
+#[cfg(target_arch = "x86_64")]
+const SW_BREAKPOINT: &[u8] = &[0xcc];
+/// Software breakpoint instruction, `brk #0` on aarch64.
+#[cfg(target_arch = "aarch64")]
+const SW_BREAKPOINT: &[u8] = &[0x00, 0x00, 0x20, 0xd4];
+
+/// Client sends Ctrl-C to interrupt the running VM.
+const GDB_INTERRUPT: u8 = 0x03;
+/// Signals reported in stop reply.
+const GDB_SIGINT: u8 = 2;
+const GDB_SIGTRAP: u8 = 5;
+/// Maximum size of packet, the data of memory read is limited by it.
+const GDB_PACKET_SIZE: usize = 0x1000;
+const PAGE_SIZE: u64 = 0x1000;
+
+const STREAM_TOKEN: u64 = 0;
+const DEBUG_TOKEN: u64 = 1;
+
+/// Input from client.
+#[derive(Debug, PartialEq, Eq)]
+enum GdbInput {
+    /// Packet with valid checksum.
+    Packet(String),
+    /// Packet with invalid checksum, client should retransmit it.
+    BadPacket,
+    /// Interrupt the running VM.
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Default)]
+enum ParseState {
+    #[default]
+    Idle,
+    Data,
+    Checksum(Option<u8>),
+}
+
+/// Split the byte stream from client into packets in the form of `$<data>#<checksum>`.
+#[derive(Default)]
+struct PacketParser {
+    state: ParseState,
+    data: Vec<u8>,
+}
+
+impl PacketParser {
+    fn feed(&mut self, byte: u8) -> Option<GdbInput> {
+        match self.state {
+            ParseState::Idle => match byte {
+                b'$' => {
+                    self.data.clear();
+                    self.state = ParseState::Data;
+                    None
+                }
+                GDB_INTERRUPT => Some(GdbInput::Interrupt),
+                // Acknowledgements from client are ignored.
+                _ => None,
+            },
+            ParseState::Data => {
+                if byte == b'#' {
+                    self.state = ParseState::Checksum(None);
+                } else {
+                    self.data.push(byte);
+                }
+                None
+            }
+            ParseState::Checksum(None) => {
+                self.state = ParseState::Checksum(Some(byte));
+                None
+            }
+            ParseState::Checksum(Some(high)) => {
+                self.state = ParseState::Idle;
+                let expected = std::str::from_utf8(&[high, byte])
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok());
+                if expected == Some(checksum(&self.data)) {
+                    Some(GdbInput::Packet(
+                        String::from_utf8_lossy(&self.data).to_string(),
+                    ))
+                } else {
+                    Some(GdbInput::BadPacket)
+                }
+            }
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0_u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data.as_bytes()))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        bail!("Invalid hex string {}", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&hex[idx..idx + 2], 16)
+                .with_context(|| format!("Invalid hex string {}", hex))
+        })
+        .collect()
+}
+
+fn parse_hex(hex: &str) -> Result<u64> {
+    u64::from_str_radix(hex, 16).with_context(|| format!("Invalid hex number {}", hex))
+}
+
+/// Parse `addr,length` in memory and breakpoint packets.
+fn parse_addr_len(args: &str) -> Result<(u64, u64)> {
+    match args.split_once(',') {
+        Some((addr, len)) => Ok((parse_hex(addr)?, parse_hex(len)?)),
+        None => bail!("Invalid arguments {}", args),
+    }
+}
+
+/// Parse thread id, `-1` means all threads and `0` means any thread, both return `None`.
+fn parse_thread_id(tid: &str) -> Result<Option<usize>> {
+    if tid == "-1" {
+        return Ok(None);
+    }
+    match parse_hex(tid)? {
+        0 => Ok(None),
+        id => Ok(Some(id as usize - 1)),
+    }
+}
+
+/// Thread id of gdb starts from 1, which is the index of vcpu plus 1.
+fn stop_reply(signal: u8, cpu_index: usize) -> String {
+    format!("T{:02x}thread:{:x};", signal, cpu_index + 1)
+}
+
+/// Action after handling a packet.
+#[derive(Debug, PartialEq, Eq)]
+enum GdbAction {
+    Reply(String),
+    /// Resume VM, single step the vcpu if it's specified.
+    Resume(Option<usize>),
+    Detach,
+    Kill,
+}
+
+struct GdbStub {
+    vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+    vm_state: Arc<(Mutex<KvmVmState>, Condvar)>,
+    cpus: Vec<Arc<CPU>>,
+    sys_mem: Arc<AddressSpace>,
+    /// Notified by vcpu when it stops for guest debug.
+    debug_evt: Arc<EventFd>,
+    /// Index of vcpu selected by client, used for registers and memory access.
+    cur_cpu: usize,
+    /// Original instructions replaced by software breakpoints, the key is guest virtual
+    /// address and the value is guest physical address and the instruction.
+    breakpoints: HashMap<u64, (u64, Vec<u8>)>,
+}
+
+impl GdbStub {
+    fn serve(&mut self, mut stream: TcpStream) -> Result<()> {
+        self.attach()?;
+        let ret = self.process(&mut stream);
+        self.detach();
+        ret
+    }
+
+    fn attach(&mut self) -> Result<()> {
+        // Client may connect before VM starts running.
+        while *self.vm_state.0.lock().unwrap() == KvmVmState::Created {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.stop_vm();
+        self.cur_cpu = 0;
+        for cpu in self.cpus.iter() {
+            cpu.set_guest_debug(Some(self.debug_evt.clone()), false)?;
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self) {
+        self.stop_vm();
+        let addrs: Vec<u64> = self.breakpoints.keys().copied().collect();
+        for addr in addrs {
+            if let Err(e) = self.remove_breakpoint(addr) {
+                error!("Failed to remove breakpoint at {:#x}: {:?}", addr, e);
+            }
+        }
+        for cpu in self.cpus.iter() {
+            if let Err(e) = cpu.set_guest_debug(None, false) {
+                error!("{:?}", e);
+            }
+            cpu.take_debug_stop();
+        }
+        self.resume_vm();
+    }
+
+    fn process(&mut self, stream: &mut TcpStream) -> Result<()> {
+        let epoll = Epoll::new()?;
+        epoll.ctl(
+            ControlOperation::Add,
+            stream.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, STREAM_TOKEN),
+        )?;
+        epoll.ctl(
+            ControlOperation::Add,
+            self.debug_evt.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, DEBUG_TOKEN),
+        )?;
+
+        let mut parser = PacketParser::default();
+        let mut buf = [0_u8; GDB_PACKET_SIZE];
+        loop {
+            let len = stream.read(&mut buf)?;
+            if len == 0 {
+                return Ok(());
+            }
+            for byte in buf[..len].iter() {
+                let packet = match parser.feed(*byte) {
+                    Some(GdbInput::Packet(packet)) => {
+                        stream.write_all(b"+")?;
+                        packet
+                    }
+                    Some(GdbInput::BadPacket) => {
+                        stream.write_all(b"-")?;
+                        continue;
+                    }
+                    // VM is already stopped.
+                    Some(GdbInput::Interrupt) | None => continue,
+                };
+
+                match self.handle_packet(&packet) {
+                    GdbAction::Reply(reply) => send_packet(stream, &reply)?,
+                    GdbAction::Resume(step_cpu) => {
+                        self.resume(step_cpu)?;
+                        match self.wait_for_stop(stream, &epoll)? {
+                            Some(reply) => send_packet(stream, &reply)?,
+                            None => return Ok(()),
+                        }
+                    }
+                    GdbAction::Detach => {
+                        send_packet(stream, "OK")?;
+                        return Ok(());
+                    }
+                    GdbAction::Kill => {
+                        info!("Gdb client kills VM");
+                        self.vm.lock().unwrap().destroy();
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &str) -> GdbAction {
+        let (cmd, args) = packet.split_at(min(packet.len(), 1));
+        let ret = match cmd {
+            "?" => Ok(stop_reply(GDB_SIGTRAP, self.cur_cpu)),
+            "g" => self.cpus[self.cur_cpu]
+                .get_gdb_regs()
+                .map(|regs| to_hex(&regs)),
+            "G" => from_hex(args)
+                .and_then(|regs| self.cpus[self.cur_cpu].set_gdb_regs(&regs))
+                .map(|_| "OK".to_string()),
+            "m" => self.handle_read_memory(args),
+            "M" => self.handle_write_memory(args),
+            "H" => self.handle_set_thread(args),
+            "T" => self.handle_thread_alive(args),
+            "Z" | "z" => self.handle_breakpoint(cmd == "Z", args),
+            "c" | "C" => return GdbAction::Resume(None),
+            "s" | "S" => return GdbAction::Resume(Some(self.cur_cpu)),
+            "v" => return self.handle_v_packet(packet),
+            "q" => Ok(self.handle_query(packet)),
+            "D" => return GdbAction::Detach,
+            "k" => return GdbAction::Kill,
+            // Empty reply means the packet is not supported.
+            _ => Ok(String::new()),
+        };
+
+        match ret {
+            Ok(reply) => GdbAction::Reply(reply),
+            Err(e) => {
+                warn!("Failed to handle gdb packet {}: {:?}", packet, e);
+                GdbAction::Reply("E01".to_string())
+            }
+        }
+    }
+
+    fn handle_query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x}", GDB_PACKET_SIZE);
+        }
+        match packet {
+            "qC" => format!("QC{:x}", self.cur_cpu + 1),
+            "qAttached" => "1".to_string(),
+            "qfThreadInfo" => {
+                let threads: Vec<String> = (1..=self.cpus.len())
+                    .map(|tid| format!("{:x}", tid))
+                    .collect();
+                format!("m{}", threads.join(","))
+            }
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// `vCont;action[:thread-id]...`, only continue and step are supported.
+    fn handle_v_packet(&self, packet: &str) -> GdbAction {
+        if packet == "vCont?" {
+            return GdbAction::Reply("vCont;c;C;s;S".to_string());
+        }
+        let actions = match packet.strip_prefix("vCont;") {
+            Some(actions) => actions,
+            None => return GdbAction::Reply(String::new()),
+        };
+
+        for action in actions.split(';') {
+            let (act, tid) = match action.split_once(':') {
+                Some((act, tid)) => (act, parse_thread_id(tid).ok().flatten()),
+                None => (action, None),
+            };
+            if act.starts_with('s') || act.starts_with('S') {
+                let step_cpu = tid.unwrap_or(self.cur_cpu);
+                if step_cpu < self.cpus.len() {
+                    return GdbAction::Resume(Some(step_cpu));
+                }
+            }
+        }
+        GdbAction::Resume(None)
+    }
+
+    fn handle_set_thread(&mut self, args: &str) -> Result<String> {
+        if args.is_empty() {
+            bail!("Invalid thread operation");
+        }
+        if let Some(cpu_index) = parse_thread_id(&args[1..])? {
+            if cpu_index >= self.cpus.len() {
+                bail!("Invalid thread id {}", &args[1..]);
+            }
+            self.cur_cpu = cpu_index;
+        }
+        Ok("OK".to_string())
+    }
+
+    fn handle_thread_alive(&self, args: &str) -> Result<String> {
+        match parse_thread_id(args)? {
+            Some(cpu_index) if cpu_index >= self.cpus.len() => {
+                bail!("Invalid thread id {}", args)
+            }
+            _ => Ok("OK".to_string()),
+        }
+    }
+
+    /// `m addr,length`
+    fn handle_read_memory(&self, args: &str) -> Result<String> {
+        let (addr, len) = parse_addr_len(args)?;
+        if len > (GDB_PACKET_SIZE / 2) as u64 {
+            bail!("Memory length {} exceeds packet size", len);
+        }
+        Ok(to_hex(&self.read_memory(addr, len)?))
+    }
+
+    /// `M addr,length:XX...`
+    fn handle_write_memory(&self, args: &str) -> Result<String> {
+        let (range, hex) = match args.split_once(':') {
+            Some(parts) => parts,
+            None => bail!("Invalid arguments {}", args),
+        };
+        let (addr, len) = parse_addr_len(range)?;
+        let data = from_hex(hex)?;
+        if data.len() as u64 != len {
+            bail!("Memory length {} mismatches data", len);
+        }
+        self.write_memory(addr, &data)?;
+        Ok("OK".to_string())
+    }
+
+    /// `Z0,addr,kind` or `z0,addr,kind`, only software breakpoint is supported.
+    fn handle_breakpoint(&mut self, insert: bool, args: &str) -> Result<String> {
+        let addr = match args.strip_prefix("0,") {
+            Some(args) => parse_addr_len(args)?.0,
+            None => return Ok(String::new()),
+        };
+        if insert {
+            self.insert_breakpoint(addr)?;
+        } else {
+            self.remove_breakpoint(addr)?;
+        }
+        Ok("OK".to_string())
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> Result<()> {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        let gpa = self.translate(addr)?;
+        let len = SW_BREAKPOINT.len() as u64;
+        let mut orig = vec![0_u8; SW_BREAKPOINT.len()];
+        self.sys_mem
+            .read(&mut orig.as_mut_slice(), GuestAddress(gpa), len)?;
+        self.sys_mem
+            .write(&mut &SW_BREAKPOINT[..], GuestAddress(gpa), len)?;
+        self.breakpoints.insert(addr, (gpa, orig));
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> Result<()> {
+        if let Some((gpa, orig)) = self.breakpoints.remove(&addr) {
+            self.sys_mem
+                .write(&mut orig.as_slice(), GuestAddress(gpa), orig.len() as u64)?;
+        }
+        Ok(())
+    }
+
+    fn translate(&self, gva: u64) -> Result<u64> {
+        let read_u64 = |gpa: u64| self.sys_mem.read_object::<u64>(GuestAddress(gpa));
+        self.cpus[self.cur_cpu].translate_gva(gva, &read_u64)
+    }
+
+    /// Access guest memory by guest virtual address page by page, as the physical pages
+    /// may be discontinuous.
+    fn access_memory<F>(&self, addr: u64, len: u64, mut access: F) -> Result<()>
+    where
+        F: FnMut(u64, u64, u64) -> Result<()>,
+    {
+        let end = addr
+            .checked_add(len)
+            .with_context(|| format!("Invalid memory range {:#x}+{:#x}", addr, len))?;
+        let mut gva = addr;
+        while gva < end {
+            let chunk = min(end - gva, PAGE_SIZE - (gva % PAGE_SIZE));
+            let gpa = self.translate(gva)?;
+            access(gpa, gva - addr, chunk)?;
+            gva += chunk;
+        }
+        Ok(())
+    }
+
+    fn read_memory(&self, addr: u64, len: u64) -> Result<Vec<u8>> {
+        let mut data = vec![0_u8; len as usize];
+        self.access_memory(addr, len, |gpa, offset, chunk| {
+            let buf = &mut data[offset as usize..(offset + chunk) as usize];
+            self.sys_mem
+                .read(&mut &mut buf[..], GuestAddress(gpa), chunk)
+        })?;
+        Ok(data)
+    }
+
+    fn write_memory(&self, addr: u64, data: &[u8]) -> Result<()> {
+        self.access_memory(addr, data.len() as u64, |gpa, offset, chunk| {
+            let buf = &data[offset as usize..(offset + chunk) as usize];
+            self.sys_mem.write(&mut &buf[..], GuestAddress(gpa), chunk)
+        })
+    }
+
+    fn resume(&mut self, step_cpu: Option<usize>) -> Result<()> {
+        for (idx, cpu) in self.cpus.iter().enumerate() {
+            cpu.take_debug_stop();
+            cpu.set_guest_debug(Some(self.debug_evt.clone()), step_cpu == Some(idx))?;
+        }
+        self.resume_vm();
+        Ok(())
+    }
+
+    /// Wait until vcpu stops for guest debug or client interrupts VM, returns the stop
+    /// reply, or `None` if client disconnects.
+    fn wait_for_stop(&mut self, stream: &mut TcpStream, epoll: &Epoll) -> Result<Option<String>> {
+        let mut events = [EpollEvent::default(); 2];
+        loop {
+            let num = match epoll.wait(-1, &mut events) {
+                Ok(num) => num,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            for event in events[..num].iter() {
+                if event.data() == DEBUG_TOKEN {
+                    let _ = self.debug_evt.read();
+                    if let Some(idx) = self.cpus.iter().position(|cpu| cpu.take_debug_stop()) {
+                        self.stop_vm();
+                        self.cur_cpu = idx;
+                        return Ok(Some(stop_reply(GDB_SIGTRAP, idx)));
+                    }
+                } else {
+                    let mut buf = [0_u8; 64];
+                    let len = stream.read(&mut buf)?;
+                    if len == 0 {
+                        return Ok(None);
+                    }
+                    if buf[..len].contains(&GDB_INTERRUPT) {
+                        self.stop_vm();
+                        return Ok(Some(stop_reply(GDB_SIGINT, self.cur_cpu)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn stop_vm(&self) {
+        let running = *self.vm_state.0.lock().unwrap() == KvmVmState::Running;
+        if running && !self.vm.lock().unwrap().pause() {
+            error!("Failed to pause VM for gdb");
+        }
+    }
+
+    fn resume_vm(&self) {
+        let paused = *self.vm_state.0.lock().unwrap() == KvmVmState::Paused;
+        if paused && !self.vm.lock().unwrap().resume() {
+            error!("Failed to resume VM for gdb");
+        }
+    }
+}
+
+fn send_packet(stream: &mut TcpStream, data: &str) -> Result<()> {
+    stream.write_all(encode_packet(data).as_bytes())?;
+    Ok(())
+}
+
+/// Start gdbstub listening on the tcp address.
+///
+/// # Arguments
+///
+/// * `addr` - Listening address, such as `0.0.0.0:1234`.
+/// * `vm` - The VM to debug.
+/// * `vm_state` - Running state of VM.
+/// * `cpus` - Vcpus of VM.
+/// * `sys_mem` - Memory address space of VM.
+pub fn start_gdbstub(
+    addr: &str,
+    vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+    vm_state: Arc<(Mutex<KvmVmState>, Condvar)>,
+    cpus: Vec<Arc<CPU>>,
+    sys_mem: Arc<AddressSpace>,
+) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Failed to bind gdbstub to {}", addr))?;
+    let mut gdbstub = GdbStub {
+        vm,
+        vm_state,
+        cpus,
+        sys_mem,
+        debug_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        cur_cpu: 0,
+        breakpoints: HashMap::new(),
+    };
+
+    info!("Gdbstub is listening on {}", addr);
+    thread::Builder::new()
+        .name("gdbstub".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        info!("Gdb client connected");
+                        if let Err(e) = gdbstub.serve(stream) {
+                            error!("Gdb session error: {:?}", e);
+                        }
+                        info!("Gdb client disconnected");
+                    }
+                    Err(e) => error!("Failed to accept gdb client: {:?}", e),
+                }
+            }
+        })
+        .with_context(|| "Failed to create gdbstub thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<GdbInput> {
+        let mut parser = PacketParser::default();
+        input.iter().filter_map(|byte| parser.feed(*byte)).collect()
+    }
+
+    #[test]
+    fn test_packet_parser() {
+        assert_eq!(encode_packet("OK"), "$OK#9a");
+        assert_eq!(parse(b"+$g#67"), vec![GdbInput::Packet("g".to_string())]);
+        assert_eq!(
+            parse(b"$m1000,4#8e$g#00"),
+            vec![GdbInput::Packet("m1000,4".to_string()), GdbInput::BadPacket]
+        );
+        assert_eq!(parse(&[GDB_INTERRUPT]), vec![GdbInput::Interrupt]);
+    }
+
+    #[test]
+    fn test_packet_args() {
+        assert_eq!(to_hex(&[0x12, 0xab, 0x00]), "12ab00");
+        assert_eq!(from_hex("12ab00").unwrap(), vec![0x12, 0xab, 0x00]);
+        assert!(from_hex("12a").is_err());
+        assert!(from_hex("zz").is_err());
+        assert_eq!(
+            parse_addr_len("ffffffff81000000,40").unwrap(),
+            (0xffff_ffff_8100_0000, 0x40)
+        );
+        assert!(parse_addr_len("1000").is_err());
+        assert_eq!(parse_thread_id("-1").unwrap(), None);
+        assert_eq!(parse_thread_id("0").unwrap(), None);
+        assert_eq!(parse_thread_id("a").unwrap(), Some(9));
+        assert_eq!(stop_reply(GDB_SIGTRAP, 1), "T05thread:2;");
+    }
+}
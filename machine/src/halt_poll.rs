@@ -0,0 +1,233 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Halt polling control of the VM.
+//!
+//! Before halting an idle vCPU, kvm polls for the wakeup event for a while, whose
+//! maximum is set per VM. In adaptive mode, the success rate of polling is sampled
+//! periodically from kvm debugfs. The polling time is grown while most of the polls
+//! succeed, and shrunk while most of them fail, within the configured maximum.
+
+use std::fs::read_to_string;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use hypervisor::kvm::KVM_FDS;
+use machine_manager::config::MachineConfig;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::qmp::qmp_schema::{HaltPollArgument, HaltPollInfo};
+
+/// Parameter of kvm module which is the default maximum polling time.
+const KVM_HALT_POLL_NS_PARAM: &str = "/sys/module/kvm/parameters/halt_poll_ns";
+const DEFAULT_HALT_POLL_NS: u32 = 200_000;
+/// Interval to sample the success rate of polling in adaptive mode.
+const ADAPTIVE_INTERVAL: Duration = Duration::from_secs(1);
+/// The polling time is grown from it, and polling is stopped if it's shrunk below it.
+const MIN_HALT_POLL_NS: u32 = 10_000;
+/// Grow the polling time if the success rate in percentage reaches it.
+const GROW_THRESHOLD: u64 = 80;
+/// Shrink the polling time if the success rate in percentage is below it.
+const SHRINK_THRESHOLD: u64 = 50;
+
+#[derive(Default)]
+struct HaltPoll {
+    /// Maximum polling time in nanoseconds.
+    max_ns: u32,
+    /// Polling time in effect, it equals to `max_ns` unless in adaptive mode.
+    current_ns: u32,
+    adaptive: bool,
+    timer_id: Option<u64>,
+    /// The attempted and successful polls of last sample.
+    last_stats: Option<(u64, u64)>,
+}
+
+impl HaltPoll {
+    fn apply(&mut self, halt_poll_ns: u32) -> Result<()> {
+        KVM_FDS.load().set_halt_poll_ns(halt_poll_ns)?;
+        self.current_ns = halt_poll_ns;
+        Ok(())
+    }
+
+    fn set_adaptive(&mut self, adaptive: bool) -> Result<()> {
+        if adaptive && KVM_FDS.load().halt_poll_stats().is_none() {
+            bail!("Adaptive halt polling is not available, kvm debugfs is not accessible");
+        }
+        self.adaptive = adaptive;
+        self.last_stats = None;
+        if adaptive {
+            self.start_timer();
+        } else {
+            self.stop_timer();
+        }
+        Ok(())
+    }
+
+    fn start_timer(&mut self) {
+        self.stop_timer();
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            let func = Box::new(halt_poll_timer_func);
+            self.timer_id = Some(ctx.timer_add(func, ADAPTIVE_INTERVAL));
+        }
+    }
+
+    fn stop_timer(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
+
+    fn adjust(&mut self) -> Result<()> {
+        let stats = match KVM_FDS.load().halt_poll_stats() {
+            Some(stats) => stats,
+            None => return Ok(()),
+        };
+        let last_stats = self.last_stats.replace(stats);
+        let (last_attempted, last_successful) = match last_stats {
+            Some(last_stats) => last_stats,
+            None => return Ok(()),
+        };
+        let halt_poll_ns = next_halt_poll_ns(
+            self.current_ns,
+            self.max_ns,
+            stats.0.saturating_sub(last_attempted),
+            stats.1.saturating_sub(last_successful),
+        );
+        if halt_poll_ns != self.current_ns {
+            info!(
+                "Adaptive halt polling: adjust polling time from {}ns to {}ns",
+                self.current_ns, halt_poll_ns
+            );
+            self.apply(halt_poll_ns)?;
+        }
+        Ok(())
+    }
+}
+
+static HALT_POLL: Lazy<Mutex<HaltPoll>> = Lazy::new(|| Mutex::new(HaltPoll::default()));
+
+fn halt_poll_timer_func() {
+    let mut halt_poll = HALT_POLL.lock().unwrap();
+    // The timer is removed after it fires.
+    halt_poll.timer_id = None;
+    if !halt_poll.adaptive {
+        return;
+    }
+    if let Err(e) = halt_poll.adjust() {
+        error!("Failed to adjust halt polling: {:?}", e);
+    }
+    halt_poll.start_timer();
+}
+
+/// Get the default maximum polling time from the parameter of kvm module.
+fn host_halt_poll_ns() -> u32 {
+    read_to_string(KVM_HALT_POLL_NS_PARAM)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_HALT_POLL_NS)
+}
+
+/// Compute the polling time by the polls since last sample.
+fn next_halt_poll_ns(current_ns: u32, max_ns: u32, attempted: u64, successful: u64) -> u32 {
+    if attempted == 0 {
+        // No poll is attempted while polling is stopped, try to restart it.
+        if current_ns == 0 {
+            return MIN_HALT_POLL_NS.min(max_ns);
+        }
+        return current_ns;
+    }
+
+    let success_rate = successful * 100 / attempted;
+    if success_rate >= GROW_THRESHOLD {
+        current_ns
+            .saturating_mul(2)
+            .max(MIN_HALT_POLL_NS)
+            .min(max_ns)
+    } else if success_rate < SHRINK_THRESHOLD {
+        let halt_poll_ns = current_ns / 2;
+        if halt_poll_ns < MIN_HALT_POLL_NS {
+            0
+        } else {
+            halt_poll_ns
+        }
+    } else {
+        current_ns
+    }
+}
+
+/// Set the halt polling of the VM by machine config, the default of kvm module is kept
+/// if it's not configured.
+pub fn init_halt_poll(config: &MachineConfig) -> Result<()> {
+    let mut halt_poll = HALT_POLL.lock().unwrap();
+    halt_poll.max_ns = config.halt_poll_ns.unwrap_or_else(host_halt_poll_ns);
+    halt_poll.current_ns = halt_poll.max_ns;
+    if let Some(halt_poll_ns) = config.halt_poll_ns {
+        halt_poll.apply(halt_poll_ns)?;
+    }
+    if config.halt_poll_adaptive {
+        halt_poll.set_adaptive(true)?;
+    }
+    Ok(())
+}
+
+pub fn set_halt_poll(args: &HaltPollArgument) -> Result<()> {
+    let mut halt_poll = HALT_POLL.lock().unwrap();
+    let max_ns = args.halt_poll_ns.unwrap_or(halt_poll.max_ns);
+    let adaptive = args.adaptive.unwrap_or(halt_poll.adaptive);
+    let halt_poll_ns = if adaptive {
+        halt_poll.current_ns.min(max_ns)
+    } else {
+        max_ns
+    };
+    halt_poll.apply(halt_poll_ns)?;
+    halt_poll.max_ns = max_ns;
+    halt_poll.set_adaptive(adaptive)
+}
+
+pub fn query_halt_poll() -> HaltPollInfo {
+    let halt_poll = HALT_POLL.lock().unwrap();
+    HaltPollInfo {
+        halt_poll_ns: halt_poll.max_ns,
+        adaptive: halt_poll.adaptive,
+        current_ns: halt_poll.current_ns,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_halt_poll_ns() {
+        // Grow while most of the polls succeed, within the maximum.
+        assert_eq!(next_halt_poll_ns(50_000, 200_000, 100, 90), 100_000);
+        assert_eq!(next_halt_poll_ns(150_000, 200_000, 100, 80), 200_000);
+        assert_eq!(next_halt_poll_ns(200_000, 200_000, 100, 100), 200_000);
+
+        // Shrink while most of the polls fail, and stop polling at last.
+        assert_eq!(next_halt_poll_ns(200_000, 200_000, 100, 10), 100_000);
+        assert_eq!(next_halt_poll_ns(15_000, 200_000, 100, 10), 0);
+
+        // Keep it in between.
+        assert_eq!(next_halt_poll_ns(100_000, 200_000, 100, 60), 100_000);
+
+        // Restart the stopped polling, unless it's disabled.
+        assert_eq!(next_halt_poll_ns(0, 200_000, 0, 0), MIN_HALT_POLL_NS);
+        assert_eq!(next_halt_poll_ns(0, 0, 0, 0), 0);
+        assert_eq!(next_halt_poll_ns(100_000, 200_000, 0, 0), 100_000);
+    }
+}
@@ -13,7 +13,10 @@
 pub mod error;
 pub mod standard_vm;
 
+mod gdbstub;
+mod halt_poll;
 mod micro_vm;
+mod sandbox;
 #[cfg(target_arch = "x86_64")]
 mod vm_state;
 
@@ -23,6 +26,7 @@ pub use crate::error::MachineError;
 pub use micro_vm::LightMachine;
 pub use standard_vm::StdMachine;
 
+use std::cmp::max;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{remove_file, File};
 use std::net::TcpListener;
@@ -41,14 +45,22 @@ use vmm_sys_util::eventfd::EventFd;
 #[cfg(target_arch = "x86_64")]
 use address_space::KvmIoListener;
 use address_space::{
-    create_backend_mem, create_default_mem, AddressSpace, KvmMemoryListener, Region,
+    create_backend_mem, create_default_mem, AddressSpace, GuestAddress, KvmMemoryListener, Region,
 };
-#[cfg(target_arch = "aarch64")]
-use cpu::CPUFeatures;
-use cpu::{ArchCPU, CPUBootConfig, CPUInterface, CPUTopology, CPU};
-use devices::legacy::FwCfgOps;
+use cpu::{
+    ArchCPU, CPUBootConfig, CPUFeatures, CPUInterface, CPUTopology, CpuThrottler, CpuTopology, CPU,
+};
+use devices::acpi::vmgenid::VmGenId;
+use devices::ahci::{ahci_pci::AhciPciDevice, ata::AtaDevice};
+use devices::legacy::{FwCfgOps, PFlash};
+use devices::misc::ivshmem::Ivshmem;
 #[cfg(feature = "scream")]
 use devices::misc::scream::Scream;
+#[cfg(target_arch = "x86_64")]
+use devices::misc::watchdog::I6300Esb;
+use devices::misc::watchdog::WatchdogActionTrigger;
+use devices::net::e1000e_pci::E1000ePciDevice;
+use devices::nvme::nvme_pci::NvmePciDevice;
 #[cfg(feature = "demo_device")]
 use devices::pci::demo_device::DemoDev;
 use devices::pci::{PciBus, PciDevOps, PciHost, RootPort};
@@ -58,8 +70,8 @@ use devices::usb::camera::UsbCamera;
 #[cfg(feature = "usb_host")]
 use devices::usb::usbhost::UsbHost;
 use devices::usb::{
-    keyboard::UsbKeyboard, storage::UsbStorage, tablet::UsbTablet, xhci::xhci_pci::XhciPciDevice,
-    UsbDevice,
+    hub::UsbHub, keyboard::UsbKeyboard, storage::UsbStorage, tablet::UsbTablet, u2f::UsbU2f,
+    usbredir::UsbRedir, xhci::xhci_pci::XhciPciDevice, UsbDevice,
 };
 #[cfg(target_arch = "aarch64")]
 use devices::InterruptController;
@@ -73,43 +85,66 @@ use machine_manager::config::parse_gpu;
 use machine_manager::config::parse_usb_camera;
 #[cfg(feature = "usb_host")]
 use machine_manager::config::parse_usb_host;
+#[cfg(target_arch = "aarch64")]
+use machine_manager::config::parse_vfio_platform;
+#[cfg(target_arch = "x86_64")]
+use machine_manager::config::parse_watchdog;
 #[cfg(feature = "scream")]
 use machine_manager::config::scream::parse_scream;
 use machine_manager::config::{
-    complete_numa_node, get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_device_id,
-    parse_fs, parse_net, parse_numa_distance, parse_numa_mem, parse_rng_dev, parse_root_port,
-    parse_scsi_controller, parse_scsi_device, parse_vfio, parse_vhost_user_blk,
+    complete_numa_node, get_multi_function, get_pci_bdf, get_virtio_pci_mode, parse_ahci,
+    parse_balloon, parse_blk, parse_crypto_dev, parse_device_id, parse_e1000e, parse_fs,
+    parse_ide_device, parse_iommu, parse_ivshmem, parse_net, parse_numa_distance, parse_numa_mem,
+    parse_nvme, parse_p9fs, parse_pmem, parse_rng_dev, parse_root_port, parse_scsi_controller,
+    parse_scsi_device, parse_sound, parse_usb_redir, parse_vfio, parse_vhost_user_blk,
     parse_virtio_serial, parse_virtserialport, parse_vsock, BootIndexInfo, DriveFile, Incoming,
-    MachineMemConfig, MigrateMode, NumaConfig, NumaDistance, NumaNode, NumaNodes, PFlashConfig,
-    PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON, MAX_VIRTIO_QUEUE,
+    IvshmemConfig, MachineMemConfig, MigrateMode, NumaConfig, NumaDistance, NumaNode, NumaNodes,
+    PFlashConfig, PciBdf, SerialConfig, VfioConfig, VirtioPciMode, VmConfig, WatchdogAction,
+    FAST_UNPLUG_ON, MAX_VIRTIO_QUEUE,
 };
 use machine_manager::config::{
-    parse_usb_keyboard, parse_usb_storage, parse_usb_tablet, parse_xhci,
+    parse_usb_hub, parse_usb_keyboard, parse_usb_storage, parse_usb_tablet, parse_usb_u2f,
+    parse_xhci,
 };
+use machine_manager::cpu_throttle::register_cpu_throttle_ops;
 use machine_manager::event_loop::EventLoop;
 use machine_manager::machine::{KvmVmState, MachineInterface};
+#[cfg(target_arch = "x86_64")]
+use machine_manager::qmp::qmp_schema::CpuInfoX86;
+use machine_manager::qmp::qmp_schema::{
+    QueryStatsArgument, Stats, StatsProvider, StatsResult, StatsTarget,
+};
+use machine_manager::qmp::{qmp_response::Response, qmp_schema};
 use migration::MigrationManager;
 use smbios::smbios_table::{build_smbios_ep30, SmbiosTable};
 use smbios::{SMBIOS_ANCHOR_FILE, SMBIOS_TABLE_FILE};
 use standard_vm::Result as StdResult;
 #[cfg(feature = "windows_emu_pid")]
 use ui::console::{get_run_stage, VmRunningStage};
+#[cfg(target_arch = "aarch64")]
+use util::device_tree::FdtNodeTemplate;
 use util::file::{clear_file, lock_file, unlock_file};
+use util::num_ops::round_up;
 use util::{
     arg_parser,
     seccomp::{BpfRule, SeccompOpt, SyscallFilter},
 };
+#[cfg(target_arch = "aarch64")]
+use vfio::VfioPlatformDevice;
 use vfio::{VfioDevice, VfioPciDevice};
 #[cfg(feature = "virtio_gpu")]
 use virtio::Gpu;
 use virtio::{
-    balloon_allow_list, find_port_by_nr, get_max_nr, vhost, Balloon, Block, BlockState, Rng,
-    RngState,
-    ScsiCntlr::{scsi_cntlr_create_scsi_bus, ScsiCntlr},
-    Serial, SerialPort, VhostKern, VhostUser, VirtioDevice, VirtioMmioDevice, VirtioMmioState,
-    VirtioNetState, VirtioPciDevice, VirtioSerialState, VIRTIO_TYPE_CONSOLE,
+    balloon_allow_list, find_port_by_nr, get_max_nr, vhost, Balloon, Block, BlockState, Crypto,
+    Iommu, P9fs, Pmem, Rng, RngState,
+    ScsiCntlr::{scsi_cntlr_create_scsi_bus, ScsiCntlr, VIRTIO_SCSI_EVT_RESET_RESCAN},
+    Serial, SerialPort, Sound, VhostKern, VhostUser, VirtioDevice, VirtioMmioDevice,
+    VirtioMmioState, VirtioNetState, VirtioPciDevice, VirtioSerialState, VIRTIO_TYPE_CONSOLE,
 };
 
+/// Alignment of the guest physical address of device memory, such as virtio-pmem.
+const DEVICE_MEM_ALIGN: u64 = 1 << 30;
+
 pub trait MachineOps {
     fn build_smbios(
         &self,
@@ -119,12 +154,13 @@ pub trait MachineOps {
         let vm_config = self.get_vm_config();
         let vmcfg_lock = vm_config.lock().unwrap();
 
+        let mut smbios_cfg = vmcfg_lock.smbios.clone();
+        if smbios_cfg.type1.uuid.is_none() {
+            smbios_cfg.type1.uuid = vmcfg_lock.uuid.clone();
+        }
+
         let mut smbios = SmbiosTable::new();
-        let table = smbios.build_smbios_tables(
-            vmcfg_lock.smbios.clone(),
-            &vmcfg_lock.machine_config,
-            mem_array,
-        );
+        let table = smbios.build_smbios_tables(smbios_cfg, &vmcfg_lock.machine_config, mem_array);
         let ep = build_smbios_ep30(table.len() as u32);
 
         let mut locked_fw_cfg = fw_cfg.lock().unwrap();
@@ -140,9 +176,9 @@ pub trait MachineOps {
 
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig>;
 
-    #[cfg(target_arch = "aarch64")]
     fn load_cpu_features(&self, vmcfg: &VmConfig) -> Result<CPUFeatures> {
-        Ok((&vmcfg.machine_config.cpu_config).into())
+        let features = CPUFeatures::try_from(&vmcfg.machine_config.cpu_config)?;
+        Ok(features)
     }
 
     /// Init memory of vm to architecture.
@@ -230,7 +266,7 @@ pub trait MachineOps {
         nr_cpus: u8,
         topology: &CPUTopology,
         boot_cfg: &Option<CPUBootConfig>,
-        #[cfg(target_arch = "aarch64")] vcpu_cfg: &Option<CPUFeatures>,
+        vcpu_cfg: &Option<CPUFeatures>,
     ) -> Result<Vec<Arc<CPU>>>
     where
         Self: Sized,
@@ -263,20 +299,16 @@ pub trait MachineOps {
 
         if let Some(boot_config) = boot_cfg {
             for (cpu_index, cpu) in cpus.iter().enumerate() {
-                cpu.realize(
-                    boot_config,
-                    topology,
-                    #[cfg(target_arch = "aarch64")]
-                    &vcpu_cfg.unwrap_or_default(),
-                )
-                .with_context(|| {
-                    format!(
-                        "Failed to realize arch cpu register/features for CPU {}/KVM",
-                        cpu_index
-                    )
-                })?;
+                cpu.realize(boot_config, topology, &vcpu_cfg.unwrap_or_default())
+                    .with_context(|| {
+                        format!(
+                            "Failed to realize arch cpu register/features for CPU {}/KVM",
+                            cpu_index
+                        )
+                    })?;
             }
         }
+        register_cpu_throttle_ops(Arc::new(CpuThrottler::new(cpus.clone())));
 
         Ok(cpus)
     }
@@ -342,6 +374,7 @@ pub trait MachineOps {
                 multi_func,
             );
             virtio_pci_device.enable_need_irqfd();
+            self.set_virtio_pci_iommu(&mut virtio_pci_device, &bdf);
             virtio_pci_device
                 .realize()
                 .with_context(|| "Failed to add virtio pci vsock device")?;
@@ -370,6 +403,24 @@ pub trait MachineOps {
 
     fn get_vm_ram(&self) -> &Arc<Region>;
 
+    /// Reclaim the host memory of the paused VM without losing the content of guest memory,
+    /// returns the size of the advised guest memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - `cold` or `pageout`, default is `pageout`.
+    fn reclaim_guest_ram(&mut self, mode: Option<&str>) -> Result<u64> {
+        let advice = match mode.unwrap_or("pageout") {
+            "cold" => libc::MADV_COLD,
+            "pageout" => libc::MADV_PAGEOUT,
+            mode => bail!("Invalid mode {}, must be one of cold or pageout", mode),
+        };
+        if *self.get_vm_state().0.lock().unwrap() != KvmVmState::Paused {
+            bail!("Guest memory can only be reclaimed when the VM is paused");
+        }
+        self.get_sys_mem().advise_ram(advice)
+    }
+
     fn get_numa_nodes(&self) -> &Option<NumaNodes>;
 
     /// Get migration mode and path from VM config. There are four modes in total:
@@ -392,7 +443,8 @@ pub trait MachineOps {
         let balloon = Arc::new(Mutex::new(Balloon::new(&device_cfg, sys_mem.clone())));
         Balloon::object_init(balloon.clone());
         if cfg_args.contains("virtio-balloon-device") {
-            let device = VirtioMmioDevice::new(sys_mem, balloon);
+            let mut device = VirtioMmioDevice::new(sys_mem, balloon);
+            device.set_id(&device_cfg.id, true);
             self.realize_virtio_mmio_device(device)?;
         } else {
             let name = device_cfg.id;
@@ -400,8 +452,9 @@ pub trait MachineOps {
             let multi_func = get_multi_function(cfg_args)?;
             let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
             let sys_mem = self.get_sys_mem().clone();
-            let virtio_pci_device =
+            let mut virtio_pci_device =
                 VirtioPciDevice::new(name, devfn, sys_mem, balloon, parent_bus, multi_func);
+            virtio_pci_device.set_mode(get_virtio_pci_mode(cfg_args)?);
             virtio_pci_device
                 .realize()
                 .with_context(|| "Failed to add virtio pci balloon device")?;
@@ -433,7 +486,7 @@ pub trait MachineOps {
             let bdf = serial_cfg.pci_bdf.unwrap();
             let multi_func = serial_cfg.multifunction;
             let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
-            let virtio_pci_device = VirtioPciDevice::new(
+            let mut virtio_pci_device = VirtioPciDevice::new(
                 serial_cfg.id.clone(),
                 devfn,
                 sys_mem,
@@ -441,6 +494,8 @@ pub trait MachineOps {
                 parent_bus,
                 multi_func,
             );
+            virtio_pci_device.set_mode(get_virtio_pci_mode(cfg_args)?);
+            self.set_virtio_pci_iommu(&mut virtio_pci_device, &bdf);
             virtio_pci_device
                 .realize()
                 .with_context(|| "Failed to add virtio pci serial device")?;
@@ -555,7 +610,7 @@ pub trait MachineOps {
             let multi_func = get_multi_function(cfg_args)?;
             let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
             let sys_mem = self.get_sys_mem().clone();
-            let vitio_pci_device = VirtioPciDevice::new(
+            let mut vitio_pci_device = VirtioPciDevice::new(
                 device_cfg.id.clone(),
                 devfn,
                 sys_mem,
@@ -563,6 +618,8 @@ pub trait MachineOps {
                 parent_bus,
                 multi_func,
             );
+            vitio_pci_device.set_mode(get_virtio_pci_mode(cfg_args)?);
+            self.set_virtio_pci_iommu(&mut vitio_pci_device, &bdf);
             vitio_pci_device
                 .realize()
                 .with_context(|| "Failed to add pci rng device")?;
@@ -571,6 +628,164 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add virtio-crypto device.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration arguments.
+    fn add_virtio_crypto(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_crypto_dev(vm_config, cfg_args)?;
+        let sys_mem = self.get_sys_mem();
+        let crypto_dev = Arc::new(Mutex::new(Crypto::new(device_cfg.clone())));
+        if cfg_args.contains("virtio-crypto-device") {
+            let device = VirtioMmioDevice::new(sys_mem, crypto_dev);
+            self.realize_virtio_mmio_device(device)
+                .with_context(|| "Failed to add virtio mmio crypto device")?;
+        } else {
+            let bdf = get_pci_bdf(cfg_args)?;
+            let multi_func = get_multi_function(cfg_args)?;
+            self.add_virtio_pci_device(&device_cfg.id, &bdf, crypto_dev, multi_func, false)
+                .with_context(|| "Failed to add pci crypto device")?;
+        }
+        Ok(())
+    }
+
+    /// Add virtio-9p device.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Device configuration arguments.
+    fn add_virtio_9p(&mut self, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_p9fs(cfg_args)?;
+        let sys_mem = self.get_sys_mem();
+        let p9fs_dev = Arc::new(Mutex::new(P9fs::new(device_cfg.clone())));
+        if cfg_args.contains("virtio-9p-device") {
+            let device = VirtioMmioDevice::new(sys_mem, p9fs_dev);
+            self.realize_virtio_mmio_device(device)
+                .with_context(|| "Failed to add virtio mmio 9p device")?;
+        } else {
+            let bdf = get_pci_bdf(cfg_args)?;
+            let multi_func = get_multi_function(cfg_args)?;
+            self.add_virtio_pci_device(&device_cfg.id, &bdf, p9fs_dev, multi_func, false)
+                .with_context(|| "Failed to add pci 9p device")?;
+        }
+        Ok(())
+    }
+
+    /// Get the guest physical window in which device memory can be mapped.
+    fn get_device_mem_window(&self) -> Result<(u64, u64)> {
+        bail!("Device memory is not supported by this machine");
+    }
+
+    /// Find a free guest physical range above the guest RAM to map device memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of the device memory.
+    fn alloc_device_mem(&mut self, size: u64) -> Result<u64> {
+        let (window_start, window_end) = self.get_device_mem_window()?;
+        let sys_mem = self.get_sys_mem();
+        let ram_end = round_up(sys_mem.memory_end_address().raw_value(), DEVICE_MEM_ALIGN)
+            .with_context(|| "Failed to align the end of guest RAM")?;
+        let mut start = max(window_start, ram_end);
+        // Device memory is mapped one after another, skip the ones already mapped.
+        while sys_mem.get_host_address(GuestAddress(start)).is_some() {
+            start += DEVICE_MEM_ALIGN;
+        }
+        if start.checked_add(size).is_none_or(|end| end > window_end) {
+            bail!(
+                "No enough guest physical address space for device memory of size 0x{:X}",
+                size
+            );
+        }
+        Ok(start)
+    }
+
+    /// Add virtio-pmem device.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration arguments.
+    fn add_virtio_pmem(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_pmem(vm_config, cfg_args)?;
+        let bdf = get_pci_bdf(cfg_args)?;
+        let multi_func = get_multi_function(cfg_args)?;
+        let start = self.alloc_device_mem(device_cfg.size)?;
+        let sys_mem = self.get_sys_mem().clone();
+        let pmem_dev = Arc::new(Mutex::new(Pmem::new(device_cfg.clone(), &sys_mem, start)));
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, pmem_dev, multi_func, false)
+            .with_context(|| "Failed to add pci pmem device")?;
+        Ok(())
+    }
+
+    /// Add pc-dimm device, which is plugged into a memory slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration arguments.
+    fn add_pc_dimm(&mut self, _vm_config: &mut VmConfig, _cfg_args: &str) -> Result<()> {
+        bail!("pc-dimm is not supported by this machine");
+    }
+
+    /// Add virtio-sound device.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Device configuration arguments.
+    fn add_virtio_sound(&mut self, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_sound(cfg_args)?;
+        let bdf = get_pci_bdf(cfg_args)?;
+        let multi_func = get_multi_function(cfg_args)?;
+        let sound_dev = Arc::new(Mutex::new(Sound::new(device_cfg.clone())));
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, sound_dev, multi_func, false)
+            .with_context(|| "Failed to add pci sound device")?;
+        Ok(())
+    }
+
+    /// Add virtio-iommu device.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Device configuration arguments.
+    fn add_virtio_iommu(&mut self, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_iommu(cfg_args)?;
+        let bdf = get_pci_bdf(cfg_args)?;
+        if bdf.bus != "pcie.0" {
+            bail!("virtio-iommu must be attached to the root bus pcie.0");
+        }
+        if self.get_iommu().is_some() {
+            bail!("Only one virtio-iommu is supported");
+        }
+        let multi_func = get_multi_function(cfg_args)?;
+        let devfn = (bdf.addr.0 << 3) + bdf.addr.1;
+        let iommu = Arc::new(Mutex::new(Iommu::new(device_cfg.clone(), devfn)));
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, iommu.clone(), multi_func, false)
+            .with_context(|| "Failed to add virtio pci iommu device")?;
+        self.set_iommu(iommu)
+    }
+
+    fn set_iommu(&mut self, _iommu: Arc<Mutex<Iommu>>) -> Result<()> {
+        bail!("virtio-iommu is not supported by this machine");
+    }
+
+    fn get_iommu(&self) -> Option<Arc<Mutex<Iommu>>> {
+        None
+    }
+
+    /// Put the virtio pci device behind the virtio iommu if there is one. Only the
+    /// devices on the root bus are supported.
+    fn set_virtio_pci_iommu(&self, pcidev: &mut VirtioPciDevice, bdf: &PciBdf) {
+        if bdf.bus != "pcie.0" {
+            return;
+        }
+        if let Some(iommu) = self.get_iommu() {
+            pcidev.set_iommu(&iommu);
+        }
+    }
+
     fn get_pci_host(&mut self) -> StdResult<&Arc<Mutex<PciHost>>> {
         bail!("No pci host found");
     }
@@ -624,6 +839,14 @@ pub trait MachineOps {
         None
     }
 
+    fn get_pflash(&self, _unit: usize) -> Option<Arc<Mutex<PFlash>>> {
+        None
+    }
+
+    fn get_vmgenid(&self) -> Option<Arc<Mutex<VmGenId>>> {
+        None
+    }
+
     fn reset_all_devices(&mut self) -> Result<()> {
         let sysbus = self.get_sys_bus();
         for dev in sysbus.devices.iter() {
@@ -682,27 +905,34 @@ pub trait MachineOps {
         // SAFETY: unwrap is safe because stand machine always make sure it not return null.
         let boot_order_vec = self.get_boot_order_list().unwrap();
         let mut locked_boot_order_vec = boot_order_vec.lock().unwrap().clone();
-        if locked_boot_order_vec.is_empty() {
-            return Ok(());
-        }
-        locked_boot_order_vec.sort_by(|x, y| x.boot_index.cmp(&y.boot_index));
-        let mut fwcfg_boot_order_string = String::new();
-        for item in &locked_boot_order_vec {
-            fwcfg_boot_order_string.push_str(&item.dev_path);
-            fwcfg_boot_order_string.push('\n');
-        }
-        fwcfg_boot_order_string.push('\0');
+        let fwcfg = match self.get_fwcfg_dev() {
+            Some(fwcfg) => fwcfg,
+            None => {
+                if !locked_boot_order_vec.is_empty() {
+                    warn!("Direct kernel boot mode don't support set boot order");
+                }
+                return Ok(());
+            }
+        };
 
-        let fwcfg = self.get_fwcfg_dev();
-        if fwcfg.is_none() {
-            warn!("Direct kernel boot mode don't support set boot order");
-            return Ok(());
+        // Keep the bootorder file empty if no device sets boot index, e.g. all of them
+        // have been unplugged.
+        let mut boot_order = Vec::new();
+        if !locked_boot_order_vec.is_empty() {
+            locked_boot_order_vec.sort_by_key(|item| item.boot_index);
+            let mut fwcfg_boot_order_string = String::new();
+            for item in &locked_boot_order_vec {
+                fwcfg_boot_order_string.push_str(&item.dev_path);
+                fwcfg_boot_order_string.push('\n');
+            }
+            fwcfg_boot_order_string.push('\0');
+            boot_order = fwcfg_boot_order_string.into_bytes();
         }
+
         fwcfg
-            .unwrap()
             .lock()
             .unwrap()
-            .modify_file_entry("bootorder", fwcfg_boot_order_string.as_bytes().to_vec())
+            .modify_file_entry("bootorder", boot_order)
             .with_context(|| "Fail to add bootorder entry for standard VM.")?;
         Ok(())
     }
@@ -776,8 +1006,17 @@ pub trait MachineOps {
             device_cfg.clone(),
             self.get_drive_files(),
         )));
+        let mode = get_virtio_pci_mode(cfg_args)?;
         let pci_dev = self
-            .add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, false)
+            .add_virtio_pci_device_with_opts(
+                &device_cfg.id,
+                &bdf,
+                device.clone(),
+                multi_func,
+                false,
+                None,
+                mode,
+            )
             .with_context(|| "Failed to add virtio pci device")?;
         if let Some(bootindex) = device_cfg.boot_index {
             // Eg: OpenFirmware device path(virtio-blk disk):
@@ -814,8 +1053,17 @@ pub trait MachineOps {
         let bus_name = format!("{}.0", device_cfg.id);
         scsi_cntlr_create_scsi_bus(&bus_name, &device)?;
 
+        let mode = get_virtio_pci_mode(cfg_args)?;
         let pci_dev = self
-            .add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, false)
+            .add_virtio_pci_device_with_opts(
+                &device_cfg.id,
+                &bdf,
+                device.clone(),
+                multi_func,
+                false,
+                None,
+                mode,
+            )
             .with_context(|| "Failed to add virtio scsi controller")?;
         self.reset_bus(&device_cfg.id)?;
         device.lock().unwrap().config.boot_prefix = pci_dev.lock().unwrap().get_dev_path();
@@ -827,6 +1075,7 @@ pub trait MachineOps {
         vm_config: &mut VmConfig,
         cfg_args: &str,
         scsi_type: u32,
+        hotplug: bool,
     ) -> Result<()> {
         let device_cfg = parse_scsi_device(vm_config, cfg_args)?;
         if let Some(bootindex) = device_cfg.boot_index {
@@ -866,11 +1115,21 @@ pub trait MachineOps {
         }
         let iothread = cntlr.config.iothread.clone();
         device.lock().unwrap().realize(iothread)?;
+        if hotplug {
+            cntlr.register_device_io_event(&device)?;
+        }
         bus.lock()
             .unwrap()
             .devices
             .insert((device_cfg.target, device_cfg.lun), device.clone());
         device.lock().unwrap().parent_bus = Arc::downgrade(bus);
+        if hotplug {
+            cntlr.notify_transport_reset(
+                device_cfg.target,
+                device_cfg.lun,
+                VIRTIO_SCSI_EVT_RESET_RESCAN,
+            )?;
+        }
 
         if let Some(bootindex) = device_cfg.boot_index {
             // Eg: OpenFirmware device path(virtio-scsi disk):
@@ -888,18 +1147,61 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Detach the hot plugged scsi device `id` from its controller. Returns false if there
+    /// is no such scsi device.
+    fn unplug_scsi_device(&mut self, vm_config: &VmConfig, id: &str) -> Result<bool> {
+        let cntlr_ids: Vec<String> = vm_config
+            .devices
+            .iter()
+            .filter(|(dev_type, _)| dev_type == "virtio-scsi-pci")
+            .filter_map(|(_, cfg_args)| parse_device_id(cfg_args).ok())
+            .collect();
+        for cntlr_id in cntlr_ids {
+            let Some(pci_dev) =
+                self.get_pci_dev_by_id_and_type(vm_config, Some(&cntlr_id), "virtio-scsi-pci")
+            else {
+                continue;
+            };
+            let locked_pcidev = pci_dev.lock().unwrap();
+            let virtio_pcidev = locked_pcidev
+                .as_any()
+                .downcast_ref::<VirtioPciDevice>()
+                .unwrap();
+            let virtio_device = virtio_pcidev.get_virtio_device().lock().unwrap();
+            let cntlr = virtio_device.as_any().downcast_ref::<ScsiCntlr>().unwrap();
+            if cntlr.unplug_device(id)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     fn add_virtio_pci_net(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let bdf = get_pci_bdf(cfg_args)?;
         let multi_func = get_multi_function(cfg_args)?;
         let device_cfg = parse_net(vm_config, cfg_args)?;
+        if let Some(bootindex) = device_cfg.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add virtio pci net device for invalid bootindex")?;
+        }
+        let mode = get_virtio_pci_mode(cfg_args)?;
+        if mode.legacy_enabled() && device_cfg.vhost_type.is_some() {
+            bail!("The legacy interface is not supported by vhost net device");
+        }
         let mut need_irqfd = false;
         let device: Arc<Mutex<dyn VirtioDevice>> = if device_cfg.vhost_type.is_some() {
             need_irqfd = true;
             if device_cfg.vhost_type == Some(String::from("vhost-kernel")) {
-                Arc::new(Mutex::new(VhostKern::Net::new(
+                let device = Arc::new(Mutex::new(VhostKern::Net::new(
                     &device_cfg,
                     self.get_sys_mem(),
-                )))
+                )));
+                MigrationManager::register_device_instance(
+                    VhostKern::VhostNetState::descriptor(),
+                    device.clone(),
+                    &device_cfg.id,
+                );
+                device
             } else {
                 Arc::new(Mutex::new(VhostUser::Net::new(
                     &device_cfg,
@@ -915,7 +1217,22 @@ pub trait MachineOps {
             );
             device
         };
-        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, need_irqfd)?;
+        let pci_dev = self.add_virtio_pci_device_with_opts(
+            &device_cfg.id,
+            &bdf,
+            device,
+            multi_func,
+            need_irqfd,
+            device_cfg.romfile.clone(),
+            mode,
+        )?;
+        if let Some(bootindex) = device_cfg.boot_index {
+            // Eg: OpenFirmware device path(virtio-net nic):
+            // /pci@i0cf8/ethernet@6[,3]/ethernet-phy@0
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &device_cfg.id);
+            }
+        }
         self.reset_bus(&device_cfg.id)?;
         Ok(())
     }
@@ -1060,6 +1377,34 @@ pub trait MachineOps {
         device: Arc<Mutex<dyn VirtioDevice>>,
         multi_func: bool,
         need_irqfd: bool,
+    ) -> Result<Arc<Mutex<dyn PciDevOps>>> {
+        self.add_virtio_pci_device_with_opts(
+            id,
+            bdf,
+            device,
+            multi_func,
+            need_irqfd,
+            None,
+            VirtioPciMode::Modern,
+        )
+    }
+
+    /// Add virtio pci device with optional properties of the transport.
+    ///
+    /// # Arguments
+    ///
+    /// * `romfile` - Path of the option ROM file exposed through the expansion ROM BAR.
+    /// * `mode` - The interfaces exposed to the driver, legacy and/or modern.
+    #[allow(clippy::too_many_arguments)]
+    fn add_virtio_pci_device_with_opts(
+        &mut self,
+        id: &str,
+        bdf: &PciBdf,
+        device: Arc<Mutex<dyn VirtioDevice>>,
+        multi_func: bool,
+        need_irqfd: bool,
+        romfile: Option<String>,
+        mode: VirtioPciMode,
     ) -> Result<Arc<Mutex<dyn PciDevOps>>> {
         let (devfn, parent_bus) = self.get_devfn_and_parent_bus(bdf)?;
         let sys_mem = self.get_sys_mem();
@@ -1074,6 +1419,9 @@ pub trait MachineOps {
         if need_irqfd {
             pcidev.enable_need_irqfd();
         }
+        pcidev.set_romfile(romfile);
+        pcidev.set_mode(mode);
+        self.set_virtio_pci_iommu(&mut pcidev, bdf);
         let clone_pcidev = Arc::new(Mutex::new(pcidev.clone()));
         pcidev
             .realize()
@@ -1227,6 +1575,154 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add emulated nvme controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Nvme configuration.
+    fn add_nvme(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let nvme_cfg = parse_nvme(vm_config, cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+        let drive_files = self.get_drive_files();
+
+        let pcidev = NvmePciDevice::new(
+            &nvme_cfg,
+            devfn,
+            parent_bus,
+            self.get_sys_mem(),
+            drive_files,
+        );
+        pcidev
+            .realize()
+            .with_context(|| format!("Failed to realize nvme {}", nvme_cfg.id))
+    }
+
+    /// Add emulated e1000e network interface card.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - E1000e configuration.
+    fn add_e1000e(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let net_cfg = parse_e1000e(vm_config, cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+
+        let pcidev = E1000ePciDevice::new(&net_cfg, devfn, parent_bus, self.get_sys_mem())?;
+        pcidev
+            .realize()
+            .with_context(|| format!("Failed to realize e1000e {}", net_cfg.id))
+    }
+
+    /// Add emulated ahci controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Ahci configuration.
+    fn add_ahci(&mut self, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let ahci_cfg = parse_ahci(cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+
+        let pcidev = AhciPciDevice::new(&ahci_cfg, devfn, parent_bus, self.get_sys_mem());
+        pcidev
+            .realize()
+            .with_context(|| format!("Failed to realize ahci {}", ahci_cfg.id))
+    }
+
+    /// Add ide-hd or ide-cd device to the port of the ahci controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Ide device configuration.
+    /// * `atapi` - Whether it is an ATAPI cdrom.
+    fn add_ide_device(
+        &mut self,
+        vm_config: &mut VmConfig,
+        cfg_args: &str,
+        atapi: bool,
+    ) -> Result<()> {
+        let device_cfg = parse_ide_device(vm_config, cfg_args)?;
+        let pci_dev = self
+            .get_pci_dev_by_id_and_type(vm_config, Some(&device_cfg.cntlr), "ahci")
+            .with_context(|| format!("Can not find ahci controller {}", device_cfg.cntlr))?;
+        let locked_pcidev = pci_dev.lock().unwrap();
+        let ahci_pcidev = locked_pcidev
+            .as_any()
+            .downcast_ref::<AhciPciDevice>()
+            .unwrap();
+        let id = device_cfg.id.clone();
+        ahci_pcidev
+            .attach_device(AtaDevice::new(device_cfg, atapi, self.get_drive_files()))
+            .with_context(|| format!("Failed to add ide device {}", id))
+    }
+
+    /// Get the trigger which performs the watchdog action on timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `action` - Action to take when the watchdog timer expires.
+    fn get_watchdog_action_trigger(
+        &self,
+        _action: WatchdogAction,
+    ) -> Result<WatchdogActionTrigger> {
+        bail!("Watchdog is not supported!");
+    }
+
+    /// Add i6300esb PCI watchdog.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Watchdog configuration.
+    #[cfg(target_arch = "x86_64")]
+    fn add_i6300esb(&mut self, vm_config: &VmConfig, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let device_cfg = parse_watchdog(cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+        let action_trigger =
+            self.get_watchdog_action_trigger(vm_config.machine_config.watchdog_action)?;
+
+        let pcidev = I6300Esb::new(device_cfg.id, devfn, parent_bus, action_trigger);
+        pcidev
+            .realize()
+            .with_context(|| "Failed to realize i6300esb watchdog")?;
+        Ok(())
+    }
+
+    /// Add sbsa generic watchdog.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Watchdog configuration.
+    #[cfg(target_arch = "aarch64")]
+    fn add_sbsa_gwdt(&mut self, _vm_config: &VmConfig, _cfg_args: &str) -> Result<()> {
+        bail!("sbsa-gwdt is not supported!");
+    }
+
+    /// Add vfio platform device.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Device configuration.
+    #[cfg(target_arch = "aarch64")]
+    fn add_vfio_platform_device(&mut self, _cfg_args: &str) -> Result<()> {
+        bail!("vfio-platform is not supported!");
+    }
+
+    /// Add VM generation ID device.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - vmgenid configuration.
+    fn add_vmgenid(&mut self, _cfg_args: &str) -> Result<()> {
+        bail!("vmgenid is not supported!");
+    }
+
     /// Add scream sound based on ivshmem.
     ///
     /// # Arguments
@@ -1261,6 +1757,33 @@ pub trait MachineOps {
             .with_context(|| "Failed to realize scream device")
     }
 
+    /// Add ivshmem-plain device which shares the memory of the host file with other VMs.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - ivshmem-plain configuration.
+    fn add_ivshmem_plain(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let dev_cfg = parse_ivshmem(vm_config, cfg_args)?;
+        self.create_ivshmem_plain(&dev_cfg, &bdf)
+    }
+
+    /// Create ivshmem-plain device and attach it to the PCI bus.
+    ///
+    /// # Arguments
+    ///
+    /// * `dev_cfg` - ivshmem-plain configuration.
+    /// * `bdf` - The bus and address of the device.
+    fn create_ivshmem_plain(&mut self, dev_cfg: &IvshmemConfig, bdf: &PciBdf) -> Result<()> {
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(bdf)?;
+        let ivshmem = Ivshmem::new_plain(dev_cfg, devfn, parent_bus)
+            .with_context(|| format!("Failed to create ivshmem {}", dev_cfg.id))?;
+        ivshmem
+            .realize()
+            .with_context(|| format!("Failed to realize ivshmem {}", dev_cfg.id))
+    }
+
     /// Get the corresponding device from the PCI bus based on the device id and device type name.
     ///
     /// # Arguments
@@ -1305,10 +1828,13 @@ pub trait MachineOps {
     ///
     /// * `vm_config` - VM configuration.
     /// * `usb_dev` - Usb device.
+    /// * `bus` - Id of the usb hub which the device is attached to, or the root port of
+    ///   controller is used.
     fn attach_usb_to_xhci_controller(
         &mut self,
         vm_config: &mut VmConfig,
         usb_dev: Arc<Mutex<dyn UsbDevice>>,
+        bus: Option<&str>,
     ) -> Result<()> {
         let parent_dev = self
             .get_pci_dev_by_id_and_type(vm_config, None, "nec-usb-xhci")
@@ -1318,7 +1844,7 @@ pub trait MachineOps {
             .as_any()
             .downcast_ref::<XhciPciDevice>()
             .with_context(|| "PciDevOps can not downcast to XhciPciDevice")?;
-        xhci_pci.attach_device(&(usb_dev))?;
+        xhci_pci.attach_device_to_bus(&(usb_dev), bus)?;
 
         Ok(())
     }
@@ -1359,7 +1885,7 @@ pub trait MachineOps {
         let kbd = keyboard
             .realize()
             .with_context(|| "Failed to realize usb keyboard device")?;
-        self.attach_usb_to_xhci_controller(vm_config, kbd)?;
+        self.attach_usb_to_xhci_controller(vm_config, kbd, device_cfg.bus.as_deref())?;
         Ok(())
     }
 
@@ -1376,7 +1902,41 @@ pub trait MachineOps {
             .realize()
             .with_context(|| "Failed to realize usb tablet device")?;
 
-        self.attach_usb_to_xhci_controller(vm_config, tbt)?;
+        self.attach_usb_to_xhci_controller(vm_config, tbt, device_cfg.bus.as_deref())?;
+
+        Ok(())
+    }
+
+    /// Add usb u2f key.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - U2F Key Configuration.
+    fn add_usb_u2f(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_usb_u2f(cfg_args)?;
+        let bus = device_cfg.bus.clone();
+        let u2f = UsbU2f::new(device_cfg)?
+            .realize()
+            .with_context(|| "Failed to realize usb u2f device")?;
+        self.attach_usb_to_xhci_controller(vm_config, u2f, bus.as_deref())?;
+
+        Ok(())
+    }
+
+    /// Add usb hub.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Hub Configuration.
+    fn add_usb_hub(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_usb_hub(cfg_args)?;
+        // SAFETY: id is already checked not none in parse_usb_hub().
+        let hub = UsbHub::new(device_cfg.id.unwrap(), device_cfg.ports);
+        let hub = hub
+            .realize()
+            .with_context(|| "Failed to realize usb hub device")?;
+
+        self.attach_usb_to_xhci_controller(vm_config, hub, None)?;
 
         Ok(())
     }
@@ -1392,7 +1952,7 @@ pub trait MachineOps {
         let camera = UsbCamera::new(device_cfg)?;
         let camera = camera.realize()?;
 
-        self.attach_usb_to_xhci_controller(vm_config, camera)?;
+        self.attach_usb_to_xhci_controller(vm_config, camera, None)?;
 
         Ok(())
     }
@@ -1404,12 +1964,43 @@ pub trait MachineOps {
     /// * `cfg_args` - USB Storage Configuration.
     fn add_usb_storage(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let device_cfg = parse_usb_storage(vm_config, cfg_args)?;
+        let boot_index = device_cfg.scsi_cfg.boot_index;
+        if let Some(bootindex) = boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Failed to add usb storage device for invalid bootindex")?;
+        }
+        let bus = device_cfg.bus.clone();
         let storage = UsbStorage::new(device_cfg, self.get_drive_files());
         let stg = storage
             .realize()
             .with_context(|| "Failed to realize usb storage device")?;
-
-        self.attach_usb_to_xhci_controller(vm_config, stg)?;
+        let id = stg.lock().unwrap().device_id().to_string();
+
+        self.attach_usb_to_xhci_controller(vm_config, stg, bus.as_deref())?;
+
+        if let Some(bootindex) = boot_index {
+            // Eg: OpenFirmware device path(usb storage attached to the hub in port 1):
+            // /pci@i0cf8/usb@5/hub@1/storage@2/channel@0/disk@0,0
+            //   |            |     |         |                  |
+            //   |            |     |         |       single scsi disk, fixed 0.
+            //   |            |     |   port of the hub holding the storage.
+            //   |            |   port of the controller holding the hub.
+            //   |        PCI slot holding the xhci controller.
+            //  PCI root as system bus port.
+            let parent_dev = self
+                .get_pci_dev_by_id_and_type(vm_config, None, "nec-usb-xhci")
+                .with_context(|| "Can not find parent device from pci bus")?;
+            let locked_parent_dev = parent_dev.lock().unwrap();
+            let xhci_pci = locked_parent_dev
+                .as_any()
+                .downcast_ref::<XhciPciDevice>()
+                .with_context(|| "PciDevOps can not downcast to XhciPciDevice")?;
+            if let Some(usb_path) = xhci_pci.get_usb_dev_path(&id, "storage") {
+                drop(locked_parent_dev);
+                let dev_path = format!("{}/channel@0/disk@0,0", usb_path);
+                self.add_bootindex_devices(bootindex, &dev_path, &id);
+            }
+        }
 
         Ok(())
     }
@@ -1428,7 +2019,33 @@ pub trait MachineOps {
             .realize()
             .with_context(|| "Failed to realize usb host device")?;
 
-        self.attach_usb_to_xhci_controller(vm_config, usbhost)?;
+        self.attach_usb_to_xhci_controller(vm_config, usbhost, None)?;
+
+        Ok(())
+    }
+
+    /// Add usb redir device, which is attached to the xhci controller when the remote
+    /// device is connected.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - USB Redir Configuration.
+    fn add_usb_redir(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_usb_redir(vm_config, cfg_args)?;
+        let usbredir = UsbRedir::new(device_cfg);
+
+        let parent_dev = self
+            .get_pci_dev_by_id_and_type(vm_config, None, "nec-usb-xhci")
+            .with_context(|| "Can not find parent device from pci bus")?;
+        let locked_parent_dev = parent_dev.lock().unwrap();
+        let xhci_pci = locked_parent_dev
+            .as_any()
+            .downcast_ref::<XhciPciDevice>()
+            .with_context(|| "PciDevOps can not downcast to XhciPciDevice")?;
+        usbredir
+            .realize_with_controller(&xhci_pci.xhci)
+            .with_context(|| "Failed to realize usb redir device")?;
 
         Ok(())
     }
@@ -1477,10 +2094,10 @@ pub trait MachineOps {
                     self.add_virtio_pci_scsi(vm_config, cfg_args)?;
                 }
                 "scsi-hd" => {
-                    self.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_DISK)?;
+                    self.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_DISK, false)?;
                 }
                 "scsi-cd" => {
-                    self.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_ROM)?;
+                    self.add_scsi_device(vm_config, cfg_args, SCSI_TYPE_ROM, false)?;
                 }
                 "virtio-net-device" => {
                     self.add_virtio_mmio_net(vm_config, cfg_args)?;
@@ -1509,6 +2126,27 @@ pub trait MachineOps {
                 "virtio-rng-device" | "virtio-rng-pci" => {
                     self.add_virtio_rng(vm_config, cfg_args)?;
                 }
+                "virtio-crypto-device" | "virtio-crypto-pci" => {
+                    self.add_virtio_crypto(vm_config, cfg_args)?;
+                }
+                "virtio-9p-device" | "virtio-9p-pci" => {
+                    self.add_virtio_9p(cfg_args)?;
+                }
+                "virtio-pmem-pci" => {
+                    self.add_virtio_pmem(vm_config, cfg_args)?;
+                }
+                "pc-dimm" => {
+                    self.add_pc_dimm(vm_config, cfg_args)?;
+                }
+                "ivshmem-plain" => {
+                    self.add_ivshmem_plain(vm_config, cfg_args)?;
+                }
+                "virtio-sound-pci" => {
+                    self.add_virtio_sound(cfg_args)?;
+                }
+                "virtio-iommu-pci" => {
+                    self.add_virtio_iommu(cfg_args)?;
+                }
                 "vfio-pci" => {
                     self.add_vfio_device(cfg_args)?;
                 }
@@ -1524,12 +2162,48 @@ pub trait MachineOps {
                 "nec-usb-xhci" => {
                     self.add_usb_xhci(cfg_args)?;
                 }
+                "nvme" => {
+                    self.add_nvme(vm_config, cfg_args)?;
+                }
+                "e1000e" => {
+                    self.add_e1000e(vm_config, cfg_args)?;
+                }
+                "ahci" => {
+                    self.add_ahci(cfg_args)?;
+                }
+                "ide-hd" => {
+                    self.add_ide_device(vm_config, cfg_args, false)?;
+                }
+                "ide-cd" => {
+                    self.add_ide_device(vm_config, cfg_args, true)?;
+                }
+                #[cfg(target_arch = "x86_64")]
+                "i6300esb" => {
+                    self.add_i6300esb(vm_config, cfg_args)?;
+                }
+                #[cfg(target_arch = "aarch64")]
+                "sbsa-gwdt" => {
+                    self.add_sbsa_gwdt(vm_config, cfg_args)?;
+                }
+                #[cfg(target_arch = "aarch64")]
+                "vfio-platform" => {
+                    self.add_vfio_platform_device(cfg_args)?;
+                }
+                "vmgenid" => {
+                    self.add_vmgenid(cfg_args)?;
+                }
                 "usb-kbd" => {
                     self.add_usb_keyboard(vm_config, cfg_args)?;
                 }
                 "usb-tablet" => {
                     self.add_usb_tablet(vm_config, cfg_args)?;
                 }
+                "usb-hub" => {
+                    self.add_usb_hub(vm_config, cfg_args)?;
+                }
+                "usb-u2f" => {
+                    self.add_usb_u2f(vm_config, cfg_args)?;
+                }
                 #[cfg(feature = "usb_camera")]
                 "usb-camera" => {
                     self.add_usb_camera(vm_config, cfg_args)?;
@@ -1541,6 +2215,9 @@ pub trait MachineOps {
                 "usb-host" => {
                     self.add_usb_host(vm_config, cfg_args)?;
                 }
+                "usb-redir" => {
+                    self.add_usb_redir(vm_config, cfg_args)?;
+                }
                 #[cfg(feature = "virtio_gpu")]
                 "virtio-gpu-pci" => {
                     self.add_virtio_pci_gpu(cfg_args)?;
@@ -1563,6 +2240,11 @@ pub trait MachineOps {
             }
         }
 
+        for filter in cloned_vm_config.object.netfilter_object.values() {
+            virtio::add_net_filter(filter, &cloned_vm_config.chardev)
+                .with_context(|| format!("Failed to add net filter {}", filter.id))?;
+        }
+
         Ok(())
     }
 
@@ -1757,9 +2439,23 @@ pub trait MachineOps {
             }
         }
 
+        // The guest time stops while the VM is paused.
+        #[cfg(target_arch = "x86_64")]
+        KVM_FDS.load().pause_clock()?;
+
         #[cfg(target_arch = "aarch64")]
         // SAFETY: ARM architecture must have interrupt controllers in user mode.
-        irq_chip.as_ref().unwrap().stop();
+        if let Err(e) = irq_chip.as_ref().unwrap().stop() {
+            // Roll back to running, the VM can't be paused without the interrupt state saved.
+            for (cpu_index, cpu) in cpus.iter().enumerate() {
+                if let Err(e) = cpu.resume() {
+                    log::error!("Failed to resume vcpu{}, {:?}", cpu_index, e);
+                }
+            }
+            self.active_drive_files()?;
+            EventLoop::get_ctx(None).unwrap().enable_clock();
+            return Err(e);
+        }
 
         *vm_state = KvmVmState::Paused;
 
@@ -1777,6 +2473,9 @@ pub trait MachineOps {
 
         self.active_drive_files()?;
 
+        #[cfg(target_arch = "x86_64")]
+        KVM_FDS.load().resume_clock()?;
+
         for (cpu_index, cpu) in cpus.iter().enumerate() {
             if let Err(e) = cpu.resume() {
                 self.deactive_drive_files()?;
@@ -1843,6 +2542,19 @@ pub trait MachineOps {
             (Paused, Running) => self
                 .vm_resume(cpus, vm_state)
                 .with_context(|| "Failed to resume vm.")?,
+            (Running, Suspended) => {
+                self.vm_pause(
+                    cpus,
+                    #[cfg(target_arch = "aarch64")]
+                    irq_chip,
+                    vm_state,
+                )
+                .with_context(|| "Failed to suspend vm.")?;
+                *vm_state = Suspended;
+            }
+            (Suspended, Running) => self
+                .vm_resume(cpus, vm_state)
+                .with_context(|| "Failed to wake up vm.")?,
             (_, Shutdown) => self
                 .vm_destroy(cpus, vm_state)
                 .with_context(|| "Failed to destroy vm.")?,
@@ -1887,6 +2599,35 @@ pub fn vm_run(
 }
 
 /// Start incoming migration from destination.
+/// Create the vfio platform device and attach it to system bus, which is shared by the
+/// aarch64 machines.
+#[cfg(target_arch = "aarch64")]
+fn create_vfio_platform_device(
+    sysbus: &mut SysBus,
+    sys_mem: &Arc<AddressSpace>,
+    cfg_args: &str,
+) -> Result<()> {
+    let device_cfg = parse_vfio_platform(cfg_args)?;
+    let path = if !device_cfg.host.is_empty() {
+        format!("/sys/bus/platform/devices/{}", device_cfg.host)
+    } else {
+        device_cfg.sysfsdev.clone()
+    };
+    if !Path::new(&path).exists() {
+        bail!("No host platform device {} is found", path);
+    }
+    let dtb = std::fs::read(&device_cfg.dtb)
+        .with_context(|| format!("Failed to read dtb {}", device_cfg.dtb))?;
+    let template = FdtNodeTemplate::from_dtb(&dtb)?;
+
+    let device = VfioDevice::new(Path::new(&path), sys_mem)
+        .with_context(|| "Failed to create vfio device.")?;
+    VfioPlatformDevice::new(device, device_cfg.id, template, sys_mem.clone())
+        .realize(sysbus)
+        .with_context(|| "Failed to realize vfio-platform device.")?;
+    Ok(())
+}
+
 fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Result<()> {
     let (mode, path) = vm.lock().unwrap().get_migrate_info();
     match mode {
@@ -1941,6 +2682,87 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
     Ok(())
 }
 
+/// Get the x86 specific information of vCPU for `query-cpus`.
+#[cfg(target_arch = "x86_64")]
+fn cpu_info_x86(cpu: &CPU) -> CpuInfoX86 {
+    let arch_cpu = cpu.arch().lock().unwrap();
+    CpuInfoX86 {
+        model: arch_cpu.model_name().to_string(),
+        features: arch_cpu.feature_names().unwrap_or_else(|e| {
+            warn!("Failed to get features of CPU {}: {:?}", cpu.id(), e);
+            Vec::new()
+        }),
+    }
+}
+
+/// Collect the statistics of the online vCPUs for `query-stats`. The `vm` target
+/// returns the statistics summed over all vCPUs.
+fn query_stats(cpus: &[Arc<CPU>], cpu_topo: &CpuTopology, args: QueryStatsArgument) -> Response {
+    if args.target == StatsTarget::Vm && args.vcpus.is_some() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(
+                "'vcpus' is only valid for 'vcpu' target".to_string(),
+            ),
+            None,
+        );
+    }
+    let names = match &args.providers {
+        Some(providers) => match providers.iter().find(|p| p.provider == StatsProvider::Kvm) {
+            Some(request) => request.names.clone(),
+            None => return Response::create_response(serde_json::json!([]), None),
+        },
+        None => None,
+    };
+
+    let mut results = Vec::new();
+    let mut vm_stats: Vec<Stats> = Vec::new();
+    for (index, cpu) in cpus.iter().enumerate() {
+        if cpu_topo.get_mask(index) != 1 {
+            continue;
+        }
+        let qom_path = format!("/machine/unattached/device[{}]", index);
+        if args
+            .vcpus
+            .as_ref()
+            .is_some_and(|vcpus| !vcpus.contains(&qom_path))
+        {
+            continue;
+        }
+        let stats: Vec<Stats> = cpu
+            .stats()
+            .into_iter()
+            .filter(|stat| {
+                names
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&stat.name))
+            })
+            .collect();
+        match args.target {
+            StatsTarget::Vcpu => results.push(StatsResult {
+                provider: StatsProvider::Kvm,
+                qom_path: Some(qom_path),
+                stats,
+            }),
+            StatsTarget::Vm => {
+                for stat in stats {
+                    match vm_stats.iter_mut().find(|s| s.name == stat.name) {
+                        Some(vm_stat) => vm_stat.value += stat.value,
+                        None => vm_stats.push(stat),
+                    }
+                }
+            }
+        }
+    }
+    if args.target == StatsTarget::Vm {
+        results.push(StatsResult {
+            provider: StatsProvider::Kvm,
+            qom_path: None,
+            stats: vm_stats,
+        });
+    }
+    Response::create_response(serde_json::to_value(results).unwrap(), None)
+}
+
 fn coverage_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     syscall_allow_list.extend(vec![
         BpfRule::new(libc::SYS_fcntl),
@@ -48,34 +48,47 @@ use anyhow::{anyhow, bail, Context, Result};
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use log::{error, info};
 
+#[cfg(target_arch = "aarch64")]
+use super::create_vfio_platform_device;
 use super::Result as MachineResult;
 use super::{error::MachineError, MachineOps};
+use crate::gdbstub::start_gdbstub;
+use crate::halt_poll::{init_halt_poll, query_halt_poll, set_halt_poll};
+use crate::query_stats;
+use crate::sandbox::enter_sandbox;
 #[cfg(target_arch = "x86_64")]
-use crate::vm_state;
+use crate::{cpu_info_x86, vm_state};
 use address_space::{AddressSpace, GuestAddress, Region};
+#[cfg(target_arch = "aarch64")]
+use boot_loader::load_dtb;
 use boot_loader::{load_linux, BootLoaderConfig};
+use chardev_backend::chardev::change_chardev;
 #[cfg(target_arch = "aarch64")]
 use cpu::CPUFeatures;
 #[cfg(target_arch = "aarch64")]
 use cpu::PMU_INTR;
 use cpu::{CPUBootConfig, CPUTopology, CpuLifecycleState, CpuTopology, CPU};
+use devices::interrupt_stats::query_interrupt_stats;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::PL031;
 #[cfg(target_arch = "x86_64")]
 use devices::legacy::SERIAL_ADDR;
 use devices::legacy::{FwCfgOps, Serial};
-use devices::sysbus::{SysBus, IRQ_BASE, IRQ_MAX};
+use devices::sysbus::{SysBus, SysBusDevOps, IRQ_BASE, IRQ_MAX};
 #[cfg(target_arch = "aarch64")]
 use devices::sysbus::{SysBusDevType, SysRes};
+use devices::Device;
 #[cfg(target_arch = "aarch64")]
 use devices::{ICGICConfig, ICGICv2Config, ICGICv3Config, InterruptController, GIC_IRQ_MAX};
 #[cfg(target_arch = "x86_64")]
 use hypervisor::kvm::KVM_FDS;
+use machine_manager::balloon_policy::{query_balloon_policy, set_balloon_policy};
 use machine_manager::config::{
-    parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BootSource, ConfigCheck, DiskFormat,
-    DriveFile, Incoming, MigrateMode, NetworkInterfaceConfig, NumaNodes, SerialConfig, VmConfig,
-    DEFAULT_VIRTQUEUE_SIZE,
+    get_chardev_change_config, parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BootSource,
+    ConfigCheck, DiskFormat, DriveFile, Incoming, MigrateMode, NetworkInterfaceConfig, NumaNodes,
+    SerialConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
 };
+use machine_manager::cpu_throttle::cpu_throttle_set;
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
 use machine_manager::machine::{
@@ -88,15 +101,18 @@ use machine_manager::qmp::{
 use mem_layout::{LayoutEntryType, MEM_LAYOUT};
 use migration::{MigrationManager, MigrationStatus};
 use syscall::syscall_whitelist;
-use util::aio::WriteZeroesState;
+use util::aio::{aio_select, WriteZeroesState};
 #[cfg(target_arch = "aarch64")]
 use util::device_tree::{self, CompileFDT, FdtBuilder};
 use util::{
     loop_context::EventLoopManager, num_ops::str_to_usize, seccomp::BpfRule, set_termi_canon_mode,
 };
+#[cfg(target_arch = "aarch64")]
+use vfio::VfioPlatformDevice;
 use virtio::{
-    create_tap, qmp_balloon, qmp_query_balloon, Block, BlockState, Net, VhostKern, VhostUser,
-    VirtioDevice, VirtioMmioDevice, VirtioMmioState, VirtioNetState,
+    create_tap, qmp_balloon, qmp_balloon_cancel, qmp_balloon_stats_interval, qmp_query_balloon,
+    qmp_query_balloon_stats, Block, BlockState, Net, VhostKern, VhostUser, VirtioDevice,
+    VirtioMmioDevice, VirtioMmioState, VirtioNetState,
 };
 
 // The replaceable block device maximum count.
@@ -178,6 +194,8 @@ pub struct LightMachine {
     sysbus: SysBus,
     // All replaceable device information.
     replaceable_info: MmioReplaceableInfo,
+    // Virtio-mmio devices which are not replaceable.
+    mmio_devices: Vec<Arc<Mutex<VirtioMmioDevice>>>,
     // VM running state.
     vm_state: Arc<(Mutex<KvmVmState>, Condvar)>,
     // Vm boot_source config.
@@ -243,6 +261,7 @@ impl LightMachine {
             sys_io,
             sysbus,
             replaceable_info: MmioReplaceableInfo::new(),
+            mmio_devices: Vec::new(),
             boot_source: Arc::new(Mutex::new(vm_config.clone().boot_source)),
             vm_state,
             vm_config: Arc::new(Mutex::new(vm_config.clone())),
@@ -308,9 +327,9 @@ impl LightMachine {
             );
         }
 
-        let mut region_base = self.sysbus.min_free_base;
         let region_size = MEM_LAYOUT[LayoutEntryType::Mmio as usize].1;
         for (id, dev) in rpl_devs.into_iter().enumerate() {
+            let region_base = self.sysbus.alloc_mmio_region(region_size, region_size)?;
             self.replaceable_info
                 .devices
                 .lock()
@@ -334,9 +353,7 @@ impl LightMachine {
                 .with_context(|| MicroVmError::RlzVirtioMmioErr)?,
                 &id.to_string(),
             );
-            region_base += region_size;
         }
-        self.sysbus.min_free_base = region_base;
         Ok(())
     }
 
@@ -485,6 +502,99 @@ impl LightMachine {
         Ok(id.to_string())
     }
 
+    fn get_replaceable_config(&self, id: &str) -> Result<Arc<dyn ConfigCheck>> {
+        self.replaceable_info
+            .configs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|config| config.id == id)
+            .map(|config| config.dev_config.clone())
+            .with_context(|| "Failed to find device configuration.")
+    }
+
+    fn add_mmio_device(&mut self, id: &str, driver: &str) -> Result<()> {
+        let is_used = self
+            .mmio_devices
+            .iter()
+            .any(|dev| dev.lock().unwrap().name() == id)
+            || self
+                .replaceable_info
+                .devices
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|info| info.used && info.id == id);
+        if is_used {
+            bail!("Device {} already exists", id);
+        }
+
+        // The backend added by `blockdev-add` or `netdev_add` has the same id as the device.
+        let dev_config = self.get_replaceable_config(id)?;
+        let cfg_any = dev_config.as_any();
+        let device: Arc<Mutex<dyn VirtioDevice>> = match driver {
+            "virtio-blk-device" => {
+                let blk_cfg = cfg_any
+                    .downcast_ref::<BlkDevConfig>()
+                    .with_context(|| MicroVmError::DevTypeErr("blk".to_string()))?;
+                Arc::new(Mutex::new(Block::new(
+                    blk_cfg.clone(),
+                    self.get_drive_files(),
+                )))
+            }
+            "virtio-net-device" => {
+                let net_cfg = cfg_any
+                    .downcast_ref::<NetworkInterfaceConfig>()
+                    .with_context(|| MicroVmError::DevTypeErr("net".to_string()))?;
+                Arc::new(Mutex::new(Net::new(net_cfg.clone())))
+            }
+            _ => bail!("Unsupported mmio device type {}.", driver),
+        };
+
+        let mut virtio_mmio = VirtioMmioDevice::new(&self.sys_mem, device);
+        virtio_mmio.set_id(id, true);
+        let dev = self.realize_virtio_mmio_device(virtio_mmio)?;
+        // The guest can't probe the device by itself after boot, the device should be
+        // registered through the `device` parameter of the virtio_mmio module.
+        let res = dev.lock().unwrap().sysbusdev_base().res;
+        info!(
+            "Hot-plugged virtio-mmio device {}: {}@0x{:08x}:{}",
+            id, res.region_size, res.region_base, res.irq
+        );
+        Ok(())
+    }
+
+    fn del_mmio_device(&mut self, id: &str) -> Result<String> {
+        let index = self
+            .mmio_devices
+            .iter()
+            .position(|dev| dev.lock().unwrap().name() == id)
+            .with_context(|| format!("Device {} not found", id))?;
+        let dev = self.mmio_devices[index].clone();
+        dev.lock()
+            .unwrap()
+            .unrealize()
+            .with_context(|| format!("Failed to unrealize device {}", id))?;
+        self.sysbus
+            .detach_device(&dev)
+            .with_context(|| format!("Failed to detach device {} from system bus", id))?;
+        self.mmio_devices.remove(index);
+        self.vm_config
+            .lock()
+            .unwrap()
+            .del_device_by_id(id.to_string());
+
+        // Release the backend of the hot-plugged block or net device.
+        let mut configs_lock = self.replaceable_info.configs.lock().unwrap();
+        if let Some(index) = configs_lock.iter().position(|config| config.id == id) {
+            let config = configs_lock.remove(index);
+            if let Some(blkconf) = config.dev_config.as_any().downcast_ref::<BlkDevConfig>() {
+                self.unregister_drive_file(&blkconf.path_on_host)?;
+            }
+        }
+        Ok(id.to_string())
+    }
+
     /// Must be called after the CPUs have been realized and GIC has been created.
     #[cfg(target_arch = "aarch64")]
     fn cpu_post_init(&self, vcpu_cfg: &Option<CPUFeatures>) -> Result<()> {
@@ -546,6 +656,9 @@ impl MachineOps for LightMachine {
 
     #[cfg(target_arch = "x86_64")]
     fn init_interrupt_controller(&mut self, _vcpu_count: u64) -> MachineResult<()> {
+        if self.vm_config.lock().unwrap().machine_config.split_irqchip {
+            bail!("Split irqchip is not supported by microvm");
+        }
         KVM_FDS
             .load()
             .vm_fd
@@ -659,18 +772,23 @@ impl MachineOps for LightMachine {
         &mut self,
         dev: VirtioMmioDevice,
     ) -> MachineResult<Arc<Mutex<VirtioMmioDevice>>> {
-        let region_base = self.sysbus.min_free_base;
         let region_size = MEM_LAYOUT[LayoutEntryType::Mmio as usize].1;
-        let realized_virtio_mmio_device = VirtioMmioDevice::realize(
+        let region_base = self.sysbus.alloc_mmio_region(region_size, region_size)?;
+        let realized_virtio_mmio_device = match VirtioMmioDevice::realize(
             dev,
             &mut self.sysbus,
             region_base,
             region_size,
             #[cfg(target_arch = "x86_64")]
             &self.boot_source,
-        )
-        .with_context(|| MicroVmError::RlzVirtioMmioErr)?;
-        self.sysbus.min_free_base += region_size;
+        ) {
+            Ok(dev) => dev,
+            Err(e) => {
+                self.sysbus.free_mmio_region(region_base);
+                return Err(e).with_context(|| MicroVmError::RlzVirtioMmioErr);
+            }
+        };
+        self.mmio_devices.push(realized_virtio_mmio_device.clone());
         Ok(realized_virtio_mmio_device)
     }
 
@@ -728,6 +846,11 @@ impl MachineOps for LightMachine {
         Ok(())
     }
 
+    #[cfg(target_arch = "aarch64")]
+    fn add_vfio_platform_device(&mut self, cfg_args: &str) -> MachineResult<()> {
+        create_vfio_platform_device(&mut self.sysbus, &self.sys_mem, cfg_args)
+    }
+
     fn add_serial_device(&mut self, config: &SerialConfig) -> MachineResult<()> {
         #[cfg(target_arch = "x86_64")]
         let region_base: u64 = SERIAL_ADDR;
@@ -760,6 +883,11 @@ impl MachineOps for LightMachine {
         if device_cfg.vhost_type.is_some() {
             let device = if device_cfg.vhost_type == Some(String::from("vhost-kernel")) {
                 let net = Arc::new(Mutex::new(VhostKern::Net::new(&device_cfg, &self.sys_mem)));
+                MigrationManager::register_device_instance(
+                    VhostKern::VhostNetState::descriptor(),
+                    net.clone(),
+                    &device_cfg.id,
+                );
                 VirtioMmioDevice::new(&self.sys_mem, net)
             } else {
                 let net = Arc::new(Mutex::new(VhostUser::Net::new(&device_cfg, &self.sys_mem)));
@@ -827,6 +955,7 @@ impl MachineOps for LightMachine {
             &locked_vm.sys_mem,
             vm_config.machine_config.nr_cpus,
         )?;
+        init_halt_poll(&vm_config.machine_config)?;
 
         let migrate_info = locked_vm.get_migrate_info();
 
@@ -842,10 +971,13 @@ impl MachineOps for LightMachine {
             locked_vm.add_devices(vm_config)?;
             trace_replaceable_info(&locked_vm.replaceable_info);
 
-            let boot_config = if migrate_info.0 == MigrateMode::Unknown {
-                Some(locked_vm.load_boot_source(None)?)
+            let (boot_config, cpu_config) = if migrate_info.0 == MigrateMode::Unknown {
+                (
+                    Some(locked_vm.load_boot_source(None)?),
+                    Some(locked_vm.load_cpu_features(vm_config)?),
+                )
             } else {
-                None
+                (None, None)
             };
 
             // vCPUs init
@@ -854,6 +986,7 @@ impl MachineOps for LightMachine {
                 vm_config.machine_config.nr_cpus,
                 &topology,
                 &boot_config,
+                &cpu_config,
             )?);
         }
 
@@ -893,7 +1026,11 @@ impl MachineOps for LightMachine {
                 locked_vm
                     .generate_fdt_node(&mut fdt_helper)
                     .with_context(|| MachineError::GenFdtErr)?;
-                let fdt_vec = fdt_helper.finish()?;
+                let mut fdt_vec = fdt_helper.finish()?;
+                let dtb = locked_vm.boot_source.lock().unwrap().dtb.clone();
+                if let Some(dtb) = dtb {
+                    fdt_vec = load_dtb(&fdt_vec, &dtb)?;
+                }
                 locked_vm
                     .sys_mem
                     .write(
@@ -905,6 +1042,19 @@ impl MachineOps for LightMachine {
             }
         }
 
+        if let Some(addr) = vm_config.gdb.as_ref() {
+            start_gdbstub(
+                addr,
+                vm.clone(),
+                locked_vm.vm_state.clone(),
+                locked_vm.cpus.clone(),
+                locked_vm.sys_mem.clone(),
+            )
+            .with_context(|| "Failed to start gdbstub")?;
+        }
+
+        enter_sandbox(vm_config).with_context(|| "Failed to enter sandbox")?;
+
         MigrationManager::register_vm_instance(vm.clone());
         #[cfg(target_arch = "x86_64")]
         MigrationManager::register_kvm_instance(
@@ -982,6 +1132,10 @@ impl MachineLifecycle for LightMachine {
         }
         true
     }
+
+    fn shared_vm_state(&self) -> Option<Arc<(Mutex<KvmVmState>, Condvar)>> {
+        Some(self.vm_state.clone())
+    }
 }
 
 impl MachineAddressInterface for LightMachine {
@@ -1063,7 +1217,7 @@ impl DeviceInterface for LightMachine {
                 {
                     let cpu_info = qmp_schema::CpuInfo::x86 {
                         common: cpu_common,
-                        x86: qmp_schema::CpuInfoX86 {},
+                        x86: cpu_info_x86(&self.cpus[cpu_index as usize]),
                     };
                     cpu_vec.push(serde_json::to_value(cpu_info).unwrap());
                 }
@@ -1126,8 +1280,7 @@ impl DeviceInterface for LightMachine {
     }
 
     fn query_balloon(&self) -> Response {
-        if let Some(actual) = qmp_query_balloon() {
-            let ret = qmp_schema::BalloonInfo { actual };
+        if let Some(ret) = qmp_query_balloon() {
             return Response::create_response(serde_json::to_value(ret).unwrap(), None);
         }
         Response::create_error_response(
@@ -1138,6 +1291,141 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn balloon_cancel(&self) -> Response {
+        match qmp_balloon_cancel() {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_balloon_stats_interval(&self, interval: u32) -> Response {
+        match qmp_balloon_stats_interval(interval) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_balloon_stats(&self) -> Response {
+        match qmp_query_balloon_stats() {
+            Ok(stats) => Response::create_response(serde_json::to_value(stats).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_balloon_policy(&self, args: qmp_schema::BalloonPolicyArgument) -> Response {
+        match set_balloon_policy(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_balloon_policy(&self) -> Response {
+        match query_balloon_policy() {
+            Ok(info) => Response::create_response(serde_json::to_value(info).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn reclaim_guest_memory(&mut self, args: qmp_schema::ReclaimGuestMemoryArgument) -> Response {
+        match self.reclaim_guest_ram(args.mode.as_deref()) {
+            Ok(size) => {
+                let info = qmp_schema::ReclaimGuestMemoryInfo { size };
+                Response::create_response(serde_json::to_value(info).unwrap(), None)
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_halt_poll(&self, args: qmp_schema::HaltPollArgument) -> Response {
+        match set_halt_poll(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_halt_poll(&self) -> Response {
+        Response::create_response(serde_json::to_value(query_halt_poll()).unwrap(), None)
+    }
+
+    fn cpu_throttle_set(&self, args: qmp_schema::CpuThrottleArgument) -> Response {
+        match cpu_throttle_set(args.percentage) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_stats(&self, args: qmp_schema::QueryStatsArgument) -> Response {
+        query_stats(&self.cpus, &self.cpu_topo, args)
+    }
+
+    fn query_interrupts(&self) -> Response {
+        let interrupts: Vec<qmp_schema::InterruptInfo> = query_interrupt_stats()
+            .into_iter()
+            .map(|stat| qmp_schema::InterruptInfo {
+                device: stat.device,
+                int_type: stat.int_type,
+                vector: stat.vector,
+                count: stat.count,
+            })
+            .collect();
+        Response::create_response(serde_json::to_value(interrupts).unwrap(), None)
+    }
+
+    fn query_vm_config(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let locked_config = vm_config.lock().unwrap();
+        match serde_json::to_value(&*locked_config) {
+            Ok(value) => Response::create_response(value, None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn query_uuid(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let uuid = match vm_config.lock().unwrap().uuid.as_ref() {
+            Some(uuid) => uuid.to_string(),
+            None => "00000000-0000-0000-0000-000000000000".to_string(),
+        };
+        let uuid_info = qmp_schema::UuidInfo { uuid };
+        Response::create_response(serde_json::to_value(uuid_info).unwrap(), None)
+    }
+
+    fn query_name(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let guest_name = &vm_config.lock().unwrap().guest_name;
+        let name_info = qmp_schema::NameInfo {
+            name: (!guest_name.is_empty()).then(|| guest_name.clone()),
+        };
+        Response::create_response(serde_json::to_value(name_info).unwrap(), None)
+    }
+
     fn query_mem(&self) -> Response {
         self.mem_show();
         Response::create_empty_response()
@@ -1172,7 +1460,13 @@ impl DeviceInterface for LightMachine {
             slot = lun + 1;
         }
 
-        match self.add_replaceable_device(&args.id, &args.driver, slot) {
+        let result = match args.driver.as_str() {
+            "virtio-blk-device" | "virtio-net-device" => {
+                self.add_mmio_device(&args.id, &args.driver)
+            }
+            _ => self.add_replaceable_device(&args.id, &args.driver, slot),
+        };
+        match result {
             Ok(()) => Response::create_empty_response(),
             Err(ref e) => {
                 error!("{:?}", e);
@@ -1186,7 +1480,17 @@ impl DeviceInterface for LightMachine {
     }
 
     fn device_del(&mut self, device_id: String) -> Response {
-        match self.del_replaceable_device(&device_id) {
+        let is_mmio_device = !device_id.is_empty()
+            && self
+                .mmio_devices
+                .iter()
+                .any(|dev| dev.lock().unwrap().name() == device_id);
+        let result = if is_mmio_device {
+            self.del_mmio_device(&device_id)
+        } else {
+            self.del_replaceable_device(&device_id)
+        };
+        match result {
             Ok(path) => {
                 let block_del_event = qmp_schema::DeviceDeleted {
                     device: Some(device_id),
@@ -1225,13 +1529,21 @@ impl DeviceInterface for LightMachine {
             boot_index: None,
             chardev: None,
             socket_path: None,
-            aio: args.file.aio,
+            aio: args
+                .file
+                .aio
+                .unwrap_or_else(|| aio_select(&args.file.filename, direct)),
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
             discard: false,
             write_zeroes: WriteZeroesState::Off,
             format: DiskFormat::Raw,
             l2_cache_size: None,
             refcount_cache_size: None,
+            shard_iothreads: Vec::new(),
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
+            media: "disk".to_string(),
         };
         if let Err(e) = config.check() {
             error!("{:?}", e);
@@ -1273,6 +1585,7 @@ impl DeviceInterface for LightMachine {
     fn netdev_add(&mut self, args: Box<qmp_schema::NetDevAddArgument>) -> Response {
         let mut config = NetworkInterfaceConfig {
             id: args.id.clone(),
+            netdev: args.id.clone(),
             host_dev_name: "".to_string(),
             mac: None,
             tap_fds: None,
@@ -1281,9 +1594,24 @@ impl DeviceInterface for LightMachine {
             iothread: None,
             queues: 2,
             mq: false,
+            sndbuf: args.sndbuf,
+            poll_us: args.poll_us.unwrap_or_default(),
+            zerocopy: args.zerocopy.unwrap_or_default(),
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            romfile: None,
+            boot_index: None,
+            max_frames: 0,
+            max_usecs: 0,
         };
+        // The busyloop and zero-copy options are rejected here as vhost-net is not supported.
+        if let Err(e) = config.check() {
+            error!("{:?}", e);
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
 
         if let Some(fds) = args.fds {
             let netdev_fd = if fds.contains(':') {
@@ -1316,7 +1644,7 @@ impl DeviceInterface for LightMachine {
             }
         } else if let Some(if_name) = args.if_name {
             config.host_dev_name = if_name.clone();
-            if create_tap(None, Some(&if_name), 1).is_err() {
+            if create_tap(None, Some(&if_name), 1, None).is_err() {
                 return Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(
                         "Tap device already in use".to_string(),
@@ -1363,6 +1691,18 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn chardev_change(&mut self, args: qmp_schema::CharDevChangeArgument) -> Response {
+        let result = get_chardev_change_config(args)
+            .and_then(|config| change_chardev(&config.id, config.backend));
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
     fn cameradev_add(&mut self, _args: qmp_schema::CameraDevAddArgument) -> Response {
         Response::create_error_response(
             qmp_schema::QmpErrorClass::GenericError(
@@ -1678,6 +2018,11 @@ impl CompileFDTHelper for LightMachine {
                 SysBusDevType::Serial => generate_serial_device_node(fdt, &sys_res)?,
                 SysBusDevType::Rtc => generate_rtc_device_node(fdt, &sys_res)?,
                 SysBusDevType::VirtioMmio => generate_virtio_devices_node(fdt, &sys_res)?,
+                SysBusDevType::VfioPlatform => {
+                    if let Some(dev) = locked_dev.as_any().downcast_ref::<VfioPlatformDevice>() {
+                        dev.generate_fdt_node(fdt)?;
+                    }
+                }
                 _ => (),
             }
         }
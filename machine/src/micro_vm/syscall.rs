@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use block_backend::zoned::{BLKCLOSEZONE, BLKFINISHZONE, BLKOPENZONE, BLKREPORTZONE, BLKRESETZONE};
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
 use util::tap::{TUNGETFEATURES, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ};
@@ -42,10 +43,10 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 53 syscalls
-/// * x86_64-unknown-musl: 52 syscalls
-/// * aarch64-unknown-gnu: 51 syscalls
-/// * aarch64-unknown-musl: 51 syscalls
+/// * x86_64-unknown-gnu: 54 syscalls
+/// * x86_64-unknown-musl: 53 syscalls
+/// * aarch64-unknown-gnu: 52 syscalls
+/// * aarch64-unknown-musl: 52 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -89,6 +90,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_mmap),
         BpfRule::new(libc::SYS_munmap),
         BpfRule::new(libc::SYS_accept4),
+        BpfRule::new(libc::SYS_sendto),
         BpfRule::new(libc::SYS_lseek),
         futex_rule(),
         BpfRule::new(libc::SYS_exit),
@@ -130,6 +132,36 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_ppoll),
         BpfRule::new(libc::SYS_connect),
         madvise_rule(),
+        // Syscalls of virtio-9p to serve the shared directory.
+        BpfRule::new(libc::SYS_renameat2),
+        #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_unlinkat),
+        BpfRule::new(libc::SYS_linkat),
+        BpfRule::new(libc::SYS_symlinkat),
+        BpfRule::new(libc::SYS_mknodat),
+        BpfRule::new(libc::SYS_fchownat),
+        BpfRule::new(libc::SYS_fchmodat),
+        BpfRule::new(libc::SYS_utimensat),
+        BpfRule::new(libc::SYS_statfs),
+        BpfRule::new(libc::SYS_lgetxattr),
+        BpfRule::new(libc::SYS_lsetxattr),
+        BpfRule::new(libc::SYS_fsync),
+        BpfRule::new(libc::SYS_ftruncate),
+        BpfRule::new(libc::SYS_getdents64),
+        BpfRule::new(libc::SYS_geteuid),
+        BpfRule::new(libc::SYS_getegid),
+        #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_rename),
+        #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_rmdir),
+        #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_link),
+        #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_symlink),
+        #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_lchown),
+        #[cfg(target_arch = "aarch64")]
+        BpfRule::new(libc::SYS_readlinkat),
     ]
 }
 
@@ -160,9 +192,16 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETQUEUE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKREPORTZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKRESETZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKOPENZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKCLOSEZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKFINISHZONE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_API_VERSION() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MP_STATE() as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_VCPU_EVENTS() as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_VCPU_EVENTS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GUEST_DEBUG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_ENABLE_CAP() as u32);
     ioctl_arch_allow_list(bpf_rule)
 }
 
@@ -171,6 +210,7 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
     bpf_rule
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_PIT2() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_CLOCK() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_CLOCK() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_IRQCHIP() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REGS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_SREGS() as u32)
@@ -180,6 +220,8 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_XCRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_LAPIC() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MSRS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_REGS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_TRANSLATE() as u32)
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -188,6 +230,7 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_ONE_REG() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DEVICE_ATTR() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REG_LIST() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_ONE_REG() as u32)
 }
 
 fn madvise_rule() -> BpfRule {
@@ -197,13 +240,17 @@ fn madvise_rule() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_COLD as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_PAGEOUT as u32);
     #[cfg(not(target_env = "musl"))]
     return BpfRule::new(libc::SYS_madvise)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_COLD as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_PAGEOUT as u32);
 }
 
 fn futex_rule() -> BpfRule {
@@ -0,0 +1,155 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Sandbox of the VM process besides seccomp.
+//!
+//! After all devices are realized and their files are opened, the process moves into
+//! new namespaces, changes its root to an empty directory and drops the privileges of
+//! root by switching to an unprivileged user.
+
+use std::ffi::CString;
+use std::io::Error;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use machine_manager::config::{SandboxConfig, VmConfig};
+
+/// Enter the sandbox configured by `-run-with`, it must be called after all the files
+/// used by devices are opened.
+pub fn enter_sandbox(vm_config: &VmConfig) -> Result<()> {
+    let config = &vm_config.sandbox;
+    if !config.enabled() {
+        return Ok(());
+    }
+
+    // The user database can't be read after changing root, resolve the ids first.
+    let ids = resolve_ids(config)?;
+
+    if config.unshare_net || config.unshare_mount {
+        // Namespaces are switched only for the calling thread, the iothreads which have
+        // been created would be left outside.
+        if vm_config.iothreads.is_some() {
+            bail!("Namespaces of sandbox can't be used with iothreads");
+        }
+        unshare_namespaces(config.unshare_net, config.unshare_mount)?;
+    }
+    if let Some(dir) = &config.chroot {
+        change_root(dir)?;
+    }
+    if let Some((uid, gid)) = ids {
+        drop_privileges(uid, gid)?;
+    }
+
+    info!("Entered sandbox {:?}", config);
+    Ok(())
+}
+
+/// Get the uid and gid to switch to. The current uid is kept if only group is given.
+fn resolve_ids(config: &SandboxConfig) -> Result<Option<(u32, u32)>> {
+    let mut ids = None;
+    if let Some(user) = &config.user {
+        let name = CString::new(user.as_str())?;
+        // SAFETY: The name is a valid C string, and the returned entry is copied at once.
+        let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+        if passwd.is_null() {
+            bail!("User {} is not found", user);
+        }
+        // SAFETY: The entry is checked to be non-null.
+        ids = Some(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) });
+    }
+    if let Some(group) = &config.group {
+        let name = CString::new(group.as_str())?;
+        // SAFETY: The name is a valid C string, and the returned entry is copied at once.
+        let grp = unsafe { libc::getgrnam(name.as_ptr()) };
+        if grp.is_null() {
+            bail!("Group {} is not found", group);
+        }
+        // SAFETY: The entry is checked to be non-null.
+        let gid = unsafe { (*grp).gr_gid };
+        // SAFETY: Getting the real uid is always successful.
+        let uid = ids.map_or_else(|| unsafe { libc::getuid() }, |(uid, _)| uid);
+        ids = Some((uid, gid));
+    }
+    Ok(ids)
+}
+
+fn unshare_namespaces(net: bool, mount: bool) -> Result<()> {
+    let mut flags = 0;
+    if net {
+        flags |= libc::CLONE_NEWNET;
+    }
+    if mount {
+        flags |= libc::CLONE_NEWNS;
+    }
+    // SAFETY: The flags are valid.
+    if unsafe { libc::unshare(flags) } != 0 {
+        return Err(Error::last_os_error()).with_context(|| "Failed to unshare namespaces");
+    }
+
+    if mount {
+        // Mount events in the new namespace should not propagate to the host.
+        let root = CString::new("/").unwrap();
+        // SAFETY: The arguments are valid C strings or null.
+        let ret = unsafe {
+            libc::mount(
+                std::ptr::null(),
+                root.as_ptr(),
+                std::ptr::null(),
+                libc::MS_SLAVE | libc::MS_REC,
+                std::ptr::null(),
+            )
+        };
+        if ret != 0 {
+            return Err(Error::last_os_error())
+                .with_context(|| "Failed to change propagation of mounts");
+        }
+    }
+    Ok(())
+}
+
+fn change_root(dir: &str) -> Result<()> {
+    let path = CString::new(dir)?;
+    // SAFETY: The path is a valid C string.
+    if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+        return Err(Error::last_os_error()).with_context(|| format!("Failed to chroot to {}", dir));
+    }
+    let root = CString::new("/").unwrap();
+    // SAFETY: The path is a valid C string.
+    if unsafe { libc::chdir(root.as_ptr()) } != 0 {
+        return Err(Error::last_os_error()).with_context(|| "Failed to change directory to /");
+    }
+    Ok(())
+}
+
+fn drop_privileges(uid: u32, gid: u32) -> Result<()> {
+    // The supplementary groups of root must be dropped before switching the user.
+    // SAFETY: Setting an empty group list is valid.
+    if unsafe { libc::setgroups(0, std::ptr::null()) } != 0 {
+        return Err(Error::last_os_error()).with_context(|| "Failed to drop supplementary groups");
+    }
+    // The gid must be changed first, it can't be changed after the privileges are dropped.
+    // SAFETY: Calling setgid is always safe, the result is checked.
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(Error::last_os_error()).with_context(|| format!("Failed to set gid {}", gid));
+    }
+    // SAFETY: Calling setuid is always safe, the result is checked.
+    if unsafe { libc::setuid(uid) } != 0 {
+        return Err(Error::last_os_error()).with_context(|| format!("Failed to set uid {}", uid));
+    }
+    // Make sure the privileges can't be regained.
+    // SAFETY: Calling setuid is always safe.
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        bail!("The privileges of root are not dropped");
+    }
+    Ok(())
+}
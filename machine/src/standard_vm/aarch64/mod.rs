@@ -26,7 +26,10 @@ use log::{error, info, warn};
 use vmm_sys_util::eventfd::EventFd;
 
 use super::{AcpiBuilder, Result as StdResult, StdMachineOps};
-use crate::MachineOps;
+use crate::gdbstub::start_gdbstub;
+use crate::halt_poll::init_halt_poll;
+use crate::sandbox::enter_sandbox;
+use crate::{create_vfio_platform_device, MachineOps};
 use acpi::{
     processor_append_priv_res, AcpiGicCpu, AcpiGicDistributor, AcpiGicIts, AcpiGicRedistributor,
     AcpiSratGiccAffinity, AcpiSratMemoryAffinity, AcpiTable, AmlBuilder, AmlDevice, AmlInteger,
@@ -39,36 +42,41 @@ use acpi::{
     ARCH_GIC_MAINT_IRQ, ID_MAPPING_ENTRY_SIZE, INTERRUPT_PPIS_COUNT, INTERRUPT_SGIS_COUNT,
     ROOT_COMPLEX_ENTRY_SIZE,
 };
-use address_space::{AddressSpace, GuestAddress, Region};
-use boot_loader::{load_linux, BootLoaderConfig};
+use address_space::{create_backend_mem, AddressSpace, FileBackend, GuestAddress, Region};
+use boot_loader::{load_dtb, load_linux, BootLoaderConfig};
 use cpu::{
     CPUBootConfig, CPUFeatures, CPUInterface, CPUTopology, CpuTopology, CPU, PMU_INTR, PPI_BASE,
 };
 use devices::acpi::ged::{acpi_dsdt_add_power_button, Ged};
+use devices::acpi::mem_hotplug::MemHotplugController;
 use devices::acpi::power::PowerDev;
+use devices::acpi::vmgenid::VmGenId;
 #[cfg(feature = "ramfb")]
 use devices::legacy::Ramfb;
 use devices::legacy::{
     FwCfgEntryType, FwCfgMem, FwCfgOps, LegacyError as DevErrorKind, PFlash, PL011, PL031,
 };
+use devices::misc::watchdog::{SbsaGwdt, WatchdogActionTrigger};
 use devices::pci::{InterruptHandler, PciDevOps, PciHost, PciIntxState};
 use devices::sysbus::{SysBus, SysBusDevType, SysRes};
 use devices::{ICGICConfig, ICGICv3Config, InterruptController, GIC_IRQ_INTERNAL, GIC_IRQ_MAX};
-use hypervisor::kvm::KVM_FDS;
+use hypervisor::{hypervisor, kvm::KVM_FDS};
 #[cfg(feature = "ramfb")]
 use machine_manager::config::parse_ramfb;
-use machine_manager::config::ShutdownAction;
 #[cfg(feature = "gtk")]
 use machine_manager::config::UiContext;
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    parse_incoming_uri, parse_pc_dimm, parse_vmgenid, parse_watchdog, BootIndexInfo, BootSource,
+    DriveFile, Incoming, MigrateMode, NumaNode, NumaNodes, PFlashConfig, PcDimmConfig,
+    SerialConfig, VmConfig, WatchdogAction,
 };
+use machine_manager::config::{RebootAction, ShutdownAction};
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
+use machine_manager::job::JobTask;
 use machine_manager::machine::{
     KvmVmState, MachineAddressInterface, MachineExternalInterface, MachineInterface,
-    MachineLifecycle, MachineTestInterface, MigrateInterface,
+    MachineLifecycle, MachineTestInterface, MigrateInterface, ShutdownCause,
 };
 use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_response::Response, qmp_schema};
 use migration::{MigrationManager, MigrationStatus};
@@ -83,6 +91,9 @@ use util::device_tree::{self, CompileFDT, FdtBuilder};
 use util::loop_context::EventLoopManager;
 use util::seccomp::BpfRule;
 use util::set_termi_canon_mode;
+use util::unix::host_page_size;
+use vfio::VfioPlatformDevice;
+use virtio::Iommu;
 
 /// The type of memory layout entry on aarch64
 pub enum LayoutEntryType {
@@ -95,6 +106,9 @@ pub enum LayoutEntryType {
     FwCfg,
     Ged,
     PowerDev,
+    Watchdog,
+    MemHotplugCtrl,
+    VmGenId,
     Mmio,
     PcieMmio,
     PciePio,
@@ -102,6 +116,7 @@ pub enum LayoutEntryType {
     HighGicRedist,
     HighPcieEcam,
     HighPcieMmio,
+    MemHotplug,
 }
 
 /// Layout of aarch64
@@ -115,13 +130,17 @@ pub const MEM_LAYOUT: &[(u64, u64)] = &[
     (0x0902_0000, 0x0000_0018),    // FwCfg
     (0x0908_0000, 0x0000_0004),    // Ged
     (0x0909_0000, 0x0000_1000),    // PowerDev
+    (0x090A_0000, 0x0000_2000),    // Watchdog
+    (0x090C_0000, 0x0000_001C),    // MemHotplugCtrl
+    (0x090D_0000, 0x0001_0000),    // VmGenId
     (0x0A00_0000, 0x0000_0200),    // Mmio
     (0x1000_0000, 0x2EFF_0000),    // PcieMmio
     (0x3EFF_0000, 0x0001_0000),    // PciePio
     (0x4000_0000, 0x7F_4000_0000), // Mem
     (510 << 30, 0x200_0000),       // HighGicRedist, (where remaining redistributors locates)
     (511 << 30, 0x1000_0000),      // HighPcieEcam
-    (512 << 30, 512 << 30),        // HighPcieMmio
+    (512 << 30, 256 << 30),        // HighPcieMmio
+    (768 << 30, 256 << 30),        // MemHotplug, reserved for hot-plugged pc-dimm
 ];
 
 /// The type of Irq entry on aarch64
@@ -175,10 +194,40 @@ pub struct StdMachine {
     boot_order_list: Arc<Mutex<Vec<BootIndexInfo>>>,
     /// FwCfg device.
     fwcfg_dev: Option<Arc<Mutex<FwCfgMem>>>,
+    /// PFlash devices, indexed by unit id.
+    pflash_devs: HashMap<usize, Arc<Mutex<PFlash>>>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
     /// machine all backend memory region tree
     machine_ram: Arc<Region>,
+    /// Generic event device, which notifies the guest of ACPI events.
+    ged: Option<Arc<Mutex<Ged>>>,
+    /// Memory hotplug controller, exists if slots of memory is set.
+    mem_hotplug: Option<Arc<Mutex<MemHotplugController>>>,
+    /// VM generation ID device.
+    vmgenid: Option<Arc<Mutex<VmGenId>>>,
+    /// Virtio iommu device, which translates the DMA of the devices on the root bus.
+    iommu: Option<Arc<Mutex<Iommu>>>,
+    /// PL031 RTC device.
+    rtc: Option<Arc<Mutex<PL031>>>,
+}
+
+/// Create the hugepage backend of PFlash in the directory of memory backend, None is
+/// returned if the directory is not on hugetlbfs.
+///
+/// # Arguments
+///
+/// * `mem_path` - The path of memory backend.
+/// * `size` - The size of PFlash.
+fn hugepage_backend(mem_path: &str, size: u64) -> Result<Option<FileBackend>> {
+    if !std::path::Path::new(mem_path).is_dir() {
+        return Ok(None);
+    }
+    let backend = FileBackend::new_mem(mem_path, size)?;
+    if backend.page_size <= host_page_size() || !size.is_multiple_of(backend.page_size) {
+        return Ok(None);
+    }
+    Ok(Some(backend))
 }
 
 impl StdMachine {
@@ -251,15 +300,21 @@ impl StdMachine {
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
             fwcfg_dev: None,
+            pflash_devs: HashMap::new(),
             drive_files: Arc::new(Mutex::new(vm_config.init_drive_files()?)),
             machine_ram: Arc::new(Region::init_container_region(
                 u64::max_value(),
                 "MachineRam",
             )),
+            ged: None,
+            mem_hotplug: None,
+            vmgenid: None,
+            iommu: None,
+            rtc: None,
         })
     }
 
-    pub fn handle_reset_request(vm: &Arc<Mutex<Self>>) -> Result<()> {
+    pub fn handle_reset_request(vm: &Arc<Mutex<Self>>, cause: ShutdownCause) -> Result<()> {
         let mut locked_vm = vm.lock().unwrap();
         let mut fdt_addr: u64 = 0;
 
@@ -293,7 +348,10 @@ impl StdMachine {
             .with_context(|| "Fail to update boot order imformation to FwCfg device")?;
 
         if QmpChannel::is_connected() {
-            let reset_msg = qmp_schema::Reset { guest: true };
+            let reset_msg = qmp_schema::Reset {
+                guest: cause.is_guest(),
+                reason: cause.reason().to_string(),
+            };
             event!(Reset; reset_msg);
         }
 
@@ -430,6 +488,12 @@ impl StdMachineOps for StdMachine {
             .add_string_entry(FwCfgEntryType::CmdlineData, cmdline.as_str())
             .with_context(|| DevErrorKind::AddEntryErr("CmdlineData".to_string()))?;
 
+        if let Some(uuid) = self.vm_config.lock().unwrap().uuid.as_ref() {
+            fwcfg
+                .add_data_entry(FwCfgEntryType::Uuid, uuid.to_be_bytes())
+                .with_context(|| DevErrorKind::AddEntryErr("Uuid".to_string()))?;
+        }
+
         let boot_order = Vec::<u8>::new();
         fwcfg
             .add_file_entry("bootorder", boot_order)
@@ -463,6 +527,37 @@ impl StdMachineOps for StdMachine {
     fn get_guest_numa(&self) -> &Option<NumaNodes> {
         &self.numa_nodes
     }
+
+    fn plug_pc_dimm(&mut self, dimm_cfg: &PcDimmConfig, hotplug: bool) -> StdResult<()> {
+        let mhpc = self
+            .mem_hotplug
+            .clone()
+            .with_context(|| "Memory hotplug is not enabled, slots of memory is not set")?;
+        match self.numa_nodes.as_ref() {
+            Some(nodes) if !nodes.contains_key(&dimm_cfg.node) => {
+                bail!("Numa node {} of pc-dimm is not found", dimm_cfg.node)
+            }
+            None if dimm_cfg.node != 0 => {
+                bail!("Numa node of pc-dimm must be 0 without numa configured")
+            }
+            _ => {}
+        }
+
+        let mut locked_mhpc = mhpc.lock().unwrap();
+        let size = dimm_cfg.mem_cfg.size;
+        let base = locked_mhpc.alloc_addr(size)?;
+        let thread_num = dimm_cfg
+            .mem_cfg
+            .prealloc_threads
+            .unwrap_or(self.cpus.len() as u8);
+        let ram = create_backend_mem(&dimm_cfg.mem_cfg, thread_num)
+            .with_context(|| format!("Failed to create memory of pc-dimm {}", dimm_cfg.id))?;
+        self.sys_mem
+            .root()
+            .add_subregion(ram, base)
+            .with_context(|| format!("Failed to map memory of pc-dimm {}", dimm_cfg.id))?;
+        locked_mhpc.plug(&dimm_cfg.id, base, size, dimm_cfg.node, hotplug)
+    }
 }
 
 impl MachineOps for StdMachine {
@@ -482,6 +577,11 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_device_mem_window(&self) -> Result<(u64, u64)> {
+        let (start, size) = MEM_LAYOUT[LayoutEntryType::Mem as usize];
+        Ok((start, start + size))
+    }
+
     fn init_interrupt_controller(&mut self, vcpu_count: u64) -> Result<()> {
         let v3 = ICGICv3Config {
             msi: true,
@@ -518,7 +618,7 @@ impl MachineOps for StdMachine {
             let irqtype = KVM_ARM_IRQ_TYPE_SPI;
             let kvm_irq = irqtype << KVM_ARM_IRQ_TYPE_SHIFT | irq;
 
-            KVM_FDS.load().set_irq_line(kvm_irq, level)
+            hypervisor().set_irq_line(kvm_irq, level)
         }) as InterruptHandler;
 
         let irq_state = Some(Arc::new(Mutex::new(PciIntxState::new(
@@ -553,29 +653,55 @@ impl MachineOps for StdMachine {
     }
 
     fn add_rtc_device(&mut self) -> Result<()> {
-        let rtc = PL031::default();
-        PL031::realize(
+        let rtc_config = self.vm_config.lock().unwrap().rtc;
+        let rtc = PL031::new(&rtc_config);
+        let rtc = PL031::realize(
             rtc,
             &mut self.sysbus,
             MEM_LAYOUT[LayoutEntryType::Rtc as usize].0,
             MEM_LAYOUT[LayoutEntryType::Rtc as usize].1,
         )
         .with_context(|| "Failed to realize PL031")?;
+        self.rtc = Some(rtc);
         Ok(())
     }
 
     fn add_ged_device(&mut self) -> Result<()> {
         let battery_present = self.vm_config.lock().unwrap().machine_config.battery;
+        let mem_slots = self
+            .vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .mem_config
+            .mem_slots;
         let ged = Ged::default();
         let ged_dev = ged
             .realize(
                 &mut self.sysbus,
                 self.power_button.clone(),
                 battery_present,
+                mem_slots > 0,
                 MEM_LAYOUT[LayoutEntryType::Ged as usize].0,
                 MEM_LAYOUT[LayoutEntryType::Ged as usize].1,
             )
             .with_context(|| "Failed to realize Ged")?;
+        if mem_slots > 0 {
+            let mhpc = MemHotplugController::new(
+                mem_slots,
+                MEM_LAYOUT[LayoutEntryType::MemHotplug as usize],
+                ged_dev.clone(),
+            );
+            let mhpc_dev = mhpc
+                .realize(
+                    &mut self.sysbus,
+                    MEM_LAYOUT[LayoutEntryType::MemHotplugCtrl as usize].0,
+                    MEM_LAYOUT[LayoutEntryType::MemHotplugCtrl as usize].1,
+                )
+                .with_context(|| "Failed to realize memory hotplug controller")?;
+            self.mem_hotplug = Some(mhpc_dev);
+        }
+        self.ged = Some(ged_dev.clone());
         if battery_present {
             let pdev = PowerDev::new(ged_dev);
             pdev.realize(
@@ -604,6 +730,67 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_watchdog_action_trigger(&self, action: WatchdogAction) -> Result<WatchdogActionTrigger> {
+        let action_evt = match action {
+            WatchdogAction::Reset => Some(self.reset_req.clone()),
+            WatchdogAction::Shutdown => Some(self.shutdown_req.clone()),
+            WatchdogAction::Pause => Some(self.pause_req.clone()),
+            WatchdogAction::Debug => None,
+        };
+        Ok(WatchdogActionTrigger::new(action, action_evt))
+    }
+
+    fn add_pc_dimm(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let dimm_cfg = parse_pc_dimm(vm_config, cfg_args)?;
+        self.plug_pc_dimm(&dimm_cfg, false)
+    }
+
+    fn add_sbsa_gwdt(&mut self, vm_config: &VmConfig, cfg_args: &str) -> Result<()> {
+        parse_watchdog(cfg_args)?;
+        if self
+            .sysbus
+            .devices
+            .iter()
+            .any(|dev| dev.lock().unwrap().sysbusdev_base().dev_type == SysBusDevType::SbsaGwdt)
+        {
+            bail!("Only one sbsa-gwdt is supported");
+        }
+        let action_trigger =
+            self.get_watchdog_action_trigger(vm_config.machine_config.watchdog_action)?;
+        let gwdt = SbsaGwdt::new(action_trigger);
+        gwdt.realize(
+            &mut self.sysbus,
+            MEM_LAYOUT[LayoutEntryType::Watchdog as usize].0,
+        )
+        .with_context(|| "Failed to realize sbsa-gwdt")?;
+        Ok(())
+    }
+
+    fn add_vfio_platform_device(&mut self, cfg_args: &str) -> Result<()> {
+        create_vfio_platform_device(&mut self.sysbus, &self.sys_mem, cfg_args)
+    }
+
+    fn add_vmgenid(&mut self, cfg_args: &str) -> Result<()> {
+        let vmgenid_cfg = parse_vmgenid(cfg_args)?;
+        if self.vmgenid.is_some() {
+            bail!("Only one vmgenid is supported");
+        }
+        let ged = self
+            .ged
+            .clone()
+            .with_context(|| "vmgenid requires the GED device")?;
+        let vmgenid = VmGenId::new(
+            vmgenid_cfg.guid,
+            MEM_LAYOUT[LayoutEntryType::VmGenId as usize].0,
+            ged,
+        )?;
+        let dev = vmgenid
+            .realize(&mut self.sysbus)
+            .with_context(|| "Failed to realize vmgenid")?;
+        self.vmgenid = Some(dev);
+        Ok(())
+    }
+
     fn syscall_whitelist(&self) -> Vec<BpfRule> {
         syscall_whitelist()
     }
@@ -637,6 +824,7 @@ impl MachineOps for StdMachine {
             &locked_vm.sys_mem,
             nr_cpus,
         )?;
+        init_halt_poll(&vm_config.machine_config)?;
 
         locked_vm
             .init_pci_host()
@@ -677,7 +865,11 @@ impl MachineOps for StdMachine {
             locked_vm
                 .generate_fdt_node(&mut fdt_helper)
                 .with_context(|| MachineError::GenFdtErr)?;
-            let fdt_vec = fdt_helper.finish()?;
+            let mut fdt_vec = fdt_helper.finish()?;
+            let dtb = locked_vm.boot_source.lock().unwrap().dtb.clone();
+            if let Some(dtb) = dtb {
+                fdt_vec = load_dtb(&fdt_vec, &dtb)?;
+            }
             locked_vm.dtb_vec = fdt_vec.clone();
             locked_vm
                 .sys_mem
@@ -716,6 +908,19 @@ impl MachineOps for StdMachine {
             locked_vm.shutdown_req.clone(),
         );
 
+        if let Some(addr) = vm_config.gdb.as_ref() {
+            start_gdbstub(
+                addr,
+                vm.clone(),
+                locked_vm.vm_state.clone(),
+                locked_vm.cpus.clone(),
+                locked_vm.sys_mem.clone(),
+            )
+            .with_context(|| "Failed to start gdbstub")?;
+        }
+
+        enter_sandbox(vm_config).with_context(|| "Failed to enter sandbox")?;
+
         MigrationManager::register_vm_config(locked_vm.get_vm_config());
         MigrationManager::register_vm_instance(vm.clone());
         if let Err(e) = MigrationManager::set_status(MigrationStatus::Setup) {
@@ -731,10 +936,21 @@ impl MachineOps for StdMachine {
         let sector_len: u32 = 1024 * 256;
         let mut flash_base: u64 = MEM_LAYOUT[LayoutEntryType::Flash as usize].0;
         let flash_size: u64 = MEM_LAYOUT[LayoutEntryType::Flash as usize].1 / 2;
+        // The CODE is write-protected when the VARS is separated to another PFlash.
+        let separate_vars = configs_vec.len() > 1;
+        let mem_path = self
+            .vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .mem_config
+            .mem_path
+            .clone();
         for i in 0..=1 {
             let (fd, read_only) = if i < configs_vec.len() {
                 let path = &configs_vec[i].path_on_host;
-                let read_only = configs_vec[i].read_only;
+                let read_only =
+                    configs_vec[i].read_only || (separate_vars && configs_vec[i].unit == 0);
                 let fd = self.fetch_drive_file(path)?;
                 (Some(fd), read_only)
             } else {
@@ -743,8 +959,30 @@ impl MachineOps for StdMachine {
 
             let pflash = PFlash::new(flash_size, &fd, sector_len, 4, 2, read_only)
                 .with_context(|| StdErrorKind::InitPflashErr)?;
-            PFlash::realize(pflash, &mut self.sysbus, flash_base, flash_size, fd)
-                .with_context(|| StdErrorKind::RlzPflashErr)?;
+            // The read-only CODE is copied to hugepages if the guest memory is backed by
+            // hugetlbfs, so that the firmware runs with less TLB misses.
+            let huge_backend = match (&fd, &mem_path) {
+                (Some(_), Some(path)) if read_only => hugepage_backend(path, flash_size)?,
+                _ => None,
+            };
+            let pflash = match (huge_backend, fd) {
+                (Some(mem_backend), Some(image)) => PFlash::realize_with_image(
+                    pflash,
+                    &mut self.sysbus,
+                    flash_base,
+                    flash_size,
+                    mem_backend,
+                    image,
+                ),
+                (_, fd) => PFlash::realize(pflash, &mut self.sysbus, flash_base, flash_size, fd),
+            }
+            .with_context(|| StdErrorKind::RlzPflashErr)?;
+            if let Some(config) = configs_vec.get(i) {
+                if config.sealed {
+                    pflash.lock().unwrap().set_sealed(true)?;
+                }
+                self.pflash_devs.insert(config.unit, pflash);
+            }
             flash_base += flash_size;
         }
 
@@ -820,6 +1058,15 @@ impl MachineOps for StdMachine {
         Ok(&self.pci_host)
     }
 
+    fn set_iommu(&mut self, iommu: Arc<Mutex<Iommu>>) -> Result<()> {
+        self.iommu = Some(iommu);
+        Ok(())
+    }
+
+    fn get_iommu(&self) -> Option<Arc<Mutex<Iommu>>> {
+        self.iommu.clone()
+    }
+
     fn get_sys_bus(&mut self) -> &SysBus {
         &self.sysbus
     }
@@ -842,6 +1089,18 @@ impl MachineOps for StdMachine {
     fn get_boot_order_list(&self) -> Option<Arc<Mutex<Vec<BootIndexInfo>>>> {
         Some(self.boot_order_list.clone())
     }
+
+    fn get_pflash(&self, unit: usize) -> Option<Arc<Mutex<PFlash>>> {
+        self.pflash_devs.get(&unit).cloned()
+    }
+
+    fn get_vmgenid(&self) -> Option<Arc<Mutex<VmGenId>>> {
+        self.vmgenid.clone()
+    }
+
+    fn get_rtc(&self) -> Option<Arc<Mutex<PL031>>> {
+        self.rtc.clone()
+    }
 }
 
 impl AcpiBuilder for StdMachine {
@@ -873,6 +1132,33 @@ impl AcpiBuilder for StdMachine {
         // Non secure EL2 flags
         gtdt.set_field(76, ACPI_GTDT_INTERRUPT_MODE_LEVEL);
 
+        let gwdt_irq = self.sysbus.devices.iter().find_map(|dev| {
+            let locked_dev = dev.lock().unwrap();
+            if locked_dev.sysbusdev_base().dev_type == SysBusDevType::SbsaGwdt {
+                return Some(locked_dev.sysbusdev_base().res.irq as u32);
+            }
+            None
+        });
+        if let Some(irq) = gwdt_irq {
+            let control_frame = MEM_LAYOUT[LayoutEntryType::Watchdog as usize].0;
+            // Platform timer count
+            gtdt.set_field(88, 1_u32);
+            // Platform timer offset
+            gtdt.set_field(92, 96_u32);
+            // Arm generic watchdog structure, 28 bytes
+            gtdt.set_table_len(124);
+            gtdt.set_field(96, 1_u8);
+            gtdt.set_field(97, 28_u16);
+            // Refresh frame physical address
+            gtdt.set_field(100, control_frame + 0x1000);
+            // Control frame physical address
+            gtdt.set_field(108, control_frame);
+            // Watchdog timer GSIV
+            gtdt.set_field(116, irq + INTERRUPT_SGIS_COUNT + INTERRUPT_PPIS_COUNT);
+            // Watchdog timer flags: level triggered, active high, non-secure
+            gtdt.set_field(120, 0_u32);
+        }
+
         let gtdt_begin = StdMachine::add_table_to_loader(acpi_data, loader, &gtdt)
             .with_context(|| "Fail to add GTDT table to loader")?;
         Ok(gtdt_begin)
@@ -1109,7 +1395,9 @@ impl AcpiBuilder for StdMachine {
             gic_cpu.flags = 5;
             gic_cpu.mpidr = mpidr & mpidr_mask;
             gic_cpu.vgic_interrupt = ARCH_GIC_MAINT_IRQ + INTERRUPT_PPIS_COUNT;
-            gic_cpu.perf_interrupt = PMU_INTR + PPI_BASE;
+            if self.cpu_features.pmu {
+                gic_cpu.perf_interrupt = PMU_INTR + PPI_BASE;
+            }
             madt.append_child(&gic_cpu.aml_bytes());
         }
 
@@ -1194,6 +1482,30 @@ impl AcpiBuilder for StdMachine {
             self.build_srat_cpu(*id, node, &mut srat);
             next_base = self.build_srat_mem(next_base, *id, node, &mut srat);
         }
+        // The window of hot-plugged pc-dimm is reported as hot-pluggable memory of the last node.
+        if self.mem_hotplug.is_some() {
+            let (base_addr, range_length) = MEM_LAYOUT[LayoutEntryType::MemHotplug as usize];
+            let last_node = self
+                .numa_nodes
+                .as_ref()
+                .unwrap()
+                .last_key_value()
+                .unwrap()
+                .0;
+            srat.append_child(
+                &AcpiSratMemoryAffinity {
+                    type_id: 1,
+                    length: size_of::<AcpiSratMemoryAffinity>() as u8,
+                    proximity_domain: *last_node,
+                    base_addr,
+                    range_length,
+                    // Enabled and hot-pluggable.
+                    flags: 3,
+                    ..Default::default()
+                }
+                .aml_bytes(),
+            );
+        }
 
         let srat_begin = StdMachine::add_table_to_loader(acpi_data, loader, &srat)
             .with_context(|| "Fail to add SRAT table to loader")?;
@@ -1268,6 +1580,10 @@ impl MachineLifecycle for StdMachine {
             .shutdown_action
     }
 
+    fn get_reboot_action(&self) -> RebootAction {
+        self.vm_config.lock().unwrap().machine_config.reboot_action
+    }
+
     fn reset(&mut self) -> bool {
         if self.reset_req.write(1).is_err() {
             error!("ARM standard vm write reset req failed");
@@ -1289,6 +1605,10 @@ impl MachineLifecycle for StdMachine {
         }
         true
     }
+
+    fn shared_vm_state(&self) -> Option<Arc<(Mutex<KvmVmState>, Condvar)>> {
+        Some(self.vm_state.clone())
+    }
 }
 
 impl MachineAddressInterface for StdMachine {
@@ -1320,6 +1640,11 @@ impl MigrateInterface for StdMachine {
         }
     }
 
+    fn migrate_task(&self, uri: String) -> Result<JobTask> {
+        let (mode, path) = parse_incoming_uri(&uri)?;
+        migration::migration_task(mode, path)
+    }
+
     fn query_migrate(&self) -> Response {
         migration::query_migrate()
     }
@@ -1327,6 +1652,21 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::set_migrate_parameters(args)
+    }
+
+    fn query_migrate_parameters(&self) -> Response {
+        migration::query_migrate_parameters()
+    }
+
+    fn migrate_set_capabilities(
+        &self,
+        args: qmp_schema::MigrateSetCapabilitiesArgument,
+    ) -> Response {
+        migration::set_migrate_capabilities(args)
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -1536,6 +1876,40 @@ fn generate_rtc_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<
     Ok(())
 }
 
+// Function that helps to generate sbsa generic watchdog node in device-tree.
+//
+// # Arguments
+//
+// * `dev_info` - Device resource info of watchdog device.
+// * `fdt` - Flatted device-tree blob where watchdog node will be filled into.
+fn generate_gwdt_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<()> {
+    let node = format!("watchdog@{:x}", res.region_base);
+    let gwdt_node_dep = fdt.begin_node(&node)?;
+    fdt.set_property_string("compatible", "arm,sbsa-gwdt")?;
+    // The control frame is followed by the refresh frame, both are 4KB.
+    let frame_size = res.region_size / 2;
+    fdt.set_property_array_u64(
+        "reg",
+        &[
+            res.region_base,
+            frame_size,
+            res.region_base + frame_size,
+            frame_size,
+        ],
+    )?;
+    fdt.set_property_array_u32(
+        "interrupts",
+        &[
+            device_tree::GIC_FDT_IRQ_TYPE_SPI,
+            res.irq as u32,
+            device_tree::IRQ_TYPE_LEVEL_HIGH,
+        ],
+    )?;
+    fdt.end_node(gwdt_node_dep)?;
+
+    Ok(())
+}
+
 fn generate_pmu_node(fdt: &mut FdtBuilder) -> util::Result<()> {
     let node = "pmu";
     let pmu_node_dep = fdt.begin_node(node)?;
@@ -1729,6 +2103,14 @@ impl CompileFDTHelper for StdMachine {
                     // SAFETY: Legacy devices guarantee is not empty.
                     generate_fwcfg_device_node(fdt, &locked_dev.sysbusdev_base().res)?;
                 }
+                SysBusDevType::SbsaGwdt => {
+                    generate_gwdt_device_node(fdt, &locked_dev.sysbusdev_base().res)?;
+                }
+                SysBusDevType::VfioPlatform => {
+                    if let Some(dev) = locked_dev.as_any().downcast_ref::<VfioPlatformDevice>() {
+                        dev.generate_fdt_node(fdt)?;
+                    }
+                }
                 _ => (),
             }
         }
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use block_backend::zoned::{BLKCLOSEZONE, BLKFINISHZONE, BLKOPENZONE, BLKREPORTZONE, BLKRESETZONE};
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
 use util::tap::{TUNGETFEATURES, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ};
@@ -205,6 +206,20 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_shmdt),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_lremovexattr),
+        // Syscalls of virtio-9p to serve the shared directory.
+        BpfRule::new(libc::SYS_renameat),
+        BpfRule::new(libc::SYS_renameat2),
+        BpfRule::new(libc::SYS_mkdirat),
+        BpfRule::new(libc::SYS_unlinkat),
+        BpfRule::new(libc::SYS_linkat),
+        BpfRule::new(libc::SYS_symlinkat),
+        BpfRule::new(libc::SYS_mknodat),
+        BpfRule::new(libc::SYS_fchownat),
+        BpfRule::new(libc::SYS_utimensat),
+        BpfRule::new(libc::SYS_statfs),
+        BpfRule::new(libc::SYS_lgetxattr),
+        BpfRule::new(libc::SYS_lsetxattr),
+        BpfRule::new(libc::SYS_fsync),
     ]
 }
 
@@ -242,6 +257,11 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETQUEUE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKREPORTZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKRESETZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKOPENZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKCLOSEZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKFINISHZONE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GSI_ROUTING() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IRQFD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VFIO_DEVICE_SET_IRQS() as u32)
@@ -265,9 +285,12 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DEVICE_ATTR() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REG_LIST() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_ARM_VCPU_INIT() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_ARM_VCPU_FINALIZE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IRQ_LINE() as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_ONE_REG() as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_ONE_REG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GUEST_DEBUG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_ENABLE_CAP() as u32);
 
     #[cfg(feature = "usb_camera_v4l2")]
     let bpf_rule = bpf_rule
@@ -295,13 +318,17 @@ fn madvise_rule() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_COLD as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_PAGEOUT as u32);
     #[cfg(target_env = "gnu")]
     return BpfRule::new(libc::SYS_madvise)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_COLD as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_PAGEOUT as u32);
 }
 
 fn futex_rule() -> BpfRule {
@@ -30,64 +30,128 @@ use std::ops::Deref;
 use std::os::unix::io::RawFd;
 use std::os::unix::prelude::AsRawFd;
 use std::rc::Rc;
+use std::str::FromStr;
 use std::string::String;
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Context};
-use log::error;
+use log::{error, info};
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
 #[cfg(target_arch = "x86_64")]
-use self::x86_64::ich9_lpc::{PM_CTRL_OFFSET, PM_EVENT_OFFSET, RST_CTRL_OFFSET, SLEEP_CTRL_OFFSET};
+use self::x86_64::ich9_lpc::{
+    PM_CTRL_OFFSET, PM_EVENT_OFFSET, RST_CTRL_OFFSET, SCI_IRQ, SLEEP_CTRL_OFFSET,
+};
 use super::Result as MachineResult;
-use crate::MachineOps;
+#[cfg(target_arch = "x86_64")]
+use crate::cpu_info_x86;
+use crate::halt_poll::{query_halt_poll, set_halt_poll};
+use crate::{query_stats, MachineOps};
 #[cfg(target_arch = "aarch64")]
 use aarch64::{LayoutEntryType, MEM_LAYOUT};
 #[cfg(target_arch = "x86_64")]
 use acpi::AcpiGenericAddress;
 use acpi::{
     AcpiRsdp, AcpiTable, AmlBuilder, TableLoader, ACPI_RSDP_FILE, ACPI_TABLE_FILE,
-    ACPI_TABLE_LOADER_FILE, TABLE_CHECKSUM_OFFSET,
+    ACPI_TABLE_LOADER_FILE, ACPI_VIOT_NODE_PCI_RANGE, ACPI_VIOT_NODE_VIRTIO_PCI_IOMMU,
+    TABLE_CHECKSUM_OFFSET,
 };
 use address_space::{
     AddressRange, FileBackend, GuestAddress, HostMemMapping, Region, RegionIoEventFd, RegionOps,
 };
-use block_backend::{qcow2::QCOW2_LIST, BlockStatus};
+use block_backend::{
+    create_qcow2_overlay,
+    dirty_bitmap::{DirtyBitmaps, DIRTY_BITMAP_LIST},
+    mirror::{
+        mirror_finish, mirror_job, mirror_job_active, mirror_start, query_mirror_jobs, MirrorSource,
+    },
+    nbd::{nbd_server_add, nbd_server_start},
+    qcow2::QCOW2_LIST,
+    BlockProperty, BlockStatus,
+};
+use chardev_backend::chardev::change_chardev;
 use cpu::{CpuTopology, CPU};
+use devices::interrupt_stats::query_interrupt_stats;
 use devices::legacy::FwCfgOps;
+#[cfg(target_arch = "aarch64")]
+use devices::legacy::PL031;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::{Hpet, RTC};
 use devices::pci::hotplug::{handle_plug, handle_unplug_pci_request};
 use devices::pci::PciBus;
+use devices::ScsiDisk::{SCSI_TYPE_DISK, SCSI_TYPE_ROM};
+use machine_manager::balloon_policy::{query_balloon_policy, set_balloon_policy};
 #[cfg(feature = "usb_camera")]
 use machine_manager::config::get_cameradev_config;
 use machine_manager::config::{
-    get_chardev_config, get_netdev_config, get_pci_df, memory_unit_conversion, BlkDevConfig,
-    ChardevType, ConfigCheck, DiskFormat, DriveConfig, ExBool, NetworkInterfaceConfig, NumaNode,
-    NumaNodes, PciBdf, ScsiCntlrConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE, M, MAX_VIRTIO_QUEUE,
+    check_mac_address, get_chardev_change_config, get_chardev_config, get_netdev_config,
+    get_pci_df, get_secret_data, memory_unit_conversion, parse_device_id, parse_vmgenid_guid,
+    rng_bytes_per_sec, BlkDevConfig, ChardevType, ConfigCheck, DiskFormat, DriveConfig, ExBool,
+    IoTimeout, IoTimeoutAction, IothreadConfig, MemZoneConfig, NetFilterConfig, NetFilterQueue,
+    NetFilterType, NetworkInterfaceConfig, NumaNode, NumaNodes, PcDimmConfig, PciBdf, RebootAction,
+    RngConfig, RngObjConfig, ScsiCntlrConfig, SecretObjConfig, ShutdownAction, VirtioPciMode,
+    VmConfig, VsockConfig, DEFAULT_VIRTQUEUE_SIZE, M, MAX_VIRTIO_QUEUE,
 };
+use machine_manager::cpu_throttle::cpu_throttle_set;
+use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
+use machine_manager::job::{job_cancel, job_pause, job_resume, query_jobs};
 use machine_manager::machine::MachineLifecycle;
-use machine_manager::machine::{DeviceInterface, KvmVmState};
+use machine_manager::machine::{
+    take_shutdown_cause, DeviceInterface, KvmVmState, ShutdownCause, IOTHREADS,
+};
 use machine_manager::qmp::qmp_schema::{BlockDevAddArgument, UpdateRegionArgument};
 use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_response::Response, qmp_schema};
 use migration::MigrationManager;
+#[cfg(feature = "display_channel")]
+use ui::display_channel::{display_channel_add, display_channel_del, query_display_channels};
 use ui::input::{key_event, point_event};
 #[cfg(feature = "vnc")]
 use ui::vnc::qmp_query_vnc;
-use util::aio::{AioEngine, WriteZeroesState};
+use util::aio::{aio_select, WriteZeroesState};
 use util::byte_code::ByteCode;
 use util::loop_context::{read_fd, EventNotifier, NotifierCallback, NotifierOperation};
 use virtio::{
-    qmp_balloon, qmp_query_balloon, Block, BlockState,
+    qmp_balloon, qmp_balloon_cancel, qmp_balloon_stats_interval, qmp_query_balloon,
+    qmp_query_balloon_stats, Block, BlockState, Iommu, Net, Rng, RngState,
     ScsiCntlr::{scsi_cntlr_create_scsi_bus, ScsiCntlr},
     VhostKern, VhostUser, VirtioDevice, VirtioNetState, VirtioPciDevice,
 };
 #[cfg(target_arch = "x86_64")]
 use x86_64::{LayoutEntryType, MEM_LAYOUT};
 
+/// Offset of the first node in ACPI VIOT table, which is the virtio-pci iommu node.
+const VIOT_NODE_OFFSET: u16 = 48;
+
 trait StdMachineOps: AcpiBuilder {
     fn init_pci_host(&self) -> Result<()>;
 
+    /// Map the memory of pc-dimm into guest and put it into a memory slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `dimm_cfg` - Config of the pc-dimm.
+    /// * `hotplug` - Whether the pc-dimm is hot-plugged.
+    fn plug_pc_dimm(&mut self, _dimm_cfg: &PcDimmConfig, _hotplug: bool) -> Result<()> {
+        bail!("pc-dimm is not supported by this machine");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn get_hpet(&self) -> Option<Arc<Mutex<Hpet>>> {
+        None
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn get_rtc(&self) -> Option<Arc<Mutex<RTC>>> {
+        None
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn get_rtc(&self) -> Option<Arc<Mutex<PL031>>> {
+        None
+    }
+
     /// Build all ACPI tables and RSDP, and add them to FwCfg as file entries.
     ///
     /// # Arguments
@@ -95,7 +159,7 @@ trait StdMachineOps: AcpiBuilder {
     /// `fw_cfg` - FwCfgOps trait object.
     fn build_acpi_tables(&self, fw_cfg: &Arc<Mutex<dyn FwCfgOps>>) -> Result<()>
     where
-        Self: Sized,
+        Self: Sized + MachineOps,
     {
         let mut loader = TableLoader::new();
         let acpi_tables = Arc::new(Mutex::new(Vec::new()));
@@ -160,6 +224,19 @@ trait StdMachineOps: AcpiBuilder {
             xsdt_entries.push(slit_addr);
         }
 
+        if let Some(iommu) = self.get_iommu() {
+            let viot_addr = Self::build_viot_table(&iommu, &acpi_tables, &mut loader)
+                .with_context(|| "Failed to build ACPI VIOT table")?;
+            xsdt_entries.push(viot_addr);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(hpet) = self.get_hpet() {
+            let hpet_addr = Self::build_hpet_table(&hpet, &acpi_tables, &mut loader)
+                .with_context(|| "Failed to build ACPI HPET table")?;
+            xsdt_entries.push(hpet_addr);
+        }
+
         #[cfg(target_arch = "aarch64")]
         {
             let pptt_addr = self
@@ -212,7 +289,17 @@ trait StdMachineOps: AcpiBuilder {
         let reset_req_fd = reset_req.as_raw_fd();
         let reset_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
             read_fd(reset_req_fd);
-            if let Err(e) = StdMachine::handle_reset_request(&clone_vm) {
+            // The reset which is not requested by guest or watchdog comes from `system_reset`.
+            let cause = take_shutdown_cause().unwrap_or(ShutdownCause::HostQmpSystemReset);
+            let reboot_act = clone_vm.lock().unwrap().get_reboot_action();
+            if reboot_act == RebootAction::Shutdown {
+                info!("Reboot action is shutdown, shutdown standard VM instead of reset");
+                if !StdMachine::handle_shutdown_action(&clone_vm, cause) {
+                    error!("Fail to shutdown standard VM");
+                }
+                return None;
+            }
+            if let Err(e) = StdMachine::handle_reset_request(&clone_vm, cause) {
                 error!("Fail to reboot standard VM, {:?}", e);
             }
 
@@ -230,6 +317,24 @@ trait StdMachineOps: AcpiBuilder {
         Ok(())
     }
 
+    /// Shutdown the VM according to the shutdown action, which is used when the
+    /// reboot of guest is handled as shutdown.
+    fn handle_shutdown_action(vm: &Arc<Mutex<StdMachine>>, cause: ShutdownCause) -> bool {
+        let locked_vm = vm.lock().unwrap();
+        let ret = match locked_vm.get_shutdown_action() {
+            ShutdownAction::ShutdownActionPoweroff => locked_vm.destroy(),
+            ShutdownAction::ShutdownActionPause => locked_vm.pause(),
+        };
+        if ret && QmpChannel::is_connected() {
+            let shutdown_msg = qmp_schema::Shutdown {
+                guest: cause.is_guest(),
+                reason: cause.reason().to_string(),
+            };
+            event!(Shutdown; shutdown_msg);
+        }
+        ret
+    }
+
     fn register_pause_event(
         &self,
         pause_req: Arc<EventFd>,
@@ -293,6 +398,15 @@ trait StdMachineOps: AcpiBuilder {
         let shutdown_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
             let _ret = shutdown_req.read();
             if clone_vm.lock().unwrap().destroy() {
+                if let Some(cause) = take_shutdown_cause() {
+                    if QmpChannel::is_connected() {
+                        let shutdown_msg = qmp_schema::Shutdown {
+                            guest: cause.is_guest(),
+                            reason: cause.reason().to_string(),
+                        };
+                        event!(Shutdown; shutdown_msg);
+                    }
+                }
                 Some(gen_delete_notifiers(&[shutdown_req_fd]))
             } else {
                 None
@@ -534,6 +648,9 @@ trait AcpiBuilder {
         let mut fadt = AcpiTable::new(*b"FACP", 6, *b"STRATO", *b"VIRTFACP", 1);
 
         fadt.set_table_len(208_usize);
+        // SCI_INT bit, offset is 46.
+        #[cfg(target_arch = "x86_64")]
+        fadt.set_field(46, SCI_IRQ as u16);
         // PM1A_EVENT bit, offset is 56.
         #[cfg(target_arch = "x86_64")]
         fadt.set_field(56, 0x600);
@@ -543,6 +660,12 @@ trait AcpiBuilder {
         // PM_TMR_BLK bit, offset is 76.
         #[cfg(target_arch = "x86_64")]
         fadt.set_field(76, 0x608);
+        // PM1_EVT_LEN and PM1_CNT_LEN, offset is 88 and 89.
+        #[cfg(target_arch = "x86_64")]
+        {
+            fadt.set_field(88, 4_u8);
+            fadt.set_field(89, 2_u8);
+        }
         #[cfg(target_arch = "aarch64")]
         {
             // FADT flag: enable HW_REDUCED_ACPI bit on aarch64 plantform.
@@ -719,6 +842,85 @@ trait AcpiBuilder {
         Ok(slit_begin)
     }
 
+    /// Build ACPI VIOT table, returns the offset of ACPI VIOT table in `acpi_data`.
+    ///
+    /// # Arguments
+    ///
+    /// `iommu` - The virtio iommu device.
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    fn build_viot_table(
+        iommu: &Arc<Mutex<Iommu>>,
+        acpi_data: &Arc<Mutex<Vec<u8>>>,
+        loader: &mut TableLoader,
+    ) -> Result<u64> {
+        let locked_iommu = iommu.lock().unwrap();
+        let endpoints = locked_iommu.endpoints();
+        let mut viot = AcpiTable::new(*b"VIOT", 0, *b"STRATO", *b"VIRTVIOT", 1);
+        // Node count, node offset and reserved bytes.
+        viot.append_child(((endpoints.len() + 1) as u16).as_bytes());
+        viot.append_child(VIOT_NODE_OFFSET.as_bytes());
+        viot.append_child(&[0_u8; 8]);
+
+        // Virtio-pci iommu node: type, reserved, length, PCI segment, BDF and reserved bytes.
+        viot.append_child(&[ACPI_VIOT_NODE_VIRTIO_PCI_IOMMU, 0]);
+        viot.append_child(16_u16.as_bytes());
+        viot.append_child(0_u16.as_bytes());
+        viot.append_child(u16::from(locked_iommu.devfn()).as_bytes());
+        viot.append_child(&[0_u8; 8]);
+
+        // One PCI range node for each endpoint, whose endpoint ID is the BDF on the root bus.
+        for endpoint in endpoints {
+            viot.append_child(&[ACPI_VIOT_NODE_PCI_RANGE, 0]);
+            viot.append_child(24_u16.as_bytes());
+            // Endpoint start.
+            viot.append_child(endpoint.as_bytes());
+            // PCI segment start and end.
+            viot.append_child(0_u16.as_bytes());
+            viot.append_child(0_u16.as_bytes());
+            // BDF start and end.
+            viot.append_child((endpoint as u16).as_bytes());
+            viot.append_child((endpoint as u16).as_bytes());
+            // Output node, which is the virtio-pci iommu node.
+            viot.append_child(VIOT_NODE_OFFSET.as_bytes());
+            viot.append_child(&[0_u8; 6]);
+        }
+
+        let viot_begin = StdMachine::add_table_to_loader(acpi_data, loader, &viot)
+            .with_context(|| "Fail to add VIOT table to loader")?;
+        Ok(viot_begin)
+    }
+
+    /// Build ACPI HPET table, returns the offset of ACPI HPET table in `acpi_data`.
+    ///
+    /// # Arguments
+    ///
+    /// `hpet` - The HPET device.
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    #[cfg(target_arch = "x86_64")]
+    fn build_hpet_table(
+        hpet: &Arc<Mutex<Hpet>>,
+        acpi_data: &Arc<Mutex<Vec<u8>>>,
+        loader: &mut TableLoader,
+    ) -> Result<u64> {
+        let locked_hpet = hpet.lock().unwrap();
+        let mut table = AcpiTable::new(*b"HPET", 1, *b"STRATO", *b"VIRTHPET", 1);
+        // Event timer block ID, which is the low 32 bits of the capabilities register.
+        table.append_child((locked_hpet.capability() as u32).as_bytes());
+        table.append_child(
+            AcpiGenericAddress::new_mem_address(locked_hpet.region_base()).as_bytes(),
+        );
+        // HPET number, main counter minimum clock tick in periodic mode and page protection.
+        table.append_child(&[0_u8]);
+        table.append_child(0_u16.as_bytes());
+        table.append_child(&[0_u8]);
+
+        let hpet_begin = StdMachine::add_table_to_loader(acpi_data, loader, &table)
+            .with_context(|| "Fail to add HPET table to loader")?;
+        Ok(hpet_begin)
+    }
+
     /// Build ACPI XSDT table, returns the offset of ACPI XSDT table in `acpi_data`.
     ///
     /// # Arguments
@@ -817,6 +1019,51 @@ fn get_device_bdf(bus: Option<String>, addr: Option<String>) -> Result<PciBdf> {
 }
 
 impl StdMachine {
+    fn plug_pc_dimm_by_qmp(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let memdev = args.memdev.as_ref().with_context(|| "Memdev not set")?;
+        let dimm_cfg = self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .get_pc_dimm_config(&args.id, memdev, args.node)?;
+        if let Err(e) = self.plug_pc_dimm(&dimm_cfg, true) {
+            // Give back the memory backend, so that it can be used or deleted later.
+            self.get_vm_config()
+                .lock()
+                .unwrap()
+                .object
+                .mem_object
+                .insert(memdev.clone(), dimm_cfg.mem_cfg);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn plug_ivshmem_plain(
+        &mut self,
+        pci_bdf: &PciBdf,
+        args: &qmp_schema::DeviceAddArgument,
+    ) -> Result<()> {
+        let memdev = args.memdev.as_ref().with_context(|| "Memdev not set")?;
+        let dev_cfg = self.get_vm_config().lock().unwrap().get_ivshmem_config(
+            &args.id,
+            memdev,
+            args.size,
+            args.readonly.unwrap_or(false),
+        )?;
+        if let Err(e) = self.create_ivshmem_plain(&dev_cfg, pci_bdf) {
+            // Give back the memory backend, so that it can be used or deleted later.
+            self.get_vm_config()
+                .lock()
+                .unwrap()
+                .object
+                .mem_object
+                .insert(memdev.clone(), dev_cfg.mem_cfg);
+            return Err(e);
+        }
+        Ok(())
+    }
+
     fn plug_virtio_pci_blk(
         &mut self,
         pci_bdf: &PciBdf,
@@ -850,6 +1097,15 @@ impl StdMachine {
                 format: conf.format,
                 l2_cache_size: conf.l2_cache_size,
                 refcount_cache_size: conf.refcount_cache_size,
+                shard_iothreads: Vec::new(),
+                key_secret: conf
+                    .key_secret
+                    .as_ref()
+                    .map(|secret| locked_vmconfig.get_secret(secret))
+                    .transpose()?,
+                copy_on_read: conf.copy_on_read,
+                io_timeout: conf.io_timeout,
+                media: conf.media.clone(),
             };
             dev.check()?;
             dev
@@ -941,10 +1197,22 @@ impl StdMachine {
         dev.check()?;
         drop(locked_vmconfig);
 
+        if let Some(bootindex) = args.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add vhost user blk pci device for invalid bootindex")?;
+        }
+
         let blk = Arc::new(Mutex::new(VhostUser::Block::new(&dev, self.get_sys_mem())));
-        self.add_virtio_pci_device(&args.id, pci_bdf, blk, multifunction, true)
+        let pci_dev = self
+            .add_virtio_pci_device(&args.id, pci_bdf, blk, multifunction, true)
             .with_context(|| "Failed to add vhost user blk pci device")?;
 
+        if let Some(bootindex) = args.boot_index {
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &args.id);
+            }
+        }
+
         Ok(())
     }
 
@@ -976,6 +1244,63 @@ impl StdMachine {
         Ok(socket_path)
     }
 
+    fn plug_vhost_vsock_pci(
+        &mut self,
+        pci_bdf: &PciBdf,
+        args: &qmp_schema::DeviceAddArgument,
+    ) -> Result<()> {
+        let multifunction = args.multifunction.unwrap_or(false);
+        let guest_cid = args.guest_cid.with_context(|| "Guest cid not set")?;
+        let dev = VsockConfig {
+            id: args.id.clone(),
+            guest_cid,
+            vhost_fd: None,
+        };
+        dev.check()?;
+
+        let vsock = Arc::new(Mutex::new(VhostKern::Vsock::new(&dev, self.get_sys_mem())));
+        self.add_virtio_pci_device(&args.id, pci_bdf, vsock.clone(), multifunction, true)
+            .with_context(|| "Failed to add vhost vsock pci device")?;
+        MigrationManager::register_device_instance(
+            VhostKern::VsockState::descriptor(),
+            vsock,
+            &args.id,
+        );
+        Ok(())
+    }
+
+    fn plug_virtio_pci_rng(
+        &mut self,
+        pci_bdf: &PciBdf,
+        args: &qmp_schema::DeviceAddArgument,
+    ) -> Result<()> {
+        let multifunction = args.multifunction.unwrap_or(false);
+        let rng = args.rng.as_ref().with_context(|| "Rng object not set")?;
+        let vm_config = self.get_vm_config();
+        let random_file = vm_config
+            .lock()
+            .unwrap()
+            .object
+            .rng_object
+            .get(rng)
+            .map(|rng_object| rng_object.filename.clone())
+            .with_context(|| "Object for rng-random device not found")?;
+        let dev = RngConfig {
+            id: args.id.clone(),
+            random_file,
+            bytes_per_sec: rng_bytes_per_sec(args.max_bytes, args.period)?,
+        };
+        dev.check()?;
+
+        let rng_dev = Arc::new(Mutex::new(Rng::new(dev)));
+        self.add_virtio_pci_device(&args.id, pci_bdf, rng_dev.clone(), multifunction, false)
+            .with_context(|| "Failed to add virtio pci rng device")?;
+        // The rng object is used by only one device, as it's done for cmdline.
+        vm_config.lock().unwrap().object.rng_object.remove(rng);
+        MigrationManager::register_device_instance(RngState::descriptor(), rng_dev, &args.id);
+        Ok(())
+    }
+
     fn plug_virtio_pci_net(
         &mut self,
         pci_bdf: &PciBdf,
@@ -987,6 +1312,11 @@ impl StdMachine {
         let vm_config = self.get_vm_config();
         let mut locked_vmconfig = vm_config.lock().unwrap();
         let dev = if let Some(conf) = locked_vmconfig.netdevs.get(netdev) {
+            if args.driver == "vhost-user-net-pci"
+                && (conf.vhost_type != Some(String::from("vhost-user")) || conf.chardev.is_none())
+            {
+                bail!("vhost-user-net-pci needs a vhost-user netdev with chardev");
+            }
             let mut socket_path: Option<String> = None;
             if let Some(chardev) = &conf.chardev {
                 socket_path = self
@@ -995,6 +1325,7 @@ impl StdMachine {
             }
             let dev = NetworkInterfaceConfig {
                 id: args.id.clone(),
+                netdev: netdev.clone(),
                 host_dev_name: conf.ifname.clone(),
                 mac: args.mac.clone(),
                 tap_fds: conf.tap_fds.clone(),
@@ -1003,8 +1334,15 @@ impl StdMachine {
                 iothread: args.iothread.clone(),
                 queues: conf.queues,
                 mq: conf.queues > 2,
+                sndbuf: conf.sndbuf,
+                poll_us: conf.poll_us,
+                zerocopy: conf.zerocopy,
                 socket_path,
                 queue_size,
+                romfile: args.romfile.clone(),
+                boot_index: args.boot_index,
+                max_frames: args.max_frames.unwrap_or_default(),
+                max_usecs: args.max_usecs.unwrap_or_default(),
             };
             dev.check()?;
             dev
@@ -1014,21 +1352,66 @@ impl StdMachine {
         locked_vmconfig.add_net_device_config(args);
         drop(locked_vmconfig);
 
-        if dev.vhost_type.is_some() {
-            let net: Arc<Mutex<dyn VirtioDevice>> =
-                if dev.vhost_type == Some(String::from("vhost-kernel")) {
-                    Arc::new(Mutex::new(VhostKern::Net::new(&dev, self.get_sys_mem())))
-                } else {
-                    Arc::new(Mutex::new(VhostUser::Net::new(&dev, self.get_sys_mem())))
-                };
-            self.add_virtio_pci_device(&args.id, pci_bdf, net, multifunction, true)
-                .with_context(|| "Failed to add vhost-kernel/vhost-user net device")?;
+        if let Some(bootindex) = args.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add virtio pci net device for invalid bootindex")?;
+        }
+
+        let romfile = dev.romfile.clone();
+        let pci_dev = if dev.vhost_type == Some(String::from("vhost-kernel")) {
+            let net_id = dev.id.clone();
+            let net = Arc::new(Mutex::new(VhostKern::Net::new(&dev, self.get_sys_mem())));
+            let pci_dev = self
+                .add_virtio_pci_device_with_opts(
+                    &args.id,
+                    pci_bdf,
+                    net.clone(),
+                    multifunction,
+                    true,
+                    romfile,
+                    VirtioPciMode::Modern,
+                )
+                .with_context(|| "Failed to add vhost-kernel net device")?;
+            MigrationManager::register_device_instance(
+                VhostKern::VhostNetState::descriptor(),
+                net,
+                &net_id,
+            );
+            pci_dev
+        } else if dev.vhost_type.is_some() {
+            let net = Arc::new(Mutex::new(VhostUser::Net::new(&dev, self.get_sys_mem())));
+            self.add_virtio_pci_device_with_opts(
+                &args.id,
+                pci_bdf,
+                net,
+                multifunction,
+                true,
+                romfile,
+                VirtioPciMode::Modern,
+            )
+            .with_context(|| "Failed to add vhost-user net device")?
         } else {
             let net_id = dev.id.clone();
             let net = Arc::new(Mutex::new(virtio::Net::new(dev)));
-            self.add_virtio_pci_device(&args.id, pci_bdf, net.clone(), multifunction, false)
+            let pci_dev = self
+                .add_virtio_pci_device_with_opts(
+                    &args.id,
+                    pci_bdf,
+                    net.clone(),
+                    multifunction,
+                    false,
+                    romfile,
+                    VirtioPciMode::Modern,
+                )
                 .with_context(|| "Failed to add virtio net device")?;
             MigrationManager::register_device_instance(VirtioNetState::descriptor(), net, &net_id);
+            pci_dev
+        };
+
+        if let Some(bootindex) = args.boot_index {
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &args.id);
+            }
         }
 
         Ok(())
@@ -1090,10 +1473,72 @@ impl StdMachine {
         Ok(())
     }
 
+    fn plug_scsi_device(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let drive = args.drive.as_ref().with_context(|| "Drive not set")?;
+        let bus = args.bus.as_ref().with_context(|| "Bus not set")?;
+        let mut cfg_args = format!("{},id={},bus={},drive={}", args.driver, args.id, bus, drive);
+        if let Some(target) = args.scsi_id {
+            cfg_args = format!("{},scsi-id={}", cfg_args, target);
+        }
+        if let Some(lun) = args.lun {
+            cfg_args = format!("{},lun={}", cfg_args, lun);
+        }
+        if let Some(serial) = args.serial_num.as_ref() {
+            cfg_args = format!("{},serial={}", cfg_args, serial);
+        }
+        if let Some(bootindex) = args.boot_index {
+            cfg_args = format!("{},bootindex={}", cfg_args, bootindex);
+        }
+        let scsi_type = if args.driver == "scsi-cd" {
+            SCSI_TYPE_ROM
+        } else {
+            SCSI_TYPE_DISK
+        };
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        for (_, dev_args) in locked_vmconfig.devices.iter() {
+            if parse_device_id(dev_args).is_ok_and(|id| id == args.id) {
+                bail!("Device id {} existed", args.id);
+            }
+        }
+        // The drive is taken by the scsi device config, put it back so that it can be
+        // deleted by blockdev-del after the device is unplugged.
+        let drive_cfg = locked_vmconfig
+            .drives
+            .get(drive)
+            .cloned()
+            .with_context(|| "Drive not found")?;
+        let ret = self.add_scsi_device(&mut locked_vmconfig, &cfg_args, scsi_type, true);
+        locked_vmconfig.drives.insert(drive.clone(), drive_cfg);
+        ret
+    }
+
+    /// Unplug the scsi device, returns false if it's not a scsi device.
+    fn handle_unplug_scsi_request(&mut self, id: &str) -> Result<bool> {
+        let vm_config = self.get_vm_config();
+        let locked_vmconfig = vm_config.lock().unwrap();
+        if !self.unplug_scsi_device(&locked_vmconfig, id)? {
+            return Ok(false);
+        }
+        drop(locked_vmconfig);
+        self.del_bootindex_devices(id);
+        self.reset_fwcfg_boot_order()?;
+        self.get_vm_config()
+            .lock()
+            .unwrap()
+            .del_device_by_id(id.to_string());
+        Ok(true)
+    }
+
     fn handle_unplug_usb_request(&mut self, id: String) -> Result<()> {
         let vm_config = self.get_vm_config();
         let mut locked_vmconfig = vm_config.lock().unwrap();
-        self.detach_usb_from_xhci_controller(&mut locked_vmconfig, id)?;
+        self.detach_usb_from_xhci_controller(&mut locked_vmconfig, id.clone())?;
+        drop(locked_vmconfig);
+        self.del_bootindex_devices(&id);
+        self.reset_fwcfg_boot_order()?;
+        self.get_vm_config().lock().unwrap().del_device_by_id(id);
 
         Ok(())
     }
@@ -1138,100 +1583,644 @@ impl StdMachine {
             shutdown_req,
         );
     }
-}
 
-impl DeviceInterface for StdMachine {
-    fn query_status(&self) -> Response {
-        let vm_state = self.get_vm_state();
-        let vmstate = vm_state.deref().0.lock().unwrap();
-        let qmp_state = match *vmstate {
-            KvmVmState::Running => qmp_schema::StatusInfo {
-                singlestep: false,
-                running: true,
-                status: qmp_schema::RunState::running,
-            },
-            KvmVmState::Paused => qmp_schema::StatusInfo {
-                singlestep: false,
-                running: false,
-                status: qmp_schema::RunState::paused,
-            },
-            _ => Default::default(),
+    fn nbd_export_drive(&self, args: &qmp_schema::NbdServerAddArgument) -> Result<()> {
+        let writable = args.writable.unwrap_or(false);
+        let name = args.name.clone().unwrap_or_else(|| args.device.clone());
+        let vm_config = self.get_vm_config();
+        let locked_vmconfig = vm_config.lock().unwrap();
+        let drive = locked_vmconfig
+            .drives
+            .get(&args.device)
+            .with_context(|| format!("Drive {} not found", args.device))?;
+        if writable && drive.read_only {
+            bail!("Drive {} is read-only", args.device);
+        }
+        let key_secret = drive
+            .key_secret
+            .as_ref()
+            .map(|secret| locked_vmconfig.get_secret(secret))
+            .transpose()?;
+
+        let files = self.get_drive_files();
+        let drive_files = files.lock().unwrap();
+        let file = VmConfig::fetch_drive_file(&drive_files, &drive.path_on_host)?;
+        let (req_align, buf_align) =
+            VmConfig::fetch_drive_align(&drive_files, &drive.path_on_host)?;
+        let prop = BlockProperty {
+            id: format!("nbd-{}", name),
+            format: drive.format,
+            iothread: None,
+            direct: drive.direct,
+            req_align,
+            buf_align,
+            discard: drive.discard,
+            write_zeroes: drive.write_zeroes,
+            l2_cache_size: drive.l2_cache_size,
+            refcount_cache_size: drive.refcount_cache_size,
+            key_secret,
+            copy_on_read: false,
+            io_timeout: None,
         };
-
-        Response::create_response(serde_json::to_value(qmp_state).unwrap(), None)
+        nbd_server_add(&name, file, prop, !writable)
     }
 
-    fn query_cpus(&self) -> Response {
-        let mut cpu_vec: Vec<serde_json::Value> = Vec::new();
-        let cpu_topo = self.get_cpu_topo();
-        let cpus = self.get_cpus();
-        for cpu_index in 0..cpu_topo.max_cpus {
-            if cpu_topo.get_mask(cpu_index as usize) == 1 {
-                let thread_id = cpus[cpu_index as usize].tid();
-                let cpu_instance = cpu_topo.get_topo_instance_for_qmp(cpu_index as usize);
-                let cpu_common = qmp_schema::CpuInfoCommon {
-                    current: true,
-                    qom_path: String::from("/machine/unattached/device[")
-                        + &cpu_index.to_string()
-                        + "]",
-                    halted: false,
-                    props: Some(cpu_instance),
-                    CPU: cpu_index as isize,
-                    thread_id: thread_id as isize,
-                };
-                #[cfg(target_arch = "x86_64")]
-                {
-                    let cpu_info = qmp_schema::CpuInfo::x86 {
-                        common: cpu_common,
-                        x86: qmp_schema::CpuInfoX86 {},
-                    };
-                    cpu_vec.push(serde_json::to_value(cpu_info).unwrap());
-                }
-                #[cfg(target_arch = "aarch64")]
-                {
-                    let cpu_info = qmp_schema::CpuInfo::Arm {
-                        common: cpu_common,
-                        arm: qmp_schema::CpuInfoArm {},
-                    };
-                    cpu_vec.push(serde_json::to_value(cpu_info).unwrap());
+    /// Find the virtio block device which the drive is attached to.
+    fn get_block_by_drive(&mut self, drive_id: &str) -> Option<Arc<Mutex<dyn VirtioDevice>>> {
+        let vm_config = self.get_vm_config();
+        let locked_vmconfig = vm_config.lock().unwrap();
+        let dev_id = locked_vmconfig
+            .devices
+            .iter()
+            .filter(|(driver, _)| driver == "virtio-blk-pci")
+            .find_map(|(_, cfg_args)| {
+                let mut id = None;
+                let mut drive = None;
+                for param in cfg_args.split(',') {
+                    if let Some(value) = param.strip_prefix("id=") {
+                        id = Some(value.to_string());
+                    } else if let Some(value) = param.strip_prefix("drive=") {
+                        drive = Some(value);
+                    }
                 }
-            }
-        }
-        Response::create_response(cpu_vec.into(), None)
-    }
+                id.filter(|_| drive == Some(drive_id))
+            })?;
+        drop(locked_vmconfig);
 
-    fn query_hotpluggable_cpus(&self) -> Response {
-        Response::create_empty_response()
+        let pci_host = self.get_pci_host().ok()?;
+        let locked_pci_host = pci_host.lock().unwrap();
+        let (_, dev) = PciBus::find_attached_bus(&locked_pci_host.root_bus, &dev_id)?;
+        let locked_dev = dev.lock().unwrap();
+        let virtio_pcidev = locked_dev.as_any().downcast_ref::<VirtioPciDevice>()?;
+        Some(virtio_pcidev.get_virtio_device().clone())
     }
 
-    fn balloon(&self, value: u64) -> Response {
-        if qmp_balloon(value) {
-            return Response::create_empty_response();
+    fn get_net_by_id(&mut self, dev_id: &str) -> Result<Arc<Mutex<dyn VirtioDevice>>> {
+        let pci_host = self.get_pci_host()?;
+        let locked_pci_host = pci_host.lock().unwrap();
+        let (_, dev) = PciBus::find_attached_bus(&locked_pci_host.root_bus, dev_id)
+            .with_context(|| format!("Device {} not found", dev_id))?;
+        let locked_dev = dev.lock().unwrap();
+        let virtio_dev = locked_dev
+            .as_any()
+            .downcast_ref::<VirtioPciDevice>()
+            .with_context(|| format!("Device {} is not a virtio-net device", dev_id))?
+            .get_virtio_device()
+            .clone();
+        if virtio_dev
+            .lock()
+            .unwrap()
+            .as_any_mut()
+            .downcast_mut::<Net>()
+            .is_none()
+        {
+            bail!("Device {} is not a virtio-net device", dev_id);
         }
-        Response::create_error_response(
-            qmp_schema::QmpErrorClass::DeviceNotActive(
-                "No balloon device has been activated".to_string(),
-            ),
-            None,
-        )
+        Ok(virtio_dev)
     }
 
-    fn query_balloon(&self) -> Response {
-        if let Some(actual) = qmp_query_balloon() {
-            let ret = qmp_schema::BalloonInfo { actual };
-            return Response::create_response(serde_json::to_value(ret).unwrap(), None);
-        }
-        Response::create_error_response(
-            qmp_schema::QmpErrorClass::DeviceNotActive(
-                "No balloon device has been activated".to_string(),
-            ),
-            None,
-        )
+    fn set_net_link(&mut self, dev_id: &str, up: bool) -> Result<()> {
+        let net = self.get_net_by_id(dev_id)?;
+        let mut locked_net = net.lock().unwrap();
+        locked_net
+            .as_any_mut()
+            .downcast_mut::<Net>()
+            .unwrap()
+            .set_link_status(up)
     }
 
-    fn query_mem(&self) -> Response {
-        self.mem_show();
-        Response::create_empty_response()
+    fn set_net_mac(&mut self, dev_id: &str, mac: &str) -> Result<()> {
+        if !check_mac_address(mac) {
+            bail!("Invalid mac address {}", mac);
+        }
+        let net = self.get_net_by_id(dev_id)?;
+        let mut locked_net = net.lock().unwrap();
+        locked_net
+            .as_any_mut()
+            .downcast_mut::<Net>()
+            .unwrap()
+            .set_mac_address(mac)
+    }
+
+    fn snapshot_drive(&mut self, args: &qmp_schema::BlockdevSnapshotSyncArgument) -> Result<()> {
+        if args.format.as_ref().is_some_and(|fmt| fmt != "qcow2") {
+            bail!("Only qcow2 format is supported for snapshot");
+        }
+        if mirror_job_active(&args.device) {
+            bail!("Drive {} is in use by block job", args.device);
+        }
+        let vm_config = self.get_vm_config();
+        let drive = vm_config
+            .lock()
+            .unwrap()
+            .drives
+            .get(&args.device)
+            .cloned()
+            .with_context(|| format!("Drive {} not found", args.device))?;
+        if drive.format == DiskFormat::Luks {
+            bail!(
+                "Drive {} of format luks does not support snapshot",
+                args.device
+            );
+        }
+        let block = self
+            .get_block_by_drive(&args.device)
+            .with_context(|| format!("Drive {} is not attached to block device", args.device))?;
+
+        let backing_file = std::fs::canonicalize(&drive.path_on_host)
+            .with_context(|| format!("Failed to get the path of {}", drive.path_on_host))?;
+        create_qcow2_overlay(
+            &args.snapshot_file,
+            &backing_file.to_string_lossy(),
+            drive.format,
+        )?;
+        let result = self
+            .register_drive_file(
+                &args.device,
+                &args.snapshot_file,
+                drive.read_only,
+                drive.direct,
+            )
+            .and_then(|()| {
+                let mut locked_block = block.lock().unwrap();
+                let result = locked_block
+                    .as_any_mut()
+                    .downcast_mut::<Block>()
+                    .with_context(|| "Device is not a virtio block device")?
+                    .snapshot_sync(&args.snapshot_file);
+                if result.is_err() {
+                    // It's safe to unwrap as the path has been registered.
+                    self.unregister_drive_file(&args.snapshot_file).unwrap();
+                }
+                result
+            });
+        if let Err(e) = result {
+            std::fs::remove_file(&args.snapshot_file)
+                .unwrap_or_else(|e| error!("Failed to remove {}: {:?}", args.snapshot_file, e));
+            return Err(e);
+        }
+
+        // The old image is opened by the overlay as backing file.
+        self.unregister_drive_file(&drive.path_on_host)?;
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        if let Some(drive) = locked_vmconfig.drives.get_mut(&args.device) {
+            drive.path_on_host = args.snapshot_file.clone();
+            drive.format = DiskFormat::Qcow2;
+        }
+        Ok(())
+    }
+
+    /// Get the virtio block device of the cdrom drive.
+    fn get_cdrom_block(
+        &mut self,
+        device: &str,
+    ) -> Result<(DriveConfig, Arc<Mutex<dyn VirtioDevice>>)> {
+        let drive = self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .drives
+            .get(device)
+            .cloned()
+            .with_context(|| format!("Drive {} not found", device))?;
+        if drive.media != "cdrom" {
+            bail!("Drive {} is not a cdrom", device);
+        }
+        if mirror_job_active(device) {
+            bail!("Drive {} is in use by block job", device);
+        }
+        let block = self
+            .get_block_by_drive(device)
+            .with_context(|| format!("Drive {} is not attached to block device", device))?;
+        Ok((drive, block))
+    }
+
+    fn eject_drive(&mut self, args: &qmp_schema::EjectArgument) -> Result<()> {
+        let (drive, block) = self.get_cdrom_block(&args.device)?;
+        if drive.path_on_host.is_empty() {
+            return Ok(());
+        }
+        block
+            .lock()
+            .unwrap()
+            .as_any_mut()
+            .downcast_mut::<Block>()
+            .with_context(|| "Device is not a virtio block device")?
+            .eject_medium()?;
+
+        self.unregister_drive_file(&drive.path_on_host)?;
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        if let Some(drive) = locked_vmconfig.drives.get_mut(&args.device) {
+            drive.path_on_host = String::new();
+        }
+        Ok(())
+    }
+
+    fn change_drive(&mut self, args: &qmp_schema::ChangeArgument) -> Result<()> {
+        let format = match args.arg.as_ref() {
+            Some(fmt) => DiskFormat::from_str(fmt)?,
+            None => DiskFormat::Raw,
+        };
+        let (drive, block) = self.get_cdrom_block(&args.device)?;
+        if drive.path_on_host == args.target {
+            bail!("Medium {} is already inserted", args.target);
+        }
+        // The old medium is still registered, and is removed after the new one is inserted.
+        self.register_drive_file(&args.device, &args.target, true, drive.direct)?;
+        let result = block
+            .lock()
+            .unwrap()
+            .as_any_mut()
+            .downcast_mut::<Block>()
+            .with_context(|| "Device is not a virtio block device")
+            .and_then(|block| block.insert_medium(&args.target, format));
+        if let Err(e) = result {
+            // It's safe to unwrap as the path has been registered.
+            self.unregister_drive_file(&args.target).unwrap();
+            return Err(e);
+        }
+
+        if !drive.path_on_host.is_empty() {
+            self.unregister_drive_file(&drive.path_on_host)?;
+        }
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        if let Some(drive) = locked_vmconfig.drives.get_mut(&args.device) {
+            drive.path_on_host = args.target.clone();
+            drive.format = format;
+        }
+        Ok(())
+    }
+
+    fn mirror_drive(&mut self, args: &qmp_schema::DriveMirrorArgument) -> Result<()> {
+        if args.sync.as_ref().is_some_and(|sync| sync != "full") {
+            bail!("Only full sync is supported for mirror");
+        }
+        let drive = self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .drives
+            .get(&args.device)
+            .cloned()
+            .with_context(|| format!("Drive {} not found", args.device))?;
+        if drive.format == DiskFormat::Luks {
+            bail!(
+                "Drive {} of format luks does not support mirror",
+                args.device
+            );
+        }
+        let format = match args.format.as_ref() {
+            Some(fmt) => DiskFormat::from_str(fmt)?,
+            None => drive.format,
+        };
+        self.get_block_by_drive(&args.device)
+            .with_context(|| format!("Drive {} is not attached to block device", args.device))?;
+        let bitmaps = DIRTY_BITMAP_LIST
+            .lock()
+            .unwrap()
+            .get(&args.device)
+            .cloned()
+            .with_context(|| format!("Drive {} is not opened", args.device))?;
+        let source = match drive.format {
+            DiskFormat::Qcow2 => MirrorSource::Qcow2(
+                QCOW2_LIST
+                    .lock()
+                    .unwrap()
+                    .get(&args.device)
+                    .cloned()
+                    .with_context(|| format!("Drive {} is not opened", args.device))?,
+            ),
+            _ => MirrorSource::Raw(
+                std::fs::File::open(&drive.path_on_host)
+                    .with_context(|| format!("Failed to open {}", drive.path_on_host))?,
+            ),
+        };
+        let job_id = args.job_id.as_ref().unwrap_or(&args.device);
+        mirror_start(job_id, &args.device, &args.target, format, source, bitmaps)
+    }
+
+    /// Switch the drive to the target image of the ready mirror job.
+    fn complete_mirror(&mut self, job_id: &str) -> Result<()> {
+        let job = mirror_job(job_id)?;
+        if !job.is_ready() {
+            bail!("Block job {} is not ready", job_id);
+        }
+        let vm_config = self.get_vm_config();
+        let drive = vm_config
+            .lock()
+            .unwrap()
+            .drives
+            .get(&job.device)
+            .cloned()
+            .with_context(|| format!("Drive {} not found", job.device))?;
+        let block = self
+            .get_block_by_drive(&job.device)
+            .with_context(|| format!("Drive {} is not attached to block device", job.device))?;
+
+        job.stop();
+        let result = self
+            .register_drive_file(&job.device, &job.target, drive.read_only, drive.direct)
+            .and_then(|()| {
+                let mut locked_block = block.lock().unwrap();
+                let result = locked_block
+                    .as_any_mut()
+                    .downcast_mut::<Block>()
+                    .with_context(|| "Device is not a virtio block device")?
+                    .replace_backend(&job.target, job.format, || job.sync_target());
+                if result.is_err() {
+                    // It's safe to unwrap as the path has been registered.
+                    self.unregister_drive_file(&job.target).unwrap();
+                }
+                result
+            });
+        if let Err(e) = result {
+            job.start()
+                .unwrap_or_else(|e| error!("Failed to restart block job {}: {:?}", job_id, e));
+            return Err(e);
+        }
+
+        mirror_finish(job_id, false)?;
+        self.unregister_drive_file(&drive.path_on_host)?;
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        if let Some(drive) = locked_vmconfig.drives.get_mut(&job.device) {
+            drive.path_on_host = job.target.clone();
+            drive.format = job.format;
+        }
+        Ok(())
+    }
+}
+
+impl DeviceInterface for StdMachine {
+    fn query_status(&self) -> Response {
+        let vm_state = self.get_vm_state();
+        let vmstate = vm_state.deref().0.lock().unwrap();
+        let qmp_state = match *vmstate {
+            KvmVmState::Running => qmp_schema::StatusInfo {
+                singlestep: false,
+                running: true,
+                status: qmp_schema::RunState::running,
+            },
+            KvmVmState::Paused => qmp_schema::StatusInfo {
+                singlestep: false,
+                running: false,
+                status: qmp_schema::RunState::paused,
+            },
+            KvmVmState::Suspended => qmp_schema::StatusInfo {
+                singlestep: false,
+                running: false,
+                status: qmp_schema::RunState::suspended,
+            },
+            _ => Default::default(),
+        };
+
+        Response::create_response(serde_json::to_value(qmp_state).unwrap(), None)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn system_wakeup(&self) -> Response {
+        match self.wakeup() {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn query_cpus(&self) -> Response {
+        let mut cpu_vec: Vec<serde_json::Value> = Vec::new();
+        let cpu_topo = self.get_cpu_topo();
+        let cpus = self.get_cpus();
+        for cpu_index in 0..cpu_topo.max_cpus {
+            if cpu_topo.get_mask(cpu_index as usize) == 1 {
+                let thread_id = cpus[cpu_index as usize].tid();
+                let cpu_instance = cpu_topo.get_topo_instance_for_qmp(cpu_index as usize);
+                let cpu_common = qmp_schema::CpuInfoCommon {
+                    current: true,
+                    qom_path: String::from("/machine/unattached/device[")
+                        + &cpu_index.to_string()
+                        + "]",
+                    halted: false,
+                    props: Some(cpu_instance),
+                    CPU: cpu_index as isize,
+                    thread_id: thread_id as isize,
+                };
+                #[cfg(target_arch = "x86_64")]
+                {
+                    let cpu_info = qmp_schema::CpuInfo::x86 {
+                        common: cpu_common,
+                        x86: cpu_info_x86(&cpus[cpu_index as usize]),
+                    };
+                    cpu_vec.push(serde_json::to_value(cpu_info).unwrap());
+                }
+                #[cfg(target_arch = "aarch64")]
+                {
+                    let cpu_info = qmp_schema::CpuInfo::Arm {
+                        common: cpu_common,
+                        arm: qmp_schema::CpuInfoArm {},
+                    };
+                    cpu_vec.push(serde_json::to_value(cpu_info).unwrap());
+                }
+            }
+        }
+        Response::create_response(cpu_vec.into(), None)
+    }
+
+    fn query_hotpluggable_cpus(&self) -> Response {
+        Response::create_empty_response()
+    }
+
+    fn balloon(&self, value: u64) -> Response {
+        if qmp_balloon(value) {
+            return Response::create_empty_response();
+        }
+        Response::create_error_response(
+            qmp_schema::QmpErrorClass::DeviceNotActive(
+                "No balloon device has been activated".to_string(),
+            ),
+            None,
+        )
+    }
+
+    fn query_balloon(&self) -> Response {
+        if let Some(ret) = qmp_query_balloon() {
+            return Response::create_response(serde_json::to_value(ret).unwrap(), None);
+        }
+        Response::create_error_response(
+            qmp_schema::QmpErrorClass::DeviceNotActive(
+                "No balloon device has been activated".to_string(),
+            ),
+            None,
+        )
+    }
+
+    fn balloon_cancel(&self) -> Response {
+        match qmp_balloon_cancel() {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_balloon_stats_interval(&self, interval: u32) -> Response {
+        match qmp_balloon_stats_interval(interval) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_balloon_stats(&self) -> Response {
+        match qmp_query_balloon_stats() {
+            Ok(stats) => Response::create_response(serde_json::to_value(stats).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_balloon_policy(&self, args: qmp_schema::BalloonPolicyArgument) -> Response {
+        match set_balloon_policy(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_balloon_policy(&self) -> Response {
+        match query_balloon_policy() {
+            Ok(info) => Response::create_response(serde_json::to_value(info).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn reclaim_guest_memory(&mut self, args: qmp_schema::ReclaimGuestMemoryArgument) -> Response {
+        match self.reclaim_guest_ram(args.mode.as_deref()) {
+            Ok(size) => {
+                let info = qmp_schema::ReclaimGuestMemoryInfo { size };
+                Response::create_response(serde_json::to_value(info).unwrap(), None)
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_halt_poll(&self, args: qmp_schema::HaltPollArgument) -> Response {
+        match set_halt_poll(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_halt_poll(&self) -> Response {
+        Response::create_response(serde_json::to_value(query_halt_poll()).unwrap(), None)
+    }
+
+    fn cpu_throttle_set(&self, args: qmp_schema::CpuThrottleArgument) -> Response {
+        match cpu_throttle_set(args.percentage) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_stats(&self, args: qmp_schema::QueryStatsArgument) -> Response {
+        query_stats(self.get_cpus(), self.get_cpu_topo(), args)
+    }
+
+    fn query_interrupts(&self) -> Response {
+        let interrupts: Vec<qmp_schema::InterruptInfo> = query_interrupt_stats()
+            .into_iter()
+            .map(|stat| qmp_schema::InterruptInfo {
+                device: stat.device,
+                int_type: stat.int_type,
+                vector: stat.vector,
+                count: stat.count,
+            })
+            .collect();
+        Response::create_response(serde_json::to_value(interrupts).unwrap(), None)
+    }
+
+    fn query_vm_config(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let locked_config = vm_config.lock().unwrap();
+        match serde_json::to_value(&*locked_config) {
+            Ok(value) => Response::create_response(value, None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn query_uuid(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let uuid = match vm_config.lock().unwrap().uuid.as_ref() {
+            Some(uuid) => uuid.to_string(),
+            None => "00000000-0000-0000-0000-000000000000".to_string(),
+        };
+        let uuid_info = qmp_schema::UuidInfo { uuid };
+        Response::create_response(serde_json::to_value(uuid_info).unwrap(), None)
+    }
+
+    fn query_name(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let guest_name = &vm_config.lock().unwrap().guest_name;
+        let name_info = qmp_schema::NameInfo {
+            name: (!guest_name.is_empty()).then(|| guest_name.clone()),
+        };
+        Response::create_response(serde_json::to_value(name_info).unwrap(), None)
+    }
+
+    fn pflash_seal(&mut self, args: qmp_schema::PFlashSealArgument) -> Response {
+        let pflash = match self.get_pflash(args.unit) {
+            Some(pflash) => pflash,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                        "PFlash unit {} not found",
+                        args.unit
+                    )),
+                    None,
+                );
+            }
+        };
+        let sealed = args.sealed.unwrap_or(true);
+        let result = pflash.lock().unwrap().set_sealed(sealed);
+        match result {
+            Ok(()) => {
+                info!("PFlash unit {} is sealed: {}", args.unit, sealed);
+                Response::create_empty_response()
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_mem(&self) -> Response {
+        self.mem_show();
+        Response::create_empty_response()
     }
 
     fn query_vnc(&self) -> Response {
@@ -1247,6 +2236,37 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    #[cfg(feature = "display_channel")]
+    fn display_channel_add(&mut self, args: qmp_schema::DisplayChannelAddArgument) -> Response {
+        let encoder = args.encoder.unwrap_or_else(|| "raw".to_string());
+        match display_channel_add(&args.id, &args.path, &encoder) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    #[cfg(feature = "display_channel")]
+    fn display_channel_del(&mut self, id: String) -> Response {
+        match display_channel_del(&id) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    #[cfg(feature = "display_channel")]
+    fn query_display_channels(&self) -> Response {
+        Response::create_response(
+            serde_json::to_value(query_display_channels()).unwrap(),
+            None,
+        )
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
         if let Err(e) = self.check_device_id_existed(&args.id) {
             return Response::create_error_response(
@@ -1299,7 +2319,7 @@ impl DeviceInterface for StdMachine {
                     );
                 }
             }
-            "virtio-net-pci" => {
+            "virtio-net-pci" | "vhost-user-net-pci" => {
                 if let Err(e) = self.plug_virtio_pci_net(&pci_bdf, args.as_ref()) {
                     error!("{:?}", e);
                     let err_str = format!("Failed to add virtio pci net: {}", e);
@@ -1309,6 +2329,25 @@ impl DeviceInterface for StdMachine {
                     );
                 }
             }
+            "vhost-vsock-pci" | "virtio-rng-pci" => {
+                let ret = if driver == "vhost-vsock-pci" {
+                    self.plug_vhost_vsock_pci(&pci_bdf, args.as_ref())
+                } else {
+                    self.plug_virtio_pci_rng(&pci_bdf, args.as_ref())
+                };
+                if let Err(e) = ret {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add {}: {}", driver, e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+                self.get_vm_config()
+                    .lock()
+                    .unwrap()
+                    .add_device_by_qmp(args.as_ref());
+            }
             "vfio-pci" => {
                 if let Err(e) = self.plug_vfio_pci_device(&pci_bdf, args.as_ref()) {
                     error!("{:?}", e);
@@ -1318,6 +2357,49 @@ impl DeviceInterface for StdMachine {
                     );
                 }
             }
+            "pc-dimm" => {
+                if let Err(e) = self.plug_pc_dimm_by_qmp(args.as_ref()) {
+                    error!("{:?}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                        None,
+                    );
+                }
+                self.get_vm_config()
+                    .lock()
+                    .unwrap()
+                    .add_device_by_qmp(args.as_ref());
+                return Response::create_empty_response();
+            }
+            "ivshmem-plain" => {
+                if let Err(e) = self.plug_ivshmem_plain(&pci_bdf, args.as_ref()) {
+                    error!("{:?}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+            }
+            "scsi-hd" | "scsi-cd" => {
+                if let Err(e) = self.plug_scsi_device(args.as_ref()) {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add {}: {}", driver, e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+                self.get_vm_config()
+                    .lock()
+                    .unwrap()
+                    .add_device_by_qmp(args.as_ref());
+                if args.boot_index.is_some() {
+                    if let Err(e) = self.reset_fwcfg_boot_order() {
+                        error!("Failed to update boot order: {:?}", e);
+                    }
+                }
+                return Response::create_empty_response();
+            }
             "usb-kbd" | "usb-tablet" | "usb-camera" | "usb-host" => {
                 if let Err(e) = self.plug_usb_device(args.as_ref()) {
                     error!("{:?}", e);
@@ -1326,6 +2408,10 @@ impl DeviceInterface for StdMachine {
                         None,
                     );
                 }
+                self.get_vm_config()
+                    .lock()
+                    .unwrap()
+                    .add_device_by_qmp(args.as_ref());
                 return Response::create_empty_response();
             }
             _ => {
@@ -1341,8 +2427,22 @@ impl DeviceInterface for StdMachine {
         let locked_pci_host = self.get_pci_host().unwrap().lock().unwrap();
         if let Some((bus, dev)) = PciBus::find_attached_bus(&locked_pci_host.root_bus, &args.id) {
             match handle_plug(&bus, &dev) {
-                Ok(()) => Response::create_empty_response(),
+                Ok(()) => {
+                    drop(locked_pci_host);
+                    self.get_vm_config()
+                        .lock()
+                        .unwrap()
+                        .add_device_by_qmp(args.as_ref());
+                    if args.boot_index.is_some() {
+                        if let Err(e) = self.reset_fwcfg_boot_order() {
+                            error!("Failed to update boot order: {:?}", e);
+                        }
+                    }
+                    Response::create_empty_response()
+                }
                 Err(e) => {
+                    drop(locked_pci_host);
+                    self.del_bootindex_devices(&args.id);
                     if let Err(e) = PciBus::detach_device(&bus, &dev) {
                         error!("{:?}", e);
                         error!("Failed to detach device");
@@ -1383,6 +2483,9 @@ impl DeviceInterface for StdMachine {
                     let dev_id = &locked_dev.name();
                     drop(locked_pci_host);
                     self.del_bootindex_devices(dev_id);
+                    if let Err(e) = self.reset_fwcfg_boot_order() {
+                        error!("Failed to update boot order: {:?}", e);
+                    }
                     let vm_config = self.get_vm_config();
                     let mut locked_config = vm_config.lock().unwrap();
                     locked_config.del_device_by_id(device_id);
@@ -1397,7 +2500,18 @@ impl DeviceInterface for StdMachine {
         }
         drop(locked_pci_host);
 
-        // The device is not a pci device, assume it is a usb device.
+        match self.handle_unplug_scsi_request(&device_id) {
+            Ok(true) => return Response::create_empty_response(),
+            Ok(false) => (),
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                )
+            }
+        }
+
+        // The device is neither a pci device nor a scsi device, assume it is a usb device.
         match self.handle_unplug_usb_request(device_id) {
             Ok(()) => Response::create_empty_response(),
             Err(e) => Response::create_error_response(
@@ -1407,62 +2521,293 @@ impl DeviceInterface for StdMachine {
         }
     }
 
-    fn blockdev_add(&self, args: Box<qmp_schema::BlockDevAddArgument>) -> Response {
-        let config = match parse_blockdev(&args) {
-            Ok(config) => config,
-            Err(e) => {
-                error!("{:?}", e);
+    fn blockdev_add(&self, args: Box<qmp_schema::BlockDevAddArgument>) -> Response {
+        let config = match parse_blockdev(&args) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("{:?}", e);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+
+        // Register drive backend file for hotplug drive.
+        if let Err(e) = self.register_drive_file(
+            &config.id,
+            &args.file.filename,
+            config.read_only,
+            config.direct,
+        ) {
+            error!("{:?}", e);
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+        match self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .add_drive_with_config(config)
+        {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => {
+                error!("{:?}", e);
+                // It's safe to unwrap as the path has been registered.
+                self.unregister_drive_file(&args.file.filename).unwrap();
+                Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                )
+            }
+        }
+    }
+
+    fn blockdev_del(&self, node_name: String) -> Response {
+        if mirror_job_active(&node_name) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Drive {} is in use by block job",
+                    node_name
+                )),
+                None,
+            );
+        }
+        match self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .del_drive_by_id(&node_name)
+        {
+            Ok(path) => {
+                // It's safe to unwrap as the path has been registered.
+                self.unregister_drive_file(&path).unwrap();
+                Response::create_empty_response()
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn chardev_add(&mut self, args: qmp_schema::CharDevAddArgument) -> Response {
+        let config = match get_chardev_config(args) {
+            Ok(conf) => conf,
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+
+        if let Err(e) = config.check() {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+
+        match self
+            .get_vm_config()
+            .lock()
+            .unwrap()
+            .add_chardev_with_config(config)
+        {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn chardev_remove(&mut self, id: String) -> Response {
+        match self.get_vm_config().lock().unwrap().del_chardev_by_id(&id) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn chardev_change(&mut self, args: qmp_schema::CharDevChangeArgument) -> Response {
+        let result = get_chardev_change_config(args)
+            .and_then(|config| change_chardev(&config.id, config.backend));
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn object_add(&mut self, args: qmp_schema::ObjectAddArgument) -> Response {
+        let vm_config = self.get_vm_config();
+        let mut locked_config = vm_config.lock().unwrap();
+        if let Ok(filter_type) = NetFilterType::from_str(&args.qom_type) {
+            let result = add_net_filter(&mut locked_config, filter_type, args);
+            return match result {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                    None,
+                ),
+            };
+        }
+        if args.qom_type == "secret" {
+            let result = get_secret_data(&args.id, args.data, args.file).and_then(|data| {
+                locked_config.add_secret_with_config(SecretObjConfig { id: args.id, data })
+            });
+            return match result {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                ),
+            };
+        }
+        if args.qom_type == "rng-random" {
+            let result = args
+                .filename
+                .with_context(|| "Filename of rng-random is not set")
+                .and_then(|filename| {
+                    locked_config.add_rng_object_with_config(RngObjConfig {
+                        id: args.id,
+                        filename,
+                    })
+                });
+            return match result {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                ),
+            };
+        }
+        if args.qom_type == "memory-backend-ram" || args.qom_type == "memory-backend-file" {
+            let is_file = args.qom_type == "memory-backend-file";
+            if is_file != args.mem_path.is_some() {
+                let err_str = format!(
+                    "mem-path is {} for {}",
+                    if is_file { "required" } else { "not supported" },
+                    args.qom_type
+                );
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+            let result = args
+                .size
+                .with_context(|| format!("Size of {} is not set", args.qom_type))
+                .and_then(|size| {
+                    locked_config.add_mem_zone_with_config(MemZoneConfig {
+                        id: args.id,
+                        size,
+                        mem_path: args.mem_path,
+                        share: args.share.unwrap_or(false),
+                        ..Default::default()
+                    })
+                });
+            return match result {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                ),
+            };
+        }
+        if args.qom_type != "iothread" || args.data.is_some() || args.file.is_some() {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Object type {} with the arguments is not supported",
+                    args.qom_type
+                )),
+                None,
+            );
+        }
+
+        let iothread = IothreadConfig {
+            id: args.id.clone(),
+        };
+        if let Err(e) = locked_config.add_iothread_with_config(iothread) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+        if let Err(e) = EventLoop::add_iothread(&args.id) {
+            // Roll back the config if the io-thread fails to start.
+            locked_config.del_iothread(&args.id).unwrap_or_else(|e| {
+                error!("Failed to remove iothread config {}: {:?}", args.id, e)
+            });
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+
+        Response::create_empty_response()
+    }
+
+    fn object_del(&mut self, id: String) -> Response {
+        let vm_config = self.get_vm_config();
+        let mut locked_config = vm_config.lock().unwrap();
+        // Drives have got the data of secret, so it can be removed directly.
+        if locked_config.object.secret_object.remove(&id).is_some() {
+            return Response::create_empty_response();
+        }
+        // The memory backends and rng objects used by devices have been taken out.
+        if locked_config.object.mem_object.remove(&id).is_some()
+            || locked_config.object.rng_object.remove(&id).is_some()
+        {
+            return Response::create_empty_response();
+        }
+        if locked_config.object.netfilter_object.contains_key(&id) {
+            if let Err(e) = virtio::del_net_filter(&id) {
                 return Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(e.to_string()),
                     None,
                 );
             }
-        };
+            locked_config.object.netfilter_object.remove(&id);
+            return Response::create_empty_response();
+        }
+        let exists = locked_config
+            .iothreads
+            .as_ref()
+            .is_some_and(|iothreads| iothreads.iter().any(|t| t.id == id));
+        if !exists {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound(format!("Object {} not found", id)),
+                None,
+            );
+        }
+        let attached = locked_config.iothread_attached_devices(&id);
+        if attached > 0 {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Iothread {} is in use by {} device(s)",
+                    id, attached
+                )),
+                None,
+            );
+        }
 
-        // Register drive backend file for hotplug drive.
-        if let Err(e) = self.register_drive_file(
-            &config.id,
-            &args.file.filename,
-            config.read_only,
-            config.direct,
-        ) {
-            error!("{:?}", e);
+        if let Err(e) = EventLoop::del_iothread(&id) {
             return Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(e.to_string()),
                 None,
             );
         }
-        match self
-            .get_vm_config()
-            .lock()
-            .unwrap()
-            .add_drive_with_config(config)
-        {
+        match locked_config.del_iothread(&id) {
             Ok(()) => Response::create_empty_response(),
-            Err(e) => {
-                error!("{:?}", e);
-                // It's safe to unwrap as the path has been registered.
-                self.unregister_drive_file(&args.file.filename).unwrap();
-                Response::create_error_response(
-                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-                    None,
-                )
-            }
-        }
-    }
-
-    fn blockdev_del(&self, node_name: String) -> Response {
-        match self
-            .get_vm_config()
-            .lock()
-            .unwrap()
-            .del_drive_by_id(&node_name)
-        {
-            Ok(path) => {
-                // It's safe to unwrap as the path has been registered.
-                self.unregister_drive_file(&path).unwrap();
-                Response::create_empty_response()
-            }
             Err(e) => Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(e.to_string()),
                 None,
@@ -1470,8 +2815,18 @@ impl DeviceInterface for StdMachine {
         }
     }
 
-    fn chardev_add(&mut self, args: qmp_schema::CharDevAddArgument) -> Response {
-        let config = match get_chardev_config(args) {
+    fn query_iothreads(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let locked_config = vm_config.lock().unwrap();
+        let mut iothreads = IOTHREADS.lock().unwrap().clone();
+        for iothread in iothreads.iter_mut() {
+            iothread.attached_devices = locked_config.iothread_attached_devices(&iothread.id);
+        }
+        Response::create_response(serde_json::to_value(iothreads).unwrap(), None)
+    }
+
+    fn netdev_add(&mut self, args: Box<qmp_schema::NetDevAddArgument>) -> Response {
+        let config = match get_netdev_config(args) {
             Ok(conf) => conf,
             Err(e) => {
                 return Response::create_error_response(
@@ -1481,18 +2836,11 @@ impl DeviceInterface for StdMachine {
             }
         };
 
-        if let Err(e) = config.check() {
-            return Response::create_error_response(
-                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-                None,
-            );
-        }
-
         match self
             .get_vm_config()
             .lock()
             .unwrap()
-            .add_chardev_with_config(config)
+            .add_netdev_with_config(config)
         {
             Ok(()) => Response::create_empty_response(),
             Err(e) => Response::create_error_response(
@@ -1502,8 +2850,8 @@ impl DeviceInterface for StdMachine {
         }
     }
 
-    fn chardev_remove(&mut self, id: String) -> Response {
-        match self.get_vm_config().lock().unwrap().del_chardev_by_id(&id) {
+    fn netdev_del(&mut self, id: String) -> Response {
+        match self.get_vm_config().lock().unwrap().del_netdev_by_id(&id) {
             Ok(()) => Response::create_empty_response(),
             Err(e) => Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(e.to_string()),
@@ -1512,24 +2860,41 @@ impl DeviceInterface for StdMachine {
         }
     }
 
-    fn netdev_add(&mut self, args: Box<qmp_schema::NetDevAddArgument>) -> Response {
-        let config = match get_netdev_config(args) {
-            Ok(conf) => conf,
-            Err(e) => {
+    fn set_link(&mut self, name: String, up: bool) -> Response {
+        match self.set_net_link(&name, up) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_mac(&mut self, name: String, mac: String) -> Response {
+        match self.set_net_mac(&name, &mac) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set_vm_generation_id(&mut self, guid: String) -> Response {
+        let vmgenid = match self.get_vmgenid() {
+            Some(vmgenid) => vmgenid,
+            None => {
                 return Response::create_error_response(
-                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    qmp_schema::QmpErrorClass::DeviceNotFound("vmgenid not found".to_string()),
                     None,
                 );
             }
         };
-
-        match self
-            .get_vm_config()
-            .lock()
-            .unwrap()
-            .add_netdev_with_config(config)
-        {
-            Ok(()) => Response::create_empty_response(),
+        match parse_vmgenid_guid(&guid) {
+            Ok(uuid) => {
+                vmgenid.lock().unwrap().update_guid(uuid);
+                Response::create_empty_response()
+            }
             Err(e) => Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(e.to_string()),
                 None,
@@ -1537,11 +2902,43 @@ impl DeviceInterface for StdMachine {
         }
     }
 
-    fn netdev_del(&mut self, id: String) -> Response {
-        match self.get_vm_config().lock().unwrap().del_netdev_by_id(&id) {
-            Ok(()) => Response::create_empty_response(),
-            Err(e) => Response::create_error_response(
-                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+    fn query_vm_generation_id(&self) -> Response {
+        match self.get_vmgenid() {
+            Some(vmgenid) => {
+                let info = qmp_schema::GuidInfo {
+                    guid: vmgenid.lock().unwrap().guid(),
+                };
+                Response::create_response(serde_json::to_value(info).unwrap(), None)
+            }
+            None => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound("vmgenid not found".to_string()),
+                None,
+            ),
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn rtc_reset_reinjection(&mut self) -> Response {
+        match self.get_rtc() {
+            Some(rtc) => {
+                rtc.lock().unwrap().reset_reinjection();
+                Response::create_empty_response()
+            }
+            None => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound("rtc not found".to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn query_rtc(&self) -> Response {
+        match self.get_rtc() {
+            Some(rtc) => {
+                let info = rtc.lock().unwrap().query();
+                Response::create_response(serde_json::to_value(info).unwrap(), None)
+            }
+            None => Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound("rtc not found".to_string()),
                 None,
             ),
         }
@@ -1600,6 +2997,10 @@ impl DeviceInterface for StdMachine {
         }
     }
 
+    fn query_migrate_capabilities(&self) -> Response {
+        migration::query_migrate_capabilities()
+    }
+
     fn update_region(&mut self, args: UpdateRegionArgument) -> Response {
         #[derive(Default)]
         struct DummyDevice {
@@ -1970,27 +3371,260 @@ impl DeviceInterface for StdMachine {
             ),
         }
     }
+
+    fn block_dirty_bitmap_add(&self, args: qmp_schema::BlockDirtyBitmapAddArgument) -> Response {
+        operate_dirty_bitmaps(&args.node, |bitmaps| {
+            bitmaps.add(
+                &args.name,
+                args.granularity,
+                args.persistent.unwrap_or(false),
+            )
+        })
+    }
+
+    fn block_dirty_bitmap_clear(&self, args: qmp_schema::BlockDirtyBitmapArgument) -> Response {
+        operate_dirty_bitmaps(&args.node, |bitmaps| bitmaps.clear(&args.name))
+    }
+
+    fn block_dirty_bitmap_remove(&self, args: qmp_schema::BlockDirtyBitmapArgument) -> Response {
+        operate_dirty_bitmaps(&args.node, |bitmaps| bitmaps.remove(&args.name))
+    }
+
+    fn block_dirty_bitmap_export(
+        &self,
+        args: qmp_schema::BlockDirtyBitmapExportArgument,
+    ) -> Response {
+        operate_dirty_bitmaps(&args.node, |bitmaps| {
+            bitmaps.export(&args.name, &args.file, args.clear.unwrap_or(false))
+        })
+    }
+
+    fn nbd_server_start(&self, addr: String) -> Response {
+        match nbd_server_start(&addr) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn nbd_server_add(&self, args: qmp_schema::NbdServerAddArgument) -> Response {
+        match self.nbd_export_drive(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn blockdev_snapshot_sync(
+        &mut self,
+        args: qmp_schema::BlockdevSnapshotSyncArgument,
+    ) -> Response {
+        match self.snapshot_drive(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn eject(&mut self, args: qmp_schema::EjectArgument) -> Response {
+        match self.eject_drive(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn change(&mut self, args: qmp_schema::ChangeArgument) -> Response {
+        match self.change_drive(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn drive_mirror(&mut self, args: qmp_schema::DriveMirrorArgument) -> Response {
+        match self.mirror_drive(&args) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn block_job_complete(&mut self, args: qmp_schema::BlockJobArgument) -> Response {
+        match self.complete_mirror(&args.device) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn block_job_cancel(&mut self, args: qmp_schema::BlockJobArgument) -> Response {
+        match mirror_finish(&args.device, true) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_block(&self) -> Response {
+        let vm_config = self.get_vm_config();
+        let locked_config = vm_config.lock().unwrap();
+        let mut blocks: Vec<qmp_schema::BlockInfo> = locked_config
+            .drives
+            .values()
+            .map(|drive| qmp_schema::BlockInfo {
+                device: drive.id.clone(),
+                inserted: (!drive.path_on_host.is_empty()).then(|| qmp_schema::BlockDeviceInfo {
+                    file: drive.path_on_host.clone(),
+                    ro: drive.read_only,
+                    drv: drive.format.to_string(),
+                    direct: drive.direct,
+                    aio: drive.aio.name().to_string(),
+                }),
+            })
+            .collect();
+        blocks.sort_by(|a, b| a.device.cmp(&b.device));
+        Response::create_response(serde_json::to_value(blocks).unwrap(), None)
+    }
+
+    fn query_block_jobs(&self) -> Response {
+        Response::create_response(serde_json::to_value(query_mirror_jobs()).unwrap(), None)
+    }
+
+    fn query_jobs(&self) -> Response {
+        Response::create_response(serde_json::to_value(query_jobs()).unwrap(), None)
+    }
+
+    fn job_pause(&mut self, id: String) -> Response {
+        match job_pause(&id) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn job_resume(&mut self, id: String) -> Response {
+        match job_resume(&id) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn job_cancel(&mut self, id: String) -> Response {
+        match job_cancel(&id) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+}
+
+fn operate_dirty_bitmaps<F>(node: &str, op: F) -> Response
+where
+    F: FnOnce(&mut DirtyBitmaps) -> Result<()>,
+{
+    let bitmaps = match DIRTY_BITMAP_LIST.lock().unwrap().get(node) {
+        Some(bitmaps) => bitmaps.clone(),
+        None => {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                    "No block device drive named {}",
+                    node
+                )),
+                None,
+            );
+        }
+    };
+    let result = op(&mut bitmaps.lock().unwrap());
+    match result {
+        Ok(()) => Response::create_empty_response(),
+        Err(e) => Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+            None,
+        ),
+    }
+}
+
+fn add_net_filter(
+    vm_config: &mut VmConfig,
+    filter_type: NetFilterType,
+    args: qmp_schema::ObjectAddArgument,
+) -> Result<()> {
+    if args.data.is_some() || args.file.is_some() {
+        bail!(
+            "Object type {} with the arguments is not supported",
+            args.qom_type
+        );
+    }
+    let filter = NetFilterConfig {
+        id: args.id,
+        filter_type,
+        netdev: args
+            .netdev
+            .with_context(|| format!("Netdev of {} not set", args.qom_type))?,
+        queue: match args.queue {
+            Some(queue) => NetFilterQueue::from_str(&queue)?,
+            None => NetFilterQueue::All,
+        },
+        outdev: args.outdev,
+        indev: args.indev,
+    };
+    vm_config.add_net_filter_with_config(filter.clone())?;
+    if let Err(e) = virtio::add_net_filter(&filter, &vm_config.chardev) {
+        vm_config.object.netfilter_object.remove(&filter.id);
+        return Err(e);
+    }
+    Ok(())
 }
 
 fn parse_blockdev(args: &BlockDevAddArgument) -> Result<DriveConfig> {
+    let direct = args
+        .cache
+        .as_ref()
+        .map_or(true, |cache| cache.direct.unwrap_or(true));
     let mut config = DriveConfig {
         id: args.node_name.clone(),
         path_on_host: args.file.filename.clone(),
         read_only: args.read_only.unwrap_or(false),
-        direct: true,
+        direct,
         iops: args.iops,
-        aio: args.file.aio,
+        aio: args
+            .file
+            .aio
+            .unwrap_or_else(|| aio_select(&args.file.filename, direct)),
         media: "disk".to_string(),
         discard: false,
         write_zeroes: WriteZeroesState::Off,
         format: DiskFormat::Raw,
         l2_cache_size: None,
         refcount_cache_size: None,
+        key_secret: args.key_secret.clone(),
+        copy_on_read: args.copy_on_read.unwrap_or(false),
+        io_timeout: None,
     };
-    if args.cache.is_some() && !args.cache.as_ref().unwrap().direct.unwrap_or(true) {
-        config.direct = false;
-        config.aio = AioEngine::Off;
-    }
     if let Some(discard) = args.discard.as_ref() {
         config.discard = discard
             .as_str()
@@ -2027,6 +3661,15 @@ fn parse_blockdev(args: &BlockDevAddArgument) -> Result<DriveConfig> {
             .with_context(|| format!("Invalid refcount cache size: {}", rc_cache))?;
         config.refcount_cache_size = Some(sz);
     }
+    if let Some(timeout) = args.io_timeout {
+        let action = match args.io_timeout_action.as_ref() {
+            Some(action) => action.parse::<IoTimeoutAction>()?,
+            None => IoTimeoutAction::Report,
+        };
+        config.io_timeout = Some(IoTimeout { timeout, action });
+    } else if args.io_timeout_action.is_some() {
+        bail!("io-timeout-action requires io-timeout");
+    }
     config.check()?;
     config.check_path()?;
     Ok(config)
@@ -10,6 +10,8 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
 use std::sync::{
     atomic::{AtomicU8, Ordering},
     Arc, Mutex, Weak,
@@ -17,21 +19,24 @@ use std::sync::{
 
 use anyhow::Context;
 use log::error;
-use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use super::VENDOR_ID_INTEL;
 use crate::standard_vm::Result;
-use acpi::{AcpiPMTimer, AcpiPmCtrl, AcpiPmEvent};
+use acpi::{AcpiPMTimer, AcpiPmCtrl, AcpiPmEvent, ACPI_SLEEP_TYPE_S3};
 use address_space::{AddressSpace, GuestAddress, Region, RegionOps};
 use devices::pci::config::{
     PciConfig, CLASS_CODE_ISA_BRIDGE, DEVICE_ID, HEADER_TYPE, HEADER_TYPE_BRIDGE,
     HEADER_TYPE_MULTIFUNC, PCI_CONFIG_SPACE_SIZE, SUB_CLASS_CODE, VENDOR_ID,
 };
 use devices::pci::{
-    le_write_u16, le_write_u32, PciBus, PciDevBase, PciDevOps, Result as PciResult,
+    le_write_u16, le_write_u32, InterruptHandler, PciBus, PciDevBase, PciDevOps,
+    Result as PciResult,
 };
 use devices::{Device, DeviceBase};
+use machine_manager::event_loop::EventLoop;
 use util::byte_code::ByteCode;
+use util::loop_context::{read_fd, EventNotifier, NotifierCallback, NotifierOperation};
 use util::num_ops::ranges_overlap;
 
 const DEVICE_ID_INTEL_ICH9: u16 = 0x2918;
@@ -42,6 +47,8 @@ pub const PM_EVENT_OFFSET: u16 = 0x600;
 pub const PM_CTRL_OFFSET: u16 = 0x604;
 pub const SLEEP_CTRL_OFFSET: u16 = 0xCE9;
 pub const RST_CTRL_OFFSET: u16 = 0xCF9;
+/// GSI of ACPI System Control Interrupt.
+pub const SCI_IRQ: u32 = 9;
 
 /// LPC bridge of ICH9 (IO controller hub 9), Device 1F : Function 0
 #[allow(clippy::upper_case_acronyms)]
@@ -55,6 +62,12 @@ pub struct LPCBridge {
     /// Reset request triggered by ACPI PM1 Control Registers.
     pub reset_req: Arc<EventFd>,
     pub shutdown_req: Arc<EventFd>,
+    /// Suspend request triggered by writing S3 sleep type to ACPI PM1 Control Registers.
+    pub suspend_req: Arc<EventFd>,
+    /// Power button event, which is notified to guest by SCI.
+    power_button: Arc<EventFd>,
+    /// Set the level of SCI.
+    irq_handler: Arc<InterruptHandler>,
 }
 
 impl LPCBridge {
@@ -63,6 +76,9 @@ impl LPCBridge {
         sys_io: Arc<AddressSpace>,
         reset_req: Arc<EventFd>,
         shutdown_req: Arc<EventFd>,
+        suspend_req: Arc<EventFd>,
+        power_button: Arc<EventFd>,
+        irq_handler: InterruptHandler,
     ) -> Result<Self> {
         Ok(Self {
             base: PciDevBase {
@@ -78,6 +94,9 @@ impl LPCBridge {
             rst_ctrl: Arc::new(AtomicU8::new(0)),
             reset_req,
             shutdown_req,
+            suspend_req,
+            power_button,
+            irq_handler: Arc::new(irq_handler),
         })
     }
 
@@ -184,8 +203,14 @@ impl LPCBridge {
         };
 
         let cloned_pmevt = self.pm_evt.clone();
+        let cloned_irq_handler = self.irq_handler.clone();
         let write_ops = move |data: &[u8], addr: GuestAddress, offset: u64| -> bool {
-            cloned_pmevt.lock().unwrap().write(data, addr, offset)
+            let mut locked_pmevt = cloned_pmevt.lock().unwrap();
+            if !locked_pmevt.write(data, addr, offset) {
+                return false;
+            }
+            update_sci(&locked_pmevt, &cloned_irq_handler);
+            true
         };
 
         let ops = RegionOps {
@@ -207,11 +232,23 @@ impl LPCBridge {
         };
 
         let clone_pmctrl = self.pm_ctrl.clone();
+        let cloned_pmevt = self.pm_evt.clone();
         let cloned_shutdown_fd = self.shutdown_req.clone();
+        let cloned_suspend_fd = self.suspend_req.clone();
         let write_ops = move |data: &[u8], addr: GuestAddress, offset: u64| -> bool {
-            if clone_pmctrl.lock().unwrap().write(data, addr, offset)
-                && cloned_shutdown_fd.write(1).is_err()
-            {
+            let mut locked_pmctrl = clone_pmctrl.lock().unwrap();
+            if !locked_pmctrl.write(data, addr, offset) {
+                return true;
+            }
+            if locked_pmctrl.sleep_type() == ACPI_SLEEP_TYPE_S3 {
+                // Guest does not run until it is woken up, so the wake status
+                // can be latched as soon as it enters sleep state.
+                cloned_pmevt.lock().unwrap().set_wake_status();
+                if cloned_suspend_fd.write(1).is_err() {
+                    error!("X86 standard vm write suspend fd failed");
+                    return false;
+                }
+            } else if cloned_shutdown_fd.write(1).is_err() {
                 error!("X86 standard vm write shutdown fd failed");
                 return false;
             }
@@ -229,6 +266,33 @@ impl LPCBridge {
 
         Ok(())
     }
+
+    fn init_power_button(&self) -> Result<()> {
+        let cloned_pmevt = self.pm_evt.clone();
+        let cloned_irq_handler = self.irq_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_pmevt = cloned_pmevt.lock().unwrap();
+            locked_pmevt.press_power_button();
+            update_sci(&locked_pmevt, &cloned_irq_handler);
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            self.power_button.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        );
+        EventLoop::update_event(vec![notifier], None)?;
+        Ok(())
+    }
+}
+
+fn update_sci(pm_evt: &AcpiPmEvent, irq_handler: &InterruptHandler) {
+    if let Err(e) = irq_handler(SCI_IRQ, pm_evt.sci_level()) {
+        error!("Failed to set SCI level: {:?}", e);
+    }
 }
 
 impl Device for LPCBridge {
@@ -290,6 +354,8 @@ impl PciDevOps for LPCBridge {
             .with_context(|| "Fail to init IO region for PM events register")?;
         self.init_pm_ctrl_reg()
             .with_context(|| "Fail to init IO region for PM control register")?;
+        self.init_power_button()
+            .with_context(|| "Fail to init power button")?;
 
         let parent_bus = self.base.parent_bus.clone();
         parent_bus
@@ -302,6 +368,14 @@ impl PciDevOps for LPCBridge {
         Ok(())
     }
 
+    fn reset(&mut self, _reset_child_device: bool) -> Result<()> {
+        self.pm_ctrl.lock().unwrap().reset();
+        let mut locked_pmevt = self.pm_evt.lock().unwrap();
+        locked_pmevt.reset();
+        update_sci(&locked_pmevt, &self.irq_handler);
+        Ok(())
+    }
+
     fn write_config(&mut self, offset: usize, data: &[u8]) {
         self.base.config.write(offset, data, 0, None, None);
         // SAFETY: offset is no more than 0xfff.
@@ -19,44 +19,57 @@ use std::collections::HashMap;
 use std::io::{Seek, SeekFrom};
 use std::mem::size_of;
 use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
 use std::sync::{Arc, Condvar, Mutex};
 
 use anyhow::{bail, Context, Result};
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use log::{error, info};
-use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
-use self::ich9_lpc::SLEEP_CTRL_OFFSET;
+use self::ich9_lpc::{SCI_IRQ, SLEEP_CTRL_OFFSET};
 use super::error::StandardVmError;
 use super::{AcpiBuilder, StdMachineOps};
 use crate::error::MachineError;
+use crate::gdbstub::start_gdbstub;
+use crate::halt_poll::init_halt_poll;
+use crate::sandbox::enter_sandbox;
 use crate::{vm_state, MachineOps};
 use acpi::{
-    AcpiIoApic, AcpiLocalApic, AcpiSratMemoryAffinity, AcpiSratProcessorAffinity, AcpiTable,
-    AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl, AmlPackage, AmlScope, AmlScopeBuilder,
-    AmlString, TableLoader, IOAPIC_BASE_ADDR, LAPIC_BASE_ADDR,
+    AcpiInterruptSourceOverride, AcpiIoApic, AcpiLocalApic, AcpiSratMemoryAffinity,
+    AcpiSratProcessorAffinity, AcpiTable, AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl,
+    AmlPackage, AmlScope, AmlScopeBuilder, AmlString, TableLoader, ACPI_SLEEP_TYPE_S3,
+    ACPI_SLEEP_TYPE_S5, IOAPIC_BASE_ADDR, LAPIC_BASE_ADDR,
 };
 use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
 use cpu::{CPUBootConfig, CPUInterface, CPUTopology, CpuTopology, CPU};
 use devices::legacy::{
-    error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, PFlash, Serial, RTC,
-    SERIAL_ADDR,
+    error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, Hpet, PFlash,
+    PitLegacyHandler, Serial, RTC, SERIAL_ADDR,
 };
-use devices::pci::{PciDevOps, PciHost};
+use devices::misc::watchdog::WatchdogActionTrigger;
+use devices::pci::{InterruptHandler, PciDevOps, PciHost};
 use devices::sysbus::SysBus;
-use hypervisor::kvm::KVM_FDS;
+use devices::{gsi_irq_handler, IoApic, Pic, IOAPIC_NUM_PINS, IOAPIC_REGION_SIZE};
+use hypervisor::{
+    hypervisor,
+    kvm::{MsiVector, KVM_FDS},
+    MsiMessage,
+};
 #[cfg(feature = "gtk")]
 use machine_manager::config::UiContext;
 use machine_manager::config::{
     parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    NumaNodes, PFlashConfig, RebootAction, SerialConfig, ShutdownAction, VmConfig, WatchdogAction,
 };
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
+use machine_manager::job::JobTask;
 use machine_manager::machine::{
     KvmVmState, MachineAddressInterface, MachineExternalInterface, MachineInterface,
-    MachineLifecycle, MachineTestInterface, MigrateInterface,
+    MachineLifecycle, MachineTestInterface, MigrateInterface, ShutdownCause,
 };
 use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_response::Response, qmp_schema};
 use mch::Mch;
@@ -67,8 +80,12 @@ use ui::gtk::gtk_display_init;
 #[cfg(feature = "vnc")]
 use ui::vnc::vnc_init;
 use util::{
-    byte_code::ByteCode, loop_context::EventLoopManager, seccomp::BpfRule, set_termi_canon_mode,
+    byte_code::ByteCode,
+    loop_context::{read_fd, EventLoopManager, EventNotifier, NotifierCallback, NotifierOperation},
+    seccomp::BpfRule,
+    set_termi_canon_mode,
 };
+use virtio::Iommu;
 
 const VENDOR_ID_INTEL: u16 = 0x8086;
 const HOLE_640K_START: u64 = 0x000A_0000;
@@ -82,6 +99,7 @@ pub enum LayoutEntryType {
     PcieMmio,
     Mmio,
     IoApic,
+    Hpet,
     LocalApic,
     IdentTss,
     MemAbove4g,
@@ -94,6 +112,7 @@ pub const MEM_LAYOUT: &[(u64, u64)] = &[
     (0xC000_0000, 0x3000_0000),      // PcieMmio
     (0xF010_0000, 0x200),            // Mmio
     (0xFEC0_0000, 0x10_0000),        // IoApic
+    (0xFED0_0000, 0x400),            // Hpet
     (0xFEE0_0000, 0x10_0000),        // LocalApic
     (0xFEF0_C000, 0x4000),           // Identity map address and TSS
     (0x1_0000_0000, 0x80_0000_0000), // MemAbove4g
@@ -105,13 +124,19 @@ enum IrqEntryType {
     Uart,
     Sysbus,
     Pcie,
+    Hpet,
 }
 
-/// IRQ MAP of x86_64
+/// The sleep state is enabled in fw_cfg file `etc/system-states`.
+const SYSTEM_STATE_ENABLED: u8 = 0x80;
+
+/// IRQ MAP of x86_64. The ISA IRQ 8 is fixed for the RTC and 9 for the ACPI SCI, so the
+/// contiguous range of sysbus devices ends below them.
 const IRQ_MAP: &[(i32, i32)] = &[
     (4, 4),   // Uart
-    (5, 15),  // Sysbus
+    (5, 7),   // Sysbus
     (16, 19), // Pcie
+    (20, 23), // Hpet
 ];
 
 /// Standard machine structure.
@@ -136,6 +161,14 @@ pub struct StdMachine {
     reset_req: Arc<EventFd>,
     /// Shutdown_req, handle VM 'ShutDown' event.
     shutdown_req: Arc<EventFd>,
+    /// Power button, notify guest to power off by ACPI.
+    power_button: Arc<EventFd>,
+    /// Suspend request, handle VM `Suspend` event.
+    suspend_req: Arc<EventFd>,
+    /// Wakeup request, handle VM `Wakeup` event.
+    wakeup_req: Arc<EventFd>,
+    /// Pause request, handle VM `Pause` event.
+    pause_req: Arc<EventFd>,
     /// All configuration information of virtual machine.
     vm_config: Arc<Mutex<VmConfig>>,
     /// List of guest NUMA nodes information.
@@ -144,10 +177,22 @@ pub struct StdMachine {
     boot_order_list: Arc<Mutex<Vec<BootIndexInfo>>>,
     /// FwCfg device.
     fwcfg_dev: Option<Arc<Mutex<FwCfgIO>>>,
+    /// PFlash devices, indexed by unit id.
+    pflash_devs: HashMap<usize, Arc<Mutex<PFlash>>>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
     /// All backend memory region tree
     machine_ram: Arc<Region>,
+    /// Virtio iommu device, which translates the DMA of the devices on the root bus.
+    iommu: Option<Arc<Mutex<Iommu>>>,
+    /// HPET device.
+    hpet: Option<Arc<Mutex<Hpet>>>,
+    /// RTC device.
+    rtc: Option<Arc<Mutex<RTC>>>,
+    /// IOAPIC emulated in userspace with split irqchip.
+    ioapic: Option<Arc<Mutex<IoApic>>>,
+    /// PIC emulated in userspace with split irqchip.
+    pic: Option<Arc<Mutex<Pic>>>,
 }
 
 impl StdMachine {
@@ -207,19 +252,41 @@ impl StdMachine {
                     MachineError::InitEventFdErr("shutdown request".to_string())
                 })?,
             ),
+            power_button: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| MachineError::InitEventFdErr("power_button".to_string()))?,
+            ),
+            suspend_req: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| MachineError::InitEventFdErr("suspend request".to_string()))?,
+            ),
+            wakeup_req: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| MachineError::InitEventFdErr("wakeup request".to_string()))?,
+            ),
+            pause_req: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| MachineError::InitEventFdErr("pause request".to_string()))?,
+            ),
             vm_config: Arc::new(Mutex::new(vm_config.clone())),
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
             fwcfg_dev: None,
+            pflash_devs: HashMap::new(),
             drive_files: Arc::new(Mutex::new(vm_config.init_drive_files()?)),
             machine_ram: Arc::new(Region::init_container_region(
                 u64::max_value(),
                 "MachineRam",
             )),
+            iommu: None,
+            hpet: None,
+            rtc: None,
+            ioapic: None,
+            pic: None,
         })
     }
 
-    pub fn handle_reset_request(vm: &Arc<Mutex<Self>>) -> Result<()> {
+    pub fn handle_reset_request(vm: &Arc<Mutex<Self>>, cause: ShutdownCause) -> Result<()> {
         let mut locked_vm = vm.lock().unwrap();
 
         for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
@@ -232,12 +299,16 @@ impl StdMachine {
         locked_vm
             .reset_all_devices()
             .with_context(|| "Fail to reset all devices")?;
+        locked_vm.reset_pic();
         locked_vm
             .reset_fwcfg_boot_order()
             .with_context(|| "Fail to update boot order information to FwCfg device")?;
 
         if QmpChannel::is_connected() {
-            let reset_msg = qmp_schema::Reset { guest: true };
+            let reset_msg = qmp_schema::Reset {
+                guest: cause.is_guest(),
+                reason: cause.reason().to_string(),
+            };
             event!(Reset; reset_msg);
         }
 
@@ -251,6 +322,90 @@ impl StdMachine {
         Ok(())
     }
 
+    /// Enter sleep state S3, all vcpus are stopped until guest is woken up.
+    pub fn handle_suspend_request(vm: &Arc<Mutex<Self>>) -> Result<()> {
+        let locked_vm = vm.lock().unwrap();
+        if !locked_vm.notify_lifecycle(KvmVmState::Running, KvmVmState::Suspended) {
+            bail!("Failed to suspend vm");
+        }
+        if let Some(rtc) = locked_vm.rtc.as_ref() {
+            rtc.lock().unwrap().notify_suspend();
+        }
+        event!(Suspend);
+
+        Ok(())
+    }
+
+    /// Wake up from sleep state S3. Like a reset, vcpus restart from firmware. The shutdown
+    /// status in CMOS tells firmware to resume guest through the waking vector, and the wake
+    /// status of ACPI PM1 event is kept for guest.
+    pub fn handle_wakeup_request(vm: &Arc<Mutex<Self>>) -> Result<()> {
+        let mut locked_vm = vm.lock().unwrap();
+
+        for cpu in locked_vm.cpus.iter() {
+            cpu.set_to_boot_state();
+        }
+        locked_vm
+            .reset_all_devices()
+            .with_context(|| "Fail to reset all devices")?;
+        locked_vm.reset_pic();
+        for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
+            cpu.reset()
+                .with_context(|| format!("Failed to reset vcpu{}", cpu_index))?;
+        }
+
+        if !locked_vm.notify_lifecycle(KvmVmState::Suspended, KvmVmState::Running) {
+            bail!("Failed to wake up vm");
+        }
+        event!(Wakeup);
+
+        Ok(())
+    }
+
+    /// Resuming from S3 relies on firmware to jump to the waking vector, it's not supported
+    /// by direct kernel boot.
+    fn is_s3_supported(&self) -> bool {
+        !self.pflash_devs.is_empty()
+    }
+
+    /// Request to wake up the suspended guest.
+    pub fn wakeup(&self) -> Result<()> {
+        if *self.vm_state.0.lock().unwrap() != KvmVmState::Suspended {
+            bail!("Guest is not suspended");
+        }
+        self.wakeup_req
+            .write(1)
+            .with_context(|| "Failed to write wakeup request")?;
+
+        Ok(())
+    }
+
+    fn register_sleep_event(
+        &self,
+        req: Arc<EventFd>,
+        clone_vm: Arc<Mutex<StdMachine>>,
+        handle: fn(&Arc<Mutex<StdMachine>>) -> Result<()>,
+    ) -> Result<()> {
+        let req_fd = req.as_raw_fd();
+        let req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            read_fd(req_fd);
+            if let Err(e) = handle(&clone_vm) {
+                error!("Fail to handle sleep request of standard VM, {:?}", e);
+            }
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            req_fd,
+            None,
+            EventSet::IN,
+            vec![req_handler],
+        );
+        EventLoop::update_event(vec![notifier], None)
+            .with_context(|| "Failed to register event notifier.")?;
+        Ok(())
+    }
+
     fn arch_init() -> Result<()> {
         let kvm_fds = KVM_FDS.load();
         let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
@@ -265,6 +420,10 @@ impl StdMachine {
             .set_tss_address((identity_addr + 0x1000) as usize)
             .with_context(|| MachineError::SetTssErr)?;
 
+        // The in-kernel PIT needs the in-kernel PIC.
+        if kvm_fds.is_split_irqchip() {
+            return Ok(());
+        }
         let pit_config = kvm_pit_config {
             flags: KVM_PIT_SPEAKER_DUMMY,
             pad: Default::default(),
@@ -283,15 +442,136 @@ impl StdMachine {
             self.sys_io.clone(),
             self.reset_req.clone(),
             self.shutdown_req.clone(),
+            self.suspend_req.clone(),
+            self.power_button.clone(),
+            self.irq_handler(),
         )?;
-        self.register_reset_event(self.reset_req.clone(), vm)
+        self.register_reset_event(self.reset_req.clone(), vm.clone())
             .with_context(|| "Fail to register reset event in LPC")?;
         self.register_shutdown_event(ich.shutdown_req.clone(), clone_vm)
             .with_context(|| "Fail to register shutdown event in LPC")?;
+        self.register_sleep_event(
+            ich.suspend_req.clone(),
+            vm.clone(),
+            StdMachine::handle_suspend_request,
+        )
+        .with_context(|| "Fail to register suspend event in LPC")?;
+        self.register_sleep_event(
+            self.wakeup_req.clone(),
+            vm,
+            StdMachine::handle_wakeup_request,
+        )
+        .with_context(|| "Fail to register wakeup event in LPC")?;
         ich.realize()?;
         Ok(())
     }
 
+    fn add_hpet_device(&mut self) -> Result<()> {
+        let (irq_start, irq_end) = IRQ_MAP[IrqEntryType::Hpet as usize];
+        let int_route_cap = (irq_start..=irq_end).fold(0_u32, |cap, irq| cap | (1 << irq));
+        let pit_handler: PitLegacyHandler = if KVM_FDS.load().is_split_irqchip() {
+            Box::new(|_| Ok(()))
+        } else {
+            Box::new(|legacy| KVM_FDS.load().set_pit_hpet_legacy(legacy))
+        };
+        let hpet = Hpet::new(int_route_cap, self.irq_handler(), pit_handler);
+        let hpet = hpet
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::Hpet as usize].0,
+                MEM_LAYOUT[LayoutEntryType::Hpet as usize].1,
+            )
+            .with_context(|| "Failed to realize HPET device")?;
+        self.hpet = Some(hpet);
+        Ok(())
+    }
+
+    /// Create the local APICs in kvm, and emulate IOAPIC and PIC in userspace.
+    fn init_split_irqchip(&mut self) -> Result<()> {
+        let kvm_fds = KVM_FDS.load();
+        kvm_fds
+            .enable_split_irqchip(IOAPIC_NUM_PINS as u32)
+            .with_context(|| MachineError::CrtIrqchipErr)?;
+        kvm_fds
+            .irq_route_table
+            .lock()
+            .unwrap()
+            .init_split_irq_route_table();
+        kvm_fds.commit_irq_routing()?;
+
+        // The interrupts signaled by irqfd are delivered to the local APICs with the
+        // routes of the pins.
+        let route_handler = Box::new(|routes: &[Option<MsiMessage>]| -> Result<()> {
+            let routes: Vec<Option<MsiVector>> = routes
+                .iter()
+                .map(|route| {
+                    route.map(|msi| MsiVector {
+                        msg_addr_lo: msi.address as u32,
+                        msg_addr_hi: (msi.address >> 32) as u32,
+                        msg_data: msi.data,
+                        masked: false,
+                    })
+                })
+                .collect();
+            let kvm_fds = KVM_FDS.load();
+            kvm_fds
+                .irq_route_table
+                .lock()
+                .unwrap()
+                .update_ioapic_routes(&routes)?;
+            kvm_fds.commit_irq_routing()
+        });
+        let ioapic = IoApic::new(Box::new(|msi| hypervisor().signal_msi(msi)), route_handler)
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::IoApic as usize].0,
+                IOAPIC_REGION_SIZE,
+            )
+            .with_context(|| "Failed to realize IOAPIC")?;
+        let pic = Pic::new()
+            .realize(&self.sys_io)
+            .with_context(|| "Failed to realize PIC")?;
+        self.ioapic = Some(ioapic);
+        self.pic = Some(pic);
+        Ok(())
+    }
+
+    /// Connect the interrupt output of the userspace PIC to the boot processor.
+    fn connect_pic(&self) -> Result<()> {
+        let (pic, cpu) = match (self.pic.as_ref(), self.cpus.first()) {
+            (Some(pic), Some(cpu)) => (pic, cpu),
+            _ => return Ok(()),
+        };
+        let cloned_pic = pic.clone();
+        cpu.set_extint_handler(Box::new(move || cloned_pic.lock().unwrap().interrupt_ack()))
+            .with_context(|| "Failed to connect PIC to vcpu")?;
+        let cpu = Arc::downgrade(cpu);
+        pic.lock()
+            .unwrap()
+            .set_output_handler(Box::new(move |level| {
+                if let Some(cpu) = cpu.upgrade() {
+                    cpu.set_extint_level(level);
+                }
+            }));
+        Ok(())
+    }
+
+    /// PIC is not on the system bus, so it's reset separately.
+    fn reset_pic(&self) {
+        if let Some(pic) = self.pic.as_ref() {
+            pic.lock().unwrap().reset();
+        }
+    }
+
+    /// Create the handler which sets the level of GSI, the interrupt controllers are
+    /// emulated in kvm unless split irqchip is enabled.
+    fn irq_handler(&self) -> InterruptHandler {
+        match (self.pic.as_ref(), self.ioapic.as_ref()) {
+            (Some(pic), Some(ioapic)) => gsi_irq_handler(pic, ioapic),
+            _ => Box::new(|gsi, level| hypervisor().set_irq_line(gsi, level)),
+        }
+    }
+
     pub fn mem_show(&self) {
         self.sys_mem.memspace_show();
         self.sys_io.memspace_show();
@@ -341,11 +621,27 @@ impl StdMachineOps for StdMachine {
         fwcfg.add_data_entry(FwCfgEntryType::MaxCpus, nr_cpus.as_bytes().to_vec())?;
         fwcfg.add_data_entry(FwCfgEntryType::Irq0Override, 1_u32.as_bytes().to_vec())?;
 
+        if let Some(uuid) = self.vm_config.lock().unwrap().uuid.as_ref() {
+            fwcfg
+                .add_data_entry(FwCfgEntryType::Uuid, uuid.to_be_bytes())
+                .with_context(|| DevErrorKind::AddEntryErr("Uuid".to_string()))?;
+        }
+
         let boot_order = Vec::<u8>::new();
         fwcfg
             .add_file_entry("bootorder", boot_order)
             .with_context(|| DevErrorKind::AddEntryErr("bootorder".to_string()))?;
 
+        // Sleep states S0-S5 supported, the enabled state has the highest bit set and the
+        // sleep type in the low bits.
+        let mut system_states = vec![0_u8; 6];
+        if self.is_s3_supported() {
+            system_states[3] = SYSTEM_STATE_ENABLED | ACPI_SLEEP_TYPE_S3 as u8;
+        }
+        fwcfg
+            .add_file_entry("etc/system-states", system_states)
+            .with_context(|| DevErrorKind::AddEntryErr("etc/system-states".to_string()))?;
+
         let fwcfg_dev = FwCfgIO::realize(fwcfg, &mut self.sysbus)
             .with_context(|| "Failed to realize fwcfg device")?;
         self.fwcfg_dev = Some(fwcfg_dev.clone());
@@ -364,6 +660,14 @@ impl StdMachineOps for StdMachine {
     fn get_guest_numa(&self) -> &Option<NumaNodes> {
         &self.numa_nodes
     }
+
+    fn get_hpet(&self) -> Option<Arc<Mutex<Hpet>>> {
+        self.hpet.clone()
+    }
+
+    fn get_rtc(&self) -> Option<Arc<Mutex<RTC>>> {
+        self.rtc.clone()
+    }
 }
 
 impl MachineOps for StdMachine {
@@ -395,7 +699,15 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_device_mem_window(&self) -> Result<(u64, u64)> {
+        let (start, size) = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize];
+        Ok((start, start + size))
+    }
+
     fn init_interrupt_controller(&mut self, _vcpu_count: u64) -> Result<()> {
+        if self.vm_config.lock().unwrap().machine_config.split_irqchip {
+            return self.init_split_irqchip();
+        }
         KVM_FDS
             .load()
             .vm_fd
@@ -444,13 +756,16 @@ impl MachineOps for StdMachine {
     }
 
     fn add_rtc_device(&mut self, mem_size: u64) -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let rtc_config = self.vm_config.lock().unwrap().rtc;
+        let mut rtc = RTC::new(&rtc_config).with_context(|| "Failed to create RTC device")?;
         rtc.set_memory(
             mem_size,
             MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].0
                 + MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].1,
         );
-        RTC::realize(rtc, &mut self.sysbus).with_context(|| "Failed to realize RTC device")?;
+        let rtc =
+            RTC::realize(rtc, &mut self.sysbus).with_context(|| "Failed to realize RTC device")?;
+        self.rtc = Some(rtc);
 
         Ok(())
     }
@@ -465,6 +780,16 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_watchdog_action_trigger(&self, action: WatchdogAction) -> Result<WatchdogActionTrigger> {
+        let action_evt = match action {
+            WatchdogAction::Reset => Some(self.reset_req.clone()),
+            WatchdogAction::Shutdown => Some(self.shutdown_req.clone()),
+            WatchdogAction::Pause => Some(self.pause_req.clone()),
+            WatchdogAction::Debug => None,
+        };
+        Ok(WatchdogActionTrigger::new(action, action_evt))
+    }
+
     fn syscall_whitelist(&self) -> Vec<BpfRule> {
         syscall_whitelist()
     }
@@ -485,6 +810,7 @@ impl MachineOps for StdMachine {
             &locked_vm.sys_mem,
             nr_cpus,
         )?;
+        init_halt_poll(&vm_config.machine_config)?;
 
         locked_vm.init_interrupt_controller(u64::from(nr_cpus))?;
         StdMachine::arch_init()?;
@@ -495,6 +821,12 @@ impl MachineOps for StdMachine {
         locked_vm
             .init_ich9_lpc(clone_vm)
             .with_context(|| "Fail to init LPC bridge")?;
+        if vm_config.machine_config.hpet {
+            locked_vm.add_hpet_device()?;
+        }
+        locked_vm
+            .register_pause_event(locked_vm.pause_req.clone(), vm.clone())
+            .with_context(|| "Fail to register pause event")?;
         locked_vm.add_devices(vm_config)?;
 
         let fwcfg = locked_vm.add_fwcfg_device(nr_cpus)?;
@@ -505,6 +837,11 @@ impl MachineOps for StdMachine {
         } else {
             None
         };
+        let cpu_config = if migrate.0 == MigrateMode::Unknown {
+            Some(locked_vm.load_cpu_features(vm_config)?)
+        } else {
+            None
+        };
         let topology = CPUTopology::new().set_topology((
             vm_config.machine_config.nr_threads,
             vm_config.machine_config.nr_cores,
@@ -515,7 +852,9 @@ impl MachineOps for StdMachine {
             nr_cpus,
             &topology,
             &boot_config,
+            &cpu_config,
         )?);
+        locked_vm.connect_pic()?;
 
         if migrate.0 == MigrateMode::Unknown {
             if let Some(fw_cfg) = fwcfg {
@@ -558,6 +897,19 @@ impl MachineOps for StdMachine {
             locked_vm.shutdown_req.clone(),
         );
 
+        if let Some(addr) = vm_config.gdb.as_ref() {
+            start_gdbstub(
+                addr,
+                vm.clone(),
+                locked_vm.vm_state.clone(),
+                locked_vm.cpus.clone(),
+                locked_vm.sys_mem.clone(),
+            )
+            .with_context(|| "Failed to start gdbstub")?;
+        }
+
+        enter_sandbox(vm_config).with_context(|| "Failed to enter sandbox")?;
+
         MigrationManager::register_vm_config(locked_vm.get_vm_config());
         MigrationManager::register_vm_instance(vm.clone());
         MigrationManager::register_kvm_instance(
@@ -577,6 +929,8 @@ impl MachineOps for StdMachine {
         // The two PFlash devices locates below 4GB, this variable represents the end address
         // of current PFlash device.
         let mut flash_end: u64 = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        // The CODE is write-protected when the VARS is separated to another PFlash.
+        let separate_vars = configs_vec.len() > 1;
         for config in configs_vec {
             let mut fd = self.fetch_drive_file(&config.path_on_host)?;
             let pfl_size = fd.metadata().unwrap().len();
@@ -614,10 +968,10 @@ impl MachineOps for StdMachine {
                 sector_len,
                 4_u32,
                 1_u32,
-                config.read_only,
+                config.read_only || (separate_vars && config.unit == 0),
             )
             .with_context(|| StandardVmError::InitPflashErr)?;
-            PFlash::realize(
+            let pflash = PFlash::realize(
                 pflash,
                 &mut self.sysbus,
                 flash_end - pfl_size,
@@ -625,6 +979,10 @@ impl MachineOps for StdMachine {
                 backend,
             )
             .with_context(|| StandardVmError::RlzPflashErr)?;
+            if config.sealed {
+                pflash.lock().unwrap().set_sealed(true)?;
+            }
+            self.pflash_devs.insert(config.unit, pflash);
             flash_end -= pfl_size;
         }
 
@@ -640,7 +998,7 @@ impl MachineOps for StdMachine {
             Some(ref ds_cfg) if ds_cfg.gtk => {
                 let ui_context = UiContext {
                     vm_name: vm_config.guest_name.clone(),
-                    power_button: None,
+                    power_button: Some(self.power_button.clone()),
                     shutdown_req: Some(self.shutdown_req.clone()),
                     pause_req: None,
                     resume_req: None,
@@ -686,6 +1044,15 @@ impl MachineOps for StdMachine {
         Ok(&self.pci_host)
     }
 
+    fn set_iommu(&mut self, iommu: Arc<Mutex<Iommu>>) -> Result<()> {
+        self.iommu = Some(iommu);
+        Ok(())
+    }
+
+    fn get_iommu(&self) -> Option<Arc<Mutex<Iommu>>> {
+        self.iommu.clone()
+    }
+
     fn get_sys_bus(&mut self) -> &SysBus {
         &self.sysbus
     }
@@ -708,6 +1075,10 @@ impl MachineOps for StdMachine {
     fn get_boot_order_list(&self) -> Option<Arc<Mutex<Vec<BootIndexInfo>>>> {
         Some(self.boot_order_list.clone())
     }
+
+    fn get_pflash(&self, unit: usize) -> Option<Arc<Mutex<PFlash>>> {
+        self.pflash_devs.get(&unit).cloned()
+    }
 }
 
 impl AcpiBuilder for StdMachine {
@@ -736,13 +1107,19 @@ impl AcpiBuilder for StdMachine {
         // 3. Info of devices attached to system bus.
         dsdt.append_child(self.sysbus.aml_bytes().as_slice());
 
-        // 4. Add _S5 sleep state.
-        let mut package = AmlPackage::new(4);
-        package.append_child(AmlInteger(5));
-        package.append_child(AmlInteger(0));
-        package.append_child(AmlInteger(0));
-        package.append_child(AmlInteger(0));
-        dsdt.append_child(AmlNameDecl::new("_S5", package).aml_bytes().as_slice());
+        // 4. Add _S3 and _S5 sleep state.
+        let mut sleep_states = vec![("_S5", ACPI_SLEEP_TYPE_S5)];
+        if self.is_s3_supported() {
+            sleep_states.push(("_S3", ACPI_SLEEP_TYPE_S3));
+        }
+        for (name, sleep_type) in sleep_states {
+            let mut package = AmlPackage::new(4);
+            package.append_child(AmlInteger(sleep_type as u64));
+            package.append_child(AmlInteger(0));
+            package.append_child(AmlInteger(0));
+            package.append_child(AmlInteger(0));
+            dsdt.append_child(AmlNameDecl::new(name, package).aml_bytes().as_slice());
+        }
 
         let dsdt_begin = StdMachine::add_table_to_loader(acpi_data, loader, &dsdt)
             .with_context(|| "Fail to add DSTD table to loader")?;
@@ -770,6 +1147,17 @@ impl AcpiBuilder for StdMachine {
         };
         madt.append_child(ioapic.aml_bytes().as_ref());
 
+        // SCI is level triggered and active high.
+        let sci_override = AcpiInterruptSourceOverride {
+            type_id: 2_u8,
+            length: size_of::<AcpiInterruptSourceOverride>() as u8,
+            bus: 0,
+            source: SCI_IRQ as u8,
+            gsi: SCI_IRQ,
+            flags: 0x000D,
+        };
+        madt.append_child(&sci_override.aml_bytes());
+
         self.cpus.iter().for_each(|cpu| {
             let lapic = AcpiLocalApic {
                 type_id: 0,
@@ -936,6 +1324,14 @@ impl MachineLifecycle for StdMachine {
         true
     }
 
+    fn powerdown(&self) -> bool {
+        if self.power_button.write(1).is_err() {
+            error!("X86 standard vm write power button failed");
+            return false;
+        }
+        true
+    }
+
     fn reset(&mut self) -> bool {
         if self.reset_req.write(1).is_err() {
             error!("X86 standard vm write reset request failed");
@@ -944,6 +1340,18 @@ impl MachineLifecycle for StdMachine {
         true
     }
 
+    fn get_shutdown_action(&self) -> ShutdownAction {
+        self.vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .shutdown_action
+    }
+
+    fn get_reboot_action(&self) -> RebootAction {
+        self.vm_config.lock().unwrap().machine_config.reboot_action
+    }
+
     fn notify_lifecycle(&self, old: KvmVmState, new: KvmVmState) -> bool {
         if let Err(e) =
             self.vm_state_transfer(&self.cpus, &mut self.vm_state.0.lock().unwrap(), old, new)
@@ -953,6 +1361,10 @@ impl MachineLifecycle for StdMachine {
         }
         true
     }
+
+    fn shared_vm_state(&self) -> Option<Arc<(Mutex<KvmVmState>, Condvar)>> {
+        Some(self.vm_state.clone())
+    }
 }
 
 impl MachineAddressInterface for StdMachine {
@@ -1003,6 +1415,12 @@ impl MachineAddressInterface for StdMachine {
             .write(&mut data, GuestAddress(addr), count)
             .is_ok()
     }
+
+    fn ioapic_eoi(&self, vector: u8) {
+        if let Some(ioapic) = self.ioapic.as_ref() {
+            ioapic.lock().unwrap().eoi(vector);
+        }
+    }
 }
 
 impl MigrateInterface for StdMachine {
@@ -1018,6 +1436,11 @@ impl MigrateInterface for StdMachine {
         }
     }
 
+    fn migrate_task(&self, uri: String) -> Result<JobTask> {
+        let (mode, path) = parse_incoming_uri(&uri)?;
+        migration::migration_task(mode, path)
+    }
+
     fn query_migrate(&self) -> Response {
         migration::query_migrate()
     }
@@ -1025,6 +1448,21 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::set_migrate_parameters(args)
+    }
+
+    fn query_migrate_parameters(&self) -> Response {
+        migration::query_migrate_parameters()
+    }
+
+    fn migrate_set_capabilities(
+        &self,
+        args: qmp_schema::MigrateSetCapabilitiesArgument,
+    ) -> Response {
+        migration::set_migrate_capabilities(args)
+    }
 }
 
 impl MachineInterface for StdMachine {}
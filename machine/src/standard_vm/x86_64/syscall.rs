@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use block_backend::zoned::{BLKCLOSEZONE, BLKFINISHZONE, BLKOPENZONE, BLKREPORTZONE, BLKRESETZONE};
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
 use util::tap::{TUNGETFEATURES, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ};
@@ -207,6 +208,24 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_fadvise64),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_shmget),
+        // Syscalls of virtio-9p to serve the shared directory.
+        BpfRule::new(libc::SYS_rename),
+        BpfRule::new(libc::SYS_renameat2),
+        BpfRule::new(libc::SYS_rmdir),
+        BpfRule::new(libc::SYS_unlinkat),
+        BpfRule::new(libc::SYS_link),
+        BpfRule::new(libc::SYS_linkat),
+        BpfRule::new(libc::SYS_symlink),
+        BpfRule::new(libc::SYS_symlinkat),
+        BpfRule::new(libc::SYS_mknodat),
+        BpfRule::new(libc::SYS_lchown),
+        BpfRule::new(libc::SYS_fchownat),
+        BpfRule::new(libc::SYS_fchmodat),
+        BpfRule::new(libc::SYS_utimensat),
+        BpfRule::new(libc::SYS_statfs),
+        BpfRule::new(libc::SYS_lgetxattr),
+        BpfRule::new(libc::SYS_lsetxattr),
+        BpfRule::new(libc::SYS_fsync),
     ]
 }
 
@@ -244,6 +263,11 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETQUEUE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKREPORTZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKRESETZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKOPENZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKCLOSEZONE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, BLKFINISHZONE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GSI_ROUTING() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IRQFD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VFIO_DEVICE_SET_IRQS() as u32)
@@ -265,6 +289,7 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_VCPU_EVENTS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_PIT2() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_CLOCK() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_CLOCK() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_IRQCHIP() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REGS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_SREGS() as u32)
@@ -285,7 +310,13 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_LAPIC() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MSRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_VCPU_EVENTS() as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_PIT2() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IRQ_LINE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GUEST_DEBUG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_ENABLE_CAP() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_TRANSLATE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_INTERRUPT() as u32);
 
     #[cfg(feature = "usb_camera_v4l2")]
     let bpf_rule = bpf_rule
@@ -313,13 +344,17 @@ fn madvise_rule() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_COLD as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_PAGEOUT as u32);
     #[cfg(target_env = "gnu")]
     return BpfRule::new(libc::SYS_madvise)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_COLD as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_PAGEOUT as u32);
 }
 
 fn futex_rule() -> BpfRule {
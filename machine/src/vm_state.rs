@@ -39,28 +39,28 @@ impl StateTransfer for KvmDevice {
         let kvm_fds = KVM_FDS.load();
         let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
 
-        // save pit
-        let pit_state = vm_fd.get_pit2()?;
-
         // save kvm_clock
         let mut kvm_clock = vm_fd.get_clock()?;
         // Reset kvm clock flag.
         kvm_clock.flags = 0;
 
-        // save ioapic
-        let mut ioapic = kvm_irqchip {
-            chip_id: KVM_IRQCHIP_IOAPIC,
+        let mut state = KvmDeviceState {
+            kvm_clock,
             ..Default::default()
         };
-        vm_fd.get_irqchip(&mut ioapic)?;
-
-        Ok(KvmDeviceState {
-            pit_state,
-            kvm_clock,
-            ioapic,
+        // There is no pit and ioapic in kvm with split irqchip.
+        if kvm_fds.is_split_irqchip() {
+            return Ok(state.as_bytes().to_vec());
         }
-        .as_bytes()
-        .to_vec())
+
+        // save pit
+        state.pit_state = vm_fd.get_pit2()?;
+
+        // save ioapic
+        state.ioapic.chip_id = KVM_IRQCHIP_IOAPIC;
+        vm_fd.get_irqchip(&mut state.ioapic)?;
+
+        Ok(state.as_bytes().to_vec())
     }
 
     fn set_state(&self, state: &[u8]) -> migration::Result<()> {
@@ -70,9 +70,11 @@ impl StateTransfer for KvmDevice {
         let kvm_state = KvmDeviceState::from_bytes(state)
             .with_context(|| MigrationError::FromBytesError("KVM_DEVICE"))?;
 
-        vm_fd.set_pit2(&kvm_state.pit_state)?;
         vm_fd.set_clock(&kvm_state.kvm_clock)?;
-        vm_fd.set_irqchip(&kvm_state.ioapic)?;
+        if !kvm_fds.is_split_irqchip() {
+            vm_fd.set_pit2(&kvm_state.pit_state)?;
+            vm_fd.set_irqchip(&kvm_state.ioapic)?;
+        }
 
         Ok(())
     }
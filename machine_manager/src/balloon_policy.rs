@@ -0,0 +1,398 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Automatic ballooning policy.
+//!
+//! The policy samples the memory statistics reported by the guest through the balloon
+//! device and the available memory of the host periodically. It adjusts the memory size
+//! of the guest within the configured bounds, so that a percentage of the guest memory
+//! is kept available, and the guest is not grown when the host is short of memory.
+
+use std::fs::read_to_string;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::event_loop::EventLoop;
+use crate::qmp::qmp_schema::{BalloonPolicyArgument, BalloonPolicyInfo};
+
+const DEFAULT_POLICY_INTERVAL: u32 = 10;
+const MIN_FREE_PERCENT: u32 = 5;
+const MAX_FREE_PERCENT: u32 = 80;
+const DEFAULT_FREE_PERCENT: u32 = 20;
+const DEFAULT_HOST_MIN_FREE: u64 = 1 << 30;
+/// The memory size of guest is not changed if the difference is smaller than it.
+const MIN_ADJUSTMENT: u64 = 16 << 20;
+
+/// Memory statistics of guest used by the policy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GuestMemStats {
+    /// Current memory size of guest, the memory in balloon is excluded.
+    pub actual: u64,
+    /// Memory available for starting new applications reported by guest.
+    pub available: u64,
+    /// Time of the report in seconds since the epoch.
+    pub last_update: u64,
+}
+
+/// Operations of balloon device used by the policy.
+pub trait BalloonPolicyOps: Send + Sync {
+    /// Get the RAM size of guest.
+    fn ram_size(&self) -> u64;
+
+    /// Get the latest memory statistics, `None` if guest doesn't report them.
+    fn guest_mem_stats(&self) -> Option<GuestMemStats>;
+
+    /// Get the interval to poll memory statistics in seconds, 0 if polling is stopped.
+    fn stats_polling_interval(&self) -> u32;
+
+    fn set_stats_polling_interval(&self, interval: u32) -> Result<()>;
+
+    /// Set the target memory size of guest.
+    fn set_guest_memory_size(&self, size: u64) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BalloonPolicyConfig {
+    /// Interval to adjust the memory size in seconds.
+    interval: u32,
+    /// Lower bound of the memory size of guest.
+    min_size: u64,
+    /// Upper bound of the memory size of guest.
+    max_size: u64,
+    /// Percentage of guest memory to be kept available.
+    free_percent: u32,
+    /// Memory of guest is not grown if the available memory of host is below it.
+    host_min_free: u64,
+}
+
+impl BalloonPolicyConfig {
+    fn new(ram_size: u64) -> Self {
+        BalloonPolicyConfig {
+            interval: DEFAULT_POLICY_INTERVAL,
+            min_size: 0,
+            max_size: ram_size,
+            free_percent: DEFAULT_FREE_PERCENT,
+            host_min_free: DEFAULT_HOST_MIN_FREE,
+        }
+    }
+
+    fn update(&mut self, args: &BalloonPolicyArgument) {
+        if let Some(interval) = args.interval {
+            self.interval = interval;
+        }
+        if let Some(min_size) = args.min_size {
+            self.min_size = min_size;
+        }
+        if let Some(max_size) = args.max_size {
+            self.max_size = max_size;
+        }
+        if let Some(free_percent) = args.free_percent {
+            self.free_percent = free_percent;
+        }
+        if let Some(host_min_free) = args.host_min_free {
+            self.host_min_free = host_min_free;
+        }
+    }
+
+    fn check(&self, ram_size: u64) -> Result<()> {
+        if self.interval == 0 {
+            bail!("The interval of balloon policy must be greater than 0");
+        }
+        if self.free_percent < MIN_FREE_PERCENT || self.free_percent > MAX_FREE_PERCENT {
+            bail!(
+                "The free-percent of balloon policy must be in range [{}, {}]",
+                MIN_FREE_PERCENT,
+                MAX_FREE_PERCENT
+            );
+        }
+        if self.max_size > ram_size {
+            bail!(
+                "The max-size {} of balloon policy exceeds the RAM size {}",
+                self.max_size,
+                ram_size
+            );
+        }
+        if self.min_size > self.max_size {
+            bail!(
+                "The min-size {} of balloon policy is greater than max-size {}",
+                self.min_size,
+                self.max_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Compute the new memory size of guest, `None` if it needn't be changed.
+    fn compute_target(&self, stats: &GuestMemStats, host_available: u64) -> Option<u64> {
+        let used = stats.actual.saturating_sub(stats.available);
+        let mut target = used * 100 / u64::from(100 - self.free_percent);
+        if host_available < self.host_min_free {
+            target = target.min(stats.actual);
+        } else {
+            target = target.min(stats.actual + host_available - self.host_min_free);
+        }
+        target = target.clamp(self.min_size, self.max_size);
+
+        if target.abs_diff(stats.actual) < MIN_ADJUSTMENT {
+            return None;
+        }
+        Some(target)
+    }
+}
+
+#[derive(Default)]
+struct BalloonPolicy {
+    ops: Option<Arc<dyn BalloonPolicyOps>>,
+    /// It's created with default values at the first time the policy is set.
+    config: Option<BalloonPolicyConfig>,
+    enabled: bool,
+    timer_id: Option<u64>,
+    /// Time of the statistics which are used last time.
+    last_update: u64,
+}
+
+impl BalloonPolicy {
+    fn start_timer(&mut self) {
+        self.stop_timer();
+        let interval = match self.config {
+            Some(config) => config.interval,
+            None => return,
+        };
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            let func = Box::new(balloon_policy_timer_func);
+            self.timer_id = Some(ctx.timer_add(func, Duration::from_secs(interval as u64)));
+        }
+    }
+
+    fn stop_timer(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
+
+    fn adjust(&mut self) -> Result<()> {
+        let ops = self
+            .ops
+            .clone()
+            .with_context(|| "No balloon device has been activated")?;
+        let config = self
+            .config
+            .with_context(|| "Balloon policy is not configured")?;
+        let stats = match ops.guest_mem_stats() {
+            Some(stats) => stats,
+            None => return Ok(()),
+        };
+        // Wait for the new statistics of guest.
+        if stats.last_update == self.last_update {
+            return Ok(());
+        }
+        self.last_update = stats.last_update;
+
+        let host_available = host_available_memory()?;
+        if let Some(target) = config.compute_target(&stats, host_available) {
+            info!(
+                "Balloon policy: adjust memory of guest from {} to {}, host available {}",
+                stats.actual, target, host_available
+            );
+            ops.set_guest_memory_size(target)?;
+        }
+        Ok(())
+    }
+}
+
+static BALLOON_POLICY: Lazy<Mutex<BalloonPolicy>> =
+    Lazy::new(|| Mutex::new(BalloonPolicy::default()));
+
+fn balloon_policy_timer_func() {
+    let mut policy = BALLOON_POLICY.lock().unwrap();
+    // The timer is removed after it fires.
+    policy.timer_id = None;
+    if !policy.enabled {
+        return;
+    }
+    if let Err(e) = policy.adjust() {
+        error!("Failed to adjust balloon by policy: {:?}", e);
+    }
+    policy.start_timer();
+}
+
+/// Get the available memory of host from `/proc/meminfo`.
+fn host_available_memory() -> Result<u64> {
+    let meminfo = read_to_string("/proc/meminfo").with_context(|| "Failed to read meminfo")?;
+    for line in meminfo.lines() {
+        if let Some(value) = line.strip_prefix("MemAvailable:") {
+            let kb = value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .with_context(|| format!("Invalid MemAvailable {}", value))?;
+            return Ok(kb << 10);
+        }
+    }
+    Err(anyhow!("MemAvailable is not found in meminfo"))
+}
+
+/// Register the balloon device which is adjusted by the policy.
+pub fn register_balloon_policy_ops(ops: Arc<dyn BalloonPolicyOps>) {
+    BALLOON_POLICY.lock().unwrap().ops = Some(ops);
+}
+
+/// Unregister the balloon device when it is unplugged, the policy is disabled as well.
+pub fn unregister_balloon_policy_ops() {
+    let mut policy = BALLOON_POLICY.lock().unwrap();
+    policy.enabled = false;
+    policy.stop_timer();
+    policy.ops = None;
+}
+
+/// Enable, disable or tune the policy. Polling of memory statistics is started
+/// with the interval of the policy if it's stopped.
+pub fn set_balloon_policy(args: &BalloonPolicyArgument) -> Result<()> {
+    let mut policy = BALLOON_POLICY.lock().unwrap();
+    let ops = policy
+        .ops
+        .clone()
+        .with_context(|| "No balloon device has been activated")?;
+    let ram_size = ops.ram_size();
+    let mut config = policy
+        .config
+        .unwrap_or_else(|| BalloonPolicyConfig::new(ram_size));
+    config.update(args);
+    config.check(ram_size)?;
+
+    if args.enable && ops.stats_polling_interval() == 0 {
+        ops.set_stats_polling_interval(config.interval)
+            .with_context(|| "Failed to start polling memory statistics of guest")?;
+    }
+    policy.config = Some(config);
+    policy.enabled = args.enable;
+    policy.last_update = 0;
+    if policy.enabled {
+        policy.start_timer();
+    } else {
+        policy.stop_timer();
+    }
+    Ok(())
+}
+
+pub fn query_balloon_policy() -> Result<BalloonPolicyInfo> {
+    let policy = BALLOON_POLICY.lock().unwrap();
+    let ops = policy
+        .ops
+        .as_ref()
+        .with_context(|| "No balloon device has been activated")?;
+    let config = policy
+        .config
+        .unwrap_or_else(|| BalloonPolicyConfig::new(ops.ram_size()));
+    Ok(BalloonPolicyInfo {
+        enabled: policy.enabled,
+        interval: config.interval,
+        min_size: config.min_size,
+        max_size: config.max_size,
+        free_percent: config.free_percent,
+        host_min_free: config.host_min_free,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1 << 30;
+
+    #[test]
+    fn test_balloon_policy_config() {
+        let mut config = BalloonPolicyConfig::new(8 * GB);
+        assert!(config.check(8 * GB).is_ok());
+
+        let args = BalloonPolicyArgument {
+            enable: true,
+            min_size: Some(2 * GB),
+            free_percent: Some(30),
+            ..Default::default()
+        };
+        config.update(&args);
+        assert_eq!(config.min_size, 2 * GB);
+        assert_eq!(config.max_size, 8 * GB);
+        assert_eq!(config.free_percent, 30);
+        assert_eq!(config.interval, DEFAULT_POLICY_INTERVAL);
+        assert!(config.check(8 * GB).is_ok());
+
+        config.free_percent = 90;
+        assert!(config.check(8 * GB).is_err());
+        config.free_percent = 30;
+        config.max_size = 16 * GB;
+        assert!(config.check(8 * GB).is_err());
+        config.max_size = GB;
+        assert!(config.check(8 * GB).is_err());
+        config.max_size = 8 * GB;
+        config.interval = 0;
+        assert!(config.check(8 * GB).is_err());
+    }
+
+    #[test]
+    fn test_balloon_policy_compute_target() {
+        let config = BalloonPolicyConfig {
+            interval: 10,
+            min_size: 2 * GB,
+            max_size: 8 * GB,
+            free_percent: 25,
+            host_min_free: GB,
+        };
+
+        // 3GB is used, keep 1GB available.
+        let stats = GuestMemStats {
+            actual: 8 * GB,
+            available: 5 * GB,
+            last_update: 1,
+        };
+        assert_eq!(config.compute_target(&stats, 16 * GB), Some(4 * GB));
+
+        // Shrinking is limited by min-size.
+        let stats = GuestMemStats {
+            actual: 4 * GB,
+            available: 4 * GB - 512 * 1024 * 1024,
+            last_update: 1,
+        };
+        assert_eq!(config.compute_target(&stats, 16 * GB), Some(2 * GB));
+
+        // 6GB is used, grow to 8GB if the host has enough memory.
+        let stats = GuestMemStats {
+            actual: 7 * GB,
+            available: GB,
+            last_update: 1,
+        };
+        assert_eq!(config.compute_target(&stats, 16 * GB), Some(8 * GB));
+        // Growing is limited by the available memory of host.
+        assert_eq!(
+            config.compute_target(&stats, GB + 512 * 1024 * 1024),
+            Some(7 * GB + 512 * 1024 * 1024)
+        );
+        // Never grow when the host is short of memory.
+        assert_eq!(config.compute_target(&stats, GB / 2), None);
+
+        // The difference is too small.
+        let stats = GuestMemStats {
+            actual: 4 * GB,
+            available: GB,
+            last_update: 1,
+        };
+        assert_eq!(config.compute_target(&stats, 16 * GB), None);
+    }
+}
@@ -84,10 +84,11 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("machine")
             .long("machine")
-            .value_name("[type=]<name>[,dump_guest_core=on|off][,mem-share=on|off]")
+            .value_name("[type=]<name>[,dump_guest_core=on|off][,mem-share=on|off][,hpet=on|off]")
             .help("'type' selects emulated machine type and set properties. \
                    'dump_guest_core' includes guest memory in a core dump. \
-                   'mem-share' sets guest memory is shareable.")
+                   'mem-share' sets guest memory is shareable. \
+                   'hpet' enables the HPET of x86_64 standard machine (default: on).")
             .takes_value(true),
         )
         .arg(
@@ -112,7 +113,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("cpu")
             .long("cpu")
-            .value_name("host[,pmu=on|off]")
+            .value_name("host|<model>[,+feature|-feature][,pmu=on|off][,sve=on|off][,sve-max-vq=<vq>]")
             .help("set CPU model and features.")
             .can_no_value(false)
             .takes_value(true)
@@ -128,7 +129,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("memory")
             .long("m")
-            .value_name("[size=]<megs>[m|M|g|G]")
+            .value_name("[size=]<megs>[m|M|g|G][,prealloc=on|off][,prealloc-threads=<n>][,prealloc-check=on|off][,slots=<n>]")
             .help("configure guest RAM(default unit: MiB).")
             .takes_value(true),
         )
@@ -177,6 +178,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("use 'initrd-file' as initial ram disk")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("dtb")
+            .long("dtb")
+            .value_name("<dtb_path>")
+            .help("merge 'dtb' into the generated device tree (aarch64 only)")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("qmp")
             .long("qmp")
@@ -239,12 +247,26 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tadd virtio pci balloon: -device virtio-balloon-pci,id=<balloon_id>,bus=<pcie.0>,addr=<0x4>[,deflate-on-oom=true|false][,free-page-reporting=true|false][,multifunction=on|off]; \
                    \n\t\tadd virtio mmio rng: -device virtio-rng-device,rng=<objrng0>,max-bytes=<1234>,period=<1000>; \
                    \n\t\tadd virtio pci rng: -device virtio-rng-pci,id=<rng_id>,rng=<objrng0>,max-bytes=<1234>,period=<1000>,bus=<pcie.0>,addr=<0x1>[,multifunction=on|off]; \
+                   \n\t\tadd virtio mmio crypto: -device virtio-crypto-device,cryptodev=<cryptodev0>; \
+                   \n\t\tadd virtio pci crypto: -device virtio-crypto-pci,id=<crypto_id>,cryptodev=<cryptodev0>,bus=<pcie.0>,addr=<0x5>[,multifunction=on|off]; \
+                   \n\t\tadd virtio mmio 9p: -device virtio-9p-device,id=<fs0>,path=<path/to/dir>,mount_tag=<hostshare>[,security_model=mapped|passthrough]; \
+                   \n\t\tadd virtio pci 9p: -device virtio-9p-pci,id=<fs0>,path=<path/to/dir>,mount_tag=<hostshare>[,security_model=mapped|passthrough],bus=<pcie.0>,addr=<0x6>[,multifunction=on|off]; \
+                   \n\t\tadd virtio pci pmem: -device virtio-pmem-pci,id=<pmem_id>,memdev=<mem0>,bus=<pcie.0>,addr=<0x6>[,multifunction=on|off]; \
+                   \n\t\tadd virtio pci sound: -device virtio-sound-pci,id=<snd_id>[,audiodev=none|alsa][,pcm=<default>],bus=<pcie.0>,addr=<0x7>[,multifunction=on|off]; \
                    \n\t\tadd pcie root port: -device pcie-root-port,id=<pcie.1>,port=<0x1>,bus=<pcie.0>,addr=<0x1>[,multifunction=on|off]; \
                    \n\t\tadd vfio pci: -device vfio-pci,id=<vfio_id>,host=<0000:1a:00.3>,bus=<pcie.0>,addr=<0x03>[,multifunction=on|off]; \
+                   \n\t\tadd vfio platform: -device vfio-platform,id=<vfio_id>,host=<fff51000.ethernet>,dtb=<path/to/overlay.dtb>; \
                    \n\t\tadd usb controller: -device nec-usb-xhci,id=<xhci>,bus=<pcie.0>,addr=<0xa>; \
-                   \n\t\tadd usb keyboard: -device usb-kbd,id=<kbd>; \
-                   \n\t\tadd usb tablet: -device usb-tablet,id=<tablet>; \
-                   \n\t\tadd usb storage: -device usb-storage,id=<storage>,drive=<drive_id>; \
+                   \n\t\tadd nvme controller: -device nvme,id=<nvme0>,drive=<drive0>,serial=<deadbeef>[,iothread=<iothread1>][,num-queues=<N>],bus=<pcie.0>,addr=<0x7>; \
+                   \n\t\tadd e1000e nic: -device e1000e,id=<net0>,netdev=<netdev0>,bus=<pcie.0>,addr=<0x8>[,mac=<12:34:56:78:9A:BC>][,iothread=<iothread1>]; \
+                   \n\t\tadd ahci controller: -device ahci,id=<ahci0>,bus=<pcie.0>,addr=<0x9>[,iothread=<iothread1>]; \
+                   \n\t\tadd ide disk: -device ide-hd,id=<disk0>,bus=<ahci0>.<0>,drive=<drive0>[,serial=<serial>]; \
+                   \n\t\tadd ide cdrom: -device ide-cd,id=<cd0>,bus=<ahci0>.<1>,drive=<drive1>; \
+                   \n\t\tadd usb hub: -device usb-hub,id=<hub>[,ports=<8>]; \
+                   \n\t\tadd usb keyboard: -device usb-kbd,id=<kbd>[,bus=<hub>]; \
+                   \n\t\tadd usb tablet: -device usb-tablet,id=<tablet>[,bus=<hub>]; \
+                   \n\t\tadd usb storage: -device usb-storage,id=<storage>,drive=<drive_id>[,bus=<hub>]; \
+                   \n\t\tadd usb u2f key: -device usb-u2f,id=<u2f>,hidraw=</dev/hidraw0>[,bus=<hub>]; \
                    \n\t\tadd scsi controller: -device virtio-scsi-pci,id=<scsi_id>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off][,iothread=<iothread1>][,num-queues=<N>]; \
                    \n\t\tadd scsi hard disk: -device scsi-hd,scsi-id=<0>,bus=<scsi0.0>,lun=<0>,drive=<drive-scsi0-0-0-0>,id=<scsi0-0-0-0>; \
                    \n\t\tadd vhost user fs: -device vhost-user-fs-pci,id=<device_id>,chardev=<chardev_id>,tag=<mount_tag>")
@@ -265,6 +287,20 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(true)
             .can_no_value(true),
         )
+        .arg(
+            Arg::with_name("log-level")
+            .long("log-level")
+            .value_name("<level>[,<subsystem>=<level>...]")
+            .help("set log level of all subsystems or each of block, net, usb and migration")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+            .long("log-format")
+            .value_name("text|json")
+            .help("set output format of log (default text)")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("pidfile")
             .long("pidfile")
@@ -297,6 +333,34 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tdo the virtual machine snapshot: -incoming file:<file path>")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("gdb")
+            .long("gdb")
+            .value_name("tcp:[<ip>]:<port>")
+            .help("start gdbstub on the tcp port for guest debugging, such as -gdb tcp::1234")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics")
+            .long("metrics")
+            .value_name("tcp:[<ip>]:<port>|unix:<path>")
+            .help("expose the metrics in Prometheus text format over http, such as -metrics tcp::9100")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config-file")
+            .long("config")
+            .value_name("<json file path>")
+            .help("load the VM configuration from the json profile, other args are applied on it")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dump-config")
+            .long("dump-config")
+            .value_name("<json file path>")
+            .help("dump the VM configuration to the json profile and exit")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("object")
             .multiple(true)
@@ -310,6 +374,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    [,mem-prealloc=<true|false>][,dump-guest-core=<true|false>][,share=<on|off>]; \
                    \n\t\tadd iothread object: -object iothread,id=<iothread_id>; \
                    \n\t\tadd rng object: -object rng-random,id=<rng_id>,filename=<file_path>; \
+                   \n\t\tadd cryptodev object: -object cryptodev-backend-builtin,id=<cryptodev_id>[,queues=<N>]; \
                    \n\t\tadd vnc tls object: -object tls-creds-x509,id=<vnc_id>,dir=</etc/pki/vnc>; \
                    \n\t\tadd authz object: -object authz-simple,id=<authz_id>,identity=<username>")
             .takes_values(true),
@@ -332,9 +397,8 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("uuid")
             .long("uuid")
-            .value_name("[uuid]")
-            .hidden(true)
-            .can_no_value(true)
+            .value_name("<uuid>")
+            .help("set the uuid of the guest, which is also reported by smbios type1 and fw_cfg.")
             .takes_value(true),
         )
         .arg(
@@ -370,9 +434,36 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("rtc")
             .long("rtc")
-            .value_name("[base=utc]")
-            .hidden(true)
-            .can_no_value(true)
+            .value_name("[base={utc|localtime|<datetime>}][,clock={host|vm}][,driftfix={none|slew}]")
+            .help("set the start time, the clock and the drift policy of the RTC")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cgroup")
+            .long("cgroup")
+            .value_name("path=<dir>[,cpu-weight=<N>][,cpuset=<cpus>]")
+            .help("place the VM in the cgroup v2 with the CPU limits")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("run-with")
+            .long("run-with")
+            .value_name("[user=<user>][,group=<group>][,chroot=<dir>][,unshare-net={on|off}][,unshare-mount={on|off}]")
+            .help("drop privileges and isolate the process after devices are realized")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("watchdog-action")
+            .long("watchdog-action")
+            .value_name("reset|shutdown|pause|debug")
+            .help("set the action when the watchdog timer expires, default to reset")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("action")
+            .long("action")
+            .value_name("[reboot=reset|shutdown][,shutdown=poweroff|pause][,panic=shutdown|pause|none][,watchdog=reset|shutdown|pause|debug]")
+            .help("set the actions on guest reboot, shutdown, panic and watchdog expiration")
             .takes_value(true),
         )
         .arg(
@@ -519,10 +610,14 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     // Parse config-file json.
     // VmConfig can be transformed by json file which described VmConfig
     // directly.
-    let mut vm_cfg = VmConfig::default();
+    let mut vm_cfg = match args.value_of("config-file") {
+        Some(path) => VmConfig::from_profile(&path)?,
+        None => VmConfig::default(),
+    };
 
     // Parse cmdline args which need to set in VmConfig
     add_args_to_config!((args.value_of("name")), vm_cfg, add_name);
+    add_args_to_config!((args.value_of("uuid")), vm_cfg, add_uuid);
     add_args_to_config!((args.value_of("machine")), vm_cfg, add_machine);
     add_args_to_config!((args.value_of("accel")), vm_cfg, add_accel);
     add_args_to_config!((args.value_of("memory")), vm_cfg, add_memory);
@@ -531,8 +626,20 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     add_args_to_config!((args.value_of("cpu")), vm_cfg, add_cpu_feature);
     add_args_to_config!((args.value_of("kernel")), vm_cfg, add_kernel);
     add_args_to_config!((args.value_of("initrd-file")), vm_cfg, add_initrd);
+    add_args_to_config!((args.value_of("dtb")), vm_cfg, add_dtb);
     add_args_to_config!((args.value_of("serial")), vm_cfg, add_serial);
     add_args_to_config!((args.value_of("incoming")), vm_cfg, add_incoming);
+    add_args_to_config!((args.value_of("gdb")), vm_cfg, add_gdb);
+    add_args_to_config!((args.value_of("metrics")), vm_cfg, add_metrics);
+    add_args_to_config!(
+        (args.value_of("watchdog-action")),
+        vm_cfg,
+        add_watchdog_action
+    );
+    add_args_to_config!((args.value_of("action")), vm_cfg, add_action);
+    add_args_to_config!((args.value_of("rtc")), vm_cfg, add_rtc);
+    add_args_to_config!((args.value_of("run-with")), vm_cfg, add_sandbox);
+    add_args_to_config!((args.value_of("cgroup")), vm_cfg, add_cgroup);
     #[cfg(feature = "vnc")]
     add_args_to_config!((args.value_of("vnc")), vm_cfg, add_vnc);
     #[cfg(feature = "gtk")]
@@ -0,0 +1,153 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CmdParser, ShutdownAction, VmConfig, WatchdogAction};
+
+/// Action to take when the guest requests a reboot, such as a triple fault on x86_64,
+/// a PSCI reset on aarch64 or the ACPI reset register.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RebootAction {
+    #[default]
+    Reset,
+    /// Handle the reboot as a guest shutdown, which then follows the shutdown action.
+    Shutdown,
+}
+
+impl FromStr for RebootAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reset" => Ok(RebootAction::Reset),
+            "shutdown" => Ok(RebootAction::Shutdown),
+            _ => Err(anyhow!(
+                "Unknown reboot action {}, must be one of reset or shutdown",
+                s
+            )),
+        }
+    }
+}
+
+/// Action to take when the guest reports a panic.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum PanicAction {
+    #[default]
+    Shutdown,
+    Pause,
+    /// Only report the panic and keep the VM running.
+    None,
+}
+
+impl FromStr for PanicAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(PanicAction::Shutdown),
+            "pause" => Ok(PanicAction::Pause),
+            "none" => Ok(PanicAction::None),
+            _ => Err(anyhow!(
+                "Unknown panic action {}, must be one of shutdown, pause or none",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for ShutdownAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "poweroff" => Ok(ShutdownAction::ShutdownActionPoweroff),
+            "pause" => Ok(ShutdownAction::ShutdownActionPause),
+            _ => Err(anyhow!(
+                "Unknown shutdown action {}, must be one of poweroff or pause",
+                s
+            )),
+        }
+    }
+}
+
+impl VmConfig {
+    /// Set the actions on guest events, e.g. `panic=pause,watchdog=reset,reboot=shutdown`.
+    /// The actions which are not given keep unchanged.
+    pub fn add_action(&mut self, action_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("action");
+        cmd_parser
+            .push("reboot")
+            .push("shutdown")
+            .push("panic")
+            .push("watchdog");
+        cmd_parser.parse(action_config)?;
+
+        let machine_config = &mut self.machine_config;
+        if let Some(reboot) = cmd_parser.get_value::<RebootAction>("reboot")? {
+            machine_config.reboot_action = reboot;
+        }
+        if let Some(shutdown) = cmd_parser.get_value::<ShutdownAction>("shutdown")? {
+            machine_config.shutdown_action = shutdown;
+        }
+        if let Some(panic) = cmd_parser.get_value::<PanicAction>("panic")? {
+            machine_config.panic_action = panic;
+        }
+        if let Some(watchdog) = cmd_parser.get_value::<WatchdogAction>("watchdog")? {
+            machine_config.watchdog_action = watchdog;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_action() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.machine_config.reboot_action, RebootAction::Reset);
+        assert_eq!(vm_config.machine_config.panic_action, PanicAction::Shutdown);
+
+        assert!(vm_config
+            .add_action("panic=pause,watchdog=reset,reboot=shutdown")
+            .is_ok());
+        let machine_config = &vm_config.machine_config;
+        assert_eq!(machine_config.panic_action, PanicAction::Pause);
+        assert_eq!(machine_config.watchdog_action, WatchdogAction::Reset);
+        assert_eq!(machine_config.reboot_action, RebootAction::Shutdown);
+        assert_eq!(
+            machine_config.shutdown_action,
+            ShutdownAction::ShutdownActionPoweroff
+        );
+
+        assert!(vm_config.add_action("shutdown=pause").is_ok());
+        assert_eq!(
+            vm_config.machine_config.shutdown_action,
+            ShutdownAction::ShutdownActionPause
+        );
+        assert_eq!(
+            vm_config.machine_config.reboot_action,
+            RebootAction::Shutdown
+        );
+
+        assert!(vm_config.add_action("reboot=pause").is_err());
+        assert!(vm_config.add_action("panic=reset").is_err());
+        assert!(vm_config.add_action("shutdown=reset").is_err());
+        assert!(vm_config.add_action("watchdog=none").is_err());
+        assert!(vm_config.add_action("crash=pause").is_err());
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{error::ConfigError, pci_args_check, DriveConfig};
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, VmConfig};
+
+/// Number of the ports of the ICH9 AHCI controller.
+pub const AHCI_MAX_PORTS: u8 = 6;
+/// Max length of the serial number in the identify data.
+const MAX_IDE_SERIAL_LEN: usize = 20;
+
+/// Config structure for the emulated AHCI controller.
+#[derive(Debug, Clone, Default)]
+pub struct AhciConfig {
+    pub id: String,
+    /// Thread name of io handler, which is also used by the attached drives.
+    pub iothread: Option<String>,
+}
+
+impl ConfigCheck for AhciConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "ahci id")?;
+        if let Some(iothread) = self.iothread.as_ref() {
+            check_arg_too_long(iothread, "iothread name")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_ahci(ahci_config: &str) -> Result<AhciConfig> {
+    let mut cmd_parser = CmdParser::new("ahci");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("iothread");
+    cmd_parser.parse(ahci_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "ahci".to_string()))?;
+    let ahci_cfg = AhciConfig {
+        id,
+        iothread: cmd_parser.get_value::<String>("iothread")?,
+    };
+    ahci_cfg.check()?;
+    Ok(ahci_cfg)
+}
+
+/// Config structure for the ide-hd and ide-cd devices attached to the AHCI controller.
+#[derive(Debug, Clone)]
+pub struct IdeDevConfig {
+    pub id: String,
+    /// AHCI controller which the device attaches to.
+    pub cntlr: String,
+    /// Port of the controller.
+    pub port: u8,
+    /// Serial number reported in the identify data.
+    pub serial: Option<String>,
+    pub drive: DriveConfig,
+    /// Secret data used to unlock the luks image.
+    pub key_secret: Option<String>,
+}
+
+impl ConfigCheck for IdeDevConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "ide device id")?;
+        if self.port >= AHCI_MAX_PORTS {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "port of ide device".to_string(),
+                0,
+                true,
+                AHCI_MAX_PORTS as u64,
+                false,
+            )));
+        }
+        if self
+            .serial
+            .as_ref()
+            .is_some_and(|serial| serial.len() > MAX_IDE_SERIAL_LEN)
+        {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "ide device serial".to_string(),
+                MAX_IDE_SERIAL_LEN,
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parse the ide-hd and ide-cd devices, which are in the format
+/// "ide-hd,id=<disk0>,bus=<ahci0>.<port>,drive=<drive0>[,serial=<serial>]".
+pub fn parse_ide_device(vm_config: &mut VmConfig, ide_config: &str) -> Result<IdeDevConfig> {
+    let mut cmd_parser = CmdParser::new("ide-device");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("drive")
+        .push("serial");
+    cmd_parser.parse(ide_config)?;
+
+    let id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "ide device".to_string()))?;
+    let bus = cmd_parser.get_value::<String>("bus")?.with_context(|| {
+        ConfigError::FieldIsMissing("bus".to_string(), "ide device".to_string())
+    })?;
+    // Format "$parent_cntlr_name.$port" is required by ahci bus.
+    let (cntlr, port) = match bus.split_once('.') {
+        Some((cntlr, port)) if !cntlr.is_empty() => (
+            cntlr.to_string(),
+            port.parse::<u8>()
+                .with_context(|| format!("Invalid port of ahci bus {}", bus))?,
+        ),
+        _ => bail!("Invalid ahci bus {}", bus),
+    };
+    let drive_id = cmd_parser.get_value::<String>("drive")?.with_context(|| {
+        ConfigError::FieldIsMissing("drive".to_string(), "ide device".to_string())
+    })?;
+
+    let drive = vm_config
+        .drives
+        .remove(&drive_id)
+        .with_context(|| "No drive configured matched for ide device")?;
+    let key_secret = match drive.key_secret.as_ref() {
+        Some(secret) => Some(vm_config.get_secret(secret)?),
+        None => None,
+    };
+    let ide_cfg = IdeDevConfig {
+        id,
+        cntlr,
+        port,
+        serial: cmd_parser.get_value::<String>("serial")?,
+        drive,
+        key_secret,
+    };
+    ide_cfg.check()?;
+    Ok(ide_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ahci_config_cmdline_parser() {
+        let config = parse_ahci("ahci,id=ahci0,bus=pcie.0,addr=0x1f.0x2,iothread=io0").unwrap();
+        assert_eq!(config.id, "ahci0");
+        assert_eq!(config.iothread.as_deref(), Some("io0"));
+        assert!(parse_ahci("ahci,bus=pcie.0,addr=0x5").is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=drive0,file=/path/to/disk,direct=off,aio=off")
+            .is_ok());
+        assert!(vm_config
+            .add_drive("id=drive1,file=/path/to/iso,media=cdrom,readonly=on")
+            .is_ok());
+        let config = parse_ide_device(
+            &mut vm_config,
+            "ide-hd,id=disk0,bus=ahci0.2,drive=drive0,serial=deadbeef",
+        )
+        .unwrap();
+        assert_eq!(config.cntlr, "ahci0");
+        assert_eq!(config.port, 2);
+        assert_eq!(config.serial.as_deref(), Some("deadbeef"));
+        assert_eq!(config.drive.path_on_host, "/path/to/disk");
+        // The drive can only be used by one device.
+        assert!(
+            parse_ide_device(&mut vm_config, "ide-hd,id=disk1,bus=ahci0.3,drive=drive0").is_err()
+        );
+
+        // Invalid bus and port.
+        assert!(parse_ide_device(&mut vm_config, "ide-cd,id=cd0,bus=ahci0,drive=drive1").is_err());
+        assert!(
+            parse_ide_device(&mut vm_config, "ide-cd,id=cd0,bus=ahci0.6,drive=drive1").is_err()
+        );
+        assert!(vm_config
+            .add_drive("id=drive1,file=/path/to/iso,media=cdrom,readonly=on")
+            .is_ok());
+        let config =
+            parse_ide_device(&mut vm_config, "ide-cd,id=cd0,bus=ahci0.5,drive=drive1").unwrap();
+        assert_eq!(config.port, 5);
+        assert!(config.drive.read_only);
+        assert!(config.serial.is_none());
+    }
+}
@@ -28,9 +28,11 @@ pub struct BalloonConfig {
     pub id: String,
     pub deflate_on_oom: bool,
     pub free_page_reporting: bool,
+    pub guest_stats: bool,
     pub auto_balloon: bool,
     pub membuf_percent: u32,
     pub monitor_interval: u32,
+    pub page_poison: bool,
 }
 
 impl ConfigCheck for BalloonConfig {
@@ -76,12 +78,16 @@ pub fn parse_balloon(vm_config: &mut VmConfig, balloon_config: &str) -> Result<B
         .push("bus")
         .push("addr")
         .push("multifunction")
+        .push("disable-legacy")
+        .push("disable-modern")
         .push("id")
         .push("deflate-on-oom")
         .push("free-page-reporting")
+        .push("guest-stats")
         .push("auto-balloon")
         .push("membuf-percent")
-        .push("monitor-interval");
+        .push("monitor-interval")
+        .push("page-poison");
     cmd_parser.parse(balloon_config)?;
 
     pci_args_check(&cmd_parser)?;
@@ -97,6 +103,9 @@ pub fn parse_balloon(vm_config: &mut VmConfig, balloon_config: &str) -> Result<B
     if let Some(default) = cmd_parser.get_value::<ExBool>("free-page-reporting")? {
         balloon.free_page_reporting = default.into();
     }
+    if let Some(default) = cmd_parser.get_value::<ExBool>("guest-stats")? {
+        balloon.guest_stats = default.into();
+    }
     if let Some(id) = cmd_parser.get_value::<String>("id")? {
         balloon.id = id;
     }
@@ -109,6 +118,9 @@ pub fn parse_balloon(vm_config: &mut VmConfig, balloon_config: &str) -> Result<B
     if let Some(monitor_interval) = cmd_parser.get_value::<u32>("monitor-interval")? {
         balloon.monitor_interval = monitor_interval;
     }
+    if let Some(default) = cmd_parser.get_value::<ExBool>("page-poison")? {
+        balloon.page_poison = default.into();
+    }
     balloon.check()?;
     vm_config.dev_name.insert("balloon".to_string(), 1);
     Ok(balloon)
@@ -224,4 +236,50 @@ mod tests {
         );
         assert!(bln_cfg_res6.is_err());
     }
+
+    #[test]
+    fn test_guest_stats_balloon_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        let balloon_configs =
+            parse_balloon(&mut vm_config, "virtio-balloon-device,id=balloon0").unwrap();
+        assert!(!balloon_configs.guest_stats);
+
+        let mut vm_config = VmConfig::default();
+        let balloon_configs = parse_balloon(
+            &mut vm_config,
+            "virtio-balloon-pci,guest-stats=true,bus=pcie.0,addr=0x1.0x2,id=balloon0",
+        )
+        .unwrap();
+        assert!(balloon_configs.guest_stats);
+
+        let mut vm_config = VmConfig::default();
+        assert!(parse_balloon(
+            &mut vm_config,
+            "virtio-balloon-device,guest-stats=2,id=balloon0"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_page_poison_balloon_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        let balloon_configs =
+            parse_balloon(&mut vm_config, "virtio-balloon-device,id=balloon0").unwrap();
+        assert!(!balloon_configs.page_poison);
+
+        let mut vm_config = VmConfig::default();
+        let balloon_configs = parse_balloon(
+            &mut vm_config,
+            "virtio-balloon-pci,free-page-reporting=on,page-poison=on,bus=pcie.0,addr=0x1.0x2,id=balloon0",
+        )
+        .unwrap();
+        assert!(balloon_configs.page_poison);
+
+        let mut vm_config = VmConfig::default();
+        assert!(parse_balloon(
+            &mut vm_config,
+            "virtio-balloon-device,page-poison=2,id=balloon0"
+        )
+        .is_err());
+    }
 }
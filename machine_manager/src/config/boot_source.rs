@@ -13,14 +13,14 @@
 use std::fmt;
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
 use crate::config::{check_arg_too_long, ConfigCheck, VmConfig, MAX_PATH_LENGTH};
 
 /// Config struct for boot-source.
-/// Contains `kernel_file`, `kernel_cmdline` and `initrd`.
+/// Contains `kernel_file`, `kernel_cmdline`, `initrd` and `dtb`.
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct BootSource {
     /// Path of the kernel image.
@@ -29,6 +29,8 @@ pub struct BootSource {
     pub kernel_cmdline: KernelParams,
     /// Config of initrd.
     pub initrd: Option<InitrdConfig>,
+    /// Path of the user-supplied dtb, which is merged into the generated one.
+    pub dtb: Option<PathBuf>,
 }
 
 impl BootSource {
@@ -58,6 +60,12 @@ impl ConfigCheck for BootSource {
         if self.initrd.is_some() {
             self.initrd.as_ref().unwrap().check()?;
         }
+        if let Some(dtb) = &self.dtb {
+            check_arg_too_long(dtb.to_str().unwrap(), "dtb")?;
+            if !dtb.is_file() {
+                return Err(anyhow!(ConfigError::UnRegularFile("Input dtb".to_string())));
+            }
+        }
 
         Ok(())
     }
@@ -234,6 +242,15 @@ impl VmConfig {
         self.boot_source.initrd = Some(InitrdConfig::new(initrd));
         Ok(())
     }
+
+    /// Add `-dtb dtb_path` config to `VmConfig`
+    pub fn add_dtb(&mut self, dtb: &str) -> Result<()> {
+        if cfg!(not(target_arch = "aarch64")) {
+            bail!("Argument \'dtb\' is only supported on aarch64");
+        }
+        self.boot_source.dtb = Some(PathBuf::from(dtb));
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -293,6 +310,15 @@ mod tests {
         assert_eq!(initrd_config.initrd_file, PathBuf::from(&initrd_path));
         assert_eq!(initrd_config.initrd_size, 0);
         assert_eq!(initrd_config.initrd_addr, 0);
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert!(vm_config.add_dtb("no_such.dtb").is_ok());
+            assert!(vm_config.boot_source.check().is_err());
+            assert!(vm_config.add_dtb(&initrd_path).is_ok());
+            assert!(vm_config.boot_source.check().is_ok());
+        }
+        #[cfg(target_arch = "x86_64")]
+        assert!(vm_config.add_dtb(&initrd_path).is_err());
         std::fs::remove_file(&kernel_path).unwrap();
         std::fs::remove_file(&initrd_path).unwrap();
     }
@@ -0,0 +1,123 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CmdParser, ConfigCheck, ConfigError, VmConfig, MAX_PATH_LENGTH};
+
+const MIN_CPU_WEIGHT: u64 = 1;
+const MAX_CPU_WEIGHT: u64 = 10000;
+
+/// Config of the cgroup v2 which the VM is placed in.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CgroupConfig {
+    /// Path of the VM cgroup, it's created if not exists.
+    pub path: String,
+    /// Relative share of CPU time of the VM.
+    pub cpu_weight: Option<u64>,
+    /// Host CPUs which the VM is allowed to run on, such as `0-3,6`.
+    pub cpuset: Option<String>,
+}
+
+impl ConfigCheck for CgroupConfig {
+    fn check(&self) -> Result<()> {
+        if self.path.len() > MAX_PATH_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "cgroup path".to_string(),
+                MAX_PATH_LENGTH,
+            )));
+        }
+
+        if let Some(weight) = self.cpu_weight {
+            if !(MIN_CPU_WEIGHT..=MAX_CPU_WEIGHT).contains(&weight) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "cgroup cpu-weight".to_string(),
+                    MIN_CPU_WEIGHT,
+                    true,
+                    MAX_CPU_WEIGHT,
+                    true,
+                )));
+            }
+        }
+
+        if let Some(cpuset) = &self.cpuset {
+            for range in cpuset.split(',') {
+                let mut cpus = range.splitn(2, '-');
+                let first = cpus.next().unwrap_or_default().parse::<u32>();
+                let last = cpus.next().map_or(first.clone(), |s| s.parse::<u32>());
+                match (first, last) {
+                    (Ok(first), Ok(last)) if first <= last => {}
+                    _ => bail!("Invalid cgroup cpuset {}", cpuset),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Set the cgroup config, e.g. `path=/sys/fs/cgroup/vm1,cpu-weight=200,cpuset=0-3:6`.
+    /// The CPU ranges of cpuset are separated by ':' in cmdline.
+    pub fn add_cgroup(&mut self, cgroup_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("cgroup");
+        cmd_parser.push("path").push("cpu-weight").push("cpuset");
+        cmd_parser.parse(cgroup_config)?;
+
+        let cgroup = CgroupConfig {
+            path: cmd_parser.get_value::<String>("path")?.with_context(|| {
+                ConfigError::FieldIsMissing("path".to_string(), "cgroup".to_string())
+            })?,
+            cpu_weight: cmd_parser.get_value::<u64>("cpu-weight")?,
+            cpuset: cmd_parser
+                .get_value::<String>("cpuset")?
+                .map(|cpuset| cpuset.replace(':', ",")),
+        };
+        cgroup.check()?;
+
+        self.cgroup = Some(cgroup);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_cgroup() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.cgroup.is_none());
+
+        assert!(vm_config
+            .add_cgroup("path=/sys/fs/cgroup/vm1,cpu-weight=200,cpuset=0-3:6")
+            .is_ok());
+        let cgroup = vm_config.cgroup.as_ref().unwrap();
+        assert_eq!(cgroup.path, "/sys/fs/cgroup/vm1");
+        assert_eq!(cgroup.cpu_weight, Some(200));
+        assert_eq!(cgroup.cpuset.as_deref(), Some("0-3,6"));
+
+        assert!(vm_config.add_cgroup("cpu-weight=200").is_err());
+        assert!(vm_config
+            .add_cgroup("path=/sys/fs/cgroup/vm1,cpu-weight=0")
+            .is_err());
+        assert!(vm_config
+            .add_cgroup("path=/sys/fs/cgroup/vm1,cpu-weight=10001")
+            .is_err());
+        assert!(vm_config
+            .add_cgroup("path=/sys/fs/cgroup/vm1,cpuset=3-1")
+            .is_err());
+        assert!(vm_config
+            .add_cgroup("path=/sys/fs/cgroup/vm1,cpuset=a")
+            .is_err());
+    }
+}
@@ -37,6 +37,8 @@ pub enum ChardevType {
         nowait: bool,
     },
     File(String),
+    /// Talk with the spice vdagent in the guest to share the clipboard.
+    Vdagent,
 }
 
 /// Config structure for virtio-serial-port.
@@ -59,6 +61,9 @@ impl ConfigCheck for VirtioSerialPort {
 pub struct ChardevConfig {
     pub id: String,
     pub backend: ChardevType,
+    /// Multiplex the backend between the device and the human monitor.
+    #[serde(default)]
+    pub mux: bool,
 }
 
 impl ConfigCheck for ChardevConfig {
@@ -76,6 +81,12 @@ impl ConfigCheck for ChardevConfig {
                 MAX_PATH_LENGTH
             )));
         }
+        if self.mux && matches!(self.backend, ChardevType::File(_)) {
+            bail!("Chardev of file-type does not support 'mux' argument");
+        }
+        if self.mux && self.backend == ChardevType::Vdagent {
+            bail!("Chardev of vdagent-type does not support 'mux' argument");
+        }
 
         Ok(())
     }
@@ -87,7 +98,7 @@ fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
         let server = cmd_parser.get_value::<String>("server")?;
         let nowait = cmd_parser.get_value::<String>("nowait")?;
         match chardev_str {
-            "stdio" | "pty" | "file" => {
+            "stdio" | "pty" | "file" | "vdagent" => {
                 if server.is_some() {
                     bail!(
                         "Chardev of {}-type does not support \'server\' argument",
@@ -141,6 +152,10 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     } else {
         false
     };
+    let mux = match cmd_parser.get_value::<ExBool>("mux")? {
+        Some(mux) => mux.into(),
+        None => false,
+    };
     check_chardev_args(cmd_parser)?;
     let chardev_type = if let Some(backend) = backend {
         match backend.as_str() {
@@ -170,6 +185,7 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
                     )));
                 }
             }
+            "vdagent" => ChardevType::Vdagent,
             _ => {
                 return Err(anyhow!(ConfigError::InvalidParam(
                     backend,
@@ -187,6 +203,7 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     Ok(ChardevConfig {
         id: chardev_id,
         backend: chardev_type,
+        mux,
     })
 }
 
@@ -229,9 +246,57 @@ pub fn get_chardev_config(args: qmp_schema::CharDevAddArgument) -> Result<Charde
             server: data.server,
             nowait: false,
         },
+        mux: false,
     })
 }
 
+/// Get the new chardev config of `chardev-change` from qmp arguments.
+///
+/// # Arguments
+///
+/// * `args` - The qmp arguments.
+pub fn get_chardev_change_config(args: qmp_schema::CharDevChangeArgument) -> Result<ChardevConfig> {
+    let data = args.backend.backend_data;
+    let backend = match args.backend.backend_type.as_str() {
+        "stdio" => ChardevType::Stdio,
+        "pty" => ChardevType::Pty,
+        "file" => ChardevType::File(data.out.with_context(|| {
+            ConfigError::FieldIsMissing("out".to_string(), "chardev".to_string())
+        })?),
+        "socket" => {
+            // The device can only work with the listening socket now.
+            if !data.server {
+                bail!("Only chardev socket as server is supported by chardev-change");
+            }
+            if data.addr.addr_type.as_str() != "unix" {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "backend".to_string(),
+                    "addr".to_string()
+                )));
+            }
+            ChardevType::Socket {
+                path: data.addr.addr_data.path,
+                server: true,
+                nowait: true,
+            }
+        }
+        backend_type => {
+            return Err(anyhow!(ConfigError::InvalidParam(
+                "backend".to_string(),
+                backend_type.to_string()
+            )));
+        }
+    };
+
+    let chardev_cfg = ChardevConfig {
+        id: args.id,
+        backend,
+        mux: false,
+    };
+    chardev_cfg.check()?;
+    Ok(chardev_cfg)
+}
+
 /// Get chardev socket path from ChardevConfig struct.
 ///
 /// # Arguments
@@ -308,7 +373,8 @@ impl VmConfig {
             .push("id")
             .push("path")
             .push("server")
-            .push("nowait");
+            .push("nowait")
+            .push("mux");
 
         cmd_parser.parse(chardev_config)?;
 
@@ -484,6 +550,8 @@ pub fn parse_virtio_serial(
         .push("bus")
         .push("addr")
         .push("multifunction")
+        .push("disable-legacy")
+        .push("disable-modern")
         .push("max_ports");
     cmd_parser.parse(serial_config)?;
     pci_args_check(&cmd_parser)?;
@@ -660,5 +728,71 @@ mod tests {
         } else {
             assert!(false);
         }
+
+        assert!(vm_config.add_chardev("stdio,id=mux_id,mux=on").is_ok());
+        assert!(vm_config.chardev.get("mux_id").unwrap().mux);
+        assert!(vm_config
+            .add_chardev("file,id=file_id,path=/path/to/file,mux=on")
+            .is_err());
+
+        assert!(vm_config.add_chardev("vdagent,id=vdagent_id").is_ok());
+        assert_eq!(
+            vm_config.chardev.get("vdagent_id").unwrap().backend,
+            ChardevType::Vdagent
+        );
+        assert!(vm_config
+            .add_chardev("vdagent,id=vdagent_mux,mux=on")
+            .is_err());
+    }
+
+    #[test]
+    fn test_chardev_change_config() {
+        let args: qmp_schema::CharDevChangeArgument = serde_json::from_str(
+            r#"{"id": "charconsole0", "backend": {"type": "socket", "data": {
+                "addr": {"type": "unix", "data": {"path": "/path/to/socket"}}, "server": true}}}"#,
+        )
+        .unwrap();
+        let chardev_cfg = get_chardev_change_config(args).unwrap();
+        assert_eq!(chardev_cfg.id, "charconsole0");
+        assert_eq!(
+            chardev_cfg.backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: true,
+                nowait: true,
+            }
+        );
+
+        let args: qmp_schema::CharDevChangeArgument = serde_json::from_str(
+            r#"{"id": "charconsole0", "backend": {"type": "file", "data": {"out": "/path/to/file"}}}"#,
+        )
+        .unwrap();
+        let chardev_cfg = get_chardev_change_config(args).unwrap();
+        assert_eq!(
+            chardev_cfg.backend,
+            ChardevType::File("/path/to/file".to_string())
+        );
+
+        let args: qmp_schema::CharDevChangeArgument = serde_json::from_str(
+            r#"{"id": "charconsole0", "backend": {"type": "pty", "data": {}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            get_chardev_change_config(args).unwrap().backend,
+            ChardevType::Pty
+        );
+
+        // Socket client and file without output path are not supported.
+        let args: qmp_schema::CharDevChangeArgument = serde_json::from_str(
+            r#"{"id": "charconsole0", "backend": {"type": "socket", "data": {
+                "addr": {"type": "unix", "data": {"path": "/path/to/socket"}}, "server": false}}}"#,
+        )
+        .unwrap();
+        assert!(get_chardev_change_config(args).is_err());
+        let args: qmp_schema::CharDevChangeArgument = serde_json::from_str(
+            r#"{"id": "charconsole0", "backend": {"type": "file", "data": {}}}"#,
+        )
+        .unwrap();
+        assert!(get_chardev_change_config(args).is_err());
     }
 }
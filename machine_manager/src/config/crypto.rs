@@ -0,0 +1,140 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::pci_args_check;
+use crate::config::{
+    check_arg_too_long, CmdParser, ConfigCheck, ConfigError, VmConfig, MAX_VIRTIO_QUEUE,
+};
+
+/// Config of cryptodev backend object, only the builtin software engine is supported.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CryptoDevObjConfig {
+    pub id: String,
+    /// Number of data queues.
+    pub queues: u16,
+}
+
+/// Config structure for virtio-crypto.
+#[derive(Debug, Clone, Default)]
+pub struct CryptoConfig {
+    pub id: String,
+    /// Number of data queues, the control queue is not included.
+    pub queues: u16,
+}
+
+impl ConfigCheck for CryptoConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "crypto id")?;
+        // One more queue is used as the control queue.
+        if self.queues < 1 || self.queues as usize >= MAX_VIRTIO_QUEUE {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "queues of cryptodev".to_string(),
+                1,
+                true,
+                MAX_VIRTIO_QUEUE as u64,
+                false,
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    pub fn add_cryptodev(&mut self, cryptodev_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("cryptodev");
+        cmd_parser.push("").push("id").push("queues");
+        cmd_parser.parse(cryptodev_config)?;
+
+        let id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), "cryptodev".to_string())
+        })?;
+        check_arg_too_long(&id, "cryptodev id")?;
+        let queues = cmd_parser.get_value::<u16>("queues")?.unwrap_or(1);
+        if self.object.cryptodev_object.contains_key(&id) {
+            return Err(anyhow!(ConfigError::IdRepeat("cryptodev".to_string(), id)));
+        }
+        self.object
+            .cryptodev_object
+            .insert(id.clone(), CryptoDevObjConfig { id, queues });
+        Ok(())
+    }
+}
+
+pub fn parse_crypto_dev(vm_config: &mut VmConfig, crypto_config: &str) -> Result<CryptoConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-crypto");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("cryptodev");
+    cmd_parser.parse(crypto_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let cryptodev = cmd_parser
+        .get_value::<String>("cryptodev")?
+        .with_context(|| {
+            ConfigError::FieldIsMissing("cryptodev".to_string(), "virtio-crypto".to_string())
+        })?;
+    let queues = vm_config
+        .object
+        .cryptodev_object
+        .remove(&cryptodev)
+        .map(|obj| obj.queues)
+        .with_context(|| format!("Object for cryptodev {} not found", cryptodev))?;
+    let crypto_cfg = CryptoConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+        queues,
+    };
+    crypto_cfg.check()?;
+    Ok(crypto_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crypto_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("cryptodev-backend-builtin,id=cryptodev0,queues=2")
+            .is_ok());
+        assert!(vm_config
+            .add_object("cryptodev-backend-builtin,id=cryptodev0")
+            .is_err());
+        let config = parse_crypto_dev(
+            &mut vm_config,
+            "virtio-crypto-pci,id=crypto0,cryptodev=cryptodev0,bus=pcie.0,addr=0x5",
+        )
+        .unwrap();
+        assert_eq!(config.id, "crypto0");
+        assert_eq!(config.queues, 2);
+        // The backend can only be used by one device.
+        assert!(
+            parse_crypto_dev(&mut vm_config, "virtio-crypto-device,cryptodev=cryptodev0").is_err()
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("cryptodev-backend-builtin,id=cryptodev0,queues=32")
+            .is_ok());
+        assert!(
+            parse_crypto_dev(&mut vm_config, "virtio-crypto-device,cryptodev=cryptodev0").is_err()
+        );
+        assert!(parse_crypto_dev(&mut vm_config, "virtio-crypto-device").is_err());
+    }
+}
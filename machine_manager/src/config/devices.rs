@@ -12,8 +12,10 @@
 
 use anyhow::Result;
 use regex::Regex;
+use serde_json::Value;
 
 use super::{CmdParser, VmConfig};
+use crate::qmp::qmp_schema;
 
 impl VmConfig {
     pub fn add_device(&mut self, device_config: &str) -> Result<()> {
@@ -28,6 +30,12 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Record the device added by QMP, so that it's kept in the dumped config.
+    pub fn add_device_by_qmp(&mut self, args: &qmp_schema::DeviceAddArgument) {
+        self.devices
+            .push((args.driver.clone(), device_add_args_to_cmdline(args)));
+    }
+
     pub fn del_device_by_id(&mut self, dev_id: String) {
         let rex = format!("id={}(,|$)", dev_id);
         let re = Regex::new(rex.as_str()).unwrap();
@@ -41,6 +49,28 @@ impl VmConfig {
     }
 }
 
+/// Convert the arguments of `device_add` to `-device` cmdline.
+fn device_add_args_to_cmdline(args: &qmp_schema::DeviceAddArgument) -> String {
+    let mut cmdline = args.driver.clone();
+    if let Ok(Value::Object(map)) = serde_json::to_value(args) {
+        for (key, value) in map.iter() {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => if *b { "on" } else { "off" }.to_string(),
+                _ => continue,
+            };
+            let key = match key.as_str() {
+                "driver" => continue,
+                "boot_index" => "bootindex",
+                key => key,
+            };
+            cmdline += &format!(",{}={}", key, value);
+        }
+    }
+    cmdline
+}
+
 pub fn parse_device_id(device_config: &str) -> Result<String> {
     let mut cmd_parser = CmdParser::new("device");
     cmd_parser.push("id");
@@ -71,4 +101,25 @@ mod tests {
         let id = ret.unwrap();
         assert_eq!("", id);
     }
+
+    #[test]
+    fn test_add_device_by_qmp() {
+        let args: qmp_schema::DeviceAddArgument = serde_json::from_str(
+            r#"{"id": "net-0", "driver": "virtio-net-pci", "netdev": "netdev-0",
+            "bus": "pcie.0", "addr": "0x2", "multifunction": true, "boot_index": 1}"#,
+        )
+        .unwrap();
+        let mut vm_config = VmConfig::default();
+        vm_config.add_device_by_qmp(&args);
+        assert_eq!(vm_config.devices.len(), 1);
+        assert_eq!(vm_config.devices[0].0, "virtio-net-pci");
+        assert_eq!(
+            vm_config.devices[0].1,
+            "virtio-net-pci,addr=0x2,bootindex=1,bus=pcie.0,id=net-0,multifunction=on,netdev=netdev-0"
+        );
+        assert_eq!(parse_device_id(&vm_config.devices[0].1).unwrap(), "net-0");
+
+        vm_config.del_device_by_id("net-0".to_string());
+        assert!(vm_config.devices.is_empty());
+    }
 }
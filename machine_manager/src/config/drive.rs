@@ -25,7 +25,8 @@ use crate::config::{
     ExBool, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::qmp_schema;
-use util::aio::{aio_probe, AioEngine, WriteZeroesState};
+use util::aio::{aio_probe, aio_select, AioEngine, WriteZeroesState};
+use util::file::BlockSize;
 
 const MAX_SERIAL_NUM: usize = 20;
 const MAX_IOPS: u64 = 1_000_000;
@@ -35,6 +36,8 @@ const MAX_UNIT_ID: usize = 2;
 const MIN_QUEUE_SIZE_BLK: u16 = 2;
 // Max size of each virtqueue for virtio-blk.
 const MAX_QUEUE_SIZE_BLK: u16 = 1024;
+// Max number of iothreads sharing the requests of a single virtqueue.
+const MAX_SHARD_IOTHREADS: usize = 8;
 
 /// Represent a single drive backend file.
 pub struct DriveFile {
@@ -54,6 +57,8 @@ pub struct DriveFile {
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
     pub buf_align: u32,
+    /// The logical and physical block size reported to guest.
+    pub block_size: BlockSize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -77,6 +82,13 @@ pub struct BlkDevConfig {
     pub format: DiskFormat,
     pub l2_cache_size: Option<u64>,
     pub refcount_cache_size: Option<u64>,
+    pub shard_iothreads: Vec<String>,
+    /// Secret data used to unlock the luks image.
+    pub key_secret: Option<String>,
+    pub copy_on_read: bool,
+    pub io_timeout: Option<IoTimeout>,
+    /// Media of the drive, "cdrom" is a read-only removable medium which may be empty.
+    pub media: String,
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +119,11 @@ impl Default for BlkDevConfig {
             format: DiskFormat::Raw,
             l2_cache_size: None,
             refcount_cache_size: None,
+            shard_iothreads: Vec::new(),
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
+            media: "disk".to_string(),
         }
     }
 }
@@ -115,6 +132,7 @@ impl Default for BlkDevConfig {
 pub enum DiskFormat {
     Raw,
     Qcow2,
+    Luks,
 }
 
 impl FromStr for DiskFormat {
@@ -124,6 +142,7 @@ impl FromStr for DiskFormat {
         match s {
             "raw" => Ok(DiskFormat::Raw),
             "qcow2" => Ok(DiskFormat::Qcow2),
+            "luks" => Ok(DiskFormat::Luks),
             _ => Err(anyhow!("Unknown format type")),
         }
     }
@@ -134,10 +153,38 @@ impl ToString for DiskFormat {
         match *self {
             DiskFormat::Raw => "raw".to_string(),
             DiskFormat::Qcow2 => "qcow2".to_string(),
+            DiskFormat::Luks => "luks".to_string(),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum IoTimeoutAction {
+    /// Only report the expired requests by QMP event.
+    Report,
+    /// Report and fail the expired requests, so that the guest sees an IO error.
+    Fail,
+}
+
+impl FromStr for IoTimeoutAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "report" => Ok(IoTimeoutAction::Report),
+            "fail" => Ok(IoTimeoutAction::Fail),
+            _ => Err(anyhow!("Unknown io timeout action")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IoTimeout {
+    /// Timeout in seconds.
+    pub timeout: u64,
+    pub action: IoTimeoutAction,
+}
+
 /// Config struct for `drive`.
 /// Contains block device's attr.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -155,6 +202,12 @@ pub struct DriveConfig {
     pub format: DiskFormat,
     pub l2_cache_size: Option<u64>,
     pub refcount_cache_size: Option<u64>,
+    /// Id of the secret object used to unlock the luks image.
+    pub key_secret: Option<String>,
+    /// Populate the clusters read from backing file into the qcow2 image.
+    pub copy_on_read: bool,
+    /// Timeout of the IO requests, and the action when they expire.
+    pub io_timeout: Option<IoTimeout>,
 }
 
 impl Default for DriveConfig {
@@ -172,6 +225,9 @@ impl Default for DriveConfig {
             format: DiskFormat::Raw,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         }
     }
 }
@@ -250,6 +306,24 @@ impl ConfigCheck for DriveConfig {
             )));
         }
 
+        if self.format == DiskFormat::Luks && self.key_secret.is_none() {
+            return Err(anyhow!(ConfigError::FieldIsMissing(
+                "key-secret".to_string(),
+                "luks drive".to_string(),
+            )));
+        }
+        if let Some(io_timeout) = self.io_timeout.as_ref() {
+            if io_timeout.timeout == 0 || self.aio == AioEngine::Off {
+                bail!("Drive parameter io-timeout should be positive and used with async aio");
+            }
+        }
+        if self.copy_on_read && (self.format != DiskFormat::Qcow2 || self.read_only) {
+            bail!("Drive parameter copy-on-read is only supported by writable qcow2 format");
+        }
+        if self.format != DiskFormat::Luks && self.key_secret.is_some() {
+            bail!("Drive parameter key-secret is only supported by luks format");
+        }
+
         if !["disk", "cdrom"].contains(&self.media.as_str()) {
             return Err(anyhow!(ConfigError::InvalidParam(
                 "media".to_string(),
@@ -288,6 +362,32 @@ impl ConfigCheck for BlkDevConfig {
             )));
         }
 
+        if self.shard_iothreads.len() > MAX_SHARD_IOTHREADS {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "number of shard iothreads of block device".to_string(),
+                0,
+                true,
+                MAX_SHARD_IOTHREADS as u64,
+                true,
+            )));
+        }
+        for iothread in self.shard_iothreads.iter() {
+            if iothread.len() > MAX_STRING_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "shard iothread name".to_string(),
+                    MAX_STRING_LENGTH,
+                )));
+            }
+        }
+        if !self.shard_iothreads.is_empty() {
+            if self.queues != 1 {
+                bail!("Sharding requests across iothreads is only supported with one queue");
+            }
+            if self.format != DiskFormat::Raw {
+                bail!("Sharding requests across iothreads is only supported with raw format");
+            }
+        }
+
         if self.queue_size <= MIN_QUEUE_SIZE_BLK || self.queue_size > MAX_QUEUE_SIZE_BLK {
             return Err(anyhow!(ConfigError::IllegalValue(
                 "queue size of block device".to_string(),
@@ -311,7 +411,7 @@ impl ConfigCheck for BlkDevConfig {
         };
         fake_drive.check()?;
         #[cfg(not(test))]
-        if self.chardev.is_none() {
+        if self.chardev.is_none() && !self.path_on_host.is_empty() {
             fake_drive.check_path()?;
         }
 
@@ -328,27 +428,36 @@ fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
     drive.id = cmd_parser
         .get_value::<String>("id")?
         .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "blk".to_string()))?;
-    drive.path_on_host = cmd_parser
-        .get_value::<String>("file")?
-        .with_context(|| ConfigError::FieldIsMissing("file".to_string(), "blk".to_string()))?;
+    drive.media = cmd_parser
+        .get_value::<String>("media")?
+        .unwrap_or_else(|| "disk".to_string());
+    // The cdrom drive may be empty at boot, and its medium is inserted later.
+    drive.path_on_host = match cmd_parser.get_value::<String>("file")? {
+        Some(file) => file,
+        None if drive.media == "cdrom" => "".to_string(),
+        None => bail!(ConfigError::FieldIsMissing(
+            "file".to_string(),
+            "blk".to_string()
+        )),
+    };
 
     if let Some(read_only) = cmd_parser.get_value::<ExBool>("readonly")? {
         drive.read_only = read_only.into();
     }
+    if drive.media == "cdrom" {
+        if cmd_parser.get_value::<ExBool>("readonly")?.is_some() && !drive.read_only {
+            bail!("Drive of cdrom media must be read-only");
+        }
+        drive.read_only = true;
+    }
     if let Some(direct) = cmd_parser.get_value::<ExBool>("direct")? {
         drive.direct = direct.into();
     }
     drive.iops = cmd_parser.get_value::<u64>("throttling.iops-total")?;
-    drive.aio = cmd_parser.get_value::<AioEngine>("aio")?.unwrap_or({
-        if drive.direct {
-            AioEngine::Native
-        } else {
-            AioEngine::Off
-        }
-    });
-    drive.media = cmd_parser
-        .get_value::<String>("media")?
-        .unwrap_or_else(|| "disk".to_string());
+    drive.aio = match cmd_parser.get_value::<AioEngine>("aio")? {
+        Some(aio) => aio,
+        None => aio_select(&drive.path_on_host, drive.direct),
+    };
     if let Some(discard) = cmd_parser.get_value::<ExBool>("discard")? {
         drive.discard = discard.into();
     }
@@ -366,10 +475,27 @@ fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
             .with_context(|| format!("Invalid refcount cache size: {}", rc_cache))?;
         drive.refcount_cache_size = Some(sz);
     }
+    drive.key_secret = cmd_parser.get_value::<String>("key-secret")?;
+    if let Some(copy_on_read) = cmd_parser.get_value::<ExBool>("copy-on-read")? {
+        drive.copy_on_read = copy_on_read.into();
+    }
+    if let Some(timeout) = cmd_parser.get_value::<u64>("io-timeout")? {
+        let action = cmd_parser
+            .get_value::<IoTimeoutAction>("io-timeout-action")?
+            .unwrap_or(IoTimeoutAction::Report);
+        drive.io_timeout = Some(IoTimeout { timeout, action });
+    } else if cmd_parser
+        .get_value::<String>("io-timeout-action")?
+        .is_some()
+    {
+        bail!("Drive parameter io-timeout-action requires io-timeout");
+    }
 
     drive.check()?;
     #[cfg(not(test))]
-    drive.check_path()?;
+    if !drive.path_on_host.is_empty() {
+        drive.check_path()?;
+    }
     Ok(drive)
 }
 
@@ -385,12 +511,15 @@ pub fn parse_blk(
         .push("bus")
         .push("addr")
         .push("multifunction")
+        .push("disable-legacy")
+        .push("disable-modern")
         .push("drive")
         .push("bootindex")
         .push("serial")
         .push("iothread")
         .push("num-queues")
-        .push("queue-size");
+        .push("queue-size")
+        .push("shard-iothreads");
 
     cmd_parser.parse(drive_config)?;
 
@@ -417,10 +546,20 @@ pub fn parse_blk(
         .get_value::<String>("id")?
         .with_context(|| "No id configured for blk device")?;
 
+    if let Some(shard_iothreads) = cmd_parser.get_value::<String>("shard-iothreads")? {
+        blkdevcfg.shard_iothreads = shard_iothreads
+            .split(':')
+            .map(|iothread| iothread.to_string())
+            .collect();
+    }
+
     if let Some(queues) = cmd_parser.get_value::<u16>("num-queues")? {
         blkdevcfg.queues = queues;
     } else if let Some(queues) = queues_auto {
-        blkdevcfg.queues = queues;
+        // Requests of the single queue are sharded across iothreads instead.
+        if blkdevcfg.shard_iothreads.is_empty() {
+            blkdevcfg.queues = queues;
+        }
     }
 
     if let Some(queue_size) = cmd_parser.get_value::<u16>("queue-size")? {
@@ -441,6 +580,12 @@ pub fn parse_blk(
     blkdevcfg.format = drive_arg.format;
     blkdevcfg.l2_cache_size = drive_arg.l2_cache_size;
     blkdevcfg.refcount_cache_size = drive_arg.refcount_cache_size;
+    blkdevcfg.copy_on_read = drive_arg.copy_on_read;
+    blkdevcfg.io_timeout = drive_arg.io_timeout;
+    blkdevcfg.media = drive_arg.media.clone();
+    if let Some(secret) = drive_arg.key_secret.as_ref() {
+        blkdevcfg.key_secret = Some(vm_config.get_secret(secret)?);
+    }
     blkdevcfg.check()?;
     Ok(blkdevcfg)
 }
@@ -507,6 +652,8 @@ pub struct PFlashConfig {
     pub path_on_host: String,
     pub read_only: bool,
     pub unit: usize,
+    /// Write-protect the PFlash from guest at startup, it can be unsealed by QMP.
+    pub sealed: bool,
 }
 
 impl ConfigCheck for PFlashConfig {
@@ -571,7 +718,11 @@ impl VmConfig {
             .push("detect-zeroes")
             .push("format")
             .push("l2-cache-size")
-            .push("refcount-cache-size");
+            .push("refcount-cache-size")
+            .push("key-secret")
+            .push("copy-on-read")
+            .push("io-timeout")
+            .push("io-timeout-action");
 
         cmd_parser.parse(block_config)?;
         let drive_cfg = parse_drive(cmd_parser)?;
@@ -680,7 +831,8 @@ impl VmConfig {
             .push("file")
             .push("format")
             .push("readonly")
-            .push("unit");
+            .push("unit")
+            .push("sealed");
 
         cmd_parser.parse(pflash_config)?;
 
@@ -699,6 +851,10 @@ impl VmConfig {
             pflash.read_only = read_only.into();
         }
 
+        if let Some(sealed) = cmd_parser.get_value::<ExBool>("sealed")? {
+            pflash.sealed = sealed.into();
+        }
+
         pflash.unit = cmd_parser.get_value::<u64>("unit")?.with_context(|| {
             ConfigError::FieldIsMissing("unit".to_string(), "pflash".to_string())
         })? as usize;
@@ -783,6 +939,39 @@ mod tests {
         assert!(parse_blk(&mut vm_config, blk_cfg, None).is_ok());
     }
 
+    #[test]
+    fn test_blk_shard_iothreads_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1.0x2,drive=rootfs,iothread=iothread0,shard-iothreads=iothread1:iothread2";
+        let blk_cfg_res = parse_blk(&mut vm_config, blk_cfg, Some(4));
+        assert!(blk_cfg_res.is_ok());
+        let blk_device_config = blk_cfg_res.unwrap();
+        assert_eq!(blk_device_config.queues, 1);
+        assert_eq!(
+            blk_device_config.shard_iothreads,
+            vec!["iothread1".to_string(), "iothread2".to_string()]
+        );
+
+        // Sharding does not work together with multiple queues.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1.0x2,drive=rootfs,num-queues=2,shard-iothreads=iothread1";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+
+        // Sharding is not supported by qcow2 format.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=qcow2")
+            .is_ok());
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1.0x2,drive=rootfs,shard-iothreads=iothread1";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+    }
+
     #[test]
     fn test_pflash_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
@@ -817,7 +1006,7 @@ mod tests {
             .add_drive("if=pflash,readonly=on,file=flash0.fd,unit=0")
             .is_ok());
         assert!(vm_config
-            .add_drive("if=pflash,file=flash1.fd,unit=1")
+            .add_drive("if=pflash,file=flash1.fd,unit=1,sealed=on")
             .is_ok());
         assert!(vm_config.pflashs.is_some());
         let pflash = vm_config.pflashs.unwrap();
@@ -830,6 +1019,7 @@ mod tests {
         assert_eq!(pflash_cfg.unit, 1);
         assert_eq!(pflash_cfg.path_on_host, "flash1.fd".to_string());
         assert_eq!(pflash_cfg.read_only, false);
+        assert!(pflash_cfg.sealed);
     }
 
     #[test]
@@ -955,4 +1145,98 @@ mod tests {
             .is_err();
         assert_eq!(ret, true);
     }
+    #[test]
+    fn test_drive_config_copy_on_read() {
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,format=qcow2,copy-on-read=on")
+            .unwrap();
+        assert!(drive_conf.copy_on_read);
+
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,format=qcow2")
+            .unwrap();
+        assert!(!drive_conf.copy_on_read);
+
+        // Only writable qcow2 supports copy-on-read.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,copy-on-read=on")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_block_drive(
+                "id=rootfs,file=/path/to/rootfs,format=qcow2,readonly=on,copy-on-read=on"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_drive_config_io_timeout() {
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,io-timeout=30")
+            .unwrap();
+        let io_timeout = drive_conf.io_timeout.unwrap();
+        assert_eq!(io_timeout.timeout, 30);
+        assert_eq!(io_timeout.action, IoTimeoutAction::Report);
+
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,io-timeout=10,io-timeout-action=fail")
+            .unwrap();
+        assert_eq!(drive_conf.io_timeout.unwrap().action, IoTimeoutAction::Fail);
+
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs")
+            .unwrap();
+        assert!(drive_conf.io_timeout.is_none());
+
+        // Invalid io timeout configurations.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,io-timeout=0")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,io-timeout-action=fail")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,io-timeout=10,io-timeout-action=abort")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_block_drive("id=rootfs,file=/path/to/rootfs,aio=off,io-timeout=10")
+            .is_err());
+    }
+
+    #[test]
+    fn test_drive_config_cdrom() {
+        // The cdrom drive is read-only, and may be empty.
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config
+            .add_block_drive("id=cd0,file=/path/to/cd.iso,media=cdrom")
+            .unwrap();
+        assert!(drive_conf.read_only);
+
+        let mut vm_config = VmConfig::default();
+        let drive_conf = vm_config.add_block_drive("id=cd1,media=cdrom").unwrap();
+        assert!(drive_conf.read_only);
+        assert!(drive_conf.path_on_host.is_empty());
+        assert!(vm_config.init_drive_files().unwrap().is_empty());
+
+        let blk_cfg = parse_blk(&mut vm_config, "virtio-blk-pci,id=blk1,drive=cd1", None).unwrap();
+        assert_eq!(blk_cfg.media, "cdrom");
+        assert!(blk_cfg.read_only);
+        assert!(blk_cfg.path_on_host.is_empty());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_block_drive("id=rootfs").is_err());
+        assert!(vm_config
+            .add_block_drive("id=cd0,file=/path/to/cd.iso,media=cdrom,readonly=off")
+            .is_err());
+    }
 }
@@ -0,0 +1,58 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::Result;
+
+use super::pci_args_check;
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck};
+
+/// Config structure for virtio-iommu.
+#[derive(Debug, Clone, Default)]
+pub struct IommuConfig {
+    pub id: String,
+}
+
+impl ConfigCheck for IommuConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "iommu id")
+    }
+}
+
+pub fn parse_iommu(iommu_config: &str) -> Result<IommuConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-iommu");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction");
+    cmd_parser.parse(iommu_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let iommu_cfg = IommuConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+    };
+    iommu_cfg.check()?;
+    Ok(iommu_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iommu_config_cmdline_parser() {
+        let config = parse_iommu("virtio-iommu-pci,id=iommu0,bus=pcie.0,addr=0x5").unwrap();
+        assert_eq!(config.id, "iommu0");
+        assert!(parse_iommu("virtio-iommu-pci,id=iommu0,bus=pcie.0,addr=0x5,bypass=on").is_err());
+    }
+}
@@ -10,7 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
@@ -41,6 +41,11 @@ impl VmConfig {
         if let Some(id) = cmd_parser.get_value::<String>("id")? {
             iothread.id = id;
         }
+        self.add_iothread_with_config(iothread)
+    }
+
+    /// Add new iothread with the config structure, used by both cmdline and qmp.
+    pub fn add_iothread_with_config(&mut self, iothread: IothreadConfig) -> Result<()> {
         iothread.check()?;
 
         if self.iothreads.is_some() {
@@ -70,6 +75,33 @@ impl VmConfig {
 
         Ok(())
     }
+
+    /// Delete the iothread from `VmConfig`.
+    pub fn del_iothread(&mut self, id: &str) -> Result<()> {
+        if let Some(iothreads) = self.iothreads.as_mut() {
+            if let Some(index) = iothreads.iter().position(|t| t.id == id) {
+                iothreads.remove(index);
+                return Ok(());
+            }
+        }
+        bail!("Iothread {} not found", id);
+    }
+
+    /// Get the number of devices which use the iothread.
+    pub fn iothread_attached_devices(&self, id: &str) -> u32 {
+        let mut count = 0;
+        for (_, dev_cfg) in self.devices.iter() {
+            let attached = dev_cfg.split(',').any(|param| match param.split_once('=') {
+                Some(("iothread", value)) => value == id,
+                Some(("shard-iothreads", value)) => value.split(':').any(|v| v == id),
+                _ => false,
+            });
+            if attached {
+                count += 1;
+            }
+        }
+        count
+    }
 }
 
 #[cfg(test)]
@@ -105,4 +137,29 @@ mod tests {
         assert!(vm_config.add_object("iothread,id=iothread0").is_ok());
         assert!(vm_config.add_object("iothread,id=iothread0").is_err());
     }
+
+    #[test]
+    fn test_iothread_del_and_attached_devices() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_object("iothread,id=iothread0").is_ok());
+        assert!(vm_config.add_object("iothread,id=iothread1").is_ok());
+        vm_config.devices.push((
+            "virtio-blk-pci".to_string(),
+            "virtio-blk-pci,id=blk0,drive=rootfs,iothread=iothread0".to_string(),
+        ));
+        vm_config.devices.push((
+            "virtio-blk-pci".to_string(),
+            "virtio-blk-pci,id=blk1,drive=data,shard-iothreads=iothread10:iothread0".to_string(),
+        ));
+        assert_eq!(vm_config.iothread_attached_devices("iothread0"), 2);
+        assert_eq!(vm_config.iothread_attached_devices("iothread1"), 0);
+
+        assert!(vm_config.del_iothread("iothread1").is_ok());
+        assert!(vm_config.del_iothread("iothread1").is_err());
+        assert_eq!(vm_config.iothreads.as_ref().unwrap().len(), 1);
+        let iothread = IothreadConfig {
+            id: "iothread1".to_string(),
+        };
+        assert!(vm_config.add_iothread_with_config(iothread).is_ok());
+    }
 }
@@ -0,0 +1,192 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::pci_args_check;
+use crate::config::{
+    check_arg_too_long, memory_unit_conversion, CmdParser, ConfigCheck, ConfigError, ExBool,
+    MemZoneConfig, VmConfig, M,
+};
+
+/// Config structure for ivshmem-plain.
+#[derive(Debug, Clone)]
+pub struct IvshmemConfig {
+    pub id: String,
+    /// Memory backend of the shared memory, which must be a shared file.
+    pub mem_cfg: MemZoneConfig,
+    /// The shared memory is read only for the guest.
+    pub readonly: bool,
+}
+
+impl ConfigCheck for IvshmemConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "ivshmem id")?;
+        if self.mem_cfg.mem_path.is_none() {
+            bail!(
+                "Memory backend {} of ivshmem {} is not backed by file",
+                self.mem_cfg.id,
+                self.id
+            );
+        }
+        if !self.mem_cfg.share {
+            bail!(
+                "Memory backend {} of ivshmem {} must be shared",
+                self.mem_cfg.id,
+                self.id
+            );
+        }
+        // The shared memory is mapped into BAR2, whose size must be a power of 2.
+        if self.mem_cfg.size < M || !self.mem_cfg.size.is_power_of_two() {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "size of ivshmem must be a power of 2, and it".to_string(),
+                M,
+                true,
+                u64::MAX,
+                true,
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Get the config of ivshmem-plain, the memory backend is taken and can't be used again.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the ivshmem.
+    /// * `memdev` - Id of the memory backend object.
+    /// * `size` - The expected size of the shared memory, not checked if `None`.
+    /// * `readonly` - The shared memory is read only for the guest.
+    pub fn get_ivshmem_config(
+        &mut self,
+        id: &str,
+        memdev: &str,
+        size: Option<u64>,
+        readonly: bool,
+    ) -> Result<IvshmemConfig> {
+        let mem_cfg = self
+            .object
+            .mem_object
+            .get(memdev)
+            .cloned()
+            .with_context(|| format!("Object for memory-backend-file {} not found", memdev))?;
+        if let Some(size) = size {
+            if size != mem_cfg.size {
+                bail!(
+                    "Size 0x{:x} of ivshmem {} mismatches with memory backend {} 0x{:x}",
+                    size,
+                    id,
+                    memdev,
+                    mem_cfg.size
+                );
+            }
+        }
+        let ivshmem_cfg = IvshmemConfig {
+            id: id.to_string(),
+            mem_cfg,
+            readonly,
+        };
+        ivshmem_cfg.check()?;
+        self.object.mem_object.remove(memdev);
+        Ok(ivshmem_cfg)
+    }
+}
+
+pub fn parse_ivshmem(vm_config: &mut VmConfig, ivshmem_config: &str) -> Result<IvshmemConfig> {
+    let mut cmd_parser = CmdParser::new("ivshmem-plain");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("memdev")
+        .push("size")
+        .push("readonly");
+    cmd_parser.parse(ivshmem_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+        ConfigError::FieldIsMissing("id".to_string(), "ivshmem-plain".to_string())
+    })?;
+    let memdev = cmd_parser.get_value::<String>("memdev")?.with_context(|| {
+        ConfigError::FieldIsMissing("memdev".to_string(), "ivshmem-plain".to_string())
+    })?;
+    let size = match cmd_parser.get_value::<String>("size")? {
+        Some(size) => Some(memory_unit_conversion(&size, M)?),
+        None => None,
+    };
+    let mut readonly = false;
+    if let Some(ro) = cmd_parser.get_value::<ExBool>("readonly")? {
+        readonly = ro.into();
+    }
+    vm_config.get_ivshmem_config(&id, &memdev, size, readonly)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ivshmem_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-file,id=mem0,size=4M,mem-path=/dev/shm/ivshmem0,share=on")
+            .is_ok());
+        let config = parse_ivshmem(
+            &mut vm_config,
+            "ivshmem-plain,id=shm0,memdev=mem0,size=4M,readonly=on,bus=pcie.0,addr=0x5",
+        )
+        .unwrap();
+        assert_eq!(config.id, "shm0");
+        assert_eq!(config.mem_cfg.size, 4 * M);
+        assert_eq!(
+            config.mem_cfg.mem_path.as_deref(),
+            Some("/dev/shm/ivshmem0")
+        );
+        assert!(config.readonly);
+        // The memory backend can only be used by one device.
+        assert!(parse_ivshmem(
+            &mut vm_config,
+            "ivshmem-plain,id=shm1,memdev=mem0,bus=pcie.0,addr=0x6"
+        )
+        .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-file,id=mem0,size=4M,mem-path=/dev/shm/ivshmem0")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem1,size=4M,share=on")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-file,id=mem2,size=3M,mem-path=/dev/shm/ivshmem2,share=on")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-file,id=mem3,size=4M,mem-path=/dev/shm/ivshmem3,share=on")
+            .is_ok());
+        // Not shared.
+        assert!(parse_ivshmem(&mut vm_config, "ivshmem-plain,id=shm0,memdev=mem0").is_err());
+        // Not backed by file.
+        assert!(parse_ivshmem(&mut vm_config, "ivshmem-plain,id=shm1,memdev=mem1").is_err());
+        // Not a power of 2.
+        assert!(parse_ivshmem(&mut vm_config, "ivshmem-plain,id=shm2,memdev=mem2").is_err());
+        // Size mismatches.
+        assert!(
+            parse_ivshmem(&mut vm_config, "ivshmem-plain,id=shm3,memdev=mem3,size=8M").is_err()
+        );
+        let config = parse_ivshmem(&mut vm_config, "ivshmem-plain,id=shm3,memdev=mem3").unwrap();
+        assert!(!config.readonly);
+        assert!(parse_ivshmem(&mut vm_config, "ivshmem-plain,memdev=mem1").is_err());
+    }
+}
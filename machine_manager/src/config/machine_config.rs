@@ -17,8 +17,8 @@ use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
 use crate::config::{
-    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, ExBool, IntegerList, VmConfig,
-    MAX_NODES,
+    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, ExBool, IntegerList,
+    PanicAction, RebootAction, VmConfig, WatchdogAction, MAX_NODES,
 };
 
 const DEFAULT_CPUS: u8 = 1;
@@ -33,6 +33,10 @@ const MAX_NR_CPUS: u64 = 254;
 const MIN_NR_CPUS: u64 = 1;
 const MAX_MEMSIZE: u64 = 549_755_813_888;
 const MIN_MEMSIZE: u64 = 134_217_728;
+/// Max number of slots for hot-plugged pc-dimm devices.
+pub const MAX_MEM_SLOTS: u32 = 256;
+/// The architectural max SVE vector length is 2048 bits, i.e. 16 quadwords.
+const MAX_SVE_VQ: u32 = 16;
 pub const K: u64 = 1024;
 pub const M: u64 = 1024 * 1024;
 pub const G: u64 = 1024 * 1024 * 1024;
@@ -92,6 +96,10 @@ pub struct MemZoneConfig {
     pub dump_guest_core: bool,
     pub share: bool,
     pub prealloc: bool,
+    /// Number of threads to touch pages, `None` means decided by the number of vcpus.
+    pub prealloc_threads: Option<u8>,
+    /// Fail before preallocation if the free hugepages are insufficient.
+    pub prealloc_check: bool,
     pub memfd: bool,
 }
 
@@ -106,6 +114,8 @@ impl Default for MemZoneConfig {
             dump_guest_core: true,
             share: false,
             prealloc: false,
+            prealloc_threads: None,
+            prealloc_check: false,
             memfd: false,
         }
     }
@@ -119,7 +129,13 @@ pub struct MachineMemConfig {
     pub dump_guest_core: bool,
     pub mem_share: bool,
     pub mem_prealloc: bool,
+    /// Number of threads to touch pages, `None` means decided by the number of vcpus.
+    pub prealloc_threads: Option<u8>,
+    /// Fail before preallocation if the free hugepages are insufficient.
+    pub prealloc_check: bool,
     pub mem_zones: Option<Vec<MemZoneConfig>>,
+    /// Number of slots for hot-plugged pc-dimm devices, memory hotplug is disabled if it's 0.
+    pub mem_slots: u32,
 }
 
 impl Default for MachineMemConfig {
@@ -130,7 +146,10 @@ impl Default for MachineMemConfig {
             dump_guest_core: true,
             mem_share: false,
             mem_prealloc: false,
+            prealloc_threads: None,
+            prealloc_check: false,
             mem_zones: None,
+            mem_slots: 0,
         }
     }
 }
@@ -138,6 +157,13 @@ impl Default for MachineMemConfig {
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct CpuConfig {
     pub pmu: PmuConfig,
+    pub sve: SveConfig,
+    /// Max SVE vector length in quadwords (128 bits), `None` means all the host supported lengths.
+    pub sve_max_vq: Option<u32>,
+    /// Name of CPU model, `None` means passing through the host CPU.
+    pub model: Option<String>,
+    /// Features to be enabled (`true`) or disabled (`false`) on top of the CPU model.
+    pub features: Vec<(String, bool)>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -147,6 +173,13 @@ pub enum PmuConfig {
     Off,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum SveConfig {
+    On,
+    #[default]
+    Off,
+}
+
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub enum ShutdownAction {
     #[default]
@@ -169,7 +202,20 @@ pub struct MachineConfig {
     pub mem_config: MachineMemConfig,
     pub cpu_config: CpuConfig,
     pub shutdown_action: ShutdownAction,
+    pub reboot_action: RebootAction,
+    pub panic_action: PanicAction,
+    pub watchdog_action: WatchdogAction,
     pub battery: bool,
+    /// Whether the HPET is available, only used by x86_64 standard VM.
+    pub hpet: bool,
+    /// Whether only the local APICs are emulated in kvm, while IOAPIC and PIC are
+    /// emulated in userspace, only used by x86_64 standard VM.
+    pub split_irqchip: bool,
+    /// Maximum time in nanoseconds the vCPUs poll before halting, the default of kvm
+    /// module is used if it's not set.
+    pub halt_poll_ns: Option<u32>,
+    /// Adjust the halt polling time of the vCPUs by the success rate of polling.
+    pub halt_poll_adaptive: bool,
 }
 
 impl Default for MachineConfig {
@@ -187,7 +233,14 @@ impl Default for MachineConfig {
             mem_config: MachineMemConfig::default(),
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
+            reboot_action: RebootAction::default(),
+            panic_action: PanicAction::default(),
+            watchdog_action: WatchdogAction::default(),
             battery: false,
+            hpet: true,
+            split_irqchip: false,
+            halt_poll_ns: None,
+            halt_poll_adaptive: false,
         }
     }
 }
@@ -217,9 +270,13 @@ impl VmConfig {
             .push("accel")
             .push("usb")
             .push("dump-guest-core")
-            .push("mem-share");
+            .push("mem-share")
+            .push("halt-poll-ns")
+            .push("halt-poll-adaptive");
         #[cfg(target_arch = "aarch64")]
         cmd_parser.push("gic-version");
+        #[cfg(target_arch = "x86_64")]
+        cmd_parser.push("hpet").push("kernel-irqchip");
         cmd_parser.parse(mach_config)?;
 
         #[cfg(target_arch = "aarch64")]
@@ -258,6 +315,29 @@ impl VmConfig {
         if let Some(mem_share) = cmd_parser.get_value::<ExBool>("mem-share")? {
             self.machine_config.mem_config.mem_share = mem_share.into();
         }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(hpet) = cmd_parser.get_value::<ExBool>("hpet")? {
+            self.machine_config.hpet = hpet.into();
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(irqchip) = cmd_parser.get_value::<String>("kernel-irqchip")? {
+            self.machine_config.split_irqchip = match irqchip.as_str() {
+                "on" => false,
+                "split" => true,
+                _ => bail!("Only \'on\' and \'split\' are supported for \'kernel-irqchip\'"),
+            };
+        }
+        // There is no PIT in split irqchip mode, HPET is the only timer to drive the guest.
+        #[cfg(target_arch = "x86_64")]
+        if self.machine_config.split_irqchip && !self.machine_config.hpet {
+            bail!("\'hpet\' can't be set to \'off\' with \'kernel-irqchip=split\'");
+        }
+        if let Some(halt_poll_ns) = cmd_parser.get_value::<u32>("halt-poll-ns")? {
+            self.machine_config.halt_poll_ns = Some(halt_poll_ns);
+        }
+        if let Some(adaptive) = cmd_parser.get_value::<ExBool>("halt-poll-adaptive")? {
+            self.machine_config.halt_poll_adaptive = adaptive.into();
+        }
 
         Ok(())
     }
@@ -280,7 +360,13 @@ impl VmConfig {
     /// Add '-m' memory config to `VmConfig`.
     pub fn add_memory(&mut self, mem_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("m");
-        cmd_parser.push("").push("size");
+        cmd_parser
+            .push("")
+            .push("size")
+            .push("prealloc")
+            .push("prealloc-threads")
+            .push("prealloc-check")
+            .push("slots");
 
         cmd_parser.parse(mem_config)?;
 
@@ -296,6 +382,23 @@ impl VmConfig {
         };
 
         self.machine_config.mem_config.mem_size = mem;
+        if let Some(prealloc) = cmd_parser.get_value::<ExBool>("prealloc")? {
+            self.machine_config.mem_config.mem_prealloc = prealloc.into();
+        }
+        self.machine_config.mem_config.prealloc_threads = self.get_prealloc_threads(&cmd_parser)?;
+        self.machine_config.mem_config.prealloc_check = self.get_prealloc_check(&cmd_parser)?;
+        if let Some(slots) = cmd_parser.get_value::<u32>("slots")? {
+            if slots > MAX_MEM_SLOTS {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "slots of memory".to_string(),
+                    0,
+                    true,
+                    MAX_MEM_SLOTS as u64,
+                    true,
+                )));
+            }
+            self.machine_config.mem_config.mem_slots = slots;
+        }
 
         Ok(())
     }
@@ -397,10 +500,41 @@ impl VmConfig {
     }
 
     pub fn add_cpu_feature(&mut self, features: &str) -> Result<()> {
+        // Features like "+avx2" or "-avx512f" are not key-value pairs, pick them out first.
+        let mut params = Vec::new();
+        let mut cpu_features = Vec::new();
+        for (i, item) in features.split(',').enumerate() {
+            let enabled = match item.chars().next() {
+                Some('+') if i > 0 => true,
+                Some('-') if i > 0 => false,
+                _ => {
+                    params.push(item);
+                    continue;
+                }
+            };
+            let name = &item[1..];
+            if name.is_empty() {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    item.to_string(),
+                    "cpu".to_string()
+                )));
+            }
+            cpu_features.push((name.to_string(), enabled));
+        }
+
         let mut cmd_parser = CmdParser::new("cpu");
         cmd_parser.push("");
         cmd_parser.push("pmu");
-        cmd_parser.parse(features)?;
+        cmd_parser.push("sve");
+        cmd_parser.push("sve-max-vq");
+        cmd_parser.parse(&params.join(","))?;
+        if let Some(model) = cmd_parser.get_value::<String>("")? {
+            self.machine_config.cpu_config.model = match model.as_str() {
+                "host" => None,
+                _ => Some(model),
+            };
+        }
+        self.machine_config.cpu_config.features = cpu_features;
         // Check PMU when actually enabling PMU.
         if let Some(k) = cmd_parser.get_value::<String>("pmu")? {
             self.machine_config.cpu_config.pmu = match k.as_ref() {
@@ -409,6 +543,28 @@ impl VmConfig {
                 _ => bail!("Invalid PMU option,must be one of \'on\" or \"off\"."),
             }
         }
+        if let Some(k) = cmd_parser.get_value::<String>("sve")? {
+            self.machine_config.cpu_config.sve = match k.as_ref() {
+                "on" => SveConfig::On,
+                "off" => SveConfig::Off,
+                _ => bail!("Invalid SVE option,must be one of \"on\" or \"off\"."),
+            }
+        }
+        if let Some(max_vq) = cmd_parser.get_value::<u32>("sve-max-vq")? {
+            if self.machine_config.cpu_config.sve != SveConfig::On {
+                bail!("sve-max-vq is only valid when SVE is enabled");
+            }
+            if !(1..=MAX_SVE_VQ).contains(&max_vq) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "sve-max-vq".to_string(),
+                    1,
+                    true,
+                    MAX_SVE_VQ as u64,
+                    true,
+                )));
+            }
+            self.machine_config.cpu_config.sve_max_vq = Some(max_vq);
+        }
         Ok(())
     }
 
@@ -531,6 +687,29 @@ impl VmConfig {
         Ok(false)
     }
 
+    fn get_prealloc_threads(&self, cmd_parser: &CmdParser) -> Result<Option<u8>> {
+        if let Some(threads) = cmd_parser.get_value::<u8>("prealloc-threads")? {
+            if threads == 0 {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "prealloc-threads".to_string(),
+                    1,
+                    true,
+                    u8::MAX as u64,
+                    true,
+                )));
+            }
+            return Ok(Some(threads));
+        }
+        Ok(None)
+    }
+
+    fn get_prealloc_check(&self, cmd_parser: &CmdParser) -> Result<bool> {
+        if let Some(check) = cmd_parser.get_value::<ExBool>("prealloc-check")? {
+            return Ok(check.into());
+        }
+        Ok(false)
+    }
+
     /// Convert memory zone cmdline to VM config
     ///
     /// # Arguments
@@ -548,7 +727,9 @@ impl VmConfig {
             .push("share")
             .push("mem-path")
             .push("dump-guest-core")
-            .push("mem-prealloc");
+            .push("mem-prealloc")
+            .push("prealloc-threads")
+            .push("prealloc-check");
         cmd_parser.parse(mem_zone)?;
 
         let zone_config = MemZoneConfig {
@@ -560,6 +741,8 @@ impl VmConfig {
             share: self.get_mem_share(&cmd_parser)?,
             mem_path: self.get_mem_path(&cmd_parser)?,
             prealloc: self.get_mem_prealloc(&cmd_parser)?,
+            prealloc_threads: self.get_prealloc_threads(&cmd_parser)?,
+            prealloc_check: self.get_prealloc_check(&cmd_parser)?,
             memfd: mem_type.eq("memory-backend-memfd"),
         };
 
@@ -594,6 +777,20 @@ impl VmConfig {
 
         Ok(zone_config)
     }
+
+    /// Add a `memory-backend-ram` object created by QMP, which can be used by pc-dimm.
+    pub fn add_mem_zone_with_config(&mut self, zone_config: MemZoneConfig) -> Result<()> {
+        if zone_config.size == 0 {
+            bail!("Size of memory backend {} can't be 0", zone_config.id);
+        }
+        if self.object.mem_object.contains_key(&zone_config.id) {
+            bail!("Object: {} has been added", zone_config.id);
+        }
+        self.object
+            .mem_object
+            .insert(zone_config.id.clone(), zone_config);
+        Ok(())
+    }
 }
 
 fn smp_read_and_check(cmd_parser: &CmdParser, name: &str, default_val: u64) -> Result<u64> {
@@ -724,7 +921,10 @@ mod tests {
             mem_share: false,
             dump_guest_core: false,
             mem_prealloc: false,
+            prealloc_threads: None,
+            prealloc_check: false,
             mem_zones: None,
+            mem_slots: 0,
         };
         let mut machine_config = MachineConfig {
             mach_type: MachineType::MicroVm,
@@ -738,7 +938,14 @@ mod tests {
             mem_config: memory_config,
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
+            reboot_action: RebootAction::default(),
+            panic_action: PanicAction::default(),
+            watchdog_action: WatchdogAction::default(),
             battery: false,
+            hpet: true,
+            split_irqchip: false,
+            halt_poll_ns: None,
+            halt_poll_adaptive: false,
         };
         assert!(machine_config.check().is_ok());
 
@@ -937,6 +1144,22 @@ mod tests {
         assert!(mem_cfg_ret.is_ok());
         let mem_size = vm_config.machine_config.mem_config.mem_size;
         assert_eq!(mem_size, 8 * 1024 * 1024 * 1024);
+
+        let memory_cfg = "size=8G,prealloc=on,prealloc-threads=4,prealloc-check=on";
+        assert!(vm_config.add_memory(memory_cfg).is_ok());
+        let mem_config = &vm_config.machine_config.mem_config;
+        assert!(mem_config.mem_prealloc);
+        assert_eq!(mem_config.prealloc_threads, Some(4));
+        assert!(mem_config.prealloc_check);
+
+        let memory_cfg = "size=8G,prealloc=on,prealloc-threads=0";
+        assert!(vm_config.add_memory(memory_cfg).is_err());
+
+        let memory_cfg = "size=8G,slots=4";
+        assert!(vm_config.add_memory(memory_cfg).is_ok());
+        assert_eq!(vm_config.machine_config.mem_config.mem_slots, 4);
+        let memory_cfg = "size=8G,slots=257";
+        assert!(vm_config.add_memory(memory_cfg).is_err());
     }
 
     #[test]
@@ -959,6 +1182,14 @@ mod tests {
         assert_eq!(machine_cfg.mem_config.dump_guest_core, false);
         assert_eq!(machine_cfg.mem_config.mem_share, false);
 
+        let mut vm_config = VmConfig::default();
+        let machine_cfg_str = "type=none,halt-poll-ns=50000,halt-poll-adaptive=on";
+        assert!(vm_config.add_machine(machine_cfg_str).is_ok());
+        assert_eq!(vm_config.machine_config.halt_poll_ns, Some(50000));
+        assert!(vm_config.machine_config.halt_poll_adaptive);
+        let machine_cfg_str = "type=none,halt-poll-ns=-1";
+        assert!(vm_config.add_machine(machine_cfg_str).is_err());
+
         let mut vm_config = VmConfig::default();
         let memory_cfg_str = "type=none,accel=kvm-tcg";
         let machine_cfg_ret = vm_config.add_machine(memory_cfg_str);
@@ -996,6 +1227,35 @@ mod tests {
             let machine_cfg_ret = vm_config.add_machine(memory_cfg_str);
             assert!(machine_cfg_ret.is_err());
         }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config.machine_config.hpet);
+            let machine_cfg_ret = vm_config.add_machine("type=q35,hpet=off");
+            assert!(machine_cfg_ret.is_ok());
+            assert!(!vm_config.machine_config.hpet);
+
+            let mut vm_config = VmConfig::default();
+            let machine_cfg_ret = vm_config.add_machine("type=q35,hpet=false");
+            assert!(machine_cfg_ret.is_ok());
+            assert!(!vm_config.machine_config.hpet);
+
+            let mut vm_config = VmConfig::default();
+            assert!(!vm_config.machine_config.split_irqchip);
+            let machine_cfg_ret = vm_config.add_machine("type=q35,kernel-irqchip=split");
+            assert!(machine_cfg_ret.is_ok());
+            assert!(vm_config.machine_config.split_irqchip);
+            let machine_cfg_ret = vm_config.add_machine("type=q35,kernel-irqchip=on");
+            assert!(machine_cfg_ret.is_ok());
+            assert!(!vm_config.machine_config.split_irqchip);
+            let machine_cfg_ret = vm_config.add_machine("type=q35,kernel-irqchip=off");
+            assert!(machine_cfg_ret.is_err());
+
+            let mut vm_config = VmConfig::default();
+            let machine_cfg_ret = vm_config.add_machine("type=q35,kernel-irqchip=split,hpet=off");
+            assert!(machine_cfg_ret.is_err());
+        }
     }
 
     #[test]
@@ -1132,5 +1392,52 @@ mod tests {
         assert!(vm_config.machine_config.cpu_config.pmu == PmuConfig::On);
         vm_config.add_cpu_feature("pmu=on").unwrap();
         assert!(vm_config.machine_config.cpu_config.pmu == PmuConfig::On);
+
+        // Test SVE flags
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_cpu_feature("host,sve-max-vq=4").is_err());
+        vm_config.add_cpu_feature("host,sve=on").unwrap();
+        assert!(vm_config.machine_config.cpu_config.sve == SveConfig::On);
+        assert!(vm_config.machine_config.cpu_config.sve_max_vq.is_none());
+        vm_config
+            .add_cpu_feature("host,sve=on,sve-max-vq=4")
+            .unwrap();
+        assert_eq!(vm_config.machine_config.cpu_config.sve_max_vq, Some(4));
+        assert!(vm_config
+            .add_cpu_feature("host,sve=on,sve-max-vq=0")
+            .is_err());
+        assert!(vm_config
+            .add_cpu_feature("host,sve=on,sve-max-vq=17")
+            .is_err());
+        assert!(vm_config.add_cpu_feature("host,sve=yes").is_err());
+        vm_config.add_cpu_feature("host,sve=off").unwrap();
+        assert!(vm_config.machine_config.cpu_config.sve == SveConfig::Off);
+    }
+
+    #[test]
+    fn test_cpu_model() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(vm_config.machine_config.cpu_config.model.is_none());
+        assert!(vm_config.machine_config.cpu_config.features.is_empty());
+
+        vm_config
+            .add_cpu_feature("Skylake-Server,-avx512f,+pcid,pmu=off")
+            .unwrap();
+        let cpu_config = &vm_config.machine_config.cpu_config;
+        assert_eq!(cpu_config.model, Some("Skylake-Server".to_string()));
+        assert_eq!(
+            cpu_config.features,
+            vec![("avx512f".to_string(), false), ("pcid".to_string(), true)]
+        );
+        assert!(cpu_config.pmu == PmuConfig::Off);
+
+        vm_config.add_cpu_feature("host,+avx2").unwrap();
+        let cpu_config = &vm_config.machine_config.cpu_config;
+        assert!(cpu_config.model.is_none());
+        assert_eq!(cpu_config.features, vec![("avx2".to_string(), true)]);
+
+        assert!(vm_config.add_cpu_feature("host,-").is_err());
+        assert!(vm_config.add_cpu_feature("host,avx2").is_err());
     }
 }
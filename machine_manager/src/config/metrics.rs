@@ -0,0 +1,96 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::VmConfig;
+
+/// Listening address of the metrics endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MetricsAddr {
+    /// Tcp address, such as `127.0.0.1:9100`.
+    Tcp(String),
+    /// Path of the unix socket.
+    Unix(String),
+}
+
+/// Parse `-metrics` cmdline, such as `tcp:127.0.0.1:9100`, `tcp::9100` or
+/// `unix:/path/to/metrics.sock`. Empty ip address means listening on all interfaces.
+pub fn parse_metrics_uri(uri: &str) -> Result<MetricsAddr> {
+    let parse_vec: Vec<&str> = uri.split(':').collect();
+    match parse_vec[0] {
+        "tcp" if parse_vec.len() == 3 => {
+            let ip = if parse_vec[1].is_empty() {
+                "0.0.0.0"
+            } else {
+                parse_vec[1]
+            };
+            if ip.parse::<Ipv4Addr>().is_err() {
+                bail!("Invalid ip address {}", ip);
+            }
+            if parse_vec[2].parse::<u16>().is_err() {
+                bail!("Invalid ip port {}", parse_vec[2]);
+            }
+            Ok(MetricsAddr::Tcp(format!("{}:{}", ip, parse_vec[2])))
+        }
+        "unix" if parse_vec.len() == 2 && !parse_vec[1].is_empty() => {
+            Ok(MetricsAddr::Unix(parse_vec[1].to_string()))
+        }
+        _ => bail!(
+            "Invalid metrics uri {}, only tcp:[<ip>]:<port> and unix:<path> are supported",
+            uri
+        ),
+    }
+}
+
+impl VmConfig {
+    /// Add listening address of the metrics endpoint.
+    pub fn add_metrics(&mut self, config: &str) -> Result<()> {
+        self.metrics = Some(parse_metrics_uri(config)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metrics_uri() {
+        assert_eq!(
+            parse_metrics_uri("tcp::9100").unwrap(),
+            MetricsAddr::Tcp("0.0.0.0:9100".to_string())
+        );
+        assert_eq!(
+            parse_metrics_uri("tcp:127.0.0.1:9100").unwrap(),
+            MetricsAddr::Tcp("127.0.0.1:9100".to_string())
+        );
+        assert_eq!(
+            parse_metrics_uri("unix:/tmp/metrics.sock").unwrap(),
+            MetricsAddr::Unix("/tmp/metrics.sock".to_string())
+        );
+        assert!(parse_metrics_uri("tcp:9100").is_err());
+        assert!(parse_metrics_uri("tcp::65536").is_err());
+        assert!(parse_metrics_uri("unix:").is_err());
+        assert!(parse_metrics_uri("http:127.0.0.1:9100").is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_metrics("tcp::9100").is_ok());
+        assert_eq!(
+            vm_config.metrics,
+            Some(MetricsAddr::Tcp("0.0.0.0:9100".to_string()))
+        );
+    }
+}
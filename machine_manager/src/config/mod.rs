@@ -18,39 +18,63 @@ pub mod error;
 #[cfg(feature = "vnc")]
 pub mod vnc;
 
+mod action;
+mod ahci;
 mod balloon;
 mod boot_source;
+mod cgroup;
 mod chardev;
+mod crypto;
 #[cfg(feature = "demo_device")]
 mod demo_dev;
 mod devices;
 mod drive;
 mod fs;
+mod gdb;
 #[cfg(feature = "virtio_gpu")]
 mod gpu;
 mod incoming;
+mod iommu;
 mod iothread;
+mod ivshmem;
 mod machine_config;
+mod metrics;
+mod net_filter;
 mod network;
 mod numa;
+mod nvme;
+mod p9fs;
+mod pc_dimm;
 mod pci;
+mod pmem;
+mod profile;
 #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
 mod ramfb;
 mod rng;
+mod rtc;
+mod sandbox;
 mod sasl_auth;
 #[cfg(feature = "scream")]
 pub mod scream;
 mod scsi;
+mod secret;
 mod smbios;
+mod sound;
 mod tls_creds;
 mod usb;
 mod vfio;
+mod vmgenid;
+mod watchdog;
 
+pub use action::*;
+pub use ahci::*;
 pub use balloon::*;
 pub use boot_source::*;
 #[cfg(feature = "usb_camera")]
 pub use camera::*;
+pub use cgroup::*;
 pub use chardev::*;
+pub use crypto::*;
 #[cfg(feature = "demo_device")]
 pub use demo_dev::*;
 pub use devices::*;
@@ -59,25 +83,40 @@ pub use display::*;
 pub use drive::*;
 pub use error::ConfigError;
 pub use fs::*;
+pub use gdb::*;
 #[cfg(feature = "virtio_gpu")]
 pub use gpu::*;
 pub use incoming::*;
+pub use iommu::*;
 pub use iothread::*;
+pub use ivshmem::*;
 pub use machine_config::*;
+pub use metrics::*;
+pub use net_filter::*;
 pub use network::*;
 pub use numa::*;
+pub use nvme::*;
+pub use p9fs::*;
+pub use pc_dimm::*;
 pub use pci::*;
+pub use pmem::*;
 #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
 pub use ramfb::*;
 pub use rng::*;
+pub use rtc::*;
+pub use sandbox::*;
 pub use sasl_auth::*;
 pub use scsi::*;
+pub use secret::*;
 pub use smbios::*;
+pub use sound::*;
 pub use tls_creds::*;
 pub use usb::*;
 pub use vfio::*;
+pub use vmgenid::*;
 #[cfg(feature = "vnc")]
 pub use vnc::*;
+pub use watchdog::*;
 
 use std::collections::HashMap;
 use std::fs::File;
@@ -90,7 +129,7 @@ use serde::{Deserialize, Serialize};
 #[cfg(target_arch = "aarch64")]
 use util::device_tree::{self, FdtBuilder};
 use util::{
-    file::{get_file_alignment, open_file},
+    file::{get_file_alignment, get_file_block_size, open_file, BlockSize},
     num_ops::str_to_usize,
     test_helper::is_test_enabled,
     trace::enable_trace_events,
@@ -116,12 +155,16 @@ pub struct ObjectConfig {
     pub mem_object: HashMap<String, MemZoneConfig>,
     pub tls_object: HashMap<String, TlsCredObjConfig>,
     pub sasl_object: HashMap<String, SaslAuthObjConfig>,
+    pub secret_object: HashMap<String, SecretObjConfig>,
+    pub cryptodev_object: HashMap<String, CryptoDevObjConfig>,
+    pub netfilter_object: HashMap<String, NetFilterConfig>,
 }
 
 /// This main config structure for Vm, contains Vm's basic configuration and devices.
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct VmConfig {
     pub guest_name: String,
+    pub uuid: Option<Uuid>,
     pub machine_config: MachineConfig,
     pub boot_source: BootSource,
     pub drives: HashMap<String, DriveConfig>,
@@ -137,6 +180,8 @@ pub struct VmConfig {
     pub global_config: HashMap<String, String>,
     pub numa_nodes: Vec<(String, String)>,
     pub incoming: Option<Incoming>,
+    pub gdb: Option<String>,
+    pub metrics: Option<MetricsAddr>,
     #[cfg(feature = "vnc")]
     pub vnc: Option<VncConfig>,
     #[cfg(feature = "gtk")]
@@ -146,6 +191,9 @@ pub struct VmConfig {
     #[cfg(feature = "windows_emu_pid")]
     pub windows_emu_pid: Option<String>,
     pub smbios: SmbiosConfig,
+    pub rtc: RtcConfig,
+    pub sandbox: SandboxConfig,
+    pub cgroup: Option<CgroupConfig>,
 }
 
 impl VmConfig {
@@ -201,6 +249,17 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Add argument `uuid` to `VmConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid `String` such as "33DB4D5E-1FF7-401C-9657-7441C03DD766".
+    pub fn add_uuid(&mut self, uuid: &str) -> Result<()> {
+        let uuid = Uuid::from_str(uuid).map_err(|_| anyhow!("Invalid uuid {}", uuid))?;
+        self.uuid = Some(uuid);
+        Ok(())
+    }
+
     /// Add argument `object` to `VmConfig`.
     ///
     /// # Arguments
@@ -221,12 +280,7 @@ impl VmConfig {
             }
             "rng-random" => {
                 let rng_cfg = parse_rng_obj(object_args)?;
-                let id = rng_cfg.id.clone();
-                if self.object.rng_object.get(&id).is_none() {
-                    self.object.rng_object.insert(id, rng_cfg);
-                } else {
-                    bail!("Object: {} has been added", id);
-                }
+                self.add_rng_object_with_config(rng_cfg)?;
             }
             "memory-backend-ram" | "memory-backend-file" | "memory-backend-memfd" => {
                 self.add_mem_zone(object_args, device_type)?;
@@ -237,6 +291,15 @@ impl VmConfig {
             "authz-simple" => {
                 self.add_saslauth(object_args)?;
             }
+            "secret" => {
+                self.add_secret(object_args)?;
+            }
+            "cryptodev-backend-builtin" => {
+                self.add_cryptodev(object_args)?;
+            }
+            "filter-mirror" | "filter-redirector" => {
+                self.add_net_filter(object_args)?;
+            }
             _ => {
                 bail!("Unknow object type: {:?}", &device_type);
             }
@@ -315,6 +378,7 @@ impl VmConfig {
                 path
             );
         }
+        let block_size = get_file_block_size(&file, req_align);
         let drive_file = DriveFile {
             id: id.to_string(),
             file,
@@ -324,6 +388,7 @@ impl VmConfig {
             locked: false,
             req_align,
             buf_align,
+            block_size,
         };
         drive_files.insert(path.to_string(), drive_file);
         Ok(())
@@ -378,10 +443,26 @@ impl VmConfig {
         }
     }
 
+    /// Get logical and physical block size from drive file store.
+    pub fn fetch_drive_block_size(
+        drive_files: &HashMap<String, DriveFile>,
+        path: &str,
+    ) -> Result<BlockSize> {
+        match drive_files.get(path) {
+            Some(drive_file) => Ok(drive_file.block_size),
+            None => Err(anyhow!("The file {} is not in drive backend", path)),
+        }
+    }
+
     /// Create initial drive file store from cmdline drive.
     pub fn init_drive_files(&self) -> Result<HashMap<String, DriveFile>> {
         let mut drive_files: HashMap<String, DriveFile> = HashMap::new();
-        for drive in self.drives.values() {
+        // The empty cdrom drive has no backend file.
+        for drive in self
+            .drives
+            .values()
+            .filter(|drive| !drive.path_on_host.is_empty())
+        {
             Self::add_drive_file(
                 &mut drive_files,
                 &drive.id,
@@ -0,0 +1,228 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_arg_too_long, CmdParser, ConfigError, VmConfig};
+
+/// Type of the net filter object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetFilterType {
+    /// Copy the packets to `outdev`.
+    Mirror,
+    /// Move the packets to `outdev`, and inject the packets from `indev`.
+    Redirector,
+}
+
+impl FromStr for NetFilterType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "filter-mirror" => Ok(NetFilterType::Mirror),
+            "filter-redirector" => Ok(NetFilterType::Redirector),
+            _ => Err(anyhow!("Unknown net filter type {}", s)),
+        }
+    }
+}
+
+/// Direction of the packets which the filter works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetFilterQueue {
+    /// Packets in both directions.
+    All,
+    /// Packets received by the guest.
+    Rx,
+    /// Packets transmitted by the guest.
+    Tx,
+}
+
+impl NetFilterQueue {
+    /// Whether the filter on this queue handles the packets in `direction`.
+    pub fn contains(&self, direction: NetFilterQueue) -> bool {
+        *self == NetFilterQueue::All || *self == direction
+    }
+}
+
+impl FromStr for NetFilterQueue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(NetFilterQueue::All),
+            "rx" => Ok(NetFilterQueue::Rx),
+            "tx" => Ok(NetFilterQueue::Tx),
+            _ => Err(anyhow!("Unknown net filter queue {}", s)),
+        }
+    }
+}
+
+/// Config of `filter-mirror` and `filter-redirector` objects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetFilterConfig {
+    /// Object Id.
+    pub id: String,
+    pub filter_type: NetFilterType,
+    /// Id of the netdev which the filter is attached to.
+    pub netdev: String,
+    pub queue: NetFilterQueue,
+    /// Id of the chardev which the packets are sent to.
+    pub outdev: Option<String>,
+    /// Id of the chardev which the packets are received from, only for redirector.
+    pub indev: Option<String>,
+}
+
+impl NetFilterConfig {
+    pub fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "net filter id")?;
+        match self.filter_type {
+            NetFilterType::Mirror => {
+                if self.outdev.is_none() {
+                    return Err(anyhow!(ConfigError::FieldIsMissing(
+                        "outdev".to_string(),
+                        "filter-mirror".to_string()
+                    )));
+                }
+                if self.indev.is_some() {
+                    bail!("Filter-mirror {} doesn't support indev", self.id);
+                }
+            }
+            NetFilterType::Redirector => {
+                if self.outdev.is_none() && self.indev.is_none() {
+                    return Err(anyhow!(ConfigError::FieldIsMissing(
+                        "indev or outdev".to_string(),
+                        "filter-redirector".to_string()
+                    )));
+                }
+                if self.indev.is_some() && self.indev == self.outdev {
+                    bail!(
+                        "Filter-redirector {} has the same indev and outdev",
+                        self.id
+                    );
+                }
+                if self.indev.is_some() && self.queue == NetFilterQueue::All {
+                    bail!(
+                        "Filter-redirector {} with indev requires queue rx or tx",
+                        self.id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    pub fn add_net_filter(&mut self, filter_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("net-filter");
+        cmd_parser
+            .push("")
+            .push("id")
+            .push("netdev")
+            .push("queue")
+            .push("outdev")
+            .push("indev");
+        cmd_parser.parse(filter_config)?;
+
+        let filter_type = cmd_parser
+            .get_value::<NetFilterType>("")?
+            .with_context(|| "Net filter type not specified")?;
+        let id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), "net filter".to_string())
+        })?;
+        let netdev = cmd_parser.get_value::<String>("netdev")?.with_context(|| {
+            ConfigError::FieldIsMissing("netdev".to_string(), "net filter".to_string())
+        })?;
+        let filter = NetFilterConfig {
+            id,
+            filter_type,
+            netdev,
+            queue: cmd_parser
+                .get_value::<NetFilterQueue>("queue")?
+                .unwrap_or(NetFilterQueue::All),
+            outdev: cmd_parser.get_value::<String>("outdev")?,
+            indev: cmd_parser.get_value::<String>("indev")?,
+        };
+
+        self.add_net_filter_with_config(filter)
+    }
+
+    /// Add net filter object config, used by both cmdline and qmp.
+    pub fn add_net_filter_with_config(&mut self, filter: NetFilterConfig) -> Result<()> {
+        filter.check()?;
+        if self.object.netfilter_object.contains_key(&filter.id) {
+            return Err(anyhow!(ConfigError::IdRepeat(
+                "net filter".to_string(),
+                filter.id
+            )));
+        }
+        self.object
+            .netfilter_object
+            .insert(filter.id.clone(), filter);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_net_filter() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("filter-mirror,id=f0,netdev=net0,outdev=chr0")
+            .is_ok());
+        let filter = vm_config.object.netfilter_object.get("f0").unwrap();
+        assert_eq!(filter.filter_type, NetFilterType::Mirror);
+        assert_eq!(filter.queue, NetFilterQueue::All);
+        assert_eq!(filter.outdev, Some("chr0".to_string()));
+        assert!(vm_config
+            .add_object("filter-mirror,id=f0,netdev=net0,outdev=chr1")
+            .is_err());
+
+        assert!(vm_config
+            .add_object("filter-redirector,id=f1,netdev=net0,queue=rx,indev=chr1,outdev=chr2")
+            .is_ok());
+        let filter = vm_config.object.netfilter_object.get("f1").unwrap();
+        assert_eq!(filter.filter_type, NetFilterType::Redirector);
+        assert_eq!(filter.queue, NetFilterQueue::Rx);
+        assert_eq!(filter.indev, Some("chr1".to_string()));
+
+        // Invalid configurations.
+        assert!(vm_config
+            .add_object("filter-mirror,id=f2,netdev=net0")
+            .is_err());
+        assert!(vm_config
+            .add_object("filter-mirror,id=f2,outdev=chr0")
+            .is_err());
+        assert!(vm_config
+            .add_object("filter-mirror,id=f2,netdev=net0,outdev=chr0,indev=chr1")
+            .is_err());
+        assert!(vm_config
+            .add_object("filter-mirror,id=f2,netdev=net0,outdev=chr0,queue=both")
+            .is_err());
+        assert!(vm_config
+            .add_object("filter-redirector,id=f2,netdev=net0")
+            .is_err());
+        assert!(vm_config
+            .add_object("filter-redirector,id=f2,netdev=net0,queue=tx,indev=chr0,outdev=chr0")
+            .is_err());
+        assert!(vm_config
+            .add_object("filter-redirector,id=f2,netdev=net0,indev=chr0")
+            .is_err());
+    }
+}
@@ -11,6 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::os::unix::io::RawFd;
+use std::path::Path;
 
 use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
@@ -39,6 +40,12 @@ pub struct NetDevcfg {
     pub ifname: String,
     pub queues: u16,
     pub chardev: Option<String>,
+    /// Send buffer size of the tap in bytes.
+    pub sndbuf: Option<u64>,
+    /// Busyloop timeout of vhost-net in microseconds, 0 means no busyloop.
+    pub poll_us: u32,
+    /// Whether to transmit packets by vhost-net with zero-copy.
+    pub zerocopy: bool,
 }
 
 impl Default for NetDevcfg {
@@ -51,6 +58,9 @@ impl Default for NetDevcfg {
             ifname: "".to_string(),
             queues: 2,
             chardev: None,
+            sndbuf: None,
+            poll_us: 0,
+            zerocopy: false,
         }
     }
 }
@@ -76,16 +86,59 @@ impl ConfigCheck for NetDevcfg {
             )));
         }
 
+        if let Some(tap_fds) = self.tap_fds.as_ref() {
+            if tap_fds.len() * 2 != self.queues as usize {
+                bail!(
+                    "The num of tap fds {} doesn't match the queues {}",
+                    tap_fds.len(),
+                    self.queues / 2
+                );
+            }
+            if let Some(vhost_fds) = self.vhost_fds.as_ref() {
+                if vhost_fds.len() != tap_fds.len() {
+                    bail!("The num of vhostfds must equal to fds");
+                }
+            }
+        }
+
+        if let Some(sndbuf) = self.sndbuf {
+            check_tap_sndbuf(sndbuf)?;
+        }
+
+        check_vhost_net_tuning(self.vhost_type.as_ref(), self.poll_us, self.zerocopy)?;
+
         Ok(())
     }
 }
 
+fn check_vhost_net_tuning(vhost_type: Option<&String>, poll_us: u32, zerocopy: bool) -> Result<()> {
+    if (poll_us != 0 || zerocopy) && vhost_type.map(|t| t.as_str()) != Some("vhost-kernel") {
+        bail!("Argument \'poll-us\' and \'zerocopy\' are only supported by vhost-net");
+    }
+    Ok(())
+}
+
+fn check_tap_sndbuf(sndbuf: u64) -> Result<()> {
+    if sndbuf == 0 || sndbuf > i32::MAX as u64 {
+        return Err(anyhow!(ConfigError::IllegalValue(
+            "sndbuf of tap".to_string(),
+            1,
+            true,
+            i32::MAX as u64,
+            true,
+        )));
+    }
+    Ok(())
+}
+
 /// Config struct for network
 /// Contains network device config, such as `host_dev_name`, `mac`...
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceConfig {
     pub id: String,
+    /// Id of the netdev, which is used to attach net filters.
+    pub netdev: String,
     pub host_dev_name: String,
     pub mac: Option<String>,
     pub tap_fds: Option<Vec<i32>>,
@@ -94,15 +147,29 @@ pub struct NetworkInterfaceConfig {
     pub iothread: Option<String>,
     pub queues: u16,
     pub mq: bool,
+    /// Send buffer size of the tap in bytes, the default size of kernel is used if not set.
+    pub sndbuf: Option<u64>,
+    /// Busyloop timeout of vhost-net in microseconds, 0 means no busyloop.
+    pub poll_us: u32,
+    /// Whether to transmit packets by vhost-net with zero-copy.
+    pub zerocopy: bool,
     pub socket_path: Option<String>,
     /// All queues of a net device have the same queue size now.
     pub queue_size: u16,
+    /// Option ROM file exposed through the expansion ROM BAR.
+    pub romfile: Option<String>,
+    pub boot_index: Option<u8>,
+    /// Max number of used rx buffers before notifying the guest, 0 means no limit.
+    pub max_frames: u32,
+    /// Max delay of rx notifications in microseconds, 0 disables the coalescing.
+    pub max_usecs: u32,
 }
 
 impl Default for NetworkInterfaceConfig {
     fn default() -> Self {
         NetworkInterfaceConfig {
             id: "".to_string(),
+            netdev: "".to_string(),
             host_dev_name: "".to_string(),
             mac: None,
             tap_fds: None,
@@ -111,8 +178,15 @@ impl Default for NetworkInterfaceConfig {
             iothread: None,
             queues: 2,
             mq: false,
+            sndbuf: None,
+            poll_us: 0,
+            zerocopy: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            romfile: None,
+            boot_index: None,
+            max_frames: 0,
+            max_usecs: 0,
         }
     }
 }
@@ -152,6 +226,35 @@ impl ConfigCheck for NetworkInterfaceConfig {
             bail!("queue size of net device should be power of 2!");
         }
 
+        if let Some(sndbuf) = self.sndbuf {
+            check_tap_sndbuf(sndbuf)?;
+        }
+
+        check_vhost_net_tuning(self.vhost_type.as_ref(), self.poll_us, self.zerocopy)?;
+
+        if let Some(romfile) = &self.romfile {
+            if romfile.len() > MAX_PATH_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "romfile path".to_string(),
+                    MAX_PATH_LENGTH,
+                )));
+            }
+            if !Path::new(romfile).is_file() {
+                return Err(anyhow!(ConfigError::UnRegularFile(
+                    "Input romfile".to_string()
+                )));
+            }
+        }
+
+        if self.max_frames != 0 || self.max_usecs != 0 {
+            if self.vhost_type.is_some() {
+                bail!("Notification coalescing is not supported by vhost net device");
+            }
+            if self.max_usecs == 0 {
+                bail!("Argument \'max-usecs\' is needed if \'max-frames\' is set");
+            }
+        }
+
         Ok(())
     }
 }
@@ -224,6 +327,15 @@ fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
     if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
         net.chardev = Some(chardev);
     }
+    if let Some(sndbuf) = cmd_parser.get_value::<u64>("sndbuf")? {
+        net.sndbuf = Some(sndbuf);
+    }
+    if let Some(poll_us) = cmd_parser.get_value::<u32>("poll-us")? {
+        net.poll_us = poll_us;
+    }
+    if let Some(zerocopy) = cmd_parser.get_value::<ExBool>("zerocopy")? {
+        net.zerocopy = zerocopy.into();
+    }
     if let Some(vhost_fd) = parse_fds(&cmd_parser, "vhostfd")? {
         net.vhost_fds = Some(vhost_fd);
     } else if let Some(vhost_fds) = parse_fds(&cmd_parser, "vhostfds")? {
@@ -263,9 +375,15 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         .push("bus")
         .push("addr")
         .push("multifunction")
+        .push("disable-legacy")
+        .push("disable-modern")
         .push("mac")
         .push("iothread")
-        .push("queue-size");
+        .push("queue-size")
+        .push("romfile")
+        .push("bootindex")
+        .push("max-frames")
+        .push("max-usecs");
 
     cmd_parser.parse(net_config)?;
     pci_args_check(&cmd_parser)?;
@@ -284,14 +402,26 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
     if let Some(queue_size) = cmd_parser.get_value::<u16>("queue-size")? {
         netdevinterfacecfg.queue_size = queue_size;
     }
+    netdevinterfacecfg.romfile = cmd_parser.get_value::<String>("romfile")?;
+    netdevinterfacecfg.boot_index = cmd_parser.get_value::<u8>("bootindex")?;
+    if let Some(max_frames) = cmd_parser.get_value::<u32>("max-frames")? {
+        netdevinterfacecfg.max_frames = max_frames;
+    }
+    if let Some(max_usecs) = cmd_parser.get_value::<u32>("max-usecs")? {
+        netdevinterfacecfg.max_usecs = max_usecs;
+    }
 
     if let Some(netcfg) = &vm_config.netdevs.remove(&netdev) {
         netdevinterfacecfg.id = netid;
+        netdevinterfacecfg.netdev = netdev.clone();
         netdevinterfacecfg.host_dev_name = netcfg.ifname.clone();
         netdevinterfacecfg.tap_fds = netcfg.tap_fds.clone();
         netdevinterfacecfg.vhost_fds = netcfg.vhost_fds.clone();
         netdevinterfacecfg.vhost_type = netcfg.vhost_type.clone();
         netdevinterfacecfg.queues = netcfg.queues;
+        netdevinterfacecfg.sndbuf = netcfg.sndbuf;
+        netdevinterfacecfg.poll_us = netcfg.poll_us;
+        netdevinterfacecfg.zerocopy = netcfg.zerocopy;
         if let Some(chardev) = &netcfg.chardev {
             netdevinterfacecfg.socket_path = Some(get_chardev_socket_path(chardev, vm_config)?);
         }
@@ -303,6 +433,51 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
     Ok(netdevinterfacecfg)
 }
 
+/// Parse the emulated e1000e nic, which only supports the single queue tap backend.
+pub fn parse_e1000e(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkInterfaceConfig> {
+    let mut cmd_parser = CmdParser::new("e1000e");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("netdev")
+        .push("bus")
+        .push("addr")
+        .push("mac")
+        .push("iothread");
+
+    cmd_parser.parse(net_config)?;
+    pci_args_check(&cmd_parser)?;
+    let mut netdevinterfacecfg = NetworkInterfaceConfig::default();
+
+    let netdev = cmd_parser
+        .get_value::<String>("netdev")?
+        .with_context(|| ConfigError::FieldIsMissing("netdev".to_string(), "e1000e".to_string()))?;
+    netdevinterfacecfg.id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "e1000e".to_string()))?;
+    netdevinterfacecfg.iothread = cmd_parser.get_value::<String>("iothread")?;
+    netdevinterfacecfg.mac = cmd_parser.get_value::<String>("mac")?;
+
+    let netcfg = vm_config
+        .netdevs
+        .remove(&netdev)
+        .with_context(|| format!("Netdev: {:?} not found for e1000e device", &netdev))?;
+    if netcfg.vhost_type.is_some() || netcfg.chardev.is_some() {
+        bail!("The e1000e device only supports the tap backend without vhost");
+    }
+    if netcfg.queues != 2 {
+        bail!("The e1000e device only supports one queue pair");
+    }
+    netdevinterfacecfg.netdev = netdev;
+    netdevinterfacecfg.host_dev_name = netcfg.ifname.clone();
+    netdevinterfacecfg.tap_fds = netcfg.tap_fds.clone();
+    netdevinterfacecfg.queues = netcfg.queues;
+    netdevinterfacecfg.sndbuf = netcfg.sndbuf;
+
+    netdevinterfacecfg.check()?;
+    Ok(netdevinterfacecfg)
+}
+
 fn get_netdev_fd(fd_name: &str) -> Result<RawFd> {
     if let Some(fd) = QmpChannel::get_fd(fd_name) {
         Ok(fd)
@@ -352,6 +527,9 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
         ifname: String::new(),
         queues,
         chardev: args.chardev,
+        sndbuf: args.sndbuf,
+        poll_us: args.poll_us.unwrap_or_default(),
+        zerocopy: args.zerocopy.unwrap_or_default(),
     };
 
     if let Some(tap_fd) = args.fd {
@@ -412,6 +590,7 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
     if config.tap_fds.is_none() && config.ifname.eq("") && netdev_type.ne("vhost-user") {
         bail!("Tap device is missing, use 'ifname' or 'fd' to configure a tap device");
     }
+    config.check()?;
 
     Ok(config)
 }
@@ -429,7 +608,10 @@ impl VmConfig {
             .push("vhostfd")
             .push("vhostfds")
             .push("queues")
-            .push("chardev");
+            .push("chardev")
+            .push("sndbuf")
+            .push("poll-us")
+            .push("zerocopy");
 
         cmd_parser.parse(netdev_config)?;
         let drive_cfg = parse_netdev(cmd_parser)?;
@@ -493,11 +675,19 @@ impl VmConfig {
             device_info = format!("{},mq={}", device_info, mq);
         }
 
+        if let Some(max_frames) = &args.max_frames {
+            device_info = format!("{},max-frames={}", device_info, max_frames);
+        }
+
+        if let Some(max_usecs) = &args.max_usecs {
+            device_info = format!("{},max-usecs={}", device_info, max_usecs);
+        }
+
         self.devices.push((args.driver.clone(), device_info));
     }
 }
 
-fn check_mac_address(mac: &str) -> bool {
+pub fn check_mac_address(mac: &str) -> bool {
     if mac.len() != MAC_ADDRESS_LENGTH {
         return false;
     }
@@ -698,6 +888,98 @@ mod tests {
         assert!(net_cfg_res.is_err());
     }
 
+    #[test]
+    fn test_pci_network_config_romfile() {
+        let romfile = std::env::temp_dir().join("test_pci_network_config_romfile.rom");
+        std::fs::write(&romfile, [0x55, 0xaa]).unwrap();
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg = format!(
+            "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x1.0x0,romfile={},bootindex=1",
+            romfile.to_str().unwrap()
+        );
+        let network_configs = parse_net(&mut vm_config, &net_cfg).unwrap();
+        assert_eq!(network_configs.romfile.as_deref(), romfile.to_str());
+        assert_eq!(network_configs.boot_index, Some(1));
+
+        // Romfile which is not a regular file.
+        assert!(vm_config.add_netdev("tap,id=eth2,ifname=tap2").is_ok());
+        let net_cfg = "virtio-net-pci,id=net2,netdev=eth2,bus=pcie.0,addr=0x2.0x0,romfile=/tmp";
+        assert!(parse_net(&mut vm_config, net_cfg).is_err());
+
+        std::fs::remove_file(romfile).unwrap();
+    }
+
+    #[test]
+    fn test_pci_network_config_coalescing() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg =
+            "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x1.0x0,max-frames=32,max-usecs=50";
+        let network_configs = parse_net(&mut vm_config, net_cfg).unwrap();
+        assert_eq!(network_configs.max_frames, 32);
+        assert_eq!(network_configs.max_usecs, 50);
+
+        // Max-frames without max-usecs.
+        assert!(vm_config.add_netdev("tap,id=eth2,ifname=tap2").is_ok());
+        let net_cfg = "virtio-net-pci,id=net2,netdev=eth2,bus=pcie.0,addr=0x2.0x0,max-frames=32";
+        assert!(parse_net(&mut vm_config, net_cfg).is_err());
+
+        // Vhost net device doesn't support coalescing.
+        assert!(vm_config
+            .add_netdev("tap,id=eth3,ifname=tap3,vhost=on")
+            .is_ok());
+        let net_cfg = "virtio-net-pci,id=net3,netdev=eth3,bus=pcie.0,addr=0x3.0x0,max-usecs=50";
+        assert!(parse_net(&mut vm_config, net_cfg).is_err());
+    }
+
+    #[test]
+    fn test_e1000e_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,sndbuf=65536")
+            .is_ok());
+        let net_cfg =
+            "e1000e,id=net0,netdev=eth0,bus=pcie.0,addr=0x3,mac=52:54:00:12:34:56,iothread=io0";
+        let network_configs = parse_e1000e(&mut vm_config, net_cfg).unwrap();
+        assert_eq!(network_configs.id, "net0");
+        assert_eq!(network_configs.netdev, "eth0");
+        assert_eq!(network_configs.host_dev_name, "tap0");
+        assert_eq!(network_configs.mac.as_deref(), Some("52:54:00:12:34:56"));
+        assert_eq!(network_configs.iothread.as_deref(), Some("io0"));
+        assert_eq!(network_configs.sndbuf, Some(65536));
+        // The netdev can only be used by one device.
+        assert!(parse_e1000e(&mut vm_config, net_cfg).is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth1,fd=35").is_ok());
+        let network_configs = parse_e1000e(
+            &mut vm_config,
+            "e1000e,id=net1,netdev=eth1,bus=pcie.0,addr=0x4",
+        )
+        .unwrap();
+        assert_eq!(network_configs.tap_fds, Some(vec![35]));
+
+        // Id is required.
+        assert!(vm_config.add_netdev("tap,id=eth2,ifname=tap2").is_ok());
+        assert!(parse_e1000e(&mut vm_config, "e1000e,netdev=eth2").is_err());
+        // Vhost, vhost-user and multi queue backends are not supported.
+        assert!(vm_config
+            .add_netdev("tap,id=eth3,ifname=tap3,vhost=on")
+            .is_ok());
+        assert!(parse_e1000e(&mut vm_config, "e1000e,id=net3,netdev=eth3").is_err());
+        assert!(vm_config.add_netdev("vhost-user,id=eth4").is_ok());
+        assert!(parse_e1000e(&mut vm_config, "e1000e,id=net4,netdev=eth4").is_err());
+        assert!(vm_config
+            .add_netdev("tap,id=eth5,ifname=tap5,queues=2")
+            .is_ok());
+        assert!(parse_e1000e(&mut vm_config, "e1000e,id=net5,netdev=eth5").is_err());
+        // Virtio-net specific arguments are rejected.
+        assert!(vm_config.add_netdev("tap,id=eth6,ifname=tap6").is_ok());
+        assert!(parse_e1000e(&mut vm_config, "e1000e,id=net6,netdev=eth6,mq=on").is_err());
+    }
+
     #[test]
     fn test_netdev_config_check() {
         let mut netdev_conf = NetDevcfg::default();
@@ -727,6 +1009,56 @@ mod tests {
         assert!(netdev_conf.check().is_ok());
         netdev_conf.vhost_type = Some(String::from("vhost-"));
         assert!(netdev_conf.check().is_err());
+
+        // The num of tap fds should match the queues.
+        let mut netdev_conf = NetDevcfg {
+            tap_fds: Some(vec![34, 35]),
+            ..Default::default()
+        };
+        assert!(netdev_conf.check().is_err());
+        netdev_conf.queues = 4;
+        assert!(netdev_conf.check().is_ok());
+        netdev_conf.vhost_fds = Some(vec![36]);
+        assert!(netdev_conf.check().is_err());
+        netdev_conf.vhost_fds = Some(vec![36, 37]);
+        assert!(netdev_conf.check().is_ok());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,fds=34:35,queues=4")
+            .is_err());
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,fds=34:35,queues=2")
+            .is_ok());
+
+        // Sndbuf of tap.
+        let mut netdev_conf = NetDevcfg {
+            sndbuf: Some(0),
+            ..Default::default()
+        };
+        assert!(netdev_conf.check().is_err());
+        netdev_conf.sndbuf = Some(i32::MAX as u64 + 1);
+        assert!(netdev_conf.check().is_err());
+        netdev_conf.sndbuf = Some(1048576);
+        assert!(netdev_conf.check().is_ok());
+        assert!(vm_config
+            .add_netdev("tap,id=eth1,ifname=tap1,sndbuf=1048576")
+            .is_ok());
+        assert_eq!(vm_config.netdevs.get("eth1").unwrap().sndbuf, Some(1048576));
+
+        // Busyloop and zero-copy of vhost-net.
+        assert!(vm_config
+            .add_netdev("tap,id=eth2,ifname=tap2,poll-us=50")
+            .is_err());
+        assert!(vm_config
+            .add_netdev("vhost-user,id=eth2,chardev=chardevid,zerocopy=on")
+            .is_err());
+        assert!(vm_config
+            .add_netdev("tap,id=eth2,ifname=tap2,vhost=on,poll-us=50,zerocopy=on")
+            .is_ok());
+        let netdev = vm_config.netdevs.get("eth2").unwrap();
+        assert_eq!(netdev.poll_us, 50);
+        assert!(netdev.zerocopy);
     }
 
     #[test]
@@ -0,0 +1,170 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{error::ConfigError, pci_args_check, DriveConfig};
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, VmConfig};
+
+/// Default number of the IO queue pairs of nvme controller.
+const DEFAULT_NVME_QUEUES: u16 = 8;
+/// Max number of the IO queue pairs of nvme controller.
+pub const MAX_NVME_QUEUES: u16 = 64;
+/// Max length of the serial number in the identify data.
+const MAX_NVME_SERIAL_LEN: usize = 20;
+
+/// Config structure for the emulated nvme controller.
+#[derive(Debug, Clone)]
+pub struct NvmeConfig {
+    pub id: String,
+    /// Serial number of the controller.
+    pub serial: String,
+    /// Thread name of io handler.
+    pub iothread: Option<String>,
+    /// Max number of the IO queue pairs.
+    pub queues: u16,
+    /// The drive backing the namespace 1.
+    pub drive: DriveConfig,
+    /// Secret data used to unlock the luks image.
+    pub key_secret: Option<String>,
+}
+
+impl ConfigCheck for NvmeConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "nvme id")?;
+        if let Some(iothread) = self.iothread.as_ref() {
+            check_arg_too_long(iothread, "iothread name")?;
+        }
+        if self.serial.len() > MAX_NVME_SERIAL_LEN {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "nvme serial".to_string(),
+                MAX_NVME_SERIAL_LEN,
+            )));
+        }
+        if self.queues == 0 || self.queues > MAX_NVME_QUEUES {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "num-queues of nvme".to_string(),
+                1,
+                true,
+                MAX_NVME_QUEUES as u64,
+                true,
+            )));
+        }
+        if self.drive.media != "disk" {
+            bail!("Nvme {} only supports the disk media", self.id);
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_nvme(vm_config: &mut VmConfig, nvme_config: &str) -> Result<NvmeConfig> {
+    let mut cmd_parser = CmdParser::new("nvme");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("drive")
+        .push("serial")
+        .push("iothread")
+        .push("num-queues");
+    cmd_parser.parse(nvme_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "nvme".to_string()))?;
+    let drive_id = cmd_parser
+        .get_value::<String>("drive")?
+        .with_context(|| ConfigError::FieldIsMissing("drive".to_string(), "nvme".to_string()))?;
+    let serial = cmd_parser
+        .get_value::<String>("serial")?
+        .with_context(|| ConfigError::FieldIsMissing("serial".to_string(), "nvme".to_string()))?;
+    let iothread = cmd_parser.get_value::<String>("iothread")?;
+    let queues = cmd_parser
+        .get_value::<u16>("num-queues")?
+        .unwrap_or(DEFAULT_NVME_QUEUES);
+
+    let drive = vm_config
+        .drives
+        .remove(&drive_id)
+        .with_context(|| "No drive configured matched for nvme")?;
+    let key_secret = match drive.key_secret.as_ref() {
+        Some(secret) => Some(vm_config.get_secret(secret)?),
+        None => None,
+    };
+    let nvme_cfg = NvmeConfig {
+        id,
+        serial,
+        iothread,
+        queues,
+        drive,
+        key_secret,
+    };
+    nvme_cfg.check()?;
+    Ok(nvme_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nvme_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=drive0,file=/path/to/disk,direct=off,aio=io_uring")
+            .is_ok());
+        let config = parse_nvme(
+            &mut vm_config,
+            "nvme,id=nvme0,drive=drive0,serial=deadbeef,iothread=iothread0,num-queues=4,bus=pcie.0,addr=0x5",
+        )
+        .unwrap();
+        assert_eq!(config.id, "nvme0");
+        assert_eq!(config.serial, "deadbeef");
+        assert_eq!(config.iothread.as_deref(), Some("iothread0"));
+        assert_eq!(config.queues, 4);
+        assert_eq!(config.drive.path_on_host, "/path/to/disk");
+        assert!(!config.drive.direct);
+        // The drive can only be used by one device.
+        assert!(parse_nvme(&mut vm_config, "nvme,id=nvme1,drive=drive0,serial=1").is_err());
+
+        let mut vm_config = VmConfig::default();
+        for id in 0..5 {
+            assert!(vm_config
+                .add_drive(&format!("id=drive{},file=/path/to/disk{}", id, id))
+                .is_ok());
+        }
+        let config = parse_nvme(&mut vm_config, "nvme,id=nvme0,drive=drive0,serial=1").unwrap();
+        assert_eq!(config.queues, DEFAULT_NVME_QUEUES);
+        assert!(config.iothread.is_none());
+        // Serial is required, and at most 20 bytes.
+        assert!(parse_nvme(&mut vm_config, "nvme,id=nvme1,drive=drive1").is_err());
+        assert!(parse_nvme(
+            &mut vm_config,
+            "nvme,id=nvme2,drive=drive2,serial=012345678901234567890"
+        )
+        .is_err());
+        // Invalid number of queues.
+        assert!(parse_nvme(
+            &mut vm_config,
+            "nvme,id=nvme3,drive=drive3,serial=3,num-queues=0"
+        )
+        .is_err());
+        assert!(parse_nvme(
+            &mut vm_config,
+            "nvme,id=nvme4,drive=drive4,serial=4,num-queues=65"
+        )
+        .is_err());
+        assert!(parse_nvme(&mut vm_config, "nvme,drive=drive5,serial=5").is_err());
+    }
+}
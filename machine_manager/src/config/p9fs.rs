@@ -0,0 +1,147 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{error::ConfigError, pci_args_check};
+use crate::config::{
+    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, MAX_TAG_LENGTH,
+};
+
+/// How the ownership and permission of files created by the guest are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum P9SecurityModel {
+    /// Files are created with the credentials of the guest user, which requires
+    /// StratoVirt to have the privilege of chown.
+    Passthrough,
+    /// Files are created with the credentials of StratoVirt, and the credentials of the
+    /// guest user are stored in the extended attributes of the files.
+    Mapped,
+}
+
+impl FromStr for P9SecurityModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "passthrough" => Ok(P9SecurityModel::Passthrough),
+            "mapped" | "mapped-xattr" => Ok(P9SecurityModel::Mapped),
+            _ => Err(anyhow!("Unknown security model {}", s)),
+        }
+    }
+}
+
+/// Config structure for virtio-9p.
+#[derive(Debug, Clone)]
+pub struct P9fsConfig {
+    pub id: String,
+    /// Path of the host directory shared with the guest.
+    pub path: String,
+    /// Tag which is used by the guest to mount the file system.
+    pub mount_tag: String,
+    pub security_model: P9SecurityModel,
+}
+
+impl ConfigCheck for P9fsConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "virtio-9p id")?;
+        check_path_too_long(&self.path, "virtio-9p path")?;
+        if self.mount_tag.is_empty() || self.mount_tag.len() >= MAX_TAG_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "virtio-9p mount_tag".to_string(),
+                MAX_TAG_LENGTH - 1,
+            )));
+        }
+        if !Path::new(&self.path).is_dir() {
+            bail!(
+                "The shared path {} of virtio-9p is not a directory",
+                self.path
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_p9fs(p9fs_config: &str) -> Result<P9fsConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-9p");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("path")
+        .push("mount_tag")
+        .push("security_model");
+    cmd_parser.parse(p9fs_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let p9fs_cfg = P9fsConfig {
+        id: cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), "virtio-9p".to_string())
+        })?,
+        path: cmd_parser.get_value::<String>("path")?.with_context(|| {
+            ConfigError::FieldIsMissing("path".to_string(), "virtio-9p".to_string())
+        })?,
+        mount_tag: cmd_parser
+            .get_value::<String>("mount_tag")?
+            .with_context(|| {
+                ConfigError::FieldIsMissing("mount_tag".to_string(), "virtio-9p".to_string())
+            })?,
+        security_model: cmd_parser
+            .get_value::<P9SecurityModel>("security_model")?
+            .unwrap_or(P9SecurityModel::Mapped),
+    };
+    p9fs_cfg.check()?;
+    Ok(p9fs_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p9fs_config_cmdline_parser() {
+        let config = parse_p9fs(
+            "virtio-9p-pci,id=p9fs0,path=/tmp,mount_tag=hostshare,security_model=passthrough,bus=pcie.0,addr=0x5",
+        )
+        .unwrap();
+        assert_eq!(config.id, "p9fs0");
+        assert_eq!(config.path, "/tmp");
+        assert_eq!(config.mount_tag, "hostshare");
+        assert_eq!(config.security_model, P9SecurityModel::Passthrough);
+
+        let config = parse_p9fs("virtio-9p-device,id=p9fs0,path=/tmp,mount_tag=hostshare").unwrap();
+        assert_eq!(config.security_model, P9SecurityModel::Mapped);
+
+        assert!(parse_p9fs("virtio-9p-device,id=p9fs0,path=/tmp").is_err());
+        assert!(parse_p9fs("virtio-9p-device,id=p9fs0,mount_tag=hostshare").is_err());
+        assert!(parse_p9fs(
+            "virtio-9p-device,id=p9fs0,path=/tmp,mount_tag=hostshare,security_model=none"
+        )
+        .is_err());
+        assert!(parse_p9fs(
+            "virtio-9p-device,id=p9fs0,path=/tmp/stratovirt_no_such_dir,mount_tag=hostshare"
+        )
+        .is_err());
+        let long_tag = "t".repeat(MAX_TAG_LENGTH);
+        assert!(parse_p9fs(&format!(
+            "virtio-9p-device,id=p9fs0,path=/tmp,mount_tag={}",
+            long_tag
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::config::{
+    check_arg_too_long, CmdParser, ConfigCheck, ConfigError, MemZoneConfig, VmConfig, M, MAX_NODES,
+};
+
+/// The size of pc-dimm must be aligned to the memory section of guest.
+const DIMM_SIZE_ALIGN: u64 = 128 * M;
+
+/// Config structure for pc-dimm.
+#[derive(Debug, Clone)]
+pub struct PcDimmConfig {
+    pub id: String,
+    /// Memory backend of the dimm.
+    pub mem_cfg: MemZoneConfig,
+    /// Guest numa node which the dimm belongs to.
+    pub node: u32,
+}
+
+impl ConfigCheck for PcDimmConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "pc-dimm id")?;
+        if self.mem_cfg.size == 0 || !self.mem_cfg.size.is_multiple_of(DIMM_SIZE_ALIGN) {
+            bail!(
+                "Size of pc-dimm {} must be a non-zero multiple of 128M",
+                self.id
+            );
+        }
+        if self.node >= MAX_NODES {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "node of pc-dimm".to_string(),
+                0,
+                true,
+                MAX_NODES as u64,
+                false,
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Get the config of pc-dimm, the memory backend is taken and can't be used again.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the pc-dimm.
+    /// * `memdev` - Id of the memory backend object.
+    /// * `node` - Guest numa node of the pc-dimm, default is 0.
+    pub fn get_pc_dimm_config(
+        &mut self,
+        id: &str,
+        memdev: &str,
+        node: Option<u32>,
+    ) -> Result<PcDimmConfig> {
+        let mem_cfg = self
+            .object
+            .mem_object
+            .get(memdev)
+            .cloned()
+            .with_context(|| format!("Object for memory-backend {} not found", memdev))?;
+        let dimm_cfg = PcDimmConfig {
+            id: id.to_string(),
+            mem_cfg,
+            node: node.unwrap_or(0),
+        };
+        dimm_cfg.check()?;
+        self.object.mem_object.remove(memdev);
+        Ok(dimm_cfg)
+    }
+}
+
+pub fn parse_pc_dimm(vm_config: &mut VmConfig, dimm_config: &str) -> Result<PcDimmConfig> {
+    let mut cmd_parser = CmdParser::new("pc-dimm");
+    cmd_parser.push("").push("id").push("memdev").push("node");
+    cmd_parser.parse(dimm_config)?;
+
+    let id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "pc-dimm".to_string()))?;
+    let memdev = cmd_parser.get_value::<String>("memdev")?.with_context(|| {
+        ConfigError::FieldIsMissing("memdev".to_string(), "pc-dimm".to_string())
+    })?;
+    let node = cmd_parser.get_value::<u32>("node")?;
+    vm_config.get_pc_dimm_config(&id, &memdev, node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pc_dimm_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem0,size=1G")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem1,size=100M")
+            .is_ok());
+        let config = parse_pc_dimm(&mut vm_config, "pc-dimm,id=dimm0,memdev=mem0,node=1").unwrap();
+        assert_eq!(config.id, "dimm0");
+        assert_eq!(config.mem_cfg.size, 1 << 30);
+        assert_eq!(config.node, 1);
+        // The memory backend can only be used by one device.
+        assert!(parse_pc_dimm(&mut vm_config, "pc-dimm,id=dimm1,memdev=mem0").is_err());
+        // The size is not aligned to 128M.
+        assert!(parse_pc_dimm(&mut vm_config, "pc-dimm,id=dimm1,memdev=mem1").is_err());
+        assert!(parse_pc_dimm(&mut vm_config, "pc-dimm,id=dimm1").is_err());
+    }
+}
@@ -131,6 +131,58 @@ pub fn get_multi_function(pci_cfg: &str) -> Result<bool> {
     Ok(false)
 }
 
+/// The interfaces exposed by virtio pci device to the driver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VirtioPciMode {
+    /// Only the modern interface (virtio 1.0) is exposed.
+    #[default]
+    Modern,
+    /// Both the legacy interface (virtio 0.9.5) and the modern interface are exposed.
+    Transitional,
+    /// Only the legacy interface is exposed.
+    Legacy,
+}
+
+impl VirtioPciMode {
+    pub fn legacy_enabled(&self) -> bool {
+        *self != VirtioPciMode::Modern
+    }
+
+    pub fn modern_enabled(&self) -> bool {
+        *self != VirtioPciMode::Legacy
+    }
+}
+
+pub fn get_virtio_pci_mode(pci_cfg: &str) -> Result<VirtioPciMode> {
+    let mut cmd_parser = CmdParser::new("virtio-pci");
+    cmd_parser
+        .push("")
+        .push("disable-legacy")
+        .push("disable-modern");
+    cmd_parser.get_parameters(pci_cfg)?;
+
+    let disable_legacy = cmd_parser
+        .get_value::<ExBool>("disable-legacy")
+        .with_context(|| "Failed to get disable-legacy parameter, please set on (default) or off.")?
+        .map_or(true, |v| v.inner);
+    let disable_modern = cmd_parser
+        .get_value::<ExBool>("disable-modern")
+        .with_context(|| "Failed to get disable-modern parameter, please set on or off (default).")?
+        .map_or(false, |v| v.inner);
+
+    let mode = match (disable_legacy, disable_modern) {
+        (true, false) => VirtioPciMode::Modern,
+        (false, false) => VirtioPciMode::Transitional,
+        (false, true) => VirtioPciMode::Legacy,
+        (true, true) => bail!("disable-legacy and disable-modern can't be both on"),
+    };
+    if cfg!(not(target_arch = "x86_64")) && mode.legacy_enabled() {
+        bail!("The legacy interface of virtio pci device is only supported on x86_64");
+    }
+
+    Ok(mode)
+}
+
 pub fn parse_root_port(rootport_cfg: &str) -> Result<RootPortConfig> {
     let mut cmd_parser = CmdParser::new("pcie-root-port");
     cmd_parser
@@ -179,6 +231,11 @@ pub fn pci_args_check(cmd_parser: &CmdParser) -> Result<()> {
         if cmd_parser.get_value::<ExBool>("multifunction")?.is_some() {
             bail!("virtio mmio device does not support multifunction arguments");
         }
+        if cmd_parser.get_value::<ExBool>("disable-legacy")?.is_some()
+            || cmd_parser.get_value::<ExBool>("disable-modern")?.is_some()
+        {
+            bail!("virtio mmio device does not support disable-legacy or disable-modern arguments");
+        }
     }
     Ok(())
 }
@@ -265,4 +322,27 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn test_get_virtio_pci_mode() {
+        let cfg = "virtio-net-pci,netdev=net0,bus=pcie.0,addr=0x2";
+        assert_eq!(get_virtio_pci_mode(cfg).unwrap(), VirtioPciMode::Modern);
+        assert!(
+            get_virtio_pci_mode(&format!("{},disable-legacy=on,disable-modern=on", cfg)).is_err()
+        );
+        assert!(get_virtio_pci_mode(&format!("{},disable-legacy=close", cfg)).is_err());
+
+        let transitional = get_virtio_pci_mode(&format!("{},disable-legacy=off", cfg));
+        let legacy = get_virtio_pci_mode(&format!("{},disable-legacy=off,disable-modern=on", cfg));
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert_eq!(transitional.unwrap(), VirtioPciMode::Transitional);
+            assert_eq!(legacy.unwrap(), VirtioPciMode::Legacy);
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            assert!(transitional.is_err());
+            assert!(legacy.is_err());
+        }
+    }
 }
@@ -0,0 +1,128 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::pci_args_check;
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, ConfigError, VmConfig};
+
+/// Config structure for virtio-pmem.
+#[derive(Debug, Clone, Default)]
+pub struct PmemConfig {
+    pub id: String,
+    /// Path of the host file which backs the persistent memory.
+    pub mem_path: String,
+    /// Size of the persistent memory.
+    pub size: u64,
+}
+
+impl ConfigCheck for PmemConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "pmem id")?;
+        if self.size == 0 {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "size of pmem".to_string(),
+                0,
+                false,
+                u64::MAX,
+                true,
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_pmem(vm_config: &mut VmConfig, pmem_config: &str) -> Result<PmemConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-pmem");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("memdev");
+    cmd_parser.parse(pmem_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let memdev = cmd_parser.get_value::<String>("memdev")?.with_context(|| {
+        ConfigError::FieldIsMissing("memdev".to_string(), "virtio-pmem".to_string())
+    })?;
+    let mem_cfg = vm_config
+        .object
+        .mem_object
+        .remove(&memdev)
+        .with_context(|| format!("Object for memory-backend-file {} not found", memdev))?;
+    let mem_path = mem_cfg
+        .mem_path
+        .with_context(|| format!("Memory backend {} of pmem is not backed by file", memdev))?;
+    if !mem_cfg.share {
+        bail!("Memory backend {} of pmem must be shared", memdev);
+    }
+
+    let pmem_cfg = PmemConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+        mem_path,
+        size: mem_cfg.size,
+    };
+    pmem_cfg.check()?;
+    Ok(pmem_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pmem_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-file,id=mem0,size=1G,mem-path=/tmp/pmem0,share=on")
+            .is_ok());
+        let config = parse_pmem(
+            &mut vm_config,
+            "virtio-pmem-pci,id=pmem0,memdev=mem0,bus=pcie.0,addr=0x6",
+        )
+        .unwrap();
+        assert_eq!(config.id, "pmem0");
+        assert_eq!(config.mem_path, "/tmp/pmem0");
+        assert_eq!(config.size, 1 << 30);
+        // The memory backend can only be used by one device.
+        assert!(parse_pmem(
+            &mut vm_config,
+            "virtio-pmem-pci,id=pmem0,memdev=mem0,bus=pcie.0,addr=0x6"
+        )
+        .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-file,id=mem0,size=1G,mem-path=/tmp/pmem0")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem1,size=1G,share=on")
+            .is_ok());
+        assert!(parse_pmem(
+            &mut vm_config,
+            "virtio-pmem-pci,id=pmem0,memdev=mem0,bus=pcie.0,addr=0x6"
+        )
+        .is_err());
+        assert!(parse_pmem(
+            &mut vm_config,
+            "virtio-pmem-pci,id=pmem1,memdev=mem1,bus=pcie.0,addr=0x7"
+        )
+        .is_err());
+        assert!(parse_pmem(
+            &mut vm_config,
+            "virtio-pmem-pci,id=pmem2,bus=pcie.0,addr=0x8"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{BufReader, Write};
+
+use anyhow::{Context, Result};
+
+use super::VmConfig;
+
+impl VmConfig {
+    /// Load `VmConfig` from the json profile, which is dumped by `-dump-config`
+    /// or `query-vm-config`.
+    pub fn from_profile(path: &str) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open config file {}", path))?;
+        let vm_config = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to parse config file {}", path))?;
+        Ok(vm_config)
+    }
+
+    /// Dump `VmConfig` to the json profile.
+    pub fn dump_profile(&self, path: &str) -> Result<()> {
+        let profile = serde_json::to_string_pretty(self)?;
+        let mut file =
+            File::create(path).with_context(|| format!("Failed to create config file {}", path))?;
+        file.write_all(profile.as_bytes())
+            .with_context(|| format!("Failed to write config file {}", path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vm_config_profile() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_name("vm1").unwrap();
+        vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .unwrap();
+        vm_config
+            .add_device("virtio-blk-pci,drive=rootfs,id=blk0,bus=pcie.0,addr=0x1")
+            .unwrap();
+
+        let path = std::env::temp_dir().join("stratovirt_test_profile.json");
+        let path = path.to_str().unwrap();
+        vm_config.dump_profile(path).unwrap();
+        let loaded = VmConfig::from_profile(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.guest_name, "vm1");
+        assert_eq!(loaded.devices, vm_config.devices);
+        let drive = loaded.drives.get("rootfs").unwrap();
+        assert_eq!(drive.path_on_host, "/path/to/rootfs");
+        assert!(drive.direct);
+        assert!(VmConfig::from_profile("/path/not/exist.json").is_err());
+    }
+}
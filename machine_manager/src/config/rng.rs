@@ -74,6 +74,8 @@ pub fn parse_rng_dev(vm_config: &mut VmConfig, rng_config: &str) -> Result<RngCo
         .push("bus")
         .push("addr")
         .push("multifunction")
+        .push("disable-legacy")
+        .push("disable-modern")
         .push("max-bytes")
         .push("period")
         .push("rng");
@@ -86,22 +88,10 @@ pub fn parse_rng_dev(vm_config: &mut VmConfig, rng_config: &str) -> Result<RngCo
         .with_context(|| ConfigError::FieldIsMissing("rng".to_string(), "rng".to_string()))?;
 
     rng_cfg.id = cmd_parser.get_value::<String>("id")?.unwrap_or_default();
-
-    if let Some(max) = cmd_parser.get_value::<u64>("max-bytes")? {
-        if let Some(peri) = cmd_parser.get_value::<u64>("period")? {
-            let mul = max
-                .checked_mul(1000)
-                .with_context(|| format!("Illegal max-bytes arguments: {:?}", max))?;
-            let div = mul
-                .checked_div(peri)
-                .with_context(|| format!("Illegal period arguments: {:?}", peri))?;
-            rng_cfg.bytes_per_sec = Some(div);
-        } else {
-            bail!("Argument 'period' is missing");
-        }
-    } else if cmd_parser.get_value::<u64>("period")?.is_some() {
-        bail!("Argument 'max-bytes' is missing");
-    }
+    rng_cfg.bytes_per_sec = rng_bytes_per_sec(
+        cmd_parser.get_value::<u64>("max-bytes")?,
+        cmd_parser.get_value::<u64>("period")?,
+    )?;
 
     rng_cfg.random_file = vm_config
         .object
@@ -114,6 +104,36 @@ pub fn parse_rng_dev(vm_config: &mut VmConfig, rng_config: &str) -> Result<RngCo
     Ok(rng_cfg)
 }
 
+/// Compute the rate limit of rng device from `max-bytes` in each `period` milliseconds,
+/// `None` if the rate is not limited.
+pub fn rng_bytes_per_sec(max_bytes: Option<u64>, period: Option<u64>) -> Result<Option<u64>> {
+    match (max_bytes, period) {
+        (Some(max), Some(peri)) => {
+            let mul = max
+                .checked_mul(1000)
+                .with_context(|| format!("Illegal max-bytes arguments: {:?}", max))?;
+            let div = mul
+                .checked_div(peri)
+                .with_context(|| format!("Illegal period arguments: {:?}", peri))?;
+            Ok(Some(div))
+        }
+        (Some(_), None) => bail!("Argument 'period' is missing"),
+        (None, Some(_)) => bail!("Argument 'max-bytes' is missing"),
+        (None, None) => Ok(None),
+    }
+}
+
+impl VmConfig {
+    /// Add rng-random object config, used by both cmdline and qmp.
+    pub fn add_rng_object_with_config(&mut self, rng_obj: RngObjConfig) -> Result<()> {
+        if self.object.rng_object.contains_key(&rng_obj.id) {
+            bail!("Object: {} has been added", rng_obj.id);
+        }
+        self.object.rng_object.insert(rng_obj.id.clone(), rng_obj);
+        Ok(())
+    }
+}
+
 pub fn parse_rng_obj(object_args: &str) -> Result<RngObjConfig> {
     let mut cmd_params = CmdParser::new("rng-object");
     cmd_params.push("").push("id").push("filename");
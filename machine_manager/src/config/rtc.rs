@@ -0,0 +1,258 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CmdParser, VmConfig};
+use util::time::mktime64;
+
+/// Start time of the RTC.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RtcBase {
+    /// The RTC starts from the UTC time of host.
+    #[default]
+    Utc,
+    /// The RTC starts from the local time of host, which is required by Windows guest.
+    Localtime,
+    /// The RTC starts from the given time in seconds since 1970-01-01 00:00:00.
+    Datetime(i64),
+}
+
+impl RtcBase {
+    /// The host time which the offset of RTC is relative to, in seconds since
+    /// 1970-01-01 00:00:00.
+    pub fn host_time(&self) -> i64 {
+        // Since 1970-01-01 00:00:00, it never cause overflow.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time wrong")
+            .as_secs() as i64;
+        match self {
+            RtcBase::Utc | RtcBase::Datetime(_) => now,
+            RtcBase::Localtime => {
+                // SAFETY: `libc::tm` is plain old data, and all zero is valid for it.
+                let mut local_tm: libc::tm = unsafe { std::mem::zeroed() };
+                // SAFETY: `libc::localtime_r` just convert calendar time to broken-down
+                // local time, and saved to `local_tm`.
+                unsafe { libc::localtime_r(&now, &mut local_tm) };
+                now + local_tm.tm_gmtoff
+            }
+        }
+    }
+
+    /// Get the start time of RTC in seconds since 1970-01-01 00:00:00.
+    pub fn start_time(&self) -> i64 {
+        match self {
+            RtcBase::Datetime(time) => *time,
+            _ => self.host_time(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            RtcBase::Utc => "utc".to_string(),
+            RtcBase::Localtime => "localtime".to_string(),
+            RtcBase::Datetime(time) => {
+                // SAFETY: `libc::tm` is plain old data, and all zero is valid for it.
+                let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+                // SAFETY: `libc::gmtime_r` just convert calendar time to broken-down
+                // format, and saved to `tm`.
+                unsafe { libc::gmtime_r(time, &mut tm) };
+                format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    tm.tm_year + 1900,
+                    tm.tm_mon + 1,
+                    tm.tm_mday,
+                    tm.tm_hour,
+                    tm.tm_min,
+                    tm.tm_sec
+                )
+            }
+        }
+    }
+}
+
+/// Parse the datetime like `2006-06-17T16:01:21` or `2006-06-17`.
+fn parse_datetime(s: &str) -> Result<i64> {
+    let (date, time) = s.split_once('T').unwrap_or((s, "00:00:00"));
+    let parse = |field: &str, len: usize| -> Result<Vec<u64>> {
+        let values = field
+            .split(['-', ':'])
+            .map(|v| v.parse::<u64>())
+            .collect::<std::result::Result<Vec<u64>, _>>()
+            .map_err(|_| anyhow!("Invalid rtc base {}", s))?;
+        if values.len() != len {
+            bail!("Invalid rtc base {}", s);
+        }
+        Ok(values)
+    };
+    let date = parse(date, 3)?;
+    let time = parse(time, 3)?;
+    if date[0] < 1970
+        || !(1..=12).contains(&date[1])
+        || !(1..=31).contains(&date[2])
+        || time[0] > 23
+        || time[1] > 59
+        || time[2] > 59
+    {
+        bail!("Invalid rtc base {}, it's out of range", s);
+    }
+    Ok(mktime64(date[0], date[1], date[2], time[0], time[1], time[2]) as i64)
+}
+
+impl FromStr for RtcBase {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "utc" => Ok(RtcBase::Utc),
+            "localtime" => Ok(RtcBase::Localtime),
+            _ if s.starts_with(|c: char| c.is_ascii_digit()) => {
+                Ok(RtcBase::Datetime(parse_datetime(s)?))
+            }
+            _ => Err(anyhow!(
+                "Unknown rtc base {}, must be one of utc, localtime or a datetime like 2006-06-17T16:01:21",
+                s
+            )),
+        }
+    }
+}
+
+/// Clock which the RTC runs with.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RtcClock {
+    /// The RTC runs with the host clock, even if the VM is paused.
+    #[default]
+    Host,
+    /// The RTC runs only when the VM is running.
+    Vm,
+}
+
+impl RtcClock {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RtcClock::Host => "host",
+            RtcClock::Vm => "vm",
+        }
+    }
+}
+
+impl FromStr for RtcClock {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(RtcClock::Host),
+            "vm" => Ok(RtcClock::Vm),
+            _ => Err(anyhow!(
+                "Unknown rtc clock {}, must be one of host or vm",
+                s
+            )),
+        }
+    }
+}
+
+/// Policy of the periodic interrupts which are missed by guest.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RtcDriftFix {
+    /// The missed interrupts are dropped.
+    #[default]
+    None,
+    /// The missed interrupts are reinjected, so that the guest which counts the
+    /// interrupts to keep time doesn't drift.
+    Slew,
+}
+
+impl FromStr for RtcDriftFix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RtcDriftFix::None),
+            "slew" => Ok(RtcDriftFix::Slew),
+            _ => Err(anyhow!(
+                "Unknown rtc driftfix {}, must be one of none or slew",
+                s
+            )),
+        }
+    }
+}
+
+/// Config of the RTC.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct RtcConfig {
+    pub base: RtcBase,
+    pub clock: RtcClock,
+    pub driftfix: RtcDriftFix,
+}
+
+impl VmConfig {
+    /// Set the RTC config, e.g. `base=localtime,driftfix=slew`.
+    pub fn add_rtc(&mut self, rtc_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("rtc");
+        cmd_parser.push("base").push("clock").push("driftfix");
+        cmd_parser.parse(rtc_config)?;
+
+        if let Some(base) = cmd_parser.get_value::<RtcBase>("base")? {
+            self.rtc.base = base;
+        }
+        if let Some(clock) = cmd_parser.get_value::<RtcClock>("clock")? {
+            self.rtc.clock = clock;
+        }
+        if let Some(driftfix) = cmd_parser.get_value::<RtcDriftFix>("driftfix")? {
+            self.rtc.driftfix = driftfix;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_rtc() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.rtc.base, RtcBase::Utc);
+        assert_eq!(vm_config.rtc.driftfix, RtcDriftFix::None);
+
+        assert!(vm_config.add_rtc("base=localtime").is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::Localtime);
+        assert_eq!(vm_config.rtc.driftfix, RtcDriftFix::None);
+
+        assert!(vm_config.add_rtc("base=utc,driftfix=slew").is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::Utc);
+        assert_eq!(vm_config.rtc.driftfix, RtcDriftFix::Slew);
+
+        assert!(vm_config.add_rtc("base=gmt").is_err());
+        assert!(vm_config.add_rtc("driftfix=catchup").is_err());
+        assert!(vm_config.add_rtc("clock=rt").is_err());
+
+        assert!(vm_config
+            .add_rtc("base=2006-06-17T16:01:21,clock=vm")
+            .is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::Datetime(1150560081));
+        assert_eq!(vm_config.rtc.base.name(), "2006-06-17T16:01:21");
+        assert_eq!(vm_config.rtc.base.start_time(), 1150560081);
+        assert_eq!(vm_config.rtc.clock, RtcClock::Vm);
+        assert!(vm_config.add_rtc("base=2006-06-17").is_ok());
+        assert_eq!(vm_config.rtc.base, RtcBase::Datetime(1150502400));
+        assert!(vm_config.add_rtc("base=1969-12-31").is_err());
+        assert!(vm_config.add_rtc("base=2006-13-17").is_err());
+        assert!(vm_config.add_rtc("base=2006-06-17T25:00:00").is_err());
+        assert!(vm_config.add_rtc("base=2006-06").is_err());
+    }
+}
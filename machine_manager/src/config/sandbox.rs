@@ -0,0 +1,135 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    check_arg_too_long, CmdParser, ConfigCheck, ConfigError, ExBool, VmConfig, MAX_PATH_LENGTH,
+};
+
+/// Config of the sandbox which the VM enters after all devices are realized.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SandboxConfig {
+    /// User which the VM runs as after the privileges are dropped.
+    pub user: Option<String>,
+    /// Group which the VM runs as, default to the primary group of `user`.
+    pub group: Option<String>,
+    /// Empty directory which is changed to the root directory.
+    pub chroot: Option<String>,
+    /// Whether to move into a new network namespace.
+    pub unshare_net: bool,
+    /// Whether to move into a new mount namespace.
+    pub unshare_mount: bool,
+}
+
+impl SandboxConfig {
+    /// Whether the sandbox is configured.
+    pub fn enabled(&self) -> bool {
+        self.user.is_some()
+            || self.group.is_some()
+            || self.chroot.is_some()
+            || self.unshare_net
+            || self.unshare_mount
+    }
+}
+
+impl ConfigCheck for SandboxConfig {
+    fn check(&self) -> Result<()> {
+        if let Some(user) = &self.user {
+            check_arg_too_long(user, "sandbox user")?;
+        }
+        if let Some(group) = &self.group {
+            check_arg_too_long(group, "sandbox group")?;
+        }
+        if let Some(chroot) = &self.chroot {
+            if chroot.len() > MAX_PATH_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "sandbox chroot path".to_string(),
+                    MAX_PATH_LENGTH,
+                )));
+            }
+            if !Path::new(chroot).is_dir() {
+                bail!("Sandbox chroot {} is not a directory", chroot);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Set the sandbox config, e.g. `user=nobody,chroot=/var/empty,unshare-net=on`.
+    pub fn add_sandbox(&mut self, sandbox_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("run-with");
+        cmd_parser
+            .push("user")
+            .push("group")
+            .push("chroot")
+            .push("unshare-net")
+            .push("unshare-mount");
+        cmd_parser.parse(sandbox_config)?;
+
+        let mut sandbox = SandboxConfig {
+            user: cmd_parser.get_value::<String>("user")?,
+            group: cmd_parser.get_value::<String>("group")?,
+            chroot: cmd_parser.get_value::<String>("chroot")?,
+            ..Default::default()
+        };
+        if let Some(unshare_net) = cmd_parser.get_value::<ExBool>("unshare-net")? {
+            sandbox.unshare_net = unshare_net.into();
+        }
+        if let Some(unshare_mount) = cmd_parser.get_value::<ExBool>("unshare-mount")? {
+            sandbox.unshare_mount = unshare_mount.into();
+        }
+        sandbox.check()?;
+
+        self.sandbox = sandbox;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_sandbox() {
+        let mut vm_config = VmConfig::default();
+        assert!(!vm_config.sandbox.enabled());
+
+        let chroot = std::env::temp_dir();
+        let sandbox_cfg = format!(
+            "user=nobody,group=nogroup,chroot={},unshare-net=on,unshare-mount=on",
+            chroot.to_str().unwrap()
+        );
+        assert!(vm_config.add_sandbox(&sandbox_cfg).is_ok());
+        assert!(vm_config.sandbox.enabled());
+        assert_eq!(vm_config.sandbox.user.as_deref(), Some("nobody"));
+        assert_eq!(vm_config.sandbox.group.as_deref(), Some("nogroup"));
+        assert_eq!(vm_config.sandbox.chroot.as_deref(), chroot.to_str());
+        assert!(vm_config.sandbox.unshare_net);
+        assert!(vm_config.sandbox.unshare_mount);
+
+        assert!(vm_config.add_sandbox("unshare-net=on").is_ok());
+        assert_eq!(vm_config.sandbox.user, None);
+        assert!(vm_config.sandbox.unshare_net);
+        assert!(!vm_config.sandbox.unshare_mount);
+
+        assert!(vm_config
+            .add_sandbox("chroot=/path/to/nonexistent/dir")
+            .is_err());
+        assert!(vm_config.add_sandbox("unshare-pid=on").is_err());
+        assert!(vm_config.add_sandbox("unshare-net=maybe").is_err());
+    }
+}
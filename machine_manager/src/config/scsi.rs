@@ -12,9 +12,10 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 
-use super::{error::ConfigError, pci_args_check, DiskFormat};
+use super::{error::ConfigError, pci_args_check, DiskFormat, IoTimeout};
 use crate::config::{
-    check_arg_too_long, CmdParser, ConfigCheck, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
+    check_arg_too_long, CmdParser, ConfigCheck, UnsignedInteger, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
+    MAX_VIRTIO_QUEUE,
 };
 use util::aio::AioEngine;
 
@@ -29,6 +30,13 @@ pub const VIRTIO_SCSI_MAX_LUN: u16 = 16383;
 /// So, max lun id supported is 255 (2^8 - 1).
 const SUPPORT_SCSI_MAX_LUN: u16 = 255;
 
+/// Medium rotation rate in Block Device Characteristics VPD page (SBC-4).
+/// 1 means non-rotating medium (e.g. solid state), and the nominal rotation
+/// rate in rpm is in [0x401, 0xfffe].
+pub const SCSI_ROTATION_RATE_NON_ROTATING: u16 = 1;
+const SCSI_ROTATION_RATE_MIN_RPM: u16 = 0x401;
+const SCSI_ROTATION_RATE_MAX_RPM: u16 = 0xfffe;
+
 // Seg_max = queue_size - 2. So, size of each virtqueue for virtio-scsi should be larger than 2.
 const MIN_QUEUE_SIZE_SCSI: u16 = 2;
 // Max size of each virtqueue for virtio-scsi.
@@ -108,6 +116,8 @@ pub fn parse_scsi_controller(
         .push("bus")
         .push("addr")
         .push("multifunction")
+        .push("disable-legacy")
+        .push("disable-modern")
         .push("iothread")
         .push("num-queues")
         .push("queue-size");
@@ -148,6 +158,10 @@ pub struct ScsiDevConfig {
     pub path_on_host: String,
     /// Serial number of the scsi device.
     pub serial: Option<String>,
+    /// World wide name of the scsi device, reported as NAA designator.
+    pub wwn: Option<u64>,
+    /// Medium rotation rate of the scsi device, 0 means not reported.
+    pub rotation_rate: u16,
     /// Scsi controller which the scsi device attaches to.
     pub cntlr: String,
     /// Scsi device can not do write operation.
@@ -165,6 +179,10 @@ pub struct ScsiDevConfig {
     pub format: DiskFormat,
     pub l2_cache_size: Option<u64>,
     pub refcount_cache_size: Option<u64>,
+    /// Secret data used to unlock the luks image.
+    pub key_secret: Option<String>,
+    pub copy_on_read: bool,
+    pub io_timeout: Option<IoTimeout>,
 }
 
 impl Default for ScsiDevConfig {
@@ -173,6 +191,8 @@ impl Default for ScsiDevConfig {
             id: "".to_string(),
             path_on_host: "".to_string(),
             serial: None,
+            wwn: None,
+            rotation_rate: 0,
             cntlr: "".to_string(),
             read_only: false,
             direct: true,
@@ -184,6 +204,9 @@ impl Default for ScsiDevConfig {
             format: DiskFormat::Raw,
             l2_cache_size: None,
             refcount_cache_size: None,
+            key_secret: None,
+            copy_on_read: false,
+            io_timeout: None,
         }
     }
 }
@@ -197,6 +220,8 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
         .push("scsi-id")
         .push("lun")
         .push("serial")
+        .push("wwn")
+        .push("rotation_rate")
         .push("bootindex")
         .push("drive");
 
@@ -216,6 +241,24 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
         scsi_dev_cfg.serial = Some(serial);
     }
 
+    if let Some(wwn) = cmd_parser.get_value::<UnsignedInteger>("wwn")? {
+        scsi_dev_cfg.wwn = Some(wwn.0 as u64);
+    }
+
+    if let Some(rotation_rate) = cmd_parser.get_value::<u16>("rotation_rate")? {
+        if rotation_rate > SCSI_ROTATION_RATE_NON_ROTATING
+            && !(SCSI_ROTATION_RATE_MIN_RPM..=SCSI_ROTATION_RATE_MAX_RPM).contains(&rotation_rate)
+        {
+            bail!(
+                "Invalid rotation_rate {}, it should be 0, 1 or in [{}, {}]",
+                rotation_rate,
+                SCSI_ROTATION_RATE_MIN_RPM,
+                SCSI_ROTATION_RATE_MAX_RPM
+            );
+        }
+        scsi_dev_cfg.rotation_rate = rotation_rate;
+    }
+
     scsi_dev_cfg.id = cmd_parser.get_value::<String>("id")?.with_context(|| {
         ConfigError::FieldIsMissing("id".to_string(), "scsi device".to_string())
     })?;
@@ -274,6 +317,44 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
     scsi_dev_cfg.format = drive_arg.format;
     scsi_dev_cfg.l2_cache_size = drive_arg.l2_cache_size;
     scsi_dev_cfg.refcount_cache_size = drive_arg.refcount_cache_size;
+    scsi_dev_cfg.copy_on_read = drive_arg.copy_on_read;
+    scsi_dev_cfg.io_timeout = drive_arg.io_timeout;
+    if let Some(secret) = drive_arg.key_secret.as_ref() {
+        scsi_dev_cfg.key_secret = Some(vm_config.get_secret(secret)?);
+    }
 
     Ok(scsi_dev_cfg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scsi_device_identifiers() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_drive("id=drive-0,file=/path/to/disk,direct=off,aio=off")
+            .unwrap();
+        let dev_cfg = parse_scsi_device(
+            &mut vm_config,
+            "scsi-hd,bus=scsi0.0,drive=drive-0,id=scsi0-0-0-0,serial=abc,wwn=0x5000c50015ea71ac,rotation_rate=1",
+        )
+        .unwrap();
+        assert_eq!(dev_cfg.serial, Some("abc".to_string()));
+        assert_eq!(dev_cfg.wwn, Some(0x5000c50015ea71ac));
+        assert_eq!(dev_cfg.rotation_rate, SCSI_ROTATION_RATE_NON_ROTATING);
+
+        for rotation_rate in ["2", "1024", "65535"] {
+            vm_config
+                .add_drive("id=drive-0,file=/path/to/disk,direct=off,aio=off")
+                .unwrap();
+            let dev_cfg = format!(
+                "scsi-hd,bus=scsi0.0,drive=drive-0,id=scsi0-0-0-0,rotation_rate={}",
+                rotation_rate
+            );
+            assert!(parse_scsi_device(&mut vm_config, &dev_cfg).is_err());
+            vm_config.drives.remove("drive-0");
+        }
+    }
+}
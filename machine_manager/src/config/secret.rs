@@ -0,0 +1,110 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_arg_too_long, CmdParser, ConfigError, VmConfig};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretObjConfig {
+    /// Object Id.
+    pub id: String,
+    /// Secret data, such as the passphrase of encrypted image.
+    pub data: String,
+}
+
+/// Get the secret data from `data` or the content of `file`. Only one of them can be set.
+pub fn get_secret_data(id: &str, data: Option<String>, file: Option<String>) -> Result<String> {
+    match (data, file) {
+        (Some(data), None) => Ok(data),
+        (None, Some(file)) => std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read secret {} from file {}", id, file)),
+        (Some(_), Some(_)) => bail!("Secret {} can't be set by both data and file", id),
+        (None, None) => Err(anyhow!(ConfigError::FieldIsMissing(
+            "data or file".to_string(),
+            "secret".to_string()
+        ))),
+    }
+}
+
+impl VmConfig {
+    pub fn add_secret(&mut self, secret_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("secret");
+        cmd_parser.push("").push("id").push("data").push("file");
+        cmd_parser.parse(secret_config)?;
+
+        let id = cmd_parser
+            .get_value::<String>("id")?
+            .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "secret".to_string()))?;
+        let data = get_secret_data(
+            &id,
+            cmd_parser.get_value::<String>("data")?,
+            cmd_parser.get_value::<String>("file")?,
+        )?;
+
+        self.add_secret_with_config(SecretObjConfig { id, data })
+    }
+
+    /// Add secret object config, used by both cmdline and qmp.
+    pub fn add_secret_with_config(&mut self, secret: SecretObjConfig) -> Result<()> {
+        check_arg_too_long(&secret.id, "secret id")?;
+        if self.object.secret_object.contains_key(&secret.id) {
+            return Err(anyhow!(ConfigError::IdRepeat(
+                "secret".to_string(),
+                secret.id
+            )));
+        }
+        self.object.secret_object.insert(secret.id.clone(), secret);
+
+        Ok(())
+    }
+
+    /// Get the data of secret object by id.
+    pub fn get_secret(&self, id: &str) -> Result<String> {
+        self.object
+            .secret_object
+            .get(id)
+            .map(|secret| secret.data.clone())
+            .with_context(|| format!("Secret {} not found", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_secret() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_object("secret,id=sec0,data=passw0rd").is_ok());
+        assert_eq!(vm_config.get_secret("sec0").unwrap(), "passw0rd");
+        assert!(vm_config.add_object("secret,id=sec0,data=other").is_err());
+        assert!(vm_config.get_secret("sec1").is_err());
+
+        let path = "/tmp/test_add_secret.txt";
+        std::fs::write(path, "s3cret").unwrap();
+        assert!(vm_config
+            .add_object(&format!("secret,id=sec1,file={}", path))
+            .is_ok());
+        assert_eq!(vm_config.get_secret("sec1").unwrap(), "s3cret");
+        assert!(vm_config
+            .add_object(&format!("secret,id=sec2,data=a,file={}", path))
+            .is_err());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(vm_config.add_object("secret,id=sec3").is_err());
+        assert!(vm_config
+            .add_object("secret,id=sec3,file=/tmp/not_exist_secret.txt")
+            .is_err());
+    }
+}
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::fmt;
 use std::str::FromStr;
 
 use anyhow::{bail, Context, Result};
@@ -118,11 +119,23 @@ fn check_valid_uuid(uuid: &str) -> bool {
 
 /// Convert an ASCII string to a 128-bit buffer.
 /// format: 33DB4D5E-1FF7-401C-9657-7441C03DD766
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Uuid {
     pub name: Vec<u8>,
 }
 
+impl Uuid {
+    /// Get the UUID in big-endian byte order as defined in RFC 4122, which is
+    /// different from the encoding of SMBIOS in the first three fields.
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.name.clone();
+        bytes[0..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        bytes
+    }
+}
+
 impl FromStr for Uuid {
     type Err = ();
 
@@ -149,6 +162,36 @@ impl FromStr for Uuid {
     }
 }
 
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = &self.name;
+        if n.len() != 16 {
+            return Err(fmt::Error);
+        }
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-\
+             {:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            n[3],
+            n[2],
+            n[1],
+            n[0],
+            n[5],
+            n[4],
+            n[7],
+            n[6],
+            n[8],
+            n[9],
+            n[10],
+            n[11],
+            n[12],
+            n[13],
+            n[14],
+            n[15]
+        )
+    }
+}
+
 impl VmConfig {
     /// # Arguments
     ///
@@ -203,6 +246,18 @@ impl VmConfig {
         self.smbios.type1.sku = cmd_parser.get_value::<String>("sku")?;
         self.smbios.type1.family = cmd_parser.get_value::<String>("family")?;
         self.smbios.type1.uuid = cmd_parser.get_value::<Uuid>("uuid")?;
+        if let Some(uuid) = &self.smbios.type1.uuid {
+            match &self.uuid {
+                Some(vm_uuid) if vm_uuid != uuid => {
+                    bail!(
+                        "smbios type1 uuid {} mismatches the uuid of VM {}",
+                        uuid,
+                        vm_uuid
+                    );
+                }
+                _ => self.uuid = Some(uuid.clone()),
+            }
+        }
         self.smbios.type1.added = true;
 
         Ok(())
@@ -396,5 +451,26 @@ mod test {
                 0xD7, 0x66
             ]
         );
+        assert_eq!(
+            uuid.to_be_bytes(),
+            &[
+                0x33, 0xDB, 0x4D, 0x5E, 0x1F, 0xF7, 0x40, 0x1C, 0x96, 0x57, 0x74, 0x41, 0xC0, 0x3D,
+                0xD7, 0x66
+            ]
+        );
+        assert_eq!(uuid.to_string(), "33db4d5e-1ff7-401c-9657-7441c03dd766");
+
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_uuid("33DB4D5E-1FF7-401C-9657-7441C03DD766")
+            .unwrap();
+        assert!(vm_config
+            .add_smbios("type=1,uuid=33DB4D5E-1FF7-401C-9657-7441C03DD767")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_smbios("type=1,uuid=33DB4D5E-1FF7-401C-9657-7441C03DD766")
+            .unwrap();
+        assert_eq!(vm_config.uuid, Some(uuid));
     }
 }
@@ -0,0 +1,119 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::pci_args_check;
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck};
+
+/// Default PCM device name of the host audio backend.
+const DEFAULT_SOUND_PCM: &str = "default";
+
+/// Host audio backend of virtio-sound device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SoundBackendType {
+    /// Discard the playback data and capture silence, at the speed of real time.
+    None,
+    #[cfg(feature = "virtio_snd_alsa")]
+    Alsa,
+}
+
+impl FromStr for SoundBackendType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SoundBackendType::None),
+            #[cfg(feature = "virtio_snd_alsa")]
+            "alsa" => Ok(SoundBackendType::Alsa),
+            _ => Err(anyhow!("Unknown audio backend {}", s)),
+        }
+    }
+}
+
+/// Config structure for virtio-sound.
+#[derive(Debug, Clone)]
+pub struct SoundConfig {
+    pub id: String,
+    pub backend: SoundBackendType,
+    /// Host PCM device used by the backend for both playback and capture.
+    pub pcm: String,
+}
+
+impl Default for SoundConfig {
+    fn default() -> Self {
+        SoundConfig {
+            id: "".to_string(),
+            backend: SoundBackendType::None,
+            pcm: DEFAULT_SOUND_PCM.to_string(),
+        }
+    }
+}
+
+impl ConfigCheck for SoundConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "sound id")?;
+        check_arg_too_long(&self.pcm, "sound pcm")
+    }
+}
+
+pub fn parse_sound(sound_config: &str) -> Result<SoundConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-sound");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("audiodev")
+        .push("pcm");
+    cmd_parser.parse(sound_config)?;
+    pci_args_check(&cmd_parser)?;
+
+    let mut sound_cfg = SoundConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+        ..Default::default()
+    };
+    if let Some(backend) = cmd_parser.get_value::<SoundBackendType>("audiodev")? {
+        sound_cfg.backend = backend;
+    }
+    if let Some(pcm) = cmd_parser.get_value::<String>("pcm")? {
+        sound_cfg.pcm = pcm;
+    }
+    sound_cfg.check()?;
+    Ok(sound_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sound_config_cmdline_parser() {
+        let config = parse_sound("virtio-sound-pci,id=snd0,bus=pcie.0,addr=0x7").unwrap();
+        assert_eq!(config.id, "snd0");
+        assert_eq!(config.backend, SoundBackendType::None);
+        assert_eq!(config.pcm, "default");
+
+        let config =
+            parse_sound("virtio-sound-pci,id=snd0,audiodev=none,pcm=hw:0,bus=pcie.0,addr=0x7")
+                .unwrap();
+        assert_eq!(config.backend, SoundBackendType::None);
+        assert_eq!(config.pcm, "hw:0");
+
+        assert!(parse_sound("virtio-sound-pci,id=snd0,audiodev=oss,bus=pcie.0,addr=0x7").is_err());
+        assert!(parse_sound("virtio-sound-pci,id=snd0,bus=pcie.0,addr=0x7,queues=2").is_err());
+    }
+}
@@ -18,14 +18,17 @@ use super::get_cameradev_by_id;
 #[cfg(feature = "usb_host")]
 use super::UnsignedInteger;
 use crate::config::{
-    check_arg_nonexist, check_arg_too_long, CmdParser, ConfigCheck, ScsiDevConfig, VmConfig,
+    check_arg_nonexist, check_arg_too_long, check_path_too_long, ChardevConfig, ChardevType,
+    CmdParser, ConfigCheck, ScsiDevConfig, VmConfig,
 };
 #[cfg(feature = "usb_camera")]
-use crate::config::{CamBackendType, CameraDevConfig};
-use util::aio::AioEngine;
+use crate::config::{CamBackendType, CameraDevConfig, ExBool};
 
 #[cfg(feature = "usb_host")]
 const USBHOST_ADDR_MAX: u8 = 127;
+const USB_HUB_DEFAULT_PORTS: u8 = 8;
+/// The port number of a hub takes 4 bits in the route string.
+const USB_HUB_MAX_PORTS: u8 = 15;
 
 /// XHCI controller configuration.
 #[derive(Debug)]
@@ -105,11 +108,16 @@ pub fn parse_xhci(conf: &str) -> Result<XhciConfig> {
 #[derive(Debug)]
 pub struct UsbKeyboardConfig {
     pub id: Option<String>,
+    /// The usb hub which the device is attached to.
+    pub bus: Option<String>,
 }
 
 impl UsbKeyboardConfig {
     fn new() -> Self {
-        UsbKeyboardConfig { id: None }
+        UsbKeyboardConfig {
+            id: None,
+            bus: None,
+        }
     }
 }
 
@@ -125,6 +133,7 @@ pub fn parse_usb_keyboard(conf: &str) -> Result<UsbKeyboardConfig> {
     cmd_parser.parse(conf)?;
     let mut dev = UsbKeyboardConfig::new();
     dev.id = cmd_parser.get_value::<String>("id")?;
+    dev.bus = cmd_parser.get_value::<String>("bus")?;
 
     dev.check()?;
     Ok(dev)
@@ -133,11 +142,16 @@ pub fn parse_usb_keyboard(conf: &str) -> Result<UsbKeyboardConfig> {
 #[derive(Debug)]
 pub struct UsbTabletConfig {
     pub id: Option<String>,
+    /// The usb hub which the device is attached to.
+    pub bus: Option<String>,
 }
 
 impl UsbTabletConfig {
     fn new() -> Self {
-        UsbTabletConfig { id: None }
+        UsbTabletConfig {
+            id: None,
+            bus: None,
+        }
     }
 }
 
@@ -153,6 +167,89 @@ pub fn parse_usb_tablet(conf: &str) -> Result<UsbTabletConfig> {
     cmd_parser.parse(conf)?;
     let mut dev = UsbTabletConfig::new();
     dev.id = cmd_parser.get_value::<String>("id")?;
+    dev.bus = cmd_parser.get_value::<String>("bus")?;
+
+    dev.check()?;
+    Ok(dev)
+}
+
+/// USB hub configuration.
+#[derive(Debug)]
+pub struct UsbHubConfig {
+    pub id: Option<String>,
+    /// Number of the downstream ports.
+    pub ports: u8,
+}
+
+impl ConfigCheck for UsbHubConfig {
+    fn check(&self) -> Result<()> {
+        check_id(self.id.clone(), "usb-hub")?;
+        if self.ports == 0 || self.ports > USB_HUB_MAX_PORTS {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "usb hub ports".to_string(),
+                1,
+                true,
+                USB_HUB_MAX_PORTS as u64,
+                true,
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_usb_hub(conf: &str) -> Result<UsbHubConfig> {
+    let mut cmd_parser = CmdParser::new("usb-hub");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("port")
+        .push("ports");
+    cmd_parser.parse(conf)?;
+    let dev = UsbHubConfig {
+        id: cmd_parser.get_value::<String>("id")?,
+        ports: cmd_parser
+            .get_value::<u8>("ports")?
+            .unwrap_or(USB_HUB_DEFAULT_PORTS),
+    };
+
+    dev.check()?;
+    Ok(dev)
+}
+
+/// Config of the USB U2F key which passes through the FIDO token of the host.
+#[derive(Clone, Debug)]
+pub struct UsbU2fConfig {
+    pub id: Option<String>,
+    /// Path of the hidraw node of the host FIDO token, such as `/dev/hidraw0`.
+    pub hidraw: String,
+    /// The usb hub which the device is attached to.
+    pub bus: Option<String>,
+}
+
+impl ConfigCheck for UsbU2fConfig {
+    fn check(&self) -> Result<()> {
+        check_id(self.id.clone(), "usb-u2f")?;
+        check_path_too_long(&self.hidraw, "usb-u2f hidraw")
+    }
+}
+
+pub fn parse_usb_u2f(conf: &str) -> Result<UsbU2fConfig> {
+    let mut cmd_parser = CmdParser::new("usb-u2f");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("port")
+        .push("hidraw");
+    cmd_parser.parse(conf)?;
+    let dev = UsbU2fConfig {
+        id: cmd_parser.get_value::<String>("id")?,
+        hidraw: cmd_parser.get_value::<String>("hidraw")?.with_context(|| {
+            ConfigError::FieldIsMissing("hidraw".to_string(), "usb-u2f".to_string())
+        })?,
+        bus: cmd_parser.get_value::<String>("bus")?,
+    };
 
     dev.check()?;
     Ok(dev)
@@ -165,7 +262,8 @@ pub fn parse_usb_camera(vm_config: &mut VmConfig, conf: &str) -> Result<UsbCamer
         .push("")
         .push("id")
         .push("cameradev")
-        .push("iothread");
+        .push("iothread")
+        .push("convert");
     cmd_parser.parse(conf)?;
 
     let mut dev = UsbCameraConfig::new();
@@ -184,6 +282,9 @@ pub fn parse_usb_camera(vm_config: &mut VmConfig, conf: &str) -> Result<UsbCamer
     dev.path = cameradev.path.clone();
     dev.drive = cameradev;
     dev.iothread = cmd_parser.get_value::<String>("iothread")?;
+    if let Some(convert) = cmd_parser.get_value::<ExBool>("convert")? {
+        dev.convert = convert.into();
+    }
 
     dev.check()?;
     Ok(dev)
@@ -204,6 +305,8 @@ pub struct UsbCameraConfig {
     pub path: Option<String>,
     pub iothread: Option<String>,
     pub drive: CameraDevConfig,
+    /// Offer the frames converted from the formats of the backend.
+    pub convert: bool,
 }
 
 #[cfg(feature = "usb_camera")]
@@ -215,6 +318,7 @@ impl UsbCameraConfig {
             path: None,
             iothread: None,
             drive: CameraDevConfig::new(),
+            convert: false,
         }
     }
 }
@@ -245,6 +349,8 @@ pub struct UsbStorageConfig {
     pub scsi_cfg: ScsiDevConfig,
     /// The backend scsi device type(Disk or CD-ROM).
     pub media: String,
+    /// The usb hub which the device is attached to.
+    pub bus: Option<String>,
 }
 
 impl UsbStorageConfig {
@@ -253,6 +359,7 @@ impl UsbStorageConfig {
             id: None,
             scsi_cfg: ScsiDevConfig::default(),
             media: "".to_string(),
+            bus: None,
         }
     }
 }
@@ -266,11 +373,6 @@ impl Default for UsbStorageConfig {
 impl ConfigCheck for UsbStorageConfig {
     fn check(&self) -> Result<()> {
         check_id(self.id.clone(), "usb-storage")?;
-
-        if self.scsi_cfg.aio_type != AioEngine::Off || self.scsi_cfg.direct {
-            bail!("USB-storage: \"aio=off,direct=false\" must be configured.");
-        }
-
         Ok(())
     }
 }
@@ -282,12 +384,15 @@ pub fn parse_usb_storage(vm_config: &mut VmConfig, drive_config: &str) -> Result
         .push("id")
         .push("bus")
         .push("port")
-        .push("drive");
+        .push("drive")
+        .push("bootindex");
 
     cmd_parser.parse(drive_config)?;
 
     let mut dev = UsbStorageConfig::new();
     dev.id = cmd_parser.get_value::<String>("id")?;
+    dev.bus = cmd_parser.get_value::<String>("bus")?;
+    dev.scsi_cfg.boot_index = cmd_parser.get_value::<u8>("bootindex")?;
 
     let storage_drive = cmd_parser.get_value::<String>("drive")?.with_context(|| {
         ConfigError::FieldIsMissing("drive".to_string(), "usb storage device".to_string())
@@ -304,12 +409,70 @@ pub fn parse_usb_storage(vm_config: &mut VmConfig, drive_config: &str) -> Result
     dev.scsi_cfg.format = drive_arg.format;
     dev.scsi_cfg.l2_cache_size = drive_arg.l2_cache_size;
     dev.scsi_cfg.refcount_cache_size = drive_arg.refcount_cache_size;
+    dev.scsi_cfg.copy_on_read = drive_arg.copy_on_read;
+    dev.scsi_cfg.io_timeout = drive_arg.io_timeout;
     dev.media = drive_arg.media.clone();
 
     dev.check()?;
     Ok(dev)
 }
 
+/// Config of the USB device redirected from the remote client by usbredir protocol.
+#[derive(Clone, Debug)]
+pub struct UsbRedirConfig {
+    /// USB redirection device id.
+    pub id: Option<String>,
+    /// The socket chardev which the usbredir protocol is transferred over.
+    pub chardev: ChardevConfig,
+}
+
+impl ConfigCheck for UsbRedirConfig {
+    fn check(&self) -> Result<()> {
+        check_id(self.id.clone(), "usb-redir")?;
+        if !matches!(self.chardev.backend, ChardevType::Socket { .. }) {
+            bail!(
+                "Chardev {} of usb-redir must be socket type",
+                self.chardev.id
+            );
+        }
+        if self.chardev.mux {
+            bail!(
+                "Chardev {} of usb-redir can't be multiplexed",
+                self.chardev.id
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_usb_redir(vm_config: &mut VmConfig, cfg_args: &str) -> Result<UsbRedirConfig> {
+    let mut cmd_parser = CmdParser::new("usb-redir");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("port")
+        .push("chardev");
+    cmd_parser.parse(cfg_args)?;
+
+    let chardev_name = cmd_parser
+        .get_value::<String>("chardev")?
+        .with_context(|| {
+            ConfigError::FieldIsMissing("chardev".to_string(), "usb-redir".to_string())
+        })?;
+    let chardev = vm_config
+        .chardev
+        .remove(&chardev_name)
+        .with_context(|| format!("Chardev {:?} not found or is in use", chardev_name))?;
+    let dev = UsbRedirConfig {
+        id: cmd_parser.get_value::<String>("id")?,
+        chardev,
+    };
+
+    dev.check()?;
+    Ok(dev)
+}
+
 #[derive(Clone, Debug, Default)]
 #[cfg(feature = "usb_host")]
 pub struct UsbHostConfig {
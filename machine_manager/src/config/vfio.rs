@@ -10,10 +10,10 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 
 use super::error::ConfigError;
-use crate::config::{check_arg_too_long, CmdParser, ConfigCheck};
+use crate::config::{check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck};
 
 #[derive(Default, Debug)]
 pub struct VfioConfig {
@@ -74,6 +74,61 @@ pub fn parse_vfio(vfio_config: &str) -> Result<VfioConfig> {
     Ok(vfio)
 }
 
+#[derive(Default, Debug)]
+pub struct VfioPlatformConfig {
+    pub sysfsdev: String,
+    pub host: String,
+    pub id: String,
+    /// Path of the dtb which holds the device node template.
+    pub dtb: String,
+}
+
+impl ConfigCheck for VfioPlatformConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.host, "host")?;
+        check_arg_too_long(&self.id, "id")?;
+        check_path_too_long(&self.sysfsdev, "sysfsdev")?;
+        check_path_too_long(&self.dtb, "dtb")?;
+
+        Ok(())
+    }
+}
+
+pub fn parse_vfio_platform(vfio_config: &str) -> Result<VfioPlatformConfig> {
+    let mut cmd_parser = CmdParser::new("vfio-platform");
+    cmd_parser
+        .push("")
+        .push("host")
+        .push("sysfsdev")
+        .push("id")
+        .push("dtb");
+    cmd_parser.parse(vfio_config)?;
+
+    let mut vfio = VfioPlatformConfig {
+        host: cmd_parser.get_value::<String>("host")?.unwrap_or_default(),
+        sysfsdev: cmd_parser
+            .get_value::<String>("sysfsdev")?
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    if vfio.host.is_empty() == vfio.sysfsdev.is_empty() {
+        return Err(anyhow!(ConfigError::InvalidParam(
+            "host and sysfsdev".to_string(),
+            "vfio-platform".to_string()
+        )));
+    }
+
+    vfio.id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+        ConfigError::FieldIsMissing("id".to_string(), "vfio-platform".to_string())
+    })?;
+    vfio.dtb = cmd_parser.get_value::<String>("dtb")?.with_context(|| {
+        ConfigError::FieldIsMissing("dtb".to_string(), "vfio-platform".to_string())
+    })?;
+    vfio.check()?;
+
+    Ok(vfio)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +186,30 @@ mod tests {
             "vfio-pci,host=0000:1a:00.3,id=net,bus=pcie.0,addr=0x1.0x2,multifunction=on";
         assert!(parse_vfio(vfio_cfg1).is_ok());
     }
+
+    #[test]
+    fn test_vfio_platform_config_cmdline_parser() {
+        let config =
+            parse_vfio_platform("vfio-platform,host=fff51000.ethernet,id=eth0,dtb=/tmp/eth.dtb")
+                .unwrap();
+        assert_eq!(config.host, "fff51000.ethernet");
+        assert_eq!(config.id, "eth0");
+        assert_eq!(config.dtb, "/tmp/eth.dtb");
+
+        let config = parse_vfio_platform(
+            "vfio-platform,sysfsdev=/sys/bus/platform/devices/fff51000.ethernet,id=eth0,dtb=/tmp/eth.dtb",
+        )
+        .unwrap();
+        assert!(config.host.is_empty());
+
+        assert!(parse_vfio_platform("vfio-platform,id=eth0,dtb=/tmp/eth.dtb").is_err());
+        assert!(parse_vfio_platform(
+            "vfio-platform,host=fff51000.ethernet,sysfsdev=/sys/bus/platform/devices/fff51000.ethernet,id=eth0,dtb=/tmp/eth.dtb"
+        )
+        .is_err());
+        assert!(parse_vfio_platform("vfio-platform,host=fff51000.ethernet,id=eth0").is_err());
+        assert!(
+            parse_vfio_platform("vfio-platform,host=fff51000.ethernet,dtb=/tmp/eth.dtb").is_err()
+        );
+    }
 }
@@ -0,0 +1,89 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, Uuid};
+
+/// Config structure for vmgenid device.
+#[derive(Debug, Clone, Default)]
+pub struct VmGenIdConfig {
+    pub id: String,
+    /// The generation ID, `None` if it's randomly generated.
+    pub guid: Option<Uuid>,
+}
+
+impl ConfigCheck for VmGenIdConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "vmgenid id")
+    }
+}
+
+/// Parse the generation ID, which is a UUID string or `auto` for a random one.
+pub fn parse_vmgenid_guid(guid: &str) -> Result<Option<Uuid>> {
+    if guid == "auto" {
+        return Ok(None);
+    }
+    Uuid::from_str(guid)
+        .map(Some)
+        .map_err(|_| anyhow!("Invalid vmgenid guid {}, must be a UUID or auto", guid))
+}
+
+/// Parse the cmdline of vmgenid device.
+///
+/// # Arguments
+///
+/// * `cfg_args` - `vmgenid[,id=str][,guid=auto|UUID]`.
+pub fn parse_vmgenid(cfg_args: &str) -> Result<VmGenIdConfig> {
+    let mut cmd_parser = CmdParser::new("vmgenid");
+    cmd_parser.push("").push("id").push("guid");
+    cmd_parser.parse(cfg_args)?;
+
+    let guid = match cmd_parser.get_value::<String>("guid")? {
+        Some(guid) => parse_vmgenid_guid(&guid)?,
+        None => None,
+    };
+    let vmgenid_cfg = VmGenIdConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+        guid,
+    };
+    vmgenid_cfg.check()?;
+    Ok(vmgenid_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vmgenid_config_cmdline_parser() {
+        let config = parse_vmgenid("vmgenid,id=vgid0").unwrap();
+        assert_eq!(config.id, "vgid0");
+        assert!(config.guid.is_none());
+        let config = parse_vmgenid("vmgenid,guid=auto").unwrap();
+        assert!(config.guid.is_none());
+
+        let config =
+            parse_vmgenid("vmgenid,id=vgid0,guid=324e6eaf-d1d1-4bf6-bf41-b9bb6c91fb87").unwrap();
+        let guid = config.guid.unwrap();
+        assert_eq!(guid.name[..4], [0xaf, 0x6e, 0x4e, 0x32]);
+        assert_eq!(
+            guid.to_string(),
+            "324e6eaf-d1d1-4bf6-bf41-b9bb6c91fb87".to_string()
+        );
+
+        assert!(parse_vmgenid("vmgenid,guid=324e6eaf").is_err());
+        assert!(parse_vmgenid("vmgenid,bus=pcie.0").is_err());
+    }
+}
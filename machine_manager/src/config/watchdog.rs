@@ -0,0 +1,124 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::pci_args_check;
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, VmConfig};
+
+/// Action to take when the guest fails to pet the watchdog in time.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum WatchdogAction {
+    #[default]
+    Reset,
+    Shutdown,
+    Pause,
+    /// Only print a message and keep the VM running.
+    Debug,
+}
+
+impl FromStr for WatchdogAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reset" => Ok(WatchdogAction::Reset),
+            "shutdown" => Ok(WatchdogAction::Shutdown),
+            "pause" => Ok(WatchdogAction::Pause),
+            "debug" => Ok(WatchdogAction::Debug),
+            _ => Err(anyhow!(
+                "Unknown watchdog action {}, must be one of reset, shutdown, pause or debug",
+                s
+            )),
+        }
+    }
+}
+
+/// Config structure for watchdog device.
+#[derive(Debug, Clone, Default)]
+pub struct WatchdogConfig {
+    pub id: String,
+}
+
+impl ConfigCheck for WatchdogConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "watchdog id")
+    }
+}
+
+/// Parse the cmdline of watchdog device, `i6300esb` is a PCI device while
+/// `sbsa-gwdt` is a system bus device at fixed address.
+pub fn parse_watchdog(cfg_args: &str) -> Result<WatchdogConfig> {
+    let mut cmd_parser = CmdParser::new("watchdog");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction");
+    cmd_parser.parse(cfg_args)?;
+    pci_args_check(&cmd_parser)?;
+
+    if cmd_parser.get_value::<String>("")? == Some("sbsa-gwdt".to_string())
+        && (cmd_parser.get_value::<String>("bus")?.is_some()
+            || cmd_parser.get_value::<String>("addr")?.is_some())
+    {
+        bail!("sbsa-gwdt does not support bus and addr arguments");
+    }
+
+    let watchdog_cfg = WatchdogConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+    };
+    watchdog_cfg.check()?;
+    Ok(watchdog_cfg)
+}
+
+impl VmConfig {
+    pub fn add_watchdog_action(&mut self, action: &str) -> Result<()> {
+        self.machine_config.watchdog_action = WatchdogAction::from_str(action)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_config_cmdline_parser() {
+        let config = parse_watchdog("i6300esb,id=wdt0,bus=pcie.0,addr=0x9").unwrap();
+        assert_eq!(config.id, "wdt0");
+        assert!(parse_watchdog("sbsa-gwdt,id=wdt0").is_ok());
+        assert!(parse_watchdog("sbsa-gwdt,id=wdt0,bus=pcie.0,addr=0x9").is_err());
+        assert!(parse_watchdog("i6300esb,id=wdt0,bus=pcie.0,addr=0x9,action=reset").is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert_eq!(
+            vm_config.machine_config.watchdog_action,
+            WatchdogAction::Reset
+        );
+        assert!(vm_config.add_watchdog_action("pause").is_ok());
+        assert_eq!(
+            vm_config.machine_config.watchdog_action,
+            WatchdogAction::Pause
+        );
+        assert!(vm_config.add_watchdog_action("debug").is_ok());
+        assert_eq!(
+            vm_config.machine_config.watchdog_action,
+            WatchdogAction::Debug
+        );
+        assert!(vm_config.add_watchdog_action("poweroff").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Throttling of vCPUs.
+//!
+//! vCPUs run for a fixed time slice, and then are forced to sleep for a while, so
+//! that they only run the given percentage of time. It slows down the guest to make
+//! live migration converge, or to limit the CPU usage of a noisy guest.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use once_cell::sync::Lazy;
+
+use crate::event_loop::EventLoop;
+
+/// Time slice in which vCPUs run between sleeps.
+const THROTTLE_TIMESLICE: Duration = Duration::from_millis(10);
+pub const MIN_THROTTLE_PERCENTAGE: u8 = 1;
+pub const MAX_THROTTLE_PERCENTAGE: u8 = 99;
+
+/// Operations of vCPUs used by the throttling.
+pub trait CpuThrottleOps: Send + Sync {
+    /// Force all vCPUs to sleep for `sleep` at once.
+    fn throttle(&self, sleep: Duration);
+}
+
+#[derive(Default)]
+struct CpuThrottle {
+    ops: Option<Arc<dyn CpuThrottleOps>>,
+    /// Percentage of time vCPUs sleep, 0 if vCPUs are not throttled.
+    percentage: u8,
+    timer_id: Option<u64>,
+}
+
+impl CpuThrottle {
+    fn start_timer(&mut self) {
+        self.stop_timer();
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            let func = Box::new(cpu_throttle_timer_func);
+            let period = THROTTLE_TIMESLICE + throttle_sleep_time(self.percentage);
+            self.timer_id = Some(ctx.timer_add(func, period));
+        }
+    }
+
+    fn stop_timer(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
+}
+
+static CPU_THROTTLE: Lazy<Mutex<CpuThrottle>> = Lazy::new(|| Mutex::new(CpuThrottle::default()));
+
+fn cpu_throttle_timer_func() {
+    let mut throttle = CPU_THROTTLE.lock().unwrap();
+    // The timer is removed after it fires.
+    throttle.timer_id = None;
+    if throttle.percentage == 0 {
+        return;
+    }
+    if let Some(ops) = throttle.ops.as_ref() {
+        ops.throttle(throttle_sleep_time(throttle.percentage));
+    }
+    throttle.start_timer();
+}
+
+/// Compute the sleep time after each time slice, so that vCPUs sleep `percentage`
+/// of time.
+fn throttle_sleep_time(percentage: u8) -> Duration {
+    let percentage = u32::from(percentage.min(MAX_THROTTLE_PERCENTAGE));
+    THROTTLE_TIMESLICE * percentage / (100 - percentage)
+}
+
+/// Register the vCPUs which are throttled.
+pub fn register_cpu_throttle_ops(ops: Arc<dyn CpuThrottleOps>) {
+    CPU_THROTTLE.lock().unwrap().ops = Some(ops);
+}
+
+/// Throttle vCPUs to sleep `percentage` of time, or stop throttling if it's 0.
+pub fn cpu_throttle_set(percentage: u8) -> Result<()> {
+    if percentage == 0 {
+        cpu_throttle_stop();
+        return Ok(());
+    }
+    if !(MIN_THROTTLE_PERCENTAGE..=MAX_THROTTLE_PERCENTAGE).contains(&percentage) {
+        bail!(
+            "The percentage of cpu throttle must be in range [{}, {}]",
+            MIN_THROTTLE_PERCENTAGE,
+            MAX_THROTTLE_PERCENTAGE
+        );
+    }
+
+    let mut throttle = CPU_THROTTLE.lock().unwrap();
+    throttle
+        .ops
+        .as_ref()
+        .with_context(|| "No vCPU can be throttled")?;
+    info!("Throttle vCPUs to sleep {}% of time", percentage);
+    throttle.percentage = percentage;
+    // The new percentage takes effect at next time slice if it's running.
+    if throttle.timer_id.is_none() {
+        throttle.start_timer();
+    }
+    Ok(())
+}
+
+/// Stop throttling vCPUs.
+pub fn cpu_throttle_stop() {
+    let mut throttle = CPU_THROTTLE.lock().unwrap();
+    if throttle.percentage != 0 {
+        info!("Stop throttling vCPUs");
+    }
+    throttle.percentage = 0;
+    throttle.stop_timer();
+}
+
+/// Get the percentage of time vCPUs sleep, 0 if vCPUs are not throttled.
+pub fn cpu_throttle_percentage() -> u8 {
+    CPU_THROTTLE.lock().unwrap().percentage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_sleep_time() {
+        assert_eq!(throttle_sleep_time(50), Duration::from_millis(10));
+        assert_eq!(throttle_sleep_time(20), Duration::from_micros(2500));
+        assert_eq!(throttle_sleep_time(99), Duration::from_millis(990));
+        assert_eq!(throttle_sleep_time(100), Duration::from_millis(990));
+    }
+
+    #[test]
+    fn test_cpu_throttle_set() {
+        assert!(cpu_throttle_set(100).is_err());
+        assert!(cpu_throttle_set(0).is_ok());
+        assert_eq!(cpu_throttle_percentage(), 0);
+    }
+}
@@ -12,19 +12,51 @@
 
 use std::collections::HashMap;
 use std::os::unix::prelude::RawFd;
-use std::sync::{Arc, Mutex};
-use std::{process, thread};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
-use anyhow::bail;
-use log::info;
+use anyhow::{anyhow, bail};
+use log::{error, info};
 
 use super::config::IothreadConfig;
 use crate::machine::IOTHREADS;
 use crate::qmp::qmp_schema::IothreadInfo;
+use util::cgroup::{join_thread_cgroup, CgroupThread};
 use util::loop_context::{
     gen_delete_notifiers, get_notifiers_fds, EventLoopContext, EventLoopManager, EventNotifier,
 };
 
+/// Used to notify the io-thread to exit.
+#[derive(Default)]
+struct IothreadManager {
+    exit: AtomicBool,
+}
+
+impl EventLoopManager for IothreadManager {
+    fn loop_should_exit(&self) -> bool {
+        self.exit.load(Ordering::Acquire)
+    }
+
+    fn loop_cleanup(&self) -> util::Result<()> {
+        Ok(())
+    }
+}
+
+/// Io-thread which runs its own event loop context.
+struct Iothread {
+    /// The context is boxed so that its address is kept when the map is updated.
+    ctx: Box<EventLoopContext>,
+    manager: Arc<Mutex<IothreadManager>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+struct ContextPtr(*mut EventLoopContext);
+
+// SAFETY: The context is only used by the io-thread and the functions which are
+// already protected, and it is released after the io-thread exits.
+unsafe impl Send for ContextPtr {}
+
 /// This struct used to manage all events occur during VM lifetime.
 /// # Notes
 ///
@@ -35,7 +67,7 @@ pub struct EventLoop {
     /// Used to handle all events which are not monitored by io-threads
     main_loop: EventLoopContext,
     /// Used to monitor events of specified device.
-    io_threads: HashMap<String, EventLoopContext>,
+    io_threads: Mutex<HashMap<String, Iothread>>,
 }
 
 static mut GLOBAL_EVENT_LOOP: Option<EventLoop> = None;
@@ -47,45 +79,116 @@ impl EventLoop {
     ///
     /// * `iothreads` - refer to `-iothread` params
     pub fn object_init(iothreads: &Option<Vec<IothreadConfig>>) -> util::Result<()> {
-        let mut io_threads = HashMap::new();
+        // SAFETY: This function is called at startup thus no concurrent accessing to
+        // GLOBAL_EVENT_LOOP.
+        unsafe {
+            if GLOBAL_EVENT_LOOP.is_some() {
+                return Ok(());
+            }
+            GLOBAL_EVENT_LOOP = Some(EventLoop {
+                main_loop: EventLoopContext::new(),
+                io_threads: Mutex::new(HashMap::new()),
+            });
+        }
+
         if let Some(thrs) = iothreads {
             for thr in thrs {
-                io_threads.insert(thr.id.clone(), EventLoopContext::new());
+                Self::add_iothread(&thr.id)?;
             }
         }
 
-        // SAFETY: This function is called at startup thus no concurrent accessing to
-        // GLOBAL_EVENT_LOOP. And each iothread has a dedicated EventLoopContext.
-        unsafe {
-            if GLOBAL_EVENT_LOOP.is_none() {
-                GLOBAL_EVENT_LOOP = Some(EventLoop {
-                    main_loop: EventLoopContext::new(),
-                    io_threads,
-                });
-
-                if let Some(event_loop) = GLOBAL_EVENT_LOOP.as_mut() {
-                    for (id, ctx) in &mut event_loop.io_threads {
-                        thread::Builder::new().name(id.to_string()).spawn(move || {
-                            let iothread_info = IothreadInfo {
-                                shrink: 0,
-                                pid: process::id(),
-                                grow: 0,
-                                max: 0,
-                                id: id.to_string(),
-                            };
-                            IOTHREADS.lock().unwrap().push(iothread_info);
-                            while let Ok(ret) = ctx.iothread_run() {
-                                if !ret {
-                                    break;
-                                }
-                            }
-                        })?;
-                    }
-                } else {
-                    bail!("Global Event Loop have not been initialized.")
+        Ok(())
+    }
+
+    fn io_threads() -> util::Result<&'static Mutex<HashMap<String, Iothread>>> {
+        // SAFETY: GLOBAL_EVENT_LOOP is only set at startup, and the io-threads map is
+        // protected by lock.
+        let event_loop = unsafe { GLOBAL_EVENT_LOOP.as_ref() }
+            .ok_or_else(|| anyhow!("Global Event Loop have not been initialized."))?;
+        Ok(&event_loop.io_threads)
+    }
+
+    /// Spawn an io-thread with a dedicated event loop context.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the io-thread.
+    pub fn add_iothread(id: &str) -> util::Result<()> {
+        let mut io_threads = Self::io_threads()?.lock().unwrap();
+        if io_threads.contains_key(id) {
+            bail!("Iothread {} already exists", id);
+        }
+
+        let mut ctx = Box::new(EventLoopContext::new());
+        let manager = Arc::new(Mutex::new(IothreadManager::default()));
+        ctx.set_manager(manager.clone());
+        let ctx_ptr = ContextPtr(ctx.as_mut());
+        let (tid_sender, tid_receiver) = mpsc::channel();
+        let handle = thread::Builder::new().name(id.to_string()).spawn(move || {
+            let ctx_ptr = ctx_ptr;
+            // SAFETY: The context is released only after this thread is joined.
+            let ctx = unsafe { &mut *ctx_ptr.0 };
+            // SAFETY: gettid has no side effect.
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+            if let Err(e) = join_thread_cgroup(CgroupThread::Iothread) {
+                error!("Failed to join cgroup for iothread: {:?}", e);
+            }
+            if tid_sender.send(tid).is_err() {
+                return;
+            }
+            while let Ok(ret) = ctx.iothread_run() {
+                if !ret {
+                    break;
                 }
             }
+        })?;
+        let tid = tid_receiver.recv()?;
+
+        io_threads.insert(
+            id.to_string(),
+            Iothread {
+                ctx,
+                manager,
+                handle: Some(handle),
+            },
+        );
+        IOTHREADS.lock().unwrap().push(IothreadInfo {
+            pid: tid,
+            id: id.to_string(),
+            ..Default::default()
+        });
+        info!("Iothread {} is started, thread id {}", id, tid);
+
+        Ok(())
+    }
+
+    /// Stop the io-thread and release its event loop context. The caller should make
+    /// sure that no device is using the io-thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the io-thread.
+    pub fn del_iothread(id: &str) -> util::Result<()> {
+        let mut iothread = Self::io_threads()?
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or_else(|| anyhow!("Iothread {} not found", id))?;
+
+        iothread
+            .manager
+            .lock()
+            .unwrap()
+            .exit
+            .store(true, Ordering::Release);
+        iothread.ctx.kick();
+        if let Some(handle) = iothread.handle.take() {
+            if handle.join().is_err() {
+                bail!("Failed to join iothread {}", id);
+            }
         }
+        IOTHREADS.lock().unwrap().retain(|thread| thread.id != id);
+        info!("Iothread {} is stopped", id);
 
         Ok(())
     }
@@ -100,7 +203,17 @@ impl EventLoop {
         unsafe {
             if let Some(event_loop) = GLOBAL_EVENT_LOOP.as_mut() {
                 if let Some(name) = name {
-                    return event_loop.io_threads.get_mut(name);
+                    return event_loop
+                        .io_threads
+                        .lock()
+                        .unwrap()
+                        .get_mut(name)
+                        .map(|iothread| {
+                            let ctx: *mut EventLoopContext = iothread.ctx.as_mut();
+                            // SAFETY: The context is boxed and kept until the io-thread is
+                            // deleted, which happens only when no device is using it.
+                            &mut *ctx
+                        });
                 }
 
                 return Some(&mut event_loop.main_loop);
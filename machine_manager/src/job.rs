@@ -0,0 +1,440 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Background jobs which run while the VM keeps running, such as block mirror.
+//!
+//! The job is registered with its operations when it's created, and it's removed
+//! when it's done. Every status change is reported by the `JOB_STATUS_CHANGE`
+//! event, and `JOB_COMPLETED` is sent when the job is done.
+//!
+//! Long-running QMP commands can also be executed asynchronously as jobs, they
+//! are run by a pool of worker threads so that the main loop is not blocked.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Sender},
+        Arc, Mutex, Weak,
+    },
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::event;
+use crate::qmp::qmp_channel::QmpChannel;
+use crate::qmp::qmp_schema::{self as schema, JobInfo};
+
+type JobListType = Lazy<Mutex<BTreeMap<String, Arc<Job>>>>;
+/// Jobs which are not done, indexed by job id.
+static JOB_LIST: JobListType = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Number of worker threads which run the asynchronous commands.
+const JOB_WORKER_NUM: usize = 4;
+
+/// Work of the asynchronous command, it may report progress by the job.
+pub type JobTask = Box<dyn FnOnce(&Job) -> Result<()> + Send>;
+
+type JobWorkersType = Lazy<Mutex<Sender<(Arc<CommandJob>, JobTask)>>>;
+/// Queue of the asynchronous commands, which are fetched by the job workers.
+static JOB_WORKERS: JobWorkersType = Lazy::new(|| {
+    let (sender, receiver) = channel::<(Arc<CommandJob>, JobTask)>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for i in 0..JOB_WORKER_NUM {
+        let receiver = receiver.clone();
+        thread::Builder::new()
+            .name(format!("job_worker{}", i))
+            .spawn(move || loop {
+                let request = receiver.lock().unwrap().recv();
+                match request {
+                    Ok((cmd_job, task)) => cmd_job.run(task),
+                    Err(_) => break,
+                }
+            })
+            .expect("Failed to create job worker thread");
+    }
+    Mutex::new(sender)
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// The job is created and not started.
+    Created,
+    Running,
+    /// The job is paused by the user.
+    Paused,
+    /// The job is waiting to be completed, e.g. the mirror target is in sync.
+    Ready,
+    /// The job is completed, cancelled or failed.
+    Done,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Created => "created",
+            JobStatus::Running => "running",
+            JobStatus::Paused => "paused",
+            JobStatus::Ready => "ready",
+            JobStatus::Done => "done",
+        }
+    }
+
+    fn can_transit(&self, to: JobStatus) -> bool {
+        matches!(
+            (self, to),
+            (JobStatus::Created, JobStatus::Running)
+                | (JobStatus::Running, JobStatus::Paused)
+                | (JobStatus::Running, JobStatus::Ready)
+                | (JobStatus::Ready, JobStatus::Paused)
+                | (JobStatus::Paused, JobStatus::Running)
+                | (
+                    JobStatus::Created | JobStatus::Running | JobStatus::Paused | JobStatus::Ready,
+                    JobStatus::Done
+                )
+        )
+    }
+}
+
+/// Operations implemented by the job drivers.
+pub trait JobOps: Send + Sync {
+    fn pause(&self) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+    /// Cancel the job, the driver must call `Job::finish` when the job is stopped.
+    fn cancel(&self) -> Result<()>;
+}
+
+pub struct Job {
+    pub id: String,
+    /// Type of the job, such as "mirror".
+    pub job_type: String,
+    status: Mutex<JobStatus>,
+    /// Progress of the job, `total` may grow when the job is running.
+    current: AtomicU64,
+    total: AtomicU64,
+    error: Mutex<Option<String>>,
+    ops: Weak<dyn JobOps>,
+}
+
+impl Job {
+    pub fn new(id: &str, job_type: &str, ops: Weak<dyn JobOps>) -> Self {
+        Job {
+            id: id.to_string(),
+            job_type: job_type.to_string(),
+            status: Mutex::new(JobStatus::Created),
+            current: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            error: Mutex::new(None),
+            ops,
+        }
+    }
+
+    pub fn status(&self) -> JobStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// Change the status of the job, it fails if the change is not allowed.
+    pub fn transit(&self, status: JobStatus) -> Result<()> {
+        let mut locked_status = self.status.lock().unwrap();
+        // The job may be finished by its driver and the user at the same time.
+        if *locked_status == status && status != JobStatus::Done {
+            return Ok(());
+        }
+        if !locked_status.can_transit(status) {
+            bail!(
+                "Job {} can not change from {} to {}",
+                self.id,
+                locked_status.as_str(),
+                status.as_str()
+            );
+        }
+        *locked_status = status;
+        drop(locked_status);
+
+        let status_msg = schema::JobStatusChange {
+            id: self.id.clone(),
+            status: status.as_str().to_string(),
+        };
+        event!(JobStatusChange; status_msg);
+        Ok(())
+    }
+
+    pub fn progress(&self) -> (u64, u64) {
+        (
+            self.current.load(Ordering::SeqCst),
+            self.total.load(Ordering::SeqCst),
+        )
+    }
+
+    pub fn add_progress(&self, delta: u64) {
+        self.current.fetch_add(delta, Ordering::SeqCst);
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::SeqCst);
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// Conclude the job and remove it, `error` is set if the job failed.
+    pub fn finish(&self, error: Option<String>, cancelled: bool) {
+        *self.error.lock().unwrap() = error.clone();
+        if let Err(e) = self.transit(JobStatus::Done) {
+            error!("{:?}", e);
+            return;
+        }
+        JOB_LIST.lock().unwrap().remove(&self.id);
+        info!("Job {} is done", self.id);
+
+        let (current, total) = self.progress();
+        let completed_msg = schema::JobCompleted {
+            id: self.id.clone(),
+            job_type: self.job_type.clone(),
+            current_progress: current,
+            total_progress: total,
+            cancelled,
+            error,
+        };
+        event!(JobCompleted; completed_msg);
+    }
+
+    fn info(&self) -> JobInfo {
+        let (current, total) = self.progress();
+        JobInfo {
+            id: self.id.clone(),
+            job_type: self.job_type.clone(),
+            status: self.status().as_str().to_string(),
+            current_progress: current,
+            total_progress: total,
+            error: self.error(),
+        }
+    }
+
+    fn ops(&self) -> Result<Arc<dyn JobOps>> {
+        self.ops
+            .upgrade()
+            .with_context(|| format!("Job {} has been released", self.id))
+    }
+}
+
+/// Register the created job, whose id must be unique.
+pub fn job_register(job: Arc<Job>) -> Result<()> {
+    let mut jobs = JOB_LIST.lock().unwrap();
+    if jobs.contains_key(&job.id) {
+        bail!("Job {} already exists", job.id);
+    }
+    jobs.insert(job.id.clone(), job);
+    Ok(())
+}
+
+pub fn job_get(id: &str) -> Result<Arc<Job>> {
+    JOB_LIST
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .with_context(|| format!("Job {} not found", id))
+}
+
+pub fn job_pause(id: &str) -> Result<()> {
+    let job = job_get(id)?;
+    if !matches!(job.status(), JobStatus::Running | JobStatus::Ready) {
+        bail!(
+            "Job {} is {}, it can't be paused",
+            id,
+            job.status().as_str()
+        );
+    }
+    job.ops()?.pause()?;
+    job.transit(JobStatus::Paused)
+}
+
+pub fn job_resume(id: &str) -> Result<()> {
+    let job = job_get(id)?;
+    if job.status() != JobStatus::Paused {
+        bail!("Job {} is not paused", id);
+    }
+    // The job may become ready as soon as it's resumed, so change the status first.
+    job.transit(JobStatus::Running)?;
+    if let Err(e) = job.ops().and_then(|ops| ops.resume()) {
+        job.transit(JobStatus::Paused)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub fn job_cancel(id: &str) -> Result<()> {
+    job_get(id)?.ops()?.cancel()
+}
+
+/// The job of an asynchronous command, which can't be paused or cancelled.
+struct CommandJob {
+    job: Arc<Job>,
+}
+
+impl CommandJob {
+    fn run(&self, task: JobTask) {
+        let error = task(&self.job).err().map(|e| format!("{:?}", e));
+        if let Some(e) = &error {
+            error!("Job {} failed: {}", self.job.id, e);
+        }
+        self.job.finish(error, false);
+    }
+}
+
+impl JobOps for CommandJob {
+    fn pause(&self) -> Result<()> {
+        bail!("Job {} can't be paused", self.job.id)
+    }
+
+    fn resume(&self) -> Result<()> {
+        bail!("Job {} can't be resumed", self.job.id)
+    }
+
+    fn cancel(&self) -> Result<()> {
+        bail!("Job {} can't be cancelled", self.job.id)
+    }
+}
+
+/// Run the command in the job workers, it returns once the job is started.
+///
+/// # Arguments
+///
+/// * `id` - Id of the job, which must be unique.
+/// * `job_type` - Type of the job, such as "migrate".
+/// * `task` - Work of the command.
+pub fn job_spawn(id: &str, job_type: &str, task: JobTask) -> Result<()> {
+    let cmd_job = Arc::new_cyclic(|ops: &Weak<CommandJob>| CommandJob {
+        job: Arc::new(Job::new(id, job_type, ops.clone())),
+    });
+    job_register(cmd_job.job.clone())?;
+    cmd_job.job.transit(JobStatus::Running)?;
+    if let Err(e) = JOB_WORKERS.lock().unwrap().send((cmd_job.clone(), task)) {
+        cmd_job.job.finish(Some(e.to_string()), false);
+        bail!("Failed to start job {}", id);
+    }
+    Ok(())
+}
+
+pub fn query_jobs() -> Vec<JobInfo> {
+    JOB_LIST
+        .lock()
+        .unwrap()
+        .values()
+        .map(|job| job.info())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestJob {
+        job: Arc<Job>,
+    }
+
+    impl JobOps for TestJob {
+        fn pause(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn resume(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<()> {
+            self.job.finish(None, true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_job_status() {
+        QmpChannel::object_init();
+        let test_job = Arc::new_cyclic(|ops: &Weak<TestJob>| TestJob {
+            job: Arc::new(Job::new("job-test", "test", ops.clone())),
+        });
+        job_register(test_job.job.clone()).unwrap();
+        assert!(job_register(test_job.job.clone()).is_err());
+
+        // The job is not started.
+        assert!(job_pause("job-test").is_err());
+        let job = job_get("job-test").unwrap();
+        job.transit(JobStatus::Running).unwrap();
+        job.set_total(100);
+        job.add_progress(40);
+        assert!(job.transit(JobStatus::Created).is_err());
+
+        job_pause("job-test").unwrap();
+        assert!(job_pause("job-test").is_err());
+        let info = query_jobs()
+            .into_iter()
+            .find(|info| info.id == "job-test")
+            .unwrap();
+        assert_eq!(info.status, "paused");
+        assert_eq!(info.current_progress, 40);
+        assert_eq!(info.total_progress, 100);
+        job_resume("job-test").unwrap();
+        assert_eq!(job.status(), JobStatus::Running);
+
+        job_cancel("job-test").unwrap();
+        assert_eq!(job.status(), JobStatus::Done);
+        assert!(job_get("job-test").is_err());
+        assert!(job_cancel("job-test").is_err());
+    }
+
+    #[test]
+    fn test_job_spawn() {
+        QmpChannel::object_init();
+        let (sender, receiver) = channel();
+        let task: JobTask = Box::new(move |job: &Job| {
+            job.set_total(2);
+            job.add_progress(1);
+            // Wait until the job is checked.
+            receiver.recv().unwrap();
+            job.add_progress(1);
+            Ok(())
+        });
+        job_spawn("job-spawn", "test", task).unwrap();
+        assert!(job_spawn("job-spawn", "test", Box::new(|_: &Job| Ok(()))).is_err());
+
+        let job = job_get("job-spawn").unwrap();
+        assert_eq!(job.status(), JobStatus::Running);
+        assert!(job_pause("job-spawn").is_err());
+        assert!(job_cancel("job-spawn").is_err());
+        sender.send(()).unwrap();
+        while job.status() != JobStatus::Done {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(job.progress(), (2, 2));
+        assert!(job.error().is_none());
+        assert!(job_get("job-spawn").is_err());
+
+        let (sender, receiver) = channel();
+        let task: JobTask = Box::new(move |_: &Job| {
+            receiver.recv().unwrap();
+            bail!("test error")
+        });
+        job_spawn("job-failed", "test", task).unwrap();
+        let job = job_get("job-failed").unwrap();
+        sender.send(()).unwrap();
+        while job.status() != JobStatus::Done {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(job.error().unwrap().contains("test error"));
+    }
+}
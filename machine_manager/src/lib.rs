@@ -21,11 +21,15 @@
 //! 2. The API interface over VM inside and outside.
 //! 3. Configuration for VM and its devices.
 
+pub mod balloon_policy;
 pub mod cmdline;
 pub mod config;
+pub mod cpu_throttle;
 pub mod error;
 pub mod event_loop;
+pub mod job;
 pub mod machine;
+pub mod metrics;
 pub mod qmp;
 pub mod signal_handler;
 pub mod socket;
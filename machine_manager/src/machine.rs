@@ -11,19 +11,27 @@
 // See the Mulan PSL v2 for more details.
 
 use std::os::unix::io::RawFd;
-use std::sync::Mutex;
+use std::sync::{Arc, Condvar, Mutex};
 
+use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
 use strum::VariantNames;
 
-use crate::config::ShutdownAction;
+use crate::config::{RebootAction, ShutdownAction};
+use crate::job::JobTask;
 use crate::qmp::qmp_response::{Response, Version};
 use crate::qmp::qmp_schema::{
-    BlockDevAddArgument, BlockdevSnapshotInternalArgument, CameraDevAddArgument,
-    CharDevAddArgument, ChardevInfo, Cmd, CmdLine, CmdParameter, DeviceAddArgument, DeviceProps,
-    Events, GicCap, HumanMonitorCmdArgument, IothreadInfo, KvmInfo, MachineInfo,
-    MigrateCapabilities, NetDevAddArgument, PropList, QmpCommand, QmpErrorClass, QmpEvent, Target,
-    TypeLists, UpdateRegionArgument,
+    BalloonPolicyArgument, BlockDevAddArgument, BlockDirtyBitmapAddArgument,
+    BlockDirtyBitmapArgument, BlockDirtyBitmapExportArgument, BlockJobArgument, BlockJobInfo,
+    BlockdevSnapshotInternalArgument, BlockdevSnapshotSyncArgument, CameraDevAddArgument,
+    ChangeArgument, CharDevAddArgument, CharDevChangeArgument, ChardevInfo, Cmd, CmdLine,
+    CmdParameter, CpuThrottleArgument, DeviceAddArgument, DeviceProps, DisplayChannelAddArgument,
+    DriveMirrorArgument, EjectArgument, Events, GicCap, HaltPollArgument, HumanMonitorCmdArgument,
+    IothreadInfo, JobInfo, KvmInfo, MachineInfo, MigrateCapabilities,
+    MigrateSetCapabilitiesArgument, MigrateSetParametersArgument, NbdServerAddArgument,
+    NetDevAddArgument, ObjectAddArgument, PFlashSealArgument, PropList, QmpCommand, QmpErrorClass,
+    QmpEvent, QueryStatsArgument, ReclaimGuestMemoryArgument, Target, TypeLists,
+    UpdateRegionArgument,
 };
 
 #[derive(Clone)]
@@ -41,6 +49,66 @@ pub enum KvmVmState {
     Migrated = 4,
     Paused = 5,
     Shutdown = 6,
+    Suspended = 7,
+}
+
+/// Cause of the VM shutdown or reset, reported in the `SHUTDOWN` and `RESET` events.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ShutdownCause {
+    /// The guest powers off, e.g. by ACPI or PSCI.
+    GuestShutdown,
+    /// The guest reboots, e.g. by a triple fault, PSCI or the ACPI reset register.
+    GuestReset,
+    /// The guest reports a panic.
+    GuestPanic,
+    /// The watchdog timer expires.
+    Watchdog,
+    /// QMP command `quit`.
+    HostQmpQuit,
+    /// QMP command `system_reset`.
+    HostQmpSystemReset,
+    /// StratoVirt receives a kill signal.
+    HostSignal,
+}
+
+impl ShutdownCause {
+    /// Whether the shutdown or reset is triggered by the guest rather than the host.
+    pub fn is_guest(&self) -> bool {
+        matches!(
+            self,
+            ShutdownCause::GuestShutdown
+                | ShutdownCause::GuestReset
+                | ShutdownCause::GuestPanic
+                | ShutdownCause::Watchdog
+        )
+    }
+
+    /// The reason string reported in QMP events.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ShutdownCause::GuestShutdown => "guest-shutdown",
+            ShutdownCause::GuestReset => "guest-reset",
+            ShutdownCause::GuestPanic => "guest-panic",
+            ShutdownCause::Watchdog => "watchdog",
+            ShutdownCause::HostQmpQuit => "host-qmp-quit",
+            ShutdownCause::HostQmpSystemReset => "host-qmp-system-reset",
+            ShutdownCause::HostSignal => "host-signal",
+        }
+    }
+}
+
+/// Cause of the shutdown or reset request which is pending in the main loop.
+static PENDING_SHUTDOWN_CAUSE: Lazy<Mutex<Option<ShutdownCause>>> = Lazy::new(|| Mutex::new(None));
+
+/// Record the cause before requesting a shutdown or reset which is handled
+/// asynchronously in the main loop.
+pub fn request_shutdown_cause(cause: ShutdownCause) {
+    *PENDING_SHUTDOWN_CAUSE.lock().unwrap() = Some(cause);
+}
+
+/// Take the cause of the pending shutdown or reset request, `None` if it's not recorded.
+pub fn take_shutdown_cause() -> Option<ShutdownCause> {
+    PENDING_SHUTDOWN_CAUSE.lock().unwrap().take()
 }
 
 /// Trait to handle virtual machine lifecycle.
@@ -104,10 +172,22 @@ pub trait MachineLifecycle {
     /// * `new` - The new `KvmVmState` expected to transform.
     fn notify_lifecycle(&self, old: KvmVmState, new: KvmVmState) -> bool;
 
+    /// Get the state of VM which can be read without locking the machine, e.g. by
+    /// the out-of-band QMP commands.
+    fn shared_vm_state(&self) -> Option<Arc<(Mutex<KvmVmState>, Condvar)>> {
+        None
+    }
+
     /// Get shutdown_action to determine the poweroff operation.
     fn get_shutdown_action(&self) -> ShutdownAction {
         ShutdownAction::ShutdownActionPoweroff
     }
+
+    /// Get reboot_action to determine the operation on guest reboot. Micro vm can not be
+    /// reset, so the reboot is handled as shutdown by default.
+    fn get_reboot_action(&self) -> RebootAction {
+        RebootAction::Shutdown
+    }
 }
 
 /// `AddressSpace` access interface of `Machine`.
@@ -133,6 +213,11 @@ pub trait MachineAddressInterface {
     fn mmio_read(&self, addr: u64, data: &mut [u8]) -> bool;
 
     fn mmio_write(&self, addr: u64, data: &[u8]) -> bool;
+
+    /// The level-triggered interrupt `vector` is acknowledged by guest, it's only
+    /// reported when IOAPIC is emulated in userspace.
+    #[cfg(target_arch = "x86_64")]
+    fn ioapic_eoi(&self, _vector: u8) {}
 }
 
 /// Device external api
@@ -168,12 +253,116 @@ pub trait DeviceInterface {
 
     fn netdev_del(&mut self, id: String) -> Response;
 
+    /// Set the link status of the net device.
+    fn set_link(&mut self, _name: String, _up: bool) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set_link is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Change the mac address of the net device.
+    fn set_mac(&mut self, _name: String, _mac: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-mac is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Change the VM generation ID.
+    fn set_vm_generation_id(&mut self, _guid: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-vm-generation-id is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the VM generation ID.
+    fn query_vm_generation_id(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-vm-generation-id is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the runtime statistics of the VM or its vCPUs.
+    fn query_stats(&self, _args: QueryStatsArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-stats is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Set the halt polling of the vCPUs.
+    fn set_halt_poll(&self, _args: HaltPollArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-halt-poll is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the halt polling of the vCPUs.
+    fn query_halt_poll(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-halt-poll is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Throttle the vCPUs to sleep a percentage of time.
+    fn cpu_throttle_set(&self, _args: CpuThrottleArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("cpu-throttle-set is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Drop the missed periodic interrupts of RTC which are waiting to be reinjected.
+    fn rtc_reset_reinjection(&mut self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("rtc-reset-reinjection is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the time of RTC.
+    fn query_rtc(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-rtc is not supported".to_string()),
+            None,
+        )
+    }
+
     /// Create a new chardev device.
     fn chardev_add(&mut self, _args: CharDevAddArgument) -> Response;
 
     /// Remove a chardev device.
     fn chardev_remove(&mut self, _id: String) -> Response;
 
+    /// Change the backend of a chardev device.
+    fn chardev_change(&mut self, _args: CharDevChangeArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("chardev-change is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Create a new object such as iothread.
+    fn object_add(&mut self, _args: ObjectAddArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("object-add is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Delete an object.
+    fn object_del(&mut self, _id: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("object-del is not supported yet".to_string()),
+            None,
+        )
+    }
+
     /// Creates a new camera device.
     fn cameradev_add(&mut self, _args: CameraDevAddArgument) -> Response {
         Response::create_response(
@@ -202,9 +391,121 @@ pub trait DeviceInterface {
     /// Query the info of vnc server.
     fn query_vnc(&self) -> Response;
 
+    /// Add a display channel over unix socket.
+    fn display_channel_add(&mut self, _args: DisplayChannelAddArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("display-channel-add is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Delete a display channel.
+    fn display_channel_del(&mut self, _id: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("display-channel-del is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the display channels.
+    fn query_display_channels(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-display-channels is not supported".to_string()),
+            None,
+        )
+    }
+
     /// Set balloon's size.
     fn balloon(&self, size: u64) -> Response;
 
+    /// Cancel the resizing of balloon in progress.
+    fn balloon_cancel(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("balloon-cancel is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Set the interval to poll the memory statistics of guest.
+    fn set_balloon_stats_interval(&self, _interval: u32) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-balloon-stats-interval is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the memory statistics of guest.
+    fn query_balloon_stats(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-balloon-stats is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Enable, disable or tune the automatic ballooning policy.
+    fn set_balloon_policy(&self, _args: BalloonPolicyArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-balloon-policy is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the automatic ballooning policy.
+    fn query_balloon_policy(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-balloon-policy is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Reclaim the host memory of the paused VM.
+    fn reclaim_guest_memory(&mut self, _args: ReclaimGuestMemoryArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("reclaim-guest-memory is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the UUID of VM.
+    fn query_uuid(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-uuid is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the name of VM.
+    fn query_name(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-name is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the effective configuration of VM.
+    fn query_vm_config(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-vm-config is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Query the number of interrupts injected for each device and vector.
+    fn query_interrupts(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-interrupts is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Seal or unseal the pflash device.
+    fn pflash_seal(&mut self, _args: PFlashSealArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("pflash-seal is not supported".to_string()),
+            None,
+        )
+    }
+
     /// Query the version of StratoVirt.
     fn query_version(&self) -> Response {
         let version = Version::new(1, 0, 5);
@@ -320,6 +621,7 @@ pub trait DeviceInterface {
             ("nec-usb-xhci", "base-xhci"),
             ("usb-tablet", "usb-hid"),
             ("usb-kbd", "usb-hid"),
+            ("usb-u2f", "usb-hid"),
             ("usb-storage", "usb-storage-dev"),
             ("virtio-gpu-pci", "virtio-gpu"),
         ];
@@ -440,9 +742,34 @@ pub trait DeviceInterface {
     }
 
     fn query_block_jobs(&self) -> Response {
-        // Fix me: qmp command call, return none temporarily.
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
-        Response::create_response(serde_json::to_value(vec_cmd).unwrap(), None)
+        let vec_jobs: Vec<BlockJobInfo> = Vec::new();
+        Response::create_response(serde_json::to_value(vec_jobs).unwrap(), None)
+    }
+
+    fn query_jobs(&self) -> Response {
+        let vec_jobs: Vec<JobInfo> = Vec::new();
+        Response::create_response(serde_json::to_value(vec_jobs).unwrap(), None)
+    }
+
+    fn job_pause(&mut self, _id: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("job-pause is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn job_resume(&mut self, _id: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("job-resume is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn job_cancel(&mut self, _id: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("job-cancel is not supported yet".to_string()),
+            None,
+        )
     }
 
     fn query_gic_capabilities(&self) -> Response {
@@ -466,6 +793,14 @@ pub trait DeviceInterface {
         Response::create_empty_response()
     }
 
+    /// Wake up the guest from suspend.
+    fn system_wakeup(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("system_wakeup is not supported yet".to_string()),
+            None,
+        )
+    }
+
     fn human_monitor_command(&self, _args: HumanMonitorCmdArgument) -> Response {
         Response::create_error_response(
             QmpErrorClass::GenericError("human-monitor-command is not supported yet".to_string()),
@@ -483,6 +818,96 @@ pub trait DeviceInterface {
     ) -> Response {
         Response::create_empty_response()
     }
+
+    fn block_dirty_bitmap_add(&self, _args: BlockDirtyBitmapAddArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("block-dirty-bitmap-add is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn block_dirty_bitmap_clear(&self, _args: BlockDirtyBitmapArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError(
+                "block-dirty-bitmap-clear is not supported yet".to_string(),
+            ),
+            None,
+        )
+    }
+
+    fn block_dirty_bitmap_remove(&self, _args: BlockDirtyBitmapArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError(
+                "block-dirty-bitmap-remove is not supported yet".to_string(),
+            ),
+            None,
+        )
+    }
+
+    fn block_dirty_bitmap_export(&self, _args: BlockDirtyBitmapExportArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError(
+                "block-dirty-bitmap-export is not supported yet".to_string(),
+            ),
+            None,
+        )
+    }
+
+    fn nbd_server_start(&self, _addr: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("nbd-server-start is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn nbd_server_add(&self, _args: NbdServerAddArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("nbd-server-add is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn blockdev_snapshot_sync(&mut self, _args: BlockdevSnapshotSyncArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("blockdev-snapshot-sync is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn eject(&mut self, _args: EjectArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("eject is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn change(&mut self, _args: ChangeArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("change is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn drive_mirror(&mut self, _args: DriveMirrorArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("drive-mirror is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn block_job_complete(&mut self, _args: BlockJobArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("block-job-complete is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    fn block_job_cancel(&mut self, _args: BlockJobArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("block-job-cancel is not supported yet".to_string()),
+            None,
+        )
+    }
 }
 
 /// Migrate external api
@@ -496,6 +921,11 @@ pub trait MigrateInterface {
         Response::create_empty_response()
     }
 
+    /// Prepares the migration and returns the work which runs in the background job.
+    fn migrate_task(&self, _uri: String) -> Result<JobTask> {
+        bail!("Asynchronous migration is not supported")
+    }
+
     /// Returns information about current migration.
     fn query_migrate(&self) -> Response {
         Response::create_empty_response()
@@ -504,6 +934,21 @@ pub trait MigrateInterface {
     fn cancel_migrate(&self) -> Response {
         Response::create_empty_response()
     }
+
+    /// Set parameters of migration.
+    fn migrate_set_parameters(&self, _args: MigrateSetParametersArgument) -> Response {
+        Response::create_empty_response()
+    }
+
+    /// Returns the current parameters of migration.
+    fn query_migrate_parameters(&self) -> Response {
+        Response::create_empty_response()
+    }
+
+    /// Set capabilities of migration.
+    fn migrate_set_capabilities(&self, _args: MigrateSetCapabilitiesArgument) -> Response {
+        Response::create_empty_response()
+    }
 }
 
 /// Machine interface which is exposed to inner hypervisor.
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Http endpoint which exposes the metrics in Prometheus text format. Every request
+//! is answered with all the metrics, whatever the request path is.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::thread;
+
+use anyhow::{Context, Result};
+use log::{error, info};
+
+use crate::config::MetricsAddr;
+use crate::temp_cleaner::TempCleaner;
+use util::metrics::render_metrics;
+
+/// Max length of the http request header.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// Read the http request header, the request body is ignored.
+fn read_request<T: Read>(stream: &mut T) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0_u8; 1024];
+    while request.len() < MAX_REQUEST_LEN {
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..len]);
+        if request.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn http_response(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+fn serve<T: Read + Write>(mut stream: T) {
+    let result = read_request(&mut stream).and_then(|_| {
+        stream.write_all(http_response(&render_metrics()).as_bytes())?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("Failed to serve metrics request: {:?}", e);
+    }
+}
+
+/// Start the metrics endpoint, requests are served one by one in its own thread.
+pub fn start_metrics_server(addr: &MetricsAddr) -> Result<()> {
+    match addr {
+        MetricsAddr::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .with_context(|| format!("Failed to bind metrics endpoint to {}", addr))?;
+            info!("Metrics endpoint is listening on {}", addr);
+            thread::Builder::new()
+                .name("metrics".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream {
+                            Ok(stream) => serve(stream),
+                            Err(e) => error!("Failed to accept metrics client: {:?}", e),
+                        }
+                    }
+                })
+                .with_context(|| "Failed to create metrics thread")?;
+        }
+        MetricsAddr::Unix(path) => {
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to bind metrics endpoint to {}", path))?;
+            TempCleaner::add_path(path.clone());
+            info!("Metrics endpoint is listening on {}", path);
+            thread::Builder::new()
+                .name("metrics".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream {
+                            Ok(stream) => serve(stream),
+                            Err(e) => error!("Failed to accept metrics client: {:?}", e),
+                        }
+                    }
+                })
+                .with_context(|| "Failed to create metrics thread")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use util::metrics::{register_metric, MetricType};
+
+    #[test]
+    fn test_metrics_server() {
+        let metric = register_metric(
+            "test_server_requests_total",
+            "Test requests.",
+            MetricType::Counter,
+            &[],
+        );
+        metric.add(5);
+
+        let path = std::env::temp_dir().join("stratovirt_test_metrics.sock");
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        start_metrics_server(&MetricsAddr::Unix(path.clone())).unwrap();
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP "));
+        assert!(response.contains("test_server_requests_total 5\n"));
+    }
+}
@@ -10,21 +10,30 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Write;
 use std::os::unix::io::RawFd;
-use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use anyhow::{bail, Result};
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 
 use super::qmp_schema::{self as schema};
+use crate::event_loop::EventLoop;
 use crate::socket::SocketRWHandler;
 use util::time::NANOSECONDS_PER_SECOND;
 
 static mut QMP_CHANNEL: Option<Arc<QmpChannel>> = None;
 
+/// Default minimum interval in milliseconds between two events of the same type.
+/// Capabilities offered in the greeting, which can be enabled by `qmp_capabilities`.
+pub const QMP_CAPABILITIES: &[&str] = &["oob"];
+
+const DEFAULT_EVENT_THROTTLE: &[(&str, u64)] = &[("BALLOON_CHANGED", 1000), ("RTC_CHANGE", 1000)];
+
 /// Macro `event!`: send event to qmp-client.
 ///
 /// # Arguments
@@ -80,6 +89,89 @@ pub fn create_timestamp() -> TimeStamp {
     }
 }
 
+/// Rate limiting state of one event type. Only one event is sent in each interval,
+/// the events within the interval are dropped except the latest one, which is sent
+/// at the end of the interval.
+#[derive(Debug, Default)]
+struct EventThrottle {
+    /// Minimum interval in milliseconds, 0 means no limit.
+    interval: u64,
+    /// The time when the last event was sent.
+    last_emit: Option<Instant>,
+    /// The latest event waiting for the end of interval.
+    pending: Option<String>,
+}
+
+/// What to do with an event that is going to be sent.
+#[derive(Debug, PartialEq, Eq)]
+enum ThrottleResult {
+    /// Send it right now.
+    Emit,
+    /// It replaces the pending event, which has been scheduled.
+    Replace,
+    /// It is pending, schedule to send it after the duration.
+    Delay(Duration),
+}
+
+impl EventThrottle {
+    fn new(interval: u64) -> Self {
+        EventThrottle {
+            interval,
+            ..Default::default()
+        }
+    }
+
+    fn check(&mut self, now: Instant, event_str: &str) -> ThrottleResult {
+        let interval = Duration::from_millis(self.interval);
+        match self.last_emit {
+            Some(last) if self.interval != 0 && now < last + interval => {
+                let scheduled = self.pending.is_some();
+                self.pending = Some(event_str.to_string());
+                if scheduled {
+                    ThrottleResult::Replace
+                } else {
+                    ThrottleResult::Delay(last + interval - now)
+                }
+            }
+            _ => {
+                self.last_emit = Some(now);
+                ThrottleResult::Emit
+            }
+        }
+    }
+
+    fn take_pending(&mut self, now: Instant) -> Option<String> {
+        let pending = self.pending.take();
+        if pending.is_some() {
+            self.last_emit = Some(now);
+        }
+        pending
+    }
+}
+
+fn default_event_throttle() -> HashMap<String, EventThrottle> {
+    DEFAULT_EVENT_THROTTLE
+        .iter()
+        .map(|(name, interval)| (name.to_string(), EventThrottle::new(*interval)))
+        .collect()
+}
+
+/// Get the name of event sent to client, such as `SHUTDOWN`.
+fn event_name(event: &schema::QmpEvent) -> String {
+    serde_json::to_value(event)
+        .ok()
+        .and_then(|value| value["event"].as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+/// Check whether the event name is supported.
+fn check_event_name(name: &str) -> Result<()> {
+    if !schema::QmpEvent::iter().any(|event| event_name(&event) == name) {
+        bail!("Invalid event name {}", name);
+    }
+    Ok(())
+}
+
 /// The struct `QmpChannel` is the only struct can handle Global variable
 /// `QMP_CHANNEL`.
 /// It is used to send event to qmp client and restore some file descriptor
@@ -89,6 +181,12 @@ pub struct QmpChannel {
     event_writer: RwLock<Option<SocketRWHandler>>,
     /// Restore file descriptor received from client.
     fds: Arc<RwLock<BTreeMap<String, RawFd>>>,
+    /// Events masked by client, which are not sent.
+    event_mask: RwLock<HashSet<String>>,
+    /// Rate limiting state of events, the key is event name.
+    event_throttle: Mutex<HashMap<String, EventThrottle>>,
+    /// Whether out-of-band execution is enabled by client.
+    oob: RwLock<bool>,
 }
 
 impl QmpChannel {
@@ -99,6 +197,9 @@ impl QmpChannel {
                 QMP_CHANNEL = Some(Arc::new(QmpChannel {
                     event_writer: RwLock::new(None),
                     fds: Arc::new(RwLock::new(BTreeMap::new())),
+                    event_mask: RwLock::new(HashSet::new()),
+                    event_throttle: Mutex::new(default_event_throttle()),
+                    oob: RwLock::new(false),
                 }));
             }
         }
@@ -113,9 +214,48 @@ impl QmpChannel {
         *Self::inner().event_writer.write().unwrap() = Some(writer);
     }
 
-    /// Unbind `SocketRWHandler` from `QMP_CHANNEL`.
+    /// Unbind `SocketRWHandler` from `QMP_CHANNEL`. The capabilities negotiated by
+    /// the client are reset.
     pub(crate) fn unbind() {
         *Self::inner().event_writer.write().unwrap() = None;
+        Self::inner().event_mask.write().unwrap().clear();
+        *Self::inner().event_throttle.lock().unwrap() = default_event_throttle();
+        *Self::inner().oob.write().unwrap() = false;
+    }
+
+    /// Set capabilities negotiated by `qmp_capabilities`.
+    ///
+    /// # Arguments
+    ///
+    /// * `caps` - The capabilities enabled, event mask and event throttle set by client.
+    pub fn set_capabilities(caps: schema::qmp_capabilities) -> Result<()> {
+        let enable = caps.enable.unwrap_or_default();
+        let event_mask = caps.event_mask.unwrap_or_default();
+        let event_throttle = caps.event_throttle.unwrap_or_default();
+        for cap in enable.iter() {
+            if !QMP_CAPABILITIES.contains(&cap.as_str()) {
+                bail!("Capability {} is not supported", cap);
+            }
+        }
+        for name in event_mask.iter() {
+            check_event_name(name)?;
+        }
+        for throttle in event_throttle.iter() {
+            check_event_name(&throttle.event)?;
+        }
+
+        *Self::inner().event_mask.write().unwrap() = event_mask.into_iter().collect();
+        let mut locked_throttle = Self::inner().event_throttle.lock().unwrap();
+        for throttle in event_throttle {
+            locked_throttle.entry(throttle.event).or_default().interval = throttle.interval;
+        }
+        *Self::inner().oob.write().unwrap() = enable.iter().any(|cap| cap == "oob");
+        Ok(())
+    }
+
+    /// Check whether out-of-band execution is enabled by client.
+    pub fn oob_enabled() -> bool {
+        *Self::inner().oob.read().unwrap()
     }
 
     /// Check whether a `SocketRWHandler` bind with `QMP_CHANNEL` or not.
@@ -147,24 +287,70 @@ impl QmpChannel {
     /// # Arguments
     ///
     /// * `event` - The `QmpEvent` sent to client.
-    #[allow(clippy::unused_io_amount)]
     pub fn send_event(event: &schema::QmpEvent) {
         if Self::is_connected() {
-            let mut event_str = serde_json::to_string(&event).unwrap();
-            let mut writer_unlocked = Self::inner().event_writer.write().unwrap();
-            let writer = writer_unlocked.as_mut().unwrap();
-
-            if let Err(e) = writer.flush() {
-                error!("flush err, {:?}", e);
+            let name = event_name(event);
+            if Self::inner().event_mask.read().unwrap().contains(&name) {
                 return;
             }
-            event_str.push_str("\r\n");
-            if let Err(e) = writer.write(event_str.as_bytes()) {
-                error!("write err, {:?}", e);
+
+            let event_str = serde_json::to_string(&event).unwrap();
+            let mut locked_throttle = Self::inner().event_throttle.lock().unwrap();
+            if let Some(throttle) = locked_throttle.get_mut(&name) {
+                match throttle.check(Instant::now(), &event_str) {
+                    ThrottleResult::Emit => (),
+                    ThrottleResult::Replace => return,
+                    ThrottleResult::Delay(delay) => {
+                        if let Some(ctx) = EventLoop::get_ctx(None) {
+                            let flush = Box::new(move || Self::flush_pending_event(&name));
+                            ctx.timer_add(flush, delay);
+                        }
+                        return;
+                    }
+                }
+            }
+            drop(locked_throttle);
+
+            if Self::write_event(event_str) {
+                info!("EVENT: --> {:?}", event);
+            }
+        }
+    }
+
+    /// Send the pending event at the end of throttle interval.
+    fn flush_pending_event(name: &str) {
+        let pending = match Self::inner().event_throttle.lock().unwrap().get_mut(name) {
+            Some(throttle) => throttle.take_pending(Instant::now()),
+            None => None,
+        };
+        if let Some(event_str) = pending {
+            if !Self::is_connected() || Self::inner().event_mask.read().unwrap().contains(name) {
                 return;
             }
-            info!("EVENT: --> {:?}", event);
+            if Self::write_event(event_str.clone()) {
+                info!("EVENT: --> {}", event_str);
+            }
+        }
+    }
+
+    #[allow(clippy::unused_io_amount)]
+    fn write_event(mut event_str: String) -> bool {
+        let mut writer_unlocked = Self::inner().event_writer.write().unwrap();
+        let writer = match writer_unlocked.as_mut() {
+            Some(writer) => writer,
+            None => return false,
+        };
+
+        if let Err(e) = writer.flush() {
+            error!("flush err, {:?}", e);
+            return false;
         }
+        event_str.push_str("\r\n");
+        if let Err(e) = writer.write(event_str.as_bytes()) {
+            error!("write err, {:?}", e);
+            return false;
+        }
+        true
     }
 
     fn inner() -> &'static std::sync::Arc<QmpChannel> {
@@ -191,3 +377,45 @@ pub fn send_device_deleted_msg(id: &str) {
         warn!("Qmp channel is not connected while sending device deleted message");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_throttle() {
+        let now = Instant::now();
+        let mut throttle = EventThrottle::new(1000);
+        assert_eq!(throttle.check(now, "event1"), ThrottleResult::Emit);
+        assert_eq!(
+            throttle.check(now + Duration::from_millis(400), "event2"),
+            ThrottleResult::Delay(Duration::from_millis(600))
+        );
+        assert_eq!(
+            throttle.check(now + Duration::from_millis(500), "event3"),
+            ThrottleResult::Replace
+        );
+        let flush_time = now + Duration::from_millis(1000);
+        assert_eq!(
+            throttle.take_pending(flush_time),
+            Some("event3".to_string())
+        );
+        assert_eq!(throttle.take_pending(flush_time), None);
+        assert_eq!(
+            throttle.check(now + Duration::from_millis(2000), "event4"),
+            ThrottleResult::Emit
+        );
+
+        let mut unlimited = EventThrottle::new(0);
+        assert_eq!(unlimited.check(now, "event1"), ThrottleResult::Emit);
+        assert_eq!(unlimited.check(now, "event2"), ThrottleResult::Emit);
+    }
+
+    #[test]
+    fn test_check_event_name() {
+        assert!(check_event_name("SHUTDOWN").is_ok());
+        assert!(check_event_name("BALLOON_CHANGED").is_ok());
+        assert!(check_event_name("Shutdown").is_err());
+        assert!(check_event_name("UNKNOWN").is_err());
+    }
+}
@@ -13,6 +13,7 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use super::qmp_channel::QMP_CAPABILITIES;
 use super::qmp_schema::{self as schema};
 
 /// Qmp greeting message.
@@ -70,7 +71,7 @@ impl QmpGreeting {
     /// * `major` - Major version number.
     pub(crate) fn create_greeting(micro: u8, minor: u8, major: u8) -> Self {
         let version = Version::new(micro, minor, major);
-        let cap: Vec<String> = Default::default();
+        let cap: Vec<String> = QMP_CAPABILITIES.iter().map(|cap| cap.to_string()).collect();
         let greeting = Greeting {
             version,
             capabilities: cap,
@@ -198,7 +199,7 @@ mod tests {
                         },
                         "package": "StratoVirt-2.3.0"
                     },
-                    "capabilities": []
+                    "capabilities": ["oob"]
                 }
             }
         "#;
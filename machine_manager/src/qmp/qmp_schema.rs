@@ -12,6 +12,8 @@
 
 pub use serde_json::Value as Any;
 
+use std::collections::BTreeMap;
+
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use strum_macros::{EnumIter, EnumString, EnumVariantNames};
@@ -95,6 +97,12 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    system_wakeup {
+        #[serde(default)]
+        arguments: system_wakeup,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     device_add {
         arguments: Box<device_add>,
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -117,6 +125,24 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "chardev-change")]
+    chardev_change {
+        arguments: chardev_change,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "object-add")]
+    object_add {
+        arguments: object_add,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "object-del")]
+    object_del {
+        arguments: object_del,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     netdev_add {
         arguments: Box<netdev_add>,
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -198,6 +224,137 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "balloon-cancel")]
+    balloon_cancel {
+        #[serde(default)]
+        arguments: balloon_cancel,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-balloon-stats-interval")]
+    set_balloon_stats_interval {
+        arguments: set_balloon_stats_interval,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-balloon-stats")]
+    query_balloon_stats {
+        #[serde(default)]
+        arguments: query_balloon_stats,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-balloon-policy")]
+    set_balloon_policy {
+        arguments: set_balloon_policy,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-balloon-policy")]
+    query_balloon_policy {
+        #[serde(default)]
+        arguments: query_balloon_policy,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "reclaim-guest-memory")]
+    reclaim_guest_memory {
+        #[serde(default)]
+        arguments: reclaim_guest_memory,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-vm-config")]
+    query_vm_config {
+        #[serde(default)]
+        arguments: query_vm_config,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "pflash-seal")]
+    pflash_seal {
+        arguments: pflash_seal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-interrupts")]
+    query_interrupts {
+        #[serde(default)]
+        arguments: query_interrupts,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-stats")]
+    query_stats {
+        arguments: query_stats,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-halt-poll")]
+    set_halt_poll {
+        arguments: set_halt_poll,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-halt-poll")]
+    query_halt_poll {
+        #[serde(default)]
+        arguments: query_halt_poll,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-log-level")]
+    set_log_level {
+        arguments: set_log_level,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-log-level")]
+    query_log_level {
+        #[serde(default)]
+        arguments: query_log_level,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "logfile-reopen")]
+    logfile_reopen {
+        #[serde(default)]
+        arguments: logfile_reopen,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-logfile")]
+    query_logfile {
+        #[serde(default)]
+        arguments: query_logfile,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "cpu-throttle-set")]
+    cpu_throttle_set {
+        arguments: cpu_throttle_set,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "display-channel-add")]
+    display_channel_add {
+        arguments: display_channel_add,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "display-channel-del")]
+    display_channel_del {
+        arguments: display_channel_del,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-display-channels")]
+    query_display_channels {
+        #[serde(default)]
+        arguments: query_display_channels,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-vnc")]
     #[strum(serialize = "query-vnc")]
     query_vnc {
@@ -226,6 +383,29 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "migrate-set-parameters")]
+    #[strum(serialize = "migrate-set-parameters")]
+    migrate_set_parameters {
+        #[serde(default)]
+        arguments: migrate_set_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "migrate-set-capabilities")]
+    #[strum(serialize = "migrate-set-capabilities")]
+    migrate_set_capabilities {
+        arguments: migrate_set_capabilities,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-migrate-parameters")]
+    #[strum(serialize = "query-migrate-parameters")]
+    query_migrate_parameters {
+        #[serde(default)]
+        arguments: query_migrate_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-version")]
     query_version {
         #[serde(default)]
@@ -419,6 +599,58 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    set_link {
+        arguments: set_link,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-mac")]
+    set_mac {
+        arguments: set_mac,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-vm-generation-id")]
+    set_vm_generation_id {
+        arguments: set_vm_generation_id,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-vm-generation-id")]
+    query_vm_generation_id {
+        #[serde(default)]
+        arguments: query_vm_generation_id,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "rtc-reset-reinjection")]
+    rtc_reset_reinjection {
+        #[serde(default)]
+        arguments: rtc_reset_reinjection,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-rtc")]
+    query_rtc {
+        #[serde(default)]
+        arguments: query_rtc,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-uuid")]
+    query_uuid {
+        #[serde(default)]
+        arguments: query_uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-name")]
+    query_name {
+        #[serde(default)]
+        arguments: query_name,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "human-monitor-command")]
     human_monitor_command {
         arguments: human_monitor_command,
@@ -437,6 +669,103 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "block-dirty-bitmap-add")]
+    block_dirty_bitmap_add {
+        arguments: block_dirty_bitmap_add,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block-dirty-bitmap-clear")]
+    block_dirty_bitmap_clear {
+        arguments: block_dirty_bitmap,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block-dirty-bitmap-remove")]
+    block_dirty_bitmap_remove {
+        arguments: block_dirty_bitmap,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block-dirty-bitmap-export")]
+    block_dirty_bitmap_export {
+        arguments: block_dirty_bitmap_export,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "nbd-server-start")]
+    nbd_server_start {
+        arguments: nbd_server_start,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "nbd-server-add")]
+    nbd_server_add {
+        arguments: nbd_server_add,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "blockdev-snapshot-sync")]
+    blockdev_snapshot_sync {
+        arguments: blockdev_snapshot_sync,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "eject")]
+    eject {
+        arguments: eject,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "change")]
+    change {
+        arguments: change,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "drive-mirror")]
+    drive_mirror {
+        arguments: drive_mirror,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block-job-complete")]
+    block_job_complete {
+        arguments: block_job,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block-job-cancel")]
+    block_job_cancel {
+        arguments: block_job,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-jobs")]
+    query_jobs {
+        #[serde(default)]
+        arguments: query_jobs,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-pause")]
+    job_pause {
+        arguments: job,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-resume")]
+    job_resume {
+        arguments: job,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-cancel")]
+    job_cancel {
+        arguments: job,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
 }
 
 /// Command trait for Deserialize and find back Response.
@@ -454,10 +783,30 @@ trait Command: Serialize {
 /// ```text
 /// -> { "execute": "qmp_capabilities" }
 /// <- { "return": {} }
+/// -> { "execute": "qmp_capabilities",
+///      "arguments": { "event-mask": [ "RESUME" ],
+///                     "event-throttle": [ { "event": "BALLOON_CHANGED", "interval": 2000 } ] } }
+/// <- { "return": {} }
+/// -> { "execute": "qmp_capabilities", "arguments": { "enable": [ "oob" ] } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct qmp_capabilities {}
+pub struct qmp_capabilities {
+    pub enable: Option<Vec<String>>,
+    #[serde(rename = "event-mask")]
+    pub event_mask: Option<Vec<String>>,
+    #[serde(rename = "event-throttle")]
+    pub event_throttle: Option<Vec<EventThrottleOptions>>,
+}
+
+/// Minimum interval in milliseconds between two events of the same type, 0 means no limit.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventThrottleOptions {
+    pub event: String,
+    pub interval: u64,
+}
 
 impl Command for qmp_capabilities {
     type Res = Empty;
@@ -580,6 +929,28 @@ impl Command for system_reset {
     }
 }
 
+/// system_wakeup
+///
+/// Wake up guest from suspend.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "system_wakeup" }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct system_wakeup {}
+
+impl Command for system_wakeup {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
 /// device_add
 ///
 /// # Arguments
@@ -608,6 +979,8 @@ pub struct device_add {
     pub addr: Option<String>,
     #[serde(rename = "lun")]
     pub lun: Option<usize>,
+    #[serde(rename = "scsi-id")]
+    pub scsi_id: Option<u8>,
     #[serde(rename = "drive")]
     pub drive: Option<String>,
     #[serde(rename = "romfile")]
@@ -650,6 +1023,20 @@ pub struct device_add {
     pub productid: Option<String>,
     pub isobufs: Option<String>,
     pub isobsize: Option<String>,
+    pub memdev: Option<String>,
+    pub node: Option<u32>,
+    pub size: Option<u64>,
+    pub readonly: Option<bool>,
+    #[serde(rename = "guest-cid")]
+    pub guest_cid: Option<u64>,
+    pub rng: Option<String>,
+    #[serde(rename = "max-bytes")]
+    pub max_bytes: Option<u64>,
+    pub period: Option<u64>,
+    #[serde(rename = "max-frames")]
+    pub max_frames: Option<u32>,
+    #[serde(rename = "max-usecs")]
+    pub max_usecs: Option<u32>,
 }
 
 pub type DeviceAddArgument = device_add;
@@ -725,8 +1112,8 @@ impl Command for update_region {
 pub struct FileOptions {
     pub driver: String,
     pub filename: String,
-    #[serde(default)]
-    pub aio: AioEngine,
+    /// The aio engine, it's selected by probing the host if not set.
+    pub aio: Option<AioEngine>,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -779,6 +1166,14 @@ pub struct blockdev_add {
     pub l2_cache_size: Option<String>,
     #[serde(rename = "refcount-cache-size")]
     pub refcount_cache_size: Option<String>,
+    #[serde(rename = "key-secret")]
+    pub key_secret: Option<String>,
+    #[serde(rename = "copy-on-read")]
+    pub copy_on_read: Option<bool>,
+    #[serde(rename = "io-timeout")]
+    pub io_timeout: Option<u64>,
+    #[serde(rename = "io-timeout-action")]
+    pub io_timeout_action: Option<String>,
 }
 
 pub type BlockDevAddArgument = blockdev_add;
@@ -798,6 +1193,7 @@ impl Command for blockdev_add {
 /// * `id` - the device's ID, must be unique.
 /// * `ifname` - the backend tap dev name.
 /// * `fds` - the file fd opened by upper level.
+/// * `sndbuf` - the send buffer size of tap in bytes.
 ///
 /// Additional arguments depend on the type.
 ///
@@ -826,6 +1222,10 @@ pub struct netdev_add {
     pub script: Option<String>,
     pub queues: Option<u16>,
     pub chardev: Option<String>,
+    pub sndbuf: Option<u64>,
+    #[serde(rename = "poll-us")]
+    pub poll_us: Option<u32>,
+    pub zerocopy: Option<bool>,
 }
 
 pub type NetDevAddArgument = netdev_add;
@@ -890,8 +1290,12 @@ pub struct AddrOptions {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct BackendDataOptions {
+    #[serde(default)]
     pub addr: AddrOptions,
+    #[serde(default)]
     pub server: bool,
+    /// Output file of `file` backend.
+    pub out: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -938,31 +1342,55 @@ impl Command for chardev_add {
     }
 }
 
-/// chardev-remove
+/// object-add
 ///
-/// Remove a chardev backend.
+/// Create a new object at runtime.
 ///
 /// # Arguments
 ///
-/// * `id` - The ID of the character device.
-///
-/// # Errors
-///
-/// If `id` is not a valid chardev backend, DeviceNotFound
+/// * `qom-type` - the type of the object, `iothread`, `secret`, `memory-backend-ram`,
+///   `memory-backend-file`, `rng-random`, `filter-mirror` or `filter-redirector`.
+/// * `id` - the object's ID, must be unique.
+/// * `data` - the data of `secret`.
+/// * `file` - the file which contains the data of `secret`.
+/// * `size` - the size in bytes of `memory-backend-ram` or `memory-backend-file`.
+/// * `mem-path` - the host file which backs `memory-backend-file`.
+/// * `share` - whether the memory of `memory-backend-file` is shared with other processes.
+/// * `netdev` - the netdev which the net filter is attached to.
+/// * `queue` - the direction of packets handled by the net filter, `all`, `rx` or `tx`.
+/// * `outdev` - the chardev which the net filter sends packets to.
+/// * `indev` - the chardev which the net filter receives packets from.
+/// * `filename` - the host character device which `rng-random` reads from.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "chardev-remove", "arguments": { "id": "chardev_id" } }
+/// -> { "execute": "object-add",
+///      "arguments": { "qom-type": "iothread", "id": "iothread1" } }
 /// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct chardev_remove {
+pub struct object_add {
+    #[serde(rename = "qom-type")]
+    pub qom_type: String,
     pub id: String,
+    pub data: Option<String>,
+    pub file: Option<String>,
+    pub netdev: Option<String>,
+    pub queue: Option<String>,
+    pub outdev: Option<String>,
+    pub indev: Option<String>,
+    pub size: Option<u64>,
+    #[serde(rename = "mem-path")]
+    pub mem_path: Option<String>,
+    pub share: Option<bool>,
+    pub filename: Option<String>,
 }
 
-impl Command for chardev_remove {
+pub type ObjectAddArgument = object_add;
+
+impl Command for object_add {
     type Res = Empty;
 
     fn back(self) -> Empty {
@@ -970,56 +1398,27 @@ impl Command for chardev_remove {
     }
 }
 
-/// device_del
+/// object-del
 ///
-/// Remove a device from a guest
+/// Remove an object. The object must not be used by any device.
 ///
 /// # Arguments
 ///
-/// * `id` - the device's ID or QOM path.
-///
-/// # Errors
-///
-/// If `id` is not a valid device, DeviceNotFound.
-///
-/// # Notes
-///
-/// When this command completes, the device may not be removed from the
-/// guest. Hot removal is an operation that requires guest cooperation.
-/// This command merely requests that the guest begin the hot removal
-/// process. Completion of the device removal process is signaled with a
-/// DEVICE_DELETED event. Guest reset will automatically complete removal
-/// for all devices.
+/// * `id` - the ID of the object.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "device_del",
-///      "arguments": { "id": "net-0" } }
+/// -> { "execute": "object-del", "arguments": { "id": "iothread1" } }
 /// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct device_del {
+pub struct object_del {
     pub id: String,
 }
 
-impl Command for device_del {
-    type Res = Empty;
-
-    fn back(self) -> Empty {
-        Default::default()
-    }
-}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct blockdev_del {
-    #[serde(rename = "node-name")]
-    pub node_name: String,
-}
-
-impl Command for blockdev_del {
+impl Command for object_del {
     type Res = Empty;
 
     fn back(self) -> Empty {
@@ -1027,17 +1426,146 @@ impl Command for blockdev_del {
     }
 }
 
-/// netdev_del
+/// chardev-remove
 ///
-/// Remove a network backend.
+/// Remove a chardev backend.
 ///
 /// # Arguments
 ///
-/// * `id` - The name of the network backend to remove.
+/// * `id` - The ID of the character device.
 ///
 /// # Errors
 ///
-/// If `id` is not a valid network backend, DeviceNotFound
+/// If `id` is not a valid chardev backend, DeviceNotFound
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "chardev-remove", "arguments": { "id": "chardev_id" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct chardev_remove {
+    pub id: String,
+}
+
+impl Command for chardev_remove {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// chardev-change
+///
+/// Change the backend of a chardev which is used by a device at runtime.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the character device.
+/// * `backend` - the new chardev backend info, `socket`(server only), `file`, `pty`
+///   and `stdio` are supported.
+///
+/// # Errors
+///
+/// If `id` is not a valid chardev backend, DeviceNotFound
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "chardev-change",
+///      "arguments": { "id": "chardev_id", "backend": { "type": "socket", "data": {
+///            "addr": { "type": "unix", "data": { "path": "/path/to/new/socket" } },
+///            "server": true }}}}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct chardev_change {
+    pub id: String,
+    pub backend: BackendOptions,
+}
+
+pub type CharDevChangeArgument = chardev_change;
+
+impl Command for chardev_change {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// device_del
+///
+/// Remove a device from a guest
+///
+/// # Arguments
+///
+/// * `id` - the device's ID or QOM path.
+///
+/// # Errors
+///
+/// If `id` is not a valid device, DeviceNotFound.
+///
+/// # Notes
+///
+/// When this command completes, the device may not be removed from the
+/// guest. Hot removal is an operation that requires guest cooperation.
+/// This command merely requests that the guest begin the hot removal
+/// process. Completion of the device removal process is signaled with a
+/// DEVICE_DELETED event. Guest reset will automatically complete removal
+/// for all devices.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "device_del",
+///      "arguments": { "id": "net-0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct device_del {
+    pub id: String,
+}
+
+impl Command for device_del {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct blockdev_del {
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+}
+
+impl Command for blockdev_del {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// netdev_del
+///
+/// Remove a network backend.
+///
+/// # Arguments
+///
+/// * `id` - The name of the network backend to remove.
+///
+/// # Errors
+///
+/// If `id` is not a valid network backend, DeviceNotFound
 ///
 /// # Examples
 ///
@@ -1184,7 +1712,9 @@ pub struct CpuInstanceProperties {
 ///             "halted":false,
 ///             "qom_path":"/machine/unattached/device[0]",
 ///             "arch":"x86",
-///             "thread_id":3134
+///             "thread_id":3134,
+///             "model":"Skylake-Server",
+///             "features":["fpu","de","pse","tsc","msr","pae","mce","cx8","apic"]
 ///          },
 ///          {
 ///             "CPU":1,
@@ -1192,7 +1722,9 @@ pub struct CpuInstanceProperties {
 ///             "halted":true,
 ///             "qom_path":"/machine/unattached/device[2]",
 ///             "arch":"x86",
-///             "thread_id":3135
+///             "thread_id":3135,
+///             "model":"Skylake-Server",
+///             "features":["fpu","de","pse","tsc","msr","pae","mce","cx8","apic"]
 ///          }
 ///       ]
 ///    }
@@ -1247,7 +1779,12 @@ pub enum CpuInfo {
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct CpuInfoX86 {}
+pub struct CpuInfoX86 {
+    #[serde(rename = "model")]
+    pub model: String,
+    #[serde(rename = "features", default)]
+    pub features: Vec<String>,
+}
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct CpuInfoArm {}
@@ -1336,10 +1873,22 @@ pub enum RunState {
 /// # Arguments
 ///
 /// * `uri` - the Uniform Resource Identifier of the destination VM or file.
+/// * `job-id` - run the migration as a background job with this id, the job
+///   handle is returned at once and the result is reported by job events.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate",
+///      "arguments": { "uri": "file:/path/to/template", "job-id": "migrate-0" } }
+/// <- { "return": { "job-id": "migrate-0" } }
+/// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct migrate {
     #[serde(rename = "uri")]
     pub uri: String,
+    #[serde(rename = "job-id", default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
 }
 
 impl Command for migrate {
@@ -1382,6 +1931,134 @@ impl Command for cancel_migrate {
 pub struct MigrationInfo {
     #[serde(rename = "status", default, skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    #[serde(
+        rename = "cpu-throttle-percentage",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cpu_throttle_percentage: Option<u8>,
+}
+
+/// migrate-set-parameters
+///
+/// Set migration parameters. Only the given parameters are changed.
+///
+/// # Arguments
+///
+/// * `max-bandwidth` - maximum speed of migration in bytes per second, 0 means no limit.
+/// * `downtime-limit` - maximum tolerated downtime of migration in milliseconds.
+/// * `compress-threads` - number of threads used to compress migration data.
+/// * `multifd-channels` - number of channels used to migrate data in parallel, only 1
+///   is supported.
+/// * `cpu-throttle-initial` - initial percentage of time vCPUs sleep when auto-converge
+///   starts throttling.
+/// * `cpu-throttle-increment` - percentage added to the throttling on each iteration
+///   that doesn't converge.
+/// * `max-cpu-throttle` - maximum percentage of time vCPUs sleep by auto-converge.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate-set-parameters",
+///      "arguments": { "max-bandwidth": 33554432, "downtime-limit": 300 } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct migrate_set_parameters {
+    #[serde(
+        rename = "max-bandwidth",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_bandwidth: Option<u64>,
+    #[serde(
+        rename = "downtime-limit",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub downtime_limit: Option<u64>,
+    #[serde(
+        rename = "compress-threads",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compress_threads: Option<u8>,
+    #[serde(
+        rename = "multifd-channels",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub multifd_channels: Option<u8>,
+    #[serde(
+        rename = "cpu-throttle-initial",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cpu_throttle_initial: Option<u8>,
+    #[serde(
+        rename = "cpu-throttle-increment",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cpu_throttle_increment: Option<u8>,
+    #[serde(
+        rename = "max-cpu-throttle",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_cpu_throttle: Option<u8>,
+}
+pub type MigrateSetParametersArgument = migrate_set_parameters;
+
+impl Command for migrate_set_parameters {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-migrate-parameters
+///
+/// Returns the current migration parameters.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-migrate-parameters" }
+/// <- { "return": { "max-bandwidth": 0, "downtime-limit": 50,
+///                  "compress-threads": 8, "multifd-channels": 1,
+///                  "cpu-throttle-initial": 20, "cpu-throttle-increment": 10,
+///                  "max-cpu-throttle": 99 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_migrate_parameters {}
+
+impl Command for query_migrate_parameters {
+    type Res = MigrationParameters;
+
+    fn back(self) -> MigrationParameters {
+        Default::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationParameters {
+    #[serde(rename = "max-bandwidth")]
+    pub max_bandwidth: u64,
+    #[serde(rename = "downtime-limit")]
+    pub downtime_limit: u64,
+    #[serde(rename = "compress-threads")]
+    pub compress_threads: u8,
+    #[serde(rename = "multifd-channels")]
+    pub multifd_channels: u8,
+    #[serde(rename = "cpu-throttle-initial")]
+    pub cpu_throttle_initial: u8,
+    #[serde(rename = "cpu-throttle-increment")]
+    pub cpu_throttle_increment: u8,
+    #[serde(rename = "max-cpu-throttle")]
+    pub max_cpu_throttle: u8,
 }
 
 /// getfd
@@ -1430,6 +2107,8 @@ pub struct Shutdown {
     /// action) rather than a host request (such as sending StratoVirt a SIGINT).
     #[serde(rename = "guest")]
     pub guest: bool,
+    /// The cause of the shutdown, one of `guest-shutdown`, `guest-reset`, `guest-panic`,
+    /// `watchdog`, `host-qmp-quit`, `host-qmp-system-reset` or `host-signal`.
     pub reason: String,
 }
 
@@ -1444,6 +2123,8 @@ pub struct Reset {
     /// ) rather than a host request (such as the QMP command system_reset).
     #[serde(rename = "guest")]
     pub guest: bool,
+    /// The cause of the reset, e.g. `guest-reset`, `watchdog` or `host-qmp-system-reset`.
+    pub reason: String,
 }
 
 /// Stop
@@ -1467,6 +2148,20 @@ pub struct Resume {}
 #[serde(deny_unknown_fields)]
 pub struct Powerdown {}
 
+/// Suspend
+///
+/// Emitted when guest enters a hardware suspension state (S3)
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Suspend {}
+
+/// Wakeup
+///
+/// Emitted when the guest has woken up from suspend state
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Wakeup {}
+
 /// DeviceDeleted
 ///
 /// Emitted whenever the device removal completion is acknowledged by the guest.
@@ -1492,6 +2187,99 @@ pub struct DeviceDeleted {
     pub path: String,
 }
 
+/// JobStatusChange
+///
+/// Emitted when the status of a background job changes.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "JOB_STATUS_CHANGE",
+///      "data": { "id": "drive-0", "status": "ready" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct JobStatusChange {
+    /// Job id.
+    pub id: String,
+    /// New status of the job.
+    pub status: String,
+}
+
+/// JobCompleted
+///
+/// Emitted when a background job is completed, cancelled or failed.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "JOB_COMPLETED",
+///      "data": { "id": "drive-0", "type": "mirror", "current-progress": 1073741824,
+///                "total-progress": 1073741824, "cancelled": false },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct JobCompleted {
+    /// Job id.
+    pub id: String,
+    /// Type of the job.
+    #[serde(rename = "type")]
+    pub job_type: String,
+    #[serde(rename = "current-progress")]
+    pub current_progress: u64,
+    #[serde(rename = "total-progress")]
+    pub total_progress: u64,
+    /// Whether the job is cancelled.
+    pub cancelled: bool,
+    /// Error message if the job failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// BlockIoTimeout
+///
+/// Emitted when the IO requests of the drive are not completed within the timeout.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "BLOCK_IO_TIMEOUT",
+///      "data": { "device": "drive-0", "count": 3, "timeout": 30, "action": "fail" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BlockIoTimeout {
+    /// Id of the drive.
+    pub device: String,
+    /// Number of the newly expired requests.
+    pub count: u64,
+    /// Timeout in seconds.
+    pub timeout: u64,
+    /// Action on the expired requests, `report` or `fail`.
+    pub action: String,
+}
+
+/// RtcChange
+///
+/// Emitted when the guest changes the time of RTC.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "RTC_CHANGE",
+///      "data": { "offset": 78 },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RtcChange {
+    /// Offset in seconds between the time of RTC and the host time in `-rtc base`.
+    pub offset: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
 #[serde(tag = "event")]
 pub enum QmpEvent {
@@ -1520,16 +2308,48 @@ pub enum QmpEvent {
         data: Powerdown,
         timestamp: TimeStamp,
     },
-    #[serde(rename = "DEVICE_DELETED")]
-    DeviceDeleted {
-        data: DeviceDeleted,
+    #[serde(rename = "SUSPEND")]
+    Suspend {
+        #[serde(default)]
+        data: Suspend,
         timestamp: TimeStamp,
     },
-    #[serde(rename = "BALLOON_CHANGED")]
+    #[serde(rename = "WAKEUP")]
+    Wakeup {
+        #[serde(default)]
+        data: Wakeup,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "DEVICE_DELETED")]
+    DeviceDeleted {
+        data: DeviceDeleted,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "BALLOON_CHANGED")]
     BalloonChanged {
         data: BalloonInfo,
         timestamp: TimeStamp,
     },
+    #[serde(rename = "JOB_STATUS_CHANGE")]
+    JobStatusChange {
+        data: JobStatusChange,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "JOB_COMPLETED")]
+    JobCompleted {
+        data: JobCompleted,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "BLOCK_IO_TIMEOUT")]
+    BlockIoTimeout {
+        data: BlockIoTimeout,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "RTC_CHANGE")]
+    RtcChange {
+        data: RtcChange,
+        timestamp: TimeStamp,
+    },
 }
 
 /// query-balloon:
@@ -1538,13 +2358,14 @@ pub enum QmpEvent {
 ///
 /// # Returns
 ///
-/// `BalloonInfo` includs the actual size of memory
+/// `BalloonInfo` includs the actual size of memory and the target size which
+/// is set by `balloon`. The resizing is in progress if they are different.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-balloon" }
-/// <- {"return":{"actual":8589934592}}
+/// <- {"return":{"actual":8589934592,"target":4294967296}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct query_balloon {}
@@ -1558,808 +2379,2264 @@ impl Command for query_balloon {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct BalloonInfo {
     pub actual: u64,
+    /// Target memory size of VM, it's not reported by `BALLOON_CHANGED` event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<u64>,
 }
 
-/// query-vnc:
-/// Information about current VNC server.
+/// balloon-cancel:
 ///
-/// # Examples
+/// Cancel the resizing of memory in progress, the target memory size is set to the
+/// actual size at once, so that the balloon is not inflated or deflated any more.
+/// It's useful if the target can't be reached by guest.
+///
+/// # Example
 ///
-/// For pc machine type started with -vnc ip:port(for example: 0.0.0.0:0):
 /// ```text
-/// -> { "execute": "query-vnc" }
-/// <- {"return": {
-///         "enabled": true,
-///         "host": "0.0.0.0",
-///         "service": "50401",
-///         "auth": "None",
-///         "family": "ipv4",
-///         "clients": [
-///             "host": "127.0.0.1",
-///             "service": "50401",
-///             "family": "ipv4",
-///         ]
-///         }
-///     }
+/// -> { "execute": "balloon-cancel" }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_vnc {}
-impl Command for query_vnc {
-    type Res = VncInfo;
-    fn back(self) -> VncInfo {
-        Default::default()
-    }
-}
+pub struct balloon_cancel {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct VncInfo {
-    #[serde(rename = "enabled")]
-    pub enabled: bool,
-    #[serde(rename = "host")]
-    pub host: String,
-    #[serde(rename = "service")]
-    pub service: String,
-    #[serde(rename = "auth")]
-    pub auth: String,
-    #[serde(rename = "family")]
-    pub family: String,
-    #[serde(rename = "clients")]
-    pub clients: Vec<VncClientInfo>,
-}
+impl Command for balloon_cancel {
+    type Res = Empty;
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct VncClientInfo {
-    #[serde(rename = "host")]
-    pub host: String,
-    #[serde(rename = "service")]
-    pub service: String,
-    #[serde(rename = "family")]
-    pub family: String,
+    fn back(self) -> Empty {
+        Default::default()
+    }
 }
 
-/// balloon:
+/// set-balloon-stats-interval:
 ///
-/// Advice VM to change memory size with the argument `value`.
+/// Set the interval to poll the memory statistics of guest by balloon device,
+/// `guest-stats` of the balloon device must be enabled.
 ///
 /// # Arguments
 ///
-/// * `value` - Memoey size.
-///
-/// # Notes
-///
-/// This is only an advice instead of command to VM,
-/// therefore, the VM changes its memory according to `value` and its condation.
+/// * `interval` - Polling interval in seconds, 0 to stop polling.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "balloon", "arguments": { "value": 589934492 } }
+/// -> { "execute": "set-balloon-stats-interval", "arguments": { "interval": 2 } }
 /// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct balloon {
-    #[serde(rename = "value")]
-    pub value: u64,
+#[serde(deny_unknown_fields)]
+pub struct set_balloon_stats_interval {
+    pub interval: u32,
 }
 
-impl Command for balloon {
+impl Command for set_balloon_stats_interval {
     type Res = Empty;
     fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// version:
+/// query-balloon-stats:
 ///
-/// Query version of StratoVirt.
+/// Query the latest memory statistics reported by guest.
+///
+/// # Returns
+///
+/// `BalloonStats` includes the time of last update in seconds since the epoch
+/// and the statistics, only the statistics reported by guest are included.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-version" }
-/// <- {"return":{"version":{"qemu":{"minor":1,"micro":0,"major":5},"package":"StratoVirt-2.3.0"},"capabilities":[]}}
+/// -> { "execute": "query-balloon-stats" }
+/// <- {"return":{"last-update":1700000000,"stats":{"stat-available-memory":1790091264,
+///     "stat-free-memory":1633808384,"stat-total-memory":2058153984}}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_version {}
-
-impl Command for query_version {
-    type Res = Version;
+pub struct query_balloon_stats {}
 
-    fn back(self) -> Version {
+impl Command for query_balloon_stats {
+    type Res = BalloonStats;
+    fn back(self) -> BalloonStats {
         Default::default()
     }
 }
 
-/// Query commands:
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BalloonStats {
+    #[serde(rename = "last-update")]
+    pub last_update: u64,
+    pub stats: BTreeMap<String, u64>,
+}
+
+/// set-balloon-policy:
 ///
-/// Query all qmp commands of StratoVirt.
+/// Enable, disable or tune the automatic ballooning policy. The policy adjusts the
+/// memory size of guest periodically according to the memory statistics of guest
+/// and the available memory of host. The arguments which are not given keep the
+/// current values.
+///
+/// # Arguments
+///
+/// * `enable` - Enable or disable the policy.
+/// * `interval` - Interval in seconds to adjust the memory size, default is 10.
+/// * `min-size` - Lower bound of the memory size of guest, default is 0.
+/// * `max-size` - Upper bound of the memory size of guest, default is the RAM size.
+/// * `free-percent` - Percentage of guest memory to be kept available, default is 20.
+/// * `host-min-free` - Memory of guest is not grown if the available memory of host
+///   is below it, default is 1GiB.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-commands" }
-/// <- {"return":[{"name":"qmp_capabilities"},{"name":"quit"},{"name":"stop"},{"name":"cont"},
-/// {"name":"system_powerdown"},{"name":"system_reset"},{"name":"device_add"},{"name":"device_del"},
-/// {"name":"chardev_add"},{"name":"chardev_remove"},{"name":"netdev_add"},{"name":"netdev_del"},
-/// {"name":"cameradev_add"},{"name":"cameradev_del"},{"name":"query-hotpluggable-cpus"},
-/// {"name":"query-cpus"},{"name":"query_status"},{"name":"getfd"},{"name":"blockdev_add"},
-/// {"name":"blockdev_del"},{"name":"balloon"},{"name":"query_balloon"},{"name":"query-vnc"},
-/// {"name":"migrate"},{"name":"query_migrate"},{"name":"cancel_migrate"},{"name":"query_version"},
-/// {"name":"query_commands"},{"name":"query_target"},{"name":"query_kvm"},{"name":"query_machines"},
-/// {"name":"query-events"},{"name":"list_type"},{"name":"device_list_properties"},{"name":"block-commit"},
-/// {"name":"query_tpm_models"},{"name":"query_tpm_types"},{"name":"query_command_line_options"},
-/// {"name":"query_migrate_capabilities"},{"name":"query_qmp_schema"},{"name":"query_sev_capabilities"},
-/// {"name":"query-chardev"},{"name":"qom-list"},{"name":"qom_get"},{"name":"query-block"},{"name":"query-named-block-nodes"},
-/// {"name":"query-blockstats"},{"name":"query-block-jobs"},{"name":"query-gic-capabilities"},{"name":"query-iothreads"},
-/// {"name":"update_region"},{"name":"input_event"},{"name":"human_monitor_command"}]}
+/// -> { "execute": "set-balloon-policy",
+///      "arguments": { "enable": true, "min-size": 1073741824, "free-percent": 30 } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_commands {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Cmd {
-    pub name: String,
+#[serde(deny_unknown_fields)]
+pub struct set_balloon_policy {
+    pub enable: bool,
+    pub interval: Option<u32>,
+    #[serde(rename = "min-size")]
+    pub min_size: Option<u64>,
+    #[serde(rename = "max-size")]
+    pub max_size: Option<u64>,
+    #[serde(rename = "free-percent")]
+    pub free_percent: Option<u32>,
+    #[serde(rename = "host-min-free")]
+    pub host_min_free: Option<u64>,
 }
+pub type BalloonPolicyArgument = set_balloon_policy;
 
-impl Command for query_commands {
-    type Res = Vec<Cmd>;
-
-    fn back(self) -> Vec<Cmd> {
+impl Command for set_balloon_policy {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query target:
+/// reclaim-guest-memory:
 ///
-/// Query the target platform where the StratoVirt is running.
+/// Reclaim the host memory of a paused VM. The content of guest memory is kept, the
+/// reclaimed pages are swapped out or dropped from page cache by the host, and are
+/// faulted back in once the VM is resumed.
+///
+/// # Arguments
+///
+/// * `mode` - `cold` to deactivate the pages so that they are reclaimed first under
+///   memory pressure, or `pageout` to reclaim them at once. Default is `pageout`.
+///
+/// # Returns
+///
+/// `ReclaimGuestMemoryInfo` includes the size of the advised guest memory.
 ///
 /// # Example
 ///
 /// ```text
-/// # for X86 platform.
-/// -> { "execute": "query-target" }
-/// <- {"return":{"arch":"x86_64"}}
-///
-/// # for Aarch64 platform.
-/// -> { "execute": "query-target" }
-/// <- {"return":{"arch":"aarch64"}}
+/// -> { "execute": "reclaim-guest-memory", "arguments": { "mode": "pageout" } }
+/// <- {"return":{"size":2147483648}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_target {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Target {
-    pub arch: String,
+#[serde(deny_unknown_fields)]
+pub struct reclaim_guest_memory {
+    pub mode: Option<String>,
 }
+pub type ReclaimGuestMemoryArgument = reclaim_guest_memory;
 
-impl Command for query_target {
-    type Res = Target;
-
-    fn back(self) -> Target {
+impl Command for reclaim_guest_memory {
+    type Res = ReclaimGuestMemoryInfo;
+    fn back(self) -> ReclaimGuestMemoryInfo {
         Default::default()
     }
 }
 
-/// Query machines:
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ReclaimGuestMemoryInfo {
+    pub size: u64,
+}
+
+/// query-balloon-policy:
 ///
-/// Query machine information.
+/// Query the automatic ballooning policy.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-machines" }
-/// <- {"return":[{"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"none","numa-mem-supported":false},
-/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"microvm","numa-mem-supported":false},
-/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"standardvm","numa-mem-supported":false}]}
+/// -> { "execute": "query-balloon-policy" }
+/// <- {"return":{"enabled":true,"interval":10,"min-size":1073741824,"max-size":4294967296,
+///     "free-percent":30,"host-min-free":1073741824}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_machines {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct MachineInfo {
-    #[serde(rename = "hotpluggable-cpus")]
-    pub hotplug: bool,
-    pub name: String,
-    #[serde(rename = "numa-mem-supported")]
-    pub numa_mem_support: bool,
-    #[serde(rename = "cpu-max")]
-    pub cpu_max: u8,
-    pub deprecated: bool,
-}
-
-impl Command for query_machines {
-    type Res = Vec<MachineInfo>;
+pub struct query_balloon_policy {}
 
-    fn back(self) -> Vec<MachineInfo> {
+impl Command for query_balloon_policy {
+    type Res = BalloonPolicyInfo;
+    fn back(self) -> BalloonPolicyInfo {
         Default::default()
     }
 }
 
-/// Query events:
+/// query-vm-config:
 ///
-/// Query all events of StratoVirt.
+/// Query the effective configuration of VM, including the devices added by QMP. The
+/// result can be saved as json profile and loaded by `-config` on the next launch.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-events" }
-/// <- {"return":[{"name":"Shutdown"},{"name":"Reset"},
-/// {"name":"Stop"},{"name":"Resume"},{"name":"DeviceDeleted"},
-/// {"name":"BalloonChanged"}]}
+/// -> { "execute": "query-vm-config" }
+/// <- {"return":{"guest_name":"StratoVirt","machine_config":{...},"devices":[...],...}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Events {
-    pub name: String,
-}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_events {}
-
-impl Command for query_events {
-    type Res = Vec<Events>;
+pub struct query_vm_config {}
 
-    fn back(self) -> Vec<Events> {
+impl Command for query_vm_config {
+    type Res = crate::config::VmConfig;
+    fn back(self) -> crate::config::VmConfig {
         Default::default()
     }
 }
 
-/// Query KVM:
+/// pflash-seal:
 ///
-/// Query if KVM is enabled.
+/// Seal or unseal the pflash device. The guest can't modify the content of a sealed
+/// pflash, which is used to protect the varstore of firmware from tampering, such as
+/// the keys of Secure Boot.
+///
+/// # Arguments
+///
+/// * `unit` - The unit id of pflash device.
+/// * `sealed` - Seal or unseal the pflash device, default is true.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-kvm" }
-/// <- {"return":{"enabled":true,"present":true}}
+/// -> { "execute": "pflash-seal", "arguments": { "unit": 1 } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_kvm {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct KvmInfo {
-    pub enabled: bool,
-    pub present: bool,
+#[serde(deny_unknown_fields)]
+pub struct pflash_seal {
+    pub unit: usize,
+    pub sealed: Option<bool>,
 }
+pub type PFlashSealArgument = pflash_seal;
 
-impl Command for query_kvm {
-    type Res = KvmInfo;
-
-    fn back(self) -> KvmInfo {
+impl Command for pflash_seal {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// List all Qom type.
+/// query-interrupts:
+///
+/// Query the number of interrupts injected by StratoVirt for each device and vector.
+/// The interrupts delivered by kvm irqfd directly, such as the ones of VFIO and vhost
+/// devices, are not counted.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "qom-list-types" }
-/// <- {"return":[{"name":"ioh3420","parent":"pcie-root-port-base"},
-/// {"name":"pcie-root-port","parent":"pcie-root-port-base"},
-/// {"name":"pcie-pci-bridge","parent":"base-pci-bridge"},
-/// {"name":"pci-bridge","parent":"base-pci-bridge"}]}
+/// -> { "execute": "query-interrupts" }
+/// <- {"return":[{"device":"serial","type":"gsi","vector":4,"count":35},
+///     {"device":"virtio-blk0","type":"msix","vector":1,"count":1024}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct list_type {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct TypeLists {
-    name: String,
-    parent: String,
-}
+pub struct query_interrupts {}
 
-impl TypeLists {
-    pub fn new(name: String, parent: String) -> Self {
-        TypeLists { name, parent }
+impl Command for query_interrupts {
+    type Res = Vec<InterruptInfo>;
+    fn back(self) -> Vec<InterruptInfo> {
+        Default::default()
     }
 }
 
-impl Command for list_type {
-    type Res = Vec<TypeLists>;
-
-    fn back(self) -> Vec<TypeLists> {
-        Default::default()
-    }
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct InterruptInfo {
+    pub device: String,
+    #[serde(rename = "type")]
+    pub int_type: String,
+    pub vector: u32,
+    pub count: u64,
 }
 
-/// Get device list properties.
+/// query-stats:
+///
+/// Query the runtime statistics of the VM or its vCPUs. The schema follows QEMU, so
+/// the tools for QEMU can be reused.
+///
+/// # Arguments
+///
+/// * `target` - `vm` for the statistics summed over all vCPUs, `vcpu` for the
+///   statistics of each vCPU.
+/// * `providers` - Only return the statistics of these providers, and only the named
+///   statistics if `names` is given. (optional)
+/// * `vcpus` - Only return the statistics of the vCPUs with these QOM paths, only valid
+///   for `vcpu` target. (optional)
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "device-list-properties", "arguments": {"typename": "virtio-blk-pci"} }
-/// <- {"return":[]}
+/// -> { "execute": "query-stats", "arguments": { "target": "vcpu",
+///      "providers": [ { "provider": "kvm", "names": [ "exits", "halt_exits" ] } ] } }
+/// <- {"return":[{"provider":"kvm","qom-path":"/machine/unattached/device[0]",
+///     "stats":[{"name":"exits","value":25321},{"name":"halt_exits","value":8765}]}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct device_list_properties {
-    pub typename: String,
+#[serde(deny_unknown_fields)]
+pub struct query_stats {
+    pub target: StatsTarget,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub providers: Option<Vec<StatsRequest>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpus: Option<Vec<String>>,
 }
+pub type QueryStatsArgument = query_stats;
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct DeviceProps {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub prop_type: String,
+impl Command for query_stats {
+    type Res = Vec<StatsResult>;
+    fn back(self) -> Vec<StatsResult> {
+        Default::default()
+    }
 }
 
-impl Command for device_list_properties {
-    type Res = Vec<DeviceProps>;
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatsTarget {
+    #[default]
+    #[serde(rename = "vm")]
+    Vm,
+    #[serde(rename = "vcpu")]
+    Vcpu,
+}
 
-    fn back(self) -> Vec<DeviceProps> {
-        Default::default()
-    }
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatsProvider {
+    #[default]
+    #[serde(rename = "kvm")]
+    Kvm,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct block_commit {}
+#[serde(deny_unknown_fields)]
+pub struct StatsRequest {
+    pub provider: StatsProvider,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub names: Option<Vec<String>>,
+}
 
-impl Command for block_commit {
-    type Res = Vec<DeviceProps>;
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct StatsResult {
+    pub provider: StatsProvider,
+    #[serde(rename = "qom-path", default, skip_serializing_if = "Option::is_none")]
+    pub qom_path: Option<String>,
+    pub stats: Vec<Stats>,
+}
 
-    fn back(self) -> Vec<DeviceProps> {
-        Default::default()
-    }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub name: String,
+    pub value: u64,
 }
 
-/// Query tpm models of StratoVirt.
+/// set-halt-poll:
+///
+/// Set the halt polling of the vCPUs. A vCPU polls for the wakeup event for a while
+/// before halting, which saves the cost of scheduling on short idle periods at the
+/// price of host CPU time.
+///
+/// # Arguments
+///
+/// * `halt-poll-ns` - Maximum time in nanoseconds the vCPUs poll before halting, 0 to
+///   disable polling. (optional)
+/// * `adaptive` - Adjust the polling time within `halt-poll-ns` by the success rate of
+///   polling, kvm debugfs is required. (optional)
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-tpm-models" }
-/// <- {"return":[]}
+/// -> { "execute": "set-halt-poll", "arguments": { "halt-poll-ns": 400000, "adaptive": true } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_tpm_models {}
-
-impl Command for query_tpm_models {
-    type Res = Vec<String>;
+#[serde(deny_unknown_fields)]
+pub struct set_halt_poll {
+    #[serde(rename = "halt-poll-ns")]
+    pub halt_poll_ns: Option<u32>,
+    pub adaptive: Option<bool>,
+}
+pub type HaltPollArgument = set_halt_poll;
 
-    fn back(self) -> Vec<String> {
+impl Command for set_halt_poll {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query target of StratoVirt.
+/// query-halt-poll:
+///
+/// Query the halt polling of the vCPUs, `current-ns` is the polling time in effect
+/// which is adjusted in adaptive mode.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-tpm-types" }
-/// <- {"return":[]}
+/// -> { "execute": "query-halt-poll" }
+/// <- {"return":{"halt-poll-ns":400000,"adaptive":true,"current-ns":100000}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_tpm_types {}
-
-impl Command for query_tpm_types {
-    type Res = Vec<String>;
+pub struct query_halt_poll {}
 
-    fn back(self) -> Vec<String> {
+impl Command for query_halt_poll {
+    type Res = HaltPollInfo;
+    fn back(self) -> HaltPollInfo {
         Default::default()
     }
 }
 
-/// Query command line options.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct HaltPollInfo {
+    #[serde(rename = "halt-poll-ns")]
+    pub halt_poll_ns: u32,
+    pub adaptive: bool,
+    #[serde(rename = "current-ns")]
+    pub current_ns: u32,
+}
+
+/// set-log-level:
+///
+/// Set the log level of a subsystem, or all the subsystems if `subsystem` is not given.
+///
+/// # Arguments
+///
+/// * `level` - the log level, one of `off`, `error`, `warn`, `info`, `debug` and `trace`.
+/// * `subsystem` - the subsystem, one of `general`, `block`, `net`, `usb` and `migration`. (optional)
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-command-line-options" }
-/// <- {"return":[]}
+/// -> { "execute": "set-log-level", "arguments": { "level": "debug", "subsystem": "block" } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_command_line_options {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct CmdParameter {
-    pub name: String,
-    pub help: String,
-    #[serde(rename = "type")]
-    pub parameter_type: String,
-}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct CmdLine {
-    pub parameters: Vec<CmdParameter>,
-    pub option: String,
+#[serde(deny_unknown_fields)]
+pub struct set_log_level {
+    pub level: String,
+    pub subsystem: Option<String>,
 }
 
-impl Command for query_command_line_options {
-    type Res = Vec<CmdLine>;
-
-    fn back(self) -> Vec<CmdLine> {
+impl Command for set_log_level {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query capabilities of migration.
+/// query-log-level:
+///
+/// Query the log levels of all the subsystems.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-migrate-capabilities" }
-/// <- {"return":[]}
+/// -> { "execute": "query-log-level" }
+/// <- {"return":[{"subsystem":"general","level":"info"},{"subsystem":"block","level":"debug"}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_migrate_capabilities {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct MigrateCapabilities {
-    pub state: bool,
-    pub capability: String,
-}
-
-impl Command for query_migrate_capabilities {
-    type Res = Vec<MigrateCapabilities>;
+pub struct query_log_level {}
 
-    fn back(self) -> Vec<MigrateCapabilities> {
+impl Command for query_log_level {
+    type Res = Vec<LogLevelInfo>;
+    fn back(self) -> Vec<LogLevelInfo> {
         Default::default()
     }
 }
 
-/// Query target of StratoVirt.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LogLevelInfo {
+    pub subsystem: String,
+    pub level: String,
+}
+
+/// logfile-reopen:
+///
+/// Reopen the log file given by `-D`, which is used after the log file is moved away
+/// by logrotate.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-qmp-schema" }
+/// -> { "execute": "logfile-reopen" }
 /// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_qmp_schema {}
+pub struct logfile_reopen {}
 
-impl Command for query_qmp_schema {
+impl Command for logfile_reopen {
     type Res = Empty;
-
     fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query capabilities of sev.
+/// query-logfile:
+///
+/// Query the log file and its size, `path` is absent if logs are output to stderr.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-sev-capabilities" }
-/// <- {"return":{}}
+/// -> { "execute": "query-logfile" }
+/// <- {"return":{"path":"/var/log/stratovirt.log","size":4096}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_sev_capabilities {}
+pub struct query_logfile {}
 
-impl Command for query_sev_capabilities {
-    type Res = Empty;
-
-    fn back(self) -> Empty {
+impl Command for query_logfile {
+    type Res = LogfileInfo;
+    fn back(self) -> LogfileInfo {
         Default::default()
     }
 }
 
-/// List all Qom.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LogfileInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub size: u64,
+}
+
+/// cpu-throttle-set:
+///
+/// Throttle the vCPUs to sleep a percentage of time, which slows down the guest to
+/// make migration converge or to limit its CPU usage.
+///
+/// # Arguments
+///
+/// * `percentage` - Percentage of time the vCPUs sleep, in range [1, 99], 0 to stop
+///   throttling.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "qom-list" }
-/// <- {"return":[]}
+/// -> { "execute": "cpu-throttle-set", "arguments": { "percentage": 30 } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct qom_list {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct PropList {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub prop_type: String,
+#[serde(deny_unknown_fields)]
+pub struct cpu_throttle_set {
+    pub percentage: u8,
 }
+pub type CpuThrottleArgument = cpu_throttle_set;
 
-impl Command for qom_list {
-    type Res = Vec<PropList>;
-
-    fn back(self) -> Vec<PropList> {
+impl Command for cpu_throttle_set {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query char devices.
+/// display-channel-add:
+///
+/// Add a display channel which sends the display to a client over a unix socket.
+///
+/// # Arguments
+///
+/// * `id` - the display channel's ID, must be unique.
+/// * `path` - the path of the unix socket the client connects to.
+/// * `encoder` - the encoder of the frames, one of `raw`, `zlib` and `h264`,
+///   default to `raw`. (optional)
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-chardev" }
-/// <- {"return":[]}
+/// -> { "execute": "display-channel-add",
+///      "arguments": { "id": "display0", "path": "/tmp/display0.sock", "encoder": "zlib" } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_chardev {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct ChardevInfo {
-    #[serde(rename = "frontend-open")]
-    pub open: bool,
-    pub filename: String,
-    pub label: String,
+#[serde(deny_unknown_fields)]
+pub struct display_channel_add {
+    pub id: String,
+    pub path: String,
+    pub encoder: Option<String>,
 }
+pub type DisplayChannelAddArgument = display_channel_add;
 
-impl Command for query_chardev {
-    type Res = Vec<ChardevInfo>;
-
-    fn back(self) -> Vec<ChardevInfo> {
+impl Command for display_channel_add {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Get qom properties.
+/// display-channel-del:
+///
+/// Delete a display channel, the connected client is disconnected.
+///
+/// # Arguments
+///
+/// * `id` - the display channel's ID.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "qom_get" }
-/// <- {"return":[]}
+/// -> { "execute": "display-channel-del", "arguments": { "id": "display0" } }
+/// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct qom_get {}
-
-impl Command for qom_get {
-    type Res = bool;
+#[serde(deny_unknown_fields)]
+pub struct display_channel_del {
+    pub id: String,
+}
 
-    fn back(self) -> bool {
+impl Command for display_channel_del {
+    type Res = Empty;
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query blocks of StratoVirt.
+/// query-display-channels:
+///
+/// Query the display channels.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-block" }
-/// <- {"return":[]}
+/// -> { "execute": "query-display-channels" }
+/// <- {"return":[{"id":"display0","path":"/tmp/display0.sock","encoder":"zlib","connected":true}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_block {}
-
-impl Command for query_block {
-    type Res = Vec<Cmd>;
+pub struct query_display_channels {}
 
-    fn back(self) -> Vec<Cmd> {
+impl Command for query_display_channels {
+    type Res = Vec<DisplayChannelInfo>;
+    fn back(self) -> Vec<DisplayChannelInfo> {
         Default::default()
     }
 }
 
-/// Query named block node.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayChannelInfo {
+    pub id: String,
+    pub path: String,
+    pub encoder: String,
+    pub connected: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BalloonPolicyInfo {
+    pub enabled: bool,
+    pub interval: u32,
+    #[serde(rename = "min-size")]
+    pub min_size: u64,
+    #[serde(rename = "max-size")]
+    pub max_size: u64,
+    #[serde(rename = "free-percent")]
+    pub free_percent: u32,
+    #[serde(rename = "host-min-free")]
+    pub host_min_free: u64,
+}
+
+/// query-vnc:
+/// Information about current VNC server.
 ///
-/// # Example
+/// # Examples
 ///
+/// For pc machine type started with -vnc ip:port(for example: 0.0.0.0:0):
 /// ```text
-/// -> { "execute": "query-named-block-nodes" }
-/// <- {"return":[]}
+/// -> { "execute": "query-vnc" }
+/// <- {"return": {
+///         "enabled": true,
+///         "host": "0.0.0.0",
+///         "service": "50401",
+///         "auth": "None",
+///         "family": "ipv4",
+///         "clients": [
+///             "host": "127.0.0.1",
+///             "service": "50401",
+///             "family": "ipv4",
+///         ]
+///         }
+///     }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_named_block_nodes {}
-
-impl Command for query_named_block_nodes {
-    type Res = Vec<Cmd>;
-
-    fn back(self) -> Vec<Cmd> {
+pub struct query_vnc {}
+impl Command for query_vnc {
+    type Res = VncInfo;
+    fn back(self) -> VncInfo {
         Default::default()
     }
 }
 
-/// Query status of blocks.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VncInfo {
+    #[serde(rename = "enabled")]
+    pub enabled: bool,
+    #[serde(rename = "host")]
+    pub host: String,
+    #[serde(rename = "service")]
+    pub service: String,
+    #[serde(rename = "auth")]
+    pub auth: String,
+    #[serde(rename = "family")]
+    pub family: String,
+    #[serde(rename = "clients")]
+    pub clients: Vec<VncClientInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VncClientInfo {
+    #[serde(rename = "host")]
+    pub host: String,
+    #[serde(rename = "service")]
+    pub service: String,
+    #[serde(rename = "family")]
+    pub family: String,
+}
+
+/// balloon:
+///
+/// Advice VM to change memory size with the argument `value`.
+///
+/// # Arguments
+///
+/// * `value` - Memoey size.
+///
+/// # Notes
+///
+/// This is only an advice instead of command to VM,
+/// therefore, the VM changes its memory according to `value` and its condation.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "balloon", "arguments": { "value": 589934492 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct balloon {
+    #[serde(rename = "value")]
+    pub value: u64,
+}
+
+impl Command for balloon {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// version:
+///
+/// Query version of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-version" }
+/// <- {"return":{"version":{"qemu":{"minor":1,"micro":0,"major":5},"package":"StratoVirt-2.3.0"},"capabilities":[]}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_version {}
+
+impl Command for query_version {
+    type Res = Version;
+
+    fn back(self) -> Version {
+        Default::default()
+    }
+}
+
+/// Query commands:
+///
+/// Query all qmp commands of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-commands" }
+/// <- {"return":[{"name":"qmp_capabilities"},{"name":"quit"},{"name":"stop"},{"name":"cont"},
+/// {"name":"system_powerdown"},{"name":"system_reset"},{"name":"system_wakeup"},
+/// {"name":"device_add"},{"name":"device_del"},
+/// {"name":"chardev_add"},{"name":"chardev_remove"},{"name":"chardev-change"},{"name":"object-add"},{"name":"object-del"},
+/// {"name":"netdev_add"},{"name":"netdev_del"},
+/// {"name":"cameradev_add"},{"name":"cameradev_del"},{"name":"query-hotpluggable-cpus"},
+/// {"name":"query-cpus"},{"name":"query_status"},{"name":"getfd"},{"name":"blockdev_add"},
+/// {"name":"blockdev_del"},{"name":"balloon"},{"name":"query_balloon"},{"name":"query-vnc"},
+/// {"name":"migrate"},{"name":"query_migrate"},{"name":"cancel_migrate"},{"name":"query_version"},
+/// {"name":"query_commands"},{"name":"query_target"},{"name":"query_kvm"},{"name":"query_machines"},
+/// {"name":"query-events"},{"name":"list_type"},{"name":"device_list_properties"},{"name":"block-commit"},
+/// {"name":"query_tpm_models"},{"name":"query_tpm_types"},{"name":"query_command_line_options"},
+/// {"name":"query_migrate_capabilities"},{"name":"query_qmp_schema"},{"name":"query_sev_capabilities"},
+/// {"name":"query-chardev"},{"name":"qom-list"},{"name":"qom_get"},{"name":"query-block"},{"name":"query-named-block-nodes"},
+/// {"name":"query-blockstats"},{"name":"query-block-jobs"},{"name":"query-gic-capabilities"},{"name":"query-iothreads"},
+/// {"name":"update_region"},{"name":"input_event"},{"name":"human_monitor_command"},
+/// {"name":"block-dirty-bitmap-add"},{"name":"block-dirty-bitmap-clear"},
+/// {"name":"block-dirty-bitmap-remove"},{"name":"block-dirty-bitmap-export"},
+/// {"name":"nbd-server-start"},{"name":"nbd-server-add"},{"name":"blockdev-snapshot-sync"},
+/// {"name":"eject"},{"name":"change"},{"name":"drive-mirror"},{"name":"block-job-complete"},{"name":"block-job-cancel"},
+/// {"name":"query-jobs"},{"name":"job-pause"},{"name":"job-resume"},{"name":"job-cancel"},
+/// {"name":"set-balloon-stats-interval"},{"name":"query-balloon-stats"},
+/// {"name":"set-balloon-policy"},{"name":"query-balloon-policy"},{"name":"reclaim-guest-memory"},
+/// {"name":"query-vm-config"},
+/// {"name":"pflash-seal"},{"name":"query-interrupts"},{"name":"set_link"},{"name":"set-mac"},
+/// {"name":"set-vm-generation-id"},{"name":"query-vm-generation-id"},
+/// {"name":"rtc-reset-reinjection"},{"name":"query-rtc"},{"name":"query-uuid"},
+/// {"name":"query-name"},{"name":"query-stats"},
+/// {"name":"set-halt-poll"},{"name":"query-halt-poll"},{"name":"cpu-throttle-set"},
+/// {"name":"balloon-cancel"},{"name":"set-log-level"},{"name":"query-log-level"},
+/// {"name":"logfile-reopen"},{"name":"query-logfile"},
+/// {"name":"display-channel-add"},{"name":"display-channel-del"},{"name":"query-display-channels"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_commands {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Cmd {
+    pub name: String,
+}
+
+impl Command for query_commands {
+    type Res = Vec<Cmd>;
+
+    fn back(self) -> Vec<Cmd> {
+        Default::default()
+    }
+}
+
+/// Query target:
+///
+/// Query the target platform where the StratoVirt is running.
+///
+/// # Example
+///
+/// ```text
+/// # for X86 platform.
+/// -> { "execute": "query-target" }
+/// <- {"return":{"arch":"x86_64"}}
+///
+/// # for Aarch64 platform.
+/// -> { "execute": "query-target" }
+/// <- {"return":{"arch":"aarch64"}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_target {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Target {
+    pub arch: String,
+}
+
+impl Command for query_target {
+    type Res = Target;
+
+    fn back(self) -> Target {
+        Default::default()
+    }
+}
+
+/// Query machines:
+///
+/// Query machine information.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-machines" }
+/// <- {"return":[{"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"none","numa-mem-supported":false},
+/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"microvm","numa-mem-supported":false},
+/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"standardvm","numa-mem-supported":false}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_machines {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct MachineInfo {
+    #[serde(rename = "hotpluggable-cpus")]
+    pub hotplug: bool,
+    pub name: String,
+    #[serde(rename = "numa-mem-supported")]
+    pub numa_mem_support: bool,
+    #[serde(rename = "cpu-max")]
+    pub cpu_max: u8,
+    pub deprecated: bool,
+}
+
+impl Command for query_machines {
+    type Res = Vec<MachineInfo>;
+
+    fn back(self) -> Vec<MachineInfo> {
+        Default::default()
+    }
+}
+
+/// Query events:
+///
+/// Query all events of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-events" }
+/// <- {"return":[{"name":"Shutdown"},{"name":"Reset"},
+/// {"name":"Stop"},{"name":"Resume"},{"name":"DeviceDeleted"},
+/// {"name":"BalloonChanged"},{"name":"JobStatusChange"},{"name":"JobCompleted"},
+/// {"name":"BlockIoTimeout"},{"name":"RtcChange"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Events {
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_events {}
+
+impl Command for query_events {
+    type Res = Vec<Events>;
+
+    fn back(self) -> Vec<Events> {
+        Default::default()
+    }
+}
+
+/// Query KVM:
+///
+/// Query if KVM is enabled.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-kvm" }
+/// <- {"return":{"enabled":true,"present":true}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_kvm {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct KvmInfo {
+    pub enabled: bool,
+    pub present: bool,
+}
+
+impl Command for query_kvm {
+    type Res = KvmInfo;
+
+    fn back(self) -> KvmInfo {
+        Default::default()
+    }
+}
+
+/// List all Qom type.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom-list-types" }
+/// <- {"return":[{"name":"ioh3420","parent":"pcie-root-port-base"},
+/// {"name":"pcie-root-port","parent":"pcie-root-port-base"},
+/// {"name":"pcie-pci-bridge","parent":"base-pci-bridge"},
+/// {"name":"pci-bridge","parent":"base-pci-bridge"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct list_type {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TypeLists {
+    name: String,
+    parent: String,
+}
+
+impl TypeLists {
+    pub fn new(name: String, parent: String) -> Self {
+        TypeLists { name, parent }
+    }
+}
+
+impl Command for list_type {
+    type Res = Vec<TypeLists>;
+
+    fn back(self) -> Vec<TypeLists> {
+        Default::default()
+    }
+}
+
+/// Get device list properties.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "device-list-properties", "arguments": {"typename": "virtio-blk-pci"} }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct device_list_properties {
+    pub typename: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceProps {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub prop_type: String,
+}
+
+impl Command for device_list_properties {
+    type Res = Vec<DeviceProps>;
+
+    fn back(self) -> Vec<DeviceProps> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct block_commit {}
+
+impl Command for block_commit {
+    type Res = Vec<DeviceProps>;
+
+    fn back(self) -> Vec<DeviceProps> {
+        Default::default()
+    }
+}
+
+/// Query tpm models of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-tpm-models" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_tpm_models {}
+
+impl Command for query_tpm_models {
+    type Res = Vec<String>;
+
+    fn back(self) -> Vec<String> {
+        Default::default()
+    }
+}
+
+/// Query target of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-tpm-types" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_tpm_types {}
+
+impl Command for query_tpm_types {
+    type Res = Vec<String>;
+
+    fn back(self) -> Vec<String> {
+        Default::default()
+    }
+}
+
+/// Query command line options.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-command-line-options" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_command_line_options {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CmdParameter {
+    pub name: String,
+    pub help: String,
+    #[serde(rename = "type")]
+    pub parameter_type: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CmdLine {
+    pub parameters: Vec<CmdParameter>,
+    pub option: String,
+}
+
+impl Command for query_command_line_options {
+    type Res = Vec<CmdLine>;
+
+    fn back(self) -> Vec<CmdLine> {
+        Default::default()
+    }
+}
+
+/// Query capabilities of migration.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-migrate-capabilities" }
+/// <- {"return":[{"state":false,"capability":"compress"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_migrate_capabilities {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateCapabilities {
+    pub state: bool,
+    pub capability: String,
+}
+
+impl Command for query_migrate_capabilities {
+    type Res = Vec<MigrateCapabilities>;
+
+    fn back(self) -> Vec<MigrateCapabilities> {
+        Default::default()
+    }
+}
+
+/// migrate-set-capabilities
+///
+/// Enable or disable capabilities of migration.
+///
+/// # Arguments
+///
+/// * `capabilities` - the capabilities and their states.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "migrate-set-capabilities",
+///      "arguments": { "capabilities": [ { "capability": "compress", "state": true } ] } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct migrate_set_capabilities {
+    pub capabilities: Vec<MigrateCapabilities>,
+}
+pub type MigrateSetCapabilitiesArgument = migrate_set_capabilities;
+
+impl Command for migrate_set_capabilities {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Query target of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-qmp-schema" }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_qmp_schema {}
+
+impl Command for query_qmp_schema {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Query capabilities of sev.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-sev-capabilities" }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_sev_capabilities {}
+
+impl Command for query_sev_capabilities {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// List all Qom.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom-list" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct qom_list {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PropList {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub prop_type: String,
+}
+
+impl Command for qom_list {
+    type Res = Vec<PropList>;
+
+    fn back(self) -> Vec<PropList> {
+        Default::default()
+    }
+}
+
+/// Query char devices.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-chardev" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_chardev {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ChardevInfo {
+    #[serde(rename = "frontend-open")]
+    pub open: bool,
+    pub filename: String,
+    pub label: String,
+}
+
+impl Command for query_chardev {
+    type Res = Vec<ChardevInfo>;
+
+    fn back(self) -> Vec<ChardevInfo> {
+        Default::default()
+    }
+}
+
+/// Get qom properties.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom_get" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct qom_get {}
+
+impl Command for qom_get {
+    type Res = bool;
+
+    fn back(self) -> bool {
+        Default::default()
+    }
+}
+
+/// Query blocks of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-block" }
+/// <- {"return":[{"device":"drive-0","inserted":{"file":"/path/to/block","ro":false,
+///     "drv":"raw","direct":true,"aio":"io_uring"}}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_block {}
+
+impl Command for query_block {
+    type Res = Vec<BlockInfo>;
+
+    fn back(self) -> Vec<BlockInfo> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockInfo {
+    /// The id of the drive.
+    pub device: String,
+    /// The medium of the drive, it's none for the empty cdrom.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inserted: Option<BlockDeviceInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDeviceInfo {
+    pub file: String,
+    pub ro: bool,
+    /// The image format.
+    pub drv: String,
+    pub direct: bool,
+    /// The aio engine used by the drive.
+    pub aio: String,
+}
+
+/// Query named block node.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-named-block-nodes" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_named_block_nodes {}
+
+impl Command for query_named_block_nodes {
+    type Res = Vec<Cmd>;
+
+    fn back(self) -> Vec<Cmd> {
+        Default::default()
+    }
+}
+
+/// Query status of blocks.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-blockstats" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_blockstats {}
+
+impl Command for query_blockstats {
+    type Res = Vec<Cmd>;
+
+    fn back(self) -> Vec<Cmd> {
+        Default::default()
+    }
+}
+
+/// Query jobs of blocks.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-block-jobs" }
+/// <- { "return": [{ "type": "mirror", "device": "drive-0", "len": 1073741824,
+///                   "offset": 1073741824, "busy": false, "paused": false, "speed": 0,
+///                   "io-status": "ok", "ready": true, "status": "ready" }]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_block_jobs {}
+
+impl Command for query_block_jobs {
+    type Res = Vec<BlockJobInfo>;
+
+    fn back(self) -> Vec<BlockJobInfo> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockJobInfo {
+    #[serde(rename = "type")]
+    pub job_type: String,
+    pub device: String,
+    pub len: u64,
+    pub offset: u64,
+    pub busy: bool,
+    pub paused: bool,
+    pub speed: u64,
+    #[serde(rename = "io-status")]
+    pub io_status: String,
+    pub ready: bool,
+    pub status: String,
+}
+
+/// Query capabilities of gic.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-gic-capabilities" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_gic_capabilities {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct GicCap {
+    emulated: bool,
+    version: u32,
+    kernel: bool,
+}
+
+impl Command for query_gic_capabilities {
+    type Res = Vec<GicCap>;
+
+    fn back(self) -> Vec<GicCap> {
+        Default::default()
+    }
+}
+
+/// Query information of iothreads.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-iothreads" }
+/// <- {"return":[{"poll-shrink":0,"thread-id":1043,"poll-grow":0,"poll-max-ns":0,
+///      "id":"iothread0","attached-devices":1}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_iothreads {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct IothreadInfo {
+    #[serde(rename = "poll-shrink")]
+    pub shrink: u32,
+    #[serde(rename = "thread-id")]
+    pub pid: u32,
+    #[serde(rename = "poll-grow")]
+    pub grow: u32,
+    #[serde(rename = "poll-max-ns")]
+    pub max: u32,
+    pub id: String,
+    #[serde(rename = "attached-devices")]
+    pub attached_devices: u32,
+}
+
+impl Command for query_iothreads {
+    type Res = Vec<IothreadInfo>;
+
+    fn back(self) -> Vec<IothreadInfo> {
+        Default::default()
+    }
+}
+/// input_event
+///
+/// # Arguments
+///
+/// * `key` - the input type such as 'keyboard' or 'pointer'.
+/// * `value` - the input value.
+
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "input_event",
+///      "arguments": { "key": "pointer", "value": "100,200,1" }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct input_event {
+    pub key: String,
+    pub value: String,
+}
+
+impl Command for input_event {
+    type Res = Vec<input_event>;
+
+    fn back(self) -> Vec<input_event> {
+        Default::default()
+    }
+}
+
+/// set_link
+///
+/// Set the link status of the virtio-net device, the guest is notified by
+/// config interrupt.
+///
+/// # Arguments
+///
+/// * `name` - the device id of the virtio-net device.
+/// * `up` - true to set the link up, false to set the link down.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "set_link",
+///      "arguments": { "name": "net-0", "up": false }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_link {
+    pub name: String,
+    pub up: bool,
+}
+
+impl Command for set_link {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// set-mac
+///
+/// Change the mac address of the virtio-net device, the guest is notified by
+/// config interrupt.
+///
+/// # Arguments
+///
+/// * `name` - the device id of the virtio-net device.
+/// * `mac` - the new mac address.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "set-mac",
+///      "arguments": { "name": "net-0", "mac": "52:54:00:12:34:57" }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_mac {
+    pub name: String,
+    pub mac: String,
+}
+
+impl Command for set_mac {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// set-vm-generation-id
+///
+/// Change the VM generation ID, the guest is notified by ACPI event.
+///
+/// # Arguments
+///
+/// * `guid` - the new generation ID in UUID format, or `auto` for a random one.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "set-vm-generation-id",
+///      "arguments": { "guid": "324e6eaf-d1d1-4bf6-bf41-b9bb6c91fb87" }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_vm_generation_id {
+    pub guid: String,
+}
+
+impl Command for set_vm_generation_id {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-vm-generation-id
+///
+/// Query the VM generation ID.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-vm-generation-id" }
+/// <- { "return": { "guid": "324e6eaf-d1d1-4bf6-bf41-b9bb6c91fb87" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_vm_generation_id {}
+
+impl Command for query_vm_generation_id {
+    type Res = GuidInfo;
+
+    fn back(self) -> GuidInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct GuidInfo {
+    pub guid: String,
+}
+
+/// rtc-reset-reinjection
+///
+/// Drop the periodic interrupts of RTC which are missed by guest and waiting to be
+/// reinjected, e.g. after guest time is corrected by management layer. It's only
+/// meaningful with `-rtc driftfix=slew`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "rtc-reset-reinjection" }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct rtc_reset_reinjection {}
+
+impl Command for rtc_reset_reinjection {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-rtc
+///
+/// Query the time of RTC, and its offset in seconds to the host time in `-rtc base`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-rtc" }
+/// <- { "return": { "base": "2006-06-17T16:01:21", "clock": "vm",
+///                  "time": 1150560141, "offset": -539481323 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_rtc {}
+
+impl Command for query_rtc {
+    type Res = RtcInfo;
+
+    fn back(self) -> RtcInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct RtcInfo {
+    /// Start time of RTC, `utc`, `localtime` or a datetime.
+    pub base: String,
+    /// Clock which RTC runs with, `host` or `vm`.
+    pub clock: String,
+    /// Current time of RTC in seconds since 1970-01-01 00:00:00.
+    pub time: i64,
+    /// Offset in seconds between the time of RTC and the host time in `-rtc base`.
+    pub offset: i64,
+}
+
+/// query-uuid
+///
+/// Query the UUID of VM, which is all zero if it's not set by `-uuid`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-uuid" }
+/// <- { "return": { "UUID": "33db4d5e-1ff7-401c-9657-7441c03dd766" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_uuid {}
+
+impl Command for query_uuid {
+    type Res = UuidInfo;
+
+    fn back(self) -> UuidInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct UuidInfo {
+    #[serde(rename = "UUID")]
+    pub uuid: String,
+}
+
+/// query-name
+///
+/// Query the name of VM, which is omitted if it's not set by `-name`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-name" }
+/// <- { "return": { "name": "StratoVirt" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_name {}
+
+impl Command for query_name {
+    type Res = NameInfo;
+
+    fn back(self) -> NameInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct NameInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// human-monitor-command
+///
+/// # Arguments
+///
+/// * `command_line` - the command line will be executed.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "human-monitor-command",
+///      "arguments": { "command-line": "drive_add dummy
+///      file=/path/to/file,format=raw,if=none,id=drive-id" }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct human_monitor_command {
+    #[serde(rename = "command-line")]
+    pub command_line: String,
+}
+pub type HumanMonitorCmdArgument = human_monitor_command;
+
+/// blockdev-snapshot-internal-sync
+///
+/// Create disk internal snapshot.
+///
+/// # Arguments
+///
+/// * `device` - the valid block device.
+/// * `name` - the snapshot name.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "blockdev-snapshot-internal-sync",
+///      "arguments": { "device": "disk0",
+///                     "name": "snapshot1" }}
+/// <- { "return": {} }
+/// ```
+///
+/// blockdev-snapshot-delete-internal-sync
+///
+/// Delete disk internal snapshot.
+///
+/// # Arguments
+///
+/// * `device` - the valid block device.
+/// * `name` - the snapshot name.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "blockdev-snapshot-delete-internal-sync",
+///      "arguments": { "device": "disk0",
+///                     "name": "snapshot1" }}
+/// <- { "return": {
+///                    "id": "1",
+///                    "name": "snapshot0",
+///                    "vm-state-size": 0,
+///                    "date-sec": 1000012,
+///                    "date-nsec": 10,
+///                    "vm-clock-sec": 100,
+///                    "vm-clock-nsec": 20,
+///                    "icount": 220414
+///  } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct blockdev_snapshot_internal {
+    pub device: String,
+    pub name: String,
+}
+pub type BlockdevSnapshotInternalArgument = blockdev_snapshot_internal;
+
+/// blockdev-snapshot-sync
+///
+/// Create a qcow2 overlay on top of the current image of the drive, and switch the
+/// drive to the overlay while the guest keeps running.
+///
+/// # Arguments
+///
+/// * `device` - the id of the drive.
+/// * `snapshot-file` - the path of the new overlay image, it must not exist.
+/// * `format` - the format of the overlay, only qcow2 is supported, default is qcow2.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "blockdev-snapshot-sync",
+///      "arguments": { "device": "drive-0",
+///                     "snapshot-file": "/path/to/overlay.qcow2" }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct blockdev_snapshot_sync {
+    pub device: String,
+    #[serde(rename = "snapshot-file")]
+    pub snapshot_file: String,
+    pub format: Option<String>,
+}
+pub type BlockdevSnapshotSyncArgument = blockdev_snapshot_sync;
+
+impl Command for blockdev_snapshot_sync {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// eject
+///
+/// Remove the medium of a cdrom drive attached to a virtio block device.
+///
+/// # Arguments
+///
+/// * `device` - the id of the drive.
+/// * `force` - accepted for compatibility, the medium is always removed.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "eject", "arguments": { "device": "cd0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct eject {
+    pub device: String,
+    pub force: Option<bool>,
+}
+pub type EjectArgument = eject;
+
+impl Command for eject {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// change
+///
+/// Insert a new medium into a cdrom drive attached to a virtio block device, the
+/// old medium is removed.
+///
+/// # Arguments
+///
+/// * `device` - the id of the drive.
+/// * `target` - the path of the new medium image.
+/// * `arg` - the format of the image, default is raw.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "change",
+///      "arguments": { "device": "cd0", "target": "/path/to/cd.iso" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct change {
+    pub device: String,
+    pub target: String,
+    pub arg: Option<String>,
+}
+pub type ChangeArgument = change;
+
+impl Command for change {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// drive-mirror
+///
+/// Start a job which copies the drive to a new image, the writes of the guest are
+/// copied too. The job is ready when the new image is in sync with the drive, and
+/// `block-job-complete` switches the drive to the new image.
+///
+/// # Arguments
+///
+/// * `device` - the id of the drive.
+/// * `target` - the path of the new image, it must not exist.
+/// * `format` - the format of the new image, raw or qcow2, default is the format of the drive.
+/// * `job-id` - the id of the job, default is the id of the drive.
+/// * `sync` - what to copy, only "full" is supported.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "drive-mirror",
+///      "arguments": { "device": "drive-0", "target": "/path/to/new.qcow2",
+///                     "format": "qcow2", "sync": "full" }}
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct drive_mirror {
+    pub device: String,
+    pub target: String,
+    pub format: Option<String>,
+    #[serde(rename = "job-id")]
+    pub job_id: Option<String>,
+    pub sync: Option<String>,
+}
+pub type DriveMirrorArgument = drive_mirror;
+
+impl Command for drive_mirror {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// block-job-complete
+///
+/// Complete the ready mirror job, the drive is switched to the new image.
+///
+/// # Arguments
+///
+/// * `device` - the id of the job.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "block-job-complete", "arguments": { "device": "drive-0" } }
+/// <- { "return": {} }
+/// ```
 ///
-/// # Example
+/// block-job-cancel
+///
+/// Cancel the mirror job, the drive keeps using the current image and the new
+/// image is left as it is.
+///
+/// # Arguments
+///
+/// * `device` - the id of the job.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-blockstats" }
-/// <- {"return":[]}
+/// -> { "execute": "block-job-cancel", "arguments": { "device": "drive-0" } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_blockstats {}
+#[serde(deny_unknown_fields)]
+pub struct block_job {
+    pub device: String,
+}
+pub type BlockJobArgument = block_job;
 
-impl Command for query_blockstats {
-    type Res = Vec<Cmd>;
+impl Command for block_job {
+    type Res = Empty;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query jobs of blocks.
+/// query-jobs
 ///
-/// # Example
+/// Query the background jobs which are not done.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-block_jobs" }
-/// <- {"return":[]}
+/// -> { "execute": "query-jobs" }
+/// <- { "return": [{ "id": "drive-0", "type": "mirror", "status": "ready",
+///                   "current-progress": 1073741824, "total-progress": 1073741824 }]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_block_jobs {}
+pub struct query_jobs {}
 
-impl Command for query_block_jobs {
-    type Res = Vec<Cmd>;
+impl Command for query_jobs {
+    type Res = Vec<JobInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<JobInfo> {
         Default::default()
     }
 }
 
-/// Query capabilities of gic.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub job_type: String,
+    pub status: String,
+    #[serde(rename = "current-progress")]
+    pub current_progress: u64,
+    #[serde(rename = "total-progress")]
+    pub total_progress: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Handle of the job which runs the asynchronous command.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct JobHandle {
+    #[serde(rename = "job-id")]
+    pub job_id: String,
+}
+
+/// job-pause
 ///
-/// # Example
+/// Pause the running or ready job.
+///
+/// # Arguments
+///
+/// * `id` - the id of the job.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-gic-capabilities" }
-/// <- {"return":[]}
+/// -> { "execute": "job-pause", "arguments": { "id": "drive-0" } }
+/// <- { "return": {} }
+/// ```
+///
+/// job-resume
+///
+/// Resume the paused job.
+///
+/// # Arguments
+///
+/// * `id` - the id of the job.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-resume", "arguments": { "id": "drive-0" } }
+/// <- { "return": {} }
+/// ```
+///
+/// job-cancel
+///
+/// Cancel the job, the job is done after it's stopped.
+///
+/// # Arguments
+///
+/// * `id` - the id of the job.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-cancel", "arguments": { "id": "drive-0" } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_gic_capabilities {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct GicCap {
-    emulated: bool,
-    version: u32,
-    kernel: bool,
+#[serde(deny_unknown_fields)]
+pub struct job {
+    pub id: String,
 }
 
-impl Command for query_gic_capabilities {
-    type Res = Vec<GicCap>;
+impl Command for job {
+    type Res = Empty;
 
-    fn back(self) -> Vec<GicCap> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query information of iothreads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "vm-state-size")]
+    pub vm_state_size: u64,
+    #[serde(rename = "date-sec")]
+    pub date_sec: u32,
+    #[serde(rename = "date-nsec")]
+    pub date_nsec: u32,
+    #[serde(rename = "vm-clock-nsec")]
+    pub vm_clock_nsec: u64,
+    #[serde(rename = "icount")]
+    pub icount: u64,
+}
+
+/// block-dirty-bitmap-add
 ///
-/// # Example
+/// Create a dirty bitmap to track the sectors written by guest.
+///
+/// # Arguments
+///
+/// * `node` - the drive id of the block device.
+/// * `name` - the name of the dirty bitmap, must be unique in the block device.
+/// * `granularity` - the bytes represented by each bit, default is 65536.
+/// * `persistent` - store the bitmap beside the image file when VM exits, default is false.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-iothreads" }
-/// <- {"return":[]}
+/// -> { "execute": "block-dirty-bitmap-add",
+///      "arguments": { "node": "drive0", "name": "bitmap0", "persistent": true } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_iothreads {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct IothreadInfo {
-    #[serde(rename = "poll-shrink")]
-    pub shrink: u32,
-    #[serde(rename = "thread-id")]
-    pub pid: u32,
-    #[serde(rename = "poll-grow")]
-    pub grow: u32,
-    #[serde(rename = "poll-max-ns")]
-    pub max: u32,
-    pub id: String,
+#[serde(deny_unknown_fields)]
+pub struct block_dirty_bitmap_add {
+    pub node: String,
+    pub name: String,
+    pub granularity: Option<u64>,
+    pub persistent: Option<bool>,
 }
+pub type BlockDirtyBitmapAddArgument = block_dirty_bitmap_add;
 
-impl Command for query_iothreads {
-    type Res = Vec<IothreadInfo>;
+impl Command for block_dirty_bitmap_add {
+    type Res = Empty;
 
-    fn back(self) -> Vec<IothreadInfo> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
-/// input_event
+
+/// block-dirty-bitmap-clear
+///
+/// Clear all the bits of a dirty bitmap.
 ///
 /// # Arguments
 ///
-/// * `key` - the input type such as 'keyboard' or 'pointer'.
-/// * `value` - the input value.
-
+/// * `node` - the drive id of the block device.
+/// * `name` - the name of the dirty bitmap.
+///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "input_event",
-///      "arguments": { "key": "pointer", "value": "100,200,1" }}
+/// -> { "execute": "block-dirty-bitmap-clear",
+///      "arguments": { "node": "drive0", "name": "bitmap0" } }
+/// <- { "return": {} }
+/// ```
+///
+/// block-dirty-bitmap-remove
+///
+/// Remove a dirty bitmap, persistent bitmap is removed from the storage too.
+///
+/// # Arguments
+///
+/// * `node` - the drive id of the block device.
+/// * `name` - the name of the dirty bitmap.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "block-dirty-bitmap-remove",
+///      "arguments": { "node": "drive0", "name": "bitmap0" } }
 /// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct input_event {
-    pub key: String,
-    pub value: String,
+#[serde(deny_unknown_fields)]
+pub struct block_dirty_bitmap {
+    pub node: String,
+    pub name: String,
 }
+pub type BlockDirtyBitmapArgument = block_dirty_bitmap;
 
-impl Command for input_event {
-    type Res = Vec<input_event>;
+impl Command for block_dirty_bitmap {
+    type Res = Empty;
 
-    fn back(self) -> Vec<input_event> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// human-monitor-command
+/// block-dirty-bitmap-export
+///
+/// Export the contents of a dirty bitmap to a file for incremental backup.
 ///
 /// # Arguments
 ///
-/// * `command_line` - the command line will be executed.
+/// * `node` - the drive id of the block device.
+/// * `name` - the name of the dirty bitmap.
+/// * `file` - the path of the exported file.
+/// * `clear` - clear the bitmap atomically after exporting, default is false.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "human-monitor-command",
-///      "arguments": { "command-line": "drive_add dummy
-///      file=/path/to/file,format=raw,if=none,id=drive-id" }}
+/// -> { "execute": "block-dirty-bitmap-export",
+///      "arguments": { "node": "drive0", "name": "bitmap0",
+///                     "file": "/tmp/bitmap0", "clear": true } }
 /// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct human_monitor_command {
-    #[serde(rename = "command-line")]
-    pub command_line: String,
+pub struct block_dirty_bitmap_export {
+    pub node: String,
+    pub name: String,
+    pub file: String,
+    pub clear: Option<bool>,
 }
-pub type HumanMonitorCmdArgument = human_monitor_command;
+pub type BlockDirtyBitmapExportArgument = block_dirty_bitmap_export;
 
-/// blockdev-snapshot-internal-sync
+impl Command for block_dirty_bitmap_export {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// nbd-server-start
 ///
-/// Create disk internal snapshot.
+/// Start the NBD server to export drives.
 ///
 /// # Arguments
 ///
-/// * `device` - the valid block device.
-/// * `name` - the snapshot name.
+/// * `addr` - the address to listen, `unix:<path>` or `tcp:<ip>:<port>`.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "blockdev-snapshot-internal-sync",
-///      "arguments": { "device": "disk0",
-///                     "name": "snapshot1" }}
+/// -> { "execute": "nbd-server-start",
+///      "arguments": { "addr": "unix:/tmp/nbd.sock" } }
 /// <- { "return": {} }
 /// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct nbd_server_start {
+    pub addr: String,
+}
+
+impl Command for nbd_server_start {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// nbd-server-add
 ///
-/// blockdev-snapshot-delete-internal-sync
-///
-/// Delete disk internal snapshot.
+/// Export a drive by the NBD server.
 ///
 /// # Arguments
 ///
-/// * `device` - the valid block device.
-/// * `name` - the snapshot name.
+/// * `device` - the id of the drive.
+/// * `name` - the export name, default is the id of the drive.
+/// * `writable` - whether clients can write the drive, default is false.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "blockdev-snapshot-delete-internal-sync",
-///      "arguments": { "device": "disk0",
-///                     "name": "snapshot1" }}
-/// <- { "return": {
-///                    "id": "1",
-///                    "name": "snapshot0",
-///                    "vm-state-size": 0,
-///                    "date-sec": 1000012,
-///                    "date-nsec": 10,
-///                    "vm-clock-sec": 100,
-///                    "vm-clock-nsec": 20,
-///                    "icount": 220414
-///  } }
+/// -> { "execute": "nbd-server-add",
+///      "arguments": { "device": "drive-0", "writable": true } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct blockdev_snapshot_internal {
+pub struct nbd_server_add {
     pub device: String,
-    pub name: String,
+    pub name: Option<String>,
+    pub writable: Option<bool>,
 }
-pub type BlockdevSnapshotInternalArgument = blockdev_snapshot_internal;
+pub type NbdServerAddArgument = nbd_server_add;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SnapshotInfo {
-    #[serde(rename = "id")]
-    pub id: String,
-    #[serde(rename = "name")]
-    pub name: String,
-    #[serde(rename = "vm-state-size")]
-    pub vm_state_size: u64,
-    #[serde(rename = "date-sec")]
-    pub date_sec: u32,
-    #[serde(rename = "date-nsec")]
-    pub date_nsec: u32,
-    #[serde(rename = "vm-clock-nsec")]
-    pub vm_clock_nsec: u64,
-    #[serde(rename = "icount")]
-    pub icount: u64,
+impl Command for nbd_server_add {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
 }
 
 /// query-mem
@@ -2979,4 +5256,50 @@ mod tests {
         let part_msg = r#"unknown field `invalid_key`, expected `command-line`"#;
         assert!(err_msg.contains(part_msg));
     }
+
+    #[test]
+    fn test_qmp_query_stats() {
+        let json_msg = r#"
+        {
+            "execute": "query-stats",
+            "arguments": {
+                "target": "vcpu",
+                "providers": [ { "provider": "kvm", "names": [ "exits" ] } ],
+                "vcpus": [ "/machine/unattached/device[0]" ]
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::query_stats { arguments, .. } => {
+                assert_eq!(arguments.target, StatsTarget::Vcpu);
+                let providers = arguments.providers.unwrap();
+                assert_eq!(providers[0].provider, StatsProvider::Kvm);
+                assert_eq!(providers[0].names, Some(vec!["exits".to_string()]));
+                assert_eq!(arguments.vcpus.unwrap().len(), 1);
+            }
+            _ => panic!("Unexpected command"),
+        }
+
+        // Unknown target.
+        let json_msg = r#"
+        {
+            "execute": "query-stats",
+            "arguments": { "target": "cryptodev" }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+
+        let result = StatsResult {
+            provider: StatsProvider::Kvm,
+            qom_path: None,
+            stats: vec![Stats {
+                name: "exits".to_string(),
+                value: 10,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&result).unwrap(),
+            r#"{"provider":"kvm","stats":[{"name":"exits","value":10}]}"#
+        );
+    }
 }
@@ -10,25 +10,30 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::VecDeque;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::rc::Rc;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::{error, info, warn};
+use serde_json::Value;
 use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
 
 use super::qmp_schema;
 use super::qmp_schema::QmpCommand;
 use super::{qmp_channel::QmpChannel, qmp_response::QmpGreeting, qmp_response::Response};
 use crate::event;
 use crate::event_loop::EventLoop;
-use crate::machine::MachineExternalInterface;
+use crate::job::job_spawn;
+use crate::machine::{KvmVmState, MachineExternalInterface, ShutdownCause};
 use crate::socket::SocketHandler;
 use crate::socket::SocketRWHandler;
 use crate::temp_cleaner::TempCleaner;
 use util::leak_bucket::LeakBucket;
+use util::logger::{query_log_file, query_log_levels, reopen_log_file, set_log_level};
 use util::loop_context::{
     gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
     NotifierOperation,
@@ -36,6 +41,9 @@ use util::loop_context::{
 use util::set_termi_canon_mode;
 
 const LEAK_BUCKET_LIMIT: u64 = 100;
+/// The io-thread which reads QMP socket, so that the out-of-band commands can be
+/// executed while the main loop is busy.
+const QMP_IOTHREAD: &str = "qmp_io";
 
 /// Type for api socket.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -59,6 +67,30 @@ impl AsRawFd for SocketStream {
     }
 }
 
+/// In-band commands read by the QMP io-thread, they are executed in the main loop
+/// in order.
+struct InbandQueue {
+    requests: Mutex<VecDeque<(QmpCommand, Option<RawFd>)>>,
+    /// Notify the main loop to execute the commands.
+    event: EventFd,
+}
+
+impl InbandQueue {
+    fn new() -> Self {
+        InbandQueue {
+            requests: Mutex::new(VecDeque::new()),
+            event: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    fn push(&self, command: QmpCommand, if_fd: Option<RawFd>) -> Result<()> {
+        self.requests.lock().unwrap().push_back((command, if_fd));
+        self.event
+            .write(1)
+            .with_context(|| "Failed to notify main loop of qmp command")
+    }
+}
+
 /// The wrapper over Unix socket and socket handler.
 ///
 /// # Example
@@ -91,6 +123,10 @@ pub struct Socket {
     stream: RwLock<Option<SocketStream>>,
     /// Perform socket command
     performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
+    /// State of VM, which is queried by the out-of-band command.
+    vm_state: Option<Arc<(Mutex<KvmVmState>, Condvar)>>,
+    /// In-band commands waiting to be executed by the main loop.
+    inband: Arc<InbandQueue>,
 }
 
 impl Socket {
@@ -104,11 +140,16 @@ impl Socket {
         listener: UnixListener,
         performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
     ) -> Self {
+        let vm_state = performer
+            .as_ref()
+            .and_then(|performer| performer.lock().unwrap().shared_vm_state());
         Socket {
             sock_type: SocketType::Unix,
             listener,
             stream: RwLock::new(None),
             performer,
+            vm_state,
+            inband: Arc::new(InbandQueue::new()),
         }
     }
 
@@ -193,9 +234,14 @@ impl Socket {
         Ok(())
     }
 
-    /// Create socket's accepted stream to `event_notifier`.
+    /// Create socket's accepted stream to `event_notifier`. The stream is handled in
+    /// the QMP io-thread if it exists, otherwise in the main loop.
     fn create_event_notifier(&mut self, shared_socket: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
+        let iothread = QMP_IOTHREAD.to_string();
+        let ctx_name = EventLoop::get_ctx(Some(&iothread))
+            .is_some()
+            .then_some(iothread);
 
         let leak_bucket = LeakBucket::new(LEAK_BUCKET_LIMIT);
         if let Err(e) = leak_bucket {
@@ -213,16 +259,15 @@ impl Socket {
             QmpChannel::unbind();
             return notifiers;
         }
+        let listener_fd = self.get_listener_fd();
+        let handler_ctx = ctx_name.clone();
         let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
             if event == EventSet::IN {
                 let socket_mutexed = shared_socket.lock().unwrap();
-                let stream_fd = socket_mutexed.get_stream_fd();
-
-                let performer = &socket_mutexed.performer.as_ref().unwrap();
                 if let Err(e) = handle_qmp(
-                    stream_fd,
-                    performer,
+                    &socket_mutexed,
                     &mut shared_leak_bucket.lock().unwrap(),
+                    handler_ctx.as_ref(),
                 ) {
                     error!("{:?}", e);
                 }
@@ -231,16 +276,36 @@ impl Socket {
                 let socket_mutexed = shared_socket.lock().unwrap();
                 let stream_fd = socket_mutexed.get_stream_fd();
 
+                socket_mutexed.inband.requests.lock().unwrap().clear();
                 QmpChannel::unbind();
+                // The listener in main loop is not resumed by the io-thread.
+                if handler_ctx.is_some() {
+                    let resume = EventNotifier::new(
+                        NotifierOperation::Resume,
+                        listener_fd,
+                        None,
+                        EventSet::IN,
+                        Vec::new(),
+                    );
+                    if let Err(e) = EventLoop::update_event(vec![resume], None) {
+                        error!("Failed to resume qmp listener: {:?}", e);
+                    }
+                }
                 Some(gen_delete_notifiers(&[stream_fd, leak_bucket_fd]))
             } else {
                 None
             }
         });
+        // Only one client is served, so the listener is parked until the stream hangs up.
+        let parked_fd = if ctx_name.is_some() {
+            None
+        } else {
+            Some(listener_fd)
+        };
         let qmp_notifier = EventNotifier::new(
             NotifierOperation::AddShared,
             self.get_stream_fd(),
-            Some(self.get_listener_fd()),
+            parked_fd,
             EventSet::IN | EventSet::HANG_UP,
             vec![handler],
         );
@@ -259,14 +324,70 @@ impl Socket {
         );
         notifiers.push(leak_bucket_notifier);
 
+        if ctx_name.is_some() {
+            if let Err(e) = EventLoop::update_event(notifiers, ctx_name.as_ref()) {
+                error!("Failed to add qmp stream to io-thread: {:?}", e);
+                QmpChannel::unbind();
+                return Vec::new();
+            }
+            let park = EventNotifier::new(
+                NotifierOperation::Park,
+                listener_fd,
+                None,
+                EventSet::IN,
+                Vec::new(),
+            );
+            return vec![park];
+        }
         notifiers
     }
+
+    /// Execute the in-band commands read by the QMP io-thread.
+    fn handle_inband(shared_socket: &Arc<Mutex<Self>>) {
+        let (inband, performer) = {
+            let locked_socket = shared_socket.lock().unwrap();
+            (
+                locked_socket.inband.clone(),
+                locked_socket.performer.clone(),
+            )
+        };
+        let performer = match performer {
+            Some(performer) => performer,
+            None => return,
+        };
+
+        loop {
+            let request = inband.requests.lock().unwrap().pop_front();
+            let (command, if_fd) = match request {
+                Some(request) => request,
+                None => break,
+            };
+            // The socket is not locked during execution, so that the out-of-band
+            // commands are not blocked.
+            let (return_msg, shutdown_flag) = qmp_command_exec(command, &performer, if_fd);
+
+            let locked_socket = shared_socket.lock().unwrap();
+            if !locked_socket.is_connected() {
+                break;
+            }
+            let mut qmp_service = locked_socket.get_socket_handler();
+            if let Err(e) = send_qmp_response(&mut qmp_service, &return_msg, shutdown_flag) {
+                error!("{:?}", e);
+            }
+        }
+    }
 }
 
 impl EventNotifierHelper for Socket {
     fn internal_notifiers(shared_socket: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
 
+        if EventLoop::get_ctx(Some(&QMP_IOTHREAD.to_string())).is_none() {
+            if let Err(e) = EventLoop::add_iothread(QMP_IOTHREAD) {
+                warn!("Failed to create qmp io-thread, out-of-band commands are executed in main loop: {:?}", e);
+            }
+        }
+
         let socket = shared_socket.clone();
         let handler: Rc<NotifierCallback> =
             Rc::new(move |_, _| Some(socket.lock().unwrap().create_event_notifier(socket.clone())));
@@ -279,6 +400,21 @@ impl EventNotifierHelper for Socket {
         );
         notifiers.push(notifier);
 
+        let socket = shared_socket.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd| {
+            read_fd(fd);
+            Socket::handle_inband(&socket);
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            shared_socket.lock().unwrap().inband.event.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        );
+        notifiers.push(notifier);
+
         notifiers
     }
 }
@@ -339,27 +475,29 @@ macro_rules! create_command_matches {
     };
 }
 
-/// Accept qmp command, analyze and exec it.
+/// Accept qmp command, analyze and exec it. The out-of-band command is executed
+/// at once, and the in-band command is sent to main loop if it's read by the QMP
+/// io-thread.
 ///
 /// # Arguments
 ///
-/// * `stream_fd` - The input stream file description.
-/// * `controller` - The controller which execute actual qmp command.
+/// * `socket` - The socket which the command is read from.
 /// * `leak_bucket` - The LeakBucket flow controller for qmp command.
+/// * `ctx_name` - The io-thread which reads the command, None for main loop.
 ///
 /// # Errors
 ///
 /// This function will fail when json parser failed or socket file description broke.
 fn handle_qmp(
-    stream_fd: RawFd,
-    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    socket: &Socket,
     leak_bucket: &mut LeakBucket,
+    ctx_name: Option<&String>,
 ) -> Result<()> {
-    let mut qmp_service = crate::socket::SocketHandler::new(stream_fd);
+    let mut qmp_service = socket.get_socket_handler();
 
     // If flow over `LEAK_BUCKET_LIMIT` per seconds, discard the request and return
     // a `OperationThrottled` error.
-    if leak_bucket.throttled(EventLoop::get_ctx(None).unwrap(), 1_u64) {
+    if leak_bucket.throttled(EventLoop::get_ctx(ctx_name).unwrap(), 1_u64) {
         qmp_service.discard()?;
         let err_resp = qmp_schema::QmpErrorClass::OperationThrottled(LEAK_BUCKET_LIMIT);
         qmp_service
@@ -370,31 +508,21 @@ fn handle_qmp(
         return Ok(());
     }
 
-    match qmp_service.decode_line() {
-        (Ok(None), _) => Ok(()),
-        (Ok(buffer), if_fd) => {
-            info!("QMP: --> {:?}", buffer);
-            let qmp_command: QmpCommand = buffer.unwrap();
-            let (return_msg, shutdown_flag) = qmp_command_exec(qmp_command, controller, if_fd);
-            info!("QMP: <-- {:?}", return_msg);
-            qmp_service.send_str(&return_msg)?;
-
-            // handle shutdown command
-            if shutdown_flag {
-                let shutdown_msg = qmp_schema::Shutdown {
-                    guest: false,
-                    reason: "host-qmp-quit".to_string(),
-                };
-                event!(Shutdown; shutdown_msg);
-                TempCleaner::clean();
-                set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
-
-                std::process::exit(0);
-            }
-
-            Ok(())
+    let (request, if_fd) = qmp_service.decode_line::<Value>();
+    match request.and_then(|request| request.map(parse_qmp_request).transpose()) {
+        Ok(None) => Ok(()),
+        Ok(Some((qmp_command, oob))) => {
+            info!("QMP: --> {:?}", qmp_command);
+            let (return_msg, shutdown_flag) = if oob {
+                qmp_oob_exec(qmp_command, socket)
+            } else if ctx_name.is_some() {
+                return socket.inband.push(qmp_command, if_fd);
+            } else {
+                qmp_command_exec(qmp_command, socket.performer.as_ref().unwrap(), if_fd)
+            };
+            send_qmp_response(&mut qmp_service, &return_msg, shutdown_flag)
         }
-        (Err(e), _) => {
+        Err(e) => {
             let err_resp = qmp_schema::QmpErrorClass::GenericError(format!("{}", &e));
             warn!("Qmp json parser made an error: {:?}", e);
             qmp_service.send_str(&serde_json::to_string(&Response::create_error_response(
@@ -405,6 +533,117 @@ fn handle_qmp(
     }
 }
 
+/// Parse the qmp request, which is executed out-of-band if it's issued by
+/// `exec-oob` instead of `execute`.
+fn parse_qmp_request(mut request: Value) -> Result<(QmpCommand, bool)> {
+    let mut oob_command = None;
+    if let Some(members) = request.as_object_mut() {
+        if let Some(name) = members.remove("exec-oob") {
+            if members.contains_key("execute") {
+                bail!("QMP input member 'execute' and 'exec-oob' can't be used together");
+            }
+            if !QmpChannel::oob_enabled() {
+                bail!("Please enable out-of-band first for the session during capabilities negotiation");
+            }
+            oob_command = Some(name.as_str().unwrap_or_default().to_string());
+            members.insert("execute".to_string(), name);
+        }
+    }
+
+    let qmp_command: QmpCommand = serde_json::from_value(request)?;
+    if let Some(name) = oob_command {
+        if !matches!(
+            qmp_command,
+            QmpCommand::quit { .. } | QmpCommand::query_status { .. }
+        ) {
+            bail!("The command {} does not support OOB", name);
+        }
+        return Ok((qmp_command, true));
+    }
+    Ok((qmp_command, false))
+}
+
+/// Send the response of qmp command, and exit if the command is `quit`.
+fn send_qmp_response(
+    qmp_service: &mut crate::socket::SocketHandler,
+    return_msg: &str,
+    shutdown_flag: bool,
+) -> Result<()> {
+    info!("QMP: <-- {:?}", return_msg);
+    qmp_service.send_str(return_msg)?;
+
+    // handle shutdown command
+    if shutdown_flag {
+        let shutdown_msg = qmp_schema::Shutdown {
+            guest: false,
+            reason: ShutdownCause::HostQmpQuit.reason().to_string(),
+        };
+        event!(Shutdown; shutdown_msg);
+        TempCleaner::clean();
+        set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
+
+        std::process::exit(0);
+    }
+
+    Ok(())
+}
+
+/// Execute the out-of-band command. The machine may be locked by the in-band
+/// command which blocks the main loop, so it's not locked here.
+fn qmp_oob_exec(qmp_command: QmpCommand, socket: &Socket) -> (String, bool) {
+    let mut shutdown_flag = false;
+    let (mut qmp_response, id) = match qmp_command {
+        QmpCommand::quit { id, .. } => {
+            // The VM is destroyed if the main loop is not busy, the process exits anyway.
+            if let Some(Ok(controller)) = socket.performer.as_ref().map(|p| p.try_lock()) {
+                controller.destroy();
+            }
+            shutdown_flag = true;
+            (Response::create_empty_response(), id)
+        }
+        QmpCommand::query_status { id, .. } => {
+            let state = socket
+                .vm_state
+                .as_ref()
+                .map(|state| *state.0.lock().unwrap());
+            let status = match state {
+                Some(KvmVmState::Running) => qmp_schema::StatusInfo {
+                    singlestep: false,
+                    running: true,
+                    status: qmp_schema::RunState::running,
+                },
+                Some(KvmVmState::Paused) => qmp_schema::StatusInfo {
+                    singlestep: false,
+                    running: false,
+                    status: qmp_schema::RunState::paused,
+                },
+                Some(KvmVmState::Suspended) => qmp_schema::StatusInfo {
+                    singlestep: false,
+                    running: false,
+                    status: qmp_schema::RunState::suspended,
+                },
+                _ => Default::default(),
+            };
+            (
+                Response::create_response(serde_json::to_value(status).unwrap(), None),
+                id,
+            )
+        }
+        _ => (
+            Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(
+                    "The command does not support OOB".to_string(),
+                ),
+                None,
+            ),
+            None,
+        ),
+    };
+
+    qmp_response.change_id(id);
+    (serde_json::to_string(&qmp_response).unwrap(), shutdown_flag)
+}
+
 /// Create a match , where `qmp_command` and its arguments matching by handle
 /// function, and exec this qmp command.
 fn qmp_command_exec(
@@ -415,6 +654,32 @@ fn qmp_command_exec(
     let mut qmp_response = Response::create_empty_response();
     let mut shutdown_flag = false;
 
+    // Long-running commands with `job-id` are run by the job workers, the job
+    // handle is returned at once and the result is reported by job events.
+    if let QmpCommand::migrate { arguments, id } = &qmp_command {
+        if let Some(job_id) = &arguments.job_id {
+            let result = controller
+                .lock()
+                .unwrap()
+                .migrate_task(arguments.uri.clone())
+                .and_then(|task| job_spawn(job_id, "migrate", task));
+            qmp_response = match result {
+                Ok(()) => {
+                    let handle = qmp_schema::JobHandle {
+                        job_id: job_id.clone(),
+                    };
+                    Response::create_response(serde_json::to_value(handle).unwrap(), None)
+                }
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                ),
+            };
+            qmp_response.change_id(id.clone());
+            return (serde_json::to_string(&qmp_response).unwrap(), false);
+        }
+    }
+
     // Use macro create match to cover most Qmp command
     let mut id = create_command_matches!(
         qmp_command.clone(); controller.lock().unwrap(); qmp_response;
@@ -422,6 +687,7 @@ fn qmp_command_exec(
         (cont, resume),
         (system_powerdown, powerdown),
         (system_reset, reset),
+        (system_wakeup, system_wakeup),
         (query_status, query_status),
         (query_version, query_version),
         (query_commands, query_commands),
@@ -442,34 +708,80 @@ fn qmp_command_exec(
         (query_named_block_nodes, query_named_block_nodes),
         (query_blockstats, query_blockstats),
         (query_block_jobs, query_block_jobs),
+        (query_jobs, query_jobs),
         (query_gic_capabilities, query_gic_capabilities),
         (query_iothreads, query_iothreads),
         (query_migrate, query_migrate),
         (cancel_migrate, cancel_migrate),
+        (query_migrate_parameters, query_migrate_parameters),
         (query_cpus, query_cpus),
         (query_balloon, query_balloon),
+        (balloon_cancel, balloon_cancel),
+        (query_balloon_stats, query_balloon_stats),
+        (query_balloon_policy, query_balloon_policy),
+        (query_vm_config, query_vm_config),
+        (query_interrupts, query_interrupts),
         (query_mem, query_mem),
         (query_vnc, query_vnc),
+        (query_display_channels, query_display_channels),
         (list_type, list_type),
+        (query_vm_generation_id, query_vm_generation_id),
+        (query_halt_poll, query_halt_poll),
+        (rtc_reset_reinjection, rtc_reset_reinjection),
+        (query_rtc, query_rtc),
+        (query_uuid, query_uuid),
+        (query_name, query_name),
         (query_hotpluggable_cpus, query_hotpluggable_cpus);
         (input_event, input_event, key, value),
+        (set_link, set_link, name, up),
+        (set_mac, set_mac, name, mac),
+        (set_vm_generation_id, set_vm_generation_id, guid),
         (device_list_properties, device_list_properties, typename),
         (device_del, device_del, id),
         (blockdev_del, blockdev_del, node_name),
         (netdev_del, netdev_del, id),
         (chardev_remove, chardev_remove, id),
+        (object_del, object_del, id),
+        (job_pause, job_pause, id),
+        (job_resume, job_resume, id),
+        (job_cancel, job_cancel, id),
+        (nbd_server_start, nbd_server_start, addr),
         (cameradev_del, cameradev_del,id),
+        (display_channel_del, display_channel_del, id),
         (balloon, balloon, value),
+        (set_balloon_stats_interval, set_balloon_stats_interval, interval),
         (migrate, migrate, uri);
         (device_add, device_add),
         (blockdev_add, blockdev_add),
         (netdev_add, netdev_add),
         (chardev_add, chardev_add),
+        (chardev_change, chardev_change),
+        (object_add, object_add),
         (cameradev_add, cameradev_add),
+        (migrate_set_parameters, migrate_set_parameters),
+        (migrate_set_capabilities, migrate_set_capabilities),
         (update_region, update_region),
         (human_monitor_command, human_monitor_command),
         (blockdev_snapshot_internal_sync, blockdev_snapshot_internal_sync),
-        (blockdev_snapshot_delete_internal_sync, blockdev_snapshot_delete_internal_sync)
+        (blockdev_snapshot_delete_internal_sync, blockdev_snapshot_delete_internal_sync),
+        (block_dirty_bitmap_add, block_dirty_bitmap_add),
+        (block_dirty_bitmap_clear, block_dirty_bitmap_clear),
+        (block_dirty_bitmap_remove, block_dirty_bitmap_remove),
+        (block_dirty_bitmap_export, block_dirty_bitmap_export),
+        (nbd_server_add, nbd_server_add),
+        (blockdev_snapshot_sync, blockdev_snapshot_sync),
+        (eject, eject),
+        (change, change),
+        (drive_mirror, drive_mirror),
+        (block_job_complete, block_job_complete),
+        (block_job_cancel, block_job_cancel),
+        (set_balloon_policy, set_balloon_policy),
+        (reclaim_guest_memory, reclaim_guest_memory),
+        (pflash_seal, pflash_seal),
+        (query_stats, query_stats),
+        (set_halt_poll, set_halt_poll),
+        (cpu_throttle_set, cpu_throttle_set),
+        (display_channel_add, display_channel_add)
     );
 
     // Handle the Qmp command which macro can't cover
@@ -484,6 +796,54 @@ fn qmp_command_exec(
                 qmp_response = controller.lock().unwrap().getfd(arguments.fd_name, if_fd);
                 id
             }
+            QmpCommand::qmp_capabilities { arguments, id } => {
+                if let Err(e) = QmpChannel::set_capabilities(arguments) {
+                    qmp_response = Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+                id
+            }
+            QmpCommand::set_log_level { arguments, id } => {
+                if let Err(e) = set_log_level(arguments.subsystem.as_deref(), &arguments.level) {
+                    qmp_response = Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+                id
+            }
+            QmpCommand::query_log_level { id, .. } => {
+                let levels = query_log_levels()
+                    .into_iter()
+                    .map(|(subsystem, level)| qmp_schema::LogLevelInfo {
+                        subsystem: subsystem.to_string(),
+                        level,
+                    })
+                    .collect::<Vec<_>>();
+                qmp_response =
+                    Response::create_response(serde_json::to_value(levels).unwrap(), None);
+                id
+            }
+            QmpCommand::logfile_reopen { id, .. } => {
+                if let Err(e) = reopen_log_file() {
+                    qmp_response = Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                        None,
+                    );
+                }
+                id
+            }
+            QmpCommand::query_logfile { id, .. } => {
+                let (path, size) = query_log_file();
+                let info = qmp_schema::LogfileInfo {
+                    path: if path.is_empty() { None } else { Some(path) },
+                    size: size as u64,
+                };
+                qmp_response = Response::create_response(serde_json::to_value(info).unwrap(), None);
+                id
+            }
             _ => None,
         }
     }
@@ -600,6 +960,40 @@ mod tests {
         recover_unix_socket_environment("06");
     }
 
+    #[test]
+    fn test_parse_oob_request() {
+        use serde_json::json;
+
+        QmpChannel::object_init();
+        let request = json!({ "exec-oob": "query-status", "id": "oob-0" });
+        // Out-of-band execution is not enabled.
+        assert!(parse_qmp_request(request.clone()).is_err());
+
+        let caps = qmp_schema::qmp_capabilities {
+            enable: Some(vec!["oob".to_string()]),
+            ..Default::default()
+        };
+        QmpChannel::set_capabilities(caps).unwrap();
+        let (qmp_command, oob) = parse_qmp_request(request).unwrap();
+        assert!(oob);
+        assert!(matches!(
+            qmp_command,
+            QmpCommand::query_status { id: Some(id), .. } if id == "oob-0"
+        ));
+        let (_, oob) = parse_qmp_request(json!({ "execute": "query-status" })).unwrap();
+        assert!(!oob);
+        assert!(parse_qmp_request(json!({ "exec-oob": "stop" })).is_err());
+        assert!(parse_qmp_request(json!({ "execute": "quit", "exec-oob": "quit" })).is_err());
+
+        let caps = qmp_schema::qmp_capabilities {
+            enable: Some(vec!["unknown".to_string()]),
+            ..Default::default()
+        };
+        assert!(QmpChannel::set_capabilities(caps).is_err());
+        QmpChannel::set_capabilities(Default::default()).unwrap();
+        assert!(!QmpChannel::oob_enabled());
+    }
+
     #[test]
     fn test_qmp_send_response() {
         use std::io::Read;
@@ -17,10 +17,11 @@ use vmm_sys_util::signal::register_signal_handler;
 
 use crate::{
     event,
+    machine::ShutdownCause,
     qmp::{qmp_channel::QmpChannel, qmp_schema},
     temp_cleaner::TempCleaner,
 };
-use util::set_termi_canon_mode;
+use util::{logger::flush_log, set_termi_canon_mode};
 
 const VM_EXIT_SUCCESS: i32 = 0;
 pub const VM_EXIT_GENE_ERR: i32 = 1;
@@ -34,6 +35,7 @@ fn basic_clean() {
 }
 
 pub fn exit_with_code(code: i32) {
+    flush_log();
     // Safe, because the basic_clean function has been executed before exit.
     unsafe {
         libc::_exit(code);
@@ -44,7 +46,7 @@ extern "C" fn handle_signal_kill(num: c_int, _: *mut siginfo_t, _: *mut c_void)
     if QmpChannel::is_connected() {
         let shutdown_msg = qmp_schema::Shutdown {
             guest: false,
-            reason: "Guest shutdown by signal ".to_string() + &num.to_string(),
+            reason: ShutdownCause::HostSignal.reason().to_string(),
         };
         event!(Shutdown; shutdown_msg);
     }
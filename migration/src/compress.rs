@@ -0,0 +1,225 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::MigrationError;
+use util::byte_code::ByteCode;
+
+/// Size of memory compressed as a whole. Both source and destination split the
+/// memory blocks with this size, so that the compressed chunks can be matched.
+pub const COMPRESS_CHUNK_SIZE: u64 = 1 << 16;
+/// Compression level of zstd, which is fast enough for migration.
+const ZSTD_LEVEL: i32 = 1;
+
+/// Compression algorithm of migration RAM stream.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompressAlgorithm {
+    Zstd = 1,
+    Zlib = 2,
+}
+
+impl CompressAlgorithm {
+    /// Algorithms supported by this side, in order of preference.
+    const SUPPORTED: [CompressAlgorithm; 2] = [CompressAlgorithm::Zstd, CompressAlgorithm::Zlib];
+
+    fn mask(self) -> u32 {
+        1 << (self as u32)
+    }
+
+    /// Bitmask of all supported algorithms.
+    pub fn supported_mask() -> u32 {
+        Self::SUPPORTED
+            .iter()
+            .fold(0, |mask, alg| mask | alg.mask())
+    }
+
+    /// Choose the most preferred algorithm supported by both sides.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_mask` - Bitmask of algorithms supported by peer.
+    pub fn negotiate(peer_mask: u32) -> Option<CompressAlgorithm> {
+        Self::SUPPORTED
+            .iter()
+            .find(|alg| peer_mask & alg.mask() != 0)
+            .copied()
+    }
+
+    pub fn from_u32(value: u32) -> Option<CompressAlgorithm> {
+        Self::SUPPORTED
+            .iter()
+            .find(|alg| **alg as u32 == value)
+            .copied()
+    }
+}
+
+impl std::fmt::Display for CompressAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CompressAlgorithm::Zstd => "zstd",
+                CompressAlgorithm::Zlib => "zlib",
+            }
+        )
+    }
+}
+
+/// Capabilities exchanged between source and destination VM before sending
+/// VM configuration.
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub struct MigrationCaps {
+    /// Bitmask of compression algorithms supported by source, or the algorithm
+    /// chosen by destination.
+    pub compress: u32,
+}
+
+impl ByteCode for MigrationCaps {}
+
+/// Compression context. Each compress thread owns its context.
+pub enum Compressor {
+    Zstd(zstd::bulk::Compressor<'static>),
+    Zlib,
+}
+
+impl Compressor {
+    pub fn new(algorithm: CompressAlgorithm) -> Result<Self> {
+        match algorithm {
+            CompressAlgorithm::Zstd => Ok(Compressor::Zstd(
+                zstd::bulk::Compressor::new(ZSTD_LEVEL)
+                    .with_context(|| "Failed to create zstd compressor")?,
+            )),
+            CompressAlgorithm::Zlib => Ok(Compressor::Zlib),
+        }
+    }
+
+    /// Compress the whole `data`.
+    pub fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let compressed = match self {
+            Compressor::Zstd(ctx) => ctx.compress(data)?,
+            Compressor::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+        };
+
+        Ok(compressed)
+    }
+}
+
+/// Decompression context.
+pub enum Decompressor {
+    Zstd(zstd::bulk::Decompressor<'static>),
+    Zlib,
+}
+
+impl Decompressor {
+    pub fn new(algorithm: CompressAlgorithm) -> Result<Self> {
+        match algorithm {
+            CompressAlgorithm::Zstd => Ok(Decompressor::Zstd(
+                zstd::bulk::Decompressor::new()
+                    .with_context(|| "Failed to create zstd decompressor")?,
+            )),
+            CompressAlgorithm::Zlib => Ok(Decompressor::Zlib),
+        }
+    }
+
+    /// Decompress `data`, which must be exactly `len` bytes after decompression.
+    pub fn decompress(&mut self, data: &[u8], len: usize) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Decompressor::Zstd(ctx) => ctx.decompress(data, len)?,
+            Decompressor::Zlib => {
+                // Read at most one byte more than expected, so that the overrun is
+                // rejected below without inflating the whole stream.
+                let mut buf = Vec::with_capacity(len + 1);
+                ZlibDecoder::new(data)
+                    .take(len as u64 + 1)
+                    .read_to_end(&mut buf)?;
+                buf
+            }
+        };
+        if decompressed.len() != len {
+            bail!(MigrationError::RecvVmMemoryErr(format!(
+                "decompressed length {} mismatch, expected {}",
+                decompressed.len(),
+                len
+            )));
+        }
+
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let all = CompressAlgorithm::supported_mask();
+        assert_eq!(
+            CompressAlgorithm::negotiate(all),
+            Some(CompressAlgorithm::Zstd)
+        );
+        assert_eq!(
+            CompressAlgorithm::negotiate(CompressAlgorithm::Zlib.mask()),
+            Some(CompressAlgorithm::Zlib)
+        );
+        assert_eq!(CompressAlgorithm::negotiate(0), None);
+        assert_eq!(
+            CompressAlgorithm::from_u32(2),
+            Some(CompressAlgorithm::Zlib)
+        );
+        assert_eq!(CompressAlgorithm::from_u32(0), None);
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let mut data = vec![0_u8; COMPRESS_CHUNK_SIZE as usize];
+        for (i, byte) in data.iter_mut().enumerate().step_by(7) {
+            *byte = i as u8;
+        }
+
+        for algorithm in CompressAlgorithm::SUPPORTED {
+            let mut compressor = Compressor::new(algorithm).unwrap();
+            let mut decompressor = Decompressor::new(algorithm).unwrap();
+            let compressed = compressor.compress(&data).unwrap();
+            assert!(compressed.len() < data.len());
+            let decompressed = decompressor.decompress(&compressed, data.len()).unwrap();
+            assert_eq!(decompressed, data);
+            assert!(decompressor
+                .decompress(&compressed, data.len() - 1)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompress_overrun() {
+        let data = vec![0_u8; 64 * COMPRESS_CHUNK_SIZE as usize];
+        for algorithm in CompressAlgorithm::SUPPORTED {
+            let mut compressor = Compressor::new(algorithm).unwrap();
+            let mut decompressor = Decompressor::new(algorithm).unwrap();
+            let compressed = compressor.compress(&data).unwrap();
+            assert!(decompressor
+                .decompress(&compressed, COMPRESS_CHUNK_SIZE as usize)
+                .is_err());
+        }
+    }
+}
@@ -253,6 +253,11 @@ pub trait Lifecycle {
             locked_vm.lock().unwrap().pause();
         }
 
+        let locked_devices = &MIGRATION_MANAGER.vmm.read().unwrap().devices;
+        for (_, device) in locked_devices.iter() {
+            device.lock().unwrap().pause()?;
+        }
+
         Ok(())
     }
 
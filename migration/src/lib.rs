@@ -14,6 +14,7 @@
 //!
 //! Offer snapshot and migration interface for VM.
 
+pub mod compress;
 pub mod error;
 pub mod general;
 pub mod manager;
@@ -24,14 +25,18 @@ pub mod snapshot;
 pub use anyhow::Result;
 
 pub use error::MigrationError;
-pub use manager::{MigrationHook, MigrationManager};
+pub use manager::{MigrationCapabilities, MigrationHook, MigrationManager};
 pub use protocol::{DeviceStateDesc, FieldDesc, MemBlock, MigrationStatus, StateTransfer};
 
+use std::io::{Read, Write};
 use std::time::Duration;
 use std::{net::TcpStream, os::unix::net::UnixStream, thread};
 
+use anyhow::bail;
 use log::error;
 
+use machine_manager::config::MigrateMode;
+use machine_manager::job::{Job, JobTask};
 use machine_manager::qmp::{qmp_response::Response, qmp_schema};
 
 /// Start to snapshot VM.
@@ -52,24 +57,60 @@ pub fn snapshot(path: String) -> Response {
     Response::create_empty_response()
 }
 
-/// Start to migrate VM with unix mode.
-///
-/// # Arguments
-///
-/// * `path` - Unix socket path, as /tmp/migration.socket.
-pub fn migration_unix_mode(path: String) -> Response {
-    let mut socket = match UnixStream::connect(path) {
-        Ok(_sock) => {
-            // Specify the tcp receiving or send timeout.
-            let time_out = Some(Duration::from_secs(30));
-            _sock
-                .set_read_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
-                .set_write_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
-        }
+/// Connect to the destination VM, with the receiving and send timeout set.
+fn connect_dest<T, F>(path: String, connect: F) -> std::io::Result<T>
+where
+    F: FnOnce(String) -> std::io::Result<T>,
+    T: MigrationSocket,
+{
+    let sock = connect(path)?;
+    // Specify the receiving or send timeout.
+    let time_out = Some(Duration::from_secs(30));
+    sock.set_timeout(time_out)
+        .unwrap_or_else(|e| error!("{:?}", e));
+    Ok(sock)
+}
+
+/// Socket which migration data is sent by.
+trait MigrationSocket: Read + Write + Send + 'static {
+    fn set_timeout(&self, time_out: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl MigrationSocket for UnixStream {
+    fn set_timeout(&self, time_out: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(time_out)?;
+        self.set_write_timeout(time_out)
+    }
+}
+
+impl MigrationSocket for TcpStream {
+    fn set_timeout(&self, time_out: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(time_out)?;
+        self.set_write_timeout(time_out)
+    }
+}
+
+/// Send migration data to the destination VM, and recover the VM if it fails.
+fn send_migration<T: MigrationSocket>(socket: &mut T) -> Result<()> {
+    if let Err(e) = MigrationManager::send_migration(socket) {
+        error!("Failed to send migration: {:?}", e);
+        MigrationManager::stop_throttle_vcpus();
+        let _ = MigrationManager::recover_from_migration();
+        let _ =
+            MigrationManager::set_status(MigrationStatus::Failed).map_err(|e| error!("{:?}", e));
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Start to send migration data in a new thread.
+fn spawn_migration<T: MigrationSocket>(
+    name: &str,
+    path: String,
+    connect: fn(String) -> std::io::Result<T>,
+) -> Response {
+    let mut socket = match connect_dest(path, connect) {
+        Ok(sock) => sock,
         Err(e) => {
             return Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(e.to_string()),
@@ -79,14 +120,9 @@ pub fn migration_unix_mode(path: String) -> Response {
     };
 
     if let Err(e) = thread::Builder::new()
-        .name("unix_migrate".to_string())
+        .name(name.to_string())
         .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
-                error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
-                let _ = MigrationManager::set_status(MigrationStatus::Failed)
-                    .map_err(|e| error!("{:?}", e));
-            }
+            let _ = send_migration(&mut socket);
         })
     {
         return Response::create_error_response(
@@ -98,57 +134,59 @@ pub fn migration_unix_mode(path: String) -> Response {
     Response::create_empty_response()
 }
 
+/// Start to migrate VM with unix mode.
+///
+/// # Arguments
+///
+/// * `path` - Unix socket path, as /tmp/migration.socket.
+pub fn migration_unix_mode(path: String) -> Response {
+    spawn_migration("unix_migrate", path, UnixStream::connect)
+}
+
 /// Start to migrate VM with tcp mode.
 ///
 /// # Arguments
 ///
 /// * `path` - Tcp ip and port, as 192.168.1.1:4446.
 pub fn migration_tcp_mode(path: String) -> Response {
-    let mut socket = match TcpStream::connect(path) {
-        Ok(_sock) => {
-            // Specify the tcp receiving or send timeout.
-            let time_out = Some(Duration::from_secs(30));
-            _sock
-                .set_read_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
-                .set_write_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
+    spawn_migration("tcp_migrate", path, TcpStream::connect)
+}
+
+/// Prepare the snapshot or migration which runs in the background job. The
+/// destination is connected before the job starts, so that the errors of
+/// connection are reported at once.
+///
+/// # Arguments
+///
+/// * `mode` - Migrate mode, file, unix or tcp.
+/// * `path` - Snapshot dir path, unix socket path or tcp ip and port.
+pub fn migration_task(mode: MigrateMode, path: String) -> Result<JobTask> {
+    let task: JobTask = match mode {
+        MigrateMode::File => Box::new(move |_: &Job| {
+            MigrationManager::save_snapshot(&path).inspect_err(|_| {
+                let _ = MigrationManager::set_status(MigrationStatus::Failed);
+            })
+        }),
+        MigrateMode::Unix => {
+            let mut socket = connect_dest(path, UnixStream::connect)?;
+            Box::new(move |_: &Job| send_migration(&mut socket))
         }
-        Err(e) => {
-            return Response::create_error_response(
-                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-                None,
-            )
+        MigrateMode::Tcp => {
+            let mut socket = connect_dest(path, TcpStream::connect)?;
+            Box::new(move |_: &Job| send_migration(&mut socket))
         }
+        MigrateMode::Unknown => bail!("Unknown migrate mode"),
     };
-
-    if let Err(e) = thread::Builder::new()
-        .name("tcp_migrate".to_string())
-        .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
-                error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
-                let _ = MigrationManager::set_status(MigrationStatus::Failed)
-                    .map_err(|e| error!("{:?}", e));
-            }
-        })
-    {
-        return Response::create_error_response(
-            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-            None,
-        );
-    };
-
-    Response::create_empty_response()
+    Ok(task)
 }
 
 /// Query the current migration status.
 pub fn query_migrate() -> Response {
     let status_str = MigrationManager::status().to_string();
+    let cpu_throttle = MigrationManager::cpu_throttle();
     let migration_info = qmp_schema::MigrationInfo {
         status: Some(status_str),
+        cpu_throttle_percentage: (cpu_throttle != 0).then_some(cpu_throttle),
     };
 
     Response::create_response(serde_json::to_value(migration_info).unwrap(), None)
@@ -165,3 +203,49 @@ pub fn cancel_migrate() -> Response {
 
     Response::create_empty_response()
 }
+
+/// Set the parameters of migration.
+///
+/// # Arguments
+///
+/// * `args` - The parameters need to be updated.
+pub fn set_migrate_parameters(args: qmp_schema::MigrateSetParametersArgument) -> Response {
+    if let Err(e) = MigrationManager::set_parameters(&args) {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    Response::create_empty_response()
+}
+
+/// Query the current parameters of migration.
+pub fn query_migrate_parameters() -> Response {
+    let parameters = MigrationManager::parameters();
+
+    Response::create_response(serde_json::to_value(parameters).unwrap(), None)
+}
+
+/// Set the capabilities of migration.
+///
+/// # Arguments
+///
+/// * `args` - The capabilities need to be updated.
+pub fn set_migrate_capabilities(args: qmp_schema::MigrateSetCapabilitiesArgument) -> Response {
+    if let Err(e) = MigrationManager::set_capabilities(&args.capabilities) {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    Response::create_empty_response()
+}
+
+/// Query the current capabilities of migration.
+pub fn query_migrate_capabilities() -> Response {
+    let caps = MigrationManager::capabilities();
+
+    Response::create_response(serde_json::to_value(caps).unwrap(), None)
+}
@@ -21,6 +21,7 @@ use anyhow::{Context, Result};
 use log::info;
 use once_cell::sync::Lazy;
 
+use crate::compress::CompressAlgorithm;
 use crate::general::translate_id;
 use crate::migration::DirtyBitmap;
 use crate::protocol::{DeviceStateDesc, MemBlock, MigrationStatus, StateTransfer};
@@ -35,6 +36,7 @@ pub(crate) static MIGRATION_MANAGER: Lazy<MigrationManager> = Lazy::new(|| Migra
     status: Arc::new(RwLock::new(MigrationStatus::None)),
     vmm_bitmaps: Arc::new(RwLock::new(HashMap::new())),
     limit: Arc::new(RwLock::new(MigrationLimit::default())),
+    caps: Arc::new(RwLock::new(MigrationCapabilities::default())),
 });
 
 /// A hook for `Device` to save device state to `Write` object and load device
@@ -138,6 +140,22 @@ pub trait MigrationHook: StateTransfer {
     fn resume(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Quiesce the device after VM is paused in source VM.
+    ///
+    /// # Notes
+    ///
+    /// For some device, such as vhost-device, the backend keeps processing
+    /// virtqueues when VM is paused, it need a step to stop the backend before
+    /// saving device state.
+    fn pause(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Restart the device quiesced by `pause` if migration is failed in source VM.
+    fn restart(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// The instance represents a single object in VM.
@@ -183,22 +201,54 @@ pub struct Vmm {
 pub struct MigrationLimit {
     /// Start time of each iteration.
     pub iteration_start_time: Instant,
+    /// Bytes of memory transferred since the start of each iteration.
+    pub iteration_transferred: u64,
     /// Virtual machine downtime.
     pub limit_downtime: u64,
     /// Max number of iterations during iteratively sending dirty memory.
     pub max_dirty_iterations: u16,
+    /// Max bandwidth of migration in bytes per second, 0 means no limit.
+    pub max_bandwidth: u64,
+    /// Number of threads used to compress migration data.
+    pub compress_threads: u8,
+    /// Initial percentage of time vCPUs sleep when auto-converge starts throttling.
+    pub cpu_throttle_initial: u8,
+    /// Percentage added to the throttling on each iteration which doesn't converge.
+    pub cpu_throttle_increment: u8,
+    /// Max percentage of time vCPUs sleep by auto-converge.
+    pub max_cpu_throttle: u8,
+    /// Percentage of time vCPUs sleep by auto-converge, 0 if vCPUs are not throttled.
+    pub cpu_throttle: u8,
 }
 
 impl Default for MigrationLimit {
     fn default() -> Self {
         Self {
             iteration_start_time: Instant::now(),
+            iteration_transferred: 0,
             limit_downtime: 50,
             max_dirty_iterations: 30,
+            max_bandwidth: 0,
+            compress_threads: 8,
+            cpu_throttle_initial: 20,
+            cpu_throttle_increment: 10,
+            max_cpu_throttle: 99,
+            cpu_throttle: 0,
         }
     }
 }
 
+/// Capabilities of migration.
+#[derive(Default)]
+pub struct MigrationCapabilities {
+    /// Whether to compress memory sent to destination.
+    pub compress: bool,
+    /// Compression algorithm negotiated with peer, `None` means no compression.
+    pub compress_algorithm: Option<CompressAlgorithm>,
+    /// Whether to throttle vCPUs if migration doesn't converge.
+    pub auto_converge: bool,
+}
+
 /// This structure is to manage all resource during migration.
 /// It is also the only way to call on `MIGRATION_MANAGER`.
 pub struct MigrationManager {
@@ -212,6 +262,8 @@ pub struct MigrationManager {
     pub vmm_bitmaps: Arc<RwLock<HashMap<u32, DirtyBitmap>>>,
     /// Limiting elements of migration.
     pub limit: Arc<RwLock<MigrationLimit>>,
+    /// Capabilities of migration.
+    pub caps: Arc<RwLock<MigrationCapabilities>>,
 }
 
 impl MigrationManager {
@@ -14,20 +14,47 @@ use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{scope, sleep};
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
 use log::{info, warn};
 
+use crate::compress::{
+    CompressAlgorithm, Compressor, Decompressor, MigrationCaps, COMPRESS_CHUNK_SIZE,
+};
 use crate::general::Lifecycle;
 use crate::manager::MIGRATION_MANAGER;
 use crate::protocol::{MemBlock, MigrationStatus, Request, Response, TransStatus};
-use crate::{MigrationError, MigrationManager};
+use crate::{MigrationError, MigrationHook, MigrationManager};
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{get_pci_bdf, PciBdf, VmConfig};
+use machine_manager::cpu_throttle::{
+    cpu_throttle_set, cpu_throttle_stop, MAX_THROTTLE_PERCENTAGE, MIN_THROTTLE_PERCENTAGE,
+};
+use machine_manager::qmp::qmp_schema::{
+    MigrateCapabilities, MigrateSetParametersArgument, MigrationParameters,
+};
+use util::byte_code::ByteCode;
 use util::unix::host_page_size;
 
+/// Max size of memory sent at a time when bandwidth of migration is limited.
+const THROTTLE_CHUNK_SIZE: u64 = 1 << 20;
+/// Time slice in milliseconds to account the bandwidth of migration.
+const THROTTLE_SLICE_MS: u128 = 100;
+/// Max number of compress threads.
+const MAX_MIGRATION_THREADS: u8 = 64;
+/// Migration data is sent through the single channel, multifd is not supported.
+const MULTIFD_CHANNELS: u8 = 1;
+/// Number of chunks compressed by each compress thread at a time.
+const COMPRESS_CHUNKS_PER_THREAD: usize = 16;
+/// Name of the capability to compress migration memory.
+const CAP_COMPRESS: &str = "compress";
+/// Name of the capability to throttle vCPUs if migration doesn't converge.
+const CAP_AUTO_CONVERGE: &str = "auto-converge";
+
 impl MigrationManager {
     /// Start VM live migration at source VM.
     ///
@@ -43,6 +70,9 @@ impl MigrationManager {
         // Activate the migration status of source and destination virtual machine.
         Self::active_migration(fd).with_context(|| "Failed to active migration")?;
 
+        // Negotiate capabilities with destination.
+        Self::send_capabilities(fd).with_context(|| "Failed to negotiate capabilities")?;
+
         // Send source virtual machine configuration.
         Self::send_vm_config(fd).with_context(|| "Failed to send vm config")?;
 
@@ -50,6 +80,11 @@ impl MigrationManager {
         Self::start_dirty_log().with_context(|| "Failed to start logging dirty page")?;
 
         // Send all memory of virtual machine itself to destination.
+        {
+            let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+            limit.iteration_start_time = Instant::now();
+            limit.iteration_transferred = 0;
+        }
         Self::send_vm_memory(fd).with_context(|| "Failed to send VM memory")?;
 
         // Iteratively send virtual machine dirty memory.
@@ -63,7 +98,9 @@ impl MigrationManager {
             if !Self::iteration_send(fd)? {
                 break;
             }
+            Self::throttle_vcpus();
         }
+        Self::stop_throttle_vcpus();
 
         // Check whether the migration is canceled.
         if Self::is_canceled() {
@@ -75,8 +112,9 @@ impl MigrationManager {
         // Pause virtual machine.
         Self::pause()?;
 
-        // Send remaining virtual machine dirty memory.
-        Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+        // Send remaining virtual machine dirty memory without bandwidth limit.
+        let blocks = Self::get_dirty_blocks()?;
+        Self::send_memory(fd, blocks, false).with_context(|| "Failed to send dirty memory")?;
 
         // Stop logging dirty pages.
         Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
@@ -118,8 +156,16 @@ impl MigrationManager {
             )));
         }
 
-        // Check source and destination virtual machine configuration.
-        let request = Request::recv_msg(fd)?;
+        // Negotiate capabilities if source requests, and then check source and
+        // destination virtual machine configuration.
+        MIGRATION_MANAGER.caps.write().unwrap().compress_algorithm = None;
+        let mut request = Request::recv_msg(fd)?;
+        if request.status == TransStatus::Capabilities {
+            info!("Receive Capabilities status");
+            Self::recv_capabilities(fd, request.length)
+                .with_context(|| "Failed to negotiate capabilities")?;
+            request = Request::recv_msg(fd)?;
+        }
         if request.status == TransStatus::VmConfig {
             info!("Receive VmConfig status");
             Self::check_vm_config(fd, request.length)
@@ -160,6 +206,70 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Send capabilities to destination and get the negotiated result.
+    fn send_capabilities<T>(fd: &mut T) -> Result<()>
+    where
+        T: Write + Read,
+    {
+        MIGRATION_MANAGER.caps.write().unwrap().compress_algorithm = None;
+        if !MIGRATION_MANAGER.caps.read().unwrap().compress {
+            return Ok(());
+        }
+
+        let caps = MigrationCaps {
+            compress: CompressAlgorithm::supported_mask(),
+        };
+        Request::send_msg(
+            fd,
+            TransStatus::Capabilities,
+            size_of::<MigrationCaps>() as u64,
+        )?;
+        fd.write_all(caps.as_bytes())?;
+
+        let result = Response::recv_msg(fd)?;
+        if result.is_err() {
+            return Err(anyhow!(MigrationError::ResponseErr));
+        }
+        let mut caps = MigrationCaps::default();
+        fd.read_exact(caps.as_mut_bytes())?;
+
+        let algorithm = CompressAlgorithm::from_u32(caps.compress);
+        match algorithm {
+            Some(alg) => info!("Compress migration memory with {}", alg),
+            None => warn!("Destination doesn't support compression, send memory uncompressed"),
+        }
+        MIGRATION_MANAGER.caps.write().unwrap().compress_algorithm = algorithm;
+
+        Ok(())
+    }
+
+    /// Receive capabilities from source, and reply the negotiated result.
+    fn recv_capabilities<T>(fd: &mut T, len: u64) -> Result<()>
+    where
+        T: Write + Read,
+    {
+        if len != size_of::<MigrationCaps>() as u64 {
+            Response::send_msg(fd, TransStatus::Error)?;
+            bail!("Invalid length {} of migration capabilities", len);
+        }
+        let mut caps = MigrationCaps::default();
+        fd.read_exact(caps.as_mut_bytes())?;
+
+        let algorithm = CompressAlgorithm::negotiate(caps.compress);
+        if let Some(alg) = algorithm {
+            info!("Decompress migration memory with {}", alg);
+        }
+        MIGRATION_MANAGER.caps.write().unwrap().compress_algorithm = algorithm;
+
+        Response::send_msg(fd, TransStatus::Ok)?;
+        let caps = MigrationCaps {
+            compress: algorithm.map_or(0, |alg| alg as u32),
+        };
+        fd.write_all(caps.as_bytes())?;
+
+        Ok(())
+    }
+
     /// Send Vm configuration from source virtual machine.
     fn send_vm_config<T>(fd: &mut T) -> Result<()>
     where
@@ -285,28 +395,205 @@ impl MigrationManager {
     where
         T: Write + Read,
     {
-        let mut state =
-            Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+        let blocks = Self::get_dirty_blocks()?;
+        if blocks.is_empty() {
+            return Ok(false);
+        }
 
-        // Check the virtual machine downtime.
-        if MIGRATION_MANAGER
-            .limit
-            .read()
-            .unwrap()
-            .iteration_start_time
-            .elapsed()
-            < Duration::from_millis(MIGRATION_MANAGER.limit.read().unwrap().limit_downtime)
+        // The dirty memory can be sent within the downtime limit with the transfer
+        // rate of last iteration, so it's time to stop VM and do the final copy.
+        let pending: u64 = blocks.iter().map(|block| block.len).sum();
+        let state = Self::estimated_downtime(pending) > Self::downtime_limit();
+
+        Self::send_memory(fd, blocks, true).with_context(|| "Failed to send dirty memory")?;
+
+        Ok(state)
+    }
+
+    /// Throttle vCPUs more if auto-converge is enabled, so that the guest dirties
+    /// memory slower than it is sent.
+    fn throttle_vcpus() {
+        if !MIGRATION_MANAGER.caps.read().unwrap().auto_converge {
+            return;
+        }
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        let percentage = next_cpu_throttle(
+            limit.cpu_throttle,
+            limit.cpu_throttle_initial,
+            limit.cpu_throttle_increment,
+            limit.max_cpu_throttle,
+        );
+        if percentage == limit.cpu_throttle {
+            return;
+        }
+        match cpu_throttle_set(percentage) {
+            Ok(()) => {
+                info!("Migration auto-converge: throttle vCPUs to {}%", percentage);
+                limit.cpu_throttle = percentage;
+            }
+            Err(e) => warn!("Failed to throttle vCPUs for migration: {:?}", e),
+        }
+    }
+
+    /// Stop throttling vCPUs by auto-converge.
+    pub fn stop_throttle_vcpus() {
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        if limit.cpu_throttle != 0 {
+            cpu_throttle_stop();
+            limit.cpu_throttle = 0;
+        }
+    }
+
+    /// Get the percentage of time vCPUs sleep by auto-converge, 0 if they are not throttled.
+    pub fn cpu_throttle() -> u8 {
+        MIGRATION_MANAGER.limit.read().unwrap().cpu_throttle
+    }
+
+    /// Get the downtime limit of migration in milliseconds.
+    fn downtime_limit() -> u64 {
+        MIGRATION_MANAGER.limit.read().unwrap().limit_downtime
+    }
+
+    /// Estimate the time in milliseconds to send `pending` bytes of memory, with the
+    /// transfer rate since last update of iteration start time. Update the iteration
+    /// start time for the next iteration.
+    ///
+    /// # Arguments
+    ///
+    /// * `pending` - The size of memory to be sent.
+    fn estimated_downtime(pending: u64) -> u64 {
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        let elapsed = limit.iteration_start_time.elapsed().as_millis() as u64;
+        let transferred = limit.iteration_transferred;
+        limit.iteration_start_time = Instant::now();
+        limit.iteration_transferred = 0;
+
+        if transferred == 0 {
+            return u64::MAX;
+        }
+        pending.saturating_mul(elapsed) / transferred
+    }
+
+    /// Set parameters of migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The parameters need to be updated.
+    pub fn set_parameters(args: &MigrateSetParametersArgument) -> Result<()> {
+        if let Some(threads) = args.compress_threads {
+            if threads == 0 || threads > MAX_MIGRATION_THREADS {
+                bail!(
+                    "Invalid migration threads number {}, it should be in range [1, {}]",
+                    threads,
+                    MAX_MIGRATION_THREADS
+                );
+            }
+        }
+        if let Some(channels) = args.multifd_channels {
+            if channels != MULTIFD_CHANNELS {
+                bail!(
+                    "Multifd migration is not supported, multifd-channels can only be {}",
+                    MULTIFD_CHANNELS
+                );
+            }
+        }
+        for percentage in [
+            args.cpu_throttle_initial,
+            args.cpu_throttle_increment,
+            args.max_cpu_throttle,
+        ]
+        .into_iter()
+        .flatten()
         {
-            state = false;
+            if !(MIN_THROTTLE_PERCENTAGE..=MAX_THROTTLE_PERCENTAGE).contains(&percentage) {
+                bail!(
+                    "Invalid cpu throttle percentage {}, it should be in range [{}, {}]",
+                    percentage,
+                    MIN_THROTTLE_PERCENTAGE,
+                    MAX_THROTTLE_PERCENTAGE
+                );
+            }
+        }
+        if Self::is_active() && args.compress_threads.is_some() {
+            bail!("compress-threads can't be changed during migration");
         }
-        // Update iteration start time.
-        MIGRATION_MANAGER
-            .limit
-            .write()
-            .unwrap()
-            .iteration_start_time = Instant::now();
 
-        Ok(state)
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        if let Some(max_bandwidth) = args.max_bandwidth {
+            limit.max_bandwidth = max_bandwidth;
+        }
+        if let Some(downtime_limit) = args.downtime_limit {
+            limit.limit_downtime = downtime_limit;
+        }
+        if let Some(compress_threads) = args.compress_threads {
+            limit.compress_threads = compress_threads;
+        }
+        if let Some(cpu_throttle_initial) = args.cpu_throttle_initial {
+            limit.cpu_throttle_initial = cpu_throttle_initial;
+        }
+        if let Some(cpu_throttle_increment) = args.cpu_throttle_increment {
+            limit.cpu_throttle_increment = cpu_throttle_increment;
+        }
+        if let Some(max_cpu_throttle) = args.max_cpu_throttle {
+            limit.max_cpu_throttle = max_cpu_throttle;
+        }
+
+        Ok(())
+    }
+
+    /// Set capabilities of migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `caps` - The capabilities and their states.
+    pub fn set_capabilities(caps: &[MigrateCapabilities]) -> Result<()> {
+        if Self::is_active() {
+            bail!("Capabilities can't be changed during migration");
+        }
+        for cap in caps.iter() {
+            if cap.capability != CAP_COMPRESS && cap.capability != CAP_AUTO_CONVERGE {
+                bail!("Unsupported migration capability {}", cap.capability);
+            }
+        }
+
+        let mut locked_caps = MIGRATION_MANAGER.caps.write().unwrap();
+        for cap in caps.iter() {
+            match cap.capability.as_str() {
+                CAP_COMPRESS => locked_caps.compress = cap.state,
+                _ => locked_caps.auto_converge = cap.state,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get capabilities of migration.
+    pub fn capabilities() -> Vec<MigrateCapabilities> {
+        let caps = MIGRATION_MANAGER.caps.read().unwrap();
+        vec![
+            MigrateCapabilities {
+                state: caps.compress,
+                capability: CAP_COMPRESS.to_string(),
+            },
+            MigrateCapabilities {
+                state: caps.auto_converge,
+                capability: CAP_AUTO_CONVERGE.to_string(),
+            },
+        ]
+    }
+
+    /// Get parameters of migration.
+    pub fn parameters() -> MigrationParameters {
+        let limit = MIGRATION_MANAGER.limit.read().unwrap();
+        MigrationParameters {
+            max_bandwidth: limit.max_bandwidth,
+            downtime_limit: limit.limit_downtime,
+            compress_threads: limit.compress_threads,
+            multifd_channels: MULTIFD_CHANNELS,
+            cpu_throttle_initial: limit.cpu_throttle_initial,
+            cpu_throttle_increment: limit.cpu_throttle_increment,
+            max_cpu_throttle: limit.max_cpu_throttle,
+        }
     }
 
     /// Receive memory data from source VM.
@@ -328,15 +615,20 @@ impl MigrationManager {
             )
         })?;
 
+        let algorithm = MIGRATION_MANAGER.caps.read().unwrap().compress_algorithm;
         if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
-            for block in blocks.iter() {
-                locked_memory.recv_memory(
-                    fd,
-                    MemBlock {
-                        gpa: block.gpa,
-                        len: block.len,
-                    },
-                )?;
+            if let Some(alg) = algorithm {
+                Self::recv_compressed_memory(fd, locked_memory, &blocks, alg)?;
+            } else {
+                for block in blocks.iter() {
+                    locked_memory.recv_memory(
+                        fd,
+                        MemBlock {
+                            gpa: block.gpa,
+                            len: block.len,
+                        },
+                    )?;
+                }
             }
         }
 
@@ -351,7 +643,8 @@ impl MigrationManager {
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
     /// * `blocks` - The memory blocks need to be sent.
-    fn send_memory<T>(fd: &mut T, blocks: Vec<MemBlock>) -> Result<()>
+    /// * `throttle` - Whether to limit the bandwidth of sending memory.
+    fn send_memory<T>(fd: &mut T, blocks: Vec<MemBlock>, throttle: bool) -> Result<()>
     where
         T: Read + Write,
     {
@@ -361,15 +654,37 @@ impl MigrationManager {
             std::slice::from_raw_parts(blocks.as_ptr() as *const MemBlock as *const u8, len)
         })?;
 
+        let algorithm = MIGRATION_MANAGER.caps.read().unwrap().compress_algorithm;
         if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
-            for block in blocks.iter() {
-                locked_memory.send_memory(
-                    fd,
-                    MemBlock {
-                        gpa: block.gpa,
-                        len: block.len,
-                    },
-                )?;
+            if let Some(alg) = algorithm {
+                Self::send_compressed_memory(fd, locked_memory, &blocks, alg, throttle)?;
+            } else if throttle {
+                let mut throttler = Throttler::new();
+                for chunk in split_blocks(&blocks, THROTTLE_CHUNK_SIZE) {
+                    let len = chunk.len;
+                    locked_memory.send_memory(fd, chunk)?;
+                    MIGRATION_MANAGER
+                        .limit
+                        .write()
+                        .unwrap()
+                        .iteration_transferred += len;
+                    throttler.throttle(len);
+                }
+            } else {
+                for block in blocks.iter() {
+                    locked_memory.send_memory(
+                        fd,
+                        MemBlock {
+                            gpa: block.gpa,
+                            len: block.len,
+                        },
+                    )?;
+                    MIGRATION_MANAGER
+                        .limit
+                        .write()
+                        .unwrap()
+                        .iteration_transferred += block.len;
+                }
             }
         }
 
@@ -381,6 +696,121 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Compress memory blocks and send them to destination VM. Memory is split
+    /// into chunks, each compress thread compresses some chunks with its own context.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `memory` - The memory instance to read memory data.
+    /// * `blocks` - The memory blocks need to be sent.
+    /// * `algorithm` - The compression algorithm.
+    /// * `throttle` - Whether to limit the bandwidth of sending memory.
+    fn send_compressed_memory<T>(
+        fd: &mut T,
+        memory: &Arc<dyn MigrationHook + Send + Sync>,
+        blocks: &[MemBlock],
+        algorithm: CompressAlgorithm,
+        throttle: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        let threads = MIGRATION_MANAGER.limit.read().unwrap().compress_threads as usize;
+        let mut contexts = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            contexts.push(Compressor::new(algorithm)?);
+        }
+
+        let mut throttler = Throttler::new();
+        let chunks = split_blocks(blocks, COMPRESS_CHUNK_SIZE);
+        for batch in chunks.chunks(threads * COMPRESS_CHUNKS_PER_THREAD) {
+            let mut bufs = Vec::with_capacity(batch.len());
+            for chunk in batch.iter() {
+                let mut buf = Vec::with_capacity(chunk.len as usize);
+                memory.send_memory(&mut buf, chunk.clone())?;
+                bufs.push(buf);
+            }
+
+            let compressed = scope(|s| {
+                let handles: Vec<_> = contexts
+                    .iter_mut()
+                    .zip(bufs.chunks(COMPRESS_CHUNKS_PER_THREAD))
+                    .map(|(ctx, group)| {
+                        s.spawn(move || {
+                            group
+                                .iter()
+                                .map(|buf| ctx.compress(buf))
+                                .collect::<Result<Vec<Vec<u8>>>>()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| {
+                        h.join()
+                            .unwrap_or_else(|_| Err(anyhow!("Compress thread panicked")))
+                    })
+                    .collect::<Result<Vec<Vec<Vec<u8>>>>>()
+            })?;
+
+            for data in compressed.iter().flatten() {
+                let len = data.len() as u32;
+                fd.write_all(len.as_bytes())?;
+                fd.write_all(data)?;
+                let sent = (size_of::<u32>() + data.len()) as u64;
+                MIGRATION_MANAGER
+                    .limit
+                    .write()
+                    .unwrap()
+                    .iteration_transferred += sent;
+                if throttle {
+                    throttler.throttle(sent);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive compressed memory blocks from source VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `memory` - The memory instance to write memory data.
+    /// * `blocks` - The memory blocks need to be received.
+    /// * `algorithm` - The compression algorithm.
+    fn recv_compressed_memory<T>(
+        fd: &mut T,
+        memory: &Arc<dyn MigrationHook + Send + Sync>,
+        blocks: &[MemBlock],
+        algorithm: CompressAlgorithm,
+    ) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        let mut decompressor = Decompressor::new(algorithm)?;
+        let mut data = Vec::new();
+        for chunk in split_blocks(blocks, COMPRESS_CHUNK_SIZE) {
+            let mut len = 0_u32;
+            fd.read_exact(len.as_mut_bytes())?;
+            if len as u64 > COMPRESS_CHUNK_SIZE * 2 {
+                bail!(MigrationError::RecvVmMemoryErr(format!(
+                    "invalid compressed length {}",
+                    len
+                )));
+            }
+            data.resize(len as usize, 0);
+            fd.read_exact(&mut data)?;
+
+            let buf = decompressor.decompress(&data, chunk.len as usize)?;
+            memory.recv_memory(&mut buf.as_slice(), chunk)?;
+        }
+
+        Ok(())
+    }
+
     /// Send entire VM memory data to destination VM.
     ///
     /// # Arguments
@@ -399,20 +829,13 @@ impl MigrationManager {
             });
         }
 
-        Self::send_memory(fd, blocks)?;
+        Self::send_memory(fd, blocks, true)?;
 
         Ok(())
     }
 
-    /// Send dirty memory data to destination VM.
-    ///
-    /// # Arguments
-    ///
-    /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn send_dirty_memory<T>(fd: &mut T) -> Result<bool>
-    where
-        T: Read + Write,
-    {
+    /// Collect dirty memory blocks of all memory slots.
+    fn get_dirty_blocks() -> Result<Vec<MemBlock>> {
         let mut blocks: Vec<MemBlock> = Vec::new();
         let mem_slots = KVM_FDS.load().get_mem_slots();
         for (_, slot) in mem_slots.lock().unwrap().iter() {
@@ -420,13 +843,7 @@ impl MigrationManager {
             blocks.extend(sub_blocks);
         }
 
-        if blocks.is_empty() {
-            return Ok(false);
-        }
-
-        Self::send_memory(fd, blocks)?;
-
-        Ok(true)
+        Ok(blocks)
     }
 
     /// Send VM state data to destination VM.
@@ -565,6 +982,10 @@ impl MigrationManager {
 
     /// Recover the virtual machine if migration is failed.
     pub fn recover_from_migration() -> Result<()> {
+        for (_, device) in MIGRATION_MANAGER.vmm.read().unwrap().devices.iter() {
+            device.lock().unwrap().restart()?;
+        }
+
         if let Some(locked_vm) = &MIGRATION_MANAGER.vmm.read().unwrap().vm {
             locked_vm.lock().unwrap().resume();
         }
@@ -573,6 +994,79 @@ impl MigrationManager {
     }
 }
 
+/// Compute the percentage of vCPU throttling for the next iteration. It starts from
+/// `initial`, and is increased by `increment` on each iteration within `max`.
+fn next_cpu_throttle(current: u8, initial: u8, increment: u8, max: u8) -> u8 {
+    if current == 0 {
+        return initial.min(max);
+    }
+    current.saturating_add(increment).min(max)
+}
+
+/// Split memory blocks into chunks no larger than `size`.
+///
+/// # Arguments
+///
+/// * `blocks` - The memory blocks.
+/// * `size` - The max size of each chunk.
+fn split_blocks(blocks: &[MemBlock], size: u64) -> Vec<MemBlock> {
+    let mut chunks = Vec::new();
+    for block in blocks.iter() {
+        let mut offset = 0;
+        while offset < block.len {
+            let len = std::cmp::min(size, block.len - offset);
+            chunks.push(MemBlock {
+                gpa: block.gpa + offset,
+                len,
+            });
+            offset += len;
+        }
+    }
+
+    chunks
+}
+
+/// Limit the bandwidth of sending memory with `max_bandwidth` of migration.
+struct Throttler {
+    /// Start time of current time slice.
+    slice_start: Instant,
+    /// Bytes sent in current time slice.
+    slice_bytes: u64,
+}
+
+impl Throttler {
+    fn new() -> Self {
+        Throttler {
+            slice_start: Instant::now(),
+            slice_bytes: 0,
+        }
+    }
+
+    /// Account the sent bytes, and sleep if the bandwidth exceeds the limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - The size of memory just sent.
+    fn throttle(&mut self, len: u64) {
+        // Bandwidth may be changed during migration, always use the latest one.
+        let max_bandwidth = MIGRATION_MANAGER.limit.read().unwrap().max_bandwidth;
+        if max_bandwidth == 0 {
+            return;
+        }
+
+        self.slice_bytes += len;
+        let expected = Duration::from_micros(self.slice_bytes * 1_000_000 / max_bandwidth);
+        let elapsed = self.slice_start.elapsed();
+        if expected > elapsed {
+            sleep(expected - elapsed);
+        }
+        if self.slice_start.elapsed().as_millis() >= THROTTLE_SLICE_MS {
+            self.slice_start = Instant::now();
+            self.slice_bytes = 0;
+        }
+    }
+}
+
 /// Dirty bitmap information of vmm memory slot.
 pub struct DirtyBitmap {
     /// Guest address.
@@ -688,7 +1182,7 @@ pub trait Migratable {
     fn get_dirty_log(slot: &MemorySlot) -> Result<Vec<MemBlock>> {
         // Get dirty memory from vmm.
         let mut vmm_dirty_bitmap = Vec::new();
-        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
+        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
         for (_, map) in bitmaps.iter() {
             if (slot.guest_phys_addr == map.gpa) && (slot.memory_size == map.len) {
                 vmm_dirty_bitmap = map.get_and_clear_dirty();
@@ -723,7 +1217,7 @@ pub trait Migratable {
             return;
         }
 
-        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
+        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
         for (_, map) in bitmaps.iter() {
             if (addr >= map.hva) && ((addr + len) <= (map.hva + map.len)) {
                 map.mark_bitmap(addr - map.hva + map.gpa, len);
@@ -775,3 +1269,114 @@ pub trait Migratable {
 }
 
 impl Migratable for MigrationManager {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_parameters() {
+        let args = MigrateSetParametersArgument {
+            max_bandwidth: Some(1 << 25),
+            downtime_limit: Some(300),
+            compress_threads: Some(4),
+            multifd_channels: None,
+            cpu_throttle_initial: Some(30),
+            cpu_throttle_increment: None,
+            max_cpu_throttle: None,
+        };
+        assert!(MigrationManager::set_parameters(&args).is_ok());
+        let parameters = MigrationManager::parameters();
+        assert_eq!(parameters.max_bandwidth, 1 << 25);
+        assert_eq!(parameters.downtime_limit, 300);
+        assert_eq!(parameters.compress_threads, 4);
+        assert_eq!(parameters.multifd_channels, 1);
+        assert_eq!(parameters.cpu_throttle_initial, 30);
+        assert_eq!(parameters.cpu_throttle_increment, 10);
+        assert_eq!(parameters.max_cpu_throttle, 99);
+
+        let args = MigrateSetParametersArgument {
+            multifd_channels: Some(0),
+            ..Default::default()
+        };
+        assert!(MigrationManager::set_parameters(&args).is_err());
+        let args = MigrateSetParametersArgument {
+            multifd_channels: Some(2),
+            ..Default::default()
+        };
+        assert!(MigrationManager::set_parameters(&args).is_err());
+        let args = MigrateSetParametersArgument {
+            multifd_channels: Some(1),
+            ..Default::default()
+        };
+        assert!(MigrationManager::set_parameters(&args).is_ok());
+        let args = MigrateSetParametersArgument {
+            compress_threads: Some(MAX_MIGRATION_THREADS + 1),
+            ..Default::default()
+        };
+        assert!(MigrationManager::set_parameters(&args).is_err());
+        assert_eq!(MigrationManager::parameters().compress_threads, 4);
+        let args = MigrateSetParametersArgument {
+            max_cpu_throttle: Some(100),
+            ..Default::default()
+        };
+        assert!(MigrationManager::set_parameters(&args).is_err());
+        assert_eq!(MigrationManager::parameters().max_cpu_throttle, 99);
+    }
+
+    #[test]
+    fn test_set_capabilities() {
+        let caps = vec![MigrateCapabilities {
+            state: true,
+            capability: CAP_COMPRESS.to_string(),
+        }];
+        assert!(MigrationManager::set_capabilities(&caps).is_ok());
+        assert!(MigrationManager::capabilities()[0].state);
+
+        let caps = vec![MigrateCapabilities {
+            state: true,
+            capability: "unknown".to_string(),
+        }];
+        assert!(MigrationManager::set_capabilities(&caps).is_err());
+        assert!(MigrationManager::capabilities()[0].state);
+
+        let caps = vec![MigrateCapabilities {
+            state: true,
+            capability: CAP_AUTO_CONVERGE.to_string(),
+        }];
+        assert!(MigrationManager::set_capabilities(&caps).is_ok());
+        let caps = MigrationManager::capabilities();
+        assert!(caps[0].state);
+        assert_eq!(caps[1].capability, CAP_AUTO_CONVERGE);
+        assert!(caps[1].state);
+    }
+
+    #[test]
+    fn test_next_cpu_throttle() {
+        assert_eq!(next_cpu_throttle(0, 20, 10, 99), 20);
+        assert_eq!(next_cpu_throttle(20, 20, 10, 99), 30);
+        assert_eq!(next_cpu_throttle(95, 20, 10, 99), 99);
+        assert_eq!(next_cpu_throttle(99, 20, 10, 99), 99);
+        assert_eq!(next_cpu_throttle(0, 50, 10, 40), 40);
+    }
+
+    #[test]
+    fn test_split_blocks() {
+        let blocks = vec![
+            MemBlock {
+                gpa: 0,
+                len: 0x2800,
+            },
+            MemBlock {
+                gpa: 0x10000,
+                len: 0x1000,
+            },
+        ];
+        let chunks = split_blocks(&blocks, 0x1000);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[2].gpa, 0x2000);
+        assert_eq!(chunks[2].len, 0x800);
+        assert_eq!(chunks[3].gpa, 0x10000);
+        assert_eq!(chunks[3].len, 0x1000);
+    }
+}
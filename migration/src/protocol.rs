@@ -132,6 +132,8 @@ pub enum TransStatus {
     Error,
     /// Unknown status in migration .
     Unknown,
+    /// Negotiate capabilities of migration.
+    Capabilities,
 }
 
 impl Default for TransStatus {
@@ -155,6 +157,7 @@ impl std::fmt::Display for TransStatus {
                 TransStatus::Ok => "Ok",
                 TransStatus::Error => "Error",
                 TransStatus::Unknown => "Unknown",
+                TransStatus::Capabilities => "Capabilities",
             }
         )
     }
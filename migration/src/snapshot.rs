@@ -29,6 +29,10 @@ pub const GICV3_SNAPSHOT_ID: &str = "gicv3";
 pub const GICV3_ITS_SNAPSHOT_ID: &str = "gicv3_its";
 pub const PL011_SNAPSHOT_ID: &str = "pl011";
 pub const PL031_SNAPSHOT_ID: &str = "pl031";
+pub const VMGENID_SNAPSHOT_ID: &str = "vmgenid";
+pub const HPET_SNAPSHOT_ID: &str = "hpet";
+pub const IOAPIC_SNAPSHOT_ID: &str = "ioapic";
+pub const PIC_SNAPSHOT_ID: &str = "pic";
 
 /// The suffix used for snapshot memory storage.
 const MEMORY_PATH_SUFFIX: &str = "memory";
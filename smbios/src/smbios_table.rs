@@ -17,6 +17,7 @@ use machine_manager::config::{
     SmbiosType2Config, SmbiosType3Config, SmbiosType4Config,
 };
 use util::byte_code::ByteCode;
+use util::checksum::obj_checksum;
 
 const TYPE0_HANDLE: u16 = 0x0;
 const TYPE1_HANDLE: u16 = 0x100;
@@ -1055,7 +1056,49 @@ impl SmbiosEntryPoint30 {
 }
 
 pub fn build_smbios_ep30(table_len: u32) -> Vec<u8> {
-    let ep = SmbiosEntryPoint30::new(table_len);
+    let mut ep = SmbiosEntryPoint30::new(table_len);
+    // The structure table address is patched by firmware, which recomputes the
+    // checksum, but keep the anchor valid as handed over through fw_cfg.
+    let sum = obj_checksum(&ep);
+    ep.checksum = (-(sum as i8)) as u8;
 
     ep.as_bytes().to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::checksum::checksum;
+
+    #[test]
+    fn test_smbios_ep30() {
+        let ep = build_smbios_ep30(0x1234);
+        assert_eq!(ep.len(), size_of::<SmbiosEntryPoint30>());
+        assert_eq!(&ep[0..5], b"_SM3_");
+        assert_eq!(ep[6] as usize, ep.len());
+        assert_eq!(&ep[12..16], &0x1234_u32.to_le_bytes());
+        assert_eq!(checksum(&ep), 0);
+    }
+
+    #[test]
+    fn test_smbios_tables() {
+        let mut smbios = SmbiosConfig::default();
+        smbios.type1.manufacturer = Some(String::from("vendor-x"));
+        smbios.type1.serial = Some(String::from("sn-0001"));
+        let mach_cfg = MachineConfig::default();
+
+        let table = SmbiosTable::new().build_smbios_tables(smbios, &mach_cfg, Vec::new());
+        // Type 0 is the first structure and type 127 terminates the table.
+        assert_eq!(table[0], 0);
+        let end = &table[table.len() - 6..];
+        assert_eq!(end, &[127, 4, 0x00, 0x7F, 0, 0]);
+
+        // Type 1 strings follow its formatted area in the order they were set.
+        let type1 = table
+            .windows(2)
+            .position(|w| w == [1, size_of::<SmbiosType1>() as u8])
+            .unwrap();
+        let strings = &table[type1 + size_of::<SmbiosType1>()..];
+        assert!(strings.starts_with(b"vendor-x\0"));
+    }
+}
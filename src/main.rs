@@ -17,18 +17,21 @@ use anyhow::{bail, Context, Result};
 use log::{error, info};
 use thiserror::Error;
 
+use chardev_backend::mux::register_mux_monitor;
 use machine::{LightMachine, MachineOps, StdMachine};
 use machine_manager::{
     cmdline::{check_api_channel, create_args_parser, create_vmconfig},
     config::MachineType,
     config::VmConfig,
     event_loop::EventLoop,
+    metrics::start_metrics_server,
     qmp::qmp_channel::QmpChannel,
     qmp::qmp_socket::Socket,
     signal_handler::{exit_with_code, register_kill_signal, VM_EXIT_GENE_ERR},
     temp_cleaner::TempCleaner,
     test_server::TestSock,
 };
+use util::cgroup::init_vm_cgroup;
 use util::loop_context::EventNotifierHelper;
 use util::test_helper::{is_test_enabled, set_test_enabled};
 use util::{arg_parser, daemonize::daemonize, logger, set_termi_canon_mode};
@@ -75,7 +78,7 @@ impl ExitCode for () {
 }
 
 fn main() {
-    ::std::process::exit(match run() {
+    let code = match run() {
         Ok(ret) => ExitCode::code(ret),
         Err(ref e) => {
             write!(&mut ::std::io::stderr(), "{}", format_args!("{:?}\r\n", e))
@@ -83,7 +86,10 @@ fn main() {
 
             1
         }
-    });
+    };
+    // Write out the buffered logs.
+    logger::flush_log();
+    ::std::process::exit(code);
 }
 
 fn run() -> Result<()> {
@@ -95,6 +101,12 @@ fn run() -> Result<()> {
 
     let logfile_path = cmd_args.value_of("display log").unwrap_or_default();
     logger::init_log(logfile_path)?;
+    if let Some(levels) = cmd_args.value_of("log-level") {
+        logger::set_log_levels(&levels).with_context(|| "Failed to set log level")?;
+    }
+    if let Some(format) = cmd_args.value_of("log-format") {
+        logger::set_log_format(&format).with_context(|| "Failed to set log format")?;
+    }
 
     std::panic::set_hook(Box::new(|panic_msg| {
         set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
@@ -115,6 +127,12 @@ fn run() -> Result<()> {
     let mut vm_config: VmConfig = create_vmconfig(&cmd_args)?;
     info!("VmConfig is {:?}", vm_config);
 
+    if let Some(path) = cmd_args.value_of("dump-config") {
+        vm_config.dump_profile(&path)?;
+        info!("VmConfig is dumped to {}", path);
+        return Ok(());
+    }
+
     match real_main(&cmd_args, &mut vm_config) {
         Ok(()) => {
             info!("MainLoop over, Vm exit");
@@ -155,6 +173,12 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
         bail!("-pidfile must be used with -daemonize together.");
     }
 
+    // The VM cgroup is joined before any other thread is created.
+    if let Some(cgroup) = vm_config.cgroup.as_ref() {
+        init_vm_cgroup(&cgroup.path, cgroup.cpu_weight, cgroup.cpuset.as_deref())
+            .with_context(|| "Failed to init cgroup")?;
+    }
+
     QmpChannel::object_init();
     EventLoop::object_init(&vm_config.iothreads)?;
     register_kill_signal();
@@ -175,6 +199,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            register_mux_monitor(vm.clone());
             vm
         }
         MachineType::StandardVm => {
@@ -200,6 +225,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            register_mux_monitor(vm.clone());
             vm
         }
         MachineType::None => {
@@ -214,6 +240,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            register_mux_monitor(vm.clone());
             vm
         }
     };
@@ -226,6 +253,10 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
         .with_context(|| "Failed to add api event to MainLoop")?;
     }
 
+    if let Some(addr) = vm_config.metrics.as_ref() {
+        start_metrics_server(addr).with_context(|| "Failed to start metrics endpoint.")?;
+    }
+
     machine::vm_run(&vm, cmd_args).with_context(|| "Failed to start VM.")?;
 
     let balloon_switch_on = vm_config.dev_name.get("balloon").is_some();
@@ -0,0 +1,230 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(target_arch = "x86_64")]
+use address_space::KvmIoListener;
+use address_space::{AddressSpace, GuestAddress, HostMemMapping, KvmMemoryListener, Region};
+use devices::pci::PciBus;
+use devices::sysbus::SysBus;
+use hypervisor::test::TestHypervisor;
+use hypervisor::{set_hypervisor, IoEventAddress, MsiMessage};
+#[cfg(target_arch = "x86_64")]
+use machine_manager::config::BootSource;
+use machine_manager::event_loop::EventLoop;
+
+/// Size of guest ram, which starts at 0.
+pub const RAM_SIZE: u64 = 64 << 20;
+/// MMIO region of system bus devices.
+pub const SYSBUS_MMIO_BASE: u64 = 0x1000_0000;
+pub const SYSBUS_MMIO_END: u64 = 0x2000_0000;
+/// MMIO region for the BARs of PCI devices.
+pub const PCI_MMIO_BASE: u64 = 0x2000_0000;
+/// Irqs allocated to system bus devices.
+pub const SYSBUS_IRQ_BASE: i32 = 32;
+const SYSBUS_IRQ_MAX: i32 = 63;
+const MEM_SLOTS_NUM: u32 = 32;
+/// Timeout of waiting for the devices to handle requests.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The hypervisor is global, so only one test machine exists at a time in a process. It's
+/// replaced by the next machine, rather than reset when the machine is dropped, as the
+/// devices may still use it on their way out.
+static MACHINE_LOCK: Mutex<()> = Mutex::new(());
+static MAIN_LOOP: Once = Once::new();
+
+fn start_main_loop() {
+    MAIN_LOOP.call_once(|| {
+        EventLoop::object_init(&None).unwrap();
+        thread::Builder::new()
+            .name("main_loop".to_string())
+            .spawn(|| EventLoop::loop_run().unwrap())
+            .unwrap();
+    });
+}
+
+pub struct TestMachine {
+    pub hypervisor: Arc<TestHypervisor>,
+    pub sys_mem: Arc<AddressSpace>,
+    #[cfg(target_arch = "x86_64")]
+    pub sys_io: Arc<AddressSpace>,
+    pub sysbus: SysBus,
+    pub pci_bus: Arc<Mutex<PciBus>>,
+    #[cfg(target_arch = "x86_64")]
+    pub boot_source: Arc<Mutex<BootSource>>,
+    /// Next free guest address to allocate.
+    free_addr: u64,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestMachine {
+    pub fn new() -> Self {
+        // Don't let the panic of previous test fail the others.
+        let guard = MACHINE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let hypervisor = Arc::new(TestHypervisor::new());
+        set_hypervisor(Some(hypervisor.clone()));
+        start_main_loop();
+
+        let sys_mem =
+            AddressSpace::new(Region::init_container_region(u64::MAX, "SysMem"), "sys_mem")
+                .unwrap();
+        sys_mem
+            .register_listener(Arc::new(Mutex::new(KvmMemoryListener::new(MEM_SLOTS_NUM))))
+            .unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, RAM_SIZE, None, false, false, false)
+                .unwrap(),
+        );
+        sys_mem
+            .root()
+            .add_subregion(Region::init_ram_region(ram, "ram"), 0)
+            .unwrap();
+
+        #[cfg(target_arch = "x86_64")]
+        let sys_io = {
+            let sys_io =
+                AddressSpace::new(Region::init_container_region(1 << 16, "SysIo"), "sys_io")
+                    .unwrap();
+            sys_io
+                .register_listener(Arc::new(Mutex::new(KvmIoListener::default())))
+                .unwrap();
+            sys_io
+        };
+
+        let sysbus = SysBus::new(
+            #[cfg(target_arch = "x86_64")]
+            &sys_io,
+            &sys_mem,
+            (SYSBUS_IRQ_BASE, SYSBUS_IRQ_MAX),
+            (SYSBUS_MMIO_BASE, SYSBUS_MMIO_END),
+        );
+        let pci_bus = Arc::new(Mutex::new(PciBus::new(
+            "pcie.0".to_string(),
+            #[cfg(target_arch = "x86_64")]
+            sys_io.root().clone(),
+            sys_mem.root().clone(),
+        )));
+
+        TestMachine {
+            hypervisor,
+            sys_mem,
+            #[cfg(target_arch = "x86_64")]
+            sys_io,
+            sysbus,
+            pci_bus,
+            #[cfg(target_arch = "x86_64")]
+            boot_source: Arc::new(Mutex::new(BootSource::default())),
+            free_addr: 0,
+            _guard: guard,
+        }
+    }
+
+    /// Allocate guest memory, which is never freed until the machine is dropped.
+    pub fn alloc(&mut self, size: u64, align: u64) -> u64 {
+        let addr = self.free_addr.div_ceil(align) * align;
+        assert!(addr + size <= RAM_SIZE, "Guest memory exhausted");
+        self.free_addr = addr + size;
+        addr
+    }
+
+    pub fn memread(&self, addr: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0_u8; len];
+        self.sys_mem
+            .read(&mut data.as_mut_slice(), GuestAddress(addr), len as u64)
+            .unwrap();
+        data
+    }
+
+    pub fn memwrite(&self, addr: u64, data: &[u8]) {
+        self.sys_mem
+            .write(&mut &data[..], GuestAddress(addr), data.len() as u64)
+            .unwrap();
+    }
+
+    /// Write to the MMIO space, which signals the matched ioeventfd instead of calling the
+    /// device's write callback, like what KVM does.
+    pub fn mmio_write(&self, addr: u64, data: &[u8]) {
+        if self
+            .hypervisor
+            .signal_ioevent(IoEventAddress::Mmio(addr), data)
+        {
+            return;
+        }
+        self.memwrite(addr, data);
+    }
+
+    pub fn readb(&self, addr: u64) -> u8 {
+        self.memread(addr, 1)[0]
+    }
+
+    pub fn readw(&self, addr: u64) -> u16 {
+        u16::from_le_bytes(self.memread(addr, 2).try_into().unwrap())
+    }
+
+    pub fn readl(&self, addr: u64) -> u32 {
+        u32::from_le_bytes(self.memread(addr, 4).try_into().unwrap())
+    }
+
+    pub fn readq(&self, addr: u64) -> u64 {
+        u64::from_le_bytes(self.memread(addr, 8).try_into().unwrap())
+    }
+
+    pub fn writeb(&self, addr: u64, value: u8) {
+        self.mmio_write(addr, &[value]);
+    }
+
+    pub fn writew(&self, addr: u64, value: u16) {
+        self.mmio_write(addr, &value.to_le_bytes());
+    }
+
+    pub fn writel(&self, addr: u64, value: u32) {
+        self.mmio_write(addr, &value.to_le_bytes());
+    }
+
+    pub fn writeq(&self, addr: u64, value: u64) {
+        self.mmio_write(addr, &value.to_le_bytes());
+    }
+
+    /// Wait until an interrupt is injected to `irq` through irqfd.
+    pub fn wait_irq(&self, irq: i32) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.hypervisor.take_irq(irq as u32) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    /// Wait until an MSI is signaled, return all the MSIs signaled so far.
+    pub fn wait_msi(&self) -> Vec<MsiMessage> {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            let msis = self.hypervisor.take_msis();
+            if !msis.is_empty() {
+                return msis;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        Vec::new()
+    }
+}
+
+impl Default for TestMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Harness to run device tests in process, without `/dev/kvm` or a guest.
+//!
+//! The devices are realized on the buses of a `TestMachine`, whose hypervisor is a
+//! `TestHypervisor`. Test code acts as the guest driver: it accesses the device registers
+//! and guest memory through the machine, and checks the interrupts recorded by the
+//! hypervisor. The device handlers run in the main loop, just like they do in a VM.
+//!
+//! It's shared by the test files as a module rather than a library, so that the
+//! `test_hypervisor` feature of `hypervisor` is only a dev-dependency, and never unified
+//! into the binaries built with the workspace.
+
+// Each test file uses only a part of the harness.
+#![allow(dead_code)]
+
+pub mod machine;
+pub mod pci;
+pub mod virtio;
@@ -0,0 +1,146 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use devices::pci::PciDevOps;
+
+use super::machine::TestMachine;
+
+pub const PCI_VENDOR_ID: usize = 0x00;
+pub const PCI_DEVICE_ID: usize = 0x02;
+pub const PCI_COMMAND: usize = 0x04;
+pub const PCI_BAR0: usize = 0x10;
+pub const PCI_CAPABILITY_LIST: usize = 0x34;
+
+pub const PCI_COMMAND_MEMORY: u16 = 0x2;
+pub const PCI_COMMAND_MASTER: u16 = 0x4;
+
+pub const PCI_CAP_ID_VNDR: u8 = 0x09;
+pub const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+const PCI_BAR_MEM_TYPE_MASK: u32 = 0x6;
+const PCI_BAR_MEM_TYPE_64: u32 = 0x4;
+
+const MSIX_CAP_CONTROL: usize = 0x02;
+const MSIX_CAP_TABLE: usize = 0x04;
+const MSIX_CAP_ENABLE: u16 = 0x8000;
+const MSIX_TABLE_BIR_MASK: u32 = 0x7;
+const MSIX_TABLE_ENTRY_SIZE: u64 = 16;
+
+/// Test driver of the device at `devfn` of the root bus. The configuration space is
+/// accessed directly, rather than by the host bridge.
+pub struct TestPciDev {
+    pub devfn: u8,
+    dev: Arc<Mutex<dyn PciDevOps>>,
+}
+
+impl TestPciDev {
+    pub fn new(machine: &TestMachine, devfn: u8) -> Self {
+        let dev = machine
+            .pci_bus
+            .lock()
+            .unwrap()
+            .devices
+            .get(&devfn)
+            .expect("No device at the devfn")
+            .clone();
+        TestPciDev { devfn, dev }
+    }
+
+    pub fn config_read(&self, offset: usize, len: usize) -> Vec<u8> {
+        let mut data = vec![0_u8; len];
+        self.dev.lock().unwrap().read_config(offset, &mut data);
+        data
+    }
+
+    pub fn config_write(&self, offset: usize, data: &[u8]) {
+        self.dev.lock().unwrap().write_config(offset, data);
+    }
+
+    pub fn config_readb(&self, offset: usize) -> u8 {
+        self.config_read(offset, 1)[0]
+    }
+
+    pub fn config_readw(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.config_read(offset, 2).try_into().unwrap())
+    }
+
+    pub fn config_readl(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.config_read(offset, 4).try_into().unwrap())
+    }
+
+    pub fn config_writew(&self, offset: usize, value: u16) {
+        self.config_write(offset, &value.to_le_bytes());
+    }
+
+    pub fn config_writel(&self, offset: usize, value: u32) {
+        self.config_write(offset, &value.to_le_bytes());
+    }
+
+    /// Get the offsets of all the capabilities with `cap_id`.
+    pub fn find_capabilities(&self, cap_id: u8) -> Vec<usize> {
+        let mut caps = Vec::new();
+        let mut offset = self.config_readb(PCI_CAPABILITY_LIST) as usize;
+        while offset != 0 {
+            if self.config_readb(offset) == cap_id {
+                caps.push(offset);
+            }
+            offset = self.config_readb(offset + 1) as usize;
+        }
+        caps
+    }
+
+    /// Set the address of memory BAR `bar`, which is mapped once the memory space is enabled.
+    pub fn set_bar(&self, bar: usize, addr: u64) {
+        let offset = PCI_BAR0 + bar * 4;
+        let low = self.config_readl(offset);
+        self.config_writel(offset, addr as u32);
+        if low & PCI_BAR_MEM_TYPE_MASK == PCI_BAR_MEM_TYPE_64 {
+            self.config_writel(offset + 4, (addr >> 32) as u32);
+        }
+    }
+
+    pub fn enable_memory(&self) {
+        let command = self.config_readw(PCI_COMMAND);
+        self.config_writew(
+            PCI_COMMAND,
+            command | PCI_COMMAND_MEMORY | PCI_COMMAND_MASTER,
+        );
+    }
+
+    /// Enable MSI-X, and set the message of `vector`. The MSI-X table must be in BAR
+    /// `bar`, which is mapped at `bar_addr`.
+    pub fn enable_msix(
+        &self,
+        machine: &TestMachine,
+        bar_addr: u64,
+        vector: u16,
+        msi_addr: u64,
+        msi_data: u32,
+    ) {
+        let cap = *self
+            .find_capabilities(PCI_CAP_ID_MSIX)
+            .first()
+            .expect("No MSI-X capability");
+        let control = self.config_readw(cap + MSIX_CAP_CONTROL);
+        self.config_writew(cap + MSIX_CAP_CONTROL, control | MSIX_CAP_ENABLE);
+
+        let table = self.config_readl(cap + MSIX_CAP_TABLE) & !MSIX_TABLE_BIR_MASK;
+        let entry = bar_addr + u64::from(table) + u64::from(vector) * MSIX_TABLE_ENTRY_SIZE;
+        machine.writel(entry, msi_addr as u32);
+        machine.writel(entry + 4, (msi_addr >> 32) as u32);
+        machine.writel(entry + 8, msi_data);
+        // Unmask the vector.
+        machine.writel(entry + 12, 0);
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::machine::TestMachine;
+
+pub const VIRTIO_CONFIG_S_ACKNOWLEDGE: u32 = 0x01;
+pub const VIRTIO_CONFIG_S_DRIVER: u32 = 0x02;
+pub const VIRTIO_CONFIG_S_DRIVER_OK: u32 = 0x04;
+pub const VIRTIO_CONFIG_S_FEATURES_OK: u32 = 0x08;
+pub const VIRTIO_CONFIG_S_NEEDS_RESET: u32 = 0x40;
+
+pub const VIRTQ_DESC_F_NEXT: u16 = 0x01;
+pub const VIRTQ_DESC_F_WRITE: u16 = 0x02;
+
+const VRING_DESC_SIZE: u64 = 16;
+const VRING_USED_ELEM_SIZE: u64 = 8;
+const VRING_ALIGN: u64 = 4096;
+
+/// Split virtqueue set up in guest memory by the test driver.
+pub struct TestVirtQueue {
+    pub size: u16,
+    pub desc: u64,
+    pub avail: u64,
+    pub used: u64,
+    free_head: u16,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl TestVirtQueue {
+    pub fn new(machine: &mut TestMachine, size: u16) -> Self {
+        let desc = machine.alloc(u64::from(size) * VRING_DESC_SIZE, VRING_ALIGN);
+        // flags, idx, ring and used_event.
+        let avail = machine.alloc(6 + 2 * u64::from(size), VRING_ALIGN);
+        // flags, idx, ring and avail_event.
+        let used = machine.alloc(6 + VRING_USED_ELEM_SIZE * u64::from(size), VRING_ALIGN);
+        let queue = TestVirtQueue {
+            size,
+            desc,
+            avail,
+            used,
+            free_head: 0,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        machine.memwrite(avail, &vec![0_u8; 6 + 2 * size as usize]);
+        machine.memwrite(used, &vec![0_u8; 6 + 8 * size as usize]);
+        queue
+    }
+
+    /// Add a descriptor chain of `(addr, len, device writable)` buffers, and make it
+    /// available to the device. Return the head index of the chain.
+    pub fn add_chain(&mut self, machine: &TestMachine, bufs: &[(u64, u32, bool)]) -> u16 {
+        let head = self.free_head;
+        for (i, (addr, len, write)) in bufs.iter().enumerate() {
+            let index = (self.free_head + i as u16) % self.size;
+            let mut flags = if *write { VIRTQ_DESC_F_WRITE } else { 0 };
+            if i + 1 < bufs.len() {
+                flags |= VIRTQ_DESC_F_NEXT;
+            }
+            let next = (index + 1) % self.size;
+
+            let mut desc = Vec::with_capacity(VRING_DESC_SIZE as usize);
+            desc.extend_from_slice(&addr.to_le_bytes());
+            desc.extend_from_slice(&len.to_le_bytes());
+            desc.extend_from_slice(&flags.to_le_bytes());
+            desc.extend_from_slice(&next.to_le_bytes());
+            machine.memwrite(self.desc + u64::from(index) * VRING_DESC_SIZE, &desc);
+        }
+        self.free_head = (self.free_head + bufs.len() as u16) % self.size;
+
+        let slot = u64::from(self.avail_idx % self.size);
+        machine.memwrite(self.avail + 4 + 2 * slot, &head.to_le_bytes());
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        machine.memwrite(self.avail + 2, &self.avail_idx.to_le_bytes());
+        head
+    }
+
+    /// Get the next `(head index, written length)` used by the device.
+    pub fn pop_used(&mut self, machine: &TestMachine) -> Option<(u16, u32)> {
+        if machine.readw(self.used + 2) == self.last_used_idx {
+            return None;
+        }
+        let elem = self.used + 4 + VRING_USED_ELEM_SIZE * u64::from(self.last_used_idx % self.size);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        Some((machine.readl(elem) as u16, machine.readl(elem + 4)))
+    }
+}
+
+const MMIO_MAGIC_VALUE_REG: u64 = 0x00;
+const MMIO_VERSION_REG: u64 = 0x04;
+const MMIO_DEVICE_ID_REG: u64 = 0x08;
+const MMIO_DEVICE_FEATURES_REG: u64 = 0x10;
+const MMIO_DEVICE_FEATURES_SEL_REG: u64 = 0x14;
+const MMIO_DRIVER_FEATURES_REG: u64 = 0x20;
+const MMIO_DRIVER_FEATURES_SEL_REG: u64 = 0x24;
+const MMIO_QUEUE_SEL_REG: u64 = 0x30;
+const MMIO_QUEUE_NUM_MAX_REG: u64 = 0x34;
+const MMIO_QUEUE_NUM_REG: u64 = 0x38;
+const MMIO_QUEUE_READY_REG: u64 = 0x44;
+const MMIO_QUEUE_NOTIFY_REG: u64 = 0x50;
+const MMIO_INTERRUPT_STATUS_REG: u64 = 0x60;
+const MMIO_INTERRUPT_ACK_REG: u64 = 0x64;
+const MMIO_STATUS_REG: u64 = 0x70;
+const MMIO_QUEUE_DESC_LOW_REG: u64 = 0x80;
+const MMIO_QUEUE_DESC_HIGH_REG: u64 = 0x84;
+const MMIO_QUEUE_AVAIL_LOW_REG: u64 = 0x90;
+const MMIO_QUEUE_AVAIL_HIGH_REG: u64 = 0x94;
+const MMIO_QUEUE_USED_LOW_REG: u64 = 0xa0;
+const MMIO_QUEUE_USED_HIGH_REG: u64 = 0xa4;
+
+pub const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
+
+/// Test driver of the virtio-mmio device at `base`.
+pub struct TestVirtioMmioDev {
+    pub base: u64,
+    pub irq: i32,
+}
+
+impl TestVirtioMmioDev {
+    pub fn new(base: u64, irq: i32) -> Self {
+        TestVirtioMmioDev { base, irq }
+    }
+
+    pub fn magic_value(&self, machine: &TestMachine) -> u32 {
+        machine.readl(self.base + MMIO_MAGIC_VALUE_REG)
+    }
+
+    pub fn version(&self, machine: &TestMachine) -> u32 {
+        machine.readl(self.base + MMIO_VERSION_REG)
+    }
+
+    pub fn device_id(&self, machine: &TestMachine) -> u32 {
+        machine.readl(self.base + MMIO_DEVICE_ID_REG)
+    }
+
+    pub fn status(&self, machine: &TestMachine) -> u32 {
+        machine.readl(self.base + MMIO_STATUS_REG)
+    }
+
+    pub fn set_status(&self, machine: &TestMachine, status: u32) {
+        machine.writel(self.base + MMIO_STATUS_REG, status);
+    }
+
+    pub fn device_features(&self, machine: &TestMachine) -> u64 {
+        machine.writel(self.base + MMIO_DEVICE_FEATURES_SEL_REG, 0);
+        let low = machine.readl(self.base + MMIO_DEVICE_FEATURES_REG);
+        machine.writel(self.base + MMIO_DEVICE_FEATURES_SEL_REG, 1);
+        let high = machine.readl(self.base + MMIO_DEVICE_FEATURES_REG);
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    pub fn set_driver_features(&self, machine: &TestMachine, features: u64) {
+        machine.writel(self.base + MMIO_DRIVER_FEATURES_SEL_REG, 0);
+        machine.writel(self.base + MMIO_DRIVER_FEATURES_REG, features as u32);
+        machine.writel(self.base + MMIO_DRIVER_FEATURES_SEL_REG, 1);
+        machine.writel(
+            self.base + MMIO_DRIVER_FEATURES_REG,
+            (features >> 32) as u32,
+        );
+    }
+
+    /// Reset the device, and negotiate the `features` with it.
+    pub fn init(&self, machine: &TestMachine, features: u64) {
+        self.set_status(machine, 0);
+        self.set_status(machine, VIRTIO_CONFIG_S_ACKNOWLEDGE);
+        self.set_status(
+            machine,
+            VIRTIO_CONFIG_S_ACKNOWLEDGE | VIRTIO_CONFIG_S_DRIVER,
+        );
+        self.set_driver_features(machine, features);
+        self.set_status(
+            machine,
+            VIRTIO_CONFIG_S_ACKNOWLEDGE | VIRTIO_CONFIG_S_DRIVER | VIRTIO_CONFIG_S_FEATURES_OK,
+        );
+        assert_ne!(self.status(machine) & VIRTIO_CONFIG_S_FEATURES_OK, 0);
+    }
+
+    /// Set up the queue `index` with at most `size` entries.
+    pub fn setup_queue(&self, machine: &mut TestMachine, index: u32, size: u16) -> TestVirtQueue {
+        machine.writel(self.base + MMIO_QUEUE_SEL_REG, index);
+        let max_size = machine.readl(self.base + MMIO_QUEUE_NUM_MAX_REG) as u16;
+        let queue = TestVirtQueue::new(machine, size.min(max_size));
+        machine.writel(self.base + MMIO_QUEUE_NUM_REG, u32::from(queue.size));
+        for (low_reg, high_reg, addr) in [
+            (
+                MMIO_QUEUE_DESC_LOW_REG,
+                MMIO_QUEUE_DESC_HIGH_REG,
+                queue.desc,
+            ),
+            (
+                MMIO_QUEUE_AVAIL_LOW_REG,
+                MMIO_QUEUE_AVAIL_HIGH_REG,
+                queue.avail,
+            ),
+            (
+                MMIO_QUEUE_USED_LOW_REG,
+                MMIO_QUEUE_USED_HIGH_REG,
+                queue.used,
+            ),
+        ] {
+            machine.writel(self.base + low_reg, addr as u32);
+            machine.writel(self.base + high_reg, (addr >> 32) as u32);
+        }
+        machine.writel(self.base + MMIO_QUEUE_READY_REG, 1);
+        queue
+    }
+
+    pub fn driver_ok(&self, machine: &TestMachine) {
+        let status = self.status(machine);
+        self.set_status(machine, status | VIRTIO_CONFIG_S_DRIVER_OK);
+    }
+
+    pub fn notify(&self, machine: &TestMachine, index: u32) {
+        machine.writel(self.base + MMIO_QUEUE_NOTIFY_REG, index);
+    }
+
+    /// Wait for the interrupt of the device, and acknowledge it. Return the interrupt status.
+    pub fn wait_interrupt(&self, machine: &TestMachine) -> u32 {
+        if !machine.wait_irq(self.irq) {
+            return 0;
+        }
+        let status = machine.readl(self.base + MMIO_INTERRUPT_STATUS_REG);
+        machine.writel(self.base + MMIO_INTERRUPT_ACK_REG, status);
+        status
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod common;
+
+use std::sync::Arc;
+
+use address_space::{GuestAddress, HostMemMapping, Region};
+use common::machine::{TestMachine, RAM_SIZE};
+use util::unix::host_page_size;
+
+#[test]
+fn test_ram_mem_slot() {
+    let machine = TestMachine::new();
+    let slots = machine.hypervisor.mem_slots();
+    assert_eq!(slots.len(), 1);
+    let slot = slots.values().next().unwrap();
+    assert_eq!(slot.guest_phys_addr, 0);
+    assert_eq!(slot.memory_size, RAM_SIZE);
+    assert!(!slot.read_only);
+
+    // Guest memory written through the address space is seen from the host mapping.
+    machine.memwrite(0x1000, &[1, 2, 3, 4]);
+    // SAFETY: the address is in the ram which is mapped until the machine is dropped.
+    let data =
+        unsafe { std::slice::from_raw_parts((slot.userspace_addr + 0x1000) as *const u8, 4) };
+    assert_eq!(data, &[1, 2, 3, 4]);
+}
+
+#[test]
+fn test_hotplug_mem_slot() {
+    let machine = TestMachine::new();
+    let size = 2 * host_page_size();
+    let addr = RAM_SIZE + size;
+    let mapping = Arc::new(
+        HostMemMapping::new(GuestAddress(addr), None, size, None, false, false, false).unwrap(),
+    );
+    let region = Region::init_ram_region(mapping.clone(), "hotplug");
+    machine
+        .sys_mem
+        .root()
+        .add_subregion(region.clone(), addr)
+        .unwrap();
+    let slots = machine.hypervisor.mem_slots();
+    assert_eq!(slots.len(), 2);
+    assert!(slots.values().any(|s| s.guest_phys_addr == addr
+        && s.memory_size == size
+        && s.userspace_addr == mapping.host_address()));
+
+    machine.sys_mem.root().delete_subregion(&region).unwrap();
+    assert_eq!(machine.hypervisor.mem_slots().len(), 1);
+}
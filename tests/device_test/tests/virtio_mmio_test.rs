@@ -0,0 +1,82 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::machine::{TestMachine, SYSBUS_MMIO_BASE};
+use common::virtio::{TestVirtioMmioDev, MMIO_MAGIC_VALUE};
+use devices::sysbus::SysBusDevOps;
+use hypervisor::{DataMatch, IoEventAddress};
+use machine_manager::config::RngConfig;
+use virtio::{Rng, VirtioMmioDevice, VIRTIO_F_VERSION_1, VIRTIO_MMIO_INT_VRING, VIRTIO_TYPE_RNG};
+
+const MMIO_REGION_SIZE: u64 = 0x200;
+const QUEUE_NOTIFY_REG: u64 = 0x50;
+const RNG_DATA_BYTES: u32 = 64;
+
+fn create_rng(machine: &mut TestMachine) -> TestVirtioMmioDev {
+    let rng = Rng::new(RngConfig {
+        id: "rng0".to_string(),
+        random_file: "/dev/urandom".to_string(),
+        bytes_per_sec: None,
+    });
+    let dev = VirtioMmioDevice::new(&machine.sys_mem, Arc::new(Mutex::new(rng)))
+        .realize(
+            &mut machine.sysbus,
+            SYSBUS_MMIO_BASE,
+            MMIO_REGION_SIZE,
+            #[cfg(target_arch = "x86_64")]
+            &machine.boot_source,
+        )
+        .unwrap();
+    let irq = dev.lock().unwrap().sysbusdev_base().res.irq;
+    TestVirtioMmioDev::new(SYSBUS_MMIO_BASE, irq)
+}
+
+#[test]
+fn test_virtio_mmio_registers() {
+    let mut machine = TestMachine::new();
+    let rng = create_rng(&mut machine);
+
+    assert_eq!(rng.magic_value(&machine), MMIO_MAGIC_VALUE);
+    assert_eq!(rng.version(&machine), 2);
+    assert_eq!(rng.device_id(&machine), VIRTIO_TYPE_RNG);
+    assert_ne!(rng.device_features(&machine) & (1 << VIRTIO_F_VERSION_1), 0);
+
+    // The queue notification is passed through the ioeventfd, rather than trapped.
+    assert!(machine.hypervisor.ioevents().contains(&(
+        IoEventAddress::Mmio(SYSBUS_MMIO_BASE + QUEUE_NOTIFY_REG),
+        Some(DataMatch::U32(0))
+    )));
+}
+
+#[test]
+fn test_virtio_mmio_rng_request() {
+    let mut machine = TestMachine::new();
+    let rng = create_rng(&mut machine);
+
+    rng.init(&machine, 1 << VIRTIO_F_VERSION_1);
+    let mut queue = rng.setup_queue(&mut machine, 0, 16);
+    rng.driver_ok(&machine);
+
+    let buf = machine.alloc(u64::from(RNG_DATA_BYTES), 8);
+    let head = queue.add_chain(&machine, &[(buf, RNG_DATA_BYTES, true)]);
+    rng.notify(&machine, 0);
+
+    assert_ne!(rng.wait_interrupt(&machine) & VIRTIO_MMIO_INT_VRING, 0);
+    assert_eq!(queue.pop_used(&machine), Some((head, RNG_DATA_BYTES)));
+    assert!(queue.pop_used(&machine).is_none());
+    let data = machine.memread(buf, RNG_DATA_BYTES as usize);
+    assert!(data.iter().any(|b| *b != 0));
+}
@@ -0,0 +1,190 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::machine::{TestMachine, PCI_MMIO_BASE};
+use common::pci::{TestPciDev, PCI_CAP_ID_VNDR, PCI_DEVICE_ID, PCI_VENDOR_ID};
+use common::virtio::{
+    TestVirtQueue, VIRTIO_CONFIG_S_ACKNOWLEDGE, VIRTIO_CONFIG_S_DRIVER, VIRTIO_CONFIG_S_DRIVER_OK,
+    VIRTIO_CONFIG_S_FEATURES_OK,
+};
+use devices::pci::PciDevOps;
+use hypervisor::MsiMessage;
+use machine_manager::config::RngConfig;
+use virtio::{Rng, VirtioPciDevice, VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG};
+
+const RNG_DEVFN: u8 = 0x8;
+const VIRTIO_PCI_VENDOR_ID: u16 = 0x1af4;
+const VIRTIO_PCI_DEVICE_ID_BASE: u16 = 0x1040;
+const VIRTIO_PCI_MSIX_BAR: usize = 1;
+const BAR_SPACING: u64 = 0x100_0000;
+
+// Offsets in the virtio pci capability.
+const VIRTIO_PCI_CAP_CFG_TYPE: usize = 3;
+const VIRTIO_PCI_CAP_BAR: usize = 4;
+const VIRTIO_PCI_CAP_OFFSET: usize = 8;
+const VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER: usize = 16;
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+
+// Offsets in the common configuration.
+const COMMON_GFSELECT: u64 = 0x8;
+const COMMON_GF: u64 = 0xc;
+const COMMON_NUMQ: u64 = 0x12;
+const COMMON_STATUS: u64 = 0x14;
+const COMMON_Q_SELECT: u64 = 0x16;
+const COMMON_Q_SIZE: u64 = 0x18;
+const COMMON_Q_MSIX: u64 = 0x1a;
+const COMMON_Q_ENABLE: u64 = 0x1c;
+const COMMON_Q_NOFF: u64 = 0x1e;
+const COMMON_Q_DESCLO: u64 = 0x20;
+const COMMON_Q_AVAILLO: u64 = 0x28;
+const COMMON_Q_USEDLO: u64 = 0x30;
+
+const MSI_ADDR: u64 = 0xfee0_0000;
+const MSI_DATA: u32 = 0x41;
+const RNG_DATA_BYTES: u32 = 64;
+
+fn bar_addr(bar: usize) -> u64 {
+    PCI_MMIO_BASE + bar as u64 * BAR_SPACING
+}
+
+fn create_rng(machine: &TestMachine) -> TestPciDev {
+    let rng = Rng::new(RngConfig {
+        id: "rng0".to_string(),
+        random_file: "/dev/urandom".to_string(),
+        bytes_per_sec: None,
+    });
+    VirtioPciDevice::new(
+        "rng0".to_string(),
+        RNG_DEVFN,
+        machine.sys_mem.clone(),
+        Arc::new(Mutex::new(rng)),
+        Arc::downgrade(&machine.pci_bus),
+        false,
+    )
+    .realize()
+    .unwrap();
+    TestPciDev::new(machine, RNG_DEVFN)
+}
+
+/// Map the BARs and enable the memory space of the device.
+fn map_bars(dev: &TestPciDev) {
+    for cap in dev.find_capabilities(PCI_CAP_ID_VNDR) {
+        let bar = dev.config_readb(cap + VIRTIO_PCI_CAP_BAR) as usize;
+        dev.set_bar(bar, bar_addr(bar));
+    }
+    dev.set_bar(VIRTIO_PCI_MSIX_BAR, bar_addr(VIRTIO_PCI_MSIX_BAR));
+    dev.enable_memory();
+}
+
+/// Get the guest address of the virtio structure, and the notify offset multiplier.
+fn find_virtio_cap(dev: &TestPciDev, cfg_type: u8) -> (u64, u32) {
+    for cap in dev.find_capabilities(PCI_CAP_ID_VNDR) {
+        if dev.config_readb(cap + VIRTIO_PCI_CAP_CFG_TYPE) != cfg_type {
+            continue;
+        }
+        let bar = dev.config_readb(cap + VIRTIO_PCI_CAP_BAR) as usize;
+        let offset = dev.config_readl(cap + VIRTIO_PCI_CAP_OFFSET);
+        let multiplier = if cfg_type == VIRTIO_PCI_CAP_NOTIFY_CFG {
+            dev.config_readl(cap + VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER)
+        } else {
+            0
+        };
+        return (bar_addr(bar) + u64::from(offset), multiplier);
+    }
+    panic!("No virtio pci capability of type {}", cfg_type);
+}
+
+#[test]
+fn test_virtio_pci_config_space() {
+    let machine = TestMachine::new();
+    let rng = create_rng(&machine);
+
+    assert_eq!(rng.config_readw(PCI_VENDOR_ID), VIRTIO_PCI_VENDOR_ID);
+    assert_eq!(
+        rng.config_readw(PCI_DEVICE_ID),
+        VIRTIO_PCI_DEVICE_ID_BASE + VIRTIO_TYPE_RNG as u16
+    );
+    map_bars(&rng);
+    let (common, _) = find_virtio_cap(&rng, VIRTIO_PCI_CAP_COMMON_CFG);
+    assert_eq!(machine.readw(common + COMMON_NUMQ), 1);
+}
+
+#[test]
+fn test_virtio_pci_rng_request_with_msix() {
+    let mut machine = TestMachine::new();
+    let rng = create_rng(&machine);
+    map_bars(&rng);
+    let (common, _) = find_virtio_cap(&rng, VIRTIO_PCI_CAP_COMMON_CFG);
+    let (notify, multiplier) = find_virtio_cap(&rng, VIRTIO_PCI_CAP_NOTIFY_CFG);
+    rng.enable_msix(
+        &machine,
+        bar_addr(VIRTIO_PCI_MSIX_BAR),
+        0,
+        MSI_ADDR,
+        MSI_DATA,
+    );
+
+    machine.writeb(common + COMMON_STATUS, 0);
+    machine.writeb(common + COMMON_STATUS, VIRTIO_CONFIG_S_ACKNOWLEDGE as u8);
+    machine.writeb(
+        common + COMMON_STATUS,
+        (VIRTIO_CONFIG_S_ACKNOWLEDGE | VIRTIO_CONFIG_S_DRIVER) as u8,
+    );
+    machine.writel(common + COMMON_GFSELECT, 1);
+    machine.writel(common + COMMON_GF, 1 << (VIRTIO_F_VERSION_1 - 32));
+    machine.writeb(
+        common + COMMON_STATUS,
+        (VIRTIO_CONFIG_S_ACKNOWLEDGE | VIRTIO_CONFIG_S_DRIVER | VIRTIO_CONFIG_S_FEATURES_OK) as u8,
+    );
+
+    machine.writew(common + COMMON_Q_SELECT, 0);
+    let size = machine.readw(common + COMMON_Q_SIZE).min(16);
+    machine.writew(common + COMMON_Q_SIZE, size);
+    let mut queue = TestVirtQueue::new(&mut machine, size);
+    for (reg, addr) in [
+        (COMMON_Q_DESCLO, queue.desc),
+        (COMMON_Q_AVAILLO, queue.avail),
+        (COMMON_Q_USEDLO, queue.used),
+    ] {
+        machine.writel(common + reg, addr as u32);
+        machine.writel(common + reg + 4, (addr >> 32) as u32);
+    }
+    machine.writew(common + COMMON_Q_MSIX, 0);
+    machine.writew(common + COMMON_Q_ENABLE, 1);
+    let notify_off = machine.readw(common + COMMON_Q_NOFF);
+    machine.writeb(
+        common + COMMON_STATUS,
+        (VIRTIO_CONFIG_S_ACKNOWLEDGE
+            | VIRTIO_CONFIG_S_DRIVER
+            | VIRTIO_CONFIG_S_FEATURES_OK
+            | VIRTIO_CONFIG_S_DRIVER_OK) as u8,
+    );
+
+    let buf = machine.alloc(u64::from(RNG_DATA_BYTES), 8);
+    let head = queue.add_chain(&machine, &[(buf, RNG_DATA_BYTES, true)]);
+    machine.writew(notify + u64::from(notify_off) * u64::from(multiplier), 0);
+
+    let msis = machine.wait_msi();
+    assert!(msis.iter().all(|msi| *msi
+        == MsiMessage {
+            address: MSI_ADDR,
+            data: MSI_DATA,
+            dev_id: msi.dev_id,
+        }));
+    assert!(!msis.is_empty());
+    assert_eq!(queue.pop_used(&machine), Some((head, RNG_DATA_BYTES)));
+}
@@ -923,7 +923,6 @@ fn scsi_hd_basic_test() {
 
     // Test 6.4 EVPD = 1, byte_code = 0x83: Inquiry scsi device identification.
     // Test 6.4 Result: Check if scsi command INQUIRY was handled successfully.
-    // Note: Stratovirt does not reply anything usefully for scsi device identification now.
     let mut inquiry_cdb = [0_u8; TEST_VIRTIO_SCSI_CDB_SIZE];
     inquiry_cdb[0] = INQUIRY;
     inquiry_cdb[1] = 0x1;
@@ -942,6 +941,8 @@ fn scsi_hd_basic_test() {
     };
     let data_in = vst.scsi_cdb_test(cdb_test_args);
     assert!(data_in.as_ref().unwrap()[1] == 0x83);
+    // The serial number is used as the vendor specific identifier.
+    assert!(&data_in.unwrap()[8..8 + DEFAULT_SCSI_SERIAL.len()] == DEFAULT_SCSI_SERIAL.as_bytes());
 
     // Test 6.5 EVPD = 1, byte_code = 0xb0: Inquiry scsi block limits.
     // Test 6.5 Result: Check if scsi command INQUIRY was handled successfully.
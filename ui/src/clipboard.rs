@@ -0,0 +1,118 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! The clipboard shared by the display clients and the guest agent. The text
+//! copied by one peer is sent to all the other peers.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use log::debug;
+use once_cell::sync::Lazy;
+
+/// Max size of the clipboard text.
+pub const CLIPBOARD_MAX_SIZE: usize = 1024 * 1024;
+
+static CLIPBOARD: Lazy<Mutex<Clipboard>> = Lazy::new(|| Mutex::new(Clipboard::default()));
+
+pub trait ClipboardOpts: Send {
+    /// The clipboard is updated by another peer, `text` is encoded in UTF-8.
+    fn clipboard_update(&mut self, text: &[u8]);
+}
+
+#[derive(Default)]
+struct Clipboard {
+    peers: HashMap<String, Arc<Mutex<dyn ClipboardOpts>>>,
+    /// The current clipboard text in UTF-8.
+    text: Vec<u8>,
+}
+
+pub fn register_clipboard(peer_name: &str, peer: Arc<Mutex<dyn ClipboardOpts>>) {
+    CLIPBOARD
+        .lock()
+        .unwrap()
+        .peers
+        .insert(peer_name.to_string(), peer);
+}
+
+pub fn unregister_clipboard(peer_name: &str) {
+    CLIPBOARD.lock().unwrap().peers.remove(peer_name);
+}
+
+/// Get the current clipboard text.
+pub fn clipboard_text() -> Vec<u8> {
+    CLIPBOARD.lock().unwrap().text.clone()
+}
+
+/// Set the clipboard text copied by `owner`, and notify the other peers.
+pub fn clipboard_set(owner: &str, text: &[u8]) {
+    let text = &text[..std::cmp::min(text.len(), CLIPBOARD_MAX_SIZE)];
+    let mut locked_clipboard = CLIPBOARD.lock().unwrap();
+    if locked_clipboard.text == text {
+        return;
+    }
+    debug!("Clipboard is set by {}, size {}", owner, text.len());
+    locked_clipboard.text = text.to_vec();
+    let peers: Vec<Arc<Mutex<dyn ClipboardOpts>>> = locked_clipboard
+        .peers
+        .iter()
+        .filter(|(name, _)| name.as_str() != owner)
+        .map(|(_, peer)| peer.clone())
+        .collect();
+    drop(locked_clipboard);
+
+    for peer in peers {
+        peer.lock().unwrap().clipboard_update(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestPeer {
+        text: Vec<u8>,
+        updated: u32,
+    }
+
+    impl ClipboardOpts for TestPeer {
+        fn clipboard_update(&mut self, text: &[u8]) {
+            self.text = text.to_vec();
+            self.updated += 1;
+        }
+    }
+
+    #[test]
+    fn test_clipboard_set() {
+        let peer1 = Arc::new(Mutex::new(TestPeer::default()));
+        let peer2 = Arc::new(Mutex::new(TestPeer::default()));
+        register_clipboard("peer1", peer1.clone());
+        register_clipboard("peer2", peer2.clone());
+
+        clipboard_set("peer1", b"hello");
+        assert_eq!(clipboard_text(), b"hello");
+        assert_eq!(peer1.lock().unwrap().updated, 0);
+        assert_eq!(peer2.lock().unwrap().text, b"hello");
+
+        // The same text is not sent again.
+        clipboard_set("peer1", b"hello");
+        assert_eq!(peer2.lock().unwrap().updated, 1);
+
+        unregister_clipboard("peer2");
+        clipboard_set("peer1", b"world");
+        assert_eq!(peer2.lock().unwrap().text, b"hello");
+        unregister_clipboard("peer1");
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Frame encoders of the display channel. The input of the encoders is the
+//! pixels of a rectangle in x8r8g8b8 format, 4 bytes per pixel without padding.
+
+use std::{
+    ffi::{c_void, CString},
+    io::Write,
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::{write::ZlibEncoder as FlateEncoder, Compression};
+use log::info;
+
+/// Encoder id sent to the client in the update messages.
+pub const ENCODER_RAW: u32 = 0;
+pub const ENCODER_ZLIB: u32 = 1;
+pub const ENCODER_H264: u32 = 2;
+
+pub trait FrameEncoder: Send {
+    /// Name of the encoder.
+    fn name(&self) -> &'static str;
+    /// Id of the encoder in the protocol.
+    fn id(&self) -> u32;
+    /// Whether the encoder always encodes the whole surface instead of the dirty area.
+    fn full_frame(&self) -> bool {
+        false
+    }
+    /// Encode the x8r8g8b8 pixels of a `width` x `height` rectangle. An empty result
+    /// means there is nothing to send for this frame.
+    fn encode(&mut self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>>;
+    /// Make the next frame decodable by itself.
+    fn force_key_frame(&mut self) {}
+}
+
+/// Create an encoder by name, one of `raw`, `zlib` and `h264`.
+pub fn create_encoder(name: &str) -> Result<Box<dyn FrameEncoder>> {
+    match name {
+        "raw" => Ok(Box::new(RawEncoder {})),
+        "zlib" => Ok(Box::new(ZlibEncoder {})),
+        "h264" => Ok(Box::new(H264Encoder::new()?)),
+        _ => bail!("Unsupported display channel encoder {}", name),
+    }
+}
+
+fn check_frame(data: &[u8], width: u32, height: u32) -> Result<()> {
+    if data.len() != width as usize * height as usize * 4 {
+        bail!("Invalid frame size {} for {}x{}", data.len(), width, height);
+    }
+    Ok(())
+}
+
+/// Send the pixels as they are.
+struct RawEncoder {}
+
+impl FrameEncoder for RawEncoder {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn id(&self) -> u32 {
+        ENCODER_RAW
+    }
+
+    fn encode(&mut self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        check_frame(data, width, height)?;
+        Ok(data.to_vec())
+    }
+}
+
+/// Compress each rectangle into an independent zlib stream.
+struct ZlibEncoder {}
+
+impl FrameEncoder for ZlibEncoder {
+    fn name(&self) -> &'static str {
+        "zlib"
+    }
+
+    fn id(&self) -> u32 {
+        ENCODER_ZLIB
+    }
+
+    fn encode(&mut self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        check_frame(data, width, height)?;
+        let mut encoder = FlateEncoder::new(Vec::new(), Compression::fast());
+        encoder
+            .write_all(data)
+            .with_context(|| "Failed to compress frame")?;
+        encoder.finish().with_context(|| "Failed to compress frame")
+    }
+}
+
+/// Convert x8r8g8b8 pixels to I420 with BT.601 limited range, the width and
+/// height must be even.
+pub fn xrgb_to_i420(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let y_size = width * height;
+    let uv_size = y_size / 4;
+    let mut out = vec![0_u8; y_size + uv_size * 2];
+    let (y_plane, uv_plane) = out.split_at_mut(y_size);
+    let (u_plane, v_plane) = uv_plane.split_at_mut(uv_size);
+
+    let rgb = |idx: usize| -> (i32, i32, i32) {
+        // Little endian x8r8g8b8 is stored as B, G, R, X.
+        (
+            data[idx * 4 + 2] as i32,
+            data[idx * 4 + 1] as i32,
+            data[idx * 4] as i32,
+        )
+    };
+
+    for row in 0..height {
+        for col in 0..width {
+            let (r, g, b) = rgb(row * width + col);
+            y_plane[row * width + col] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        }
+    }
+    for row in 0..height / 2 {
+        for col in 0..width / 2 {
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let (pr, pg, pb) = rgb((row * 2 + dy) * width + col * 2 + dx);
+                r += pr;
+                g += pg;
+                b += pb;
+            }
+            let (r, g, b) = (r / 4, g / 4, b / 4);
+            let idx = row * (width / 2) + col;
+            u_plane[idx] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v_plane[idx] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+    }
+    out
+}
+
+const OPENH264_LIBS: [&str; 4] = [
+    "libopenh264.so",
+    "libopenh264.so.7",
+    "libopenh264.so.6",
+    "libopenh264.so.5",
+];
+/// SCREEN_CONTENT_REAL_TIME in EUsageType.
+const H264_USAGE_SCREEN: c_int = 1;
+/// RC_BITRATE_MODE in RC_MODES.
+const H264_RC_BITRATE: c_int = 1;
+/// videoFormatI420 in EVideoFormatType.
+const H264_FORMAT_I420: c_int = 23;
+/// videoFrameTypeSkip in EVideoFrameType.
+const H264_FRAME_SKIP: c_int = 4;
+const H264_MAX_LAYER_NUM: usize = 128;
+const H264_FRAME_RATE: f32 = 30.0;
+
+#[repr(C)]
+struct SEncParamBase {
+    usage_type: c_int,
+    pic_width: c_int,
+    pic_height: c_int,
+    target_bitrate: c_int,
+    rc_mode: c_int,
+    max_frame_rate: f32,
+}
+
+#[repr(C)]
+struct SSourcePicture {
+    color_format: c_int,
+    stride: [c_int; 4],
+    data: [*mut u8; 4],
+    pic_width: c_int,
+    pic_height: c_int,
+    time_stamp: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SLayerBSInfo {
+    temporal_id: u8,
+    spatial_id: u8,
+    quality_id: u8,
+    frame_type: c_int,
+    layer_type: u8,
+    sub_seq_id: c_int,
+    nal_count: c_int,
+    nal_length: *mut c_int,
+    bs_buf: *mut u8,
+}
+
+#[repr(C)]
+struct SFrameBSInfo {
+    layer_num: c_int,
+    layer_info: [SLayerBSInfo; H264_MAX_LAYER_NUM],
+    frame_type: c_int,
+    frame_size: c_int,
+    time_stamp: i64,
+}
+
+type SvcEncoder = *const SvcEncoderVtbl;
+
+/// The virtual table of ISVCEncoder in the C API of openh264.
+#[repr(C)]
+struct SvcEncoderVtbl {
+    initialize: unsafe extern "C" fn(*mut SvcEncoder, *const SEncParamBase) -> c_int,
+    initialize_ext: *const c_void,
+    get_default_params: *const c_void,
+    uninitialize: unsafe extern "C" fn(*mut SvcEncoder) -> c_int,
+    encode_frame:
+        unsafe extern "C" fn(*mut SvcEncoder, *const SSourcePicture, *mut SFrameBSInfo) -> c_int,
+    encode_parameter_sets: *const c_void,
+    force_intra_frame: unsafe extern "C" fn(*mut SvcEncoder, bool) -> c_int,
+    set_option: *const c_void,
+    get_option: *const c_void,
+}
+
+type CreateEncoderFn = unsafe extern "C" fn(*mut *mut SvcEncoder) -> c_int;
+type DestroyEncoderFn = unsafe extern "C" fn(*mut SvcEncoder);
+
+/// H.264 encoder backed by the openh264 library of the host, which is loaded at
+/// runtime so that it is not a build dependency.
+struct H264Encoder {
+    lib: *mut c_void,
+    encoder: *mut SvcEncoder,
+    destroy: DestroyEncoderFn,
+    width: u32,
+    height: u32,
+    timestamp: i64,
+    bs_info: Box<SFrameBSInfo>,
+}
+
+// SAFETY: the encoder is only used by the owner which is protected by a mutex.
+unsafe impl Send for H264Encoder {}
+
+fn load_symbol(lib: *mut c_void, name: &str) -> Result<*mut c_void> {
+    let symbol = CString::new(name)?;
+    // SAFETY: lib is a valid handle returned by dlopen.
+    let func = unsafe { libc::dlsym(lib, symbol.as_ptr() as *const c_char) };
+    if func.is_null() {
+        bail!("Failed to find {} in openh264 library", name);
+    }
+    Ok(func)
+}
+
+impl H264Encoder {
+    fn new() -> Result<Self> {
+        let mut lib = ptr::null_mut();
+        for name in OPENH264_LIBS {
+            let lib_name = CString::new(name)?;
+            // SAFETY: lib_name is a valid C string.
+            lib = unsafe { libc::dlopen(lib_name.as_ptr(), libc::RTLD_NOW) };
+            if !lib.is_null() {
+                info!("Display channel uses {} for h264 encoding", name);
+                break;
+            }
+        }
+        if lib.is_null() {
+            bail!("Failed to load openh264 library for h264 encoder");
+        }
+
+        let symbols = load_symbol(lib, "WelsCreateSVCEncoder")
+            .and_then(|create| Ok((create, load_symbol(lib, "WelsDestroySVCEncoder")?)));
+        let (create, destroy) = match symbols {
+            Ok(s) => s,
+            Err(e) => {
+                // SAFETY: lib is a valid handle returned by dlopen.
+                unsafe { libc::dlclose(lib) };
+                return Err(e);
+            }
+        };
+        // SAFETY: the symbols have the signatures declared in openh264 codec_api.h.
+        let (create, destroy) = unsafe {
+            (
+                std::mem::transmute::<*mut c_void, CreateEncoderFn>(create),
+                std::mem::transmute::<*mut c_void, DestroyEncoderFn>(destroy),
+            )
+        };
+        let mut encoder: *mut SvcEncoder = ptr::null_mut();
+        // SAFETY: encoder is a valid pointer to store the created encoder.
+        if unsafe { create(&mut encoder) } != 0 || encoder.is_null() {
+            // SAFETY: lib is a valid handle returned by dlopen.
+            unsafe { libc::dlclose(lib) };
+            bail!("Failed to create openh264 encoder");
+        }
+
+        Ok(H264Encoder {
+            lib,
+            encoder,
+            destroy,
+            width: 0,
+            height: 0,
+            timestamp: 0,
+            // SAFETY: SFrameBSInfo is plain data, all zero is a valid value.
+            bs_info: Box::new(unsafe { std::mem::zeroed() }),
+        })
+    }
+
+    fn vtbl(&self) -> &SvcEncoderVtbl {
+        // SAFETY: encoder is created by openh264 and lives until drop.
+        unsafe { &**self.encoder }
+    }
+
+    fn setup(&mut self, width: u32, height: u32) -> Result<()> {
+        if self.width == width && self.height == height {
+            return Ok(());
+        }
+        if self.width != 0 {
+            // SAFETY: the encoder has been initialized.
+            unsafe { (self.vtbl().uninitialize)(self.encoder) };
+            self.width = 0;
+            self.height = 0;
+        }
+        let param = SEncParamBase {
+            usage_type: H264_USAGE_SCREEN,
+            pic_width: width as c_int,
+            pic_height: height as c_int,
+            // About 4Mbps for 1080p.
+            target_bitrate: (width * height * 2) as c_int,
+            rc_mode: H264_RC_BITRATE,
+            max_frame_rate: H264_FRAME_RATE,
+        };
+        // SAFETY: param is a valid encoding parameter.
+        if unsafe { (self.vtbl().initialize)(self.encoder, &param) } != 0 {
+            bail!("Failed to initialize openh264 encoder {}x{}", width, height);
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+}
+
+impl FrameEncoder for H264Encoder {
+    fn name(&self) -> &'static str {
+        "h264"
+    }
+
+    fn id(&self) -> u32 {
+        ENCODER_H264
+    }
+
+    fn full_frame(&self) -> bool {
+        true
+    }
+
+    fn encode(&mut self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        check_frame(data, width, height)?;
+        // I420 needs even width and height, the last odd column and row are dropped.
+        let enc_width = width & !1;
+        let enc_height = height & !1;
+        if enc_width == 0 || enc_height == 0 {
+            return Ok(Vec::new());
+        }
+        let mut cropped = Vec::with_capacity((enc_width * enc_height * 4) as usize);
+        for row in data
+            .chunks_exact(width as usize * 4)
+            .take(enc_height as usize)
+        {
+            cropped.extend_from_slice(&row[..enc_width as usize * 4]);
+        }
+        self.setup(enc_width, enc_height)?;
+
+        let mut yuv = xrgb_to_i420(&cropped, enc_width as usize, enc_height as usize);
+        let y_size = (enc_width * enc_height) as usize;
+        let base = yuv.as_mut_ptr();
+        // SAFETY: the offsets are inside the I420 buffer.
+        let planes = unsafe { [base, base.add(y_size), base.add(y_size * 5 / 4)] };
+        let pic = SSourcePicture {
+            color_format: H264_FORMAT_I420,
+            stride: [
+                enc_width as c_int,
+                (enc_width / 2) as c_int,
+                (enc_width / 2) as c_int,
+                0,
+            ],
+            data: [planes[0], planes[1], planes[2], ptr::null_mut()],
+            pic_width: enc_width as c_int,
+            pic_height: enc_height as c_int,
+            time_stamp: self.timestamp,
+        };
+        self.timestamp += (1000.0 / H264_FRAME_RATE) as i64;
+
+        // SAFETY: pic points to the I420 buffer which lives during encoding.
+        let ret = unsafe { (self.vtbl().encode_frame)(self.encoder, &pic, self.bs_info.as_mut()) };
+        if ret != 0 {
+            bail!("Failed to encode h264 frame, error {}", ret);
+        }
+        let info = &self.bs_info;
+        let mut out = Vec::with_capacity(info.frame_size.max(0) as usize);
+        if info.frame_type == H264_FRAME_SKIP {
+            return Ok(out);
+        }
+        for layer in info.layer_info.iter().take(info.layer_num as usize) {
+            let mut size = 0;
+            for i in 0..layer.nal_count as usize {
+                // SAFETY: nal_length has nal_count entries.
+                size += unsafe { *layer.nal_length.add(i) } as usize;
+            }
+            // SAFETY: bs_buf holds the nals of the layer.
+            out.extend_from_slice(unsafe { std::slice::from_raw_parts(layer.bs_buf, size) });
+        }
+        Ok(out)
+    }
+
+    fn force_key_frame(&mut self) {
+        if self.width != 0 {
+            // SAFETY: the encoder has been initialized.
+            unsafe { (self.vtbl().force_intra_frame)(self.encoder, true) };
+        }
+    }
+}
+
+impl Drop for H264Encoder {
+    fn drop(&mut self) {
+        // SAFETY: the encoder and the library are valid until now.
+        unsafe {
+            if self.width != 0 {
+                (self.vtbl().uninitialize)(self.encoder);
+            }
+            (self.destroy)(self.encoder);
+            libc::dlclose(self.lib);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_raw_zlib_encoder() {
+        let data: Vec<u8> = (0..4 * 8 * 2).map(|i| (i % 7) as u8).collect();
+        let mut raw = create_encoder("raw").unwrap();
+        assert_eq!(raw.id(), ENCODER_RAW);
+        assert_eq!(raw.encode(&data, 8, 2).unwrap(), data);
+        assert!(raw.encode(&data, 8, 3).is_err());
+
+        let mut zlib = create_encoder("zlib").unwrap();
+        assert!(!zlib.full_frame());
+        let encoded = zlib.encode(&data, 8, 2).unwrap();
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(encoded.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        assert!(create_encoder("vp8").is_err());
+    }
+
+    #[test]
+    fn test_xrgb_to_i420() {
+        // White and black pixels in a 2x2 block.
+        let data = [
+            0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0,
+        ];
+        let yuv = xrgb_to_i420(&data, 2, 2);
+        assert_eq!(yuv.len(), 6);
+        assert_eq!(&yuv[..4], &[235, 16, 235, 16]);
+        assert_eq!(&yuv[4..], &[128, 128]);
+    }
+}
@@ -0,0 +1,713 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Display channel: a display transport independent of VNC. The framebuffer is
+//! sent to one client over a unix socket with a simple message protocol, and
+//! the frames are encoded by the selected encoder.
+//!
+//! Every message starts with a header of `type` and `size` of the payload, all
+//! the integers are u32 in little endian.
+//!
+//! Server messages:
+//! * HELLO: version, encoder id.
+//! * SURFACE: width, height of the surface, the pixels are in x8r8g8b8 format.
+//! * UPDATE: x, y, width, height, encoder id, encoded data.
+//! * CURSOR: width, height, hot_x, hot_y, a8r8g8b8 pixels.
+//!
+//! Client messages:
+//! * KEY: keycode, down.
+//! * POINTER: button mask, x, y in the coordinates of the surface.
+//! * REFRESH: request the whole surface.
+
+pub mod encoder;
+
+use std::{
+    cmp,
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    ptr,
+    rc::Rc,
+    sync::{Arc, Mutex, Weak},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use vmm_sys_util::epoll::EventSet;
+
+use crate::{
+    console::{
+        graphic_hardware_update, register_display, unregister_display, DisplayChangeListener,
+        DisplayChangeListenerOperations, DisplayMouse, DisplaySurface,
+        DISPLAY_UPDATE_INTERVAL_DEFAULT,
+    },
+    input::{key_event, point_event, ABS_MAX},
+    pixman::{
+        get_image_data, get_image_height, get_image_stride, get_image_width,
+        pixman_image_linebuf_create, pixman_image_linebuf_fill, ref_pixman_image,
+        unref_pixman_image,
+    },
+};
+use encoder::{create_encoder, FrameEncoder};
+use machine_manager::{
+    event_loop::EventLoop, qmp::qmp_schema::DisplayChannelInfo, temp_cleaner::TempCleaner,
+};
+use util::{
+    file::clear_file,
+    loop_context::{gen_delete_notifiers, EventNotifier, NotifierCallback, NotifierOperation},
+    pixman::{pixman_format_code_t, pixman_image_t},
+    unix::limit_permission,
+};
+
+const PROTOCOL_VERSION: u32 = 1;
+const MSG_HEADER_SIZE: usize = 8;
+
+const MSG_HELLO: u32 = 0;
+const MSG_SURFACE: u32 = 1;
+const MSG_UPDATE: u32 = 2;
+const MSG_CURSOR: u32 = 3;
+
+const MSG_KEY: u32 = 0;
+const MSG_POINTER: u32 = 1;
+const MSG_REFRESH: u32 = 2;
+/// Max payload size of the client messages.
+const MAX_CLIENT_MSG_SIZE: u32 = 64;
+/// The client is disconnected if the pending output exceeds this size.
+const MAX_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
+
+static DISPLAY_CHANNELS: Lazy<Mutex<HashMap<String, Arc<Mutex<DisplayChannel>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Rect {
+    fn union(&self, other: &Rect) -> Rect {
+        let x = cmp::min(self.x, other.x);
+        let y = cmp::min(self.y, other.y);
+        let right = cmp::max(self.x + self.w, other.x + other.w);
+        let bottom = cmp::max(self.y + self.h, other.y + other.h);
+        Rect {
+            x,
+            y,
+            w: right - x,
+            h: bottom - y,
+        }
+    }
+
+    /// Clip the rectangle to the surface of `width` x `height`.
+    fn clip(&self, width: i32, height: i32) -> Option<Rect> {
+        let x = cmp::max(self.x, 0);
+        let y = cmp::max(self.y, 0);
+        let right = cmp::min(self.x + self.w, width);
+        let bottom = cmp::min(self.y + self.h, height);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Rect {
+            x,
+            y,
+            w: right - x,
+            h: bottom - y,
+        })
+    }
+}
+
+fn append_msg(buf: &mut Vec<u8>, msg_type: u32, fields: &[u32], data: &[u8]) {
+    let size = fields.len() * 4 + data.len();
+    buf.extend_from_slice(&msg_type.to_le_bytes());
+    buf.extend_from_slice(&(size as u32).to_le_bytes());
+    for field in fields {
+        buf.extend_from_slice(&field.to_le_bytes());
+    }
+    buf.extend_from_slice(data);
+}
+
+struct ChannelClient {
+    stream: UnixStream,
+    /// Received bytes which are not a complete message yet.
+    inbuf: Vec<u8>,
+    /// Bytes which are not sent yet.
+    outbuf: Vec<u8>,
+}
+
+impl ChannelClient {
+    /// Write the pending output without blocking.
+    fn flush(&mut self) -> Result<()> {
+        while !self.outbuf.is_empty() {
+            match self.stream.write(&self.outbuf) {
+                Ok(0) => bail!("Display channel client is closed"),
+                Ok(n) => {
+                    self.outbuf.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).with_context(|| "Failed to write display channel"),
+            }
+        }
+        if self.outbuf.len() > MAX_OUTPUT_SIZE {
+            bail!("Display channel client is too slow");
+        }
+        Ok(())
+    }
+}
+
+pub struct DisplayChannel {
+    id: String,
+    path: String,
+    listener: UnixListener,
+    encoder: Box<dyn FrameEncoder>,
+    client: Option<ChannelClient>,
+    /// Guest image referenced by the channel.
+    image: *mut pixman_image_t,
+    format: pixman_format_code_t,
+    /// Area changed since the last update.
+    dirty: Option<Rect>,
+    cursor: Option<DisplayMouse>,
+    dcl: Option<Arc<Mutex<DisplayChangeListener>>>,
+}
+
+// SAFETY: The raw pointer of the guest image is only accessed with the lock of the
+// channel held, and the image is referenced until it is replaced. The display change
+// listener is only used in the main loop thread. So implement Send is safe.
+unsafe impl Send for DisplayChannel {}
+
+impl DisplayChannel {
+    fn surface_size(&self) -> (i32, i32) {
+        (get_image_width(self.image), get_image_height(self.image))
+    }
+
+    fn set_all_dirty(&mut self) {
+        let (width, height) = self.surface_size();
+        self.dirty = Some(Rect {
+            x: 0,
+            y: 0,
+            w: width,
+            h: height,
+        });
+    }
+
+    fn switch_surface(&mut self, surface: &DisplaySurface) {
+        unref_pixman_image(self.image);
+        self.image = ref_pixman_image(surface.image);
+        self.format = surface.format;
+        self.set_all_dirty();
+        self.encoder.force_key_frame();
+
+        let (width, height) = self.surface_size();
+        if let Some(client) = self.client.as_mut() {
+            append_msg(
+                &mut client.outbuf,
+                MSG_SURFACE,
+                &[width as u32, height as u32],
+                &[],
+            );
+        }
+    }
+
+    fn update_cursor(&mut self, cursor: &DisplayMouse) {
+        self.cursor = Some(cursor.clone());
+        if let Some(client) = self.client.as_mut() {
+            append_msg(
+                &mut client.outbuf,
+                MSG_CURSOR,
+                &[cursor.width, cursor.height, cursor.hot_x, cursor.hot_y],
+                &cursor.data,
+            );
+        }
+    }
+
+    /// Read the pixels of `rect` in x8r8g8b8 format.
+    fn read_pixels(&self, rect: &Rect) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity((rect.w * rect.h * 4) as usize);
+        let stride = get_image_stride(self.image) as usize;
+        let data = get_image_data(self.image) as *const u8;
+        let native = matches!(
+            self.format,
+            pixman_format_code_t::PIXMAN_x8r8g8b8 | pixman_format_code_t::PIXMAN_a8r8g8b8
+        );
+        let line_buf = if native {
+            ptr::null_mut()
+        } else {
+            pixman_image_linebuf_create(pixman_format_code_t::PIXMAN_x8r8g8b8, rect.w)
+        };
+
+        for y in rect.y..rect.y + rect.h {
+            if native {
+                // SAFETY: the rectangle is inside the image.
+                let row = unsafe {
+                    std::slice::from_raw_parts(
+                        data.add(y as usize * stride + rect.x as usize * 4),
+                        rect.w as usize * 4,
+                    )
+                };
+                pixels.extend_from_slice(row);
+            } else {
+                pixman_image_linebuf_fill(line_buf, self.image, rect.w, rect.x, y);
+                let line = get_image_data(line_buf) as *const u8;
+                // SAFETY: the line buffer holds one row of the rectangle.
+                let row = unsafe { std::slice::from_raw_parts(line, rect.w as usize * 4) };
+                pixels.extend_from_slice(row);
+            }
+        }
+        unref_pixman_image(line_buf);
+        pixels
+    }
+
+    /// Encode and send the dirty area.
+    fn send_update(&mut self) -> Result<()> {
+        if self.client.is_none() || self.image.is_null() {
+            return Ok(());
+        }
+        let (width, height) = self.surface_size();
+        let dirty = match self.dirty.take().and_then(|d| d.clip(width, height)) {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+        let rect = if self.encoder.full_frame() {
+            Rect {
+                x: 0,
+                y: 0,
+                w: width,
+                h: height,
+            }
+        } else {
+            dirty
+        };
+
+        let pixels = self.read_pixels(&rect);
+        let data = self.encoder.encode(&pixels, rect.w as u32, rect.h as u32)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let encoder_id = self.encoder.id();
+        let client = self.client.as_mut().unwrap();
+        append_msg(
+            &mut client.outbuf,
+            MSG_UPDATE,
+            &[
+                rect.x as u32,
+                rect.y as u32,
+                rect.w as u32,
+                rect.h as u32,
+                encoder_id,
+            ],
+            &data,
+        );
+        Ok(())
+    }
+
+    /// Flush the output, the client is disconnected on error.
+    fn flush(&mut self) -> Option<Vec<EventNotifier>> {
+        let client = self.client.as_mut()?;
+        if let Err(e) = client.flush() {
+            warn!("Display channel {}: {:?}", self.id, e);
+            return self.disconnect();
+        }
+        None
+    }
+
+    fn connect(&mut self, stream: UnixStream) -> Result<()> {
+        stream
+            .set_nonblocking(true)
+            .with_context(|| "Failed to set display channel client nonblocking")?;
+        let mut client = ChannelClient {
+            stream,
+            inbuf: Vec::new(),
+            outbuf: Vec::new(),
+        };
+        append_msg(
+            &mut client.outbuf,
+            MSG_HELLO,
+            &[PROTOCOL_VERSION, self.encoder.id()],
+            &[],
+        );
+        let (width, height) = self.surface_size();
+        append_msg(
+            &mut client.outbuf,
+            MSG_SURFACE,
+            &[width as u32, height as u32],
+            &[],
+        );
+        self.client = Some(client);
+        if let Some(cursor) = self.cursor.clone() {
+            self.update_cursor(&cursor);
+        }
+        self.set_all_dirty();
+        self.encoder.force_key_frame();
+        self.send_update()
+    }
+
+    fn disconnect(&mut self) -> Option<Vec<EventNotifier>> {
+        let client = self.client.take()?;
+        info!("Display channel {} client is disconnected", self.id);
+        Some(gen_delete_notifiers(&[client.stream.as_raw_fd()]))
+    }
+
+    fn handle_client_msg(&mut self, msg_type: u32, payload: &[u8]) -> Result<()> {
+        let field = |idx: usize| -> Result<u32> {
+            match payload.get(idx * 4..idx * 4 + 4) {
+                Some(b) => Ok(u32::from_le_bytes(b.try_into().unwrap())),
+                None => bail!("Invalid size {} of message {}", payload.len(), msg_type),
+            }
+        };
+        match msg_type {
+            MSG_KEY => key_event(field(0)? as u16, field(1)? != 0)?,
+            MSG_POINTER => {
+                let (width, height) = self.surface_size();
+                if width <= 0 || height <= 0 {
+                    return Ok(());
+                }
+                let x = cmp::min(field(1)?, width as u32 - 1) as u64;
+                let y = cmp::min(field(2)?, height as u32 - 1) as u64;
+                point_event(
+                    field(0)?,
+                    (x * ABS_MAX / width as u64) as u32,
+                    (y * ABS_MAX / height as u64) as u32,
+                )?;
+            }
+            MSG_REFRESH => {
+                self.set_all_dirty();
+                self.encoder.force_key_frame();
+            }
+            _ => bail!("Unknown message type {}", msg_type),
+        }
+        Ok(())
+    }
+
+    fn handle_client_input(&mut self) -> Option<Vec<EventNotifier>> {
+        let client = self.client.as_mut()?;
+        let mut buf = [0_u8; 4096];
+        loop {
+            match client.stream.read(&mut buf) {
+                Ok(0) => return self.disconnect(),
+                Ok(n) => client.inbuf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Display channel {} read error: {:?}", self.id, e);
+                    return self.disconnect();
+                }
+            }
+        }
+
+        let mut inbuf = std::mem::take(&mut client.inbuf);
+        let mut offset = 0;
+        while inbuf.len() - offset >= MSG_HEADER_SIZE {
+            let msg_type = u32::from_le_bytes(inbuf[offset..offset + 4].try_into().unwrap());
+            let size = u32::from_le_bytes(inbuf[offset + 4..offset + 8].try_into().unwrap());
+            if size > MAX_CLIENT_MSG_SIZE {
+                warn!("Display channel {} message is too large", self.id);
+                return self.disconnect();
+            }
+            let end = offset + MSG_HEADER_SIZE + size as usize;
+            if inbuf.len() < end {
+                break;
+            }
+            if let Err(e) = self.handle_client_msg(msg_type, &inbuf[offset + MSG_HEADER_SIZE..end])
+            {
+                error!("Display channel {}: {:?}", self.id, e);
+            }
+            offset = end;
+        }
+        inbuf.drain(..offset);
+        if let Some(client) = self.client.as_mut() {
+            client.inbuf = inbuf;
+        }
+        None
+    }
+}
+
+impl Drop for DisplayChannel {
+    fn drop(&mut self) {
+        unref_pixman_image(self.image);
+    }
+}
+
+fn get_client_handler(channel: Arc<Mutex<DisplayChannel>>) -> Rc<NotifierCallback> {
+    Rc::new(move |event, _| {
+        let mut locked_channel = channel.lock().unwrap();
+        if event & EventSet::HANG_UP == EventSet::HANG_UP {
+            return locked_channel.disconnect();
+        }
+        locked_channel.handle_client_input()
+    })
+}
+
+fn get_listener_handler(channel: Arc<Mutex<DisplayChannel>>) -> Rc<NotifierCallback> {
+    Rc::new(move |_, _| {
+        let mut locked_channel = channel.lock().unwrap();
+        let stream = match locked_channel.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!(
+                    "Display channel {} accept error: {:?}",
+                    locked_channel.id, e
+                );
+                return None;
+            }
+        };
+        if locked_channel.client.is_some() {
+            warn!(
+                "Display channel {} already has a client, reject the new one",
+                locked_channel.id
+            );
+            return None;
+        }
+
+        let stream_fd = stream.as_raw_fd();
+        if let Err(e) = locked_channel.connect(stream) {
+            error!("Display channel {}: {:?}", locked_channel.id, e);
+            locked_channel.client = None;
+            return None;
+        }
+        info!("Display channel {} client is connected", locked_channel.id);
+        let mut notifiers = vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            stream_fd,
+            None,
+            EventSet::IN | EventSet::HANG_UP,
+            vec![get_client_handler(channel.clone())],
+        )];
+        if let Some(mut n) = locked_channel.flush() {
+            notifiers.append(&mut n);
+        }
+        Some(notifiers)
+    })
+}
+
+struct DisplayChannelOpts {
+    channel: Weak<Mutex<DisplayChannel>>,
+}
+
+impl DisplayChangeListenerOperations for DisplayChannelOpts {
+    fn dpy_switch(&self, surface: &DisplaySurface) -> Result<()> {
+        if let Some(channel) = self.channel.upgrade() {
+            channel.lock().unwrap().switch_surface(surface);
+        }
+        Ok(())
+    }
+
+    fn dpy_refresh(&self, dcl: &Arc<Mutex<DisplayChangeListener>>) -> Result<()> {
+        let channel = match self.channel.upgrade() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        if channel.lock().unwrap().client.is_none() {
+            dcl.lock().unwrap().update_interval = 0;
+            return Ok(());
+        }
+        dcl.lock().unwrap().update_interval = DISPLAY_UPDATE_INTERVAL_DEFAULT;
+        let con_id = dcl.lock().unwrap().con_id;
+        graphic_hardware_update(con_id);
+
+        let mut locked_channel = channel.lock().unwrap();
+        // Skip the frame if the client has not received the previous one yet, the
+        // dirty area is sent later.
+        let backlogged = match locked_channel.client.as_ref() {
+            Some(client) => !client.outbuf.is_empty(),
+            None => return Ok(()),
+        };
+        if !backlogged {
+            if let Err(e) = locked_channel.send_update() {
+                error!("Display channel {}: {:?}", locked_channel.id, e);
+            }
+        }
+        if let Some(notifiers) = locked_channel.flush() {
+            drop(locked_channel);
+            EventLoop::update_event(notifiers, None)?;
+        }
+        Ok(())
+    }
+
+    fn dpy_image_update(&self, x: i32, y: i32, w: i32, h: i32) -> Result<()> {
+        if let Some(channel) = self.channel.upgrade() {
+            let mut locked_channel = channel.lock().unwrap();
+            let rect = Rect { x, y, w, h };
+            locked_channel.dirty = Some(match locked_channel.dirty {
+                Some(dirty) => dirty.union(&rect),
+                None => rect,
+            });
+        }
+        Ok(())
+    }
+
+    fn dpy_cursor_update(&self, cursor: &DisplayMouse) -> Result<()> {
+        if let Some(channel) = self.channel.upgrade() {
+            channel.lock().unwrap().update_cursor(cursor);
+        }
+        Ok(())
+    }
+}
+
+/// Add a display channel listening on the unix socket `path`.
+///
+/// # Arguments
+///
+/// * `id` - Id of the display channel.
+/// * `path` - Path of the unix socket.
+/// * `encoder_name` - Encoder of the frames, one of `raw`, `zlib` and `h264`.
+pub fn display_channel_add(id: &str, path: &str, encoder_name: &str) -> Result<()> {
+    if DISPLAY_CHANNELS.lock().unwrap().contains_key(id) {
+        bail!("Display channel {} already exists", id);
+    }
+    let encoder = create_encoder(encoder_name)?;
+
+    clear_file(path.to_string())?;
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind socket for display channel, path:{}", path))?;
+    listener
+        .set_nonblocking(true)
+        .with_context(|| "Failed to set display channel socket nonblocking")?;
+    // add file to temporary pool, so it could be cleaned when vm exit.
+    TempCleaner::add_path(path.to_string());
+    limit_permission(path).with_context(|| {
+        format!(
+            "Failed to change file permission for display channel, path:{}",
+            path
+        )
+    })?;
+    let listener_fd = listener.as_raw_fd();
+
+    let channel = Arc::new(Mutex::new(DisplayChannel {
+        id: id.to_string(),
+        path: path.to_string(),
+        listener,
+        encoder,
+        client: None,
+        image: ptr::null_mut(),
+        format: pixman_format_code_t::PIXMAN_x8r8g8b8,
+        dirty: None,
+        cursor: None,
+        dcl: None,
+    }));
+    let opts = Arc::new(DisplayChannelOpts {
+        channel: Arc::downgrade(&channel),
+    });
+    let dcl = Arc::new(Mutex::new(DisplayChangeListener::new(None, opts)));
+    channel.lock().unwrap().dcl = Some(dcl.clone());
+    register_display(&dcl)?;
+
+    let notifier = EventNotifier::new(
+        NotifierOperation::AddShared,
+        listener_fd,
+        None,
+        EventSet::IN,
+        vec![get_listener_handler(channel.clone())],
+    );
+    if let Err(e) = EventLoop::update_event(vec![notifier], None) {
+        unregister_display(&Some(Arc::downgrade(&dcl)))?;
+        return Err(e);
+    }
+    DISPLAY_CHANNELS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), channel);
+    Ok(())
+}
+
+/// Delete the display channel, the connected client is disconnected.
+pub fn display_channel_del(id: &str) -> Result<()> {
+    let channel = match DISPLAY_CHANNELS.lock().unwrap().remove(id) {
+        Some(c) => c,
+        None => bail!("Display channel {} not found", id),
+    };
+    let mut locked_channel = channel.lock().unwrap();
+    let mut fds: Vec<RawFd> = vec![locked_channel.listener.as_raw_fd()];
+    if let Some(client) = locked_channel.client.take() {
+        fds.push(client.stream.as_raw_fd());
+    }
+    let dcl = locked_channel.dcl.take();
+    let path = locked_channel.path.clone();
+    drop(locked_channel);
+
+    EventLoop::update_event(gen_delete_notifiers(&fds), None)?;
+    unregister_display(&dcl.as_ref().map(Arc::downgrade))?;
+    clear_file(path)?;
+    Ok(())
+}
+
+/// Query the information of all the display channels.
+pub fn query_display_channels() -> Vec<DisplayChannelInfo> {
+    let mut info: Vec<DisplayChannelInfo> = DISPLAY_CHANNELS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|channel| {
+            let locked_channel = channel.lock().unwrap();
+            DisplayChannelInfo {
+                id: locked_channel.id.clone(),
+                path: locked_channel.path.clone(),
+                encoder: locked_channel.encoder.name().to_string(),
+                connected: locked_channel.client.is_some(),
+            }
+        })
+        .collect();
+    info.sort_by(|a, b| a.id.cmp(&b.id));
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_channel_msg() {
+        let mut buf = Vec::new();
+        append_msg(&mut buf, MSG_UPDATE, &[1, 2], &[0xaa]);
+        assert_eq!(
+            buf,
+            vec![2, 0, 0, 0, 9, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0xaa]
+        );
+
+        let rect = Rect {
+            x: 10,
+            y: 10,
+            w: 10,
+            h: 10,
+        }
+        .union(&Rect {
+            x: 0,
+            y: 15,
+            w: 5,
+            h: 20,
+        });
+        assert_eq!(
+            rect,
+            Rect {
+                x: 0,
+                y: 10,
+                w: 20,
+                h: 25
+            }
+        );
+        assert_eq!(
+            rect.clip(15, 30),
+            Some(Rect {
+                x: 0,
+                y: 10,
+                w: 15,
+                h: 20
+            })
+        );
+        assert_eq!(rect.clip(15, 5), None);
+    }
+}
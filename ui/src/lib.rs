@@ -10,8 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod clipboard;
 #[cfg(feature = "console")]
 pub mod console;
+#[cfg(feature = "display_channel")]
+pub mod display_channel;
 pub mod error;
 #[cfg(feature = "gtk")]
 pub mod gtk;
@@ -27,6 +27,7 @@ use sscanf::scanf;
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use crate::{
+    clipboard::{clipboard_set, CLIPBOARD_MAX_SIZE},
     console::console_select,
     error::VncError,
     input::{
@@ -39,7 +40,7 @@ use crate::{
     vnc::{
         auth_sasl::AuthState, framebuffer_update, round_up_div, server_io::VncServer,
         set_area_dirty, write_pixel, BIT_PER_BYTE, DIRTY_PIXELS_NUM, DIRTY_WIDTH_BITS,
-        MAX_IMAGE_SIZE, MAX_WINDOW_HEIGHT, MIN_OUTPUT_LIMIT, OUTPUT_THROTTLE_SCALE,
+        MAX_IMAGE_SIZE, MAX_WINDOW_HEIGHT, MIN_OUTPUT_LIMIT, OUTPUT_THROTTLE_SCALE, VNC_CLIPBOARD,
     },
 };
 use util::{
@@ -69,6 +70,12 @@ const ENCODING_DESKTOP_RESIZE_EXT: i32 = -308;
 const ENCODING_ALPHA_CURSOR: i32 = -314;
 const ENCODING_WMVI: i32 = 1464686185;
 
+// Reason and status of ExtendedDesktopSize.
+const DESKTOP_SIZE_REASON_SERVER: i32 = 0;
+const DESKTOP_SIZE_REASON_CLIENT: i32 = 1;
+const DESKTOP_SIZE_STATUS_OK: i32 = 0;
+const DESKTOP_SIZE_STATUS_PROHIBITED: i32 = 1;
+
 /// This trait is used to send bytes,
 /// the return is the total number of bytes sented.
 pub trait IoOperations {
@@ -103,6 +110,7 @@ enum ClientMsg {
     KeyEvent = 4,
     PointerEvent = 5,
     ClientCutText = 6,
+    SetDesktopSize = 251,
     InvalidMsg,
 }
 
@@ -110,6 +118,7 @@ enum ClientMsg {
 pub enum ServerMsg {
     FramebufferUpdate = 0,
     SetColourMapEntries = 1,
+    ServerCutText = 3,
 }
 
 impl From<u8> for ClientMsg {
@@ -121,6 +130,7 @@ impl From<u8> for ClientMsg {
             4 => ClientMsg::KeyEvent,
             5 => ClientMsg::PointerEvent,
             6 => ClientMsg::ClientCutText,
+            251 => ClientMsg::SetDesktopSize,
             _ => ClientMsg::InvalidMsg,
         }
     }
@@ -672,7 +682,10 @@ impl ClientIoHandler {
                     .unwrap_or_else(|e| error!("Point event error: {:?}", e));
             }
             ClientMsg::ClientCutText => {
-                self.client_cut_event();
+                self.client_cut_event()?;
+            }
+            ClientMsg::SetDesktopSize => {
+                self.set_desktop_size();
             }
             _ => {
                 self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
@@ -796,6 +809,7 @@ impl ClientIoHandler {
         }
 
         let mut locked_dpm = self.client.client_dpm.lock().unwrap();
+        let resize_ext = locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt);
         locked_dpm.feature = 0;
         locked_dpm.enc = 0;
         while num_encoding > 0 {
@@ -859,6 +873,12 @@ impl ClientIoHandler {
 
             num_encoding -= 1;
         }
+        if !resize_ext && locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt) {
+            // Client learns the support of extended desktop size from the first
+            // ExtendedDesktopSize rectangle, so send the current size anyway.
+            locked_dpm.client_width = 0;
+            locked_dpm.client_height = 0;
+        }
 
         drop(locked_dpm);
         let mut buf: Vec<u8> = Vec::new();
@@ -984,21 +1004,68 @@ impl ClientIoHandler {
     }
 
     /// Client cut text.
-    fn client_cut_event(&mut self) {
+    fn client_cut_event(&mut self) -> Result<()> {
         let buf = self.read_incoming_msg();
         if self.expect == 1 {
             self.expect = 8;
-            return;
+            return Ok(());
         }
         if self.expect == 8 {
-            let buf = [buf[4], buf[5], buf[6], buf[7]];
-            let len = u32::from_be_bytes(buf);
+            let len = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+            if len as usize > CLIPBOARD_MAX_SIZE {
+                self.client.conn_state.lock().unwrap().dis_conn = true;
+                return Err(anyhow!(VncError::ProtocolMessageFailed(format!(
+                    "client cut text with length {}",
+                    len
+                ))));
+            }
             if len > 0 {
                 self.expect += len as usize;
+                return Ok(());
+            }
+        }
+
+        // The text of RFB protocol is encoded in ISO 8859-1.
+        let text: String = buf[8..].iter().map(|&c| c as char).collect();
+        clipboard_set(VNC_CLIPBOARD, text.as_bytes());
+        self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        Ok(())
+    }
+
+    /// Client requests to change the desktop size. The resolution is decided by the
+    /// guest, so the request is always rejected.
+    fn set_desktop_size(&mut self) {
+        let buf = self.read_incoming_msg();
+        if self.expect == 1 {
+            self.expect = 8;
+            return;
+        }
+        if self.expect == 8 {
+            let num_screens = buf[6] as usize;
+            if num_screens > 0 {
+                self.expect += num_screens * 16;
                 return;
             }
         }
 
+        let locked_dpm = self.client.client_dpm.lock().unwrap();
+        let width = locked_dpm.client_width;
+        let height = locked_dpm.client_height;
+        drop(locked_dpm);
+        let mut buf: Vec<u8> = Vec::new();
+        buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
+        buf.append(&mut (0_u8).to_be_bytes().to_vec());
+        buf.append(&mut (1_u16).to_be_bytes().to_vec());
+        extended_desktop_size(
+            DESKTOP_SIZE_REASON_CLIENT,
+            DESKTOP_SIZE_STATUS_PROHIBITED,
+            width,
+            height,
+            &mut buf,
+        );
+        let client = self.client.clone();
+        vnc_write(&client, buf);
+        vnc_flush(&client);
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
     }
 
@@ -1258,8 +1325,8 @@ pub fn desktop_resize(
     }
     drop(locked_surface);
     let mut locked_dpm = client.client_dpm.lock().unwrap();
-    if (!locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt)
-        && !locked_dpm.has_feature(VncFeatures::VncFeatureResize))
+    let resize_ext = locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt);
+    if (!resize_ext && !locked_dpm.has_feature(VncFeatures::VncFeatureResize))
         || (locked_dpm.client_width == width && locked_dpm.client_height == height)
     {
         return Ok(());
@@ -1271,10 +1338,62 @@ pub fn desktop_resize(
     buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
     buf.append(&mut (0_u8).to_be_bytes().to_vec());
     buf.append(&mut (1_u16).to_be_bytes().to_vec());
-    framebuffer_update(0, 0, width, height, ENCODING_DESKTOPRESIZE, buf);
+    if resize_ext {
+        extended_desktop_size(
+            DESKTOP_SIZE_REASON_SERVER,
+            DESKTOP_SIZE_STATUS_OK,
+            width,
+            height,
+            buf,
+        );
+    } else {
+        framebuffer_update(0, 0, width, height, ENCODING_DESKTOPRESIZE, buf);
+    }
     Ok(())
 }
 
+/// ExtendedDesktopSize rectangle with a single screen.
+///
+/// # Arguments
+///
+/// * `reason` - Why the desktop size is sent, in x-position of the rectangle.
+/// * `status` - Status of the request of client, in y-position of the rectangle.
+fn extended_desktop_size(reason: i32, status: i32, width: i32, height: i32, buf: &mut Vec<u8>) {
+    framebuffer_update(
+        reason,
+        status,
+        width,
+        height,
+        ENCODING_DESKTOP_RESIZE_EXT,
+        buf,
+    );
+    buf.append(&mut (1_u8).to_be_bytes().to_vec()); // Number of screens.
+    buf.append(&mut [0; 3].to_vec()); // Padding.
+    buf.append(&mut (0_u32).to_be_bytes().to_vec()); // Screen id.
+    buf.append(&mut (0_u16).to_be_bytes().to_vec()); // X-position.
+    buf.append(&mut (0_u16).to_be_bytes().to_vec()); // Y-position.
+    buf.append(&mut (width as u16).to_be_bytes().to_vec()); // Width.
+    buf.append(&mut (height as u16).to_be_bytes().to_vec()); // Height.
+    buf.append(&mut (0_u32).to_be_bytes().to_vec()); // Flags.
+}
+
+/// Send the clipboard text to client.
+///
+/// # Arguments
+///
+/// * `text` - Clipboard text in UTF-8.
+pub fn server_cut_text(text: &[u8], buf: &mut Vec<u8>) {
+    // The text of RFB protocol is encoded in ISO 8859-1, replace the other characters.
+    let text: Vec<u8> = String::from_utf8_lossy(text)
+        .chars()
+        .map(|c| u8::try_from(c as u32).unwrap_or(b'?'))
+        .collect();
+    buf.append(&mut (ServerMsg::ServerCutText as u8).to_be_bytes().to_vec());
+    buf.append(&mut [0; 3].to_vec()); // Padding.
+    buf.append(&mut (text.len() as u32).to_be_bytes().to_vec());
+    buf.extend_from_slice(&text);
+}
+
 /// Set color depth for client.
 pub fn set_color_depth(client: &Arc<ClientState>, buf: &mut Vec<u8>) {
     let mut locked_dpm = client.client_dpm.lock().unwrap();
@@ -30,6 +30,7 @@ use core::time;
 use once_cell::sync::Lazy;
 
 use crate::{
+    clipboard::{register_clipboard, ClipboardOpts},
     console::{
         graphic_hardware_update, register_display, DisplayChangeListener,
         DisplayChangeListenerOperations, DisplayMouse, DisplaySurface,
@@ -43,8 +44,8 @@ use crate::{
     },
     vnc::{
         client_io::{
-            desktop_resize, display_cursor_define, get_rects, set_color_depth, vnc_flush,
-            vnc_update_output_throttle, vnc_write, DisplayMode, Rectangle, ServerMsg,
+            desktop_resize, display_cursor_define, get_rects, server_cut_text, set_color_depth,
+            vnc_flush, vnc_update_output_throttle, vnc_write, DisplayMode, Rectangle, ServerMsg,
             ENCODING_HEXTILE, ENCODING_RAW,
         },
         encoding::enc_hextile::hextile_send_framebuffer_update,
@@ -79,6 +80,8 @@ pub const OUTPUT_THROTTLE_SCALE: i32 = 5;
 pub const MIN_OUTPUT_LIMIT: i32 = 1024 * 1024 * OUTPUT_THROTTLE_SCALE;
 const DEFAULT_REFRESH_INTERVAL: u64 = 30;
 pub const BIT_PER_BYTE: u32 = 8;
+/// Name of vnc clients in the clipboard.
+pub const VNC_CLIPBOARD: &str = "vnc";
 
 pub const fn round_up_div(n: u64, d: u64) -> u64 {
     (n + d - 1) / d
@@ -256,6 +259,26 @@ impl DisplayChangeListenerOperations for VncInterface {
     }
 }
 
+/// Clipboard of vnc clients.
+#[derive(Default)]
+struct VncClipboard {}
+
+impl ClipboardOpts for VncClipboard {
+    fn clipboard_update(&mut self, text: &[u8]) {
+        if VNC_SERVERS.lock().unwrap().is_empty() {
+            return;
+        }
+        let server = VNC_SERVERS.lock().unwrap()[0].clone();
+        let mut buf: Vec<u8> = Vec::new();
+        server_cut_text(text, &mut buf);
+        let locked_handlers = server.client_handlers.lock().unwrap();
+        for client in locked_handlers.values() {
+            vnc_write(client, buf.clone());
+            vnc_flush(client);
+        }
+    }
+}
+
 /// Initizlization function of vnc
 ///
 /// # Arguments
@@ -304,6 +327,9 @@ pub fn vnc_init(vnc: &Option<VncConfig>, object: &ObjectConfig) -> Result<()> {
     // Register in display console.
     register_display(&dcl)?;
 
+    // Share the clipboard with the guest.
+    register_clipboard(VNC_CLIPBOARD, Arc::new(Mutex::new(VncClipboard::default())));
+
     // Register the event to listen for client's connection.
     let vnc_io = Arc::new(Mutex::new(VncConnHandler::new(listener, server)));
 
@@ -12,21 +12,25 @@
 
 mod libaio;
 mod raw;
+mod threads;
 mod uring;
 
 pub use raw::*;
 
 use std::clone::Clone;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{cmp, str::FromStr};
 
 use anyhow::{anyhow, bail, Context, Result};
 use libc::c_void;
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
+use threads::ThreadsAioContext;
 use uring::IoUringContext;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -44,6 +48,8 @@ const AIO_OFF: &str = "off";
 const AIO_NATIVE: &str = "native";
 /// Io-uring aio type.
 const AIO_IOURING: &str = "io_uring";
+/// Thread pool aio type.
+const AIO_THREADS: &str = "threads";
 /// Max bytes of bounce buffer for IO.
 const MAX_LEN_BOUNCE_BUFF: u64 = 1 << 20;
 
@@ -56,6 +62,19 @@ pub enum AioEngine {
     Native = 1,
     #[serde(alias = "iouring")]
     IoUring = 2,
+    #[serde(alias = "threads")]
+    Threads = 3,
+}
+
+impl AioEngine {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AioEngine::Off => AIO_OFF,
+            AioEngine::Native => AIO_NATIVE,
+            AioEngine::IoUring => AIO_IOURING,
+            AioEngine::Threads => AIO_THREADS,
+        }
+    }
 }
 
 impl FromStr for AioEngine {
@@ -66,6 +85,7 @@ impl FromStr for AioEngine {
             AIO_OFF => Ok(AioEngine::Off),
             AIO_NATIVE => Ok(AioEngine::Native),
             AIO_IOURING => Ok(AioEngine::IoUring),
+            AIO_THREADS => Ok(AioEngine::Threads),
             _ => Err(()),
         }
     }
@@ -199,11 +219,15 @@ pub struct Aio<T: Clone + 'static> {
     pub incomplete_cnt: Arc<AtomicU64>,
     max_events: usize,
     pub complete_func: Arc<AioCompleteFunc<T>>,
+    /// Submission time of the async requests, and whether it has been reported as expired.
+    submit_time: HashMap<u64, (Instant, bool)>,
+    /// Expired requests which have been failed, they are freed silently on completion.
+    abandoned: HashSet<u64>,
 }
 
 pub fn aio_probe(engine: AioEngine) -> Result<()> {
     match engine {
-        AioEngine::Off => {}
+        AioEngine::Off | AioEngine::Threads => {}
         AioEngine::Native => {
             let ctx = LibaioContext::probe(1)?;
             // SAFETY: if no err, ctx is valid.
@@ -216,6 +240,53 @@ pub fn aio_probe(engine: AioEngine) -> Result<()> {
     Ok(())
 }
 
+/// Whether native aio submits the IO of the file synchronously, as its file system
+/// doesn't support asynchronous direct IO, e.g. NFS, tmpfs and FUSE.
+fn native_aio_is_sync(path: &str) -> bool {
+    const NFS_SUPER_MAGIC: i64 = 0x6969;
+    const TMPFS_MAGIC: i64 = 0x0102_1994;
+    const FUSE_SUPER_MAGIC: i64 = 0x6573_5546;
+    const CIFS_MAGIC_NUMBER: i64 = 0xFF53_4D42;
+    const SMB2_MAGIC_NUMBER: i64 = 0xFE53_4D42;
+
+    let Ok(path) = std::ffi::CString::new(path) else {
+        return false;
+    };
+    // SAFETY: `libc::statfs` is plain old data, and all zero is valid for it.
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is a valid C string and stat is valid.
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } < 0 {
+        return false;
+    }
+    matches!(
+        stat.f_type as i64,
+        NFS_SUPER_MAGIC | TMPFS_MAGIC | FUSE_SUPER_MAGIC | CIFS_MAGIC_NUMBER | SMB2_MAGIC_NUMBER
+    )
+}
+
+/// Select the aio engine for the drive if it's not specified. The engines are probed
+/// in the order of io_uring, native and threads. Native aio is only used with direct
+/// IO, as the buffered IO is submitted synchronously by it, and it's skipped for the
+/// file on the file systems where it's synchronous even with direct IO.
+///
+/// # Arguments
+///
+/// * `path` - The path of the drive file.
+/// * `direct` - Whether the file is opened with direct IO.
+pub fn aio_select(path: &str, direct: bool) -> AioEngine {
+    let mut engines = vec![AioEngine::IoUring];
+    if direct && !native_aio_is_sync(path) {
+        engines.push(AioEngine::Native);
+    }
+    for engine in engines {
+        match aio_probe(engine) {
+            Ok(()) => return engine,
+            Err(e) => warn!("Aio engine {} is not available: {:?}", engine.name(), e),
+        }
+    }
+    AioEngine::Threads
+}
+
 impl<T: Clone + 'static> Aio<T> {
     pub fn new(func: Arc<AioCompleteFunc<T>>, engine: AioEngine) -> Result<Self> {
         let max_events: usize = 128;
@@ -224,6 +295,7 @@ impl<T: Clone + 'static> Aio<T> {
             AioEngine::Off => None,
             AioEngine::Native => Some(Box::new(LibaioContext::new(max_events as u32, &fd)?)),
             AioEngine::IoUring => Some(Box::new(IoUringContext::new(max_events as u32, &fd)?)),
+            AioEngine::Threads => Some(Box::new(ThreadsAioContext::new(max_events as u32, &fd)?)),
         };
 
         Ok(Aio {
@@ -235,6 +307,8 @@ impl<T: Clone + 'static> Aio<T> {
             incomplete_cnt: Arc::new(AtomicU64::new(0)),
             max_events,
             complete_func: func,
+            submit_time: HashMap::new(),
+            abandoned: HashSet::new(),
         })
     }
 
@@ -319,6 +393,13 @@ impl<T: Clone + 'static> Aio<T> {
             // SAFETY: evt.data is specified by submit and not dropped at other place.
             unsafe {
                 let node = evt.user_data as *mut CbNode<T>;
+                if self.abandoned.remove(&evt.user_data) {
+                    // The request has been failed by timeout, just free it.
+                    self.aio_in_flight.unlink(&(*node));
+                    drop(Box::from_raw(node));
+                    continue;
+                }
+                self.submit_time.remove(&evt.user_data);
                 let res = if (evt.status == 0) && (evt.res == (*node).value.nbytes as i64) {
                     done = true;
                     evt.res
@@ -342,6 +423,35 @@ impl<T: Clone + 'static> Aio<T> {
         Ok(done)
     }
 
+    /// Check the async requests which are not completed within `timeout`, the number of
+    /// newly expired requests is returned. If `fail` is true, the expired requests are
+    /// completed with `-ETIMEDOUT` at once, and freed silently when the host completes them.
+    pub fn check_timeout(&mut self, timeout: Duration, fail: bool) -> Result<usize> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for (user_data, (time, reported)) in self.submit_time.iter_mut() {
+            if !*reported && now.duration_since(*time) >= timeout {
+                *reported = true;
+                expired.push(*user_data);
+            }
+        }
+        if !fail {
+            return Ok(expired.len());
+        }
+
+        for user_data in expired.iter() {
+            self.submit_time.remove(user_data);
+            let node = *user_data as *mut CbNode<T>;
+            // SAFETY: user_data is the address of the node which is not freed until it
+            // is completed by the host or dropped from the queue.
+            let cb = unsafe { &(*node).value };
+            self.abandoned.insert(*user_data);
+            self.incomplete_cnt.fetch_sub(1, Ordering::SeqCst);
+            (self.complete_func)(cb, -(libc::ETIMEDOUT as i64))?;
+        }
+        Ok(expired.len())
+    }
+
     fn process_list(&mut self) -> Result<()> {
         if self.ctx.is_none() {
             warn!("Can not process aio list with invalid ctx.");
@@ -352,6 +462,8 @@ impl<T: Clone + 'static> Aio<T> {
 
             for _ in self.aio_in_flight.len..self.max_events {
                 match self.aio_in_queue.pop_tail() {
+                    // The request failed by timeout is not submitted anymore.
+                    Some(node) if self.abandoned.remove(&node.value.user_data) => continue,
                     Some(node) => {
                         iocbs.push(&node.value as *const AioCb<T>);
                         self.aio_in_flight.add_head(node);
@@ -360,7 +472,9 @@ impl<T: Clone + 'static> Aio<T> {
                 }
             }
 
-            // The iocbs must not be empty.
+            if iocbs.is_empty() {
+                continue;
+            }
             let (nr, is_err) = match self.ctx.as_mut().unwrap().submit(&iocbs) {
                 Ok(nr) => (nr, false),
                 Err(e) => {
@@ -382,6 +496,7 @@ impl<T: Clone + 'static> Aio<T> {
             if is_err {
                 // Fail one request, retry the rest.
                 if let Some(node) = self.aio_in_queue.pop_tail() {
+                    self.submit_time.remove(&node.value.user_data);
                     self.incomplete_cnt.fetch_sub(1, Ordering::SeqCst);
                     (self.complete_func)(&(node).value, -1)?;
                 }
@@ -398,6 +513,8 @@ impl<T: Clone + 'static> Aio<T> {
         let mut node = Box::new(Node::new(cb));
         node.value.user_data = (&mut (*node) as *mut CbNode<T>) as u64;
 
+        self.submit_time
+            .insert(node.value.user_data, (Instant::now(), false));
         self.aio_in_queue.add_head(node);
         self.incomplete_cnt.fetch_add(1, Ordering::SeqCst);
         if self.aio_in_queue.len + self.aio_in_flight.len >= self.max_events {
@@ -890,6 +1007,61 @@ mod tests {
         test_sync_rw_all_align(OpCode::Pwritev, false);
     }
 
+    #[test]
+    fn test_threads_aio_rw() {
+        let tmp_file = TempFile::new().unwrap();
+        let file = tmp_file.into_file();
+        let file_fd = file.as_raw_fd();
+        let mut aio = Aio::new(
+            Arc::new(|_: &AioCb<i32>, res: i64| -> Result<()> {
+                assert!(res >= 0);
+                Ok(())
+            }),
+            AioEngine::Threads,
+        )
+        .unwrap();
+        assert_eq!(aio.get_engine(), AioEngine::Threads);
+
+        let mut wbuf = vec![0x5A_u8; 4096];
+        let mut rbuf = vec![0_u8; 4096];
+        for (opcode, buf) in [(OpCode::Pwritev, &mut wbuf), (OpCode::Preadv, &mut rbuf)] {
+            let aiocb = AioCb {
+                direct: false,
+                req_align: 512,
+                buf_align: 512,
+                discard: false,
+                write_zeroes: WriteZeroesState::Off,
+                file_fd,
+                opcode,
+                iovec: vec![Iovec::new(buf.as_mut_ptr() as u64, buf.len() as u64)],
+                offset: 512,
+                nbytes: buf.len() as u64,
+                user_data: 0,
+                iocompletecb: 0,
+                combine_req: None,
+            };
+            aio.submit_request(aiocb).unwrap();
+            aio.flush_request().unwrap();
+            while aio.incomplete_cnt.load(Ordering::SeqCst) != 0 {
+                if aio.fd.read().is_ok() {
+                    aio.handle_complete().unwrap();
+                } else {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+        assert_eq!(wbuf, rbuf);
+    }
+
+    #[test]
+    fn test_aio_select() {
+        let engine = aio_select("/path/not/exist", false);
+        assert_ne!(engine, AioEngine::Native);
+        assert_ne!(engine, AioEngine::Off);
+        assert!(aio_probe(engine).is_ok());
+        assert_eq!("threads".parse::<AioEngine>(), Ok(AioEngine::Threads));
+    }
+
     #[test]
     fn test_iovecs_split() {
         let iovecs = vec![Iovec::new(0, 100), Iovec::new(200, 100)];
@@ -0,0 +1,216 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::VecDeque;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use log::error;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::{
+    raw_datasync, raw_readv, raw_writev, AioCb, AioContext, AioEvent, Iovec, OpCode, Result,
+};
+
+/// Max number of the worker threads of each context.
+const MAX_WORKERS: usize = 16;
+/// The worker thread exits if there is no request within the time.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The request executed by the worker thread.
+struct ThreadsReq {
+    user_data: u64,
+    opcode: OpCode,
+    fd: RawFd,
+    iovec: Vec<Iovec>,
+    offset: usize,
+}
+
+impl ThreadsReq {
+    fn execute(&self) -> i64 {
+        match self.opcode {
+            OpCode::Preadv => raw_readv(self.fd, &self.iovec, self.offset),
+            OpCode::Pwritev => raw_writev(self.fd, &self.iovec, self.offset),
+            OpCode::Fdsync => raw_datasync(self.fd),
+            _ => -1,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ThreadsQueue {
+    /// Requests waiting for the worker threads.
+    pending: VecDeque<ThreadsReq>,
+    /// Events of the completed requests.
+    completed: Vec<AioEvent>,
+    /// Number of the running worker threads.
+    workers: usize,
+    /// Number of the worker threads waiting for requests.
+    idle: usize,
+    exit: bool,
+}
+
+struct ThreadsShared {
+    queue: Mutex<ThreadsQueue>,
+    cond: Condvar,
+    /// Notify the completion of requests.
+    eventfd: EventFd,
+}
+
+/// The thread pool context, which executes the requests by synchronous syscalls in
+/// the worker threads. It works with any file and kernel, and keeps the IO thread
+/// responsive on the file systems where libaio is synchronous, e.g. NFS and tmpfs.
+/// The worker threads are spawned on demand and bounded by `MAX_WORKERS`.
+pub(crate) struct ThreadsAioContext {
+    shared: Arc<ThreadsShared>,
+    workers: Vec<JoinHandle<()>>,
+    events: Vec<AioEvent>,
+}
+
+impl ThreadsAioContext {
+    pub fn new(max_size: u32, eventfd: &EventFd) -> Result<Self> {
+        let eventfd = eventfd
+            .try_clone()
+            .with_context(|| "Failed to clone eventfd for threads aio")?;
+        Ok(ThreadsAioContext {
+            shared: Arc::new(ThreadsShared {
+                queue: Mutex::new(ThreadsQueue::default()),
+                cond: Condvar::new(),
+                eventfd,
+            }),
+            workers: Vec::new(),
+            events: Vec::with_capacity(max_size as usize),
+        })
+    }
+
+    fn spawn_worker(&mut self) -> Result<()> {
+        // Release the worker threads exited for idle.
+        self.workers.retain(|worker| !worker.is_finished());
+        let shared = self.shared.clone();
+        self.shared.queue.lock().unwrap().workers += 1;
+        match thread::Builder::new()
+            .name("aio-worker".to_string())
+            .spawn(move || worker_loop(&shared))
+        {
+            Ok(worker) => {
+                self.workers.push(worker);
+                Ok(())
+            }
+            Err(e) => {
+                self.shared.queue.lock().unwrap().workers -= 1;
+                Err(e).with_context(|| "Failed to spawn aio worker thread")
+            }
+        }
+    }
+}
+
+fn worker_loop(shared: &ThreadsShared) {
+    loop {
+        let req = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.exit {
+                    return;
+                }
+                if let Some(req) = queue.pending.pop_front() {
+                    break req;
+                }
+                queue.idle += 1;
+                let (locked_queue, res) = shared.cond.wait_timeout(queue, IDLE_TIMEOUT).unwrap();
+                queue = locked_queue;
+                queue.idle -= 1;
+                if res.timed_out() && queue.pending.is_empty() {
+                    queue.workers -= 1;
+                    return;
+                }
+            }
+        };
+
+        let res = req.execute();
+        shared.queue.lock().unwrap().completed.push(AioEvent {
+            user_data: req.user_data,
+            status: 0,
+            res,
+        });
+        if let Err(e) = shared.eventfd.write(1) {
+            error!("Failed to notify completion of threads aio: {:?}", e);
+        }
+    }
+}
+
+impl Drop for ThreadsAioContext {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().exit = true;
+        self.shared.cond.notify_all();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Failed to join aio worker thread");
+            }
+        }
+    }
+}
+
+impl<T: Clone> AioContext<T> for ThreadsAioContext {
+    fn submit(&mut self, iocbp: &[*const AioCb<T>]) -> Result<usize> {
+        let mut reqs = Vec::with_capacity(iocbp.len());
+        for iocb in iocbp {
+            // SAFETY: iocb is valid until request is finished.
+            let cb = unsafe { &*(*iocb) };
+            match cb.opcode {
+                OpCode::Preadv | OpCode::Pwritev | OpCode::Fdsync => {}
+                _ => bail!("Failed to submit threads aio, opcode is not supported."),
+            }
+            reqs.push(ThreadsReq {
+                user_data: cb.user_data,
+                opcode: cb.opcode,
+                fd: cb.file_fd,
+                iovec: cb.iovec.clone(),
+                offset: cb.offset,
+            });
+        }
+
+        if self.shared.queue.lock().unwrap().workers == 0 {
+            self.spawn_worker()?;
+        }
+        let nr = reqs.len();
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.pending.extend(reqs);
+        let workers = queue.workers;
+        let wanted = std::cmp::min(
+            workers + queue.pending.len().saturating_sub(queue.idle),
+            MAX_WORKERS,
+        );
+        drop(queue);
+
+        // Spawn more workers if the idle ones are not enough.
+        for _ in workers..wanted {
+            if let Err(e) = self.spawn_worker() {
+                error!("{:?}", e);
+                break;
+            }
+        }
+        self.shared.cond.notify_all();
+        Ok(nr)
+    }
+
+    fn get_events(&mut self) -> &[AioEvent] {
+        self.events.clear();
+        std::mem::swap(
+            &mut self.events,
+            &mut self.shared.queue.lock().unwrap().completed,
+        );
+        &self.events
+    }
+}
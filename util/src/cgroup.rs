@@ -0,0 +1,122 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Cgroup v2 of the VM.
+//!
+//! The whole VM process is placed into the VM cgroup, which limits the CPU budget
+//! of the VM by `cpu.weight` and `cpuset.cpus`. Three threaded child cgroups are
+//! created under it, and the threads join them by their types:
+//! - `emulator`: the main thread, and the threads it creates by default.
+//! - `vcpu`: the vCPU threads.
+//! - `iothread`: the iothreads.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use once_cell::sync::Lazy;
+
+/// Controllers enabled for the threaded child cgroups.
+const THREADED_CONTROLLERS: [&str; 2] = ["cpu", "cpuset"];
+
+/// Path of the VM cgroup, threads join its child cgroups after it's initialized.
+static VM_CGROUP: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+/// Types of the threads, each type has its own child cgroup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgroupThread {
+    Emulator,
+    Vcpu,
+    Iothread,
+}
+
+impl CgroupThread {
+    fn name(&self) -> &'static str {
+        match self {
+            CgroupThread::Emulator => "emulator",
+            CgroupThread::Vcpu => "vcpu",
+            CgroupThread::Iothread => "iothread",
+        }
+    }
+}
+
+fn write_cgroup_file(dir: &Path, file: &str, value: &str) -> Result<()> {
+    fs::write(dir.join(file), value)
+        .with_context(|| format!("Failed to write {} to {:?}", value, dir.join(file)))
+}
+
+/// Create the VM cgroup at `path` if it doesn't exist, set its CPU limits and move
+/// the process into it. It needs to be called by the main thread before the other
+/// threads of the VM are created.
+///
+/// # Arguments
+///
+/// * `path` - Path of the VM cgroup in the cgroup v2 hierarchy.
+/// * `cpu_weight` - Value of `cpu.weight`, in range [1, 10000].
+/// * `cpuset` - Value of `cpuset.cpus`, such as `0-3,6`.
+pub fn init_vm_cgroup(path: &str, cpu_weight: Option<u64>, cpuset: Option<&str>) -> Result<()> {
+    let vm_cgroup = PathBuf::from(path);
+    fs::create_dir_all(&vm_cgroup)
+        .with_context(|| format!("Failed to create cgroup {:?}", vm_cgroup))?;
+    if !vm_cgroup.join("cgroup.controllers").exists() {
+        bail!("{:?} is not in a cgroup v2 hierarchy", vm_cgroup);
+    }
+
+    if let Some(weight) = cpu_weight {
+        write_cgroup_file(&vm_cgroup, "cpu.weight", &weight.to_string())?;
+    }
+    if let Some(cpus) = cpuset {
+        write_cgroup_file(&vm_cgroup, "cpuset.cpus", cpus)?;
+    }
+
+    let controllers = fs::read_to_string(vm_cgroup.join("cgroup.controllers"))?;
+    let subtree_control = controllers
+        .split_whitespace()
+        .filter(|c| THREADED_CONTROLLERS.contains(c))
+        .map(|c| format!("+{}", c))
+        .collect::<Vec<String>>()
+        .join(" ");
+    for thread in [
+        CgroupThread::Emulator,
+        CgroupThread::Vcpu,
+        CgroupThread::Iothread,
+    ] {
+        let child = vm_cgroup.join(thread.name());
+        fs::create_dir_all(&child)
+            .with_context(|| format!("Failed to create cgroup {:?}", child))?;
+        write_cgroup_file(&child, "cgroup.type", "threaded")?;
+    }
+    if !subtree_control.is_empty() {
+        write_cgroup_file(&vm_cgroup, "cgroup.subtree_control", &subtree_control)?;
+    }
+
+    // Writing 0 moves the calling process or thread.
+    write_cgroup_file(&vm_cgroup, "cgroup.procs", "0")?;
+    *VM_CGROUP.lock().unwrap() = Some(vm_cgroup);
+    join_thread_cgroup(CgroupThread::Emulator)?;
+
+    info!("The VM is placed in cgroup {}", path);
+    Ok(())
+}
+
+/// Move the calling thread into the child cgroup of its type, nothing is done if the
+/// VM cgroup is not initialized.
+pub fn join_thread_cgroup(thread: CgroupThread) -> Result<()> {
+    let locked_cgroup = VM_CGROUP.lock().unwrap();
+    let vm_cgroup = match locked_cgroup.as_ref() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    write_cgroup_file(&vm_cgroup.join(thread.name()), "cgroup.threads", "0")
+}
@@ -13,6 +13,7 @@
 use anyhow::{anyhow, Context, Result};
 use byteorder::{BigEndian, ByteOrder};
 
+use crate::num_ops::round_up;
 use crate::UtilError;
 
 pub const CLK_PHANDLE: u32 = 1;
@@ -39,6 +40,7 @@ const FDT_LAST_COMP_VERSION: u32 = 16;
 const FDT_BEGIN_NODE: u32 = 0x00000001;
 const FDT_END_NODE: u32 = 0x00000002;
 const FDT_PROP: u32 = 0x00000003;
+const FDT_NOP: u32 = 0x00000004;
 const FDT_END: u32 = 0x00000009;
 // Memory reservation block alignment.
 const MEM_RESERVE_ALIGNMENT: usize = 8;
@@ -289,6 +291,281 @@ impl FdtBuilder {
     }
 }
 
+/// Node of a parsed flattened device tree.
+#[derive(Default, Clone, Debug)]
+struct FdtNode {
+    name: String,
+    properties: Vec<(String, Vec<u8>)>,
+    subnodes: Vec<FdtNode>,
+}
+
+impl FdtNode {
+    /// Merge `other` into this node. Properties of `other` replace the ones with
+    /// the same name, and subnodes with the same name are merged recursively.
+    fn merge(&mut self, other: FdtNode) {
+        for (name, value) in other.properties {
+            match self.properties.iter_mut().find(|(n, _)| *n == name) {
+                Some(prop) => prop.1 = value,
+                None => self.properties.push((name, value)),
+            }
+        }
+        for subnode in other.subnodes {
+            match self.subnodes.iter_mut().find(|n| n.name == subnode.name) {
+                Some(node) => node.merge(subnode),
+                None => self.subnodes.push(subnode),
+            }
+        }
+    }
+
+    fn build(&self, fdt: &mut FdtBuilder) -> Result<()> {
+        let node_dep = fdt.begin_node(&self.name)?;
+        for (name, value) in self.properties.iter() {
+            fdt.set_property(name, value)?;
+        }
+        for subnode in self.subnodes.iter() {
+            subnode.build(fdt)?;
+        }
+        fdt.end_node(node_dep)
+    }
+}
+
+/// Flattened device tree parsed from a dtb blob.
+struct FdtBlob {
+    root: FdtNode,
+    mem_reserve: Vec<FdtReserveEntry>,
+    boot_cpuid_phys: u32,
+}
+
+fn fdt_err(msg: &str) -> anyhow::Error {
+    anyhow!(UtilError::InvalidFdt(msg.to_string()))
+}
+
+fn read_be_u32(blob: &[u8], offset: usize) -> Result<u32> {
+    blob.get(offset..offset + 4)
+        .map(BigEndian::read_u32)
+        .ok_or_else(|| fdt_err("unexpected end of blob"))
+}
+
+fn read_cstr(blob: &[u8], offset: usize) -> Result<&str> {
+    let bytes = blob
+        .get(offset..)
+        .ok_or_else(|| fdt_err("string offset out of range"))?;
+    let len = bytes
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| fdt_err("unterminated string"))?;
+    std::str::from_utf8(&bytes[..len]).map_err(|_| fdt_err("string is not valid utf-8"))
+}
+
+impl FdtBlob {
+    fn parse(blob: &[u8]) -> Result<Self> {
+        if blob.len() < FDT_HEADER_SIZE || read_be_u32(blob, 0)? != FDT_MAGIC {
+            return Err(fdt_err("bad magic"));
+        }
+        let total_size = read_be_u32(blob, 4)? as usize;
+        if total_size > blob.len() {
+            return Err(fdt_err("total size exceeds blob size"));
+        }
+        let blob = &blob[..total_size];
+        let off_dt_struct = read_be_u32(blob, 8)? as usize;
+        let off_dt_strings = read_be_u32(blob, 12)? as usize;
+        let off_mem_rsvmap = read_be_u32(blob, 16)? as usize;
+        if read_be_u32(blob, 24)? > FDT_VERSION {
+            return Err(fdt_err("unsupported version"));
+        }
+        let boot_cpuid_phys = read_be_u32(blob, 28)?;
+        let strings = blob
+            .get(off_dt_strings..)
+            .ok_or_else(|| fdt_err("strings block out of range"))?;
+
+        let mut mem_reserve = Vec::new();
+        let mut offset = off_mem_rsvmap;
+        loop {
+            let entry = blob
+                .get(offset..offset + 16)
+                .ok_or_else(|| fdt_err("memory reservation block out of range"))?;
+            let address = BigEndian::read_u64(&entry[0..8]);
+            let size = BigEndian::read_u64(&entry[8..16]);
+            if address == 0 && size == 0 {
+                break;
+            }
+            mem_reserve.push(FdtReserveEntry { address, size });
+            offset += 16;
+        }
+
+        // Nodes which are not closed yet, the first one is the root node.
+        let mut stack: Vec<FdtNode> = Vec::new();
+        let mut root = None;
+        let mut offset = off_dt_struct;
+        loop {
+            let token = read_be_u32(blob, offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    if root.is_some() {
+                        return Err(fdt_err("multiple root nodes"));
+                    }
+                    let name = read_cstr(blob, offset)?;
+                    offset = round_up(
+                        (offset + name.len() + 1) as u64,
+                        STRUCTURE_BLOCK_ALIGNMENT as u64,
+                    )
+                    .ok_or_else(|| fdt_err("offset overflow"))?
+                        as usize;
+                    stack.push(FdtNode {
+                        name: name.to_string(),
+                        ..Default::default()
+                    });
+                }
+                FDT_END_NODE => {
+                    let node = stack.pop().ok_or_else(|| fdt_err("unbalanced node"))?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.subnodes.push(node),
+                        None => root = Some(node),
+                    }
+                }
+                FDT_PROP => {
+                    let len = read_be_u32(blob, offset)? as usize;
+                    let nameoff = read_be_u32(blob, offset + 4)? as usize;
+                    offset += 8;
+                    let value = blob
+                        .get(offset..offset + len)
+                        .ok_or_else(|| fdt_err("property value out of range"))?;
+                    let name = read_cstr(strings, nameoff)?;
+                    stack
+                        .last_mut()
+                        .ok_or_else(|| fdt_err("property outside of node"))?
+                        .properties
+                        .push((name.to_string(), value.to_vec()));
+                    offset = round_up((offset + len) as u64, STRUCTURE_BLOCK_ALIGNMENT as u64)
+                        .ok_or_else(|| fdt_err("offset overflow"))?
+                        as usize;
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                _ => return Err(fdt_err("unknown structure token")),
+            }
+        }
+        if !stack.is_empty() {
+            return Err(fdt_err("unclosed node"));
+        }
+
+        Ok(FdtBlob {
+            root: root.ok_or_else(|| fdt_err("no root node"))?,
+            mem_reserve,
+            boot_cpuid_phys,
+        })
+    }
+}
+
+/// Merge a user-supplied dtb into the machine-generated one.
+///
+/// Nodes of `overlay` are added to `base`, and properties of `overlay` replace the
+/// ones of `base` with the same path and name. Memory reservations of both are kept.
+///
+/// # Arguments
+///
+/// * `base` - The machine-generated flattened device tree.
+/// * `overlay` - The user-supplied flattened device tree.
+pub fn merge_fdt(base: &[u8], overlay: &[u8]) -> Result<Vec<u8>> {
+    let mut base = FdtBlob::parse(base).with_context(|| "Failed to parse base dtb")?;
+    let overlay = FdtBlob::parse(overlay).with_context(|| "Failed to parse overlay dtb")?;
+
+    base.root.merge(overlay.root);
+    base.mem_reserve.extend(overlay.mem_reserve);
+
+    let mut fdt = FdtBuilder::new();
+    fdt.add_mem_reserve(&base.mem_reserve)?;
+    fdt.set_boot_cpuid_phys(base.boot_cpuid_phys);
+    base.root.build(&mut fdt)?;
+    let merged = fdt.finish()?;
+    if merged.len() > FDT_MAX_SIZE as usize {
+        return Err(fdt_err("merged dtb is larger than the maximum size"));
+    }
+
+    Ok(merged)
+}
+
+/// Names of the nodes generated by dtc for overlays, which never describe a device.
+const FDT_OVERLAY_META_NODES: [&str; 3] = ["__symbols__", "__fixups__", "__local_fixups__"];
+
+/// Device node loaded from a user-supplied dtb, which is used as the template of the
+/// node of a passthrough device. Either a plain dtb whose first node under the root is
+/// the device node, or an overlay whose first fragment holds the device node in
+/// `__overlay__` is accepted.
+#[derive(Clone, Debug)]
+pub struct FdtNodeTemplate {
+    node: FdtNode,
+}
+
+impl FdtNodeTemplate {
+    pub fn from_dtb(blob: &[u8]) -> Result<Self> {
+        let root = FdtBlob::parse(blob)
+            .with_context(|| "Failed to parse device node template")?
+            .root;
+        let container = root
+            .subnodes
+            .iter()
+            .find_map(|n| n.subnodes.iter().find(|s| s.name == "__overlay__"))
+            .unwrap_or(&root);
+        let node = container
+            .subnodes
+            .iter()
+            .find(|n| !FDT_OVERLAY_META_NODES.contains(&n.name.as_str()))
+            .ok_or_else(|| fdt_err("no device node in template"))?;
+
+        Ok(FdtNodeTemplate { node: node.clone() })
+    }
+
+    /// Name of the device node without the unit address.
+    pub fn name(&self) -> &str {
+        self.node.name.split('@').next().unwrap_or_default()
+    }
+
+    /// Value of the property `prop` of the device node.
+    pub fn property(&self, prop: &str) -> Option<&[u8]> {
+        self.node
+            .properties
+            .iter()
+            .find(|(name, _)| name == prop)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Add the device node to `fdt`. The properties in `props` replace the ones of the
+    /// template, and the phandle of the template is dropped as it may conflict with the
+    /// ones allocated by the machine.
+    ///
+    /// # Arguments
+    ///
+    /// * `fdt` - The FdtBuilder to be filled.
+    /// * `unit_address` - Unit address of the node, which is usually the base of the first region.
+    /// * `props` - Properties generated by the machine, such as `reg` and `interrupts`.
+    pub fn build(
+        &self,
+        fdt: &mut FdtBuilder,
+        unit_address: u64,
+        props: &[(&str, Vec<u8>)],
+    ) -> Result<()> {
+        let node_dep = fdt.begin_node(&format!("{}@{:x}", self.name(), unit_address))?;
+        for (name, value) in self.node.properties.iter() {
+            if name == "phandle"
+                || name == "linux,phandle"
+                || props.iter().any(|(prop, _)| prop == name)
+            {
+                continue;
+            }
+            fdt.set_property(name, value)?;
+        }
+        for (name, value) in props.iter() {
+            fdt.set_property(name, value)?;
+        }
+        for subnode in self.node.subnodes.iter() {
+            subnode.build(fdt)?;
+        }
+        fdt.end_node(node_dep)
+    }
+}
+
 /// Trait for devices to be added to the Flattened Device Tree.
 #[allow(clippy::upper_case_acronyms)]
 pub trait CompileFDT {
@@ -493,4 +770,127 @@ mod tests {
         ];
         assert!(fdt_builder.add_mem_reserve(&mem_reservations).is_err());
     }
+
+    #[test]
+    fn test_merge_fdt() {
+        let mut base = FdtBuilder::new();
+        let root_node = base.begin_node("").unwrap();
+        base.set_property_string("compatible", "linux,dummy-virt")
+            .unwrap();
+        let chosen_node = base.begin_node("chosen").unwrap();
+        base.set_property_string("bootargs", "console=ttyAMA0")
+            .unwrap();
+        base.end_node(chosen_node).unwrap();
+        base.end_node(root_node).unwrap();
+        base.set_boot_cpuid_phys(1);
+        let base = base.finish().unwrap();
+
+        let mut overlay = FdtBuilder::new();
+        overlay
+            .add_mem_reserve(&[FdtReserveEntry {
+                address: 0x4000_0000,
+                size: 0x1000,
+            }])
+            .unwrap();
+        let root_node = overlay.begin_node("").unwrap();
+        let chosen_node = overlay.begin_node("chosen").unwrap();
+        overlay
+            .set_property_string("bootargs", "console=hvc0")
+            .unwrap();
+        overlay
+            .set_property_u32("linux,initrd-start", 0x100)
+            .unwrap();
+        overlay.end_node(chosen_node).unwrap();
+        let led_node = overlay.begin_node("led").unwrap();
+        overlay.set_property_string("label", "heartbeat").unwrap();
+        overlay.end_node(led_node).unwrap();
+        overlay.end_node(root_node).unwrap();
+        let overlay = overlay.finish().unwrap();
+
+        let merged = FdtBlob::parse(&merge_fdt(&base, &overlay).unwrap()).unwrap();
+        assert_eq!(merged.boot_cpuid_phys, 1);
+        assert_eq!(merged.mem_reserve.len(), 1);
+        assert_eq!(merged.mem_reserve[0].address, 0x4000_0000);
+        assert_eq!(merged.root.properties.len(), 1);
+        assert_eq!(merged.root.subnodes.len(), 2);
+        let chosen = &merged.root.subnodes[0];
+        assert_eq!(chosen.name, "chosen");
+        assert_eq!(chosen.properties[0].0, "bootargs");
+        assert_eq!(chosen.properties[0].1, b"console=hvc0\0".to_vec());
+        assert_eq!(chosen.properties[1].1, 0x100_u32.to_be_bytes().to_vec());
+        assert_eq!(merged.root.subnodes[1].name, "led");
+
+        assert!(merge_fdt(&base, &overlay[..FDT_HEADER_SIZE]).is_err());
+        assert!(merge_fdt(&base, &[0_u8; FDT_HEADER_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_fdt_node_template() {
+        let mut overlay = FdtBuilder::new();
+        let root_node = overlay.begin_node("").unwrap();
+        let fragment_node = overlay.begin_node("fragment@0").unwrap();
+        overlay.set_property_string("target-path", "/").unwrap();
+        let overlay_node = overlay.begin_node("__overlay__").unwrap();
+        let eth_node = overlay.begin_node("ethernet@fff51000").unwrap();
+        overlay
+            .set_property_string("compatible", "calxeda,hb-xgmac")
+            .unwrap();
+        overlay.set_property_array_u32("reg", &[0, 0x1000]).unwrap();
+        overlay.set_property_u32("phandle", 0x8).unwrap();
+        let mdio_node = overlay.begin_node("mdio").unwrap();
+        overlay.end_node(mdio_node).unwrap();
+        overlay.end_node(eth_node).unwrap();
+        overlay.end_node(overlay_node).unwrap();
+        overlay.end_node(fragment_node).unwrap();
+        let symbols_node = overlay.begin_node("__symbols__").unwrap();
+        overlay.end_node(symbols_node).unwrap();
+        overlay.end_node(root_node).unwrap();
+        let overlay = overlay.finish().unwrap();
+
+        let template = FdtNodeTemplate::from_dtb(&overlay).unwrap();
+        assert_eq!(template.name(), "ethernet");
+        assert_eq!(
+            template.property("compatible"),
+            Some(b"calxeda,hb-xgmac\0".as_slice())
+        );
+
+        let mut fdt = FdtBuilder::new();
+        let root_node = fdt.begin_node("").unwrap();
+        template
+            .build(
+                &mut fdt,
+                0xa00_0000,
+                &[(
+                    "reg",
+                    [0_u32, 0xa00_0000, 0, 0x1000]
+                        .iter()
+                        .flat_map(|v| v.to_be_bytes())
+                        .collect(),
+                )],
+            )
+            .unwrap();
+        fdt.end_node(root_node).unwrap();
+        let blob = FdtBlob::parse(&fdt.finish().unwrap()).unwrap();
+        let node = &blob.root.subnodes[0];
+        assert_eq!(node.name, "ethernet@a000000");
+        assert_eq!(node.properties.len(), 2);
+        assert_eq!(node.properties[0].0, "compatible");
+        assert_eq!(node.properties[1].0, "reg");
+        assert_eq!(node.properties[1].1.len(), 16);
+        assert_eq!(node.subnodes[0].name, "mdio");
+
+        // Plain dtb with the device node under the root is accepted as well.
+        let mut plain = FdtBuilder::new();
+        let root_node = plain.begin_node("").unwrap();
+        let led_node = plain.begin_node("led").unwrap();
+        plain.end_node(led_node).unwrap();
+        plain.end_node(root_node).unwrap();
+        let template = FdtNodeTemplate::from_dtb(&plain.finish().unwrap()).unwrap();
+        assert_eq!(template.name(), "led");
+
+        let mut empty = FdtBuilder::new();
+        let root_node = empty.begin_node("").unwrap();
+        empty.end_node(root_node).unwrap();
+        assert!(FdtNodeTemplate::from_dtb(&empty.finish().unwrap()).is_err());
+    }
 }
@@ -11,14 +11,23 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::{remove_file, File, OpenOptions};
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
 use anyhow::{bail, Context, Ok, Result};
+use vmm_sys_util::ioctl::ioctl_with_mut_ref;
+use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr};
 
 const MIN_FILE_ALIGN: u32 = 512;
 const MAX_FILE_ALIGN: u32 = 4096;
+
+const BLK_IOCTL_TYPE: u32 = 0x12;
+ioctl_io_nr!(BLKSSZGET, BLK_IOCTL_TYPE, 104);
+ioctl_io_nr!(BLKPBSZGET, BLK_IOCTL_TYPE, 123);
+
+/// Request the alignment of direct IO by statx, supported since Linux 6.1.
+const STATX_DIOALIGN: u32 = 0x2000;
 /// Permission to read
 const FILE_LOCK_READ: u64 = 0x01;
 /// Permission to write
@@ -60,11 +69,134 @@ fn is_io_aligned(file: &File, buf: u64, size: usize) -> bool {
     ret >= 0 || nix::errno::errno() != libc::EINVAL
 }
 
+/// The part of `struct statx` of kernel which is used to get the alignment of direct IO.
+#[repr(C)]
+struct StatxDio {
+    stx_mask: u32,
+    stx_blksize: u32,
+    unused: [u64; 18],
+    stx_dio_mem_align: u32,
+    stx_dio_offset_align: u32,
+    spare: [u64; 12],
+}
+
+/// Logical and physical block size of the host file in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSize {
+    /// The smallest unit the file can be addressed in.
+    pub logical: u32,
+    /// The smallest unit the file can be written in without read-modify-write.
+    pub physical: u32,
+}
+
+impl Default for BlockSize {
+    fn default() -> Self {
+        BlockSize {
+            logical: MIN_FILE_ALIGN,
+            physical: MIN_FILE_ALIGN,
+        }
+    }
+}
+
+fn is_block_device(file: &File) -> bool {
+    file.metadata()
+        .map_or(false, |meta| meta.file_type().is_block_device())
+}
+
+/// Get the block size of the host block device.
+fn get_blkdev_block_size(file: &File) -> Option<BlockSize> {
+    let mut logical: libc::c_int = 0;
+    // SAFETY: file is valid, and the kernel writes an int to logical.
+    if unsafe { ioctl_with_mut_ref(file, BLKSSZGET(), &mut logical) } < 0 {
+        return None;
+    }
+    let mut physical: libc::c_uint = 0;
+    // SAFETY: file is valid, and the kernel writes an unsigned int to physical.
+    if unsafe { ioctl_with_mut_ref(file, BLKPBSZGET(), &mut physical) } < 0 {
+        physical = logical as u32;
+    }
+    Some(BlockSize {
+        logical: logical as u32,
+        physical,
+    })
+}
+
+/// Get the alignment of offset and buffer of direct IO reported by kernel.
+fn get_dio_alignment(file: &File) -> Option<(u32, u32)> {
+    // SAFETY: `StatxDio` is plain old data, and all zero is valid for it.
+    let mut stx: StatxDio = unsafe { std::mem::zeroed() };
+    // SAFETY: file is valid, the path is an empty C string and stx is large enough
+    // to hold `struct statx`.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            file.as_raw_fd(),
+            b"\0".as_ptr(),
+            libc::AT_EMPTY_PATH,
+            STATX_DIOALIGN,
+            &mut stx as *mut StatxDio,
+        )
+    };
+    if ret < 0 || stx.stx_mask & STATX_DIOALIGN == 0 || stx.stx_dio_offset_align == 0 {
+        return None;
+    }
+    Some((stx.stx_dio_offset_align, stx.stx_dio_mem_align))
+}
+
+/// Get the block size of the host file which is reported to guest, so that the guest
+/// requests are aligned for direct IO, e.g. for the 4Kn disk.
+///
+/// # Arguments
+///
+/// * `file` - The opened file.
+/// * `req_align` - The alignment requirement of request of the file.
+pub fn get_file_block_size(file: &File, req_align: u32) -> BlockSize {
+    let valid =
+        |size: u32| size.is_power_of_two() && (MIN_FILE_ALIGN..=MAX_FILE_ALIGN).contains(&size);
+    if is_block_device(file) {
+        if let Some(size) = get_blkdev_block_size(file).filter(|size| valid(size.logical)) {
+            let physical = if valid(size.physical) && size.physical >= size.logical {
+                size.physical
+            } else {
+                size.logical
+            };
+            return BlockSize {
+                logical: size.logical,
+                physical,
+            };
+        }
+    }
+    let logical = if valid(req_align) {
+        req_align
+    } else {
+        MIN_FILE_ALIGN
+    };
+    BlockSize {
+        logical,
+        physical: logical,
+    }
+}
+
 pub fn get_file_alignment(file: &File, direct: bool) -> (u32, u32) {
     if !direct {
         return (1, 1);
     }
 
+    // The block size of block device is exactly the alignment requirement.
+    if is_block_device(file) {
+        if let Some(size) = get_blkdev_block_size(file) {
+            if size.logical.is_power_of_two() {
+                return (size.logical, size.logical);
+            }
+        }
+    }
+    if let Some(align) = get_dio_alignment(file) {
+        if align.0.is_power_of_two() && align.1.is_power_of_two() {
+            return align;
+        }
+    }
+
+    // Guess the alignment by reading the file.
     let mut req_align = 0;
     let mut buf_align = 0;
     // SAFETY: we allocate aligned memory and free it later.
@@ -14,6 +14,7 @@ pub mod aio;
 pub mod arg_parser;
 pub mod bitmap;
 pub mod byte_code;
+pub mod cgroup;
 pub mod checksum;
 pub mod clock;
 pub mod daemonize;
@@ -26,6 +27,7 @@ pub mod leak_bucket;
 pub mod link_list;
 pub mod logger;
 pub mod loop_context;
+pub mod metrics;
 pub mod num_ops;
 pub mod offsetof;
 #[cfg(feature = "pixman")]
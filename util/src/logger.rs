@@ -11,15 +11,17 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::num::Wrapping;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
-use std::sync::Mutex;
-use std::time::UNIX_EPOCH;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::{Level, LevelFilter, Log, Metadata, Record};
+use once_cell::sync::{Lazy, OnceCell};
 
 use crate::time::{get_format_time, gettime};
 use crate::unix::gettid;
@@ -28,6 +30,196 @@ use crate::unix::gettid;
 const LOG_ROTATE_SIZE_MAX: usize = 100 * 1024 * 1024;
 // Logs are retained for seven days.
 const LOG_ROTATE_COUNT_MAX: u32 = 7;
+// The buffered records are written to the log file at least once a second.
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Subsystems which the log records are tagged with, each has its own log level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogSubsystem {
+    General = 0,
+    Block,
+    Net,
+    Usb,
+    Migration,
+}
+
+pub const LOG_SUBSYSTEMS: [LogSubsystem; 5] = [
+    LogSubsystem::General,
+    LogSubsystem::Block,
+    LogSubsystem::Net,
+    LogSubsystem::Usb,
+    LogSubsystem::Migration,
+];
+
+// Module path prefixes of the subsystems, the records of other modules belong to `General`.
+const SUBSYSTEM_MODULES: [(&str, LogSubsystem); 13] = [
+    ("block_backend", LogSubsystem::Block),
+    ("virtio::device::block", LogSubsystem::Block),
+    ("virtio::device::scsi_cntlr", LogSubsystem::Block),
+    ("virtio::vhost::user::block", LogSubsystem::Block),
+    ("devices::scsi", LogSubsystem::Block),
+    ("devices::ahci", LogSubsystem::Block),
+    ("devices::nvme", LogSubsystem::Block),
+    ("virtio::device::net", LogSubsystem::Net),
+    ("virtio::vhost::kernel::net", LogSubsystem::Net),
+    ("virtio::vhost::user::net", LogSubsystem::Net),
+    ("devices::net", LogSubsystem::Net),
+    ("devices::usb", LogSubsystem::Usb),
+    ("migration", LogSubsystem::Migration),
+];
+
+impl LogSubsystem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogSubsystem::General => "general",
+            LogSubsystem::Block => "block",
+            LogSubsystem::Net => "net",
+            LogSubsystem::Usb => "usb",
+            LogSubsystem::Migration => "migration",
+        }
+    }
+
+    fn from_target(target: &str) -> Self {
+        for (module, subsystem) in SUBSYSTEM_MODULES.iter() {
+            if let Some(rest) = target.strip_prefix(module) {
+                if rest.is_empty() || rest.starts_with("::") {
+                    return *subsystem;
+                }
+            }
+        }
+        LogSubsystem::General
+    }
+}
+
+impl FromStr for LogSubsystem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        LOG_SUBSYSTEMS
+            .iter()
+            .find(|subsystem| subsystem.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown log subsystem {}", s))
+    }
+}
+
+/// Output format of the log records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("Unknown log format {}, it should be text or json", s),
+        }
+    }
+}
+
+struct LogConfig {
+    levels: [LevelFilter; LOG_SUBSYSTEMS.len()],
+    format: LogFormat,
+}
+
+static LOG_CONFIG: Lazy<RwLock<LogConfig>> = Lazy::new(|| {
+    RwLock::new(LogConfig {
+        levels: [LevelFilter::Info; LOG_SUBSYSTEMS.len()],
+        format: LogFormat::Text,
+    })
+});
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(level).map_err(|_| anyhow!("Unknown log level {}", level))
+}
+
+fn update_max_level(config: &LogConfig) {
+    let max_level = config
+        .levels
+        .iter()
+        .max()
+        .copied()
+        .unwrap_or(LevelFilter::Off);
+    log::set_max_level(max_level);
+}
+
+/// Set the log level of `subsystem`, or all the subsystems if it's `None`.
+///
+/// # Arguments
+///
+/// * `subsystem` - Name of the subsystem, such as `block`.
+/// * `level` - One of `off`, `error`, `warn`, `info`, `debug` and `trace`.
+pub fn set_log_level(subsystem: Option<&str>, level: &str) -> Result<()> {
+    let level = parse_level(level)?;
+    let subsystem = subsystem.map(LogSubsystem::from_str).transpose()?;
+
+    let mut config = LOG_CONFIG.write().unwrap();
+    match subsystem {
+        Some(s) => config.levels[s as usize] = level,
+        None => config.levels = [level; LOG_SUBSYSTEMS.len()],
+    }
+    update_max_level(&config);
+    Ok(())
+}
+
+/// Set the log levels by spec like `info,block=debug,usb=trace`, the level without
+/// subsystem applies to all the subsystems. The spec is checked before applying.
+pub fn set_log_levels(spec: &str) -> Result<()> {
+    let mut entries = Vec::new();
+    for entry in spec.split(',').filter(|e| !e.is_empty()) {
+        let (subsystem, level) = match entry.split_once('=') {
+            Some((subsystem, level)) => (Some(subsystem.parse::<LogSubsystem>()?), level),
+            None => (None, entry),
+        };
+        entries.push((subsystem, parse_level(level)?));
+    }
+
+    let mut config = LOG_CONFIG.write().unwrap();
+    for (subsystem, level) in entries {
+        match subsystem {
+            Some(s) => config.levels[s as usize] = level,
+            None => config.levels = [level; LOG_SUBSYSTEMS.len()],
+        }
+    }
+    update_max_level(&config);
+    Ok(())
+}
+
+/// Set the output format of the log records, `text` or `json`.
+pub fn set_log_format(format: &str) -> Result<()> {
+    LOG_CONFIG.write().unwrap().format = format.parse::<LogFormat>()?;
+    Ok(())
+}
+
+/// Get the log levels of all the subsystems.
+pub fn query_log_levels() -> Vec<(&'static str, String)> {
+    let config = LOG_CONFIG.read().unwrap();
+    LOG_SUBSYSTEMS
+        .iter()
+        .map(|s| (s.name(), config.levels[*s as usize].as_str().to_lowercase()))
+        .collect()
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
 
 fn format_now() -> String {
     let (sec, nsec) = gettime();
@@ -50,9 +242,63 @@ struct FileRotate {
     path: String,
     current_size: Wrapping<usize>,
     create_day: i32,
+    last_flush: Instant,
 }
 
+/// The log file shared by the logger and the QMP commands.
+static LOG_FILE: OnceCell<Arc<Mutex<FileRotate>>> = OnceCell::new();
+
 impl FileRotate {
+    fn new(handler: Box<dyn Write + Send>, path: String) -> Result<Self> {
+        let (current_size, create_day) = if path.is_empty() {
+            (Wrapping(0), 0)
+        } else {
+            let metadata = File::open(&path)?.metadata()?;
+            let mod_time = metadata.modified()?;
+            let sec = mod_time.duration_since(UNIX_EPOCH)?.as_secs();
+            (
+                Wrapping(metadata.len() as usize),
+                get_format_time(sec as i64)[2],
+            )
+        };
+        Ok(FileRotate {
+            handler,
+            path,
+            current_size,
+            create_day,
+            last_flush: Instant::now(),
+        })
+    }
+
+    fn write_record(&mut self, record: &[u8], level: Level) -> Result<()> {
+        self.handler.write_all(record)?;
+        // Warnings and errors are written out immediately.
+        if level <= Level::Warn || self.last_flush.elapsed() >= LOG_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        self.handler
+            .flush()
+            .with_context(|| "Failed to flush log file")
+    }
+
+    /// Reopen the log file by path, the records are written to the new file if the
+    /// old one has been moved away.
+    fn reopen(&mut self) -> Result<()> {
+        if self.path.is_empty() {
+            bail!("Logs are output to stderr, there is no log file to reopen");
+        }
+        self.flush()?;
+        let handler = Box::new(BufWriter::new(open_log_file(&self.path)?));
+        let new_file = FileRotate::new(handler, self.path.clone())?;
+        *self = new_file;
+        Ok(())
+    }
+
     fn rotate_file(&mut self, size_inc: usize) -> Result<()> {
         if self.path.is_empty() {
             return Ok(());
@@ -91,7 +337,8 @@ impl FileRotate {
         }
 
         // Update log file.
-        self.handler = Box::new(open_log_file(&self.path)?);
+        self.flush()?;
+        self.handler = Box::new(BufWriter::new(open_log_file(&self.path)?));
         self.current_size = Wrapping(0);
         self.create_day = today;
         Ok(())
@@ -100,13 +347,13 @@ impl FileRotate {
 
 /// Format like "%year-%mon-%dayT%hour:%min:%sec.%nsec
 struct VmLogger {
-    rotate: Mutex<FileRotate>,
-    level: Level,
+    rotate: Arc<Mutex<FileRotate>>,
 }
 
 impl Log for VmLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        let subsystem = LogSubsystem::from_target(metadata.target());
+        metadata.level() <= LOG_CONFIG.read().unwrap().levels[subsystem as usize]
     }
 
     fn log(&self, record: &Record) {
@@ -116,20 +363,35 @@ impl Log for VmLogger {
 
         let pid = unsafe { libc::getpid() };
         let tid = gettid();
-        let formatmsg = format_args!(
-            "{:<5}: [{}][{}][{}: {}]:{}: {}\n",
-            format_now(),
-            pid,
-            tid,
-            record.file().unwrap_or(""),
-            record.line().unwrap_or(0),
-            record.level(),
-            record.args()
-        )
-        .to_string();
+        let subsystem = LogSubsystem::from_target(record.target());
+        let format = LOG_CONFIG.read().unwrap().format;
+        let formatmsg = match format {
+            LogFormat::Text => format!(
+                "{:<5}: [{}][{}][{}: {}]:{}: [{}] {}\n",
+                format_now(),
+                pid,
+                tid,
+                record.file().unwrap_or(""),
+                record.line().unwrap_or(0),
+                record.level(),
+                subsystem.name(),
+                record.args()
+            ),
+            LogFormat::Json => format!(
+                "{{\"time\":\"{}\",\"pid\":{},\"tid\":{},\"file\":\"{}\",\"line\":{},\"level\":\"{}\",\"subsystem\":\"{}\",\"message\":\"{}\"}}\n",
+                format_now(),
+                pid,
+                tid,
+                json_escape(record.file().unwrap_or("")),
+                record.line().unwrap_or(0),
+                record.level(),
+                subsystem.name(),
+                json_escape(&record.args().to_string())
+            ),
+        };
 
         let mut rotate = self.rotate.lock().unwrap();
-        if let Err(e) = rotate.handler.write_all(formatmsg.as_bytes()) {
+        if let Err(e) = rotate.write_record(formatmsg.as_bytes(), record.level()) {
             println!("Failed to log message {:?}", e);
             return;
         }
@@ -138,49 +400,41 @@ impl Log for VmLogger {
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Err(e) = self.rotate.lock().unwrap().flush() {
+            println!("{:?}", e);
+        }
+    }
 }
 
 fn init_vm_logger(
-    level: Level,
+    level: LevelFilter,
     logfile: Box<dyn Write + Send>,
     logfile_path: String,
 ) -> Result<()> {
-    let current_size;
-    let create_day;
-    if logfile_path.is_empty() {
-        current_size = Wrapping(0);
-        create_day = 0;
-    } else {
-        let metadata = File::open(&logfile_path)?.metadata()?;
-        current_size = Wrapping(metadata.len() as usize);
-        let mod_time = metadata.modified()?;
-        let sec = mod_time.duration_since(UNIX_EPOCH)?.as_secs();
-        create_day = get_format_time(sec as i64)[2];
-    };
-    let rotate = Mutex::new(FileRotate {
-        handler: logfile,
-        path: logfile_path,
-        current_size,
-        create_day,
-    });
-
-    let logger = VmLogger { rotate, level };
-    log::set_boxed_logger(Box::new(logger)).map(|()| log::set_max_level(LevelFilter::Trace))?;
+    let rotate = Arc::new(Mutex::new(FileRotate::new(logfile, logfile_path)?));
+    if LOG_FILE.set(rotate.clone()).is_err() {
+        bail!("Logger has been initialized");
+    }
+
+    let mut config = LOG_CONFIG.write().unwrap();
+    config.levels = [level; LOG_SUBSYSTEMS.len()];
+    let logger = VmLogger { rotate };
+    log::set_boxed_logger(Box::new(logger)).map(|()| update_max_level(&config))?;
     Ok(())
 }
 
 fn init_logger_with_env(logfile: Box<dyn Write + Send>, logfile_path: String) -> Result<()> {
     let level = match std::env::var("STRATOVIRT_LOG_LEVEL") {
         Ok(l) => match l.to_lowercase().as_str() {
-            "error" => Level::Error,
-            "warn" => Level::Warn,
-            "info" => Level::Info,
-            "debug" => Level::Debug,
-            "trace" => Level::Trace,
-            _ => Level::Info,
+            "error" => LevelFilter::Error,
+            "warn" => LevelFilter::Warn,
+            "info" => LevelFilter::Info,
+            "debug" => LevelFilter::Debug,
+            "trace" => LevelFilter::Trace,
+            _ => LevelFilter::Info,
         },
-        _ => Level::Info,
+        _ => LevelFilter::Info,
     };
 
     init_vm_logger(level, logfile, logfile_path)?;
@@ -202,8 +456,131 @@ pub fn init_log(path: String) -> Result<()> {
     let logfile: Box<dyn Write + Send> = if path.is_empty() {
         Box::new(std::io::stderr())
     } else {
-        Box::new(open_log_file(&path)?)
+        Box::new(BufWriter::new(open_log_file(&path)?))
     };
     init_logger_with_env(logfile, path.clone())
         .with_context(|| format!("Failed to init logger: {}", path))
 }
+
+/// Reopen the log file, used by logrotate after the log file is moved away.
+pub fn reopen_log_file() -> Result<()> {
+    match LOG_FILE.get() {
+        Some(rotate) => rotate.lock().unwrap().reopen(),
+        None => bail!("Logger is not initialized"),
+    }
+}
+
+/// Get the path and the size of the log file, the path is empty if logs are output
+/// to stderr.
+pub fn query_log_file() -> (String, usize) {
+    match LOG_FILE.get() {
+        Some(rotate) => {
+            let locked_rotate = rotate.lock().unwrap();
+            (locked_rotate.path.clone(), locked_rotate.current_size.0)
+        }
+        None => (String::new(), 0),
+    }
+}
+
+/// Write out the buffered records before exit. It doesn't wait for the lock, as it
+/// may be called in signal handler.
+pub fn flush_log() {
+    if let Some(rotate) = LOG_FILE.get() {
+        if let Ok(mut locked_rotate) = rotate.try_lock() {
+            let _ = locked_rotate.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_subsystem() {
+        assert_eq!(
+            LogSubsystem::from_target("block_backend::qcow2"),
+            LogSubsystem::Block
+        );
+        assert_eq!(
+            LogSubsystem::from_target("virtio::device::net"),
+            LogSubsystem::Net
+        );
+        assert_eq!(
+            LogSubsystem::from_target("virtio::device::net_filter"),
+            LogSubsystem::General
+        );
+        assert_eq!(
+            LogSubsystem::from_target("devices::usb::storage"),
+            LogSubsystem::Usb
+        );
+        assert_eq!(
+            LogSubsystem::from_target("migration::manager"),
+            LogSubsystem::Migration
+        );
+        assert_eq!(LogSubsystem::from_target("machine"), LogSubsystem::General);
+        assert_eq!("usb".parse::<LogSubsystem>().unwrap(), LogSubsystem::Usb);
+        assert!("gpu".parse::<LogSubsystem>().is_err());
+    }
+
+    #[test]
+    fn test_set_log_levels() {
+        assert!(set_log_levels("info,block=debug,usb=trace").is_ok());
+        let levels = query_log_levels();
+        assert_eq!(
+            levels[LogSubsystem::General as usize],
+            ("general", "info".to_string())
+        );
+        assert_eq!(
+            levels[LogSubsystem::Block as usize],
+            ("block", "debug".to_string())
+        );
+        assert_eq!(
+            levels[LogSubsystem::Usb as usize],
+            ("usb", "trace".to_string())
+        );
+
+        assert!(set_log_level(Some("usb"), "off").is_ok());
+        assert_eq!(query_log_levels()[LogSubsystem::Usb as usize].1, "off");
+        assert!(set_log_level(Some("gpu"), "info").is_err());
+        assert!(set_log_level(None, "verbose").is_err());
+        // Invalid spec doesn't change any level.
+        assert!(set_log_levels("warn,net=loud").is_err());
+        assert_eq!(query_log_levels()[LogSubsystem::General as usize].1, "info");
+
+        assert!(set_log_format("json").is_ok());
+        assert!(set_log_format("xml").is_err());
+        assert_eq!(json_escape("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+        assert!(set_log_format("text").is_ok());
+    }
+
+    #[test]
+    fn test_log_file_reopen() {
+        let path = "/tmp/stratovirt_test_logger.log".to_string();
+        let rotated = format!("{}.1", path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&rotated);
+
+        let handler = Box::new(BufWriter::new(open_log_file(&path).unwrap()));
+        let mut rotate = FileRotate::new(handler, path.clone()).unwrap();
+        // Info records are buffered, and errors are written out immediately.
+        rotate.write_record(b"first\n", Level::Info).unwrap();
+        rotate.write_record(b"second\n", Level::Error).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first\nsecond\n");
+
+        // The log file is moved away by logrotate.
+        std::fs::rename(&path, &rotated).unwrap();
+        rotate.write_record(b"third\n", Level::Info).unwrap();
+        rotate.reopen().unwrap();
+        assert_eq!(rotate.current_size.0, 0);
+        rotate.write_record(b"fourth\n", Level::Warn).unwrap();
+        assert_eq!(std::fs::read(&rotated).unwrap(), b"first\nsecond\nthird\n");
+        assert_eq!(std::fs::read(&path).unwrap(), b"fourth\n");
+
+        let mut stderr = FileRotate::new(Box::new(std::io::stderr()), String::new()).unwrap();
+        assert!(stderr.reopen().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+}
@@ -27,15 +27,34 @@ use nix::{
     poll::{ppoll, PollFd, PollFlags},
     sys::time::TimeSpec,
 };
+use once_cell::sync::Lazy;
 use vmm_sys_util::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::clock::{get_current_time, ClockState};
+use crate::metrics::{register_metric, Metric, MetricType};
 use crate::UtilError;
 
 const READY_EVENT_MAX: usize = 256;
 const AIO_PRFETCH_CYCLE_TIME: usize = 100;
 
+static LOOP_POLLS: Lazy<Arc<Metric>> = Lazy::new(|| {
+    register_metric(
+        "stratovirt_event_loop_polls_total",
+        "Number of polls which have ready events in all the event loops.",
+        MetricType::Counter,
+        &[],
+    )
+});
+static LOOP_HANDLE_TIME: Lazy<Arc<Metric>> = Lazy::new(|| {
+    register_metric(
+        "stratovirt_event_loop_handle_microseconds_total",
+        "Time spent in handling the ready events in all the event loops.",
+        MetricType::Counter,
+        &[],
+    )
+});
+
 #[derive(Debug)]
 pub enum NotifierOperation {
     /// Add a file descriptor to the event table, and bind a notifier to
@@ -609,6 +628,7 @@ impl EventLoopContext {
             self.kick_me.store(false, Ordering::SeqCst);
         }
 
+        let handle_start = Instant::now();
         for i in 0..ev_count {
             // SAFETY: elements in self.events_map never get released in other functions
             let event = unsafe {
@@ -633,6 +653,10 @@ impl EventLoopContext {
                 error!("update event failed: {}", e);
             }
         }
+        if ev_count > 0 {
+            LOOP_POLLS.inc();
+            LOOP_HANDLE_TIME.add(handle_start.elapsed().as_micros() as u64);
+        }
 
         self.run_timers();
         self.clear_gc();
@@ -0,0 +1,183 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Lightweight metrics which are updated in the hot paths by atomics, and rendered
+//! in Prometheus text format on request.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use once_cell::sync::Lazy;
+
+static METRICS: Lazy<Mutex<Vec<MetricEntry>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricType {
+    /// Monotonically increasing value.
+    Counter,
+    /// Value which can go up and down.
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Metric {
+    value: AtomicU64,
+}
+
+impl Metric {
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+struct MetricEntry {
+    name: &'static str,
+    help: &'static str,
+    metric_type: MetricType,
+    /// Rendered labels, such as `{device="blk0"}`.
+    labels: String,
+    /// The metric is unregistered when the owner drops it, such as the hot-unplugged device.
+    metric: Weak<Metric>,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Register a metric, the caller holds the returned handle to update it.
+///
+/// # Arguments
+///
+/// * `name` - Metric name, such as `stratovirt_vcpu_exits_total`.
+/// * `help` - Description of the metric.
+/// * `metric_type` - Counter or gauge.
+/// * `labels` - Label pairs to distinguish the metrics with the same name.
+pub fn register_metric(
+    name: &'static str,
+    help: &'static str,
+    metric_type: MetricType,
+    labels: &[(&str, &str)],
+) -> Arc<Metric> {
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        let pairs: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+            .collect();
+        format!("{{{}}}", pairs.join(","))
+    };
+    let metric = Arc::new(Metric::default());
+    METRICS.lock().unwrap().push(MetricEntry {
+        name,
+        help,
+        metric_type,
+        labels,
+        metric: Arc::downgrade(&metric),
+    });
+    metric
+}
+
+/// Render all the registered metrics in Prometheus text format.
+pub fn render_metrics() -> String {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.retain(|entry| entry.metric.strong_count() > 0);
+
+    // Samples of the same metric must be grouped together.
+    let mut names: Vec<&'static str> = Vec::new();
+    for entry in metrics.iter() {
+        if !names.contains(&entry.name) {
+            names.push(entry.name);
+        }
+    }
+
+    let mut output = String::new();
+    for name in names {
+        let mut header = false;
+        for entry in metrics.iter().filter(|entry| entry.name == name) {
+            let value = match entry.metric.upgrade() {
+                Some(metric) => metric.get(),
+                None => continue,
+            };
+            if !header {
+                let _ = writeln!(output, "# HELP {} {}", name, entry.help);
+                let _ = writeln!(output, "# TYPE {} {}", name, entry.metric_type.as_str());
+                header = true;
+            }
+            let _ = writeln!(output, "{}{} {}", name, entry.labels, value);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let exits = register_metric(
+            "test_exits_total",
+            "Test exits.",
+            MetricType::Counter,
+            &[("reason", "io")],
+        );
+        let size = register_metric("test_size_bytes", "Test size.", MetricType::Gauge, &[]);
+        let exits_mmio = register_metric(
+            "test_exits_total",
+            "Test exits.",
+            MetricType::Counter,
+            &[("reason", "mm\"io")],
+        );
+        exits.inc();
+        exits.add(2);
+        exits_mmio.inc();
+        size.set(4096);
+
+        let output = render_metrics();
+        assert!(output.contains(
+            "# HELP test_exits_total Test exits.\n# TYPE test_exits_total counter\n\
+             test_exits_total{reason=\"io\"} 3\ntest_exits_total{reason=\"mm\\\"io\"} 1\n"
+        ));
+        assert!(output.contains("# TYPE test_size_bytes gauge\ntest_size_bytes 4096\n"));
+
+        drop(exits_mmio);
+        let output = render_metrics();
+        assert!(output.contains("test_exits_total{reason=\"io\"} 3\n"));
+        assert!(!output.contains("mm\\\"io"));
+    }
+}
@@ -41,6 +41,9 @@ const IFNAME_SIZE: usize = 16;
 ioctl_iow_nr!(TUNSETIFF, 84, 202, ::std::os::raw::c_int);
 ioctl_ior_nr!(TUNGETFEATURES, 84, 207, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETOFFLOAD, 84, 208, ::std::os::raw::c_int);
+ioctl_ior_nr!(TUNGETIFF, 84, 210, ::std::os::raw::c_uint);
+ioctl_iow_nr!(TUNSETSNDBUF, 84, 212, ::std::os::raw::c_int);
+ioctl_ior_nr!(TUNGETVNETHDRSZ, 84, 215, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, 84, 216, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETQUEUE, 84, 217, ::std::os::raw::c_int);
 
@@ -99,6 +102,7 @@ impl Tap {
                 libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
                 File::from_raw_fd(fd)
             };
+            check_tap_flags(&file, queue_pairs)?;
         } else {
             return Err(anyhow!(
                 "Open tap failed, unsupported operation, error is {}",
@@ -141,6 +145,41 @@ impl Tap {
             return Err(anyhow!("ioctl TUNSETVNETHDRSZ failed.".to_string()));
         }
 
+        // The vnet header length is shared by all the users of the tap, such as vhost-net,
+        // read it back to make sure that the kernel uses the same length as us.
+        let hdr_size = self.get_hdr_size()?;
+        if hdr_size != len {
+            bail!(
+                "Tap vnet header length mismatch, expect {} but kernel uses {}",
+                len,
+                hdr_size
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn get_hdr_size(&self) -> Result<u32> {
+        let mut len: u32 = 0;
+        let ret = unsafe { ioctl_with_mut_ref(self.file.as_ref(), TUNGETVNETHDRSZ(), &mut len) };
+        if ret < 0 {
+            return Err(anyhow!("ioctl TUNGETVNETHDRSZ failed.".to_string()));
+        }
+
+        Ok(len)
+    }
+
+    /// Set the send buffer size of tap in bytes, which limits the in-flight packets
+    /// transmitted by the guest.
+    pub fn set_sndbuf(&self, size: i32) -> Result<()> {
+        let ret = unsafe { ioctl_with_ref(self.file.as_ref(), TUNSETSNDBUF(), &size) };
+        if ret < 0 {
+            return Err(anyhow!(
+                "ioctl TUNSETSNDBUF failed, error is {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
         Ok(())
     }
 
@@ -189,3 +228,39 @@ impl Tap {
         self.file.as_raw_fd()
     }
 }
+
+/// Check the flags of the tap opened by upper level, the vnet header is required by
+/// virtio-net, and multiqueue tap is required if there are more than one queue pairs.
+fn check_tap_flags(file: &File, queue_pairs: u16) -> Result<()> {
+    let mut if_req = IfReq {
+        ifr_name: [0_u8; IFNAME_SIZE],
+        ifr_flags: 0,
+    };
+    let ret = unsafe { ioctl_with_mut_ref(file, TUNGETIFF(), &mut if_req) };
+    if ret < 0 {
+        bail!(
+            "Failed to get tap ifr flags of fd {}, error is {}",
+            file.as_raw_fd(),
+            std::io::Error::last_os_error()
+        );
+    }
+
+    if if_req.ifr_flags & IFF_TAP == 0 {
+        bail!("Fd {} is not a tap device", file.as_raw_fd());
+    }
+    if if_req.ifr_flags & IFF_VNET_HDR == 0 {
+        bail!(
+            "Tap fd {} is opened without IFF_VNET_HDR, which is required by virtio-net",
+            file.as_raw_fd()
+        );
+    }
+    if queue_pairs > 1 && if_req.ifr_flags & IFF_MULTI_QUEUE == 0 {
+        bail!(
+            "Tap fd {} is opened without IFF_MULTI_QUEUE, but {} queue pairs are required",
+            file.as_raw_fd(),
+            queue_pairs
+        );
+    }
+
+    Ok(())
+}
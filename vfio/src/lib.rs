@@ -14,6 +14,8 @@ pub mod error;
 
 mod vfio_dev;
 mod vfio_pci;
+#[cfg(target_arch = "aarch64")]
+mod vfio_platform;
 
 pub use error::VfioError;
 pub use vfio_dev::{
@@ -23,6 +25,8 @@ pub use vfio_dev::{
     VFIO_GROUP_SET_CONTAINER, VFIO_IOMMU_MAP_DMA, VFIO_IOMMU_UNMAP_DMA, VFIO_SET_IOMMU,
 };
 pub use vfio_pci::VfioPciDevice;
+#[cfg(target_arch = "aarch64")]
+pub use vfio_platform::VfioPlatformDevice;
 
 use std::collections::HashMap;
 use std::os::unix::io::RawFd;
@@ -448,10 +448,18 @@ impl VfioGroup {
 }
 
 pub struct VfioDevInfo {
+    pub num_regions: u32,
     pub num_irqs: u32,
     flags: u32,
 }
 
+impl VfioDevInfo {
+    /// Whether the device is a platform device rather than a PCI device.
+    pub fn is_platform(&self) -> bool {
+        self.flags & vfio::VFIO_DEVICE_FLAGS_PLATFORM != 0
+    }
+}
+
 /// Vfio device includes the group and container it belongs to, I/O regions and interrupt
 /// notifications info.
 pub struct VfioDevice {
@@ -497,8 +505,8 @@ struct VfioRegionWithCap {
 
 #[allow(dead_code)]
 pub struct VfioIrq {
-    count: u32,
-    flags: u32,
+    pub(crate) count: u32,
+    pub(crate) flags: u32,
     index: u32,
 }
 
@@ -605,18 +613,27 @@ impl VfioDevice {
 
         // Safe as device is the owner of file, and we will verify the result is valid.
         let ret = unsafe { ioctl_with_mut_ref(device, VFIO_DEVICE_GET_INFO(), &mut dev_info) };
-        if ret < 0
-            || (dev_info.flags & vfio::VFIO_DEVICE_FLAGS_PCI) == 0
-            || dev_info.num_regions < vfio::VFIO_PCI_CONFIG_REGION_INDEX + 1
-            || dev_info.num_irqs < vfio::VFIO_PCI_MSIX_IRQ_INDEX + 1
-        {
+        if ret < 0 {
             return Err(anyhow!(VfioError::VfioIoctl(
                 "VFIO_DEVICE_GET_INFO".to_string(),
                 std::io::Error::last_os_error(),
             )));
         }
+        if dev_info.flags & vfio::VFIO_DEVICE_FLAGS_PLATFORM == 0
+            && ((dev_info.flags & vfio::VFIO_DEVICE_FLAGS_PCI) == 0
+                || dev_info.num_regions < vfio::VFIO_PCI_CONFIG_REGION_INDEX + 1
+                || dev_info.num_irqs < vfio::VFIO_PCI_MSIX_IRQ_INDEX + 1)
+        {
+            bail!(
+                "Unsupported vfio device, flags 0x{:x}, {} regions, {} irqs",
+                dev_info.flags,
+                dev_info.num_regions,
+                dev_info.num_irqs
+            );
+        }
 
         Ok(VfioDevInfo {
+            num_regions: dev_info.num_regions,
             num_irqs: dev_info.num_irqs,
             flags: dev_info.flags,
         })
@@ -699,8 +716,17 @@ impl VfioDevice {
     }
 
     pub fn get_regions_info(&self) -> Result<Vec<VfioRegion>> {
+        self.get_regions_info_in(vfio::VFIO_PCI_BAR0_REGION_INDEX..vfio::VFIO_PCI_ROM_REGION_INDEX)
+    }
+
+    /// Get the information of all the regions of a platform device.
+    pub fn get_platform_regions_info(&self) -> Result<Vec<VfioRegion>> {
+        self.get_regions_info_in(0..self.dev_info.num_regions)
+    }
+
+    fn get_regions_info_in(&self, indexes: std::ops::Range<u32>) -> Result<Vec<VfioRegion>> {
         let mut regions: Vec<VfioRegion> = Vec::new();
-        for index in vfio::VFIO_PCI_BAR0_REGION_INDEX..vfio::VFIO_PCI_ROM_REGION_INDEX {
+        for index in indexes {
             let info = self
                 .region_info(index)
                 .with_context(|| "Fail to get region info")?;
@@ -849,6 +875,84 @@ impl VfioDevice {
         Ok(())
     }
 
+    /// Bind the INTx of device to the trigger eventfd. The INTx is masked by host once it is
+    /// triggered, and it is unmasked when the unmask eventfd is signaled.
+    ///
+    /// # Arguments
+    ///
+    /// * `trigger_fd` - Eventfd which is signaled when the INTx is asserted.
+    /// * `unmask_fd` - Eventfd which is signaled when the INTx is acknowledged by guest.
+    pub fn enable_intx(&mut self, trigger_fd: RawFd, unmask_fd: RawFd) -> Result<()> {
+        self.enable_irq(vfio::VFIO_PCI_INTX_IRQ_INDEX, trigger_fd, Some(unmask_fd))
+    }
+
+    /// Bind the irq of `index` to the trigger eventfd. If `unmask_fd` is provided, the irq is
+    /// unmasked by host when the unmask eventfd is signaled, which is required by the irqs
+    /// automatically masked once they are triggered.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the irq.
+    /// * `trigger_fd` - Eventfd which is signaled when the irq is asserted.
+    /// * `unmask_fd` - Eventfd which is signaled when the irq is acknowledged by guest.
+    pub fn enable_irq(
+        &mut self,
+        index: u32,
+        trigger_fd: RawFd,
+        unmask_fd: Option<RawFd>,
+    ) -> Result<()> {
+        let mut actions = vec![(vfio::VFIO_IRQ_SET_ACTION_TRIGGER, trigger_fd)];
+        if let Some(fd) = unmask_fd {
+            actions.push((vfio::VFIO_IRQ_SET_ACTION_UNMASK, fd));
+        }
+        for (action, fd) in actions {
+            let mut irq_set = array_to_vec::<vfio::vfio_irq_set, u32>(1);
+            irq_set[0].argsz = (size_of::<vfio::vfio_irq_set>() + size_of::<RawFd>()) as u32;
+            irq_set[0].flags = vfio::VFIO_IRQ_SET_DATA_EVENTFD | action;
+            irq_set[0].index = index;
+            irq_set[0].start = 0u32;
+            irq_set[0].count = 1u32;
+
+            // It is safe as enough memory space to save irq_set data.
+            let data: &mut [u8] = unsafe { irq_set[0].data.as_mut_slice(size_of::<RawFd>()) };
+            LittleEndian::write_i32(data, fd);
+            // Safe as device is the owner of file, and we will verify the result is valid.
+            let ret = unsafe { ioctl_with_ref(&self.fd, VFIO_DEVICE_SET_IRQS(), &irq_set[0]) };
+            if ret < 0 {
+                return Err(anyhow!(VfioError::VfioIoctl(
+                    "VFIO_DEVICE_SET_IRQS".to_string(),
+                    std::io::Error::last_os_error(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Unbind the INTx of device, the unmask eventfd is released as well.
+    pub fn disable_intx(&mut self) -> Result<()> {
+        self.disable_irq(vfio::VFIO_PCI_INTX_IRQ_INDEX)
+    }
+
+    /// Unbind the irq of `index`, the unmask eventfd is released as well.
+    pub fn disable_irq(&mut self, index: u32) -> Result<()> {
+        let mut irq_set = array_to_vec::<vfio::vfio_irq_set, u32>(0);
+        irq_set[0].argsz = size_of::<vfio::vfio_irq_set>() as u32;
+        irq_set[0].flags = vfio::VFIO_IRQ_SET_DATA_NONE | vfio::VFIO_IRQ_SET_ACTION_TRIGGER;
+        irq_set[0].index = index;
+        irq_set[0].start = 0u32;
+        irq_set[0].count = 0u32;
+
+        // Safe as device is the owner of file, and we will verify the result is valid.
+        let ret = unsafe { ioctl_with_ref(&self.fd, VFIO_DEVICE_SET_IRQS(), &irq_set[0]) };
+        if ret < 0 {
+            return Err(anyhow!(VfioError::VfioIoctl(
+                "VFIO_DEVICE_SET_IRQS".to_string(),
+                std::io::Error::last_os_error(),
+            )));
+        }
+        Ok(())
+    }
+
     pub fn reset(&self) -> Result<()> {
         // Safe as device is the owner of file, and we verify the device supports being reset.
         if self.dev_info.flags & vfio::VFIO_DEVICE_FLAGS_RESET != 0 {
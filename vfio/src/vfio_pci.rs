@@ -41,8 +41,8 @@ use devices::pci::msix::{
     MSIX_TABLE_OFFSET, MSIX_TABLE_SIZE_MAX,
 };
 use devices::pci::{
-    init_multifunction, le_read_u16, le_read_u32, le_write_u16, le_write_u32, pci_ext_cap_id,
-    pci_ext_cap_next, pci_ext_cap_ver, PciBus, PciDevBase, PciDevOps,
+    init_intx, init_multifunction, le_read_u16, le_read_u32, le_write_u16, le_write_u32,
+    pci_ext_cap_id, pci_ext_cap_next, pci_ext_cap_ver, PciBus, PciDevBase, PciDevOps,
 };
 use devices::{Device, DeviceBase};
 use hypervisor::kvm::{MsiVector, KVM_FDS};
@@ -64,10 +64,20 @@ struct VfioMsixInfo {
     // Msix entries.
     entries: u16,
     // Vfio device irq info
-    #[allow(dead_code)]
     vfio_irq: HashMap<u32, VfioIrq>,
 }
 
+struct VfioIntx {
+    // Eventfd which is signaled by vfio when the INTx is asserted.
+    trigger: EventFd,
+    // Eventfd which is signaled by kvm when the INTx is acknowledged by guest.
+    resample: EventFd,
+    // Gsi which the INTx is routed to.
+    gsi: u32,
+    // Whether the INTx is bound to kvm.
+    enabled: bool,
+}
+
 struct VfioBar {
     vfio_region: VfioRegion,
     region_type: RegionType,
@@ -91,6 +101,8 @@ pub struct VfioPciDevice {
     vfio_device: Arc<Mutex<VfioDevice>>,
     // Cache of MSI-X setup.
     msix_info: Option<VfioMsixInfo>,
+    // INTx setup, it's None if the INTx is not supported or not wired.
+    intx: Option<VfioIntx>,
     // Bars information without ROM.
     vfio_bars: Arc<Mutex<Vec<VfioBar>>>,
     // Maintains a list of GSI with irqfds that are registered to kvm.
@@ -123,6 +135,7 @@ impl VfioPciDevice {
             config_offset: 0,
             vfio_device,
             msix_info: None,
+            intx: None,
             vfio_bars: Arc::new(Mutex::new(Vec::with_capacity(PCI_NUM_BARS as usize))),
             gsi_msi_routes: Arc::new(Mutex::new(Vec::new())),
             dev_id: Arc::new(AtomicU16::new(0)),
@@ -271,6 +284,32 @@ impl VfioPciDevice {
         })
     }
 
+    /// Get INTx information if the device supports INTx and the INTx of it is wired.
+    fn get_intx_info(&self) -> Result<Option<VfioIntx>> {
+        let supported = self
+            .msix_info
+            .as_ref()
+            .and_then(|info| info.vfio_irq.get(&vfio::VFIO_PCI_INTX_IRQ_INDEX))
+            .is_some_and(|irq| irq.count > 0);
+        let gsi = self
+            .base
+            .config
+            .intx
+            .as_ref()
+            .and_then(|intx| intx.lock().unwrap().gsi());
+        match gsi {
+            Some(gsi) if supported => Ok(Some(VfioIntx {
+                trigger: EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| "Failed to create INTx trigger eventfd")?,
+                resample: EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| "Failed to create INTx resample eventfd")?,
+                gsi,
+                enabled: false,
+            })),
+            _ => Ok(None),
+        }
+    }
+
     /// Get vfio bars information. Vfio device won't allow to mmap the MSI-X table area,
     /// we need to separate MSI-X table area and region mmap area.
     fn bar_region_info(&mut self) -> Result<Vec<VfioBar>> {
@@ -722,6 +761,46 @@ impl VfioPciDevice {
         Ok(())
     }
 
+    /// Bind the INTx to kvm with the resampler. The INTx is masked by vfio once it's triggered,
+    /// and unmasked after guest acknowledges it, which avoids the level interrupt storm.
+    fn vfio_enable_intx(&mut self) -> Result<()> {
+        let intx = match self.intx.as_mut() {
+            Some(intx) if !intx.enabled => intx,
+            _ => return Ok(()),
+        };
+
+        KVM_FDS
+            .load()
+            .register_irqfd_with_resample(&intx.trigger, &intx.resample, intx.gsi)?;
+        if let Err(e) = self
+            .vfio_device
+            .lock()
+            .unwrap()
+            .enable_intx(intx.trigger.as_raw_fd(), intx.resample.as_raw_fd())
+        {
+            KVM_FDS.load().unregister_irqfd(&intx.trigger, intx.gsi)?;
+            return Err(e);
+        }
+        intx.enabled = true;
+        Ok(())
+    }
+
+    fn vfio_disable_intx(&mut self) -> Result<()> {
+        let intx = match self.intx.as_mut() {
+            Some(intx) if intx.enabled => intx,
+            _ => return Ok(()),
+        };
+
+        self.vfio_device
+            .lock()
+            .unwrap()
+            .disable_intx()
+            .with_context(|| "Failed to disable INTx")?;
+        KVM_FDS.load().unregister_irqfd(&intx.trigger, intx.gsi)?;
+        intx.enabled = false;
+        Ok(())
+    }
+
     fn vfio_enable_msix(&mut self) -> Result<()> {
         let mut gsi_routes = self.gsi_msi_routes.lock().unwrap();
         if gsi_routes.len() == 0 {
@@ -787,6 +866,7 @@ impl VfioPciDevice {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        self.vfio_disable_intx()?;
         self.vfio_disable_msix()?;
         self.vfio_unregister_all_irqfd()?;
         self.unregister_bars()?;
@@ -857,6 +937,16 @@ impl PciDevOps for VfioPciDevice {
             || "Failed to init vfio device multifunction.",
         )?;
 
+        devices::pci::Result::with_context(
+            init_intx(
+                self.name(),
+                &mut self.base.config,
+                self.base.parent_bus.clone(),
+                self.base.devfn,
+            ),
+            || "Failed to init vfio device INTx.",
+        )?;
+
         #[cfg(target_arch = "aarch64")]
         {
             let bus_num = self
@@ -879,6 +969,9 @@ impl PciDevOps for VfioPciDevice {
             || "Failed to get bar region info",
         )?));
         devices::pci::Result::with_context(self.register_bars(), || "Failed to register bars")?;
+        self.intx =
+            devices::pci::Result::with_context(self.get_intx_info(), || "Failed to get INTx info")?;
+        devices::pci::Result::with_context(self.vfio_enable_intx(), || "Failed to enable INTx")?;
 
         let devfn = self.base.devfn;
         let dev = Arc::new(Mutex::new(self));
@@ -939,6 +1032,9 @@ impl PciDevOps for VfioPciDevice {
             error!("Failed to read device pci config, error is {:?}", e);
             return;
         }
+        if self.intx.is_some() {
+            return;
+        }
         for (i, data) in data.iter_mut().enumerate().take(size) {
             if i + offset == 0x3d {
                 // Clear INIx
@@ -1000,7 +1096,12 @@ impl PciDevOps for VfioPciDevice {
         } else if ranges_overlap(offset, size, cap_offset, MSIX_CAP_SIZE as usize).unwrap() {
             let is_enable = is_msix_enabled(cap_offset, &self.base.config.config);
 
+            // INTx and MSI-X can't be enabled at the same time in vfio, INTx is used when the
+            // driver of guest falls back from MSI-X.
             if !was_enable && is_enable {
+                if let Err(e) = self.vfio_disable_intx() {
+                    error!("{:?}\nFailed to disable INTx.", e);
+                }
                 if let Err(e) = self.vfio_enable_msix() {
                     error!("{:?}\nFailed to enable MSI-X.", e);
                 }
@@ -1008,6 +1109,9 @@ impl PciDevOps for VfioPciDevice {
                 if let Err(e) = self.vfio_disable_msix() {
                     error!("{:?}\nFailed to disable MSI-X.", e);
                 }
+                if let Err(e) = self.vfio_enable_intx() {
+                    error!("{:?}\nFailed to enable INTx.", e);
+                }
             }
         }
     }
@@ -0,0 +1,341 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use vfio_bindings::bindings::vfio;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::vfio_dev::VfioDevice;
+use address_space::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region, RegionOps};
+use devices::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
+use devices::{Device, DeviceBase};
+use hypervisor::hypervisor;
+use util::device_tree::{self, CompileFDT, FdtBuilder, FdtNodeTemplate};
+use util::unix::host_page_size;
+
+/// Device region mapped to the MMIO space of guest.
+struct PlatformRegion {
+    base: u64,
+    size: u64,
+    region: Region,
+}
+
+/// Device irq bound to an SPI of guest.
+struct PlatformIrq {
+    index: u32,
+    irq: i32,
+    trigger: EventFd,
+    /// Resample eventfd of the level-triggered irq, which is automatically masked by host
+    /// once it is triggered, and unmasked when guest acknowledges it.
+    resample: Option<EventFd>,
+}
+
+/// Platform device passed through by vfio. The FDT node of it is generated from the
+/// template provided by user, with the regions and irqs allocated by the machine.
+pub struct VfioPlatformDevice {
+    base: SysBusDevBase,
+    vfio_device: Arc<Mutex<VfioDevice>>,
+    template: FdtNodeTemplate,
+    sys_mem: Arc<AddressSpace>,
+    regions: Vec<PlatformRegion>,
+    irqs: Vec<PlatformIrq>,
+}
+
+impl VfioPlatformDevice {
+    pub fn new(
+        vfio_device: Arc<Mutex<VfioDevice>>,
+        id: String,
+        template: FdtNodeTemplate,
+        sys_mem: Arc<AddressSpace>,
+    ) -> Self {
+        let mut base = SysBusDevBase::new(SysBusDevType::VfioPlatform);
+        base.base = DeviceBase::new(id, false);
+        Self {
+            base,
+            vfio_device,
+            template,
+            sys_mem,
+            regions: Vec::new(),
+            irqs: Vec::new(),
+        }
+    }
+
+    pub fn realize(mut self, sysbus: &mut SysBus) -> Result<Arc<Mutex<Self>>> {
+        if !self.vfio_device.lock().unwrap().dev_info.is_platform() {
+            bail!("{} is not a platform device", self.name());
+        }
+        self.map_regions(sysbus)?;
+        self.enable_irqs(sysbus)?;
+
+        let region = self.regions.first();
+        self.base.set_sys(
+            self.irqs.first().map_or(-1, |irq| irq.irq),
+            region.map_or(0, |r| r.base),
+            region.map_or(0, |r| r.size),
+        );
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_dynamic_device(&dev)?;
+        Ok(dev)
+    }
+
+    fn region_ops(&self, region_offset: u64) -> RegionOps {
+        let cloned_dev = self.vfio_device.clone();
+        let read = move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+            if let Err(e) = cloned_dev
+                .lock()
+                .unwrap()
+                .read_region(data, region_offset, offset)
+            {
+                error!("Failed to read vfio platform region: {:?}", e);
+                return false;
+            }
+            true
+        };
+
+        let cloned_dev = self.vfio_device.clone();
+        let write = move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+            if let Err(e) = cloned_dev
+                .lock()
+                .unwrap()
+                .write_region(data, region_offset, offset)
+            {
+                error!("Failed to write vfio platform region: {:?}", e);
+                return false;
+            }
+            true
+        };
+
+        RegionOps {
+            read: Arc::new(read),
+            write: Arc::new(write),
+        }
+    }
+
+    /// Allocate the MMIO space for all the device regions. The accesses to the mappable
+    /// parts of regions are passed through without VM exits, and the others are trapped.
+    fn map_regions(&mut self, sysbus: &mut SysBus) -> Result<()> {
+        let page_size = host_page_size();
+        let vfio_regions = self
+            .vfio_device
+            .lock()
+            .unwrap()
+            .get_platform_regions_info()?;
+        for (index, vfio_region) in vfio_regions.iter().enumerate() {
+            if vfio_region.size == 0 {
+                continue;
+            }
+            let base = sysbus
+                .alloc_mmio_region(vfio_region.size.div_ceil(page_size) * page_size, page_size)?;
+            let region = Region::init_io_region(
+                vfio_region.size,
+                self.region_ops(vfio_region.region_offset),
+                "VfioPlatformRegion",
+            );
+
+            let read_only = vfio_region.flags & vfio::VFIO_REGION_INFO_FLAG_WRITE == 0;
+            for mmap in vfio_region.mmaps.iter() {
+                let dev = self.vfio_device.lock().unwrap().fd.try_clone()?;
+                let fb = Some(FileBackend {
+                    file: Arc::new(dev),
+                    offset: vfio_region.region_offset + mmap.offset,
+                    page_size,
+                });
+                let host_mmap = HostMemMapping::new(
+                    GuestAddress(base + mmap.offset),
+                    None,
+                    mmap.size,
+                    fb,
+                    false,
+                    true,
+                    read_only,
+                )?;
+                let ram_device = Region::init_ram_device_region(Arc::new(host_mmap), "VfioRam");
+                region
+                    .add_subregion(ram_device, mmap.offset)
+                    .with_context(|| {
+                        format!(
+                            "Failed to add sub region at the region {} in memory space",
+                            index
+                        )
+                    })?;
+            }
+
+            self.sys_mem
+                .root()
+                .add_subregion(region.clone(), base)
+                .with_context(|| {
+                    format!(
+                        "Failed to register vfio platform region: offset=0x{:x},size={}",
+                        base, vfio_region.size
+                    )
+                })?;
+            self.regions.push(PlatformRegion {
+                base,
+                size: vfio_region.size,
+                region,
+            });
+        }
+        Ok(())
+    }
+
+    /// Bind the device irqs to the SPIs allocated from system bus.
+    fn enable_irqs(&mut self, sysbus: &mut SysBus) -> Result<()> {
+        let num_irqs = self.vfio_device.lock().unwrap().dev_info.num_irqs;
+        let irqs_info = self.vfio_device.lock().unwrap().get_irqs_info(num_irqs)?;
+        for index in 0..num_irqs {
+            let info = match irqs_info.get(&index) {
+                Some(info) if info.count > 0 => info,
+                _ => continue,
+            };
+            if info.flags & vfio::VFIO_IRQ_INFO_EVENTFD == 0 {
+                bail!("Irq {} of {} does not support eventfd", index, self.name());
+            }
+
+            let irq = sysbus.alloc_irq()?;
+            let trigger = EventFd::new(libc::EFD_NONBLOCK)?;
+            let resample = if info.flags & vfio::VFIO_IRQ_INFO_AUTOMASKED != 0 {
+                let resample = EventFd::new(libc::EFD_NONBLOCK)?;
+                hypervisor().register_irqfd_with_resample(&trigger, &resample, irq as u32)?;
+                Some(resample)
+            } else {
+                hypervisor().register_irqfd(&trigger, irq as u32)?;
+                None
+            };
+            if let Err(e) = self.vfio_device.lock().unwrap().enable_irq(
+                index,
+                trigger.as_raw_fd(),
+                resample.as_ref().map(|evt| evt.as_raw_fd()),
+            ) {
+                hypervisor().unregister_irqfd(&trigger, irq as u32)?;
+                return Err(e);
+            }
+            self.irqs.push(PlatformIrq {
+                index,
+                irq,
+                trigger,
+                resample,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VfioPlatformDevice {
+    fn drop(&mut self) {
+        for irq in self.irqs.iter() {
+            if let Err(e) = self.vfio_device.lock().unwrap().disable_irq(irq.index) {
+                error!(
+                    "Failed to disable irq {} of vfio platform device: {:?}",
+                    irq.index, e
+                );
+            }
+            if let Err(e) = hypervisor().unregister_irqfd(&irq.trigger, irq.irq as u32) {
+                error!(
+                    "Failed to unregister irqfd of vfio platform device: {:?}",
+                    e
+                );
+            }
+        }
+        for region in self.regions.iter() {
+            if let Err(e) = self.sys_mem.root().delete_subregion(&region.region) {
+                error!("Failed to unregister vfio platform region: {:?}", e);
+            }
+        }
+    }
+}
+
+impl Device for VfioPlatformDevice {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for VfioPlatformDevice {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    // The device regions are registered in memory space with their own ops, so the
+    // accesses never reach here.
+    fn read(&mut self, _data: &mut [u8], _base: GuestAddress, _offset: u64) -> bool {
+        false
+    }
+
+    fn write(&mut self, _data: &[u8], _base: GuestAddress, _offset: u64) -> bool {
+        false
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.vfio_device
+            .lock()
+            .unwrap()
+            .reset()
+            .with_context(|| "Failed to reset vfio platform device")
+    }
+}
+
+impl acpi::AmlBuilder for VfioPlatformDevice {
+    fn aml_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+impl CompileFDT for VfioPlatformDevice {
+    fn generate_fdt_node(&self, fdt: &mut FdtBuilder) -> Result<()> {
+        let reg: Vec<u8> = self
+            .regions
+            .iter()
+            .flat_map(|r| [r.base, r.size])
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let interrupts: Vec<u8> = self
+            .irqs
+            .iter()
+            .flat_map(|irq| {
+                let irq_type = if irq.resample.is_some() {
+                    device_tree::IRQ_TYPE_LEVEL_HIGH
+                } else {
+                    device_tree::IRQ_TYPE_EDGE_RISING
+                };
+                [device_tree::GIC_FDT_IRQ_TYPE_SPI, irq.irq as u32, irq_type]
+            })
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+
+        let mut props = vec![("reg", reg)];
+        if !interrupts.is_empty() {
+            props.push(("interrupts", interrupts));
+            props.push((
+                "interrupt-parent",
+                device_tree::GIC_PHANDLE.to_be_bytes().to_vec(),
+            ));
+        }
+        let unit_address = self.regions.first().map_or(0, |r| r.base);
+        self.template.build(fdt, unit_address, &props)
+    }
+}
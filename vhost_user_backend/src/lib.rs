@@ -0,0 +1,31 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Vhost-user backend
+//!
+//! The library provides the backend side of the vhost-user protocol which is used by
+//! the external device daemons connected with StratoVirt, such as vhost_user_fs.
+//!
+//! ## Design
+//!
+//! - `server`: listens on the unix socket, negotiates features and dispatches the
+//!   requests from StratoVirt to the [`VhostUserReqHandler`] of the device.
+//! - `memory`: maps the guest memory table shared by StratoVirt.
+//! - `vring`: saves the configuration, kick and call eventfds of the virtio queues.
+
+pub mod memory;
+pub mod server;
+pub mod vring;
+
+pub use memory::VhostUserMemTable;
+pub use server::{VhostUserReqHandler, VhostUserServerHandler};
+pub use vring::{parse_vring_fd_index, VringInfo};
@@ -0,0 +1,133 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use log::error;
+
+use address_space::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use virtio::vhost::user::RegionMemInfo;
+
+/// The guest memory shared by StratoVirt with the memory table.
+pub struct VhostUserMemTable {
+    /// Address space which the guest memory is mapped to.
+    sys_mem: Arc<AddressSpace>,
+    /// The regions added to the address space.
+    regions: Vec<Region>,
+    /// The guest memory region information.
+    mem_info: Vec<RegionMemInfo>,
+}
+
+impl VhostUserMemTable {
+    /// Create an empty memory table.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the address space.
+    pub fn new(name: &str) -> Result<Self> {
+        let sys_mem = AddressSpace::new(Region::init_container_region(u64::MAX, name), name)
+            .with_context(|| "Failed to create address space")?;
+
+        Ok(VhostUserMemTable {
+            sys_mem,
+            regions: Vec::new(),
+            mem_info: Vec::new(),
+        })
+    }
+
+    /// Get the address space of the guest memory.
+    pub fn sys_mem(&self) -> &Arc<AddressSpace> {
+        &self.sys_mem
+    }
+
+    /// Replace the mappings of guest memory with the new memory table.
+    ///
+    /// # Arguments
+    ///
+    /// * `regions` - The memory region information of the memory table.
+    /// * `fds` - The files descriptors of each memory region.
+    pub fn set_mem_table(&mut self, regions: &[RegionMemInfo], fds: &[RawFd]) -> Result<()> {
+        if regions.len() != fds.len() {
+            bail!(
+                "The length of fds {} for mem table is invalid, expected {}",
+                fds.len(),
+                regions.len()
+            );
+        }
+
+        for region in &self.regions {
+            if let Err(e) = self.sys_mem.root().delete_subregion(region) {
+                error!("Failed to delete subregion for setting mem table, {:?}", e);
+            }
+        }
+        self.regions = Vec::new();
+
+        self.mem_info = regions.to_vec();
+
+        for (index, region_config) in regions.iter().enumerate() {
+            let file = unsafe { File::from_raw_fd(fds[index]) };
+            let fileback = FileBackend {
+                file: Arc::new(file),
+                offset: region_config.mmap_offset,
+                page_size: 0_u64,
+            };
+
+            let mmap = Arc::new(
+                HostMemMapping::new(
+                    GuestAddress(region_config.guest_phys_addr),
+                    None,
+                    region_config.memory_size,
+                    Some(fileback),
+                    false,
+                    true,
+                    false,
+                )
+                .with_context(|| {
+                    format!(
+                        "Failed to create the mapping of host memory for setting mem table, addr: 0x{:X}, size: {}, offset: {}",
+                        region_config.guest_phys_addr,
+                        region_config.memory_size,
+                        region_config.mmap_offset,
+                    )
+                })?,
+            );
+
+            let region = Region::init_ram_region(mmap.clone(), "VhostUserRam");
+            self.sys_mem
+                .root()
+                .add_subregion(region.clone(), mmap.start_address().raw_value())
+                .with_context(|| "Failed to add subregion for setting mem table")?;
+
+            self.regions.push(region);
+        }
+
+        Ok(())
+    }
+
+    /// Translate the userspace address of StratoVirt to the guest physical address.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The userspace address in StratoVirt.
+    pub fn get_guest_address(&self, addr: u64) -> Result<u64> {
+        for info in self.mem_info.iter() {
+            if addr >= info.userspace_addr && addr < info.userspace_addr + info.memory_size {
+                return Ok(info.guest_phys_addr + addr - info.userspace_addr);
+            }
+        }
+
+        bail!("Failed to find the guest address for addr: 0x{:X}", addr);
+    }
+}
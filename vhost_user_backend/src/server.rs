@@ -12,13 +12,19 @@
 
 use std::mem::size_of;
 use std::os::unix::io::RawFd;
+use std::rc::Rc;
 use std::slice;
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 
 use anyhow::{bail, Context, Result};
 use log::error;
+use vmm_sys_util::epoll::EventSet;
 
 use machine_manager::temp_cleaner::TempCleaner;
+use util::loop_context::{EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation};
 use util::unix::limit_permission;
 use virtio::vhost::user::{
     RegionMemInfo, VhostUserHdrFlag, VhostUserMemHdr, VhostUserMsgHdr, VhostUserMsgReq,
@@ -47,7 +53,7 @@ pub trait VhostUserReqHandler: Send + Sync {
     ///
     /// * `regions` - The slice of memory region information for the message of memory table.
     /// * `fds` - The files descriptors are used to map shared memory for the process and
-    /// StratoVirt.
+    ///   StratoVirt.
     fn set_mem_table(&mut self, regions: &[RegionMemInfo], fds: &[RawFd]) -> Result<()>;
 
     /// Set the size of descriptors in the virtio queue.
@@ -272,7 +278,7 @@ impl VhostUserServerHandler {
         Ok(())
     }
 
-    #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
+    #[allow(clippy::cast_ptr_alignment)]
     fn set_msg_mem_table(
         &mut self,
         hdr: &VhostUserMsgHdr,
@@ -480,3 +486,77 @@ impl VhostUserServerHandler {
         Ok(())
     }
 }
+
+trait CreateEventNotifier {
+    fn create_event_notifier(
+        &mut self,
+        server_handler: Arc<Mutex<Self>>,
+    ) -> Option<Vec<EventNotifier>>;
+}
+
+impl CreateEventNotifier for VhostUserServerHandler {
+    fn create_event_notifier(
+        &mut self,
+        server_handler: Arc<Mutex<Self>>,
+    ) -> Option<Vec<EventNotifier>> {
+        if let Err(e) = self.sock.domain.accept() {
+            error!("Failed to accept the socket for vhost user server, {:?}", e);
+            return None;
+        }
+
+        let mut notifiers = Vec::new();
+
+        let should_exit = self.should_exit.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
+            if event == EventSet::IN {
+                let mut lock_server_handler = server_handler.lock().unwrap();
+                if let Err(e) = lock_server_handler.handle_request() {
+                    error!("Failed to handle request for vhost user server, {:?}", e);
+                }
+            }
+            if event & EventSet::HANG_UP == EventSet::HANG_UP {
+                should_exit.store(true, Ordering::Release);
+            }
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            self.sock.domain.get_stream_raw_fd(),
+            None,
+            EventSet::IN | EventSet::HANG_UP,
+            vec![handler],
+        );
+        notifiers.push(notifier);
+
+        Some(notifiers)
+    }
+}
+
+impl EventNotifierHelper for VhostUserServerHandler {
+    fn internal_notifiers(server_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+
+        let server_handler_clone = server_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            server_handler_clone
+                .lock()
+                .unwrap()
+                .create_event_notifier(server_handler_clone.clone())
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            server_handler
+                .lock()
+                .unwrap()
+                .sock
+                .domain
+                .get_listener_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        );
+        notifiers.push(notifier);
+
+        notifiers
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::memory::VhostUserMemTable;
+use address_space::GuestAddress;
+use virtio::QueueConfig;
+
+/// For VHOST_USER_SET_VRING_KICK and VHOST_USER_SET_VRING_CALL and VHOST_USER_SET_
+/// VRING_ERR, Bits (0-7) of the payload contain the vring index. Bit 8 is the invalid
+/// FD flag. This flag is set when there is no file descriptor in the ancillary data.
+/// This signals that polling should be used instead of waiting for the kick.
+const VHOST_USER_VRING_IDX_MASK: usize = 0xff;
+const VHOST_USER_VRING_NO_FD_MASK: usize = 0x1 << 8;
+
+/// Get the vring index from the payload of the request for setting kick or call fd.
+///
+/// # Arguments
+///
+/// * `payload` - The payload of VHOST_USER_SET_VRING_KICK or VHOST_USER_SET_VRING_CALL.
+pub fn parse_vring_fd_index(payload: usize) -> Result<usize> {
+    if (payload & VHOST_USER_VRING_NO_FD_MASK) != 0 {
+        bail!("The polling mode is not supported");
+    }
+    Ok(payload & VHOST_USER_VRING_IDX_MASK)
+}
+
+/// The information of virtio queue set by StratoVirt.
+pub struct VringInfo {
+    /// The configuration of virtio queue.
+    pub config: QueueConfig,
+    /// Eventfd signaled by the guest when buffers need to be processed.
+    pub kick_evt: Option<Arc<EventFd>>,
+    /// Eventfd used to notify the guest.
+    pub call_evt: Option<Arc<EventFd>>,
+}
+
+impl VringInfo {
+    /// Create the information of virtio queue with the max size.
+    pub fn new(queue_size: u16) -> Self {
+        VringInfo {
+            config: QueueConfig::new(queue_size),
+            kick_evt: None,
+            call_evt: None,
+        }
+    }
+
+    /// Set the addresses of virtio queue, the addresses are userspace addresses of
+    /// StratoVirt which are translated with the memory table.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_table` - The guest memory table.
+    /// * `desc_table` - The start address of descriptor table.
+    /// * `used_ring` - The start address of used ring.
+    /// * `avail_ring` - The start address of avail ring.
+    pub fn set_addr(
+        &mut self,
+        mem_table: &VhostUserMemTable,
+        desc_table: u64,
+        used_ring: u64,
+        avail_ring: u64,
+    ) -> Result<()> {
+        let desc_addr = mem_table.get_guest_address(desc_table)?;
+        let used_addr = mem_table.get_guest_address(used_ring)?;
+        let avail_addr = mem_table.get_guest_address(avail_ring)?;
+        let sys_mem = mem_table.sys_mem();
+
+        self.config.desc_table = GuestAddress(desc_addr);
+        self.config.addr_cache.desc_table_host = sys_mem
+            .get_host_address(GuestAddress(desc_addr))
+            .unwrap_or(0);
+        self.config.avail_ring = GuestAddress(avail_addr);
+        self.config.addr_cache.avail_ring_host = sys_mem
+            .get_host_address(GuestAddress(avail_addr))
+            .unwrap_or(0);
+        self.config.used_ring = GuestAddress(used_addr);
+        self.config.addr_cache.used_ring_host = sys_mem
+            .get_host_address(GuestAddress(used_addr))
+            .unwrap_or(0);
+
+        if self.config.addr_cache.desc_table_host == 0
+            || self.config.addr_cache.avail_ring_host == 0
+            || self.config.addr_cache.used_ring_host == 0
+        {
+            bail!(
+                "Failed to set vring addr, got host address failed. desc: 0x{:X}, avail: 0x{:X}, used: 0x{:X}",
+                desc_addr,
+                avail_addr,
+                used_addr
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Set the eventfd used to notify the guest, the fd is owned by the vring.
+    pub fn set_call(&mut self, fd: RawFd) {
+        let call_evt = unsafe { EventFd::from_raw_fd(fd) };
+        self.call_evt = Some(Arc::new(call_evt));
+    }
+
+    /// Set the eventfd signaled by the guest, the fd is owned by the vring.
+    pub fn set_kick(&mut self, fd: RawFd) {
+        let kick_evt = unsafe { EventFd::from_raw_fd(fd) };
+        self.kick_evt = Some(Arc::new(kick_evt));
+    }
+
+    /// Notify the guest that the used ring is updated.
+    pub fn notify(&self) -> Result<()> {
+        if let Some(call_evt) = self.call_evt.as_ref() {
+            call_evt
+                .write(1)
+                .with_context(|| "Failed to write call fd")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vring_fd_index() {
+        assert_eq!(parse_vring_fd_index(0).unwrap(), 0);
+        assert_eq!(parse_vring_fd_index(0x1).unwrap(), 1);
+        assert_eq!(parse_vring_fd_index(0xff).unwrap(), 0xff);
+        assert!(parse_vring_fd_index(0x101).is_err());
+    }
+
+    #[test]
+    fn test_vring_set_addr_without_mem_table() {
+        let mem_table = VhostUserMemTable::new("VringTestMem").unwrap();
+        let mut vring = VringInfo::new(256);
+        assert!(vring.set_addr(&mem_table, 0x1000, 0x2000, 0x3000).is_err());
+        assert!(vring.notify().is_ok());
+    }
+}
@@ -20,7 +20,6 @@ pub mod fuse_req;
 pub mod sandbox;
 pub mod securecomputing;
 pub mod vhost_user_fs;
-pub mod vhost_user_server;
 pub mod virtio_fs;
 
 use std::collections::HashSet;
@@ -78,7 +77,7 @@ impl ExitCode for () {
 }
 
 fn main() {
-    ::std::process::exit(match run() {
+    let code = match run() {
         Ok(ret) => ExitCode::code(ret),
         Err(ref e) => {
             write!(&mut ::std::io::stderr(), "{}", format_args!("{:?}\r\n", e))
@@ -86,7 +85,10 @@ fn main() {
 
             1
         }
-    });
+    };
+    // Write out the buffered logs.
+    logger::flush_log();
+    ::std::process::exit(code);
 }
 
 fn parse_capabilities(cmd_args: &arg_parser::ArgMatches) -> Result<HashSet<String>> {
@@ -10,26 +10,19 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::{
-    rc::Rc,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
-    },
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
 };
 
 use anyhow::{Context, Result};
-use log::error;
-use vmm_sys_util::epoll::EventSet;
 
 use super::cmdline::FsConfig;
 use super::fs::set_rlimit_nofile;
-use super::vhost_user_server::VhostUserServerHandler;
 use super::virtio_fs::VirtioFs;
 use machine_manager::{event_loop::EventLoop, temp_cleaner::TempCleaner};
-use util::loop_context::{
-    EventLoopManager, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
-};
+use util::loop_context::{EventLoopManager, EventNotifierHelper};
+use vhost_user_backend::VhostUserServerHandler;
 
 /// The vhost-user filesystem device contains virtio fs device and the vhost-user
 /// server which can be connected with the vhost-user client in StratoVirt.
@@ -41,80 +34,6 @@ pub struct VhostUserFs {
     should_exit: Arc<AtomicBool>,
 }
 
-trait CreateEventNotifier {
-    fn create_event_notifier(
-        &mut self,
-        server_handler: Arc<Mutex<Self>>,
-    ) -> Option<Vec<EventNotifier>>;
-}
-
-impl CreateEventNotifier for VhostUserServerHandler {
-    fn create_event_notifier(
-        &mut self,
-        server_handler: Arc<Mutex<Self>>,
-    ) -> Option<Vec<EventNotifier>> {
-        if let Err(e) = self.sock.domain.accept() {
-            error!("Failed to accept the socket for vhost user server, {:?}", e);
-            return None;
-        }
-
-        let mut notifiers = Vec::new();
-
-        let should_exit = self.should_exit.clone();
-        let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
-            if event == EventSet::IN {
-                let mut lock_server_handler = server_handler.lock().unwrap();
-                if let Err(e) = lock_server_handler.handle_request() {
-                    error!("Failed to handle request for vhost user server, {:?}", e);
-                }
-            }
-            if event & EventSet::HANG_UP == EventSet::HANG_UP {
-                should_exit.store(true, Ordering::Release);
-            }
-            None
-        });
-        let notifier = EventNotifier::new(
-            NotifierOperation::AddShared,
-            self.sock.domain.get_stream_raw_fd(),
-            None,
-            EventSet::IN | EventSet::HANG_UP,
-            vec![handler],
-        );
-        notifiers.push(notifier);
-
-        Some(notifiers)
-    }
-}
-
-impl EventNotifierHelper for VhostUserServerHandler {
-    fn internal_notifiers(server_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
-        let mut notifiers = Vec::new();
-
-        let server_handler_clone = server_handler.clone();
-        let handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
-            server_handler_clone
-                .lock()
-                .unwrap()
-                .create_event_notifier(server_handler_clone.clone())
-        });
-        let notifier = EventNotifier::new(
-            NotifierOperation::AddShared,
-            server_handler
-                .lock()
-                .unwrap()
-                .sock
-                .domain
-                .get_listener_raw_fd(),
-            None,
-            EventSet::IN,
-            vec![handler],
-        );
-        notifiers.push(notifier);
-
-        notifiers
-    }
-}
-
 impl VhostUserFs {
     /// Create a new vhost-user filesystem device.
     ///
@@ -16,13 +16,7 @@ const VIRIOT_FS_HIGH_PRIO_QUEUE_NUM: u64 = 1;
 const VIRTIO_FS_REQ_QUEUES_NUM: u64 = 1;
 /// The max queue size.
 const VIRTIO_FS_MAX_QUEUE_SIZE: u16 = 1024;
-const VIRTIO_FS_VRING_IDX_MASK: usize = 0xff;
-/// For VHOST_USER_SET_VRING_KICK and VHOST_USER_SET_VRING_CALL and VHOST_USER_SET_
-/// VRING_ERR, Bits (0-7) of the payload contain the vring index. Bit 8 is the invalid
-/// FD flag. This flag is set when there is no file descriptor in the ancillary data.
-/// This signals that polling should be used instead of waiting for the kick.
-use std::fs::File;
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
@@ -32,22 +26,20 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use super::fs::FileSystem;
 use super::fuse_req::FuseReq;
-use super::vhost_user_server::VhostUserReqHandler;
 use crate::cmdline::FsConfig;
-use address_space::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use address_space::AddressSpace;
 use machine_manager::event_loop::EventLoop;
 use util::loop_context::{
     gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
     NotifierOperation,
 };
+use vhost_user_backend::{parse_vring_fd_index, VhostUserMemTable, VhostUserReqHandler, VringInfo};
 use virtio::{
     vhost::user::RegionMemInfo, virtio_has_feature, Queue, QueueConfig,
     VhostUser::VHOST_USER_F_PROTOCOL_FEATURES, QUEUE_TYPE_SPLIT_VRING, VIRTIO_F_RING_EVENT_IDX,
     VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
 };
 
-const VIRTIO_FS_VRING_NO_FD_MASK: usize = 0x1 << 8;
-
 struct FsIoHandler {
     queue: Queue,
     kick_evt: Arc<EventFd>,
@@ -141,27 +133,10 @@ impl EventNotifierHelper for FsIoHandler {
     }
 }
 
-struct QueueInfo {
-    config: QueueConfig,
-    kick_evt: Option<Arc<EventFd>>,
-    call_evt: Option<Arc<EventFd>>,
-}
-
-impl QueueInfo {
-    fn new(queue_size: u16) -> Self {
-        QueueInfo {
-            config: QueueConfig::new(queue_size),
-            kick_evt: None,
-            call_evt: None,
-        }
-    }
-}
-
 struct VirtioFsConfig {
     device_features: u64,
     driver_features: u64,
-    queues_info: Vec<QueueInfo>,
-    mem_regions: Vec<Region>,
+    queues_info: Vec<VringInfo>,
 }
 
 impl VirtioFsConfig {
@@ -172,18 +147,17 @@ impl VirtioFsConfig {
 
         let mut queues_info = Vec::new();
         for _i in 0..(VIRIOT_FS_HIGH_PRIO_QUEUE_NUM + VIRTIO_FS_REQ_QUEUES_NUM) {
-            queues_info.push(QueueInfo::new(VIRTIO_FS_MAX_QUEUE_SIZE));
+            queues_info.push(VringInfo::new(VIRTIO_FS_MAX_QUEUE_SIZE));
         }
 
         VirtioFsConfig {
             device_features,
             driver_features: 0_u64,
             queues_info,
-            mem_regions: Vec::new(),
         }
     }
 
-    fn get_mut_queue_config(&mut self, queue_index: usize) -> Result<&mut QueueInfo> {
+    fn get_mut_queue_config(&mut self, queue_index: usize) -> Result<&mut VringInfo> {
         self.queues_info
             .get_mut(queue_index)
             .with_context(|| format!("The select index of queue {} overflows", queue_index))
@@ -198,12 +172,10 @@ pub struct VirtioFs {
     config: VirtioFsConfig,
     /// Fs handlers of I/O request.
     fs_handlers: Vec<Option<Arc<Mutex<FsIoHandler>>>>,
-    /// Address space mapped witch StratoVirt.
-    sys_mem: Arc<AddressSpace>,
+    /// Guest memory mapped witch StratoVirt.
+    mem_table: VhostUserMemTable,
     /// File system used to store inode and file information.
     fs: Arc<Mutex<FileSystem>>,
-    /// The quest memory region information.
-    mem_info: Vec<RegionMemInfo>,
 }
 
 impl VirtioFs {
@@ -213,11 +185,7 @@ impl VirtioFs {
     ///
     /// * `source_dir` - The path of source directory shared in host.
     pub fn new(fs_config: FsConfig) -> Result<Self> {
-        let sys_mem = AddressSpace::new(
-            Region::init_container_region(u64::max_value(), "VirtioFsMem"),
-            "VirtioFsMem",
-        )
-        .with_context(|| "Failed to create address space")?;
+        let mem_table = VhostUserMemTable::new("VirtioFsMem")?;
 
         let mut fs_handlers = Vec::new();
         for _i in 0..(VIRIOT_FS_HIGH_PRIO_QUEUE_NUM + VIRTIO_FS_REQ_QUEUES_NUM) {
@@ -231,22 +199,11 @@ impl VirtioFs {
         Ok(VirtioFs {
             config: VirtioFsConfig::new(),
             fs_handlers,
-            sys_mem,
+            mem_table,
             fs,
-            mem_info: Vec::new(),
         })
     }
 
-    fn get_guest_address(&self, addr: u64) -> Result<u64> {
-        for (_, info) in self.mem_info.iter().enumerate() {
-            if addr >= info.userspace_addr && addr < info.userspace_addr + info.memory_size {
-                return Ok(info.guest_phys_addr + addr - info.userspace_addr);
-            }
-        }
-
-        bail!("Failed to find the guest address for addr: 0x{:X}", addr);
-    }
-
     fn register_fs_handler(&mut self, queue_index: usize) -> Result<()> {
         // Before setting up new notifiers, we should remove old ones.
         self.unregister_fs_handler(queue_index)?;
@@ -267,7 +224,7 @@ impl VirtioFs {
                 queue_info.config,
                 queue_info.kick_evt.as_ref().unwrap().clone(),
                 queue_info.call_evt.as_ref().unwrap().clone(),
-                &self.sys_mem,
+                self.mem_table.sys_mem(),
                 driver_features,
                 self.fs.clone(),
             )
@@ -308,52 +265,7 @@ impl VhostUserReqHandler for VirtioFs {
     }
 
     fn set_mem_table(&mut self, regions: &[RegionMemInfo], fds: &[RawFd]) -> Result<()> {
-        if !self.config.mem_regions.is_empty() {
-            for region in &self.config.mem_regions {
-                if let Err(e) = self.sys_mem.root().delete_subregion(region) {
-                    error!("Failed to delete subregion for setting mem table, {:?}", e);
-                }
-            }
-            self.config.mem_regions = Vec::new();
-        }
-
-        self.mem_info = regions.to_vec();
-
-        for (index, region_config) in regions.iter().enumerate() {
-            let file = unsafe { File::from_raw_fd(fds[index]) };
-            let fileback = FileBackend {
-                file: Arc::new(file),
-                offset: region_config.mmap_offset,
-                page_size: 0_u64,
-            };
-
-            let mmap = Arc::new(
-                HostMemMapping::new(
-                    GuestAddress(region_config.guest_phys_addr),
-                    None,
-                    region_config.memory_size,
-                    Some(fileback),
-                    false,
-                    true,
-                    false,
-                )
-                    .with_context(||
-                        format!("Failed to create the mapping of host memory for setting mem table, addr: 0x{:X}, size: {}, offset: {}",
-                                region_config.guest_phys_addr, region_config.memory_size, region_config.mmap_offset,
-                        )
-                    )?
-            );
-
-            let region = Region::init_ram_region(mmap.clone(), "VirtioFsRam");
-            self.sys_mem
-                .root()
-                .add_subregion(region.clone(), mmap.start_address().raw_value())
-                .with_context(|| "Failed to add subregion for setting mem table")?;
-
-            self.config.mem_regions.push(region);
-        }
-
-        Ok(())
+        self.mem_table.set_mem_table(regions, fds)
     }
 
     fn set_vring_num(&mut self, queue_index: usize, num: u16) -> Result<()> {
@@ -380,51 +292,13 @@ impl VhostUserReqHandler for VirtioFs {
         avail_ring: u64,
         _log: u64,
     ) -> Result<()> {
-        let cloned_mem_space = self.sys_mem.clone();
-
-        let desc_addr = self.get_guest_address(desc_table)?;
-        let used_addr = self.get_guest_address(used_ring)?;
-        let avail_addr = self.get_guest_address(avail_ring)?;
-
-        if let Err(_ret) = self
-            .config
-            .get_mut_queue_config(queue_index)
-            .map(|queue_info| {
-                queue_info.config.desc_table = GuestAddress(desc_addr);
-                queue_info.config.addr_cache.desc_table_host = cloned_mem_space
-                    .get_host_address(GuestAddress(desc_addr))
-                    .unwrap_or(0);
-
-                queue_info.config.avail_ring = GuestAddress(avail_addr);
-                queue_info.config.addr_cache.avail_ring_host = cloned_mem_space
-                    .get_host_address(GuestAddress(avail_addr))
-                    .unwrap_or(0);
-
-                queue_info.config.used_ring = GuestAddress(used_addr);
-                queue_info.config.addr_cache.used_ring_host = cloned_mem_space
-                    .get_host_address(GuestAddress(used_addr))
-                    .unwrap_or(0);
-
-                if queue_info.config.addr_cache.desc_table_host == 0
-                    || queue_info.config.addr_cache.avail_ring_host == 0
-                    || queue_info.config.addr_cache.used_ring_host == 0
-                {
-                    return Err(());
-                }
-
-                Ok(())
-            })
-        {
-            bail!(
-                "Failed to set vring addr, got host address failed. Index: {}, desc: 0x{:X}, avail: 0x{:X}, used: 0x{:X}",
-                queue_index,
-                desc_addr,
-                avail_addr,
-                used_addr
-            );
-        }
-
-        Ok(())
+        let mem_table = &self.mem_table;
+        self.config
+            .queues_info
+            .get_mut(queue_index)
+            .with_context(|| format!("The select index of queue {} overflows", queue_index))?
+            .set_addr(mem_table, desc_table, used_ring, avail_ring)
+            .with_context(|| format!("Failed to set vring addr, index: {}", queue_index))
     }
 
     fn set_vring_base(&mut self, _queue_index: usize, _num: u16) -> Result<()> {
@@ -432,16 +306,10 @@ impl VhostUserReqHandler for VirtioFs {
     }
 
     fn set_vring_call(&mut self, queue_index: usize, fd: RawFd) -> Result<()> {
-        if (queue_index & VIRTIO_FS_VRING_NO_FD_MASK) != 0 {
-            bail!("The polling mode is not supported");
-        }
-        let index = queue_index & VIRTIO_FS_VRING_IDX_MASK;
+        let index = parse_vring_fd_index(queue_index)?;
         self.config
             .get_mut_queue_config(index)
-            .map(|queue_info| {
-                let call_evt = unsafe { EventFd::from_raw_fd(fd) };
-                queue_info.call_evt = Some(Arc::new(call_evt));
-            })
+            .map(|queue_info| queue_info.set_call(fd))
             .with_context(|| format!("Failed to set vring call, index: {}", index))?;
 
         if !virtio_has_feature(self.config.driver_features, VHOST_USER_F_PROTOCOL_FEATURES) {
@@ -452,16 +320,10 @@ impl VhostUserReqHandler for VirtioFs {
     }
 
     fn set_vring_kick(&mut self, queue_index: usize, fd: RawFd) -> Result<()> {
-        if (queue_index & VIRTIO_FS_VRING_NO_FD_MASK) != 0 {
-            bail!("The polling mode is not supported");
-        }
-        let index = queue_index & VIRTIO_FS_VRING_IDX_MASK;
+        let index = parse_vring_fd_index(queue_index)?;
         self.config
             .get_mut_queue_config(index)
-            .map(|queue_info| {
-                let kick_evt = unsafe { EventFd::from_raw_fd(fd) };
-                queue_info.kick_evt = Some(Arc::new(kick_evt));
-            })
+            .map(|queue_info| queue_info.set_kick(fd))
             .with_context(|| format!("Failed to set vring kick, index: {}", index))?;
         Ok(())
     }
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::BTreeMap;
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
@@ -17,11 +18,11 @@ use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::{
     cmp::{self, Reverse},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Context, Result};
-use log::{error, warn};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd, timerfd::TimerFd};
 
 use crate::{
@@ -33,11 +34,14 @@ use address_space::{
     AddressSpace, FlatRange, GuestAddress, Listener, ListenerReqType, RegionIoEventFd, RegionType,
 };
 use machine_manager::{
+    balloon_policy::{
+        register_balloon_policy_ops, unregister_balloon_policy_ops, BalloonPolicyOps, GuestMemStats,
+    },
     config::{BalloonConfig, DEFAULT_VIRTQUEUE_SIZE},
     event,
     event_loop::{register_event_helper, unregister_event_helper},
     qmp::qmp_channel::QmpChannel,
-    qmp::qmp_schema::BalloonInfo,
+    qmp::qmp_schema::{BalloonInfo, BalloonStats},
 };
 use util::{
     bitmap::Bitmap,
@@ -45,13 +49,16 @@ use util::{
     loop_context::{
         read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
     },
+    metrics::{register_metric, Metric, MetricType},
     num_ops::round_down,
     offset_of,
     seccomp::BpfRule,
     unix::host_page_size,
 };
 
+const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1;
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2;
+const VIRTIO_BALLOON_F_PAGE_POISON: u32 = 4;
 const VIRTIO_BALLOON_F_REPORTING: u32 = 5;
 /// The feature for Auto-balloon
 const VIRTIO_BALLOON_F_MESSAGE_VQ: u32 = 16;
@@ -63,6 +70,22 @@ const BALLOON_DEFLATE_EVENT: bool = false;
 const IN_IOVEC: bool = true;
 const OUT_IOVEC: bool = false;
 const BITS_OF_TYPE_U64: u64 = 64;
+/// Tags of memory statistics used by the balloon policy.
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+/// Names of memory statistics, indexed by the tag.
+const BALLOON_STAT_NAMES: [&str; 10] = [
+    "stat-swap-in",
+    "stat-swap-out",
+    "stat-major-faults",
+    "stat-minor-faults",
+    "stat-free-memory",
+    "stat-total-memory",
+    "stat-available-memory",
+    "stat-disk-caches",
+    "stat-htlb-pgalloc",
+    "stat-htlb-pgfail",
+];
 
 static mut BALLOON_DEV: Option<Arc<Mutex<Balloon>>> = None;
 
@@ -76,13 +99,21 @@ struct GuestIovec {
 }
 
 #[derive(Clone, Copy, Default)]
-#[allow(dead_code)]
 #[repr(packed(1))]
 struct BalloonStat {
-    _tag: u16,
+    tag: u16,
     val: u64,
 }
 
+/// Memory statistics reported by guest through the stats queue.
+#[derive(Default)]
+struct BalloonGuestStats {
+    /// Time of the report in seconds since the epoch.
+    last_update: u64,
+    /// Values of statistics indexed by the tag.
+    values: BTreeMap<u16, u64>,
+}
+
 /// Balloon configuration, which would be used to transport data between `Guest` and `Host`.
 #[derive(Copy, Clone, Default)]
 #[allow(dead_code)]
@@ -92,7 +123,9 @@ struct VirtioBalloonConfig {
     /// Number of pages we've actually got in balloon device.
     actual: u32,
     _reserved: u32,
-    _reserved1: u32,
+    /// The value guest uses to poison the free pages.
+    /// This parameter takes effect only when VIRTIO_BALLOON_F_PAGE_POISON is supported.
+    poison_val: u32,
     /// Buffer percent is a percentage of memory actually needed by
     /// the applications and services running inside the virtual machine.
     /// This parameter takes effect only when VIRTIO_BALLOON_F_MESSAGE_VQ is supported.
@@ -107,6 +140,23 @@ impl ByteCode for BalloonStat {}
 impl ByteCode for GuestIovec {}
 impl ByteCode for VirtioBalloonConfig {}
 
+/// Fill the balloon page with the poison value, as guest expects the free pages are poisoned.
+///
+/// # Arguments
+///
+/// * `hva` - The host virtual address of the balloon page.
+/// * `poison_val` - The poison value.
+fn poison_page(hva: u64, poison_val: u32) {
+    // SAFETY: hva is the host address of a 4K-aligned balloon page which is mapped for guest ram.
+    let page = unsafe {
+        std::slice::from_raw_parts_mut(
+            hva as *mut u32,
+            BALLOON_PAGE_SIZE as usize / size_of::<u32>(),
+        )
+    };
+    page.fill(poison_val);
+}
+
 /// Bitmap for balloon. It is used if the host page size is bigger than 4k.
 struct BalloonedPageBitmap {
     /// The start hva address of bitmap.
@@ -222,12 +272,15 @@ impl Request {
         Ok(request)
     }
 
-    fn balloon_deflate_page(&self, hvaset: &mut Vec<(u64, bool)>) {
+    fn balloon_deflate_page(&self, hvaset: &mut Vec<(u64, bool)>, poison_val: u32) {
         let mut free_len: u64 = 0;
         let mut start_addr: u64 = 0;
         let mut last_addr: u64 = 0;
 
         while let Some((hva, _)) = hvaset.pop() {
+            if poison_val != 0 {
+                poison_page(hva, poison_val);
+            }
             if last_addr == 0 {
                 free_len += 1;
                 start_addr = hva;
@@ -260,11 +313,13 @@ impl Request {
     ///
     /// * `req_type` - A label used to mark balloon pages.
     /// * `mem` - Collection of all Ram regions.
+    /// * `poison_val` - The value to poison the deflated pages, `0` means no poison.
     fn mark_balloon_page(
         &self,
         req_type: bool,
         address_space: &Arc<AddressSpace>,
         mem: &Arc<Mutex<BlnMemInfo>>,
+        poison_val: u32,
     ) {
         let mut last_addr: u64 = 0;
         let mut last_share = false;
@@ -291,7 +346,7 @@ impl Request {
         hvaset.sort_by_key(|&b| Reverse(b.0));
 
         if req_type == BALLOON_DEFLATE_EVENT {
-            self.balloon_deflate_page(&mut hvaset);
+            self.balloon_deflate_page(&mut hvaset, poison_val);
             return;
         }
 
@@ -577,6 +632,16 @@ struct BalloonIoHandler {
     def_queue: Arc<Mutex<Queue>>,
     /// Deflate EventFd.
     def_evt: Arc<EventFd>,
+    /// Stats queue.
+    stats_queue: Option<Arc<Mutex<Queue>>>,
+    /// Stats EventFd.
+    stats_evt: Option<Arc<EventFd>>,
+    /// The stats buffer held by device, it's returned to guest to poll statistics.
+    stats_desc_index: Option<u16>,
+    /// Timer to poll the memory statistics of guest.
+    stats_timer: Arc<Mutex<TimerFd>>,
+    /// Memory statistics reported by guest.
+    guest_stats: Arc<Mutex<BalloonGuestStats>>,
     /// Reporting queue.
     report_queue: Option<Arc<Mutex<Queue>>>,
     /// Reporting EventFd.
@@ -595,6 +660,8 @@ struct BalloonIoHandler {
     event_timer: Arc<Mutex<TimerFd>>,
     /// Actual balloon size
     balloon_actual: Arc<AtomicU32>,
+    /// The value guest uses to poison the free pages.
+    poison_val: Arc<AtomicU32>,
 }
 
 impl BalloonIoHandler {
@@ -627,7 +694,7 @@ impl BalloonIoHandler {
             let req = Request::parse(&elem, OUT_IOVEC)
                 .with_context(|| "Fail to parse available descriptor chain")?;
             if !self.mem_info.lock().unwrap().has_huge_page() {
-                req.mark_balloon_page(req_type, &self.mem_space, &self.mem_info);
+                req.mark_balloon_page(req_type, &self.mem_space, &self.mem_info, self.poison_val());
             }
             locked_queue
                 .vring
@@ -642,6 +709,15 @@ impl BalloonIoHandler {
         Ok(())
     }
 
+    /// Get the poison value of free pages, `0` if page poison is not negotiated.
+    fn poison_val(&self) -> u32 {
+        if virtio_has_feature(self.driver_features, VIRTIO_BALLOON_F_PAGE_POISON) {
+            self.poison_val.load(Ordering::Acquire)
+        } else {
+            0
+        }
+    }
+
     fn reporting_evt_handler(&mut self) -> Result<()> {
         let queue = self
             .report_queue
@@ -660,7 +736,9 @@ impl BalloonIoHandler {
             }
             let req = Request::parse(&elem, IN_IOVEC)
                 .with_context(|| "Fail to parse available descriptor chain")?;
-            if !self.mem_info.lock().unwrap().has_huge_page() {
+            // The reported pages are reused by guest without being deflated, they must keep
+            // the poison value, so they can't be discarded.
+            if !self.mem_info.lock().unwrap().has_huge_page() && self.poison_val() == 0 {
                 req.release_pages(&self.mem_info);
             }
             locked_queue
@@ -676,6 +754,69 @@ impl BalloonIoHandler {
         Ok(())
     }
 
+    fn stats_evt_handler(&mut self) -> Result<()> {
+        let queue = self
+            .stats_queue
+            .as_ref()
+            .with_context(|| VirtioError::VirtQueueIsNone)?;
+        let mut locked_queue = queue.lock().unwrap();
+
+        loop {
+            let elem = locked_queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for memory statistics")?;
+
+            if elem.desc_num == 0 {
+                break;
+            }
+            // The old buffer should have been returned, return it in case.
+            if let Some(desc_index) = self.stats_desc_index.replace(elem.index) {
+                locked_queue
+                    .vring
+                    .add_used(&self.mem_space, desc_index, 0)
+                    .with_context(|| "Failed to add balloon response into used queue")?;
+            }
+            let req = Request::parse(&elem, OUT_IOVEC)
+                .with_context(|| "Fail to parse available descriptor chain")?;
+            let mut values = BTreeMap::new();
+            for iov in req.iovec.iter() {
+                let mut offset = 0;
+                while let Some(stat) = iov_to_buf::<BalloonStat>(&self.mem_space, iov, offset) {
+                    offset += size_of::<BalloonStat>() as u64;
+                    values.insert(stat.tag, stat.val);
+                }
+            }
+            let mut guest_stats = self.guest_stats.lock().unwrap();
+            guest_stats.values = values;
+            guest_stats.last_update = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|t| t.as_secs())
+                .unwrap_or_default();
+        }
+
+        Ok(())
+    }
+
+    /// Return the stats buffer to guest, guest fills it with new statistics and adds it back.
+    fn stats_timer_handler(&mut self) -> Result<()> {
+        let desc_index = match self.stats_desc_index.take() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let queue = self
+            .stats_queue
+            .as_ref()
+            .with_context(|| VirtioError::VirtQueueIsNone)?;
+        let mut locked_queue = queue.lock().unwrap();
+        locked_queue
+            .vring
+            .add_used(&self.mem_space, desc_index, 0)
+            .with_context(|| "Failed to add balloon response into used queue")?;
+        (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+            .with_context(|| VirtioError::InterruptTrigger("balloon", VirtioInterruptType::Vring))
+    }
+
     fn auto_msg_evt_handler(&mut self) -> Result<()> {
         let queue = self
             .msg_queue
@@ -728,6 +869,7 @@ impl BalloonIoHandler {
         let balloon_size = self.get_balloon_memory_size();
         let msg = BalloonInfo {
             actual: ram_size - balloon_size,
+            target: None,
         };
         event!(BalloonChanged; msg);
     }
@@ -806,6 +948,51 @@ impl EventNotifierHelper for BalloonIoHandler {
             handler,
         ));
 
+        // register event notifier for memory statistics event.
+        if let Some(stats_evt) = locked_balloon_io.stats_evt.as_ref() {
+            let cloned_balloon_io = balloon_io.clone();
+            let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                let mut locked_balloon_io = cloned_balloon_io.lock().unwrap();
+                if locked_balloon_io.device_broken.load(Ordering::SeqCst) {
+                    return None;
+                }
+                if let Err(e) = locked_balloon_io.stats_evt_handler() {
+                    error!("Failed to get memory statistics: {:?}", e);
+                    report_virtio_error(
+                        locked_balloon_io.interrupt_cb.clone(),
+                        locked_balloon_io.driver_features,
+                        &locked_balloon_io.device_broken,
+                    );
+                }
+                None
+            });
+            notifiers.push(build_event_notifier(stats_evt.as_raw_fd(), handler));
+
+            // register event notifier for polling memory statistics.
+            let cloned_balloon_io = balloon_io.clone();
+            let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                let mut locked_balloon_io = cloned_balloon_io.lock().unwrap();
+                if locked_balloon_io.device_broken.load(Ordering::SeqCst) {
+                    return None;
+                }
+                if let Err(e) = locked_balloon_io.stats_timer_handler() {
+                    error!("Failed to poll memory statistics: {:?}", e);
+                    report_virtio_error(
+                        locked_balloon_io.interrupt_cb.clone(),
+                        locked_balloon_io.driver_features,
+                        &locked_balloon_io.device_broken,
+                    );
+                }
+                None
+            });
+            notifiers.push(build_event_notifier(
+                locked_balloon_io.stats_timer.lock().unwrap().as_raw_fd(),
+                handler,
+            ));
+        }
+
         // register event notifier for free page reporting event.
         if let Some(report_evt) = locked_balloon_io.report_evt.as_ref() {
             let cloned_balloon_io = balloon_io.clone();
@@ -882,6 +1069,8 @@ pub struct Balloon {
     bln_cfg: BalloonConfig,
     /// Actual memory pages of balloon device.
     actual: Arc<AtomicU32>,
+    /// The value guest uses to poison the free pages.
+    poison_val: Arc<AtomicU32>,
     /// Target memory pages of balloon device.
     num_pages: u32,
     /// Interrupt callback function.
@@ -892,6 +1081,14 @@ pub struct Balloon {
     mem_space: Arc<AddressSpace>,
     /// Event timer for BALLOON_CHANGED event.
     event_timer: Arc<Mutex<TimerFd>>,
+    /// Timer to poll the memory statistics of guest.
+    stats_timer: Arc<Mutex<TimerFd>>,
+    /// Interval to poll the memory statistics in seconds, 0 if polling is stopped.
+    stats_interval: u32,
+    /// Memory statistics reported by guest.
+    guest_stats: Arc<Mutex<BalloonGuestStats>>,
+    /// Gauge of the memory size taken by balloon device.
+    size_metric: Arc<Metric>,
 }
 
 impl Balloon {
@@ -902,6 +1099,9 @@ impl Balloon {
     /// * `bln_cfg` - Balloon configuration.
     pub fn new(bln_cfg: &BalloonConfig, mem_space: Arc<AddressSpace>) -> Balloon {
         let mut queue_num = QUEUE_NUM_BALLOON;
+        if bln_cfg.guest_stats {
+            queue_num += 1;
+        }
         if bln_cfg.free_page_reporting {
             queue_num += 1;
         }
@@ -913,11 +1113,21 @@ impl Balloon {
             base: VirtioBase::new(VIRTIO_TYPE_BALLOON, queue_num, DEFAULT_VIRTQUEUE_SIZE),
             bln_cfg: bln_cfg.clone(),
             actual: Arc::new(AtomicU32::new(0)),
+            poison_val: Arc::new(AtomicU32::new(0)),
             num_pages: 0u32,
             interrupt_cb: None,
             mem_info: Arc::new(Mutex::new(BlnMemInfo::new())),
             mem_space,
             event_timer: Arc::new(Mutex::new(TimerFd::new().unwrap())),
+            stats_timer: Arc::new(Mutex::new(TimerFd::new().unwrap())),
+            stats_interval: 0,
+            guest_stats: Arc::new(Mutex::new(BalloonGuestStats::default())),
+            size_metric: register_metric(
+                "stratovirt_balloon_size_bytes",
+                "Memory size taken by the balloon device.",
+                MetricType::Gauge,
+                &[],
+            ),
         }
     }
 
@@ -927,6 +1137,7 @@ impl Balloon {
         // words, this function will not be called simultaneously.
         unsafe {
             if BALLOON_DEV.is_none() {
+                register_balloon_policy_ops(Arc::new(BalloonPolicyDev { dev: dev.clone() }));
                 BALLOON_DEV = Some(dev)
             }
         }
@@ -966,11 +1177,41 @@ impl Balloon {
         })?;
         let msg = BalloonInfo {
             actual: self.get_guest_memory_size(),
+            target: None,
+        };
+        event!(BalloonChanged; msg);
+        Ok(())
+    }
+
+    /// Cancel the resizing in progress by setting the target to the actual size, the
+    /// pages which are being inflated or deflated by guest are kept in the balloon.
+    fn cancel_resize(&mut self) -> Result<()> {
+        let actual = self.actual.load(Ordering::Acquire);
+        if self.num_pages == actual {
+            return Ok(());
+        }
+        info!(
+            "Cancel balloon resizing, target {} pages, actual {} pages",
+            self.num_pages, actual
+        );
+        self.num_pages = actual;
+        self.signal_config_change().with_context(|| {
+            "Failed to notify about configuration change after canceling balloon resizing"
+        })?;
+        let msg = BalloonInfo {
+            actual: self.get_guest_memory_size(),
+            target: None,
         };
         event!(BalloonChanged; msg);
         Ok(())
     }
 
+    /// Get the target memory size of guest.
+    fn get_target_memory_size(&self) -> u64 {
+        self.mem_info.lock().unwrap().get_ram_size()
+            - ((self.num_pages as u64) << VIRTIO_BALLOON_PFN_SHIFT)
+    }
+
     /// Get the size of memory that reclaimed by balloon.
     fn get_balloon_memory_size(&self) -> u64 {
         (self.actual.load(Ordering::Acquire) as u64) << VIRTIO_BALLOON_PFN_SHIFT
@@ -984,6 +1225,89 @@ impl Balloon {
     fn set_num_pages(&mut self, target: u32) {
         self.num_pages = target;
     }
+
+    /// Set the interval to poll the memory statistics of guest.
+    ///
+    /// # Argument
+    ///
+    /// * `interval` - Polling interval in seconds, 0 to stop polling.
+    fn set_stats_polling_interval(&mut self, interval: u32) -> Result<()> {
+        if !self.bln_cfg.guest_stats {
+            bail!("guest-stats of balloon device is not enabled");
+        }
+        let mut timer = self.stats_timer.lock().unwrap();
+        if interval == 0 {
+            timer
+                .clear()
+                .with_context(|| "Failed to stop polling memory statistics")?;
+        } else {
+            let interval_dur = Duration::from_secs(interval as u64);
+            timer
+                .reset(interval_dur, Some(interval_dur))
+                .with_context(|| "Failed to start polling memory statistics")?;
+        }
+        self.stats_interval = interval;
+        Ok(())
+    }
+
+    fn get_guest_stats(&self) -> Result<BalloonStats> {
+        if !self.bln_cfg.guest_stats {
+            bail!("guest-stats of balloon device is not enabled");
+        }
+        let guest_stats = self.guest_stats.lock().unwrap();
+        let mut stats = BTreeMap::new();
+        for (tag, val) in guest_stats.values.iter() {
+            if let Some(name) = BALLOON_STAT_NAMES.get(*tag as usize) {
+                stats.insert(name.to_string(), *val);
+            }
+        }
+        Ok(BalloonStats {
+            last_update: guest_stats.last_update,
+            stats,
+        })
+    }
+}
+
+/// Balloon device adjusted by the automatic ballooning policy.
+struct BalloonPolicyDev {
+    dev: Arc<Mutex<Balloon>>,
+}
+
+impl BalloonPolicyOps for BalloonPolicyDev {
+    fn ram_size(&self) -> u64 {
+        let mem_info = self.dev.lock().unwrap().mem_info.clone();
+        let ram_size = mem_info.lock().unwrap().get_ram_size();
+        ram_size
+    }
+
+    fn guest_mem_stats(&self) -> Option<GuestMemStats> {
+        let locked_dev = self.dev.lock().unwrap();
+        let guest_stats = locked_dev.guest_stats.lock().unwrap();
+        let available = guest_stats
+            .values
+            .get(&VIRTIO_BALLOON_S_AVAIL)
+            .or_else(|| guest_stats.values.get(&VIRTIO_BALLOON_S_MEMFREE))?;
+        Some(GuestMemStats {
+            actual: locked_dev.get_guest_memory_size(),
+            available: *available,
+            last_update: guest_stats.last_update,
+        })
+    }
+
+    fn stats_polling_interval(&self) -> u32 {
+        self.dev.lock().unwrap().stats_interval
+    }
+
+    fn set_stats_polling_interval(&self, interval: u32) -> Result<()> {
+        self.dev
+            .lock()
+            .unwrap()
+            .set_stats_polling_interval(interval)
+    }
+
+    fn set_guest_memory_size(&self, size: u64) -> Result<()> {
+        self.dev.lock().unwrap().set_guest_memory_size(size)
+    }
 }
 
 impl VirtioDevice for Balloon {
@@ -1003,14 +1327,35 @@ impl VirtioDevice for Balloon {
         Ok(())
     }
 
+    fn unrealize(&mut self) -> Result<()> {
+        self.mem_space
+            .unregister_listener(self.mem_info.clone())
+            .with_context(|| "Failed to unregister memory listener defined by balloon device.")?;
+        // Only one balloon device is supported, drop the global reference of it so that
+        // the balloon QMP commands report that no balloon device exists.
+        unregister_balloon_policy_ops();
+        // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other
+        // words, this function will not be called simultaneously.
+        unsafe {
+            BALLOON_DEV = None;
+        }
+        Ok(())
+    }
+
     fn init_config_features(&mut self) -> Result<()> {
         self.base.device_features = 1u64 << VIRTIO_F_VERSION_1;
+        if self.bln_cfg.guest_stats {
+            self.base.device_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
+        }
         if self.bln_cfg.deflate_on_oom {
             self.base.device_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
         }
         if self.bln_cfg.free_page_reporting {
             self.base.device_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
         }
+        if self.bln_cfg.page_poison {
+            self.base.device_features |= 1u64 << VIRTIO_BALLOON_F_PAGE_POISON;
+        }
         if self.bln_cfg.auto_balloon {
             self.base.device_features |= 1u64 << VIRTIO_BALLOON_F_MESSAGE_VQ;
         }
@@ -1022,7 +1367,7 @@ impl VirtioDevice for Balloon {
             num_pages: self.num_pages,
             actual: self.actual.load(Ordering::Acquire),
             _reserved: 0_u32,
-            _reserved1: 0_u32,
+            poison_val: self.poison_val.load(Ordering::Acquire),
             membuf_percent: self.bln_cfg.membuf_percent,
             monitor_interval: self.bln_cfg.monitor_interval,
         };
@@ -1030,6 +1375,8 @@ impl VirtioDevice for Balloon {
         let config_len =
             if virtio_has_feature(self.base.device_features, VIRTIO_BALLOON_F_MESSAGE_VQ) {
                 size_of::<VirtioBalloonConfig>()
+            } else if virtio_has_feature(self.base.device_features, VIRTIO_BALLOON_F_PAGE_POISON) {
+                offset_of!(VirtioBalloonConfig, membuf_percent)
             } else {
                 offset_of!(VirtioBalloonConfig, _reserved)
             };
@@ -1038,16 +1385,26 @@ impl VirtioDevice for Balloon {
         read_config_default(config, offset, data)
     }
 
-    fn write_config(&mut self, _offset: u64, data: &[u8]) -> Result<()> {
-        // Guest update actual balloon size
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
         // Safe, because the results will be checked.
-        let old_actual = self.actual.load(Ordering::Acquire);
-        let new_actual = match unsafe { data.align_to::<u32>() } {
+        let new_config = match unsafe { data.align_to::<u32>() } {
             (_, [new_config], _) => *new_config,
             _ => {
                 return Err(anyhow!(VirtioError::FailedToWriteConfig));
             }
         };
+        // Guest update the poison value of free pages.
+        if offset == offset_of!(VirtioBalloonConfig, poison_val) as u64 {
+            if !virtio_has_feature(self.base.device_features, VIRTIO_BALLOON_F_PAGE_POISON) {
+                return Err(anyhow!(VirtioError::FailedToWriteConfig));
+            }
+            self.poison_val.store(new_config, Ordering::Release);
+            return Ok(());
+        }
+
+        // Guest update actual balloon size
+        let old_actual = self.actual.load(Ordering::Acquire);
+        let new_actual = new_config;
         if old_actual != new_actual {
             let mut timer = self.event_timer.lock().unwrap();
             if let Ok(ret) = timer.is_armed() {
@@ -1059,6 +1416,8 @@ impl VirtioDevice for Balloon {
             }
         }
         self.actual.store(new_actual, Ordering::Release);
+        self.size_metric
+            .set((new_actual as u64) << VIRTIO_BALLOON_PFN_SHIFT);
 
         Ok(())
     }
@@ -1082,8 +1441,17 @@ impl VirtioDevice for Balloon {
         let def_queue = queues[1].clone();
         let def_evt = queue_evts[1].clone();
 
-        // Get report queue and eventfd.
+        // Get stats queue and eventfd.
         let mut queue_index = 2;
+        let mut stats_queue = None;
+        let mut stats_evt = None;
+        if virtio_has_feature(self.base.device_features, VIRTIO_BALLOON_F_STATS_VQ) {
+            stats_queue = Some(queues[queue_index].clone());
+            stats_evt = Some(queue_evts[queue_index].clone());
+            queue_index += 1;
+        }
+
+        // Get report queue and eventfd.
         let mut report_queue = None;
         let mut report_evt = None;
         if virtio_has_feature(self.base.device_features, VIRTIO_BALLOON_F_REPORTING) {
@@ -1108,6 +1476,11 @@ impl VirtioDevice for Balloon {
             inf_evt,
             def_queue,
             def_evt,
+            stats_queue,
+            stats_evt,
+            stats_desc_index: None,
+            stats_timer: self.stats_timer.clone(),
+            guest_stats: self.guest_stats.clone(),
             report_queue,
             report_evt,
             msg_queue,
@@ -1117,6 +1490,7 @@ impl VirtioDevice for Balloon {
             mem_info: self.mem_info.clone(),
             event_timer: self.event_timer.clone(),
             balloon_actual: self.actual.clone(),
+            poison_val: self.poison_val.clone(),
         };
 
         let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
@@ -1137,6 +1511,11 @@ impl VirtioDevice for Balloon {
         }
         Ok(())
     }
+
+    fn iommu_supported(&self) -> bool {
+        // The page frame numbers reported by the guest are guest physical addresses.
+        false
+    }
 }
 
 pub fn qmp_balloon(target: u64) -> bool {
@@ -1157,16 +1536,46 @@ pub fn qmp_balloon(target: u64) -> bool {
     false
 }
 
-pub fn qmp_query_balloon() -> Option<u64> {
+pub fn qmp_query_balloon() -> Option<BalloonInfo> {
     // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other
     // words, this function will not be called simultaneously.
     if let Some(dev) = unsafe { &BALLOON_DEV } {
         let unlocked_dev = dev.lock().unwrap();
-        return Some(unlocked_dev.get_guest_memory_size());
+        return Some(BalloonInfo {
+            actual: unlocked_dev.get_guest_memory_size(),
+            target: Some(unlocked_dev.get_target_memory_size()),
+        });
     }
     None
 }
 
+pub fn qmp_balloon_cancel() -> Result<()> {
+    // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other
+    // words, this function will not be called simultaneously.
+    if let Some(dev) = unsafe { (*std::ptr::addr_of!(BALLOON_DEV)).as_ref() } {
+        return dev.lock().unwrap().cancel_resize();
+    }
+    bail!("No balloon device has been activated");
+}
+
+pub fn qmp_balloon_stats_interval(interval: u32) -> Result<()> {
+    // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other
+    // words, this function will not be called simultaneously.
+    if let Some(dev) = unsafe { (*std::ptr::addr_of!(BALLOON_DEV)).as_ref() } {
+        return dev.lock().unwrap().set_stats_polling_interval(interval);
+    }
+    bail!("No balloon device has been activated");
+}
+
+pub fn qmp_query_balloon_stats() -> Result<BalloonStats> {
+    // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other
+    // words, this function will not be called simultaneously.
+    if let Some(dev) = unsafe { (*std::ptr::addr_of!(BALLOON_DEV)).as_ref() } {
+        return dev.lock().unwrap().get_guest_stats();
+    }
+    bail!("No balloon device has been activated");
+}
+
 /// Create a syscall bpf rule for device `Balloon`.
 pub fn balloon_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     syscall_allow_list.extend(vec![
@@ -1234,9 +1643,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
 
         let mem_space = address_space_init();
@@ -1285,9 +1696,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
 
         let mem_space = address_space_init();
@@ -1307,9 +1720,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
 
         let mem_space = address_space_init();
@@ -1329,9 +1744,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
 
         let mem_space = address_space_init();
@@ -1350,9 +1767,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
 
         let mem_space = address_space_init();
@@ -1364,6 +1783,37 @@ mod tests {
         assert_eq!(balloon.actual.load(Ordering::Acquire), 1);
     }
 
+    #[test]
+    fn test_page_poison() {
+        let mut bln_cfg = BalloonConfig {
+            id: "bln".to_string(),
+            ..Default::default()
+        };
+        let mem_space = address_space_init();
+        let mut balloon = Balloon::new(&bln_cfg, mem_space.clone());
+        balloon.realize().unwrap();
+        let poison_offset = offset_of!(VirtioBalloonConfig, poison_val) as u64;
+        let write_data = 0xaaaa_aaaa_u32.to_le_bytes();
+        assert!(balloon.write_config(poison_offset, &write_data).is_err());
+
+        bln_cfg.page_poison = true;
+        let mut balloon = Balloon::new(&bln_cfg, mem_space);
+        balloon.realize().unwrap();
+        assert!(virtio_has_feature(
+            balloon.base.device_features,
+            VIRTIO_BALLOON_F_PAGE_POISON
+        ));
+        balloon.write_config(poison_offset, &write_data).unwrap();
+        assert_eq!(balloon.actual.load(Ordering::Acquire), 0);
+        let mut read_data = [0_u8; 4];
+        balloon.read_config(poison_offset, &mut read_data).unwrap();
+        assert_eq!(read_data, write_data);
+
+        let mut page = vec![0_u32; BALLOON_PAGE_SIZE as usize / size_of::<u32>()];
+        poison_page(page.as_mut_ptr() as u64, 0xaaaa_aaaa);
+        assert!(page.iter().all(|val| *val == 0xaaaa_aaaa));
+    }
+
     #[test]
     fn test_balloon_process() {
         let mem_space = address_space_init();
@@ -1371,9 +1821,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
         let mut bln = Balloon::new(&bln_cfg, mem_space.clone());
         bln.realize().unwrap();
@@ -1447,6 +1899,11 @@ mod tests {
             inf_evt: event_inf.clone(),
             def_queue: queue2,
             def_evt: event_def,
+            stats_queue: None,
+            stats_evt: None,
+            stats_desc_index: None,
+            stats_timer: bln.stats_timer.clone(),
+            guest_stats: bln.guest_stats.clone(),
             report_queue: None,
             report_evt: None,
             msg_queue: None,
@@ -1456,13 +1913,17 @@ mod tests {
             mem_info: bln.mem_info.clone(),
             event_timer: bln.event_timer.clone(),
             balloon_actual: bln.actual.clone(),
+            poison_val: bln.poison_val.clone(),
         };
 
         let balloon = Arc::new(Mutex::new(bln));
         Balloon::object_init(balloon);
 
         // Query balloon.
-        assert_eq!(qmp_query_balloon(), Some(MEMORY_SIZE));
+        assert_eq!(
+            qmp_query_balloon().map(|info| info.actual),
+            Some(MEMORY_SIZE)
+        );
 
         // Create SplitVringDesc and set addr to be 0x2000.
         let desc = SplitVringDesc {
@@ -1493,7 +1954,10 @@ mod tests {
 
         assert!(handler.process_balloon_queue(BALLOON_INFLATE_EVENT).is_ok());
         assert_eq!(handler.get_balloon_memory_size(), 0);
-        assert_eq!(qmp_query_balloon(), Some(MEMORY_SIZE));
+        assert_eq!(
+            qmp_query_balloon().map(|info| info.actual),
+            Some(MEMORY_SIZE)
+        );
 
         // SplitVringDesc for deflate.
         let desc = SplitVringDesc {
@@ -1520,6 +1984,43 @@ mod tests {
         assert!(handler.process_balloon_queue(BALLOON_DEFLATE_EVENT).is_ok());
     }
 
+    #[test]
+    fn test_balloon_cancel_resize() {
+        // BALLOON_CHANGED event is sent once the target is changed.
+        QmpChannel::object_init();
+        let bln_cfg = BalloonConfig {
+            id: "bln".to_string(),
+            deflate_on_oom: true,
+            ..Default::default()
+        };
+        let mem_space = address_space_init();
+        let mut bln = Balloon::new(&bln_cfg, mem_space);
+        let ram_fr1 = create_flat_range(0, MEMORY_SIZE, 0);
+        let blninfo = BlnMemInfo::new();
+        assert!(blninfo
+            .handle_request(Some(&ram_fr1), None, ListenerReqType::AddRegion)
+            .is_ok());
+        bln.mem_info = Arc::new(Mutex::new(blninfo));
+        let cb = Arc::new(Box::new(
+            move |_: &VirtioInterruptType, _: Option<&Queue>, _: bool| Ok(()),
+        ) as VirtioInterrupt);
+        bln.interrupt_cb = Some(cb);
+
+        // Guest inflates a part of the target pages.
+        assert!(bln.set_guest_memory_size(MEMORY_SIZE / 2).is_ok());
+        assert_eq!(bln.get_target_memory_size(), MEMORY_SIZE / 2);
+        bln.actual.store(1, Ordering::Release);
+        assert_eq!(
+            bln.get_guest_memory_size(),
+            MEMORY_SIZE - (1 << VIRTIO_BALLOON_PFN_SHIFT)
+        );
+
+        // The target is changed to the actual size at once.
+        assert!(bln.cancel_resize().is_ok());
+        assert_eq!(bln.num_pages, 1);
+        assert_eq!(bln.get_target_memory_size(), bln.get_guest_memory_size());
+    }
+
     #[test]
     fn test_balloon_activate() {
         let mem_space = address_space_init();
@@ -1555,9 +2056,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: Default::default(),
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
         let mut bln = Balloon::new(&bln_cfg, mem_space.clone());
         bln.base.queues = queues;
@@ -1624,9 +2127,11 @@ mod tests {
             id: "bln".to_string(),
             deflate_on_oom: true,
             free_page_reporting: true,
+            guest_stats: false,
             auto_balloon: false,
             membuf_percent: 0,
             monitor_interval: 0,
+            page_poison: false,
         };
         let mem_space = address_space_init();
         let mut bln = Balloon::new(&bln_cfg, mem_space);
@@ -1656,4 +2161,55 @@ mod tests {
 
         assert!(bln.update_config(None).is_err());
     }
+
+    #[test]
+    fn test_balloon_init_guest_stats() {
+        let bln_cfg = BalloonConfig {
+            id: "bln".to_string(),
+            deflate_on_oom: false,
+            free_page_reporting: true,
+            guest_stats: true,
+            auto_balloon: false,
+            membuf_percent: 0,
+            monitor_interval: 0,
+            page_poison: false,
+        };
+        let mem_space = address_space_init();
+        let mut bln = Balloon::new(&bln_cfg, mem_space);
+        bln.realize().unwrap();
+        assert_eq!(bln.queue_num(), 4);
+        let feature = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_BALLOON_F_STATS_VQ | 1u64 << VIRTIO_BALLOON_F_REPORTING);
+        assert_eq!(bln.base.device_features, feature);
+
+        // Test polling interval.
+        assert!(bln.set_stats_polling_interval(2).is_ok());
+        assert_eq!(bln.stats_interval, 2);
+        assert!(bln.stats_timer.lock().unwrap().is_armed().unwrap());
+        assert!(bln.set_stats_polling_interval(0).is_ok());
+        assert!(!bln.stats_timer.lock().unwrap().is_armed().unwrap());
+
+        // Test statistics reported by guest.
+        assert!(bln.get_guest_stats().unwrap().stats.is_empty());
+        {
+            let mut guest_stats = bln.guest_stats.lock().unwrap();
+            guest_stats.last_update = 100;
+            guest_stats.values.insert(VIRTIO_BALLOON_S_MEMFREE, 4096);
+            guest_stats.values.insert(VIRTIO_BALLOON_S_AVAIL, 8192);
+            guest_stats.values.insert(0xff, 1);
+        }
+        let stats = bln.get_guest_stats().unwrap();
+        assert_eq!(stats.last_update, 100);
+        assert_eq!(stats.stats.len(), 2);
+        assert_eq!(stats.stats.get("stat-free-memory"), Some(&4096));
+        assert_eq!(stats.stats.get("stat-available-memory"), Some(&8192));
+
+        let bln_cfg = BalloonConfig {
+            guest_stats: false,
+            ..bln_cfg
+        };
+        let mut bln = Balloon::new(&bln_cfg, address_space_init());
+        assert!(bln.set_stats_polling_interval(2).is_err());
+        assert!(bln.get_guest_stats().is_err());
+    }
 }
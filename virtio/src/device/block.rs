@@ -11,12 +11,12 @@
 // See the Mulan PSL v2 for more details.
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
@@ -29,19 +29,27 @@ use crate::{
     check_config_space_rw, gpa_hva_iovec_map, iov_discard_back, iov_discard_front, iov_to_buf,
     read_config_default, report_virtio_error, virtio_has_feature, Element, Queue, VirtioBase,
     VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO,
-    VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR,
-    VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP, VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH,
-    VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
+    VIRTIO_BLK_F_BLK_SIZE, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ,
+    VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_TOPOLOGY, VIRTIO_BLK_F_WRITE_ZEROES,
+    VIRTIO_BLK_F_ZONED, VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK,
+    VIRTIO_BLK_S_UNSUPP, VIRTIO_BLK_S_ZONE_ACTIVE_RESOURCE, VIRTIO_BLK_S_ZONE_INVALID_CMD,
+    VIRTIO_BLK_S_ZONE_OPEN_RESOURCE, VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID,
+    VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES, VIRTIO_BLK_T_ZONE_APPEND,
+    VIRTIO_BLK_T_ZONE_CLOSE, VIRTIO_BLK_T_ZONE_FINISH, VIRTIO_BLK_T_ZONE_OPEN,
+    VIRTIO_BLK_T_ZONE_REPORT, VIRTIO_BLK_T_ZONE_RESET, VIRTIO_BLK_T_ZONE_RESET_ALL,
     VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
     VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
 };
 use address_space::{AddressSpace, GuestAddress};
 use block_backend::{
-    create_block_backend, remove_block_backend, BlockDriverOps, BlockIoErrorCallback,
-    BlockProperty, BlockStatus,
+    create_block_backend,
+    dirty_bitmap::{register_dirty_bitmaps, unregister_dirty_bitmaps, DirtyBitmaps},
+    qcow2::QCOW2_LIST,
+    remove_block_backend,
+    zoned::{ZoneInfo, ZoneModel, ZoneOp, ZonedDevice},
+    BlockDriverOps, BlockIoErrorCallback, BlockProperty, BlockStatus,
 };
-use machine_manager::config::{BlkDevConfig, ConfigCheck, DriveFile, VmConfig};
+use machine_manager::config::{BlkDevConfig, ConfigCheck, DiskFormat, DriveFile, VmConfig};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper, EventLoop};
 use migration::{
     migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
@@ -53,10 +61,12 @@ use util::aio::{
     WriteZeroesState,
 };
 use util::byte_code::ByteCode;
+use util::file::BlockSize;
 use util::leak_bucket::LeakBucket;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::metrics::{register_metric, Metric, MetricType};
 use util::offset_of;
 
 /// Number of virtqueues.
@@ -77,6 +87,12 @@ const MAX_NUM_MERGE_BYTES: u64 = i32::MAX as u64;
 const MAX_MILLIS_TIME_PROCESS_QUEUE: u16 = 100;
 /// Max number sectors of per request.
 const MAX_REQUEST_SECTORS: u32 = u32::MAX >> SECTOR_SHIFT;
+/// Zone models of the zoned block device.
+const VIRTIO_BLK_Z_HM: u8 = 1;
+const VIRTIO_BLK_Z_HA: u8 = 2;
+/// Size of the header and the descriptor of the zone report.
+const ZONE_REPORT_HDR_SIZE: u64 = 64;
+const ZONE_DESC_SIZE: u64 = 64;
 
 type SenderConfig = (
     Option<Arc<Mutex<dyn BlockDriverOps<AioCompleteCb>>>>,
@@ -85,6 +101,7 @@ type SenderConfig = (
     u64,
     Option<String>,
     bool,
+    Option<Arc<Mutex<DirtyBitmaps>>>,
 );
 
 fn get_serial_num_config(serial_num: &str) -> Vec<u8> {
@@ -120,6 +137,112 @@ struct DiscardWriteZeroesSeg {
 
 impl ByteCode for DiscardWriteZeroesSeg {}
 
+/// Header of the zone report returned to the guest, followed by the zone descriptors.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VirtioBlkZoneReport {
+    nr_zones: u64,
+    reserved: [u8; 56],
+}
+
+impl Default for VirtioBlkZoneReport {
+    fn default() -> Self {
+        VirtioBlkZoneReport {
+            nr_zones: 0,
+            reserved: [0; 56],
+        }
+    }
+}
+
+impl ByteCode for VirtioBlkZoneReport {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VirtioBlkZoneDescriptor {
+    z_cap: u64,
+    z_start: u64,
+    z_wp: u64,
+    z_type: u8,
+    z_state: u8,
+    reserved: [u8; 38],
+}
+
+impl Default for VirtioBlkZoneDescriptor {
+    fn default() -> Self {
+        VirtioBlkZoneDescriptor {
+            z_cap: 0,
+            z_start: 0,
+            z_wp: 0,
+            z_type: 0,
+            z_state: 0,
+            reserved: [0; 38],
+        }
+    }
+}
+
+impl ByteCode for VirtioBlkZoneDescriptor {}
+
+impl From<&ZoneInfo> for VirtioBlkZoneDescriptor {
+    fn from(zone: &ZoneInfo) -> Self {
+        // The types and conditions of zone are the same in virtio and host.
+        VirtioBlkZoneDescriptor {
+            z_cap: zone.capacity.to_le(),
+            z_start: zone.start.to_le(),
+            z_wp: zone.wp.to_le(),
+            z_type: zone.zone_type,
+            z_state: zone.cond,
+            reserved: [0; 38],
+        }
+    }
+}
+
+/// IO counters of one block device.
+struct BlockMetrics {
+    read_bytes: Arc<Metric>,
+    write_bytes: Arc<Metric>,
+    read_requests: Arc<Metric>,
+    write_requests: Arc<Metric>,
+    latency: Arc<Metric>,
+}
+
+impl BlockMetrics {
+    fn new(id: &str) -> Self {
+        let labels = [("device", id)];
+        BlockMetrics {
+            read_bytes: register_metric(
+                "stratovirt_block_read_bytes_total",
+                "Number of bytes read by the block device.",
+                MetricType::Counter,
+                &labels,
+            ),
+            write_bytes: register_metric(
+                "stratovirt_block_write_bytes_total",
+                "Number of bytes written by the block device.",
+                MetricType::Counter,
+                &labels,
+            ),
+            read_requests: register_metric(
+                "stratovirt_block_read_requests_total",
+                "Number of read requests completed by the block device.",
+                MetricType::Counter,
+                &labels,
+            ),
+            write_requests: register_metric(
+                "stratovirt_block_write_requests_total",
+                "Number of write requests completed by the block device.",
+                MetricType::Counter,
+                &labels,
+            ),
+            latency: register_metric(
+                "stratovirt_block_request_latency_microseconds_total",
+                "Total latency of the requests completed by the block device.",
+                MetricType::Counter,
+                &labels,
+            ),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AioCompleteCb {
     queue: Arc<Mutex<Queue>>,
@@ -128,6 +251,14 @@ pub struct AioCompleteCb {
     req: Arc<Request>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
+    /// The shards dispatching this request and the id of the request in it.
+    shard: Option<(Arc<BlockShards>, u64)>,
+    /// Dirty bitmaps to record the sectors written by the request.
+    dirty_bitmaps: Option<Arc<Mutex<DirtyBitmaps>>>,
+    /// The sectors written by the request.
+    dirty_range: Option<SectorRange>,
+    /// IO counters of the device and the time the request is submitted.
+    metrics: Option<(Arc<BlockMetrics>, Instant)>,
 }
 
 impl AioCompleteCb {
@@ -144,18 +275,55 @@ impl AioCompleteCb {
             req,
             interrupt_cb,
             driver_features,
+            shard: None,
+            dirty_bitmaps: None,
+            dirty_range: None,
+            metrics: None,
         }
     }
 
     fn complete_request(&self, status: u8) -> Result<()> {
+        if status == VIRTIO_BLK_S_OK {
+            if let (Some(bitmaps), Some(range)) = (self.dirty_bitmaps.as_ref(), self.dirty_range) {
+                bitmaps.lock().unwrap().mark_dirty(
+                    range.start << SECTOR_SHIFT,
+                    (range.end - range.start) << SECTOR_SHIFT,
+                );
+            }
+        }
         let mut req = Some(self.req.as_ref());
         while let Some(req_raw) = req {
+            if status == VIRTIO_BLK_S_OK {
+                self.update_metrics(req_raw);
+            }
             self.complete_one_request(req_raw, status)?;
             req = req_raw.next.as_ref().as_ref();
         }
+        if let Some((shards, id)) = self.shard.as_ref() {
+            shards.release(*id)?;
+        }
         Ok(())
     }
 
+    fn update_metrics(&self, req: &Request) {
+        let (metrics, start) = match self.metrics.as_ref() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        match req.out_header.request_type {
+            VIRTIO_BLK_T_IN => {
+                metrics.read_bytes.add(req.data_len);
+                metrics.read_requests.inc();
+            }
+            VIRTIO_BLK_T_OUT => {
+                metrics.write_bytes.add(req.data_len);
+                metrics.write_requests.inc();
+            }
+            _ => return,
+        }
+        metrics.latency.add(start.elapsed().as_micros() as u64);
+    }
+
     fn complete_one_request(&self, req: &Request, status: u8) -> Result<()> {
         if let Err(ref e) = self.mem_space.write_object(&status, req.in_header) {
             bail!("Failed to write the status (blk io completion) {:?}", e);
@@ -253,13 +421,40 @@ impl Request {
         }
 
         match out_header.request_type {
+            VIRTIO_BLK_T_ZONE_APPEND
+            | VIRTIO_BLK_T_ZONE_REPORT
+            | VIRTIO_BLK_T_ZONE_OPEN
+            | VIRTIO_BLK_T_ZONE_CLOSE
+            | VIRTIO_BLK_T_ZONE_FINISH
+            | VIRTIO_BLK_T_ZONE_RESET
+            | VIRTIO_BLK_T_ZONE_RESET_ALL
+                if handler.zoned.is_none() =>
+            {
+                error!(
+                    "Zone request {} to non-zoned block device",
+                    out_header.request_type
+                );
+                *status = VIRTIO_BLK_S_UNSUPP;
+            }
+            // The append sector is returned in front of the status byte.
+            VIRTIO_BLK_T_ZONE_APPEND if in_iov_elem.len <= size_of::<u64>() as u32 => {
+                bail!(
+                    "Invalid in header for zone append request: length {}",
+                    in_iov_elem.len
+                );
+            }
             VIRTIO_BLK_T_IN
             | VIRTIO_BLK_T_GET_ID
             | VIRTIO_BLK_T_OUT
             | VIRTIO_BLK_T_DISCARD
-            | VIRTIO_BLK_T_WRITE_ZEROES => {
+            | VIRTIO_BLK_T_WRITE_ZEROES
+            | VIRTIO_BLK_T_ZONE_APPEND
+            | VIRTIO_BLK_T_ZONE_REPORT => {
                 let data_iovec = match out_header.request_type {
-                    VIRTIO_BLK_T_OUT | VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                    VIRTIO_BLK_T_OUT
+                    | VIRTIO_BLK_T_DISCARD
+                    | VIRTIO_BLK_T_WRITE_ZEROES
+                    | VIRTIO_BLK_T_ZONE_APPEND => {
                         iov_discard_front(&mut elem.out_iovec, size_of::<RequestOutHeader>() as u64)
                     }
                     // Otherwise discard the last "status" byte.
@@ -271,7 +466,12 @@ impl Request {
                 request.data_len = data_len;
                 request.iovec = iovec;
             }
-            VIRTIO_BLK_T_FLUSH => (),
+            VIRTIO_BLK_T_FLUSH
+            | VIRTIO_BLK_T_ZONE_OPEN
+            | VIRTIO_BLK_T_ZONE_CLOSE
+            | VIRTIO_BLK_T_ZONE_FINISH
+            | VIRTIO_BLK_T_ZONE_RESET
+            | VIRTIO_BLK_T_ZONE_RESET_ALL => (),
             others => {
                 error!("Request type {} is not supported for block", others);
                 *status = VIRTIO_BLK_S_UNSUPP;
@@ -287,9 +487,9 @@ impl Request {
 
     fn execute(
         &self,
-        iohandler: &mut BlockIoHandler,
+        ctx: &RequestContext,
         block_backend: Arc<Mutex<dyn BlockDriverOps<AioCompleteCb>>>,
-        aiocompletecb: AioCompleteCb,
+        mut aiocompletecb: AioCompleteCb,
     ) -> Result<()> {
         let mut req = Some(self);
         let mut iovecs = Vec::new();
@@ -315,7 +515,7 @@ impl Request {
             }
         }
 
-        let serial_num = &iohandler.serial_num;
+        let serial_num = &ctx.serial_num;
         let mut locked_backend = block_backend.lock().unwrap();
         match request_type {
             VIRTIO_BLK_T_IN => {
@@ -324,6 +524,11 @@ impl Request {
                     .with_context(|| "Failed to process block request for reading")?;
             }
             VIRTIO_BLK_T_OUT => {
+                aiocompletecb.dirty_range = Some(self.sector_range());
+                if let Some(zoned) = ctx.zoned.as_ref() {
+                    let range = aiocompletecb.dirty_range.unwrap();
+                    zoned.advance_wp(range.start, range.end - range.start);
+                }
                 locked_backend
                     .write_vectored(iovecs, offset, aiocompletecb)
                     .with_context(|| "Failed to process block request for writing")?;
@@ -346,35 +551,178 @@ impl Request {
                 aiocompletecb.complete_request(status)?;
             }
             VIRTIO_BLK_T_DISCARD => {
-                if !iohandler.discard {
+                if !ctx.discard {
                     error!("Device does not support discard");
                     return aiocompletecb.complete_request(VIRTIO_BLK_S_UNSUPP);
                 }
                 drop(locked_backend);
-                self.handle_discard_write_zeroes_req(iohandler, aiocompletecb, OpCode::Discard)?;
+                self.handle_discard_write_zeroes_req(
+                    ctx,
+                    block_backend,
+                    aiocompletecb,
+                    OpCode::Discard,
+                )?;
             }
             VIRTIO_BLK_T_WRITE_ZEROES => {
-                if iohandler.write_zeroes == WriteZeroesState::Off {
+                if ctx.write_zeroes == WriteZeroesState::Off {
                     error!("Device does not support write-zeroes");
                     return aiocompletecb.complete_request(VIRTIO_BLK_S_UNSUPP);
                 }
                 drop(locked_backend);
                 self.handle_discard_write_zeroes_req(
-                    iohandler,
+                    ctx,
+                    block_backend,
                     aiocompletecb,
                     OpCode::WriteZeroes,
                 )?;
             }
+            VIRTIO_BLK_T_ZONE_APPEND => {
+                drop(locked_backend);
+                self.handle_zone_append_req(ctx, block_backend, iovecs, aiocompletecb)?;
+            }
+            VIRTIO_BLK_T_ZONE_REPORT => {
+                let status = self.handle_zone_report_req(ctx);
+                aiocompletecb.complete_request(status)?;
+            }
+            VIRTIO_BLK_T_ZONE_OPEN
+            | VIRTIO_BLK_T_ZONE_CLOSE
+            | VIRTIO_BLK_T_ZONE_FINISH
+            | VIRTIO_BLK_T_ZONE_RESET
+            | VIRTIO_BLK_T_ZONE_RESET_ALL => {
+                let status = self.handle_zone_mgmt_req(ctx);
+                aiocompletecb.complete_request(status)?;
+            }
             // The illegal request type has been handled in method new().
             _ => {}
         };
         Ok(())
     }
 
+    /// Write the data at the write pointer of the zone, and return the written sector in
+    /// the in header.
+    fn handle_zone_append_req(
+        &self,
+        ctx: &RequestContext,
+        block_backend: Arc<Mutex<dyn BlockDriverOps<AioCompleteCb>>>,
+        iovecs: Vec<Iovec>,
+        mut aiocompletecb: AioCompleteCb,
+    ) -> Result<()> {
+        // The zone requests are only accepted by zoned device, see Request::new().
+        let zoned = ctx.zoned.as_ref().unwrap();
+        let zone_start = self.out_header.sector;
+        let nr_sectors = self.get_req_sector_num();
+        if nr_sectors > zoned.max_append_sectors as u64 {
+            error!(
+                "Zone append of {} sectors exceeds the limit {}",
+                nr_sectors, zoned.max_append_sectors
+            );
+            return aiocompletecb.complete_request(VIRTIO_BLK_S_IOERR);
+        }
+        let sector = match zoned.append_sector(zone_start, nr_sectors) {
+            Ok(sector) => sector,
+            Err(e) => {
+                error!("Failed to process zone append request: {:?}", e);
+                return aiocompletecb.complete_request(VIRTIO_BLK_S_ZONE_INVALID_CMD);
+            }
+        };
+
+        // The append sector is in front of the status byte of the in header.
+        let append_addr = GuestAddress(self.in_header.0 - size_of::<u64>() as u64);
+        aiocompletecb
+            .mem_space
+            .write_object(&sector.to_le(), append_addr)
+            .with_context(|| "Failed to write the sector of zone append")?;
+        aiocompletecb.dirty_range = Some(SectorRange::new(sector, sector + nr_sectors));
+        let offset = (sector as usize) << SECTOR_SHIFT;
+        let result = block_backend
+            .lock()
+            .unwrap()
+            .write_vectored(iovecs, offset, aiocompletecb);
+        if result.is_err() {
+            // The write pointer has been moved forward, reload it from the device.
+            zoned.refresh_wps()?;
+        }
+        result.with_context(|| "Failed to process block request for zone append")
+    }
+
+    fn handle_zone_report_req(&self, ctx: &RequestContext) -> u8 {
+        let zoned = ctx.zoned.as_ref().unwrap();
+        if self.data_len < ZONE_REPORT_HDR_SIZE || self.out_header.sector >= ctx.disk_sectors {
+            error!(
+                "Invalid zone report request, sector {}, buffer length {}",
+                self.out_header.sector, self.data_len
+            );
+            return VIRTIO_BLK_S_IOERR;
+        }
+        let max_zones = (self.data_len - ZONE_REPORT_HDR_SIZE) / ZONE_DESC_SIZE;
+        let zones = match zoned.report_zones(self.out_header.sector, max_zones as usize) {
+            Ok(zones) => zones,
+            Err(e) => {
+                error!("Failed to process zone report request: {:?}", e);
+                return VIRTIO_BLK_S_IOERR;
+            }
+        };
+
+        let report = VirtioBlkZoneReport {
+            nr_zones: (zones.len() as u64).to_le(),
+            ..Default::default()
+        };
+        let mut buf = report.as_bytes().to_vec();
+        for zone in zones.iter() {
+            buf.extend_from_slice(VirtioBlkZoneDescriptor::from(zone).as_bytes());
+        }
+        match iov_from_buf_direct(&self.iovec, &buf) {
+            Ok(_) => VIRTIO_BLK_S_OK,
+            Err(e) => {
+                error!("Failed to write zone report: {:?}", e);
+                VIRTIO_BLK_S_IOERR
+            }
+        }
+    }
+
+    /// The zone management is done synchronously by the ioctls of the host device.
+    fn handle_zone_mgmt_req(&self, ctx: &RequestContext) -> u8 {
+        let zoned = ctx.zoned.as_ref().unwrap();
+        let (op, sector, nr_sectors) = match self.out_header.request_type {
+            VIRTIO_BLK_T_ZONE_RESET_ALL => (ZoneOp::Reset, 0, ctx.disk_sectors),
+            request_type => {
+                let sector = self.out_header.sector;
+                if !sector.is_multiple_of(zoned.zone_sectors) || sector >= ctx.disk_sectors {
+                    error!("Zone management request to invalid sector {}", sector);
+                    return VIRTIO_BLK_S_ZONE_INVALID_CMD;
+                }
+                let op = match request_type {
+                    VIRTIO_BLK_T_ZONE_OPEN => ZoneOp::Open,
+                    VIRTIO_BLK_T_ZONE_CLOSE => ZoneOp::Close,
+                    VIRTIO_BLK_T_ZONE_FINISH => ZoneOp::Finish,
+                    _ => ZoneOp::Reset,
+                };
+                (op, sector, zoned.zone_sectors)
+            }
+        };
+        match zoned.zone_mgmt(op, sector, nr_sectors) {
+            Ok(()) => VIRTIO_BLK_S_OK,
+            Err(e) => {
+                error!("Failed to process zone management request: {:?}", e);
+                let errno = e
+                    .root_cause()
+                    .downcast_ref::<std::io::Error>()
+                    .and_then(|e| e.raw_os_error());
+                match errno {
+                    Some(libc::EINVAL) => VIRTIO_BLK_S_ZONE_INVALID_CMD,
+                    Some(libc::ETOOMANYREFS) => VIRTIO_BLK_S_ZONE_OPEN_RESOURCE,
+                    Some(libc::EOVERFLOW) => VIRTIO_BLK_S_ZONE_ACTIVE_RESOURCE,
+                    _ => VIRTIO_BLK_S_IOERR,
+                }
+            }
+        }
+    }
+
     fn handle_discard_write_zeroes_req(
         &self,
-        iohandler: &mut BlockIoHandler,
-        iocompletecb: AioCompleteCb,
+        ctx: &RequestContext,
+        block_backend: Arc<Mutex<dyn BlockDriverOps<AioCompleteCb>>>,
+        mut iocompletecb: AioCompleteCb,
         opcode: OpCode,
     ) -> Result<()> {
         let size = size_of::<DiscardWriteZeroesSeg>() as u64;
@@ -397,7 +745,7 @@ impl Request {
         let num_sectors = LittleEndian::read_u32(segment.num_sectors.as_bytes());
         if sector
             .checked_add(num_sectors as u64)
-            .filter(|&off| off <= iohandler.disk_sectors)
+            .filter(|&off| off <= ctx.disk_sectors)
             .is_none()
             || num_sectors > MAX_REQUEST_SECTORS
         {
@@ -413,8 +761,7 @@ impl Request {
             return iocompletecb.complete_request(VIRTIO_BLK_S_UNSUPP);
         }
 
-        // The block_backend is not None here.
-        let block_backend = iohandler.block_backend.as_ref().unwrap();
+        iocompletecb.dirty_range = Some(SectorRange::new(sector, sector + num_sectors as u64));
         let mut locked_backend = block_backend.lock().unwrap();
         let offset = (sector as usize) << SECTOR_SHIFT;
         let nbytes = (num_sectors as u64) << SECTOR_SHIFT;
@@ -427,7 +774,7 @@ impl Request {
                 .discard(offset, nbytes, iocompletecb)
                 .with_context(|| "Failed to process block request for discard")?;
         } else if opcode == OpCode::WriteZeroes {
-            let unmap = flags == VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP && iohandler.discard;
+            let unmap = flags == VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP && ctx.discard;
             locked_backend
                 .write_zeroes(offset, nbytes, iocompletecb, unmap)
                 .with_context(|| "Failed to process block request for write-zeroes")?;
@@ -437,7 +784,7 @@ impl Request {
 
     fn io_range_valid(&self, disk_sectors: u64) -> bool {
         match self.out_header.request_type {
-            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => {
+            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT | VIRTIO_BLK_T_ZONE_APPEND => {
                 if self.data_len % SECTOR_SIZE != 0 {
                     error!("Failed to process block request with size not aligned to 512B");
                     return false;
@@ -463,6 +810,243 @@ impl Request {
     fn get_req_sector_num(&self) -> u64 {
         self.data_len / SECTOR_SIZE
     }
+
+    /// Get the sectors accessed by the request, including the merged ones.
+    fn sector_range(&self) -> SectorRange {
+        match self.out_header.request_type {
+            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => {
+                let mut sector_num = 0;
+                let mut req = Some(self);
+                while let Some(req_raw) = req {
+                    sector_num += req_raw.get_req_sector_num();
+                    req = req_raw.next.as_ref().as_ref();
+                }
+                // Note: sector plus sector_num has been checked not overflow.
+                SectorRange::new(self.out_header.sector, self.out_header.sector + sector_num)
+            }
+            // The range of discard and write-zeroes is stored in the data, and the
+            // zone requests change the zones, just make them exclusive with all the
+            // other requests.
+            VIRTIO_BLK_T_DISCARD
+            | VIRTIO_BLK_T_WRITE_ZEROES
+            | VIRTIO_BLK_T_ZONE_APPEND
+            | VIRTIO_BLK_T_ZONE_REPORT
+            | VIRTIO_BLK_T_ZONE_OPEN
+            | VIRTIO_BLK_T_ZONE_CLOSE
+            | VIRTIO_BLK_T_ZONE_FINISH
+            | VIRTIO_BLK_T_ZONE_RESET
+            | VIRTIO_BLK_T_ZONE_RESET_ALL => SectorRange::new(0, u64::MAX),
+            _ => SectorRange::new(0, 0),
+        }
+    }
+}
+
+/// Properties of the block device used to execute requests.
+#[derive(Clone)]
+struct RequestContext {
+    /// The number of sectors of the disk image.
+    disk_sectors: u64,
+    /// Serial number of the block device.
+    serial_num: Option<String>,
+    /// Supporting discard or not.
+    discard: bool,
+    /// The write-zeroes state.
+    write_zeroes: WriteZeroesState,
+    /// The zoned block device of host.
+    zoned: Option<Arc<ZonedDevice>>,
+}
+
+/// Sectors [start, end) accessed by a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SectorRange {
+    start: u64,
+    end: u64,
+}
+
+impl SectorRange {
+    fn new(start: u64, end: u64) -> Self {
+        SectorRange { start, end }
+    }
+
+    fn overlaps(&self, other: &SectorRange) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// Ordering state of the requests dispatched to shards. A request is not
+/// dispatched until all the earlier requests overlapping with it are completed.
+struct ShardState<T> {
+    /// Id of the next request.
+    next_id: u64,
+    /// Id and sectors of the dispatched requests.
+    inflight: Vec<(u64, SectorRange)>,
+    /// Requests blocked by overlapping requests, in the order of submission.
+    waiting: VecDeque<(u64, SectorRange, T)>,
+}
+
+impl<T> ShardState<T> {
+    fn new() -> Self {
+        ShardState {
+            next_id: 0,
+            inflight: Vec::new(),
+            waiting: VecDeque::new(),
+        }
+    }
+
+    fn alloc_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn is_blocked(&self, range: &SectorRange) -> bool {
+        self.inflight.iter().any(|(_, r)| r.overlaps(range))
+            || self.waiting.iter().any(|(_, r, _)| r.overlaps(range))
+    }
+
+    /// Return the request if it can be dispatched now, otherwise keep it waiting.
+    fn admit(&mut self, id: u64, range: SectorRange, item: T) -> Option<T> {
+        if self.is_blocked(&range) {
+            self.waiting.push_back((id, range, item));
+            return None;
+        }
+        self.inflight.push((id, range));
+        Some(item)
+    }
+
+    /// Remove the completed request, and return the waiting requests which
+    /// are not blocked any more.
+    fn release(&mut self, id: u64) -> Vec<T> {
+        self.inflight.retain(|(inflight_id, _)| *inflight_id != id);
+
+        let mut ready = Vec::new();
+        let mut blocked: Vec<SectorRange> = Vec::new();
+        for (id, range, item) in std::mem::take(&mut self.waiting) {
+            if self.inflight.iter().any(|(_, r)| r.overlaps(&range))
+                || blocked.iter().any(|r| r.overlaps(&range))
+            {
+                blocked.push(range);
+                self.waiting.push_back((id, range, item));
+            } else {
+                self.inflight.push((id, range));
+                ready.push(item);
+            }
+        }
+        ready
+    }
+}
+
+/// Requests dispatched to a shard, waiting to be submitted in its iothread.
+struct BlockShard {
+    pending: Mutex<Vec<(Arc<Request>, AioCompleteCb)>>,
+    /// Eventfd to notify the iothread of the shard.
+    kick_evt: EventFd,
+}
+
+/// Shards of a single virtqueue. The virtqueue is still popped by its owner,
+/// while the requests are submitted and completed in the iothreads of shards.
+struct BlockShards {
+    shards: Vec<Arc<BlockShard>>,
+    /// Index of shard used by next dispatched request.
+    next_shard: AtomicUsize,
+    state: Mutex<ShardState<(Arc<Request>, AioCompleteCb)>>,
+}
+
+impl BlockShards {
+    fn new(shards: Vec<Arc<BlockShard>>) -> Self {
+        BlockShards {
+            shards,
+            next_shard: AtomicUsize::new(0),
+            state: Mutex::new(ShardState::new()),
+        }
+    }
+
+    fn submit(self: &Arc<Self>, req: Arc<Request>, mut aiocompletecb: AioCompleteCb) -> Result<()> {
+        let range = req.sector_range();
+        let mut locked_state = self.state.lock().unwrap();
+        let id = locked_state.alloc_id();
+        aiocompletecb.shard = Some((self.clone(), id));
+        let ready = locked_state.admit(id, range, (req, aiocompletecb));
+        drop(locked_state);
+
+        if let Some((req, aiocompletecb)) = ready {
+            self.dispatch(req, aiocompletecb)?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(&self, req: Arc<Request>, aiocompletecb: AioCompleteCb) -> Result<()> {
+        let index = self.next_shard.fetch_add(1, Ordering::SeqCst) % self.shards.len();
+        let shard = &self.shards[index];
+        shard.pending.lock().unwrap().push((req, aiocompletecb));
+        shard
+            .kick_evt
+            .write(1)
+            .with_context(|| VirtioError::EventFdWrite)
+    }
+
+    fn release(&self, id: u64) -> Result<()> {
+        let ready = self.state.lock().unwrap().release(id);
+        for (req, aiocompletecb) in ready {
+            self.dispatch(req, aiocompletecb)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.inflight.clear();
+        locked_state.waiting.clear();
+        drop(locked_state);
+        for shard in self.shards.iter() {
+            shard.pending.lock().unwrap().clear();
+        }
+    }
+}
+
+/// Handler of a shard, which submits requests in the iothread of the shard.
+struct BlockShardHandler {
+    shard: Arc<BlockShard>,
+    /// The block backend owned by the shard.
+    block_backend: Arc<Mutex<dyn BlockDriverOps<AioCompleteCb>>>,
+    ctx: RequestContext,
+    driver_features: u64,
+    device_broken: Arc<AtomicBool>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+}
+
+impl BlockShardHandler {
+    fn process_pending(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut *self.shard.pending.lock().unwrap());
+        for (req, aiocompletecb) in pending {
+            req.execute(&self.ctx, self.block_backend.clone(), aiocompletecb)?;
+        }
+        self.block_backend.lock().unwrap().flush_request()
+    }
+}
+
+impl EventNotifierHelper for BlockShardHandler {
+    fn internal_notifiers(handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut h_lock = h_clone.lock().unwrap();
+            if h_lock.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Err(ref e) = h_lock.process_pending() {
+                error!("Failed to handle block IO of shard {:?}", e);
+                report_virtio_error(
+                    h_lock.interrupt_cb.clone(),
+                    h_lock.driver_features,
+                    &h_lock.device_broken,
+                );
+            }
+            None
+        });
+        let kick_fd = handler.lock().unwrap().shard.kick_evt.as_raw_fd();
+        vec![build_event_notifier(kick_fd, vec![h], None)]
+    }
 }
 
 /// Control block of Block IO.
@@ -503,9 +1087,27 @@ struct BlockIoHandler {
     discard: bool,
     /// The write-zeroes state.
     write_zeroes: WriteZeroesState,
+    /// Shards which the requests are dispatched to.
+    shards: Option<Arc<BlockShards>>,
+    /// Dirty bitmaps of the block device.
+    dirty_bitmaps: Option<Arc<Mutex<DirtyBitmaps>>>,
+    /// IO counters of the block device.
+    metrics: Option<Arc<BlockMetrics>>,
+    /// The zoned block device of host.
+    zoned: Option<Arc<ZonedDevice>>,
 }
 
 impl BlockIoHandler {
+    fn request_context(&self) -> RequestContext {
+        RequestContext {
+            disk_sectors: self.disk_sectors,
+            serial_num: self.serial_num.clone(),
+            discard: self.discard,
+            write_zeroes: self.write_zeroes,
+            zoned: self.zoned.clone(),
+        }
+    }
+
     fn merge_req_queue(&self, mut req_queue: Vec<Request>) -> Vec<Request> {
         req_queue.sort_by(|a, b| a.out_header.sector.cmp(&b.out_header.sector));
 
@@ -602,18 +1204,26 @@ impl BlockIoHandler {
             return Ok(done);
         }
 
+        let ctx = self.request_context();
         let merge_req_queue = self.merge_req_queue(req_queue);
         for req in merge_req_queue.into_iter() {
             let req_rc = Arc::new(req);
-            let aiocompletecb = AioCompleteCb::new(
+            let mut aiocompletecb = AioCompleteCb::new(
                 self.queue.clone(),
                 self.mem_space.clone(),
                 req_rc.clone(),
                 self.interrupt_cb.clone(),
                 self.driver_features,
             );
-            if let Some(block_backend) = self.block_backend.as_ref() {
-                req_rc.execute(self, block_backend.clone(), aiocompletecb)?;
+            aiocompletecb.dirty_bitmaps = self.dirty_bitmaps.clone();
+            aiocompletecb.metrics = self
+                .metrics
+                .as_ref()
+                .map(|metrics| (metrics.clone(), Instant::now()));
+            if let Some(shards) = self.shards.as_ref() {
+                shards.submit(req_rc, aiocompletecb)?;
+            } else if let Some(block_backend) = self.block_backend.as_ref() {
+                req_rc.execute(&ctx, block_backend.clone(), aiocompletecb)?;
             } else {
                 warn!("Failed to execute block request, block_backend not specified");
                 aiocompletecb.complete_request(VIRTIO_BLK_S_IOERR)?;
@@ -644,6 +1254,8 @@ impl BlockIoHandler {
                 locked_status = status.lock().unwrap();
                 *locked_status = BlockStatus::NormalIO;
             }
+            // The backend may be switched while waiting for the status lock.
+            self.receive_pending_config();
         }
 
         let mut done = false;
@@ -731,16 +1343,31 @@ impl BlockIoHandler {
         complete_cb.complete_request(status)
     }
 
+    fn apply_config(&mut self, config: SenderConfig) {
+        let (image, req_align, buf_align, disk_sectors, serial_num, direct, dirty_bitmaps) = config;
+        self.disk_sectors = disk_sectors;
+        self.dirty_bitmaps = dirty_bitmaps;
+        self.block_backend = image;
+        self.req_align = req_align;
+        self.buf_align = buf_align;
+        self.serial_num = serial_num;
+        self.direct = direct;
+    }
+
+    fn receive_pending_config(&mut self) {
+        while let Ok(config) = self.receiver.try_recv() {
+            self.apply_config(config);
+        }
+    }
+
     fn update_evt_handler(&mut self) {
-        match self.receiver.recv() {
-            Ok((image, req_align, buf_align, disk_sectors, serial_num, direct)) => {
-                self.disk_sectors = disk_sectors;
-                self.block_backend = image;
-                self.req_align = req_align;
-                self.buf_align = buf_align;
-                self.serial_num = serial_num;
-                self.direct = direct;
+        match self.receiver.try_recv() {
+            Ok(config) => {
+                self.apply_config(config);
+                self.receive_pending_config();
             }
+            // The config has been received before processing queue.
+            Err(TryRecvError::Empty) => (),
             Err(e) => {
                 error!("Failed to receive config in updating handler {:?}", e);
                 self.disk_sectors = 0;
@@ -749,6 +1376,7 @@ impl BlockIoHandler {
                 self.buf_align = 1;
                 self.serial_num = None;
                 self.direct = true;
+                self.dirty_bitmaps = None;
             }
         };
 
@@ -928,6 +1556,31 @@ pub struct VirtioBlkConfig {
 
 impl ByteCode for VirtioBlkConfig {}
 
+/// Config space following `VirtioBlkConfig`, only available when `VIRTIO_BLK_F_ZONED` is set.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioBlkZonedConfig {
+    /// Secure erase is not supported.
+    max_secure_erase_sectors: u32,
+    max_secure_erase_seg: u32,
+    secure_erase_sector_alignment: u32,
+    /// Number of sectors of each zone.
+    zone_sectors: u32,
+    /// Max number of open zones, 0 means no limit.
+    max_open_zones: u32,
+    /// Max number of active zones, 0 means no limit.
+    max_active_zones: u32,
+    /// Max number of sectors of one zone append.
+    max_append_sectors: u32,
+    /// Write granularity in bytes.
+    write_granularity: u32,
+    /// Zone model.
+    model: u8,
+    unused2: [u8; 3],
+}
+
+impl ByteCode for VirtioBlkZonedConfig {}
+
 /// State of block device.
 #[repr(C)]
 #[derive(Clone, Copy, Desc, ByteCode)]
@@ -968,6 +1621,22 @@ pub struct Block {
     update_evts: Vec<Arc<EventFd>>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// Block backends opened by the shards of the virtqueue.
+    shard_backends: Vec<Arc<Mutex<dyn BlockDriverOps<AioCompleteCb>>>>,
+    /// Shards which the requests of the virtqueue are dispatched to.
+    shards: Option<Arc<BlockShards>>,
+    /// Eventfds of shard handlers registered in the shard iothreads.
+    shard_evts: Vec<Vec<RawFd>>,
+    /// Dirty bitmaps tracking the sectors written by guest.
+    dirty_bitmaps: Option<Arc<Mutex<DirtyBitmaps>>>,
+    /// IO counters of the block device.
+    metrics: Option<Arc<BlockMetrics>>,
+    /// The zoned block device of host, which is exposed to the guest with its zones.
+    zoned: Option<Arc<ZonedDevice>>,
+    /// Zoned characteristics in the config space.
+    zoned_config: VirtioBlkZonedConfig,
+    /// Logical and physical block size of the host file reported to guest.
+    block_size: BlockSize,
 }
 
 impl Block {
@@ -977,9 +1646,11 @@ impl Block {
     ) -> Block {
         let queue_num = blk_cfg.queues as usize;
         let queue_size = blk_cfg.queue_size;
+        let metrics = Some(Arc::new(BlockMetrics::new(&blk_cfg.id)));
         Self {
             base: VirtioBase::new(VIRTIO_TYPE_BLOCK, queue_num, queue_size),
             blk_cfg,
+            metrics,
             req_align: 1,
             buf_align: 1,
             drive_files,
@@ -997,11 +1668,18 @@ impl Block {
             self.config_space.num_queues = self.blk_cfg.queues;
         }
 
+        let logical = self.block_size.logical;
+        let physical = self.block_size.physical;
+        self.config_space.blk_size = logical;
+        // Both sizes are power of 2, and the physical one is not less than the logical one.
+        self.config_space.physical_block_exp = (physical / logical).trailing_zeros() as u8;
+        self.config_space.min_io_size = (physical / logical) as u16;
+
         if self.blk_cfg.discard {
             // Just support one segment per request.
             self.config_space.max_discard_seg = 1;
-            // The default discard alignment is 1 sector.
-            self.config_space.discard_sector_alignment = 1;
+            // The discard alignment is the logical block.
+            self.config_space.discard_sector_alignment = logical >> SECTOR_SHIFT;
             self.config_space.max_discard_sectors = MAX_REQUEST_SECTORS;
         }
 
@@ -1011,10 +1689,33 @@ impl Block {
             self.config_space.max_write_zeroes_sectors = MAX_REQUEST_SECTORS;
             self.config_space.write_zeroes_may_unmap = 1;
         }
+
+        self.zoned_config = VirtioBlkZonedConfig::default();
+        if let Some(zoned) = self.zoned.as_ref() {
+            self.zoned_config.zone_sectors = zoned.zone_sectors as u32;
+            self.zoned_config.max_open_zones = zoned.max_open_zones;
+            self.zoned_config.max_active_zones = zoned.max_active_zones;
+            self.zoned_config.max_append_sectors = zoned.max_append_sectors;
+            self.zoned_config.write_granularity = self.req_align.max(SECTOR_SIZE as u32);
+            self.zoned_config.model = match zoned.model {
+                ZoneModel::HostManaged => VIRTIO_BLK_Z_HM,
+                ZoneModel::HostAware => VIRTIO_BLK_Z_HA,
+            };
+        }
+    }
+
+    /// Get the whole config space, including the zoned characteristics.
+    fn get_blk_config(&self) -> Vec<u8> {
+        let mut config = self.config_space.as_bytes().to_vec();
+        config.extend_from_slice(self.zoned_config.as_bytes());
+        config.truncate(self.get_blk_config_size());
+        config
     }
 
     fn get_blk_config_size(&self) -> usize {
-        if virtio_has_feature(self.base.device_features, VIRTIO_BLK_F_WRITE_ZEROES) {
+        if virtio_has_feature(self.base.device_features, VIRTIO_BLK_F_ZONED) {
+            size_of::<VirtioBlkConfig>() + size_of::<VirtioBlkZonedConfig>()
+        } else if virtio_has_feature(self.base.device_features, VIRTIO_BLK_F_WRITE_ZEROES) {
             offset_of!(VirtioBlkConfig, unused1)
         } else if virtio_has_feature(self.base.device_features, VIRTIO_BLK_F_DISCARD) {
             offset_of!(VirtioBlkConfig, max_write_zeroes_sectors)
@@ -1030,6 +1731,256 @@ impl Block {
             report_virtio_error(interrupt_cb.clone(), cloned_features, &clone_broken);
         })
     }
+
+    fn activate_shards(
+        &mut self,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        ctx: RequestContext,
+    ) -> Result<()> {
+        let mut shards = Vec::new();
+        for (index, block_backend) in self.shard_backends.iter().enumerate() {
+            let shard = Arc::new(BlockShard {
+                pending: Mutex::new(Vec::new()),
+                kick_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            });
+            let handler = BlockShardHandler {
+                shard: shard.clone(),
+                block_backend: block_backend.clone(),
+                ctx: ctx.clone(),
+                driver_features: self.base.driver_features,
+                device_broken: self.base.broken.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+            };
+            let iothread = &self.blk_cfg.shard_iothreads[index];
+            let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+            let mut evts = Vec::new();
+            register_event_helper(notifiers, Some(iothread), &mut evts)?;
+            self.shard_evts.push(evts);
+
+            let err_cb = self.gen_error_cb(interrupt_cb.clone());
+            block_backend
+                .lock()
+                .unwrap()
+                .register_io_event(self.base.broken.clone(), err_cb)?;
+            shards.push(shard);
+        }
+        self.shards = Some(Arc::new(BlockShards::new(shards)));
+        Ok(())
+    }
+
+    fn block_property(&self, drive_id: &str) -> BlockProperty {
+        BlockProperty {
+            id: drive_id.to_string(),
+            format: self.blk_cfg.format,
+            iothread: self.blk_cfg.iothread.clone(),
+            direct: self.blk_cfg.direct,
+            req_align: self.req_align,
+            buf_align: self.buf_align,
+            discard: self.blk_cfg.discard,
+            write_zeroes: self.blk_cfg.write_zeroes,
+            l2_cache_size: self.blk_cfg.l2_cache_size,
+            refcount_cache_size: self.blk_cfg.refcount_cache_size,
+            key_secret: self.blk_cfg.key_secret.clone(),
+            copy_on_read: self.blk_cfg.copy_on_read,
+            io_timeout: self.blk_cfg.io_timeout,
+        }
+    }
+
+    /// Switch the backend to the qcow2 overlay whose backing file is the current image.
+    /// The overlay must have been registered in drive files. Requests are quiesced during
+    /// switching, and the guest keeps running.
+    pub fn snapshot_sync(&mut self, snapshot_file: &str) -> Result<()> {
+        self.replace_backend(snapshot_file, DiskFormat::Qcow2, || Ok(()))
+    }
+
+    /// Switch the backend to the image `path`, which must have been registered in drive
+    /// files. `quiesced` is called when the requests are quiesced before switching.
+    pub fn replace_backend<F>(&mut self, path: &str, format: DiskFormat, quiesced: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let backend = self
+            .block_backend
+            .clone()
+            .with_context(|| format!("No block backend of block device {}", self.blk_cfg.id))?;
+        if !self.shard_backends.is_empty() || self.zoned.is_some() {
+            bail!(
+                "Block device {} with shard iothreads or zones does not support switching image",
+                self.blk_cfg.id
+            );
+        }
+
+        let drive_files = self.drive_files.clone();
+        let locked_drive_files = drive_files.lock().unwrap();
+        let drive_id = VmConfig::get_drive_id(&locked_drive_files, &self.blk_cfg.path_on_host)?;
+        let file = VmConfig::fetch_drive_file(&locked_drive_files, path)?;
+        let alignments = VmConfig::fetch_drive_align(&locked_drive_files, path)?;
+        drop(locked_drive_files);
+
+        // Hold the status lock, so that the queue handlers can not submit requests to the
+        // old backend until they receive the new one.
+        let status = backend.lock().unwrap().get_status();
+        let _locked_status = status.lock().unwrap();
+        backend.lock().unwrap().drain_request();
+        if let Some(qcow2) = QCOW2_LIST.lock().unwrap().get(&drive_id) {
+            qcow2.lock().unwrap().flush_metadata()?;
+        }
+        quiesced()?;
+
+        let (req_align, buf_align) = (self.req_align, self.buf_align);
+        self.req_align = alignments.0;
+        self.buf_align = alignments.1;
+        let aio = Aio::new(Arc::new(BlockIoHandler::complete_func), self.blk_cfg.aio)?;
+        let conf = BlockProperty {
+            format,
+            ..self.block_property(&drive_id)
+        };
+        // The old qcow2 is replaced in the qcow2 list and exit notifiers.
+        let new_backend = match create_block_backend(file, aio, conf) {
+            Ok(new_backend) => new_backend,
+            Err(e) => {
+                self.req_align = req_align;
+                self.buf_align = buf_align;
+                return Err(e);
+            }
+        };
+
+        if self.device_activated() {
+            if let Some(cb) = self.interrupt_cb.as_ref() {
+                let err_cb = self.gen_error_cb(cb.clone());
+                new_backend
+                    .lock()
+                    .unwrap()
+                    .register_io_event(self.base.broken.clone(), err_cb)?;
+                backend.lock().unwrap().unregister_io_event()?;
+            }
+        }
+        if self.blk_cfg.format == DiskFormat::Qcow2 && format != DiskFormat::Qcow2 {
+            remove_block_backend(&drive_id);
+        }
+        self.block_backend = Some(new_backend);
+        self.blk_cfg.path_on_host = path.to_string();
+        self.blk_cfg.format = format;
+
+        for sender in &self.senders {
+            sender
+                .send((
+                    self.block_backend.clone(),
+                    self.req_align,
+                    self.buf_align,
+                    self.disk_sectors,
+                    self.blk_cfg.serial_num.clone(),
+                    self.blk_cfg.direct,
+                    self.dirty_bitmaps.clone(),
+                ))
+                .with_context(|| VirtioError::ChannelSend("image fd".to_string()))?;
+        }
+        for update_evt in &self.update_evts {
+            update_evt
+                .write(1)
+                .with_context(|| VirtioError::EventFdWrite)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the medium of the cdrom, the guest sees an empty disk after it re-reads the capacity.
+    pub fn eject_medium(&mut self) -> Result<()> {
+        self.change_medium(None)
+    }
+
+    /// Insert the image `path` as the medium of the cdrom, the image must have been registered
+    /// in drive files.
+    pub fn insert_medium(&mut self, path: &str, format: DiskFormat) -> Result<()> {
+        self.change_medium(Some((path, format)))
+    }
+
+    fn change_medium(&mut self, medium: Option<(&str, DiskFormat)>) -> Result<()> {
+        if self.blk_cfg.media != "cdrom" {
+            bail!("Block device {} is not removable", self.blk_cfg.id);
+        }
+
+        let drive_files = self.drive_files.clone();
+        let locked_drive_files = drive_files.lock().unwrap();
+        if let Some(backend) = self.block_backend.take() {
+            let mut locked_backend = backend.lock().unwrap();
+            locked_backend.drain_request();
+            if self.device_activated() {
+                locked_backend.unregister_io_event()?;
+            }
+            drop(locked_backend);
+            let drive_id = VmConfig::get_drive_id(&locked_drive_files, &self.blk_cfg.path_on_host)?;
+            remove_block_backend(&drive_id);
+            unregister_dirty_bitmaps(&drive_id);
+        }
+        self.dirty_bitmaps = None;
+        self.blk_cfg.path_on_host = String::new();
+        self.req_align = 1;
+        self.buf_align = 1;
+        self.disk_sectors = DUMMY_IMG_SIZE >> SECTOR_SHIFT;
+
+        if let Some((path, format)) = medium {
+            let file = VmConfig::fetch_drive_file(&locked_drive_files, path)?;
+            let alignments = VmConfig::fetch_drive_align(&locked_drive_files, path)?;
+            let drive_id = VmConfig::get_drive_id(&locked_drive_files, path)?;
+            let aio = Aio::new(Arc::new(BlockIoHandler::complete_func), self.blk_cfg.aio)?;
+            self.blk_cfg.format = format;
+            let backend = create_block_backend(file, aio, self.block_property(&drive_id))?;
+            if let Some(cb) = self
+                .interrupt_cb
+                .as_ref()
+                .filter(|_| self.device_activated())
+            {
+                let err_cb = self.gen_error_cb(cb.clone());
+                backend
+                    .lock()
+                    .unwrap()
+                    .register_io_event(self.base.broken.clone(), err_cb)?;
+            }
+            self.disk_sectors = backend.lock().unwrap().disk_size()? >> SECTOR_SHIFT;
+            (self.req_align, self.buf_align) = alignments;
+            self.block_backend = Some(backend);
+            self.blk_cfg.path_on_host = path.to_string();
+        }
+        drop(locked_drive_files);
+        self.config_space.capacity = self.disk_sectors;
+
+        // The queue handlers apply the new medium and notify the guest of the config change.
+        for sender in &self.senders {
+            sender
+                .send((
+                    self.block_backend.clone(),
+                    self.req_align,
+                    self.buf_align,
+                    self.disk_sectors,
+                    self.blk_cfg.serial_num.clone(),
+                    self.blk_cfg.direct,
+                    self.dirty_bitmaps.clone(),
+                ))
+                .with_context(|| VirtioError::ChannelSend("image fd".to_string()))?;
+        }
+        for update_evt in &self.update_evts {
+            update_evt
+                .write(1)
+                .with_context(|| VirtioError::EventFdWrite)?;
+        }
+        Ok(())
+    }
+
+    fn deactivate_shards(&mut self) -> Result<()> {
+        for (index, evts) in self.shard_evts.iter_mut().enumerate() {
+            unregister_event_helper(Some(&self.blk_cfg.shard_iothreads[index]), evts)?;
+        }
+        self.shard_evts.clear();
+        for block_backend in self.shard_backends.iter() {
+            let mut block_backend = block_backend.lock().unwrap();
+            block_backend.drain_request();
+            block_backend.unregister_io_event()?;
+        }
+        if let Some(shards) = self.shards.take() {
+            shards.clear();
+        }
+        Ok(())
+    }
 }
 
 impl VirtioDevice for Block {
@@ -1051,6 +2002,16 @@ impl VirtioDevice for Block {
                 self.blk_cfg.iothread,
             );
         }
+        for iothread in self.blk_cfg.shard_iothreads.iter() {
+            if EventLoop::get_ctx(Some(iothread)).is_none() {
+                bail!(
+                    "Shard IOThread {:?} of Block is not configured in params.",
+                    iothread
+                );
+            }
+        }
+
+        self.shard_backends.clear();
 
         if !self.blk_cfg.path_on_host.is_empty() {
             let drive_files = self.drive_files.lock().unwrap();
@@ -1058,29 +2019,53 @@ impl VirtioDevice for Block {
             let alignments = VmConfig::fetch_drive_align(&drive_files, &self.blk_cfg.path_on_host)?;
             self.req_align = alignments.0;
             self.buf_align = alignments.1;
+            self.block_size =
+                VmConfig::fetch_drive_block_size(&drive_files, &self.blk_cfg.path_on_host)?;
             let drive_id = VmConfig::get_drive_id(&drive_files, &self.blk_cfg.path_on_host)?;
 
             let aio = Aio::new(Arc::new(BlockIoHandler::complete_func), self.blk_cfg.aio)?;
-            let conf = BlockProperty {
-                id: drive_id,
-                format: self.blk_cfg.format,
-                iothread: self.blk_cfg.iothread.clone(),
-                direct: self.blk_cfg.direct,
-                req_align: self.req_align,
-                buf_align: self.buf_align,
-                discard: self.blk_cfg.discard,
-                write_zeroes: self.blk_cfg.write_zeroes,
-                l2_cache_size: self.blk_cfg.l2_cache_size,
-                refcount_cache_size: self.blk_cfg.refcount_cache_size,
+            let conf = self.block_property(&drive_id);
+            if !self.blk_cfg.shard_iothreads.is_empty() && conf.format != DiskFormat::Raw {
+                bail!("Shard iothreads of Block only support raw format");
+            }
+            self.zoned = match conf.format {
+                DiskFormat::Raw => ZonedDevice::probe(&file)
+                    .with_context(|| "Failed to probe zoned block device")?
+                    .map(Arc::new),
+                _ => None,
             };
+            for iothread in self.blk_cfg.shard_iothreads.iter() {
+                // Each shard owns a backend, so that AIO is submitted and completed in its iothread.
+                let shard_aio =
+                    Aio::new(Arc::new(BlockIoHandler::complete_func), self.blk_cfg.aio)?;
+                let shard_conf = BlockProperty {
+                    iothread: Some(iothread.clone()),
+                    ..conf.clone()
+                };
+                let shard_file = file
+                    .try_clone()
+                    .with_context(|| "Failed to clone drive backend file for shard")?;
+                self.shard_backends
+                    .push(create_block_backend(shard_file, shard_aio, shard_conf)?);
+            }
             let backend = create_block_backend(file, aio, conf)?;
             let disk_size = backend.lock().unwrap().disk_size()?;
+            // Persistent dirty bitmaps are stored beside the image, which must be a regular file.
+            let bitmaps_path = std::fs::metadata(&self.blk_cfg.path_on_host)
+                .is_ok_and(|meta| meta.is_file())
+                .then(|| format!("{}.bitmaps", self.blk_cfg.path_on_host));
+            self.dirty_bitmaps = Some(
+                register_dirty_bitmaps(&drive_id, disk_size, bitmaps_path)
+                    .with_context(|| "Failed to load dirty bitmaps")?,
+            );
             self.block_backend = Some(backend);
             self.disk_sectors = disk_size >> SECTOR_SHIFT;
         } else {
             self.req_align = 1;
             self.buf_align = 1;
             self.block_backend = None;
+            self.dirty_bitmaps = None;
+            self.zoned = None;
             self.disk_sectors = DUMMY_IMG_SIZE >> SECTOR_SHIFT;
         }
 
@@ -1107,6 +2092,16 @@ impl VirtioDevice for Block {
         if self.blk_cfg.write_zeroes != WriteZeroesState::Off {
             self.base.device_features |= 1_u64 << VIRTIO_BLK_F_WRITE_ZEROES;
         }
+        if self.zoned.is_some() {
+            self.base.device_features |= 1_u64 << VIRTIO_BLK_F_ZONED;
+        }
+        // The guest assumes 512 bytes block if they are not offered.
+        if self.block_size.logical != SECTOR_SIZE as u32 {
+            self.base.device_features |= 1_u64 << VIRTIO_BLK_F_BLK_SIZE;
+        }
+        if self.block_size.physical != self.block_size.logical {
+            self.base.device_features |= 1_u64 << VIRTIO_BLK_F_TOPOLOGY;
+        }
         self.build_device_config_space();
 
         Ok(())
@@ -1114,22 +2109,22 @@ impl VirtioDevice for Block {
 
     fn unrealize(&mut self) -> Result<()> {
         MigrationManager::unregister_device_instance(BlockState::descriptor(), &self.blk_cfg.id);
+        if self.blk_cfg.path_on_host.is_empty() {
+            return Ok(());
+        }
         let drive_files = self.drive_files.lock().unwrap();
         let drive_id = VmConfig::get_drive_id(&drive_files, &self.blk_cfg.path_on_host)?;
         remove_block_backend(&drive_id);
+        unregister_dirty_bitmaps(&drive_id);
         Ok(())
     }
 
     fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
-        let config_len = self.get_blk_config_size();
-        let config = &self.config_space.as_bytes()[..config_len];
-        read_config_default(config, offset, data)
+        read_config_default(&self.get_blk_config(), offset, data)
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
-        let config_len = self.get_blk_config_size();
-        let config = &self.config_space.as_bytes()[..config_len];
-        check_config_space_rw(config, offset, data)?;
+        check_config_space_rw(&self.get_blk_config(), offset, data)?;
         // The only writable field is "writeback", but it's not supported for now,
         // so do nothing here.
         Ok(())
@@ -1142,6 +2137,16 @@ impl VirtioDevice for Block {
         queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
         self.interrupt_cb = Some(interrupt_cb.clone());
+        if !self.shard_backends.is_empty() {
+            let ctx = RequestContext {
+                disk_sectors: self.disk_sectors,
+                serial_num: self.blk_cfg.serial_num.clone(),
+                discard: self.blk_cfg.discard,
+                write_zeroes: self.blk_cfg.write_zeroes,
+                zoned: self.zoned.clone(),
+            };
+            self.activate_shards(interrupt_cb.clone(), ctx)?;
+        }
         let queues = self.base.queues.clone();
         for (index, queue) in queues.iter().enumerate() {
             if !queue.lock().unwrap().is_enabled() {
@@ -1172,6 +2177,10 @@ impl VirtioDevice for Block {
                 },
                 discard: self.blk_cfg.discard,
                 write_zeroes: self.blk_cfg.write_zeroes,
+                shards: self.shards.clone(),
+                dirty_bitmaps: self.dirty_bitmaps.clone(),
+                metrics: self.metrics.clone(),
+                zoned: self.zoned.clone(),
             };
 
             let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
@@ -1213,6 +2222,7 @@ impl VirtioDevice for Block {
             block_backend.drain_request();
             block_backend.unregister_io_event()?;
         }
+        self.deactivate_shards()?;
         self.update_evts.clear();
         self.senders.clear();
         Ok(())
@@ -1226,8 +2236,9 @@ impl VirtioDevice for Block {
                 .downcast_ref::<BlkDevConfig>()
                 .unwrap()
                 .clone();
-            // microvm type block device don't support multiple queue.
+            // microvm type block device don't support multiple queue and shards.
             self.blk_cfg.queues = QUEUE_NUM_BLK as u16;
+            self.blk_cfg.shard_iothreads.clear();
         } else {
             self.blk_cfg = Default::default();
         }
@@ -1246,6 +2257,12 @@ impl VirtioDevice for Block {
         }
 
         self.realize()?;
+        if self.zoned.is_some() {
+            bail!(
+                "Zoned block device {} can't be hot plugged",
+                self.blk_cfg.id
+            );
+        }
 
         if is_plug {
             // Block backend is set after device realized.
@@ -1280,6 +2297,7 @@ impl VirtioDevice for Block {
                     self.disk_sectors,
                     self.blk_cfg.serial_num.clone(),
                     self.blk_cfg.direct,
+                    self.dirty_bitmaps.clone(),
                 ))
                 .with_context(|| VirtioError::ChannelSend("image fd".to_string()))?;
         }
@@ -1516,6 +2534,115 @@ mod tests {
         assert_eq!(id_bytes_temp.len(), 20);
     }
 
+    #[test]
+    fn test_zoned_config() {
+        assert_eq!(size_of::<VirtioBlkZonedConfig>(), 36);
+        assert_eq!(
+            size_of::<VirtioBlkZoneReport>() as u64,
+            ZONE_REPORT_HDR_SIZE
+        );
+        assert_eq!(size_of::<VirtioBlkZoneDescriptor>() as u64, ZONE_DESC_SIZE);
+
+        let mut block = Block::new(
+            BlkDevConfig::default(),
+            Arc::new(Mutex::new(HashMap::new())),
+        );
+        block.realize().unwrap();
+        assert_eq!(block.device_features(0) & (1 << VIRTIO_BLK_F_ZONED), 0);
+        let config_len = block.get_blk_config_size();
+        assert_eq!(block.get_blk_config().len(), config_len);
+
+        block.base.device_features |= 1 << VIRTIO_BLK_F_ZONED;
+        block.zoned_config.zone_sectors = 0x1000;
+        block.zoned_config.model = VIRTIO_BLK_Z_HM;
+        assert_eq!(block.get_blk_config().len(), 96);
+        let mut data = [0_u8; 4];
+        block.read_config(72, &mut data).unwrap();
+        assert_eq!(u32::from_le_bytes(data), 0x1000);
+        block.read_config(92, &mut data[..1]).unwrap();
+        assert_eq!(data[0], VIRTIO_BLK_Z_HM);
+        assert!(block.read_config(96, &mut data[..1]).is_err());
+    }
+
+    #[test]
+    fn test_block_size_config() {
+        let mut block = init_default_block();
+        block.realize().unwrap();
+        block.init_config_features().unwrap();
+        assert_eq!(block.device_features(0) & (1 << VIRTIO_BLK_F_BLK_SIZE), 0);
+        assert_eq!(block.device_features(0) & (1 << VIRTIO_BLK_F_TOPOLOGY), 0);
+
+        // 512 bytes logical block and 4K physical block.
+        block.block_size = BlockSize {
+            logical: 512,
+            physical: 4096,
+        };
+        block.init_config_features().unwrap();
+        assert_eq!(block.device_features(0) & (1 << VIRTIO_BLK_F_BLK_SIZE), 0);
+        assert_ne!(block.device_features(0) & (1 << VIRTIO_BLK_F_TOPOLOGY), 0);
+        let mut data = [0_u8; 4];
+        block.read_config(24, &mut data).unwrap();
+        assert_eq!(data, [3, 0, 8, 0]);
+
+        // 4Kn disk.
+        block.block_size = BlockSize {
+            logical: 4096,
+            physical: 4096,
+        };
+        block.init_config_features().unwrap();
+        assert_ne!(block.device_features(0) & (1 << VIRTIO_BLK_F_BLK_SIZE), 0);
+        assert_eq!(block.device_features(0) & (1 << VIRTIO_BLK_F_TOPOLOGY), 0);
+        block.read_config(20, &mut data).unwrap();
+        assert_eq!(u32::from_le_bytes(data), 4096);
+        block.read_config(24, &mut data).unwrap();
+        assert_eq!(data, [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_shard_request_order() {
+        let mut req = Request {
+            desc_index: 0,
+            out_header: RequestOutHeader {
+                request_type: VIRTIO_BLK_T_OUT,
+                io_prio: 0,
+                sector: 8,
+            },
+            iovec: Vec::new(),
+            data_len: 8 * SECTOR_SIZE,
+            in_len: 1,
+            in_header: GuestAddress(0),
+            next: Box::new(None),
+        };
+        let mut merged = req.clone();
+        merged.out_header.sector = 16;
+        *req.next = Some(merged);
+        assert_eq!(req.sector_range(), SectorRange::new(8, 24));
+        req.out_header.request_type = VIRTIO_BLK_T_DISCARD;
+        assert_eq!(req.sector_range(), SectorRange::new(0, u64::MAX));
+        req.out_header.request_type = VIRTIO_BLK_T_ZONE_APPEND;
+        assert_eq!(req.sector_range(), SectorRange::new(0, u64::MAX));
+        req.out_header.request_type = VIRTIO_BLK_T_FLUSH;
+        assert_eq!(req.sector_range(), SectorRange::new(0, 0));
+
+        let mut state = ShardState::new();
+        let ids: Vec<u64> = (0..5).map(|_| state.alloc_id()).collect();
+        assert_eq!(state.admit(ids[0], SectorRange::new(0, 8), 0), Some(0));
+        // Overlapping with the inflight request.
+        assert_eq!(state.admit(ids[1], SectorRange::new(4, 12), 1), None);
+        // Overlapping with the waiting request only.
+        assert_eq!(state.admit(ids[2], SectorRange::new(10, 16), 2), None);
+        assert_eq!(state.admit(ids[3], SectorRange::new(100, 108), 3), Some(3));
+        // Requests without sectors are never blocked.
+        assert_eq!(state.admit(ids[4], SectorRange::new(0, 0), 4), Some(4));
+
+        assert_eq!(state.release(ids[3]), Vec::<i32>::new());
+        assert_eq!(state.release(ids[0]), vec![1]);
+        assert_eq!(state.release(ids[1]), vec![2]);
+        assert_eq!(state.release(ids[2]), Vec::<i32>::new());
+        assert!(state.inflight.iter().all(|(id, _)| *id == ids[4]));
+        assert!(state.waiting.is_empty());
+    }
+
     // Test iothread and qos capability. The function will spawn a thread called 'iothread', then
     // io request will be handled by this thread.
     #[test]
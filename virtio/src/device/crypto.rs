@@ -0,0 +1,700 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::error;
+use thiserror::Error;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::{
+    check_config_space_rw, get_buf_and_discard, gpa_hva_iovec_map, iov_discard_front, iov_to_buf,
+    read_config_default, report_virtio_error, ElemIovec, Element, Queue, VirtioBase, VirtioDevice,
+    VirtioError, VirtioInterrupt, VirtioInterruptType, VirtioTrace, VIRTIO_F_VERSION_1,
+    VIRTIO_TYPE_CRYPTO,
+};
+use address_space::AddressSpace;
+use block_backend::luks::crypto::{CbcCipher, XtsCipher, AES_BLOCK_SIZE};
+use machine_manager::{
+    config::{CryptoConfig, DEFAULT_VIRTQUEUE_SIZE},
+    event_loop::{register_event_helper, unregister_event_helper},
+};
+use util::aio::iov_from_buf_direct;
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Crypto services.
+const VIRTIO_CRYPTO_SERVICE_CIPHER: u32 = 0;
+
+/// Cipher algorithms.
+const VIRTIO_CRYPTO_CIPHER_AES_CBC: u32 = 3;
+const VIRTIO_CRYPTO_CIPHER_AES_XTS: u32 = 13;
+
+const fn virtio_crypto_opcode(service: u32, op: u32) -> u32 {
+    (service << 8) | op
+}
+
+/// Opcodes of the control queue.
+const VIRTIO_CRYPTO_CIPHER_CREATE_SESSION: u32 =
+    virtio_crypto_opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x02);
+const VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION: u32 =
+    virtio_crypto_opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x03);
+
+/// Opcodes of the data queues.
+const VIRTIO_CRYPTO_CIPHER_ENCRYPT: u32 = virtio_crypto_opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x00);
+const VIRTIO_CRYPTO_CIPHER_DECRYPT: u32 = virtio_crypto_opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x01);
+
+/// Operation types of symmetric algorithms.
+const VIRTIO_CRYPTO_SYM_OP_CIPHER: u32 = 1;
+
+/// Operations of cipher session.
+const VIRTIO_CRYPTO_OP_ENCRYPT: u32 = 1;
+const VIRTIO_CRYPTO_OP_DECRYPT: u32 = 2;
+
+/// Status of requests.
+const VIRTIO_CRYPTO_OK: u8 = 0;
+const VIRTIO_CRYPTO_ERR: u8 = 1;
+const VIRTIO_CRYPTO_BADMSG: u8 = 2;
+const VIRTIO_CRYPTO_NOTSUPP: u8 = 3;
+const VIRTIO_CRYPTO_INVSESS: u8 = 4;
+
+/// Status of the device.
+const VIRTIO_CRYPTO_S_HW_READY: u32 = 1;
+
+/// Size of the union in the control request, which is followed by the key.
+const CTRL_REQ_UNION_SIZE: usize = 56;
+/// Offset of `op_type` in the union of creating session request.
+const CTRL_REQ_OP_TYPE_OFFSET: usize = 48;
+/// Size of the union in the data request, which is followed by the IV and the source data.
+const DATA_REQ_UNION_SIZE: usize = 48;
+/// Offset of `op_type` in the union of data request.
+const DATA_REQ_OP_TYPE_OFFSET: usize = 40;
+
+const CRYPTO_MAX_CIPHER_KEY_LEN: u32 = 64;
+const CRYPTO_MAX_IV_LEN: u32 = 32;
+/// Max length of the data of one request.
+const CRYPTO_MAX_SIZE: u64 = 1 << 22;
+/// Max number of sessions, to limit the memory used by the guest.
+const CRYPTO_MAX_SESSIONS: usize = 4096;
+
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("Crypto operation {0} is not supported")]
+    NotSupported(String),
+    #[error("Crypto session {0} not found")]
+    InvalidSession(u64),
+    #[error("Bad crypto request: {0}")]
+    BadMessage(String),
+}
+
+fn error_status(err: &anyhow::Error) -> u8 {
+    match err.downcast_ref::<CryptoError>() {
+        Some(CryptoError::NotSupported(_)) => VIRTIO_CRYPTO_NOTSUPP,
+        Some(CryptoError::InvalidSession(_)) => VIRTIO_CRYPTO_INVSESS,
+        Some(CryptoError::BadMessage(_)) => VIRTIO_CRYPTO_BADMSG,
+        None => VIRTIO_CRYPTO_ERR,
+    }
+}
+
+/// Engine which performs the operations of crypto sessions. The builtin software engine
+/// is used for now, and the host kernel crypto such as AF_ALG can be supported by
+/// implementing this trait.
+pub trait CryptoBackend: Send {
+    /// Create a cipher session with the key, and return the id of the session.
+    fn create_cipher_session(&mut self, algo: u32, key: &[u8]) -> Result<u64>;
+
+    fn destroy_session(&mut self, session_id: u64) -> Result<()>;
+
+    /// Encrypt or decrypt `data` in place.
+    fn cipher(&mut self, session_id: u64, iv: &[u8], data: &mut [u8], encrypt: bool) -> Result<()>;
+
+    /// Destroy all the sessions, it's called when the device is reset.
+    fn reset(&mut self);
+}
+
+enum CipherSession {
+    AesCbc(Box<CbcCipher>),
+    AesXts(XtsCipher),
+}
+
+/// Pure software engine of AES ciphers.
+#[derive(Default)]
+pub struct BuiltinCryptoBackend {
+    sessions: HashMap<u64, CipherSession>,
+    next_session_id: u64,
+}
+
+impl CryptoBackend for BuiltinCryptoBackend {
+    fn create_cipher_session(&mut self, algo: u32, key: &[u8]) -> Result<u64> {
+        if self.sessions.len() >= CRYPTO_MAX_SESSIONS {
+            bail!("Too many crypto sessions, max is {}", CRYPTO_MAX_SESSIONS);
+        }
+        let session = match algo {
+            VIRTIO_CRYPTO_CIPHER_AES_CBC => CipherSession::AesCbc(Box::new(CbcCipher::new(key)?)),
+            VIRTIO_CRYPTO_CIPHER_AES_XTS => CipherSession::AesXts(XtsCipher::new(key)?),
+            _ => {
+                return Err(anyhow!(CryptoError::NotSupported(format!(
+                    "cipher algorithm {}",
+                    algo
+                ))))
+            }
+        };
+        let session_id = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(session_id, session);
+        Ok(session_id)
+    }
+
+    fn destroy_session(&mut self, session_id: u64) -> Result<()> {
+        self.sessions
+            .remove(&session_id)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!(CryptoError::InvalidSession(session_id)))
+    }
+
+    fn cipher(&mut self, session_id: u64, iv: &[u8], data: &mut [u8], encrypt: bool) -> Result<()> {
+        let session = self
+            .sessions
+            .get(&session_id)
+            .ok_or_else(|| anyhow!(CryptoError::InvalidSession(session_id)))?;
+        let iv: &[u8; AES_BLOCK_SIZE] = iv.try_into().map_err(|_| {
+            anyhow!(CryptoError::BadMessage(format!(
+                "invalid IV length {}",
+                iv.len()
+            )))
+        })?;
+        // Ciphertext stealing is not supported, so the data must be full blocks.
+        if data.is_empty() || !data.len().is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(anyhow!(CryptoError::BadMessage(format!(
+                "invalid data length {}",
+                data.len()
+            ))));
+        }
+        match (session, encrypt) {
+            (CipherSession::AesCbc(cbc), true) => cbc.encrypt(data, iv),
+            (CipherSession::AesCbc(cbc), false) => cbc.decrypt(data, iv),
+            (CipherSession::AesXts(xts), encrypt) => xts.process_with_iv(data, iv, encrypt),
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.sessions.clear();
+    }
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioCryptoConfig {
+    status: u32,
+    max_dataqueues: u32,
+    crypto_services: u32,
+    cipher_algo_l: u32,
+    cipher_algo_h: u32,
+    hash_algo: u32,
+    mac_algo_l: u32,
+    mac_algo_h: u32,
+    aead_algo: u32,
+    max_cipher_key_len: u32,
+    max_auth_key_len: u32,
+    reserved: u32,
+    max_size: u64,
+}
+
+impl ByteCode for VirtioCryptoConfig {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioCryptoCtrlHeader {
+    opcode: u32,
+    algo: u32,
+    flag: u32,
+    queue_id: u32,
+}
+
+impl ByteCode for VirtioCryptoCtrlHeader {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioCryptoCipherSessionPara {
+    algo: u32,
+    key_len: u32,
+    op: u32,
+    padding: u32,
+}
+
+impl ByteCode for VirtioCryptoCipherSessionPara {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioCryptoSessionInput {
+    session_id: u64,
+    status: u32,
+    padding: u32,
+}
+
+impl ByteCode for VirtioCryptoSessionInput {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioCryptoOpHeader {
+    opcode: u32,
+    algo: u32,
+    session_id: u64,
+    flag: u32,
+    padding: u32,
+}
+
+impl ByteCode for VirtioCryptoOpHeader {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioCryptoCipherDataPara {
+    iv_len: u32,
+    src_data_len: u32,
+    dst_data_len: u32,
+    padding: u32,
+}
+
+impl ByteCode for VirtioCryptoCipherDataPara {}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0_u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Handler of one queue, the last queue is the control queue and the others are data queues.
+struct CryptoHandler {
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: Arc<EventFd>,
+    /// Whether the queue is the control queue.
+    ctrl: bool,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    backend: Arc<Mutex<dyn CryptoBackend>>,
+    device_broken: Arc<AtomicBool>,
+}
+
+impl CryptoHandler {
+    fn write_buf(&self, iovec: &[ElemIovec], buf: &[u8]) -> Result<usize> {
+        let (_, hva_iovec) = gpa_hva_iovec_map(iovec, &self.mem_space)?;
+        iov_from_buf_direct(&hva_iovec, buf)
+    }
+
+    fn create_session(&self, req: &[u8], key_iovec: &[ElemIovec]) -> Result<u64> {
+        let op_type = read_u32(req, CTRL_REQ_OP_TYPE_OFFSET);
+        if op_type != VIRTIO_CRYPTO_SYM_OP_CIPHER {
+            return Err(anyhow!(CryptoError::NotSupported(format!(
+                "symmetric operation type {}",
+                op_type
+            ))));
+        }
+        // It's safe to unwrap as the union is larger than the parameters.
+        let para = *VirtioCryptoCipherSessionPara::from_bytes(
+            &req[..size_of::<VirtioCryptoCipherSessionPara>()],
+        )
+        .unwrap();
+        if para.op != VIRTIO_CRYPTO_OP_ENCRYPT && para.op != VIRTIO_CRYPTO_OP_DECRYPT {
+            return Err(anyhow!(CryptoError::BadMessage(format!(
+                "invalid cipher operation {}",
+                { para.op }
+            ))));
+        }
+        if para.key_len > CRYPTO_MAX_CIPHER_KEY_LEN {
+            return Err(anyhow!(CryptoError::BadMessage(format!(
+                "invalid key length {}",
+                { para.key_len }
+            ))));
+        }
+        let mut key = vec![0_u8; para.key_len as usize];
+        if iov_to_buf(&self.mem_space, key_iovec, &mut key)? < key.len() {
+            return Err(anyhow!(CryptoError::BadMessage(
+                "key is incomplete".to_string()
+            )));
+        }
+        self.backend
+            .lock()
+            .unwrap()
+            .create_cipher_session(para.algo, &key)
+    }
+
+    fn handle_ctrl_request(&self, elem: &mut Element) -> Result<usize> {
+        let mut header = VirtioCryptoCtrlHeader::default();
+        let mut data_iovec =
+            get_buf_and_discard(&self.mem_space, &mut elem.out_iovec, header.as_mut_bytes())
+                .with_context(|| "Failed to get crypto control header")?;
+        let mut req = [0_u8; CTRL_REQ_UNION_SIZE];
+        let key_iovec = get_buf_and_discard(&self.mem_space, &mut data_iovec, &mut req)
+            .with_context(|| "Failed to get crypto control request")?;
+
+        match header.opcode {
+            VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION => {
+                let session_id = u64::from(read_u32(&req, 0)) | u64::from(read_u32(&req, 4)) << 32;
+                let status = match self.backend.lock().unwrap().destroy_session(session_id) {
+                    Ok(()) => VIRTIO_CRYPTO_OK,
+                    Err(e) => {
+                        error!("Failed to destroy crypto session: {:?}", e);
+                        error_status(&e)
+                    }
+                };
+                self.write_buf(&elem.in_iovec, &[status])
+            }
+            opcode => {
+                let mut input = VirtioCryptoSessionInput::default();
+                let result = if opcode == VIRTIO_CRYPTO_CIPHER_CREATE_SESSION {
+                    self.create_session(&req, &key_iovec)
+                } else {
+                    Err(anyhow!(CryptoError::NotSupported(format!(
+                        "control opcode {}",
+                        opcode
+                    ))))
+                };
+                match result {
+                    Ok(session_id) => input.session_id = session_id,
+                    Err(e) => {
+                        error!("Failed to create crypto session: {:?}", e);
+                        input.status = error_status(&e) as u32;
+                    }
+                }
+                self.write_buf(&elem.in_iovec, input.as_bytes())
+            }
+        }
+    }
+
+    /// Do the cipher operation, and return the destination data.
+    fn do_cipher(&self, req: &[u8], data_iovec: &[ElemIovec], in_size: u64) -> Result<Vec<u8>> {
+        let header =
+            *VirtioCryptoOpHeader::from_bytes(&req[..size_of::<VirtioCryptoOpHeader>()]).unwrap();
+        let encrypt = match header.opcode {
+            VIRTIO_CRYPTO_CIPHER_ENCRYPT => true,
+            VIRTIO_CRYPTO_CIPHER_DECRYPT => false,
+            opcode => {
+                return Err(anyhow!(CryptoError::NotSupported(format!(
+                    "data opcode {}",
+                    opcode
+                ))))
+            }
+        };
+        let req = &req[size_of::<VirtioCryptoOpHeader>()..];
+        let op_type = read_u32(req, DATA_REQ_OP_TYPE_OFFSET);
+        if op_type != VIRTIO_CRYPTO_SYM_OP_CIPHER {
+            return Err(anyhow!(CryptoError::NotSupported(format!(
+                "symmetric operation type {}",
+                op_type
+            ))));
+        }
+        let para = *VirtioCryptoCipherDataPara::from_bytes(
+            &req[..size_of::<VirtioCryptoCipherDataPara>()],
+        )
+        .unwrap();
+        if para.iv_len > CRYPTO_MAX_IV_LEN
+            || para.src_data_len as u64 > CRYPTO_MAX_SIZE
+            || para.dst_data_len != para.src_data_len
+            || para.dst_data_len as u64 >= in_size
+        {
+            return Err(anyhow!(CryptoError::BadMessage(format!(
+                "invalid length, iv {}, src {}, dst {}",
+                { para.iv_len },
+                { para.src_data_len },
+                { para.dst_data_len }
+            ))));
+        }
+
+        let iv_len = para.iv_len as usize;
+        let mut buf = vec![0_u8; iv_len + para.src_data_len as usize];
+        if iov_to_buf(&self.mem_space, data_iovec, &mut buf)? < buf.len() {
+            return Err(anyhow!(CryptoError::BadMessage(
+                "data is incomplete".to_string()
+            )));
+        }
+        let (iv, data) = buf.split_at_mut(iv_len);
+        self.backend
+            .lock()
+            .unwrap()
+            .cipher(header.session_id, iv, data, encrypt)?;
+        Ok(data.to_vec())
+    }
+
+    fn handle_data_request(&self, elem: &mut Element) -> Result<usize> {
+        let in_size = Element::iovec_size(&elem.in_iovec);
+        if in_size == 0 {
+            bail!("Invalid length of crypto data request, in_iovec size is 0");
+        }
+        let mut req = [0_u8; size_of::<VirtioCryptoOpHeader>() + DATA_REQ_UNION_SIZE];
+        let data_iovec = get_buf_and_discard(&self.mem_space, &mut elem.out_iovec, &mut req)
+            .with_context(|| "Failed to get crypto data request")?;
+
+        let (status, dst) = match self.do_cipher(&req, &data_iovec, in_size) {
+            Ok(dst) => (VIRTIO_CRYPTO_OK, dst),
+            Err(e) => {
+                error!("Failed to process crypto data request: {:?}", e);
+                (error_status(&e), Vec::new())
+            }
+        };
+        // The destination data is followed by the status, which is the last byte.
+        let mut in_iovec = elem.in_iovec.clone();
+        let written = self.write_buf(&in_iovec, &dst)?;
+        let status_iovec = iov_discard_front(&mut in_iovec, in_size - 1)
+            .with_context(|| "Failed to get crypto status iovec")?;
+        self.write_buf(status_iovec, &[status])?;
+        Ok(written + 1)
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        self.trace_request("Crypto".to_string(), "to IO".to_string());
+        let mut locked_queue = self.queue.lock().unwrap();
+        loop {
+            let mut elem = locked_queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for crypto")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let len = if self.ctrl {
+                self.handle_ctrl_request(&mut elem)?
+            } else {
+                self.handle_data_request(&mut elem)?
+            };
+
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, len as u32)
+                .with_context(|| format!("Failed to add used ring {}", elem.index))?;
+
+            if locked_queue
+                .vring
+                .should_notify(&self.mem_space, self.driver_features)
+            {
+                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+                    .with_context(|| {
+                        VirtioError::InterruptTrigger("crypto", VirtioInterruptType::Vring)
+                    })?;
+                self.trace_send_interrupt("Crypto".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EventNotifierHelper for CryptoHandler {
+    fn internal_notifiers(crypto_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_handler = crypto_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_handler = cloned_handler.lock().unwrap();
+            if locked_handler.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            locked_handler.process_queue().unwrap_or_else(|e| {
+                error!("Failed to process queue for virtio crypto, err: {:?}", e);
+                report_virtio_error(
+                    locked_handler.interrupt_cb.clone(),
+                    locked_handler.driver_features,
+                    &locked_handler.device_broken,
+                );
+            });
+            None
+        });
+
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            crypto_handler.lock().unwrap().queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+impl VirtioTrace for CryptoHandler {}
+
+/// Crypto device structure, which supports the cipher service.
+pub struct Crypto {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// Configuration of virtio crypto device.
+    crypto_cfg: CryptoConfig,
+    /// Config space of the device.
+    config_space: VirtioCryptoConfig,
+    /// Engine of the crypto sessions.
+    backend: Arc<Mutex<dyn CryptoBackend>>,
+}
+
+impl Crypto {
+    pub fn new(crypto_cfg: CryptoConfig) -> Self {
+        // The last queue is the control queue.
+        let queue_num = crypto_cfg.queues as usize + 1;
+        Crypto {
+            base: VirtioBase::new(VIRTIO_TYPE_CRYPTO, queue_num, DEFAULT_VIRTQUEUE_SIZE),
+            crypto_cfg,
+            config_space: VirtioCryptoConfig::default(),
+            backend: Arc::new(Mutex::new(BuiltinCryptoBackend::default())),
+        }
+    }
+}
+
+impl VirtioDevice for Crypto {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        self.base.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
+        self.config_space = VirtioCryptoConfig {
+            status: VIRTIO_CRYPTO_S_HW_READY,
+            max_dataqueues: self.crypto_cfg.queues as u32,
+            crypto_services: 1 << VIRTIO_CRYPTO_SERVICE_CIPHER,
+            cipher_algo_l: 1 << VIRTIO_CRYPTO_CIPHER_AES_CBC | 1 << VIRTIO_CRYPTO_CIPHER_AES_XTS,
+            max_cipher_key_len: CRYPTO_MAX_CIPHER_KEY_LEN,
+            max_size: CRYPTO_MAX_SIZE,
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        read_config_default(self.config_space.as_bytes(), offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        check_config_space_rw(self.config_space.as_bytes(), offset, data)?;
+        // The config space is read-only for the driver, so do nothing here.
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let queues = self.base.queues.clone();
+        if queues.len() != self.base.queue_num {
+            bail!(
+                "Invalid queue number {} of virtio crypto, expected {}",
+                queues.len(),
+                self.base.queue_num
+            );
+        }
+        for (index, queue) in queues.into_iter().enumerate() {
+            let handler = CryptoHandler {
+                queue,
+                queue_evt: queue_evts[index].clone(),
+                ctrl: index == self.crypto_cfg.queues as usize,
+                mem_space: mem_space.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+                driver_features: self.base.driver_features,
+                backend: self.backend.clone(),
+                device_broken: self.base.broken.clone(),
+            };
+            let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+            register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+        }
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        self.backend.lock().unwrap().reset();
+        unregister_event_helper(None, &mut self.base.deactivate_evts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crypto_config() {
+        let mut crypto = Crypto::new(CryptoConfig {
+            id: "crypto0".to_string(),
+            queues: 2,
+        });
+        assert_eq!(crypto.queue_num(), 3);
+        assert_eq!(crypto.device_type(), VIRTIO_TYPE_CRYPTO);
+        crypto.realize().unwrap();
+
+        let mut data = [0_u8; 4];
+        crypto.read_config(0, &mut data).unwrap();
+        assert_eq!(u32::from_le_bytes(data), VIRTIO_CRYPTO_S_HW_READY);
+        crypto.read_config(4, &mut data).unwrap();
+        assert_eq!(u32::from_le_bytes(data), 2);
+        crypto.read_config(12, &mut data).unwrap();
+        assert_eq!(u32::from_le_bytes(data), 1 << 3 | 1 << 13);
+        assert!(crypto
+            .read_config(size_of::<VirtioCryptoConfig>() as u64, &mut data)
+            .is_err());
+        assert!(crypto.write_config(0, &data).is_ok());
+    }
+
+    #[test]
+    fn test_builtin_crypto_backend() {
+        let mut backend = BuiltinCryptoBackend::default();
+        let cbc = backend
+            .create_cipher_session(VIRTIO_CRYPTO_CIPHER_AES_CBC, &[1_u8; 16])
+            .unwrap();
+        let xts = backend
+            .create_cipher_session(VIRTIO_CRYPTO_CIPHER_AES_XTS, &[2_u8; 64])
+            .unwrap();
+        assert_ne!(cbc, xts);
+
+        let iv = [3_u8; AES_BLOCK_SIZE];
+        let plain: Vec<u8> = (0..64).collect();
+        for session in [cbc, xts] {
+            let mut data = plain.clone();
+            backend.cipher(session, &iv, &mut data, true).unwrap();
+            assert_ne!(data, plain);
+            backend.cipher(session, &iv, &mut data, false).unwrap();
+            assert_eq!(data, plain);
+        }
+
+        let mut data = plain.clone();
+        let err = backend.cipher(cbc, &iv[..8], &mut data, true).unwrap_err();
+        assert_eq!(error_status(&err), VIRTIO_CRYPTO_BADMSG);
+        let err = backend.cipher(cbc, &iv, &mut data[..10], true).unwrap_err();
+        assert_eq!(error_status(&err), VIRTIO_CRYPTO_BADMSG);
+        let err = backend.create_cipher_session(2, &[0_u8; 16]).unwrap_err();
+        assert_eq!(error_status(&err), VIRTIO_CRYPTO_NOTSUPP);
+        let err = backend
+            .create_cipher_session(VIRTIO_CRYPTO_CIPHER_AES_CBC, &[0_u8; 20])
+            .unwrap_err();
+        assert_eq!(error_status(&err), VIRTIO_CRYPTO_ERR);
+
+        backend.destroy_session(cbc).unwrap();
+        let err = backend.cipher(cbc, &iv, &mut data, true).unwrap_err();
+        assert_eq!(error_status(&err), VIRTIO_CRYPTO_INVSESS);
+        assert!(backend.destroy_session(cbc).is_err());
+        backend.reset();
+        assert!(backend.destroy_session(xts).is_err());
+    }
+}
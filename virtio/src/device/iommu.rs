@@ -0,0 +1,845 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{Context, Result};
+use log::error;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::{
+    check_config_space_rw, gpa_hva_iovec_map, iov_to_buf, read_config_default, virtio_has_feature,
+    Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType,
+    VIRTIO_F_VERSION_1, VIRTIO_TYPE_IOMMU,
+};
+use address_space::{AddressSpace, GuestAddress, IommuMapping, IommuNotifier, IommuTranslate};
+use machine_manager::{
+    config::{IommuConfig, DEFAULT_VIRTQUEUE_SIZE},
+    event_loop::{register_event_helper, unregister_event_helper},
+};
+use util::aio::iov_from_buf_direct;
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use util::offset_of;
+
+/// Feature bits of virtio iommu.
+const VIRTIO_IOMMU_F_INPUT_RANGE: u32 = 0;
+const VIRTIO_IOMMU_F_DOMAIN_RANGE: u32 = 1;
+const VIRTIO_IOMMU_F_MAP_UNMAP: u32 = 2;
+const VIRTIO_IOMMU_F_BYPASS_CONFIG: u32 = 6;
+
+/// Request types.
+const VIRTIO_IOMMU_T_ATTACH: u8 = 1;
+const VIRTIO_IOMMU_T_DETACH: u8 = 2;
+const VIRTIO_IOMMU_T_MAP: u8 = 3;
+const VIRTIO_IOMMU_T_UNMAP: u8 = 4;
+
+/// Status of the requests.
+const VIRTIO_IOMMU_S_OK: u8 = 0;
+const VIRTIO_IOMMU_S_UNSUPP: u8 = 2;
+const VIRTIO_IOMMU_S_DEVERR: u8 = 3;
+const VIRTIO_IOMMU_S_INVAL: u8 = 4;
+const VIRTIO_IOMMU_S_RANGE: u8 = 5;
+const VIRTIO_IOMMU_S_NOENT: u8 = 6;
+
+const VIRTIO_IOMMU_ATTACH_F_BYPASS: u32 = 1;
+const VIRTIO_IOMMU_MAP_F_READ: u32 = 1;
+const VIRTIO_IOMMU_MAP_F_WRITE: u32 = 2;
+
+const QUEUE_NUM_IOMMU: usize = 2;
+const REQUEST_QUEUE: usize = 0;
+/// Only 4KiB page granule is supported.
+const IOMMU_PAGE_SIZE_MASK: u64 = !0xfff;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuConfig {
+    page_size_mask: u64,
+    input_start: u64,
+    input_end: u64,
+    domain_start: u32,
+    domain_end: u32,
+    probe_size: u32,
+    bypass: u8,
+    reserved: [u8; 3],
+}
+
+impl ByteCode for VirtioIommuConfig {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuReqHead {
+    req_type: u8,
+    reserved: [u8; 3],
+}
+
+impl ByteCode for VirtioIommuReqHead {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuReqTail {
+    status: u8,
+    reserved: [u8; 3],
+}
+
+impl ByteCode for VirtioIommuReqTail {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuReqAttach {
+    head: VirtioIommuReqHead,
+    domain: u32,
+    endpoint: u32,
+    flags: u32,
+    reserved: [u8; 4],
+}
+
+impl ByteCode for VirtioIommuReqAttach {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuReqDetach {
+    head: VirtioIommuReqHead,
+    domain: u32,
+    endpoint: u32,
+    reserved: [u8; 8],
+}
+
+impl ByteCode for VirtioIommuReqDetach {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuReqMap {
+    head: VirtioIommuReqHead,
+    domain: u32,
+    virt_start: u64,
+    virt_end: u64,
+    phys_start: u64,
+    flags: u32,
+}
+
+impl ByteCode for VirtioIommuReqMap {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioIommuReqUnmap {
+    head: VirtioIommuReqHead,
+    domain: u32,
+    virt_start: u64,
+    virt_end: u64,
+    reserved: [u8; 4],
+}
+
+impl ByteCode for VirtioIommuReqUnmap {}
+
+/// Mapping in a domain, which is indexed by the start IO virtual address.
+#[derive(Clone, Copy, Debug)]
+struct DomainMapping {
+    /// The last IO virtual address of the mapping, inclusive.
+    virt_end: u64,
+    phys_start: u64,
+}
+
+#[derive(Default)]
+struct IommuDomain {
+    mappings: BTreeMap<u64, DomainMapping>,
+    /// The DMA of the endpoints in a bypass domain is not translated.
+    bypass: bool,
+}
+
+#[derive(Default)]
+struct IommuState {
+    domains: HashMap<u32, IommuDomain>,
+    /// The endpoints behind the IOMMU, and the domains they are attached to.
+    endpoints: HashMap<u32, Option<u32>>,
+    /// Notifiers of mapping changes, indexed by endpoint.
+    notifiers: HashMap<u32, Vec<IommuNotifier>>,
+    /// Whether the DMA of the endpoints which are not attached to any domain bypasses the IOMMU.
+    bypass: bool,
+}
+
+impl IommuState {
+    fn domain_endpoints(&self, domain: u32) -> Vec<u32> {
+        self.endpoints
+            .iter()
+            .filter(|(_, attached)| **attached == Some(domain))
+            .map(|(endpoint, _)| *endpoint)
+            .collect()
+    }
+
+    /// Detach the endpoint from its domain, and destroy the domain if it's the last endpoint.
+    fn detach_endpoint(&mut self, endpoint: u32) {
+        if let Some(domain) = self.endpoints.insert(endpoint, None).flatten() {
+            if self.domain_endpoints(domain).is_empty() {
+                self.domains.remove(&domain);
+            }
+        }
+    }
+
+    fn attach(&mut self, domain: u32, endpoint: u32, flags: u32, driver_features: u64) -> u8 {
+        if flags & !VIRTIO_IOMMU_ATTACH_F_BYPASS != 0 {
+            return VIRTIO_IOMMU_S_INVAL;
+        }
+        let bypass = flags & VIRTIO_IOMMU_ATTACH_F_BYPASS != 0;
+        if bypass && !virtio_has_feature(driver_features, VIRTIO_IOMMU_F_BYPASS_CONFIG) {
+            return VIRTIO_IOMMU_S_INVAL;
+        }
+        if !self.endpoints.contains_key(&endpoint) {
+            return VIRTIO_IOMMU_S_NOENT;
+        }
+        if let Some(exist) = self.domains.get(&domain) {
+            if exist.bypass != bypass {
+                return VIRTIO_IOMMU_S_INVAL;
+            }
+        }
+
+        self.detach_endpoint(endpoint);
+        self.domains.entry(domain).or_insert(IommuDomain {
+            mappings: BTreeMap::new(),
+            bypass,
+        });
+        self.endpoints.insert(endpoint, Some(domain));
+        VIRTIO_IOMMU_S_OK
+    }
+
+    fn detach(&mut self, domain: u32, endpoint: u32) -> u8 {
+        match self.endpoints.get(&endpoint) {
+            None => VIRTIO_IOMMU_S_NOENT,
+            Some(attached) if *attached != Some(domain) => VIRTIO_IOMMU_S_INVAL,
+            Some(_) => {
+                self.detach_endpoint(endpoint);
+                VIRTIO_IOMMU_S_OK
+            }
+        }
+    }
+
+    fn map(&mut self, req: &VirtioIommuReqMap) -> u8 {
+        let (domain, virt_start, virt_end) = (req.domain, req.virt_start, req.virt_end);
+        let domain = match self.domains.get_mut(&domain) {
+            Some(domain) => domain,
+            None => return VIRTIO_IOMMU_S_NOENT,
+        };
+        // MMIO mapping is not supported.
+        if req.flags & !(VIRTIO_IOMMU_MAP_F_READ | VIRTIO_IOMMU_MAP_F_WRITE) != 0
+            || virt_start > virt_end
+            || domain.bypass
+        {
+            return VIRTIO_IOMMU_S_INVAL;
+        }
+        if let Some((_, prev)) = domain.mappings.range(..=virt_end).next_back() {
+            if prev.virt_end >= virt_start {
+                return VIRTIO_IOMMU_S_INVAL;
+            }
+        }
+
+        domain.mappings.insert(
+            virt_start,
+            DomainMapping {
+                virt_end,
+                phys_start: req.phys_start,
+            },
+        );
+        VIRTIO_IOMMU_S_OK
+    }
+
+    fn unmap(&mut self, req: &VirtioIommuReqUnmap) -> u8 {
+        let (domain, virt_start, virt_end) = (req.domain, req.virt_start, req.virt_end);
+        let domain = match self.domains.get_mut(&domain) {
+            Some(domain) => domain,
+            None => return VIRTIO_IOMMU_S_NOENT,
+        };
+        if virt_start > virt_end || domain.bypass {
+            return VIRTIO_IOMMU_S_INVAL;
+        }
+        // The mappings can't be split, so no mapping is removed if any of them is partially
+        // covered by the range.
+        if let Some((_, prev)) = domain.mappings.range(..virt_start).next_back() {
+            if prev.virt_end >= virt_start {
+                return VIRTIO_IOMMU_S_RANGE;
+            }
+        }
+        let mut unmapped = Vec::new();
+        for (start, mapping) in domain.mappings.range(virt_start..=virt_end) {
+            if mapping.virt_end > virt_end {
+                return VIRTIO_IOMMU_S_RANGE;
+            }
+            unmapped.push(*start);
+        }
+        for start in unmapped {
+            domain.mappings.remove(&start);
+        }
+        VIRTIO_IOMMU_S_OK
+    }
+
+    /// Handle the request, returns the status and the endpoints whose mappings are changed.
+    fn handle_request(&mut self, req: &[u8], driver_features: u64) -> (u8, Vec<u32>) {
+        let req_type = match VirtioIommuReqHead::from_bytes(&req[..size_of::<VirtioIommuReqHead>()])
+        {
+            Some(head) => head.req_type,
+            None => return (VIRTIO_IOMMU_S_DEVERR, Vec::new()),
+        };
+        match req_type {
+            VIRTIO_IOMMU_T_ATTACH => match VirtioIommuReqAttach::from_bytes(req) {
+                Some(attach) => (
+                    self.attach(
+                        attach.domain,
+                        attach.endpoint,
+                        attach.flags,
+                        driver_features,
+                    ),
+                    vec![attach.endpoint],
+                ),
+                None => (VIRTIO_IOMMU_S_INVAL, Vec::new()),
+            },
+            VIRTIO_IOMMU_T_DETACH => match VirtioIommuReqDetach::from_bytes(req) {
+                Some(detach) => (
+                    self.detach(detach.domain, detach.endpoint),
+                    vec![detach.endpoint],
+                ),
+                None => (VIRTIO_IOMMU_S_INVAL, Vec::new()),
+            },
+            VIRTIO_IOMMU_T_MAP => match VirtioIommuReqMap::from_bytes(req) {
+                Some(map) => (self.map(map), self.domain_endpoints(map.domain)),
+                None => (VIRTIO_IOMMU_S_INVAL, Vec::new()),
+            },
+            VIRTIO_IOMMU_T_UNMAP => match VirtioIommuReqUnmap::from_bytes(req) {
+                Some(unmap) => (self.unmap(unmap), self.domain_endpoints(unmap.domain)),
+                None => (VIRTIO_IOMMU_S_INVAL, Vec::new()),
+            },
+            _ => (VIRTIO_IOMMU_S_UNSUPP, Vec::new()),
+        }
+    }
+
+    /// Reset the domains, and all endpoints are detached.
+    fn reset(&mut self) {
+        self.domains.clear();
+        for attached in self.endpoints.values_mut() {
+            *attached = None;
+        }
+        self.bypass = true;
+    }
+}
+
+/// An endpoint behind the virtio iommu, which translates the DMA addresses of the endpoint.
+struct IommuEndpoint {
+    id: u32,
+    state: Arc<RwLock<IommuState>>,
+}
+
+impl IommuTranslate for IommuEndpoint {
+    fn translate(&self, iova: GuestAddress) -> Option<(GuestAddress, u64)> {
+        let state = self.state.read().unwrap();
+        let domain = match state.endpoints.get(&self.id).copied().flatten() {
+            Some(domain) => state.domains.get(&domain)?,
+            None if state.bypass => return Some((iova, u64::MAX - iova.raw_value())),
+            None => return None,
+        };
+        if domain.bypass {
+            return Some((iova, u64::MAX - iova.raw_value()));
+        }
+
+        let (virt_start, mapping) = domain.mappings.range(..=iova.raw_value()).next_back()?;
+        if iova.raw_value() > mapping.virt_end {
+            return None;
+        }
+        let offset = iova.raw_value() - virt_start;
+        Some((
+            GuestAddress(mapping.phys_start + offset),
+            (mapping.virt_end - iova.raw_value()).saturating_add(1),
+        ))
+    }
+
+    fn mappings(&self) -> Option<Vec<IommuMapping>> {
+        let state = self.state.read().unwrap();
+        let domain = match state.endpoints.get(&self.id).copied().flatten() {
+            Some(domain) => state.domains.get(&domain),
+            None if state.bypass => return None,
+            None => return Some(Vec::new()),
+        };
+        match domain {
+            Some(domain) if domain.bypass => None,
+            Some(domain) => Some(
+                domain
+                    .mappings
+                    .iter()
+                    .map(|(virt_start, mapping)| IommuMapping {
+                        iova: *virt_start,
+                        gpa: mapping.phys_start,
+                        size: (mapping.virt_end - virt_start).saturating_add(1),
+                    })
+                    .collect(),
+            ),
+            None => Some(Vec::new()),
+        }
+    }
+
+    fn add_notifier(&self, notifier: IommuNotifier) {
+        let mut state = self.state.write().unwrap();
+        state.notifiers.entry(self.id).or_default().push(notifier);
+    }
+
+    fn clear_notifiers(&self) {
+        self.state.write().unwrap().notifiers.remove(&self.id);
+    }
+}
+
+struct IommuHandler {
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: Arc<EventFd>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    mem_space: Arc<AddressSpace>,
+    state: Arc<RwLock<IommuState>>,
+}
+
+impl IommuHandler {
+    fn handle_request(&self, elem: &Element) -> Result<usize> {
+        let mut req = vec![0_u8; size_of::<VirtioIommuReqMap>()];
+        let len = iov_to_buf(&self.mem_space, &elem.out_iovec, &mut req)?;
+        req.truncate(len);
+
+        let (status, endpoints) = if len < size_of::<VirtioIommuReqHead>() {
+            (VIRTIO_IOMMU_S_DEVERR, Vec::new())
+        } else {
+            let mut state = self.state.write().unwrap();
+            let (status, endpoints) = state.handle_request(&req, self.driver_features);
+            let notifiers: Vec<IommuNotifier> = if status == VIRTIO_IOMMU_S_OK {
+                endpoints
+                    .iter()
+                    .filter_map(|endpoint| state.notifiers.get(endpoint))
+                    .flatten()
+                    .cloned()
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            drop(state);
+            (status, notifiers)
+        };
+        // Notify the mapping changes after the state is unlocked, as the notifiers may
+        // look up the mappings.
+        for notifier in endpoints {
+            notifier();
+        }
+
+        let tail = VirtioIommuReqTail {
+            status,
+            ..Default::default()
+        };
+        let (_, hva_iovec) = gpa_hva_iovec_map(&elem.in_iovec, &self.mem_space)?;
+        iov_from_buf_direct(&hva_iovec, tail.as_bytes())
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        let mut need_interrupt = false;
+        loop {
+            let elem = self
+                .queue
+                .lock()
+                .unwrap()
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for iommu request queue")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let len = self.handle_request(&elem)?;
+            self.queue
+                .lock()
+                .unwrap()
+                .vring
+                .add_used(&self.mem_space, elem.index, len as u32)
+                .with_context(|| format!("Failed to add used ring {}", elem.index))?;
+            need_interrupt = true;
+        }
+
+        if need_interrupt {
+            let mut locked_queue = self.queue.lock().unwrap();
+            if locked_queue
+                .vring
+                .should_notify(&self.mem_space, self.driver_features)
+            {
+                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+                    .with_context(|| {
+                        VirtioError::InterruptTrigger("iommu", VirtioInterruptType::Vring)
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EventNotifierHelper for IommuHandler {
+    fn internal_notifiers(iommu_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_handler = iommu_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if let Err(e) = cloned_handler.lock().unwrap().process_queue() {
+                error!("Failed to process iommu request queue, err: {:?}", e);
+            }
+            None
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            iommu_handler.lock().unwrap().queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+/// Virtio iommu device structure, which translates the DMA addresses of the endpoints.
+pub struct Iommu {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// Configuration of virtio iommu device.
+    iommu_cfg: IommuConfig,
+    /// Devfn of the iommu on the root bus, which is reported to the guest by ACPI VIOT table.
+    devfn: u8,
+    /// Config space of the device.
+    config_space: VirtioIommuConfig,
+    /// Domains and endpoints, shared with the translation of endpoints.
+    state: Arc<RwLock<IommuState>>,
+}
+
+impl Iommu {
+    pub fn new(iommu_cfg: IommuConfig, devfn: u8) -> Self {
+        Iommu {
+            base: VirtioBase::new(VIRTIO_TYPE_IOMMU, QUEUE_NUM_IOMMU, DEFAULT_VIRTQUEUE_SIZE),
+            iommu_cfg,
+            devfn,
+            config_space: VirtioIommuConfig::default(),
+            state: Arc::new(RwLock::new(IommuState {
+                bypass: true,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Put an endpoint behind the iommu, and returns the translation of its DMA addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - Endpoint ID, which is the requester ID for PCI device.
+    pub fn add_endpoint(&mut self, endpoint: u32) -> Arc<dyn IommuTranslate> {
+        self.state.write().unwrap().endpoints.insert(endpoint, None);
+        Arc::new(IommuEndpoint {
+            id: endpoint,
+            state: self.state.clone(),
+        })
+    }
+
+    /// Get the sorted IDs of the endpoints behind the iommu.
+    pub fn endpoints(&self) -> Vec<u32> {
+        let mut endpoints: Vec<u32> = self
+            .state
+            .read()
+            .unwrap()
+            .endpoints
+            .keys()
+            .copied()
+            .collect();
+        endpoints.sort_unstable();
+        endpoints
+    }
+
+    /// Get the id of the iommu device.
+    pub fn id(&self) -> &str {
+        &self.iommu_cfg.id
+    }
+
+    /// Get the devfn of the iommu on the root bus.
+    pub fn devfn(&self) -> u8 {
+        self.devfn
+    }
+
+    fn reset_state(&mut self) {
+        self.state.write().unwrap().reset();
+        self.config_space.bypass = 1;
+    }
+}
+
+impl VirtioDevice for Iommu {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        self.base.device_features = 1 << VIRTIO_F_VERSION_1 as u64
+            | 1 << VIRTIO_IOMMU_F_INPUT_RANGE as u64
+            | 1 << VIRTIO_IOMMU_F_DOMAIN_RANGE as u64
+            | 1 << VIRTIO_IOMMU_F_MAP_UNMAP as u64
+            | 1 << VIRTIO_IOMMU_F_BYPASS_CONFIG as u64;
+        self.config_space = VirtioIommuConfig {
+            page_size_mask: IOMMU_PAGE_SIZE_MASK,
+            input_start: 0,
+            input_end: u64::MAX,
+            domain_start: 0,
+            domain_end: u32::MAX,
+            probe_size: 0,
+            // The DMA bypasses the iommu until the driver is ready, so that the firmware can
+            // access the devices behind it.
+            bypass: 1,
+            reserved: [0; 3],
+        };
+        Ok(())
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        read_config_default(self.config_space.as_bytes(), offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        check_config_space_rw(self.config_space.as_bytes(), offset, data)?;
+        // Only the bypass field is writable for the driver.
+        let bypass_offset = offset_of!(VirtioIommuConfig, bypass) as u64;
+        if offset == bypass_offset && data.len() == 1 {
+            let bypass = data[0] != 0;
+            self.config_space.bypass = bypass as u8;
+            self.state.write().unwrap().bypass = bypass;
+        }
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let queues = &self.base.queues;
+        let handler = IommuHandler {
+            queue: queues[REQUEST_QUEUE].clone(),
+            queue_evt: queue_evts[REQUEST_QUEUE].clone(),
+            interrupt_cb,
+            driver_features: self.base.driver_features,
+            mem_space,
+            state: self.state.clone(),
+        };
+        // No fault is reported, so the event queue is not handled.
+        let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+        register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        unregister_event_helper(None, &mut self.base.deactivate_evts)?;
+        self.reset_state();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.reset_state();
+        Ok(())
+    }
+
+    fn iommu_supported(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attach_req(domain: u32, endpoint: u32, flags: u32) -> Vec<u8> {
+        VirtioIommuReqAttach {
+            head: VirtioIommuReqHead {
+                req_type: VIRTIO_IOMMU_T_ATTACH,
+                ..Default::default()
+            },
+            domain,
+            endpoint,
+            flags,
+            ..Default::default()
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    fn map_req(domain: u32, virt_start: u64, virt_end: u64, phys_start: u64) -> Vec<u8> {
+        VirtioIommuReqMap {
+            head: VirtioIommuReqHead {
+                req_type: VIRTIO_IOMMU_T_MAP,
+                ..Default::default()
+            },
+            domain,
+            virt_start,
+            virt_end,
+            phys_start,
+            flags: VIRTIO_IOMMU_MAP_F_READ | VIRTIO_IOMMU_MAP_F_WRITE,
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    fn unmap_req(domain: u32, virt_start: u64, virt_end: u64) -> Vec<u8> {
+        VirtioIommuReqUnmap {
+            head: VirtioIommuReqHead {
+                req_type: VIRTIO_IOMMU_T_UNMAP,
+                ..Default::default()
+            },
+            domain,
+            virt_start,
+            virt_end,
+            ..Default::default()
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    #[test]
+    fn test_iommu_config() {
+        let mut iommu = Iommu::new(IommuConfig::default(), 0);
+        assert_eq!(iommu.queue_num(), QUEUE_NUM_IOMMU);
+        assert_eq!(iommu.device_type(), VIRTIO_TYPE_IOMMU);
+        iommu.realize().unwrap();
+
+        let mut data = [0_u8; 8];
+        iommu.read_config(0, &mut data).unwrap();
+        assert_eq!(u64::from_le_bytes(data), IOMMU_PAGE_SIZE_MASK);
+
+        let bypass_offset = offset_of!(VirtioIommuConfig, bypass) as u64;
+        let endpoint = iommu.add_endpoint(8);
+        assert!(endpoint.translate(GuestAddress(0x1000)).is_some());
+        iommu.write_config(bypass_offset, &[0]).unwrap();
+        assert!(endpoint.translate(GuestAddress(0x1000)).is_none());
+        assert_eq!(endpoint.mappings(), Some(Vec::new()));
+
+        iommu.reset().unwrap();
+        assert!(endpoint.mappings().is_none());
+        let mut bypass = [0_u8; 1];
+        iommu.read_config(bypass_offset, &mut bypass).unwrap();
+        assert_eq!(bypass[0], 1);
+    }
+
+    #[test]
+    fn test_iommu_requests() {
+        let mut iommu = Iommu::new(IommuConfig::default(), 0);
+        let endpoint = iommu.add_endpoint(8);
+        let features = 1 << VIRTIO_IOMMU_F_BYPASS_CONFIG as u64;
+        let mut state = iommu.state.write().unwrap();
+
+        // The endpoint doesn't exist.
+        assert_eq!(
+            state.handle_request(&attach_req(1, 9, 0), features).0,
+            VIRTIO_IOMMU_S_NOENT
+        );
+        assert_eq!(
+            state.handle_request(&attach_req(1, 8, 0), features),
+            (VIRTIO_IOMMU_S_OK, vec![8])
+        );
+        // The domain exists without bypass.
+        assert_eq!(
+            state
+                .handle_request(&attach_req(1, 8, VIRTIO_IOMMU_ATTACH_F_BYPASS), features)
+                .0,
+            VIRTIO_IOMMU_S_INVAL
+        );
+
+        assert_eq!(
+            state.handle_request(&map_req(1, 0x10000, 0x1ffff, 0x200000), features),
+            (VIRTIO_IOMMU_S_OK, vec![8])
+        );
+        assert_eq!(
+            state
+                .handle_request(&map_req(2, 0x30000, 0x3ffff, 0x300000), features)
+                .0,
+            VIRTIO_IOMMU_S_NOENT
+        );
+        // Overlapped with the existing mapping.
+        assert_eq!(
+            state
+                .handle_request(&map_req(1, 0x0, 0x10fff, 0x300000), features)
+                .0,
+            VIRTIO_IOMMU_S_INVAL
+        );
+        assert_eq!(
+            state
+                .handle_request(&map_req(1, 0x30000, 0x3ffff, 0x300000), features)
+                .0,
+            VIRTIO_IOMMU_S_OK
+        );
+        drop(state);
+
+        assert_eq!(
+            endpoint.translate(GuestAddress(0x10800)),
+            Some((GuestAddress(0x200800), 0xf800))
+        );
+        assert!(endpoint.translate(GuestAddress(0x20000)).is_none());
+        assert_eq!(endpoint.mappings().unwrap().len(), 2);
+
+        let mut state = iommu.state.write().unwrap();
+        // The mapping can't be split.
+        assert_eq!(
+            state
+                .handle_request(&unmap_req(1, 0x0, 0x17fff), features)
+                .0,
+            VIRTIO_IOMMU_S_RANGE
+        );
+        assert_eq!(
+            state
+                .handle_request(&unmap_req(1, 0x0, 0x2ffff), features)
+                .0,
+            VIRTIO_IOMMU_S_OK
+        );
+        drop(state);
+        assert!(endpoint.translate(GuestAddress(0x10800)).is_none());
+        assert_eq!(
+            endpoint.mappings(),
+            Some(vec![IommuMapping {
+                iova: 0x30000,
+                gpa: 0x300000,
+                size: 0x10000,
+            }])
+        );
+
+        // Move the endpoint to a bypass domain, and the old domain is destroyed.
+        let mut state = iommu.state.write().unwrap();
+        assert_eq!(
+            state
+                .handle_request(&attach_req(2, 8, VIRTIO_IOMMU_ATTACH_F_BYPASS), features)
+                .0,
+            VIRTIO_IOMMU_S_OK
+        );
+        assert!(!state.domains.contains_key(&1));
+        assert_eq!(
+            state
+                .handle_request(&map_req(2, 0x0, 0xfff, 0x0), features)
+                .0,
+            VIRTIO_IOMMU_S_INVAL
+        );
+        drop(state);
+        assert_eq!(
+            endpoint.translate(GuestAddress(0x20000)),
+            Some((GuestAddress(0x20000), u64::MAX - 0x20000))
+        );
+        assert!(endpoint.mappings().is_none());
+    }
+}
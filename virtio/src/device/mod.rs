@@ -12,9 +12,18 @@
 
 pub mod balloon;
 pub mod block;
+pub mod crypto;
 #[cfg(feature = "virtio_gpu")]
 pub mod gpu;
+pub mod iommu;
 pub mod net;
+pub mod net_filter;
+pub mod p9fs;
+mod p9fs_server;
+pub mod pmem;
 pub mod rng;
 pub mod scsi_cntlr;
 pub mod serial;
+pub mod sound;
+#[cfg(feature = "virtio_snd_alsa")]
+mod sound_alsa;
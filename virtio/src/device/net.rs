@@ -18,6 +18,7 @@ use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{cmp, fs, mem};
 
 use anyhow::{bail, Context, Result};
@@ -26,14 +27,16 @@ use log::{error, warn};
 use once_cell::sync::Lazy;
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
+use crate::device::net_filter::{get_net_filter_chain, NetFilterChain};
 use crate::{
-    check_config_space_rw, iov_discard_front, iov_to_buf, mem_to_buf, read_config_default,
+    check_config_space_rw, get_buf_and_discard, iov_discard_front, mem_to_buf, read_config_default,
     report_virtio_error, virtio_has_feature, ElemIovec, Element, Queue, VirtioBase, VirtioDevice,
     VirtioError, VirtioInterrupt, VirtioInterruptType, VirtioNetHdr, VirtioTrace,
     VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC,
     VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ,
     VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_NOTF_COAL, VIRTIO_NET_CTRL_NOTF_COAL_RX_SET,
+    VIRTIO_NET_CTRL_NOTF_COAL_TX_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
     VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST, VIRTIO_NET_CTRL_RX_NOMULTI,
     VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_CTRL_VLAN,
     VIRTIO_NET_CTRL_VLAN_ADD, VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM,
@@ -41,12 +44,13 @@ use crate::{
     VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN,
     VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO,
     VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
-    VIRTIO_NET_F_MQ, VIRTIO_NET_OK, VIRTIO_TYPE_NET,
+    VIRTIO_NET_F_MQ, VIRTIO_NET_F_MRG_RXBUF, VIRTIO_NET_F_NOTF_COAL, VIRTIO_NET_F_STATUS,
+    VIRTIO_NET_OK, VIRTIO_NET_S_LINK_UP, VIRTIO_TYPE_NET,
 };
 use address_space::{AddressSpace, RegionCache};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
-    config::{ConfigCheck, NetworkInterfaceConfig},
+    config::{ConfigCheck, NetFilterQueue, NetworkInterfaceConfig},
     event_loop::EventLoop,
 };
 use migration::{
@@ -54,11 +58,13 @@ use migration::{
     StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
+use util::aio::{iov_from_buf_direct, iov_to_buf_direct, Iovec};
 use util::byte_code::ByteCode;
 use util::loop_context::gen_delete_notifiers;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::metrics::{register_metric, Metric, MetricType};
 use util::num_ops::str_to_usize;
 use util::tap::{
     Tap, IFF_MULTI_QUEUE, TUN_F_CSUM, TUN_F_TSO4, TUN_F_TSO6, TUN_F_TSO_ECN, TUN_F_UFO,
@@ -78,6 +84,9 @@ const CTRL_MAX_VLAN: u16 = 1 << 12;
 const MAX_MAC_ADDR_NUM: usize = 0xff;
 /// The header length of virtio net packet.
 const NET_HDR_LENGTH: usize = mem::size_of::<VirtioNetHdr>();
+/// The header length of virtio net packet without `num_buffers`, which is used by the legacy
+/// driver if neither VIRTIO_F_VERSION_1 nor VIRTIO_NET_F_MRG_RXBUF is negotiated.
+const NET_HDR_LENGTH_LEGACY: usize = NET_HDR_LENGTH - mem::size_of::<u16>();
 /// The length of vlan tag.
 const VLAN_TAG_LENGTH: usize = 4;
 /// The offset of vlan tpid for 802.1Q tag.
@@ -161,6 +170,24 @@ struct CtrlMacInfo {
     multi_mac_of: bool,
 }
 
+/// The notifications coalescing parameters of queues.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct CoalesceParams {
+    /// Max number of used buffers before notifying the driver, 0 means no limit.
+    max_packets: u32,
+    /// Max delay of notifications in microseconds, 0 disables the coalescing.
+    usecs: u32,
+}
+
+impl ByteCode for CoalesceParams {}
+
+impl CoalesceParams {
+    fn enabled(&self) -> bool {
+        self.usecs != 0
+    }
+}
+
 pub struct CtrlInfo {
     /// The control rx mode for packet receive filtering.
     rx_mode: CtrlRxMode,
@@ -168,6 +195,10 @@ pub struct CtrlInfo {
     mac_info: CtrlMacInfo,
     /// The map of all the vlan ids.
     vlan_map: HashMap<u16, u32>,
+    /// The notifications coalescing parameters of rx queues.
+    rx_coalesce: CoalesceParams,
+    /// The notifications coalescing parameters of tx queues.
+    tx_coalesce: CoalesceParams,
     /// The net device status.
     config: Arc<Mutex<VirtioNetConfig>>,
 }
@@ -178,6 +209,8 @@ impl CtrlInfo {
             rx_mode: CtrlRxMode::default(),
             mac_info: CtrlMacInfo::default(),
             vlan_map: HashMap::new(),
+            rx_coalesce: CoalesceParams::default(),
+            tx_coalesce: CoalesceParams::default(),
             config,
         }
     }
@@ -402,6 +435,38 @@ impl CtrlInfo {
         ack
     }
 
+    fn handle_notf_coal(
+        &mut self,
+        mem_space: &AddressSpace,
+        cmd: u8,
+        data_iovec: &mut Vec<ElemIovec>,
+    ) -> u8 {
+        let mut ack = VIRTIO_NET_OK;
+        let mut params = CoalesceParams::default();
+
+        *data_iovec = get_buf_and_discard(mem_space, data_iovec, params.as_mut_bytes())
+            .unwrap_or_else(|e| {
+                error!("Failed to get coalescing parameters, error is {:?}", e);
+                ack = VIRTIO_NET_ERR;
+                Vec::new()
+            });
+        if ack == VIRTIO_NET_ERR {
+            return ack;
+        }
+        params.max_packets = LittleEndian::read_u32(params.max_packets.as_bytes());
+        params.usecs = LittleEndian::read_u32(params.usecs.as_bytes());
+
+        match cmd {
+            VIRTIO_NET_CTRL_NOTF_COAL_TX_SET => self.tx_coalesce = params,
+            VIRTIO_NET_CTRL_NOTF_COAL_RX_SET => self.rx_coalesce = params,
+            _ => {
+                error!("Invalid cmd {} when handling control notf coal", cmd);
+                ack = VIRTIO_NET_ERR;
+            }
+        }
+        ack
+    }
+
     fn filter_packets(&mut self, buf: &[u8]) -> bool {
         // Broadcast address: 0xff:0xff:0xff:0xff:0xff:0xff.
         let bcast = [0xff; MAC_ADDR_LEN];
@@ -462,26 +527,6 @@ impl CtrlInfo {
     }
 }
 
-fn get_buf_and_discard(
-    mem_space: &AddressSpace,
-    iovec: &mut [ElemIovec],
-    buf: &mut [u8],
-) -> Result<Vec<ElemIovec>> {
-    iov_to_buf(mem_space, iovec, buf).and_then(|size| {
-        if size < buf.len() {
-            error!("Invalid length {}, expected length {}", size, buf.len());
-            bail!("Invalid length {}, expected length {}", size, buf.len());
-        }
-        Ok(())
-    })?;
-
-    if let Some(data_iovec) = iov_discard_front(iovec, buf.len() as u64) {
-        Ok(data_iovec.to_vec())
-    } else {
-        Ok(Vec::new())
-    }
-}
-
 /// The control queue is used to verify the multi queue feature.
 pub struct CtrlVirtio {
     /// The control queue.
@@ -600,6 +645,13 @@ impl NetCtrlHandler {
                         &mut data_iovec,
                     );
                 }
+                VIRTIO_NET_CTRL_NOTF_COAL => {
+                    ack = self.ctrl.ctrl_info.lock().unwrap().handle_notf_coal(
+                        &self.mem_space,
+                        ctrl_hdr.cmd,
+                        &mut data_iovec,
+                    );
+                }
                 _ => {
                     error!(
                         "Control queue header class {} not supported",
@@ -669,14 +721,39 @@ impl EventNotifierHelper for NetCtrlHandler {
     }
 }
 
+/// The notification of used buffers which is postponed by notifications coalescing.
+#[derive(Default)]
+struct NotifyCoalesce {
+    /// Number of used buffers which the driver has not been notified of.
+    pending: u32,
+    /// Timer to send the postponed notification.
+    timer_id: Option<u64>,
+}
+
+impl NotifyCoalesce {
+    fn cancel(&mut self, iothread: Option<&String>) {
+        self.pending = 0;
+        if let Some(timer_id) = self.timer_id.take() {
+            if let Some(ctx) = EventLoop::get_ctx(iothread) {
+                ctx.timer_del(timer_id);
+            }
+        }
+    }
+}
+
 struct TxVirtio {
     queue: Arc<Mutex<Queue>>,
     queue_evt: Arc<EventFd>,
+    coalesce: Arc<Mutex<NotifyCoalesce>>,
 }
 
 impl TxVirtio {
     fn new(queue: Arc<Mutex<Queue>>, queue_evt: Arc<EventFd>) -> Self {
-        TxVirtio { queue, queue_evt }
+        TxVirtio {
+            queue,
+            queue_evt,
+            coalesce: Arc::new(Mutex::new(NotifyCoalesce::default())),
+        }
     }
 }
 
@@ -684,6 +761,7 @@ struct RxVirtio {
     queue_full: bool,
     queue: Arc<Mutex<Queue>>,
     queue_evt: Arc<EventFd>,
+    coalesce: Arc<Mutex<NotifyCoalesce>>,
 }
 
 impl RxVirtio {
@@ -692,6 +770,33 @@ impl RxVirtio {
             queue_full: false,
             queue,
             queue_evt,
+            coalesce: Arc::new(Mutex::new(NotifyCoalesce::default())),
+        }
+    }
+}
+
+/// Packet counters of one network device.
+struct NetMetrics {
+    rx_packets: Arc<Metric>,
+    tx_packets: Arc<Metric>,
+}
+
+impl NetMetrics {
+    fn new(id: &str) -> Self {
+        let labels = [("device", id)];
+        NetMetrics {
+            rx_packets: register_metric(
+                "stratovirt_net_rx_packets_total",
+                "Number of packets received by the network device.",
+                MetricType::Counter,
+                &labels,
+            ),
+            tx_packets: register_metric(
+                "stratovirt_net_tx_packets_total",
+                "Number of packets transmitted by the network device.",
+                MetricType::Counter,
+                &labels,
+            ),
         }
     }
 }
@@ -710,6 +815,17 @@ struct NetIoHandler {
     is_listening: bool,
     ctrl_info: Arc<Mutex<CtrlInfo>>,
     queue_size: u16,
+    metrics: Option<Arc<NetMetrics>>,
+    /// Net filters attached to the netdev.
+    filters: Option<Arc<NetFilterChain>>,
+    /// Whether the packets injected by net filters are consumed by this handler.
+    inject_packets: bool,
+    /// Link status of the device, packets are dropped when the link is down.
+    link_up: Arc<AtomicBool>,
+    /// Iothread which the handler and its timers run in.
+    iothread: Option<String>,
+    /// The header length of virtio net packet negotiated with the driver.
+    hdr_len: usize,
 }
 
 impl NetIoHandler {
@@ -774,8 +890,144 @@ impl NetIoHandler {
         iovecs
     }
 
+    /// Pass the packet in iovecs through the net filters, return false if it should not be
+    /// delivered anymore.
+    fn filter_packet(
+        &self,
+        direction: NetFilterQueue,
+        iovecs: &[libc::iovec],
+        size: usize,
+    ) -> bool {
+        let filters = match self.filters.as_ref() {
+            Some(filters) if !filters.is_empty() => filters,
+            _ => return true,
+        };
+        if size <= self.hdr_len {
+            return true;
+        }
+        let iovecs: Vec<Iovec> = iovecs
+            .iter()
+            .map(|iov| Iovec::new(iov.iov_base as u64, iov.iov_len as u64))
+            .collect();
+        let mut buf = vec![0_u8; size];
+        match iov_to_buf_direct(&iovecs, 0, &mut buf) {
+            Ok(len) => filters.filter(direction, &buf[self.hdr_len..len]),
+            Err(e) => {
+                error!("Failed to copy packet for net filter: {:?}", e);
+                true
+            }
+        }
+    }
+
+    /// Deliver the packets injected by net filters to the guest.
+    fn handle_injected_rx(&mut self) -> Result<()> {
+        let filters = match self.filters.as_ref() {
+            Some(filters) if self.inject_packets => filters.clone(),
+            _ => return Ok(()),
+        };
+        let mut queue = self.rx.queue.lock().unwrap();
+        while let Some(packet) = filters.pop_injected(NetFilterQueue::Rx) {
+            let elem = queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for net rx")?;
+            if elem.desc_num == 0 {
+                filters.push_front_injected(NetFilterQueue::Rx, packet);
+                self.rx.queue_full = true;
+                break;
+            } else if elem.in_iovec.is_empty() {
+                bail!("The length of in iovec is 0");
+            }
+            let iovecs: Vec<Iovec> = NetIoHandler::get_libc_iovecs(
+                &self.mem_space,
+                queue.vring.get_cache(),
+                &elem.in_iovec,
+            )
+            .iter()
+            .map(|iov| Iovec::new(iov.iov_base as u64, iov.iov_len as u64))
+            .collect();
+
+            // The packet is in one buffer, so num_buffers in the header is 1.
+            let mut buf = vec![0_u8; self.hdr_len + packet.len()];
+            if self.hdr_len == NET_HDR_LENGTH {
+                LittleEndian::write_u16(&mut buf[NET_HDR_LENGTH - 2..NET_HDR_LENGTH], 1);
+            }
+            buf[self.hdr_len..].copy_from_slice(&packet);
+            let size = iov_from_buf_direct(&iovecs, &buf)?;
+            if size < buf.len() {
+                warn!("Net rx buffer is too small for the injected packet, drop it");
+                queue.vring.push_back();
+                continue;
+            }
+            if MigrationManager::is_active() {
+                for iov in iovecs.iter() {
+                    MigrationManager::mark_dirty_log(iov.iov_base, iov.iov_len);
+                }
+            }
+
+            queue
+                .vring
+                .add_used(&self.mem_space, elem.index, size as u32)
+                .with_context(|| {
+                    format!(
+                        "Failed to add used ring for net rx, index: {}, len: {}",
+                        elem.index, size
+                    )
+                })?;
+            self.notify_used(&mut queue, true)?;
+        }
+        Ok(())
+    }
+
+    /// Send the packets injected by net filters to the tap.
+    fn handle_injected_tx(&mut self) -> Result<()> {
+        let filters = match self.filters.as_ref() {
+            Some(filters) if self.inject_packets => filters.clone(),
+            _ => return Ok(()),
+        };
+        let hdr = vec![0_u8; self.hdr_len];
+        while let Some(packet) = filters.pop_injected(NetFilterQueue::Tx) {
+            if self.tap_fd == -1 {
+                continue;
+            }
+            let iovecs = [
+                libc::iovec {
+                    iov_base: hdr.as_ptr() as *mut libc::c_void,
+                    iov_len: hdr.len(),
+                },
+                libc::iovec {
+                    iov_base: packet.as_ptr() as *mut libc::c_void,
+                    iov_len: packet.len(),
+                },
+            ];
+            if self.send_packets(self.tap_fd, &iovecs) == -1 {
+                filters.push_front_injected(NetFilterQueue::Tx, packet);
+                self.tx.queue_evt.write(1).with_context(|| {
+                    "Failed to trigger tx queue event when writev blocked".to_string()
+                })?;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the packets received from the tap, as if the cable is pulled out.
+    fn drain_tap(&mut self) {
+        let tap = match self.tap.as_mut() {
+            Some(tap) => tap,
+            None => return,
+        };
+        let mut buf = vec![0_u8; NET_HDR_LENGTH + u16::MAX as usize];
+        while tap.read(&mut buf).is_ok() {}
+    }
+
     fn handle_rx(&mut self) -> Result<()> {
         self.trace_request("Net".to_string(), "to rx".to_string());
+        if !self.link_up.load(Ordering::SeqCst) {
+            self.drain_tap();
+            return Ok(());
+        }
+        self.handle_injected_rx()?;
         if self.tap.is_none() {
             return Ok(());
         }
@@ -809,12 +1061,12 @@ impl NetIoHandler {
 
             // Read the data from the tap device.
             let size = NetIoHandler::read_from_tap(&iovecs, self.tap.as_mut().unwrap());
-            if size < (NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH) as i32 {
+            if size < (self.hdr_len + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH) as i32 {
                 queue.vring.push_back();
                 break;
             }
 
-            let mut buf = vec![0_u8; NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH];
+            let mut buf = vec![0_u8; self.hdr_len + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH];
             get_net_header(&iovecs, &mut buf).and_then(|size| {
                 if size != buf.len() {
                     bail!(
@@ -829,7 +1081,8 @@ impl NetIoHandler {
                 .ctrl_info
                 .lock()
                 .unwrap()
-                .filter_packets(&buf[NET_HDR_LENGTH..])
+                .filter_packets(&buf[self.hdr_len..])
+                || !self.filter_packet(NetFilterQueue::Rx, &iovecs, size as usize)
             {
                 queue.vring.push_back();
                 continue;
@@ -845,17 +1098,11 @@ impl NetIoHandler {
                     )
                 })?;
 
-            if queue
-                .vring
-                .should_notify(&self.mem_space, self.driver_features)
-            {
-                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
-                    .with_context(|| {
-                        VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring)
-                    })?;
-                self.trace_send_interrupt("Net".to_string());
-            }
+            self.notify_used(&mut queue, true)?;
 
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.rx_packets.inc();
+            }
             rx_packets += 1;
             if rx_packets >= self.queue_size {
                 self.rx
@@ -869,6 +1116,95 @@ impl NetIoHandler {
         Ok(())
     }
 
+    /// Notify the driver of the used buffers in rx or tx queue. If notifications coalescing
+    /// is enabled, the notification is postponed until `max_packets` buffers are used or
+    /// `usecs` microseconds elapse.
+    fn notify_used(&self, queue: &mut Queue, is_rx: bool) -> Result<()> {
+        let (queue_ref, coalesce) = if is_rx {
+            (&self.rx.queue, &self.rx.coalesce)
+        } else {
+            (&self.tx.queue, &self.tx.coalesce)
+        };
+        let params = {
+            let locked_ctrl_info = self.ctrl_info.lock().unwrap();
+            if is_rx {
+                locked_ctrl_info.rx_coalesce
+            } else {
+                locked_ctrl_info.tx_coalesce
+            }
+        };
+
+        if params.enabled() {
+            let mut locked_coalesce = coalesce.lock().unwrap();
+            locked_coalesce.pending += 1;
+            if params.max_packets == 0 || locked_coalesce.pending < params.max_packets {
+                if locked_coalesce.timer_id.is_none() {
+                    self.start_coalesce_timer(
+                        queue_ref,
+                        coalesce,
+                        &mut locked_coalesce,
+                        params.usecs,
+                    );
+                }
+                return Ok(());
+            }
+            locked_coalesce.cancel(self.iothread.as_ref());
+        }
+
+        if queue
+            .vring
+            .should_notify(&self.mem_space, self.driver_features)
+        {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&*queue), false).with_context(
+                || VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring),
+            )?;
+            self.trace_send_interrupt("Net".to_string());
+        }
+        Ok(())
+    }
+
+    fn start_coalesce_timer(
+        &self,
+        queue: &Arc<Mutex<Queue>>,
+        coalesce: &Arc<Mutex<NotifyCoalesce>>,
+        locked_coalesce: &mut NotifyCoalesce,
+        usecs: u32,
+    ) {
+        let queue = Arc::downgrade(queue);
+        let coalesce = coalesce.clone();
+        let mem_space = self.mem_space.clone();
+        let interrupt_cb = self.interrupt_cb.clone();
+        let driver_features = self.driver_features;
+        let device_broken = self.device_broken.clone();
+        let func = Box::new(move || {
+            let mut locked_coalesce = coalesce.lock().unwrap();
+            locked_coalesce.pending = 0;
+            locked_coalesce.timer_id = None;
+            drop(locked_coalesce);
+
+            let queue = match queue.upgrade() {
+                Some(queue) if !device_broken.load(Ordering::SeqCst) => queue,
+                _ => return,
+            };
+            let mut locked_queue = queue.lock().unwrap();
+            if !locked_queue.is_enabled()
+                || !locked_queue
+                    .vring
+                    .should_notify(&mem_space, driver_features)
+            {
+                return;
+            }
+            if let Err(e) = (interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+            {
+                error!("Failed to send postponed net notification: {:?}", e);
+            }
+        });
+        if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
+            locked_coalesce.timer_id =
+                Some(ctx.timer_add(func, Duration::from_micros(u64::from(usecs))));
+        }
+    }
+
     fn send_packets(&self, tap_fd: libc::c_int, iovecs: &[libc::iovec]) -> i8 {
         loop {
             // SAFETY: the arguments of writev has been checked and is correct.
@@ -895,6 +1231,7 @@ impl NetIoHandler {
 
     fn handle_tx(&mut self) -> Result<()> {
         self.trace_request("Net".to_string(), "to tx".to_string());
+        self.handle_injected_tx()?;
         let mut queue = self.tx.queue.lock().unwrap();
 
         let mut tx_packets = 0;
@@ -919,6 +1256,14 @@ impl NetIoHandler {
             } else {
                 -1_i32
             };
+            let size = iovecs.iter().map(|iov| iov.iov_len).sum();
+            let tap_fd = if self.link_up.load(Ordering::SeqCst)
+                && self.filter_packet(NetFilterQueue::Tx, &iovecs, size)
+            {
+                tap_fd
+            } else {
+                -1_i32
+            };
             if tap_fd != -1 && self.send_packets(tap_fd, &iovecs) == -1 {
                 queue.vring.push_back();
                 self.tx.queue_evt.write(1).with_context(|| {
@@ -932,15 +1277,9 @@ impl NetIoHandler {
                 .add_used(&self.mem_space, elem.index, 0)
                 .with_context(|| format!("Net tx: Failed to add used ring {}", elem.index))?;
 
-            if queue
-                .vring
-                .should_notify(&self.mem_space, self.driver_features)
-            {
-                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
-                    .with_context(|| {
-                        VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring)
-                    })?;
-                self.trace_send_interrupt("Net".to_string());
+            self.notify_used(&mut queue, false)?;
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.tx_packets.inc();
             }
             tx_packets += 1;
             if tx_packets >= self.queue_size {
@@ -967,6 +1306,9 @@ impl NetIoHandler {
         let old_tap_fd = locked_net_io.tap_fd;
         locked_net_io.tap_fd = -1;
         if let Some(tap) = locked_net_io.tap.as_ref() {
+            if let Err(e) = tap.set_hdr_size(locked_net_io.hdr_len as u32) {
+                error!("Failed to set tap hdr size: {:?}", e);
+            }
             locked_net_io.tap_fd = tap.as_raw_fd();
         }
 
@@ -978,6 +1320,11 @@ impl NetIoHandler {
         if old_tap_fd != -1 {
             notifiers_fds.push(old_tap_fd);
         }
+        if let Some(filters) = locked_net_io.filters.as_ref() {
+            if locked_net_io.inject_packets {
+                notifiers_fds.push(filters.inject_evt.as_raw_fd());
+            }
+        }
         let mut notifiers = gen_delete_notifiers(&notifiers_fds);
         drop(locked_net_io);
 
@@ -1049,6 +1396,15 @@ impl EventNotifierHelper for NetIoHandler {
             if locked_net_io.device_broken.load(Ordering::SeqCst) {
                 return None;
             }
+            if let Err(ref e) = locked_net_io.handle_injected_rx() {
+                error!("Failed to handle injected rx for net, {:?}", e);
+                report_virtio_error(
+                    locked_net_io.interrupt_cb.clone(),
+                    locked_net_io.driver_features,
+                    &locked_net_io.device_broken,
+                );
+                return None;
+            }
             if let Some(tap) = locked_net_io.tap.as_ref() {
                 if !locked_net_io.is_listening {
                     let notifier = vec![EventNotifier::new(
@@ -1098,6 +1454,38 @@ impl EventNotifierHelper for NetIoHandler {
             EventSet::IN,
         ));
 
+        // Register event notifier for the packets injected by net filters.
+        if let Some(filters) = locked_net_io.filters.as_ref() {
+            if locked_net_io.inject_packets {
+                let cloned_net_io = net_io.clone();
+                let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+                    read_fd(fd);
+                    let mut locked_net_io = cloned_net_io.lock().unwrap();
+                    if locked_net_io.device_broken.load(Ordering::SeqCst) {
+                        return None;
+                    }
+                    if let Err(ref e) = locked_net_io
+                        .handle_injected_rx()
+                        .and_then(|_| locked_net_io.handle_injected_tx())
+                    {
+                        error!("Failed to handle injected packets for net, {:?}", e);
+                        report_virtio_error(
+                            locked_net_io.interrupt_cb.clone(),
+                            locked_net_io.driver_features,
+                            &locked_net_io.device_broken,
+                        );
+                    }
+                    None
+                });
+                notifiers.push(build_event_notifier(
+                    filters.inject_evt.as_raw_fd(),
+                    Some(handler),
+                    NotifierOperation::AddShared,
+                    EventSet::IN,
+                ));
+            }
+        }
+
         // Register event notifier for tap.
         let cloned_net_io = net_io.clone();
         if let Some(tap) = locked_net_io.tap.as_ref() {
@@ -1178,6 +1566,14 @@ pub struct Net {
     update_evts: Vec<Arc<EventFd>>,
     /// The information about control command.
     ctrl_info: Option<Arc<Mutex<CtrlInfo>>>,
+    /// Packet counters of the network device.
+    metrics: Option<Arc<NetMetrics>>,
+    /// Net filters attached to the netdev.
+    filters: Option<Arc<NetFilterChain>>,
+    /// Link status of the device, which can be changed at runtime.
+    link_up: Arc<AtomicBool>,
+    /// Used to notify the config change, available after the device is activated.
+    interrupt_cb: Option<Arc<VirtioInterrupt>>,
 }
 
 impl Net {
@@ -1188,13 +1584,48 @@ impl Net {
             QUEUE_NUM_NET
         };
         let queue_size = net_cfg.queue_size;
+        let metrics = Some(Arc::new(NetMetrics::new(&net_cfg.id)));
 
         Self {
             base: VirtioBase::new(VIRTIO_TYPE_NET, queue_num, queue_size),
             net_cfg,
+            metrics,
+            link_up: Arc::new(AtomicBool::new(true)),
             ..Default::default()
         }
     }
+
+    /// Set the link status, and notify the guest by config interrupt. Packets are
+    /// dropped in both directions when the link is down.
+    pub fn set_link_status(&mut self, up: bool) -> Result<()> {
+        self.link_up.store(up, Ordering::SeqCst);
+        self.config_space.lock().unwrap().status = if up { VIRTIO_NET_S_LINK_UP } else { 0 };
+        self.notify_config_change()
+    }
+
+    /// Change the mac address in config space, and notify the guest by config interrupt.
+    /// The guest driver gets the new address when it reads the config space again.
+    pub fn set_mac_address(&mut self, mac: &str) -> Result<()> {
+        let mut locked_config = self.config_space.lock().unwrap();
+        let old_mac = locked_config.mac;
+        if build_device_config_space(&mut locked_config, mac) == 0 {
+            bail!("Invalid mac address {}", mac);
+        }
+        mark_mac_table(&old_mac, false);
+        mark_mac_table(&locked_config.mac, true);
+        drop(locked_config);
+        self.net_cfg.mac = Some(mac.to_string());
+        self.notify_config_change()
+    }
+
+    fn notify_config_change(&self) -> Result<()> {
+        if let Some(interrupt_cb) = self.interrupt_cb.as_ref() {
+            interrupt_cb(&VirtioInterruptType::Config, None, false).with_context(|| {
+                VirtioError::InterruptTrigger("net", VirtioInterruptType::Config)
+            })?;
+        }
+        Ok(())
+    }
 }
 
 /// Set Mac address configured into the virtio configuration, and return features mask with
@@ -1289,10 +1720,12 @@ fn check_mq(dev_name: &str, queue_pair: u16) -> Result<()> {
 /// * `net_fd` - Fd of tap device opened.
 /// * `host_dev_name` - Path of tap device on host.
 /// * `queue_pairs` - The number of virtio queue pairs.
+/// * `sndbuf` - Send buffer size of tap device in bytes.
 pub fn create_tap(
     net_fds: Option<&Vec<i32>>,
     host_dev_name: Option<&str>,
     queue_pairs: u16,
+    sndbuf: Option<u64>,
 ) -> Result<Option<Vec<Tap>>> {
     if net_fds.is_none() && host_dev_name.is_none() {
         return Ok(None);
@@ -1323,6 +1756,11 @@ pub fn create_tap(
 
         tap.set_hdr_size(NET_HDR_LENGTH as u32)
             .with_context(|| "Failed to set tap hdr size")?;
+        if let Some(size) = sndbuf {
+            // The size has been checked not bigger than i32::MAX.
+            tap.set_sndbuf(size as i32)
+                .with_context(|| "Failed to set tap sndbuf")?;
+        }
 
         taps.push(tap);
     }
@@ -1330,12 +1768,27 @@ pub fn create_tap(
     Ok(Some(taps))
 }
 
+/// Get the header length of virtio net packet according to the negotiated features.
+///
+/// # Arguments
+///
+/// * `features` - The driver features.
+fn get_net_hdr_len(features: u64) -> usize {
+    if virtio_has_feature(features, VIRTIO_F_VERSION_1)
+        || virtio_has_feature(features, VIRTIO_NET_F_MRG_RXBUF)
+    {
+        NET_HDR_LENGTH
+    } else {
+        NET_HDR_LENGTH_LEGACY
+    }
+}
+
 /// Get the tap offload flags from driver features.
 ///
 /// # Arguments
 ///
 /// * `features` - The driver features.
-fn get_tap_offload_flags(features: u64) -> u32 {
+pub fn get_tap_offload_flags(features: u64) -> u32 {
     let mut flags: u32 = 0;
     if virtio_has_feature(features, VIRTIO_NET_F_GUEST_CSUM) {
         flags |= TUN_F_CSUM;
@@ -1377,8 +1830,13 @@ impl VirtioDevice for Net {
 
         let queue_pairs = self.net_cfg.queues / 2;
         if !self.net_cfg.host_dev_name.is_empty() {
-            self.taps = create_tap(None, Some(&self.net_cfg.host_dev_name), queue_pairs)
-                .with_context(|| "Failed to open tap with file path")?;
+            self.taps = create_tap(
+                None,
+                Some(&self.net_cfg.host_dev_name),
+                queue_pairs,
+                self.net_cfg.sndbuf,
+            )
+            .with_context(|| "Failed to open tap with file path")?;
         } else if let Some(fds) = self.net_cfg.tap_fds.as_mut() {
             let mut created_fds = 0;
             if let Some(taps) = &self.taps {
@@ -1390,13 +1848,17 @@ impl VirtioDevice for Net {
             }
 
             if created_fds != fds.len() {
-                self.taps = create_tap(Some(fds), None, queue_pairs)
+                self.taps = create_tap(Some(fds), None, queue_pairs, self.net_cfg.sndbuf)
                     .with_context(|| "Failed to open tap")?;
             }
         } else {
             self.taps = None;
         }
 
+        if !self.net_cfg.netdev.is_empty() {
+            self.filters = Some(get_net_filter_chain(&self.net_cfg.netdev)?);
+        }
+
         self.init_config_features()?;
 
         Ok(())
@@ -1417,10 +1879,17 @@ impl VirtioDevice for Net {
             | 1 << VIRTIO_NET_F_CTRL_RX_EXTRA
             | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
             | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_STATUS
+            | 1 << VIRTIO_NET_F_NOTF_COAL
             | 1 << VIRTIO_F_RING_INDIRECT_DESC
             | 1 << VIRTIO_F_RING_EVENT_IDX;
 
         let mut locked_config = self.config_space.lock().unwrap();
+        locked_config.status = if self.link_up.load(Ordering::SeqCst) {
+            VIRTIO_NET_S_LINK_UP
+        } else {
+            0
+        };
 
         let queue_pairs = self.net_cfg.queues / 2;
         if self.net_cfg.mq
@@ -1494,7 +1963,12 @@ impl VirtioDevice for Net {
     ) -> Result<()> {
         let queues = self.base.queues.clone();
         let queue_num = queues.len();
-        let ctrl_info = Arc::new(Mutex::new(CtrlInfo::new(self.config_space.clone())));
+        let mut ctrl_info = CtrlInfo::new(self.config_space.clone());
+        ctrl_info.rx_coalesce = CoalesceParams {
+            max_packets: self.net_cfg.max_frames,
+            usecs: self.net_cfg.max_usecs,
+        };
+        let ctrl_info = Arc::new(Mutex::new(ctrl_info));
         self.ctrl_info = Some(ctrl_info.clone());
         let driver_features = self.base.driver_features;
         if (driver_features & 1 << VIRTIO_NET_F_CTRL_VQ != 0) && (queue_num % 2 != 0) {
@@ -1522,6 +1996,7 @@ impl VirtioDevice for Net {
         // The features about offload is included in bits 0 to 31.
         let features = self.driver_features(0_u32);
         let flags = get_tap_offload_flags(features as u64);
+        let hdr_len = get_net_hdr_len(driver_features);
 
         let mut senders = Vec::new();
         let queue_pairs = queue_num / 2;
@@ -1537,6 +2012,8 @@ impl VirtioDevice for Net {
             if let Some(tap) = self.taps.as_ref().map(|t| t[index].clone()) {
                 tap.set_offload(flags)
                     .with_context(|| "Failed to set tap offload")?;
+                tap.set_hdr_size(hdr_len as u32)
+                    .with_context(|| "Failed to set tap hdr size")?;
             }
 
             let update_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
@@ -1554,6 +2031,12 @@ impl VirtioDevice for Net {
                 is_listening: true,
                 ctrl_info: ctrl_info.clone(),
                 queue_size: self.queue_size_max(),
+                metrics: self.metrics.clone(),
+                filters: self.filters.clone(),
+                inject_packets: index == 0,
+                link_up: self.link_up.clone(),
+                iothread: self.net_cfg.iothread.clone(),
+                hdr_len,
             };
             if let Some(tap) = &handler.tap {
                 handler.tap_fd = tap.as_raw_fd();
@@ -1568,6 +2051,7 @@ impl VirtioDevice for Net {
             self.update_evts.push(update_evt);
         }
         self.senders = Some(senders);
+        self.interrupt_cb = Some(interrupt_cb);
         self.base.broken.store(false, Ordering::SeqCst);
 
         Ok(())
@@ -1632,6 +2116,7 @@ impl VirtioDevice for Net {
         )?;
         self.update_evts.clear();
         self.ctrl_info = None;
+        self.interrupt_cb = None;
         Ok(())
     }
 }
@@ -1744,12 +2229,12 @@ mod tests {
     #[test]
     fn test_net_create_tap() {
         // Test None net_fds and host_dev_name.
-        assert!(create_tap(None, None, 16).unwrap().is_none());
+        assert!(create_tap(None, None, 16, None).unwrap().is_none());
 
         // Test create tap with net_fds and host_dev_name.
         let net_fds = vec![32, 33];
         let tap_name = "tap0";
-        if let Err(err) = create_tap(Some(&net_fds), Some(&tap_name), 1) {
+        if let Err(err) = create_tap(Some(&net_fds), Some(&tap_name), 1, None) {
             let err_msg = format!("Failed to create tap, index is 0");
             assert_eq!(err.to_string(), err_msg);
         } else {
@@ -1757,7 +2242,7 @@ mod tests {
         }
 
         // Test create tap with empty net_fds.
-        if let Err(err) = create_tap(Some(&vec![]), None, 1) {
+        if let Err(err) = create_tap(Some(&vec![]), None, 1, None) {
             let err_msg = format!("Failed to get fd from index 0");
             assert_eq!(err.to_string(), err_msg);
         } else {
@@ -1765,7 +2250,7 @@ mod tests {
         }
 
         // Test create tap with tap_name which is not exist.
-        if let Err(err) = create_tap(None, Some("the_tap_is_not_exist"), 1) {
+        if let Err(err) = create_tap(None, Some("the_tap_is_not_exist"), 1, None) {
             let err_msg =
                 format!("Failed to create tap with name the_tap_is_not_exist, index is 0");
             assert_eq!(err.to_string(), err_msg);
@@ -1793,6 +2278,14 @@ mod tests {
         assert_eq!(ctrl_info.filter_packets(&buf), false);
     }
 
+    #[test]
+    fn test_net_hdr_len() {
+        assert_eq!(get_net_hdr_len(1 << VIRTIO_F_VERSION_1), NET_HDR_LENGTH);
+        assert_eq!(get_net_hdr_len(1 << VIRTIO_NET_F_MRG_RXBUF), NET_HDR_LENGTH);
+        // The legacy driver without mergeable rx buffers doesn't use num_buffers.
+        assert_eq!(get_net_hdr_len(1 << VIRTIO_NET_F_CSUM), 10);
+    }
+
     #[test]
     fn test_net_config_space() {
         let mut net_config = VirtioNetConfig::default();
@@ -1807,6 +2300,29 @@ mod tests {
         assert_eq!(ret, 0);
     }
 
+    #[test]
+    fn test_net_set_link_and_mac() {
+        let mut net = Net::new(NetworkInterfaceConfig::default());
+        assert!(net.link_up.load(Ordering::SeqCst));
+
+        // The guest is not notified before the device is activated.
+        net.set_link_status(false).unwrap();
+        assert!(!net.link_up.load(Ordering::SeqCst));
+        let status = net.config_space.lock().unwrap().status;
+        assert_eq!(status, 0);
+        net.set_link_status(true).unwrap();
+        let status = net.config_space.lock().unwrap().status;
+        assert_eq!(status, VIRTIO_NET_S_LINK_UP);
+
+        net.set_mac_address("52:54:00:aa:bb:cc").unwrap();
+        let mac = net.config_space.lock().unwrap().mac;
+        assert_eq!(mac, [0x52, 0x54, 0x00, 0xaa, 0xbb, 0xcc]);
+        assert_eq!(net.net_cfg.mac, Some("52:54:00:aa:bb:cc".to_string()));
+        assert!(net.set_mac_address("52:54:00:aa:bb:").is_err());
+        let mac = net.config_space.lock().unwrap().mac;
+        assert_eq!(mac, [0x52, 0x54, 0x00, 0xaa, 0xbb, 0xcc]);
+    }
+
     #[test]
     fn test_mac_table() {
         let mut mac = FIRST_DEFAULT_MAC;
@@ -0,0 +1,346 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, error, warn};
+use once_cell::sync::Lazy;
+use vmm_sys_util::eventfd::EventFd;
+
+use chardev_backend::chardev::{Chardev, InputReceiver};
+use machine_manager::{
+    config::{ChardevConfig, NetFilterConfig, NetFilterQueue, NetFilterType},
+    event_loop::EventLoop,
+};
+use util::loop_context::EventNotifierHelper;
+
+/// Max length of the packet transferred through the chardevs of net filters.
+const MAX_FILTER_PACKET_LEN: usize = 65536;
+/// Size of the big-endian length field before each packet in the chardev stream.
+const PACKET_LEN_SIZE: usize = 4;
+/// Max number of the injected packets which are not consumed by the net device yet.
+const MAX_INJECTED_PACKETS: usize = 256;
+
+/// Filter chains of netdevs, the key is the id of netdev.
+static NET_FILTER_CHAINS: Lazy<Mutex<HashMap<String, Arc<NetFilterChain>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Get the filter chain of the netdev, it is created if not exists. The net device and
+/// the filters can be created in any order.
+pub fn get_net_filter_chain(netdev: &str) -> Result<Arc<NetFilterChain>> {
+    let mut chains = NET_FILTER_CHAINS.lock().unwrap();
+    if let Some(chain) = chains.get(netdev) {
+        return Ok(chain.clone());
+    }
+    let chain = Arc::new(NetFilterChain::new()?);
+    chains.insert(netdev.to_string(), chain.clone());
+    Ok(chain)
+}
+
+/// Create the net filter with its chardevs, and attach it to the netdev.
+///
+/// # Arguments
+///
+/// * `config` - Config of the net filter.
+/// * `chardevs` - Configs of the chardevs, which `outdev` and `indev` refer to.
+pub fn add_net_filter(
+    config: &NetFilterConfig,
+    chardevs: &HashMap<String, ChardevConfig>,
+) -> Result<()> {
+    if NET_FILTER_CHAINS
+        .lock()
+        .unwrap()
+        .values()
+        .any(|chain| chain.contains(&config.id))
+    {
+        bail!("Net filter {} already exists", config.id);
+    }
+    let chain = get_net_filter_chain(&config.netdev)?;
+    let mut filter = NetFilter {
+        id: config.id.clone(),
+        filter_type: config.filter_type,
+        queue: config.queue,
+        outdev: None,
+        indev: None,
+    };
+    if let Some(outdev) = config.outdev.as_ref() {
+        filter.outdev = Some(create_filter_chardev(outdev, chardevs)?);
+    }
+    if let Some(indev) = config.indev.as_ref() {
+        let chardev = match create_filter_chardev(indev, chardevs) {
+            Ok(chardev) => chardev,
+            Err(e) => {
+                filter.release();
+                return Err(e);
+            }
+        };
+        let receiver = Arc::new(Mutex::new(FilterInput {
+            chain: Arc::downgrade(&chain),
+            direction: config.queue,
+            buf: Vec::new(),
+        }));
+        chardev.lock().unwrap().set_receiver(&receiver);
+        filter.indev = Some(chardev);
+    }
+
+    let mut filters = chain.filters.lock().unwrap();
+    filters.push(filter);
+    chain.filter_num.store(filters.len(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Detach the net filter from its netdev and release its chardevs.
+pub fn del_net_filter(id: &str) -> Result<()> {
+    let chains = NET_FILTER_CHAINS.lock().unwrap();
+    for chain in chains.values() {
+        let mut filters = chain.filters.lock().unwrap();
+        if let Some(pos) = filters.iter().position(|filter| filter.id == id) {
+            let filter = filters.remove(pos);
+            chain.filter_num.store(filters.len(), Ordering::SeqCst);
+            drop(filters);
+            filter.release();
+            return Ok(());
+        }
+    }
+    bail!("Net filter {} not found", id);
+}
+
+fn create_filter_chardev(
+    id: &str,
+    chardevs: &HashMap<String, ChardevConfig>,
+) -> Result<Arc<Mutex<Chardev>>> {
+    let config = chardevs
+        .get(id)
+        .with_context(|| format!("Chardev {} not found for net filter", id))?;
+    let chardev = Arc::new(Mutex::new(Chardev::new(config.clone())));
+    chardev
+        .lock()
+        .unwrap()
+        .realize()
+        .with_context(|| format!("Failed to realize chardev {} of net filter", id))?;
+    EventLoop::update_event(
+        EventNotifierHelper::internal_notifiers(chardev.clone()),
+        None,
+    )?;
+    Ok(chardev)
+}
+
+/// Send the packet to the chardev, prefixed with its length. The packet is dropped if
+/// the chardev is not connected or can't keep up with the traffic.
+fn send_packet(chardev: &Arc<Mutex<Chardev>>, packet: &[u8]) {
+    if chardev.lock().unwrap().outbuf_is_full() {
+        return;
+    }
+    let mut buf = Vec::with_capacity(PACKET_LEN_SIZE + packet.len());
+    buf.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+    buf.extend_from_slice(packet);
+    if let Err(e) = Chardev::fill_outbuf(chardev, &buf, None) {
+        debug!("Net filter drops packet: {:?}", e);
+    }
+}
+
+struct NetFilter {
+    id: String,
+    filter_type: NetFilterType,
+    queue: NetFilterQueue,
+    outdev: Option<Arc<Mutex<Chardev>>>,
+    indev: Option<Arc<Mutex<Chardev>>>,
+}
+
+impl NetFilter {
+    fn release(&self) {
+        for chardev in [self.outdev.as_ref(), self.indev.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if let Err(e) = chardev.lock().unwrap().unrealize() {
+                error!(
+                    "Failed to release chardev of net filter {}: {:?}",
+                    self.id, e
+                );
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct InjectedPackets {
+    rx: VecDeque<Vec<u8>>,
+    tx: VecDeque<Vec<u8>>,
+}
+
+impl InjectedPackets {
+    fn queue(&mut self, direction: NetFilterQueue) -> &mut VecDeque<Vec<u8>> {
+        match direction {
+            NetFilterQueue::Tx => &mut self.tx,
+            _ => &mut self.rx,
+        }
+    }
+}
+
+/// Filters attached to a netdev, the packets pass them in the order they are added.
+/// Packets are passed without virtio net header.
+pub struct NetFilterChain {
+    filters: Mutex<Vec<NetFilter>>,
+    /// Number of filters, checked before the packet is copied in datapath.
+    filter_num: AtomicUsize,
+    /// Packets received from the indev of redirectors.
+    injected: Mutex<InjectedPackets>,
+    /// Notify the net device to consume the injected packets.
+    pub inject_evt: Arc<EventFd>,
+}
+
+impl NetFilterChain {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            filters: Mutex::new(Vec::new()),
+            filter_num: AtomicUsize::new(0),
+            injected: Mutex::new(InjectedPackets::default()),
+            inject_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        })
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.filters.lock().unwrap().iter().any(|f| f.id == id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filter_num.load(Ordering::SeqCst) == 0
+    }
+
+    /// Pass the packet through the filters, return false if it is redirected and should
+    /// not be delivered anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - `Rx` for packets received by guest, `Tx` for packets sent by guest.
+    /// * `packet` - The ethernet frame.
+    pub fn filter(&self, direction: NetFilterQueue, packet: &[u8]) -> bool {
+        let filters = self.filters.lock().unwrap();
+        for filter in filters.iter() {
+            if !filter.queue.contains(direction) {
+                continue;
+            }
+            let outdev = match filter.outdev.as_ref() {
+                Some(outdev) => outdev,
+                None => continue,
+            };
+            send_packet(outdev, packet);
+            if filter.filter_type == NetFilterType::Redirector {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn inject(&self, direction: NetFilterQueue, packet: Vec<u8>) {
+        let mut injected = self.injected.lock().unwrap();
+        let queue = injected.queue(direction);
+        if queue.len() >= MAX_INJECTED_PACKETS {
+            warn!("Too many injected packets, drop the packet from net filter");
+            return;
+        }
+        queue.push_back(packet);
+        drop(injected);
+        if let Err(e) = self.inject_evt.write(1) {
+            error!("Failed to notify the injected packets: {:?}", e);
+        }
+    }
+
+    /// Get the next injected packet in `direction`.
+    pub fn pop_injected(&self, direction: NetFilterQueue) -> Option<Vec<u8>> {
+        self.injected.lock().unwrap().queue(direction).pop_front()
+    }
+
+    /// Put back the packet which can't be consumed now.
+    pub fn push_front_injected(&self, direction: NetFilterQueue, packet: Vec<u8>) {
+        self.injected
+            .lock()
+            .unwrap()
+            .queue(direction)
+            .push_front(packet);
+    }
+}
+
+/// Receive the length prefixed packets from the indev of redirector.
+struct FilterInput {
+    chain: Weak<NetFilterChain>,
+    direction: NetFilterQueue,
+    buf: Vec<u8>,
+}
+
+impl InputReceiver for FilterInput {
+    fn receive(&mut self, buffer: &[u8]) {
+        let chain = match self.chain.upgrade() {
+            Some(chain) => chain,
+            None => return,
+        };
+        self.buf.extend_from_slice(buffer);
+        while self.buf.len() >= PACKET_LEN_SIZE {
+            let mut len_bytes = [0_u8; PACKET_LEN_SIZE];
+            len_bytes.copy_from_slice(&self.buf[..PACKET_LEN_SIZE]);
+            let len = u32::from_be_bytes(len_bytes) as usize;
+            if len == 0 || len > MAX_FILTER_PACKET_LEN {
+                error!("Invalid packet length {} from net filter", len);
+                self.buf.clear();
+                return;
+            }
+            if self.buf.len() < PACKET_LEN_SIZE + len {
+                return;
+            }
+            let packet = self.buf[PACKET_LEN_SIZE..PACKET_LEN_SIZE + len].to_vec();
+            self.buf.drain(..PACKET_LEN_SIZE + len);
+            chain.inject(self.direction, packet);
+        }
+    }
+
+    fn remain_size(&mut self) -> usize {
+        MAX_FILTER_PACKET_LEN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_input() {
+        let chain = get_net_filter_chain("test_filter_input").unwrap();
+        let mut input = FilterInput {
+            chain: Arc::downgrade(&chain),
+            direction: NetFilterQueue::Rx,
+            buf: Vec::new(),
+        };
+
+        // Packets may be split or merged in the stream.
+        input.receive(&[0, 0, 0, 3, 1, 2]);
+        assert!(chain.pop_injected(NetFilterQueue::Rx).is_none());
+        input.receive(&[3, 0, 0, 0, 1, 4]);
+        assert_eq!(chain.pop_injected(NetFilterQueue::Rx), Some(vec![1, 2, 3]));
+        assert_eq!(chain.pop_injected(NetFilterQueue::Rx), Some(vec![4]));
+        assert!(chain.pop_injected(NetFilterQueue::Tx).is_none());
+        assert_eq!(chain.inject_evt.read().unwrap(), 2);
+
+        // Invalid length drops the pending data.
+        input.receive(&[0, 0, 0, 0, 1]);
+        assert!(input.buf.is_empty());
+        assert!(chain.pop_injected(NetFilterQueue::Rx).is_none());
+
+        chain.push_front_injected(NetFilterQueue::Tx, vec![5]);
+        assert_eq!(chain.pop_injected(NetFilterQueue::Tx), Some(vec![5]));
+        assert!(chain.is_empty());
+        assert!(chain.filter(NetFilterQueue::Tx, &[1, 2, 3]));
+    }
+}
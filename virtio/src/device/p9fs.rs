@@ -0,0 +1,283 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::p9fs_server::{P9Server, P9_MAX_MSIZE};
+use crate::{
+    check_config_space_rw, gpa_hva_iovec_map, iov_to_buf, read_config_default, report_virtio_error,
+    Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType,
+    VirtioTrace, VIRTIO_F_VERSION_1, VIRTIO_TYPE_9P,
+};
+use address_space::AddressSpace;
+use machine_manager::{
+    config::{P9fsConfig, DEFAULT_VIRTQUEUE_SIZE},
+    event_loop::{register_event_helper, unregister_event_helper},
+};
+use util::aio::iov_from_buf_direct;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+const QUEUE_NUM_9P: usize = 1;
+
+/// The mount tag is available in the config space.
+const VIRTIO_9P_MOUNT_TAG: u32 = 0;
+
+/// Room for the request, which is the max message size and some slack for the header.
+const P9_MAX_REQ_SIZE: u64 = P9_MAX_MSIZE as u64 + 4096;
+
+struct P9fsHandler {
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: Arc<EventFd>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    /// The 9P server shared with the device, which keeps the fids across activations.
+    server: Arc<Mutex<P9Server>>,
+    device_broken: Arc<AtomicBool>,
+}
+
+impl P9fsHandler {
+    fn handle_request(&self, elem: &Element) -> Result<usize> {
+        let req_size = Element::iovec_size(&elem.out_iovec);
+        if req_size > P9_MAX_REQ_SIZE {
+            bail!("Invalid length {} of 9p request", req_size);
+        }
+        let mut req = vec![0_u8; req_size as usize];
+        iov_to_buf(&self.mem_space, &elem.out_iovec, &mut req)
+            .with_context(|| "Failed to get 9p request")?;
+        let resp_cap = Element::iovec_size(&elem.in_iovec) as usize;
+
+        let resp = self.server.lock().unwrap().handle_message(&req, resp_cap)?;
+        let (_, hva_iovec) = gpa_hva_iovec_map(&elem.in_iovec, &self.mem_space)?;
+        iov_from_buf_direct(&hva_iovec, &resp)
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        self.trace_request("9p".to_string(), "to IO".to_string());
+        let mut locked_queue = self.queue.lock().unwrap();
+        loop {
+            let elem = locked_queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for 9p")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let len = self.handle_request(&elem)?;
+
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, len as u32)
+                .with_context(|| format!("Failed to add used ring {}", elem.index))?;
+
+            if locked_queue
+                .vring
+                .should_notify(&self.mem_space, self.driver_features)
+            {
+                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+                    .with_context(|| {
+                        VirtioError::InterruptTrigger("9p", VirtioInterruptType::Vring)
+                    })?;
+                self.trace_send_interrupt("9p".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EventNotifierHelper for P9fsHandler {
+    fn internal_notifiers(p9fs_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_handler = p9fs_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_handler = cloned_handler.lock().unwrap();
+            if locked_handler.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            locked_handler.process_queue().unwrap_or_else(|e| {
+                error!("Failed to process queue for virtio 9p, err: {:?}", e);
+                report_virtio_error(
+                    locked_handler.interrupt_cb.clone(),
+                    locked_handler.driver_features,
+                    &locked_handler.device_broken,
+                );
+            });
+            None
+        });
+
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            p9fs_handler.lock().unwrap().queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+impl VirtioTrace for P9fsHandler {}
+
+/// Virtio 9p device structure, which shares a host directory with the guest by 9P2000.L.
+pub struct P9fs {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// Configuration of virtio 9p device.
+    p9fs_cfg: P9fsConfig,
+    /// Config space of the device, which is the length and the bytes of the mount tag.
+    config_space: Vec<u8>,
+    /// The 9P server of the shared directory.
+    server: Option<Arc<Mutex<P9Server>>>,
+}
+
+impl P9fs {
+    /// Create a virtio 9p device.
+    ///
+    /// # Arguments
+    ///
+    /// * `p9fs_cfg` - Configuration of the device.
+    pub fn new(p9fs_cfg: P9fsConfig) -> Self {
+        let tag = p9fs_cfg.mount_tag.as_bytes();
+        let mut config_space = (tag.len() as u16).to_le_bytes().to_vec();
+        config_space.extend_from_slice(tag);
+        P9fs {
+            base: VirtioBase::new(VIRTIO_TYPE_9P, QUEUE_NUM_9P, DEFAULT_VIRTQUEUE_SIZE),
+            p9fs_cfg,
+            config_space,
+            server: None,
+        }
+    }
+}
+
+impl VirtioDevice for P9fs {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        let server = P9Server::new(&self.p9fs_cfg.path, self.p9fs_cfg.security_model)?;
+        self.server = Some(Arc::new(Mutex::new(server)));
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        self.server = None;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        self.base.device_features =
+            1 << VIRTIO_F_VERSION_1 as u64 | 1 << VIRTIO_9P_MOUNT_TAG as u64;
+        Ok(())
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        read_config_default(&self.config_space, offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        check_config_space_rw(&self.config_space, offset, data)?;
+        // The config space is read-only for the driver, so do nothing here.
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let queues = &self.base.queues;
+        if queues.len() != QUEUE_NUM_9P {
+            bail!(
+                "Invalid queue number {} of virtio 9p, expected {}",
+                queues.len(),
+                QUEUE_NUM_9P
+            );
+        }
+        let server = self
+            .server
+            .clone()
+            .with_context(|| "Virtio 9p is not realized")?;
+        let handler = P9fsHandler {
+            queue: queues[0].clone(),
+            queue_evt: queue_evts[0].clone(),
+            mem_space,
+            interrupt_cb,
+            driver_features: self.base.driver_features,
+            server,
+            device_broken: self.base.broken.clone(),
+        };
+        let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+        register_event_helper(notifiers, None, &mut self.base.deactivate_evts)
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        unregister_event_helper(None, &mut self.base.deactivate_evts)?;
+        // All the fids are lost when the driver resets the device.
+        if let Some(server) = self.server.as_ref() {
+            server.lock().unwrap().reset();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use machine_manager::config::P9SecurityModel;
+
+    use super::*;
+
+    #[test]
+    fn test_p9fs_realize() {
+        let path = "/tmp/stratovirt_test_p9fs_realize";
+        std::fs::create_dir_all(path).unwrap();
+        let mut p9fs = P9fs::new(P9fsConfig {
+            id: "fs0".to_string(),
+            path: path.to_string(),
+            mount_tag: "share".to_string(),
+            security_model: P9SecurityModel::Mapped,
+        });
+        assert_eq!(p9fs.queue_num(), QUEUE_NUM_9P);
+        assert_eq!(p9fs.device_type(), VIRTIO_TYPE_9P);
+        p9fs.realize().unwrap();
+        assert_ne!(p9fs.device_features(0) & (1 << VIRTIO_9P_MOUNT_TAG), 0);
+
+        let mut data = [0_u8; 7];
+        p9fs.read_config(0, &mut data).unwrap();
+        assert_eq!(u16::from_le_bytes([data[0], data[1]]), 5);
+        assert_eq!(&data[2..], b"share");
+        assert!(p9fs.read_config(7, &mut data[..1]).is_err());
+        assert!(p9fs.write_config(0, &data[..1]).is_ok());
+
+        p9fs.unrealize().unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+
+        // The shared directory must exist.
+        assert!(p9fs.realize().is_err());
+    }
+}
@@ -0,0 +1,1468 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! The server of 9P2000.L protocol, which serves the files of a host directory.
+//!
+//! The fids are tracked by the path relative to the shared directory. The walk never goes
+//! through a symbolic link or above the shared directory, and the final symbolic links are
+//! never followed, so the guest can't access the files out of the shared directory.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use machine_manager::config::P9SecurityModel;
+
+/// The only supported version of the protocol.
+const P9_PROTO_VERSION: &str = "9P2000.L";
+const P9_PROTO_UNKNOWN: &str = "unknown";
+
+/// The max message size supported by the device.
+pub const P9_MAX_MSIZE: u32 = 512 * 1024;
+/// Header of all messages: size[4] type[1] tag[2].
+const P9_HDR_SIZE: usize = 7;
+/// Header of Rread and Rreaddir: size[4] type[1] tag[2] count[4].
+const P9_IOHDR_SIZE: usize = 11;
+/// Size of qid: type[1] version[4] path[8].
+const P9_QID_SIZE: usize = 13;
+/// Max number of names in one Twalk.
+const P9_MAX_WALK: u16 = 16;
+/// Magic number of the file system reported by Rstatfs.
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+const P9_NONUNAME: u32 = u32::MAX;
+
+/// Message types of 9P2000.L.
+const P9_RLERROR: u8 = 7;
+const P9_TSTATFS: u8 = 8;
+const P9_TLOPEN: u8 = 12;
+const P9_TLCREATE: u8 = 14;
+const P9_TSYMLINK: u8 = 16;
+const P9_TMKNOD: u8 = 18;
+const P9_TRENAME: u8 = 20;
+const P9_TREADLINK: u8 = 22;
+const P9_TGETATTR: u8 = 24;
+const P9_TSETATTR: u8 = 26;
+const P9_TXATTRWALK: u8 = 30;
+const P9_TXATTRCREATE: u8 = 32;
+const P9_TREADDIR: u8 = 40;
+const P9_TFSYNC: u8 = 50;
+const P9_TLOCK: u8 = 52;
+const P9_TGETLOCK: u8 = 54;
+const P9_TLINK: u8 = 70;
+const P9_TMKDIR: u8 = 72;
+const P9_TRENAMEAT: u8 = 74;
+const P9_TUNLINKAT: u8 = 76;
+const P9_TVERSION: u8 = 100;
+const P9_TAUTH: u8 = 102;
+const P9_TATTACH: u8 = 104;
+const P9_TFLUSH: u8 = 108;
+const P9_TWALK: u8 = 110;
+const P9_TREAD: u8 = 116;
+const P9_TWRITE: u8 = 118;
+const P9_TCLUNK: u8 = 120;
+const P9_TREMOVE: u8 = 122;
+
+/// Types of qid.
+const P9_QTDIR: u8 = 0x80;
+const P9_QTSYMLINK: u8 = 0x02;
+const P9_QTFILE: u8 = 0x00;
+
+/// Flags of Tlopen and Tlcreate, which are the same as the open flags of x86 linux.
+const P9_DOTL_ACCMODE: u32 = 0o3;
+const P9_DOTL_TRUNC: u32 = 0o1000;
+const P9_DOTL_APPEND: u32 = 0o2000;
+const P9_DOTL_NONBLOCK: u32 = 0o4000;
+const P9_DOTL_DSYNC: u32 = 0o10000;
+const P9_DOTL_DIRECTORY: u32 = 0o200000;
+const P9_DOTL_NOATIME: u32 = 0o1000000;
+const P9_DOTL_SYNC: u32 = 0o4000000;
+
+/// Mask of the basic fields in Rgetattr.
+const P9_GETATTR_BASIC: u64 = 0x7ff;
+
+/// Valid bits of Tsetattr.
+const P9_ATTR_MODE: u32 = 1 << 0;
+const P9_ATTR_UID: u32 = 1 << 1;
+const P9_ATTR_GID: u32 = 1 << 2;
+const P9_ATTR_SIZE: u32 = 1 << 3;
+const P9_ATTR_ATIME: u32 = 1 << 4;
+const P9_ATTR_MTIME: u32 = 1 << 5;
+const P9_ATTR_ATIME_SET: u32 = 1 << 7;
+const P9_ATTR_MTIME_SET: u32 = 1 << 8;
+
+/// Flag of Tunlinkat to remove a directory.
+const P9_DOTL_AT_REMOVEDIR: u32 = 0x200;
+
+const P9_LOCK_SUCCESS: u8 = 0;
+const P9_LOCK_TYPE_UNLCK: u8 = 2;
+
+/// Extended attributes which save the credentials of the guest in mapped security model.
+const XATTR_UID: &str = "user.virtfs.uid";
+const XATTR_GID: &str = "user.virtfs.gid";
+const XATTR_MODE: &str = "user.virtfs.mode";
+const XATTR_RDEV: &str = "user.virtfs.rdev";
+
+/// Host mode of the files and directories created in mapped security model.
+const MAPPED_FILE_MODE: u32 = 0o600;
+const MAPPED_DIR_MODE: u32 = 0o700;
+
+fn errno(code: i32) -> Error {
+    Error::from_raw_os_error(code)
+}
+
+fn err_code(e: &Error) -> u32 {
+    match e.raw_os_error() {
+        Some(code) => code as u32,
+        None if e.kind() == ErrorKind::InvalidInput => libc::EINVAL as u32,
+        None => libc::EIO as u32,
+    }
+}
+
+fn cstring(path: &Path) -> IoResult<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| errno(libc::EINVAL))
+}
+
+fn check_ret(ret: libc::c_int) -> IoResult<()> {
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Decoder of the request message.
+struct P9Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> P9Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        P9Reader { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> IoResult<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| errno(libc::EINVAL))?;
+        let data = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> IoResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> IoResult<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> IoResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> IoResult<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> IoResult<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+}
+
+/// Encoder of the response message.
+struct P9Writer {
+    buf: Vec<u8>,
+}
+
+impl P9Writer {
+    fn new() -> Self {
+        P9Writer {
+            buf: vec![0; P9_HDR_SIZE],
+        }
+    }
+
+    fn u8(&mut self, val: u8) -> &mut Self {
+        self.buf.push(val);
+        self
+    }
+
+    fn u16(&mut self, val: u16) -> &mut Self {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, val: u32) -> &mut Self {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, val: u64) -> &mut Self {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+
+    fn string(&mut self, val: &[u8]) -> &mut Self {
+        self.u16(val.len() as u16);
+        self.buf.extend_from_slice(val);
+        self
+    }
+
+    fn qid(&mut self, qid: &Qid) -> &mut Self {
+        self.u8(qid.qtype).u32(qid.version).u64(qid.path)
+    }
+
+    fn finish(mut self, msg_type: u8, tag: u16) -> Vec<u8> {
+        let size = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&size.to_le_bytes());
+        self.buf[4] = msg_type;
+        self.buf[5..7].copy_from_slice(&tag.to_le_bytes());
+        self.buf
+    }
+}
+
+/// The unique identification of a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Qid {
+    qtype: u8,
+    version: u32,
+    path: u64,
+}
+
+/// Attributes of a file seen by the guest.
+struct P9Stat {
+    meta: fs::Metadata,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+}
+
+impl P9Stat {
+    fn file_type(&self) -> u32 {
+        self.mode & libc::S_IFMT
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type() == libc::S_IFDIR
+    }
+
+    fn qid(&self) -> Qid {
+        let qtype = match self.file_type() {
+            libc::S_IFDIR => P9_QTDIR,
+            libc::S_IFLNK => P9_QTSYMLINK,
+            _ => P9_QTFILE,
+        };
+        Qid {
+            qtype,
+            version: (self.meta.mtime() as u32) ^ (self.meta.size() << 8) as u32,
+            path: self.meta.ino(),
+        }
+    }
+
+    fn dirent_type(&self) -> u8 {
+        match self.file_type() {
+            libc::S_IFDIR => libc::DT_DIR,
+            libc::S_IFLNK => libc::DT_LNK,
+            libc::S_IFCHR => libc::DT_CHR,
+            libc::S_IFBLK => libc::DT_BLK,
+            libc::S_IFIFO => libc::DT_FIFO,
+            libc::S_IFSOCK => libc::DT_SOCK,
+            _ => libc::DT_REG,
+        }
+    }
+}
+
+struct DirEntry {
+    qid: Qid,
+    dtype: u8,
+    name: Vec<u8>,
+}
+
+struct Fid {
+    /// Path relative to the shared directory.
+    path: PathBuf,
+    /// User who attaches the file system.
+    uid: u32,
+    /// The opened file.
+    file: Option<File>,
+    /// Entries of the opened directory, which are read at the first Treaddir.
+    entries: Option<Vec<DirEntry>>,
+}
+
+impl Fid {
+    fn new(path: PathBuf, uid: u32) -> Self {
+        Fid {
+            path,
+            uid,
+            file: None,
+            entries: None,
+        }
+    }
+}
+
+/// The 9P2000.L server of the shared directory.
+pub struct P9Server {
+    /// The shared directory in host.
+    root: PathBuf,
+    security_model: P9SecurityModel,
+    /// The negotiated max message size.
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl P9Server {
+    /// Create the server of the shared directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Path of the shared directory.
+    /// * `security_model` - How the credentials of the guest are stored.
+    pub fn new(root: &str, security_model: P9SecurityModel) -> Result<Self> {
+        let root = match fs::canonicalize(root) {
+            Ok(path) if path.is_dir() => path,
+            _ => bail!("Invalid shared directory {} of virtio-9p", root),
+        };
+        Ok(P9Server {
+            root,
+            security_model,
+            msize: P9_MAX_MSIZE,
+            fids: HashMap::new(),
+        })
+    }
+
+    /// Clunk all the fids, which is done when the device is reset.
+    pub fn reset(&mut self) {
+        self.fids.clear();
+        self.msize = P9_MAX_MSIZE;
+    }
+
+    /// Handle a request message and return the response message.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The request message.
+    /// * `resp_cap` - Size of the buffer provided by the guest for the response.
+    pub fn handle_message(&mut self, req: &[u8], resp_cap: usize) -> Result<Vec<u8>> {
+        if req.len() < P9_HDR_SIZE {
+            bail!("Invalid length {} of 9p request", req.len());
+        }
+        let size = u32::from_le_bytes(req[0..4].try_into().unwrap()) as usize;
+        if size < P9_HDR_SIZE || size > req.len() {
+            bail!(
+                "The size {} of 9p request mismatches with the length {}",
+                size,
+                req.len()
+            );
+        }
+        let mut reader = P9Reader::new(&req[..size]);
+        reader.u32().unwrap();
+        let msg_type = reader.u8().unwrap();
+        let tag = reader.u16().unwrap();
+        if resp_cap < P9_IOHDR_SIZE {
+            bail!("Invalid length {} of 9p response buffer", resp_cap);
+        }
+
+        let mut writer = P9Writer::new();
+        let resp = match self.dispatch(msg_type, &mut reader, &mut writer, resp_cap) {
+            Ok(()) if writer.buf.len() <= resp_cap => writer.finish(msg_type + 1, tag),
+            Ok(()) => Self::error_message(libc::EMSGSIZE as u32, tag),
+            Err(e) => Self::error_message(err_code(&e), tag),
+        };
+        Ok(resp)
+    }
+
+    fn error_message(ecode: u32, tag: u16) -> Vec<u8> {
+        let mut writer = P9Writer::new();
+        writer.u32(ecode);
+        writer.finish(P9_RLERROR, tag)
+    }
+
+    fn dispatch(
+        &mut self,
+        msg_type: u8,
+        r: &mut P9Reader,
+        w: &mut P9Writer,
+        resp_cap: usize,
+    ) -> IoResult<()> {
+        match msg_type {
+            P9_TVERSION => self.version(r, w),
+            P9_TATTACH => self.attach(r, w),
+            P9_TWALK => self.walk(r, w),
+            P9_TLOPEN => self.lopen(r, w),
+            P9_TLCREATE => self.lcreate(r, w),
+            P9_TREAD => self.read(r, w, resp_cap),
+            P9_TWRITE => self.write(r, w),
+            P9_TCLUNK => self.clunk(r),
+            P9_TREMOVE => self.remove(r),
+            P9_TGETATTR => self.getattr(r, w),
+            P9_TSETATTR => self.setattr(r),
+            P9_TREADDIR => self.readdir(r, w, resp_cap),
+            P9_TSTATFS => self.statfs(r, w),
+            P9_TFSYNC => self.fsync(r),
+            P9_TMKDIR => self.mkdir(r, w),
+            P9_TSYMLINK => self.symlink(r, w),
+            P9_TMKNOD => self.mknod(r, w),
+            P9_TREADLINK => self.readlink(r, w),
+            P9_TLINK => self.link(r),
+            P9_TRENAME => self.rename(r),
+            P9_TRENAMEAT => self.renameat(r),
+            P9_TUNLINKAT => self.unlinkat(r),
+            P9_TLOCK => {
+                // The locks are only effective in the guest.
+                w.u8(P9_LOCK_SUCCESS);
+                Ok(())
+            }
+            P9_TGETLOCK => self.getlock(r, w),
+            // The request is handled synchronously, there is nothing to flush.
+            P9_TFLUSH => Ok(()),
+            P9_TAUTH | P9_TXATTRWALK | P9_TXATTRCREATE => Err(errno(libc::EOPNOTSUPP)),
+            _ => Err(errno(libc::EOPNOTSUPP)),
+        }
+    }
+
+    fn fid(&self, fid: u32) -> IoResult<&Fid> {
+        self.fids.get(&fid).ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn fid_mut(&mut self, fid: u32) -> IoResult<&mut Fid> {
+        self.fids.get_mut(&fid).ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn host_path(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Get the path of the new entry `name` in the directory of `dfid`.
+    fn child_path(&self, dfid: u32, name: &[u8]) -> IoResult<PathBuf> {
+        if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') {
+            return Err(errno(libc::EINVAL));
+        }
+        let dir = &self.fid(dfid)?.path;
+        if !self.stat(dir)?.is_dir() {
+            return Err(errno(libc::ENOTDIR));
+        }
+        Ok(dir.join(OsStr::from_bytes(name)))
+    }
+
+    fn get_xattr(&self, path: &Path, name: &str, buf: &mut [u8]) -> Option<()> {
+        let cpath = cstring(path).ok()?;
+        let cname = CString::new(name).unwrap();
+        let ret = unsafe {
+            libc::lgetxattr(
+                cpath.as_ptr(),
+                cname.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret as usize != buf.len() {
+            return None;
+        }
+        Some(())
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> IoResult<()> {
+        let cpath = cstring(path)?;
+        let cname = CString::new(name).unwrap();
+        check_ret(unsafe {
+            libc::lsetxattr(
+                cpath.as_ptr(),
+                cname.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        })
+    }
+
+    /// Get the attributes of the file, the path is relative to the shared directory.
+    fn stat(&self, path: &Path) -> IoResult<P9Stat> {
+        let host_path = self.host_path(path);
+        let meta = fs::symlink_metadata(&host_path)?;
+        let mut stat = P9Stat {
+            mode: meta.mode(),
+            uid: meta.uid(),
+            gid: meta.gid(),
+            rdev: meta.rdev(),
+            meta,
+        };
+        if self.security_model == P9SecurityModel::Mapped && !stat.meta.file_type().is_symlink() {
+            let mut val = [0_u8; 4];
+            if self.get_xattr(&host_path, XATTR_UID, &mut val).is_some() {
+                stat.uid = u32::from_le_bytes(val);
+            }
+            if self.get_xattr(&host_path, XATTR_GID, &mut val).is_some() {
+                stat.gid = u32::from_le_bytes(val);
+            }
+            if self.get_xattr(&host_path, XATTR_MODE, &mut val).is_some() {
+                stat.mode = u32::from_le_bytes(val);
+            }
+            let mut val = [0_u8; 8];
+            if self.get_xattr(&host_path, XATTR_RDEV, &mut val).is_some() {
+                stat.rdev = u64::from_le_bytes(val);
+            }
+        }
+        Ok(stat)
+    }
+
+    /// Set the credentials of the guest to the new created file.
+    fn set_creds(
+        &self,
+        host_path: &Path,
+        uid: u32,
+        gid: u32,
+        mode: u32,
+        rdev: u64,
+    ) -> IoResult<()> {
+        match self.security_model {
+            P9SecurityModel::Passthrough => {
+                let cpath = cstring(host_path)?;
+                if mode & libc::S_IFMT != libc::S_IFLNK {
+                    check_ret(unsafe {
+                        libc::fchmodat(libc::AT_FDCWD, cpath.as_ptr(), mode & 0o7777, 0)
+                    })?;
+                }
+                let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+                if uid != euid || gid != egid {
+                    check_ret(unsafe { libc::lchown(cpath.as_ptr(), uid, gid) })?;
+                }
+                Ok(())
+            }
+            P9SecurityModel::Mapped => {
+                self.set_xattr(host_path, XATTR_UID, &uid.to_le_bytes())?;
+                self.set_xattr(host_path, XATTR_GID, &gid.to_le_bytes())?;
+                self.set_xattr(host_path, XATTR_MODE, &mode.to_le_bytes())?;
+                self.set_xattr(host_path, XATTR_RDEV, &rdev.to_le_bytes())
+            }
+        }
+    }
+
+    /// Create a file with the credentials of the guest, the created file is removed if
+    /// failing to set the credentials.
+    fn create_with_creds<F>(
+        &self,
+        path: &Path,
+        uid: u32,
+        gid: u32,
+        mode: u32,
+        rdev: u64,
+        create: F,
+    ) -> IoResult<()>
+    where
+        F: FnOnce(&Path) -> IoResult<()>,
+    {
+        let host_path = self.host_path(path);
+        create(&host_path)?;
+        if let Err(e) = self.set_creds(&host_path, uid, gid, mode, rdev) {
+            if mode & libc::S_IFMT == libc::S_IFDIR {
+                let _ = fs::remove_dir(&host_path);
+            } else {
+                let _ = fs::remove_file(&host_path);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn open_host(&self, host_path: &Path, flags: i32, mode: u32) -> IoResult<File> {
+        let cpath = cstring(host_path)?;
+        let fd = unsafe {
+            libc::open(
+                cpath.as_ptr(),
+                flags | libc::O_CLOEXEC | libc::O_NOFOLLOW,
+                mode,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Convert the flags of Tlopen and Tlcreate to the open flags of host.
+    fn open_flags(flags: u32) -> i32 {
+        let mut host_flags = match flags & P9_DOTL_ACCMODE {
+            0 => libc::O_RDONLY,
+            1 => libc::O_WRONLY,
+            _ => libc::O_RDWR,
+        };
+        let flag_map = [
+            (P9_DOTL_TRUNC, libc::O_TRUNC),
+            (P9_DOTL_APPEND, libc::O_APPEND),
+            (P9_DOTL_NONBLOCK, libc::O_NONBLOCK),
+            (P9_DOTL_DSYNC, libc::O_DSYNC),
+            (P9_DOTL_DIRECTORY, libc::O_DIRECTORY),
+            (P9_DOTL_NOATIME, libc::O_NOATIME),
+            (P9_DOTL_SYNC, libc::O_SYNC),
+        ];
+        for (dotl_flag, host_flag) in flag_map {
+            if flags & dotl_flag == dotl_flag {
+                host_flags |= host_flag;
+            }
+        }
+        host_flags
+    }
+
+    fn version(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let msize = r.u32()?;
+        let version = r.string()?;
+        // A new session is started, all fids are clunked.
+        self.fids.clear();
+        if (msize as usize) < P9_IOHDR_SIZE + P9_QID_SIZE {
+            return Err(errno(libc::EINVAL));
+        }
+        self.msize = msize.min(P9_MAX_MSIZE);
+        w.u32(self.msize);
+        if version.starts_with(P9_PROTO_VERSION.as_bytes()) {
+            w.string(P9_PROTO_VERSION.as_bytes());
+        } else {
+            w.string(P9_PROTO_UNKNOWN.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn attach(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let _afid = r.u32()?;
+        let _uname = r.string()?;
+        let _aname = r.string()?;
+        let n_uname = r.u32()?;
+        if self.fids.contains_key(&fid) {
+            return Err(errno(libc::EBADF));
+        }
+        let uid = if n_uname == P9_NONUNAME { 0 } else { n_uname };
+        let qid = self.stat(Path::new(""))?.qid();
+        self.fids.insert(fid, Fid::new(PathBuf::new(), uid));
+        w.qid(&qid);
+        Ok(())
+    }
+
+    fn walk(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let newfid = r.u32()?;
+        let nwname = r.u16()?;
+        if nwname > P9_MAX_WALK {
+            return Err(errno(libc::EINVAL));
+        }
+        let mut names = Vec::with_capacity(nwname as usize);
+        for _ in 0..nwname {
+            names.push(r.string()?);
+        }
+        let (mut path, uid) = {
+            let fid = self.fid(fid)?;
+            (fid.path.clone(), fid.uid)
+        };
+        if newfid != fid && self.fids.contains_key(&newfid) {
+            return Err(errno(libc::EBADF));
+        }
+
+        let mut stat = self.stat(&path)?;
+        let mut qids = Vec::with_capacity(names.len());
+        for name in names.iter() {
+            let next = if !stat.meta.is_dir() {
+                Err(errno(libc::ENOTDIR))
+            } else if name.is_empty() || name.contains(&b'/') {
+                Err(errno(libc::EINVAL))
+            } else {
+                let mut next = path.clone();
+                match *name {
+                    b"." => {}
+                    // The parent of the shared directory is itself.
+                    b".." => {
+                        next.pop();
+                    }
+                    _ => next.push(OsStr::from_bytes(name)),
+                }
+                self.stat(&next).map(|next_stat| (next, next_stat))
+            };
+            match next {
+                Ok((next, next_stat)) => {
+                    qids.push(next_stat.qid());
+                    path = next;
+                    stat = next_stat;
+                }
+                // Only the error of the first name is reported.
+                Err(e) if qids.is_empty() => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        if qids.len() == names.len() {
+            self.fids.insert(newfid, Fid::new(path, uid));
+        }
+        w.u16(qids.len() as u16);
+        for qid in qids.iter() {
+            w.qid(qid);
+        }
+        Ok(())
+    }
+
+    fn lopen(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let flags = r.u32()?;
+        let path = self.fid(fid)?.path.clone();
+        if self.fid(fid)?.file.is_some() {
+            return Err(errno(libc::EBADF));
+        }
+        let stat = self.stat(&path)?;
+        let host_flags = if stat.meta.is_dir() {
+            libc::O_RDONLY | libc::O_DIRECTORY
+        } else {
+            Self::open_flags(flags)
+        };
+        let file = self.open_host(&self.host_path(&path), host_flags, 0)?;
+        let fid = self.fid_mut(fid)?;
+        fid.file = Some(file);
+        fid.entries = None;
+        w.qid(&stat.qid()).u32(0);
+        Ok(())
+    }
+
+    fn lcreate(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let name = r.string()?;
+        let flags = r.u32()?;
+        let mode = r.u32()?;
+        let gid = r.u32()?;
+        let path = self.child_path(fid, name)?;
+        let uid = self.fid(fid)?.uid;
+        let host_flags = Self::open_flags(flags) | libc::O_CREAT | libc::O_EXCL;
+        let host_mode = match self.security_model {
+            P9SecurityModel::Passthrough => mode & 0o7777,
+            P9SecurityModel::Mapped => MAPPED_FILE_MODE,
+        };
+
+        let mut file = None;
+        self.create_with_creds(
+            &path,
+            uid,
+            gid,
+            libc::S_IFREG | (mode & 0o7777),
+            0,
+            |host_path| {
+                file = Some(self.open_host(host_path, host_flags, host_mode)?);
+                Ok(())
+            },
+        )?;
+        let qid = self.stat(&path)?.qid();
+        let fid = self.fid_mut(fid)?;
+        fid.path = path;
+        fid.file = file;
+        fid.entries = None;
+        w.qid(&qid).u32(0);
+        Ok(())
+    }
+
+    fn read(&mut self, r: &mut P9Reader, w: &mut P9Writer, resp_cap: usize) -> IoResult<()> {
+        let fid = r.u32()?;
+        let offset = r.u64()?;
+        let count = r.u32()? as usize;
+        let file = self
+            .fid(fid)?
+            .file
+            .as_ref()
+            .ok_or_else(|| errno(libc::EBADF))?;
+        let max = (self.msize as usize).min(resp_cap) - P9_IOHDR_SIZE;
+        let mut buf = vec![0_u8; count.min(max)];
+        let len = file.read_at(&mut buf, offset)?;
+        w.u32(len as u32);
+        w.buf.extend_from_slice(&buf[..len]);
+        Ok(())
+    }
+
+    fn write(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let offset = r.u64()?;
+        let count = r.u32()? as usize;
+        let data = r.bytes(count)?;
+        let file = self
+            .fid(fid)?
+            .file
+            .as_ref()
+            .ok_or_else(|| errno(libc::EBADF))?;
+        let len = file.write_at(data, offset)?;
+        w.u32(len as u32);
+        Ok(())
+    }
+
+    fn clunk(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let fid = r.u32()?;
+        self.fids
+            .remove(&fid)
+            .map(|_| ())
+            .ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn remove(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let fid = r.u32()?;
+        // The fid is clunked even if the remove fails.
+        let fid = self.fids.remove(&fid).ok_or_else(|| errno(libc::EBADF))?;
+        if fid.path.as_os_str().is_empty() {
+            return Err(errno(libc::EBUSY));
+        }
+        let host_path = self.host_path(&fid.path);
+        if fs::symlink_metadata(&host_path)?.is_dir() {
+            fs::remove_dir(host_path)
+        } else {
+            fs::remove_file(host_path)
+        }
+    }
+
+    fn getattr(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let _request_mask = r.u64()?;
+        let stat = self.stat(&self.fid(fid)?.path)?;
+        let meta = &stat.meta;
+        w.u64(P9_GETATTR_BASIC)
+            .qid(&stat.qid())
+            .u32(stat.mode)
+            .u32(stat.uid)
+            .u32(stat.gid)
+            .u64(meta.nlink())
+            .u64(stat.rdev)
+            .u64(meta.size())
+            .u64(meta.blksize())
+            .u64(meta.blocks())
+            .u64(meta.atime() as u64)
+            .u64(meta.atime_nsec() as u64)
+            .u64(meta.mtime() as u64)
+            .u64(meta.mtime_nsec() as u64)
+            .u64(meta.ctime() as u64)
+            .u64(meta.ctime_nsec() as u64)
+            // btime, gen and data_version are not supported.
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0);
+        Ok(())
+    }
+
+    fn setattr(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let fid = r.u32()?;
+        let valid = r.u32()?;
+        let mode = r.u32()?;
+        let uid = r.u32()?;
+        let gid = r.u32()?;
+        let size = r.u64()?;
+        let atime_sec = r.u64()?;
+        let atime_nsec = r.u64()?;
+        let mtime_sec = r.u64()?;
+        let mtime_nsec = r.u64()?;
+
+        let path = self.fid(fid)?.path.clone();
+        let host_path = self.host_path(&path);
+        let cpath = cstring(&host_path)?;
+        let stat = self.stat(&path)?;
+        // The symbolic link in host is never followed.
+        let is_symlink = stat.meta.file_type().is_symlink();
+
+        if valid & P9_ATTR_MODE != 0 {
+            match self.security_model {
+                _ if is_symlink => return Err(errno(libc::EOPNOTSUPP)),
+                P9SecurityModel::Passthrough => check_ret(unsafe {
+                    libc::fchmodat(libc::AT_FDCWD, cpath.as_ptr(), mode & 0o7777, 0)
+                })?,
+                P9SecurityModel::Mapped => {
+                    let mode = stat.file_type() | (mode & 0o7777);
+                    self.set_xattr(&host_path, XATTR_MODE, &mode.to_le_bytes())?;
+                }
+            }
+        }
+        if valid & (P9_ATTR_UID | P9_ATTR_GID) != 0 {
+            let uid = if valid & P9_ATTR_UID != 0 {
+                uid
+            } else {
+                u32::MAX
+            };
+            let gid = if valid & P9_ATTR_GID != 0 {
+                gid
+            } else {
+                u32::MAX
+            };
+            match self.security_model {
+                P9SecurityModel::Passthrough => {
+                    check_ret(unsafe { libc::lchown(cpath.as_ptr(), uid, gid) })?
+                }
+                P9SecurityModel::Mapped => {
+                    if uid != u32::MAX {
+                        self.set_xattr(&host_path, XATTR_UID, &uid.to_le_bytes())?;
+                    }
+                    if gid != u32::MAX {
+                        self.set_xattr(&host_path, XATTR_GID, &gid.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+        if valid & P9_ATTR_SIZE != 0 {
+            match self.fid(fid)?.file.as_ref() {
+                Some(file) => file.set_len(size)?,
+                None => self
+                    .open_host(&host_path, libc::O_WRONLY, 0)?
+                    .set_len(size)?,
+            }
+        }
+        if valid & (P9_ATTR_ATIME | P9_ATTR_MTIME) != 0 {
+            let time = |set: bool, given: bool, sec: u64, nsec: u64| -> libc::timespec {
+                let mut ts = libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_OMIT,
+                };
+                if set && given {
+                    ts.tv_sec = sec as libc::time_t;
+                    ts.tv_nsec = nsec as libc::c_long;
+                } else if set {
+                    ts.tv_nsec = libc::UTIME_NOW;
+                }
+                ts
+            };
+            let times = [
+                time(
+                    valid & P9_ATTR_ATIME != 0,
+                    valid & P9_ATTR_ATIME_SET != 0,
+                    atime_sec,
+                    atime_nsec,
+                ),
+                time(
+                    valid & P9_ATTR_MTIME != 0,
+                    valid & P9_ATTR_MTIME_SET != 0,
+                    mtime_sec,
+                    mtime_nsec,
+                ),
+            ];
+            check_ret(unsafe {
+                libc::utimensat(
+                    libc::AT_FDCWD,
+                    cpath.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn read_entries(&self, path: &Path) -> IoResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        let mut parent = path.to_path_buf();
+        parent.pop();
+        for (name, entry_path) in [(".", path.to_path_buf()), ("..", parent)] {
+            let stat = self.stat(&entry_path)?;
+            entries.push(DirEntry {
+                qid: stat.qid(),
+                dtype: stat.dirent_type(),
+                name: name.as_bytes().to_vec(),
+            });
+        }
+        for entry in fs::read_dir(self.host_path(path))? {
+            let entry = entry?;
+            // The entry may be removed in the meantime.
+            let stat = match self.stat(&path.join(entry.file_name())) {
+                Ok(stat) => stat,
+                Err(_) => continue,
+            };
+            entries.push(DirEntry {
+                qid: stat.qid(),
+                dtype: stat.dirent_type(),
+                name: entry.file_name().as_bytes().to_vec(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn readdir(&mut self, r: &mut P9Reader, w: &mut P9Writer, resp_cap: usize) -> IoResult<()> {
+        let fid = r.u32()?;
+        let offset = r.u64()?;
+        let count = r.u32()? as usize;
+        let (path, opened, cached) = {
+            let fid = self.fid(fid)?;
+            (fid.path.clone(), fid.file.is_some(), fid.entries.is_some())
+        };
+        if !opened {
+            return Err(errno(libc::EBADF));
+        }
+        // The directory is read again when the guest rewinds it.
+        if offset == 0 || !cached {
+            let entries = self.read_entries(&path)?;
+            self.fid_mut(fid)?.entries = Some(entries);
+        }
+
+        let max = count.min((self.msize as usize).min(resp_cap) - P9_IOHDR_SIZE);
+        let mut data = P9Writer { buf: Vec::new() };
+        let entries = self.fid(fid)?.entries.as_ref().unwrap();
+        for (index, entry) in entries.iter().enumerate().skip(offset as usize) {
+            let len = P9_QID_SIZE + 8 + 1 + 2 + entry.name.len();
+            if data.buf.len() + len > max {
+                break;
+            }
+            data.qid(&entry.qid)
+                .u64(index as u64 + 1)
+                .u8(entry.dtype)
+                .string(&entry.name);
+        }
+        w.u32(data.buf.len() as u32);
+        w.buf.extend_from_slice(&data.buf);
+        Ok(())
+    }
+
+    fn statfs(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let cpath = cstring(&self.host_path(&self.fid(fid)?.path))?;
+        let mut st = MaybeUninit::<libc::statvfs>::zeroed();
+        check_ret(unsafe { libc::statvfs(cpath.as_ptr(), st.as_mut_ptr()) })?;
+        let st = unsafe { st.assume_init() };
+        w.u32(V9FS_MAGIC)
+            .u32(st.f_bsize as u32)
+            .u64(st.f_blocks)
+            .u64(st.f_bfree)
+            .u64(st.f_bavail)
+            .u64(st.f_files)
+            .u64(st.f_ffree)
+            .u64(st.f_fsid)
+            .u32(st.f_namemax as u32);
+        Ok(())
+    }
+
+    fn fsync(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let fid = r.u32()?;
+        let datasync = r.u32()?;
+        let file = self
+            .fid(fid)?
+            .file
+            .as_ref()
+            .ok_or_else(|| errno(libc::EBADF))?;
+        if datasync != 0 {
+            file.sync_data()
+        } else {
+            file.sync_all()
+        }
+    }
+
+    fn mkdir(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let mode = r.u32()?;
+        let gid = r.u32()?;
+        let path = self.child_path(dfid, name)?;
+        let uid = self.fid(dfid)?.uid;
+        let host_mode = match self.security_model {
+            P9SecurityModel::Passthrough => mode & 0o7777,
+            P9SecurityModel::Mapped => MAPPED_DIR_MODE,
+        };
+        self.create_with_creds(
+            &path,
+            uid,
+            gid,
+            libc::S_IFDIR | (mode & 0o7777),
+            0,
+            |host_path| {
+                let cpath = cstring(host_path)?;
+                check_ret(unsafe { libc::mkdir(cpath.as_ptr(), host_mode) })
+            },
+        )?;
+        w.qid(&self.stat(&path)?.qid());
+        Ok(())
+    }
+
+    fn symlink(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let target = r.string()?;
+        let gid = r.u32()?;
+        let path = self.child_path(dfid, name)?;
+        let uid = self.fid(dfid)?.uid;
+        let target = OsStr::from_bytes(target);
+        self.create_with_creds(
+            &path,
+            uid,
+            gid,
+            libc::S_IFLNK | 0o777,
+            0,
+            |host_path| match self.security_model {
+                P9SecurityModel::Passthrough => std::os::unix::fs::symlink(target, host_path),
+                // The symbolic link is a regular file which saves the target.
+                P9SecurityModel::Mapped => {
+                    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL;
+                    let file = self.open_host(host_path, flags, MAPPED_FILE_MODE)?;
+                    file.write_all_at(target.as_bytes(), 0)
+                }
+            },
+        )?;
+        w.qid(&self.stat(&path)?.qid());
+        Ok(())
+    }
+
+    fn mknod(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let mode = r.u32()?;
+        let major = r.u32()?;
+        let minor = r.u32()?;
+        let gid = r.u32()?;
+        let path = self.child_path(dfid, name)?;
+        let uid = self.fid(dfid)?.uid;
+        let rdev = libc::makedev(major, minor);
+        self.create_with_creds(&path, uid, gid, mode, rdev, |host_path| {
+            let cpath = cstring(host_path)?;
+            match self.security_model {
+                P9SecurityModel::Passthrough => {
+                    check_ret(unsafe { libc::mknod(cpath.as_ptr(), mode, rdev) })
+                }
+                // The special file is a regular file, whose type is saved in the xattr.
+                P9SecurityModel::Mapped => check_ret(unsafe {
+                    libc::mknod(cpath.as_ptr(), libc::S_IFREG | MAPPED_FILE_MODE, 0)
+                }),
+            }
+        })?;
+        w.qid(&self.stat(&path)?.qid());
+        Ok(())
+    }
+
+    fn readlink(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let path = self.fid(fid)?.path.clone();
+        let stat = self.stat(&path)?;
+        if stat.file_type() != libc::S_IFLNK {
+            return Err(errno(libc::EINVAL));
+        }
+        let host_path = self.host_path(&path);
+        let target = if stat.meta.file_type().is_symlink() {
+            fs::read_link(host_path)?.into_os_string().into_vec()
+        } else {
+            let file = self.open_host(&host_path, libc::O_RDONLY, 0)?;
+            let mut buf = vec![0_u8; (stat.meta.size() as usize).min(libc::PATH_MAX as usize)];
+            let len = file.read_at(&mut buf, 0)?;
+            buf.truncate(len);
+            buf
+        };
+        w.string(&target);
+        Ok(())
+    }
+
+    fn link(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let dfid = r.u32()?;
+        let fid = r.u32()?;
+        let name = r.string()?;
+        let path = self.child_path(dfid, name)?;
+        let src = self.host_path(&self.fid(fid)?.path);
+        fs::hard_link(src, self.host_path(&path))
+    }
+
+    /// Rename the file, the paths of the fids in the renamed file are changed too.
+    fn rename_path(&mut self, old: PathBuf, new: PathBuf) -> IoResult<()> {
+        if old.as_os_str().is_empty() {
+            return Err(errno(libc::EBUSY));
+        }
+        fs::rename(self.host_path(&old), self.host_path(&new))?;
+        for fid in self.fids.values_mut() {
+            if fid.path == old {
+                fid.path = new.clone();
+            } else if let Ok(suffix) = fid.path.strip_prefix(&old) {
+                fid.path = new.join(suffix);
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let fid = r.u32()?;
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let new = self.child_path(dfid, name)?;
+        let old = self.fid(fid)?.path.clone();
+        self.rename_path(old, new)
+    }
+
+    fn renameat(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let olddirfid = r.u32()?;
+        let oldname = r.string()?;
+        let newdirfid = r.u32()?;
+        let newname = r.string()?;
+        let old = self.child_path(olddirfid, oldname)?;
+        let new = self.child_path(newdirfid, newname)?;
+        self.rename_path(old, new)
+    }
+
+    fn unlinkat(&mut self, r: &mut P9Reader) -> IoResult<()> {
+        let dfid = r.u32()?;
+        let name = r.string()?;
+        let flags = r.u32()?;
+        let host_path = self.host_path(&self.child_path(dfid, name)?);
+        if flags & P9_DOTL_AT_REMOVEDIR != 0 {
+            fs::remove_dir(host_path)
+        } else {
+            fs::remove_file(host_path)
+        }
+    }
+
+    fn getlock(&mut self, r: &mut P9Reader, w: &mut P9Writer) -> IoResult<()> {
+        let fid = r.u32()?;
+        let _lock_type = r.u8()?;
+        let start = r.u64()?;
+        let length = r.u64()?;
+        let proc_id = r.u32()?;
+        let client_id = r.string()?;
+        self.fid(fid)?;
+        // The locks are only effective in the guest, so no conflicting lock in host.
+        w.u8(P9_LOCK_TYPE_UNLCK)
+            .u64(start)
+            .u64(length)
+            .u32(proc_id)
+            .string(client_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/stratovirt_test_p9fs";
+
+    fn request(server: &mut P9Server, msg_type: u8, body: P9Writer) -> (u8, Vec<u8>) {
+        let req = body.finish(msg_type, 1);
+        let resp = server.handle_message(&req, 8192).unwrap();
+        assert_eq!(
+            resp.len(),
+            u32::from_le_bytes(resp[0..4].try_into().unwrap()) as usize
+        );
+        assert_eq!(u16::from_le_bytes(resp[5..7].try_into().unwrap()), 1);
+        (resp[4], resp[P9_HDR_SIZE..].to_vec())
+    }
+
+    fn body() -> P9Writer {
+        P9Writer::new()
+    }
+
+    fn init_server(dir: &str, security_model: P9SecurityModel) -> P9Server {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let mut server = P9Server::new(dir, security_model).unwrap();
+        let mut b = body();
+        b.u32(8192).string(b"9P2000.L");
+        let (msg_type, resp) = request(&mut server, P9_TVERSION, b);
+        assert_eq!(msg_type, P9_TVERSION + 1);
+        assert_eq!(u32::from_le_bytes(resp[0..4].try_into().unwrap()), 8192);
+        assert_eq!(&resp[6..], b"9P2000.L");
+
+        let mut b = body();
+        b.u32(0).u32(u32::MAX).string(b"root").string(b"").u32(0);
+        let (msg_type, resp) = request(&mut server, P9_TATTACH, b);
+        assert_eq!(msg_type, P9_TATTACH + 1);
+        assert_eq!(resp[0], P9_QTDIR);
+        server
+    }
+
+    fn walk(server: &mut P9Server, fid: u32, newfid: u32, names: &[&[u8]]) -> (u8, Vec<u8>) {
+        let mut b = body();
+        b.u32(fid).u32(newfid).u16(names.len() as u16);
+        for name in names {
+            b.string(name);
+        }
+        request(server, P9_TWALK, b)
+    }
+
+    fn error_code(msg_type: u8, resp: &[u8]) -> i32 {
+        assert_eq!(msg_type, P9_RLERROR);
+        u32::from_le_bytes(resp[0..4].try_into().unwrap()) as i32
+    }
+
+    #[test]
+    fn test_p9fs_file_ops() {
+        let dir = TEST_DIR.to_string() + "_file";
+        let mut server = init_server(&dir, P9SecurityModel::Passthrough);
+        let euid = unsafe { libc::geteuid() };
+        let egid = unsafe { libc::getegid() };
+        server.fid_mut(0).unwrap().uid = euid;
+
+        // Create and write a file.
+        let (msg_type, _) = walk(&mut server, 0, 1, &[]);
+        assert_eq!(msg_type, P9_TWALK + 1);
+        let mut b = body();
+        b.u32(1).string(b"file").u32(2).u32(0o640).u32(egid);
+        let (msg_type, resp) = request(&mut server, P9_TLCREATE, b);
+        assert_eq!(msg_type, P9_TLCREATE + 1);
+        assert_eq!(resp[0], P9_QTFILE);
+        let mut b = body();
+        b.u32(1).u64(2).u32(5).buf.extend_from_slice(b"hello");
+        let (msg_type, resp) = request(&mut server, P9_TWRITE, b);
+        assert_eq!(msg_type, P9_TWRITE + 1);
+        assert_eq!(u32::from_le_bytes(resp[0..4].try_into().unwrap()), 5);
+        let mut b = body();
+        b.u32(1).u64(0).u32(100);
+        let (msg_type, resp) = request(&mut server, P9_TREAD, b);
+        assert_eq!(msg_type, P9_TREAD + 1);
+        assert_eq!(&resp[4..], b"\0\0hello");
+        let mut b = body();
+        b.u32(1).u64(P9_GETATTR_BASIC);
+        let (msg_type, resp) = request(&mut server, P9_TGETATTR, b);
+        assert_eq!(msg_type, P9_TGETATTR + 1);
+        let mode = u32::from_le_bytes(resp[21..25].try_into().unwrap());
+        assert_eq!(mode, libc::S_IFREG | 0o640);
+        let mut b = body();
+        b.u32(1);
+        assert_eq!(request(&mut server, P9_TCLUNK, b).0, P9_TCLUNK + 1);
+
+        // Walk to the file, and can't walk through it.
+        let (msg_type, resp) = walk(&mut server, 0, 2, &[b"file"]);
+        assert_eq!(msg_type, P9_TWALK + 1);
+        assert_eq!(u16::from_le_bytes(resp[0..2].try_into().unwrap()), 1);
+        let (msg_type, resp) = walk(&mut server, 2, 3, &[b"x"]);
+        assert_eq!(error_code(msg_type, &resp), libc::ENOTDIR);
+        let (msg_type, resp) = walk(&mut server, 0, 3, &[b"nonexist"]);
+        assert_eq!(error_code(msg_type, &resp), libc::ENOENT);
+        let (msg_type, resp) = walk(&mut server, 0, 3, &[b"a/b"]);
+        assert_eq!(error_code(msg_type, &resp), libc::EINVAL);
+        // Partial walk returns the qids walked and the newfid is not created.
+        let (msg_type, resp) = walk(&mut server, 0, 3, &[b"file", b"x"]);
+        assert_eq!(msg_type, P9_TWALK + 1);
+        assert_eq!(u16::from_le_bytes(resp[0..2].try_into().unwrap()), 1);
+        assert!(server.fid(3).is_err());
+        // Can't walk above the shared directory.
+        let (msg_type, _) = walk(&mut server, 0, 3, &[b"..", b"..", b"file"]);
+        assert_eq!(msg_type, P9_TWALK + 1);
+        assert_eq!(server.fid(3).unwrap().path, PathBuf::from("file"));
+
+        // Truncate the file.
+        let mut b = body();
+        b.u32(2).u32(P9_ATTR_SIZE).u32(0).u32(0).u32(0).u64(3);
+        b.u64(0).u64(0).u64(0).u64(0);
+        assert_eq!(request(&mut server, P9_TSETATTR, b).0, P9_TSETATTR + 1);
+        assert_eq!(fs::metadata(dir.clone() + "/file").unwrap().len(), 3);
+
+        // Rename the file, the path of fid is changed.
+        let mut b = body();
+        b.u32(0).string(b"file").u32(0).string(b"renamed");
+        assert_eq!(request(&mut server, P9_TRENAMEAT, b).0, P9_TRENAMEAT + 1);
+        assert_eq!(server.fid(2).unwrap().path, PathBuf::from("renamed"));
+        assert!(Path::new(&(dir.clone() + "/renamed")).exists());
+
+        // Remove the file.
+        let mut b = body();
+        b.u32(2);
+        assert_eq!(request(&mut server, P9_TREMOVE, b).0, P9_TREMOVE + 1);
+        assert!(server.fid(2).is_err());
+        assert!(!Path::new(&(dir.clone() + "/renamed")).exists());
+
+        // Unknown fid.
+        let mut b = body();
+        b.u32(100).u64(0).u32(1);
+        let (msg_type, resp) = request(&mut server, P9_TREAD, b);
+        assert_eq!(error_code(msg_type, &resp), libc::EBADF);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_p9fs_dir_ops() {
+        let dir = TEST_DIR.to_string() + "_dir";
+        let mut server = init_server(&dir, P9SecurityModel::Mapped);
+
+        let mut b = body();
+        b.u32(0).string(b"subdir").u32(0o755).u32(100);
+        let (msg_type, resp) = request(&mut server, P9_TMKDIR, b);
+        assert_eq!(msg_type, P9_TMKDIR + 1);
+        assert_eq!(resp[0], P9_QTDIR);
+        // Host symbolic link is never walked through.
+        std::os::unix::fs::symlink("/", dir.clone() + "/escape").unwrap();
+        let (msg_type, resp) = walk(&mut server, 0, 1, &[b"escape", b"etc"]);
+        assert_eq!(msg_type, P9_TWALK + 1);
+        assert_eq!(u16::from_le_bytes(resp[0..2].try_into().unwrap()), 1);
+        assert!(server.fid(1).is_err());
+
+        // The symbolic link is saved in a regular file in mapped model.
+        let mut b = body();
+        b.u32(0).string(b"link").string(b"subdir").u32(100);
+        let (msg_type, resp) = request(&mut server, P9_TSYMLINK, b);
+        assert_eq!(msg_type, P9_TSYMLINK + 1);
+        assert_eq!(resp[0], P9_QTSYMLINK);
+        assert!(fs::symlink_metadata(dir.clone() + "/link")
+            .unwrap()
+            .is_file());
+        walk(&mut server, 0, 1, &[b"link"]);
+        let mut b = body();
+        b.u32(1);
+        let (msg_type, resp) = request(&mut server, P9_TREADLINK, b);
+        assert_eq!(msg_type, P9_TREADLINK + 1);
+        assert_eq!(&resp[2..], b"subdir");
+
+        // Credentials of the guest are saved in xattrs, which may be unsupported by the fs.
+        let mut b = body();
+        b.u32(1).u64(P9_GETATTR_BASIC);
+        let (_, resp) = request(&mut server, P9_TGETATTR, b);
+        let mode = u32::from_le_bytes(resp[21..25].try_into().unwrap());
+        assert_eq!(mode, libc::S_IFLNK | 0o777);
+        let gid = u32::from_le_bytes(resp[29..33].try_into().unwrap());
+        assert_eq!(gid, 100);
+
+        // Read the directory.
+        walk(&mut server, 0, 2, &[]);
+        let mut b = body();
+        b.u32(2).u32(0);
+        assert_eq!(request(&mut server, P9_TLOPEN, b).0, P9_TLOPEN + 1);
+        let mut b = body();
+        b.u32(2).u64(0).u32(4096);
+        let (msg_type, resp) = request(&mut server, P9_TREADDIR, b);
+        assert_eq!(msg_type, P9_TREADDIR + 1);
+        let mut reader = P9Reader::new(&resp[4..]);
+        let mut names = Vec::new();
+        let mut last_offset = 0;
+        while reader.pos < reader.buf.len() {
+            reader.bytes(P9_QID_SIZE).unwrap();
+            last_offset = reader.u64().unwrap();
+            reader.u8().unwrap();
+            names.push(std::ffi::OsString::from_vec(
+                reader.string().unwrap().to_vec(),
+            ));
+        }
+        names.sort();
+        assert_eq!(names, vec![".", "..", "escape", "link", "subdir"]);
+        let mut b = body();
+        b.u32(2).u64(last_offset).u32(4096);
+        let (_, resp) = request(&mut server, P9_TREADDIR, b);
+        assert_eq!(u32::from_le_bytes(resp[0..4].try_into().unwrap()), 0);
+
+        let mut b = body();
+        b.u32(0).string(b"subdir").u32(0);
+        let (msg_type, resp) = request(&mut server, P9_TUNLINKAT, b);
+        assert_eq!(error_code(msg_type, &resp), libc::EISDIR);
+        let mut b = body();
+        b.u32(0).string(b"subdir").u32(P9_DOTL_AT_REMOVEDIR);
+        assert_eq!(request(&mut server, P9_TUNLINKAT, b).0, P9_TUNLINKAT + 1);
+        let mut b = body();
+        b.u32(0).string(b"..").u32(0);
+        let (msg_type, resp) = request(&mut server, P9_TUNLINKAT, b);
+        assert_eq!(error_code(msg_type, &resp), libc::EINVAL);
+
+        // A new version clunks all the fids.
+        let mut b = body();
+        b.u32(4096).string(b"9P2000");
+        let (_, resp) = request(&mut server, P9_TVERSION, b);
+        assert_eq!(&resp[6..], b"unknown");
+        assert!(server.fids.is_empty());
+        assert!(server.handle_message(&[0_u8; 4], 4096).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::{
+    check_config_space_rw, gpa_hva_iovec_map, iov_to_buf, read_config_default, report_virtio_error,
+    Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType,
+    VirtioTrace, VIRTIO_F_VERSION_1, VIRTIO_TYPE_PMEM,
+};
+use address_space::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use machine_manager::{
+    config::{PmemConfig, DEFAULT_VIRTQUEUE_SIZE},
+    event_loop::{register_event_helper, unregister_event_helper},
+};
+use util::aio::iov_from_buf_direct;
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+const QUEUE_NUM_PMEM: usize = 1;
+
+/// The only request type, which asks the device to persist the guest writes.
+const VIRTIO_PMEM_REQ_TYPE_FLUSH: u32 = 0;
+
+/// Results of the flush request.
+const VIRTIO_PMEM_RESP_OK: u32 = 0;
+const VIRTIO_PMEM_RESP_EIO: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioPmemConfig {
+    /// Guest physical address of the persistent memory.
+    start: u64,
+    /// Size of the persistent memory.
+    size: u64,
+}
+
+impl ByteCode for VirtioPmemConfig {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioPmemReq {
+    req_type: u32,
+}
+
+impl ByteCode for VirtioPmemReq {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioPmemResp {
+    ret: u32,
+}
+
+impl ByteCode for VirtioPmemResp {}
+
+struct PmemHandler {
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: Arc<EventFd>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    /// The host file which backs the persistent memory.
+    file: Arc<File>,
+    device_broken: Arc<AtomicBool>,
+}
+
+impl PmemHandler {
+    fn handle_request(&self, elem: &Element) -> Result<usize> {
+        let mut req = VirtioPmemReq::default();
+        let size = iov_to_buf(&self.mem_space, &elem.out_iovec, req.as_mut_bytes())
+            .with_context(|| "Failed to get pmem request")?;
+        if size < size_of::<VirtioPmemReq>() {
+            bail!("Invalid length of pmem request {}", size);
+        }
+        if Element::iovec_size(&elem.in_iovec) < size_of::<VirtioPmemResp>() as u64 {
+            bail!("Invalid length of pmem response buffer");
+        }
+
+        let ret = match req.req_type {
+            VIRTIO_PMEM_REQ_TYPE_FLUSH => {
+                // The guest writes hit the shared mapping of the file directly, and the
+                // size of file never changes, so fdatasync is enough to persist them.
+                if let Err(e) = self.file.sync_data() {
+                    error!("Failed to flush pmem backing file: {:?}", e);
+                    VIRTIO_PMEM_RESP_EIO
+                } else {
+                    VIRTIO_PMEM_RESP_OK
+                }
+            }
+            req_type => {
+                error!("Unsupported pmem request type {}", req_type);
+                VIRTIO_PMEM_RESP_EIO
+            }
+        };
+
+        let resp = VirtioPmemResp { ret };
+        let (_, hva_iovec) = gpa_hva_iovec_map(&elem.in_iovec, &self.mem_space)?;
+        iov_from_buf_direct(&hva_iovec, resp.as_bytes())
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        self.trace_request("Pmem".to_string(), "to IO".to_string());
+        let mut locked_queue = self.queue.lock().unwrap();
+        loop {
+            let elem = locked_queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for pmem")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let len = self.handle_request(&elem)?;
+
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, len as u32)
+                .with_context(|| format!("Failed to add used ring {}", elem.index))?;
+
+            if locked_queue
+                .vring
+                .should_notify(&self.mem_space, self.driver_features)
+            {
+                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+                    .with_context(|| {
+                        VirtioError::InterruptTrigger("pmem", VirtioInterruptType::Vring)
+                    })?;
+                self.trace_send_interrupt("Pmem".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EventNotifierHelper for PmemHandler {
+    fn internal_notifiers(pmem_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_handler = pmem_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_handler = cloned_handler.lock().unwrap();
+            if locked_handler.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            locked_handler.process_queue().unwrap_or_else(|e| {
+                error!("Failed to process queue for virtio pmem, err: {:?}", e);
+                report_virtio_error(
+                    locked_handler.interrupt_cb.clone(),
+                    locked_handler.driver_features,
+                    &locked_handler.device_broken,
+                );
+            });
+            None
+        });
+
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            pmem_handler.lock().unwrap().queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+impl VirtioTrace for PmemHandler {}
+
+/// Pmem device structure, which exposes a host file to the guest as persistent memory.
+pub struct Pmem {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// Configuration of virtio pmem device.
+    pmem_cfg: PmemConfig,
+    /// Config space of the device.
+    config_space: VirtioPmemConfig,
+    /// System address space, where the persistent memory is mapped.
+    sys_mem: Arc<AddressSpace>,
+    /// The host file which backs the persistent memory.
+    file: Option<Arc<File>>,
+    /// Memory region of the persistent memory.
+    region: Option<Region>,
+}
+
+impl Pmem {
+    /// Create a virtio pmem device.
+    ///
+    /// # Arguments
+    ///
+    /// * `pmem_cfg` - Configuration of the device.
+    /// * `sys_mem` - System address space.
+    /// * `start` - Guest physical address where the persistent memory is mapped.
+    pub fn new(pmem_cfg: PmemConfig, sys_mem: &Arc<AddressSpace>, start: u64) -> Self {
+        let size = pmem_cfg.size;
+        Pmem {
+            base: VirtioBase::new(VIRTIO_TYPE_PMEM, QUEUE_NUM_PMEM, DEFAULT_VIRTQUEUE_SIZE),
+            pmem_cfg,
+            config_space: VirtioPmemConfig { start, size },
+            sys_mem: sys_mem.clone(),
+            file: None,
+            region: None,
+        }
+    }
+}
+
+impl VirtioDevice for Pmem {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        let start = self.config_space.start;
+        let size = self.config_space.size;
+        let file_back = FileBackend::new_mem(&self.pmem_cfg.mem_path, size)?;
+        let file = file_back.file.clone();
+        let mapping = HostMemMapping::new(
+            GuestAddress(start),
+            None,
+            size,
+            Some(file_back),
+            false,
+            true,
+            false,
+        )
+        .with_context(|| format!("Failed to map {} for pmem", self.pmem_cfg.mem_path))?;
+        let region = Region::init_ram_device_region(Arc::new(mapping), "PmemRegion");
+        self.sys_mem
+            .root()
+            .add_subregion(region.clone(), start)
+            .with_context(|| "Failed to add pmem region to system memory")?;
+        self.file = Some(file);
+        self.region = Some(region);
+
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        if let Some(region) = self.region.take() {
+            self.sys_mem
+                .root()
+                .delete_subregion(&region)
+                .with_context(|| "Failed to delete pmem region from system memory")?;
+        }
+        self.file = None;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        self.base.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
+        Ok(())
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        read_config_default(self.config_space.as_bytes(), offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        check_config_space_rw(self.config_space.as_bytes(), offset, data)?;
+        // The config space is read-only for the driver, so do nothing here.
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let queues = &self.base.queues;
+        if queues.len() != QUEUE_NUM_PMEM {
+            bail!(
+                "Invalid queue number {} of virtio pmem, expected {}",
+                queues.len(),
+                QUEUE_NUM_PMEM
+            );
+        }
+        let file = self
+            .file
+            .clone()
+            .with_context(|| "Virtio pmem is not realized")?;
+        let handler = PmemHandler {
+            queue: queues[0].clone(),
+            queue_evt: queue_evts[0].clone(),
+            mem_space,
+            interrupt_cb,
+            driver_features: self.base.driver_features,
+            file,
+            device_broken: self.base.broken.clone(),
+        };
+        let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+        register_event_helper(notifiers, None, &mut self.base.deactivate_evts)
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        unregister_event_helper(None, &mut self.base.deactivate_evts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36, "sysmem");
+        let sys_space = AddressSpace::new(root, "sysmem").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x1000_0000,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone(), "sysmem"),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    #[test]
+    fn test_pmem_realize() {
+        let sys_mem = address_space_init();
+        let path = "/tmp/stratovirt_test_pmem";
+        let start = 1 << 30;
+        let size = 0x20_0000;
+        let mut pmem = Pmem::new(
+            PmemConfig {
+                id: "pmem0".to_string(),
+                mem_path: path.to_string(),
+                size,
+            },
+            &sys_mem,
+            start,
+        );
+        assert_eq!(pmem.queue_num(), QUEUE_NUM_PMEM);
+        assert_eq!(pmem.device_type(), VIRTIO_TYPE_PMEM);
+        pmem.realize().unwrap();
+
+        let mut data = [0_u8; 8];
+        pmem.read_config(0, &mut data).unwrap();
+        assert_eq!(u64::from_le_bytes(data), start);
+        pmem.read_config(8, &mut data).unwrap();
+        assert_eq!(u64::from_le_bytes(data), size);
+        assert!(pmem.read_config(16, &mut data).is_err());
+        assert!(pmem.write_config(0, &data).is_ok());
+
+        // Guest writes go to the backing file directly.
+        assert!(sys_mem
+            .write_object(&0x5a5a_u16, GuestAddress(start + 0x100))
+            .is_ok());
+        let mut buf = [0_u8; 2];
+        let file = pmem.file.clone().unwrap();
+        std::os::unix::fs::FileExt::read_at(file.as_ref(), &mut buf, 0x100).unwrap();
+        assert_eq!(u16::from_le_bytes(buf), 0x5a5a);
+        // The persistent memory is not reported as guest RAM.
+        assert!(!sys_mem.address_in_memory(GuestAddress(start), 1));
+        assert_eq!(sys_mem.memory_end_address().raw_value(), 0x1000_0000);
+
+        pmem.unrealize().unwrap();
+        assert!(sys_mem.get_host_address(GuestAddress(start)).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
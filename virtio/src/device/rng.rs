@@ -269,6 +269,12 @@ impl VirtioDevice for Rng {
         Ok(())
     }
 
+    fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(RngState::descriptor(), &self.rng_cfg.id);
+        self.random_file = None;
+        Ok(())
+    }
+
     fn init_config_features(&mut self) -> Result<()> {
         self.base.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
         Ok(())
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::VecDeque;
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
@@ -24,7 +25,7 @@ use crate::{
     check_config_space_rw, gpa_hva_iovec_map, iov_discard_front, iov_to_buf, read_config_default,
     report_virtio_error, Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt,
     VirtioInterruptType, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
-    VIRTIO_TYPE_SCSI,
+    VIRTIO_SCSI_F_HOTPLUG, VIRTIO_TYPE_SCSI,
 };
 use address_space::{AddressSpace, GuestAddress};
 use block_backend::BlockIoErrorCallback;
@@ -32,6 +33,7 @@ use devices::ScsiBus::{
     ScsiBus, ScsiRequest, ScsiRequestOps, ScsiSense, ScsiXferMode, CHECK_CONDITION,
     EMULATE_SCSI_OPS, SCSI_CMD_BUF_SIZE, SCSI_SENSE_INVALID_OPCODE,
 };
+use devices::ScsiDisk::ScsiDevice;
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
     config::{ScsiCntlrConfig, VIRTIO_SCSI_MAX_LUN, VIRTIO_SCSI_MAX_TARGET},
@@ -75,6 +77,19 @@ pub const VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET: u32 = 5;
 pub const VIRTIO_SCSI_T_TMF_QUERY_TASK: u32 = 6;
 pub const VIRTIO_SCSI_T_TMF_QUERY_TASK_SET: u32 = 7;
 
+/// Event types reported in the event queue.
+const VIRTIO_SCSI_T_TRANSPORT_RESET: u32 = 1;
+/// The flag is set in the event type if some events are dropped because no buffer is available.
+const VIRTIO_SCSI_T_EVENTS_MISSED: u32 = 0x8000_0000;
+
+/// Reasons of the transport reset event.
+pub const VIRTIO_SCSI_EVT_RESET_HARD: u32 = 0;
+pub const VIRTIO_SCSI_EVT_RESET_RESCAN: u32 = 1;
+pub const VIRTIO_SCSI_EVT_RESET_REMOVED: u32 = 2;
+
+/// Max number of the events waiting for the guest buffers.
+const SCSI_MAX_PENDING_EVENTS: usize = 64;
+
 /// Command-specific response values.
 /// The request was completed and the status byte if filled with a SCSI status code.
 const VIRTIO_SCSI_S_OK: u8 = 0;
@@ -105,6 +120,24 @@ struct VirtioScsiConfig {
 
 impl ByteCode for VirtioScsiConfig {}
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioScsiEvent {
+    event: u32,
+    lun: [u8; 8],
+    reason: u32,
+}
+
+impl ByteCode for VirtioScsiEvent {}
+
+/// Events which are waiting to be reported in the event queue.
+#[derive(Default)]
+struct ScsiPendingEvents {
+    events: VecDeque<VirtioScsiEvent>,
+    /// Some events are dropped, which is reported in the next event.
+    missed: bool,
+}
+
 /// Virtio Scsi Controller device structure.
 #[derive(Default)]
 pub struct ScsiCntlr {
@@ -116,6 +149,13 @@ pub struct ScsiCntlr {
     config_space: VirtioScsiConfig,
     /// Scsi bus.
     pub bus: Option<Arc<Mutex<ScsiBus>>>,
+    /// Events waiting for the buffers of the event queue.
+    pending_events: Arc<Mutex<ScsiPendingEvents>>,
+    /// EventFd of the event queue, which is used to report the pending events.
+    event_queue_evt: Option<Arc<EventFd>>,
+    /// Interrupt callback of the activated device, which is used by the io error callback
+    /// of the hot plugged devices.
+    interrupt_cb: Option<Arc<VirtioInterrupt>>,
 }
 
 impl ScsiCntlr {
@@ -131,6 +171,72 @@ impl ScsiCntlr {
         }
     }
 
+    /// Report the transport reset event of the lun to the guest, such as the lun is hot plugged
+    /// or unplugged. It's ignored if the guest doesn't negotiate VIRTIO_SCSI_F_HOTPLUG.
+    pub fn notify_transport_reset(&self, target: u8, lun: u16, reason: u32) -> Result<()> {
+        if self.base.driver_features & (1_u64 << VIRTIO_SCSI_F_HOTPLUG) == 0 {
+            return Ok(());
+        }
+        let Some(queue_evt) = self.event_queue_evt.as_ref() else {
+            return Ok(());
+        };
+
+        let mut pending = self.pending_events.lock().unwrap();
+        if pending.events.len() >= SCSI_MAX_PENDING_EVENTS {
+            pending.missed = true;
+        } else {
+            pending.events.push_back(VirtioScsiEvent {
+                event: VIRTIO_SCSI_T_TRANSPORT_RESET,
+                lun: virtio_scsi_lun(target, lun),
+                reason,
+            });
+        }
+        drop(pending);
+        queue_evt
+            .write(1)
+            .with_context(|| "Failed to notify the scsi event queue")
+    }
+
+    /// Register the io event of the hot plugged device if the controller is activated, or
+    /// it's registered when the controller is activated.
+    pub fn register_device_io_event(&self, device: &Arc<Mutex<ScsiDevice>>) -> Result<()> {
+        let Some(interrupt_cb) = self.interrupt_cb.as_ref() else {
+            return Ok(());
+        };
+        let err_cb = self.gen_error_cb(interrupt_cb.clone());
+        let locked_device = device.lock().unwrap();
+        // SAFETY: the disk_image is assigned after device realized.
+        let disk_image = locked_device.block_backend.as_ref().unwrap();
+        let mut locked_backend = disk_image.lock().unwrap();
+        locked_backend.register_io_event(self.base.broken.clone(), err_cb)
+    }
+
+    /// Detach the scsi device `id` from the bus and report it to the guest. Returns false
+    /// if the device is not attached to this controller.
+    pub fn unplug_device(&self, id: &str) -> Result<bool> {
+        let bus = self.bus.as_ref().unwrap();
+        let mut locked_bus = bus.lock().unwrap();
+        let Some((target, lun)) = locked_bus
+            .devices
+            .iter()
+            .find(|(_, dev)| dev.lock().unwrap().config.id == id)
+            .map(|(key, _)| *key)
+        else {
+            return Ok(false);
+        };
+        let device = locked_bus.devices.remove(&(target, lun)).unwrap();
+        drop(locked_bus);
+
+        if self.interrupt_cb.is_some() {
+            let locked_device = device.lock().unwrap();
+            // SAFETY: the disk_image is assigned after device realized.
+            let disk_image = locked_device.block_backend.as_ref().unwrap();
+            disk_image.lock().unwrap().unregister_io_event()?;
+        }
+        self.notify_transport_reset(target, lun, VIRTIO_SCSI_EVT_RESET_REMOVED)?;
+        Ok(true)
+    }
+
     fn gen_error_cb(&self, interrupt_cb: Arc<VirtioInterrupt>) -> BlockIoErrorCallback {
         let cloned_features = self.base.driver_features;
         let clone_broken = self.base.broken.clone();
@@ -164,12 +270,13 @@ impl VirtioDevice for ScsiCntlr {
     }
 
     fn init_config_features(&mut self) -> Result<()> {
-        self.config_space.num_queues = self.config.queues;
         self.config_space.max_sectors = 0xFFFF_u32;
         // cmd_per_lun: maximum number of linked commands can be sent to one LUN. 32bit.
         self.config_space.cmd_per_lun = 128;
         // seg_max: queue size - 2, 32 bit.
         self.config_space.seg_max = self.queue_size_max() as u32 - 2;
+        // Only channel 0 is used, and the targets are addressed by one byte in the lun.
+        self.config_space.max_channel = 0;
         self.config_space.max_target = VIRTIO_SCSI_MAX_TARGET;
         self.config_space.max_lun = VIRTIO_SCSI_MAX_LUN as u32;
         // num_queues: request queues number.
@@ -177,7 +284,8 @@ impl VirtioDevice for ScsiCntlr {
 
         self.base.device_features |= (1_u64 << VIRTIO_F_VERSION_1)
             | (1_u64 << VIRTIO_F_RING_EVENT_IDX)
-            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC);
+            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC)
+            | (1_u64 << VIRTIO_SCSI_F_HOTPLUG);
 
         Ok(())
     }
@@ -230,13 +338,15 @@ impl VirtioDevice for ScsiCntlr {
         // Register event notifier for event queue.
         let event_queue = queues[1].clone();
         let event_queue_evt = queue_evts[1].clone();
+        self.event_queue_evt = Some(event_queue_evt.clone());
         let event_handler = ScsiEventQueueHandler {
-            _queue: event_queue,
+            queue: event_queue,
             queue_evt: event_queue_evt,
-            _mem_space: mem_space.clone(),
-            _interrupt_cb: interrupt_cb.clone(),
-            _driver_features: self.base.driver_features,
+            mem_space: mem_space.clone(),
+            interrupt_cb: interrupt_cb.clone(),
+            driver_features: self.base.driver_features,
             device_broken: self.base.broken.clone(),
+            pending_events: self.pending_events.clone(),
         };
         let notifiers =
             EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(event_handler)));
@@ -271,20 +381,19 @@ impl VirtioDevice for ScsiCntlr {
         self.base.broken.store(false, Ordering::SeqCst);
 
         // Register event notifier for device aio.
+        self.interrupt_cb = Some(interrupt_cb);
         let bus = self.bus.as_ref().unwrap();
         let locked_bus = bus.lock().unwrap();
         for device in locked_bus.devices.values() {
-            let locked_device = device.lock().unwrap();
-            let err_cb = self.gen_error_cb(interrupt_cb.clone());
-            // SAFETY: the disk_image is assigned after device realized.
-            let disk_image = locked_device.block_backend.as_ref().unwrap();
-            let mut locked_backend = disk_image.lock().unwrap();
-            locked_backend.register_io_event(self.base.broken.clone(), err_cb)?;
+            self.register_device_io_event(device)?;
         }
         Ok(())
     }
 
     fn deactivate(&mut self) -> Result<()> {
+        self.event_queue_evt = None;
+        self.interrupt_cb = None;
+        *self.pending_events.lock().unwrap() = ScsiPendingEvents::default();
         unregister_event_helper(
             self.config.iothread.as_ref(),
             &mut self.base.deactivate_evts,
@@ -679,17 +788,19 @@ impl EventNotifierHelper for ScsiCtrlQueueHandler {
 
 struct ScsiEventQueueHandler {
     /// The Event virtqueue.
-    _queue: Arc<Mutex<Queue>>,
+    queue: Arc<Mutex<Queue>>,
     /// EventFd for the Event virtqueue.
     queue_evt: Arc<EventFd>,
     /// The address space to which the scsi HBA belongs.
-    _mem_space: Arc<AddressSpace>,
+    mem_space: Arc<AddressSpace>,
     /// The interrupt callback function.
-    _interrupt_cb: Arc<VirtioInterrupt>,
+    interrupt_cb: Arc<VirtioInterrupt>,
     /// Bit mask of features negotiated by the backend and the frontend.
-    _driver_features: u64,
+    driver_features: u64,
     /// Device is broken or not.
     device_broken: Arc<AtomicBool>,
+    /// Events waiting for the buffers of the event queue.
+    pending_events: Arc<Mutex<ScsiPendingEvents>>,
 }
 
 impl EventNotifierHelper for ScsiEventQueueHandler {
@@ -717,6 +828,68 @@ impl EventNotifierHelper for ScsiEventQueueHandler {
 
 impl ScsiEventQueueHandler {
     fn handle_event(&mut self) -> Result<()> {
+        let result = self.handle_event_queue_requests();
+        if result.is_err() {
+            report_virtio_error(
+                self.interrupt_cb.clone(),
+                self.driver_features,
+                &self.device_broken,
+            );
+        }
+
+        result
+    }
+
+    /// The buffers in the event queue are used only when there are pending events.
+    fn handle_event_queue_requests(&mut self) -> Result<()> {
+        let mut pending = self.pending_events.lock().unwrap();
+        let mut queue = self.queue.lock().unwrap();
+        let mut notify = false;
+        while !pending.events.is_empty() {
+            let elem = queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let resp_desc = elem
+                .in_iovec
+                .first()
+                .with_context(|| "Error request in event queue. Empty datain buf!")?;
+            if (resp_desc.len as usize) < size_of::<VirtioScsiEvent>() {
+                bail!("Invalid length {} for scsi event", resp_desc.len);
+            }
+            // The queue is not empty, which is checked in the loop condition.
+            let mut event = pending.events.pop_front().unwrap();
+            if pending.missed {
+                event.event |= VIRTIO_SCSI_T_EVENTS_MISSED;
+                pending.missed = false;
+            }
+            self.mem_space
+                .write_object(&event, resp_desc.addr)
+                .with_context(|| "Failed to write the scsi event")?;
+            queue
+                .vring
+                .add_used(
+                    &self.mem_space,
+                    elem.index,
+                    size_of::<VirtioScsiEvent>() as u32,
+                )
+                .with_context(|| {
+                    format!("Failed to add used ring(scsi event), index {}", elem.index)
+                })?;
+            notify = true;
+        }
+
+        if notify
+            && queue
+                .vring
+                .should_notify(&self.mem_space, self.driver_features)
+        {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false).with_context(
+                || VirtioError::InterruptTrigger("scsi event queue", VirtioInterruptType::Vring),
+            )?;
+        }
         Ok(())
     }
 }
@@ -741,6 +914,17 @@ fn virtio_scsi_get_lun_id(lun: [u8; 8]) -> u16 {
     (((lun[2] as u16) << 8) | (lun[3] as u16)) & 0x3FFF
 }
 
+/// Build the lun in the above format, and the flat space addressing method is used if the lun
+/// id is larger than 255.
+fn virtio_scsi_lun(target: u8, lun: u16) -> [u8; 8] {
+    let lun_id = if lun > 0xff { lun | 0x4000 } else { lun };
+    let mut bytes = [0_u8; 8];
+    bytes[0] = 1;
+    bytes[1] = target;
+    bytes[2..4].copy_from_slice(&lun_id.to_be_bytes());
+    bytes
+}
+
 struct ScsiCmdQueueHandler {
     /// The scsi controller.
     scsibus: Arc<Mutex<ScsiBus>>,
@@ -934,3 +1118,41 @@ pub fn scsi_cntlr_create_scsi_bus(
     locked_scsi_cntlr.bus = Some(Arc::new(Mutex::new(bus)));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtio_scsi_lun() {
+        let lun = virtio_scsi_lun(3, 5);
+        assert_eq!(lun, [1, 3, 0, 5, 0, 0, 0, 0]);
+        assert_eq!(virtio_scsi_get_lun_id(lun), 5);
+
+        // Flat space addressing method is used for the lun larger than 255.
+        let lun = virtio_scsi_lun(0, 0x1234);
+        assert_eq!(lun[2..4], [0x52, 0x34]);
+        assert_eq!(virtio_scsi_get_lun_id(lun), 0x1234);
+    }
+
+    #[test]
+    fn test_virtio_scsi_config_space() {
+        let mut cntlr = ScsiCntlr::new(ScsiCntlrConfig {
+            queues: 4,
+            ..Default::default()
+        });
+        cntlr.realize().unwrap();
+        assert_eq!({ cntlr.config_space.num_queues }, 4);
+        assert_eq!({ cntlr.config_space.max_channel }, 0);
+        assert_eq!({ cntlr.config_space.max_target }, VIRTIO_SCSI_MAX_TARGET);
+        assert_ne!(
+            cntlr.base.device_features & (1_u64 << VIRTIO_SCSI_F_HOTPLUG),
+            0
+        );
+        // The events are dropped before the device is activated.
+        assert!(cntlr
+            .notify_transport_reset(0, 0, VIRTIO_SCSI_EVT_RESET_RESCAN)
+            .is_ok());
+        assert!(cntlr.pending_events.lock().unwrap().events.is_empty());
+    }
+}
@@ -30,7 +30,9 @@ use crate::{
     VIRTIO_TYPE_CONSOLE,
 };
 use address_space::AddressSpace;
-use chardev_backend::chardev::{Chardev, ChardevNotifyDevice, ChardevStatus, InputReceiver};
+use chardev_backend::chardev::{
+    register_chardev, Chardev, ChardevNotifyDevice, ChardevStatus, InputReceiver,
+};
 use machine_manager::{
     config::{ChardevType, VirtioSerialInfo, VirtioSerialPort, DEFAULT_VIRTQUEUE_SIZE},
     event_loop::EventLoop,
@@ -352,9 +354,13 @@ pub struct SerialPort {
 
 impl SerialPort {
     pub fn new(port_cfg: VirtioSerialPort) -> Self {
-        // Console is default host connected. And pty chardev has opened by default in realize()
-        // function.
-        let host_connected = port_cfg.is_console || port_cfg.chardev.backend == ChardevType::Pty;
+        // Console is default host connected. And pty and vdagent chardev have opened by default
+        // in realize() function.
+        let host_connected = port_cfg.is_console
+            || matches!(
+                port_cfg.chardev.backend,
+                ChardevType::Pty | ChardevType::Vdagent
+            );
 
         SerialPort {
             name: Some(port_cfg.id),
@@ -377,6 +383,7 @@ impl SerialPort {
             EventNotifierHelper::internal_notifiers(self.chardev.clone()),
             None,
         )?;
+        register_chardev(&self.chardev);
         Ok(())
     }
 
@@ -433,6 +440,12 @@ impl SerialPortHandler {
         let mut queue_lock = self.output_queue.lock().unwrap();
 
         loop {
+            // Stop popping requests when chardev can not drain the data in time. Output
+            // handling will be resumed once the chardev output buffer is drained.
+            if self.output_paused() {
+                break;
+            }
+
             let elem = queue_lock
                 .vring
                 .pop_avail(&self.mem_space, self.driver_features)?;
@@ -489,6 +502,13 @@ impl SerialPortHandler {
         Ok(())
     }
 
+    fn output_paused(&self) -> bool {
+        self.port.as_ref().is_some_and(|port| {
+            let port_locked = port.lock().unwrap();
+            port_locked.host_connected && port_locked.chardev.lock().unwrap().outbuf_is_full()
+        })
+    }
+
     fn write_chardev_msg(&self, buffer: &[u8], write_len: usize) {
         let port_locked = self.port.as_ref().unwrap().lock().unwrap();
         // Discard output buffer if this port's chardev is not connected.
@@ -496,21 +516,15 @@ impl SerialPortHandler {
             return;
         }
 
-        if let Some(output) = &mut port_locked.chardev.lock().unwrap().output {
-            let mut locked_output = output.lock().unwrap();
-            // To do:
-            // If the buffer is not fully written to chardev, the incomplete part will be discarded.
-            // This may occur when chardev is abnormal. Consider optimizing this logic in the
-            // future.
-            if let Err(e) = locked_output.write_all(&buffer[..write_len]) {
-                error!("Failed to write msg to chardev: {:?}", e);
-            }
-            if let Err(e) = locked_output.flush() {
-                error!("Failed to flush msg to chardev: {:?}", e);
-            }
-        } else {
-            error!("Failed to get output fd");
-        };
+        // The data which can not be written at once is buffered by chardev, and the output
+        // queue is kicked again when the buffer is drained.
+        if let Err(e) = Chardev::fill_outbuf(
+            &port_locked.chardev,
+            &buffer[..write_len],
+            Some(self.output_queue_evt.clone()),
+        ) {
+            error!("Failed to write msg to chardev: {:?}", e);
+        }
     }
 
     fn input_handle_internal(&mut self, buffer: &[u8]) -> Result<()> {
@@ -0,0 +1,1140 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::{error, warn};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+#[cfg(feature = "virtio_snd_alsa")]
+use super::sound_alsa::AlsaPcmBackend;
+use crate::{
+    check_config_space_rw, gpa_hva_iovec_map, iov_discard_front, iov_to_buf, read_config_default,
+    report_virtio_error, ElemIovec, Element, Queue, VirtioBase, VirtioDevice, VirtioError,
+    VirtioInterrupt, VirtioInterruptType, VIRTIO_F_VERSION_1, VIRTIO_TYPE_SOUND,
+};
+use address_space::AddressSpace;
+use machine_manager::{
+    config::{SoundBackendType, SoundConfig, DEFAULT_VIRTQUEUE_SIZE},
+    event_loop::{register_event_helper, unregister_event_helper},
+};
+use util::aio::iov_from_buf_direct;
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Request codes of the control queue.
+const VIRTIO_SND_R_JACK_INFO: u32 = 1;
+const VIRTIO_SND_R_PCM_INFO: u32 = 0x0100;
+const VIRTIO_SND_R_PCM_SET_PARAMS: u32 = 0x0101;
+const VIRTIO_SND_R_PCM_PREPARE: u32 = 0x0102;
+const VIRTIO_SND_R_PCM_RELEASE: u32 = 0x0103;
+const VIRTIO_SND_R_PCM_START: u32 = 0x0104;
+const VIRTIO_SND_R_PCM_STOP: u32 = 0x0105;
+const VIRTIO_SND_R_CHMAP_INFO: u32 = 0x0200;
+
+/// Status codes of the responses.
+const VIRTIO_SND_S_OK: u32 = 0x8000;
+const VIRTIO_SND_S_BAD_MSG: u32 = 0x8001;
+const VIRTIO_SND_S_NOT_SUPP: u32 = 0x8002;
+const VIRTIO_SND_S_IO_ERR: u32 = 0x8003;
+
+/// Data flow directions.
+const VIRTIO_SND_D_OUTPUT: u8 = 0;
+const VIRTIO_SND_D_INPUT: u8 = 1;
+
+/// Supported PCM sample formats.
+pub const VIRTIO_SND_PCM_FMT_S8: u8 = 3;
+pub const VIRTIO_SND_PCM_FMT_U8: u8 = 4;
+pub const VIRTIO_SND_PCM_FMT_S16: u8 = 5;
+pub const VIRTIO_SND_PCM_FMT_S32: u8 = 17;
+pub const VIRTIO_SND_PCM_FMT_FLOAT: u8 = 19;
+
+/// Supported PCM frame rates, and the rates in Hz.
+const SOUND_PCM_RATES: [(u8, u32); 7] = [
+    (1, 8000),
+    (2, 11025),
+    (3, 16000),
+    (4, 22050),
+    (5, 32000),
+    (6, 44100),
+    (7, 48000),
+];
+
+const QUEUE_NUM_SOUND: usize = 4;
+const CTRL_QUEUE: usize = 0;
+const TX_QUEUE: usize = 2;
+const RX_QUEUE: usize = 3;
+
+/// Stream 0 is for playback with the TX queue, and stream 1 is for capture with the RX queue.
+const SOUND_STREAMS: [u8; 2] = [VIRTIO_SND_D_OUTPUT, VIRTIO_SND_D_INPUT];
+const SOUND_CHANNELS_MIN: u8 = 1;
+const SOUND_CHANNELS_MAX: u8 = 2;
+/// Max size of the PCM buffer, to limit the memory used by the guest.
+const SOUND_BUFFER_BYTES_MAX: u32 = 1 << 20;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndConfig {
+    jacks: u32,
+    streams: u32,
+    chmaps: u32,
+}
+
+impl ByteCode for VirtioSndConfig {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndHdr {
+    code: u32,
+}
+
+impl ByteCode for VirtioSndHdr {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndQueryInfo {
+    code: u32,
+    start_id: u32,
+    count: u32,
+    size: u32,
+}
+
+impl ByteCode for VirtioSndQueryInfo {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndPcmInfo {
+    hda_fn_nid: u32,
+    features: u32,
+    formats: u64,
+    rates: u64,
+    direction: u8,
+    channels_min: u8,
+    channels_max: u8,
+    padding: [u8; 5],
+}
+
+impl ByteCode for VirtioSndPcmInfo {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndPcmSetParams {
+    code: u32,
+    stream_id: u32,
+    buffer_bytes: u32,
+    period_bytes: u32,
+    features: u32,
+    channels: u8,
+    format: u8,
+    rate: u8,
+    padding: u8,
+}
+
+impl ByteCode for VirtioSndPcmSetParams {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndPcmXfer {
+    stream_id: u32,
+}
+
+impl ByteCode for VirtioSndPcmXfer {}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioSndPcmStatus {
+    status: u32,
+    latency_bytes: u32,
+}
+
+impl ByteCode for VirtioSndPcmStatus {}
+
+/// Get the size in bytes of one sample.
+fn sample_bytes(format: u8) -> Option<u32> {
+    match format {
+        VIRTIO_SND_PCM_FMT_S8 | VIRTIO_SND_PCM_FMT_U8 => Some(1),
+        VIRTIO_SND_PCM_FMT_S16 => Some(2),
+        VIRTIO_SND_PCM_FMT_S32 | VIRTIO_SND_PCM_FMT_FLOAT => Some(4),
+        _ => None,
+    }
+}
+
+/// Direction of the PCM stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmDirection {
+    /// From the guest to the host.
+    Output,
+    /// From the host to the guest.
+    Input,
+}
+
+/// Parameters of the PCM stream set by the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcmParams {
+    pub channels: u8,
+    /// Sample format, one of `VIRTIO_SND_PCM_FMT_*`.
+    pub format: u8,
+    /// Frame rate in Hz.
+    pub rate: u32,
+    pub buffer_bytes: u32,
+    pub period_bytes: u32,
+}
+
+impl PcmParams {
+    /// Size in bytes of one frame, which contains a sample of each channel.
+    pub fn frame_bytes(&self) -> u32 {
+        // The format has been checked when the parameters are set.
+        sample_bytes(self.format).unwrap_or(1) * self.channels as u32
+    }
+
+    /// Time to play or capture `len` bytes.
+    pub fn duration(&self, len: usize) -> Duration {
+        let frames = len as u64 / self.frame_bytes() as u64;
+        Duration::from_micros(frames * 1_000_000 / self.rate as u64)
+    }
+}
+
+/// Host audio backend of a PCM stream. The data transfer is blocking, so the
+/// backend is driven by a dedicated thread of each stream.
+pub trait PcmBackend: Send {
+    /// Open the host PCM with the parameters.
+    fn open(&mut self, params: &PcmParams) -> Result<()>;
+
+    /// Play the data, return after all the data is queued to the host.
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Capture data until the buffer is filled.
+    fn read(&mut self, data: &mut [u8]) -> Result<()>;
+
+    /// Close the host PCM, it's fine to close a PCM which is not opened.
+    fn close(&mut self);
+}
+
+/// Backend without host audio, which discards the playback data and captures
+/// silence, at the speed of real time.
+#[derive(Default)]
+pub struct NullPcmBackend {
+    params: Option<PcmParams>,
+}
+
+impl PcmBackend for NullPcmBackend {
+    fn open(&mut self, params: &PcmParams) -> Result<()> {
+        self.params = Some(*params);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let params = self.params.as_ref().with_context(|| "PCM is not opened")?;
+        thread::sleep(params.duration(data.len()));
+        Ok(())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> Result<()> {
+        let params = self.params.as_ref().with_context(|| "PCM is not opened")?;
+        let silence = if params.format == VIRTIO_SND_PCM_FMT_U8 {
+            0x80
+        } else {
+            0
+        };
+        data.fill(silence);
+        thread::sleep(params.duration(data.len()));
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.params = None;
+    }
+}
+
+fn create_pcm_backend(config: &SoundConfig, direction: PcmDirection) -> Box<dyn PcmBackend> {
+    match config.backend {
+        SoundBackendType::None => {
+            let _ = direction;
+            Box::<NullPcmBackend>::default()
+        }
+        #[cfg(feature = "virtio_snd_alsa")]
+        SoundBackendType::Alsa => Box::new(AlsaPcmBackend::new(&config.pcm, direction)),
+    }
+}
+
+/// The queue which the requests are completed to, shared by the event loop and the stream threads.
+#[derive(Clone)]
+struct SoundQueue {
+    queue: Arc<Mutex<Queue>>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+}
+
+impl SoundQueue {
+    fn write_buf(&self, iovec: &[ElemIovec], buf: &[u8]) -> Result<usize> {
+        let (_, hva_iovec) = gpa_hva_iovec_map(iovec, &self.mem_space)?;
+        iov_from_buf_direct(&hva_iovec, buf)
+    }
+
+    fn add_used(&self, elem: &Element, len: u32) -> Result<()> {
+        let mut locked_queue = self.queue.lock().unwrap();
+        locked_queue
+            .vring
+            .add_used(&self.mem_space, elem.index, len)
+            .with_context(|| format!("Failed to add used ring {}", elem.index))?;
+        if locked_queue
+            .vring
+            .should_notify(&self.mem_space, self.driver_features)
+        {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue), false)
+                .with_context(|| {
+                    VirtioError::InterruptTrigger("sound", VirtioInterruptType::Vring)
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Complete the transfer request. The captured data is written for the input stream,
+    /// and it's padded with zero if it's shorter than the buffer. The status is the last
+    /// part of the device writable buffer.
+    fn complete_xfer(&self, elem: &Element, data: &[u8], status: u32) -> Result<()> {
+        let status_size = size_of::<VirtioSndPcmStatus>() as u64;
+        let in_size = Element::iovec_size(&elem.in_iovec);
+        if in_size < status_size {
+            bail!(
+                "Invalid in_iovec size {} of sound transfer request",
+                in_size
+            );
+        }
+        let data_size = in_size - status_size;
+        let mut in_iovec = elem.in_iovec.clone();
+        if data_size != 0 {
+            let mut buf = vec![0_u8; data_size as usize];
+            let len = min(data.len(), buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            self.write_buf(&in_iovec, &buf)?;
+        }
+        let status_iovec = iov_discard_front(&mut in_iovec, data_size)
+            .with_context(|| "Failed to get sound status iovec")?;
+        let pcm_status = VirtioSndPcmStatus {
+            status,
+            latency_bytes: 0,
+        };
+        self.write_buf(status_iovec, pcm_status.as_bytes())?;
+        self.add_used(elem, in_size as u32)
+    }
+}
+
+/// Messages from the event loop to the stream thread.
+enum PcmMsg {
+    Xfer(Element),
+    Start,
+    Stop,
+    /// Complete all the pending transfer requests.
+    Release,
+    Exit,
+}
+
+/// Thread which transfers the data between the queue and the host backend.
+struct PcmWorker {
+    direction: PcmDirection,
+    sound_queue: SoundQueue,
+    backend: Arc<Mutex<Box<dyn PcmBackend>>>,
+    receiver: Receiver<PcmMsg>,
+    /// Transfer requests which are not handled.
+    pending: VecDeque<Element>,
+    running: bool,
+}
+
+impl PcmWorker {
+    fn run(&mut self) {
+        loop {
+            let msg = if self.running && !self.pending.is_empty() {
+                match self.receiver.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            } else {
+                match self.receiver.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => return,
+                }
+            };
+
+            let result = match msg {
+                Some(PcmMsg::Xfer(elem)) => {
+                    self.pending.push_back(elem);
+                    Ok(())
+                }
+                Some(PcmMsg::Start) => {
+                    self.running = true;
+                    Ok(())
+                }
+                Some(PcmMsg::Stop) => {
+                    self.running = false;
+                    Ok(())
+                }
+                Some(PcmMsg::Release) => {
+                    self.running = false;
+                    self.flush()
+                }
+                Some(PcmMsg::Exit) => return,
+                // It's safe to unwrap as the pending requests are not empty.
+                None => {
+                    let elem = self.pending.pop_front().unwrap();
+                    self.transfer(&elem)
+                }
+            };
+            if let Err(e) = result {
+                error!("Failed to handle sound transfer request: {:?}", e);
+            }
+        }
+    }
+
+    fn transfer(&mut self, elem: &Element) -> Result<()> {
+        match self.direction {
+            PcmDirection::Output => {
+                let mut out_iovec = elem.out_iovec.clone();
+                let data_iovec =
+                    iov_discard_front(&mut out_iovec, size_of::<VirtioSndPcmXfer>() as u64)
+                        .unwrap_or_default();
+                let size = min(
+                    Element::iovec_size(data_iovec),
+                    SOUND_BUFFER_BYTES_MAX as u64,
+                );
+                let mut data = vec![0_u8; size as usize];
+                let len = iov_to_buf(&self.sound_queue.mem_space, data_iovec, &mut data)?;
+                let status = match self.backend.lock().unwrap().write(&data[..len]) {
+                    Ok(()) => VIRTIO_SND_S_OK,
+                    Err(e) => {
+                        warn!("Failed to play sound: {:?}", e);
+                        VIRTIO_SND_S_IO_ERR
+                    }
+                };
+                self.sound_queue.complete_xfer(elem, &[], status)
+            }
+            PcmDirection::Input => {
+                let size = Element::iovec_size(&elem.in_iovec)
+                    .saturating_sub(size_of::<VirtioSndPcmStatus>() as u64);
+                let mut data = vec![0_u8; min(size, SOUND_BUFFER_BYTES_MAX as u64) as usize];
+                let status = match self.backend.lock().unwrap().read(&mut data) {
+                    Ok(()) => VIRTIO_SND_S_OK,
+                    Err(e) => {
+                        warn!("Failed to capture sound: {:?}", e);
+                        VIRTIO_SND_S_IO_ERR
+                    }
+                };
+                self.sound_queue.complete_xfer(elem, &data, status)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while let Some(elem) = self.pending.pop_front() {
+            self.sound_queue
+                .complete_xfer(&elem, &[], VIRTIO_SND_S_OK)?;
+        }
+        Ok(())
+    }
+}
+
+/// State of the PCM stream, which is changed by the control requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PcmState {
+    Idle,
+    ParamsSet,
+    Prepared,
+    Running,
+    Stopped,
+    Released,
+}
+
+struct PcmStream {
+    direction: PcmDirection,
+    state: PcmState,
+    params: Option<PcmParams>,
+    backend: Arc<Mutex<Box<dyn PcmBackend>>>,
+    /// Sender to the stream thread, available after the device is activated.
+    sender: Option<Sender<PcmMsg>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PcmStream {
+    fn new(config: &SoundConfig, direction: PcmDirection) -> Self {
+        PcmStream {
+            direction,
+            state: PcmState::Idle,
+            params: None,
+            backend: Arc::new(Mutex::new(create_pcm_backend(config, direction))),
+            sender: None,
+            thread: None,
+        }
+    }
+
+    fn info(&self) -> VirtioSndPcmInfo {
+        let mut formats = 0_u64;
+        for format in [
+            VIRTIO_SND_PCM_FMT_S8,
+            VIRTIO_SND_PCM_FMT_U8,
+            VIRTIO_SND_PCM_FMT_S16,
+            VIRTIO_SND_PCM_FMT_S32,
+            VIRTIO_SND_PCM_FMT_FLOAT,
+        ] {
+            formats |= 1 << format;
+        }
+        let mut rates = 0_u64;
+        for (rate, _) in SOUND_PCM_RATES.iter() {
+            rates |= 1 << rate;
+        }
+        VirtioSndPcmInfo {
+            formats,
+            rates,
+            direction: match self.direction {
+                PcmDirection::Output => VIRTIO_SND_D_OUTPUT,
+                PcmDirection::Input => VIRTIO_SND_D_INPUT,
+            },
+            channels_min: SOUND_CHANNELS_MIN,
+            channels_max: SOUND_CHANNELS_MAX,
+            ..Default::default()
+        }
+    }
+
+    fn send(&self, msg: PcmMsg) {
+        if let Some(sender) = self.sender.as_ref() {
+            if sender.send(msg).is_err() {
+                error!("The thread of sound stream exited unexpectedly");
+            }
+        }
+    }
+
+    fn set_params(&mut self, req: &VirtioSndPcmSetParams) -> u32 {
+        if !matches!(
+            self.state,
+            PcmState::Idle | PcmState::ParamsSet | PcmState::Prepared | PcmState::Released
+        ) {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        if req.features != 0 {
+            return VIRTIO_SND_S_NOT_SUPP;
+        }
+        let rate = match SOUND_PCM_RATES.iter().find(|(rate, _)| *rate == req.rate) {
+            Some((_, hz)) => *hz,
+            None => return VIRTIO_SND_S_NOT_SUPP,
+        };
+        if sample_bytes(req.format).is_none()
+            || !(SOUND_CHANNELS_MIN..=SOUND_CHANNELS_MAX).contains(&req.channels)
+        {
+            return VIRTIO_SND_S_NOT_SUPP;
+        }
+        if req.period_bytes == 0
+            || req.buffer_bytes > SOUND_BUFFER_BYTES_MAX
+            || req.period_bytes > req.buffer_bytes
+        {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+
+        self.backend.lock().unwrap().close();
+        self.params = Some(PcmParams {
+            channels: req.channels,
+            format: req.format,
+            rate,
+            buffer_bytes: req.buffer_bytes,
+            period_bytes: req.period_bytes,
+        });
+        self.state = PcmState::ParamsSet;
+        VIRTIO_SND_S_OK
+    }
+
+    fn prepare(&mut self) -> u32 {
+        if !matches!(
+            self.state,
+            PcmState::ParamsSet | PcmState::Prepared | PcmState::Released
+        ) {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        // It's safe to unwrap as the parameters have been set in these states.
+        let params = self.params.unwrap();
+        let mut locked_backend = self.backend.lock().unwrap();
+        locked_backend.close();
+        if let Err(e) = locked_backend.open(&params) {
+            error!("Failed to open host PCM for sound: {:?}", e);
+            return VIRTIO_SND_S_IO_ERR;
+        }
+        self.state = PcmState::Prepared;
+        VIRTIO_SND_S_OK
+    }
+
+    fn start(&mut self) -> u32 {
+        if !matches!(self.state, PcmState::Prepared | PcmState::Stopped) {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        self.send(PcmMsg::Start);
+        self.state = PcmState::Running;
+        VIRTIO_SND_S_OK
+    }
+
+    fn stop(&mut self) -> u32 {
+        if self.state != PcmState::Running {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        self.send(PcmMsg::Stop);
+        self.state = PcmState::Stopped;
+        VIRTIO_SND_S_OK
+    }
+
+    fn release(&mut self) -> u32 {
+        if !matches!(self.state, PcmState::Prepared | PcmState::Stopped) {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        self.send(PcmMsg::Release);
+        self.backend.lock().unwrap().close();
+        self.state = PcmState::Released;
+        VIRTIO_SND_S_OK
+    }
+
+    fn activate(&mut self, sound_queue: SoundQueue) -> Result<()> {
+        let (sender, receiver) = channel();
+        let mut worker = PcmWorker {
+            direction: self.direction,
+            sound_queue,
+            backend: self.backend.clone(),
+            receiver,
+            pending: VecDeque::new(),
+            running: false,
+        };
+        let thread = thread::Builder::new()
+            .name(format!("sound {:?}", self.direction).to_lowercase())
+            .spawn(move || worker.run())
+            .with_context(|| "Failed to create thread for sound stream")?;
+        self.sender = Some(sender);
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    fn deactivate(&mut self) {
+        self.send(PcmMsg::Exit);
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Failed to join the thread of sound stream");
+            }
+        }
+        self.backend.lock().unwrap().close();
+        self.state = PcmState::Idle;
+        self.params = None;
+    }
+}
+
+/// Handler of the control queue.
+struct SoundCtrlHandler {
+    sound_queue: SoundQueue,
+    queue_evt: Arc<EventFd>,
+    streams: Arc<Mutex<Vec<PcmStream>>>,
+}
+
+impl SoundCtrlHandler {
+    fn query_pcm_info(&self, req: &VirtioSndQueryInfo, infos: &mut Vec<u8>) -> u32 {
+        let locked_streams = self.streams.lock().unwrap();
+        let info_size = size_of::<VirtioSndPcmInfo>();
+        if (req.size as usize) < info_size
+            || req.start_id as u64 + req.count as u64 > locked_streams.len() as u64
+        {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        for stream in locked_streams
+            .iter()
+            .skip(req.start_id as usize)
+            .take(req.count as usize)
+        {
+            infos.extend_from_slice(stream.info().as_bytes());
+            infos.resize(infos.len() + req.size as usize - info_size, 0);
+        }
+        VIRTIO_SND_S_OK
+    }
+
+    fn handle_pcm_request(&self, code: u32, req: &[u8]) -> u32 {
+        let mut set_params = VirtioSndPcmSetParams::default();
+        let size = min(req.len(), size_of::<VirtioSndPcmSetParams>());
+        set_params.as_mut_bytes()[..size].copy_from_slice(&req[..size]);
+        let mut locked_streams = self.streams.lock().unwrap();
+        let stream = match locked_streams.get_mut(set_params.stream_id as usize) {
+            Some(stream) => stream,
+            None => return VIRTIO_SND_S_BAD_MSG,
+        };
+        match code {
+            VIRTIO_SND_R_PCM_SET_PARAMS => {
+                if req.len() < size_of::<VirtioSndPcmSetParams>() {
+                    return VIRTIO_SND_S_BAD_MSG;
+                }
+                stream.set_params(&set_params)
+            }
+            VIRTIO_SND_R_PCM_PREPARE => stream.prepare(),
+            VIRTIO_SND_R_PCM_START => stream.start(),
+            VIRTIO_SND_R_PCM_STOP => stream.stop(),
+            VIRTIO_SND_R_PCM_RELEASE => stream.release(),
+            _ => VIRTIO_SND_S_NOT_SUPP,
+        }
+    }
+
+    /// Handle the control request, and return the response.
+    fn handle_ctrl_request(&self, elem: &Element) -> Result<Vec<u8>> {
+        let mut req = vec![0_u8; size_of::<VirtioSndPcmSetParams>()];
+        let len = iov_to_buf(&self.sound_queue.mem_space, &elem.out_iovec, &mut req)?;
+        req.truncate(len);
+        let mut payload = Vec::new();
+        let status = if len < size_of::<VirtioSndHdr>() {
+            VIRTIO_SND_S_BAD_MSG
+        } else {
+            let code = VirtioSndHdr::from_bytes(&req[..size_of::<VirtioSndHdr>()])
+                .unwrap()
+                .code;
+            match code {
+                VIRTIO_SND_R_PCM_INFO | VIRTIO_SND_R_JACK_INFO | VIRTIO_SND_R_CHMAP_INFO => {
+                    match VirtioSndQueryInfo::from_bytes(&req) {
+                        Some(query) if code == VIRTIO_SND_R_PCM_INFO => {
+                            self.query_pcm_info(query, &mut payload)
+                        }
+                        // There is no jack and channel map.
+                        _ => VIRTIO_SND_S_BAD_MSG,
+                    }
+                }
+                VIRTIO_SND_R_PCM_SET_PARAMS..=VIRTIO_SND_R_PCM_STOP => {
+                    if len < size_of::<VirtioSndHdr>() + size_of::<u32>() {
+                        VIRTIO_SND_S_BAD_MSG
+                    } else {
+                        self.handle_pcm_request(code, &req)
+                    }
+                }
+                _ => VIRTIO_SND_S_NOT_SUPP,
+            }
+        };
+        if status != VIRTIO_SND_S_OK {
+            payload.clear();
+        }
+
+        let mut resp = VirtioSndHdr { code: status }.as_bytes().to_vec();
+        resp.extend(payload);
+        Ok(resp)
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        loop {
+            let elem = self
+                .sound_queue
+                .queue
+                .lock()
+                .unwrap()
+                .vring
+                .pop_avail(
+                    &self.sound_queue.mem_space,
+                    self.sound_queue.driver_features,
+                )
+                .with_context(|| "Failed to pop avail ring for sound control queue")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            let resp = self.handle_ctrl_request(&elem)?;
+            let len = self.sound_queue.write_buf(&elem.in_iovec, &resp)?;
+            self.sound_queue.add_used(&elem, len as u32)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handler of the TX or RX queue, which dispatches the transfer requests to the stream thread.
+struct SoundXferHandler {
+    sound_queue: SoundQueue,
+    queue_evt: Arc<EventFd>,
+    /// Id of the stream served by the queue.
+    stream_id: u32,
+    sender: Sender<PcmMsg>,
+}
+
+impl SoundXferHandler {
+    fn process_queue(&mut self) -> Result<()> {
+        let mut elems = Vec::new();
+        let mut locked_queue = self.sound_queue.queue.lock().unwrap();
+        loop {
+            let elem = locked_queue
+                .vring
+                .pop_avail(
+                    &self.sound_queue.mem_space,
+                    self.sound_queue.driver_features,
+                )
+                .with_context(|| "Failed to pop avail ring for sound transfer queue")?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            elems.push(elem);
+        }
+        drop(locked_queue);
+
+        for elem in elems {
+            let mut xfer = VirtioSndPcmXfer::default();
+            let len = iov_to_buf(
+                &self.sound_queue.mem_space,
+                &elem.out_iovec,
+                xfer.as_mut_bytes(),
+            )?;
+            if len < size_of::<VirtioSndPcmXfer>() || xfer.stream_id != self.stream_id {
+                warn!("Invalid sound transfer request of stream {}", {
+                    xfer.stream_id
+                });
+                self.sound_queue
+                    .complete_xfer(&elem, &[], VIRTIO_SND_S_BAD_MSG)?;
+                continue;
+            }
+            self.sender
+                .send(PcmMsg::Xfer(elem))
+                .with_context(|| "The thread of sound stream exited unexpectedly")?;
+        }
+        Ok(())
+    }
+}
+
+fn build_queue_notifier<F>(queue_evt: &EventFd, broken: Arc<AtomicBool>, f: F) -> EventNotifier
+where
+    F: Fn() + 'static,
+{
+    let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+        read_fd(fd);
+        if !broken.load(Ordering::SeqCst) {
+            f();
+        }
+        None
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        queue_evt.as_raw_fd(),
+        None,
+        EventSet::IN,
+        vec![handler],
+    )
+}
+
+impl EventNotifierHelper for SoundCtrlHandler {
+    fn internal_notifiers(handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let locked_handler = handler.lock().unwrap();
+        let sound_queue = locked_handler.sound_queue.clone();
+        let broken = Arc::new(AtomicBool::new(false));
+        let cloned_broken = broken.clone();
+        let cloned_handler = handler.clone();
+        vec![build_queue_notifier(
+            &locked_handler.queue_evt,
+            broken,
+            move || {
+                cloned_handler
+                    .lock()
+                    .unwrap()
+                    .process_queue()
+                    .unwrap_or_else(|e| {
+                        error!("Failed to process sound control queue, err: {:?}", e);
+                        report_virtio_error(
+                            sound_queue.interrupt_cb.clone(),
+                            sound_queue.driver_features,
+                            &cloned_broken,
+                        );
+                    });
+            },
+        )]
+    }
+}
+
+impl EventNotifierHelper for SoundXferHandler {
+    fn internal_notifiers(handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let locked_handler = handler.lock().unwrap();
+        let sound_queue = locked_handler.sound_queue.clone();
+        let broken = Arc::new(AtomicBool::new(false));
+        let cloned_broken = broken.clone();
+        let cloned_handler = handler.clone();
+        vec![build_queue_notifier(
+            &locked_handler.queue_evt,
+            broken,
+            move || {
+                cloned_handler
+                    .lock()
+                    .unwrap()
+                    .process_queue()
+                    .unwrap_or_else(|e| {
+                        error!("Failed to process sound transfer queue, err: {:?}", e);
+                        report_virtio_error(
+                            sound_queue.interrupt_cb.clone(),
+                            sound_queue.driver_features,
+                            &cloned_broken,
+                        );
+                    });
+            },
+        )]
+    }
+}
+
+/// Sound device structure, which has a playback stream and a capture stream.
+pub struct Sound {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// Configuration of virtio sound device.
+    sound_cfg: SoundConfig,
+    /// Config space of the device.
+    config_space: VirtioSndConfig,
+    streams: Arc<Mutex<Vec<PcmStream>>>,
+}
+
+impl Sound {
+    pub fn new(sound_cfg: SoundConfig) -> Self {
+        let streams = SOUND_STREAMS
+            .iter()
+            .map(|dir| {
+                let direction = if *dir == VIRTIO_SND_D_OUTPUT {
+                    PcmDirection::Output
+                } else {
+                    PcmDirection::Input
+                };
+                PcmStream::new(&sound_cfg, direction)
+            })
+            .collect();
+        Sound {
+            base: VirtioBase::new(VIRTIO_TYPE_SOUND, QUEUE_NUM_SOUND, DEFAULT_VIRTQUEUE_SIZE),
+            sound_cfg,
+            config_space: VirtioSndConfig::default(),
+            streams: Arc::new(Mutex::new(streams)),
+        }
+    }
+}
+
+impl VirtioDevice for Sound {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        self.base.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
+        self.config_space = VirtioSndConfig {
+            jacks: 0,
+            streams: SOUND_STREAMS.len() as u32,
+            chmaps: 0,
+        };
+        Ok(())
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        read_config_default(self.config_space.as_bytes(), offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        check_config_space_rw(self.config_space.as_bytes(), offset, data)?;
+        // The config space is read-only for the driver, so do nothing here.
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let queues = self.base.queues.clone();
+        if queues.len() != QUEUE_NUM_SOUND || queue_evts.len() != QUEUE_NUM_SOUND {
+            bail!(
+                "Invalid queue number {} of virtio sound, expected {}",
+                queues.len(),
+                QUEUE_NUM_SOUND
+            );
+        }
+        let sound_queue = |index: usize| SoundQueue {
+            queue: queues[index].clone(),
+            mem_space: mem_space.clone(),
+            interrupt_cb: interrupt_cb.clone(),
+            driver_features: self.base.driver_features,
+        };
+
+        let ctrl_handler = SoundCtrlHandler {
+            sound_queue: sound_queue(CTRL_QUEUE),
+            queue_evt: queue_evts[CTRL_QUEUE].clone(),
+            streams: self.streams.clone(),
+        };
+        let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(ctrl_handler)));
+        register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+
+        // No event is reported as there is no jack, so the event queue is not handled.
+        let mut locked_streams = self.streams.lock().unwrap();
+        for (stream_id, queue_index) in [TX_QUEUE, RX_QUEUE].into_iter().enumerate() {
+            let stream = &mut locked_streams[stream_id];
+            stream.activate(sound_queue(queue_index))?;
+            let xfer_handler = SoundXferHandler {
+                sound_queue: sound_queue(queue_index),
+                queue_evt: queue_evts[queue_index].clone(),
+                stream_id: stream_id as u32,
+                // It's safe to unwrap as the stream has been activated.
+                sender: stream.sender.clone().unwrap(),
+            };
+            let notifiers =
+                EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(xfer_handler)));
+            register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+        }
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        unregister_event_helper(None, &mut self.base.deactivate_evts)?;
+        for stream in self.streams.lock().unwrap().iter_mut() {
+            stream.deactivate();
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Sound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sound")
+            .field("sound_cfg", &self.sound_cfg)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_params_req(stream_id: u32, format: u8, rate: u8, channels: u8) -> Vec<u8> {
+        VirtioSndPcmSetParams {
+            code: VIRTIO_SND_R_PCM_SET_PARAMS,
+            stream_id,
+            buffer_bytes: 8192,
+            period_bytes: 1024,
+            features: 0,
+            channels,
+            format,
+            rate,
+            padding: 0,
+        }
+        .as_bytes()
+        .to_vec()
+    }
+
+    fn pcm_req(code: u32, stream_id: u32) -> Vec<u8> {
+        let mut req = code.to_le_bytes().to_vec();
+        req.extend(stream_id.to_le_bytes());
+        req
+    }
+
+    #[test]
+    fn test_sound_config() {
+        let mut sound = Sound::new(SoundConfig::default());
+        assert_eq!(sound.queue_num(), QUEUE_NUM_SOUND);
+        assert_eq!(sound.device_type(), VIRTIO_TYPE_SOUND);
+        sound.realize().unwrap();
+
+        let mut data = [0_u8; 4];
+        sound.read_config(4, &mut data).unwrap();
+        assert_eq!(u32::from_le_bytes(data), 2);
+        assert!(sound
+            .read_config(size_of::<VirtioSndConfig>() as u64, &mut data)
+            .is_err());
+        assert!(sound.write_config(0, &data).is_ok());
+    }
+
+    #[test]
+    fn test_sound_pcm_state() {
+        let sound = Sound::new(SoundConfig::default());
+        let mut locked_streams = sound.streams.lock().unwrap();
+        let stream = &mut locked_streams[0];
+        let mut req = VirtioSndPcmSetParams::default();
+        req.as_mut_bytes()
+            .copy_from_slice(&set_params_req(0, VIRTIO_SND_PCM_FMT_S16, 7, 2));
+
+        // The parameters must be set before prepare.
+        assert_eq!(stream.prepare(), VIRTIO_SND_S_BAD_MSG);
+        assert_eq!(stream.set_params(&req), VIRTIO_SND_S_OK);
+        assert_eq!(stream.params.unwrap().rate, 48000);
+        assert_eq!(stream.params.unwrap().frame_bytes(), 4);
+        assert_eq!(stream.start(), VIRTIO_SND_S_BAD_MSG);
+        assert_eq!(stream.prepare(), VIRTIO_SND_S_OK);
+        assert_eq!(stream.start(), VIRTIO_SND_S_OK);
+        assert_eq!(stream.set_params(&req), VIRTIO_SND_S_BAD_MSG);
+        assert_eq!(stream.release(), VIRTIO_SND_S_BAD_MSG);
+        assert_eq!(stream.stop(), VIRTIO_SND_S_OK);
+        assert_eq!(stream.release(), VIRTIO_SND_S_OK);
+        assert_eq!(stream.state, PcmState::Released);
+        assert_eq!(stream.prepare(), VIRTIO_SND_S_OK);
+
+        // Unsupported parameters.
+        req.format = 0;
+        assert_eq!(stream.set_params(&req), VIRTIO_SND_S_NOT_SUPP);
+        req.format = VIRTIO_SND_PCM_FMT_S16;
+        req.rate = 13;
+        assert_eq!(stream.set_params(&req), VIRTIO_SND_S_NOT_SUPP);
+        req.rate = 6;
+        req.channels = 3;
+        assert_eq!(stream.set_params(&req), VIRTIO_SND_S_NOT_SUPP);
+        req.channels = 1;
+        req.period_bytes = 16384;
+        assert_eq!(stream.set_params(&req), VIRTIO_SND_S_BAD_MSG);
+    }
+
+    #[test]
+    fn test_sound_pcm_info() {
+        let sound = Sound::new(SoundConfig::default());
+        let streams = sound.streams.lock().unwrap();
+        let info = streams[0].info();
+        assert_eq!(info.direction, VIRTIO_SND_D_OUTPUT);
+        assert_eq!({ info.formats } & (1 << VIRTIO_SND_PCM_FMT_S16), 1 << 5);
+        assert_eq!({ info.rates } & (1 << 7), 1 << 7);
+        assert_eq!(streams[1].info().direction, VIRTIO_SND_D_INPUT);
+        assert_eq!(size_of::<VirtioSndPcmInfo>(), 32);
+        assert_eq!(size_of::<VirtioSndPcmSetParams>(), 24);
+        assert_eq!(
+            pcm_req(VIRTIO_SND_R_PCM_START, 1).len(),
+            size_of::<VirtioSndHdr>() + size_of::<u32>()
+        );
+    }
+
+    #[test]
+    fn test_null_pcm_backend() {
+        let params = PcmParams {
+            channels: 1,
+            format: VIRTIO_SND_PCM_FMT_U8,
+            rate: 8000,
+            buffer_bytes: 800,
+            period_bytes: 80,
+        };
+        assert_eq!(params.duration(80), Duration::from_millis(10));
+
+        let mut backend = NullPcmBackend::default();
+        let mut data = [0_u8; 8];
+        assert!(backend.read(&mut data).is_err());
+        backend.open(&params).unwrap();
+        backend.read(&mut data).unwrap();
+        assert_eq!(data, [0x80; 8]);
+        backend.write(&data).unwrap();
+        backend.close();
+        assert!(backend.write(&data).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use alsa::pcm::{Access, Format, Frames, HwParams, PCM};
+use alsa::{Direction, ValueOr};
+use anyhow::{bail, Context, Result};
+
+use super::sound::{
+    PcmBackend, PcmDirection, PcmParams, VIRTIO_SND_PCM_FMT_FLOAT, VIRTIO_SND_PCM_FMT_S16,
+    VIRTIO_SND_PCM_FMT_S32, VIRTIO_SND_PCM_FMT_S8, VIRTIO_SND_PCM_FMT_U8,
+};
+
+/// Backend which plays and captures by the host ALSA PCM. The PCM of PipeWire and
+/// PulseAudio can also be used through the ALSA plugins of them.
+pub struct AlsaPcmBackend {
+    name: String,
+    direction: PcmDirection,
+    pcm: Option<PCM>,
+    frame_bytes: usize,
+}
+
+impl AlsaPcmBackend {
+    pub fn new(name: &str, direction: PcmDirection) -> Self {
+        AlsaPcmBackend {
+            name: name.to_string(),
+            direction,
+            pcm: None,
+            frame_bytes: 1,
+        }
+    }
+
+    fn set_hw_params(&self, pcm: &PCM, params: &PcmParams) -> Result<()> {
+        let format = match params.format {
+            VIRTIO_SND_PCM_FMT_S8 => Format::S8,
+            VIRTIO_SND_PCM_FMT_U8 => Format::U8,
+            VIRTIO_SND_PCM_FMT_S16 => Format::S16LE,
+            VIRTIO_SND_PCM_FMT_S32 => Format::S32LE,
+            VIRTIO_SND_PCM_FMT_FLOAT => Format::FloatLE,
+            _ => bail!("Unsupported PCM format {}", params.format),
+        };
+        let frame_bytes = params.frame_bytes() as Frames;
+        let hwp = HwParams::any(pcm)?;
+        hwp.set_access(Access::RWInterleaved)?;
+        hwp.set_format(format)?;
+        hwp.set_channels(params.channels as u32)?;
+        hwp.set_rate_resample(true)?;
+        hwp.set_rate(params.rate, ValueOr::Nearest)?;
+        hwp.set_period_size_near(
+            params.period_bytes as Frames / frame_bytes,
+            ValueOr::Nearest,
+        )?;
+        hwp.set_buffer_size_near(params.buffer_bytes as Frames / frame_bytes)?;
+        pcm.hw_params(&hwp)?;
+
+        if self.direction == PcmDirection::Output {
+            // Start playing after the first period is written, to avoid underrun at the beginning.
+            let swp = pcm.sw_params_current()?;
+            swp.set_start_threshold(hwp.get_period_size()?)?;
+            pcm.sw_params(&swp)?;
+        }
+        Ok(())
+    }
+
+    /// Recover the PCM from the error such as underrun or overrun.
+    fn recover(pcm: &PCM, err: alsa::Error) -> Result<()> {
+        pcm.try_recover(err, true)
+            .with_context(|| format!("Failed to recover PCM from error: {}", err))
+    }
+}
+
+impl PcmBackend for AlsaPcmBackend {
+    fn open(&mut self, params: &PcmParams) -> Result<()> {
+        let dir = match self.direction {
+            PcmDirection::Output => Direction::Playback,
+            PcmDirection::Input => Direction::Capture,
+        };
+        let pcm = PCM::new(&self.name, dir, false)
+            .with_context(|| format!("Failed to open ALSA PCM {}", self.name))?;
+        self.set_hw_params(&pcm, params)
+            .with_context(|| format!("Failed to set parameters of ALSA PCM {}", self.name))?;
+        pcm.prepare()?;
+        self.frame_bytes = params.frame_bytes() as usize;
+        self.pcm = Some(pcm);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let pcm = self.pcm.as_ref().with_context(|| "PCM is not opened")?;
+        let io = pcm.io_bytes();
+        let mut offset = 0;
+        while data.len() - offset >= self.frame_bytes {
+            match io.writei(&data[offset..]) {
+                Ok(frames) => offset += frames * self.frame_bytes,
+                Err(e) => Self::recover(pcm, e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> Result<()> {
+        let pcm = self.pcm.as_ref().with_context(|| "PCM is not opened")?;
+        let io = pcm.io_bytes();
+        let mut offset = 0;
+        while data.len() - offset >= self.frame_bytes {
+            match io.readi(&mut data[offset..]) {
+                Ok(frames) => offset += frames * self.frame_bytes,
+                Err(e) => Self::recover(pcm, e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some(pcm) = self.pcm.take() {
+            if self.direction == PcmDirection::Output {
+                // Play the data left in the buffer, the error is ignored as the PCM is dropped.
+                let _ = pcm.drain();
+            }
+        }
+    }
+}
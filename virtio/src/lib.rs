@@ -34,12 +34,18 @@ mod transport;
 
 pub use device::balloon::*;
 pub use device::block::{Block, BlockState, VirtioBlkConfig};
+pub use device::crypto::Crypto;
 #[cfg(feature = "virtio_gpu")]
 pub use device::gpu::*;
+pub use device::iommu::Iommu;
 pub use device::net::*;
+pub use device::net_filter::{add_net_filter, del_net_filter};
+pub use device::p9fs::P9fs;
+pub use device::pmem::Pmem;
 pub use device::rng::{Rng, RngState};
 pub use device::scsi_cntlr as ScsiCntlr;
 pub use device::serial::{find_port_by_nr, get_max_nr, Serial, SerialPort, VirtioSerialState};
+pub use device::sound::Sound;
 pub use error::VirtioError;
 pub use error::*;
 pub use queue::*;
@@ -77,9 +83,14 @@ pub const VIRTIO_TYPE_CONSOLE: u32 = 3;
 pub const VIRTIO_TYPE_RNG: u32 = 4;
 pub const VIRTIO_TYPE_BALLOON: u32 = 5;
 pub const VIRTIO_TYPE_SCSI: u32 = 8;
+pub const VIRTIO_TYPE_9P: u32 = 9;
 pub const VIRTIO_TYPE_GPU: u32 = 16;
 pub const VIRTIO_TYPE_VSOCK: u32 = 19;
+pub const VIRTIO_TYPE_CRYPTO: u32 = 20;
+pub const VIRTIO_TYPE_IOMMU: u32 = 23;
+pub const VIRTIO_TYPE_SOUND: u32 = 25;
 pub const VIRTIO_TYPE_FS: u32 = 26;
+pub const VIRTIO_TYPE_PMEM: u32 = 27;
 
 // The Status of Virtio Device.
 const CONFIG_STATUS_ACKNOWLEDGE: u32 = 0x01;
@@ -125,6 +136,8 @@ pub const VIRTIO_NET_F_HOST_TSO6: u32 = 12;
 pub const VIRTIO_NET_F_HOST_UFO: u32 = 14;
 /// Device can merge receive buffers.
 pub const VIRTIO_NET_F_MRG_RXBUF: u32 = 15;
+/// Configuration status field is available.
+pub const VIRTIO_NET_F_STATUS: u32 = 16;
 /// Control channel is available.
 pub const VIRTIO_NET_F_CTRL_VQ: u32 = 17;
 /// Control channel RX mode support.
@@ -137,6 +150,10 @@ pub const VIRTIO_NET_F_CTRL_RX_EXTRA: u32 = 20;
 pub const VIRTIO_NET_F_MQ: u32 = 22;
 /// Set Mac Address through control channel.
 pub const VIRTIO_NET_F_CTRL_MAC_ADDR: u32 = 23;
+/// Device supports notifications coalescing.
+pub const VIRTIO_NET_F_NOTF_COAL: u32 = 53;
+/// The link of net device is up.
+pub const VIRTIO_NET_S_LINK_UP: u16 = 1;
 /// Configuration cols and rows are valid.
 pub const VIRTIO_CONSOLE_F_SIZE: u64 = 0;
 /// Device has support for multiple ports.
@@ -165,6 +182,8 @@ pub const VIRTIO_BLK_F_DISCARD: u32 = 13;
 pub const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 14;
 /// Unmap flags for write zeroes command.
 pub const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
+/// Zoned block device is supported.
+pub const VIRTIO_BLK_F_ZONED: u32 = 17;
 /// GPU EDID feature is supported.
 pub const VIRTIO_GPU_F_EDID: u32 = 1;
 
@@ -210,6 +229,13 @@ pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u16 = 0;
 pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN: u16 = 1;
 /// The maximum pairs of multiple queue.
 pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX: u16 = 0x8000;
+
+/// The driver can send control commands for notifications coalescing.
+pub const VIRTIO_NET_CTRL_NOTF_COAL: u8 = 6;
+/// The driver sets the notifications coalescing parameters of tx queues.
+pub const VIRTIO_NET_CTRL_NOTF_COAL_TX_SET: u8 = 0;
+/// The driver sets the notifications coalescing parameters of rx queues.
+pub const VIRTIO_NET_CTRL_NOTF_COAL_RX_SET: u8 = 1;
 /// Support more than one virtqueue.
 pub const VIRTIO_BLK_F_MQ: u32 = 12;
 
@@ -238,6 +264,20 @@ pub const VIRTIO_BLK_T_GET_ID: u32 = 8;
 pub const VIRTIO_BLK_T_DISCARD: u32 = 11;
 /// Write zeroes command.
 pub const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+/// Zone append command.
+pub const VIRTIO_BLK_T_ZONE_APPEND: u32 = 15;
+/// Zone report command.
+pub const VIRTIO_BLK_T_ZONE_REPORT: u32 = 16;
+/// Zone open command.
+pub const VIRTIO_BLK_T_ZONE_OPEN: u32 = 18;
+/// Zone close command.
+pub const VIRTIO_BLK_T_ZONE_CLOSE: u32 = 20;
+/// Zone finish command.
+pub const VIRTIO_BLK_T_ZONE_FINISH: u32 = 22;
+/// Zone reset command.
+pub const VIRTIO_BLK_T_ZONE_RESET: u32 = 24;
+/// Zone reset all command.
+pub const VIRTIO_BLK_T_ZONE_RESET_ALL: u32 = 26;
 /// Device id length
 pub const VIRTIO_BLK_ID_BYTES: u32 = 20;
 /// Success
@@ -246,6 +286,14 @@ pub const VIRTIO_BLK_S_OK: u8 = 0;
 pub const VIRTIO_BLK_S_IOERR: u8 = 1;
 /// Unsupported.
 pub const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+/// Invalid zone command.
+pub const VIRTIO_BLK_S_ZONE_INVALID_CMD: u8 = 3;
+/// Write is not at the write pointer of the sequential zone.
+pub const VIRTIO_BLK_S_ZONE_UNALIGNED_WP: u8 = 4;
+/// Exceeding the limit of open zones.
+pub const VIRTIO_BLK_S_ZONE_OPEN_RESOURCE: u8 = 5;
+/// Exceeding the limit of active zones.
+pub const VIRTIO_BLK_S_ZONE_ACTIVE_RESOURCE: u8 = 6;
 
 /// The Type of virtio gpu, refer to Virtio Spec.
 /// 2D commands:
@@ -723,6 +771,36 @@ pub trait VirtioDevice: Send + AsAny {
         Ok(())
     }
 
+    /// Reset the device specific state set up by the driver, used when the driver writes
+    /// 0 to device status. Unlike `reset`, the backend must be kept, as the driver may
+    /// initialize the device again at once.
+    fn reset_driver_state(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Bring virtio device back to the state before driver initialization, used when the
+    /// driver writes 0 to device status. Queue handlers are stopped first if the device is
+    /// activated, then the common virtio state (queue configs, interrupt status, features)
+    /// and the device specific state of the driver are cleared.
+    fn driver_reset(&mut self) -> Result<()> {
+        if self.device_activated() {
+            self.deactivate()
+                .with_context(|| "Failed to deactivate virtio device")?;
+        }
+        self.virtio_base_mut().reset();
+        self.reset_driver_state()
+            .with_context(|| "Failed to reset driver state of virtio device")
+    }
+
+    /// Bring virtio device back to its initial state, used when the transport is reset,
+    /// e.g. on system reset or hot unplug. Besides the driver reset, the device specific
+    /// `reset` is done.
+    fn full_reset(&mut self) -> Result<()> {
+        self.driver_reset()?;
+        self.reset()
+            .with_context(|| "Failed to reset virtio device")
+    }
+
     /// Update the low level config of MMIO device,
     /// for example: update the images file fd of virtio block device.
     ///
@@ -747,6 +825,12 @@ pub trait VirtioDevice: Send + AsAny {
     fn has_control_queue(&self) -> bool {
         false
     }
+
+    /// Get whether the virtio device can be placed behind the IOMMU, in which case
+    /// it accesses guest memory by IO virtual addresses.
+    fn iommu_supported(&self) -> bool {
+        true
+    }
 }
 
 /// Check boundary for config space rw.
@@ -851,6 +935,27 @@ pub fn iov_discard_back(iovec: &mut [ElemIovec], mut size: u64) -> Option<&mut [
     None
 }
 
+/// Read iovec to buf, and return the iovec after the read bytes.
+pub fn get_buf_and_discard(
+    mem_space: &AddressSpace,
+    iovec: &mut [ElemIovec],
+    buf: &mut [u8],
+) -> Result<Vec<ElemIovec>> {
+    iov_to_buf(mem_space, iovec, buf).and_then(|size| {
+        if size < buf.len() {
+            error!("Invalid length {}, expected length {}", size, buf.len());
+            bail!("Invalid length {}, expected length {}", size, buf.len());
+        }
+        Ok(())
+    })?;
+
+    if let Some(data_iovec) = iov_discard_front(iovec, buf.len() as u64) {
+        Ok(data_iovec.to_vec())
+    } else {
+        Ok(Vec::new())
+    }
+}
+
 /// Convert GPA buffer iovec to HVA buffer iovec.
 /// If don't need the entire iovec, use iov_discard_front/iov_discard_back firstly.
 fn gpa_hva_iovec_map(
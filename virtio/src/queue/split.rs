@@ -51,6 +51,8 @@ const VRING_FLAGS_AND_IDX_LEN: u64 = size_of::<SplitVringFlagsIdx>() as u64;
 const VRING_IDX_POSITION: u64 = size_of::<u16>() as u64;
 /// The length of virtio descriptor.
 const DESCRIPTOR_LEN: u64 = size_of::<SplitVringDesc>() as u64;
+/// The alignment of the used ring in the vring laid out by the legacy interface.
+pub const VIRTIO_LEGACY_VRING_ALIGN: u64 = 4096;
 
 #[derive(Default, Clone, Copy)]
 pub struct VirtioAddrCache {
@@ -144,6 +146,24 @@ impl QueueConfig {
         *self = Self::new(self.max_size);
     }
 
+    /// Set the addresses of the vring which is laid out contiguously by the legacy interface:
+    /// the descriptor table starts at `addr`, followed by the available ring, and the used
+    /// ring starts at the next boundary of `align`.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Guest physical address of the vring.
+    /// * `align` - The alignment of the used ring, which must be power of 2.
+    pub fn set_legacy_addr(&mut self, addr: GuestAddress, align: u64) {
+        let size = u64::from(self.size);
+        self.desc_table = addr;
+        self.avail_ring = addr.unchecked_add(DESCRIPTOR_LEN * size);
+        let avail_end = self
+            .avail_ring
+            .unchecked_add(VRING_AVAIL_LEN_EXCEPT_AVAILELEM + AVAILELEM_LEN * size);
+        self.used_ring = GuestAddress((avail_end.raw_value() + align - 1) & !(align - 1));
+    }
+
     pub fn set_addr_cache(
         &mut self,
         mem_space: Arc<AddressSpace>,
@@ -1148,6 +1168,32 @@ mod tests {
         assert_eq!(queue.is_valid(&sys_space), false);
     }
 
+    #[test]
+    fn test_legacy_queue_layout() {
+        let sys_space = address_space_init();
+
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.set_legacy_addr(GuestAddress(0x1000), VIRTIO_LEGACY_VRING_ALIGN);
+        assert_eq!(queue_config.desc_table, GuestAddress(0x1000));
+        assert_eq!(
+            queue_config.avail_ring,
+            GuestAddress(0x1000 + (QUEUE_SIZE as u64) * DESCRIPTOR_LEN)
+        );
+        assert_eq!(
+            queue_config.used_ring,
+            GuestAddress(align(
+                0x1000
+                    + (QUEUE_SIZE as u64) * DESCRIPTOR_LEN
+                    + VRING_AVAIL_LEN_EXCEPT_AVAILELEM
+                    + AVAILELEM_LEN * (QUEUE_SIZE as u64),
+                VIRTIO_LEGACY_VRING_ALIGN,
+            ))
+        );
+        queue_config.ready = true;
+        let queue = Queue::new(queue_config, QUEUE_TYPE_SPLIT_VRING).unwrap();
+        assert_eq!(queue.is_valid(&sys_space), true);
+    }
+
     #[test]
     fn test_valid_queue_02() {
         let sys_space = address_space_init();
@@ -144,6 +144,7 @@ impl VirtioMmioDevice {
                 dev_type: SysBusDevType::VirtioMmio,
                 res: SysRes::default(),
                 interrupt_evt: Some(Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap())),
+                interrupt_counter: None,
             },
             device,
             host_notify_info: HostNotifyInfo::new(queue_num),
@@ -159,7 +160,6 @@ impl VirtioMmioDevice {
         region_size: u64,
         #[cfg(target_arch = "x86_64")] bs: &Arc<Mutex<BootSource>>,
     ) -> Result<Arc<Mutex<Self>>> {
-        self.assign_interrupt_cb();
         self.device
             .lock()
             .unwrap()
@@ -170,6 +170,8 @@ impl VirtioMmioDevice {
             bail!("Mmio region space exhausted.");
         }
         self.set_sys_resource(sysbus, region_base, region_size)?;
+        // The interrupt counter is registered after the irq is allocated.
+        self.assign_interrupt_cb();
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size, "VirtioMmio")?;
 
@@ -186,6 +188,33 @@ impl VirtioMmioDevice {
         Ok(dev)
     }
 
+    /// Set the id of the device, and whether it can be hot-unplugged.
+    pub fn set_id(&mut self, id: &str, hotpluggable: bool) {
+        self.base.base = DeviceBase::new(id.to_string(), hotpluggable);
+    }
+
+    /// Unrealize the device before it is detached from system bus. The guest driver is
+    /// notified by a config interrupt with `NEEDS_RESET` set in device status, which makes
+    /// it unbind from the device, then the device is reset and unrealized.
+    pub fn unrealize(&mut self) -> Result<()> {
+        if !self.hotpluggable() {
+            bail!("Device {} does not support hot-unplug", self.name());
+        }
+
+        if let Some(cb) = self.interrupt_cb.as_ref() {
+            cb(&VirtioInterruptType::Config, None, true)
+                .with_context(|| "Failed to notify the guest of device removal")?;
+        }
+
+        let mut locked_dev = self.device.lock().unwrap();
+        locked_dev
+            .full_reset()
+            .with_context(|| "Failed to reset the virtio device")?;
+        locked_dev
+            .unrealize()
+            .with_context(|| "Failed to unrealize the virtio device")
+    }
+
     /// Activate the virtio device, this function is called by vcpu thread when frontend
     /// virtio driver is ready and write `DRIVER_OK` to backend.
     fn activate(&mut self) -> Result<()> {
@@ -233,6 +262,7 @@ impl VirtioMmioDevice {
 
     fn assign_interrupt_cb(&mut self) {
         let interrupt_evt = self.base.interrupt_evt.clone();
+        let interrupt_counter = self.base.interrupt_counter.clone();
         let locked_dev = self.device.lock().unwrap();
         let virtio_base = locked_dev.virtio_base();
         let device_status = virtio_base.device_status.clone();
@@ -261,6 +291,9 @@ impl VirtioMmioDevice {
                 interrupt
                     .write(1)
                     .with_context(|| VirtioError::EventFdWrite)?;
+                if let Some(counter) = interrupt_counter.as_ref() {
+                    counter.inc();
+                }
 
                 Ok(())
             },
@@ -355,7 +388,15 @@ impl VirtioMmioDevice {
                     isr.fetch_and(!value, Ordering::SeqCst);
                 }
             }
-            STATUS_REG => locked_device.set_device_status(value),
+            STATUS_REG => {
+                // Writing 0 to device status requests a device reset, the queue
+                // configuration and interrupt status must be cleared as well, otherwise
+                // the reloaded driver would see the stale state.
+                if value == 0 && locked_device.device_status() != 0 {
+                    locked_device.driver_reset()?;
+                }
+                locked_device.set_device_status(value);
+            }
             QUEUE_DESC_LOW_REG => locked_device.queue_config_mut(true).map(|config| {
                 config.desc_table = GuestAddress(config.desc_table.0 | u64::from(value));
             })?,
@@ -638,6 +679,7 @@ mod tests {
         pub config_space: Vec<u8>,
         pub b_active: bool,
         pub b_realized: bool,
+        pub b_reset: bool,
     }
 
     impl VirtioDeviceTest {
@@ -651,6 +693,7 @@ mod tests {
                 base: VirtioBase::new(VIRTIO_TYPE_BLOCK, QUEUE_NUM, QUEUE_SIZE),
                 b_active: false,
                 b_realized: false,
+                b_reset: false,
                 config_space,
             }
         }
@@ -671,6 +714,11 @@ mod tests {
             Ok(())
         }
 
+        fn unrealize(&mut self) -> Result<()> {
+            self.b_realized = false;
+            Ok(())
+        }
+
         fn init_config_features(&mut self) -> Result<()> {
             Ok(())
         }
@@ -696,6 +744,16 @@ mod tests {
             self.b_active = true;
             Ok(())
         }
+
+        fn deactivate(&mut self) -> Result<()> {
+            self.b_active = false;
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.b_reset = true;
+            Ok(())
+        }
     }
 
     #[test]
@@ -1294,4 +1352,93 @@ mod tests {
                 | CONFIG_STATUS_FEATURES_OK
         );
     }
+
+    #[test]
+    fn test_virtio_mmio_device_reset() {
+        let virtio_device = Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let sys_space = address_space_init();
+        let mut virtio_mmio_device = VirtioMmioDevice::new(&sys_space, virtio_device.clone());
+        let addr = GuestAddress(0);
+
+        virtio_mmio_device.assign_interrupt_cb();
+        let mut locked_device = virtio_device.lock().unwrap();
+        locked_device.set_queue_select(0);
+        if let Ok(config) = locked_device.queue_config_mut(true) {
+            config.desc_table = GuestAddress(0);
+            config.avail_ring = GuestAddress((QUEUE_SIZE as u64) * 16);
+            config.used_ring = GuestAddress(align(
+                (QUEUE_SIZE as u64) * 16 + 8 + 2 * (QUEUE_SIZE as u64),
+                4096,
+            ));
+            config.size = QUEUE_SIZE;
+            config.ready = true;
+        }
+        drop(locked_device);
+
+        let mut buf: Vec<u8> = vec![0xff, 0xff, 0xff, 0xff];
+        LittleEndian::write_u32(
+            &mut buf[..],
+            CONFIG_STATUS_ACKNOWLEDGE
+                | CONFIG_STATUS_DRIVER
+                | CONFIG_STATUS_DRIVER_OK
+                | CONFIG_STATUS_FEATURES_OK,
+        );
+        assert!(virtio_mmio_device.write(&buf[..], addr, STATUS_REG));
+        assert!(virtio_device.lock().unwrap().b_active);
+        virtio_device
+            .lock()
+            .unwrap()
+            .set_interrupt_status(VIRTIO_MMIO_INT_VRING);
+
+        // Writing 0 to device status resets the device.
+        LittleEndian::write_u32(&mut buf[..], 0);
+        assert!(virtio_mmio_device.write(&buf[..], addr, STATUS_REG));
+        let locked_device = virtio_device.lock().unwrap();
+        assert!(!locked_device.b_active);
+        // The backend is kept for the driver to initialize the device again.
+        assert!(!locked_device.b_reset);
+        assert!(!locked_device.device_activated());
+        assert_eq!(locked_device.device_status(), 0);
+        assert_eq!(locked_device.interrupt_status(), 0);
+        assert!(locked_device.virtio_base().queues.is_empty());
+        let config = &locked_device.virtio_base().queues_config[0];
+        assert!(!config.ready);
+        assert_eq!(config.avail_ring, GuestAddress(0));
+    }
+
+    #[test]
+    fn test_virtio_mmio_device_unplug() {
+        let virtio_device = Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let sys_space = address_space_init();
+        let mut virtio_mmio_device = VirtioMmioDevice::new(&sys_space, virtio_device.clone());
+        let addr = GuestAddress(0);
+
+        virtio_device.lock().unwrap().realize().unwrap();
+        virtio_mmio_device.assign_interrupt_cb();
+        let mut buf: Vec<u8> = vec![0xff, 0xff, 0xff, 0xff];
+        LittleEndian::write_u32(
+            &mut buf[..],
+            CONFIG_STATUS_ACKNOWLEDGE
+                | CONFIG_STATUS_DRIVER
+                | CONFIG_STATUS_DRIVER_OK
+                | CONFIG_STATUS_FEATURES_OK,
+        );
+        assert!(virtio_mmio_device.write(&buf[..], addr, STATUS_REG));
+        assert!(virtio_device.lock().unwrap().b_active);
+
+        // The device is not hotpluggable by default.
+        assert!(virtio_mmio_device.unrealize().is_err());
+        assert!(virtio_device.lock().unwrap().b_active);
+
+        virtio_mmio_device.set_id("mmio-0", true);
+        virtio_mmio_device.unrealize().unwrap();
+        // The guest driver is notified by a config interrupt.
+        let interrupt_evt = virtio_mmio_device.base.interrupt_evt.as_ref().unwrap();
+        assert_eq!(interrupt_evt.read().unwrap(), 1);
+        let locked_device = virtio_device.lock().unwrap();
+        assert!(!locked_device.b_active);
+        assert!(!locked_device.b_realized);
+        assert!(!locked_device.device_activated());
+        assert_eq!(locked_device.device_status(), 0);
+    }
 }
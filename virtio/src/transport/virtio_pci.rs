@@ -11,6 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::cmp::{max, min};
+use std::fs;
 use std::mem::size_of;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex, Weak};
@@ -18,27 +19,30 @@ use std::sync::{Arc, Mutex, Weak};
 use anyhow::{anyhow, bail, Context};
 use byteorder::{ByteOrder, LittleEndian};
 use log::{debug, error, warn};
-use machine_manager::config::M;
+use machine_manager::config::{VirtioPciMode, M};
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::{
-    virtio_has_feature, NotifyEventFds, Queue, VirtioBaseState, VirtioDevice, VirtioDeviceQuirk,
-    VirtioInterrupt, VirtioInterruptType,
+    virtio_has_feature, Iommu, NotifyEventFds, Queue, VirtioBaseState, VirtioDevice,
+    VirtioDeviceQuirk, VirtioInterrupt, VirtioInterruptType,
 };
 use crate::{
     CONFIG_STATUS_ACKNOWLEDGE, CONFIG_STATUS_DRIVER, CONFIG_STATUS_DRIVER_OK, CONFIG_STATUS_FAILED,
     CONFIG_STATUS_FEATURES_OK, CONFIG_STATUS_NEEDS_RESET, INVALID_VECTOR_NUM,
-    QUEUE_TYPE_PACKED_VRING, QUEUE_TYPE_SPLIT_VRING, VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1,
-    VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING, VIRTIO_TYPE_BLOCK, VIRTIO_TYPE_CONSOLE,
-    VIRTIO_TYPE_FS, VIRTIO_TYPE_GPU, VIRTIO_TYPE_NET, VIRTIO_TYPE_SCSI,
+    QUEUE_TYPE_PACKED_VRING, QUEUE_TYPE_SPLIT_VRING, VIRTIO_F_ACCESS_PLATFORM,
+    VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_LEGACY_VRING_ALIGN, VIRTIO_MMIO_INT_CONFIG,
+    VIRTIO_MMIO_INT_VRING, VIRTIO_TYPE_9P, VIRTIO_TYPE_BALLOON, VIRTIO_TYPE_BLOCK,
+    VIRTIO_TYPE_CONSOLE, VIRTIO_TYPE_FS, VIRTIO_TYPE_GPU, VIRTIO_TYPE_IOMMU, VIRTIO_TYPE_NET,
+    VIRTIO_TYPE_RNG, VIRTIO_TYPE_SCSI, VIRTIO_TYPE_SOUND,
 };
 use address_space::{
     AddressRange, AddressSpace, GuestAddress, HostMemMapping, Region, RegionIoEventFd, RegionOps,
 };
 use devices::pci::config::{
-    RegionType, BAR_SPACE_UNMAPPED, DEVICE_ID, MINIMUM_BAR_SIZE_FOR_MMIO, PCIE_CONFIG_SPACE_SIZE,
-    PCI_SUBDEVICE_ID_QEMU, PCI_VENDOR_ID_REDHAT_QUMRANET, REG_SIZE, REVISION_ID, STATUS,
-    STATUS_INTERRUPT, SUBSYSTEM_ID, SUBSYSTEM_VENDOR_ID, SUB_CLASS_CODE, VENDOR_ID,
+    RegionType, BAR_SPACE_UNMAPPED, DEVICE_ID, MINIMUM_BAR_SIZE_FOR_MMIO, MINIMUM_ROM_SIZE,
+    PCIE_CONFIG_SPACE_SIZE, PCI_SUBDEVICE_ID_QEMU, PCI_VENDOR_ID_REDHAT_QUMRANET, REG_SIZE,
+    REVISION_ID, STATUS, STATUS_INTERRUPT, SUBSYSTEM_ID, SUBSYSTEM_VENDOR_ID, SUB_CLASS_CODE,
+    VENDOR_ID,
 };
 use devices::pci::msix::{update_dev_id, MsixState};
 use devices::pci::{
@@ -58,6 +62,7 @@ const VIRTIO_QUEUE_MAX: u32 = 1024;
 const VIRTIO_PCI_VENDOR_ID: u16 = PCI_VENDOR_ID_REDHAT_QUMRANET;
 const VIRTIO_PCI_DEVICE_ID_BASE: u16 = 0x1040;
 const VIRTIO_PCI_ABI_VERSION: u8 = 1;
+const VIRTIO_PCI_LEGACY_ABI_VERSION: u8 = 0;
 const VIRTIO_PCI_CLASS_ID_NET: u16 = 0x0280;
 const VIRTIO_PCI_CLASS_ID_BLOCK: u16 = 0x0100;
 const VIRTIO_PCI_CLASS_ID_STORAGE_OTHER: u16 = 0x0180;
@@ -65,6 +70,7 @@ const VIRTIO_PCI_CLASS_ID_COMMUNICATION_OTHER: u16 = 0x0780;
 #[cfg(target_arch = "aarch64")]
 const VIRTIO_PCI_CLASS_ID_DISPLAY_OTHER: u16 = 0x0380;
 const VIRTIO_PCI_CLASS_ID_DISPLAY_VGA: u16 = 0x0300;
+const VIRTIO_PCI_CLASS_ID_MULTIMEDIA_AUDIO: u16 = 0x0401;
 const VIRTIO_PCI_CLASS_ID_OTHERS: u16 = 0x00ff;
 
 const VIRTIO_PCI_CAP_COMMON_OFFSET: u32 = 0x0;
@@ -78,9 +84,17 @@ const VIRTIO_PCI_CAP_NOTIFY_LENGTH: u32 = 0x1000;
 const VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER: u32 = 4;
 
 const VIRTIO_PCI_BAR_MAX: u8 = 3;
+const VIRTIO_PCI_LEGACY_BAR_IDX: u8 = 0;
 const VIRTIO_PCI_MSIX_BAR_IDX: u8 = 1;
 const VIRTIO_PCI_MEM_BAR_IDX: u8 = 2;
 
+/// The size of the I/O bar of the legacy interface, which holds the legacy registers and
+/// the device-specific configuration.
+const VIRTIO_PCI_LEGACY_BAR_SIZE: u64 = 0x100;
+/// The guest physical address of vring is written to the legacy interface in the page
+/// frame number of 4K pages.
+const VIRTIO_PCI_QUEUE_ADDR_SHIFT: u32 = 12;
+
 const PCI_CAP_VNDR_AND_NEXT_SIZE: u8 = 2;
 const PCI_CAP_ID_VNDR: u8 = 0x9;
 
@@ -123,6 +137,31 @@ const COMMON_Q_USEDLO_REG: u64 = 0x30;
 /// The high 32bit of queue's Used Ring address - Read Write.
 const COMMON_Q_USEDHI_REG: u64 = 0x34;
 
+/// Bitmask of the features supported by the device(host) (bits 0 to 31) - Read Only.
+const LEGACY_HOST_FEATURES_REG: u64 = 0x0;
+/// Bitmask of features activated by the driver (guest) (bits 0 to 31) - Read Write.
+const LEGACY_GUEST_FEATURES_REG: u64 = 0x4;
+/// The page frame number of the currently selected queue - Read Write.
+const LEGACY_QUEUE_PFN_REG: u64 = 0x8;
+/// The size for the currently selected queue - Read Only.
+const LEGACY_QUEUE_NUM_REG: u64 = 0xc;
+/// Queue selector - Read Write.
+const LEGACY_QUEUE_SEL_REG: u64 = 0xe;
+/// Queue notifier - Write Only.
+const LEGACY_QUEUE_NOTIFY_REG: u64 = 0x10;
+/// Device status - Read Write.
+const LEGACY_STATUS_REG: u64 = 0x12;
+/// Interrupt status, cleared by reading - Read Only.
+const LEGACY_ISR_REG: u64 = 0x13;
+/// The configuration vector for MSI-X, only exists if MSI-X is enabled - Read Write.
+const LEGACY_MSIX_CONFIG_REG: u64 = 0x14;
+/// The queue vector for MSI-X, only exists if MSI-X is enabled - Read Write.
+const LEGACY_MSIX_QUEUE_REG: u64 = 0x16;
+/// The offset of the device-specific configuration if MSI-X is disabled.
+const LEGACY_CONFIG_OFFSET: u64 = 0x14;
+/// The offset of the device-specific configuration if MSI-X is enabled.
+const LEGACY_CONFIG_OFFSET_MSIX: u64 = 0x18;
+
 /// The max features select num, only 0 or 1 is valid:
 ///   0: select feature bits 0 to 31.
 ///   1: select feature bits 32 to 63.
@@ -154,6 +193,35 @@ fn init_gpu_bar0(config: &mut PciConfig) -> PciResult<()> {
     Ok(())
 }
 
+/// Load the option ROM file and expose it through the expansion ROM BAR.
+fn init_rom_bar(config: &mut PciConfig, romfile: &str) -> PciResult<()> {
+    let rom = fs::read(romfile).with_context(|| format!("Failed to read romfile {}", romfile))?;
+    if rom.is_empty() {
+        bail!("Romfile {} is empty", romfile);
+    }
+    let size = max(rom.len() as u64, MINIMUM_ROM_SIZE).next_power_of_two();
+
+    let rom = Arc::new(rom);
+    let read_ops = move |data: &mut [u8], _addr: GuestAddress, offset: u64| -> bool {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = *rom.get(offset as usize + i).unwrap_or(&0);
+        }
+        true
+    };
+    let write_ops = move |_data: &[u8], _addr: GuestAddress, offset: u64| -> bool {
+        warn!("Write to read-only option ROM at offset 0x{:x}", offset);
+        true
+    };
+    let rom_ops = RegionOps {
+        read: Arc::new(read_ops),
+        write: Arc::new(write_ops),
+    };
+    let region = Region::init_io_region(size, rom_ops, "VirtioPciRom");
+    config.register_rom(region, size)?;
+
+    Ok(())
+}
+
 /// Get class id according to device type.
 ///
 /// # Arguments
@@ -167,6 +235,8 @@ fn get_virtio_class_id(device_type: u32, _device_quirk: Option<VirtioDeviceQuirk
         VIRTIO_TYPE_FS => VIRTIO_PCI_CLASS_ID_STORAGE_OTHER,
         VIRTIO_TYPE_NET => VIRTIO_PCI_CLASS_ID_NET,
         VIRTIO_TYPE_CONSOLE => VIRTIO_PCI_CLASS_ID_COMMUNICATION_OTHER,
+        VIRTIO_TYPE_SOUND => VIRTIO_PCI_CLASS_ID_MULTIMEDIA_AUDIO,
+        VIRTIO_TYPE_IOMMU => VIRTIO_PCI_CLASS_ID_OTHERS,
         #[cfg(target_arch = "x86_64")]
         VIRTIO_TYPE_GPU => VIRTIO_PCI_CLASS_ID_DISPLAY_VGA,
         #[cfg(target_arch = "aarch64")]
@@ -183,6 +253,20 @@ fn get_virtio_class_id(device_type: u32, _device_quirk: Option<VirtioDeviceQuirk
     }
 }
 
+/// Get the PCI device id of the transitional device, which is known by the legacy drivers.
+fn get_virtio_legacy_device_id(device_type: u32) -> Option<u16> {
+    match device_type {
+        VIRTIO_TYPE_NET => Some(0x1000),
+        VIRTIO_TYPE_BLOCK => Some(0x1001),
+        VIRTIO_TYPE_BALLOON => Some(0x1002),
+        VIRTIO_TYPE_CONSOLE => Some(0x1003),
+        VIRTIO_TYPE_SCSI => Some(0x1004),
+        VIRTIO_TYPE_RNG => Some(0x1005),
+        VIRTIO_TYPE_9P => Some(0x1009),
+        _ => None,
+    }
+}
+
 #[allow(clippy::upper_case_acronyms)]
 #[repr(u8)]
 enum VirtioPciCapType {
@@ -301,6 +385,8 @@ pub struct VirtioPciDevice {
     dev_id: Arc<AtomicU16>,
     /// Memory AddressSpace
     sys_mem: Arc<AddressSpace>,
+    /// AddressSpace for DMA if the device is behind the virtio iommu.
+    dma_mem: Option<Arc<AddressSpace>>,
     /// Offset of VirtioPciCfgAccessCap in Pci config space.
     cfg_cap_offset: usize,
     /// Eventfds used for guest notify the Device.
@@ -311,6 +397,10 @@ pub struct VirtioPciDevice {
     multi_func: bool,
     /// If the device need to register irqfd to kvm.
     need_irqfd: bool,
+    /// Option ROM file exposed through the expansion ROM BAR.
+    romfile: Option<String>,
+    /// The interfaces exposed to the driver.
+    mode: VirtioPciMode,
 }
 
 impl VirtioPciDevice {
@@ -333,11 +423,14 @@ impl VirtioPciDevice {
             device,
             dev_id: Arc::new(AtomicU16::new(0)),
             sys_mem,
+            dma_mem: None,
             cfg_cap_offset: 0,
             notify_eventfds: Arc::new(NotifyEventFds::new(queue_num)),
             interrupt_cb: None,
             multi_func,
             need_irqfd: false,
+            romfile: None,
+            mode: VirtioPciMode::Modern,
         }
     }
 
@@ -345,6 +438,41 @@ impl VirtioPciDevice {
         self.need_irqfd = true;
     }
 
+    pub fn set_romfile(&mut self, romfile: Option<String>) {
+        self.romfile = romfile;
+    }
+
+    pub fn set_mode(&mut self, mode: VirtioPciMode) {
+        self.mode = mode;
+    }
+
+    /// Put the device behind the virtio iommu. The device must be on the root bus, whose
+    /// bus number is 0, so the endpoint ID is the devfn. The DMA addresses are translated
+    /// by the iommu if VIRTIO_F_ACCESS_PLATFORM is negotiated.
+    pub fn set_iommu(&mut self, iommu: &Arc<Mutex<Iommu>>) {
+        if !self.device.lock().unwrap().iommu_supported() {
+            return;
+        }
+        let endpoint = iommu
+            .lock()
+            .unwrap()
+            .add_endpoint(u32::from(self.base.devfn));
+        self.dma_mem = Some(
+            self.sys_mem
+                .new_iommu_view(endpoint, &format!("{}-iommu", self.name())),
+        );
+    }
+
+    /// Get the AddressSpace for DMA according to the negotiated features.
+    fn dma_space(&self, driver_features: u64) -> Arc<AddressSpace> {
+        match self.dma_mem.as_ref() {
+            Some(dma_mem) if virtio_has_feature(driver_features, VIRTIO_F_ACCESS_PLATFORM) => {
+                dma_mem.clone()
+            }
+            _ => self.sys_mem.clone(),
+        }
+    }
+
     fn assign_interrupt_cb(&mut self) {
         let locked_dev = self.device.lock().unwrap();
         let virtio_base = locked_dev.virtio_base();
@@ -413,6 +541,21 @@ impl VirtioPciDevice {
         ret
     }
 
+    fn legacy_ioeventfds(&self) -> Vec<RegionIoEventFd> {
+        let mut ret = Vec::new();
+        let eventfds = (*self.notify_eventfds).clone();
+        for (index, eventfd) in eventfds.events.into_iter().enumerate() {
+            ret.push(RegionIoEventFd {
+                fd: eventfd,
+                addr_range: AddressRange::from((LEGACY_QUEUE_NOTIFY_REG, 2u64)),
+                data_match: true,
+                data: index as u64,
+            })
+        }
+
+        ret
+    }
+
     fn modern_mem_region_map<T: ByteCode>(&mut self, data: T) -> PciResult<usize> {
         let cap_offset = self.base.config.add_pci_cap(
             PCI_CAP_ID_VNDR,
@@ -435,6 +578,7 @@ impl VirtioPciDevice {
         let queue_type = locked_dev.queue_type();
         let features = locked_dev.virtio_base().driver_features;
         let broken = locked_dev.virtio_base().broken.clone();
+        let mem_space = self.dma_space(features);
 
         let mut queues = Vec::new();
         let queues_config = &mut locked_dev.virtio_base_mut().queues_config;
@@ -443,14 +587,14 @@ impl VirtioPciDevice {
                 debug!("queue is not ready, please check your init process");
             } else {
                 q_config.set_addr_cache(
-                    self.sys_mem.clone(),
+                    mem_space.clone(),
                     self.interrupt_cb.clone().unwrap(),
                     features,
                     &broken,
                 );
             }
             let queue = Queue::new(*q_config, queue_type).unwrap();
-            if q_config.ready && !queue.is_valid(&self.sys_mem) {
+            if q_config.ready && !queue.is_valid(&mem_space) {
                 error!("Failed to activate device: Invalid queue");
                 return false;
             }
@@ -481,11 +625,9 @@ impl VirtioPciDevice {
         }
 
         let queue_evts = (*self.notify_eventfds).clone().events;
-        if let Err(e) = locked_dev.activate(
-            self.sys_mem.clone(),
-            self.interrupt_cb.clone().unwrap(),
-            queue_evts,
-        ) {
+        if let Err(e) =
+            locked_dev.activate(mem_space, self.interrupt_cb.clone().unwrap(), queue_evts)
+        {
             error!("Failed to activate device, error is {:?}", e);
             return false;
         }
@@ -494,21 +636,19 @@ impl VirtioPciDevice {
         true
     }
 
-    fn deactivate_device(&self) -> bool {
+    /// Reset the virtio device for the driver, and the interrupt state of the transport.
+    fn deactivate_device(&self) -> PciResult<()> {
         if self.need_irqfd && self.base.config.msix.is_some() {
             let msix = self.base.config.msix.as_ref().unwrap();
-            if msix.lock().unwrap().unregister_irqfd().is_err() {
-                return false;
-            }
+            msix.lock()
+                .unwrap()
+                .unregister_irqfd()
+                .with_context(|| "Failed to unregister irqfd")?;
         }
 
-        let mut locked_dev = self.device.lock().unwrap();
-        if locked_dev.device_activated() {
-            if let Err(e) = locked_dev.deactivate() {
-                error!("Failed to deactivate virtio device, error is {:?}", e);
-                return false;
-            }
-            locked_dev.virtio_base_mut().reset();
+        self.device.lock().unwrap().driver_reset()?;
+        if let Some(iommu) = self.dma_mem.as_ref().and_then(|dma_mem| dma_mem.iommu()) {
+            iommu.clear_notifiers();
         }
 
         if let Some(intx) = &self.base.config.intx {
@@ -518,7 +658,7 @@ impl VirtioPciDevice {
             msix.lock().unwrap().clear_pending_vectors();
         }
 
-        true
+        Ok(())
     }
 
     /// Read data from the common config of virtio device.
@@ -662,7 +802,9 @@ impl VirtioPciDevice {
                     self.activate_device();
                 } else if old_status != 0 && locked_device.device_status() == 0 {
                     drop(locked_device);
-                    self.deactivate_device();
+                    if let Err(e) = self.deactivate_device() {
+                        error!("Failed to reset virtio device, error is {:?}", e);
+                    }
                 }
             }
             COMMON_Q_SELECT_REG => {
@@ -721,6 +863,207 @@ impl VirtioPciDevice {
         Ok(())
     }
 
+    /// The device-specific configuration follows the legacy registers, whose offset
+    /// depends on whether MSI-X is enabled.
+    fn legacy_config_offset(&self) -> u64 {
+        let msix_enabled = self
+            .base
+            .config
+            .msix
+            .as_ref()
+            .map_or(false, |msix| msix.lock().unwrap().enabled);
+        if msix_enabled {
+            LEGACY_CONFIG_OFFSET_MSIX
+        } else {
+            LEGACY_CONFIG_OFFSET
+        }
+    }
+
+    /// Read data from the registers of the legacy interface.
+    /// Return the register value in u32.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the register in the legacy I/O bar.
+    fn read_legacy_config(&self, offset: u64) -> u32 {
+        let locked_device = self.device.lock().unwrap();
+        match offset {
+            LEGACY_HOST_FEATURES_REG => locked_device.device_features(0),
+            LEGACY_GUEST_FEATURES_REG => locked_device.driver_features(0),
+            LEGACY_QUEUE_PFN_REG => locked_device.queue_config().map_or(0, |config| {
+                (config.desc_table.0 >> VIRTIO_PCI_QUEUE_ADDR_SHIFT) as u32
+            }),
+            // The legacy driver finds out the number of queues by the queue of size 0.
+            LEGACY_QUEUE_NUM_REG => locked_device
+                .queue_config()
+                .map_or(0, |config| u32::from(config.max_size)),
+            LEGACY_QUEUE_SEL_REG => locked_device.queue_select() as u32,
+            LEGACY_STATUS_REG => locked_device.device_status(),
+            LEGACY_ISR_REG => {
+                let isr = locked_device
+                    .virtio_base()
+                    .interrupt_status
+                    .swap(0, Ordering::SeqCst);
+                if let Some(intx) = &self.base.config.intx {
+                    intx.lock().unwrap().notify(0);
+                }
+                isr
+            }
+            LEGACY_MSIX_CONFIG_REG => locked_device.config_vector() as u32,
+            LEGACY_MSIX_QUEUE_REG => locked_device
+                .queue_config()
+                .map_or(u32::from(INVALID_VECTOR_NUM), |config| {
+                    u32::from(config.vector)
+                }),
+            _ => 0,
+        }
+    }
+
+    /// Write data to the registers of the legacy interface.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the register in the legacy I/O bar.
+    /// * `value` - The value to write.
+    ///
+    /// # Errors
+    ///
+    /// Returns Error if the offset is out of bound.
+    fn write_legacy_config(&mut self, offset: u64, value: u32) -> PciResult<()> {
+        let mut locked_device = self.device.lock().unwrap();
+        match offset {
+            LEGACY_GUEST_FEATURES_REG => {
+                if locked_device.device_status() & CONFIG_STATUS_DRIVER_OK != 0 {
+                    error!("it's not allowed to set features after the driver is ready");
+                    return Ok(());
+                }
+                // The legacy interface only negotiates the feature bits 0 to 31, and the
+                // packed virtqueue is not supported.
+                locked_device.set_driver_features(0, value);
+                locked_device.set_queue_type(QUEUE_TYPE_SPLIT_VRING);
+            }
+            LEGACY_QUEUE_PFN_REG => {
+                let config = locked_device.queue_config_mut(false)?;
+                if value == 0 {
+                    config.reset();
+                } else {
+                    // The size of vring is fixed to the max size in the legacy interface.
+                    config.size = config.max_size;
+                    config.set_legacy_addr(
+                        GuestAddress(u64::from(value) << VIRTIO_PCI_QUEUE_ADDR_SHIFT),
+                        VIRTIO_LEGACY_VRING_ALIGN,
+                    );
+                    config.ready = true;
+                }
+            }
+            LEGACY_QUEUE_SEL_REG => {
+                if value < VIRTIO_QUEUE_MAX {
+                    locked_device.set_queue_select(value as u16);
+                }
+            }
+            LEGACY_QUEUE_NOTIFY_REG => {
+                // The notification is delivered by ioeventfd generally, and it's handled here
+                // only if the ioeventfd is not registered.
+                if let Some(evt) = self.notify_eventfds.events.get(value as usize) {
+                    evt.write(1)
+                        .with_context(|| format!("Failed to notify queue {}", value))?;
+                }
+            }
+            LEGACY_STATUS_REG => {
+                let old_status = locked_device.device_status();
+                locked_device.set_device_status(value);
+                // There is no FEATURES_OK in the legacy interface, the features are
+                // negotiated once the driver is ready.
+                if value == 0 {
+                    drop(locked_device);
+                    if old_status != 0 {
+                        if let Err(e) = self.deactivate_device() {
+                            error!("Failed to reset virtio device, error is {:?}", e);
+                        }
+                    }
+                } else if locked_device.check_device_status(
+                    CONFIG_STATUS_ACKNOWLEDGE | CONFIG_STATUS_DRIVER | CONFIG_STATUS_DRIVER_OK,
+                    CONFIG_STATUS_FAILED,
+                ) {
+                    drop(locked_device);
+                    self.activate_device();
+                }
+            }
+            LEGACY_MSIX_CONFIG_REG => {
+                if self.base.config.revise_msix_vector(value) {
+                    locked_device.set_config_vector(value as u16);
+                } else {
+                    locked_device.set_config_vector(INVALID_VECTOR_NUM);
+                }
+                locked_device.set_interrupt_status(0);
+            }
+            LEGACY_MSIX_QUEUE_REG => {
+                let val = if self.base.config.revise_msix_vector(value) {
+                    value as u16
+                } else {
+                    INVALID_VECTOR_NUM
+                };
+                locked_device
+                    .queue_config_mut(false)
+                    .map(|config| config.vector = val)?;
+            }
+            _ => {
+                return Err(anyhow!(PciError::PciRegister(offset)));
+            }
+        };
+
+        Ok(())
+    }
+
+    fn build_legacy_cfg_ops(virtio_pci: Arc<Mutex<VirtioPciDevice>>) -> RegionOps {
+        let cloned_virtio_pci = virtio_pci.clone();
+        let legacy_read = move |data: &mut [u8], _addr: GuestAddress, offset: u64| -> bool {
+            let locked_pci = cloned_virtio_pci.lock().unwrap();
+            let config_offset = locked_pci.legacy_config_offset();
+            if offset >= config_offset {
+                let locked_device = locked_pci.device.lock().unwrap();
+                if let Err(e) = locked_device.read_config(offset - config_offset, data) {
+                    error!("Failed to read virtio-dev config space, error is {:?}", e);
+                    return false;
+                }
+                return true;
+            }
+
+            write_data_u32(data, locked_pci.read_legacy_config(offset))
+        };
+
+        let legacy_write = move |data: &[u8], _addr: GuestAddress, offset: u64| -> bool {
+            let mut locked_pci = virtio_pci.lock().unwrap();
+            let config_offset = locked_pci.legacy_config_offset();
+            if offset >= config_offset {
+                let mut locked_device = locked_pci.device.lock().unwrap();
+                if let Err(e) = locked_device.write_config(offset - config_offset, data) {
+                    error!("Failed to write virtio-dev config space, error is {:?}", e);
+                    return false;
+                }
+                return true;
+            }
+
+            let mut value = 0;
+            if !read_data_u32(data, &mut value) {
+                return false;
+            }
+            if let Err(e) = locked_pci.write_legacy_config(offset, value) {
+                error!(
+                    "Failed to write legacy config of virtio-pci device, error is {:?}",
+                    e,
+                );
+                return false;
+            }
+            true
+        };
+
+        RegionOps {
+            read: Arc::new(legacy_read),
+            write: Arc::new(legacy_write),
+        }
+    }
+
     fn build_common_cfg_ops(virtio_pci: Arc<Mutex<VirtioPciDevice>>) -> RegionOps {
         let cloned_virtio_pci = virtio_pci.clone();
         let common_read = move |data: &mut [u8], _addr: GuestAddress, offset: u64| -> bool {
@@ -861,6 +1204,10 @@ impl VirtioPciDevice {
 
     // Access virtio configuration through VirtioPciCfgAccessCap.
     fn do_cfg_access(&mut self, start: usize, end: usize, is_write: bool) {
+        // VirtioPciCfgAccessCap doesn't exist if the modern interface is disabled.
+        if self.cfg_cap_offset == 0 {
+            return;
+        }
         let pci_cfg_data_offset =
             self.cfg_cap_offset + offset_of!(VirtioPciCfgAccessCap, pci_cfg_data);
         let cap_size = size_of::<VirtioPciCfgAccessCap>();
@@ -994,12 +1341,23 @@ impl PciDevOps for VirtioPciDevice {
             VENDOR_ID as usize,
             VIRTIO_PCI_VENDOR_ID,
         )?;
-        le_write_u16(
-            &mut self.base.config.config,
-            DEVICE_ID as usize,
-            VIRTIO_PCI_DEVICE_ID_BASE + device_type as u16,
-        )?;
-        self.base.config.config[REVISION_ID] = VIRTIO_PCI_ABI_VERSION;
+        // The transitional device is exposed with the device id known by legacy drivers.
+        let (device_id, revision_id) = if self.mode.legacy_enabled() {
+            let device_id = get_virtio_legacy_device_id(device_type).with_context(|| {
+                format!(
+                    "Virtio device type {} doesn't support the legacy interface",
+                    device_type
+                )
+            })?;
+            (device_id, VIRTIO_PCI_LEGACY_ABI_VERSION)
+        } else {
+            (
+                VIRTIO_PCI_DEVICE_ID_BASE + device_type as u16,
+                VIRTIO_PCI_ABI_VERSION,
+            )
+        };
+        le_write_u16(&mut self.base.config.config, DEVICE_ID as usize, device_id)?;
+        self.base.config.config[REVISION_ID] = revision_id;
         let class_id = get_virtio_class_id(device_type, device_quirk);
         le_write_u16(
             &mut self.base.config.config,
@@ -1014,6 +1372,9 @@ impl PciDevOps for VirtioPciDevice {
         // For compatibility with windows viogpu as front-end drivers.
         let subsysid = if device_type == VIRTIO_TYPE_GPU {
             PCI_SUBDEVICE_ID_QEMU
+        } else if self.mode.legacy_enabled() {
+            // The legacy drivers match the device type by the subsystem id.
+            device_type as u16
         } else {
             0x40 + device_type as u16
         };
@@ -1028,59 +1389,61 @@ impl PciDevOps for VirtioPciDevice {
         #[cfg(target_arch = "aarch64")]
         self.base.config.set_interrupt_pin();
 
-        let common_cap = VirtioPciCap::new(
-            size_of::<VirtioPciCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
-            VirtioPciCapType::Common as u8,
-            VIRTIO_PCI_MEM_BAR_IDX,
-            VIRTIO_PCI_CAP_COMMON_OFFSET,
-            VIRTIO_PCI_CAP_COMMON_LENGTH,
-        );
-        self.modern_mem_region_map(common_cap)?;
-
-        let isr_cap = VirtioPciCap::new(
-            size_of::<VirtioPciCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
-            VirtioPciCapType::ISR as u8,
-            VIRTIO_PCI_MEM_BAR_IDX,
-            VIRTIO_PCI_CAP_ISR_OFFSET,
-            VIRTIO_PCI_CAP_ISR_LENGTH,
-        );
-        self.modern_mem_region_map(isr_cap)?;
-
-        let device_cap = VirtioPciCap::new(
-            size_of::<VirtioPciCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
-            VirtioPciCapType::Device as u8,
-            VIRTIO_PCI_MEM_BAR_IDX,
-            VIRTIO_PCI_CAP_DEVICE_OFFSET,
-            VIRTIO_PCI_CAP_DEVICE_LENGTH,
-        );
-        self.modern_mem_region_map(device_cap)?;
-
-        let notify_cap = VirtioPciNotifyCap::new(
-            size_of::<VirtioPciNotifyCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
-            VirtioPciCapType::Notify as u8,
-            VIRTIO_PCI_MEM_BAR_IDX,
-            VIRTIO_PCI_CAP_NOTIFY_OFFSET,
-            VIRTIO_PCI_CAP_NOTIFY_LENGTH,
-            VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER,
-        );
-        self.modern_mem_region_map(notify_cap)?;
+        if self.mode.modern_enabled() {
+            let common_cap = VirtioPciCap::new(
+                size_of::<VirtioPciCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
+                VirtioPciCapType::Common as u8,
+                VIRTIO_PCI_MEM_BAR_IDX,
+                VIRTIO_PCI_CAP_COMMON_OFFSET,
+                VIRTIO_PCI_CAP_COMMON_LENGTH,
+            );
+            self.modern_mem_region_map(common_cap)?;
+
+            let isr_cap = VirtioPciCap::new(
+                size_of::<VirtioPciCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
+                VirtioPciCapType::ISR as u8,
+                VIRTIO_PCI_MEM_BAR_IDX,
+                VIRTIO_PCI_CAP_ISR_OFFSET,
+                VIRTIO_PCI_CAP_ISR_LENGTH,
+            );
+            self.modern_mem_region_map(isr_cap)?;
+
+            let device_cap = VirtioPciCap::new(
+                size_of::<VirtioPciCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
+                VirtioPciCapType::Device as u8,
+                VIRTIO_PCI_MEM_BAR_IDX,
+                VIRTIO_PCI_CAP_DEVICE_OFFSET,
+                VIRTIO_PCI_CAP_DEVICE_LENGTH,
+            );
+            self.modern_mem_region_map(device_cap)?;
+
+            let notify_cap = VirtioPciNotifyCap::new(
+                size_of::<VirtioPciNotifyCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
+                VirtioPciCapType::Notify as u8,
+                VIRTIO_PCI_MEM_BAR_IDX,
+                VIRTIO_PCI_CAP_NOTIFY_OFFSET,
+                VIRTIO_PCI_CAP_NOTIFY_LENGTH,
+                VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER,
+            );
+            self.modern_mem_region_map(notify_cap)?;
 
-        let cfg_cap = VirtioPciCfgAccessCap::new(
-            size_of::<VirtioPciCfgAccessCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
-            VirtioPciCapType::CfgAccess as u8,
-        );
-        self.cfg_cap_offset = self.modern_mem_region_map(cfg_cap)?;
-
-        // Make related fields of PCI config writable for VirtioPciCfgAccessCap.
-        let write_mask = &mut self.base.config.write_mask[self.cfg_cap_offset..];
-        write_mask[offset_of!(VirtioPciCap, bar_id)] = !0;
-        le_write_u32(write_mask, offset_of!(VirtioPciCap, offset), !0)?;
-        le_write_u32(write_mask, offset_of!(VirtioPciCap, length), !0)?;
-        le_write_u32(
-            write_mask,
-            offset_of!(VirtioPciCfgAccessCap, pci_cfg_data),
-            !0,
-        )?;
+            let cfg_cap = VirtioPciCfgAccessCap::new(
+                size_of::<VirtioPciCfgAccessCap>() as u8 + PCI_CAP_VNDR_AND_NEXT_SIZE,
+                VirtioPciCapType::CfgAccess as u8,
+            );
+            self.cfg_cap_offset = self.modern_mem_region_map(cfg_cap)?;
+
+            // Make related fields of PCI config writable for VirtioPciCfgAccessCap.
+            let write_mask = &mut self.base.config.write_mask[self.cfg_cap_offset..];
+            write_mask[offset_of!(VirtioPciCap, bar_id)] = !0;
+            le_write_u32(write_mask, offset_of!(VirtioPciCap, offset), !0)?;
+            le_write_u32(write_mask, offset_of!(VirtioPciCap, length), !0)?;
+            le_write_u32(
+                write_mask,
+                offset_of!(VirtioPciCfgAccessCap, pci_cfg_data),
+                !0,
+            )?;
+        }
 
         let nvectors = self.device.lock().unwrap().queue_num() + 1;
         init_msix(
@@ -1106,30 +1469,60 @@ impl PciDevOps for VirtioPciDevice {
             init_gpu_bar0(&mut self.base.config)?;
         }
 
+        if let Some(romfile) = self.romfile.clone() {
+            init_rom_bar(&mut self.base.config, &romfile)?;
+        }
+
         self.device
             .lock()
             .unwrap()
             .realize()
             .with_context(|| "Failed to realize virtio device")?;
+        if self.dma_mem.is_some() {
+            self.device
+                .lock()
+                .unwrap()
+                .virtio_base_mut()
+                .device_features |= 1 << VIRTIO_F_ACCESS_PLATFORM;
+        }
 
         let name = self.name();
         let devfn = self.base.devfn;
+        let mode = self.mode;
         let dev = Arc::new(Mutex::new(self));
-        let mut mem_region_size = ((VIRTIO_PCI_CAP_NOTIFY_OFFSET + VIRTIO_PCI_CAP_NOTIFY_LENGTH)
-            as u64)
-            .next_power_of_two();
-        mem_region_size = max(mem_region_size, MINIMUM_BAR_SIZE_FOR_MMIO as u64);
-        let modern_mem_region =
-            Region::init_container_region(mem_region_size, "VirtioPciModernMem");
-        Self::modern_mem_region_init(dev.clone(), &modern_mem_region)?;
-
-        dev.lock().unwrap().base.config.register_bar(
-            VIRTIO_PCI_MEM_BAR_IDX as usize,
-            modern_mem_region,
-            RegionType::Mem64Bit,
-            false,
-            mem_region_size,
-        )?;
+        if mode.legacy_enabled() {
+            let legacy_region = Region::init_io_region(
+                VIRTIO_PCI_LEGACY_BAR_SIZE,
+                Self::build_legacy_cfg_ops(dev.clone()),
+                "VirtioPciLegacy",
+            );
+            legacy_region.set_ioeventfds(&dev.lock().unwrap().legacy_ioeventfds());
+            dev.lock().unwrap().base.config.register_bar(
+                VIRTIO_PCI_LEGACY_BAR_IDX as usize,
+                legacy_region,
+                RegionType::Io,
+                false,
+                VIRTIO_PCI_LEGACY_BAR_SIZE,
+            )?;
+        }
+
+        if mode.modern_enabled() {
+            let mut mem_region_size =
+                ((VIRTIO_PCI_CAP_NOTIFY_OFFSET + VIRTIO_PCI_CAP_NOTIFY_LENGTH) as u64)
+                    .next_power_of_two();
+            mem_region_size = max(mem_region_size, MINIMUM_BAR_SIZE_FOR_MMIO as u64);
+            let modern_mem_region =
+                Region::init_container_region(mem_region_size, "VirtioPciModernMem");
+            Self::modern_mem_region_init(dev.clone(), &modern_mem_region)?;
+
+            dev.lock().unwrap().base.config.register_bar(
+                VIRTIO_PCI_MEM_BAR_IDX as usize,
+                modern_mem_region,
+                RegionType::Mem64Bit,
+                false,
+                mem_region_size,
+            )?;
+        }
 
         // Register device to pci bus.
         let pci_bus = dev.lock().unwrap().base.parent_bus.upgrade().unwrap();
@@ -1196,7 +1589,7 @@ impl PciDevOps for VirtioPciDevice {
     }
 
     fn reset(&mut self, _reset_child_device: bool) -> PciResult<()> {
-        self.deactivate_device();
+        self.deactivate_device()?;
         self.device
             .lock()
             .unwrap()
@@ -1228,6 +1621,15 @@ impl PciDevOps for VirtioPciDevice {
                 let dev_path = self.populate_dev_path(parent_dev_path, self.base.devfn, "/scsi@");
                 Some(dev_path)
             }
+            VIRTIO_TYPE_NET => {
+                // The option ROM of the nic boots from its phy, which is matched by the
+                // firmware with the path "/ethernet@$slot_id[,function_id]/ethernet-phy@0".
+                let parent_dev_path = self.get_parent_dev_path(parent_bus);
+                let mut dev_path =
+                    self.populate_dev_path(parent_dev_path, self.base.devfn, "/ethernet@");
+                dev_path.push_str("/ethernet-phy@0");
+                Some(dev_path)
+            }
             _ => None,
         }
     }
@@ -1277,9 +1679,10 @@ impl StateTransfer for VirtioPciDevice {
 
         // Set virtio pci common config state.
         let mut locked_device = self.device.lock().unwrap();
+        let mem_space = self.dma_space(locked_device.virtio_base().driver_features);
         locked_device.virtio_base_mut().set_state(
             &pci_state.virtio_base,
-            self.sys_mem.clone(),
+            mem_space,
             self.interrupt_cb.clone().unwrap(),
         );
 
@@ -1310,12 +1713,9 @@ impl MigrationHook for VirtioPciDevice {
 
         let queue_evts = (*self.notify_eventfds).clone().events;
         if let Some(cb) = self.interrupt_cb.clone() {
-            if let Err(e) =
-                self.device
-                    .lock()
-                    .unwrap()
-                    .activate(self.sys_mem.clone(), cb, queue_evts)
-            {
+            let mut locked_dev = self.device.lock().unwrap();
+            let mem_space = self.dma_space(locked_dev.virtio_base().driver_features);
+            if let Err(e) = locked_dev.activate(mem_space, cb, queue_evts) {
                 error!("Failed to resume device, error is {:?}", e);
             }
         } else {
@@ -1596,6 +1996,84 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_legacy_config() {
+        let virtio_dev = Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let sys_mem = AddressSpace::new(
+            Region::init_container_region(u64::max_value(), "sysmem"),
+            "sysmem",
+        )
+        .unwrap();
+        let parent_bus = Arc::new(Mutex::new(PciBus::new(
+            String::from("test bus"),
+            #[cfg(target_arch = "x86_64")]
+            Region::init_container_region(1 << 16, "parent_bus"),
+            sys_mem.root().clone(),
+        )));
+        let mut virtio_pci = VirtioPciDevice::new(
+            String::from("test device"),
+            0,
+            sys_mem,
+            virtio_dev.clone(),
+            Arc::downgrade(&parent_bus),
+            false,
+        );
+        virtio_pci.set_mode(VirtioPciMode::Transitional);
+
+        // The legacy driver only negotiates the feature bits 0 to 31.
+        assert_eq!(
+            virtio_pci.read_legacy_config(LEGACY_HOST_FEATURES_REG),
+            0xFFFF_FFF0
+        );
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_GUEST_FEATURES_REG, 0xCF)
+            .is_ok());
+        assert_eq!(virtio_dev.lock().unwrap().base.driver_features, 0xC0_u64);
+        assert_eq!(
+            virtio_pci.read_legacy_config(LEGACY_GUEST_FEATURES_REG),
+            0xC0
+        );
+
+        // The queue which doesn't exist is of size 0.
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_QUEUE_SEL_REG, 1)
+            .is_ok());
+        assert_eq!(
+            virtio_pci.read_legacy_config(LEGACY_QUEUE_NUM_REG),
+            u32::from(VIRTIO_DEVICE_QUEUE_SIZE)
+        );
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_QUEUE_SEL_REG, VIRTIO_DEVICE_QUEUE_NUM as u32)
+            .is_ok());
+        assert_eq!(virtio_pci.read_legacy_config(LEGACY_QUEUE_NUM_REG), 0);
+
+        // The vring is laid out contiguously from the page frame number.
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_QUEUE_SEL_REG, 0)
+            .is_ok());
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_QUEUE_PFN_REG, 0x10)
+            .is_ok());
+        assert_eq!(virtio_pci.read_legacy_config(LEGACY_QUEUE_PFN_REG), 0x10);
+        let queue_config = virtio_dev.lock().unwrap().base.queues_config[0];
+        assert!(queue_config.ready);
+        assert_eq!(queue_config.desc_table, GuestAddress(0x10000));
+        assert_eq!(queue_config.avail_ring, GuestAddress(0x11000));
+        assert_eq!(queue_config.used_ring, GuestAddress(0x12000));
+
+        // The queue is disabled by writing 0 to the page frame number.
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_QUEUE_PFN_REG, 0)
+            .is_ok());
+        assert!(!virtio_dev.lock().unwrap().base.queues_config[0].ready);
+
+        // The device-specific configuration follows the registers if MSI-X is disabled.
+        assert_eq!(virtio_pci.legacy_config_offset(), LEGACY_CONFIG_OFFSET);
+        assert!(virtio_pci
+            .write_legacy_config(LEGACY_HOST_FEATURES_REG, 0)
+            .is_err());
+    }
+
     #[test]
     fn test_virtio_pci_config_access() {
         let virtio_dev: Arc<Mutex<dyn VirtioDevice>> =
@@ -1661,6 +2139,44 @@ mod tests {
         assert!(virtio_pci.realize().is_ok());
     }
 
+    #[test]
+    fn test_virtio_pci_realize_transitional() {
+        let virtio_dev: Arc<Mutex<dyn VirtioDevice>> =
+            Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let sys_mem = AddressSpace::new(
+            Region::init_container_region(u64::max_value(), "sysmem"),
+            "sysmem",
+        )
+        .unwrap();
+        let parent_bus = Arc::new(Mutex::new(PciBus::new(
+            String::from("test bus"),
+            #[cfg(target_arch = "x86_64")]
+            Region::init_container_region(1 << 16, "parent_bus"),
+            sys_mem.root().clone(),
+        )));
+        let mut virtio_pci = VirtioPciDevice::new(
+            String::from("test device"),
+            0,
+            sys_mem,
+            virtio_dev,
+            Arc::downgrade(&parent_bus),
+            false,
+        );
+        virtio_pci.set_mode(VirtioPciMode::Transitional);
+        assert!(virtio_pci.realize().is_ok());
+
+        // The transitional device is known by the legacy drivers.
+        let dev = parent_bus.lock().unwrap().devices.get(&0).unwrap().clone();
+        let locked_dev = dev.lock().unwrap();
+        let config = &locked_dev.pci_base().config.config;
+        assert_eq!(le_read_u16(config, DEVICE_ID as usize).unwrap(), 0x1000);
+        assert_eq!(config[REVISION_ID], VIRTIO_PCI_LEGACY_ABI_VERSION);
+        assert_eq!(
+            le_read_u16(config, SUBSYSTEM_ID).unwrap(),
+            VIRTIO_DEVICE_TEST_TYPE as u16
+        );
+    }
+
     #[test]
     fn test_device_activate() {
         let sys_mem = AddressSpace::new(
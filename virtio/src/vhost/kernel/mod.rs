@@ -13,7 +13,7 @@
 mod net;
 mod vsock;
 
-pub use net::Net;
+pub use net::{Net, VhostNetState};
 pub use vsock::{Vsock, VsockState};
 
 use std::fs::{File, OpenOptions};
@@ -22,7 +22,7 @@ use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Context, Result};
-use log::debug;
+use log::{debug, error};
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref};
 use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr};
@@ -49,6 +49,12 @@ ioctl_iow_nr!(VHOST_SET_VRING_BASE, VHOST, 0x12, VhostVringState);
 ioctl_iowr_nr!(VHOST_GET_VRING_BASE, VHOST, 0x12, VhostVringState);
 ioctl_iow_nr!(VHOST_SET_VRING_KICK, VHOST, 0x20, VhostVringFile);
 ioctl_iow_nr!(VHOST_SET_VRING_CALL, VHOST, 0x21, VhostVringFile);
+ioctl_iow_nr!(
+    VHOST_SET_VRING_BUSYLOOP_TIMEOUT,
+    VHOST,
+    0x23,
+    VhostVringState
+);
 ioctl_iow_nr!(VHOST_NET_SET_BACKEND, VHOST, 0x30, VhostVringFile);
 ioctl_iow_nr!(VHOST_VSOCK_SET_GUEST_CID, VHOST, 0x60, u64);
 ioctl_iow_nr!(VHOST_VSOCK_SET_RUNNING, VHOST, 0x61, i32);
@@ -125,6 +131,9 @@ impl ByteCode for VhostMemory {}
 #[derive(Clone)]
 struct VhostMemInfo {
     regions: Arc<Mutex<Vec<VhostMemoryRegion>>>,
+    /// The AddressSpace for DMA if the device is behind the iommu, whose memory
+    /// regions are addressed by IO virtual address.
+    dma_space: Option<Arc<AddressSpace>>,
     enabled: bool,
 }
 
@@ -132,13 +141,34 @@ impl VhostMemInfo {
     fn new() -> VhostMemInfo {
         VhostMemInfo {
             regions: Arc::new(Mutex::new(Vec::new())),
+            dma_space: None,
             enabled: false,
         }
     }
 
+    fn dma_regions(dma_space: &AddressSpace) -> Vec<VhostMemoryRegion> {
+        dma_space
+            .dma_ram_ranges()
+            .iter()
+            .map(|fr| VhostMemoryRegion {
+                guest_phys_addr: fr.addr_range.base.raw_value(),
+                memory_size: fr.addr_range.size,
+                userspace_addr: fr.owner.get_host_address().unwrap() + fr.offset_in_region,
+                flags_padding: 0_u64,
+            })
+            .collect()
+    }
+
+    fn regions(&self) -> Vec<VhostMemoryRegion> {
+        match self.dma_space.as_ref() {
+            Some(dma_space) => Self::dma_regions(dma_space),
+            None => self.regions.lock().unwrap().clone(),
+        }
+    }
+
     fn addr_to_host(&self, addr: GuestAddress) -> Option<u64> {
         let addr = addr.raw_value();
-        for region in self.regions.lock().unwrap().iter() {
+        for region in self.regions().iter() {
             if addr >= region.guest_phys_addr && addr < region.guest_phys_addr + region.memory_size
             {
                 let offset = addr - region.guest_phys_addr;
@@ -253,6 +283,80 @@ impl VhostBackend {
 
         Ok(VhostBackend { fd, mem_info })
     }
+
+    /// Set the AddressSpace used by the guest driver for DMA. If the device is behind the
+    /// iommu, the memory table is made of the iommu mappings and is updated once the
+    /// mappings are changed.
+    pub fn set_dma_space(&self, mem_space: &Arc<AddressSpace>) -> Result<()> {
+        let mut locked_mem_info = self.mem_info.lock().unwrap();
+        let iommu = match mem_space.iommu() {
+            Some(iommu) => iommu,
+            None => {
+                locked_mem_info.dma_space = None;
+                return Ok(());
+            }
+        };
+        locked_mem_info.dma_space = Some(mem_space.clone());
+
+        let fd = self
+            .fd
+            .try_clone()
+            .with_context(|| "Failed to clone vhost fd")?;
+        let dma_space = mem_space.clone();
+        iommu.add_notifier(Arc::new(move || {
+            let regions = VhostMemInfo::dma_regions(&dma_space);
+            if let Err(e) = set_mem_table(&fd, &regions) {
+                error!(
+                    "Failed to update vhost mem table for iommu mappings: {:?}",
+                    e
+                );
+            }
+        }));
+        Ok(())
+    }
+
+    /// Set the time in microseconds that vhost polls the vring and the backend before
+    /// waiting for the notification, 0 disables the busyloop.
+    pub fn set_vring_busyloop_timeout(&self, queue_idx: usize, timeout: u32) -> Result<()> {
+        let vring_state = VhostVringState {
+            index: queue_idx as u32,
+            num: timeout,
+        };
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_BUSYLOOP_TIMEOUT(), &vring_state) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_BUSYLOOP_TIMEOUT".to_string()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn set_mem_table(fd: &File, regions: &[VhostMemoryRegion]) -> Result<()> {
+    let vm_size = std::mem::size_of::<VhostMemory>();
+    let vmr_size = std::mem::size_of::<VhostMemoryRegion>();
+    let mut bytes: Vec<u8> = vec![0; vm_size + std::mem::size_of_val(regions)];
+
+    bytes[0..vm_size].copy_from_slice(
+        VhostMemory {
+            nregions: regions.len() as u32,
+            padding: 0,
+        }
+        .as_bytes(),
+    );
+
+    for (index, region) in regions.iter().enumerate() {
+        bytes[(vm_size + index * vmr_size)..(vm_size + (index + 1) * vmr_size)]
+            .copy_from_slice(region.as_bytes());
+    }
+
+    let ret = unsafe { ioctl_with_ptr(fd, VHOST_SET_MEM_TABLE(), bytes.as_ptr()) };
+    if ret < 0 {
+        return Err(anyhow!(VirtioError::VhostIoctl(
+            "VHOST_SET_MEM_TABLE".to_string()
+        )));
+    }
+    Ok(())
 }
 
 impl AsRawFd for VhostBackend {
@@ -304,33 +408,8 @@ impl VhostOps for VhostBackend {
     }
 
     fn set_mem_table(&self) -> Result<()> {
-        let regions = self.mem_info.lock().unwrap().regions.lock().unwrap().len();
-        let vm_size = std::mem::size_of::<VhostMemory>();
-        let vmr_size = std::mem::size_of::<VhostMemoryRegion>();
-        let mut bytes: Vec<u8> = vec![0; vm_size + regions * vmr_size];
-
-        bytes[0..vm_size].copy_from_slice(
-            VhostMemory {
-                nregions: regions as u32,
-                padding: 0,
-            }
-            .as_bytes(),
-        );
-
-        let locked_mem_info = self.mem_info.lock().unwrap();
-        let locked_regions = locked_mem_info.regions.lock().unwrap();
-        for (index, region) in locked_regions.iter().enumerate() {
-            bytes[(vm_size + index * vmr_size)..(vm_size + (index + 1) * vmr_size)]
-                .copy_from_slice(region.as_bytes());
-        }
-
-        let ret = unsafe { ioctl_with_ptr(self, VHOST_SET_MEM_TABLE(), bytes.as_ptr()) };
-        if ret < 0 {
-            return Err(anyhow!(VirtioError::VhostIoctl(
-                "VHOST_SET_MEM_TABLE".to_string()
-            )));
-        }
-        Ok(())
+        let regions = self.mem_info.lock().unwrap().regions();
+        set_mem_table(&self.fd, &regions)
     }
 
     fn set_vring_num(&self, queue_idx: usize, num: u16) -> Result<()> {
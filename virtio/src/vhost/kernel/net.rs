@@ -22,7 +22,9 @@ use super::super::{VhostIoHandler, VhostNotify, VhostOps};
 use super::{VhostBackend, VhostVringFile, VHOST_NET_SET_BACKEND};
 use crate::read_config_default;
 use crate::{
-    device::net::{build_device_config_space, create_tap, CtrlInfo, MAC_ADDR_LEN},
+    device::net::{
+        build_device_config_space, create_tap, get_tap_offload_flags, CtrlInfo, MAC_ADDR_LEN,
+    },
     error::VirtioError,
     virtio_has_feature, CtrlVirtio, NetCtrlHandler, VirtioBase, VirtioDevice, VirtioInterrupt,
     VirtioNetConfig, VIRTIO_F_ACCESS_PLATFORM, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX,
@@ -31,8 +33,10 @@ use crate::{
     VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MQ, VIRTIO_TYPE_NET,
 };
 use address_space::AddressSpace;
-use machine_manager::config::NetworkInterfaceConfig;
+use machine_manager::config::{NetworkInterfaceConfig, MAX_VIRTIO_QUEUE};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 use util::tap::Tap;
@@ -41,6 +45,8 @@ use util::tap::Tap;
 const QUEUE_NUM_NET: usize = 2;
 /// Feature for vhost-net to add virtio_net_hdr for RX, and strip for TX packets.
 const VHOST_NET_F_VIRTIO_NET_HDR: u32 = 27;
+/// Zero-copy TX can only be enabled for all devices by the parameter of vhost_net module.
+const VHOST_NET_ZCOPYTX_PARAM: &str = "/sys/module/vhost_net/parameters/experimental_zcopytx";
 
 trait VhostNetBackend {
     /// Attach virtio net ring to a raw socket, or tap device.
@@ -72,6 +78,35 @@ impl VhostNetBackend for VhostBackend {
     }
 }
 
+/// Check whether zero-copy TX is enabled by vhost_net module. Vhost-net transmits
+/// the packets with zero-copy for all the taps once it's enabled.
+fn check_zerocopy_tx() -> Result<()> {
+    let param = std::fs::read_to_string(VHOST_NET_ZCOPYTX_PARAM)
+        .with_context(|| format!("Failed to read {}", VHOST_NET_ZCOPYTX_PARAM))?;
+    if param.trim() == "0" || param.trim() == "N" {
+        bail!("Zero-copy TX is disabled by vhost_net module, load it with experimental_zcopytx=1");
+    }
+    Ok(())
+}
+
+/// State of vhost-kernel net device.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct VhostNetState {
+    /// Bit mask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Virtio net configurations, including mac and mtu.
+    config_space: VirtioNetConfig,
+    /// Last avail idx of rx and tx queues in vhost-net backends, the length is
+    /// `MAX_VIRTIO_QUEUE` which is required to be a literal by `Desc`.
+    last_avail_idx: [u16; 32],
+    /// Device broken status.
+    broken: bool,
+}
+
 /// Network device structure.
 pub struct Net {
     /// Virtio device base property.
@@ -90,6 +125,10 @@ pub struct Net {
     mem_space: Arc<AddressSpace>,
     /// Save irqfd used for vhost-net.
     call_events: Vec<Arc<EventFd>>,
+    /// Last avail idx of rx and tx queues in vhost-net backends.
+    last_avail_idx: [u16; MAX_VIRTIO_QUEUE],
+    /// Whether the vhost-net backends are detached from taps for migration.
+    backend_paused: bool,
 }
 
 impl Net {
@@ -110,7 +149,51 @@ impl Net {
             vhost_features: 0_u64,
             mem_space: mem_space.clone(),
             call_events: Vec::new(),
+            last_avail_idx: [0; MAX_VIRTIO_QUEUE],
+            backend_paused: false,
+        }
+    }
+
+    /// Attach the rx and tx queues of all vhost-net backends to their taps, or detach
+    /// them to stop the rings.
+    fn set_backends_running(&self, running: bool) -> Result<()> {
+        let backends = self
+            .backends
+            .as_ref()
+            .with_context(|| "Failed to get backend for vhost net")?;
+        let taps = self
+            .taps
+            .as_ref()
+            .with_context(|| "Failed to get tap for vhost net")?;
+        for (backend, tap) in backends.iter().zip(taps.iter()) {
+            let fd = if running { tap.file.as_raw_fd() } else { -1 };
+            // 2 queues: rx and tx.
+            for queue_index in 0..2 {
+                backend.set_backend(queue_index, fd)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Get the last avail idx of rx and tx queues from all vhost-net backends.
+    fn get_last_avail_idx(&self) -> Result<[u16; MAX_VIRTIO_QUEUE]> {
+        let mut last_avail_idx = [0; MAX_VIRTIO_QUEUE];
+        let backends = self
+            .backends
+            .as_ref()
+            .with_context(|| "Failed to get backend for vhost net")?;
+        for (index, backend) in backends.iter().enumerate() {
+            for queue_index in 0..2 {
+                last_avail_idx[index * 2 + queue_index] =
+                    backend.get_vring_base(queue_index).with_context(|| {
+                        format!(
+                            "Failed to get vring base for vhost net, index: {}",
+                            index * 2 + queue_index,
+                        )
+                    })?;
+            }
+        }
+        Ok(last_avail_idx)
     }
 }
 
@@ -124,6 +207,10 @@ impl VirtioDevice for Net {
     }
 
     fn realize(&mut self) -> Result<()> {
+        if self.net_cfg.zerocopy {
+            check_zerocopy_tx()?;
+        }
+
         let queue_pairs = self.net_cfg.queues / 2;
         let mut backends = Vec::with_capacity(queue_pairs as usize);
         for index in 0..queue_pairs {
@@ -146,8 +233,13 @@ impl VirtioDevice for Net {
             _ => Some(self.net_cfg.host_dev_name.as_str()),
         };
 
-        self.taps = create_tap(self.net_cfg.tap_fds.as_ref(), host_dev_name, queue_pairs)
-            .with_context(|| "Failed to create tap for vhost net")?;
+        self.taps = create_tap(
+            self.net_cfg.tap_fds.as_ref(),
+            host_dev_name,
+            queue_pairs,
+            self.net_cfg.sndbuf,
+        )
+        .with_context(|| "Failed to create tap for vhost net")?;
         self.backends = Some(backends);
 
         self.init_config_features()?;
@@ -194,6 +286,7 @@ impl VirtioDevice for Net {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(VhostNetState::descriptor(), &self.net_cfg.id);
         Ok(())
     }
 
@@ -244,7 +337,7 @@ impl VirtioDevice for Net {
 
             let ctrl_handler = NetCtrlHandler {
                 ctrl: CtrlVirtio::new(ctrl_queue, ctrl_queue_evt, ctrl_info),
-                mem_space,
+                mem_space: mem_space.clone(),
                 interrupt_cb: interrupt_cb.clone(),
                 driver_features,
                 device_broken: self.base.broken.clone(),
@@ -260,6 +353,8 @@ impl VirtioDevice for Net {
             )?;
         }
 
+        // The features about offload is included in bits 0 to 31.
+        let flags = get_tap_offload_flags(driver_features & 0xffff_ffff);
         let queue_pairs = queue_num / 2;
         for index in 0..queue_pairs {
             let mut host_notifies = Vec::new();
@@ -273,6 +368,9 @@ impl VirtioDevice for Net {
             backend
                 .set_features(self.vhost_features)
                 .with_context(|| "Failed to set features for vhost net")?;
+            backend
+                .set_dma_space(&mem_space)
+                .with_context(|| "Failed to set dma space for vhost net")?;
             backend
                 .set_mem_table()
                 .with_context(|| "Failed to set mem table for vhost net")?;
@@ -299,12 +397,14 @@ impl VirtioDevice for Net {
                             queue_index,
                         )
                     })?;
-                backend.set_vring_base(queue_index, 0).with_context(|| {
-                    format!(
-                        "Failed to set vring base for vhost net, index: {}",
-                        queue_index,
-                    )
-                })?;
+                backend
+                    .set_vring_base(queue_index, self.last_avail_idx[index * 2 + queue_index])
+                    .with_context(|| {
+                        format!(
+                            "Failed to set vring base for vhost net, index: {}",
+                            queue_index,
+                        )
+                    })?;
                 backend
                     .set_vring_kick(queue_index, queue_evts[index * 2 + queue_index].clone())
                     .with_context(|| {
@@ -313,6 +413,16 @@ impl VirtioDevice for Net {
                             index * 2 + queue_index,
                         )
                     })?;
+                if self.net_cfg.poll_us != 0 {
+                    backend
+                        .set_vring_busyloop_timeout(queue_index, self.net_cfg.poll_us)
+                        .with_context(|| {
+                            format!(
+                                "Failed to set vring busyloop timeout for vhost net, index: {}",
+                                index * 2 + queue_index,
+                            )
+                        })?;
+                }
 
                 drop(queue);
 
@@ -343,6 +453,8 @@ impl VirtioDevice for Net {
                     None => bail!("Failed to get tap for vhost net"),
                     Some(taps) => taps[index].clone(),
                 };
+                tap.set_offload(flags)
+                    .with_context(|| "Failed to set tap offload")?;
                 backend
                     .set_backend(queue_index, tap.file.as_raw_fd())
                     .with_context(|| {
@@ -368,6 +480,7 @@ impl VirtioDevice for Net {
                 )?;
             }
         }
+        self.backend_paused = false;
         self.base.broken.store(false, Ordering::SeqCst);
 
         Ok(())
@@ -398,6 +511,73 @@ impl VirtioDevice for Net {
                 backend.set_backend(queue_index, -1)?;
             }
         }
+        self.last_avail_idx = [0; MAX_VIRTIO_QUEUE];
+
+        Ok(())
+    }
+}
+
+impl StateTransfer for Net {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = VhostNetState {
+            device_features: self.base.device_features,
+            driver_features: self.base.driver_features,
+            config_space: *self.config_space.lock().unwrap(),
+            last_avail_idx: self.last_avail_idx,
+            broken: self.base.broken.load(Ordering::SeqCst),
+        };
+        // The backends have been stopped and the avail idx has been saved in `pause`.
+        if self.backend_paused || !self.device_activated() {
+            return Ok(state.as_bytes().to_vec());
+        }
+
+        self.set_backends_running(false)
+            .with_context(|| "Failed to stop vhost net backends")?;
+        state.last_avail_idx = self.get_last_avail_idx()?;
+        self.set_backends_running(true)
+            .with_context(|| "Failed to restart vhost net backends")?;
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state = VhostNetState::from_bytes(state)
+            .with_context(|| migration::error::MigrationError::FromBytesError("VHOST_NET"))?;
+        self.base.device_features = state.device_features;
+        self.base.driver_features = state.driver_features;
+        self.base.broken.store(state.broken, Ordering::SeqCst);
+        *self.config_space.lock().unwrap() = state.config_space;
+        self.last_avail_idx = state.last_avail_idx;
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&VhostNetState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Net {
+    fn pause(&mut self) -> migration::Result<()> {
+        if !self.device_activated() || self.backend_paused {
+            return Ok(());
+        }
+
+        self.set_backends_running(false)
+            .with_context(|| "Failed to stop vhost net backends")?;
+        self.last_avail_idx = self.get_last_avail_idx()?;
+        self.backend_paused = true;
+
+        Ok(())
+    }
+
+    fn restart(&mut self) -> migration::Result<()> {
+        if !self.backend_paused {
+            return Ok(());
+        }
+
+        self.set_backends_running(true)
+            .with_context(|| "Failed to restart vhost net backends")?;
+        self.backend_paused = false;
 
         Ok(())
     }
@@ -442,6 +622,7 @@ mod tests {
     fn test_vhost_net_realize() {
         let net1 = NetworkInterfaceConfig {
             id: "eth1".to_string(),
+            netdev: "".to_string(),
             host_dev_name: "tap1".to_string(),
             mac: Some("1F:2C:3E:4A:5B:6D".to_string()),
             vhost_type: Some("vhost-kernel".to_string()),
@@ -450,8 +631,15 @@ mod tests {
             iothread: None,
             queues: 2,
             mq: false,
+            sndbuf: None,
+            poll_us: 0,
+            zerocopy: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            romfile: None,
+            boot_index: None,
+            max_frames: 0,
+            max_usecs: 0,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -464,6 +652,7 @@ mod tests {
 
         let net1 = NetworkInterfaceConfig {
             id: "eth0".to_string(),
+            netdev: "".to_string(),
             host_dev_name: "".to_string(),
             mac: Some("1A:2B:3C:4D:5E:6F".to_string()),
             vhost_type: Some("vhost-kernel".to_string()),
@@ -472,8 +661,15 @@ mod tests {
             iothread: None,
             queues: 2,
             mq: false,
+            sndbuf: None,
+            poll_us: 0,
+            zerocopy: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            romfile: None,
+            boot_index: None,
+            max_frames: 0,
+            max_usecs: 0,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -528,4 +724,35 @@ mod tests {
         let mut read_data: Vec<u8> = vec![0; len as usize];
         assert_eq!(vhost_net.read_config(offset, &mut read_data).is_ok(), false);
     }
+
+    #[test]
+    fn test_vhost_net_migration_state() {
+        let vhost_net_space = vhost_address_space_init();
+        let mut vhost_net = Net::new(&NetworkInterfaceConfig::default(), &vhost_net_space);
+        vhost_net.base.device_features = 0x0123_4567_89ab_cdef;
+        vhost_net.base.driver_features = 0x8000_0000;
+        {
+            let mut locked_config = vhost_net.config_space.lock().unwrap();
+            locked_config.mac = [0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f];
+            locked_config.mtu = 1400;
+        }
+        vhost_net.last_avail_idx[0] = 5;
+        vhost_net.last_avail_idx[1] = 6;
+
+        // The backends of inactive device need not to be paused.
+        assert!(vhost_net.pause().is_ok());
+        assert!(!vhost_net.backend_paused);
+        let state = vhost_net.get_state_vec().unwrap();
+
+        let mut dst_net = Net::new(&NetworkInterfaceConfig::default(), &vhost_net_space);
+        assert!(dst_net.set_state_mut(&state).is_ok());
+        assert_eq!(dst_net.base.device_features, 0x0123_4567_89ab_cdef);
+        assert_eq!(dst_net.base.driver_features, 0x8000_0000);
+        assert_eq!(
+            dst_net.config_space.lock().unwrap().mac,
+            [0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f]
+        );
+        assert_eq!({ dst_net.config_space.lock().unwrap().mtu }, 1400);
+        assert_eq!(dst_net.last_avail_idx[..2], [5, 6]);
+    }
 }
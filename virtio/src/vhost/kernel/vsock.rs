@@ -84,6 +84,8 @@ pub struct VsockState {
     last_avail_idx: [u16; 2],
     /// Device broken status.
     broken: bool,
+    /// Context ID of the guest.
+    guest_cid: u64,
 }
 
 /// Vsock device structure.
@@ -98,7 +100,7 @@ pub struct Vsock {
     backend: Option<VhostBackend>,
     /// Last avail idx in vsock backend queue.
     last_avail_idx: [u16; 2],
-    /// System address space.
+    /// System address space, which is replaced by the address space for DMA on activation.
     mem_space: Arc<AddressSpace>,
     /// Event queue for vsock.
     event_queue: Option<Arc<Mutex<Queue>>>,
@@ -106,6 +108,8 @@ pub struct Vsock {
     interrupt_cb: Option<Arc<VirtioInterrupt>>,
     /// Save irqfd used for vhost-vsock
     call_events: Vec<Arc<EventFd>>,
+    /// Whether the vhost backend is stopped for migration.
+    backend_paused: bool,
 }
 
 impl Vsock {
@@ -120,6 +124,7 @@ impl Vsock {
             event_queue: None,
             interrupt_cb: None,
             call_events: Vec::new(),
+            backend_paused: false,
         }
     }
 
@@ -189,6 +194,13 @@ impl VirtioDevice for Vsock {
         Ok(())
     }
 
+    fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(VsockState::descriptor(), &self.vsock_cfg.id);
+        // Close the vhost-vsock device, so that the guest cid can be used by others.
+        self.backend = None;
+        Ok(())
+    }
+
     fn init_config_features(&mut self) -> Result<()> {
         let backend = self.backend.as_ref().unwrap();
         let features = backend
@@ -230,7 +242,7 @@ impl VirtioDevice for Vsock {
 
     fn activate(
         &mut self,
-        _: Arc<AddressSpace>,
+        mem_space: Arc<AddressSpace>,
         interrupt_cb: Arc<VirtioInterrupt>,
         queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
@@ -242,6 +254,7 @@ impl VirtioDevice for Vsock {
         // This event queue will be handled.
         self.event_queue = Some(queues[2].clone());
         self.interrupt_cb = Some(interrupt_cb.clone());
+        self.mem_space = mem_space.clone();
 
         // Preliminary setup for vhost net.
         let backend = match &self.backend {
@@ -249,8 +262,11 @@ impl VirtioDevice for Vsock {
             Some(backend_) => backend_,
         };
         backend
-            .set_features(self.base.driver_features)
+            .set_features(self.base.driver_features & !(1_u64 << VIRTIO_F_ACCESS_PLATFORM))
             .with_context(|| "Failed to set features for vsock")?;
+        backend
+            .set_dma_space(&mem_space)
+            .with_context(|| "Failed to set dma space for vsock")?;
         backend
             .set_mem_table()
             .with_context(|| "Failed to set mem table for vsock")?;
@@ -305,6 +321,7 @@ impl VirtioDevice for Vsock {
 
         backend.set_guest_cid(cid)?;
         backend.set_running(true)?;
+        self.backend_paused = false;
 
         if self.call_events.is_empty() {
             let handler = VhostIoHandler {
@@ -335,19 +352,26 @@ impl VirtioDevice for Vsock {
 
 impl StateTransfer for Vsock {
     fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = VsockState {
+            device_features: self.base.device_features,
+            driver_features: self.base.driver_features,
+            config_space: self.config_space,
+            last_avail_idx: self.last_avail_idx,
+            broken: self.base.broken.load(Ordering::SeqCst),
+            guest_cid: self.vsock_cfg.guest_cid,
+        };
+        // The backend has been stopped and the avail idx has been saved in `pause`.
+        if self.backend_paused {
+            return Ok(state.as_bytes().to_vec());
+        }
+
         migration::Result::with_context(self.backend.as_ref().unwrap().set_running(false), || {
             "Failed to set vsock backend stopping"
         })?;
 
         let last_avail_idx_0 = self.backend.as_ref().unwrap().get_vring_base(0).unwrap();
         let last_avail_idx_1 = self.backend.as_ref().unwrap().get_vring_base(1).unwrap();
-        let state = VsockState {
-            device_features: self.base.device_features,
-            driver_features: self.base.driver_features,
-            config_space: self.config_space,
-            last_avail_idx: [last_avail_idx_0, last_avail_idx_1],
-            broken: self.base.broken.load(Ordering::SeqCst),
-        };
+        state.last_avail_idx = [last_avail_idx_0, last_avail_idx_1];
 
         migration::Result::with_context(self.backend.as_ref().unwrap().set_running(true), || {
             "Failed to set vsock backend running"
@@ -367,6 +391,14 @@ impl StateTransfer for Vsock {
         self.base.broken.store(state.broken, Ordering::SeqCst);
         self.config_space = state.config_space;
         self.last_avail_idx = state.last_avail_idx;
+        // The state saved by old version has no guest cid, use the configured one.
+        if state.guest_cid != 0 && state.guest_cid != self.vsock_cfg.guest_cid {
+            return Err(anyhow!(
+                "Guest cid mismatch for vsock: source {}, destination {}",
+                state.guest_cid,
+                self.vsock_cfg.guest_cid
+            ));
+        }
         Ok(())
     }
 
@@ -376,14 +408,59 @@ impl StateTransfer for Vsock {
 }
 
 impl MigrationHook for Vsock {
-    #[cfg(target_arch = "aarch64")]
     fn resume(&mut self) -> migration::Result<()> {
+        if self.device_activated() {
+            let backend = self.backend.as_ref().unwrap();
+            migration::Result::with_context(
+                backend.set_guest_cid(self.vsock_cfg.guest_cid),
+                || "Failed to set guest cid for vsock",
+            )?;
+            migration::Result::with_context(backend.set_running(true), || {
+                "Failed to set vsock backend running"
+            })?;
+        }
         migration::Result::with_context(self.transport_reset(), || {
             "Failed to resume virtio vsock device"
         })?;
 
         Ok(())
     }
+
+    fn pause(&mut self) -> migration::Result<()> {
+        if !self.device_activated() || self.backend_paused {
+            return Ok(());
+        }
+
+        let backend = self.backend.as_ref().unwrap();
+        migration::Result::with_context(backend.set_running(false), || {
+            "Failed to set vsock backend stopping"
+        })?;
+        for (queue_index, last_avail_idx) in self.last_avail_idx.iter_mut().enumerate() {
+            *last_avail_idx =
+                migration::Result::with_context(backend.get_vring_base(queue_index), || {
+                    format!("Failed to get vring base for vsock, index: {}", queue_index)
+                })?;
+        }
+        self.backend_paused = true;
+
+        Ok(())
+    }
+
+    fn restart(&mut self) -> migration::Result<()> {
+        if !self.backend_paused {
+            return Ok(());
+        }
+
+        migration::Result::with_context(self.backend.as_ref().unwrap().set_running(true), || {
+            "Failed to set vsock backend running"
+        })?;
+        self.backend_paused = false;
+        migration::Result::with_context(self.transport_reset(), || {
+            "Failed to send vsock transport reset event"
+        })?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -463,6 +540,35 @@ mod tests {
         assert_eq!(vsock.read_config(3, &mut buf).is_err(), true);
     }
 
+    #[test]
+    fn test_vsock_migration_state() {
+        let mut vsock = vsock_create_instance();
+
+        // The backend of inactive device need not to be paused.
+        assert!(vsock.pause().is_ok());
+        assert!(!vsock.backend_paused);
+        assert!(vsock.restart().is_ok());
+
+        let mut state = VsockState {
+            device_features: 0x0123_4567_89ab_cdef,
+            driver_features: 0x8000_0000,
+            config_space: [0; 8],
+            last_avail_idx: [5, 6],
+            broken: false,
+            guest_cid: 3,
+        };
+        assert!(vsock.set_state_mut(state.as_bytes()).is_ok());
+        assert_eq!(vsock.base.driver_features, 0x8000_0000);
+        assert_eq!(vsock.last_avail_idx, [5, 6]);
+
+        // The state saved by old version has no guest cid.
+        state.guest_cid = 0;
+        assert!(vsock.set_state_mut(state.as_bytes()).is_ok());
+
+        state.guest_cid = 4;
+        assert!(vsock.set_state_mut(state.as_bytes()).is_err());
+    }
+
     #[test]
     fn test_vsock_realize() {
         // test vsock new method
@@ -270,4 +270,9 @@ impl VirtioDevice for Block {
 
         Ok(())
     }
+
+    fn iommu_supported(&self) -> bool {
+        // The vhost-user backend accesses guest memory by guest physical address.
+        false
+    }
 }
@@ -137,11 +137,26 @@ fn vhost_user_reconnect(client: &Arc<Mutex<VhostUserClient>>) {
         }
     }
 
+    if locked_client.queues.is_empty() {
+        // The device has not been activated by the guest, nothing to restore.
+        info!("Reconnecting vhost-user {} succeed.", dev_type);
+        return;
+    }
+
     if let Err(e) = locked_client.activate_vhost_user() {
-        error!("Failed to reactivate vhost-user net, {:?}", e);
-    } else {
-        info!("Reconnecting vhost-user net succeed.");
+        error!("Failed to reactivate vhost-user {}, {:?}", dev_type, e);
+        return;
     }
+
+    // The backend resubmits the unresolved descriptors recorded in the inflight
+    // region after the vrings are set, kick all queues to let it also handle the
+    // requests which are added by guest during disconnection.
+    for evt in locked_client.queue_evts.iter() {
+        if let Err(e) = evt.write(1) {
+            error!("Failed to kick vhost-user {} queue, {:?}", dev_type, e);
+        }
+    }
+    info!("Reconnecting vhost-user {} succeed.", dev_type);
 }
 
 impl EventNotifierHelper for VhostUserClient {
@@ -353,10 +368,28 @@ struct VhostInflight {
     // The inflight file.
     file: Arc<File>,
     // Fd mmap addr, used for migration.
-    _addr: u64,
+    addr: u64,
     inner: VhostUserInflight,
 }
 
+impl Drop for VhostInflight {
+    fn drop(&mut self) {
+        // Safe because the region is mapped in `set_inflight` and is not used any more.
+        let ret = unsafe {
+            libc::munmap(
+                self.addr as *mut libc::c_void,
+                self.inner.mmap_size as libc::size_t,
+            )
+        };
+        if ret != 0 {
+            error!(
+                "Failed to munmap inflight region, {:?}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub enum VhostBackendType {
     TypeNet,
@@ -378,6 +411,8 @@ impl ToString for VhostBackendType {
 pub struct VhostUserClient {
     client: Arc<Mutex<ClientInternal>>,
     mem_info: VhostUserMemInfo,
+    /// Memory listener registered to the address space, unregistered when the client is dropped.
+    mem_listener: Arc<Mutex<VhostUserMemInfo>>,
     delete_evts: Vec<RawFd>,
     mem_space: Arc<AddressSpace>,
     queues: Vec<Arc<Mutex<Queue>>>,
@@ -406,14 +441,16 @@ impl VhostUserClient {
         })?;
 
         let mem_info = VhostUserMemInfo::new();
+        let mem_listener = Arc::new(Mutex::new(mem_info.clone()));
         mem_space
-            .register_listener(Arc::new(Mutex::new(mem_info.clone())))
+            .register_listener(mem_listener.clone())
             .with_context(|| "Failed to register memory for vhost user client")?;
 
         let client = Arc::new(Mutex::new(ClientInternal::new(sock, max_queue_num)));
         Ok(VhostUserClient {
             client,
             mem_info,
+            mem_listener,
             delete_evts: Vec::new(),
             mem_space: mem_space.clone(),
             queues: Vec::new(),
@@ -449,46 +486,52 @@ impl VhostUserClient {
     }
 
     /// Set inflight fd, include get inflight fd from vhost and set inflight to vhost.
+    /// The inflight region is kept across reconnection, so that the new backend can
+    /// resubmit the descriptors which were not completed by the old one.
     pub fn set_inflight(&mut self, queue_num: u16, queue_size: u16) -> Result<()> {
         if self.backend_type != VhostBackendType::TypeBlock {
             // Only vhost-user-blk supports inflight fd now.
             return Ok(());
         }
-        let protocol_feature = self
-            .get_protocol_features()
-            .with_context(|| "Failed to get protocol features for vhost-user blk")?;
-        if virtio_has_feature(
-            protocol_feature,
+        if !virtio_has_feature(
+            self.protocol_features,
             VHOST_USER_PROTOCOL_F_INFLIGHT_SHMFD as u32,
         ) {
-            if self.inflight.is_none() {
-                // Expect 1 fd.
-                let mut fds = [RawFd::default()];
-                let vhost_user_inflight = self.get_inflight_fd(queue_num, queue_size, &mut fds)?;
-                let file = Arc::new(unsafe { File::from_raw_fd(fds[0]) });
-                let hva = do_mmap(
-                    &Some(file.as_ref()),
-                    vhost_user_inflight.mmap_size,
-                    vhost_user_inflight.mmap_offset,
-                    true,
-                    true,
-                    false,
-                )?;
-                let inflight = VhostInflight {
-                    file,
-                    _addr: hva,
-                    inner: vhost_user_inflight,
-                };
-                self.inflight = Some(inflight);
-            }
-            let inflight = self.inflight.as_ref().unwrap();
-            self.set_inflight_fd(inflight.inner.clone(), inflight.file.as_raw_fd())?;
-        } else {
-            bail!(
-                "Failed to get inflight fd, spdk doesn't support, spdk protocol feature: {:#b}",
-                protocol_feature
+            warn!(
+                "Inflight I/O tracking is not negotiated, protocol features: {:#b}",
+                self.protocol_features
             );
+            return Ok(());
+        }
+
+        if let Some(inflight) = self.inflight.as_ref() {
+            // Queue layout has been changed, the old region is useless.
+            if inflight.inner.queue_num != queue_num || inflight.inner.queue_size != queue_size {
+                self.inflight = None;
+            }
+        }
+        if self.inflight.is_none() {
+            // Expect 1 fd.
+            let mut fds = [RawFd::default()];
+            let vhost_user_inflight = self.get_inflight_fd(queue_num, queue_size, &mut fds)?;
+            let file = Arc::new(unsafe { File::from_raw_fd(fds[0]) });
+            let hva = do_mmap(
+                &Some(file.as_ref()),
+                vhost_user_inflight.mmap_size,
+                vhost_user_inflight.mmap_offset,
+                true,
+                true,
+                false,
+            )?;
+            let inflight = VhostInflight {
+                file,
+                addr: hva,
+                inner: vhost_user_inflight,
+            };
+            self.inflight = Some(inflight);
         }
+        let inflight = self.inflight.as_ref().unwrap();
+        self.set_inflight_fd(inflight.inner.clone(), inflight.file.as_raw_fd())?;
         Ok(())
     }
 
@@ -600,6 +643,9 @@ impl VhostUserClient {
         self.queue_evts.clear();
         self.call_events.clear();
         self.queues.clear();
+        // All requests are discarded by device reset, they must not be resubmitted
+        // on next activation.
+        self.inflight = None;
 
         Ok(())
     }
@@ -764,6 +810,21 @@ impl VhostUserClient {
     }
 }
 
+impl Drop for VhostUserClient {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .mem_space
+            .unregister_listener(self.mem_listener.clone())
+        {
+            error!(
+                "Failed to unregister memory listener for vhost-user {}, {:?}",
+                self.backend_type.to_string(),
+                e
+            );
+        }
+    }
+}
+
 impl VhostOps for VhostUserClient {
     fn set_owner(&self) -> Result<()> {
         let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::SetOwner as u32, 0, 0);
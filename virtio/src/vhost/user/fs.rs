@@ -190,4 +190,9 @@ impl VirtioDevice for Fs {
 
         self.realize()
     }
+
+    fn iommu_supported(&self) -> bool {
+        // The vhost-user backend accesses guest memory by guest physical address.
+        false
+    }
 }
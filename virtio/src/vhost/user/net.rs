@@ -13,11 +13,14 @@
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use vmm_sys_util::eventfd::EventFd;
 
 use super::super::VhostOps;
-use super::{listen_guest_notifier, VhostBackendType, VhostUserClient};
+use super::{
+    listen_guest_notifier, VhostBackendType, VhostUserClient, VHOST_USER_F_PROTOCOL_FEATURES,
+    VHOST_USER_PROTOCOL_F_MQ,
+};
 use crate::{
     device::net::{build_device_config_space, CtrlInfo, MAC_ADDR_LEN},
     read_config_default, virtio_has_feature, CtrlVirtio, NetCtrlHandler, VirtioBase, VirtioDevice,
@@ -50,6 +53,8 @@ pub struct Net {
     client: Option<Arc<Mutex<VhostUserClient>>>,
     /// Whether irqfd can be used.
     enable_irqfd: bool,
+    /// Vhost user protocol features negotiated with the backend.
+    protocol_features: u64,
 }
 
 impl Net {
@@ -69,6 +74,7 @@ impl Net {
             mem_space: mem_space.clone(),
             client: None,
             enable_irqfd: false,
+            protocol_features: 0_u64,
         }
     }
 
@@ -100,6 +106,7 @@ impl Net {
         self.base.broken.store(false, Ordering::SeqCst);
         self.config_space = Default::default();
         self.client = None;
+        self.protocol_features = 0;
 
         Ok(())
     }
@@ -139,13 +146,41 @@ impl VirtioDevice for Net {
     }
 
     fn init_config_features(&mut self) -> Result<()> {
-        let client = self.client.as_ref().unwrap();
-        self.base.device_features = client
-            .lock()
-            .unwrap()
+        let locked_client = self.client.as_ref().unwrap().lock().unwrap();
+        self.base.device_features = locked_client
             .get_features()
             .with_context(|| "Failed to get features for vhost-user net")?;
 
+        let queue_pairs = self.net_cfg.queues / 2;
+        if virtio_has_feature(self.base.device_features, VHOST_USER_F_PROTOCOL_FEATURES) {
+            let protocol_features = locked_client
+                .get_protocol_features()
+                .with_context(|| "Failed to get protocol features for vhost-user net")?;
+            self.protocol_features = protocol_features & (1 << VHOST_USER_PROTOCOL_F_MQ);
+            locked_client
+                .set_protocol_features(self.protocol_features)
+                .with_context(|| "Failed to set protocol features for vhost-user net")?;
+
+            if virtio_has_feature(protocol_features, VHOST_USER_PROTOCOL_F_MQ as u32) {
+                let max_queue_num = locked_client
+                    .get_max_queue_num()
+                    .with_context(|| "Failed to get queue num for vhost-user net")?;
+                if u64::from(self.net_cfg.queues) > max_queue_num {
+                    bail!(
+                        "Exceed the max queue num that vhost-user net backend supported ({} queues)",
+                        max_queue_num
+                    );
+                }
+            }
+        }
+        if queue_pairs > 1 && self.protocol_features & (1 << VHOST_USER_PROTOCOL_F_MQ) == 0 {
+            bail!(
+                "vhost-user net backend doesn't support multi queue, protocol features: {:#b}",
+                self.protocol_features
+            );
+        }
+        drop(locked_client);
+
         let features = 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_NET_F_GUEST_CSUM
             | 1 << VIRTIO_NET_F_GUEST_TSO4
@@ -158,7 +193,6 @@ impl VirtioDevice for Net {
 
         let mut locked_config = self.config_space.lock().unwrap();
 
-        let queue_pairs = self.net_cfg.queues / 2;
         if self.net_cfg.mq
             && (VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN..=VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX)
                 .contains(&queue_pairs)
@@ -245,6 +279,7 @@ impl VirtioDevice for Net {
             client.set_queue_evts(&queue_evts);
         }
         client.features = driver_features & !(1 << VIRTIO_NET_F_MAC);
+        client.protocol_features = self.protocol_features;
 
         if !self.enable_irqfd {
             listen_guest_notifier(
@@ -291,4 +326,9 @@ impl VirtioDevice for Net {
     fn has_control_queue(&self) -> bool {
         virtio_has_feature(self.base.device_features, VIRTIO_NET_F_CTRL_VQ)
     }
+
+    fn iommu_supported(&self) -> bool {
+        // The vhost-user backend accesses guest memory by guest physical address.
+        false
+    }
 }